@@ -0,0 +1,409 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::advice::{advise, with_advice, Advice};
+use crate::utils::convert::Conversion;
+use crate::utils::ignore::Ignore;
+use crate::utils::index::{Index, IndexEntry, WorkTreeChange};
+use crate::utils::objects::{write_object, ObjectType};
+use crate::utils::path_from_bytes;
+use crate::utils::pathspec::{is_within, normalize, Pathspec};
+use crate::utils::refs::resolve_ref;
+use crate::utils::repository::Repository;
+use crate::utils::stats::Stats;
+use crate::utils::work_tree::{list_files, read_file, WorkTreeFile};
+
+impl CommandArgs for AddArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.paths.is_empty() && !(self.update || self.all) {
+            eprintln!("Nothing specified, nothing added.");
+            advise(
+                repo,
+                Advice::AddEmptyPathspec,
+                "Maybe you wanted to say 'git add .'?\n\
+                 Turn this message off by running\n\
+                 \"git config advice.addEmptyPathspec false\"",
+            )?;
+            return Ok(());
+        }
+
+        let mut stats = Stats::start();
+        let mut index = Index::load(repo)?;
+        stats.phase("read index");
+        let work_tree = repo.work_tree()?;
+        let prefix = repo.prefix()?;
+        // Without paths, -u and -A apply to the whole working tree
+        let pathspec = if self.paths.is_empty() {
+            Pathspec::all(&prefix)
+        } else {
+            Pathspec::new(&prefix, &self.paths)?
+        };
+
+        // Ignored files are only added with --force
+        let mut ignore = if self.force {
+            None
+        } else {
+            Some(Ignore::load(repo)?)
+        };
+        let files: Vec<_> = list_files(&work_tree, ignore.as_mut())?
+            .into_iter()
+            .filter(|file| pathspec.matches(&file.path))
+            .collect();
+        stats.phase("scan working tree");
+
+        let mut ignored = Vec::new();
+        for path in &self.paths {
+            let normalized = normalize(&prefix, path)?;
+            let Ok(metadata) = work_tree.join(&normalized).symlink_metadata() else {
+                // Paths that no longer exist must at least be tracked
+                if !index
+                    .entries()
+                    .iter()
+                    .any(|e| is_within(&e.path, &normalized))
+                {
+                    anyhow::bail!("pathspec '{}' did not match any files", path.display());
+                }
+                continue;
+            };
+
+            // Untracked paths given explicitly are refused if ignored
+            if let Some(ignore) = &mut ignore {
+                if !normalized.is_empty()
+                    && !index.contains(normalized.as_bytes())
+                    && ignore
+                        .matching_pattern(&normalized, metadata.is_dir())?
+                        .is_some_and(|pattern| !pattern.negated)
+                {
+                    ignored.push(path.display().to_string());
+                }
+            }
+        }
+        if !ignored.is_empty() {
+            anyhow::bail!(with_advice(
+                repo,
+                Advice::AddIgnoredFile,
+                format!(
+                    "The following paths are ignored by one of your .gitignore files:\n{}",
+                    ignored.join("\n")
+                ),
+                "Use -f if you really want to add them.\n\
+                 Turn this message off by running\n\
+                 \"git config advice.addIgnoredFile false\"",
+            )?);
+        }
+
+        let mut conversion = Conversion::load(repo)?;
+        let mut changed = false;
+
+        // Tracked files are updated (or removed) first
+        let tracked: Vec<_> = index
+            .entries()
+            .iter()
+            .filter(|entry| pathspec.matches(&entry.path))
+            .cloned()
+            .collect();
+        let mut previous_path = None;
+        for entry in tracked {
+            // Conflicting stages of a path are resolved together
+            if previous_path.as_ref() == Some(&entry.path) {
+                continue;
+            }
+            previous_path = Some(entry.path.clone());
+
+            let path = work_tree.join(path_from_bytes(&entry.path));
+            let Ok(metadata) = path.symlink_metadata() else {
+                writeln_if(self.verbose || self.dry_run, writer, "remove", &entry.path)?;
+                index.remove_entry(&entry.path);
+                changed = true;
+                continue;
+            };
+
+            if entry.stage == 0 && !entry.intent_to_add && !index.is_modified(&entry, &work_tree)? {
+                continue;
+            }
+
+            let file = WorkTreeFile {
+                path: entry.path,
+                metadata,
+            };
+            writeln_if(self.verbose || self.dry_run, writer, "add", &file.path)?;
+            if !self.dry_run {
+                index.add_entry(index_entry(repo, &work_tree, file, &mut conversion)?);
+            }
+            changed = true;
+        }
+
+        // Then untracked files are added, unless only tracked files are updated
+        if !self.update {
+            for file in files {
+                if index.contains(&file.path) {
+                    continue;
+                }
+
+                writeln_if(self.verbose || self.dry_run, writer, "add", &file.path)?;
+                if !self.dry_run {
+                    index.add_entry(index_entry(repo, &work_tree, file, &mut conversion)?);
+                }
+                changed = true;
+            }
+        }
+
+        stats.phase("update index");
+
+        if changed && !self.dry_run {
+            index.write(repo)?;
+            stats.phase("write index");
+        }
+
+        // The statistics go to stderr, after the added paths
+        if self.verbose {
+            stats.write(&mut std::io::stderr())?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the content of a file to the object database (with its line endings
+/// converted) and create its index entry.
+/// Nested repositories are recorded as submodules at their current commit.
+pub(crate) fn index_entry(
+    repo: &Repository,
+    work_tree: &Path,
+    file: WorkTreeFile,
+    conversion: &mut Conversion,
+) -> anyhow::Result<IndexEntry> {
+    let path = work_tree.join(path_from_bytes(&file.path));
+
+    let hash = if file.is_repository() {
+        let submodule = repo.open(path.join(".git"), Some(path.clone()));
+        match resolve_ref(&submodule, "HEAD")? {
+            Some(hash) => hash,
+            None => anyhow::bail!(
+                "'{}/' does not have a commit checked out",
+                String::from_utf8_lossy(&file.path)
+            ),
+        }
+    } else {
+        let content = read_file(&path, &file.metadata)?;
+        // Symlink targets are stored as is
+        let content = if file.metadata.is_symlink() {
+            content
+        } else {
+            conversion.convert_to_git(&String::from_utf8_lossy(&file.path), content)?
+        };
+        write_object(repo, ObjectType::Blob, &content)?
+    };
+
+    Ok(IndexEntry::from_metadata(file.path, hash, &file.metadata))
+}
+
+/// Write the tree of the tracked files of the working tree: the index with the
+/// files that changed updated to their content, and those deleted removed.
+///
+/// # Returns
+///
+/// The hash of the tree
+pub(crate) fn write_work_tree(
+    repo: &Repository,
+    index: &Index,
+    work_tree: &Path,
+) -> anyhow::Result<String> {
+    let mut conversion = Conversion::load(repo)?;
+    let mut work_index = index.clone();
+    for entry in index.entries() {
+        match index.compare(entry, work_tree)? {
+            None => {},
+            Some(WorkTreeChange::Deleted) => {
+                work_index.remove_entry(&entry.path);
+            },
+            Some(_) => {
+                let metadata = work_tree
+                    .join(path_from_bytes(&entry.path))
+                    .symlink_metadata()?;
+                let file = WorkTreeFile {
+                    path: entry.path.clone(),
+                    metadata,
+                };
+                work_index.add_entry(index_entry(repo, work_tree, file, &mut conversion)?);
+            },
+        }
+    }
+    work_index.write_tree(repo, false)
+}
+
+/// Report an action on a path, as done by `--verbose` and `--dry-run`.
+fn writeln_if<W>(enabled: bool, writer: &mut W, action: &str, path: &[u8]) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if enabled {
+        writeln!(writer, "{action} '{}'", String::from_utf8_lossy(path))?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct AddArgs {
+    /// don't actually add the files, just show what would be added
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// show the added paths, and how many files were scanned and hashed on stderr
+    #[arg(short, long)]
+    verbose: bool,
+    /// only update files that are already tracked
+    #[arg(short, long, conflicts_with = "all")]
+    update: bool,
+    /// add changes from all tracked and untracked files
+    #[arg(short = 'A', long)]
+    all: bool,
+    /// allow adding otherwise ignored files
+    #[arg(short, long)]
+    force: bool,
+    /// the files or directories to add
+    #[arg(value_name = "pathspec")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::hash_object;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::create_dir_all(temp.join("dir")).unwrap();
+        fs::write(temp.join("a.txt"), "a").unwrap();
+        fs::write(temp.join("dir/b.txt"), "b").unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn args(paths: &[&str]) -> AddArgs {
+        AddArgs {
+            dry_run: false,
+            verbose: true,
+            update: false,
+            all: false,
+            force: false,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn add(repo: &Repository, args: AddArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn paths(repo: &Repository) -> Vec<String> {
+        let index = Index::load(repo).unwrap();
+        index
+            .entries()
+            .iter()
+            .map(|e| String::from_utf8(e.path.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn adds_files_and_directories() {
+        let (_temp, repo) = setup();
+
+        assert_eq!(add(&repo, args(&["a.txt"])).unwrap(), "add 'a.txt'\n");
+        assert_eq!(add(&repo, args(&["."])).unwrap(), "add 'dir/b.txt'\n");
+        assert_eq!(paths(&repo), vec!["a.txt", "dir/b.txt"]);
+
+        let index = Index::load(&repo).unwrap();
+        let entry = index.entry(b"a.txt", 0).unwrap();
+        assert_eq!(entry.hash, hash_object(&ObjectType::Blob, b"a"));
+        assert!(repo.object_path(&entry.hash, true).is_ok());
+
+        // Unchanged files are not added again
+        assert_eq!(add(&repo, args(&["."])).unwrap(), "");
+    }
+
+    #[test]
+    fn updates_tracked_files() {
+        let (temp, repo) = setup();
+        add(&repo, args(&["a.txt"])).unwrap();
+
+        fs::write(temp.join("a.txt"), "changed").unwrap();
+        let output = add(&repo, AddArgs {
+            update: true,
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "add 'a.txt'\n");
+        assert_eq!(paths(&repo), vec!["a.txt"]);
+
+        fs::remove_file(temp.join("a.txt")).unwrap();
+        let output = add(&repo, AddArgs {
+            all: true,
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "remove 'a.txt'\nadd 'dir/b.txt'\n");
+        assert_eq!(paths(&repo), vec!["dir/b.txt"]);
+    }
+
+    #[test]
+    fn does_not_write_on_dry_run() {
+        let (_temp, repo) = setup();
+        let output = add(&repo, AddArgs {
+            dry_run: true,
+            verbose: false,
+            ..args(&["dir"])
+        })
+        .unwrap();
+
+        assert_eq!(output, "add 'dir/b.txt'\n");
+        assert!(paths(&repo).is_empty());
+    }
+
+    #[test]
+    fn skips_ignored_files() {
+        let (temp, repo) = setup();
+        fs::write(temp.join(".gitignore"), "*.txt\n!a.txt\n").unwrap();
+        fs::write(temp.join("dir/c.log"), "c").unwrap();
+
+        assert_eq!(
+            add(&repo, args(&["."])).unwrap(),
+            "add '.gitignore'\nadd 'a.txt'\nadd 'dir/c.log'\n"
+        );
+
+        let err = add(&repo, args(&["dir/b.txt"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The following paths are ignored by one of your .gitignore files:\ndir/b.txt\n\
+             hint: Use -f if you really want to add them.\n\
+             hint: Turn this message off by running\n\
+             hint: \"git config advice.addIgnoredFile false\""
+        );
+        let output = add(&repo, AddArgs {
+            force: true,
+            ..args(&["dir/b.txt"])
+        })
+        .unwrap();
+        assert_eq!(output, "add 'dir/b.txt'\n");
+    }
+
+    #[test]
+    fn fails_on_unmatched_pathspec() {
+        let (_temp, repo) = setup();
+        let err = add(&repo, args(&["a.txt", "missing"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'missing' did not match any files"
+        );
+        assert!(paths(&repo).is_empty());
+    }
+}
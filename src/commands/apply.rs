@@ -0,0 +1,695 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::apply::{apply_binary, apply_hunks, parse_patch, FilePatch, PatchBody};
+use crate::utils::checkout::{remove_empty_parents, remove_path, write_file};
+use crate::utils::index::{is_valid_path, Index, IndexEntry};
+use crate::utils::merge::merge_content;
+use crate::utils::objects::{hash_object, read_object_of_type, write_object, ObjectType};
+use crate::utils::path_from_bytes;
+use crate::utils::quote::quotes_non_ascii;
+use crate::utils::refs::is_hash;
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
+use crate::utils::tree::MODE_FILE;
+use crate::utils::work_tree::{file_mode, hash_file, read_file};
+
+impl CommandArgs for ApplyArgs {
+    fn run<W>(self, repo: &Repository, _writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut patches = Vec::new();
+        let inputs = match self.patches.is_empty() {
+            true => vec![PathBuf::from("-")],
+            false => self.patches.clone(),
+        };
+        for input in &inputs {
+            let mut content = Vec::new();
+            if input.as_os_str() == "-" {
+                std::io::stdin()
+                    .read_to_end(&mut content)
+                    .context("read patch from standard input")?;
+            } else {
+                content = std::fs::read(input)
+                    .context(format!("can't open patch '{}'", input.display()))?;
+            }
+            patches.extend(parse_patch(&content, self.strip)?);
+        }
+
+        if patches.is_empty() {
+            if self.allow_empty {
+                return Ok(());
+            }
+            anyhow::bail!("No valid patches in input (allow with \"--allow-empty\")");
+        }
+        if self.reverse {
+            patches.iter_mut().for_each(FilePatch::reverse);
+        }
+
+        let use_index = self.cached || self.index || self.three_way;
+        let mut application = Application {
+            repo,
+            work_tree: repo.work_tree()?,
+            index: match use_index {
+                true => Some(Index::load(repo)?),
+                false => None,
+            },
+            args: &self,
+            files: Vec::new(),
+        };
+
+        let quote_non_ascii = quotes_non_ascii(repo)?;
+        let mut failed = false;
+        for patch in &patches {
+            if self.verbose {
+                eprintln!("Checking patch {}...", patch.name(quote_non_ascii));
+            }
+            failed |= !application.check(patch)?;
+        }
+        if failed {
+            return Err(SilentExit(1).into());
+        }
+        if self.check {
+            return Ok(());
+        }
+
+        let conflicts = application.write()?;
+        if self.verbose {
+            for patch in &patches {
+                eprintln!("Applied patch {} cleanly.", patch.name(quote_non_ascii));
+            }
+        }
+        for path in &conflicts {
+            eprintln!("U {}", String::from_utf8_lossy(path));
+        }
+        match conflicts.is_empty() {
+            true => Ok(()),
+            false => Err(SilentExit(1).into()),
+        }
+    }
+}
+
+/// The base, our and their content of a file merged with conflicts
+type Stages = [Vec<u8>; 3];
+
+/// The content of a file once patched
+struct PatchedFile {
+    mode: u32,
+    content: Vec<u8>,
+    /// The stages of the file if it was merged with conflicts
+    stages: Option<Stages>,
+}
+
+/// The patches checked so far, applied in memory until they all apply
+struct Application<'a> {
+    repo: &'a Repository,
+    work_tree: PathBuf,
+    /// The index, if the patches are applied to it
+    index: Option<Index>,
+    args: &'a ApplyArgs,
+    /// Each path changed so far with its patched file (`None` if it is removed),
+    /// in the order of the changes
+    files: Vec<(Vec<u8>, Option<PatchedFile>)>,
+}
+
+impl Application<'_> {
+    /// Apply a patch in memory, printing why it does not apply if it does not.
+    ///
+    /// # Returns
+    ///
+    /// Whether the patch applies
+    fn check(&mut self, patch: &FilePatch) -> anyhow::Result<bool> {
+        // Paths going out of the working tree (or into the git directory) are refused
+        for path in patch.old_path.iter().chain(&patch.new_path) {
+            if !is_valid_path(path) {
+                eprintln!("error: invalid path '{}'", String::from_utf8_lossy(path));
+                return Ok(false);
+            }
+        }
+
+        let old = match &patch.old_path {
+            Some(path) => {
+                if !self.matches_index(path)? {
+                    return Ok(false);
+                }
+                match self.read(path)? {
+                    Some(old) => Some(old),
+                    None => {
+                        let reason = match self.args.cached || self.args.index {
+                            true => "does not exist in index",
+                            false => "No such file or directory",
+                        };
+                        eprintln!("error: {}: {reason}", String::from_utf8_lossy(path));
+                        return Ok(false);
+                    },
+                }
+            },
+            None => None,
+        };
+        if let Some(path) = &patch.new_path {
+            if patch.old_path.as_ref() != Some(path) && !self.is_free(path)? {
+                return Ok(false);
+            }
+        }
+
+        let path = patch
+            .old_path
+            .as_ref()
+            .or(patch.new_path.as_ref())
+            .context("patch without paths")?;
+        let path = String::from_utf8_lossy(path);
+        // Files created by patches that do not tell their mode are regular files
+        let (old_mode, old_content) = old.unwrap_or((MODE_FILE, Vec::new()));
+        let mode = patch.new_mode.unwrap_or(old_mode);
+
+        let mut stages = None;
+        let content = match &patch.body {
+            PatchBody::Text(hunks) => match apply_hunks(&old_content, hunks) {
+                Ok(content) => content,
+                Err(i) => {
+                    if self.args.verbose {
+                        let preimage = hunks[i].old_lines.concat();
+                        eprintln!(
+                            "error: while searching for:\n{}",
+                            String::from_utf8_lossy(&preimage)
+                        );
+                    }
+                    eprintln!("error: patch failed: {path}:{}", hunks[i].old_start);
+                    match self.merge(patch, &old_content)? {
+                        Some((content, conflict)) => {
+                            stages = conflict;
+                            content
+                        },
+                        None => {
+                            eprintln!("error: {path}: patch does not apply");
+                            return Ok(false);
+                        },
+                    }
+                },
+            },
+            PatchBody::Binary { forward: None, .. } => {
+                eprintln!(
+                    "error: cannot reverse-apply a binary patch without the reverse hunk to \
+                     '{path}'"
+                );
+                return Ok(false);
+            },
+            PatchBody::Binary {
+                forward: Some(chunk),
+                ..
+            } => {
+                if !self.matches_old_hash(patch, &path, &old_content) {
+                    return Ok(false);
+                }
+                let Ok(content) = apply_binary(&old_content, chunk) else {
+                    eprintln!("error: binary patch does not apply to '{path}'");
+                    return Ok(false);
+                };
+                if let Some(expected) = patch.new_hash.as_ref().filter(|hash| is_hash(hash)) {
+                    let hash = hash_object(&ObjectType::Blob, &content);
+                    if patch.new_path.is_some() && hash != *expected {
+                        eprintln!(
+                            "error: binary patch to '{path}' creates incorrect result \
+                             (expecting {expected}, got {hash})"
+                        );
+                        return Ok(false);
+                    }
+                }
+                content
+            },
+            PatchBody::BinaryDiffers => {
+                let content = match patch.new_path {
+                    Some(_) => patch
+                        .new_hash
+                        .as_ref()
+                        .filter(|hash| is_hash(hash))
+                        .and_then(|hash| {
+                            read_object_of_type(self.repo, hash, ObjectType::Blob).ok()
+                        }),
+                    None => Some(Vec::new()),
+                };
+                let full_index = patch.old_hash.as_ref().is_some_and(|hash| is_hash(hash));
+                let Some(content) = content.filter(|_| full_index) else {
+                    eprintln!(
+                        "error: cannot apply binary patch to '{path}' without full index line"
+                    );
+                    eprintln!("error: {path}: patch does not apply");
+                    return Ok(false);
+                };
+                if !self.matches_old_hash(patch, &path, &old_content) {
+                    return Ok(false);
+                }
+                content
+            },
+        };
+
+        if patch.new_path.is_none() && !content.is_empty() {
+            eprintln!("error: removal patch leaves file contents");
+            eprintln!("error: {path}: patch does not apply");
+            return Ok(false);
+        }
+
+        if let Some(old_path) = patch.old_path.as_ref() {
+            if patch.new_path.is_none() || patch.is_rename() {
+                self.files.push((old_path.clone(), None));
+            }
+        }
+        if let Some(new_path) = &patch.new_path {
+            let file = PatchedFile {
+                mode,
+                content,
+                stages,
+            };
+            self.files.push((new_path.clone(), Some(file)));
+        }
+        Ok(true)
+    }
+
+    /// Fall back to merging the changes of a patch that does not apply into the
+    /// current content of its file, from the content the patch was made against.
+    ///
+    /// # Returns
+    ///
+    /// The merged content with the base, our and their content if it conflicts,
+    /// or `None` if there is nothing to merge from
+    fn merge(
+        &self,
+        patch: &FilePatch,
+        ours: &[u8],
+    ) -> anyhow::Result<Option<(Vec<u8>, Option<Stages>)>> {
+        let PatchBody::Text(hunks) = &patch.body else {
+            return Ok(None);
+        };
+        if !self.args.three_way {
+            return Ok(None);
+        }
+        eprintln!("Falling back to three-way merge...");
+
+        let base = patch
+            .old_hash
+            .as_ref()
+            .and_then(|hash| resolve_revision(self.repo, hash).ok())
+            .and_then(|hash| read_object_of_type(self.repo, &hash, ObjectType::Blob).ok());
+        let Some(theirs) = base.as_ref().and_then(|base| apply_hunks(base, hunks).ok()) else {
+            eprintln!("error: repository lacks the necessary blob to fall back on 3-way merge.");
+            return Ok(None);
+        };
+        let base = base.unwrap_or_default();
+
+        let path = patch.new_path.as_ref().or(patch.old_path.as_ref());
+        let path = String::from_utf8_lossy(path.context("patch without paths")?);
+        let merged = merge_content(&base, ours, &theirs, ("ours", "theirs"));
+        if merged.conflicts == 0 {
+            eprintln!("Applied patch to '{path}' cleanly.");
+            return Ok(Some((merged.content, None)));
+        }
+        eprintln!("Applied patch to '{path}' with conflicts.");
+        Ok(Some((merged.content, Some([base, ours.to_vec(), theirs]))))
+    }
+
+    /// The mode and content of a file, as left by the previous patches or read
+    /// from the index or the working tree.
+    ///
+    /// # Returns
+    ///
+    /// The mode and content, or `None` if there is no such file
+    fn read(&self, path: &[u8]) -> anyhow::Result<Option<(u32, Vec<u8>)>> {
+        if let Some((_, file)) = self.files.iter().rev().find(|(changed, _)| changed == path) {
+            return Ok(file.as_ref().map(|file| (file.mode, file.content.clone())));
+        }
+
+        if self.args.cached {
+            let entry = self.index.as_ref().and_then(|index| index.entry(path, 0));
+            let Some(entry) = entry else {
+                return Ok(None);
+            };
+            let content = read_object_of_type(self.repo, &entry.hash, ObjectType::Blob)?;
+            return Ok(Some((entry.mode, content)));
+        }
+
+        let full_path = self.work_tree.join(path_from_bytes(path));
+        let Ok(metadata) = full_path.symlink_metadata() else {
+            return Ok(None);
+        };
+        Ok(Some((
+            file_mode(&metadata),
+            read_file(&full_path, &metadata)?,
+        )))
+    }
+
+    /// Whether a file of the working tree patched along with the index has the
+    /// content of its index entry, printing why if it does not.
+    fn matches_index(&self, path: &[u8]) -> anyhow::Result<bool> {
+        let Some(index) = self.index.as_ref().filter(|_| !self.args.cached) else {
+            return Ok(true);
+        };
+        if self.files.iter().any(|(changed, _)| changed == path) {
+            return Ok(true);
+        }
+
+        let display = String::from_utf8_lossy(path);
+        let Some(entry) = index.entry(path, 0) else {
+            eprintln!("error: {display}: does not exist in index");
+            return Ok(false);
+        };
+        let full_path = self.work_tree.join(path_from_bytes(path));
+        let Ok(metadata) = full_path.symlink_metadata() else {
+            return Ok(true);
+        };
+        if hash_file(&full_path, &metadata)? != entry.hash {
+            eprintln!("error: {display}: does not match index");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Whether a binary patch was made against the current content of its file,
+    /// printing why if it was not.
+    fn matches_old_hash(&self, patch: &FilePatch, path: &str, content: &[u8]) -> bool {
+        let Some(expected) = patch.old_hash.as_ref().filter(|hash| is_hash(hash)) else {
+            return true;
+        };
+        let hash = match patch.old_path {
+            Some(_) => hash_object(&ObjectType::Blob, content),
+            None => "0".repeat(expected.len()),
+        };
+        if hash != *expected {
+            eprintln!(
+                "error: the patch applies to '{path}' ({hash}), which does not match the \
+                 current contents."
+            );
+            eprintln!("error: {path}: patch does not apply");
+            return false;
+        }
+        true
+    }
+
+    /// Whether a file can be created at a path, printing why if it cannot.
+    fn is_free(&self, path: &[u8]) -> anyhow::Result<bool> {
+        let exists_in = match self.files.iter().rev().find(|(changed, _)| changed == path) {
+            Some((_, None)) => None,
+            Some((_, Some(_))) if self.args.cached => Some("index"),
+            Some((_, Some(_))) => Some("working directory"),
+            None if self
+                .index
+                .as_ref()
+                .is_some_and(|index| index.contains(path)) =>
+            {
+                Some("index")
+            },
+            None if !self.args.cached
+                && self
+                    .work_tree
+                    .join(path_from_bytes(path))
+                    .symlink_metadata()
+                    .is_ok() =>
+            {
+                Some("working directory")
+            },
+            None => None,
+        };
+        if let Some(location) = exists_in {
+            let path = String::from_utf8_lossy(path);
+            eprintln!("error: {path}: already exists in {location}");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Write the patched files to the working tree and the index.
+    ///
+    /// # Returns
+    ///
+    /// The paths merged with conflicts
+    fn write(mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut conflicts = Vec::new();
+
+        for (path, file) in &self.files {
+            let full_path = self.work_tree.join(path_from_bytes(path));
+            let Some(file) = file else {
+                if let Some(index) = &mut self.index {
+                    index.remove_entry(path);
+                }
+                if !self.args.cached && full_path.symlink_metadata().is_ok() {
+                    remove_path(&full_path)?;
+                    remove_empty_parents(&full_path, &self.work_tree);
+                }
+                continue;
+            };
+
+            if !self.args.cached {
+                write_file(&full_path, file.mode, &file.content)?;
+            }
+            let Some(index) = &mut self.index else {
+                continue;
+            };
+
+            if let Some(stages) = &file.stages {
+                index.remove_entry(path);
+                for (stage, content) in (1..).zip(stages) {
+                    let hash = write_object(self.repo, ObjectType::Blob, content)?;
+                    let mut entry = IndexEntry::new(path.clone(), file.mode, hash);
+                    entry.stage = stage;
+                    index.add_entry(entry);
+                }
+                conflicts.push(path.clone());
+                continue;
+            }
+
+            let hash = write_object(self.repo, ObjectType::Blob, &file.content)?;
+            let entry = match self.args.cached {
+                true => IndexEntry::new(path.clone(), file.mode, hash),
+                false => IndexEntry::from_metadata(path.clone(), hash, &metadata(&full_path)?),
+            };
+            index.add_entry(entry);
+        }
+
+        if let Some(index) = &self.index {
+            index.write(self.repo)?;
+        }
+        Ok(conflicts)
+    }
+}
+
+fn metadata(path: &Path) -> anyhow::Result<std::fs::Metadata> {
+    path.symlink_metadata()
+        .context(format!("stat {}", path.display()))
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ApplyArgs {
+    /// apply the patch to the index only, leaving the working tree alone
+    #[arg(long, conflicts_with = "index")]
+    cached: bool,
+    /// apply the patch to both the index and the working tree
+    #[arg(long)]
+    index: bool,
+    /// only check whether the patch applies, without applying it
+    #[arg(long)]
+    check: bool,
+    /// fall back to a three-way merge when the patch does not apply (implies --index)
+    #[arg(short = '3', long = "3way", conflicts_with = "cached")]
+    three_way: bool,
+    /// apply the patch in reverse
+    #[arg(short = 'R', long)]
+    reverse: bool,
+    /// remove this many leading components from the paths of the patch
+    #[arg(short = 'p', value_name = "n", default_value_t = 1)]
+    strip: usize,
+    /// report each patch applied
+    #[arg(short, long)]
+    verbose: bool,
+    /// do not fail on a patch without changes to any file
+    #[arg(long)]
+    allow_empty: bool,
+    /// the patches to apply (the standard input by default, or with -)
+    #[arg(value_name = "patch")]
+    patches: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    const PATCH: &str = "\
+diff --git a/f b/g
+similarity index 80%
+rename from f
+rename to g
+index 8a1218a..c4fcee8 100644
+--- a/f
++++ b/g
+@@ -1,5 +1,5 @@
+ 1
+ 2
+-3
++three
+ 4
+ 5
+diff --git a/gone b/gone
+deleted file mode 100644
+index 7898192..0000000
+--- a/gone
++++ /dev/null
+@@ -1 +0,0 @@
+-a
+diff --git a/run b/run
+new file mode 100755
+index 0000000..ce01362
+--- /dev/null
++++ b/run
+@@ -0,0 +1 @@
++hello
+";
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::write(temp.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(temp.join("f"), "1\n2\n3\n4\n5\n").unwrap();
+        fs::write(temp.join("gone"), "a\n").unwrap();
+        fs::write(temp.join("patch"), PATCH).unwrap();
+        let repo = temp.repo();
+
+        let mut index = Index::default();
+        for path in ["f", "gone"] {
+            let file = temp.join(path);
+            let metadata = fs::symlink_metadata(&file).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, &fs::read(&file).unwrap()).unwrap();
+            index.add_entry(IndexEntry::from_metadata(path.into(), hash, &metadata));
+        }
+        index.write(&repo).unwrap();
+
+        (temp, repo)
+    }
+
+    fn args(repo: &Repository) -> ApplyArgs {
+        ApplyArgs {
+            cached: false,
+            index: false,
+            check: false,
+            three_way: false,
+            reverse: false,
+            strip: 1,
+            verbose: false,
+            allow_empty: false,
+            patches: vec![repo.cwd().join("patch")],
+        }
+    }
+
+    fn paths(repo: &Repository) -> Vec<(String, u8)> {
+        let index = Index::load(repo).unwrap();
+        let entries = index.entries().iter();
+        entries
+            .map(|e| (String::from_utf8(e.path.clone()).unwrap(), e.stage))
+            .collect()
+    }
+
+    #[test]
+    fn applies_renames_deletions_and_new_files() {
+        let (temp, repo) = setup();
+
+        ApplyArgs {
+            check: true,
+            ..args(&repo)
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert!(fs::metadata(temp.join("f")).is_ok());
+
+        ApplyArgs {
+            index: true,
+            ..args(&repo)
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("g")).unwrap(),
+            "1\n2\nthree\n4\n5\n"
+        );
+        assert_eq!(fs::read_to_string(temp.join("run")).unwrap(), "hello\n");
+        assert_eq!(
+            file_mode(&fs::metadata(temp.join("run")).unwrap()),
+            0o100755
+        );
+        assert!(fs::metadata(temp.join("f")).is_err() && fs::metadata(temp.join("gone")).is_err());
+        assert_eq!(paths(&repo), [("g".to_string(), 0), ("run".to_string(), 0)]);
+
+        // Applying it again fails, and reversing it restores the files
+        assert!(args(&repo).run(&repo, &mut Vec::new()).is_err());
+        ApplyArgs {
+            reverse: true,
+            ..args(&repo)
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("f")).unwrap(),
+            "1\n2\n3\n4\n5\n"
+        );
+        assert_eq!(fs::read_to_string(temp.join("gone")).unwrap(), "a\n");
+        assert!(fs::metadata(temp.join("g")).is_err() && fs::metadata(temp.join("run")).is_err());
+    }
+
+    #[test]
+    fn refuses_paths_outside_of_the_work_tree() {
+        let (temp, repo) = setup();
+        for path in [
+            "../evil.txt",
+            ".git/hooks/pre-commit",
+            "dir/.GIT/config",
+            "a/./b",
+        ] {
+            let patch = format!(
+                "diff --git a/{path} b/{path}\n\
+                 new file mode 100755\n\
+                 --- /dev/null\n\
+                 +++ b/{path}\n\
+                 @@ -0,0 +1 @@\n\
+                 +evil\n"
+            );
+            fs::write(temp.join("patch"), patch).unwrap();
+
+            let error = args(&repo).run(&repo, &mut Vec::new()).unwrap_err();
+            assert_eq!(error.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+        }
+        assert!(!temp.join("../evil.txt").exists());
+        assert!(!temp.join(".git/hooks").exists());
+        assert!(!temp.join("dir").exists() && !temp.join("a").exists());
+    }
+
+    #[test]
+    fn falls_back_to_three_way_merge() {
+        let (temp, repo) = setup();
+        fs::write(temp.join("f"), "one\n2\n3\n4\n5\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"one\n2\n3\n4\n5\n").unwrap();
+        let metadata = fs::symlink_metadata(temp.join("f")).unwrap();
+        index.add_entry(IndexEntry::from_metadata("f".into(), hash, &metadata));
+        index.write(&repo).unwrap();
+
+        // Nothing is applied when a patch does not apply
+        let error = args(&repo).run(&repo, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+        assert!(fs::metadata(temp.join("gone")).is_ok() && fs::metadata(temp.join("run")).is_err());
+
+        let args = ApplyArgs {
+            three_way: true,
+            ..args(&repo)
+        };
+        args.run(&repo, &mut Vec::new()).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("g")).unwrap(),
+            "one\n2\nthree\n4\n5\n"
+        );
+        assert_eq!(paths(&repo), [("g".to_string(), 0), ("run".to_string(), 0)]);
+    }
+}
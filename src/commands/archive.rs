@@ -0,0 +1,296 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::archive::{collect_entries, write_tar, write_zip, Archive, FORMATS};
+use crate::utils::commit::{peel, read_commit, Ident};
+use crate::utils::config::Config;
+use crate::utils::objects::ObjectType;
+use crate::utils::pathspec::Pathspec;
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
+
+/// The permission bits cleared from archived files unless `tar.umask` is set
+const DEFAULT_UMASK: u32 = 0o002;
+
+impl CommandArgs for ArchiveArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.list {
+            for format in FORMATS {
+                writeln!(writer, "{format}")?;
+            }
+            return Ok(());
+        }
+
+        let format = self.format()?;
+        let tree_ish = self
+            .tree_ish
+            .as_deref()
+            .context("must specify a tree-ish")?;
+        let archive = self.collect(repo, tree_ish)?;
+
+        let umask = match Config::load(repo)?.get("tar.umask") {
+            Some(umask) => {
+                u32::from_str_radix(umask, 8).context(format!("invalid tar.umask '{umask}'"))?
+            },
+            None => DEFAULT_UMASK,
+        };
+
+        match &self.output {
+            Some(path) => {
+                let file = File::create(path).context(format!("create {}", path.display()))?;
+                let mut file = BufWriter::new(file);
+                write_archive(repo, &archive, format, umask, &mut file)?;
+                file.flush()?;
+            },
+            None => write_archive(repo, &archive, format, umask, writer)?,
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveArgs {
+    /// The format to write: the one given, or the one
+    /// matching the extension of the output file (tar by default).
+    fn format(&self) -> anyhow::Result<&str> {
+        let format = match (&self.format, &self.output) {
+            (Some(format), _) => format.as_str(),
+            (None, Some(output)) => match output.extension().and_then(|ext| ext.to_str()) {
+                Some("zip") => "zip",
+                _ => "tar",
+            },
+            (None, None) => "tar",
+        };
+
+        if !FORMATS.contains(&format) {
+            anyhow::bail!("Unknown archive format '{format}'");
+        }
+        Ok(format)
+    }
+
+    /// List the entries of the tree to archive, with the time to give them: the
+    /// committer date if a commit was given, or the current time for a bare tree.
+    fn collect(&self, repo: &Repository, tree_ish: &str) -> anyhow::Result<Archive> {
+        let hash = resolve_revision(repo, tree_ish)?;
+        let (tree, commit, mtime) = match peel(repo, &hash)? {
+            (commit, ObjectType::Commit) => {
+                let parsed = read_commit(repo, &commit)?;
+                let mtime = Ident::parse(&parsed.committer)?.timestamp;
+                (parsed.tree, Some(commit), mtime)
+            },
+            (tree, ObjectType::Tree) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                (tree, None, now.as_secs() as i64)
+            },
+            _ => anyhow::bail!("not a tree object: {hash}"),
+        };
+
+        let pathspec = Pathspec::new(&repo.prefix()?, &self.paths)?;
+        let entries = collect_entries(repo, &tree, &self.prefix, &pathspec)?;
+
+        // Each path has to match something in the tree
+        for path in &self.paths {
+            let single = Pathspec::new(&repo.prefix()?, std::slice::from_ref(path))?;
+            if collect_entries(repo, &tree, "", &single)?.is_empty() {
+                anyhow::bail!("pathspec '{}' did not match any files", path.display());
+            }
+        }
+
+        Ok(Archive {
+            entries,
+            commit,
+            mtime,
+        })
+    }
+}
+
+fn write_archive<W>(
+    repo: &Repository,
+    archive: &Archive,
+    format: &str,
+    umask: u32,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    match format {
+        "zip" => write_zip(repo, archive, writer),
+        _ => write_tar(repo, archive, umask, writer),
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ArchiveArgs {
+    /// the format of the archive (tar or zip)
+    #[arg(long, value_name = "fmt")]
+    format: Option<String>,
+    /// prepend a prefix to each path in the archive
+    #[arg(long, value_name = "prefix", default_value = "")]
+    prefix: String,
+    /// write the archive to a file instead of standard output
+    #[arg(short, long, value_name = "file")]
+    output: Option<PathBuf>,
+    /// list the available formats
+    #[arg(short, long)]
+    list: bool,
+    /// the tree or commit to archive
+    #[arg(value_name = "tree-ish", required_unless_present = "list")]
+    tree_ish: Option<String>,
+    /// only archive these paths
+    #[arg(value_name = "path")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let mut index = Index::default();
+        for (path, mode, content) in [
+            ("a.txt", 0o100644, "a\n"),
+            ("dir/run.sh", 0o100755, "#!/bin/sh\n"),
+            ("dir/sub/link", 0o120000, "../run.sh"),
+        ] {
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.into(), mode, hash));
+        }
+        let tree = index.write_tree(&repo, false).unwrap();
+        let commit = create_commit(&repo, &tree, Vec::new(), "first\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        (temp, repo)
+    }
+
+    fn args(tree_ish: &str, paths: &[&str]) -> ArchiveArgs {
+        ArchiveArgs {
+            format: None,
+            prefix: String::new(),
+            output: None,
+            list: false,
+            tree_ish: Some(tree_ish.to_string()),
+            paths: paths.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn archive(repo: &Repository, args: ArchiveArgs) -> anyhow::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(output)
+    }
+
+    /// List the name, type and size of each member of a tar archive.
+    fn tar_members(tar: &[u8]) -> Vec<(String, char, usize)> {
+        let mut members = Vec::new();
+        let mut offset = 0;
+        while tar[offset] != 0 {
+            let header = &tar[offset..offset + 512];
+            let field = |start: usize, end: usize| {
+                let value = &header[start..end];
+                let length = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+                String::from_utf8_lossy(&value[..length]).into_owned()
+            };
+            let size = usize::from_str_radix(&field(124, 135), 8).unwrap();
+            members.push((field(0, 100), header[156] as char, size));
+            offset += 512 + size.next_multiple_of(512);
+        }
+        members
+    }
+
+    #[test]
+    fn writes_tar_archives() {
+        let (_temp, repo) = setup();
+        let commit = resolve_revision(&repo, "HEAD").unwrap();
+
+        let output = archive(&repo, ArchiveArgs {
+            prefix: "project/".to_string(),
+            ..args("HEAD", &[])
+        })
+        .unwrap();
+        assert_eq!(output.len(), 10240);
+        assert_eq!(tar_members(&output), [
+            ("pax_global_header".to_string(), 'g', 52),
+            ("project/".to_string(), '5', 0),
+            ("project/a.txt".to_string(), '0', 2),
+            ("project/dir/".to_string(), '5', 0),
+            ("project/dir/run.sh".to_string(), '0', 10),
+            ("project/dir/sub/".to_string(), '5', 0),
+            ("project/dir/sub/link".to_string(), '2', 0),
+        ]);
+        assert_eq!(
+            &output[512..564],
+            format!("52 comment={commit}\n").as_bytes()
+        );
+        // The executable keeps its permissions, minus the default umask
+        assert_eq!(&output[512 * 6 + 100..512 * 6 + 108], b"0000775\0");
+
+        // Trees have no commit to record, and paths limit what is archived
+        let output = archive(&repo, args("HEAD^{tree}", &["dir/sub"])).unwrap();
+        assert_eq!(tar_members(&output), [
+            ("dir/".to_string(), '5', 0),
+            ("dir/sub/".to_string(), '5', 0),
+            ("dir/sub/link".to_string(), '2', 0),
+        ]);
+
+        let err = archive(&repo, args("HEAD", &["missing"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'missing' did not match any files"
+        );
+    }
+
+    #[test]
+    fn writes_zip_archives() {
+        let (temp, repo) = setup();
+        let commit = resolve_revision(&repo, "HEAD").unwrap();
+
+        archive(&repo, ArchiveArgs {
+            output: Some(temp.join("out.zip")),
+            ..args("HEAD", &["a.txt"])
+        })
+        .unwrap();
+        let output = fs::read(temp.join("out.zip")).unwrap();
+
+        // A single stored file, then its central directory header and the commit as comment
+        assert_eq!(&output[..4], b"PK\x03\x04");
+        assert_eq!(&output[8..10], [0, 0]);
+        assert_eq!(&output[30..35], b"a.txt");
+        assert_eq!(&output[44..46], b"a\n");
+        assert_eq!(&output[46..50], b"PK\x01\x02");
+        assert!(output.ends_with(commit.as_bytes()));
+
+        let err = archive(&repo, ArchiveArgs {
+            format: Some("rar".to_string()),
+            ..args("HEAD", &[])
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown archive format 'rar'");
+    }
+}
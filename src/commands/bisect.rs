@@ -0,0 +1,583 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+use crate::commands::log::write_commit;
+use crate::commands::switch::{switch, LocalChanges, Target};
+use crate::commands::CommandArgs;
+use crate::utils::commit::read_commit;
+use crate::utils::mailmap::Mailmap;
+use crate::utils::refs::{delete_ref, head_branch, list_refs, resolve_ref, shorten_ref, write_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::walk::RevWalk;
+
+/// The file holding the branch (or commit) checked out when the bisection started
+const BISECT_START: &str = "BISECT_START";
+/// The file recording the commands of the bisection, and what they found
+const BISECT_LOG: &str = "BISECT_LOG";
+
+/// The exit code of `bisect run` commands for commits that cannot be tested
+const SKIP_EXIT_CODE: i32 = 125;
+
+impl CommandArgs for BisectArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.command {
+            BisectCommand::Start(args) => start(repo, args, writer),
+            BisectCommand::Bad(args) => {
+                if args.revisions.len() > 1 {
+                    anyhow::bail!("'bisect bad' can take only one argument");
+                }
+                mark_revisions(repo, Term::Bad, &args.revisions)?;
+                next(repo, writer).map(|_| ())
+            },
+            BisectCommand::Good(args) => {
+                mark_revisions(repo, Term::Good, &args.revisions)?;
+                next(repo, writer).map(|_| ())
+            },
+            BisectCommand::Skip(args) => {
+                mark_revisions(repo, Term::Skip, &args.revisions)?;
+                next(repo, writer).map(|_| ())
+            },
+            BisectCommand::Reset(args) => reset(repo, args.commit, writer),
+            BisectCommand::Log => {
+                check_bisecting(repo)?;
+                let log = fs::read(repo.git_dir()?.join(BISECT_LOG)).context("read BISECT_LOG")?;
+                writer.write_all(&log)?;
+                Ok(())
+            },
+            BisectCommand::Run(args) => run(repo, &args.command, writer),
+        }
+    }
+}
+
+/// What a commit is marked as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Term {
+    Bad,
+    Good,
+    Skip,
+}
+
+impl Term {
+    fn name(self) -> &'static str {
+        match self {
+            Term::Bad => "bad",
+            Term::Good => "good",
+            Term::Skip => "skip",
+        }
+    }
+
+    /// The ref marking a commit, `refs/bisect/bad` being moved to each new bad commit.
+    fn ref_name(self, hash: &str) -> String {
+        match self {
+            Term::Bad => "refs/bisect/bad".to_string(),
+            term => format!("refs/bisect/{}-{hash}", term.name()),
+        }
+    }
+}
+
+/// Where a bisection is after a commit is marked
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    /// Waiting for the first bad commit, or the first good one
+    Waiting,
+    /// A commit to test was checked out
+    Testing,
+    /// The first bad commit was found
+    Found,
+    /// Only skipped commits are left, any of which may be the first bad commit
+    OnlySkipped,
+}
+
+/// Start a bisection from the current branch (or commit), optionally
+/// marking a bad commit and good ones right away.
+fn start<W>(repo: &Repository, args: StartArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let git_dir = repo.git_dir()?;
+    let revisions: Vec<_> = args
+        .revisions
+        .iter()
+        .map(|revision| resolve_commit(repo, revision))
+        .collect::<anyhow::Result<_>>()?;
+
+    // Starting again keeps what was checked out before the first start
+    let original = match fs::read_to_string(git_dir.join(BISECT_START)) {
+        Ok(original) => original.trim_end().to_string(),
+        Err(_) => match (head_branch(repo)?, resolve_ref(repo, "HEAD")?) {
+            (Some(branch), _) => shorten_ref(&branch).to_string(),
+            (None, Some(hash)) => hash,
+            (None, None) => anyhow::bail!("bad HEAD - I need a HEAD"),
+        },
+    };
+    clean_state(repo)?;
+    fs::write(git_dir.join(BISECT_START), format!("{original}\n")).context("write BISECT_START")?;
+
+    let quoted: String = args
+        .revisions
+        .iter()
+        .map(|revision| format!(" '{revision}'"))
+        .collect();
+    append_log(repo, &format!("git bisect start{quoted}\n"))?;
+
+    if let Some((bad, good)) = revisions.split_first() {
+        mark(repo, Term::Bad, bad)?;
+        for hash in good {
+            mark(repo, Term::Good, hash)?;
+        }
+    }
+    next(repo, writer).map(|_| ())
+}
+
+/// Mark commits (HEAD by default) as bad, good or skipped.
+fn mark_revisions(repo: &Repository, term: Term, revisions: &[String]) -> anyhow::Result<()> {
+    check_bisecting(repo)?;
+    let head = ["HEAD".to_string()];
+    let revisions = if revisions.is_empty() {
+        &head[..]
+    } else {
+        revisions
+    };
+    for revision in revisions {
+        let hash = resolve_commit(repo, revision)?;
+        mark(repo, term, &hash)?;
+    }
+    Ok(())
+}
+
+fn mark(repo: &Repository, term: Term, hash: &str) -> anyhow::Result<()> {
+    write_ref(repo, &term.ref_name(hash), hash)?;
+    let subject = read_commit(repo, hash)?.subject();
+    append_log(
+        repo,
+        &format!(
+            "# {}: [{hash}] {subject}\ngit bisect {} {hash}\n",
+            term.name(),
+            term.name()
+        ),
+    )
+}
+
+/// Find the next commit to test, and check it out, unless the first bad
+/// commit is found or there are not both a bad and a good commit yet.
+fn next<W>(repo: &Repository, writer: &mut W) -> anyhow::Result<Outcome>
+where
+    W: Write,
+{
+    let bad = resolve_ref(repo, "refs/bisect/bad")?;
+    let mut good = Vec::new();
+    let mut skipped = HashSet::new();
+    for (name, hash) in list_refs(repo, "refs/bisect/")? {
+        if name.starts_with("refs/bisect/good-") {
+            good.push(hash);
+        } else if name.starts_with("refs/bisect/skip-") {
+            skipped.insert(hash);
+        }
+    }
+
+    let status = match (&bad, good.len()) {
+        (None, 0) => "waiting for both good and bad commits".to_string(),
+        (None, 1) => "waiting for bad commit, 1 good commit known".to_string(),
+        (None, count) => format!("waiting for bad commit, {count} good commits known"),
+        (Some(_), 0) => "waiting for good commit(s), bad commit known".to_string(),
+        (Some(bad), _) => return bisect_step(repo, bad, &good, &skipped, writer),
+    };
+    writeln!(writer, "status: {status}")?;
+    append_log(repo, &format!("# status: {status}\n"))?;
+    Ok(Outcome::Waiting)
+}
+
+fn bisect_step<W>(
+    repo: &Repository,
+    bad: &str,
+    good: &[String],
+    skipped: &HashSet<String>,
+    writer: &mut W,
+) -> anyhow::Result<Outcome>
+where
+    W: Write,
+{
+    // The commits that may be the first bad one, newest first
+    let mut walk = RevWalk::new(repo);
+    walk.push(bad)?;
+    for hash in good {
+        walk.hide(hash)?;
+    }
+    let candidates = walk.collect::<anyhow::Result<Vec<_>>>()?;
+    if candidates.is_empty() {
+        anyhow::bail!(
+            "Some good revs are not ancestors of the bad rev.\n\
+             git bisect cannot work properly in this case.\n\
+             Maybe you mistook good and bad revs?"
+        );
+    }
+
+    let parents: Vec<_> = candidates
+        .iter()
+        .map(|(hash, commit)| (hash.clone(), commit.parents.clone()))
+        .collect();
+    let Some((best, reaches)) = find_bisection(&parents, skipped) else {
+        let left: Vec<_> = parents
+            .iter()
+            .map(|(hash, _)| hash)
+            .filter(|hash| *hash == bad || skipped.contains(*hash))
+            .collect();
+        writeln!(writer, "There are only 'skip'ped commits left to test.")?;
+        writeln!(writer, "The first bad commit could be any of:")?;
+        for hash in left {
+            writeln!(writer, "{hash}")?;
+        }
+        writeln!(writer, "We cannot bisect more!")?;
+        return Ok(Outcome::OnlySkipped);
+    };
+
+    let commit = read_commit(repo, &best)?;
+    if best == bad {
+        writeln!(writer, "{best} is the first bad commit")?;
+        writeln!(writer, "commit {best}")?;
+        write_commit(&commit, &Mailmap::load(repo)?, writer)?;
+        append_log(
+            repo,
+            &format!("# first bad commit: [{best}] {}\n", commit.subject()),
+        )?;
+        return Ok(Outcome::Found);
+    }
+
+    switch(
+        repo,
+        Target::Detached(best.clone()),
+        LocalChanges::Carry,
+        &mut std::io::sink(),
+    )?;
+    let all = parents.len();
+    let left = all - reaches - 1;
+    let steps = estimate_steps(all);
+    writeln!(
+        writer,
+        "Bisecting: {left} revision{} left to test after this (roughly {steps} step{})",
+        if left == 1 { "" } else { "s" },
+        if steps == 1 { "" } else { "s" }
+    )?;
+    writeln!(writer, "[{best}] {}", commit.subject())?;
+    Ok(Outcome::Testing)
+}
+
+/// Find the commit splitting the candidates the most evenly: the one the
+/// closest to reaching half of them (itself included), as git does.
+///
+/// # Returns
+///
+/// The commit and how many candidates it reaches, or `None` if only
+/// the bad commit and skipped commits are left
+fn find_bisection(
+    candidates: &[(String, Vec<String>)],
+    skipped: &HashSet<String>,
+) -> Option<(String, usize)> {
+    let all = candidates.len();
+    let positions: HashMap<&str, usize> = candidates
+        .iter()
+        .enumerate()
+        .map(|(position, (hash, _))| (hash.as_str(), position))
+        .collect();
+    // The parents of each candidate that are candidates too
+    let parents: Vec<Vec<usize>> = candidates
+        .iter()
+        .map(|(_, parents)| {
+            parents
+                .iter()
+                .filter_map(|parent| positions.get(parent.as_str()).copied())
+                .collect()
+        })
+        .collect();
+    let halfway = |weight: usize| (2 * weight).abs_diff(all) <= 1;
+
+    // Commits without candidate parents reach themselves, merges are counted
+    // right away, and the others reach one more commit than their parent
+    let mut weights: Vec<Option<usize>> = parents
+        .iter()
+        .enumerate()
+        .map(|(position, own)| match own.len() {
+            0 => Some(1),
+            1 => None,
+            _ => Some(count_reachable(&parents, position)),
+        })
+        .collect();
+    while weights.iter().any(Option::is_none) {
+        for position in 0..all {
+            if weights[position].is_some() {
+                continue;
+            }
+            let Some(weight) = weights[parents[position][0]] else {
+                continue;
+            };
+            weights[position] = Some(weight + 1);
+            // Skipped commits are never chosen early, as one may be as good
+            if halfway(weight + 1) && skipped.is_empty() {
+                return Some((candidates[position].0.clone(), weight + 1));
+            }
+        }
+    }
+
+    // The first commit (the newest) wins between commits as close to halfway
+    let mut best: Option<(usize, usize)> = None;
+    for (position, weight) in weights.iter().map(|weight| weight.unwrap()).enumerate() {
+        if skipped.contains(&candidates[position].0) {
+            continue;
+        }
+        let distance = weight.min(all - weight);
+        if best.is_none_or(|(_, best)| distance > best) {
+            best = Some((position, distance));
+        }
+    }
+    // The bad commit (which reaches every candidate) is only the best one
+    // when every other candidate is skipped
+    let (position, _) = best?;
+    if position == 0 && all > 1 {
+        return None;
+    }
+    Some((candidates[position].0.clone(), weights[position].unwrap()))
+}
+
+/// Count the candidates reachable from a candidate (itself included).
+fn count_reachable(parents: &[Vec<usize>], position: usize) -> usize {
+    let mut reachable = HashSet::new();
+    let mut pending = vec![position];
+    while let Some(position) = pending.pop() {
+        if reachable.insert(position) {
+            pending.extend(&parents[position]);
+        }
+    }
+    reachable.len()
+}
+
+/// Estimate how many more commits have to be tested, as git does.
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = all.ilog2() as usize;
+    let e = 1 << n;
+    let x = all - e;
+    if e < 3 * x {
+        n
+    } else {
+        n - 1
+    }
+}
+
+/// Run a command on each commit to test, marking it as good if it succeeds,
+/// skipping it if it exits with 125, or marking it as bad if it fails with
+/// another code below 128, until the first bad commit is found.
+fn run<W>(repo: &Repository, command: &[String], writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    check_bisecting(repo)?;
+    let quoted: String = command
+        .iter()
+        .map(|arg| format!(" '{}'", arg.replace('\'', "'\\''")))
+        .collect();
+
+    loop {
+        writeln!(writer, "running {quoted}")?;
+        // The output of the command goes to the same stdout
+        writer.flush()?;
+        // The arguments follow the command, which may use the syntax of the shell
+        let status = repo
+            .command("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", command[0]))
+            .args(command)
+            .current_dir(repo.work_tree()?)
+            .status()
+            .context(format!("unable to run '{}'", command.join(" ")))?;
+        let term = match status.code() {
+            Some(0) => Term::Good,
+            Some(SKIP_EXIT_CODE) => Term::Skip,
+            Some(1..=127) => Term::Bad,
+            code => anyhow::bail!(
+                "bisect run failed: exit code {} from '{}' is < 0 or >= 128",
+                code.unwrap_or(-1),
+                command.join(" ")
+            ),
+        };
+
+        let head = resolve_commit(repo, "HEAD")?;
+        mark(repo, term, &head)?;
+        match next(repo, writer)? {
+            Outcome::Testing => continue,
+            Outcome::Found => {
+                writeln!(writer, "bisect found first bad commit")?;
+                return Ok(());
+            },
+            Outcome::OnlySkipped => anyhow::bail!("bisect run cannot continue any more"),
+            Outcome::Waiting => anyhow::bail!("bisect run failed: no good and bad commits yet"),
+        }
+    }
+}
+
+/// Check out the branch (or commit) the bisection started from, or the
+/// given commit, and forget the state of the bisection.
+fn reset<W>(repo: &Repository, commit: Option<String>, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let Ok(original) = fs::read_to_string(repo.git_dir()?.join(BISECT_START)) else {
+        writeln!(writer, "We are not bisecting.")?;
+        return Ok(());
+    };
+    let target = commit.unwrap_or_else(|| original.trim_end().to_string());
+    let target = match resolve_ref(repo, &format!("refs/heads/{target}"))? {
+        Some(_) => Target::Branch(target),
+        None => Target::Detached(target),
+    };
+    switch(repo, target, LocalChanges::Carry, writer)?;
+    clean_state(repo)
+}
+
+/// Remove the refs and the files of a bisection.
+fn clean_state(repo: &Repository) -> anyhow::Result<()> {
+    for (name, _) in list_refs(repo, "refs/bisect/")? {
+        delete_ref(repo, &name)?;
+    }
+    for file in [BISECT_START, BISECT_LOG] {
+        let path = repo.git_dir()?.join(file);
+        if path.exists() {
+            fs::remove_file(&path).context(format!("remove {file}"))?;
+        }
+    }
+    Ok(())
+}
+
+fn check_bisecting(repo: &Repository) -> anyhow::Result<()> {
+    if !repo.git_dir()?.join(BISECT_START).exists() {
+        anyhow::bail!("You need to start by \"git bisect start\"");
+    }
+    Ok(())
+}
+
+fn append_log(repo: &Repository, entry: &str) -> anyhow::Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(repo.git_dir()?.join(BISECT_LOG))
+        .and_then(|mut file| file.write_all(entry.as_bytes()))
+        .context("write BISECT_LOG")
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct BisectArgs {
+    #[command(subcommand)]
+    command: BisectCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum BisectCommand {
+    /// start a bisection, optionally with a bad commit followed by good ones
+    Start(StartArgs),
+    /// mark a commit (HEAD by default) as bad, containing the change looked for
+    Bad(MarkArgs),
+    /// mark commits (HEAD by default) as good, not containing the change looked for
+    Good(MarkArgs),
+    /// mark commits (HEAD by default) as not testable
+    Skip(MarkArgs),
+    /// end the bisection, checking out the original branch or the given commit
+    Reset(ResetArgs),
+    /// show the commands of the bisection and what they found
+    Log,
+    /// test the commits with a command until the first bad commit is found
+    /// (0 for good, 125 to skip, any other code below 128 for bad)
+    Run(RunArgs),
+}
+
+#[derive(Args, Debug)]
+struct StartArgs {
+    /// the bad commit, then the good commits
+    #[arg(value_name = "revision")]
+    revisions: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct MarkArgs {
+    /// the commits to mark
+    #[arg(value_name = "revision")]
+    revisions: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct ResetArgs {
+    /// the commit to check out instead of the original branch
+    commit: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// the command to run and its arguments
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A linear history of commits named by their position, newest first.
+    fn linear(count: usize) -> Vec<(String, Vec<String>)> {
+        (0..count)
+            .rev()
+            .map(|position| {
+                let parents = (position > 0).then(|| (position - 1).to_string());
+                (position.to_string(), parents.into_iter().collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_bisection_points() {
+        let none = HashSet::new();
+        // The oldest commit halfway is chosen, as git does
+        assert_eq!(
+            find_bisection(&linear(9), &none),
+            Some(("3".to_string(), 4))
+        );
+        assert_eq!(
+            find_bisection(&linear(2), &none),
+            Some(("0".to_string(), 1))
+        );
+        assert_eq!(
+            find_bisection(&linear(1), &none),
+            Some(("0".to_string(), 1))
+        );
+
+        // Skipped commits are not chosen, and the bad commit is not either
+        // if any other candidate is left
+        let skipped = HashSet::from(["3".to_string(), "4".to_string()]);
+        assert_eq!(
+            find_bisection(&linear(9), &skipped),
+            Some(("5".to_string(), 6))
+        );
+        let skipped = HashSet::from(["0".to_string()]);
+        assert_eq!(find_bisection(&linear(2), &skipped), None);
+
+        // A merge reaches the commits of both of its sides
+        let merge = vec![
+            ("m".to_string(), vec!["a".to_string(), "b".to_string()]),
+            ("a".to_string(), vec![]),
+            ("b".to_string(), vec![]),
+        ];
+        assert_eq!(find_bisection(&merge, &none), Some(("a".to_string(), 1)));
+
+        assert_eq!(estimate_steps(2), 0);
+        assert_eq!(estimate_steps(5), 1);
+        assert_eq!(estimate_steps(9), 2);
+        assert_eq!(estimate_steps(1000), 9);
+    }
+}
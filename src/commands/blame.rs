@@ -0,0 +1,655 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::commit::{read_commit, Ident};
+use crate::utils::date::format_iso;
+use crate::utils::diff::{diff_lines, split_lines};
+use crate::utils::mailmap::Mailmap;
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::pathspec::normalize;
+use crate::utils::refs::resolve_ref;
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::tree::find_entry;
+use crate::utils::work_tree::read_file;
+
+/// The hash the lines changed in the working tree are attributed to
+const NOT_COMMITTED: &str = "0000000000000000000000000000000000000000";
+
+impl CommandArgs for BlameArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let (revision, file) = match self.args.as_slice() {
+            [file] => (None, file),
+            [revision, file] => (Some(revision.as_str()), file),
+            _ => anyhow::bail!("usage: git blame [<options>] [<rev>] [--] <file>"),
+        };
+        let path = normalize(&repo.prefix()?, Path::new(file))?;
+
+        // Without a revision, the file is blamed as it is in the working tree
+        let (start, content, blob) = match revision {
+            Some(revision) => {
+                let commit = resolve_commit(repo, revision)?;
+                let blob = blob_at(repo, &commit, &path)?
+                    .context(format!("no such path {path} in {revision}"))?;
+                let content = read_object_of_type(repo, &blob, ObjectType::Blob)?;
+                (Some(commit), content, Some(blob))
+            },
+            None => {
+                let file_path = repo.work_tree()?.join(&path);
+                let metadata = std::fs::symlink_metadata(&file_path)
+                    .context(format!("Cannot lstat '{file}'"))?;
+                let content = read_file(&file_path, &metadata)?;
+                (resolve_ref(repo, "HEAD")?, content, None)
+            },
+        };
+
+        let lines = split_lines(&content);
+        let ranges = line_ranges(&self.ranges, lines.len(), &path)?;
+
+        let mut blame = Blame::new(
+            repo,
+            &path,
+            ranges.iter().flat_map(|&(start, end)| start..end),
+        );
+        match (&start, &blob) {
+            (Some(commit), Some(blob)) => {
+                let lines = blame.origins.keys().map(|&line| (line, line)).collect();
+                blame.assign(commit, blob, lines)?;
+            },
+            _ => blame.start_from_working_tree(start.as_deref(), &content)?,
+        }
+        let origins = blame.run()?;
+
+        let mailmap = Mailmap::load(repo)?;
+        let mut infos = HashMap::new();
+        for origin in origins.values() {
+            if !infos.contains_key(&origin.commit) {
+                let info =
+                    CommitInfo::load(repo, &mailmap, &origin.commit, &path, start.as_deref())?;
+                infos.insert(origin.commit.clone(), info);
+            }
+        }
+
+        let output = Output {
+            path: &path,
+            lines: &lines,
+            origins: &origins,
+            infos: &infos,
+        };
+        if self.porcelain {
+            output.write_porcelain(writer)
+        } else {
+            output.write(writer)
+        }
+    }
+}
+
+/// Parse the `-L` options into sorted and merged ranges of (0-based) line numbers,
+/// as the first line and the one after the last:
+/// `<start>,<end>`, `<start>,+<count>`, `<start>,-<count>` (the lines ending at `<start>`),
+/// `<start>` to the end of the file, and `,<end>` from its start.
+fn line_ranges(
+    options: &[String],
+    line_count: usize,
+    path: &str,
+) -> anyhow::Result<Vec<(usize, usize)>> {
+    if options.is_empty() {
+        return Ok(vec![(0, line_count)]);
+    }
+
+    let number = |value: &str| -> anyhow::Result<usize> {
+        value
+            .parse::<usize>()
+            .ok()
+            .filter(|&number| number > 0)
+            .context(format!("invalid line number '{value}'"))
+    };
+
+    let mut ranges = Vec::new();
+    for option in options {
+        let (start, end) = option.split_once(',').unwrap_or((option, ""));
+        let start = if start.is_empty() { 1 } else { number(start)? };
+        if start > line_count.max(1) {
+            anyhow::bail!("file {path} has only {line_count} lines");
+        }
+
+        let (start, end) = if let Some(count) = end.strip_prefix('+') {
+            (start, start + number(count)? - 1)
+        } else if let Some(count) = end.strip_prefix('-') {
+            ((start + 1).saturating_sub(number(count)?).max(1), start)
+        } else if end.is_empty() {
+            (start, line_count)
+        } else {
+            let end = number(end)?;
+            (start.min(end), start.max(end))
+        };
+        ranges.push((start - 1, end.min(line_count)));
+    }
+
+    ranges.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Ok(merged)
+}
+
+/// The hash of the blob at a path in the tree of a commit, if it exists.
+fn blob_at(repo: &Repository, commit: &str, path: &str) -> anyhow::Result<Option<String>> {
+    let tree = read_commit(repo, commit)?.tree;
+    let entry = find_entry(repo, &tree, path)?;
+    Ok(entry
+        .filter(|entry| !entry.is_tree())
+        .map(|entry| entry.hash))
+}
+
+/// Lines suspected of a commit: their final number, and
+/// their number in the commit's version of the file
+type Lines = Vec<(usize, usize)>;
+
+/// The commit a line was introduced by, and the (0-based) number
+/// of the line in the version of the file in that commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Origin {
+    commit: String,
+    line: usize,
+}
+
+/// Passes the lines of a file from each commit to its parents, as long as the parents have
+/// them too, so each line ends up with the oldest commit in which it was last changed.
+///
+/// Commits are visited newest first (by committer date), so all the lines
+/// a commit is suspected of are collected from its children before it is visited.
+struct Blame<'a> {
+    repo: &'a Repository,
+    path: &'a str,
+    /// The lines suspected of each queued commit
+    pending: HashMap<String, Lines>,
+    /// The blob of the file in each queued commit
+    blobs: HashMap<String, String>,
+    /// The commits to visit, ordered by committer date and then by insertion order
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    sequence: usize,
+    /// The origin found for each blamed (final) line, `None` until it is found
+    origins: HashMap<usize, Option<Origin>>,
+}
+
+impl<'a> Blame<'a> {
+    fn new<I>(repo: &'a Repository, path: &'a str, lines: I) -> Self
+    where
+        I: Iterator<Item = usize>,
+    {
+        Blame {
+            repo,
+            path,
+            pending: HashMap::new(),
+            blobs: HashMap::new(),
+            queue: BinaryHeap::new(),
+            sequence: 0,
+            origins: lines.map(|line| (line, None)).collect(),
+        }
+    }
+
+    /// Suspect a commit of some lines, queuing it if it was not already.
+    fn assign(&mut self, commit: &str, blob: &str, lines: Lines) -> anyhow::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        if !self.pending.contains_key(commit) {
+            let committer = read_commit(self.repo, commit)?.committer;
+            let date = Ident::parse(&committer).map_or(0, |ident| ident.timestamp);
+            self.queue
+                .push((date, Reverse(self.sequence), commit.to_string()));
+            self.sequence += 1;
+            self.blobs.insert(commit.to_string(), blob.to_string());
+        }
+        self.pending
+            .entry(commit.to_string())
+            .or_default()
+            .extend(lines);
+        Ok(())
+    }
+
+    /// Attribute the lines of the working tree file that differ from the committed
+    /// one (if any) to [`NOT_COMMITTED`], and suspect the commit of the others.
+    fn start_from_working_tree(
+        &mut self,
+        commit: Option<&str>,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let mut lines: Vec<_> = self.origins.keys().map(|&line| (line, line)).collect();
+        let committed = match commit {
+            Some(commit) => blob_at(self.repo, commit, self.path)?.map(|blob| (commit, blob)),
+            None => None,
+        };
+        if let Some((commit, blob)) = committed {
+            let committed = read_object_of_type(self.repo, &blob, ObjectType::Blob)?;
+            let passed;
+            (passed, lines) = pass_lines(&committed, content, lines);
+            self.assign(commit, &blob, passed)?;
+        }
+        self.blame(NOT_COMMITTED, lines);
+        Ok(())
+    }
+
+    fn blame(&mut self, commit: &str, lines: Lines) {
+        for (line, origin) in lines {
+            self.origins.insert(
+                line,
+                Some(Origin {
+                    commit: commit.to_string(),
+                    line: origin,
+                }),
+            );
+        }
+    }
+
+    /// Visit the queued commits until every line is attributed.
+    ///
+    /// # Returns
+    ///
+    /// The origin of each blamed line, by final line number
+    fn run(mut self) -> anyhow::Result<HashMap<usize, Origin>> {
+        while let Some((_, _, commit)) = self.queue.pop() {
+            let mut lines = self.pending.remove(&commit).unwrap_or_default();
+            let blob = self.blobs[&commit].clone();
+
+            let mut parents = Vec::new();
+            for parent in read_commit(self.repo, &commit)?.parents {
+                if let Some(parent_blob) = blob_at(self.repo, &parent, self.path)? {
+                    parents.push((parent, parent_blob));
+                }
+            }
+
+            // A parent with the same version of the file takes all the lines
+            if let Some((parent, parent_blob)) = parents.iter().find(|(_, b)| *b == blob) {
+                let (parent, parent_blob) = (parent.clone(), parent_blob.clone());
+                self.assign(&parent, &parent_blob, lines)?;
+                continue;
+            }
+
+            // Otherwise each parent takes the lines it has, in order
+            let content = read_object_of_type(self.repo, &blob, ObjectType::Blob)?;
+            for (parent, parent_blob) in parents {
+                if lines.is_empty() {
+                    break;
+                }
+                let parent_content =
+                    read_object_of_type(self.repo, &parent_blob, ObjectType::Blob)?;
+                let passed;
+                (passed, lines) = pass_lines(&parent_content, &content, lines);
+                self.assign(&parent, &parent_blob, passed)?;
+            }
+            self.blame(&commit, lines);
+        }
+
+        self.origins
+            .into_iter()
+            .map(|(line, origin)| Ok((line, origin.context("line was not attributed")?)))
+            .collect()
+    }
+}
+
+/// Split the lines suspected of a version of a file between
+/// those unchanged from an older version and the others.
+///
+/// # Returns
+///
+/// The unchanged lines, numbered in the older version,
+/// and the changed ones, numbered in the newer version
+fn pass_lines(old: &[u8], new: &[u8], lines: Lines) -> (Lines, Lines) {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+
+    // The number of each new line in the old version, if unchanged
+    let mut unchanged = vec![None; new_lines.len()];
+    let (mut old_line, mut new_line) = (0, 0);
+    for change in diff_lines(&old_lines, &new_lines) {
+        while new_line < change.new_start {
+            unchanged[new_line] = Some(old_line);
+            old_line += 1;
+            new_line += 1;
+        }
+        old_line = change.old_start + change.old_len;
+        new_line = change.new_start + change.new_len;
+    }
+    while new_line < new_lines.len() {
+        unchanged[new_line] = Some(old_line);
+        old_line += 1;
+        new_line += 1;
+    }
+
+    let mut passed = Vec::new();
+    let mut kept = Vec::new();
+    for (line, suspect_line) in lines {
+        match unchanged[suspect_line] {
+            Some(old_line) => passed.push((line, old_line)),
+            None => kept.push((line, suspect_line)),
+        }
+    }
+    (passed, kept)
+}
+
+/// What is shown of a commit lines are attributed to
+struct CommitInfo {
+    author: Ident,
+    committer: Ident,
+    summary: String,
+    /// Whether the commit has no parents, so its lines may be older
+    boundary: bool,
+    /// The first parent with a version of the file
+    previous: Option<String>,
+}
+
+impl CommitInfo {
+    /// Load the information of a commit (with the canonical names and emails of its
+    /// author and committer), or describe the working tree (based on the blamed
+    /// commit) for [`NOT_COMMITTED`].
+    fn load(
+        repo: &Repository,
+        mailmap: &Mailmap,
+        hash: &str,
+        path: &str,
+        start: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let (parents, author, committer, summary) = if hash == NOT_COMMITTED {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let ident = Ident {
+                name: b"Not Committed Yet".to_vec(),
+                email: b"not.committed.yet".to_vec(),
+                timestamp: now.as_secs() as i64,
+                offset: "+0000".to_string(),
+            };
+            let parents = start.map(str::to_string).into_iter().collect();
+            let summary = format!("Version of {path} from {path}");
+            (parents, ident.clone(), ident, summary)
+        } else {
+            let commit = read_commit(repo, hash)?;
+            let mut author = Ident::parse(&commit.author)?;
+            let mut committer = Ident::parse(&commit.committer)?;
+            mailmap.map_ident(&mut author);
+            mailmap.map_ident(&mut committer);
+            let summary = commit.subject();
+            (commit.parents, author, committer, summary)
+        };
+
+        let mut previous = None;
+        for parent in &parents {
+            if blob_at(repo, parent, path)?.is_some() {
+                previous = Some(parent.clone());
+                break;
+            }
+        }
+
+        Ok(CommitInfo {
+            author,
+            committer,
+            summary,
+            boundary: parents.is_empty() && hash != NOT_COMMITTED,
+            previous,
+        })
+    }
+}
+
+/// The blamed lines of a file and what they are attributed to
+struct Output<'a> {
+    path: &'a str,
+    lines: &'a [&'a [u8]],
+    origins: &'a HashMap<usize, Origin>,
+    infos: &'a HashMap<String, CommitInfo>,
+}
+
+impl Output<'_> {
+    /// The blamed lines, in order, with their origin.
+    fn blamed(&self) -> Vec<(usize, &Origin)> {
+        let mut blamed: Vec<_> = self
+            .origins
+            .iter()
+            .map(|(&line, origin)| (line, origin))
+            .collect();
+        blamed.sort_by_key(|(line, _)| *line);
+        blamed
+    }
+
+    /// Write each line prefixed with its commit, author, date and number.
+    fn write<W>(&self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let blamed = self.blamed();
+        let author_width = self
+            .infos
+            .values()
+            .map(|info| String::from_utf8_lossy(&info.author.name).chars().count())
+            .max()
+            .unwrap_or(0);
+        let number_width = blamed
+            .last()
+            .map_or(1, |(line, _)| (line + 1).to_string().len());
+
+        for (line, origin) in blamed {
+            let info = &self.infos[&origin.commit];
+            // Boundary commits are marked with a `^`, keeping the columns aligned
+            let hash = if info.boundary {
+                format!("^{}", &origin.commit[..7])
+            } else {
+                origin.commit[..8].to_string()
+            };
+            let author = String::from_utf8_lossy(&info.author.name);
+            let date = format_iso(info.author.timestamp, &info.author.offset);
+
+            write!(
+                writer,
+                "{hash} ({author:<author_width$} {date} {:>number_width$}) ",
+                line + 1
+            )?;
+            write_line(writer, self.lines[line])?;
+        }
+        Ok(())
+    }
+
+    /// Write the lines in a format meant for scripts: groups of consecutive lines from the
+    /// same commit start with a header, followed by the commit details the first time.
+    fn write_porcelain<W>(&self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let blamed = self.blamed();
+        let mut shown = HashSet::new();
+
+        let mut index = 0;
+        while index < blamed.len() {
+            let (line, origin) = blamed[index];
+            let group = blamed[index..]
+                .iter()
+                .enumerate()
+                .take_while(|(offset, (other_line, other))| {
+                    other.commit == origin.commit
+                        && *other_line == line + offset
+                        && other.line == origin.line + offset
+                })
+                .count();
+
+            for (offset, &(line, origin)) in blamed[index..index + group].iter().enumerate() {
+                write!(writer, "{} {} {}", origin.commit, origin.line + 1, line + 1)?;
+                if offset == 0 {
+                    write!(writer, " {group}")?;
+                }
+                writeln!(writer)?;
+
+                if offset == 0 && shown.insert(&origin.commit) {
+                    self.write_details(writer, &self.infos[&origin.commit])?;
+                }
+                writer.write_all(b"\t")?;
+                write_line(writer, self.lines[line])?;
+            }
+            index += group;
+        }
+        Ok(())
+    }
+
+    fn write_details<W>(&self, writer: &mut W, info: &CommitInfo) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        for (role, ident) in [("author", &info.author), ("committer", &info.committer)] {
+            writer.write_all(format!("{role} ").as_bytes())?;
+            writer.write_all(&ident.name)?;
+            writer.write_all(format!("\n{role}-mail <").as_bytes())?;
+            writer.write_all(&ident.email)?;
+            writeln!(writer, ">")?;
+            writeln!(writer, "{role}-time {}", ident.timestamp)?;
+            writeln!(writer, "{role}-tz {}", ident.offset)?;
+        }
+        writeln!(writer, "summary {}", info.summary)?;
+        if info.boundary {
+            writeln!(writer, "boundary")?;
+        }
+        if let Some(previous) = &info.previous {
+            writeln!(writer, "previous {previous} {}", self.path)?;
+        }
+        writeln!(writer, "filename {}", self.path)?;
+        Ok(())
+    }
+}
+
+/// Write a line of the file, terminating it if it is the last one and is not.
+fn write_line<W>(writer: &mut W, line: &[u8]) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    writer.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct BlameArgs {
+    /// only blame the lines in the range <start>,<end> (or <start>,+<count>)
+    #[arg(short = 'L', value_name = "range")]
+    ranges: Vec<String>,
+    /// show the output in a format meant for scripts
+    #[arg(long)]
+    porcelain: bool,
+    /// the revision to start from (the working tree by default), and the file to blame
+    #[arg(value_name = "rev", num_args = 1..=2, required = true)]
+    args: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository, String, String) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let commit = |content: &str, parents: Vec<String>, message: &str| {
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            let mut index = Index::default();
+            index.add_entry(IndexEntry::new("file.txt".into(), 0o100644, hash));
+            let tree = index.write_tree(&repo, false).unwrap();
+            create_commit(&repo, &tree, parents, message).unwrap()
+        };
+        let first = commit("one\ntwo\nthree\n", Vec::new(), "first\n");
+        let second = commit("one\n2\nthree\nfour\n", vec![first.clone()], "second\n");
+        write_ref(&repo, "refs/heads/main", &second).unwrap();
+        fs::write(temp.join("file.txt"), "one\n2\nthree\nfour\n").unwrap();
+        (temp, repo, first, second)
+    }
+
+    fn blame(repo: &Repository, ranges: &[&str], porcelain: bool, args: &[&str]) -> String {
+        let args = BlameArgs {
+            ranges: ranges.iter().map(|range| range.to_string()).collect(),
+            porcelain,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+        let mut output = Vec::new();
+        args.run(repo, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn blames_lines_on_the_commits_that_changed_them() {
+        let (_temp, repo, first, second) = setup();
+        let date = "2023-11-14 22:13:20 +0000";
+
+        assert_eq!(
+            blame(&repo, &[], false, &["HEAD", "file.txt"]),
+            format!(
+                "^{} (A U Thor {date} 1) one\n\
+                 {} (A U Thor {date} 2) 2\n\
+                 ^{} (A U Thor {date} 3) three\n\
+                 {} (A U Thor {date} 4) four\n",
+                &first[..7],
+                &second[..8],
+                &first[..7],
+                &second[..8]
+            )
+        );
+
+        let output = blame(&repo, &["3,+2"], true, &["file.txt"]);
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], format!("{first} 3 3 1"));
+        assert_eq!(lines[1], "author A U Thor");
+        assert_eq!(lines[9], "summary first");
+        assert_eq!(lines[10], "boundary");
+        assert_eq!(lines[11], "filename file.txt");
+        assert_eq!(lines[12], "\tthree");
+        assert_eq!(lines[13], format!("{second} 4 4 1"));
+        assert_eq!(lines[23], format!("previous {first} file.txt"));
+        assert_eq!(lines[25], "\tfour");
+    }
+
+    #[test]
+    fn blames_working_tree_changes_on_no_commit() {
+        let (temp, repo, _, second) = setup();
+        fs::write(temp.join("file.txt"), "one\n2\nnew\nthree\nfour\n").unwrap();
+
+        let output = blame(&repo, &["2,3"], false, &["file.txt"]);
+        let lines: Vec<_> = output.lines().collect();
+        assert!(lines[0].starts_with(&format!("{} (A U Thor          ", &second[..8])));
+        assert!(lines[1].starts_with("00000000 (Not Committed Yet "));
+        assert!(lines[1].ends_with(" 3) new"));
+
+        let args = BlameArgs {
+            ranges: vec!["9".to_string()],
+            porcelain: false,
+            args: vec!["file.txt".to_string()],
+        };
+        let err = args.run(&repo, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "file file.txt has only 5 lines");
+    }
+}
@@ -0,0 +1,316 @@
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::{worktree, CommandArgs};
+use crate::utils::refs::{
+    delete_ref, is_valid_ref_name, resolve_ref, shorten_ref, update_ref, RefCache,
+};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::walk::{reachable_commits, MergeFilter};
+
+impl CommandArgs for BranchArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.delete || self.force_delete {
+            if self.names.is_empty() {
+                anyhow::bail!("branch name required");
+            }
+            return delete_branches(repo, &self.names, self.force_delete, writer);
+        }
+
+        match self.names.as_slice() {
+            [] => list_branches(repo, &self, writer),
+            [name] => create_branch(repo, name, "HEAD"),
+            [name, start_point] => create_branch(repo, name, start_point),
+            _ => anyhow::bail!("too many arguments for a create operation"),
+        }
+    }
+}
+
+/// List the local branches, marking the current one with `*`
+/// and those checked out in other worktrees with `+`.
+fn list_branches<W>(repo: &Repository, args: &BranchArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let filter = MergeFilter::new(repo, args.merged.as_deref(), args.no_merged.as_deref())?;
+    let mut refs = RefCache::new(repo);
+    let current = refs.head_branch()?;
+    let worktree_branches: Vec<_> = worktree::list(repo)?
+        .into_iter()
+        .filter_map(|worktree| worktree.branch)
+        .collect();
+
+    if current.is_none() {
+        if let Some(head) = refs.resolve("HEAD")? {
+            if filter.matches(repo, &head)? {
+                writeln!(writer, "* (HEAD detached at {})", &head[..7])?;
+            }
+        }
+    }
+
+    for (name, hash) in refs.list("refs/heads/")? {
+        if !filter.matches(repo, &hash)? {
+            continue;
+        }
+
+        // Symbolic refs are shown with the branch they point to, never as current
+        let target = refs.resolve_symref(&name)?.name;
+        if target != name {
+            writeln!(
+                writer,
+                "  {} -> {}",
+                shorten_ref(&name),
+                shorten_ref(&target)
+            )?;
+            continue;
+        }
+
+        let marker = if current.as_ref() == Some(&name) {
+            '*'
+        } else if worktree_branches.contains(&name) {
+            '+'
+        } else {
+            ' '
+        };
+        writeln!(writer, "{marker} {}", shorten_ref(&name))?;
+    }
+
+    Ok(())
+}
+
+/// Create a branch pointing at a commit.
+fn create_branch(repo: &Repository, name: &str, start_point: &str) -> anyhow::Result<()> {
+    let branch_ref = format!("refs/heads/{name}");
+    if !is_valid_ref_name(&branch_ref) {
+        anyhow::bail!("'{name}' is not a valid branch name");
+    }
+    if resolve_ref(repo, &branch_ref)?.is_some() {
+        anyhow::bail!("a branch named '{name}' already exists");
+    }
+
+    let commit = resolve_commit(repo, start_point)?;
+    update_ref(
+        repo,
+        &branch_ref,
+        &commit,
+        &format!("branch: Created from {start_point}"),
+    )
+}
+
+/// Delete branches. Unless forced, a branch must be merged into HEAD.
+fn delete_branches<W>(
+    repo: &Repository,
+    names: &[String],
+    force: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let worktrees = worktree::list(repo)?;
+    let merged = match resolve_ref(repo, "HEAD")? {
+        Some(head) if !force => reachable_commits(repo, &head)?,
+        _ => Default::default(),
+    };
+
+    for name in names {
+        let branch_ref = format!("refs/heads/{name}");
+        let Some(hash) = resolve_ref(repo, &branch_ref)? else {
+            anyhow::bail!("branch '{name}' not found");
+        };
+
+        if let Some(worktree) = worktrees
+            .iter()
+            .find(|worktree| worktree.branch.as_ref() == Some(&branch_ref))
+        {
+            anyhow::bail!(
+                "cannot delete branch '{name}' used by worktree at '{}'",
+                worktree.path.display()
+            );
+        }
+
+        if !force && !merged.contains(&hash) {
+            anyhow::bail!(
+                "The branch '{name}' is not fully merged.\n\
+                 If you are sure you want to delete it, run 'git branch -D {name}'."
+            );
+        }
+
+        delete_ref(repo, &branch_ref)?;
+        writeln!(writer, "Deleted branch {name} (was {}).", &hash[..7])?;
+    }
+
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct BranchArgs {
+    /// delete fully merged branches
+    #[arg(short, long)]
+    delete: bool,
+    /// delete branches, even if they are not merged
+    #[arg(short = 'D')]
+    force_delete: bool,
+    /// only list branches whose tips are reachable from the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    merged: Option<String>,
+    /// only list branches whose tips are not reachable from the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    no_merged: Option<String>,
+    /// the branch to create (optionally followed by its start point) or the branches to delete
+    #[arg(value_name = "branch")]
+    names: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    /// Create a repository with `main` at a commit, `merged` at its parent
+    /// and `side` on a separate line of history.
+    fn setup() -> (TempRepo, Repository, [String; 3]) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+
+        let commit = |parent: Option<&str>, message: &str| {
+            let parent = parent.map(|p| format!("parent {p}\n")).unwrap_or_default();
+            let content = format!(
+                "tree {TREE}\n{parent}author a <a@b> 0 +0000\ncommitter a <a@b> 0 +0000\n\n{message}\n"
+            );
+            write_object(&repo, ObjectType::Commit, content.as_bytes()).unwrap()
+        };
+        let base = commit(None, "base");
+        let tip = commit(Some(&base), "tip");
+        let side = commit(None, "side");
+
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        write_ref(&repo, "refs/heads/main", &tip).unwrap();
+        write_ref(&repo, "refs/heads/merged", &base).unwrap();
+        write_ref(&repo, "refs/heads/side", &side).unwrap();
+
+        (temp, repo, [base, tip, side])
+    }
+
+    fn args(names: &[&str]) -> BranchArgs {
+        BranchArgs {
+            delete: false,
+            force_delete: false,
+            merged: None,
+            no_merged: None,
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn branch(repo: &Repository, args: BranchArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn lists_branches() {
+        let (_temp, repo, [base, ..]) = setup();
+        assert_eq!(
+            branch(&repo, args(&[])).unwrap(),
+            "* main\n  merged\n  side\n"
+        );
+
+        // HEAD may point to the current branch through another symbolic ref
+        write_symref(&repo, "refs/heads/alias", "refs/heads/main").unwrap();
+        write_symref(&repo, "HEAD", "refs/heads/alias").unwrap();
+        assert_eq!(
+            branch(&repo, args(&[])).unwrap(),
+            "  alias -> main\n* main\n  merged\n  side\n"
+        );
+
+        write_ref(&repo, "HEAD", &base).unwrap();
+        assert_eq!(
+            branch(&repo, args(&[])).unwrap(),
+            format!(
+                "* (HEAD detached at {})\n  alias -> main\n  main\n  merged\n  side\n",
+                &base[..7]
+            )
+        );
+    }
+
+    #[test]
+    fn filters_merged_branches() {
+        let (_temp, repo, _) = setup();
+
+        let output = branch(&repo, BranchArgs {
+            merged: Some("HEAD".to_string()),
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "* main\n  merged\n");
+
+        let output = branch(&repo, BranchArgs {
+            no_merged: Some("HEAD".to_string()),
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "  side\n");
+
+        let output = branch(&repo, BranchArgs {
+            merged: Some("merged".to_string()),
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "  merged\n");
+    }
+
+    #[test]
+    fn creates_branches() {
+        let (_temp, repo, [base, tip, _]) = setup();
+
+        branch(&repo, args(&["new"])).unwrap();
+        branch(&repo, args(&["feature/old", "merged"])).unwrap();
+        assert_eq!(resolve_ref(&repo, "refs/heads/new").unwrap(), Some(tip));
+        assert_eq!(
+            resolve_ref(&repo, "refs/heads/feature/old").unwrap(),
+            Some(base)
+        );
+
+        assert!(branch(&repo, args(&["new"])).is_err());
+        assert!(branch(&repo, args(&["a..b"])).is_err());
+    }
+
+    #[test]
+    fn deletes_merged_branches() {
+        let (_temp, repo, [base, ..]) = setup();
+        let delete = |names: &[&str], force: bool| BranchArgs {
+            delete: !force,
+            force_delete: force,
+            ..args(names)
+        };
+
+        assert_eq!(
+            branch(&repo, delete(&["merged"], false)).unwrap(),
+            format!("Deleted branch merged (was {}).\n", &base[..7])
+        );
+
+        let err = branch(&repo, delete(&["side"], false)).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("The branch 'side' is not fully merged."));
+        assert!(branch(&repo, delete(&["side"], true)).is_ok());
+
+        assert!(branch(&repo, delete(&["main"], true)).is_err());
+        assert!(branch(&repo, delete(&["missing"], true)).is_err());
+        assert_eq!(branch(&repo, args(&[])).unwrap(), "* main\n");
+    }
+}
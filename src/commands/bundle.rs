@@ -0,0 +1,526 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+use crate::commands::CommandArgs;
+use crate::utils::bundle::Bundle;
+use crate::utils::commit::{peel, read_commit, Tag};
+use crate::utils::objects::{object_exists, read_original_object, write_object, ObjectType};
+use crate::utils::pack::{read_pack, write_pack};
+use crate::utils::refs::{list_refs, read_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::{ref_candidates, resolve_commit, resolve_revision};
+use crate::utils::tree::{read_tree, MODE_GITLINK};
+use crate::utils::walk::{reachable_commits, RevWalk};
+
+impl CommandArgs for BundleArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.command {
+            BundleCommand::Create(args) => create_bundle(repo, args, writer),
+            BundleCommand::Verify(args) => verify_bundle(repo, args, writer),
+            BundleCommand::ListHeads(args) => list_heads(args, writer),
+            BundleCommand::Unbundle(args) => unbundle(repo, &args.file, writer),
+        }
+    }
+}
+
+fn create_bundle<W>(repo: &Repository, args: CreateArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    // The revisions are split into the tips to include and the commits to exclude
+    let mut tips = Vec::new();
+    let mut excluded = Vec::new();
+    for revision in &args.revisions {
+        let prefix = match revision.as_str() {
+            "--all" => Some("refs/"),
+            "--branches" => Some("refs/heads/"),
+            "--tags" => Some("refs/tags/"),
+            "--remotes" => Some("refs/remotes/"),
+            _ => None,
+        };
+        if let Some(prefix) = prefix {
+            for (name, _) in list_refs(repo, prefix)? {
+                tips.push(name);
+            }
+            if revision == "--all" {
+                tips.push("HEAD".to_string());
+            }
+        } else if let Some(revision) = revision.strip_prefix('^') {
+            excluded.push(resolve_commit(repo, revision)?);
+        } else if let Some((from, to)) = revision.split_once("..") {
+            excluded.push(resolve_commit(repo, or_head(from))?);
+            tips.push(or_head(to).to_string());
+        } else {
+            tips.push(revision.clone());
+        }
+    }
+
+    let mut hidden = HashSet::new();
+    for hash in &excluded {
+        hidden.extend(reachable_commits(repo, hash)?);
+    }
+
+    // Only the tips that are refs are recorded, unless they are excluded. Tags
+    // are kept when the commit they point to is excluded (e.g. an old release
+    // in an incremental bundle), as the tag itself is not in the basis.
+    let mut walk = RevWalk::new(repo);
+    let mut refs: Vec<(String, String)> = Vec::new();
+    let mut tip_objects = Vec::new();
+    for tip in &tips {
+        let hash = resolve_revision(repo, tip)?;
+        let (peeled, object_type) = peel(repo, &hash)?;
+        if object_type == ObjectType::Commit && hidden.contains(&peeled) {
+            if hash == peeled {
+                continue;
+            }
+        } else if object_type == ObjectType::Commit {
+            walk.push(&peeled)?;
+        }
+        tip_objects.push(hash.clone());
+
+        let name = ref_candidates(tip)
+            .into_iter()
+            .find(|name| read_ref(repo, name).is_ok_and(|value| value.is_some()));
+        if let Some(name) = name {
+            if !refs.iter().any(|(_, existing)| *existing == name) {
+                refs.push((hash, name));
+            }
+        }
+    }
+    for hash in &excluded {
+        walk.hide(hash)?;
+    }
+
+    if refs.is_empty() {
+        anyhow::bail!("Refusing to create empty bundle.");
+    }
+
+    // The excluded parents of the included commits are the prerequisites
+    let mut commits = Vec::new();
+    let mut prerequisites: Vec<(String, String)> = Vec::new();
+    for entry in walk {
+        let (hash, commit) = entry?;
+        for parent in &commit.parents {
+            if hidden.contains(parent) && !prerequisites.iter().any(|(hash, _)| hash == parent) {
+                let subject = read_commit(repo, parent)?.subject();
+                prerequisites.push((parent.clone(), subject));
+            }
+        }
+        commits.push((hash, commit));
+    }
+
+    // The trees and blobs of the prerequisites are not stored again
+    let mut known = HashSet::new();
+    for (hash, _) in &prerequisites {
+        let tree = read_commit(repo, hash)?.tree;
+        collect_tree(repo, &tree, &mut known, &mut Vec::new())?;
+    }
+
+    let mut objects = Vec::new();
+    for hash in tip_objects {
+        collect_tag(repo, &hash, &mut known, &mut objects)?;
+    }
+    for (hash, commit) in &commits {
+        objects.push(hash.clone());
+        collect_tree(repo, &commit.tree, &mut known, &mut objects)?;
+    }
+
+    let mut pack = Vec::new();
+    write_pack(repo, &objects, &mut pack)?;
+    let bundle = Bundle {
+        prerequisites,
+        refs,
+        pack,
+    };
+
+    if args.file == Path::new("-") {
+        bundle.write(writer)
+    } else {
+        let file = File::create(&args.file).context(format!("create {}", args.file.display()))?;
+        let mut file = BufWriter::new(file);
+        bundle.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Collect a tag object (and the tags it points to), along with the
+/// trees and blobs they point to; commits are collected by the walk.
+fn collect_tag(
+    repo: &Repository,
+    hash: &str,
+    known: &mut HashSet<String>,
+    objects: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let (object_type, content) = read_original_object(repo, hash)?;
+    match object_type {
+        ObjectType::Tag => {
+            if known.insert(hash.to_string()) {
+                objects.push(hash.to_string());
+                collect_tag(repo, &Tag::parse(&content)?.object, known, objects)?;
+            }
+        },
+        ObjectType::Tree => collect_tree(repo, hash, known, objects)?,
+        ObjectType::Blob => {
+            if known.insert(hash.to_string()) {
+                objects.push(hash.to_string());
+            }
+        },
+        ObjectType::Commit => {},
+    }
+    Ok(())
+}
+
+/// Collect a tree and everything below it, skipping the objects already known.
+fn collect_tree(
+    repo: &Repository,
+    hash: &str,
+    known: &mut HashSet<String>,
+    objects: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if !known.insert(hash.to_string()) {
+        return Ok(());
+    }
+    objects.push(hash.to_string());
+
+    for entry in read_tree(repo, hash)? {
+        if entry.is_tree() {
+            collect_tree(repo, &entry.hash, known, objects)?;
+        } else if entry.mode != MODE_GITLINK && known.insert(entry.hash.clone()) {
+            objects.push(entry.hash);
+        }
+    }
+    Ok(())
+}
+
+/// An empty side of a range (`..main`, `main..`) stands for HEAD.
+fn or_head(revision: &str) -> &str {
+    if revision.is_empty() {
+        "HEAD"
+    } else {
+        revision
+    }
+}
+
+/// Read a bundle and check that the repository has its prerequisites.
+fn read_verified(repo: &Repository, path: &Path) -> anyhow::Result<Bundle> {
+    let bundle = Bundle::read(path)?;
+    let missing = bundle.missing_prerequisites(repo)?;
+    if !missing.is_empty() {
+        let missing: Vec<_> = missing
+            .iter()
+            .map(|(hash, comment)| format!("{hash} {comment}"))
+            .collect();
+        anyhow::bail!(
+            "Repository lacks these prerequisite commits:\n{}",
+            missing.join("\n")
+        );
+    }
+    Ok(bundle)
+}
+
+fn verify_bundle<W>(repo: &Repository, args: VerifyArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let bundle = read_verified(repo, &args.file)?;
+
+    if !args.quiet {
+        let count = |n: usize| {
+            if n == 1 {
+                "this ref".to_string()
+            } else {
+                format!("these {n} refs")
+            }
+        };
+        writeln!(writer, "The bundle contains {}:", count(bundle.refs.len()))?;
+        for (hash, name) in &bundle.refs {
+            writeln!(writer, "{hash} {name}")?;
+        }
+        if bundle.prerequisites.is_empty() {
+            writeln!(writer, "The bundle records a complete history.")?;
+        } else {
+            let n = bundle.prerequisites.len();
+            writeln!(writer, "The bundle requires {}:", count(n))?;
+            for (hash, comment) in &bundle.prerequisites {
+                writeln!(writer, "{hash} {comment}")?;
+            }
+        }
+        writeln!(writer, "The bundle uses this hash algorithm: sha1")?;
+    }
+
+    eprintln!("{} is okay", args.file.display());
+    Ok(())
+}
+
+fn list_heads<W>(args: ListHeadsArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let bundle = Bundle::read(&args.file)?;
+    for (hash, name) in &bundle.refs {
+        let listed = args.refnames.is_empty()
+            || args
+                .refnames
+                .iter()
+                .any(|refname| ref_candidates(refname).contains(name));
+        if listed {
+            writeln!(writer, "{hash} {name}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Store the objects of a bundle and list its refs, leaving the refs to update to the caller.
+fn unbundle<W>(repo: &Repository, path: &Path, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let bundle = read_verified(repo, path)?;
+    for (object_type, content) in read_pack(repo, &bundle.pack)? {
+        write_object(repo, object_type, &content)?;
+    }
+
+    // Every ref must now be complete, down to the prerequisites
+    for (hash, name) in &bundle.refs {
+        if !object_exists(repo, hash)? {
+            anyhow::bail!("bundle is missing object {hash} of {name}");
+        }
+        writeln!(writer, "{hash} {name}")?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct BundleArgs {
+    #[command(subcommand)]
+    command: BundleCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum BundleCommand {
+    /// create a bundle of the history selected by the revisions
+    Create(CreateArgs),
+    /// check that a bundle is valid and can be applied to the repository
+    Verify(VerifyArgs),
+    /// list the refs of a bundle
+    ListHeads(ListHeadsArgs),
+    /// store the objects of a bundle in the repository and list its refs
+    Unbundle(UnbundleArgs),
+}
+
+#[derive(Args, Debug)]
+struct CreateArgs {
+    /// the bundle file to write (- for standard output)
+    file: PathBuf,
+    /// the refs to include (--all for every ref, --branches, --tags or --remotes for a kind of
+    /// refs), and ^<rev> or <rev>..<rev> to exclude history
+    #[arg(
+        value_name = "rev-list-args",
+        required = true,
+        allow_hyphen_values = true
+    )]
+    revisions: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// only report whether the bundle is valid
+    #[arg(short, long)]
+    quiet: bool,
+    /// the bundle file to verify
+    file: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct ListHeadsArgs {
+    /// the bundle file
+    file: PathBuf,
+    /// only list these refs
+    #[arg(value_name = "refname")]
+    refnames: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct UnbundleArgs {
+    /// the bundle file
+    file: PathBuf,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, create_tag};
+    use crate::utils::env;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    /// Commit a file on top of the current commit of `main`.
+    fn commit(repo: &Repository, content: &str) -> String {
+        let blob = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+        let mut tree = b"100644 file\0".to_vec();
+        tree.extend(crate::utils::hex::decode(blob.as_bytes()).unwrap());
+        let tree = write_object(repo, ObjectType::Tree, &tree).unwrap();
+
+        let parents = resolve_revision(repo, "main").into_iter().collect();
+        let hash = create_commit(repo, &tree, parents, &format!("{content}\n")).unwrap();
+        write_ref(repo, "refs/heads/main", &hash).unwrap();
+        hash
+    }
+
+    fn run(repo: &Repository, command: BundleCommand) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        BundleArgs { command }.run(repo, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    fn create(repo: &Repository, file: &str, revisions: &[&str]) -> anyhow::Result<String> {
+        run(
+            repo,
+            BundleCommand::Create(CreateArgs {
+                file: repo.cwd().join(file),
+                revisions: revisions
+                    .iter()
+                    .map(|revision| revision.to_string())
+                    .collect(),
+            }),
+        )
+    }
+
+    fn verify(repo: &Repository, file: &str, quiet: bool) -> anyhow::Result<String> {
+        run(
+            repo,
+            BundleCommand::Verify(VerifyArgs {
+                quiet,
+                file: repo.cwd().join(file),
+            }),
+        )
+    }
+
+    #[test]
+    fn creates_and_verifies_bundles() {
+        let (temp, repo) = setup();
+        let first = commit(&repo, "one");
+        let second = commit(&repo, "two");
+        let tag = create_tag(&repo, &second, "v1", "release\n").unwrap();
+        write_ref(&repo, "refs/tags/v1", &tag).unwrap();
+
+        create(&repo, "full.bundle", &["--all"]).unwrap();
+        assert_eq!(
+            verify(&repo, "full.bundle", false).unwrap(),
+            format!(
+                "The bundle contains these 3 refs:\n{second} refs/heads/main\n{tag} refs/tags/v1\n\
+                 {second} HEAD\nThe bundle records a complete history.\n\
+                 The bundle uses this hash algorithm: sha1\n"
+            )
+        );
+
+        create(&repo, "part.bundle", &["main~1..main"]).unwrap();
+        let bundle = Bundle::read(&temp.join("part.bundle")).unwrap();
+        assert_eq!(bundle.prerequisites, [(first.clone(), "one".to_string())]);
+        assert_eq!(bundle.refs, [(
+            second.clone(),
+            "refs/heads/main".to_string()
+        )]);
+        assert_eq!(
+            run(
+                &repo,
+                BundleCommand::ListHeads(ListHeadsArgs {
+                    file: temp.join("full.bundle"),
+                    refnames: vec!["v1".to_string()],
+                })
+            )
+            .unwrap(),
+            format!("{tag} refs/tags/v1\n")
+        );
+
+        // An incremental bundle keeps the tags of the basis, but not its branches
+        let old_tag = create_tag(&repo, &first, "v0", "old release\n").unwrap();
+        write_ref(&repo, "refs/tags/v0", &old_tag).unwrap();
+        write_ref(&repo, "refs/heads/old", &first).unwrap();
+        create(&repo, "incremental.bundle", &[
+            "--all",
+            &format!("^{first}"),
+        ])
+        .unwrap();
+        let bundle = Bundle::read(&temp.join("incremental.bundle")).unwrap();
+        assert_eq!(bundle.prerequisites, [(first.clone(), "one".to_string())]);
+        let names: Vec<&str> = bundle.refs.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, [
+            "refs/heads/main",
+            "refs/tags/v0",
+            "refs/tags/v1",
+            "HEAD"
+        ]);
+        create(&repo, "tags.bundle", &["--tags"]).unwrap();
+        let bundle = Bundle::read(&temp.join("tags.bundle")).unwrap();
+        assert_eq!(bundle.refs, [
+            (old_tag, "refs/tags/v0".to_string()),
+            (tag, "refs/tags/v1".to_string())
+        ]);
+
+        let err = create(&repo, "empty.bundle", &["main..main"]).unwrap_err();
+        assert_eq!(err.to_string(), "Refusing to create empty bundle.");
+        let err = create(&repo, "empty.bundle", &[&first]).unwrap_err();
+        assert_eq!(err.to_string(), "Refusing to create empty bundle.");
+    }
+
+    #[test]
+    fn unbundles_into_other_repositories() {
+        let (temp, repo) = setup();
+        let first = commit(&repo, "one");
+        let second = commit(&repo, "two");
+        create(&repo, "part.bundle", &["main~1..main"]).unwrap();
+        create(&repo, "full.bundle", &["main"]).unwrap();
+
+        fs::create_dir_all(temp.join("other/.git/objects")).unwrap();
+        let other = repo.open(temp.join("other/.git"), None);
+
+        let err = run(
+            &other,
+            BundleCommand::Unbundle(UnbundleArgs {
+                file: temp.join("part.bundle"),
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Repository lacks these prerequisite commits:\n{first} one")
+        );
+
+        let output = run(
+            &other,
+            BundleCommand::Unbundle(UnbundleArgs {
+                file: temp.join("full.bundle"),
+            }),
+        )
+        .unwrap();
+        assert_eq!(output, format!("{second} refs/heads/main\n"));
+        assert_eq!(read_commit(&other, &second).unwrap().parents, [first]);
+        assert!(verify(&other, "part.bundle", true).unwrap().is_empty());
+    }
+}
@@ -1,45 +1,234 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::collections::HashSet;
+use std::io::{BufRead, Read, Write};
 
 use anyhow::Context;
 use clap::Args;
-use flate2::read::ZlibDecoder;
 
 use crate::commands::CommandArgs;
-use crate::utils::objects::{parse_header, ObjectType};
-use crate::utils::{get_object_path, hex};
+use crate::utils::commit::{peel, Commit, Ident, Tag};
+use crate::utils::convert::Conversion;
+use crate::utils::hex;
+use crate::utils::objects::{
+    list_objects, object_exists, open_object, parse_header, read_object_of_type, ObjectType,
+};
+use crate::utils::replace::replace_object;
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
 
 impl CommandArgs for CatFileArgs {
-    fn run<W>(self, writer: &mut W) -> anyhow::Result<()>
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
     where
         W: Write,
     {
+        if let Some(format) = &self.flags.batch_check {
+            let format = BatchFormat::parse(format)?;
+            return if self.batch_all_objects {
+                batch_check_all(repo, &format, self.unordered, writer)
+            } else {
+                batch_check(repo, &format, std::io::stdin().lock(), writer)
+            };
+        }
+
+        let object = self.object_hash.as_deref().context("<object> required")?;
+        let hash = &replace_object(repo, object)?;
         match self.flags {
             CatFileFlags {
                 show_type: true, ..
-            } => read_object_type(&self.object_hash, self.allow_unknown_type, writer),
+            } => read_object_type(repo, hash, self.allow_unknown_type, writer),
             CatFileFlags { size: true, .. } => {
-                read_object_size(&self.object_hash, self.allow_unknown_type, writer)
+                read_object_size(repo, hash, self.allow_unknown_type, writer)
             },
             CatFileFlags {
                 exit_zero: true, ..
             }
             | CatFileFlags {
                 pretty_print: true, ..
-            } => read_object_pretty(&self.object_hash, self.flags.exit_zero, writer),
-            _ => unreachable!("either -t, -s, -e, or -p must be specified"),
+            } => read_object_pretty(repo, hash, self.flags.exit_zero, self.deref, writer),
+            CatFileFlags { filters: true, .. } => {
+                read_object_filtered(repo, object, self.path.as_deref(), writer)
+            },
+            _ => {
+                unreachable!("either -t, -s, -e, -p, --filters or --batch-check must be specified")
+            },
+        }
+    }
+}
+
+/// The format of `--batch-check` when none is given
+const DEFAULT_BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+/// A placeholder of a `--batch-check` format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Atom {
+    ObjectName,
+    ObjectType,
+    ObjectSize,
+    /// The text following the object name on the input line
+    Rest,
+    /// The hash, type and size of the object a tag points to once peeled
+    /// (of the object itself if it is not a tag)
+    Deref,
+}
+
+/// A part of a `--batch-check` format
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FormatPart {
+    Literal(String),
+    Atom(Atom),
+}
+
+/// A parsed `--batch-check` format (e.g. `%(objectname) %(deref)`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BatchFormat(Vec<FormatPart>);
+
+impl BatchFormat {
+    fn parse(format: &str) -> anyhow::Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = format;
+        while let Some(start) = rest.find("%(") {
+            if start > 0 {
+                parts.push(FormatPart::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find(')')
+                .context(format!("unterminated format element in '{format}'"))?;
+            let atom = match &rest[start + 2..start + end] {
+                "objectname" => Atom::ObjectName,
+                "objecttype" => Atom::ObjectType,
+                "objectsize" => Atom::ObjectSize,
+                "rest" => Atom::Rest,
+                "deref" => Atom::Deref,
+                name => anyhow::bail!("unknown format element: %({name})"),
+            };
+            parts.push(FormatPart::Atom(atom));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(FormatPart::Literal(rest.to_string()));
+        }
+        Ok(BatchFormat(parts))
+    }
+
+    /// Whether the format uses an atom, for the work it needs to be done.
+    fn uses(&self, atom: Atom) -> bool {
+        self.0.contains(&FormatPart::Atom(atom))
+    }
+
+    /// Expand the format for an object, `rest` being the text
+    /// following its name on the input line.
+    fn expand(&self, repo: &Repository, hash: &str, rest: &str) -> anyhow::Result<String> {
+        let (object_type, size) = object_summary(repo, hash)?;
+        let mut output = String::new();
+        for part in &self.0 {
+            match part {
+                FormatPart::Literal(literal) => output.push_str(literal),
+                FormatPart::Atom(Atom::ObjectName) => output.push_str(hash),
+                FormatPart::Atom(Atom::ObjectType) => output.push_str(&object_type),
+                FormatPart::Atom(Atom::ObjectSize) => output.push_str(&size),
+                FormatPart::Atom(Atom::Rest) => output.push_str(rest),
+                FormatPart::Atom(Atom::Deref) => output.push_str(&deref_summary(repo, hash)?),
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// The type and size of an object, read from its header.
+fn object_summary(repo: &Repository, hash: &str) -> anyhow::Result<(String, String)> {
+    let mut object_type = Vec::new();
+    read_object_type(repo, hash, false, &mut object_type)?;
+    let mut size = Vec::new();
+    read_object_size(repo, hash, false, &mut size)?;
+    Ok((String::from_utf8(object_type)?, String::from_utf8(size)?))
+}
+
+/// The hash, type and size of the object a tag points to once peeled.
+fn deref_summary(repo: &Repository, hash: &str) -> anyhow::Result<String> {
+    let (target, _) = peel(repo, hash)?;
+    let (object_type, size) = object_summary(repo, &target)?;
+    Ok(format!("{target} {object_type} {size}"))
+}
+
+/// Print the format expanded for every object of the repository, sorted by
+/// hash, or in the order they are stored (which is faster for large repositories).
+fn batch_check_all<W>(
+    repo: &Repository,
+    format: &BatchFormat,
+    unordered: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let mut objects = list_objects(repo)?;
+    if unordered {
+        // Only the first copy of an object stored more than once is listed
+        let mut seen = HashSet::new();
+        objects.retain(|(hash, ..)| seen.insert(hash.clone()));
+    } else {
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        objects.dedup_by(|a, b| a.0 == b.0);
+    }
+
+    // The type and size are already known for the default format
+    let default = *format == BatchFormat::parse(DEFAULT_BATCH_FORMAT)?;
+    for (hash, object_type, size) in objects {
+        if default {
+            writeln!(writer, "{hash} {object_type} {size}")?;
+        } else {
+            writeln!(writer, "{}", format.expand(repo, &hash, "")?)?;
         }
     }
+    Ok(())
+}
+
+/// Print the format expanded for each object named on a line of the input
+/// (the text after the name being `%(rest)` if the format uses it), or that
+/// it is missing.
+fn batch_check<R, W>(
+    repo: &Repository,
+    format: &BatchFormat,
+    reader: R,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) if format.uses(Atom::Rest) => (name, rest.trim_start()),
+            _ => (line, ""),
+        };
+        let hash = match resolve_revision(repo, name) {
+            Ok(hash) if object_exists(repo, &hash)? => hash,
+            _ => {
+                writeln!(writer, "{name} missing")?;
+                continue;
+            },
+        };
+
+        writeln!(writer, "{}", format.expand(repo, &hash, rest)?)?;
+        // Flush each answer, as the input may come from a process waiting for it
+        writer.flush()?;
+    }
+    Ok(())
 }
 
-fn read_object_pretty<W>(hash: &str, exit: bool, writer: &mut W) -> anyhow::Result<()>
+fn read_object_pretty<W>(
+    repo: &Repository,
+    hash: &str,
+    exit: bool,
+    deref: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
 where
     W: Write,
 {
-    let object_path = get_object_path(hash, true)?;
-    let file = File::open(object_path)?;
-    // Create a zlib decoder to read the object header and content
-    let mut zlib = BufReader::new(ZlibDecoder::new(file));
+    // The object header and content, decompressed as they are read
+    let mut zlib = open_object(repo, hash)?;
 
     // Read the object header
     let mut header = Vec::new();
@@ -48,8 +237,9 @@ where
 
     // Read the object content
     let mut buf = Vec::new();
-    let object_size = match header.parse_type()? {
-        ObjectType::Tree => read_tree_pretty(&mut zlib, &mut buf)?,
+    let object_type = header.parse_type()?;
+    let object_size = match object_type {
+        ObjectType::Tree => read_tree_pretty(repo, &mut zlib, &mut buf)?,
         // Blobs, commits, and tags are pretty-printed as is
         _ => zlib.read_to_end(&mut buf)?,
     };
@@ -59,6 +249,27 @@ where
         anyhow::bail!("object size does not match header");
     }
 
+    // Commits and tags are only printed if they parse, identities included
+    match object_type {
+        ObjectType::Commit => {
+            let commit = Commit::parse(&buf).context(format!("bad commit object {hash}"))?;
+            for ident in [&commit.author, &commit.committer] {
+                Ident::parse(ident).context(format!("bad commit object {hash}"))?;
+            }
+        },
+        ObjectType::Tag => {
+            let tag = Tag::parse(&buf).context(format!("bad tag object {hash}"))?;
+            if let Some(tagger) = &tag.tagger {
+                Ident::parse(tagger).context(format!("bad tag object {hash}"))?;
+            }
+            // The tag is followed by the object it points to once peeled
+            if deref && !exit {
+                buf.extend(format!("{}\n", deref_summary(repo, hash)?).as_bytes());
+            }
+        },
+        _ => {},
+    }
+
     // Exit early if the object exists and passes validation
     if exit {
         return Ok(());
@@ -68,8 +279,33 @@ where
     writer.write_all(&buf).context("write object to stdout")
 }
 
+/// Print the content of a blob as it would be checked out at a path (e.g. with
+/// CRLF line endings, or the content of an LFS pointer file).
+fn read_object_filtered<W>(
+    repo: &Repository,
+    object: &str,
+    path: Option<&str>,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    // The path is the one of `<rev>:<path>`, unless given with `--path`
+    let path = match (path, object.split_once(':')) {
+        (Some(path), _) => path,
+        (None, Some((_, path))) => path,
+        (None, None) => anyhow::bail!("<object>:<path> required, only <object> '{object}' given"),
+    };
+
+    let hash = resolve_revision(repo, object)?;
+    let content = read_object_of_type(repo, &hash, ObjectType::Blob)?;
+    let content = Conversion::load(repo)?.convert_to_work_tree(path, content)?;
+    writer.write_all(&content).context("write object to stdout")
+}
+
 fn read_tree_pretty(
-    zlib: &mut BufReader<ZlibDecoder<File>>,
+    repo: &Repository,
+    zlib: &mut dyn BufRead,
     buf: &mut Vec<u8>,
 ) -> anyhow::Result<usize> {
     let mut entries = Vec::new();
@@ -105,7 +341,7 @@ fn read_tree_pretty(
         // Find the object type of the entry
         let hash_str = std::str::from_utf8(&hash).context("object hash is not valid utf-8")?;
         let mut object_type = Vec::new();
-        read_object_type(hash_str, false, &mut object_type)?;
+        read_object_type(repo, hash_str, false, &mut object_type)?;
 
         // Append the remaining entry fields
         entry.extend(object_type);
@@ -125,14 +361,17 @@ fn read_tree_pretty(
     Ok(object_size)
 }
 
-fn read_object_type<W>(hash: &str, allow_unknown_type: bool, writer: &mut W) -> anyhow::Result<()>
+fn read_object_type<W>(
+    repo: &Repository,
+    hash: &str,
+    allow_unknown_type: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
 where
     W: Write,
 {
-    let object_path = get_object_path(hash, true)?;
-    let file = File::open(object_path)?;
-    // Create a zlib decoder to read the object header
-    let mut zlib = BufReader::new(ZlibDecoder::new(file));
+    // The object header, decompressed as it is read
+    let mut zlib = open_object(repo, hash)?;
 
     // Read the object header
     let mut buf = Vec::new();
@@ -149,14 +388,17 @@ where
         .context("write object type to writer")
 }
 
-fn read_object_size<W>(hash: &str, allow_unknown_type: bool, writer: &mut W) -> anyhow::Result<()>
+fn read_object_size<W>(
+    repo: &Repository,
+    hash: &str,
+    allow_unknown_type: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
 where
     W: Write,
 {
-    let object_path = get_object_path(hash, true)?;
-    let file = File::open(object_path)?;
-    // Create a zlib decoder to read the object header
-    let mut zlib = BufReader::new(ZlibDecoder::new(file));
+    // The object header, decompressed as it is read
+    let mut zlib = open_object(repo, hash)?;
 
     // Read the object header
     let mut buf = Vec::new();
@@ -180,9 +422,26 @@ pub(crate) struct CatFileArgs {
     /// allow -s and -t to work with broken/corrupt objects
     #[arg(long, requires = "header")]
     allow_unknown_type: bool,
+    /// show the hash, type and size of the object a tag points to once
+    /// peeled after the tag (with -p)
+    #[arg(long, requires = "pretty_print")]
+    deref: bool,
+    /// show all objects with --batch-check
+    #[arg(long, requires = "batch_check")]
+    batch_all_objects: bool,
+    /// list the objects in the order they are stored (with --batch-all-objects)
+    #[arg(long, requires = "batch_all_objects")]
+    unordered: bool,
+    /// the path to apply the filters of with --filters
+    #[arg(long, requires = "filters")]
+    path: Option<String>,
     /// the object to display
-    #[arg(name = "object")]
-    object_hash: String,
+    #[arg(
+        name = "object",
+        required_unless_present = "batch_check",
+        conflicts_with = "batch_check"
+    )]
+    object_hash: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -200,6 +459,20 @@ struct CatFileFlags {
     /// pretty-print <object> content
     #[arg(short)]
     pretty_print: bool,
+    /// show the content of a blob as checked out (line endings, LFS pointers)
+    #[arg(long)]
+    filters: bool,
+    /// show the hash, type and size of the objects named on standard input,
+    /// or the format given with %(objectname), %(objecttype), %(objectsize),
+    /// %(rest) and %(deref) placeholders
+    #[arg(
+        long,
+        value_name = "format",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = DEFAULT_BATCH_FORMAT
+    )]
+    batch_check: Option<String>,
 }
 
 #[cfg(test)]
@@ -210,10 +483,14 @@ mod tests {
     use flate2::write::ZlibEncoder;
     use flate2::Compression;
 
-    use crate::commands::cat_file::{CatFileArgs, CatFileFlags};
+    use crate::commands::cat_file::{
+        batch_check, BatchFormat, CatFileArgs, CatFileFlags, DEFAULT_BATCH_FORMAT,
+    };
     use crate::commands::CommandArgs;
-    use crate::utils::test::{TempEnv, TempPwd};
-    use crate::utils::{env, hex};
+    use crate::utils::hex;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::pack::write_pack;
+    use crate::utils::test::TempRepo;
 
     const BLOB_CONTENT: &str = "Hello, World!";
     const OBJECT_HASH: &str = "2f22503f99671604495c84465f0113d002193369";
@@ -291,10 +568,9 @@ mod tests {
 
     #[test]
     fn displays_non_tree() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -306,13 +582,19 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: true,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, BLOB_CONTENT.as_bytes());
@@ -320,18 +602,16 @@ mod tests {
 
     #[test]
     fn displays_tree() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let tree_path = pwd.path().join(OBJECT_PATH);
+        let tree_path = temp.join(OBJECT_PATH);
         let blob_hash_hex = "01c6a63b7fc32f6f49988a9a12b8d7d199febeab";
 
         // Create the object path and write the hashed content
         fs::create_dir_all(tree_path.parent().unwrap()).unwrap();
         fs::write(&tree_path, compress_tree(blob_hash_hex, true, true)).unwrap();
 
-        let blob_path = pwd
-            .path()
+        let blob_path = temp
             .join(".git/objects")
             .join(&blob_hash_hex[..2])
             .join(&blob_hash_hex[2..]);
@@ -346,13 +626,19 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: true,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, tree_content(blob_hash_hex, true));
@@ -360,10 +646,9 @@ mod tests {
 
     #[test]
     fn exits_successfully() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -375,13 +660,19 @@ mod tests {
                 size: false,
                 exit_zero: true,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert!(output.is_empty());
@@ -389,10 +680,9 @@ mod tests {
 
     #[test]
     fn displays_object_type() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -404,13 +694,19 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, b"blob");
@@ -418,10 +714,9 @@ mod tests {
 
     #[test]
     fn displays_object_size() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -433,13 +728,19 @@ mod tests {
                 size: true,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, BLOB_CONTENT.len().to_string().as_bytes());
@@ -447,10 +748,9 @@ mod tests {
 
     #[test]
     fn displays_object_type_with_unknown_type() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -462,13 +762,19 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: true,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, b"unknown");
@@ -476,10 +782,9 @@ mod tests {
 
     #[test]
     fn displays_object_size_with_unknown_type() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -491,13 +796,19 @@ mod tests {
                 size: true,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: true,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, BLOB_CONTENT.len().to_string().as_bytes());
@@ -505,10 +816,9 @@ mod tests {
 
     #[test]
     fn fails_to_display_object_type_with_unknown_type() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -520,21 +830,26 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn fails_to_display_object_size_with_unknown_type() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -546,21 +861,26 @@ mod tests {
                 size: true,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn fails_to_display_non_tree_with_invalid_size() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -572,29 +892,33 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: true,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn fails_to_display_tree_with_invalid_size() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let tree_path = pwd.path().join(OBJECT_PATH);
+        let tree_path = temp.join(OBJECT_PATH);
         let blob_hash_hex = "01c6a63b7fc32f6f49988a9a12b8d7d199febeab";
 
         // Create the object path and write the hashed content
         fs::create_dir_all(tree_path.parent().unwrap()).unwrap();
         fs::write(&tree_path, compress_tree(blob_hash_hex, true, false)).unwrap();
 
-        let blob_path = pwd
-            .path()
+        let blob_path = temp
             .join(".git/objects")
             .join(&blob_hash_hex[..2])
             .join(&blob_hash_hex[2..]);
@@ -609,21 +933,26 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: true,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn fails_to_display_non_tree_with_unknown_type() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -635,29 +964,33 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: true,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn fails_to_display_tree_with_unknown_type() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let tree_path = pwd.path().join(OBJECT_PATH);
+        let tree_path = temp.join(OBJECT_PATH);
         let blob_hash_hex = "01c6a63b7fc32f6f49988a9a12b8d7d199febeab";
 
         // Create the object path and write the hashed content
         fs::create_dir_all(tree_path.parent().unwrap()).unwrap();
         fs::write(&tree_path, compress_tree(blob_hash_hex, false, true)).unwrap();
 
-        let blob_path = pwd
-            .path()
+        let blob_path = temp
             .join(".git/objects")
             .join(&blob_hash_hex[..2])
             .join(&blob_hash_hex[2..]);
@@ -672,21 +1005,26 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: true,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn displays_object_size_with_invalid_size() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let object_path = pwd.path().join(OBJECT_PATH);
+        let object_path = temp.join(OBJECT_PATH);
 
         // Create the object path and write the hashed content
         fs::create_dir_all(object_path.parent().unwrap()).unwrap();
@@ -698,13 +1036,19 @@ mod tests {
                 size: true,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, b"0");
@@ -712,8 +1056,7 @@ mod tests {
 
     #[test]
     fn fails_to_display_object_with_invalid_hash() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
-        let _pwd = TempPwd::new();
+        let temp = TempRepo::new();
 
         let args = CatFileArgs {
             flags: CatFileFlags {
@@ -721,19 +1064,24 @@ mod tests {
                 size: false,
                 exit_zero: false,
                 pretty_print: true,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn fails_to_display_header_with_invalid_hash() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
-        let _pwd = TempPwd::new();
+        let temp = TempRepo::new();
 
         let args = CatFileArgs {
             flags: CatFileFlags {
@@ -741,12 +1089,159 @@ mod tests {
                 size: true,
                 exit_zero: false,
                 pretty_print: false,
+                batch_check: None,
+                filters: false,
             },
             allow_unknown_type: false,
-            object_hash: OBJECT_HASH.to_string(),
+            deref: false,
+            batch_all_objects: false,
+            unordered: false,
+            path: None,
+            object_hash: Some(OBJECT_HASH.to_string()),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn lists_all_objects() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        // A pack holding a copy of the loose blob and another one
+        let loose = write_object(&repo, ObjectType::Blob, BLOB_CONTENT.as_bytes()).unwrap();
+        let packed = write_object(&repo, ObjectType::Blob, b"packed\n").unwrap();
+        let mut pack = Vec::new();
+        write_pack(&repo, &[packed.clone(), loose.clone()], &mut pack).unwrap();
+        fs::create_dir_all(temp.join(".git/objects/pack")).unwrap();
+        fs::write(temp.join(".git/objects/pack/pack-test.pack"), pack).unwrap();
+        fs::remove_file(repo.object_path(&packed, true).unwrap()).unwrap();
+
+        let run = |unordered: bool| {
+            let args = CatFileArgs {
+                flags: CatFileFlags {
+                    show_type: false,
+                    size: false,
+                    exit_zero: false,
+                    pretty_print: false,
+                    batch_check: Some(DEFAULT_BATCH_FORMAT.to_string()),
+                    filters: false,
+                },
+                allow_unknown_type: false,
+                deref: false,
+                batch_all_objects: true,
+                unordered,
+                path: None,
+                object_hash: None,
+            };
+            let mut output = Vec::new();
+            args.run(&repo, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // Sorted by hash, or loose objects first, each object listed once
+        assert_eq!(loose, "b45ef6fec89518d314f546fd6c3025367b721684");
+        assert_eq!(packed, "24b0b059501066adf88b7094eb01f43cb6234251");
+        let loose = format!("{loose} blob 13\n");
+        let packed = format!("{packed} blob 7\n");
+        assert_eq!(run(false), format!("{packed}{loose}"));
+        assert_eq!(run(true), format!("{loose}{packed}"));
+    }
+
+    #[test]
+    fn checks_objects_from_input() {
+        let temp = TempRepo::new();
+        let object_path = temp.join(OBJECT_PATH);
+        fs::create_dir_all(object_path.parent().unwrap()).unwrap();
+        fs::write(&object_path, compress_blob(true, true)).unwrap();
+
+        let input = format!("{OBJECT_HASH}\n{}\n", "0".repeat(40));
+        let mut output = Vec::new();
+        batch_check(
+            &temp.repo(),
+            &BatchFormat::parse(DEFAULT_BATCH_FORMAT).unwrap(),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{OBJECT_HASH} blob 13\n{} missing\n", "0".repeat(40))
+        );
+    }
+
+    #[test]
+    fn validates_commits_and_tags() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let ident = "A U Thor <author@example.com> 1700000000 +0000";
+        let commit = format!(
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor {ident}\ncommitter {ident}\n\
+             gpgsig -----BEGIN PGP SIGNATURE-----\n \n -----END PGP SIGNATURE-----\n\nmessage\n"
+        );
+        let commit_hash = write_object(&repo, ObjectType::Commit, commit.as_bytes()).unwrap();
+        let tag = format!("object {commit_hash}\ntype commit\ntag v1\ntagger {ident}\n\ntag\n");
+        let tag_hash = write_object(&repo, ObjectType::Tag, tag.as_bytes()).unwrap();
+        let broken = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor broken\n\n";
+        let broken = write_object(&repo, ObjectType::Commit, broken.as_bytes()).unwrap();
+
+        let run = |hash: &str, deref: bool| {
+            let args = CatFileArgs {
+                flags: CatFileFlags {
+                    show_type: false,
+                    size: false,
+                    exit_zero: false,
+                    pretty_print: true,
+                    batch_check: None,
+                    filters: false,
+                },
+                allow_unknown_type: false,
+                deref,
+                batch_all_objects: false,
+                unordered: false,
+                path: None,
+                object_hash: Some(hash.to_string()),
+            };
+            let mut output = Vec::new();
+            args.run(&repo, &mut output)
+                .map(|()| String::from_utf8(output).unwrap())
+        };
+
+        // Folded headers are kept as they are
+        assert_eq!(run(&commit_hash, false).unwrap(), commit);
+        assert_eq!(run(&tag_hash, false).unwrap(), tag);
+        assert_eq!(
+            run(&tag_hash, true).unwrap(),
+            format!("{tag}{commit_hash} commit {}\n", commit.len())
+        );
+        let err = run(&broken, false).unwrap_err();
+        assert_eq!(err.to_string(), format!("bad commit object {broken}"));
+    }
+
+    #[test]
+    fn checks_objects_with_a_format() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let blob = write_object(&repo, ObjectType::Blob, BLOB_CONTENT.as_bytes()).unwrap();
+        let tag = format!("object {blob}\ntype blob\ntag v1\n\ntag\n");
+        let tag = write_object(&repo, ObjectType::Tag, tag.as_bytes()).unwrap();
+
+        let format = BatchFormat::parse("%(objecttype) %(deref) (%(rest))").unwrap();
+        let input = format!("{tag} some text\n{blob}\n");
+        let mut output = Vec::new();
+        batch_check(&repo, &format, input.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("tag {blob} blob 13 (some text)\nblob {blob} blob 13 ()\n")
+        );
+
+        let err = BatchFormat::parse("%(objectname) %(unknown)").unwrap_err();
+        assert_eq!(err.to_string(), "unknown format element: %(unknown)");
+    }
 }
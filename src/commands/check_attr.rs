@@ -0,0 +1,276 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::attributes::{is_valid_name, Attributes};
+use crate::utils::index::Index;
+use crate::utils::pathspec::normalize;
+use crate::utils::quote::{quote_path, quotes_non_ascii, unquote_c_style};
+use crate::utils::repository::Repository;
+
+impl CommandArgs for CheckAttrArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let (names, paths) = self.split_args()?;
+        if self.stdin && !paths.is_empty() {
+            anyhow::bail!("can't specify files with --stdin");
+        }
+        if !self.stdin && paths.is_empty() {
+            anyhow::bail!("no file specified");
+        }
+        if self.null_terminated && !self.stdin {
+            anyhow::bail!("-z only makes sense with --stdin");
+        }
+        if let Some(name) = names.iter().find(|name| !is_valid_name(name)) {
+            anyhow::bail!("{name}: not a valid attribute name");
+        }
+
+        let mut check = AttrCheck {
+            prefix: repo.prefix()?,
+            attributes: if self.cached {
+                Attributes::load_cached(repo, &Index::load(repo)?)?
+            } else {
+                Attributes::load(repo)?
+            },
+            quote_non_ascii: quotes_non_ascii(repo)?,
+        };
+
+        if self.stdin {
+            return self.check_stdin(&mut check, &names, std::io::stdin().lock(), writer);
+        }
+        for path in paths {
+            self.check_path(&mut check, &names, path, writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckAttrArgs {
+    /// Split the arguments into the attributes to check and the paths. Without `--`,
+    /// the first argument is the attribute and the others are paths (with `--all`
+    /// or `--stdin`, all arguments are paths or attributes respectively).
+    fn split_args(&self) -> anyhow::Result<(Vec<&str>, Vec<&str>)> {
+        let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        let paths: Vec<&str> = self.paths.iter().map(String::as_str).collect();
+
+        if self.all {
+            if !paths.is_empty() && !args.is_empty() {
+                anyhow::bail!("attributes and --all both specified");
+            }
+            return Ok((Vec::new(), [args, paths].concat()));
+        }
+        if !paths.is_empty() || self.stdin {
+            if args.is_empty() {
+                anyhow::bail!("no attribute specified");
+            }
+            return Ok((args, paths));
+        }
+        match args.split_first() {
+            Some((name, paths)) => Ok((vec![*name], paths.to_vec())),
+            None => anyhow::bail!("no attribute specified"),
+        }
+    }
+
+    /// Check the paths read from standard input, one per line (or NUL-terminated
+    /// with `-z`). The result of each path is flushed before the next one is read.
+    fn check_stdin<R, W>(
+        &self,
+        check: &mut AttrCheck,
+        names: &[&str],
+        reader: R,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let terminator = if self.null_terminated { b'\0' } else { b'\n' };
+        for record in reader.split(terminator) {
+            let record = record?;
+            let path = if self.null_terminated {
+                String::from_utf8_lossy(&record).into_owned()
+            } else {
+                let line = String::from_utf8_lossy(&record);
+                String::from_utf8_lossy(&unquote_c_style(line.trim_end_matches('\r').as_bytes())?)
+                    .into_owned()
+            };
+            if path.is_empty() {
+                continue;
+            }
+
+            self.check_path(check, names, &path, writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the attributes of a path, as `<path>: <attribute>: <value>` lines.
+    fn check_path<W>(
+        &self,
+        check: &mut AttrCheck,
+        names: &[&str],
+        path: &str,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let full_path = normalize(&check.prefix, Path::new(path))?;
+        let values = if self.all {
+            check.attributes.all(&full_path)?
+        } else {
+            let values = check.attributes.check(&full_path, names)?;
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .zip(values)
+                .collect()
+        };
+
+        for (name, value) in values {
+            if self.null_terminated {
+                write!(writer, "{path}\0{name}\0{value}\0")?;
+            } else {
+                writer.write_all(&quote_path(path.as_bytes(), check.quote_non_ascii))?;
+                writeln!(writer, ": {name}: {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state needed to check paths given relative to the current directory
+struct AttrCheck {
+    prefix: String,
+    attributes: Attributes,
+    /// Whether non-ASCII bytes of paths are quoted (`core.quotePath`)
+    quote_non_ascii: bool,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CheckAttrArgs {
+    /// show every attribute that is set, unset or has a value
+    #[arg(short, long)]
+    all: bool,
+    /// read the .gitattributes files of the index instead of the working tree
+    #[arg(long)]
+    cached: bool,
+    /// read the paths from standard input, one per line
+    #[arg(long)]
+    stdin: bool,
+    /// separate the input and output records with NUL instead of newline
+    #[arg(short = 'z')]
+    null_terminated: bool,
+    /// the attribute followed by the paths (or the attributes, before `--`)
+    #[arg(value_name = "attr")]
+    args: Vec<String>,
+    /// the paths to check
+    #[arg(value_name = "pathname", last = true)]
+    paths: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/info")).unwrap();
+        fs::create_dir_all(temp.join("sub")).unwrap();
+        fs::write(
+            temp.join(".gitattributes"),
+            "*.txt text eol=lf\n*.bin binary\n",
+        )
+        .unwrap();
+        fs::write(temp.join("sub/.gitattributes"), "*.txt -text\n").unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn args(args: &[&str], paths: &[&str]) -> CheckAttrArgs {
+        CheckAttrArgs {
+            all: false,
+            cached: false,
+            stdin: false,
+            null_terminated: false,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+
+    fn check_attr(repo: &Repository, args: CheckAttrArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn shows_attributes_of_paths() {
+        let (_temp, repo) = setup();
+
+        assert_eq!(
+            check_attr(&repo, args(&["text", "a.txt", "sub/b.txt", "c"], &[])).unwrap(),
+            "a.txt: text: set\nsub/b.txt: text: unset\nc: text: unspecified\n"
+        );
+        assert_eq!(
+            check_attr(&repo, args(&["eol", "diff"], &["a.txt", "x.bin"])).unwrap(),
+            "a.txt: eol: lf\na.txt: diff: unspecified\n\
+             x.bin: eol: unspecified\nx.bin: diff: unset\n"
+        );
+        assert_eq!(
+            check_attr(&repo, CheckAttrArgs {
+                all: true,
+                ..args(&["x.bin", "sub/b.txt"], &[])
+            })
+            .unwrap(),
+            "x.bin: binary: set\nx.bin: diff: unset\nx.bin: merge: unset\nx.bin: text: unset\n\
+             sub/b.txt: text: unset\nsub/b.txt: eol: lf\n"
+        );
+
+        let err = check_attr(&repo, args(&["-bad", "a.txt"], &[])).unwrap_err();
+        assert_eq!(err.to_string(), "-bad: not a valid attribute name");
+    }
+
+    #[test]
+    fn reads_paths_from_stdin() {
+        let (_temp, repo) = setup();
+        let args = CheckAttrArgs {
+            stdin: true,
+            ..args(&["text"], &[])
+        };
+        let mut check = AttrCheck {
+            prefix: String::new(),
+            attributes: Attributes::load(&repo).unwrap(),
+            quote_non_ascii: true,
+        };
+
+        let mut output = Vec::new();
+        args.check_stdin(
+            &mut check,
+            &["text"],
+            &b"a.txt\n\"sub/b.txt\"\n"[..],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a.txt: text: set\nsub/b.txt: text: unset\n"
+        );
+
+        let args = CheckAttrArgs {
+            null_terminated: true,
+            ..args
+        };
+        let mut output = Vec::new();
+        args.check_stdin(&mut check, &["text"], &b"a.txt\0"[..], &mut output)
+            .unwrap();
+        assert_eq!(output, b"a.txt\0text\0set\0");
+    }
+}
@@ -0,0 +1,324 @@
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::ignore::{Ignore, IgnorePattern};
+use crate::utils::index::Index;
+use crate::utils::pathspec::normalize;
+use crate::utils::quote::{quote_path, quotes_non_ascii, unquote_c_style};
+use crate::utils::repository::Repository;
+
+impl CommandArgs for CheckIgnoreArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.stdin && !self.paths.is_empty() {
+            anyhow::bail!("cannot specify pathnames with --stdin");
+        }
+        if !self.stdin && self.paths.is_empty() {
+            anyhow::bail!("no path specified");
+        }
+        if self.null_terminated && !self.stdin {
+            anyhow::bail!("-z only makes sense with --stdin");
+        }
+        if self.non_matching && !self.verbose {
+            anyhow::bail!("--non-matching is only valid with --verbose");
+        }
+
+        let mut check = IgnoreCheck::new(repo, self.no_index)?;
+        let matched = if self.stdin {
+            self.check_stdin(&mut check, std::io::stdin().lock(), writer)?
+        } else {
+            let mut matched = false;
+            for path in &self.paths {
+                matched |= self.check_path(&mut check, &path.to_string_lossy(), writer)?;
+            }
+            matched
+        };
+
+        // Nothing being ignored is a (silent) failure, as with grep
+        if !matched {
+            return Err(SilentExit(1).into());
+        }
+        Ok(())
+    }
+}
+
+impl CheckIgnoreArgs {
+    /// Check the paths read from standard input, one per line (or NUL-terminated
+    /// with `-z`). The result of each path is flushed before the next one is read,
+    /// so another process can feed paths and wait for their results.
+    ///
+    /// # Returns
+    ///
+    /// Whether any path was matched by a pattern
+    fn check_stdin<R, W>(
+        &self,
+        check: &mut IgnoreCheck,
+        reader: R,
+        writer: &mut W,
+    ) -> anyhow::Result<bool>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let terminator = if self.null_terminated { b'\0' } else { b'\n' };
+        let mut matched = false;
+
+        for record in reader.split(terminator) {
+            let record = record?;
+            let path = if self.null_terminated {
+                String::from_utf8_lossy(&record).into_owned()
+            } else {
+                let line = String::from_utf8_lossy(&record);
+                String::from_utf8_lossy(&unquote_c_style(line.trim_end_matches('\r').as_bytes())?)
+                    .into_owned()
+            };
+            if path.is_empty() {
+                continue;
+            }
+
+            matched |= self.check_path(check, &path, writer)?;
+            writer.flush()?;
+        }
+
+        Ok(matched)
+    }
+
+    /// Check a path and write the result.
+    ///
+    /// # Returns
+    ///
+    /// Whether the path was matched by a pattern (a negated one only counts with `--verbose`)
+    fn check_path<W>(
+        &self,
+        check: &mut IgnoreCheck,
+        path: &str,
+        writer: &mut W,
+    ) -> anyhow::Result<bool>
+    where
+        W: Write,
+    {
+        let quote_non_ascii = check.quote_non_ascii;
+        // Paths re-included by a negated pattern are only shown with --verbose
+        let pattern = check
+            .matching_pattern(path)?
+            .filter(|pattern| self.verbose || !pattern.negated);
+        if self.quiet || (pattern.is_none() && !self.non_matching) {
+            return Ok(pattern.is_some());
+        }
+
+        let separator = if self.null_terminated { "\0" } else { ":" };
+        let terminator = if self.null_terminated { "\0" } else { "\n" };
+        if self.verbose {
+            match pattern {
+                Some(pattern) => write!(
+                    writer,
+                    "{}{separator}{}{separator}{}",
+                    pattern.source, pattern.line, pattern.text
+                )?,
+                None => write!(writer, "{separator}{separator}")?,
+            }
+            write!(writer, "{}", if self.null_terminated { "\0" } else { "\t" })?;
+        }
+        // Paths are quoted when they contain special characters, unless NUL-terminated
+        if self.null_terminated {
+            write!(writer, "{path}{terminator}")?;
+        } else {
+            writer.write_all(&quote_path(path.as_bytes(), quote_non_ascii))?;
+            write!(writer, "{terminator}")?;
+        }
+
+        Ok(pattern.is_some())
+    }
+}
+
+/// The state needed to check paths given relative to the current directory
+struct IgnoreCheck {
+    work_tree: PathBuf,
+    prefix: String,
+    /// The tracked files, which are never ignored (empty with `--no-index`)
+    index: Index,
+    ignore: Ignore,
+    /// Whether non-ASCII bytes of paths are quoted (`core.quotePath`)
+    quote_non_ascii: bool,
+}
+
+impl IgnoreCheck {
+    fn new(repo: &Repository, no_index: bool) -> anyhow::Result<Self> {
+        Ok(IgnoreCheck {
+            work_tree: repo.work_tree()?,
+            prefix: repo.prefix()?,
+            index: if no_index {
+                Index::default()
+            } else {
+                Index::load(repo)?
+            },
+            ignore: Ignore::load(repo)?,
+            quote_non_ascii: quotes_non_ascii(repo)?,
+        })
+    }
+
+    /// Find the pattern deciding whether an untracked path is ignored.
+    fn matching_pattern(&mut self, path: &str) -> anyhow::Result<Option<&IgnorePattern>> {
+        let path = normalize(&self.prefix, Path::new(path))?;
+        if path.is_empty() || self.index.contains(path.as_bytes()) {
+            return Ok(None);
+        }
+
+        let is_dir = self.work_tree.join(&path).is_dir();
+        self.ignore.matching_pattern(&path, is_dir)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CheckIgnoreArgs {
+    /// don't output anything, only report through the exit status
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// show the file, line and pattern matching each path
+    #[arg(short, long)]
+    verbose: bool,
+    /// don't look in the index, so tracked files can be checked as well
+    #[arg(long)]
+    no_index: bool,
+    /// read the paths from standard input, one per line
+    #[arg(long)]
+    stdin: bool,
+    /// separate the input and output records with NUL instead of newline
+    #[arg(short = 'z')]
+    null_terminated: bool,
+    /// show the paths that don't match any pattern as well (with --verbose)
+    #[arg(short, long)]
+    non_matching: bool,
+    /// the paths to check
+    #[arg(value_name = "pathname")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/info")).unwrap();
+        fs::create_dir_all(temp.join("build")).unwrap();
+        fs::write(temp.join(".gitignore"), "*.log\n!keep.log\nbuild/\n").unwrap();
+        fs::write(temp.join(".git/info/exclude"), "# local\nsecret\n").unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            format!(
+                "[core]\n\texcludesFile = {}\n",
+                temp.join("global-ignore").display()
+            ),
+        )
+        .unwrap();
+        fs::write(temp.join("global-ignore"), "*.tmp\n").unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn args(paths: &[&str]) -> CheckIgnoreArgs {
+        CheckIgnoreArgs {
+            quiet: false,
+            verbose: false,
+            no_index: false,
+            stdin: false,
+            null_terminated: false,
+            non_matching: false,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn check_ignore(repo: &Repository, args: CheckIgnoreArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn shows_ignored_paths() {
+        let (temp, repo) = setup();
+        let paths = ["a.log", "keep.log", "build/out", "secret", "x.tmp", "a.txt"];
+
+        let output = check_ignore(&repo, args(&paths)).unwrap();
+        assert_eq!(output, "a.log\nbuild/out\nsecret\nx.tmp\n");
+
+        let output = check_ignore(&repo, CheckIgnoreArgs {
+            verbose: true,
+            ..args(&paths)
+        })
+        .unwrap();
+        assert_eq!(
+            output,
+            format!(
+                ".gitignore:1:*.log\ta.log\n.gitignore:2:!keep.log\tkeep.log\n\
+                 .gitignore:3:build/\tbuild/out\n.git/info/exclude:2:secret\tsecret\n\
+                 {}:1:*.tmp\tx.tmp\n",
+                temp.join("global-ignore").display()
+            )
+        );
+
+        let err = check_ignore(&repo, args(&["a.txt", "keep.log"])).unwrap_err();
+        assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+    }
+
+    #[test]
+    fn reads_paths_from_stdin() {
+        let (_temp, repo) = setup();
+        let args = CheckIgnoreArgs {
+            stdin: true,
+            verbose: true,
+            non_matching: true,
+            ..args(&[])
+        };
+
+        let mut check = IgnoreCheck::new(&repo, false).unwrap();
+        let mut output = Vec::new();
+        let input = "a.log\n\"tab\\there.log\"\na.txt\n";
+        assert!(args
+            .check_stdin(&mut check, input.as_bytes(), &mut output)
+            .unwrap());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ".gitignore:1:*.log\ta.log\n.gitignore:1:*.log\t\"tab\\there.log\"\n::\ta.txt\n"
+        );
+
+        let args = CheckIgnoreArgs {
+            null_terminated: true,
+            ..args
+        };
+        let mut output = Vec::new();
+        args.check_stdin(&mut check, &b"keep.log\0a.txt\0"[..], &mut output)
+            .unwrap();
+        assert_eq!(
+            output,
+            b".gitignore\x002\0!keep.log\0keep.log\0\0\0\0a.txt\0"
+        );
+    }
+
+    #[test]
+    fn skips_tracked_files() {
+        let (_temp, repo) = setup();
+        let mut index = Index::default();
+        let hash = "e69de29bb2d1d6434b8b29ad5ab04b28f4ba04b8".to_string();
+        index.add_entry(IndexEntry::new("a.log".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+
+        assert!(check_ignore(&repo, args(&["a.log"])).is_err());
+        let output = check_ignore(&repo, CheckIgnoreArgs {
+            no_index: true,
+            ..args(&["a.log"])
+        })
+        .unwrap();
+        assert_eq!(output, "a.log\n");
+    }
+}
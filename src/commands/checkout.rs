@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::commands::restore::{restore_paths, Restore};
+use crate::commands::switch::{switch, LocalChanges, Target};
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::advice::Advice;
+use crate::utils::checkout::checkout_entry;
+use crate::utils::convert::Conversion;
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::index::{Index, IndexEntry};
+use crate::utils::merge::merge_content;
+use crate::utils::objects::{read_object_of_type, write_object, ObjectType};
+use crate::utils::pathspec::{is_within, normalize};
+use crate::utils::reflog::NULL_HASH;
+use crate::utils::refs::{head_branch, resolve_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::{resolve_revision, resolve_tree};
+use crate::utils::tree::TreeEntry;
+
+impl CommandArgs for CheckoutArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let has_dash_dash = !self.paths.is_empty();
+        let (revision, paths) = self.split_args(repo)?;
+
+        if !paths.is_empty() {
+            if let Some(branch) = &self.new_branch {
+                let revision = revision.as_deref().unwrap_or_default();
+                anyhow::bail!(
+                    "'{revision}' is not a commit and a branch '{branch}' cannot be created from it"
+                );
+            }
+            if self.detach {
+                anyhow::bail!("'--detach' cannot be used with updating paths");
+            }
+            if self.merge {
+                if revision.is_some() {
+                    anyhow::bail!(
+                        "'--merge' cannot be used when checking out paths from a tree-ish"
+                    );
+                }
+                return merge_paths(repo, &paths, writer);
+            }
+            return self.checkout_paths(repo, revision.as_deref(), &paths, !has_dash_dash, writer);
+        }
+
+        let target = match (self.new_branch, revision) {
+            (Some(name), start) => Target::NewBranch {
+                name,
+                start: start.unwrap_or_else(|| "HEAD".to_string()),
+            },
+            (None, Some(revision))
+                if !self.detach
+                    && resolve_ref(repo, &format!("refs/heads/{revision}"))?.is_some() =>
+            {
+                Target::Branch(revision)
+            },
+            (None, revision) => {
+                if revision.is_none() && !self.detach {
+                    // Without arguments, HEAD is checked out again
+                    return Ok(());
+                }
+                Target::Detached(revision.unwrap_or_else(|| "HEAD".to_string()))
+            },
+        };
+        // Leaving a branch for a commit without `--detach` may not be intended
+        let detached = match &target {
+            Target::Detached(revision) if !self.detach && head_branch(repo)?.is_some() => {
+                Some(revision.clone())
+            },
+            _ => None,
+        };
+        let local_changes = match (self.force, self.merge) {
+            (true, _) => LocalChanges::Discard,
+            (false, true) => LocalChanges::Merge,
+            (false, false) => LocalChanges::Carry,
+        };
+        switch(repo, target, local_changes, writer)?;
+        if let Some(revision) = detached {
+            if Advice::DetachedHead.enabled(repo)? {
+                eprint!("{}", detached_head_advice(&revision));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The advice shown when HEAD is detached by checking out a commit.
+fn detached_head_advice(revision: &str) -> String {
+    format!(
+        "Note: switching to '{revision}'.\n\
+         \n\
+         You are in 'detached HEAD' state. You can look around, make experimental\n\
+         changes and commit them, and you can discard any commits you make in this\n\
+         state without impacting any branches by switching back to a branch.\n\
+         \n\
+         If you want to create a new branch to retain commits you create, you may\n\
+         do so (now or later) by using -c with the switch command. Example:\n\
+         \n\
+         \x20 git switch -c <new-branch-name>\n\
+         \n\
+         Or undo this operation with:\n\
+         \n\
+         \x20 git switch -\n\
+         \n\
+         Turn off this advice by setting config variable advice.detachedHead to false\n\
+         \n"
+    )
+}
+
+impl CheckoutArgs {
+    /// Split the arguments into the branch or commit to switch to (or the tree-ish to
+    /// check the paths out from) and the paths. Without `--`, the first argument is
+    /// a revision if it resolves to one, and the other arguments are paths.
+    fn split_args(&self, repo: &Repository) -> anyhow::Result<(Option<String>, Vec<PathBuf>)> {
+        let mut args = self.args.iter();
+        if !self.paths.is_empty() {
+            if args.len() > 1 {
+                anyhow::bail!("only one reference expected");
+            }
+            return Ok((args.next().cloned(), self.paths.clone()));
+        }
+
+        let revision = match args.as_slice().first() {
+            Some(first) if self.new_branch.is_some() || resolve_revision(repo, first).is_ok() => {
+                args.next().cloned()
+            },
+            _ => None,
+        };
+        Ok((revision, args.map(PathBuf::from).collect()))
+    }
+
+    /// Check out paths from the index, or from a tree-ish into both the index and
+    /// the working tree (keeping the files that are not in the tree).
+    fn checkout_paths<W>(
+        &self,
+        repo: &Repository,
+        revision: Option<&str>,
+        paths: &[PathBuf],
+        report: bool,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let source = revision
+            .map(|revision| resolve_tree(repo, revision))
+            .transpose()?;
+        let written = restore_paths(repo, paths, &Restore {
+            source: source.as_deref(),
+            staged: source.is_some(),
+            worktree: true,
+            overlay: true,
+        })?;
+
+        if report {
+            let from = match &source {
+                Some(tree) => &tree[..7],
+                None => "the index",
+            };
+            let plural = if written == 1 { "" } else { "s" };
+            writeln!(writer, "Updated {written} path{plural} from {from}")?;
+        }
+
+        let head = resolve_ref(repo, "HEAD")?.unwrap_or_else(|| NULL_HASH.to_string());
+        let hook = Hook {
+            args: vec![head.clone(), head, "0".to_string()],
+            ..Hook::default()
+        };
+        match run_hook(repo, "post-checkout", hook)? {
+            0 => Ok(()),
+            code => Err(SilentExit(code).into()),
+        }
+    }
+}
+
+/// Recreate the conflicted merge of the unmerged paths matching some paths in the
+/// working tree, from their stages in the index. Resolved paths are unmerged again
+/// first, if the index recorded their conflicting stages.
+fn merge_paths<W>(repo: &Repository, paths: &[PathBuf], writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let work_tree = repo.work_tree()?;
+    let prefix = repo.prefix()?;
+    let mut index = Index::load(repo)?;
+    let specs = paths
+        .iter()
+        .map(|path| normalize(&prefix, path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let matches = |path: &[u8]| specs.iter().any(|spec| is_within(path, spec));
+
+    for (path, spec) in paths.iter().zip(&specs) {
+        if !index
+            .entries()
+            .iter()
+            .any(|entry| is_within(&entry.path, spec))
+        {
+            anyhow::bail!(
+                "pathspec '{}' did not match any file(s) known to git",
+                path.display()
+            );
+        }
+    }
+
+    let resolved: Vec<Vec<u8>> = index
+        .resolve_undo()
+        .iter()
+        .map(|(path, _)| path.clone())
+        .filter(|path| matches(path) && index.entry(path, 0).is_some())
+        .collect();
+    for path in resolved {
+        index.unresolve(&path);
+    }
+
+    let mut conflicts: BTreeMap<Vec<u8>, [Option<&IndexEntry>; 3]> = BTreeMap::new();
+    for entry in index.entries() {
+        if entry.stage != 0 && matches(&entry.path) {
+            conflicts.entry(entry.path.clone()).or_default()[entry.stage as usize - 1] =
+                Some(entry);
+        }
+    }
+
+    let mut conversion = Conversion::load(repo)?;
+    for (path, [base, ours, theirs]) in &conflicts {
+        let (Some(ours), Some(theirs)) = (ours, theirs) else {
+            anyhow::bail!(
+                "path '{}' does not have all necessary versions",
+                String::from_utf8_lossy(path)
+            );
+        };
+        let read = |entry: &IndexEntry| read_object_of_type(repo, &entry.hash, ObjectType::Blob);
+        let base = match base {
+            Some(base) => read(base)?,
+            None => Vec::new(),
+        };
+        let merged = merge_content(&base, &read(ours)?, &read(theirs)?, ("ours", "theirs"));
+        let entry = TreeEntry {
+            mode: ours.mode,
+            name: path.clone(),
+            hash: write_object(repo, ObjectType::Blob, &merged.content)?,
+        };
+        checkout_entry(repo, &entry, &work_tree, &mut conversion)?;
+    }
+    index.write(repo)?;
+
+    let plural = if conflicts.len() == 1 { "" } else { "s" };
+    writeln!(
+        writer,
+        "Recreated {} merge conflict{plural}",
+        conflicts.len()
+    )?;
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CheckoutArgs {
+    /// create a new branch at the start point (HEAD by default) and switch to it
+    #[arg(short = 'b', value_name = "new-branch", conflicts_with = "detach")]
+    new_branch: Option<String>,
+    /// detach HEAD at the commit (HEAD by default)
+    #[arg(long)]
+    detach: bool,
+    /// throw away local changes and untracked files in the way when switching
+    #[arg(short, long)]
+    force: bool,
+    /// merge local changes that would be overwritten when switching, or recreate
+    /// the conflicted merge of unmerged paths
+    #[arg(short, long, conflicts_with = "force")]
+    merge: bool,
+    /// the branch or commit to switch to, or the tree-ish and paths to check out
+    #[arg(value_name = "branch")]
+    args: Vec<String>,
+    /// the paths to check out
+    #[arg(value_name = "pathspec", last = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository, [String; 2]) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let mut index = Index::default();
+        let mut trees = Vec::new();
+        let mut parents = Vec::new();
+        for content in ["first\n", "second\n"] {
+            fs::write(temp.join("a"), content).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new("a".into(), 0o100644, hash));
+            let tree = index.write_tree(&repo, false).unwrap();
+            parents = vec![create_commit(&repo, &tree, parents, content).unwrap()];
+            trees.push(tree);
+        }
+        index.write(&repo).unwrap();
+        write_ref(&repo, "refs/heads/main", &parents[0]).unwrap();
+        (temp, repo, [trees.remove(0), trees.remove(0)])
+    }
+
+    fn checkout(repo: &Repository, args: &[&str], paths: &[&str]) -> anyhow::Result<String> {
+        let mut new_branch = None;
+        let mut rest = args;
+        if let ["-b", name, others @ ..] = args {
+            new_branch = Some(name.to_string());
+            rest = others;
+        }
+        let args = CheckoutArgs {
+            new_branch,
+            detach: false,
+            force: false,
+            merge: false,
+            args: rest.iter().map(|arg| arg.to_string()).collect(),
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn switches_branches_and_commits() {
+        let (temp, repo, _) = setup();
+
+        assert_eq!(
+            checkout(&repo, &["-b", "side", "HEAD~1"], &[]).unwrap(),
+            "Switched to a new branch 'side'\n"
+        );
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), "first\n");
+        assert_eq!(
+            checkout(&repo, &["main"], &[]).unwrap(),
+            "Switched to branch 'main'\n"
+        );
+        let output = checkout(&repo, &["main~1"], &[]).unwrap();
+        assert!(output.starts_with("HEAD is now at "));
+        assert_eq!(head_branch(&repo).unwrap(), None);
+    }
+
+    #[test]
+    fn checks_out_paths() {
+        let (temp, repo, [first, _]) = setup();
+
+        fs::write(temp.join("a"), "changed\n").unwrap();
+        assert_eq!(
+            checkout(&repo, &["a"], &[]).unwrap(),
+            "Updated 1 path from the index\n"
+        );
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), "second\n");
+
+        assert_eq!(
+            checkout(&repo, &["main~1", "a"], &[]).unwrap(),
+            format!("Updated 1 path from {}\n", &first[..7])
+        );
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), "first\n");
+        let index = Index::load(&repo).unwrap();
+        let staged = &index.entry(b"a", 0).unwrap().hash;
+        assert_eq!(
+            staged,
+            &write_object(&repo, ObjectType::Blob, b"first\n").unwrap()
+        );
+
+        // Nothing is reported with `--`
+        assert_eq!(checkout(&repo, &["main"], &["a"]).unwrap(), "");
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), "second\n");
+    }
+
+    #[test]
+    fn recreates_conflicts() {
+        let (temp, repo, _) = setup();
+        let mut index = Index::load(&repo).unwrap();
+        for (stage, content) in [(1, "first\n"), (2, "ours\n"), (3, "theirs\n")] {
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            let mut entry = IndexEntry::new("a".into(), 0o100644, hash);
+            entry.stage = stage;
+            index.add_entry(entry);
+        }
+        index.write(&repo).unwrap();
+        let merge = |paths: &[&str]| {
+            let args = CheckoutArgs {
+                new_branch: None,
+                detach: false,
+                force: false,
+                merge: true,
+                args: Vec::new(),
+                paths: paths.iter().map(PathBuf::from).collect(),
+            };
+            let mut output = Vec::new();
+            args.run(&repo, &mut output)
+                .map(|_| String::from_utf8(output).unwrap())
+        };
+        let conflicted = "<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n";
+
+        assert_eq!(merge(&["a"]).unwrap(), "Recreated 1 merge conflict\n");
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), conflicted);
+
+        // Resolved paths are unmerged again
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"resolved\n").unwrap();
+        index.add_entry(IndexEntry::new("a".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+        assert_eq!(merge(&["a"]).unwrap(), "Recreated 1 merge conflict\n");
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), conflicted);
+        let index = Index::load(&repo).unwrap();
+        let stages: Vec<_> = index.entries().iter().map(|entry| entry.stage).collect();
+        assert_eq!(stages, [1, 2, 3]);
+
+        let err = merge(&["missing"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'missing' did not match any file(s) known to git"
+        );
+    }
+}
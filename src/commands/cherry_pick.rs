@@ -0,0 +1,832 @@
+use std::io::Write;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::commit::cleanup_message;
+use crate::commands::rerere::{self, rerere};
+use crate::commands::CommandArgs;
+use crate::utils::advice::{warning, with_advice, Advice};
+use crate::utils::checkout::{check_overwritten, reset_tree, switch_tree, Operation};
+use crate::utils::commit::{create_commit, create_commit_as, read_commit, Commit};
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::index::Index;
+use crate::utils::merge::merge_files;
+use crate::utils::refs::{
+    delete_ref, head_branch, resolve_ref, shorten_ref, update_head, write_ref,
+};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::sequencer::{self, Action, Options, Step};
+use crate::utils::tree::EMPTY_TREE;
+use crate::utils::walk::RevWalk;
+
+/// The advice for committing (or applying a commit) with unmerged files
+const RESOLVE_CONFLICT: &str = "Fix them up in the work tree, and then use 'git add/rm <file>'\n\
+                                as appropriate to mark resolution and make a commit.";
+
+impl CommandArgs for CherryPickArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.sequencer.operation() {
+            Some(operation) => run_operation(repo, Action::Pick, operation, writer),
+            None => {
+                let options = Options {
+                    no_commit: self.no_commit,
+                };
+                start(repo, Action::Pick, &self.commits, options, writer)
+            },
+        }
+    }
+}
+
+/// What is done with a stopped cherry-pick or revert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SequencerOperation {
+    /// Commit the resolved changes and apply the remaining commits
+    Continue,
+    /// Drop the changes of the commit it stopped at and apply the remaining commits
+    Skip,
+    /// Go back to the commit HEAD was at before it started
+    Abort,
+    /// Forget about it, keeping HEAD, the index and the working tree as they are
+    Quit,
+}
+
+/// Run an operation on a stopped cherry-pick or revert.
+///
+/// # Arguments
+///
+/// * `repo` - The repository the commits are applied in
+/// * `action` - The command running the operation, named in its errors
+/// * `operation` - The operation to run
+/// * `writer` - Where the progress is written
+pub(crate) fn run_operation<W>(
+    repo: &Repository,
+    action: Action,
+    operation: SequencerOperation,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let state = sequencer::load(repo)?;
+    let stopped = stopped_action(repo)?;
+    if state.is_none() && stopped.is_none() {
+        anyhow::bail!("no cherry-pick or revert in progress");
+    }
+
+    match operation {
+        SequencerOperation::Continue => {
+            if let Some(action) = stopped {
+                commit_resolved(repo, action, writer)?;
+            }
+        },
+        SequencerOperation::Skip => {
+            let head = resolve_ref(repo, "HEAD")?;
+            let tree = match &head {
+                Some(head) => read_commit(repo, head)?.tree,
+                None => EMPTY_TREE.to_string(),
+            };
+            let mut index = Index::load(repo)?;
+            reset_tree(repo, &mut index, &repo.work_tree()?, &tree)?;
+            index.write(repo)?;
+            rerere::clear(repo)?;
+            clear_stopped(repo)?;
+        },
+        SequencerOperation::Abort => {
+            let head = resolve_ref(repo, "HEAD")?;
+            let target = match &state {
+                // HEAD is only rewound if it did not move since the sequence stopped
+                Some(state) if head.as_deref() != Some(state.abort_safety.as_str()) => {
+                    warning("You seem to have moved HEAD. Not rewinding, check your HEAD!");
+                    None
+                },
+                Some(state) => Some(state.head.clone()),
+                None => head.clone(),
+            };
+            if let Some(target) = target {
+                let tree = read_commit(repo, &target)?.tree;
+                let mut index = Index::load(repo)?;
+                reset_tree(repo, &mut index, &repo.work_tree()?, &tree)?;
+                index.write(repo)?;
+                if head.as_deref() != Some(target.as_str()) {
+                    update_head(repo, &target, &format!("{}: --abort", action.command()))?;
+                }
+            }
+            rerere::clear(repo)?;
+            clear_stopped(repo)?;
+            return sequencer::remove(repo);
+        },
+        SequencerOperation::Quit => {
+            clear_stopped(repo)?;
+            return sequencer::remove(repo);
+        },
+    }
+
+    // The commit it stopped at is done (or skipped), unless it could not be applied
+    // at all, and the remaining ones are applied
+    let Some(mut state) = state else {
+        return Ok(());
+    };
+    let done = stopped.is_some() || state.options.no_commit;
+    if !state.todo.is_empty() && (done || operation == SequencerOperation::Skip) {
+        state.todo.remove(0);
+    }
+    run_steps(repo, state.todo, &state.head, state.options, writer)
+}
+
+/// Apply commits to HEAD.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to apply the commits in
+/// * `action` - Whether the commits are picked or reverted
+/// * `revisions` - The commits to apply (`<from>..<to>` for the commits of a range)
+/// * `options` - The options of the sequence
+/// * `writer` - Where the progress is written
+pub(crate) fn start<W>(
+    repo: &Repository,
+    action: Action,
+    revisions: &[String],
+    options: Options,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if sequencer::in_progress(repo)? || stopped_action(repo)?.is_some() {
+        let command = action.command();
+        anyhow::bail!(with_advice(
+            repo,
+            Advice::SequencerInUse,
+            "a cherry-pick or revert is already in progress".to_string(),
+            &format!("try \"git {command} (--continue | --skip | --abort | --quit)\""),
+        )?);
+    }
+
+    let mut todo = Vec::new();
+    for hash in expand_revisions(repo, revisions)? {
+        todo.push(Step { action, hash });
+    }
+    if todo.is_empty() {
+        anyhow::bail!("empty commit set passed");
+    }
+
+    let head = resolve_ref(repo, "HEAD")?.unwrap_or_default();
+    run_steps(repo, todo, &head, options, writer)
+}
+
+/// The commits of revisions in the order they are applied: a commit by itself,
+/// and the commits of a `<from>..<to>` range oldest first.
+fn expand_revisions(repo: &Repository, revisions: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut commits = Vec::new();
+    for revision in revisions {
+        let Some((from, to)) = revision.split_once("..") else {
+            commits.push(resolve_commit(repo, revision)?);
+            continue;
+        };
+
+        let or_head = |side: &'_ str| if side.is_empty() { "HEAD" } else { side }.to_string();
+        let mut walk = RevWalk::new(repo);
+        walk.hide(&resolve_commit(repo, &or_head(from))?)?;
+        walk.push(&resolve_commit(repo, &or_head(to))?)?;
+        let mut range = walk
+            .map(|entry| entry.map(|(hash, _)| hash))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        range.reverse();
+        commits.extend(range);
+    }
+    Ok(commits)
+}
+
+/// Apply a list of commits, saving the state of the sequence if it stops.
+fn run_steps<W>(
+    repo: &Repository,
+    todo: Vec<Step>,
+    head: &str,
+    options: Options,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    for (i, step) in todo.iter().enumerate() {
+        let (stop, applied) = match apply_step(repo, step, options.no_commit, writer) {
+            Ok(Applied::Done) => continue,
+            Ok(Applied::Conflicts(hash)) => (conflict_error(repo, step.action, &hash), true),
+            Ok(Applied::Empty) => (empty_error(step.action), true),
+            Err(err) => (err, false),
+        };
+
+        // Only a sequence of several commits keeps a todo list to come back to,
+        // once it applied something
+        if (applied && todo.len() > 1) || i > 0 || sequencer::in_progress(repo)? {
+            let current = resolve_ref(repo, "HEAD")?.unwrap_or_default();
+            sequencer::save(repo, &todo[i..], head, &current, options)?;
+        }
+        return Err(stop);
+    }
+    sequencer::remove(repo)
+}
+
+/// What happened when applying a commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Applied {
+    /// The changes were applied (and committed, unless they were not to be)
+    Done,
+    /// The changes of the commit with this hash conflict, and were left to resolve
+    Conflicts(String),
+    /// The changes are already in HEAD, so there is nothing to commit
+    Empty,
+}
+
+/// The changes of a commit (or their inverse) merged into the index and the
+/// working tree
+#[derive(Debug, Clone)]
+pub(crate) struct MergedStep {
+    /// The hash of the commit
+    pub(crate) hash: String,
+    /// The commit
+    pub(crate) commit: Commit,
+    /// The message of the commit to create
+    pub(crate) message: String,
+    /// The merged tree
+    pub(crate) tree: String,
+    /// The commit HEAD is at, if any
+    pub(crate) head: Option<String>,
+    /// The tree of HEAD
+    pub(crate) head_tree: String,
+    /// The paths left with conflicts
+    pub(crate) conflicts: Vec<String>,
+}
+
+/// Merge the changes of a commit (or their inverse) into the index and the working
+/// tree, the changes made by the commit being their side and its parent the base.
+///
+/// The changes are merged into HEAD, or into the index if `into_index` is set
+/// (otherwise the index must match HEAD). The messages of the merge are written.
+pub(crate) fn merge_step<W>(
+    repo: &Repository,
+    step: &Step,
+    into_index: bool,
+    writer: &mut W,
+) -> anyhow::Result<MergedStep>
+where
+    W: Write,
+{
+    let action = step.action;
+    let hash = resolve_commit(repo, &step.hash)?;
+    let commit = read_commit(repo, &hash)?;
+    let parent_tree = match commit.parents.as_slice() {
+        [] => EMPTY_TREE.to_string(),
+        [parent] => read_commit(repo, parent)?.tree,
+        _ => anyhow::bail!("commit {hash} is a merge but no -m option was given."),
+    };
+
+    let head = resolve_ref(repo, "HEAD")?;
+    let head_tree = match &head {
+        Some(head) => read_commit(repo, head)?.tree,
+        None => EMPTY_TREE.to_string(),
+    };
+    let mut index = Index::load(repo)?;
+    if index.entries().iter().any(|entry| entry.stage != 0) {
+        let doing = match action {
+            Action::Revert => "Reverting",
+            _ => "Cherry-picking",
+        };
+        anyhow::bail!(with_advice(
+            repo,
+            Advice::ResolveConflict,
+            format!("{doing} is not possible because you have unmerged files."),
+            RESOLVE_CONFLICT,
+        )?);
+    }
+    let ours = index.write_tree(repo, false)?;
+    if !into_index && ours != head_tree {
+        anyhow::bail!(with_advice(
+            repo,
+            Advice::CommitBeforeMerge,
+            format!(
+                "your local changes would be overwritten by {}.",
+                action.command()
+            ),
+            "commit your changes or stash them to proceed.",
+        )?);
+    }
+
+    let subject = commit.subject();
+    let label = format!("{} ({subject})", &hash[..7]);
+    let (base, theirs, label, message) = match action {
+        Action::Revert => (
+            commit.tree.clone(),
+            parent_tree,
+            format!("parent of {label}"),
+            format!("Revert \"{subject}\"\n\nThis reverts commit {hash}.\n"),
+        ),
+        _ => (
+            parent_tree,
+            commit.tree.clone(),
+            label,
+            String::from_utf8_lossy(&commit.message).into_owned(),
+        ),
+    };
+
+    let merge = merge_files(repo, &base, &ours, &theirs, ("HEAD", &label))?;
+    for path in merge.binary_conflicts() {
+        warning(&format!(
+            "Cannot merge binary files: {path} (HEAD vs. {label})"
+        ));
+    }
+    for message in &merge.messages {
+        writeln!(writer, "{message}")?;
+    }
+    let work_tree = repo.work_tree()?;
+    let merged = merge.write_work_tree(repo)?;
+    check_overwritten(repo, &index, &work_tree, &ours, &merged, Operation::Merge)?;
+    switch_tree(repo, &mut index, &work_tree, &ours, &merged)?;
+    merge.add_conflicts(&mut index);
+    index.write(repo)?;
+    if !merge.conflicts().is_empty() {
+        rerere(repo)?;
+    }
+
+    Ok(MergedStep {
+        hash,
+        commit,
+        message,
+        tree: merged,
+        head,
+        head_tree,
+        conflicts: merge.conflicts().into_iter().map(String::from).collect(),
+    })
+}
+
+/// Apply the changes of a commit (or their inverse) to the index and the working tree,
+/// then commit them unless `no_commit` is set.
+///
+/// The changes are merged as [`merge_step`] does. When the changes conflict or are
+/// empty, the commit is recorded in `CHERRY_PICK_HEAD` (or `REVERT_HEAD`) for
+/// `--continue`, unless they are not committed, and the message in `MERGE_MSG`.
+pub(crate) fn apply_step<W>(
+    repo: &Repository,
+    step: &Step,
+    no_commit: bool,
+    writer: &mut W,
+) -> anyhow::Result<Applied>
+where
+    W: Write,
+{
+    let action = step.action;
+    let MergedStep {
+        hash,
+        commit,
+        message,
+        tree,
+        head,
+        head_tree,
+        conflicts,
+    } = merge_step(repo, step, no_commit, writer)?;
+
+    let message_path = repo.git_dir()?.join("MERGE_MSG");
+    if !conflicts.is_empty() || no_commit {
+        std::fs::write(&message_path, conflict_message(&message, &conflicts))
+            .context("write MERGE_MSG")?;
+    }
+    if no_commit {
+        return Ok(match conflicts.is_empty() {
+            true => Applied::Done,
+            false => Applied::Conflicts(hash),
+        });
+    }
+    if !conflicts.is_empty() {
+        write_ref(repo, action.head_ref(), &hash)?;
+        return Ok(Applied::Conflicts(hash));
+    }
+    if tree == head_tree {
+        write_ref(repo, action.head_ref(), &hash)?;
+        std::fs::write(&message_path, &message).context("write MERGE_MSG")?;
+        return Ok(Applied::Empty);
+    }
+
+    let parents: Vec<String> = head.into_iter().collect();
+    let new = match action {
+        Action::Revert => create_commit(repo, &tree, parents, &message)?,
+        _ => create_commit_as(repo, &tree, parents, &message, &commit.author)?,
+    };
+    finish_commit(repo, &new, &format!("{}: ", action.command()), writer)?;
+    Ok(Applied::Done)
+}
+
+/// A commit message followed by comments listing the conflicting paths, if any,
+/// as it is left in `MERGE_MSG`.
+pub(crate) fn conflict_message(message: &str, conflicts: &[String]) -> String {
+    let mut content = message.to_string();
+    if !conflicts.is_empty() {
+        content.push_str("\n# Conflicts:\n");
+        for path in conflicts {
+            content.push_str(&format!("#\t{path}\n"));
+        }
+    }
+    content
+}
+
+/// Commit the resolved changes of the commit a cherry-pick or revert stopped at,
+/// with the message of `MERGE_MSG`.
+fn commit_resolved<W>(repo: &Repository, action: Action, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let author = match action {
+        Action::Revert => None,
+        _ => {
+            let stopped = resolve_commit(repo, action.head_ref())?;
+            Some(read_commit(repo, &stopped)?.author)
+        },
+    };
+    let Some(hash) = create_resolved_commit(repo, author.as_deref())? else {
+        return Err(empty_error(action));
+    };
+    clear_stopped(repo)?;
+    finish_commit(
+        repo,
+        &hash,
+        &format!("commit ({}): ", action.command()),
+        writer,
+    )
+}
+
+/// Create a commit on top of HEAD from the index once its conflicts are resolved,
+/// with the message of `MERGE_MSG` (without its comments).
+///
+/// # Arguments
+///
+/// * `repo` - The repository to commit in
+/// * `author` - The author line to keep, or `None` for the current user
+///
+/// # Returns
+///
+/// The hash of the commit, or `None` if the index matches HEAD
+pub(crate) fn create_resolved_commit(
+    repo: &Repository,
+    author: Option<&[u8]>,
+) -> anyhow::Result<Option<String>> {
+    let mut index = Index::load(repo)?;
+    if index.entries().iter().any(|entry| entry.stage != 0) {
+        anyhow::bail!(with_advice(
+            repo,
+            Advice::ResolveConflict,
+            "Committing is not possible because you have unmerged files.".to_string(),
+            RESOLVE_CONFLICT,
+        )?);
+    }
+    let tree = index.write_tree(repo, false)?;
+    let head = resolve_ref(repo, "HEAD")?;
+    let head_tree = match &head {
+        Some(head) => read_commit(repo, head)?.tree,
+        None => EMPTY_TREE.to_string(),
+    };
+    if tree == head_tree {
+        return Ok(None);
+    }
+
+    // The comments listing the conflicts are dropped from the message
+    let message_path = repo.git_dir()?.join("MERGE_MSG");
+    let message = std::fs::read_to_string(&message_path).context("read MERGE_MSG")?;
+    let message: Vec<&str> = message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    let message = cleanup_message(&message.join("\n"));
+    if message.is_empty() {
+        anyhow::bail!("Aborting commit due to empty commit message.");
+    }
+
+    let parents: Vec<String> = head.into_iter().collect();
+    let hash = match author {
+        Some(author) => create_commit_as(repo, &tree, parents, &message, author)?,
+        None => create_commit(repo, &tree, parents, &message)?,
+    };
+    Ok(Some(hash))
+}
+
+/// Move HEAD to a new commit, record the resolutions of its conflicts, run the
+/// `post-commit` hook and show the commit.
+pub(crate) fn finish_commit<W>(
+    repo: &Repository,
+    hash: &str,
+    reflog_prefix: &str,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let subject = read_commit(repo, hash)?.subject();
+    update_head(repo, hash, &format!("{reflog_prefix}{subject}"))?;
+    // The resolutions of the conflicts of the commit are recorded
+    rerere(repo)?;
+    run_hook(repo, "post-commit", Hook::default())?;
+
+    let branch = match head_branch(repo)? {
+        Some(branch) => shorten_ref(&branch).to_string(),
+        None => "detached HEAD".to_string(),
+    };
+    writeln!(writer, "[{branch} {}] {subject}", &hash[..7])?;
+    Ok(())
+}
+
+/// The action whose commit is recorded as the one it stopped at, if any.
+fn stopped_action(repo: &Repository) -> anyhow::Result<Option<Action>> {
+    for action in [Action::Pick, Action::Revert] {
+        if resolve_ref(repo, action.head_ref())?.is_some() {
+            return Ok(Some(action));
+        }
+    }
+    Ok(None)
+}
+
+/// Forget the commit a cherry-pick or revert stopped at, and its message.
+fn clear_stopped(repo: &Repository) -> anyhow::Result<()> {
+    for action in [Action::Pick, Action::Revert] {
+        delete_ref(repo, action.head_ref())?;
+    }
+    let message_path = repo.git_dir()?.join("MERGE_MSG");
+    if message_path.is_file() {
+        std::fs::remove_file(&message_path).context("remove MERGE_MSG")?;
+    }
+    Ok(())
+}
+
+/// The error for a commit whose changes conflict.
+fn conflict_error(repo: &Repository, action: Action, hash: &str) -> anyhow::Error {
+    let subject = read_commit(repo, hash)
+        .map(|commit| commit.subject())
+        .unwrap_or_default();
+    let verb = match action {
+        Action::Revert => "revert",
+        _ => "apply",
+    };
+    let command = action.command();
+    let message = format!("could not {verb} {}... {subject}", &hash[..7]);
+    let advice = format!(
+        "After resolving the conflicts, mark them with\n\
+         \"git add/rm <pathspec>\", then run\n\
+         \"git {command} --continue\".\n\
+         You can instead skip this commit with \"git {command} --skip\".\n\
+         To abort and get back to the state before \"git {command}\",\n\
+         run \"git {command} --abort\"."
+    );
+    let message =
+        with_advice(repo, Advice::MergeConflict, message.clone(), &advice).unwrap_or(message);
+    anyhow::anyhow!(message)
+}
+
+/// The error for a commit whose changes are already in HEAD.
+fn empty_error(action: Action) -> anyhow::Error {
+    let command = action.command();
+    anyhow::anyhow!(
+        "The previous {command} is now empty, possibly due to conflict resolution.\n\
+         If you wish to commit it anyway, use:\n\n    \
+         git commit --allow-empty\n\n\
+         Otherwise, please use 'git {command} --skip'"
+    )
+}
+
+// The flags running an operation on a stopped cherry-pick or revert (not a doc
+// comment, which would become the description of the commands it is flattened in)
+#[derive(Args, Debug, Default)]
+pub(crate) struct SequencerArgs {
+    /// commit the resolved changes and apply the remaining commits
+    #[arg(long = "continue", group = "operation")]
+    continue_: bool,
+    /// drop the changes of the current commit and apply the remaining commits
+    #[arg(long, group = "operation")]
+    skip: bool,
+    /// go back to the commit HEAD was at before the sequence started
+    #[arg(long, group = "operation")]
+    abort: bool,
+    /// forget about the sequence, keeping the current changes
+    #[arg(long, group = "operation")]
+    quit: bool,
+}
+
+impl SequencerArgs {
+    /// The operation requested, if any.
+    pub(crate) fn operation(&self) -> Option<SequencerOperation> {
+        [
+            (self.continue_, SequencerOperation::Continue),
+            (self.skip, SequencerOperation::Skip),
+            (self.abort, SequencerOperation::Abort),
+            (self.quit, SequencerOperation::Quit),
+        ]
+        .into_iter()
+        .find(|(set, _)| *set)
+        .map(|(_, operation)| operation)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CherryPickArgs {
+    /// apply the changes to the index and the working tree without committing them
+    #[arg(short = 'n', long, conflicts_with = "operation")]
+    no_commit: bool,
+    #[command(flatten)]
+    sequencer: SequencerArgs,
+    /// the commits to apply (<from>..<to> for the commits of a range)
+    #[arg(
+        value_name = "commit",
+        required_unless_present = "operation",
+        conflicts_with = "operation"
+    )]
+    commits: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::write_symref;
+    use crate::utils::test::TempRepo;
+
+    /// Write a commit with some files (a root commit without a parent), returning its hash.
+    fn commit(repo: &Repository, parent: &str, files: &[(&str, &str)], message: &str) -> String {
+        let mut index = Index::default();
+        for (path, content) in files {
+            let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+        }
+        let tree = index.write_tree(repo, false).unwrap();
+        let parents = [parent].into_iter().filter(|parent| !parent.is_empty());
+        create_commit(repo, &tree, parents.map(String::from).collect(), message).unwrap()
+    }
+
+    /// Set up a repository whose `main` and `side` branches changed the same line
+    /// of `f`, `side` then adding `g`.
+    fn setup() -> (TempRepo, Repository, [String; 3]) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let base = commit(&repo, "", &[("f", "a\nb\nc\n")], "base\n");
+        let change = commit(&repo, &base, &[("f", "a\nB\nc\n")], "side change\n");
+        let add = commit(
+            &repo,
+            &change,
+            &[("f", "a\nB\nc\n"), ("g", "g\n")],
+            "add g\n",
+        );
+        let main = commit(&repo, &base, &[("f", "a\nX\nc\n")], "main change\n");
+        write_ref(&repo, "refs/heads/main", &main).unwrap();
+
+        fs::write(temp.join("f"), "a\nX\nc\n").unwrap();
+        let mut index = Index::default();
+        let hash = write_object(&repo, ObjectType::Blob, b"a\nX\nc\n").unwrap();
+        index.add_entry(IndexEntry::new("f".into(), 0o100644, hash));
+        index.refresh(temp.path()).unwrap();
+        index.write(&repo).unwrap();
+        (temp, repo, [change, add, main])
+    }
+
+    #[test]
+    fn picks_commits_and_continues_after_conflicts() {
+        let (temp, repo, [change, add, main]) = setup();
+
+        let mut output = Vec::new();
+        let commits = [change.clone(), add.clone()];
+        let err = start(
+            &repo,
+            Action::Pick,
+            &commits,
+            Options::default(),
+            &mut output,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with(&format!(
+            "could not apply {}... side change\n",
+            &change[..7]
+        )));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Auto-merging f\nCONFLICT (content): Merge conflict in f\n"
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join("f")).unwrap(),
+            format!(
+                "a\n<<<<<<< HEAD\nX\n=======\nB\n>>>>>>> {} (side change)\nc\n",
+                &change[..7]
+            )
+        );
+        let index = Index::load(&repo).unwrap();
+        let stages: Vec<u8> = index.entries().iter().map(|entry| entry.stage).collect();
+        assert_eq!(stages, [1, 2, 3]);
+        assert_eq!(
+            resolve_ref(&repo, "CHERRY_PICK_HEAD").unwrap(),
+            Some(change.clone())
+        );
+        assert!(sequencer::in_progress(&repo).unwrap());
+
+        // The conflicts have to be resolved first
+        let err = run_operation(
+            &repo,
+            Action::Pick,
+            SequencerOperation::Continue,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("Committing is not possible"));
+
+        fs::write(temp.join("f"), "a\nY\nc\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"a\nY\nc\n").unwrap();
+        index.add_entry(IndexEntry::new("f".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+
+        let mut output = Vec::new();
+        run_operation(
+            &repo,
+            Action::Pick,
+            SequencerOperation::Continue,
+            &mut output,
+        )
+        .unwrap();
+        let head = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        let head_commit = read_commit(&repo, &head).unwrap();
+        let picked = read_commit(&repo, &head_commit.parents[0]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "[main {}] side change\n[main {}] add g\n",
+                &head_commit.parents[0][..7],
+                &head[..7]
+            )
+        );
+        assert_eq!(picked.parents, [main]);
+        assert_eq!(picked.message, b"side change\n");
+        assert_eq!(picked.author, read_commit(&repo, &change).unwrap().author);
+        assert_eq!(fs::read_to_string(temp.join("g")).unwrap(), "g\n");
+        assert_eq!(resolve_ref(&repo, "CHERRY_PICK_HEAD").unwrap(), None);
+        assert!(!sequencer::in_progress(&repo).unwrap());
+        assert!(!repo.git_dir().unwrap().join("MERGE_MSG").exists());
+    }
+
+    #[test]
+    fn aborts_and_applies_without_committing() {
+        let (temp, repo, [change, add, main]) = setup();
+
+        let commits = [change.clone()];
+        start(
+            &repo,
+            Action::Pick,
+            &commits,
+            Options::default(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        // A single commit has no todo list
+        assert!(!sequencer::in_progress(&repo).unwrap());
+        run_operation(
+            &repo,
+            Action::Pick,
+            SequencerOperation::Abort,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(temp.join("f")).unwrap(), "a\nX\nc\n");
+        assert!(Index::load(&repo)
+            .unwrap()
+            .entries()
+            .iter()
+            .all(|entry| entry.stage == 0));
+        assert_eq!(resolve_ref(&repo, "CHERRY_PICK_HEAD").unwrap(), None);
+        let err = run_operation(
+            &repo,
+            Action::Pick,
+            SequencerOperation::Abort,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "no cherry-pick or revert in progress");
+
+        let options = Options { no_commit: true };
+        let range = [format!("{change}..{add}")];
+        start(&repo, Action::Pick, &range, options, &mut Vec::new()).unwrap();
+        assert_eq!(resolve_ref(&repo, "HEAD").unwrap(), Some(main));
+        assert_eq!(fs::read_to_string(temp.join("g")).unwrap(), "g\n");
+        assert!(Index::load(&repo).unwrap().entry(b"g", 0).is_some());
+        let message = fs::read_to_string(repo.git_dir().unwrap().join("MERGE_MSG")).unwrap();
+        assert_eq!(message, "add g\n");
+    }
+}
@@ -0,0 +1,959 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::submodule::update_submodules;
+use crate::commands::CommandArgs;
+use crate::utils::advice::warning;
+use crate::utils::bundle::{is_bundle, Bundle};
+use crate::utils::checkout::checkout_tree;
+use crate::utils::commit::read_commit;
+use crate::utils::index::{Index, IndexEntry, Stat};
+use crate::utils::objects::{object_exists, write_object};
+use crate::utils::pack::read_pack;
+use crate::utils::path_from_bytes;
+use crate::utils::pathspec::Pathspec;
+use crate::utils::protocol::filter::Filter;
+use crate::utils::protocol::promisor::fetch_missing;
+use crate::utils::protocol::shallow::Deepen;
+use crate::utils::protocol::transport::Connection;
+use crate::utils::reflog::log_ref_update;
+use crate::utils::refs::{
+    list_refs, resolve_symref, update_ref, write_ref, write_symref, ResolvedRef,
+};
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::TempPath;
+use crate::utils::transfer::{copy_objects, open_repository};
+use crate::utils::tree::{flatten_tree_checked, MODE_GITLINK};
+
+impl CommandArgs for CloneArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        // A mirror is a bare repository that also keeps the refs in sync
+        let bare = self.bare || self.mirror;
+        // Bundles are cloned from as a repository holding their refs and objects
+        let bundle_path = repo.cwd().join(&self.repository);
+        let bundle = match is_bundle(&bundle_path) {
+            true => Some(Bundle::read(&bundle_path)?),
+            false => None,
+        };
+        // A new repository has none of the commits a bundle may require
+        if let Some(bundle) = bundle
+            .as_ref()
+            .filter(|bundle| !bundle.prerequisites.is_empty())
+        {
+            let missing: Vec<_> = bundle
+                .prerequisites
+                .iter()
+                .map(|(hash, comment)| format!("{hash} {comment}"))
+                .collect();
+            anyhow::bail!(
+                "Repository lacks these prerequisite commits:\n{}",
+                missing.join("\n")
+            );
+        }
+        // Repositories served by a daemon are only reached through the git protocol
+        let source = match self.repository.starts_with("git://") || bundle.is_some() {
+            true => None,
+            false => Some(open_repository(repo, &self.repository)?),
+        };
+        let url = match &source {
+            None if bundle.is_some() => std::fs::canonicalize(&bundle_path)?
+                .to_string_lossy()
+                .into_owned(),
+            None => self.repository.clone(),
+            Some(source) if source.url.starts_with("file://") => source.url.clone(),
+            Some(source) => std::fs::canonicalize(repo.cwd().join(&source.url))?
+                .to_string_lossy()
+                .into_owned(),
+        };
+
+        // Objects are copied from the object directory of local paths, not of URLs
+        let local = source
+            .as_ref()
+            .is_some_and(|source| !source.url.starts_with("file://"))
+            && !self.no_local;
+        if self.local && !local {
+            warning("--local is ignored");
+        }
+        // Only histories fetched from upload-pack are cut off
+        let deepen = match self.depth {
+            Some(0) => anyhow::bail!("depth 0 is not a positive number"),
+            Some(_) if local || self.shared || bundle.is_some() => {
+                warning("--depth is ignored in local clones; use file:// instead.");
+                None
+            },
+            depth => depth.map(Deepen),
+        };
+        // Objects left out are fetched on demand from the remote
+        let filter = match self.filter.as_deref().map(Filter::parse).transpose()? {
+            Some(_) if local || self.shared || bundle.is_some() => {
+                warning("--filter is ignored in local clones; use file:// instead.");
+                None
+            },
+            filter => filter,
+        };
+        let mut references = Vec::new();
+        for reference in &self.reference {
+            match open_repository(repo, reference) {
+                Ok(other) => references.push(other.repo.object_dir(false)?.canonicalize()?),
+                Err(_) => {
+                    anyhow::bail!("reference repository '{reference}' is not a local repository.")
+                },
+            }
+        }
+
+        let directory = match self.directory {
+            Some(directory) => directory,
+            None => guess_directory(&self.repository, bare),
+        };
+        let path = repo.cwd().join(&directory);
+        if path.exists() && path.read_dir()?.next().is_some() {
+            anyhow::bail!(
+                "destination path '{}' already exists and is not an empty directory.",
+                directory.display()
+            );
+        }
+
+        // Progress goes to stderr, apart from the output of the command
+        if !self.quiet {
+            match bare {
+                true => eprintln!("Cloning into bare repository '{}'...", directory.display()),
+                false => eprintln!("Cloning into '{}'...", directory.display()),
+            }
+        }
+
+        // The git directory may be kept apart, the working tree pointing to it
+        // with a gitfile
+        let (git_dir, work_tree) = match (bare, &self.separate_git_dir) {
+            (true, _) => (path.clone(), None),
+            (false, Some(git_dir)) => (repo.cwd().join(git_dir), Some(path.clone())),
+            (false, None) => (path.join(".git"), Some(path.clone())),
+        };
+        // What the clone creates is removed if it fails or is interrupted
+        let junk = match path.exists() {
+            false => Some(TempPath::new(path.clone())),
+            true if !bare => Some(TempPath::new(path.join(".git"))),
+            true => None,
+        };
+        let separate_junk = self
+            .separate_git_dir
+            .is_some()
+            .then(|| TempPath::new(git_dir.clone()));
+        std::fs::create_dir_all(git_dir.join("objects")).context(format!(
+            "could not create leading directories of '{}'",
+            git_dir.display()
+        ))?;
+        if separate_junk.is_some() {
+            std::fs::create_dir_all(&path)?;
+            std::fs::write(
+                path.join(".git"),
+                format!("gitdir: {}\n", git_dir.display()),
+            )?;
+        }
+        std::fs::create_dir_all(git_dir.join("refs").join("heads"))?;
+        std::fs::create_dir_all(git_dir.join("refs").join("tags"))?;
+
+        // Repositories given by URL (or with --no-local) are fetched from
+        // upload-pack, unless their objects are borrowed
+        let (connection, refs, head) = match (&source, &bundle) {
+            // HEAD points to a branch it is at, as bundles have no symrefs
+            (_, Some(bundle)) => {
+                let refs: Vec<(String, String)> = bundle
+                    .refs
+                    .iter()
+                    .filter(|(_, name)| name.starts_with("refs/"))
+                    .map(|(hash, name)| (name.clone(), hash.clone()))
+                    .collect();
+                let hash = bundle
+                    .refs
+                    .iter()
+                    .find(|(_, name)| name == "HEAD")
+                    .map(|(hash, _)| hash.clone());
+                // `main` is preferred among the branches HEAD could be at
+                let branches: Vec<&str> = refs
+                    .iter()
+                    .filter(|(name, tip)| {
+                        name.starts_with("refs/heads/") && Some(tip) == hash.as_ref()
+                    })
+                    .map(|(name, _)| name.as_str())
+                    .collect();
+                let name = match branches.first() {
+                    _ if branches.contains(&"refs/heads/main") => "refs/heads/main",
+                    Some(branch) => branch,
+                    None => "HEAD",
+                };
+                let name = name.to_string();
+                (None, refs, ResolvedRef { name, hash })
+            },
+            (Some(source), _) if local || self.shared => {
+                let refs = list_refs(&source.repo, "refs/")?;
+                (None, refs, resolve_symref(&source.repo, "HEAD")?)
+            },
+            _ => {
+                let connection = match &source {
+                    Some(source) => Connection::open(
+                        repo,
+                        &source.repo.git_dir()?,
+                        self.upload_pack.as_deref(),
+                    )?,
+                    None => Connection::connect(&url)?,
+                };
+                let refs = connection
+                    .refs
+                    .iter()
+                    .filter(|(name, _)| name.starts_with("refs/") && !name.ends_with("^{}"))
+                    .cloned()
+                    .collect();
+                let head = ResolvedRef {
+                    name: connection
+                        .head_symref()
+                        .unwrap_or(match connection.refs.first() {
+                            Some((name, _)) if name == "HEAD" => "HEAD",
+                            _ => "refs/heads/main",
+                        })
+                        .to_string(),
+                    hash: connection
+                        .refs
+                        .iter()
+                        .find(|(name, _)| name == "HEAD")
+                        .map(|(_, hash)| hash.clone()),
+                };
+                (Some(connection), refs, head)
+            },
+        };
+        std::fs::write(
+            git_dir.join("config"),
+            config(&url, &head.name, bare, self.mirror, filter.as_ref()),
+        )?;
+        let clone = repo.open(git_dir, work_tree.clone());
+
+        // Objects the clone borrows are not copied
+        let mut alternates = references;
+        if let Some(source) = &source {
+            let source_objects = source.repo.object_dir(true)?.canonicalize()?;
+            if self.shared {
+                alternates.push(source_objects);
+            } else if local {
+                alternates.extend(read_alternates(&source_objects)?);
+                let mut hardlinks = !self.no_hardlinks;
+                copy_object_dir(
+                    &source_objects,
+                    &clone.object_dir(false)?,
+                    &mut hardlinks,
+                    self.local,
+                )?;
+            }
+        }
+        if !alternates.is_empty() {
+            let info = clone.object_dir(false)?.join("info");
+            std::fs::create_dir_all(&info)?;
+            let lines: String = alternates
+                .iter()
+                .map(|dir| format!("{}\n", dir.display()))
+                .collect();
+            std::fs::write(info.join("alternates"), lines)?;
+        }
+
+        // Copy the refs as mapped by the fetch refspec of the new remote
+        let mut updates = Vec::new();
+        for (name, hash) in refs {
+            let local = if self.mirror {
+                name
+            } else if let Some(branch) = name.strip_prefix("refs/heads/") {
+                match bare {
+                    true => name.clone(),
+                    false => format!("refs/remotes/origin/{branch}"),
+                }
+            } else if name.starts_with("refs/tags/") {
+                name
+            } else {
+                continue;
+            };
+            updates.push((local, hash));
+        }
+        let mut wants: Vec<String> = Vec::new();
+        for hash in updates.iter().map(|(_, hash)| hash).chain(&head.hash) {
+            if !wants.contains(hash) {
+                wants.push(hash.clone());
+            }
+        }
+        match (connection, &source, &bundle) {
+            (Some(connection), ..) => {
+                connection.fetch(&clone, &wants, deepen, filter.as_ref())?;
+            },
+            (None, Some(source), _) => {
+                for hash in &wants {
+                    copy_objects(&source.repo, &clone, hash)?;
+                }
+            },
+            (None, None, Some(bundle)) => {
+                for (object_type, content) in read_pack(&clone, &bundle.pack)? {
+                    write_object(&clone, object_type, &content)?;
+                }
+            },
+            (None, None, None) => unreachable!("repositories served by a daemon are fetched"),
+        }
+        let message = format!("clone: from {url}");
+        for (local, hash) in updates {
+            update_ref(&clone, &local, &hash, &message)?;
+        }
+
+        let Some(commit) = head.hash else {
+            // HEAD still points to the branch the first commit will be made on
+            write_symref(&clone, "HEAD", &head.name)?;
+            warning("You appear to have cloned an empty repository.");
+            junk.into_iter()
+                .chain(separate_junk)
+                .for_each(TempPath::keep);
+            return Ok(());
+        };
+        match head.name.strip_prefix("refs/heads/") {
+            Some(branch) => {
+                write_symref(&clone, "HEAD", &head.name)?;
+                if !bare {
+                    let remote_head = format!("refs/remotes/origin/{branch}");
+                    write_symref(&clone, "refs/remotes/origin/HEAD", &remote_head)?;
+                    update_ref(&clone, &head.name, &commit, &message)?;
+                }
+            },
+            None => write_ref(&clone, "HEAD", &commit)?,
+        }
+
+        if let Some(work_tree) = work_tree {
+            log_ref_update(&clone, "HEAD", None, &commit, &message)?;
+            let tree = read_commit(&clone, &commit)?.tree;
+            // The blobs left out are fetched all at once rather than as each is checked out
+            if filter.is_some() {
+                let mut missing = Vec::new();
+                for entry in flatten_tree_checked(&clone, &tree)? {
+                    if entry.mode != MODE_GITLINK && !object_exists(&clone, &entry.hash)? {
+                        missing.push(entry.hash);
+                    }
+                }
+                if !missing.is_empty() {
+                    fetch_missing(&clone, &missing)?;
+                }
+            }
+            let mut index = Index::default();
+            for entry in checkout_tree(&clone, &tree, &work_tree)? {
+                let metadata = work_tree
+                    .join(path_from_bytes(&entry.name))
+                    .symlink_metadata()?;
+                index.add_entry(IndexEntry {
+                    stat: Stat::from_metadata(&metadata),
+                    ..IndexEntry::new(entry.name, entry.mode, entry.hash)
+                });
+            }
+            index.write(&clone)?;
+        }
+
+        junk.into_iter()
+            .chain(separate_junk)
+            .for_each(TempPath::keep);
+        if !self.quiet && local {
+            eprintln!("done.");
+        }
+
+        if self.recurse_submodules && !bare {
+            update_submodules(&clone, &Pathspec::all(""), true, true, "", writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Clone the repository of a submodule into its own git directory, checking
+/// out its default branch at the path of the submodule.
+///
+/// # Arguments
+///
+/// * `repo` - The superproject
+/// * `url` - The URL of the repository of the submodule
+/// * `directory` - Where the submodule is checked out
+/// * `git_dir` - The git directory of the submodule (`.git/modules/<name>`)
+/// * `writer` - Where to write the output
+pub(crate) fn clone_submodule<W>(
+    repo: &Repository,
+    url: &str,
+    directory: &Path,
+    git_dir: &Path,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    CloneArgs {
+        separate_git_dir: Some(git_dir.to_path_buf()),
+        ..CloneArgs::new(url.to_string(), Some(directory.to_path_buf()))
+    }
+    .run(repo, writer)
+}
+
+/// The object directories listed in the alternates of an object directory,
+/// relative ones being resolved from it.
+fn read_alternates(object_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let Ok(content) = std::fs::read_to_string(object_dir.join("info").join("alternates")) else {
+        return Ok(Vec::new());
+    };
+    Ok(content
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| object_dir.join(line).canonicalize().ok())
+        .collect())
+}
+
+/// Copy an object directory (except its alternates) into another one,
+/// hardlinking the files when possible.
+///
+/// # Arguments
+///
+/// * `from` - The object directory to copy
+/// * `to` - The object directory of the clone
+/// * `hardlinks` - Whether to hardlink the files, unset once a link fails
+///   (e.g. across file systems) so that the remaining files are copied
+/// * `required` - Whether failing to link a file is an error (`--local`)
+fn copy_object_dir(
+    from: &Path,
+    to: &Path,
+    hardlinks: &mut bool,
+    required: bool,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(to).context(format!("create {}", to.display()))?;
+    for entry in std::fs::read_dir(from).context(format!("read {}", from.display()))? {
+        let entry = entry?;
+        let (source, target) = (entry.path(), to.join(entry.file_name()));
+        if entry.file_type()?.is_dir() {
+            copy_object_dir(&source, &target, hardlinks, required)?;
+            continue;
+        }
+        if source.ends_with("info/alternates") || target.exists() {
+            continue;
+        }
+
+        if *hardlinks {
+            match std::fs::hard_link(&source, &target) {
+                Ok(()) => continue,
+                Err(err) if required => {
+                    anyhow::bail!("failed to create link '{}': {err}", target.display())
+                },
+                Err(_) => *hardlinks = false,
+            }
+        }
+        std::fs::copy(&source, &target).context(format!("copy {}", source.display()))?;
+    }
+    Ok(())
+}
+
+/// Guess the directory to clone into from the path of the repository
+/// (e.g. `repo` for `/path/to/repo/.git` or `repo.bundle`, or `repo.git` for a
+/// bare clone of it).
+pub(crate) fn guess_directory(repository: &str, bare: bool) -> PathBuf {
+    let path = repository.trim_end_matches('/');
+    let path = path.strip_suffix("/.git").unwrap_or(path);
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = name
+        .strip_suffix(".git")
+        .or_else(|| name.strip_suffix(".bundle"))
+        .unwrap_or(name);
+    match bare {
+        true => PathBuf::from(format!("{name}.git")),
+        false => PathBuf::from(name),
+    }
+}
+
+/// The configuration of a new clone, with `origin` as the remote it was cloned from.
+///
+/// # Arguments
+///
+/// * `url` - The URL of the repository that was cloned
+/// * `head` - The ref HEAD of the remote points to, which a non-bare clone checks out
+/// * `bare` - Whether the clone has no working tree
+/// * `mirror` - Whether every ref of the remote is mapped to the same ref of the clone
+/// * `filter` - The filter of a partial clone, which makes the remote its promisor
+fn config(url: &str, head: &str, bare: bool, mirror: bool, filter: Option<&Filter>) -> String {
+    // Partial clones need the `partialclone` extension, only known from version 1 on
+    let version = u8::from(filter.is_some());
+    let mut config = format!(
+        "[core]\n\trepositoryformatversion = {version}\n\tfilemode = true\n\tbare = {bare}\n"
+    );
+    if !bare {
+        config.push_str("\tlogallrefupdates = true\n");
+    }
+    if filter.is_some() {
+        config.push_str("[extensions]\n\tpartialclone = origin\n");
+    }
+
+    config.push_str(&format!("[remote \"origin\"]\n\turl = {url}\n"));
+    if mirror {
+        config.push_str("\tfetch = +refs/*:refs/*\n\tmirror = true\n");
+    } else if !bare {
+        config.push_str("\tfetch = +refs/heads/*:refs/remotes/origin/*\n");
+    }
+    if let Some(filter) = filter {
+        config.push_str(&format!(
+            "\tpromisor = true\n\tpartialclonefilter = {filter}\n"
+        ));
+    }
+
+    // The checked out branch tracks the same branch of the remote
+    if let (false, Some(branch)) = (bare, head.strip_prefix("refs/heads/")) {
+        config.push_str(&format!(
+            "[branch \"{branch}\"]\n\tremote = origin\n\tmerge = {head}\n"
+        ));
+    }
+    config
+}
+
+impl CloneArgs {
+    fn new(repository: String, directory: Option<PathBuf>) -> Self {
+        CloneArgs {
+            bare: false,
+            mirror: false,
+            local: false,
+            no_local: false,
+            no_hardlinks: false,
+            shared: false,
+            reference: Vec::new(),
+            upload_pack: None,
+            depth: None,
+            filter: None,
+            separate_git_dir: None,
+            recurse_submodules: false,
+            quiet: false,
+            repository,
+            directory,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CloneArgs {
+    /// create a bare repository
+    #[arg(long)]
+    bare: bool,
+    /// create a mirror repository (implies bare)
+    #[arg(long)]
+    mirror: bool,
+    /// copy the objects of a local repository directly, hardlinking them
+    #[arg(short, long)]
+    local: bool,
+    /// copy the objects of a local repository as a remote one does
+    #[arg(long)]
+    no_local: bool,
+    /// copy the objects instead of hardlinking them
+    #[arg(long)]
+    no_hardlinks: bool,
+    /// borrow the objects of a local repository instead of copying them
+    #[arg(short, long)]
+    shared: bool,
+    /// borrow the objects of a reference repository
+    #[arg(long, value_name = "repo")]
+    reference: Vec<String>,
+    /// the command run instead of `git upload-pack` to serve a repository given by URL
+    #[arg(short, long, value_name = "upload-pack")]
+    upload_pack: Option<String>,
+    /// only fetch this many commits of each history, cutting it off below them
+    #[arg(long, value_name = "depth")]
+    depth: Option<u32>,
+    /// leave out the objects the filter excludes (e.g. `blob:none`), fetching
+    /// them from the remote when they are needed
+    #[arg(long, value_name = "filter-spec")]
+    filter: Option<String>,
+    /// keep the git directory at another path, pointed to by a `.git` gitfile
+    #[arg(long, value_name = "git-dir", conflicts_with_all = ["bare", "mirror"])]
+    separate_git_dir: Option<PathBuf>,
+    /// initialize and clone the submodules (recursively) once cloned
+    #[arg(long)]
+    recurse_submodules: bool,
+    /// be quiet
+    #[arg(short, long)]
+    quiet: bool,
+    /// the repository to clone from
+    repository: String,
+    /// the directory to clone into
+    directory: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::objects::{hash_object, read_object, ObjectType};
+    use crate::utils::pack::write_pack;
+    use crate::utils::refs::resolve_ref;
+    use crate::utils::test::TempRepo;
+
+    /// Create a repository in `src` with a commit on `main` and `dev`, a tag and a note.
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_AUTHOR_DATE, "0 +0000")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_DATE, "0 +0000");
+        fs::create_dir_all(temp.join("src/.git/refs/heads")).unwrap();
+        let source = temp.repo_at("src");
+        write_symref(&source, "HEAD", "refs/heads/main").unwrap();
+
+        let mut index = Index::default();
+        let blob = write_object(&source, ObjectType::Blob, b"hello\n").unwrap();
+        index.add_entry(IndexEntry::new("file".into(), 0o100644, blob));
+        let tree = index.write_tree(&source, false).unwrap();
+        let commit = create_commit(&source, &tree, Vec::new(), "initial\n").unwrap();
+        for name in [
+            "refs/heads/main",
+            "refs/heads/dev",
+            "refs/tags/v1",
+            "refs/notes/commits",
+        ] {
+            write_ref(&source, name, &commit).unwrap();
+        }
+        let repo = temp.repo();
+        (temp, repo, commit)
+    }
+
+    fn args(directory: &str) -> CloneArgs {
+        CloneArgs {
+            quiet: true,
+            ..CloneArgs::new("src".to_string(), Some(directory.into()))
+        }
+    }
+
+    #[test]
+    fn clones_into_work_tree() {
+        let (temp, repo, commit) = setup();
+        // The directory guessed from the path of the repository is the repository itself
+        let mut output = Vec::new();
+        CloneArgs {
+            quiet: false,
+            directory: None,
+            repository: "src/".to_string(),
+            ..args("")
+        }
+        .run(&repo, &mut output)
+        .unwrap_err();
+
+        // The progress is not part of the output
+        CloneArgs {
+            quiet: false,
+            ..args("dst")
+        }
+        .run(&repo, &mut output)
+        .unwrap();
+        assert!(output.is_empty());
+        assert_eq!(
+            fs::read_to_string(temp.join("dst/file")).unwrap(),
+            "hello\n"
+        );
+        let url = temp.join("src");
+        assert_eq!(
+            fs::read_to_string(temp.join("dst/.git/config")).unwrap(),
+            format!(
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = false\n\
+                 \tlogallrefupdates = true\n[remote \"origin\"]\n\turl = {}\n\
+                 \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+                 [branch \"main\"]\n\tremote = origin\n\tmerge = refs/heads/main\n",
+                url.display()
+            )
+        );
+
+        let clone = temp.repo_at("dst");
+        let refs: Vec<String> = list_refs(&clone, "refs/")
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(refs, [
+            "refs/heads/main",
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/dev",
+            "refs/remotes/origin/main",
+            "refs/tags/v1",
+        ]);
+        assert_eq!(resolve_ref(&clone, "HEAD").unwrap(), Some(commit));
+        assert_eq!(Index::load(&clone).unwrap().entries().len(), 1);
+    }
+
+    #[test]
+    fn clones_bare_and_mirror_repositories() {
+        let (temp, repo, commit) = setup();
+        CloneArgs {
+            bare: true,
+            ..args("bare.git")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        CloneArgs {
+            mirror: true,
+            ..args("mirror.git")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+
+        let bare = temp.repo_at("bare.git");
+        let mirror = temp.repo_at("mirror.git");
+        let names = |repo: &Repository| -> Vec<String> {
+            list_refs(repo, "refs/")
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+        assert_eq!(names(&bare), [
+            "refs/heads/dev",
+            "refs/heads/main",
+            "refs/tags/v1"
+        ]);
+        assert_eq!(names(&mirror), [
+            "refs/heads/dev",
+            "refs/heads/main",
+            "refs/notes/commits",
+            "refs/tags/v1",
+        ]);
+        assert_eq!(resolve_ref(&mirror, "HEAD").unwrap(), Some(commit));
+        assert!(!fs::exists(temp.join("mirror.git/file")).unwrap());
+
+        let config = fs::read_to_string(temp.join("mirror.git/config")).unwrap();
+        assert!(config.contains("\tbare = true\n"));
+        assert!(config.contains("\tfetch = +refs/*:refs/*\n\tmirror = true\n"));
+        assert!(!config.contains("[branch"));
+        assert!(!fs::read_to_string(temp.join("bare.git/config"))
+            .unwrap()
+            .contains("fetch"));
+    }
+
+    #[test]
+    fn borrows_or_links_objects() {
+        let (temp, repo, commit) = setup();
+        let source_objects = temp.join("src/.git/objects");
+        let object = |clone: &str| {
+            temp.join(format!(
+                "{clone}/.git/objects/{}/{}",
+                &commit[..2],
+                &commit[2..]
+            ))
+        };
+
+        CloneArgs {
+            shared: true,
+            ..args("shared")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("shared/.git/objects/info/alternates")).unwrap(),
+            format!("{}\n", source_objects.display())
+        );
+        assert!(!fs::exists(object("shared")).unwrap());
+        assert_eq!(
+            fs::read_to_string(temp.join("shared/file")).unwrap(),
+            "hello\n"
+        );
+
+        // Local clones hardlink the objects, unless told otherwise
+        args("linked").run(&repo, &mut Vec::new()).unwrap();
+        let links = |path: PathBuf| fs::metadata(path).unwrap().nlink();
+        assert_eq!(links(object("linked")), 2);
+        CloneArgs {
+            no_hardlinks: true,
+            ..args("copied")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert_eq!(links(object("copied")), 1);
+
+        // Only the reachable objects are fetched from upload-pack with --no-local
+        let source = temp.repo_at("src");
+        let unreachable = write_object(&source, ObjectType::Blob, b"unreachable\n").unwrap();
+        CloneArgs {
+            no_local: true,
+            ..args("fetched")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert_eq!(links(object("fetched")), 1);
+        assert!(read_object(&temp.repo_at("fetched"), &unreachable).is_err());
+
+        // A clone that fails leaves nothing behind
+        CloneArgs {
+            no_local: true,
+            upload_pack: Some("false".to_string()),
+            ..args("failed")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap_err();
+        assert!(!fs::exists(temp.join("failed")).unwrap());
+
+        // Objects of the reference repository are not copied by the transport
+        let url = format!("file://{}", temp.join("src").display());
+        CloneArgs {
+            reference: vec!["linked".to_string()],
+            repository: url,
+            ..args("referenced")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert!(!fs::exists(object("referenced")).unwrap());
+        assert_eq!(
+            fs::read_to_string(temp.join("referenced/file")).unwrap(),
+            "hello\n"
+        );
+
+        let err = CloneArgs {
+            reference: vec!["missing".to_string()],
+            ..args("dst")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "reference repository 'missing' is not a local repository."
+        );
+        assert!(!fs::exists(temp.join("dst")).unwrap());
+    }
+
+    #[test]
+    fn clones_from_bundles() {
+        let (temp, repo, commit) = setup();
+        let source = temp.repo_at("src");
+        let tree = read_commit(&source, &commit).unwrap().tree;
+        let blob = hash_object(&ObjectType::Blob, b"hello\n");
+        let mut pack = Vec::new();
+        write_pack(&source, &[commit.clone(), tree, blob], &mut pack).unwrap();
+        let mut bundle = Bundle {
+            prerequisites: Vec::new(),
+            refs: ["HEAD", "refs/heads/dev", "refs/heads/main", "refs/tags/v1"]
+                .iter()
+                .map(|name| (commit.clone(), name.to_string()))
+                .collect(),
+            pack,
+        };
+        let mut content = Vec::new();
+        bundle.write(&mut content).unwrap();
+        fs::write(temp.join("repo.bundle"), content).unwrap();
+
+        // The directory is guessed without the extension of the bundle
+        CloneArgs {
+            directory: None,
+            ..CloneArgs::new("repo.bundle".to_string(), None)
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("repo/file")).unwrap(),
+            "hello\n"
+        );
+        let config = fs::read_to_string(temp.join("repo/.git/config")).unwrap();
+        assert!(config.contains(&format!(
+            "\turl = {}\n",
+            temp.join("repo.bundle").canonicalize().unwrap().display()
+        )));
+        let clone = temp.repo_at("repo");
+        assert_eq!(
+            resolve_symref(&clone, "HEAD").unwrap().name,
+            "refs/heads/main"
+        );
+        assert_eq!(
+            resolve_ref(&clone, "refs/remotes/origin/main").unwrap(),
+            Some(commit.clone())
+        );
+        assert_eq!(resolve_ref(&clone, "refs/tags/v1").unwrap(), Some(commit));
+
+        // Incremental bundles cannot be cloned from
+        bundle.prerequisites = vec![("a".repeat(40), "base".to_string())];
+        let mut content = Vec::new();
+        bundle.write(&mut content).unwrap();
+        fs::write(temp.join("repo.bundle"), content).unwrap();
+        let err = CloneArgs {
+            repository: "repo.bundle".to_string(),
+            ..args("incremental")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Repository lacks these prerequisite commits:\n{} base",
+                "a".repeat(40)
+            )
+        );
+        assert!(!fs::exists(temp.join("incremental")).unwrap());
+    }
+
+    #[test]
+    fn clones_partially() {
+        let (temp, repo, _) = setup();
+        fs::write(
+            temp.join("src/.git/config"),
+            "[uploadpack]\n\tallowFilter = true\n\tallowAnySHA1InWant = true\n",
+        )
+        .unwrap();
+        let blob = hash_object(&ObjectType::Blob, b"hello\n");
+        CloneArgs {
+            bare: true,
+            no_local: true,
+            filter: Some("blob:none".to_string()),
+            ..args("bare.git")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+
+        // The remote is recorded as the promisor of the blobs left out
+        let config = fs::read_to_string(temp.join("bare.git/config")).unwrap();
+        assert!(config.contains("\trepositoryformatversion = 1\n"));
+        assert!(config.contains("[extensions]\n\tpartialclone = origin\n"));
+        assert!(config.contains("\tpromisor = true\n\tpartialclonefilter = blob:none\n"));
+        let promisors = fs::read_dir(temp.join("bare.git/objects/pack"))
+            .unwrap()
+            .filter(|entry| {
+                let path = entry.as_ref().unwrap().path();
+                path.extension()
+                    .is_some_and(|extension| extension == "promisor")
+            })
+            .count();
+        assert_eq!(promisors, 1);
+
+        // Missing blobs are fetched when read
+        let clone = temp.repo_at("bare.git");
+        assert!(!object_exists(&clone, &blob).unwrap());
+        assert_eq!(
+            read_object(&clone, &blob).unwrap(),
+            (ObjectType::Blob, b"hello\n".to_vec())
+        );
+        assert!(object_exists(&clone, &blob).unwrap());
+
+        // The blobs checked out are fetched
+        CloneArgs {
+            no_local: true,
+            filter: Some("blob:none".to_string()),
+            ..args("dst")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("dst/file")).unwrap(),
+            "hello\n"
+        );
+
+        // Servers that do not filter send every object
+        fs::remove_file(temp.join("src/.git/config")).unwrap();
+        CloneArgs {
+            bare: true,
+            no_local: true,
+            filter: Some("blob:none".to_string()),
+            ..args("full.git")
+        }
+        .run(&repo, &mut Vec::new())
+        .unwrap();
+        assert!(object_exists(&temp.repo_at("full.git"), &blob).unwrap());
+    }
+}
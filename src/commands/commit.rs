@@ -0,0 +1,581 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::rerere::rerere;
+use crate::commands::status::write_commit_status;
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::commit::{create_commit, create_signed_commit, read_commit};
+use crate::utils::config::Config;
+use crate::utils::diff::{diff_trees, write_patch, DEFAULT_CONTEXT};
+use crate::utils::editor::launch_editor;
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::index::Index;
+use crate::utils::refs::{head_branch, resolve_ref, shorten_ref, update_head};
+use crate::utils::repository::Repository;
+use crate::utils::tree::EMPTY_TREE;
+
+/// The line below which an edited message is ignored
+const SCISSORS: &str = "------------------------ >8 ------------------------";
+
+impl CommandArgs for CommitArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let config = Config::load(repo)?;
+        // Without a message, it is written in the editor (from the template if any)
+        let message = self.message()?;
+        let edit = self.edit || message.is_none();
+        let template = match (&message, &self.template) {
+            (Some(_), _) => None,
+            (None, Some(path)) => Some(path.clone()),
+            (None, None) => config.get_path("commit.template"),
+        };
+        let template = template
+            .map(|path| {
+                std::fs::read_to_string(&path)
+                    .context(format!("could not read '{}'", path.display()))
+            })
+            .transpose()?;
+        let mut message = message.or(template.clone()).unwrap_or_default();
+        if !message.is_empty() && !message.ends_with('\n') {
+            message.push('\n');
+        }
+
+        let index_file = Some(repo.index_path()?);
+        if !self.no_verify {
+            let hook = Hook {
+                index_file: index_file.clone(),
+                ..Hook::default()
+            };
+            if run_hook(repo, "pre-commit", hook)? != 0 {
+                return Err(SilentExit(1).into());
+            }
+        }
+
+        let mut index = Index::load(repo)?;
+        let tree = index.write_tree(repo, false)?;
+        index.write(repo)?;
+
+        let head = resolve_ref(repo, "HEAD")?;
+        let parent_tree = match &head {
+            Some(head) => Some(read_commit(repo, head)?.tree),
+            None => None,
+        };
+        if !self.allow_empty && parent_tree.as_deref().unwrap_or(EMPTY_TREE) == tree {
+            anyhow::bail!("nothing to commit");
+        }
+
+        // The message goes through a file, which the editor and the `commit-msg`
+        // hook may edit
+        let message_path = repo.git_dir()?.join("COMMIT_EDITMSG");
+        let comment_char = comment_char(&config, &message)?;
+        if edit {
+            let verbose = !self.no_verbose
+                && (self.verbose || config.get_bool("commit.verbose")?.unwrap_or(false));
+            let diff = match verbose {
+                true => Some((parent_tree.as_deref(), tree.as_str())),
+                false => None,
+            };
+            message = editor_content(repo, &message, comment_char, diff)?;
+        }
+        std::fs::write(&message_path, &message).context("write COMMIT_EDITMSG")?;
+        if edit {
+            launch_editor(repo, &message_path)?;
+        }
+        if !self.no_verify {
+            let hook = Hook {
+                args: vec![message_path.display().to_string()],
+                index_file,
+                ..Hook::default()
+            };
+            if run_hook(repo, "commit-msg", hook)? != 0 {
+                return Err(SilentExit(1).into());
+            }
+        }
+        // Comments are only stripped from messages written in the editor
+        let message = std::fs::read_to_string(&message_path)?;
+        let message = match edit {
+            true => strip_comments(&message, comment_char),
+            false => cleanup_message(&message),
+        };
+        if message.is_empty() {
+            anyhow::bail!("Aborting commit due to empty commit message.");
+        }
+        if template.is_some_and(|template| strip_comments(&template, comment_char) == message) {
+            anyhow::bail!("Aborting commit; you did not edit the message.");
+        }
+
+        // `--no-gpg-sign` and `-S` override `commit.gpgSign`
+        let sign = match self.gpg_sign {
+            _ if self.no_gpg_sign => None,
+            Some(key) => Some(key),
+            None if config.get_bool("commit.gpgSign")?.unwrap_or(false) => Some(String::new()),
+            None => None,
+        };
+
+        let parents: Vec<String> = head.iter().cloned().collect();
+        let hash = match sign {
+            Some(key) => {
+                let key = Some(key.as_str()).filter(|key| !key.is_empty());
+                create_signed_commit(repo, &tree, parents, &message, key)
+                    .context("failed to write commit object")?
+            },
+            None => create_commit(repo, &tree, parents, &message)?,
+        };
+
+        let subject = read_commit(repo, &hash)?.subject();
+        let reflog_message = if head.is_some() {
+            format!("commit: {subject}")
+        } else {
+            format!("commit (initial): {subject}")
+        };
+        update_head(repo, &hash, &reflog_message)?;
+        rerere(repo)?;
+        run_hook(repo, "post-commit", Hook::default())?;
+
+        if !self.quiet {
+            let branch = match head_branch(repo)? {
+                Some(branch) => shorten_ref(&branch).to_string(),
+                None => "detached HEAD".to_string(),
+            };
+            let root = if head.is_none() { " (root-commit)" } else { "" };
+            writeln!(writer, "[{branch}{root} {}] {subject}", &hash[..7])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CommitArgs {
+    /// Get the commit message from the `-m` paragraphs or the `-F` file.
+    ///
+    /// # Returns
+    ///
+    /// The message, or `None` if none was given
+    fn message(&self) -> anyhow::Result<Option<String>> {
+        match &self.file {
+            Some(path) => read_message_file(path).map(Some),
+            None if self.message.is_empty() => Ok(None),
+            None => Ok(Some(self.message.join("\n\n"))),
+        }
+    }
+}
+
+/// Prepare a commit message to edit: the message, followed by commented
+/// instructions and status, and the staged changes below a scissors line.
+///
+/// # Arguments
+///
+/// * `repo` - The repository the commit is made in
+/// * `message` - The initial message, empty or ending with a newline
+/// * `comment_char` - The character starting the lines to ignore
+/// * `diff` - The trees of HEAD (if any) and of the index, to show the staged
+///   changes of (`commit.verbose`)
+fn editor_content(
+    repo: &Repository,
+    message: &str,
+    comment_char: char,
+    diff: Option<(Option<&str>, &str)>,
+) -> anyhow::Result<String> {
+    let mut comments = format!(
+        "Please enter the commit message for your changes. Lines starting\n\
+         with '{comment_char}' will be ignored, and an empty message aborts the commit.\n\n"
+    );
+    let mut status = Vec::new();
+    write_commit_status(repo, &mut status)?;
+    comments.push_str(&String::from_utf8_lossy(&status));
+
+    let mut content = format!("{message}\n");
+    for line in comments.lines() {
+        // Indented lines are not separated from the comment character
+        match line {
+            "" => content.push(comment_char),
+            _ if line.starts_with('\t') => content.push_str(&format!("{comment_char}{line}")),
+            _ => content.push_str(&format!("{comment_char} {line}")),
+        }
+        content.push('\n');
+    }
+
+    if let Some((head_tree, tree)) = diff {
+        content.push_str(&format!(
+            "{comment_char} {SCISSORS}\n\
+             {comment_char} Do not modify or remove the line above.\n\
+             {comment_char} Everything below it will be ignored.\n"
+        ));
+        let mut patch = Vec::new();
+        write_patch(
+            repo,
+            &diff_trees(repo, head_tree, Some(tree))?,
+            DEFAULT_CONTEXT,
+            &mut patch,
+        )?;
+        content.push_str(&String::from_utf8_lossy(&patch));
+    }
+    Ok(content)
+}
+
+/// Get the character starting the comments of edited messages (`core.commentChar`,
+/// `#` by default). With `auto`, it is the first of `#;@!$%^&|:` that does not
+/// start a line of the message.
+fn comment_char(config: &Config, message: &str) -> anyhow::Result<char> {
+    match config.get("core.commentChar") {
+        None => Ok('#'),
+        Some("auto") => Ok("#;@!$%^&|:"
+            .chars()
+            .find(|&c| !message.lines().any(|line| line.starts_with(c)))
+            .unwrap_or('#')),
+        Some(value) => {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => anyhow::bail!("core.commentChar should only be one character"),
+            }
+        },
+    }
+}
+
+/// Clean up an edited message: everything below the scissors line and the
+/// comments are dropped, then it is cleaned up as [`cleanup_message`] does.
+pub(crate) fn strip_comments(message: &str, comment_char: char) -> String {
+    let scissors = format!("{comment_char} {SCISSORS}");
+    let lines: Vec<&str> = message
+        .lines()
+        .take_while(|line| *line != scissors)
+        .filter(|line| !line.starts_with(comment_char))
+        .collect();
+    cleanup_message(&lines.join("\n"))
+}
+
+/// Read a message from a file, or from standard input if the path is `-`.
+pub(crate) fn read_message_file(path: &Path) -> anyhow::Result<String> {
+    if path.as_os_str() == "-" {
+        let mut message = String::new();
+        std::io::stdin()
+            .read_to_string(&mut message)
+            .context("read message from standard input")?;
+        return Ok(message);
+    }
+
+    std::fs::read_to_string(path).context(format!("could not read file '{}'", path.display()))
+}
+
+/// Clean up a commit message: trailing whitespace is removed from each line,
+/// consecutive empty lines are collapsed, and leading and trailing empty lines
+/// are removed. A non-empty message ends with a newline.
+pub(crate) fn cleanup_message(message: &str) -> String {
+    let mut cleaned = String::new();
+    let mut pending_blank = false;
+
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() {
+            pending_blank = !cleaned.is_empty();
+            continue;
+        }
+        if pending_blank {
+            cleaned.push('\n');
+            pending_blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+
+    cleaned
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CommitArgs {
+    /// use the given message as the commit message (multiple messages are separate paragraphs)
+    #[arg(short, long = "message", value_name = "message")]
+    message: Vec<String>,
+    /// read the commit message from a file ("-" for standard input)
+    #[arg(short = 'F', long, value_name = "file", conflicts_with = "message")]
+    file: Option<PathBuf>,
+    /// edit the message given with -m or -F (the editor is used when none is given)
+    #[arg(short, long)]
+    edit: bool,
+    /// start the message to edit from a file (overrides commit.template)
+    #[arg(short, long, value_name = "file")]
+    template: Option<PathBuf>,
+    /// show the staged changes below the message to edit (overrides commit.verbose)
+    #[arg(short, long, overrides_with = "no_verbose")]
+    verbose: bool,
+    /// do not show the staged changes, even if commit.verbose is set
+    #[arg(long)]
+    no_verbose: bool,
+    /// allow a commit with the same tree as its parent
+    #[arg(long)]
+    allow_empty: bool,
+    /// sign the commit (with the key-id, or user.signingKey by default)
+    #[arg(
+        short = 'S',
+        long,
+        value_name = "key-id",
+        num_args = 0..=1,
+        default_missing_value = ""
+    )]
+    gpg_sign: Option<String>,
+    /// do not sign the commit, even if commit.gpgSign is set
+    #[arg(long)]
+    no_gpg_sign: bool,
+    /// suppress the summary
+    #[arg(short, long)]
+    quiet: bool,
+    /// bypass the pre-commit and commit-msg hooks
+    #[arg(short, long)]
+    no_verify: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{read_object_of_type, write_object, ObjectType};
+    use crate::utils::reflog::read_reflog;
+    use crate::utils::refs::write_symref;
+    use crate::utils::signature::{split_commit_signature, verify_signature, SignatureStatus};
+    use crate::utils::test::{ssh_keygen, TempRepo};
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "Author")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "Committer")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    /// Stage a file with the given content.
+    fn stage(repo: &Repository, path: &str, content: &str) {
+        let mut index = Index::load(repo).unwrap();
+        let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+        index.add_entry(IndexEntry::new(path.into(), 0o100644, hash));
+        index.write(repo).unwrap();
+    }
+
+    fn args(messages: &[&str]) -> CommitArgs {
+        CommitArgs {
+            message: messages.iter().map(|message| message.to_string()).collect(),
+            file: None,
+            edit: false,
+            template: None,
+            verbose: false,
+            no_verbose: false,
+            allow_empty: false,
+            gpg_sign: None,
+            no_gpg_sign: false,
+            quiet: false,
+            no_verify: false,
+        }
+    }
+
+    fn commit(repo: &Repository, args: CommitArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn commits_the_index() {
+        let (_temp, repo) = setup();
+
+        stage(&repo, "a.txt", "a\n");
+        let output = commit(&repo, args(&["first  ", "body\n\n\n"])).unwrap();
+        let first = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(
+            output,
+            format!("[main (root-commit) {}] first\n", &first[..7])
+        );
+        // Same hash as `git commit -m "first  " -m "body"` with the same identities
+        assert_eq!(first, "97e0a3582c045d492078c9e21eb12c8afc9c83dd");
+
+        let err = commit(&repo, args(&["again"])).unwrap_err();
+        assert_eq!(err.to_string(), "nothing to commit");
+        let err = commit(&repo, CommitArgs {
+            allow_empty: true,
+            ..args(&[" \n "])
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Aborting commit due to empty commit message."
+        );
+
+        stage(&repo, "b.txt", "b\n");
+        let output = commit(&repo, args(&["second"])).unwrap();
+        let second = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(output, format!("[main {}] second\n", &second[..7]));
+        assert_eq!(read_commit(&repo, &second).unwrap().parents, [first]);
+
+        let messages: Vec<_> = read_reflog(&repo, "HEAD")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(messages, ["commit (initial): first", "commit: second"]);
+    }
+
+    #[test]
+    fn signs_commits() {
+        let (temp, repo) = setup();
+        let (key, public_key) = ssh_keygen(temp.path());
+        fs::write(
+            temp.join("allowed_signers"),
+            format!("committer@example.com {public_key}\n"),
+        )
+        .unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            format!(
+                "[gpg]\n\tformat = ssh\n[gpg \"ssh\"]\n\tallowedSignersFile = {}\n\
+                 [user]\n\tsigningKey = {}\n",
+                temp.join("allowed_signers").display(),
+                key.display()
+            ),
+        )
+        .unwrap();
+
+        stage(&repo, "a.txt", "a\n");
+        commit(&repo, CommitArgs {
+            gpg_sign: Some(String::new()),
+            ..args(&["signed"])
+        })
+        .unwrap();
+
+        let hash = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        let content = read_object_of_type(&repo, &hash, ObjectType::Commit).unwrap();
+        let (payload, signature) = split_commit_signature(&content).unwrap();
+        let config = Config::load(&repo).unwrap();
+        let check = verify_signature(&config, &payload, &signature, None).unwrap();
+        assert_eq!(check.status, SignatureStatus::Good);
+        assert_eq!(check.signer.as_deref(), Some("committer@example.com"));
+
+        // `commit.gpgSign` signs by default, unless `--no-gpg-sign` is given
+        fs::write(
+            temp.join(".git/config"),
+            "[commit]\n\tgpgSign = true\n[gpg]\n\tformat = ssh\n[user]\n\tsigningKey = missing\n",
+        )
+        .unwrap();
+        assert!(commit(&repo, CommitArgs {
+            allow_empty: true,
+            ..args(&["unsigned"])
+        })
+        .is_err());
+        commit(&repo, CommitArgs {
+            allow_empty: true,
+            no_gpg_sign: true,
+            ..args(&["unsigned"])
+        })
+        .unwrap();
+        let hash = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        let content = read_object_of_type(&repo, &hash, ObjectType::Commit).unwrap();
+        assert!(split_commit_signature(&content).is_none());
+    }
+
+    #[test]
+    fn runs_commit_hooks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (temp, repo) = setup();
+        let write_hook = |name: &str, script: &str| {
+            let path = temp.join(format!(".git/hooks/{name}"));
+            fs::write(&path, format!("#!/bin/sh\n{script}")).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        fs::create_dir_all(temp.join(".git/hooks")).unwrap();
+        write_hook("pre-commit", "test -f allowed\n");
+        write_hook("commit-msg", "echo 'Signed-off-by: Hook' >> \"$1\"\n");
+        write_hook(
+            "post-commit",
+            "git_dir=$GIT_DIR; echo done > \"$git_dir/done\"\n",
+        );
+        stage(&repo, "a.txt", "a\n");
+
+        // A failing pre-commit hook stops the commit, unless hooks are bypassed
+        let err = commit(&repo, args(&["first"])).unwrap_err();
+        assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+        assert_eq!(resolve_ref(&repo, "HEAD").unwrap(), None);
+        commit(&repo, CommitArgs {
+            no_verify: true,
+            ..args(&["first"])
+        })
+        .unwrap();
+        let head = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(read_commit(&repo, &head).unwrap().message, b"first\n");
+
+        // The commit-msg hook edits the message
+        fs::write(temp.join("allowed"), "").unwrap();
+        fs::remove_file(temp.join(".git/done")).unwrap();
+        stage(&repo, "b.txt", "b\n");
+        commit(&repo, args(&["second"])).unwrap();
+        let head = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(
+            read_commit(&repo, &head).unwrap().message,
+            b"second\nSigned-off-by: Hook\n"
+        );
+        assert!(fs::exists(temp.join(".git/done")).unwrap());
+    }
+
+    #[test]
+    fn edits_the_message() {
+        let (temp, repo) = setup();
+        let config = format!(
+            "[commit]\n\ttemplate = {}\n\tverbose = true\n[core]\n\tcommentChar = \";\"\n",
+            temp.join("template").display()
+        );
+        fs::write(temp.join("template"), "Subject\n\n; ignored\n").unwrap();
+        let editor = "\teditor = sed -i s/Subject/Edited/\n";
+        fs::write(temp.join(".git/config"), format!("{config}{editor}")).unwrap();
+        stage(&repo, "a.txt", "a\n");
+
+        commit(&repo, args(&[])).unwrap();
+        let head = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(read_commit(&repo, &head).unwrap().message, b"Edited\n");
+        let content = fs::read_to_string(temp.join(".git/COMMIT_EDITMSG")).unwrap();
+        assert!(content.starts_with(
+            "Edited\n\n; ignored\n\n\
+             ; Please enter the commit message for your changes. Lines starting\n\
+             ; with ';' will be ignored, and an empty message aborts the commit.\n;\n\
+             ; On branch main\n;\n; Initial commit\n;\n\
+             ; Changes to be committed:\n;\tnew file:   a.txt\n;\n"
+        ));
+        assert!(content.ends_with(&format!(
+            "; {SCISSORS}\n\
+             ; Do not modify or remove the line above.\n\
+             ; Everything below it will be ignored.\n\
+             diff --git a/a.txt b/a.txt\nnew file mode 100644\nindex 0000000..7898192\n\
+             --- /dev/null\n+++ b/a.txt\n@@ -0,0 +1 @@\n+a\n"
+        )));
+
+        // An untouched template aborts the commit, and `-e` edits a given message
+        fs::write(temp.join(".git/config"), format!("{config}\teditor = :\n")).unwrap();
+        let err = commit(&repo, CommitArgs {
+            allow_empty: true,
+            ..args(&[])
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Aborting commit; you did not edit the message."
+        );
+        commit(&repo, CommitArgs {
+            allow_empty: true,
+            edit: true,
+            ..args(&["kept", "; dropped"])
+        })
+        .unwrap();
+        let head = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(read_commit(&repo, &head).unwrap().message, b"kept\n");
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::Write;
+
+use clap::{Args, Subcommand};
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::commit::peel;
+use crate::utils::commit_graph::{write_commit_graph, CommitGraph};
+use crate::utils::objects::{list_loose_files, read_original_object, ObjectType};
+use crate::utils::refs::{list_refs, resolve_ref};
+use crate::utils::repository::Repository;
+
+impl CommandArgs for CommitGraphArgs {
+    fn run<W>(self, repo: &Repository, _writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.command {
+            CommitGraphCommand::Write(args) => write(repo, args.reachable),
+            CommitGraphCommand::Verify => verify(repo),
+        }
+    }
+}
+
+/// Write the commit-graph of the commits reachable from the refs, or of the
+/// loose commits (and their ancestors).
+fn write(repo: &Repository, reachable: bool) -> anyhow::Result<()> {
+    let mut commits = Vec::new();
+    if reachable {
+        let refs = list_refs(repo, "refs/")?.into_iter().map(|(_, hash)| hash);
+        for hash in refs.chain(resolve_ref(repo, "HEAD")?) {
+            let (peeled, object_type) = peel(repo, &hash)?;
+            if object_type == ObjectType::Commit {
+                commits.push(peeled);
+            }
+        }
+    } else {
+        for (_, hash) in list_loose_files(repo)? {
+            let Some(hash) = hash else {
+                continue;
+            };
+            if read_original_object(repo, &hash)?.0 == ObjectType::Commit {
+                commits.push(hash);
+            }
+        }
+    }
+
+    write_commit_graph(repo, &commits)?;
+    Ok(())
+}
+
+/// Check the commit-graph against the commit objects, reporting each problem.
+fn verify(repo: &Repository) -> anyhow::Result<()> {
+    let path = CommitGraph::path(repo)?;
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    let graph = CommitGraph::parse(std::fs::read(&path)?)?;
+    let errors = graph.verify(repo)?;
+    for error in &errors {
+        eprintln!("error: {error}");
+    }
+    if !errors.is_empty() {
+        return Err(SilentExit(1).into());
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CommitGraphArgs {
+    #[command(subcommand)]
+    command: CommitGraphCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CommitGraphCommand {
+    /// write the commit-graph of the loose commits and their ancestors
+    Write(WriteArgs),
+    /// check that the commit-graph matches the commits
+    Verify,
+}
+
+#[derive(Args, Debug)]
+struct WriteArgs {
+    /// write the commit-graph of the commits reachable from the refs instead
+    #[arg(long)]
+    reachable: bool,
+}
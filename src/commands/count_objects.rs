@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::advice::warning;
+use crate::utils::display_path;
+use crate::utils::objects::{hash_object, list_loose_files};
+use crate::utils::pack::read_pack;
+use crate::utils::repository::Repository;
+
+/// The extensions of the files that belong to a pack
+const PACK_EXTENSIONS: [&str; 7] = ["pack", "idx", "rev", "bitmap", "keep", "promisor", "mtimes"];
+
+impl CommandArgs for CountObjectsArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut garbage = Garbage {
+            repo,
+            report: self.verbose,
+            count: 0,
+            size: 0,
+        };
+
+        let packs = scan_packs(repo, &repo.object_dir(true)?.join("pack"), &mut garbage)?;
+        let mut packed = HashSet::new();
+        let mut size_pack = 0;
+        for pack in &packs {
+            packed.extend(pack.hashes.iter().cloned());
+            size_pack += pack.size;
+        }
+
+        let mut count = 0;
+        let mut size = 0;
+        let mut prune_packable = 0;
+        for (path, hash) in list_loose_files(repo)? {
+            let metadata =
+                std::fs::symlink_metadata(&path).context(format!("stat {}", path.display()))?;
+            match hash {
+                Some(hash) => {
+                    count += 1;
+                    size += disk_usage(&metadata);
+                    if packed.contains(&hash) {
+                        prune_packable += 1;
+                    }
+                },
+                None => garbage.found("garbage found", &path, &metadata),
+            }
+        }
+
+        let format_size = |bytes: u64| {
+            if self.human_readable {
+                humanise_bytes(bytes)
+            } else {
+                (bytes / 1024).to_string()
+            }
+        };
+
+        if !self.verbose {
+            let size = if self.human_readable {
+                humanise_bytes(size)
+            } else {
+                format!("{} kilobytes", size / 1024)
+            };
+            writeln!(writer, "{count} objects, {size}")?;
+            return Ok(());
+        }
+
+        let in_pack: usize = packs.iter().map(|pack| pack.hashes.len()).sum();
+        writeln!(writer, "count: {count}")?;
+        writeln!(writer, "size: {}", format_size(size))?;
+        writeln!(writer, "in-pack: {in_pack}")?;
+        writeln!(writer, "packs: {}", packs.len())?;
+        writeln!(writer, "size-pack: {}", format_size(size_pack))?;
+        writeln!(writer, "prune-packable: {prune_packable}")?;
+        writeln!(writer, "garbage: {}", garbage.count)?;
+        writeln!(writer, "size-garbage: {}", format_size(garbage.size))?;
+        for alternate in repo.alternate_object_dirs()? {
+            writeln!(writer, "alternate: {}", alternate.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The files of the object database that are neither objects nor complete packs
+struct Garbage<'r> {
+    repo: &'r Repository,
+    /// Whether each file is reported on stderr
+    report: bool,
+    count: usize,
+    /// The total size of the files, in bytes
+    size: u64,
+}
+
+impl Garbage<'_> {
+    fn found(&mut self, reason: &str, path: &Path, metadata: &Metadata) {
+        if self.report {
+            warning(&format!("{reason}: {}", display_path(self.repo, path)));
+        }
+        self.count += 1;
+        self.size += metadata.len();
+    }
+}
+
+/// A pack with its index
+struct Pack {
+    /// The hashes of the objects of the pack
+    hashes: Vec<String>,
+    /// The size of the pack and index files, in bytes
+    size: u64,
+}
+
+/// Read the packs of the pack directory, counting the files that are not
+/// a pack or an index, and the packs or indexes without their counterpart,
+/// as garbage.
+fn scan_packs(
+    repo: &Repository,
+    pack_dir: &Path,
+    garbage: &mut Garbage,
+) -> anyhow::Result<Vec<Pack>> {
+    let Ok(entries) = std::fs::read_dir(pack_dir) else {
+        return Ok(Vec::new());
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        files.push((
+            entry.file_name().to_string_lossy().into_owned(),
+            entry.path(),
+        ));
+    }
+    files.sort();
+
+    // The files of each pack, by the name without the extension
+    let mut groups: Vec<(String, Vec<PathBuf>)> = Vec::new();
+    for (name, path) in files {
+        if name.starts_with("multi-pack-index") {
+            continue;
+        }
+        let Some((stem, _)) = name
+            .rsplit_once('.')
+            .filter(|(_, extension)| PACK_EXTENSIONS.contains(extension))
+        else {
+            let metadata =
+                std::fs::symlink_metadata(&path).context(format!("stat {}", path.display()))?;
+            garbage.found("garbage found", &path, &metadata);
+            continue;
+        };
+
+        match groups.last_mut() {
+            Some((last, paths)) if last == stem => paths.push(path),
+            _ => groups.push((stem.to_string(), vec![path])),
+        }
+    }
+
+    let mut packs = Vec::new();
+    for (stem, paths) in groups {
+        let pack_path = pack_dir.join(format!("{stem}.pack"));
+        let idx_path = pack_dir.join(format!("{stem}.idx"));
+        let reason = match (paths.contains(&pack_path), paths.contains(&idx_path)) {
+            (true, true) => {
+                let pack =
+                    std::fs::read(&pack_path).context(format!("read {}", pack_path.display()))?;
+                let hashes = read_pack(repo, &pack)
+                    .context(format!("read {}", pack_path.display()))?
+                    .into_iter()
+                    .map(|(object_type, content)| hash_object(&object_type, &content))
+                    .collect();
+                let idx_size = std::fs::metadata(&idx_path)
+                    .context(format!("stat {}", idx_path.display()))?
+                    .len();
+                packs.push(Pack {
+                    hashes,
+                    size: pack.len() as u64 + idx_size,
+                });
+                continue;
+            },
+            (true, false) => "no corresponding .idx",
+            (false, true) => "no corresponding .pack",
+            (false, false) => "no corresponding .idx or .pack",
+        };
+
+        for path in paths {
+            let metadata =
+                std::fs::symlink_metadata(&path).context(format!("stat {}", path.display()))?;
+            garbage.found(reason, &path, &metadata);
+        }
+    }
+
+    Ok(packs)
+}
+
+/// The space a file takes on disk, in bytes.
+#[cfg(unix)]
+fn disk_usage(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn disk_usage(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Format a number of bytes with the largest unit it has at least one of
+/// (e.g. `1.50 KiB`), with two decimals.
+fn humanise_bytes(bytes: u64) -> String {
+    if bytes > 1 << 30 {
+        let fraction = (bytes & ((1 << 30) - 1)) / 10737419;
+        format!("{}.{fraction:02} GiB", bytes >> 30)
+    } else if bytes > 1 << 20 {
+        let rounded = bytes + 5243;
+        let fraction = ((rounded & ((1 << 20) - 1)) * 100) >> 20;
+        format!("{}.{fraction:02} MiB", rounded >> 20)
+    } else if bytes > 1 << 10 {
+        let rounded = bytes + 5;
+        let fraction = ((rounded & ((1 << 10) - 1)) * 100) >> 10;
+        format!("{}.{fraction:02} KiB", rounded >> 10)
+    } else if bytes == 1 {
+        "1 byte".to_string()
+    } else {
+        format!("{bytes} bytes")
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CountObjectsArgs {
+    /// show the objects in packs, the garbage files (reported on stderr) and the alternates as well
+    #[arg(short, long)]
+    verbose: bool,
+    /// show the sizes with units (e.g. `1.50 KiB`)
+    #[arg(short = 'H', long)]
+    human_readable: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn count_objects(repo: &Repository, verbose: bool) -> String {
+        let args = CountObjectsArgs {
+            verbose,
+            human_readable: false,
+        };
+        let mut output = Vec::new();
+        args.run(repo, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn counts_loose_objects_and_garbage() {
+        let (temp, repo) = setup();
+        write_object(&repo, ObjectType::Blob, b"one\n").unwrap();
+        let two = write_object(&repo, ObjectType::Blob, b"two\n").unwrap();
+        fs::write(
+            temp.join(format!(".git/objects/{}/garbage", &two[..2])),
+            "abc",
+        )
+        .unwrap();
+        fs::create_dir(temp.join(".git/objects/pack")).unwrap();
+        fs::write(temp.join(".git/objects/pack/pack-1234.pack"), "12345").unwrap();
+        fs::write(temp.join(".git/objects/pack/pack-1234.keep"), "").unwrap();
+        fs::write(temp.join(".git/objects/pack/notes.txt"), "1").unwrap();
+
+        let output = count_objects(&repo, false);
+        assert!(output.starts_with("2 objects, "), "{output}");
+
+        let output = count_objects(&repo, true);
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "count: 2");
+        assert_eq!(lines[2..], [
+            "in-pack: 0",
+            "packs: 0",
+            "size-pack: 0",
+            "prune-packable: 0",
+            "garbage: 4",
+            "size-garbage: 0"
+        ]);
+    }
+
+    #[test]
+    fn humanises_sizes() {
+        assert_eq!(humanise_bytes(0), "0 bytes");
+        assert_eq!(humanise_bytes(1), "1 byte");
+        assert_eq!(humanise_bytes(1024), "1024 bytes");
+        assert_eq!(humanise_bytes(86016), "84.00 KiB");
+        assert_eq!(humanise_bytes(1536), "1.50 KiB");
+        assert_eq!(humanise_bytes(3 << 20), "3.00 MiB");
+        assert_eq!(humanise_bytes((5 << 30) + 1), "5.00 GiB");
+    }
+}
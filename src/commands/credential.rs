@@ -0,0 +1,38 @@
+use std::io::Write;
+
+use clap::{Args, ValueEnum};
+
+use crate::commands::CommandArgs;
+use crate::utils::credential::{Credential, Credentials};
+use crate::utils::repository::Repository;
+
+impl CommandArgs for CredentialArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let credential = Credential::read(&mut std::io::stdin().lock())?;
+        let credentials = Credentials::new(repo)?;
+        match self.action {
+            CredentialAction::Fill => credentials.fill(&credential)?.write(writer),
+            CredentialAction::Approve => credentials.approve(&credential),
+            CredentialAction::Reject => credentials.reject(&credential),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CredentialAction {
+    /// fill the username and password of the credential
+    Fill,
+    /// tell the credential helpers to store the credential
+    Approve,
+    /// tell the credential helpers to erase the credential
+    Reject,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct CredentialArgs {
+    /// what to do with the credential read from the standard input
+    action: CredentialAction,
+}
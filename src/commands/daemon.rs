@@ -0,0 +1,104 @@
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+
+use crate::commands::CommandArgs;
+use crate::utils::protocol::daemon::{serve_connection, DaemonOptions, DEFAULT_PORT};
+use crate::utils::protocol::Service;
+use crate::utils::repository::Repository;
+
+impl CommandArgs for DaemonArgs {
+    fn run<W>(self, repo: &Repository, _writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut services = vec![Service::UploadPack];
+        for service in &self.enable {
+            services.push(service.service());
+        }
+        for service in &self.disable {
+            services.retain(|enabled| *enabled != service.service());
+        }
+        let options = DaemonOptions {
+            base_path: self.base_path,
+            export_all: self.export_all,
+            whitelist: self.directories,
+            services,
+        };
+
+        // A single connection is served over the standard input and output
+        if self.inetd {
+            let mut input = std::io::stdin().lock();
+            let mut output = std::io::stdout().lock();
+            return serve_connection(repo, &options, &mut input, &mut output);
+        }
+
+        let listener = TcpListener::bind((self.listen.as_str(), self.port))
+            .context(format!("unable to listen on {}:{}", self.listen, self.port))?;
+        let options = Arc::new(options);
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("error: accept connection: {err}");
+                    continue;
+                },
+            };
+            let options = Arc::clone(&options);
+            let repo = repo.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = serve_connection(&repo, &options, &mut &stream, &mut &stream) {
+                    eprintln!("error: {err}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A service that can be enabled or disabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DaemonService {
+    UploadPack,
+    ReceivePack,
+}
+
+impl DaemonService {
+    fn service(self) -> Service {
+        match self {
+            DaemonService::UploadPack => Service::UploadPack,
+            DaemonService::ReceivePack => Service::ReceivePack,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct DaemonArgs {
+    /// the directories repositories must be in to be served (any by default)
+    directories: Vec<PathBuf>,
+    /// the address to listen on
+    #[arg(long, value_name = "host", default_value = "0.0.0.0")]
+    listen: String,
+    /// the port to listen on
+    #[arg(long, default_value_t = DEFAULT_PORT)]
+    port: u16,
+    /// serve the paths requested relative to this directory
+    #[arg(long, value_name = "path")]
+    base_path: Option<PathBuf>,
+    /// serve repositories without a git-daemon-export-ok file
+    #[arg(long)]
+    export_all: bool,
+    /// enable a service (upload-pack is enabled by default)
+    #[arg(long, value_name = "service")]
+    enable: Vec<DaemonService>,
+    /// disable a service
+    #[arg(long, value_name = "service")]
+    disable: Vec<DaemonService>,
+    /// serve a single connection over the standard input and output (as run by inetd)
+    #[arg(long)]
+    inetd: bool,
+}
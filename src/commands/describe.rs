@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::commit::{read_commit, Ident, Tag};
+use crate::utils::index::Index;
+use crate::utils::objects::{read_object, ObjectType};
+use crate::utils::refs::{list_refs, resolve_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::submodule::has_tracked_changes;
+
+/// The maximum number of tags considered before the walk stops at the nearest ones
+const MAX_CANDIDATES: usize = 10;
+
+/// The flag of the commits queued in the walk; tag candidates use the next bits
+const SEEN: u32 = 1;
+
+impl CommandArgs for DescribeArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.dirty.is_some() && !self.commits.is_empty() {
+            anyhow::bail!("option '--dirty' and commit-ishes cannot be used together");
+        }
+
+        let names = tag_names(repo)?;
+        let commits = if self.commits.is_empty() {
+            vec!["HEAD".to_string()]
+        } else {
+            self.commits.clone()
+        };
+
+        for commit in &commits {
+            let hash = resolve_commit(repo, commit)?;
+            let mut description = self.describe(repo, &names, &hash)?;
+
+            if let Some(mark) = &self.dirty {
+                let index = Index::load(repo)?;
+                if has_tracked_changes(repo, &index, resolve_ref(repo, "HEAD")?.as_deref())? {
+                    description.push_str(mark);
+                }
+            }
+            writeln!(writer, "{description}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Name a commit as `git submodule status` does: by the nearest annotated tag,
+/// or else by the nearest tag, or else by a ref pointing to it (e.g.
+/// `heads/main`), falling back to its abbreviated hash.
+pub(crate) fn name_commit(repo: &Repository, hash: &str) -> anyhow::Result<String> {
+    let names = tag_names(repo)?;
+    for tags in [false, true] {
+        let args = DescribeArgs {
+            tags,
+            long: false,
+            always: false,
+            abbrev: 7,
+            dirty: None,
+            commits: Vec::new(),
+        };
+        if let Ok(description) = args.describe(repo, &names, hash) {
+            return Ok(description);
+        }
+    }
+
+    let refs = list_refs(repo, "refs/")?;
+    Ok(match refs.iter().find(|(_, target)| target == hash) {
+        Some((name, _)) => name.trim_start_matches("refs/").to_string(),
+        None => hash[..7].to_string(),
+    })
+}
+
+/// A tag that can describe a commit
+#[derive(Debug)]
+struct TagName {
+    /// The name of the tag, without `refs/tags/`
+    name: String,
+    /// Whether the tag is annotated (lightweight tags are only used with `--tags`)
+    annotated: bool,
+    /// The tagger date of annotated tags
+    date: i64,
+}
+
+/// A tag found while walking back from the described commit
+struct Candidate<'a> {
+    tag: &'a TagName,
+    /// The number of commits reachable from the described commit but not from the tag
+    depth: usize,
+    /// The flag marking the commits reachable from the tag
+    flag: u32,
+}
+
+impl DescribeArgs {
+    /// Describe a commit by the nearest tag it descends from, as `<tag>-<n>-g<hash>`.
+    ///
+    /// The history is walked newest first, collecting the tagged commits met along the
+    /// way, until the commits left to walk are all reachable from the best candidates.
+    /// The depth of a candidate counts the walked commits it cannot reach.
+    fn describe(
+        &self,
+        repo: &Repository,
+        names: &HashMap<String, TagName>,
+        hash: &str,
+    ) -> anyhow::Result<String> {
+        let usable = |tag: &&TagName| self.tags || tag.annotated;
+
+        if let Some(tag) = names.get(hash).filter(usable) {
+            if !self.long {
+                return Ok(tag.name.clone());
+            }
+        }
+        if names.is_empty() && !self.always {
+            anyhow::bail!("No names found, cannot describe anything.");
+        }
+
+        let mut walk = DateList::new(repo);
+        let mut candidates: Vec<Candidate> = Vec::new();
+        let mut unannotated = 0;
+        let mut gave_up_on = None;
+        walk.flags.insert(hash.to_string(), SEEN);
+        walk.insert(hash)?;
+
+        while let Some(commit) = walk.pop() {
+            if let Some(tag) = names.get(&commit) {
+                if !usable(&tag) {
+                    unannotated += 1;
+                } else if candidates.len() < MAX_CANDIDATES {
+                    let flag = 1 << (candidates.len() + 1);
+                    candidates.push(Candidate {
+                        tag,
+                        depth: walk.seen - 1,
+                        flag,
+                    });
+                    *walk.flags.entry(commit.clone()).or_default() |= flag;
+                } else {
+                    gave_up_on = Some(commit);
+                    break;
+                }
+            }
+
+            let flags = walk.flags[&commit];
+            for candidate in &mut candidates {
+                if flags & candidate.flag == 0 {
+                    candidate.depth += 1;
+                }
+            }
+
+            // Stop once the only path left is reachable from the best candidates
+            if candidates.iter().any(|candidate| candidate.tag.annotated) && walk.is_empty() {
+                let best_depth = candidates.iter().map(|candidate| candidate.depth).min();
+                let best_flags = candidates
+                    .iter()
+                    .filter(|candidate| Some(candidate.depth) == best_depth)
+                    .fold(0, |flags, candidate| flags | candidate.flag);
+                if flags & best_flags == best_flags {
+                    break;
+                }
+            }
+
+            walk.push_parents(&commit)?;
+        }
+
+        if candidates.is_empty() {
+            if self.always {
+                return Ok(hash[..self.abbrev.clamp(4, hash.len())].to_string());
+            }
+            if unannotated > 0 {
+                anyhow::bail!(
+                    "No annotated tags can describe '{hash}'.\n\
+                     However, there were unannotated tags: try --tags."
+                );
+            }
+            anyhow::bail!("No tags can describe '{hash}'.\nTry --always, or create some tags.");
+        }
+
+        // The nearest candidate wins, the first one found on ties
+        candidates.sort_by_key(|candidate| candidate.depth);
+        let best = &mut candidates[0];
+        if let Some(commit) = gave_up_on {
+            walk.insert(&commit)?;
+        }
+        best.depth += walk.finish_depth(best.flag)?;
+
+        if self.abbrev == 0 {
+            return Ok(best.tag.name.clone());
+        }
+        Ok(format!(
+            "{}-{}-g{}",
+            best.tag.name,
+            best.depth,
+            &hash[..self.abbrev.clamp(4, hash.len())]
+        ))
+    }
+}
+
+/// The commits left to walk, newest (by committer date) first, with the flags
+/// propagated from the commits they were reached from
+struct DateList<'a> {
+    repo: &'a Repository,
+    /// The queued commits and their dates, where commits with the same date
+    /// keep the order they were queued in
+    queue: Vec<(i64, String)>,
+    /// The flags of the commits reached so far
+    flags: HashMap<String, u32>,
+    /// The number of commits popped so far
+    seen: usize,
+}
+
+impl<'a> DateList<'a> {
+    fn new(repo: &'a Repository) -> Self {
+        DateList {
+            repo,
+            queue: Vec::new(),
+            flags: HashMap::new(),
+            seen: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn insert(&mut self, hash: &str) -> anyhow::Result<()> {
+        let commit = read_commit(self.repo, hash)?;
+        let date = Ident::parse(&commit.committer).map_or(0, |ident| ident.timestamp);
+        let position = self
+            .queue
+            .iter()
+            .position(|(queued, _)| *queued < date)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(position, (date, hash.to_string()));
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        self.seen += 1;
+        Some(self.queue.remove(0).1)
+    }
+
+    /// Queue the parents of a commit that were not reached yet,
+    /// passing the flags of the commit on to all of them.
+    fn push_parents(&mut self, hash: &str) -> anyhow::Result<()> {
+        let flags = self.flags[hash];
+        for parent in read_commit(self.repo, hash)?.parents {
+            let parent_flags = self.flags.entry(parent.clone()).or_default();
+            let reached = *parent_flags & SEEN != 0;
+            *parent_flags |= flags;
+            if !reached {
+                self.insert(&parent)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk the rest of the history to count the commits the best candidate
+    /// (marked by `flag`) cannot reach, until all queued commits are reachable from it.
+    fn finish_depth(&mut self, flag: u32) -> anyhow::Result<usize> {
+        let mut depth = 0;
+        while let Some(commit) = self.pop() {
+            if self.flags[&commit] & flag != 0 {
+                if self
+                    .queue
+                    .iter()
+                    .all(|(_, queued)| self.flags[queued] & flag != 0)
+                {
+                    break;
+                }
+            } else {
+                depth += 1;
+            }
+            self.push_parents(&commit)?;
+        }
+        Ok(depth)
+    }
+}
+
+/// Map the tagged commits to the tag describing them. A commit with several
+/// tags is described by an annotated one, the newest if there are several.
+fn tag_names(repo: &Repository) -> anyhow::Result<HashMap<String, TagName>> {
+    let mut names: HashMap<String, TagName> = HashMap::new();
+
+    for (name, hash) in list_refs(repo, "refs/tags/")? {
+        let mut tag = TagName {
+            name: name["refs/tags/".len()..].to_string(),
+            annotated: false,
+            date: 0,
+        };
+
+        let (mut object_type, mut content) = read_object(repo, &hash)?;
+        let mut target = hash;
+        while object_type == ObjectType::Tag {
+            let tag_object = Tag::parse(&content)?;
+            if !tag.annotated {
+                tag.annotated = true;
+                tag.date = tag_object
+                    .tagger
+                    .as_deref()
+                    .and_then(|tagger| Ident::parse(tagger).ok())
+                    .map_or(0, |ident| ident.timestamp);
+            }
+            target = tag_object.object;
+            (object_type, content) = read_object(repo, &target)?;
+        }
+        if object_type != ObjectType::Commit {
+            continue;
+        }
+
+        let replace = match names.get(&target) {
+            None => true,
+            Some(existing) => tag.annotated && (!existing.annotated || existing.date < tag.date),
+        };
+        if replace {
+            names.insert(target, tag);
+        }
+    }
+
+    Ok(names)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct DescribeArgs {
+    /// use any tag, including lightweight tags
+    #[arg(long)]
+    tags: bool,
+    /// always use the long format, even when the commit is tagged
+    #[arg(long)]
+    long: bool,
+    /// show the abbreviated commit hash if no tag can describe the commit
+    #[arg(long)]
+    always: bool,
+    /// the number of hash digits to show (0 to only show the tag)
+    #[arg(long, value_name = "n", default_value_t = 7)]
+    abbrev: usize,
+    /// append <mark> ("-dirty" by default) if the working tree has local changes
+    #[arg(
+        long,
+        value_name = "mark",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-dirty"
+    )]
+    dirty: Option<String>,
+    /// the commits to describe (HEAD by default)
+    #[arg(value_name = "commit-ish")]
+    commits: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, create_tag};
+    use crate::utils::env;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    fn args(commits: &[&str]) -> DescribeArgs {
+        DescribeArgs {
+            tags: false,
+            long: false,
+            always: false,
+            abbrev: 7,
+            dirty: None,
+            commits: commits.iter().map(|commit| commit.to_string()).collect(),
+        }
+    }
+
+    fn describe(repo: &Repository, args: DescribeArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn describes_commits_by_nearest_tag() {
+        let (_temp, repo) = setup();
+        let mut commits = Vec::new();
+        for message in ["one\n", "two\n", "three\n"] {
+            let parents = commits.last().cloned().into_iter().collect();
+            commits.push(create_commit(&repo, EMPTY_TREE, parents, message).unwrap());
+        }
+        write_ref(&repo, "refs/heads/main", &commits[2]).unwrap();
+
+        let err = describe(&repo, args(&[])).unwrap_err();
+        assert_eq!(err.to_string(), "No names found, cannot describe anything.");
+        let output = describe(&repo, DescribeArgs {
+            always: true,
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, format!("{}\n", &commits[2][..7]));
+
+        write_ref(&repo, "refs/tags/light", &commits[1]).unwrap();
+        let err = describe(&repo, args(&[])).unwrap_err();
+        assert!(err.to_string().ends_with("try --tags."));
+
+        let tag = create_tag(&repo, &commits[0], "v1", "v1\n").unwrap();
+        write_ref(&repo, "refs/tags/v1", &tag).unwrap();
+        let output = describe(&repo, args(&["HEAD", "HEAD~2"])).unwrap();
+        assert_eq!(output, format!("v1-2-g{}\nv1\n", &commits[2][..7]));
+
+        let output = describe(&repo, DescribeArgs {
+            tags: true,
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, format!("light-1-g{}\n", &commits[2][..7]));
+
+        let output = describe(&repo, DescribeArgs {
+            long: true,
+            abbrev: 10,
+            ..args(&["v1"])
+        })
+        .unwrap();
+        assert_eq!(output, format!("v1-0-g{}\n", &commits[0][..10]));
+
+        let output = describe(&repo, DescribeArgs {
+            abbrev: 0,
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "v1\n");
+
+        let err = describe(&repo, DescribeArgs {
+            dirty: Some("-dirty".to_string()),
+            ..args(&["HEAD"])
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "option '--dirty' and commit-ishes cannot be used together"
+        );
+    }
+}
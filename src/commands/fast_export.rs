@@ -0,0 +1,588 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+
+use crate::commands::CommandArgs;
+use crate::utils::advice::warning;
+use crate::utils::commit::{peel, read_commit, Commit, Tag};
+use crate::utils::objects::{read_object, ObjectType};
+use crate::utils::quote::quote_path;
+use crate::utils::refs::list_refs;
+use crate::utils::repository::Repository;
+use crate::utils::revision::{resolve_commit, resolve_full_ref_name, resolve_revision};
+use crate::utils::signature::split_tag_signature;
+use crate::utils::tree::{flatten_tree, MODE_GITLINK};
+use crate::utils::walk::RevWalk;
+
+/// The hash written to delete a ref
+const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+impl CommandArgs for FastExportArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        self.export(repo, writer)?;
+        Ok(())
+    }
+}
+
+/// Export the history of refs as a fast-import stream to rewrite it: the
+/// signatures of tags are stripped, as those of commits are, since they do
+/// not hold for the rewritten objects.
+///
+/// # Returns
+///
+/// The mark of each object exported
+pub(crate) fn export_history<W>(
+    repo: &Repository,
+    revisions: Vec<String>,
+    writer: &mut W,
+) -> anyhow::Result<HashMap<String, u64>>
+where
+    W: Write,
+{
+    let args = FastExportArgs {
+        signed_tags: SignedTags::Strip,
+        export_marks: None,
+        import_marks: None,
+        no_data: false,
+        full_tree: false,
+        reference_excluded_parents: false,
+        use_done_feature: false,
+        revisions,
+    };
+    args.export(repo, writer)
+}
+
+impl FastExportArgs {
+    /// Write the stream.
+    ///
+    /// # Returns
+    ///
+    /// The mark of each object exported
+    fn export<W>(&self, repo: &Repository, writer: &mut W) -> anyhow::Result<HashMap<String, u64>>
+    where
+        W: Write,
+    {
+        // The revisions are split into the tips to export and the commits to exclude
+        let mut tips = Vec::new();
+        let mut excluded = Vec::new();
+        for revision in &self.revisions {
+            let prefix = match revision.as_str() {
+                "--all" => Some("refs/"),
+                "--branches" => Some("refs/heads/"),
+                "--tags" => Some("refs/tags/"),
+                "--remotes" => Some("refs/remotes/"),
+                _ => None,
+            };
+            if let Some(prefix) = prefix {
+                for (name, _) in list_refs(repo, prefix)? {
+                    tips.push(name);
+                }
+                if revision == "--all" {
+                    tips.push("HEAD".to_string());
+                }
+            } else if let Some(revision) = revision.strip_prefix('^') {
+                excluded.push(resolve_commit(repo, revision)?);
+            } else if let Some((from, to)) = revision.split_once("..") {
+                excluded.push(resolve_commit(repo, or_head(from))?);
+                tips.push(or_head(to).to_string());
+            } else {
+                tips.push(revision.clone());
+            }
+        }
+
+        let mut export = Export {
+            repo,
+            args: self,
+            marks: HashMap::new(),
+            last_mark: 0,
+            sources: HashMap::new(),
+        };
+        if let Some(path) = &self.import_marks {
+            export.import_marks(path)?;
+        }
+
+        // Each commit is exported on the ref it is reached from first, the tips
+        // being reached from their own ref
+        let mut refs: Vec<(String, String)> = Vec::new();
+        let mut walk = RevWalk::new(repo);
+        for tip in &tips {
+            let hash = resolve_revision(repo, tip)?;
+            let name = resolve_full_ref_name(repo, tip)?.unwrap_or_else(|| tip.clone());
+            if refs.iter().any(|(existing, _)| *existing == name) {
+                continue;
+            }
+            let (peeled, object_type) = peel(repo, &hash)?;
+            if object_type != ObjectType::Commit {
+                warning(&format!(
+                    "{name}: unexpected object of type {object_type}, skipping."
+                ));
+                continue;
+            }
+            walk.push(&peeled)?;
+            export.sources.entry(peeled).or_insert_with(|| name.clone());
+            refs.push((name, hash));
+        }
+        for hash in &excluded {
+            walk.hide(hash)?;
+        }
+
+        let mut commits = Vec::new();
+        for entry in walk {
+            let (hash, commit) = entry?;
+            let source = export.sources[&hash].clone();
+            for parent in &commit.parents {
+                export
+                    .sources
+                    .entry(parent.clone())
+                    .or_insert_with(|| source.clone());
+            }
+            commits.push((hash, commit));
+        }
+
+        if self.use_done_feature {
+            writer.write_all(b"feature done\n")?;
+        }
+        // The commits already exported (whose marks were imported) are not exported again
+        let mut exported = HashSet::new();
+        for (hash, commit) in topological_order(commits) {
+            if !export.marks.contains_key(&hash) {
+                export.write_commit(&hash, &commit, writer)?;
+            }
+            exported.insert(export.sources[&hash].clone());
+        }
+
+        // The refs no commit was exported on are updated at the end, then the tags
+        let mut tags = Vec::new();
+        for (name, hash) in &refs {
+            let (peeled, _) = peel(repo, hash)?;
+            if peeled != *hash {
+                tags.push((name, hash));
+                continue;
+            }
+            if export.sources[hash] == *name && exported.contains(name) {
+                continue;
+            }
+            let from = match export.marks.get(hash) {
+                Some(mark) => format!(":{mark}"),
+                None if self.reference_excluded_parents => hash.clone(),
+                None => NULL_HASH.to_string(),
+            };
+            writeln!(writer, "reset {name}\nfrom {from}\n")?;
+        }
+        for (name, hash) in tags {
+            export.write_tag(name, hash, writer)?;
+        }
+
+        if self.use_done_feature {
+            writer.write_all(b"done\n")?;
+        }
+        if let Some(path) = &self.export_marks {
+            export.export_marks(path)?;
+        }
+        Ok(export.marks)
+    }
+}
+
+/// The state of an export
+struct Export<'a> {
+    repo: &'a Repository,
+    args: &'a FastExportArgs,
+    /// The mark of each object exported
+    marks: HashMap<String, u64>,
+    last_mark: u64,
+    /// The ref each commit is exported on
+    sources: HashMap<String, String>,
+}
+
+impl Export<'_> {
+    fn mark(&mut self, hash: &str) -> u64 {
+        self.last_mark += 1;
+        self.marks.insert(hash.to_string(), self.last_mark);
+        self.last_mark
+    }
+
+    /// Write a commit, preceded by the blobs it adds that were not exported yet.
+    /// Its changes are the ones from its first parent if that parent was
+    /// exported (or is referenced), and all its files otherwise.
+    fn write_commit<W>(&mut self, hash: &str, commit: &Commit, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let args = self.args;
+        let parent = |parent: &String| match self.marks.get(parent) {
+            Some(mark) => Some(format!(":{mark}")),
+            None if args.reference_excluded_parents => Some(parent.clone()),
+            None => None,
+        };
+        let parents: Vec<Option<String>> = commit.parents.iter().map(parent).collect();
+
+        let old_files = match (parents.first(), args.full_tree) {
+            (Some(Some(_)), false) => {
+                files(self.repo, &read_commit(self.repo, &commit.parents[0])?.tree)?
+            },
+            _ => BTreeMap::new(),
+        };
+        let new_files = files(self.repo, &commit.tree)?;
+        let changes = diff_files(&old_files, &new_files);
+
+        if !args.no_data {
+            for change in &changes {
+                if let Change::Modify(_, mode, hash) = change {
+                    if *mode != MODE_GITLINK && !self.marks.contains_key(hash) {
+                        let (_, content) = read_object(self.repo, hash)?;
+                        let mark = self.mark(hash);
+                        writeln!(writer, "blob\nmark :{mark}\ndata {}", content.len())?;
+                        writer.write_all(&content)?;
+                        writer.write_all(b"\n")?;
+                    }
+                }
+            }
+        }
+
+        let name = self.sources[hash].clone();
+        if commit.parents.is_empty() {
+            writeln!(writer, "reset {name}")?;
+        }
+        let mark = self.mark(hash);
+        writeln!(writer, "commit {name}\nmark :{mark}")?;
+        writer.write_all(b"author ")?;
+        writer.write_all(&commit.author)?;
+        writer.write_all(b"\ncommitter ")?;
+        writer.write_all(&commit.committer)?;
+        writer.write_all(b"\n")?;
+        // Signatures do not hold for the imported commit, which may differ
+        for (header, value) in &commit.extra_headers {
+            if header == "encoding" {
+                writer.write_all(b"encoding ")?;
+                writer.write_all(value)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        writeln!(writer, "data {}", commit.message.len())?;
+        writer.write_all(&commit.message)?;
+        if !commit.message.ends_with(b"\n") {
+            writer.write_all(b"\n")?;
+        }
+
+        let mut parents = parents.into_iter();
+        if let Some(Some(from)) = parents.next() {
+            writeln!(writer, "from {from}")?;
+        }
+        for merge in parents.flatten() {
+            writeln!(writer, "merge {merge}")?;
+        }
+        if args.full_tree {
+            writer.write_all(b"deleteall\n")?;
+        }
+        for change in &changes {
+            match change {
+                Change::Delete(path) => {
+                    writer.write_all(b"D ")?;
+                    writer.write_all(&export_path(path))?;
+                },
+                Change::Modify(path, mode, hash) => {
+                    let data = match self.marks.get(hash) {
+                        Some(mark) if *mode != MODE_GITLINK => format!(":{mark}"),
+                        _ => hash.clone(),
+                    };
+                    write!(writer, "M {mode:06o} {data} ")?;
+                    writer.write_all(&export_path(path))?;
+                },
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Write an annotated tag, handling its signature as requested.
+    fn write_tag<W>(&mut self, name: &str, hash: &str, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let (_, content) = read_object(self.repo, hash)?;
+        let mut tag = Tag::parse(&content)?;
+        if tag.object_type == ObjectType::Tag {
+            anyhow::bail!("tag {name} tags another tag, which is not supported");
+        }
+        let name = name.strip_prefix("refs/tags/").unwrap_or(name);
+
+        if let Some((payload, _)) = split_tag_signature(&content) {
+            let strip = match self.args.signed_tags {
+                SignedTags::Abort => anyhow::bail!(
+                    "encountered signed tag {name}; use --signed-tags=<mode> to handle it"
+                ),
+                SignedTags::Verbatim => false,
+                SignedTags::Warn => {
+                    warning(&format!("exporting signed tag {name}"));
+                    false
+                },
+                SignedTags::WarnStrip => {
+                    warning(&format!("stripping signature from tag {name}"));
+                    true
+                },
+                SignedTags::Strip => true,
+            };
+            if strip {
+                tag = Tag::parse(&payload)?;
+            }
+        }
+
+        let from = match self.marks.get(&tag.object) {
+            Some(mark) => format!(":{mark}"),
+            None if self.args.reference_excluded_parents => tag.object.clone(),
+            None => anyhow::bail!("tag {name} tags an object that is not exported"),
+        };
+        writeln!(writer, "tag {name}\nfrom {from}")?;
+        if let Some(tagger) = &tag.tagger {
+            writer.write_all(b"tagger ")?;
+            writer.write_all(tagger)?;
+            writer.write_all(b"\n")?;
+        }
+        writeln!(writer, "data {}", tag.message.len())?;
+        writer.write_all(&tag.message)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Read the marks of objects exported before (`:<mark> <hash>` lines).
+    fn import_marks(&mut self, path: &PathBuf) -> anyhow::Result<()> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("cannot read marks file {}", path.display()))?;
+        for line in content.lines() {
+            let mark = line
+                .strip_prefix(':')
+                .and_then(|line| line.split_once(' '))
+                .and_then(|(mark, hash)| Some((mark.parse::<u64>().ok()?, hash)));
+            let Some((mark, hash)) = mark else {
+                anyhow::bail!("corrupt mark line: {line}");
+            };
+            self.marks.insert(hash.to_string(), mark);
+            self.last_mark = self.last_mark.max(mark);
+        }
+        Ok(())
+    }
+
+    /// Write the marks of the objects exported, by mark.
+    fn export_marks(&self, path: &PathBuf) -> anyhow::Result<()> {
+        let mut marks: Vec<_> = self.marks.iter().map(|(hash, mark)| (mark, hash)).collect();
+        marks.sort();
+        let content: String = marks
+            .into_iter()
+            .map(|(mark, hash)| format!(":{mark} {hash}\n"))
+            .collect();
+        std::fs::write(path, content).context(format!("cannot write {}", path.display()))
+    }
+}
+
+/// A change to a file between two commits
+enum Change {
+    Delete(Vec<u8>),
+    Modify(Vec<u8>, u32, String),
+}
+
+impl Change {
+    fn path(&self) -> &[u8] {
+        match self {
+            Change::Delete(path) | Change::Modify(path, ..) => path,
+        }
+    }
+}
+
+/// The files of a tree, by path.
+fn files(repo: &Repository, tree: &str) -> anyhow::Result<BTreeMap<Vec<u8>, (u32, String)>> {
+    Ok(flatten_tree(repo, tree)?
+        .into_iter()
+        .map(|entry| (entry.name, (entry.mode, entry.hash)))
+        .collect())
+}
+
+/// List the changes between two sets of files, sorted by path with the files
+/// of a directory before the directory itself (which is then a file being
+/// replaced). A file replaced by a directory is deleted first, so that
+/// deleting it does not delete the directory.
+fn diff_files(
+    old: &BTreeMap<Vec<u8>, (u32, String)>,
+    new: &BTreeMap<Vec<u8>, (u32, String)>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for path in old.keys().filter(|path| !new.contains_key(*path)) {
+        changes.push(Change::Delete(path.clone()));
+    }
+    for (path, (mode, hash)) in new {
+        if old.get(path) != Some(&(*mode, hash.clone())) {
+            changes.push(Change::Modify(path.clone(), *mode, hash.clone()));
+        }
+    }
+
+    changes.sort_by(|a, b| depth_first(a.path(), b.path()));
+    let (mut replaced, others): (Vec<_>, Vec<_>) = changes.into_iter().partition(|change| {
+        let Change::Delete(path) = change else {
+            return false;
+        };
+        let dir = [path.as_slice(), b"/"].concat();
+        new.range(dir.clone()..)
+            .next()
+            .is_some_and(|(next, _)| next.starts_with(&dir))
+    });
+    replaced.extend(others);
+    replaced
+}
+
+/// Compare paths, sorting `dir/file` before `dir`.
+fn depth_first(a: &[u8], b: &[u8]) -> Ordering {
+    let length = a.len().min(b.len());
+    a[..length]
+        .cmp(&b[..length])
+        .then_with(|| b.len().cmp(&a.len()))
+}
+
+/// Quote a path of a file command: in the C style if it has special
+/// characters, or just enclosed in quotes if it has spaces.
+pub(crate) fn export_path(path: &[u8]) -> Vec<u8> {
+    let quoted = quote_path(path, true);
+    if quoted == path && path.contains(&b' ') {
+        return [b"\"", path, b"\""].concat();
+    }
+    quoted
+}
+
+/// Sort commits so that no commit comes before its parents, and the commits
+/// of a line of history are not mixed with those of other lines.
+///
+/// # Arguments
+///
+/// * `commits` - The commits, newest first
+fn topological_order(commits: Vec<(String, Commit)>) -> Vec<(String, Commit)> {
+    let positions: HashMap<&str, usize> = commits
+        .iter()
+        .enumerate()
+        .map(|(position, (hash, _))| (hash.as_str(), position))
+        .collect();
+    let mut children = vec![0; commits.len()];
+    for (_, commit) in &commits {
+        for parent in &commit.parents {
+            if let Some(&position) = positions.get(parent.as_str()) {
+                children[position] += 1;
+            }
+        }
+    }
+
+    // Commits are taken from the top of the stack, the tips coming in the
+    // order of the walk, and parents once all their children were taken
+    let mut stack: Vec<usize> = (0..commits.len())
+        .filter(|&position| children[position] == 0)
+        .rev()
+        .collect();
+    let mut order = Vec::with_capacity(commits.len());
+    while let Some(position) = stack.pop() {
+        order.push(position);
+        for parent in &commits[position].1.parents {
+            if let Some(&parent) = positions.get(parent.as_str()) {
+                children[parent] -= 1;
+                if children[parent] == 0 {
+                    stack.push(parent);
+                }
+            }
+        }
+    }
+
+    let mut commits: Vec<Option<(String, Commit)>> = commits.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .rev()
+        .filter_map(|position| commits[position].take())
+        .collect()
+}
+
+fn or_head(revision: &str) -> &str {
+    if revision.is_empty() {
+        "HEAD"
+    } else {
+        revision
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct FastExportArgs {
+    /// what to do with signed tags
+    #[arg(long, value_enum, default_value = "abort")]
+    signed_tags: SignedTags,
+    /// write the marks of the exported objects to this file when done
+    #[arg(long, value_name = "file")]
+    export_marks: Option<PathBuf>,
+    /// read the marks of objects exported before from this file, not exporting them again
+    #[arg(long, value_name = "file")]
+    import_marks: Option<PathBuf>,
+    /// refer to blobs by hash instead of exporting them
+    #[arg(long)]
+    no_data: bool,
+    /// write every file of each commit instead of its changes
+    #[arg(long)]
+    full_tree: bool,
+    /// refer to the parents that are not exported by hash instead of leaving them out
+    #[arg(long)]
+    reference_excluded_parents: bool,
+    /// start the stream with `feature done` and end it with `done`
+    #[arg(long)]
+    use_done_feature: bool,
+    /// the refs to export (--all, --branches, --tags and --remotes for all of them),
+    /// and ^<rev> or <rev>..<rev> to exclude history
+    #[arg(value_name = "rev-list-args", allow_hyphen_values = true)]
+    revisions: Vec<String>,
+}
+
+/// What is done with signed tags
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SignedTags {
+    /// fail
+    Abort,
+    /// keep the signature
+    Verbatim,
+    /// keep the signature, with a warning
+    Warn,
+    /// remove the signature, with a warning
+    WarnStrip,
+    /// remove the signature
+    Strip,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(changes: &[Change]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| match change {
+                Change::Delete(path) => format!("D {}", String::from_utf8_lossy(path)),
+                Change::Modify(path, ..) => format!("M {}", String::from_utf8_lossy(path)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn orders_changes_so_that_paths_do_not_conflict() {
+        let file = |path: &str| (path.as_bytes().to_vec(), (0o100644, "1".repeat(40)));
+        let old = BTreeMap::from([file("a"), file("dir/file"), file("file")]);
+        let new = BTreeMap::from([file("a.txt"), file("dir"), file("file/inner")]);
+
+        // A directory replaced by a file is emptied first, and a file
+        // replaced by a directory is deleted first
+        assert_eq!(paths(&diff_files(&old, &new)), [
+            "D file",
+            "M a.txt",
+            "D a",
+            "D dir/file",
+            "M dir",
+            "M file/inner"
+        ]);
+    }
+}
@@ -0,0 +1,909 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::advice::warning;
+use crate::utils::commit::{Commit, Ident, Tag};
+use crate::utils::objects::{hash_object, read_object, ObjectType};
+use crate::utils::pack::PackBuilder;
+use crate::utils::quote::unquote_c_style;
+use crate::utils::refs::{delete_ref, is_hash, resolve_ref, update_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::tree::{parse_tree, serialize_tree, TreeEntry, MODE_GITLINK, MODE_TREE};
+use crate::utils::walk::is_ancestor;
+
+/// The hash given to delete a branch
+const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+impl CommandArgs for FastImportArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut import = Import::new(repo, self);
+        if let Some(path) = import.args.import_marks.clone() {
+            import.import_marks(&path, false)?;
+        }
+        if let Some(path) = import.args.import_marks_if_exists.clone() {
+            import.import_marks(&path, true)?;
+        }
+        import.read_stream(&mut Stream::new(std::io::stdin().lock()), writer)?;
+        import.finish()?;
+        Ok(())
+    }
+}
+
+/// Import a fast-import stream quietly, as for rewriting history: branches
+/// are updated even if they lose commits.
+///
+/// # Returns
+///
+/// The object of each mark
+pub(crate) fn import_history<R>(
+    repo: &Repository,
+    reader: R,
+) -> anyhow::Result<HashMap<u64, String>>
+where
+    R: BufRead,
+{
+    let args = FastImportArgs {
+        force: true,
+        quiet: true,
+        stats: false,
+        done: false,
+        date_format: DateFormat::Raw,
+        export_marks: None,
+        import_marks: None,
+        import_marks_if_exists: None,
+    };
+    let mut import = Import::new(repo, args);
+    import.read_stream(&mut Stream::new(reader), &mut std::io::sink())?;
+    import.finish()
+}
+
+/// A fast-import stream, read line by line
+pub(crate) struct Stream<R> {
+    reader: R,
+    /// A line read ahead, to be read again
+    pending: Option<Vec<u8>>,
+}
+
+impl<R: BufRead> Stream<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Stream {
+            reader,
+            pending: None,
+        }
+    }
+
+    /// Read the next line (without its LF), skipping comments.
+    pub(crate) fn next_line(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+        loop {
+            let mut line = Vec::new();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            if !line.starts_with(b"#") {
+                return Ok(Some(line));
+            }
+        }
+    }
+
+    pub(crate) fn unread(&mut self, line: Vec<u8>) {
+        self.pending = Some(line);
+    }
+
+    /// Read the next line if it starts with a prefix.
+    ///
+    /// # Returns
+    ///
+    /// The rest of the line
+    pub(crate) fn next_if(&mut self, prefix: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.next_line()? {
+            Some(line) if line.starts_with(prefix) => Ok(Some(line[prefix.len()..].to_vec())),
+            Some(line) => {
+                self.unread(line);
+                Ok(None)
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Skip the optional empty line ending a command.
+    fn skip_empty_line(&mut self) -> anyhow::Result<()> {
+        if let Some(line) = self.next_line()? {
+            if !line.is_empty() {
+                self.unread(line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a `data` command: either `data <count>` followed by exactly that
+    /// many bytes, or `data <<<delimiter>` followed by lines up to the
+    /// delimiter. Both may be followed by an optional LF.
+    pub(crate) fn read_data(&mut self) -> anyhow::Result<Vec<u8>> {
+        let line = self.next_line()?.context("expected data command")?;
+        let Some(length) = line.strip_prefix(b"data ") else {
+            anyhow::bail!("expected 'data n' command, found: {}", show(&line));
+        };
+
+        let mut data = Vec::new();
+        if let Some(delimiter) = length.strip_prefix(b"<<") {
+            loop {
+                let mut line = Vec::new();
+                if self.reader.read_until(b'\n', &mut line)? == 0 {
+                    anyhow::bail!("EOF in data (terminator '{}' not found)", show(delimiter));
+                }
+                if line.strip_suffix(b"\n").unwrap_or(&line) == delimiter {
+                    break;
+                }
+                data.extend(line);
+            }
+        } else {
+            let length: usize = std::str::from_utf8(length)?
+                .parse()
+                .context(format!("invalid data length: {}", show(length)))?;
+            data.resize(length, 0);
+            self.reader
+                .read_exact(&mut data)
+                .context(format!("EOF in data ({length} bytes expected)"))?;
+        }
+
+        if self.reader.fill_buf()?.first() == Some(&b'\n') {
+            self.reader.consume(1);
+        }
+        Ok(data)
+    }
+}
+
+/// The files of a branch, by path
+type Files = BTreeMap<Vec<u8>, (u32, String)>;
+
+/// A branch being imported
+#[derive(Default)]
+struct Branch {
+    /// The commit at the tip of the branch, if it has one
+    tip: Option<String>,
+    /// The files of the tip
+    files: Files,
+    /// Whether the branch is deleted (reset to the null hash)
+    deleted: bool,
+}
+
+/// The state of an import
+struct Import<'a> {
+    repo: &'a Repository,
+    args: FastImportArgs,
+    pack: PackBuilder,
+    /// The hashes of the objects written to the packs of the import
+    written: HashSet<String>,
+    /// The trees, commits and tags of the import, as they are read back
+    objects: HashMap<String, (ObjectType, Vec<u8>)>,
+    /// The number of objects of each type, and of duplicates
+    counts: HashMap<ObjectType, usize>,
+    duplicates: usize,
+    marks: HashMap<u64, String>,
+    branches: BTreeMap<String, Branch>,
+    tags: BTreeMap<String, String>,
+}
+
+impl<'a> Import<'a> {
+    fn new(repo: &'a Repository, args: FastImportArgs) -> Self {
+        Import {
+            repo,
+            args,
+            pack: PackBuilder::new(),
+            written: HashSet::new(),
+            objects: HashMap::new(),
+            counts: HashMap::new(),
+            duplicates: 0,
+            marks: HashMap::new(),
+            branches: BTreeMap::new(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Read the commands of a stream up to its end, or up to `done`.
+    fn read_stream<R, W>(&mut self, stream: &mut Stream<R>, writer: &mut W) -> anyhow::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        while let Some(line) = stream.next_line()? {
+            if line.is_empty() {
+                continue;
+            } else if line == b"blob" {
+                self.read_blob(stream)?;
+            } else if let Some(name) = line.strip_prefix(b"commit ") {
+                self.read_commit(stream, text(name)?)?;
+            } else if let Some(name) = line.strip_prefix(b"tag ") {
+                self.read_tag(stream, text(name)?)?;
+            } else if let Some(name) = line.strip_prefix(b"reset ") {
+                self.read_reset(stream, text(name)?)?;
+            } else if line == b"checkpoint" {
+                self.checkpoint()?;
+                stream.skip_empty_line()?;
+            } else if line.starts_with(b"progress ") {
+                writer.write_all(&line)?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+                stream.skip_empty_line()?;
+            } else if let Some(mark) = line.strip_prefix(b"get-mark ") {
+                let hash = self.mark(text(mark)?)?;
+                writeln!(writer, "{hash}")?;
+                writer.flush()?;
+            } else if let Some(feature) = line.strip_prefix(b"feature ") {
+                self.feature(text(feature)?)?;
+            } else if line.starts_with(b"option ") {
+                // Options of other importers, and options of this one that can be given as arguments
+            } else if line == b"done" {
+                return Ok(());
+            } else {
+                anyhow::bail!("unsupported command: {}", show(&line));
+            }
+        }
+
+        if self.args.done {
+            anyhow::bail!("stream ends early");
+        }
+        Ok(())
+    }
+
+    fn read_blob<R>(&mut self, stream: &mut Stream<R>) -> anyhow::Result<()>
+    where
+        R: BufRead,
+    {
+        let mark = self.read_mark(stream)?;
+        stream.next_if(b"original-oid ")?;
+        let data = stream.read_data()?;
+        let hash = self.add_object(ObjectType::Blob, data)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash);
+        }
+        Ok(())
+    }
+
+    fn read_commit<R>(&mut self, stream: &mut Stream<R>, name: &str) -> anyhow::Result<()>
+    where
+        R: BufRead,
+    {
+        let mark = self.read_mark(stream)?;
+        stream.next_if(b"original-oid ")?;
+        let author = match stream.next_if(b"author ")? {
+            Some(author) => Some(self.ident(&author)?),
+            None => None,
+        };
+        let committer = stream
+            .next_if(b"committer ")?
+            .context(format!("expected committer for {name}"))?;
+        let committer = self.ident(&committer)?;
+        let mut extra_headers = Vec::new();
+        if let Some(encoding) = stream.next_if(b"encoding ")? {
+            extra_headers.push(("encoding".to_string(), encoding));
+        }
+        let message = stream.read_data()?;
+
+        // The branch starts from its tip, from the ref of the repository if
+        // it is new to the stream, unless another commit is given
+        if !self.branches.contains_key(name) {
+            let mut branch = Branch::default();
+            if let Some(tip) = resolve_ref(self.repo, name)? {
+                branch.files = self.commit_files(&tip)?;
+                branch.tip = Some(tip);
+            }
+            self.branches.insert(name.to_string(), branch);
+        }
+        if let Some(from) = stream.next_if(b"from ")? {
+            let from = text(&from)?;
+            let branch = if from == NULL_HASH {
+                Branch::default()
+            } else {
+                let tip = self.commit_ish(from)?;
+                Branch {
+                    files: self.commit_files(&tip)?,
+                    tip: Some(tip),
+                    deleted: false,
+                }
+            };
+            self.branches.insert(name.to_string(), branch);
+        }
+        let mut parents: Vec<String> = self.branches[name].tip.iter().cloned().collect();
+        while let Some(merge) = stream.next_if(b"merge ")? {
+            parents.push(self.commit_ish(text(&merge)?)?);
+        }
+
+        let mut files = std::mem::take(&mut self.branches.get_mut(name).unwrap().files);
+        while let Some(line) = stream.next_line()? {
+            if line.is_empty() {
+                break;
+            }
+            if !self.file_command(stream, &mut files, &line)? {
+                stream.unread(line);
+                break;
+            }
+        }
+
+        let entries: Vec<_> = files
+            .iter()
+            .map(|(path, (mode, hash))| (path.as_slice(), *mode, hash.as_str()))
+            .collect();
+        let tree = self.write_tree(&entries)?;
+        let commit = Commit {
+            tree,
+            parents,
+            author: author.unwrap_or_else(|| committer.clone()),
+            committer,
+            extra_headers,
+            message,
+        };
+        let hash = self.add_object(ObjectType::Commit, commit.serialize())?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash.clone());
+        }
+
+        let branch = self.branches.get_mut(name).unwrap();
+        branch.tip = Some(hash);
+        branch.files = files;
+        branch.deleted = false;
+        Ok(())
+    }
+
+    /// Apply a file command of a commit to its files.
+    ///
+    /// # Returns
+    ///
+    /// Whether the line was a file command
+    fn file_command<R>(
+        &mut self,
+        stream: &mut Stream<R>,
+        files: &mut Files,
+        line: &[u8],
+    ) -> anyhow::Result<bool>
+    where
+        R: BufRead,
+    {
+        if line == b"deleteall" {
+            files.clear();
+        } else if let Some(path) = line.strip_prefix(b"D ") {
+            remove_path(files, &parse_path(path)?);
+        } else if let Some(rest) = line.strip_prefix(b"M ") {
+            let mut fields = rest.splitn(3, |&b| b == b' ');
+            let (Some(mode), Some(data), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                anyhow::bail!("invalid file command: {}", show(line));
+            };
+            let mode = match text(mode)? {
+                "644" | "100644" => 0o100644,
+                "755" | "100755" => 0o100755,
+                "120000" => 0o120000,
+                "160000" => MODE_GITLINK,
+                "040000" | "40000" => MODE_TREE,
+                mode => anyhow::bail!("invalid mode {mode} in: {}", show(line)),
+            };
+            let path = parse_path(path)?;
+            let hash = if data == b"inline" {
+                self.add_object(ObjectType::Blob, stream.read_data()?)?
+            } else {
+                self.data_ref(text(data)?)?
+            };
+
+            remove_path(files, &path);
+            // A file replaces the directories and files in its way
+            for (index, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+                files.remove(&path[..index]);
+            }
+            if mode == MODE_TREE {
+                for (subpath, entry) in self.tree_files(&hash)? {
+                    let full_path = if path.is_empty() {
+                        subpath
+                    } else {
+                        [path.as_slice(), b"/", &subpath].concat()
+                    };
+                    files.insert(full_path, entry);
+                }
+            } else {
+                files.insert(path, (mode, hash));
+            }
+        } else if let Some(rest) = line.strip_prefix(b"N ") {
+            // Notes are files named after the commit they annotate
+            let space = rest
+                .iter()
+                .position(|&b| b == b' ')
+                .context(format!("invalid note command: {}", show(line)))?;
+            let (data, commit) = (&rest[..space], &rest[space + 1..]);
+            let commit = self.commit_ish(text(commit)?)?;
+            let hash = if data == b"inline" {
+                self.add_object(ObjectType::Blob, stream.read_data()?)?
+            } else {
+                self.data_ref(text(data)?)?
+            };
+            files.insert(commit.into_bytes(), (0o100644, hash));
+        } else if let Some(rest) = line
+            .strip_prefix(b"C ")
+            .or_else(|| line.strip_prefix(b"R "))
+        {
+            let (source, target) = split_source_path(rest)?;
+            let target = parse_path(target)?;
+            let dir = [source.as_slice(), b"/"].concat();
+            let copied: Vec<_> = files
+                .iter()
+                .filter(|(path, _)| **path == source || path.starts_with(&dir))
+                .map(|(path, entry)| (path[source.len()..].to_vec(), entry.clone()))
+                .collect();
+            if copied.is_empty() {
+                anyhow::bail!("path {} not in branch", show(&source));
+            }
+            if line.starts_with(b"R ") {
+                remove_path(files, &source);
+            }
+            remove_path(files, &target);
+            for (suffix, entry) in copied {
+                files.insert([target.as_slice(), &suffix].concat(), entry);
+            }
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn read_tag<R>(&mut self, stream: &mut Stream<R>, name: &str) -> anyhow::Result<()>
+    where
+        R: BufRead,
+    {
+        let mark = self.read_mark(stream)?;
+        let from = stream
+            .next_if(b"from ")?
+            .context(format!("expected from command for tag {name}"))?;
+        let object = self.commit_ish(text(&from)?)?;
+        stream.next_if(b"original-oid ")?;
+        let tagger = match stream.next_if(b"tagger ")? {
+            Some(tagger) => Some(self.ident(&tagger)?),
+            None => None,
+        };
+        let message = stream.read_data()?;
+
+        let tag = Tag {
+            object_type: self.object_type(&object)?,
+            object,
+            name: name.as_bytes().to_vec(),
+            tagger,
+            message,
+        };
+        let hash = self.add_object(ObjectType::Tag, tag.serialize())?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash.clone());
+        }
+        self.tags.insert(format!("refs/tags/{name}"), hash);
+        Ok(())
+    }
+
+    fn read_reset<R>(&mut self, stream: &mut Stream<R>, name: &str) -> anyhow::Result<()>
+    where
+        R: BufRead,
+    {
+        let mut branch = Branch::default();
+        if let Some(from) = stream.next_if(b"from ")? {
+            let from = text(&from)?;
+            if from == NULL_HASH {
+                branch.deleted = true;
+            } else {
+                let tip = self.commit_ish(from)?;
+                branch.files = self.commit_files(&tip)?;
+                branch.tip = Some(tip);
+            }
+        }
+        stream.skip_empty_line()?;
+        self.tags.remove(name);
+        self.branches.insert(name.to_string(), branch);
+        Ok(())
+    }
+
+    fn feature(&mut self, feature: &str) -> anyhow::Result<()> {
+        let (name, value) = match feature.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (feature, None),
+        };
+        match (name, value) {
+            ("done", None) => self.args.done = true,
+            ("force", None) => self.args.force = true,
+            ("date-format", Some(format)) => {
+                self.args.date_format = DateFormat::from_str(format, false)
+                    .map_err(|_| anyhow::anyhow!("unknown --date-format argument {format}"))?;
+            },
+            ("import-marks", Some(path)) => self.import_marks(&PathBuf::from(path), false)?,
+            ("import-marks-if-exists", Some(path)) => {
+                self.import_marks(&PathBuf::from(path), true)?
+            },
+            ("export-marks", Some(path)) => self.args.export_marks = Some(PathBuf::from(path)),
+            ("get-mark" | "notes", None) => {},
+            _ => anyhow::bail!("this version of fast-import does not support feature {feature}"),
+        }
+        Ok(())
+    }
+
+    /// Read the optional `mark :<mark>` of an object.
+    fn read_mark<R>(&self, stream: &mut Stream<R>) -> anyhow::Result<Option<u64>>
+    where
+        R: BufRead,
+    {
+        let Some(mark) = stream.next_if(b"mark :")? else {
+            return Ok(None);
+        };
+        let mark = text(&mark)?;
+        match mark.parse() {
+            Ok(0) | Err(_) => anyhow::bail!("invalid mark :{mark}"),
+            Ok(mark) => Ok(Some(mark)),
+        }
+    }
+
+    /// Resolve a mark (`:<mark>`).
+    fn mark(&self, mark: &str) -> anyhow::Result<String> {
+        let number = mark
+            .strip_prefix(':')
+            .and_then(|number| number.parse::<u64>().ok())
+            .context(format!("invalid mark {mark}"))?;
+        self.marks
+            .get(&number)
+            .cloned()
+            .context(format!("mark {mark} not declared"))
+    }
+
+    /// Resolve the object of a file command: a mark or a hash.
+    fn data_ref(&self, data: &str) -> anyhow::Result<String> {
+        if data.starts_with(':') {
+            self.mark(data)
+        } else if is_hash(data) {
+            Ok(data.to_string())
+        } else {
+            anyhow::bail!("invalid dataref: {data}")
+        }
+    }
+
+    /// Resolve the commit of a `from` or `merge` command: a mark, a branch of
+    /// the stream, a hash, or a revision of the repository.
+    fn commit_ish(&self, value: &str) -> anyhow::Result<String> {
+        if value.starts_with(':') {
+            return self.mark(value);
+        }
+        if let Some(tip) = self
+            .branches
+            .get(value)
+            .and_then(|branch| branch.tip.clone())
+        {
+            return Ok(tip);
+        }
+        if is_hash(value) {
+            return Ok(value.to_string());
+        }
+        resolve_commit(self.repo, value)
+    }
+
+    /// Check an identity line, giving it the current date with `--date-format=now`.
+    fn ident(&self, line: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self.args.date_format {
+            DateFormat::Raw => {
+                Ident::parse(line).context(format!("invalid raw date in {}", show(line)))?;
+                Ok(line.to_vec())
+            },
+            DateFormat::Now => {
+                let Some(ident) = line.strip_suffix(b" now") else {
+                    anyhow::bail!("date in {} is not 'now'", show(line));
+                };
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                Ok([ident, format!(" {now} +0000").as_bytes()].concat())
+            },
+        }
+    }
+
+    /// Add an object to the pack, unless it already is in the repository.
+    ///
+    /// # Returns
+    ///
+    /// The hash of the object
+    fn add_object(&mut self, object_type: ObjectType, content: Vec<u8>) -> anyhow::Result<String> {
+        let hash = hash_object(&object_type, &content);
+        let exists = self.written.contains(&hash)
+            || self.repo.object_path(&hash, true).is_ok()
+            || (self.repo.packs(false)?)
+                .iter()
+                .any(|pack| pack.find(&hash).is_some());
+        if exists {
+            self.duplicates += 1;
+            return Ok(hash);
+        }
+
+        self.pack.add(object_type, &content)?;
+        self.written.insert(hash.clone());
+        *self.counts.entry(object_type).or_default() += 1;
+        if object_type != ObjectType::Blob {
+            self.objects.insert(hash.clone(), (object_type, content));
+        }
+        Ok(hash)
+    }
+
+    /// Read an object of the import or of the repository.
+    fn read_object(&self, hash: &str) -> anyhow::Result<(ObjectType, Vec<u8>)> {
+        match self.objects.get(hash) {
+            Some(object) => Ok(object.clone()),
+            None => read_object(self.repo, hash),
+        }
+    }
+
+    fn object_type(&self, hash: &str) -> anyhow::Result<ObjectType> {
+        Ok(self.read_object(hash)?.0)
+    }
+
+    /// The files of a commit, by path.
+    fn commit_files(&self, hash: &str) -> anyhow::Result<Files> {
+        let (object_type, content) = self.read_object(hash)?;
+        if object_type != ObjectType::Commit {
+            anyhow::bail!("object {hash} is a {object_type}, not a commit");
+        }
+        self.tree_files(&Commit::parse(&content)?.tree)
+    }
+
+    /// The files of a tree, by path.
+    fn tree_files(&self, hash: &str) -> anyhow::Result<Files> {
+        let (object_type, content) = self.read_object(hash)?;
+        if object_type != ObjectType::Tree {
+            anyhow::bail!("object {hash} is a {object_type}, not a tree");
+        }
+
+        let mut files = Files::new();
+        for entry in parse_tree(&content)? {
+            if entry.is_tree() {
+                for (path, file) in self.tree_files(&entry.hash)? {
+                    files.insert([entry.name.as_slice(), b"/", &path].concat(), file);
+                }
+            } else {
+                files.insert(entry.name, (entry.mode, entry.hash));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Write the trees of files sorted by path.
+    ///
+    /// # Returns
+    ///
+    /// The hash of the root tree
+    fn write_tree(&mut self, files: &[(&[u8], u32, &str)]) -> anyhow::Result<String> {
+        let mut entries = Vec::new();
+        let mut rest = files;
+        while let Some(&(path, mode, hash)) = rest.first() {
+            let Some(slash) = path.iter().position(|&b| b == b'/') else {
+                entries.push(TreeEntry {
+                    mode,
+                    name: path.to_vec(),
+                    hash: hash.to_string(),
+                });
+                rest = &rest[1..];
+                continue;
+            };
+
+            // The files of a directory follow each other
+            let dir = &path[..=slash];
+            let count = rest
+                .iter()
+                .take_while(|(path, ..)| path.starts_with(dir))
+                .count();
+            let subtree: Vec<_> = rest[..count]
+                .iter()
+                .map(|&(path, mode, hash)| (&path[slash + 1..], mode, hash))
+                .collect();
+            entries.push(TreeEntry {
+                mode: MODE_TREE,
+                name: path[..slash].to_vec(),
+                hash: self.write_tree(&subtree)?,
+            });
+            rest = &rest[count..];
+        }
+        self.add_object(ObjectType::Tree, serialize_tree(entries)?)
+    }
+
+    /// Write the pack of the objects imported so far, then update the refs
+    /// and write the marks.
+    fn checkpoint(&mut self) -> anyhow::Result<()> {
+        let pack = std::mem::replace(&mut self.pack, PackBuilder::new());
+        if pack.len() > 0 {
+            pack.store(self.repo, false)?;
+        }
+
+        let mut failed = false;
+        for (name, branch) in &self.branches {
+            let old = resolve_ref(self.repo, name)?;
+            match &branch.tip {
+                None if branch.deleted => {
+                    delete_ref(self.repo, name)?;
+                },
+                None => {},
+                Some(tip) if old.as_ref() == Some(tip) => {},
+                Some(tip) => {
+                    if let Some(old) = &old {
+                        if !self.args.force && !is_ancestor(self.repo, old, tip)? {
+                            warning(&format!(
+                                "Not updating {name} (new tip {tip} does not contain {old})"
+                            ));
+                            failed = true;
+                            continue;
+                        }
+                    }
+                    update_ref(self.repo, name, tip, "fast-import")?;
+                },
+            }
+        }
+        for (name, hash) in &self.tags {
+            update_ref(self.repo, name, hash, "fast-import")?;
+        }
+
+        if let Some(path) = &self.args.export_marks {
+            let mut marks: Vec<_> = self.marks.iter().collect();
+            marks.sort();
+            let content: String = marks
+                .into_iter()
+                .map(|(mark, hash)| format!(":{mark} {hash}\n"))
+                .collect();
+            std::fs::write(path, content).context(format!("cannot write {}", path.display()))?;
+        }
+
+        if failed {
+            return Err(SilentExit(1).into());
+        }
+        Ok(())
+    }
+
+    /// End the import, showing its statistics unless it is quiet.
+    ///
+    /// # Returns
+    ///
+    /// The object of each mark
+    fn finish(mut self) -> anyhow::Result<HashMap<u64, String>> {
+        let result = self.checkpoint();
+        if !self.args.quiet || self.args.stats {
+            let count = |object_type| self.counts.get(&object_type).copied().unwrap_or(0);
+            let total: usize = self.counts.values().sum();
+            eprintln!("fast-import statistics:");
+            eprintln!(
+                "Total objects:   {total:>10} ({:>10} duplicates)",
+                self.duplicates
+            );
+            eprintln!("      blobs  :   {:>10}", count(ObjectType::Blob));
+            eprintln!("      trees  :   {:>10}", count(ObjectType::Tree));
+            eprintln!("      commits:   {:>10}", count(ObjectType::Commit));
+            eprintln!("      tags   :   {:>10}", count(ObjectType::Tag));
+            eprintln!("Total branches:  {:>10}", self.branches.len());
+            eprintln!("      marks:     {:>10}", self.marks.len());
+        }
+        result.map(|()| self.marks)
+    }
+
+    /// Read marks written by a previous import or export (`:<mark> <hash>` lines).
+    fn import_marks(&mut self, path: &PathBuf, if_exists: bool) -> anyhow::Result<()> {
+        if if_exists && !path.exists() {
+            return Ok(());
+        }
+        let content = std::fs::read_to_string(path)
+            .context(format!("cannot read marks file {}", path.display()))?;
+        for line in content.lines() {
+            let mark = line
+                .strip_prefix(':')
+                .and_then(|line| line.split_once(' '))
+                .and_then(|(mark, hash)| Some((mark.parse::<u64>().ok()?, hash)));
+            match mark {
+                Some((mark, hash)) if is_hash(hash) => {
+                    self.marks.insert(mark, hash.to_string());
+                },
+                _ => anyhow::bail!("corrupt mark line: {line}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove a file, or all the files of a directory.
+fn remove_path(files: &mut Files, path: &[u8]) {
+    if path.is_empty() {
+        files.clear();
+        return;
+    }
+    files.remove(path);
+    let dir = [path, b"/"].concat();
+    let removed: Vec<_> = files
+        .range(dir.clone()..)
+        .take_while(|(file, _)| file.starts_with(&dir))
+        .map(|(file, _)| file.clone())
+        .collect();
+    for file in removed {
+        files.remove(&file);
+    }
+}
+
+/// Parse the path of a file command, which is C-quoted if it starts with a quote.
+pub(crate) fn parse_path(path: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let path = if path.starts_with(b"\"") {
+        unquote_c_style(path)?
+    } else {
+        path.to_vec()
+    };
+    Ok(path.strip_suffix(b"/").unwrap_or(&path).to_vec())
+}
+
+/// Split the paths of a copy or rename: the source path ends at its closing
+/// quote if it is quoted, and at the first space otherwise.
+fn split_source_path(paths: &[u8]) -> anyhow::Result<(Vec<u8>, &[u8])> {
+    let end = if paths.starts_with(b"\"") {
+        let mut escaped = false;
+        let close = paths[1..].iter().position(|&b| {
+            let close = b == b'"' && !escaped;
+            escaped = b == b'\\' && !escaped;
+            close
+        });
+        close.map(|close| close + 2)
+    } else {
+        paths.iter().position(|&b| b == b' ')
+    };
+    let end = end.context(format!("missing target path in: {}", show(paths)))?;
+    let target = paths[end..]
+        .strip_prefix(b" ")
+        .context(format!("missing space after source: {}", show(paths)))?;
+    Ok((parse_path(&paths[..end])?, target))
+}
+
+fn text(bytes: &[u8]) -> anyhow::Result<&str> {
+    std::str::from_utf8(bytes).context(format!("invalid UTF-8: {}", show(bytes)))
+}
+
+fn show(bytes: &[u8]) -> std::borrow::Cow<'_, str> {
+    String::from_utf8_lossy(bytes)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct FastImportArgs {
+    /// update branches even if their new tip does not contain their old one
+    #[arg(long)]
+    force: bool,
+    /// do not show statistics
+    #[arg(long)]
+    quiet: bool,
+    /// show statistics (the default)
+    #[arg(long)]
+    stats: bool,
+    /// fail if the stream does not end with the `done` command
+    #[arg(long)]
+    done: bool,
+    /// the format of the dates of identities
+    #[arg(long, value_enum, default_value = "raw")]
+    date_format: DateFormat,
+    /// write the marks to this file when done
+    #[arg(long, value_name = "file")]
+    export_marks: Option<PathBuf>,
+    /// read marks from this file before reading the stream
+    #[arg(long, value_name = "file")]
+    import_marks: Option<PathBuf>,
+    /// like --import-marks, doing nothing if the file does not exist
+    #[arg(long, value_name = "file")]
+    import_marks_if_exists: Option<PathBuf>,
+}
+
+/// The format of the dates of identities
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DateFormat {
+    /// the seconds since the epoch, and the offset from UTC (e.g. `1700000000 +0100`)
+    Raw,
+    /// `now`, replaced with the current date
+    Now,
+}
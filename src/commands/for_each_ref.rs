@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::commit::{message_subject, peel, Commit, Tag};
+use crate::utils::objects::{read_object, ObjectType};
+use crate::utils::refs::{shorten_ref, RefCache};
+use crate::utils::repository::Repository;
+use crate::utils::revision::{resolve_commit, resolve_revision};
+use crate::utils::walk::{reachable_commits, MergeFilter};
+use crate::utils::wildmatch::wildmatch;
+
+/// The format used without `--format`
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+impl CommandArgs for ForEachRefArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let format = parse_format(self.format.as_deref().unwrap_or(DEFAULT_FORMAT))?;
+        let filter = RefFilter::new(repo, &self)?;
+        let mut refs = RefCache::new(repo);
+        let head = refs.head_branch()?;
+
+        let mut count = 0;
+        for (name, hash) in refs.list("refs/")? {
+            if self.count.is_some_and(|limit| count >= limit) {
+                break;
+            }
+            if !self.patterns.is_empty()
+                && !self
+                    .patterns
+                    .iter()
+                    .any(|pattern| pattern_matches(pattern, &name))
+            {
+                continue;
+            }
+            if !filter.matches(repo, &hash)? {
+                continue;
+            }
+
+            let object = RefObject::read(repo, &name, &hash)?;
+            let mut line = Vec::new();
+            for item in &format {
+                match item {
+                    FormatItem::Literal(bytes) => line.extend(bytes),
+                    FormatItem::Atom(atom) => {
+                        line.extend(object.atom(repo, atom, head.as_deref())?.into_bytes())
+                    },
+                }
+            }
+            line.push(b'\n');
+            writer.write_all(&line)?;
+            count += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Check whether a ref name matches a pattern: a prefix ending at a `/` (or the
+/// whole name), or a glob pattern.
+fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let prefix = pattern.trim_end_matches('/');
+    match name.strip_prefix(prefix) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => true,
+        _ => wildmatch(pattern, name, false),
+    }
+}
+
+/// The filters on the objects the refs point to
+struct RefFilter {
+    /// The objects given to `--points-at`
+    points_at: Vec<String>,
+    /// The commits given to `--contains`
+    contains: Vec<String>,
+    /// The commits given to `--no-contains`
+    no_contains: Vec<String>,
+    merge: MergeFilter,
+}
+
+impl RefFilter {
+    fn new(repo: &Repository, args: &ForEachRefArgs) -> anyhow::Result<Self> {
+        let resolve_all = |revisions: &[String], resolve: fn(&Repository, &str) -> _| {
+            revisions
+                .iter()
+                .map(|revision| resolve(repo, revision))
+                .collect::<anyhow::Result<Vec<String>>>()
+        };
+
+        Ok(RefFilter {
+            points_at: resolve_all(&args.points_at, resolve_revision)?,
+            contains: resolve_all(&args.contains, resolve_commit)?,
+            no_contains: resolve_all(&args.no_contains, resolve_commit)?,
+            merge: MergeFilter::new(repo, args.merged.as_deref(), args.no_merged.as_deref())?,
+        })
+    }
+
+    /// Whether the object a ref points to passes the filters.
+    fn matches(&self, repo: &Repository, hash: &str) -> anyhow::Result<bool> {
+        // A tag points at an object if the tag itself or its (direct) target is the object
+        if !self.points_at.is_empty() && !self.points_at.iter().any(|target| target == hash) {
+            let (object_type, content) = read_object(repo, hash)?;
+            if object_type != ObjectType::Tag {
+                return Ok(false);
+            }
+            let object = Tag::parse(&content)?.object;
+            if !self.points_at.contains(&object) {
+                return Ok(false);
+            }
+        }
+
+        if !self.contains.is_empty() || !self.no_contains.is_empty() {
+            let (hash, object_type) = peel(repo, hash)?;
+            if object_type != ObjectType::Commit {
+                return Ok(false);
+            }
+            let reachable: HashSet<String> = reachable_commits(repo, &hash)?;
+            if !self.contains.is_empty() && !self.contains.iter().any(|c| reachable.contains(c)) {
+                return Ok(false);
+            }
+            if self.no_contains.iter().any(|c| reachable.contains(c)) {
+                return Ok(false);
+            }
+        }
+
+        self.merge.matches(repo, hash)
+    }
+}
+
+/// A part of a `--format` string
+#[derive(Debug, PartialEq, Eq)]
+enum FormatItem {
+    Literal(Vec<u8>),
+    /// A `%(<atom>)` placeholder
+    Atom(String),
+}
+
+/// The atoms that can be used in a format (each also with a `*` prefix)
+const ATOMS: [&str; 8] = [
+    "refname",
+    "refname:short",
+    "objectname",
+    "objectname:short",
+    "objecttype",
+    "objectsize",
+    "subject",
+    "HEAD",
+];
+
+/// Parse a `--format` string: `%(<atom>)` is a placeholder, `%%` a literal `%`
+/// and `%<xx>` the byte with the hexadecimal value `<xx>`.
+fn parse_format(format: &str) -> anyhow::Result<Vec<FormatItem>> {
+    let mut items = Vec::new();
+    let mut literal = Vec::new();
+    let mut rest = format.as_bytes();
+
+    while let Some((&c, tail)) = rest.split_first() {
+        rest = tail;
+        if c != b'%' {
+            literal.push(c);
+            continue;
+        }
+
+        if let Some(tail) = rest.strip_prefix(b"(") {
+            let end = tail
+                .iter()
+                .position(|&c| c == b')')
+                .ok_or_else(|| anyhow::anyhow!("malformed format string {format}"))?;
+            let atom = String::from_utf8_lossy(&tail[..end]).into_owned();
+            if !ATOMS.contains(&atom.strip_prefix('*').unwrap_or(&atom)) {
+                anyhow::bail!("unknown field name: {atom}");
+            }
+            items.push(FormatItem::Literal(std::mem::take(&mut literal)));
+            items.push(FormatItem::Atom(atom));
+            rest = &tail[end + 1..];
+        } else if let Some(tail) = rest.strip_prefix(b"%") {
+            literal.push(b'%');
+            rest = tail;
+        } else if let Some(byte) = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            literal.push(byte);
+            rest = &rest[2..];
+        } else {
+            literal.push(b'%');
+        }
+    }
+
+    items.push(FormatItem::Literal(literal));
+    Ok(items)
+}
+
+/// A ref and the object it points to
+struct RefObject<'a> {
+    name: &'a str,
+    hash: &'a str,
+    object_type: ObjectType,
+    content: Vec<u8>,
+}
+
+impl<'a> RefObject<'a> {
+    fn read(repo: &Repository, name: &'a str, hash: &'a str) -> anyhow::Result<Self> {
+        let (object_type, content) = read_object(repo, hash)?;
+        Ok(RefObject {
+            name,
+            hash,
+            object_type,
+            content,
+        })
+    }
+
+    /// The value of an atom for the ref. Atoms prefixed with `*` describe the
+    /// object a tag points to, and are empty for other objects.
+    fn atom(&self, repo: &Repository, atom: &str, head: Option<&str>) -> anyhow::Result<String> {
+        if let Some(atom) = atom.strip_prefix('*') {
+            if self.object_type != ObjectType::Tag {
+                return Ok(String::new());
+            }
+            let hash = Tag::parse(&self.content)?.object;
+            return RefObject::read(repo, self.name, &hash)?.atom(repo, atom, head);
+        }
+
+        Ok(match atom {
+            "refname" => self.name.to_string(),
+            "refname:short" => shorten_ref(self.name).to_string(),
+            "objectname" => self.hash.to_string(),
+            "objectname:short" => self.hash[..7].to_string(),
+            "objecttype" => self.object_type.to_string(),
+            "objectsize" => self.content.len().to_string(),
+            "subject" => match self.object_type {
+                ObjectType::Commit => Commit::parse(&self.content)?.subject(),
+                ObjectType::Tag => message_subject(&Tag::parse(&self.content)?.message),
+                _ => String::new(),
+            },
+            "HEAD" => {
+                let current = head.is_some_and(|branch| branch == self.name);
+                if current { "*" } else { " " }.to_string()
+            },
+            _ => anyhow::bail!("unknown field name: {atom}"),
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ForEachRefArgs {
+    /// the format of each line, with `%(<atom>)` placeholders
+    #[arg(long)]
+    format: Option<String>,
+    /// stop after showing this many refs
+    #[arg(long, value_name = "n")]
+    count: Option<usize>,
+    /// only list refs pointing at the object (directly or through a tag)
+    #[arg(long, value_name = "object")]
+    points_at: Vec<String>,
+    /// only list refs whose commits contain the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    contains: Vec<String>,
+    /// only list refs whose commits do not contain the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    no_contains: Vec<String>,
+    /// only list refs whose commits are reachable from the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    merged: Option<String>,
+    /// only list refs whose commits are not reachable from the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    no_merged: Option<String>,
+    /// only list refs matching these patterns (prefixes or globs)
+    #[arg(value_name = "pattern")]
+    patterns: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, create_tag};
+    use crate::utils::env;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    /// Create a repository with `main` at a second commit, `old` at the first one,
+    /// an annotated tag `v1` of the first commit and a tag `v1n` of `v1`.
+    fn setup() -> (TempRepo, Repository, String, String) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let first = create_commit(&repo, EMPTY_TREE, Vec::new(), "one\n").unwrap();
+        let second = create_commit(&repo, EMPTY_TREE, vec![first.clone()], "two\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &second).unwrap();
+        write_ref(&repo, "refs/heads/old", &first).unwrap();
+        let tag = create_tag(&repo, &first, "v1", "release one\n").unwrap();
+        write_ref(&repo, "refs/tags/v1", &tag).unwrap();
+        let nested = create_tag(&repo, &tag, "v1n", "nested\n").unwrap();
+        write_ref(&repo, "refs/tags/v1n", &nested).unwrap();
+        (temp, repo, first, second)
+    }
+
+    fn args(format: &str) -> ForEachRefArgs {
+        ForEachRefArgs {
+            format: Some(format.to_string()),
+            count: None,
+            points_at: Vec::new(),
+            contains: Vec::new(),
+            no_contains: Vec::new(),
+            merged: None,
+            no_merged: None,
+            patterns: Vec::new(),
+        }
+    }
+
+    fn for_each_ref(repo: &Repository, args: ForEachRefArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn formats_refs() {
+        let (_temp, repo, _, second) = setup();
+
+        let output = for_each_ref(&repo, ForEachRefArgs {
+            format: None,
+            patterns: vec!["refs/heads".to_string()],
+            ..args("")
+        })
+        .unwrap();
+        assert!(output.starts_with(&format!("{second} commit\trefs/heads/main\n")));
+        assert_eq!(output.lines().count(), 2);
+
+        assert_eq!(
+            for_each_ref(
+                &repo,
+                args(
+                    "%(HEAD)%(refname:short) %(objecttype) %(subject)|%(*objecttype) %(*subject)%%"
+                )
+            )
+            .unwrap(),
+            "*main commit two| %\n \
+             old commit one| %\n \
+             v1 tag release one|commit one%\n \
+             v1n tag nested|tag release one%\n"
+        );
+        assert_eq!(
+            for_each_ref(&repo, ForEachRefArgs {
+                count: Some(1),
+                patterns: vec!["refs/*/v*".to_string(), "refs/t".to_string()],
+                ..args("%(refname)%41")
+            })
+            .unwrap(),
+            "refs/tags/v1A\n"
+        );
+
+        let err = for_each_ref(&repo, args("%(bogus)")).unwrap_err();
+        assert_eq!(err.to_string(), "unknown field name: bogus");
+    }
+
+    #[test]
+    fn filters_refs_by_object() {
+        let (_temp, repo, first, _) = setup();
+        let names = |args: ForEachRefArgs| for_each_ref(&repo, args).unwrap();
+
+        assert_eq!(
+            names(ForEachRefArgs {
+                points_at: vec![first.clone()],
+                ..args("%(refname:short)")
+            }),
+            "old\nv1\n"
+        );
+        assert_eq!(
+            names(ForEachRefArgs {
+                contains: vec![first.clone()],
+                no_contains: vec!["HEAD".to_string()],
+                ..args("%(refname:short)")
+            }),
+            "old\nv1\nv1n\n"
+        );
+        assert_eq!(
+            names(ForEachRefArgs {
+                no_merged: Some("old".to_string()),
+                ..args("%(refname:short)")
+            }),
+            "main\n"
+        );
+        assert_eq!(
+            names(ForEachRefArgs {
+                merged: Some("HEAD".to_string()),
+                patterns: vec!["refs/tags/".to_string()],
+                ..args("%(refname:short)")
+            }),
+            "v1\nv1n\n"
+        );
+    }
+}
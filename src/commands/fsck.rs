@@ -0,0 +1,569 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::commit::{Commit, Ident, Tag};
+use crate::utils::display_path;
+use crate::utils::index::Index;
+use crate::utils::objects::{hash_object, list_loose_files, read_original_object, ObjectType};
+use crate::utils::pack::read_pack;
+use crate::utils::reflog::{read_reflog, NULL_HASH};
+use crate::utils::refs::{head_branch, list_refs, resolve_ref};
+use crate::utils::repository::Repository;
+use crate::utils::stats::Throughput;
+use crate::utils::tree::{parse_tree, TreeEntry, MODE_GITLINK};
+
+/// An object is corrupt or has an invalid link
+const ERROR_OBJECT: i32 = 1;
+/// An object reachable from the refs, the reflogs or the index is missing
+const ERROR_REACHABLE: i32 = 2;
+/// A pack cannot be read
+const ERROR_PACK: i32 = 4;
+/// A branch points to something else than a commit
+const ERROR_REFS: i32 = 8;
+
+impl CommandArgs for FsckArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut fsck = Fsck {
+            repo,
+            objects: HashMap::new(),
+            used: HashSet::new(),
+            reachable: HashSet::new(),
+            missing: HashMap::new(),
+            corrupt: HashSet::new(),
+            names: self.name_objects.then(HashMap::new),
+            pending: Vec::new(),
+            errors: 0,
+        };
+
+        let mut throughput = Throughput::start("Checking objects", self.progress);
+        fsck.read_objects(&mut throughput)?;
+        throughput.finish();
+        for object in fsck.objects.values() {
+            for (hash, ..) in object.links(None) {
+                fsck.used.insert(hash);
+            }
+        }
+        fsck.add_roots(!self.no_reflogs)?;
+        fsck.traverse(writer)?;
+
+        let mut hashes: Vec<&String> = fsck.objects.keys().chain(fsck.missing.keys()).collect();
+        hashes.sort();
+        for hash in hashes {
+            if let Some(object_type) = fsck.missing.get(hash) {
+                writeln!(writer, "missing {object_type} {}", fsck.describe(hash))?;
+                fsck.errors |= ERROR_REACHABLE;
+                continue;
+            }
+            if fsck.reachable.contains(hash) {
+                continue;
+            }
+
+            let object_type = fsck.objects[hash].object_type();
+            if self.unreachable {
+                writeln!(writer, "unreachable {object_type} {}", fsck.describe(hash))?;
+            } else if !fsck.used.contains(hash) && !self.no_dangling {
+                writeln!(writer, "dangling {object_type} {}", fsck.describe(hash))?;
+            }
+        }
+
+        match fsck.errors {
+            0 => Ok(()),
+            errors => Err(SilentExit(errors).into()),
+        }
+    }
+}
+
+/// A parsed object
+enum Object {
+    Blob,
+    Tree(Vec<TreeEntry>),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    fn parse(object_type: ObjectType, content: &[u8]) -> anyhow::Result<Self> {
+        Ok(match object_type {
+            ObjectType::Blob => Object::Blob,
+            ObjectType::Tree => Object::Tree(parse_tree(content)?),
+            ObjectType::Commit => Object::Commit(Commit::parse(content)?),
+            ObjectType::Tag => Object::Tag(Tag::parse(content)?),
+        })
+    }
+
+    fn object_type(&self) -> ObjectType {
+        match self {
+            Object::Blob => ObjectType::Blob,
+            Object::Tree(_) => ObjectType::Tree,
+            Object::Commit(_) => ObjectType::Commit,
+            Object::Tag(_) => ObjectType::Tag,
+        }
+    }
+
+    /// The objects this object points to, with their expected type and,
+    /// if this object is named `name`, their name.
+    fn links(&self, name: Option<&str>) -> Vec<(String, ObjectType, Option<String>)> {
+        match self {
+            Object::Blob => Vec::new(),
+            Object::Tree(entries) => entries
+                .iter()
+                .filter(|entry| entry.mode != MODE_GITLINK)
+                .map(|entry| {
+                    let path = String::from_utf8_lossy(&entry.name);
+                    let (object_type, suffix) = if entry.is_tree() {
+                        (ObjectType::Tree, "/")
+                    } else {
+                        (ObjectType::Blob, "")
+                    };
+                    let name = name.map(|name| format!("{name}{path}{suffix}"));
+                    (entry.hash.clone(), object_type, name)
+                })
+                .collect(),
+            Object::Commit(commit) => {
+                let mut links = vec![(
+                    commit.tree.clone(),
+                    ObjectType::Tree,
+                    name.map(|name| format!("{name}:")),
+                )];
+                let names = match name {
+                    Some(name) => parent_names(name, commit.parents.len()),
+                    None => vec![None; commit.parents.len()],
+                };
+                for (parent, name) in commit.parents.iter().zip(names) {
+                    links.push((parent.clone(), ObjectType::Commit, name));
+                }
+                links
+            },
+            Object::Tag(tag) => vec![(tag.object.clone(), tag.object_type, name.map(String::from))],
+        }
+    }
+}
+
+/// Name the parents of a commit named `name`, relative to it: `<name>^` (or
+/// `<start>~<n+1>` if the name is `<start>~<n>`) for the first parent, and
+/// `<name>^<n>` for the others.
+fn parent_names(name: &str, count: usize) -> Vec<Option<String>> {
+    let digits = name.trim_end_matches(|char: char| char.is_ascii_digit());
+    let (start, generation) = if let Some(start) = name.strip_suffix('^') {
+        (start, 1)
+    } else if let Some(start) = digits
+        .strip_suffix('~')
+        .filter(|_| digits.len() < name.len())
+    {
+        (start, name[digits.len()..].parse().unwrap_or(0))
+    } else {
+        (name, 0)
+    };
+
+    (0..count)
+        .map(|index| {
+            Some(
+                if index > 0 {
+                    format!("{name}^{}", index + 1)
+                } else if generation > 0 {
+                    format!("{start}~{}", generation + 1)
+                } else {
+                    format!("{name}^")
+                },
+            )
+        })
+        .collect()
+}
+
+/// The state of a check of the object database
+struct Fsck<'a> {
+    repo: &'a Repository,
+    /// The objects of the database that could be read and parsed
+    objects: HashMap<String, Object>,
+    /// The objects another object points to
+    used: HashSet<String>,
+    /// The objects found to be reachable so far
+    reachable: HashSet<String>,
+    /// The reachable objects that are not in the database, with their expected type
+    missing: HashMap<String, ObjectType>,
+    /// The objects whose file could not be read or parsed
+    corrupt: HashSet<String>,
+    /// The first name each reachable object was reached by, if objects are named
+    names: Option<HashMap<String, String>>,
+    /// The reachable objects whose links are still to be followed
+    pending: Vec<String>,
+    /// The kinds of errors found, as a bit set
+    errors: i32,
+}
+
+impl Fsck<'_> {
+    /// Read and check the loose objects and the objects of the packs,
+    /// counting them in `throughput`.
+    fn read_objects(&mut self, throughput: &mut Throughput) -> anyhow::Result<()> {
+        for (path, hash) in list_loose_files(self.repo)? {
+            let Some(hash) = hash else {
+                let temporary = path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("tmp_obj_"));
+                if !temporary {
+                    eprintln!("bad sha1 file: {}", display_path(self.repo, &path));
+                }
+                continue;
+            };
+
+            let Ok((object_type, content)) = read_original_object(self.repo, &hash) else {
+                eprintln!(
+                    "error: {hash}: object corrupt or missing: {}",
+                    display_path(self.repo, &path)
+                );
+                self.errors |= ERROR_OBJECT;
+                self.corrupt.insert(hash);
+                continue;
+            };
+            let real = hash_object(&object_type, &content);
+            if real != hash {
+                eprintln!(
+                    "error: {real}: hash-path mismatch, found at: {}",
+                    display_path(self.repo, &path)
+                );
+                self.errors |= ERROR_OBJECT;
+                self.corrupt.insert(hash);
+                continue;
+            }
+            throughput.add(content.len());
+            self.add_object(hash, object_type, &content);
+        }
+
+        let Ok(entries) = std::fs::read_dir(self.repo.object_dir(true)?.join("pack")) else {
+            return Ok(());
+        };
+        let mut packs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "pack") {
+                packs.push(path);
+            }
+        }
+        packs.sort();
+
+        for path in packs {
+            let pack = std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|pack| read_pack(self.repo, &pack));
+            match pack {
+                Ok(objects) => {
+                    for (object_type, content) in objects {
+                        throughput.add(content.len());
+                        let hash = hash_object(&object_type, &content);
+                        if !self.objects.contains_key(&hash) {
+                            self.add_object(hash, object_type, &content);
+                        }
+                    }
+                },
+                Err(err) => {
+                    eprintln!("error: {}: {err:#}", display_path(self.repo, &path));
+                    self.errors |= ERROR_PACK;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether an object is known, reading it from the alternates of the
+    /// repository if it is borrowed from them.
+    fn has_object(&mut self, hash: &str) -> bool {
+        if self.objects.contains_key(hash) {
+            return true;
+        }
+        if self.corrupt.contains(hash) {
+            return false;
+        }
+        let Ok((object_type, content)) = read_original_object(self.repo, hash) else {
+            return false;
+        };
+        self.add_object(hash.to_string(), object_type, &content);
+        self.objects.contains_key(hash)
+    }
+
+    fn add_object(&mut self, hash: String, object_type: ObjectType, content: &[u8]) {
+        match Object::parse(object_type, content) {
+            Ok(object) => {
+                self.objects.insert(hash, object);
+            },
+            Err(err) => {
+                eprintln!("error in {object_type} {hash}: {err:#}");
+                self.errors |= ERROR_OBJECT;
+                self.corrupt.insert(hash);
+            },
+        }
+    }
+
+    /// Start the walk from the refs, `HEAD`, the entries of the reflogs
+    /// (if `reflogs` is set) and the index, naming the objects after them.
+    fn add_roots(&mut self, reflogs: bool) -> anyhow::Result<()> {
+        let mut refs = list_refs(self.repo, "refs/")?;
+        match resolve_ref(self.repo, "HEAD")? {
+            Some(hash) => refs.push(("HEAD".to_string(), hash)),
+            None => {
+                if let Some(branch) = head_branch(self.repo)? {
+                    let branch = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+                    eprintln!("notice: HEAD points to an unborn branch ({branch})");
+                }
+            },
+        }
+
+        let mut default_refs = 0;
+        for (name, hash) in &refs {
+            if !self.has_object(hash) {
+                eprintln!("error: {name}: invalid sha1 pointer {hash}");
+                self.errors |= ERROR_REACHABLE;
+                continue;
+            }
+            if self.objects[hash].object_type() != ObjectType::Commit
+                && name.starts_with("refs/heads/")
+            {
+                eprintln!("error: {name}: not a commit");
+                self.errors |= ERROR_REFS;
+            }
+            default_refs += 1;
+            self.add_root(hash, name.clone());
+        }
+
+        if reflogs {
+            for (name, _) in &refs {
+                for entry in read_reflog(self.repo, name)? {
+                    let timestamp = Ident::parse(&entry.ident).map_or(0, |ident| ident.timestamp);
+                    for hash in [&entry.old, &entry.new] {
+                        if hash == NULL_HASH {
+                            continue;
+                        }
+                        if !self.has_object(hash) {
+                            eprintln!("error: {name}: invalid reflog entry {hash}");
+                            self.errors |= ERROR_REACHABLE;
+                            continue;
+                        }
+                        self.add_root(hash, format!("{name}@{{{timestamp}}}"));
+                    }
+                }
+            }
+        }
+        if default_refs == 0 {
+            eprintln!("notice: No default references");
+        }
+
+        for entry in Index::load(self.repo)?.entries() {
+            if entry.mode == MODE_GITLINK {
+                continue;
+            }
+            self.add_root(
+                &entry.hash,
+                format!(":{}", String::from_utf8_lossy(&entry.path)),
+            );
+            if !self.has_object(&entry.hash) {
+                self.missing.insert(entry.hash.clone(), ObjectType::Blob);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_root(&mut self, hash: &str, name: String) {
+        if let Some(names) = &mut self.names {
+            names.entry(hash.to_string()).or_insert(name);
+        }
+        self.used.insert(hash.to_string());
+        if self.reachable.insert(hash.to_string()) && self.has_object(hash) {
+            self.pending.push(hash.to_string());
+        }
+    }
+
+    /// Follow the links of the reachable objects, reporting the ones to missing objects.
+    fn traverse<W: Write>(&mut self, writer: &mut W) -> anyhow::Result<()> {
+        while let Some(hash) = self.pending.pop() {
+            let object = &self.objects[&hash];
+            let object_type = object.object_type();
+            let name = self
+                .names
+                .as_ref()
+                .and_then(|names| names.get(&hash).cloned());
+
+            for (link, link_type, link_name) in object.links(name.as_deref()) {
+                if let (Some(names), Some(link_name)) = (&mut self.names, link_name) {
+                    names.entry(link.clone()).or_insert(link_name);
+                }
+                if self.has_object(&link) && self.objects[&link].object_type() != link_type {
+                    eprintln!(
+                        "error in {object_type} {}: wrong object type in link",
+                        self.describe(&hash)
+                    );
+                    self.errors |= ERROR_OBJECT;
+                }
+                if !self.reachable.insert(link.clone()) {
+                    continue;
+                }
+
+                if self.objects.contains_key(&link) {
+                    self.pending.push(link);
+                    continue;
+                }
+                // Corrupt objects are only reported as missing
+                if !self.corrupt.contains(&link) {
+                    writeln!(
+                        writer,
+                        "broken link from {:>7} {}\n              to {:>7} {}",
+                        object_type.to_string(),
+                        self.describe(&hash),
+                        link_type.to_string(),
+                        self.describe(&link)
+                    )?;
+                }
+                self.missing.insert(link, link_type);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The hash of an object, followed by its name in parentheses if it has one.
+    fn describe(&self, hash: &str) -> String {
+        match self.names.as_ref().and_then(|names| names.get(hash)) {
+            Some(name) => format!("{hash} ({name})"),
+            None => hash.to_string(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct FsckArgs {
+    /// show every unreachable object, instead of only the dangling ones
+    /// (those no other object points to)
+    #[arg(long)]
+    unreachable: bool,
+    /// don't show the dangling objects
+    #[arg(long)]
+    no_dangling: bool,
+    /// don't consider the objects of the reflogs as reachable
+    #[arg(long)]
+    no_reflogs: bool,
+    /// show how each reachable object is reached (e.g. `HEAD~3:src/foo.rs`)
+    /// next to its hash
+    #[arg(long)]
+    name_objects: bool,
+    /// show how many objects are checked per second, and a summary of the throughput
+    #[arg(long)]
+    progress: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::hex;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    /// Write the `src` directory of a commit, holding a file with some content.
+    fn src_tree(repo: &Repository, name: &str, content: &str) -> String {
+        let blob = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+        let mut src = format!("100644 {name}\0").into_bytes();
+        src.extend(hex::decode(blob.as_bytes()).unwrap());
+        write_object(repo, ObjectType::Tree, &src).unwrap()
+    }
+
+    /// Write a commit of a tree holding `src/<name>` with some content.
+    fn commit(repo: &Repository, parent: Option<&str>, name: &str, content: &str) -> String {
+        let mut root = b"40000 src\0".to_vec();
+        root.extend(hex::decode(src_tree(repo, name, content).as_bytes()).unwrap());
+        let root = write_object(repo, ObjectType::Tree, &root).unwrap();
+
+        let parent = parent.map_or(String::new(), |parent| format!("parent {parent}\n"));
+        let content = format!(
+            "tree {root}\n{parent}author A <a@example.com> 1700000000 +0000\n\
+             committer C <c@example.com> 1700000000 +0000\n\n{name}\n"
+        );
+        write_object(repo, ObjectType::Commit, content.as_bytes()).unwrap()
+    }
+
+    fn fsck(repo: &Repository, args: FsckArgs) -> (String, i32) {
+        let mut output = Vec::new();
+        let code = match args.run(repo, &mut output) {
+            Ok(()) => 0,
+            Err(err) => err.downcast_ref::<SilentExit>().unwrap().0,
+        };
+        (String::from_utf8(output).unwrap(), code)
+    }
+
+    fn args() -> FsckArgs {
+        FsckArgs {
+            unreachable: false,
+            no_dangling: false,
+            no_reflogs: false,
+            name_objects: false,
+            progress: false,
+        }
+    }
+
+    #[test]
+    fn reports_dangling_and_unreachable_objects() {
+        let (_temp, repo) = setup();
+        let first = commit(&repo, None, "a.rs", "a\n");
+        write_ref(&repo, "refs/heads/main", &first).unwrap();
+        let dangling = commit(&repo, None, "b.rs", "b\n");
+        let blob = hash_object(&ObjectType::Blob, b"b\n");
+
+        assert_eq!(
+            fsck(&repo, args()),
+            (format!("dangling commit {dangling}\n"), 0)
+        );
+
+        let (output, code) = fsck(&repo, FsckArgs {
+            unreachable: true,
+            ..args()
+        });
+        assert_eq!(code, 0);
+        assert!(
+            output.contains(&format!("unreachable blob {blob}\n")),
+            "{output}"
+        );
+        assert_eq!(output.lines().count(), 4);
+    }
+
+    #[test]
+    fn names_missing_objects_by_path() {
+        let (temp, repo) = setup();
+        let first = commit(&repo, None, "foo.rs", "one\n");
+        let second = commit(&repo, Some(&first), "foo.rs", "two\n");
+        let third = commit(&repo, Some(&second), "foo.rs", "three\n");
+        fs::write(temp.join(".git/refs/heads/main"), format!("{third}\n")).unwrap();
+
+        let src = src_tree(&repo, "foo.rs", "one\n");
+        let blob = hash_object(&ObjectType::Blob, b"one\n");
+        fs::remove_file(temp.join(format!(".git/objects/{}/{}", &blob[..2], &blob[2..]))).unwrap();
+        let (output, code) = fsck(&repo, FsckArgs {
+            name_objects: true,
+            ..args()
+        });
+        assert_eq!(code, ERROR_REACHABLE);
+        assert_eq!(
+            output,
+            format!(
+                "broken link from    tree {src} (refs/heads/main~2:src/)\n              \
+                 to    blob {blob} (refs/heads/main~2:src/foo.rs)\n\
+                 missing blob {blob} (refs/heads/main~2:src/foo.rs)\n"
+            )
+        );
+    }
+}
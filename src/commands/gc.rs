@@ -0,0 +1,78 @@
+use std::io::Write;
+use std::time::SystemTime;
+
+use clap::Args;
+
+use crate::commands::{rerere, CommandArgs};
+use crate::utils::refs::pack_refs;
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::{remove_leftovers, STALE_AFTER};
+
+impl CommandArgs for GcArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        // A stale `packed-refs.lock` would make packing the refs fail
+        remove_leftovers(repo, SystemTime::now() - STALE_AFTER, false, writer)?;
+        pack_refs(repo, true, true)?;
+        rerere::gc(repo)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct GcArgs {}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{resolve_ref, write_ref};
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn removes_stale_locks_and_temporary_files() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        fs::create_dir_all(temp.join(".git/objects/pack")).unwrap();
+        fs::create_dir_all(temp.join(".git/objects/aa")).unwrap();
+        let repo = temp.repo();
+        let blob = write_object(&repo, ObjectType::Blob, b"content\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &blob).unwrap();
+
+        let old = SystemTime::now() - 2 * STALE_AFTER;
+        for path in [
+            ".git/packed-refs.lock",
+            ".git/refs/heads/main.lock",
+            ".git/objects/aa/tmp_obj_1234",
+            ".git/objects/pack/tmp_pack_5678",
+        ] {
+            fs::File::create(temp.join(path))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        // Recent files may belong to a running command
+        fs::write(temp.join(".git/index.lock"), "").unwrap();
+        fs::write(temp.join(".git/objects/aa/tmp_obj_9abc"), "").unwrap();
+
+        let mut output = Vec::new();
+        GcArgs {}.run(&repo, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Removing stale temporary file .git/objects/aa/tmp_obj_1234\n\
+             Removing stale temporary file .git/objects/pack/tmp_pack_5678\n\
+             Removing stale lock file .git/packed-refs.lock\n\
+             Removing stale lock file .git/refs/heads/main.lock\n"
+        );
+        assert!(temp.join(".git/index.lock").exists());
+        assert!(temp.join(".git/objects/aa/tmp_obj_9abc").exists());
+        assert!(!temp.join(".git/packed-refs.lock").exists());
+
+        // The refs are packed once their locks are gone
+        assert!(!temp.join(".git/refs/heads/main").exists());
+        assert_eq!(resolve_ref(&repo, "refs/heads/main").unwrap(), Some(blob));
+    }
+}
@@ -0,0 +1,350 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::diff::is_binary;
+use crate::utils::index::Index;
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::path_from_bytes;
+use crate::utils::pathspec::Pathspec;
+use crate::utils::regex::Regex;
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_tree;
+use crate::utils::tree::{flatten_tree, MODE_GITLINK};
+use crate::utils::work_tree::read_file;
+
+impl CommandArgs for GrepArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let pattern = if self.fixed_strings {
+            escape(&self.pattern)
+        } else {
+            self.pattern.clone()
+        };
+        let regex = Regex::new(&pattern, self.ignore_case)?;
+
+        let files = self.collect_files(repo)?;
+        let outputs = self.scan(repo, &regex, &files)?;
+
+        let mut matched = false;
+        for output in outputs {
+            matched |= !output.is_empty();
+            writer.write_all(&output)?;
+        }
+
+        // As with grep, finding nothing is a (silent) failure
+        if !matched {
+            return Err(SilentExit(1).into());
+        }
+        Ok(())
+    }
+}
+
+/// Where the content of a searched file comes from
+#[derive(Debug)]
+enum Source {
+    /// A file of the working tree
+    File(PathBuf),
+    /// A blob of the index or of a tree
+    Blob(String),
+}
+
+impl GrepArgs {
+    /// List the files to search, with the name they are shown with: the tracked
+    /// files of the working tree or of the index, or the files of each tree given.
+    fn collect_files(&self, repo: &Repository) -> anyhow::Result<Vec<(String, Source)>> {
+        let pathspec = Pathspec::new(&repo.prefix()?, &self.paths)?;
+        let mut files = Vec::new();
+
+        if self.trees.is_empty() {
+            let index = Index::load(repo)?;
+            let work_tree = if self.cached {
+                None
+            } else {
+                Some(repo.work_tree()?)
+            };
+
+            let mut previous: Option<&[u8]> = None;
+            for entry in index.entries() {
+                // Conflicted paths have several entries but are searched once
+                if entry.mode == MODE_GITLINK
+                    || previous == Some(entry.path.as_slice())
+                    || !pathspec.matches(&entry.path)
+                {
+                    continue;
+                }
+                previous = Some(&entry.path);
+
+                let name = pathspec.display(&entry.path);
+                match &work_tree {
+                    Some(work_tree) => {
+                        let path = work_tree.join(path_from_bytes(&entry.path));
+                        // Deleted files are skipped
+                        if path.symlink_metadata().is_ok() {
+                            files.push((name, Source::File(path)));
+                        }
+                    },
+                    None => files.push((name, Source::Blob(entry.hash.clone()))),
+                }
+            }
+        }
+
+        for tree_ish in &self.trees {
+            let tree = resolve_tree(repo, tree_ish)?;
+            for entry in flatten_tree(repo, &tree)? {
+                let path = String::from_utf8_lossy(&entry.name).into_owned();
+                if entry.mode == MODE_GITLINK || !pathspec.matches(&path) {
+                    continue;
+                }
+                let name = format!("{tree_ish}:{}", pathspec.display(&path));
+                files.push((name, Source::Blob(entry.hash)));
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Search the files, split between as many threads as there are CPUs.
+    ///
+    /// # Returns
+    ///
+    /// The output for each file, in order (empty if nothing matched)
+    fn scan(
+        &self,
+        repo: &Repository,
+        regex: &Regex,
+        files: &[(String, Source)],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk_size = files.len().div_ceil(threads).max(1);
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = files
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(name, source)| self.search(repo, regex, name, source))
+                            .collect::<anyhow::Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+
+            let mut outputs = Vec::with_capacity(files.len());
+            for handle in handles {
+                let chunk = handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("grep thread panicked"))?;
+                outputs.extend(chunk?);
+            }
+            Ok(outputs)
+        })
+    }
+
+    /// Search a file, formatting the matching lines (or only its name with `-l`).
+    fn search(
+        &self,
+        repo: &Repository,
+        regex: &Regex,
+        name: &str,
+        source: &Source,
+    ) -> anyhow::Result<Vec<u8>> {
+        let content = match source {
+            Source::File(path) => {
+                let metadata = path.symlink_metadata()?;
+                read_file(path, &metadata)?
+            },
+            Source::Blob(hash) => read_object_of_type(repo, hash, ObjectType::Blob)
+                .context(format!("read blob of {name}"))?,
+        };
+
+        let mut output = Vec::new();
+        let lines = content
+            .strip_suffix(b"\n")
+            .unwrap_or(&content)
+            .split(|&b| b == b'\n');
+        for (number, line) in lines.enumerate() {
+            if !regex.is_match(line) {
+                continue;
+            }
+
+            if self.files_with_matches {
+                writeln!(output, "{name}")?;
+                break;
+            }
+            if is_binary(&content) {
+                writeln!(output, "Binary file {name} matches")?;
+                break;
+            }
+
+            write!(output, "{name}:")?;
+            if self.line_number {
+                write!(output, "{}:", number + 1)?;
+            }
+            output.extend(line);
+            output.push(b'\n');
+        }
+        Ok(output)
+    }
+}
+
+/// Escape a fixed string so it matches itself as a regular expression.
+fn escape(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '.' | '[' | ']' | '*' | '\\' | '^' | '$') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct GrepArgs {
+    /// show line numbers
+    #[arg(short = 'n', long)]
+    line_number: bool,
+    /// match case-insensitively
+    #[arg(short, long)]
+    ignore_case: bool,
+    /// only show the names of the files with matches
+    #[arg(short = 'l', long, visible_alias = "name-only")]
+    files_with_matches: bool,
+    /// interpret the pattern as a fixed string instead of a regular expression
+    #[arg(short = 'F', long)]
+    fixed_strings: bool,
+    /// search the files staged in the index instead of the working tree
+    #[arg(long, conflicts_with = "trees")]
+    cached: bool,
+    /// the (basic) regular expression to search for
+    pattern: String,
+    /// the trees to search instead of the working tree
+    #[arg(value_name = "tree")]
+    trees: Vec<String>,
+    /// only search these paths
+    #[arg(value_name = "path", last = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        fs::create_dir_all(temp.join("src")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let mut index = Index::default();
+        for (path, content) in [
+            ("README", "Hello\nworld\n"),
+            ("src/main.rs", "fn main() {\n    hello();\n}\n"),
+            ("data.bin", "hello\0world"),
+        ] {
+            fs::write(temp.join(path), content).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.into(), 0o100644, hash));
+        }
+        let tree = index.write_tree(&repo, false).unwrap();
+        index.write(&repo).unwrap();
+        let commit = create_commit(&repo, &tree, Vec::new(), "first\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        (temp, repo)
+    }
+
+    fn args(pattern: &str) -> GrepArgs {
+        GrepArgs {
+            line_number: false,
+            ignore_case: false,
+            files_with_matches: false,
+            fixed_strings: false,
+            cached: false,
+            pattern: pattern.to_string(),
+            trees: Vec::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    fn grep(repo: &Repository, args: GrepArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn searches_the_working_tree() {
+        let (temp, repo) = setup();
+        fs::write(
+            temp.join("src/main.rs"),
+            "fn main() {\n    hello();\n    Hello();\n}\n",
+        )
+        .unwrap();
+        fs::write(temp.join("untracked"), "hello\n").unwrap();
+
+        assert_eq!(
+            grep(&repo, args("hel*o")).unwrap(),
+            "Binary file data.bin matches\nsrc/main.rs:    hello();\n"
+        );
+        assert_eq!(
+            grep(&repo, GrepArgs {
+                line_number: true,
+                ignore_case: true,
+                paths: vec![PathBuf::from("src")],
+                ..args("^ *hello")
+            })
+            .unwrap(),
+            "src/main.rs:2:    hello();\nsrc/main.rs:3:    Hello();\n"
+        );
+
+        let err = grep(&repo, args("missing")).unwrap_err();
+        assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+    }
+
+    #[test]
+    fn searches_the_index_and_trees() {
+        let (temp, repo) = setup();
+        fs::write(temp.join("README"), "nothing\n").unwrap();
+
+        assert_eq!(
+            grep(&repo, GrepArgs {
+                cached: true,
+                files_with_matches: true,
+                ..args("world")
+            })
+            .unwrap(),
+            "README\ndata.bin\n"
+        );
+        assert_eq!(
+            grep(&repo, GrepArgs {
+                fixed_strings: true,
+                trees: vec!["HEAD".to_string()],
+                ..args("hello()")
+            })
+            .unwrap(),
+            "HEAD:src/main.rs:    hello();\n"
+        );
+    }
+}
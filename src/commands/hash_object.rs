@@ -8,8 +8,10 @@ use flate2::Compression;
 use sha1::{Digest, Sha1};
 
 use crate::commands::CommandArgs;
-use crate::utils::git_object_dir;
+use crate::utils::convert::Conversion;
 use crate::utils::objects::{format_header, ObjectType};
+use crate::utils::pathspec::normalize;
+use crate::utils::repository::Repository;
 
 impl CommandArgs for HashObjectArgs {
     /// Hashes the object and writes it to the `.git/objects` directory if requested.
@@ -17,16 +19,30 @@ impl CommandArgs for HashObjectArgs {
     /// # Arguments
     ///
     /// * `self` - The `HashObjectArgs` containing the command arguments.
+    /// * `repo` - The repository to write the object to.
     ///
     /// # Returns
     ///
     /// * `anyhow::Result<()>` - The result of the command execution.
-    fn run<W>(self, writer: &mut W) -> anyhow::Result<()>
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
     where
         W: Write,
     {
         // Create blob from header and file content.
-        let content = std::fs::read(&self.path).context(format!("read {}", self.path.display()))?;
+        let mut content =
+            std::fs::read(&self.path).context(format!("read {}", self.path.display()))?;
+
+        // Blobs of files in a working tree get their line endings converted, as when added
+        if self.object_type == ObjectType::Blob && !self.no_filters && repo.work_tree().is_ok() {
+            if let Ok(path) = normalize(&repo.prefix()?, &self.path) {
+                let mut conversion = Conversion::load(repo)?;
+                // Only writing the object can lose line endings worth a warning
+                if !self.write {
+                    conversion = conversion.without_safe_crlf();
+                }
+                content = conversion.convert_to_git(&path, content)?;
+            }
+        }
         let header = format_header(self.object_type, content.len());
         let mut blob = header.into_bytes();
         blob.extend(content);
@@ -42,7 +58,7 @@ impl CommandArgs for HashObjectArgs {
 
         // Write blob to the object database if requested.
         if self.write {
-            write_blob(&blob, &hash)?;
+            write_blob(repo, &blob, &hash)?;
         }
 
         // Display the hash of the blob.
@@ -55,18 +71,19 @@ impl CommandArgs for HashObjectArgs {
 ///
 /// # Arguments
 ///
+/// * `repo` - The repository to write the blob to.
 /// * `blob` - The blob data to be written.
 /// * `hash` - The hash of the blob.
 ///
 /// # Returns
 ///
 /// * `anyhow::Result<()>` - The result of the write operation.
-fn write_blob(blob: &[u8], hash: &str) -> anyhow::Result<()> {
+fn write_blob(repo: &Repository, blob: &[u8], hash: &str) -> anyhow::Result<()> {
     // Split the hash into directory and file name.
     let (dir_name, file_name) = hash.split_at(2);
 
     // Create the object directory if it doesn't exist.
-    let object_dir = git_object_dir(false)?.join(dir_name);
+    let object_dir = repo.object_dir(false)?.join(dir_name);
     std::fs::create_dir_all(&object_dir).context("create subdir in .git/objects")?;
 
     // Compress the blob with zlib.
@@ -87,6 +104,9 @@ pub(crate) struct HashObjectArgs {
     /// write the object into the object database
     #[arg(short)]
     write: bool,
+    /// hash the file as is, without converting its line endings
+    #[arg(long)]
+    no_filters: bool,
     /// process file as it were from this path
     #[arg(value_name = "file")]
     path: PathBuf,
@@ -99,9 +119,8 @@ mod tests {
 
     use super::{write_blob, HashObjectArgs};
     use crate::commands::CommandArgs;
-    use crate::utils::env;
     use crate::utils::objects::ObjectType;
-    use crate::utils::test::{TempEnv, TempPwd};
+    use crate::utils::test::TempRepo;
 
     const OBJECT_CONTENT: &str = "Hello, World!";
     const FILE_NAME: &str = "testfile.txt";
@@ -109,20 +128,20 @@ mod tests {
 
     #[test]
     fn hashes_blob_and_displays_hash() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let file_path = pwd.path().join(FILE_NAME);
+        let file_path = temp.join(FILE_NAME);
         fs::write(&file_path, OBJECT_CONTENT).unwrap();
 
         let args = HashObjectArgs {
             write: false,
+            no_filters: false,
             path: file_path,
             object_type: ObjectType::Blob,
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, OBJECT_HASH.as_bytes());
@@ -130,68 +149,60 @@ mod tests {
 
     #[test]
     fn writes_blob_to_object_database() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let file_path = pwd.path().join(FILE_NAME);
+        let file_path = temp.join(FILE_NAME);
 
         fs::write(&file_path, OBJECT_CONTENT).unwrap();
         // Create the .git directory.
-        fs::create_dir_all(pwd.path().join(".git/objects")).unwrap();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
 
         let args = HashObjectArgs {
             write: true,
+            no_filters: false,
             path: file_path,
             object_type: ObjectType::Blob,
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_ok());
 
         // Check that the object file was written to the object database.
         let (dir_name, file_name) = OBJECT_HASH.split_at(2);
-        let object_path = pwd
-            .path()
-            .join(".git/objects")
-            .join(dir_name)
-            .join(file_name);
+        let object_path = temp.join(".git/objects").join(dir_name).join(file_name);
         assert!(object_path.exists());
     }
 
     #[test]
     fn fails_on_nonexistent_file() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
-        let _pwd = TempPwd::new();
+        let temp = TempRepo::new();
 
         let args = HashObjectArgs {
             write: false,
+            no_filters: false,
             path: PathBuf::from("nonexistent.txt"),
             object_type: ObjectType::Blob,
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 
     #[test]
     fn write_blob_creates_object_database() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
         let blob = format!("blob {}\0{}", OBJECT_CONTENT.len(), OBJECT_CONTENT);
         // Create the .git directory.
-        fs::create_dir(pwd.path().join(".git")).unwrap();
+        fs::create_dir(temp.join(".git")).unwrap();
 
-        let result = write_blob(blob.as_bytes(), OBJECT_HASH);
+        let repo = temp.repo();
+        let result = write_blob(&repo, blob.as_bytes(), OBJECT_HASH);
         assert!(result.is_ok());
 
         // Check that the object directory and file were created.
         let (dir_name, file_name) = OBJECT_HASH.split_at(2);
-        let object_dir = pwd
-            .path()
-            .join(".git/objects")
-            .join(dir_name)
-            .join(file_name);
+        let object_dir = temp.join(".git/objects").join(dir_name).join(file_name);
         assert!(object_dir.exists());
     }
 }
@@ -1,23 +1,25 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
 
 use crate::commands::CommandArgs;
 use crate::utils::env;
+use crate::utils::repository::Repository;
 
 impl CommandArgs for InitArgs {
-    fn run<W>(self, writer: &mut W) -> anyhow::Result<()>
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
     where
         W: Write,
     {
-        let init_path = get_init_path(self.directory, self.bare)?;
+        let init_path = get_init_path(self.directory, self.bare, repo.explicit_git_dir())?;
 
         // The directory where git objects are stored.
         // GIT_OBJECT_DIRECTORY takes precedence over the default 'objects' directory.
-        let object_dir = std::env::var(env::GIT_OBJECT_DIRECTORY)
-            .map(|object_dir| init_path.join(object_dir))
-            .unwrap_or_else(|_| init_path.join("objects"));
+        let object_dir = repo
+            .env()
+            .var_os(env::GIT_OBJECT_DIRECTORY)
+            .map_or_else(|| init_path.join("objects"), |dir| init_path.join(dir));
 
         // Create the git directory and its subdirectories.
         std::fs::create_dir_all(object_dir)?;
@@ -50,26 +52,31 @@ pub(crate) fn get_head_ref_content(initial_branch: &str) -> String {
 /// Returns the path to initialize the git repository.
 ///
 /// - If the target directory is not specified, the current directory is used.
-/// - If the `--bare` flag is passed, the target directory is used as the .git directory (unless GIT_DIR is set).
+/// - If the `--bare` flag is passed, the target directory is used as the .git directory (unless the git directory is set).
 /// - If the `--bare` flag is not passed, a .git directory is created in the target directory.
 ///
-/// > Note: The `GIT_DIR` environment variable takes precedence over the default `.git` directory.
+/// > Note: The `--git-dir` option (or `GIT_DIR` environment variable) takes precedence over the default `.git` directory.
 ///
 /// # Arguments
 ///
 /// * `target_dir` - The directory to create the repository in.
 /// * `bare` - Create a bare repository.
+/// * `git_dir` - The explicitly requested git directory, if any.
 ///
 /// # Returns
 ///
 /// The path to initialize the git repository.
-fn get_init_path(target_dir: Option<PathBuf>, bare: bool) -> anyhow::Result<PathBuf> {
+fn get_init_path(
+    target_dir: Option<PathBuf>,
+    bare: bool,
+    git_dir: Option<&Path>,
+) -> anyhow::Result<PathBuf> {
     // Creates a .git directory in the target directory.
     if !bare {
         // If the target directory is not specified, use the current directory.
         let target_dir = target_dir.unwrap_or_else(|| ".".into());
-        // Prioritize the GIT_DIR environment variable over '.git'
-        let git_dir = std::env::var(env::GIT_DIR).unwrap_or_else(|_| ".git".to_string());
+        // Prioritize the explicit git directory over '.git'
+        let git_dir = git_dir.unwrap_or_else(|| Path::new(".git"));
         return Ok(target_dir.join(git_dir));
     }
 
@@ -78,10 +85,10 @@ fn get_init_path(target_dir: Option<PathBuf>, bare: bool) -> anyhow::Result<Path
     if let Some(target_dir) = target_dir {
         Ok(target_dir)
     } else {
-        // If the target directory is not specified, use the path defined
-        // in GIT_DIR or the current directory.
-        let target_dir = std::env::var(env::GIT_DIR).unwrap_or_else(|_| ".".to_string());
-        Ok(target_dir.into())
+        // If the target directory is not specified, use the explicit
+        // git directory or the current directory.
+        let target_dir = git_dir.unwrap_or_else(|| Path::new("."));
+        Ok(target_dir.to_path_buf())
     }
 }
 
@@ -109,7 +116,7 @@ mod tests {
     use super::InitArgs;
     use crate::commands::CommandArgs;
     use crate::utils::env;
-    use crate::utils::test::{TempEnv, TempPwd};
+    use crate::utils::test::TempRepo;
 
     const INITIAL_BRANCH: &str = "main";
     const CUSTOM_GIT_DIR: &str = "custom_git_dir";
@@ -117,18 +124,17 @@ mod tests {
 
     #[test]
     fn inits_repo() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let git_dir = pwd.path().join(".git");
+        let git_dir = temp.join(".git");
         let args = InitArgs {
-            directory: Some(pwd.path().to_path_buf()),
+            directory: Some(temp.path().to_path_buf()),
             bare: false,
             quiet: true,
             initial_branch: INITIAL_BRANCH.to_string(),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_ok());
         assert!(git_dir.exists());
         assert!(git_dir.join("objects").exists());
@@ -141,41 +147,39 @@ mod tests {
 
     #[test]
     fn inits_bare_repo() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
         let args = InitArgs {
-            directory: Some(pwd.path().to_path_buf()),
+            directory: Some(temp.path().to_path_buf()),
             bare: true,
             quiet: true,
             initial_branch: INITIAL_BRANCH.to_string(),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_ok());
-        assert!(pwd.path().join("objects").exists());
-        assert!(pwd.path().join("refs").exists());
-        assert!(pwd.path().join("HEAD").exists());
+        assert!(temp.join("objects").exists());
+        assert!(temp.join("refs").exists());
+        assert!(temp.join("HEAD").exists());
 
-        let head_content = fs::read_to_string(pwd.path().join("HEAD")).unwrap();
+        let head_content = fs::read_to_string(temp.join("HEAD")).unwrap();
         assert_eq!(head_content, "ref: refs/heads/main\n");
     }
 
     #[test]
     fn inits_repo_with_branch() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
-        let pwd = TempPwd::new();
-        let git_dir = pwd.path().join(".git");
+        let git_dir = temp.join(".git");
         let custom_branch = "develop".to_string();
         let args = InitArgs {
-            directory: Some(pwd.path().to_path_buf()),
+            directory: Some(temp.path().to_path_buf()),
             bare: false,
             quiet: true,
             initial_branch: custom_branch.clone(),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_ok());
         assert!(git_dir.exists());
         assert!(git_dir.join("HEAD").exists());
@@ -186,21 +190,17 @@ mod tests {
 
     #[test]
     fn inits_repo_with_custom_git_dir() {
-        let _env = TempEnv::from([
-            (env::GIT_DIR, Some(CUSTOM_GIT_DIR)),
-            (env::GIT_OBJECT_DIRECTORY, None),
-        ]);
+        let temp = TempRepo::new().env(env::GIT_DIR, CUSTOM_GIT_DIR);
 
-        let pwd = TempPwd::new();
-        let git_dir = pwd.path().join(CUSTOM_GIT_DIR);
+        let git_dir = temp.join(CUSTOM_GIT_DIR);
         let args = InitArgs {
-            directory: Some(pwd.path().to_path_buf()),
+            directory: Some(temp.path().to_path_buf()),
             bare: false,
             quiet: true,
             initial_branch: INITIAL_BRANCH.to_string(),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_ok());
         assert!(git_dir.exists());
         assert!(git_dir.join("objects").exists());
@@ -213,21 +213,17 @@ mod tests {
 
     #[test]
     fn inits_repo_with_custom_git_object_dir() {
-        let _env = TempEnv::from([
-            (env::GIT_DIR, None),
-            (env::GIT_OBJECT_DIRECTORY, Some(CUSTOM_OBJECT_DIR)),
-        ]);
+        let temp = TempRepo::new().env(env::GIT_OBJECT_DIRECTORY, CUSTOM_OBJECT_DIR);
 
-        let pwd = TempPwd::new();
-        let git_dir = pwd.path().join(".git");
+        let git_dir = temp.join(".git");
         let args = InitArgs {
-            directory: Some(pwd.path().to_path_buf()),
+            directory: Some(temp.path().to_path_buf()),
             bare: false,
             quiet: true,
             initial_branch: INITIAL_BRANCH.to_string(),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_ok());
         assert!(git_dir.exists());
         assert!(git_dir.join(CUSTOM_OBJECT_DIR).exists());
@@ -235,7 +231,7 @@ mod tests {
 
     #[test]
     fn fail_on_invalid_init_path() {
-        let _env = TempEnv::from([(env::GIT_DIR, None), (env::GIT_OBJECT_DIRECTORY, None)]);
+        let temp = TempRepo::new();
 
         let args = InitArgs {
             directory: Some(PathBuf::from("/invalid/directory")),
@@ -244,7 +240,7 @@ mod tests {
             initial_branch: INITIAL_BRANCH.to_string(),
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 }
@@ -0,0 +1,304 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use crate::commands::CommandArgs;
+use crate::utils::advice::warning;
+use crate::utils::commit::read_commit;
+use crate::utils::config::Config;
+use crate::utils::index::{Index, Stat};
+use crate::utils::lfs::{self, Endpoint, Pointer};
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::path_from_bytes;
+use crate::utils::pathspec::{is_within, normalize};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::tree::{flatten_tree, MODE_GITLINK, MODE_SYMLINK};
+
+/// The remote LFS objects are fetched from by default
+const DEFAULT_REMOTE: &str = "origin";
+
+impl CommandArgs for LfsArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.command {
+            LfsCommand::Fetch(args) => fetch(repo, args.args, writer),
+            LfsCommand::Checkout(args) => checkout(repo, &args.paths),
+            LfsCommand::Smudge => {
+                let mut content = Vec::new();
+                std::io::stdin().read_to_end(&mut content)?;
+                writer.write_all(&smudge(repo, content)?)?;
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Download the LFS objects of the trees of some commits (HEAD by default)
+/// that are missing from the local store.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to fetch the objects into
+/// * `args` - An optional remote (`origin` by default), then the commits
+/// * `writer` - Where the number of downloaded objects is written
+fn fetch<W>(repo: &Repository, mut args: Vec<String>, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let config = Config::load(repo)?;
+    let remote = match args.first() {
+        Some(first) if config.get(&format!("remote.{first}.url")).is_some() => args.remove(0),
+        _ => DEFAULT_REMOTE.to_string(),
+    };
+    if args.is_empty() {
+        args.push("HEAD".to_string());
+    }
+
+    let mut missing: Vec<Pointer> = Vec::new();
+    for revision in &args {
+        let tree = read_commit(repo, &resolve_commit(repo, revision)?)?.tree;
+        for entry in flatten_tree(repo, &tree)? {
+            if entry.mode == MODE_GITLINK || entry.mode == MODE_SYMLINK {
+                continue;
+            }
+            let content = read_object_of_type(repo, &entry.hash, ObjectType::Blob)?;
+            if let Some(pointer) = Pointer::parse(&content) {
+                if !lfs::has_object(repo, &pointer)? && !missing.contains(&pointer) {
+                    missing.push(pointer);
+                }
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        Endpoint::for_remote(repo, &remote)?.download(repo, &missing)?;
+    }
+    let plural = if missing.len() == 1 { "" } else { "s" };
+    writeln!(writer, "Downloaded {} LFS object{plural}", missing.len())?;
+    Ok(())
+}
+
+/// Replace the pointer files of the working tree matching some paths (all of them
+/// by default) by their content, if it is in the local store.
+fn checkout(repo: &Repository, paths: &[PathBuf]) -> anyhow::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let prefix = repo.prefix()?;
+    let specs = match paths.is_empty() {
+        true => vec![String::new()],
+        false => paths
+            .iter()
+            .map(|path| normalize(&prefix, path))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
+
+    let mut index = Index::load(repo)?;
+    let mut updated = Vec::new();
+    for entry in index.entries() {
+        if entry.stage != 0
+            || entry.mode == MODE_GITLINK
+            || entry.mode == MODE_SYMLINK
+            || !specs.iter().any(|spec| is_within(&entry.path, spec))
+        {
+            continue;
+        }
+        let Some(pointer) =
+            Pointer::parse(&read_object_of_type(repo, &entry.hash, ObjectType::Blob)?)
+        else {
+            continue;
+        };
+
+        // Only the files still holding the pointer are replaced
+        let path = work_tree.join(path_from_bytes(&entry.path));
+        let current = std::fs::read(&path).unwrap_or_default();
+        if Pointer::parse(&current).as_ref() != Some(&pointer) {
+            continue;
+        }
+        let Some(content) = lfs::read_object(repo, &pointer)? else {
+            warning(&format!(
+                "skipped '{}', its LFS object is not local (see 'lfs fetch')",
+                String::from_utf8_lossy(&entry.path)
+            ));
+            continue;
+        };
+
+        std::fs::write(&path, content)?;
+        let mut entry = entry.clone();
+        entry.stat = Stat::from_metadata(&path.symlink_metadata()?);
+        updated.push(entry);
+    }
+
+    if !updated.is_empty() {
+        for entry in updated {
+            index.add_entry(entry);
+        }
+        index.write(repo)?;
+    }
+    Ok(())
+}
+
+/// Convert a pointer file to its content, downloading the content from the
+/// default remote if it is not local. Other content is kept as is.
+fn smudge(repo: &Repository, content: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if let Some(pointer) = Pointer::parse(&content) {
+        if !lfs::has_object(repo, &pointer)? {
+            Endpoint::for_remote(repo, DEFAULT_REMOTE)?.download(repo, &[pointer])?;
+        }
+    }
+    lfs::smudge(repo, content)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct LfsArgs {
+    #[command(subcommand)]
+    command: LfsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum LfsCommand {
+    /// download the LFS objects of commits from a remote
+    Fetch(FetchArgs),
+    /// replace pointer files in the working tree by their downloaded content
+    Checkout(CheckoutArgs),
+    /// convert a pointer file on standard input to its content
+    Smudge,
+}
+
+#[derive(Args, Debug)]
+struct FetchArgs {
+    /// the remote to fetch from (origin by default), then the commits (HEAD by default)
+    #[arg(value_name = "ref")]
+    args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct CheckoutArgs {
+    /// the paths to check out (all by default)
+    #[arg(value_name = "path")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    /// Set up a repository whose `origin` has the LFS object of its file `large`.
+    fn setup() -> (TempRepo, Repository, Pointer) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join("origin/.git/objects")).unwrap();
+        let origin = temp.repo().open(temp.join("origin/.git"), None);
+        let pointer = lfs::write_object(&origin, b"large content\n").unwrap();
+
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let config = format!(
+            "[remote \"origin\"]\n\turl = {}\n",
+            fs::canonicalize(temp.join("origin")).unwrap().display()
+        );
+        fs::write(temp.join(".git/config"), config).unwrap();
+        fs::write(temp.join(".gitattributes"), "large filter=lfs -text\n").unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        fs::write(temp.join("large"), pointer.to_string()).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, pointer.to_string().as_bytes()).unwrap();
+        let mut index = Index::default();
+        index.add_entry(IndexEntry::new("large".into(), 0o100644, hash));
+        index.refresh(temp.path()).unwrap();
+        index.write(&repo).unwrap();
+        let tree = index.write_tree(&repo, false).unwrap();
+        let commit = create_commit(&repo, &tree, Vec::new(), "add large\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        (temp, repo, pointer)
+    }
+
+    #[test]
+    fn fetches_and_checks_out_objects() {
+        let (temp, repo, pointer) = setup();
+
+        // Without the object, the pointer file is kept
+        checkout(&repo, &[]).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("large")).unwrap(),
+            pointer.to_string()
+        );
+
+        let mut output = Vec::new();
+        fetch(&repo, vec!["origin".to_string()], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Downloaded 1 LFS object\n"
+        );
+        let mut output = Vec::new();
+        fetch(&repo, Vec::new(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Downloaded 0 LFS objects\n"
+        );
+
+        checkout(&repo, &[PathBuf::from("large")]).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("large")).unwrap(),
+            "large content\n"
+        );
+        // The cached metadata is that of the content (comparing the entry
+        // would hash the file if it was written within the same timestamp as
+        // the index, which finds it different from the pointer)
+        let index = Index::load(&repo).unwrap();
+        let entry = index.entry(b"large", 0).unwrap();
+        let metadata = fs::symlink_metadata(temp.join("large")).unwrap();
+        assert_eq!(entry.stat, Stat::from_metadata(&metadata));
+    }
+
+    #[test]
+    fn smudges_pointer_files() {
+        let (_temp, repo, pointer) = setup();
+
+        assert_eq!(
+            smudge(&repo, b"not a pointer\n".to_vec()).unwrap(),
+            b"not a pointer\n"
+        );
+        // The object is downloaded from origin
+        let content = smudge(&repo, pointer.to_string().into_bytes()).unwrap();
+        assert_eq!(content, b"large content\n");
+        assert!(lfs::has_object(&repo, &pointer).unwrap());
+
+        // And files with `filter=lfs` are smudged when checked out, and cleaned when added
+        let mut conversion = crate::utils::convert::Conversion::load(&repo).unwrap();
+        let blob = pointer.to_string().into_bytes();
+        assert_eq!(
+            conversion
+                .convert_to_work_tree("large", blob.clone())
+                .unwrap(),
+            b"large content\n"
+        );
+        assert_eq!(
+            conversion
+                .convert_to_git("large", b"large content\n".to_vec())
+                .unwrap(),
+            blob
+        );
+        assert_eq!(
+            conversion
+                .convert_to_work_tree("other", blob.clone())
+                .unwrap(),
+            blob
+        );
+    }
+}
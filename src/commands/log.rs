@@ -0,0 +1,701 @@
+use std::io::{IsTerminal, Write};
+
+use clap::{Args, ValueEnum};
+
+use crate::commands::CommandArgs;
+use crate::utils::color::{config_color, use_color, RESET};
+use crate::utils::commit::{Commit, Ident};
+use crate::utils::config::{parse_bool, Config};
+use crate::utils::date::format_default;
+use crate::utils::decorate::Decorations;
+use crate::utils::mailmap::Mailmap;
+use crate::utils::notes::{default_notes_ref, Notes, DEFAULT_NOTES_REF};
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::refs::{head_branch, resolve_ref, shorten_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::signature::SignatureCache;
+use crate::utils::walk::RevWalk;
+
+impl CommandArgs for LogArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let config = Config::load(repo)?;
+        let show_signature =
+            self.show_signature || config.get_bool("log.showSignature")?.unwrap_or(false);
+        let mut signatures = if show_signature {
+            Some(SignatureCache::load(repo, &config)?)
+        } else {
+            None
+        };
+        // `--no-use-mailmap` and `--use-mailmap` override `log.mailmap`
+        let use_mailmap = !self.no_use_mailmap
+            && (self.use_mailmap || config.get_bool("log.mailmap")?.unwrap_or(true));
+        let mailmap = if use_mailmap {
+            Mailmap::load(repo)?
+        } else {
+            Mailmap::default()
+        };
+        let notes = if self.no_notes {
+            None
+        } else {
+            Some(Notes::load(repo, &default_notes_ref(&config))?)
+        };
+
+        let when = if self.no_color {
+            Some("never")
+        } else {
+            self.color.as_deref()
+        };
+        let color = use_color(&config, "color.diff", when)?;
+        let (commit_color, reset) = match color {
+            true => (config_color(&config, "color.diff.commit", "yellow")?, RESET),
+            false => (String::new(), ""),
+        };
+        // `--decorate` and `--no-decorate` override `log.decorate`
+        let style = match (self.no_decorate, self.decorate) {
+            (true, _) => DecorateStyle::No,
+            (_, Some(style)) => style,
+            _ => config
+                .get("log.decorate")
+                .map_or(DecorateStyle::Auto, DecorateStyle::parse),
+        };
+        let decorations = match style {
+            DecorateStyle::Short => Some(Decorations::load(repo, &config, false, color)?),
+            DecorateStyle::Full => Some(Decorations::load(repo, &config, true, color)?),
+            DecorateStyle::Auto if std::io::stdout().is_terminal() => {
+                Some(Decorations::load(repo, &config, false, color)?)
+            },
+            DecorateStyle::Auto | DecorateStyle::No => None,
+        };
+
+        let mut walk = RevWalk::new(repo);
+        let revisions = if self.revisions.is_empty() {
+            if resolve_ref(repo, "HEAD")?.is_none() {
+                let branch = head_branch(repo)?.unwrap_or_else(|| "HEAD".to_string());
+                anyhow::bail!(
+                    "your current branch '{}' does not have any commits yet",
+                    shorten_ref(&branch)
+                );
+            }
+            vec!["HEAD".to_string()]
+        } else {
+            self.revisions.clone()
+        };
+
+        // Exclusions have to be known before the walk starts
+        let mut include = Vec::new();
+        for revision in &revisions {
+            if let Some(excluded) = revision.strip_prefix('^') {
+                walk.hide(&resolve_commit(repo, excluded)?)?;
+            } else if let Some((from, to)) = revision.split_once("..") {
+                walk.hide(&resolve_commit(repo, or_head(from))?)?;
+                include.push(resolve_commit(repo, or_head(to))?);
+            } else {
+                include.push(resolve_commit(repo, revision)?);
+            }
+        }
+        for hash in include {
+            walk.push(&hash)?;
+        }
+
+        for (index, entry) in walk.take(self.max_count.unwrap_or(usize::MAX)).enumerate() {
+            let (hash, commit) = entry?;
+
+            let signature = match &mut signatures {
+                Some(cache) => {
+                    let content = read_object_of_type(repo, &hash, ObjectType::Commit)?;
+                    // Verification errors are shown in place of the result
+                    match cache.verify_commit(&hash, &content) {
+                        Ok(check) => check.map(|check| check.output),
+                        Err(err) => Some(format!("error: {err}")),
+                    }
+                },
+                None => None,
+            };
+
+            let decoration = match &decorations {
+                Some(decorations) => decorations.format(&hash),
+                None => String::new(),
+            };
+            if self.oneline {
+                if let Some(signature) = signature {
+                    writeln!(writer, "{signature}")?;
+                }
+                writeln!(
+                    writer,
+                    "{commit_color}{}{reset}{decoration} {}",
+                    &hash[..7],
+                    commit.subject()
+                )?;
+                continue;
+            }
+
+            if index > 0 {
+                writeln!(writer)?;
+            }
+            writeln!(writer, "{commit_color}commit {hash}{reset}{decoration}")?;
+            if let Some(signature) = signature {
+                writeln!(writer, "{signature}")?;
+            }
+            write_commit(&commit, &mailmap, writer)?;
+            if let Some(notes) = &notes {
+                write_notes(repo, notes, &hash, writer)?;
+            }
+        }
+
+        if let Some(cache) = signatures {
+            cache.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An empty side of a `<from>..<to>` range means `HEAD`.
+fn or_head(revision: &str) -> &str {
+    if revision.is_empty() {
+        "HEAD"
+    } else {
+        revision
+    }
+}
+
+/// Write the headers and the indented message of a commit (after the `commit` line),
+/// with the canonical name and email of the author.
+pub(crate) fn write_commit<W>(
+    commit: &Commit,
+    mailmap: &Mailmap,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if commit.parents.len() > 1 {
+        let parents: Vec<_> = commit.parents.iter().map(|parent| &parent[..7]).collect();
+        writeln!(writer, "Merge: {}", parents.join(" "))?;
+    }
+
+    let mut author = Ident::parse(&commit.author)?;
+    mailmap.map_ident(&mut author);
+    writeln!(
+        writer,
+        "Author: {} <{}>",
+        String::from_utf8_lossy(&author.name),
+        String::from_utf8_lossy(&author.email)
+    )?;
+    writeln!(
+        writer,
+        "Date:   {}",
+        format_default(author.timestamp, &author.offset)
+    )?;
+    writeln!(writer)?;
+
+    let message = String::from_utf8_lossy(&commit.message);
+    for line in message.trim_end_matches('\n').lines() {
+        writeln!(writer, "    {line}")?;
+    }
+
+    Ok(())
+}
+
+/// Write the note of an object (if it has one) under a `Notes:` header,
+/// preceded by an empty line.
+pub(crate) fn write_notes<W>(
+    repo: &Repository,
+    notes: &Notes,
+    hash: &str,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let Some(note) = notes.read(repo, hash)? else {
+        return Ok(());
+    };
+
+    // Notes of other refs than the default one are labeled with their ref
+    writeln!(writer)?;
+    match notes.name.as_str() {
+        DEFAULT_NOTES_REF => writeln!(writer, "Notes:")?,
+        name => writeln!(
+            writer,
+            "Notes ({}):",
+            name.strip_prefix("refs/notes/").unwrap_or(name)
+        )?,
+    }
+    let note = String::from_utf8_lossy(&note);
+    for line in note.strip_suffix('\n').unwrap_or(&note).split('\n') {
+        writeln!(writer, "    {line}")?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct LogArgs {
+    /// limit the number of commits to output
+    #[arg(short = 'n', long, value_name = "number")]
+    max_count: Option<usize>,
+    /// show each commit on a single line (abbreviated hash and subject)
+    #[arg(long)]
+    oneline: bool,
+    /// check the signatures of signed commits and show the results
+    #[arg(long)]
+    show_signature: bool,
+    /// show the canonical names and emails of the `.mailmap` file (the default,
+    /// unless `log.mailmap` is false)
+    #[arg(long, visible_alias = "mailmap")]
+    use_mailmap: bool,
+    /// show the names and emails as recorded in the commits
+    #[arg(long, visible_alias = "no-mailmap")]
+    no_use_mailmap: bool,
+    /// do not show the notes of the commits
+    #[arg(long)]
+    no_notes: bool,
+    /// show the names of the refs pointing to the commits (short by default, unless
+    /// `log.decorate` is set)
+    #[arg(
+        long,
+        value_name = "style",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "short",
+        overrides_with = "no_decorate"
+    )]
+    decorate: Option<DecorateStyle>,
+    /// do not show the names of the refs pointing to the commits
+    #[arg(long, overrides_with = "decorate")]
+    no_decorate: bool,
+    /// color the output: always (the default), never, or auto to only color the
+    /// output of terminals
+    #[arg(
+        long,
+        value_name = "when",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "always",
+        overrides_with = "no_color"
+    )]
+    color: Option<String>,
+    /// do not color the output
+    #[arg(long, overrides_with = "color")]
+    no_color: bool,
+    /// the commits to start from (`^<rev>` and `<rev1>..<rev2>` exclude commits)
+    #[arg(value_name = "revision")]
+    revisions: Vec<String>,
+}
+
+/// How the refs pointing to the commits are shown
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DecorateStyle {
+    /// the names of the refs without `refs/heads/`, `refs/tags/` or `refs/remotes/`
+    Short,
+    /// the full names of the refs
+    Full,
+    /// the short names if the output goes to a terminal
+    Auto,
+    /// no names
+    No,
+}
+
+impl DecorateStyle {
+    /// Parse the style set with `log.decorate`: a boolean, `short`, `full` or
+    /// `auto`. Other values show no names.
+    fn parse(value: &str) -> Self {
+        match value {
+            "short" => DecorateStyle::Short,
+            "full" => DecorateStyle::Full,
+            "auto" => DecorateStyle::Auto,
+            value => match parse_bool(value) {
+                Ok(true) => DecorateStyle::Short,
+                _ => DecorateStyle::No,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::notes::write_notes_tree;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::{ssh_keygen, ssh_sign, TempRepo};
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    /// Write a commit on top of `parents`, optionally signed with an SSH key.
+    fn commit(
+        repo: &Repository,
+        parents: &[&str],
+        message: &str,
+        timestamp: i64,
+        key: Option<&std::path::Path>,
+    ) -> String {
+        let mut payload = format!("tree {TREE}\n");
+        for parent in parents {
+            payload.push_str(&format!("parent {parent}\n"));
+        }
+        payload.push_str(&format!(
+            "author A U Thor <author@example.com> {timestamp} +0100\n\
+             committer C O Mitter <committer@example.com> {timestamp} +0100\n\n{message}"
+        ));
+
+        let content = match key {
+            Some(key) => {
+                let signature = ssh_sign(key, payload.as_bytes());
+                let header = format!("gpgsig {}", signature.trim_end().replace('\n', "\n "));
+                payload.replacen("\n\n", &format!("\n{header}\n\n"), 1)
+            },
+            None => payload,
+        };
+
+        write_object(repo, ObjectType::Commit, content.as_bytes()).unwrap()
+    }
+
+    fn log(repo: &Repository, args: LogArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    fn args(revisions: &[&str]) -> LogArgs {
+        LogArgs {
+            max_count: None,
+            oneline: false,
+            show_signature: false,
+            use_mailmap: false,
+            no_use_mailmap: false,
+            no_notes: false,
+            // Neither depends on whether the tests run in a terminal
+            decorate: None,
+            no_decorate: true,
+            color: None,
+            no_color: true,
+            revisions: revisions.iter().map(|rev| rev.to_string()).collect(),
+        }
+    }
+
+    fn oneline(revisions: &[&str]) -> LogArgs {
+        LogArgs {
+            oneline: true,
+            ..args(revisions)
+        }
+    }
+
+    #[test]
+    fn decorates_commits_with_refs() {
+        let (temp, repo) = setup();
+        let first = commit(&repo, &[], "first\n", 1700000000, None);
+        let second = commit(&repo, &[&first], "second\n", 1700000100, None);
+        let tag = format!(
+            "object {first}\ntype commit\ntag v1.0\n\
+             tagger C O Mitter <committer@example.com> 1700000000 +0100\n\nv1.0\n"
+        );
+        let tag = write_object(&repo, ObjectType::Tag, tag.as_bytes()).unwrap();
+        for (name, hash) in [
+            ("refs/heads/main", &second),
+            ("refs/heads/topic", &first),
+            ("refs/remotes/origin/main", &first),
+            ("refs/tags/v0.9", &first),
+            ("refs/tags/v1.0", &tag),
+            ("refs/pull/1/head", &first),
+        ] {
+            write_ref(&repo, name, hash).unwrap();
+        }
+        let decorate = |style| LogArgs {
+            decorate: Some(style),
+            no_decorate: false,
+            ..oneline(&[])
+        };
+
+        assert_eq!(
+            log(&repo, decorate(DecorateStyle::Short)).unwrap(),
+            format!(
+                "{} (HEAD -> main) second\n\
+                 {} (tag: v1.0, tag: v0.9, origin/main, topic) first\n",
+                &second[..7],
+                &first[..7]
+            )
+        );
+        assert_eq!(
+            log(&repo, LogArgs {
+                max_count: Some(1),
+                ..decorate(DecorateStyle::Full)
+            })
+            .unwrap(),
+            format!("{} (HEAD -> refs/heads/main) second\n", &second[..7])
+        );
+
+        // A detached HEAD is shown on its own, `log.decorate` applies without
+        // --decorate, and each kind of ref has its color
+        fs::write(temp.join(".git/HEAD"), format!("{first}\n")).unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            "[log]\n\tdecorate = short\n[color \"decorate\"]\n\tbranch = blue\n",
+        )
+        .unwrap();
+        let repo = temp.repo();
+        assert_eq!(
+            log(&repo, LogArgs {
+                no_decorate: false,
+                color: Some("always".to_string()),
+                no_color: false,
+                ..oneline(&[])
+            })
+            .unwrap(),
+            format!(
+                "\x1b[33m{}\x1b[m\x1b[33m (\x1b[m\x1b[1;36mHEAD\x1b[m\x1b[33m, \x1b[m\
+                 \x1b[1;33mtag: v1.0\x1b[m\x1b[33m, \x1b[m\x1b[1;33mtag: v0.9\x1b[m\
+                 \x1b[33m, \x1b[m\x1b[1;31morigin/main\x1b[m\x1b[33m, \x1b[m\
+                 \x1b[34mtopic\x1b[m\x1b[33m)\x1b[m first\n",
+                &first[..7]
+            )
+        );
+    }
+
+    #[test]
+    fn logs_commits_newest_first() {
+        let (_temp, repo) = setup();
+        let first = commit(&repo, &[], "first\n", 1700000000, None);
+        let second = commit(&repo, &[&first], "second\n\nbody\n", 1700000100, None);
+        write_ref(&repo, "refs/heads/main", &second).unwrap();
+
+        assert_eq!(
+            log(&repo, args(&[])).unwrap(),
+            format!(
+                "commit {second}\n\
+                 Author: A U Thor <author@example.com>\n\
+                 Date:   Tue Nov 14 23:15:00 2023 +0100\n\
+                 \n    second\n    \n    body\n\
+                 \n\
+                 commit {first}\n\
+                 Author: A U Thor <author@example.com>\n\
+                 Date:   Tue Nov 14 23:13:20 2023 +0100\n\
+                 \n    first\n"
+            )
+        );
+    }
+
+    #[test]
+    fn shows_canonical_authors() {
+        let (temp, repo) = setup();
+        let first = commit(&repo, &[], "first\n", 1700000000, None);
+        write_ref(&repo, "refs/heads/main", &first).unwrap();
+        fs::write(
+            temp.join(".mailmap"),
+            "Real Name <real@example.com> <AUTHOR@example.com>\n",
+        )
+        .unwrap();
+
+        let output = log(&repo, args(&[])).unwrap();
+        assert!(
+            output.contains("Author: Real Name <real@example.com>\n"),
+            "{output}"
+        );
+
+        let recorded = LogArgs {
+            no_use_mailmap: true,
+            ..args(&[])
+        };
+        let output = log(&repo, recorded).unwrap();
+        assert!(
+            output.contains("Author: A U Thor <author@example.com>\n"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn shows_notes_after_messages() {
+        let (_temp, repo) = setup();
+        let first = commit(&repo, &[], "first\n", 1700000000, None);
+        let second = commit(&repo, &[&first], "second\n", 1700000100, None);
+        write_ref(&repo, "refs/heads/main", &second).unwrap();
+
+        let note = write_object(&repo, ObjectType::Blob, b"a note\n\nof two paragraphs\n").unwrap();
+        let tree = write_notes_tree(&repo, &[(first.clone(), note)].into()).unwrap();
+        let content = format!(
+            "tree {tree}\nauthor A <a@example.com> 1700000000 +0000\n\
+             committer A <a@example.com> 1700000000 +0000\n\nNotes added by 'git notes add'\n"
+        );
+        let notes = write_object(&repo, ObjectType::Commit, content.as_bytes()).unwrap();
+        write_ref(&repo, "refs/notes/commits", &notes).unwrap();
+
+        let output = log(&repo, args(&[])).unwrap();
+        assert!(
+            output.ends_with("    first\n\nNotes:\n    a note\n    \n    of two paragraphs\n"),
+            "{output}"
+        );
+        let output = log(&repo, LogArgs {
+            no_notes: true,
+            ..args(&[])
+        })
+        .unwrap();
+        assert!(output.ends_with("    first\n"), "{output}");
+    }
+
+    #[test]
+    fn logs_merges_and_ranges() {
+        let (_temp, repo) = setup();
+        let base = commit(&repo, &[], "base\n", 1700000000, None);
+        let side = commit(&repo, &[&base], "side\n", 1700000200, None);
+        let main = commit(&repo, &[&base], "main\n", 1700000100, None);
+        let merge = commit(&repo, &[&main, &side], "merge\n", 1700000300, None);
+        write_ref(&repo, "refs/heads/main", &merge).unwrap();
+        write_ref(&repo, "refs/heads/side", &side).unwrap();
+
+        assert_eq!(
+            log(&repo, oneline(&[])).unwrap(),
+            format!(
+                "{} merge\n{} side\n{} main\n{} base\n",
+                &merge[..7],
+                &side[..7],
+                &main[..7],
+                &base[..7]
+            )
+        );
+        assert_eq!(
+            log(&repo, oneline(&["side..main"])).unwrap(),
+            format!("{} merge\n{} main\n", &merge[..7], &main[..7])
+        );
+        assert_eq!(
+            log(&repo, LogArgs {
+                max_count: Some(1),
+                ..oneline(&["side"])
+            })
+            .unwrap(),
+            format!("{} side\n", &side[..7])
+        );
+
+        let output = log(&repo, LogArgs {
+            max_count: Some(1),
+            ..args(&[])
+        })
+        .unwrap();
+        assert!(output.contains(&format!("\nMerge: {} {}\n", &main[..7], &side[..7])));
+    }
+
+    fn signed_head() -> LogArgs {
+        LogArgs {
+            max_count: Some(1),
+            show_signature: true,
+            ..args(&[])
+        }
+    }
+
+    #[test]
+    fn shows_and_caches_signatures() {
+        let (temp, repo) = setup();
+        let (key, public_key) = ssh_keygen(temp.path());
+        fs::write(
+            temp.join("allowed_signers"),
+            format!("a@example.com {public_key}\n"),
+        )
+        .unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            format!(
+                "[gpg \"ssh\"]\n\tallowedSignersFile = {}\n",
+                temp.join("allowed_signers").display()
+            ),
+        )
+        .unwrap();
+
+        let unsigned = commit(&repo, &[], "unsigned\n", 1700000000, None);
+        let signed = commit(&repo, &[&unsigned], "signed\n", 1700000100, Some(&key));
+        write_ref(&repo, "refs/heads/main", &signed).unwrap();
+
+        let output = log(&repo, LogArgs {
+            show_signature: true,
+            ..oneline(&[])
+        })
+        .unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Good \"git\" signature for a@example.com"));
+        assert_eq!(lines[1], format!("{} signed", &signed[..7]));
+        assert_eq!(lines[2], format!("{} unsigned", &unsigned[..7]));
+
+        // Only signed commits are cached, and cached results are reused
+        let cache = fs::read_to_string(temp.join(".git/signature-cache")).unwrap();
+        assert_eq!(cache.lines().count(), 2);
+        let cache = cache.replace(lines[0], "cached result");
+        fs::write(temp.join(".git/signature-cache"), cache).unwrap();
+        let output = log(&repo, signed_head()).unwrap();
+        assert!(output.starts_with(&format!("commit {signed}\ncached result\nAuthor:")));
+
+        // Changing the allowed signers invalidates the cache
+        fs::write(temp.join("allowed_signers"), "").unwrap();
+        let output = log(&repo, signed_head()).unwrap();
+        assert!(output.contains("\nNo principal matched.\nAuthor:"));
+    }
+
+    #[test]
+    fn does_not_cache_gpg_signatures() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (temp, repo) = setup();
+        let gpg = temp.join("gpg");
+        let script = |status: &str, message: &str| {
+            fs::write(
+                &gpg,
+                format!(
+                    "#!/bin/sh\ncat >/dev/null\necho '[GNUPG:] {status} 0123456789ABCDEF A U Thor'\n\
+                     echo '{message}' >&2\n"
+                ),
+            )
+            .unwrap();
+            fs::set_permissions(&gpg, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        fs::write(
+            temp.join(".git/config"),
+            format!("[gpg]\n\tprogram = {}\n", gpg.display()),
+        )
+        .unwrap();
+
+        let payload = format!(
+            "tree {TREE}\n\
+             author A U Thor <author@example.com> 1700000000 +0100\n\
+             committer C O Mitter <committer@example.com> 1700000000 +0100\n\
+             gpgsig -----BEGIN PGP SIGNATURE-----\n \n signature\n -----END PGP SIGNATURE-----\n\
+             \nsigned\n"
+        );
+        let signed = write_object(&repo, ObjectType::Commit, payload.as_bytes()).unwrap();
+        write_ref(&repo, "refs/heads/main", &signed).unwrap();
+
+        script("GOODSIG", "Good signature from A U Thor");
+        let output = log(&repo, signed_head()).unwrap();
+        assert!(output.contains("\nGood signature from A U Thor\nAuthor:"));
+        assert!(!temp.join(".git/signature-cache").exists());
+
+        // The keyring may change between commands (e.g. a key being revoked)
+        script("REVKEYSIG", "Signature made with a revoked key");
+        let output = log(&repo, signed_head()).unwrap();
+        assert!(output.contains("\nSignature made with a revoked key\nAuthor:"));
+    }
+
+    #[test]
+    fn fails_on_unborn_branch() {
+        let (_temp, repo) = setup();
+
+        let result = log(&repo, args(&[]));
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "your current branch 'main' does not have any commits yet"
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::ignore::Ignore;
+use crate::utils::index::{Index, IndexEntry};
+use crate::utils::pathspec::Pathspec;
+use crate::utils::quote::{quote_path, quotes_non_ascii};
+use crate::utils::repository::Repository;
+use crate::utils::submodule::open_submodule;
+use crate::utils::tree::MODE_GITLINK;
+use crate::utils::work_tree::list_files;
+
+impl CommandArgs for LsFilesArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.recurse_submodules && (self.others || self.modified || self.resolve_undo) {
+            anyhow::bail!("ls-files --recurse-submodules unsupported mode");
+        }
+
+        let index = Index::load(repo)?;
+        let work_tree = repo.work_tree()?;
+        let pathspec = Pathspec::new(&repo.prefix()?, &self.paths)?;
+        let format = PathFormat {
+            null_terminated: self.null_terminated,
+            quote_non_ascii: quotes_non_ascii(repo)?,
+        };
+
+        // Cached files are shown by default
+        let show_cached =
+            self.cached || !(self.others || self.modified || self.stage || self.resolve_undo);
+
+        // Untracked files are listed before the tracked ones
+        if self.others {
+            let mut ignore = if self.exclude_standard {
+                Some(Ignore::load(repo)?)
+            } else {
+                None
+            };
+            for file in list_files(&work_tree, ignore.as_mut())? {
+                if index.contains(&file.path) || !pathspec.matches(&file.path) {
+                    continue;
+                }
+
+                let mut path = pathspec.relative(&file.path);
+                // Nested repositories are shown as directories
+                if file.is_repository() {
+                    path.push(b'/');
+                }
+                format.write(&path, writer)?;
+            }
+        }
+
+        for entry in index.entries() {
+            // Initialized submodules are listed by the files they track
+            if self.recurse_submodules && entry.mode == MODE_GITLINK {
+                if let Some(submodule) = open_submodule(repo, &entry.path)? {
+                    if pathspec.matches_within(&entry.path) {
+                        self.write_submodule(&submodule, &entry.path, &pathspec, &format, writer)?;
+                    }
+                    continue;
+                }
+            }
+
+            if !pathspec.matches(&entry.path) {
+                continue;
+            }
+
+            if show_cached || self.stage {
+                self.write_entry(entry, &pathspec, &format, writer)?;
+            }
+
+            if self.modified && index.is_modified(entry, &work_tree)? {
+                self.write_entry(entry, &pathspec, &format, writer)?;
+            }
+        }
+
+        if self.resolve_undo {
+            for (path, stages) in index.resolve_undo().iter() {
+                if !pathspec.matches(path) {
+                    continue;
+                }
+
+                for (stage, resolved) in (1..).zip(stages) {
+                    if let Some((mode, hash)) = resolved {
+                        write!(writer, "{mode:06o} {hash} {stage}\t")?;
+                        format.write(&pathspec.relative(path), writer)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl LsFilesArgs {
+    /// Write an index entry, with its mode, hash and stage if `--stage` is given.
+    fn write_entry<W>(
+        &self,
+        entry: &IndexEntry,
+        pathspec: &Pathspec,
+        format: &PathFormat,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.stage {
+            write!(
+                writer,
+                "{:06o} {} {}\t",
+                entry.mode, entry.hash, entry.stage
+            )?;
+        }
+
+        format.write(&pathspec.relative(&entry.path), writer)?;
+        Ok(())
+    }
+
+    /// Write the entries of a submodule, recursing into its own submodules.
+    ///
+    /// # Arguments
+    ///
+    /// * `submodule` - The repository of the submodule
+    /// * `path` - The path of the submodule relative to the root of the working tree,
+    ///   which its entries are shown under
+    fn write_submodule<W>(
+        &self,
+        submodule: &Repository,
+        path: &[u8],
+        pathspec: &Pathspec,
+        format: &PathFormat,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let index = Index::load(submodule)?;
+
+        for entry in index.entries() {
+            let mut full_path = path.to_vec();
+            full_path.push(b'/');
+            full_path.extend(&entry.path);
+            if entry.mode == MODE_GITLINK {
+                if let Some(nested) = open_submodule(submodule, &entry.path)? {
+                    if pathspec.matches_within(&full_path) {
+                        self.write_submodule(&nested, &full_path, pathspec, format, writer)?;
+                    }
+                    continue;
+                }
+            }
+
+            if pathspec.matches(&full_path) {
+                let entry = IndexEntry {
+                    path: full_path,
+                    ..entry.clone()
+                };
+                self.write_entry(&entry, pathspec, format, writer)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How the paths are written
+struct PathFormat {
+    /// Whether paths are terminated with NUL and written as they are,
+    /// rather than terminated with newlines and quoted if needed
+    null_terminated: bool,
+    /// Whether non-ASCII bytes are quoted (`core.quotePath`)
+    quote_non_ascii: bool,
+}
+
+impl PathFormat {
+    fn write<W>(&self, path: &[u8], writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        if self.null_terminated {
+            writer.write_all(path)?;
+            writer.write_all(b"\0")
+        } else {
+            writer.write_all(&quote_path(path, self.quote_non_ascii))?;
+            writer.write_all(b"\n")
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct LsFilesArgs {
+    /// show cached files in the output (default)
+    #[arg(short, long)]
+    cached: bool,
+    /// show other (untracked) files in the output
+    #[arg(short, long)]
+    others: bool,
+    /// leave out the files ignored by .gitignore, info/exclude and core.excludesFile
+    #[arg(long)]
+    exclude_standard: bool,
+    /// show modified files in the output
+    #[arg(short, long)]
+    modified: bool,
+    /// show staged contents' mode bits, object name and stage number in the output
+    #[arg(short, long)]
+    stage: bool,
+    /// show the conflicting stages recorded for resolved paths
+    #[arg(long)]
+    resolve_undo: bool,
+    /// separate paths with NUL instead of newline
+    #[arg(short = 'z')]
+    null_terminated: bool,
+    /// recurse through the initialized submodules
+    #[arg(long)]
+    recurse_submodules: bool,
+    /// the files to show
+    #[arg(value_name = "file")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{hash_object, ObjectType};
+    use crate::utils::test::{index_file, TempRepo};
+
+    /// Create a repository tracking `a.txt`, `dir/b.txt` and a conflicted `dir/c.txt`.
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        fs::create_dir_all(temp.join("dir")).unwrap();
+        fs::write(temp.join("a.txt"), "a").unwrap();
+        fs::write(temp.join("dir/b.txt"), "b").unwrap();
+        fs::write(temp.join("dir/c.txt"), "c").unwrap();
+        fs::write(temp.join("untracked.txt"), "").unwrap();
+
+        let a = hash_object(&ObjectType::Blob, b"a");
+        let b = hash_object(&ObjectType::Blob, b"b");
+        fs::write(
+            temp.join(".git/index"),
+            index_file(2, &[
+                ("a.txt", &a, 0),
+                ("dir/b.txt", &b, 0),
+                ("dir/c.txt", &a, 2),
+                ("dir/c.txt", &b, 3),
+            ]),
+        )
+        .unwrap();
+
+        let repo = temp.repo();
+        (temp, repo, a)
+    }
+
+    fn ls_files(repo: &Repository, args: LsFilesArgs) -> String {
+        let mut output = Vec::new();
+        args.run(repo, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn args() -> LsFilesArgs {
+        LsFilesArgs {
+            cached: false,
+            others: false,
+            exclude_standard: false,
+            modified: false,
+            stage: false,
+            resolve_undo: false,
+            null_terminated: false,
+            recurse_submodules: false,
+            paths: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn lists_cached_files() {
+        let (_temp, repo, _) = setup();
+        assert_eq!(
+            ls_files(&repo, args()),
+            "a.txt\ndir/b.txt\ndir/c.txt\ndir/c.txt\n"
+        );
+    }
+
+    #[test]
+    fn lists_staged_entries() {
+        let (_temp, repo, a) = setup();
+        let output = ls_files(&repo, LsFilesArgs {
+            stage: true,
+            ..args()
+        });
+
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], format!("100644 {a} 0\ta.txt"));
+        assert!(lines[2].ends_with(" 2\tdir/c.txt"));
+        assert!(lines[3].ends_with(" 3\tdir/c.txt"));
+    }
+
+    #[test]
+    fn quotes_paths() {
+        let (temp, repo, a) = setup();
+        let mut index = Index::load(&repo).unwrap();
+        index.add_entry(IndexEntry::new(b"caf\xe9".to_vec(), 0o100644, a));
+        index.write(&repo).unwrap();
+
+        assert_eq!(
+            ls_files(&repo, args()),
+            "a.txt\n\"caf\\351\"\ndir/b.txt\ndir/c.txt\ndir/c.txt\n"
+        );
+        let repo = temp.repo();
+        let mut output = Vec::new();
+        LsFilesArgs {
+            null_terminated: true,
+            ..args()
+        }
+        .run(&repo, &mut output)
+        .unwrap();
+        assert!(output.starts_with(b"a.txt\0caf\xe9\0"));
+
+        fs::write(temp.join(".git/config"), "[core]\n\tquotePath = false\n").unwrap();
+        let repo = temp.repo();
+        let mut output = Vec::new();
+        args().run(&repo, &mut output).unwrap();
+        assert!(output.starts_with(b"a.txt\ncaf\xe9\n"));
+    }
+
+    #[test]
+    fn lists_other_files() {
+        let (temp, repo, _) = setup();
+        let output = ls_files(&repo, LsFilesArgs {
+            others: true,
+            null_terminated: true,
+            ..args()
+        });
+        assert_eq!(output, "untracked.txt\0");
+
+        fs::write(temp.join(".gitignore"), "untracked.*\n").unwrap();
+        let output = ls_files(&repo, LsFilesArgs {
+            others: true,
+            exclude_standard: true,
+            ..args()
+        });
+        assert_eq!(output, ".gitignore\n");
+    }
+
+    #[test]
+    fn lists_modified_files() {
+        let (temp, repo, _) = setup();
+        fs::write(temp.join("a.txt"), "changed").unwrap();
+        fs::remove_file(temp.join("dir/b.txt")).unwrap();
+
+        let output = ls_files(&repo, LsFilesArgs {
+            modified: true,
+            paths: vec![PathBuf::from("a.txt"), PathBuf::from("dir/b.txt")],
+            ..args()
+        });
+        assert_eq!(output, "a.txt\ndir/b.txt\n");
+    }
+
+    #[test]
+    fn lists_resolved_conflicts() {
+        let (_temp, repo, a) = setup();
+        let mut index = Index::load(&repo).unwrap();
+        index.add_entry(IndexEntry::new("dir/c.txt".into(), 0o100644, a.clone()));
+        index.write(&repo).unwrap();
+
+        let output = ls_files(&repo, LsFilesArgs {
+            resolve_undo: true,
+            ..args()
+        });
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], format!("100644 {a} 2\tdir/c.txt"));
+        assert!(lines[1].ends_with(" 3\tdir/c.txt"));
+    }
+
+    #[test]
+    fn lists_files_of_submodules() {
+        let (temp, repo, a) = setup();
+        fs::create_dir_all(temp.join("sub/.git")).unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        index.add_entry(IndexEntry::new("sub".into(), MODE_GITLINK, a.clone()));
+        index.write(&repo).unwrap();
+
+        let submodule = open_submodule(&repo, b"sub").unwrap().unwrap();
+        let mut sub_index = Index::default();
+        sub_index.add_entry(IndexEntry::new("x.txt".into(), 0o100644, a.clone()));
+        sub_index.write(&submodule).unwrap();
+
+        let sub = vec![PathBuf::from("sub")];
+        assert_eq!(
+            ls_files(&repo, LsFilesArgs {
+                paths: sub.clone(),
+                ..args()
+            }),
+            "sub\n"
+        );
+        let output = ls_files(&repo, LsFilesArgs {
+            recurse_submodules: true,
+            stage: true,
+            paths: sub,
+            ..args()
+        });
+        assert_eq!(output, format!("100644 {a} 0\tsub/x.txt\n"));
+    }
+
+    #[test]
+    fn lists_files_relative_to_current_dir() {
+        let (temp, ..) = setup();
+        let repo = temp.repo_at("dir");
+
+        assert_eq!(ls_files(&repo, args()), "b.txt\nc.txt\nc.txt\n");
+
+        let output = ls_files(&repo, LsFilesArgs {
+            paths: vec![PathBuf::from("../a.txt")],
+            ..args()
+        });
+        assert_eq!(output, "../a.txt\n");
+    }
+}
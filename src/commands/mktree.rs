@@ -0,0 +1,196 @@
+use std::io::{BufRead, Write};
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::objects::ObjectType;
+use crate::utils::quote::unquote_c_style;
+use crate::utils::repository::Repository;
+use crate::utils::tree::{TreeBuilder, TreeEntry};
+
+impl CommandArgs for MktreeArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        make_trees(repo, &self, std::io::stdin().lock(), writer)
+    }
+}
+
+/// Write the trees listed on the input (in the format of `ls-tree`, in any
+/// order), printing the hash of each one.
+fn make_trees<R, W>(
+    repo: &Repository,
+    args: &MktreeArgs,
+    mut reader: R,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let terminator = if args.null_terminated { 0 } else { b'\n' };
+    let mut builder = TreeBuilder::new();
+    let mut written = 0;
+
+    loop {
+        let mut line = Vec::new();
+        if reader.read_until(terminator, &mut line)? == 0 {
+            break;
+        }
+        if line.last() == Some(&terminator) {
+            line.pop();
+        }
+
+        // In batch mode, an empty line ends each tree
+        if line.is_empty() {
+            if !args.batch {
+                anyhow::bail!("input format error: (blank line only valid in batch mode)");
+            }
+            writeln!(writer, "{}", builder.write(repo, args.missing)?)?;
+            writer.flush()?;
+            builder = TreeBuilder::new();
+            written += 1;
+            continue;
+        }
+
+        let entry = parse_entry(&line, !args.null_terminated)?;
+        builder.insert(entry.mode, &entry.name, &entry.hash)?;
+    }
+
+    if !builder.is_empty() || written == 0 {
+        writeln!(writer, "{}", builder.write(repo, args.missing)?)?;
+    }
+    Ok(())
+}
+
+/// Parse an entry in the format of `ls-tree` (`<mode> SP <type> SP <hash> TAB <path>`),
+/// checking that its type matches its mode.
+fn parse_entry(line: &[u8], quoted: bool) -> anyhow::Result<TreeEntry> {
+    let format_error = || anyhow::anyhow!("input format error: {}", String::from_utf8_lossy(line));
+
+    let tab = line
+        .iter()
+        .position(|&b| b == b'\t')
+        .ok_or_else(format_error)?;
+    let (info, path) = (&line[..tab], &line[tab + 1..]);
+    let mut fields = info.split(|&b| b == b' ');
+    let (Some(mode), Some(object_type), Some(hash), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(format_error());
+    };
+
+    let mode = std::str::from_utf8(mode)
+        .ok()
+        .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+        .ok_or_else(format_error)?;
+    let object_type = ObjectType::try_from(object_type).map_err(|_| format_error())?;
+    let hash = String::from_utf8(hash.to_vec()).map_err(|_| format_error())?;
+    let name = match path.first() {
+        Some(b'"') if quoted => unquote_c_style(path)?,
+        _ => path.to_vec(),
+    };
+
+    let entry = TreeEntry { mode, name, hash };
+    if entry.object_type() != object_type {
+        anyhow::bail!(
+            "entry '{}' object type ({object_type}) doesn't match mode type ({})",
+            String::from_utf8_lossy(&entry.name),
+            entry.object_type()
+        );
+    }
+    Ok(entry)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct MktreeArgs {
+    /// read entries terminated by NUL instead of newline, with unquoted paths
+    #[arg(short = 'z')]
+    null_terminated: bool,
+    /// allow the objects of the entries to be missing from the object database
+    #[arg(long)]
+    missing: bool,
+    /// write a tree for each group of entries ended by an empty line
+    #[arg(long)]
+    batch: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::write_object;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::{read_tree, EMPTY_TREE};
+
+    #[test]
+    fn makes_trees_from_unsorted_entries() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        let blob = write_object(&repo, ObjectType::Blob, b"hi\n").unwrap();
+        let missing = "1111111111111111111111111111111111111111";
+
+        let make = |input: String, missing: bool, batch: bool| {
+            let args = MktreeArgs {
+                null_terminated: false,
+                missing,
+                batch,
+            };
+            let mut output = Vec::new();
+            make_trees(&repo, &args, input.as_bytes(), &mut output)
+                .map(|()| String::from_utf8(output).unwrap())
+        };
+
+        // Directories are sorted as if their name ended with a slash
+        let input = format!(
+            "100644 blob {blob}\tz\n040000 tree {EMPTY_TREE}\ta\n100644 blob {blob}\ta.b\n"
+        );
+        let tree = make(input, false, false).unwrap();
+        assert_eq!(tree, "16f481af2ad3a49ae69972df7f0b5389b3c88532\n");
+        let names: Vec<_> = read_tree(&repo, tree.trim())
+            .unwrap()
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).unwrap())
+            .collect();
+        assert_eq!(names, ["a.b", "a", "z"]);
+
+        // Missing objects are only allowed with --missing
+        let input = format!("100644 blob {missing}\tz\n");
+        let err = make(input.clone(), false, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("entry 'z' object {missing} is unavailable")
+        );
+        let tree = make(input, true, false).unwrap();
+        assert_eq!(tree, "2fd93a324d28ec4351d9a84f89ad334f0694c751\n");
+
+        let err = make(format!("040000 tree {blob}\tz\n"), true, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("entry 'z' object {blob} is a blob but specified type was (tree)")
+        );
+        let err = make(format!("100644 tree {blob}\tz\n"), false, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "entry 'z' object type (tree) doesn't match mode type (blob)"
+        );
+        let err = make(format!("100644 blob {blob}\ta/b\n"), false, false).unwrap_err();
+        assert_eq!(err.to_string(), "path a/b contains slash");
+
+        // Each group of entries is a tree in batch mode
+        let input = format!("100644 blob {blob}\tz\n\n100644 blob {blob}\ty\n");
+        let trees = make(input, false, true).unwrap();
+        assert_eq!(
+            trees,
+            "db991f7ce73f47e96a0403d36b1c504fbc1e50f6\n\
+             3a8e5aa856bdd7d474009e940781dfea167ab15f\n"
+        );
+        assert_eq!(
+            make(String::new(), false, false).unwrap(),
+            format!("{EMPTY_TREE}\n")
+        );
+    }
+}
@@ -1,21 +1,146 @@
+use std::fmt;
 use std::io::Write;
 
 use clap::Subcommand;
 
+use crate::utils::repository::Repository;
+
+mod add;
+mod apply;
+mod archive;
+mod bisect;
+mod blame;
+mod branch;
+mod bundle;
 mod cat_file;
+mod check_attr;
+mod check_ignore;
+mod checkout;
+mod cherry_pick;
+mod clone;
+mod commit;
+mod commit_graph;
+mod count_objects;
+mod credential;
+mod daemon;
+mod describe;
+mod fast_export;
+mod fast_import;
+mod for_each_ref;
+mod fsck;
+mod gc;
+mod grep;
 mod hash_object;
 mod init;
+mod lfs;
+mod log;
+mod ls_files;
+mod mktree;
+mod mv;
+mod notes;
+mod pack_refs;
+mod prune;
+mod read_tree;
+mod rebase;
+mod receive_pack;
+mod reflog;
+mod replace;
+mod rerere;
+mod restore;
+mod rev_parse;
+mod revert;
+mod rewrite_history;
+mod rm;
+mod serve_http;
+mod shortlog;
+mod show;
+mod show_branch;
 mod show_ref;
+mod sparse_checkout;
+mod stash;
+mod status;
+mod submodule;
+mod subtree;
+mod switch;
+mod tag;
+mod update_index;
+mod upload_pack;
+mod verify_commit;
+mod verify_pack;
+mod verify_tag;
+mod worktree;
+mod write_tree;
 
 impl Command {
-    pub fn run(self) -> anyhow::Result<()> {
+    pub fn run(self, repo: &Repository) -> anyhow::Result<()> {
         let mut stdout = std::io::stdout();
 
         match self {
-            Command::HashObject(args) => args.run(&mut stdout),
-            Command::Init(args) => args.run(&mut stdout),
-            Command::CatFile(args) => args.run(&mut stdout),
-            Command::ShowRef(args) => args.run(&mut stdout),
+            Command::HashObject(args) => args.run(repo, &mut stdout),
+            Command::Init(args) => args.run(repo, &mut stdout),
+            Command::CatFile(args) => args.run(repo, &mut stdout),
+            Command::ShowRef(args) => args.run(repo, &mut stdout),
+            Command::Worktree(args) => args.run(repo, &mut stdout),
+            Command::LsFiles(args) => args.run(repo, &mut stdout),
+            Command::Log(args) => args.run(repo, &mut stdout),
+            Command::VerifyCommit(args) => args.run(repo, &mut stdout),
+            Command::VerifyTag(args) => args.run(repo, &mut stdout),
+            Command::Add(args) => args.run(repo, &mut stdout),
+            Command::Status(args) => args.run(repo, &mut stdout),
+            Command::WriteTree(args) => args.run(repo, &mut stdout),
+            Command::Branch(args) => args.run(repo, &mut stdout),
+            Command::Tag(args) => args.run(repo, &mut stdout),
+            Command::UpdateIndex(args) => args.run(repo, &mut stdout),
+            Command::ReadTree(args) => args.run(repo, &mut stdout),
+            Command::Subtree(args) => args.run(repo, &mut stdout),
+            Command::Rm(args) => args.run(repo, &mut stdout),
+            Command::Mv(args) => args.run(repo, &mut stdout),
+            Command::Stash(args) => args.run(repo, &mut stdout),
+            Command::Reflog(args) => args.run(repo, &mut stdout),
+            Command::Commit(args) => args.run(repo, &mut stdout),
+            Command::Show(args) => args.run(repo, &mut stdout),
+            Command::CheckIgnore(args) => args.run(repo, &mut stdout),
+            Command::Describe(args) => args.run(repo, &mut stdout),
+            Command::Archive(args) => args.run(repo, &mut stdout),
+            Command::Bundle(args) => args.run(repo, &mut stdout),
+            Command::Blame(args) => args.run(repo, &mut stdout),
+            Command::RevParse(args) => args.run(repo, &mut stdout),
+            Command::Grep(args) => args.run(repo, &mut stdout),
+            Command::ForEachRef(args) => args.run(repo, &mut stdout),
+            Command::CheckAttr(args) => args.run(repo, &mut stdout),
+            Command::Switch(args) => args.run(repo, &mut stdout),
+            Command::Restore(args) => args.run(repo, &mut stdout),
+            Command::Checkout(args) => args.run(repo, &mut stdout),
+            Command::Lfs(args) => args.run(repo, &mut stdout),
+            Command::CherryPick(args) => args.run(repo, &mut stdout),
+            Command::Revert(args) => args.run(repo, &mut stdout),
+            Command::Rebase(args) => args.run(repo, &mut stdout),
+            Command::ShowBranch(args) => args.run(repo, &mut stdout),
+            Command::Apply(args) => args.run(repo, &mut stdout),
+            Command::RewriteHistory(args) => args.run(repo, &mut stdout),
+            Command::Shortlog(args) => args.run(repo, &mut stdout),
+            Command::CountObjects(args) => args.run(repo, &mut stdout),
+            Command::Fsck(args) => args.run(repo, &mut stdout),
+            Command::Notes(args) => args.run(repo, &mut stdout),
+            Command::Replace(args) => args.run(repo, &mut stdout),
+            Command::Prune(args) => args.run(repo, &mut stdout),
+            Command::Clone(args) => args.run(repo, &mut stdout),
+            Command::CommitGraph(args) => args.run(repo, &mut stdout),
+            Command::PackRefs(args) => args.run(repo, &mut stdout),
+            Command::UploadPack(args) => args.run(repo, &mut stdout),
+            Command::Gc(args) => args.run(repo, &mut stdout),
+            Command::ReceivePack(args) => args.run(repo, &mut stdout),
+            Command::ServeHttp(args) => args.run(repo, &mut stdout),
+            Command::Daemon(args) => args.run(repo, &mut stdout),
+            Command::Credential(args) => args.run(repo, &mut stdout),
+            Command::FastExport(args) => args.run(repo, &mut stdout),
+            Command::FastImport(args) => args.run(repo, &mut stdout),
+            Command::VerifyPack(args) => args.run(repo, &mut stdout),
+            Command::Bisect(args) => args.run(repo, &mut stdout),
+            Command::Mktree(args) => args.run(repo, &mut stdout),
+            Command::SparseCheckout(args) => args.run(repo, &mut stdout),
+            Command::Submodule(args) => args.run(repo, &mut stdout),
+            Command::Rerere(args) => args.run(repo, &mut stdout),
         }
     }
 }
@@ -26,10 +151,85 @@ pub(crate) enum Command {
     Init(init::InitArgs),
     CatFile(cat_file::CatFileArgs),
     ShowRef(show_ref::ShowRefArgs),
+    Worktree(worktree::WorktreeArgs),
+    LsFiles(ls_files::LsFilesArgs),
+    Log(log::LogArgs),
+    VerifyCommit(verify_commit::VerifyCommitArgs),
+    VerifyTag(verify_tag::VerifyTagArgs),
+    Add(add::AddArgs),
+    Status(status::StatusArgs),
+    WriteTree(write_tree::WriteTreeArgs),
+    Branch(branch::BranchArgs),
+    Tag(tag::TagArgs),
+    UpdateIndex(update_index::UpdateIndexArgs),
+    ReadTree(read_tree::ReadTreeArgs),
+    Subtree(subtree::SubtreeArgs),
+    Rm(rm::RmArgs),
+    Mv(mv::MvArgs),
+    Stash(stash::StashArgs),
+    Reflog(reflog::ReflogArgs),
+    Commit(commit::CommitArgs),
+    Show(show::ShowArgs),
+    CheckIgnore(check_ignore::CheckIgnoreArgs),
+    Describe(describe::DescribeArgs),
+    Archive(archive::ArchiveArgs),
+    Bundle(bundle::BundleArgs),
+    Blame(blame::BlameArgs),
+    RevParse(rev_parse::RevParseArgs),
+    Grep(grep::GrepArgs),
+    ForEachRef(for_each_ref::ForEachRefArgs),
+    CheckAttr(check_attr::CheckAttrArgs),
+    Switch(switch::SwitchArgs),
+    Restore(restore::RestoreArgs),
+    Checkout(checkout::CheckoutArgs),
+    Lfs(lfs::LfsArgs),
+    CherryPick(cherry_pick::CherryPickArgs),
+    Revert(revert::RevertArgs),
+    Rebase(rebase::RebaseArgs),
+    ShowBranch(show_branch::ShowBranchArgs),
+    Apply(apply::ApplyArgs),
+    #[command(alias = "rewrite")]
+    RewriteHistory(rewrite_history::RewriteHistoryArgs),
+    Shortlog(shortlog::ShortlogArgs),
+    CountObjects(count_objects::CountObjectsArgs),
+    Fsck(fsck::FsckArgs),
+    Notes(notes::NotesArgs),
+    Replace(replace::ReplaceArgs),
+    Prune(prune::PruneArgs),
+    Clone(clone::CloneArgs),
+    CommitGraph(commit_graph::CommitGraphArgs),
+    PackRefs(pack_refs::PackRefsArgs),
+    UploadPack(upload_pack::UploadPackArgs),
+    Gc(gc::GcArgs),
+    ReceivePack(receive_pack::ReceivePackArgs),
+    ServeHttp(serve_http::ServeHttpArgs),
+    Daemon(daemon::DaemonArgs),
+    Credential(credential::CredentialArgs),
+    FastExport(fast_export::FastExportArgs),
+    FastImport(fast_import::FastImportArgs),
+    VerifyPack(verify_pack::VerifyPackArgs),
+    Bisect(bisect::BisectArgs),
+    Mktree(mktree::MktreeArgs),
+    SparseCheckout(sparse_checkout::SparseCheckoutArgs),
+    Submodule(submodule::SubmoduleArgs),
+    Rerere(rerere::RerereArgs),
 }
 
 pub(crate) trait CommandArgs {
-    fn run<W>(self, writer: &mut W) -> anyhow::Result<()>
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
     where
         W: Write;
 }
+
+/// An error ending a command with an exit code, without any message
+/// (e.g. `rev-parse --verify --quiet` for a revision that does not exist)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SilentExit(pub(crate) i32);
+
+impl fmt::Display for SilentExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "exit status {}", self.0)
+    }
+}
+
+impl std::error::Error for SilentExit {}
@@ -0,0 +1,349 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::index::Index;
+use crate::utils::pathspec::{is_within, normalize};
+use crate::utils::repository::Repository;
+
+/// A file or directory to move, with the index entries moving along with it
+struct Rename {
+    source: String,
+    destination: String,
+    /// The paths of the moved index entries and their new paths
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl CommandArgs for MvArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut index = Index::load(repo)?;
+        let work_tree = repo.work_tree()?;
+        let prefix = repo.prefix()?;
+
+        let Some((destination, sources)) = self.paths.split_last() else {
+            anyhow::bail!("usage: git mv [<options>] <source>... <destination>");
+        };
+        let destination = normalize(&prefix, destination)?;
+        let sources = sources
+            .iter()
+            .map(|source| normalize(&prefix, source))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Sources are moved into an existing directory, keeping their names
+        let into_directory = work_tree.join(&destination).is_dir();
+        if !into_directory && sources.len() > 1 {
+            anyhow::bail!("destination '{destination}' is not a directory");
+        }
+
+        let mut renames: Vec<Rename> = Vec::new();
+        for source in sources {
+            let destination = if into_directory {
+                let name = source.rsplit('/').next().unwrap_or(&source);
+                format!("{destination}/{name}")
+                    .trim_start_matches('/')
+                    .to_string()
+            } else {
+                destination.clone()
+            };
+            if self.dry_run {
+                writeln!(writer, "Checking rename of '{source}' to '{destination}'")?;
+            }
+
+            match self.check_rename(&index, &work_tree, &renames, &source, &destination) {
+                Ok(entries) => renames.push(Rename {
+                    source,
+                    destination,
+                    entries,
+                }),
+                Err(_) if self.skip_errors => continue,
+                Err(err) => {
+                    anyhow::bail!("{err}, source={source}, destination={destination}")
+                },
+            }
+        }
+
+        for rename in &renames {
+            if self.verbose || self.dry_run {
+                writeln!(
+                    writer,
+                    "Renaming {} to {}",
+                    rename.source, rename.destination
+                )?;
+            }
+            if self.dry_run {
+                continue;
+            }
+
+            let destination = work_tree.join(&rename.destination);
+            if let Err(err) = std::fs::rename(work_tree.join(&rename.source), &destination) {
+                if self.skip_errors {
+                    continue;
+                }
+                anyhow::bail!("renaming '{}' failed: {err}", rename.source);
+            }
+
+            for (old_path, new_path) in &rename.entries {
+                let Some(mut entry) = index.entry(old_path, 0).cloned() else {
+                    continue;
+                };
+                index.remove_entry(old_path);
+                // The file is unchanged, so its cached metadata still applies
+                entry.path = new_path.clone();
+                index.add_entry(entry);
+            }
+        }
+
+        if !self.dry_run && !renames.is_empty() {
+            index.write(repo)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl MvArgs {
+    /// Check whether a file or directory can be moved.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index the entries are moved in
+    /// * `work_tree` - The root of the working tree
+    /// * `renames` - The renames already accepted
+    /// * `source` - The path to move
+    /// * `destination` - The path to move it to
+    ///
+    /// # Returns
+    ///
+    /// The paths of the index entries to move and their new paths,
+    /// or the reason the path cannot be moved
+    fn check_rename(
+        &self,
+        index: &Index,
+        work_tree: &Path,
+        renames: &[Rename],
+        source: &str,
+        destination: &str,
+    ) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let Ok(metadata) = work_tree.join(source).symlink_metadata() else {
+            anyhow::bail!("bad source");
+        };
+        if is_within(destination, source) {
+            anyhow::bail!("can not move directory into itself");
+        }
+
+        let destination_exists = work_tree.join(destination).symlink_metadata().is_ok();
+        let entries = if metadata.is_dir() && !index.contains(source.as_bytes()) {
+            if destination_exists {
+                anyhow::bail!("cannot move directory over file");
+            }
+
+            let entries: Vec<_> = index
+                .entries()
+                .iter()
+                .filter(|entry| is_within(&entry.path, source))
+                .map(|entry| {
+                    let rest = &entry.path[source.len()..];
+                    (entry.path.clone(), [destination.as_bytes(), rest].concat())
+                })
+                .collect();
+            if entries.is_empty() {
+                anyhow::bail!("source directory is empty");
+            }
+            if let Some((path, _)) = entries
+                .iter()
+                .find(|(path, _)| index.entry(path, 0).is_none())
+            {
+                anyhow::bail!("conflicted ({})", String::from_utf8_lossy(path));
+            }
+            entries
+        } else {
+            if !index.contains(source.as_bytes()) {
+                anyhow::bail!("not under version control");
+            }
+            if index.entry(source.as_bytes(), 0).is_none() {
+                anyhow::bail!("conflicted");
+            }
+            if destination_exists && !self.force {
+                anyhow::bail!("destination exists");
+            }
+            vec![(source.as_bytes().to_vec(), destination.as_bytes().to_vec())]
+        };
+
+        if renames
+            .iter()
+            .any(|rename| rename.destination == destination)
+        {
+            anyhow::bail!("multiple sources for the same target");
+        }
+
+        Ok(entries)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct MvArgs {
+    /// force move/rename even if target exists
+    #[arg(short, long)]
+    force: bool,
+    /// skip move/rename errors
+    #[arg(short = 'k')]
+    skip_errors: bool,
+    /// dry run
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// be verbose
+    #[arg(short, long)]
+    verbose: bool,
+    /// the files or directories to move, followed by the destination
+    #[arg(value_name = "source", num_args = 2.., required = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::create_dir_all(temp.join("dir")).unwrap();
+        fs::create_dir_all(temp.join("other")).unwrap();
+        fs::write(temp.join("a.txt"), "a").unwrap();
+        fs::write(temp.join("untracked.txt"), "untracked").unwrap();
+        fs::write(temp.join("dir/b.txt"), "b").unwrap();
+        fs::write(temp.join("dir/c.txt"), "c").unwrap();
+        let repo = temp.repo();
+
+        let mut index = Index::default();
+        for path in ["a.txt", "dir/b.txt", "dir/c.txt"] {
+            let file = temp.join(path);
+            let metadata = fs::symlink_metadata(&file).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, &fs::read(&file).unwrap()).unwrap();
+            index.add_entry(IndexEntry::from_metadata(path.into(), hash, &metadata));
+        }
+        index.write(&repo).unwrap();
+
+        (temp, repo)
+    }
+
+    fn args(paths: &[&str]) -> MvArgs {
+        MvArgs {
+            force: false,
+            skip_errors: false,
+            dry_run: false,
+            verbose: true,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn mv(repo: &Repository, args: MvArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn paths(repo: &Repository) -> Vec<String> {
+        let index = Index::load(repo).unwrap();
+        index
+            .entries()
+            .iter()
+            .map(|e| String::from_utf8(e.path.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn moves_files_and_directories() {
+        let (temp, repo) = setup();
+
+        assert_eq!(
+            mv(&repo, args(&["a.txt", "z.txt"])).unwrap(),
+            "Renaming a.txt to z.txt\n"
+        );
+        assert!(!temp.join("a.txt").exists());
+        assert_eq!(fs::read_to_string(temp.join("z.txt")).unwrap(), "a");
+
+        mv(&repo, args(&["dir", "other"])).unwrap();
+        assert!(!temp.join("dir").exists());
+        assert_eq!(paths(&repo), vec![
+            "other/dir/b.txt",
+            "other/dir/c.txt",
+            "z.txt"
+        ]);
+
+        // The moved entries are still up to date
+        let index = Index::load(&repo).unwrap();
+        let entry = index.entry(b"z.txt", 0).unwrap();
+        assert!(!index.is_modified(entry, temp.path()).unwrap());
+    }
+
+    #[test]
+    fn refuses_bad_renames() {
+        let (temp, repo) = setup();
+
+        let err = mv(&repo, args(&["missing", "b"])).unwrap_err();
+        assert_eq!(err.to_string(), "bad source, source=missing, destination=b");
+        let err = mv(&repo, args(&["untracked.txt", "b"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "not under version control, source=untracked.txt, destination=b"
+        );
+        let err = mv(&repo, args(&["a.txt", "untracked.txt"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "destination exists, source=a.txt, destination=untracked.txt"
+        );
+        let err = mv(&repo, args(&["dir", "dir/sub"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "can not move directory into itself, source=dir, destination=dir/sub"
+        );
+        assert_eq!(paths(&repo), vec!["a.txt", "dir/b.txt", "dir/c.txt"]);
+
+        // Errors can be skipped, and existing files overwritten by force
+        let output = mv(&repo, MvArgs {
+            skip_errors: true,
+            ..args(&["missing", "a.txt", "other"])
+        })
+        .unwrap();
+        assert_eq!(output, "Renaming a.txt to other/a.txt\n");
+        mv(&repo, MvArgs {
+            force: true,
+            ..args(&["other/a.txt", "untracked.txt"])
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(temp.join("untracked.txt")).unwrap(), "a");
+        assert_eq!(paths(&repo), vec![
+            "dir/b.txt",
+            "dir/c.txt",
+            "untracked.txt"
+        ]);
+    }
+
+    #[test]
+    fn does_not_move_on_dry_run() {
+        let (temp, repo) = setup();
+        let output = mv(&repo, MvArgs {
+            dry_run: true,
+            verbose: false,
+            ..args(&["a.txt", "z.txt"])
+        })
+        .unwrap();
+
+        assert_eq!(
+            output,
+            "Checking rename of 'a.txt' to 'z.txt'\nRenaming a.txt to z.txt\n"
+        );
+        assert!(temp.join("a.txt").exists());
+        assert_eq!(paths(&repo), vec!["a.txt", "dir/b.txt", "dir/c.txt"]);
+    }
+}
@@ -0,0 +1,721 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{Args, Subcommand, ValueEnum};
+
+use crate::commands::commit::{cleanup_message, read_message_file};
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::commit::read_commit;
+use crate::utils::config::Config;
+use crate::utils::display_path;
+use crate::utils::editor::launch_editor;
+use crate::utils::merge::merge_content;
+use crate::utils::notes::{default_notes_ref, expand_notes_ref, read_notes, Notes};
+use crate::utils::objects::{read_object_of_type, write_object, ObjectType};
+use crate::utils::refs::{
+    delete_ref, resolve_ref, resolve_symref, update_ref, write_ref, write_symref,
+};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
+use crate::utils::walk::merge_bases;
+
+/// The commit of the notes merged so far, while conflicts are being resolved
+const MERGE_PARTIAL: &str = "NOTES_MERGE_PARTIAL";
+/// The notes ref being merged into
+const MERGE_REF: &str = "NOTES_MERGE_REF";
+/// The directory with the conflicting notes, named after their objects
+const MERGE_WORKTREE: &str = "NOTES_MERGE_WORKTREE";
+
+impl CommandArgs for NotesArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let name = match &self.reference {
+            Some(name) => expand_notes_ref(name),
+            None => default_notes_ref(&Config::load(repo)?),
+        };
+
+        // Without a subcommand, every note is listed
+        match self
+            .command
+            .unwrap_or(NotesCommand::List(ObjectArgs { object: None }))
+        {
+            NotesCommand::Add(args) => add(repo, &name, args),
+            NotesCommand::Show(args) => {
+                let notes = Notes::load(repo, &name)?;
+                let object = resolve_object(repo, args.object.as_deref())?;
+                match notes.read(repo, &object)? {
+                    Some(note) => writer.write_all(&note)?,
+                    None => anyhow::bail!("no note found for object {object}."),
+                }
+                Ok(())
+            },
+            NotesCommand::List(args) => {
+                let notes = Notes::load(repo, &name)?;
+                match args.object {
+                    Some(object) => {
+                        let object = resolve_object(repo, Some(&object))?;
+                        match notes.notes.get(&object) {
+                            Some(blob) => writeln!(writer, "{blob}")?,
+                            None => anyhow::bail!("no note found for object {object}."),
+                        }
+                    },
+                    None => {
+                        for (object, blob) in &notes.notes {
+                            writeln!(writer, "{blob} {object}")?;
+                        }
+                    },
+                }
+                Ok(())
+            },
+            NotesCommand::Remove(args) => remove(repo, &name, args),
+            NotesCommand::Merge(args) => merge(repo, &name, args, writer),
+        }
+    }
+}
+
+/// Resolve the object a note is attached to (HEAD by default).
+fn resolve_object(repo: &Repository, object: Option<&str>) -> anyhow::Result<String> {
+    let object = object.unwrap_or("HEAD");
+    resolve_revision(repo, object)
+        .map_err(|_| anyhow::anyhow!("failed to resolve '{object}' as a valid ref."))
+}
+
+/// Attach a note to an object, replacing its note with `--force`.
+/// An empty message removes the note instead.
+fn add(repo: &Repository, name: &str, args: AddArgs) -> anyhow::Result<()> {
+    let mut notes = Notes::load(repo, name)?;
+    let object = resolve_object(repo, args.object.as_deref())?;
+    let existing = notes.read(repo, &object)?;
+    if existing.is_some() && !args.force {
+        anyhow::bail!(
+            "Cannot add notes. Found existing notes for object {object}. Use '-f' to overwrite \
+             existing notes"
+        );
+    }
+
+    let message = match (&args.file, args.message.is_empty()) {
+        (Some(path), _) => cleanup_message(&read_message_file(path)?),
+        (None, false) => cleanup_message(&args.message.join("\n\n")),
+        (None, true) => edit_note(repo, &object, existing.as_deref())?,
+    };
+
+    let message = if message.is_empty() {
+        eprintln!("Removing note for object {object}");
+        if notes.notes.remove(&object).is_none() {
+            return Ok(());
+        }
+        "Notes removed by 'git notes add'"
+    } else {
+        if existing.is_some() {
+            eprintln!("Overwriting existing notes for object {object}");
+        }
+        let blob = write_object(repo, ObjectType::Blob, message.as_bytes())?;
+        notes.notes.insert(object, blob);
+        "Notes added by 'git notes add'"
+    };
+    notes.commit(repo, Vec::new(), message)?;
+    Ok(())
+}
+
+/// Let the user write the note of an object in the editor, starting from its current note.
+///
+/// # Returns
+///
+/// The cleaned up note, without the comment lines
+fn edit_note(repo: &Repository, object: &str, existing: Option<&[u8]>) -> anyhow::Result<String> {
+    let path = repo.git_dir()?.join("NOTES_EDITMSG");
+    let mut template = String::from_utf8_lossy(existing.unwrap_or_default()).into_owned();
+    template.push_str(&format!(
+        "\n#\n# Write/edit the notes for the following object:\n#\n# {object}\n"
+    ));
+    std::fs::write(&path, template).context("write NOTES_EDITMSG")?;
+    launch_editor(repo, &path)?;
+
+    let message = std::fs::read_to_string(&path).context("read NOTES_EDITMSG")?;
+    let lines: Vec<&str> = message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    Ok(cleanup_message(&lines.join("\n")))
+}
+
+/// Remove the notes of objects, failing for the objects without one
+/// (unless `--ignore-missing` is given), in which case none is removed.
+fn remove(repo: &Repository, name: &str, args: RemoveArgs) -> anyhow::Result<()> {
+    let mut notes = Notes::load(repo, name)?;
+    let objects = if args.objects.is_empty() {
+        vec!["HEAD".to_string()]
+    } else {
+        args.objects
+    };
+
+    let mut missing = false;
+    let mut removed = false;
+    for object in &objects {
+        let hash = resolve_object(repo, Some(object))?;
+        if notes.notes.remove(&hash).is_some() {
+            eprintln!("Removing note for object {object}");
+            removed = true;
+        } else {
+            eprintln!("Object {object} has no note");
+            missing |= !args.ignore_missing;
+        }
+    }
+
+    if missing {
+        return Err(SilentExit(1).into());
+    }
+    if removed {
+        notes.commit(repo, Vec::new(), "Notes removed by 'git notes remove'")?;
+    }
+    Ok(())
+}
+
+/// Merge the notes of another notes ref into the notes ref. Notes changed on both
+/// sides since their merge base are resolved with the strategy, or left in
+/// `NOTES_MERGE_WORKTREE` for the user to resolve with the `manual` strategy.
+fn merge<W>(repo: &Repository, name: &str, args: MergeArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if args.commit {
+        return commit_merge(repo);
+    }
+    if args.abort {
+        return abort_merge(repo);
+    }
+
+    let git_dir = repo.git_dir()?;
+    if git_dir.join(MERGE_PARTIAL).exists() || git_dir.join(MERGE_WORKTREE).exists() {
+        anyhow::bail!(
+            "You have not concluded your previous notes merge (.git/NOTES_MERGE_* exists).\n\
+             Please, use 'git notes merge --commit' or 'git notes merge --abort' to \
+             commit/abort the previous merge before you start a new notes merge."
+        );
+    }
+
+    let remote_name = expand_notes_ref(args.reference.as_deref().unwrap_or_default());
+    let mut local = Notes::load(repo, name)?;
+    let remote = Notes::load(repo, &remote_name)?;
+    let message = format!("Merged notes from {remote_name} into {name}");
+    let verbose = !args.quiet;
+
+    let (local_commit, remote_commit) = match (&local.commit, &remote.commit) {
+        (Some(local_commit), Some(remote_commit)) => (local_commit.clone(), remote_commit),
+        (None, Some(remote_commit)) => {
+            return update_ref(repo, name, remote_commit, &format!("notes: {message}"));
+        },
+        (Some(_), None) => return Ok(()),
+        (None, None) => anyhow::bail!(
+            "Cannot merge empty notes ref ({remote_name}) into empty notes ref ({name})"
+        ),
+    };
+
+    let base = merge_bases(repo, &local_commit, remote_commit)?
+        .into_iter()
+        .next();
+    if base.as_ref() == Some(remote_commit) {
+        if verbose {
+            writeln!(writer, "Already up to date.")?;
+        }
+        return Ok(());
+    }
+    if base.as_ref() == Some(&local_commit) {
+        if verbose {
+            writeln!(writer, "Fast-forward")?;
+        }
+        return update_ref(repo, name, remote_commit, &format!("notes: {message}"));
+    }
+
+    let base_notes = match &base {
+        Some(base) => read_notes(repo, base)?,
+        None => BTreeMap::new(),
+    };
+    let objects: BTreeSet<String> = base_notes
+        .keys()
+        .chain(local.notes.keys())
+        .chain(remote.notes.keys())
+        .cloned()
+        .collect();
+
+    let strategy = args.strategy.unwrap_or_default();
+    let mut conflicts = Vec::new();
+    for object in objects {
+        let base_note = base_notes.get(&object).cloned();
+        let local_note = local.notes.get(&object).cloned();
+        let remote_note = remote.notes.get(&object).cloned();
+        if remote_note == base_note || remote_note == local_note {
+            continue;
+        }
+
+        let merged = if local_note == base_note {
+            remote_note
+        } else {
+            match strategy {
+                Strategy::Manual => {
+                    conflicts.push((object, base_note, local_note, remote_note));
+                    continue;
+                },
+                Strategy::Ours => {
+                    if verbose {
+                        writeln!(writer, "Using local notes for {object}")?;
+                    }
+                    local_note
+                },
+                Strategy::Theirs => {
+                    if verbose {
+                        writeln!(writer, "Using remote notes for {object}")?;
+                    }
+                    remote_note
+                },
+                Strategy::Union | Strategy::CatSortUniq => {
+                    if verbose && strategy == Strategy::Union {
+                        writeln!(writer, "Concatenating local and remote notes for {object}")?;
+                    } else if verbose {
+                        writeln!(
+                            writer,
+                            "Concatenating unique lines in local and remote notes for {object}"
+                        )?;
+                    }
+                    combine_notes(repo, strategy, local_note, remote_note)?
+                },
+            }
+        };
+
+        match merged {
+            Some(note) => local.notes.insert(object, note),
+            None => local.notes.remove(&object),
+        };
+    }
+
+    if conflicts.is_empty() {
+        local.commit(repo, vec![remote_commit.clone()], &message)?;
+        return Ok(());
+    }
+
+    // The merged notes are kept aside until the conflicts are resolved
+    let worktree = git_dir.join(MERGE_WORKTREE);
+    std::fs::create_dir_all(&worktree).context(format!("create {}", worktree.display()))?;
+    for (object, base_note, local_note, remote_note) in conflicts {
+        local.notes.remove(&object);
+        let read = |note: &Option<String>| match note {
+            Some(blob) => read_object_of_type(repo, blob, ObjectType::Blob),
+            None => Ok(Vec::new()),
+        };
+
+        let content = match (&local_note, &remote_note) {
+            (None, _) | (_, None) => {
+                let (deleted, modified) = if local_note.is_none() {
+                    (name, remote_name.as_str())
+                } else {
+                    (remote_name.as_str(), name)
+                };
+                writeln!(
+                    writer,
+                    "CONFLICT (delete/modify): Notes for object {object} deleted in {deleted} \
+                     and modified in {modified}. Version from {modified} left in tree."
+                )?;
+                read(
+                    if local_note.is_none() {
+                        &remote_note
+                    } else {
+                        &local_note
+                    },
+                )?
+            },
+            (Some(_), Some(_)) => {
+                writeln!(writer, "Auto-merging notes for {object}")?;
+                let merged = merge_content(
+                    &read(&base_note)?,
+                    &read(&local_note)?,
+                    &read(&remote_note)?,
+                    (name, &remote_name),
+                );
+                if merged.conflicts > 0 {
+                    let reason = if base_note.is_some() {
+                        "content"
+                    } else {
+                        "add/add"
+                    };
+                    writeln!(
+                        writer,
+                        "CONFLICT ({reason}): Merge conflict in notes for object {object}"
+                    )?;
+                }
+                merged.content
+            },
+        };
+        let path = worktree.join(&object);
+        std::fs::write(&path, content).context(format!("write {}", path.display()))?;
+    }
+
+    let partial = local.commit_tree(repo, vec![remote_commit.clone()], &message)?;
+    write_ref(repo, MERGE_PARTIAL, &partial)?;
+    write_symref(repo, MERGE_REF, name)?;
+
+    eprintln!(
+        "Automatic notes merge failed. Fix conflicts in {} and commit the result with 'git \
+         notes merge --commit', or abort the merge with 'git notes merge --abort'.",
+        display_path(repo, &worktree)
+    );
+    Err(SilentExit(1).into())
+}
+
+/// Combine the notes of both sides of a merge, the note of one side being
+/// kept as it is if the other side deleted it.
+///
+/// # Returns
+///
+/// The hash of the combined note
+fn combine_notes(
+    repo: &Repository,
+    strategy: Strategy,
+    local: Option<String>,
+    remote: Option<String>,
+) -> anyhow::Result<Option<String>> {
+    let (Some(local), Some(remote)) = (&local, &remote) else {
+        return Ok(local.or(remote));
+    };
+    let local = read_object_of_type(repo, local, ObjectType::Blob)?;
+    let remote = read_object_of_type(repo, remote, ObjectType::Blob)?;
+
+    let combined = if strategy == Strategy::Union {
+        // The notes are separated by an empty line
+        let mut combined = local.strip_suffix(b"\n").unwrap_or(&local).to_vec();
+        combined.extend(b"\n\n");
+        combined.extend(&remote);
+        combined
+    } else {
+        let lines: BTreeSet<&[u8]> = local
+            .split(|&b| b == b'\n')
+            .chain(remote.split(|&b| b == b'\n'))
+            .filter(|line| !line.is_empty())
+            .collect();
+        lines
+            .into_iter()
+            .flat_map(|line| [line, b"\n"])
+            .flatten()
+            .copied()
+            .collect()
+    };
+    Ok(Some(write_object(repo, ObjectType::Blob, &combined)?))
+}
+
+/// Commit the notes of a merge whose conflicts were resolved in `NOTES_MERGE_WORKTREE`.
+fn commit_merge(repo: &Repository) -> anyhow::Result<()> {
+    let Some(partial) = resolve_ref(repo, MERGE_PARTIAL)? else {
+        anyhow::bail!("failed to read ref {MERGE_PARTIAL}");
+    };
+    let name = resolve_symref(repo, MERGE_REF)?.name;
+    if name == MERGE_REF {
+        anyhow::bail!("failed to resolve {MERGE_REF}");
+    }
+
+    let commit = read_commit(repo, &partial)?;
+    if resolve_ref(repo, &name)? != commit.parents.first().cloned() {
+        anyhow::bail!("Notes ref {name} has moved since the notes merge started");
+    }
+
+    let mut notes = Notes {
+        name,
+        commit: None,
+        notes: read_notes(repo, &partial)?,
+    };
+    let worktree = repo.git_dir()?.join(MERGE_WORKTREE);
+    for (object, path) in resolved_notes(&worktree)? {
+        let content = std::fs::read(&path).context(format!("read {}", path.display()))?;
+        notes
+            .notes
+            .insert(object, write_object(repo, ObjectType::Blob, &content)?);
+    }
+
+    let message = String::from_utf8_lossy(&commit.message);
+    notes.commit(repo, commit.parents, message.trim_end())?;
+    abort_merge(repo)
+}
+
+/// The notes of the objects in `NOTES_MERGE_WORKTREE`, by object.
+fn resolved_notes(worktree: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let Ok(entries) = std::fs::read_dir(worktree) else {
+        return Ok(Vec::new());
+    };
+    let mut notes = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        notes.push((
+            entry.file_name().to_string_lossy().into_owned(),
+            entry.path(),
+        ));
+    }
+    Ok(notes)
+}
+
+/// Forget about the merge in progress, with the resolved conflicts.
+fn abort_merge(repo: &Repository) -> anyhow::Result<()> {
+    let worktree = repo.git_dir()?.join(MERGE_WORKTREE);
+    if worktree.exists() {
+        std::fs::remove_dir_all(&worktree).context(format!("remove {}", worktree.display()))?;
+    }
+    delete_ref(repo, MERGE_PARTIAL)?;
+    delete_ref(repo, MERGE_REF)?;
+    Ok(())
+}
+
+/// How notes changed on both sides of a merge are resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum Strategy {
+    /// leave the conflicts in `NOTES_MERGE_WORKTREE` to be resolved by the user
+    #[default]
+    Manual,
+    /// keep the local note
+    Ours,
+    /// keep the note of the merged notes ref
+    Theirs,
+    /// concatenate the notes
+    Union,
+    /// concatenate the notes, sorting their lines and removing duplicates
+    #[value(name = "cat_sort_uniq")]
+    CatSortUniq,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct NotesArgs {
+    /// the notes ref to use (`core.notesRef` or `refs/notes/commits` by default)
+    #[arg(long = "ref", value_name = "ref")]
+    reference: Option<String>,
+    #[command(subcommand)]
+    command: Option<NotesCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum NotesCommand {
+    /// attach a note to an object
+    Add(AddArgs),
+    /// show the note of an object
+    Show(ObjectArgs),
+    /// list the notes and the objects they are attached to (the default)
+    List(ObjectArgs),
+    /// remove the notes of objects
+    Remove(RemoveArgs),
+    /// merge the notes of another notes ref
+    Merge(MergeArgs),
+}
+
+#[derive(Args, Debug)]
+struct AddArgs {
+    /// use the given message as the note (multiple messages are separate paragraphs)
+    #[arg(short, long = "message", value_name = "message")]
+    message: Vec<String>,
+    /// read the note from a file ("-" for standard input)
+    #[arg(short = 'F', long, value_name = "file", conflicts_with = "message")]
+    file: Option<PathBuf>,
+    /// replace the note of an object that already has one
+    #[arg(short, long)]
+    force: bool,
+    /// the object to attach the note to (HEAD by default)
+    object: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ObjectArgs {
+    /// the object to show the note of (HEAD by default)
+    object: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct RemoveArgs {
+    /// do not fail for objects without a note
+    #[arg(long)]
+    ignore_missing: bool,
+    /// the objects to remove the notes of (HEAD by default)
+    objects: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct MergeArgs {
+    /// resolve the notes changed on both sides with the given strategy
+    #[arg(short, long)]
+    strategy: Option<Strategy>,
+    /// do not report how the notes were merged
+    #[arg(short, long)]
+    quiet: bool,
+    /// commit the notes of a merge whose conflicts were resolved
+    #[arg(long, conflicts_with_all = ["abort", "reference", "strategy"])]
+    commit: bool,
+    /// abort the merge in progress
+    #[arg(long, conflicts_with_all = ["reference", "strategy"])]
+    abort: bool,
+    /// the notes ref to merge
+    #[arg(
+        value_name = "notes-ref",
+        required_unless_present_any = ["commit", "abort"]
+    )]
+    reference: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::write_symref;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        let content = format!(
+            "tree {EMPTY_TREE}\nauthor A <a@example.com> 1700000000 +0000\n\
+             committer A <a@example.com> 1700000000 +0000\n\nfirst\n"
+        );
+        let head = write_object(&repo, ObjectType::Commit, content.as_bytes()).unwrap();
+        write_ref(&repo, "refs/heads/main", &head).unwrap();
+        (temp, repo, head)
+    }
+
+    fn notes(
+        repo: &Repository,
+        reference: Option<&str>,
+        command: NotesCommand,
+    ) -> anyhow::Result<String> {
+        let args = NotesArgs {
+            reference: reference.map(str::to_string),
+            command: Some(command),
+        };
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn add(
+        repo: &Repository,
+        reference: Option<&str>,
+        message: &str,
+        force: bool,
+    ) -> anyhow::Result<String> {
+        let args = AddArgs {
+            message: vec![message.to_string()],
+            file: None,
+            force,
+            object: None,
+        };
+        notes(repo, reference, NotesCommand::Add(args))
+    }
+
+    fn show(repo: &Repository) -> anyhow::Result<String> {
+        notes(repo, None, NotesCommand::Show(ObjectArgs { object: None }))
+    }
+
+    fn merge(repo: &Repository, strategy: Option<Strategy>) -> anyhow::Result<String> {
+        let args = MergeArgs {
+            strategy,
+            quiet: false,
+            commit: false,
+            abort: false,
+            reference: Some("other".to_string()),
+        };
+        notes(repo, None, NotesCommand::Merge(args))
+    }
+
+    #[test]
+    fn adds_shows_and_removes_notes() {
+        let (_temp, repo, head) = setup();
+
+        add(&repo, None, "hello\n\n\nworld  ", false).unwrap();
+        assert_eq!(show(&repo).unwrap(), "hello\n\nworld\n");
+        let err = add(&repo, None, "again", false).unwrap_err();
+        assert!(err.to_string().starts_with("Cannot add notes."), "{err}");
+        add(&repo, None, "again", true).unwrap();
+
+        let blob = write_object(&repo, ObjectType::Blob, b"again\n").unwrap();
+        let list = NotesCommand::List(ObjectArgs { object: None });
+        assert_eq!(
+            notes(&repo, None, list).unwrap(),
+            format!("{blob} {head}\n")
+        );
+        let commit = read_commit(
+            &repo,
+            &resolve_ref(&repo, "refs/notes/commits").unwrap().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(commit.message, b"Notes added by 'git notes add'\n");
+        assert_eq!(commit.parents.len(), 1);
+
+        let remove = |ignore_missing| RemoveArgs {
+            ignore_missing,
+            objects: Vec::new(),
+        };
+        notes(&repo, None, NotesCommand::Remove(remove(false))).unwrap();
+        let err = show(&repo).unwrap_err();
+        assert_eq!(err.to_string(), format!("no note found for object {head}."));
+        let err = notes(&repo, None, NotesCommand::Remove(remove(false))).unwrap_err();
+        assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+        notes(&repo, None, NotesCommand::Remove(remove(true))).unwrap();
+    }
+
+    #[test]
+    fn merges_notes() {
+        let (temp, repo, _) = setup();
+        add(&repo, Some("other"), "base", false).unwrap();
+
+        // The empty ref is fast-forwarded, then both sides change the note
+        assert_eq!(merge(&repo, None).unwrap(), "");
+        assert_eq!(show(&repo).unwrap(), "base\n");
+        add(&repo, None, "local\nshared", true).unwrap();
+        add(&repo, Some("other"), "remote\nshared", true).unwrap();
+        let local = resolve_ref(&repo, "refs/notes/commits").unwrap();
+
+        merge(&repo, Some(Strategy::Union)).unwrap();
+        assert_eq!(show(&repo).unwrap(), "local\nshared\n\nremote\nshared\n");
+        write_ref(&repo, "refs/notes/commits", local.as_deref().unwrap()).unwrap();
+        merge(&repo, Some(Strategy::CatSortUniq)).unwrap();
+        assert_eq!(show(&repo).unwrap(), "local\nremote\nshared\n");
+        assert_eq!(merge(&repo, None).unwrap(), "Already up to date.\n");
+
+        // Conflicts are resolved by the user
+        write_ref(&repo, "refs/notes/commits", local.as_deref().unwrap()).unwrap();
+        let err = merge(&repo, None).unwrap_err();
+        assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+        assert!(merge(&repo, None).is_err());
+        let worktree = fs::read_dir(temp.join(".git/NOTES_MERGE_WORKTREE")).unwrap();
+        let path = worktree.into_iter().next().unwrap().unwrap().path();
+        fs::write(path, "resolved\n").unwrap();
+
+        let args = MergeArgs {
+            strategy: None,
+            quiet: false,
+            commit: true,
+            abort: false,
+            reference: None,
+        };
+        notes(&repo, None, NotesCommand::Merge(args)).unwrap();
+        assert_eq!(show(&repo).unwrap(), "resolved\n");
+        let commit = read_commit(
+            &repo,
+            &resolve_ref(&repo, "refs/notes/commits").unwrap().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(commit.parents[0], local.unwrap());
+        assert_eq!(
+            commit.message,
+            b"Merged notes from refs/notes/other into refs/notes/commits\n"
+        );
+        assert!(!temp.join(".git/NOTES_MERGE_PARTIAL").exists());
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::refs::pack_refs;
+use crate::utils::repository::Repository;
+
+impl CommandArgs for PackRefsArgs {
+    fn run<W>(self, repo: &Repository, _writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        pack_refs(repo, self.all, !self.no_prune)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct PackRefsArgs {
+    /// pack every ref, not only the tags
+    #[arg(long)]
+    all: bool,
+    /// remove the loose refs once packed (the default)
+    #[arg(long, overrides_with = "no_prune")]
+    prune: bool,
+    /// keep the loose refs once packed
+    #[arg(long, overrides_with = "prune")]
+    no_prune: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{delete_ref, list_refs, resolve_ref, write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        let commit = create_commit(&repo, EMPTY_TREE, Vec::new(), "root\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        write_ref(&repo, "refs/heads/topic/one", &commit).unwrap();
+        (temp, repo, commit)
+    }
+
+    fn args() -> PackRefsArgs {
+        PackRefsArgs {
+            all: false,
+            prune: false,
+            no_prune: false,
+        }
+    }
+
+    #[test]
+    fn packs_tags_with_peeled_values() {
+        let (temp, repo, commit) = setup();
+        let tag = write_object(
+            &repo,
+            ObjectType::Tag,
+            format!("object {commit}\ntype commit\ntag v1\ntagger a <a@b> 0 +0000\n\nv1\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        write_ref(&repo, "refs/tags/v1", &tag).unwrap();
+        write_ref(&repo, "refs/tags/light", &commit).unwrap();
+
+        args().run(&repo, &mut Vec::new()).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join(".git/packed-refs")).unwrap(),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n{commit} refs/tags/light\n\
+                 {tag} refs/tags/v1\n^{commit}\n"
+            )
+        );
+        // Only the tags are packed by default, and their directory is kept
+        assert!(!temp.join(".git/refs/tags/v1").exists());
+        assert!(temp.join(".git/refs/tags").is_dir());
+        assert!(temp.join(".git/refs/heads/main").exists());
+        assert_eq!(resolve_ref(&repo, "refs/tags/v1").unwrap(), Some(tag));
+
+        // Deleting a ref rewrites the file under its lock
+        fs::write(temp.join(".git/packed-refs.lock"), "").unwrap();
+        assert!(delete_ref(&repo, "refs/tags/light")
+            .unwrap_err()
+            .to_string()
+            .ends_with("packed-refs.lock': File exists."));
+        fs::remove_file(temp.join(".git/packed-refs.lock")).unwrap();
+        assert!(delete_ref(&repo, "refs/tags/light").unwrap());
+        assert!(!fs::read_to_string(temp.join(".git/packed-refs"))
+            .unwrap()
+            .contains("light"));
+    }
+
+    #[test]
+    fn packs_and_prunes_all_refs() {
+        let (temp, repo, commit) = setup();
+        write_ref(&repo, "refs/bisect/bad", &commit).unwrap();
+        write_ref(&repo, "refs/heads/missing", &"1".repeat(40)).unwrap();
+        let refs = list_refs(&repo, "refs/").unwrap();
+
+        let keep = PackRefsArgs {
+            all: true,
+            no_prune: true,
+            ..args()
+        };
+        keep.run(&repo, &mut Vec::new()).unwrap();
+        assert!(temp.join(".git/refs/heads/main").exists());
+
+        // A loose ref locked by another command is kept
+        fs::write(temp.join(".git/refs/heads/main.lock"), "").unwrap();
+        let prune = PackRefsArgs {
+            all: true,
+            ..args()
+        };
+        prune.run(&repo, &mut Vec::new()).unwrap();
+        assert!(temp.join(".git/refs/heads/main").exists());
+        assert!(!temp.join(".git/refs/heads/topic").exists());
+        assert!(temp.join(".git/refs/heads/missing").exists());
+        assert!(temp.join(".git/refs/bisect/bad").exists());
+        assert_eq!(list_refs(&repo, "refs/").unwrap(), refs);
+        assert_eq!(
+            fs::read_to_string(temp.join(".git/packed-refs")).unwrap(),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n{commit} refs/heads/main\n\
+                 {commit} refs/heads/topic/one\n"
+            )
+        );
+    }
+}
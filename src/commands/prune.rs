@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::{worktree, CommandArgs};
+use crate::utils::commit::{Commit, Tag};
+use crate::utils::index::Index;
+use crate::utils::objects::{hash_object, list_loose_files, read_original_object, ObjectType};
+use crate::utils::pack::read_pack;
+use crate::utils::reflog::{read_reflog, NULL_HASH};
+use crate::utils::refs::list_refs;
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::{remove_leftovers, STALE_AFTER};
+use crate::utils::tree::{parse_tree, EMPTY_TREE, MODE_GITLINK};
+
+impl CommandArgs for PruneArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        // Other repositories may borrow any of the objects
+        if repo.has_precious_objects()? {
+            anyhow::bail!("cannot prune in a precious-objects repo");
+        }
+
+        let reachable = reachable_objects(repo)?;
+        let mut fanouts = HashSet::new();
+        for (path, hash) in list_loose_files(repo)? {
+            // Other files are left to the removal of temporary files below
+            let Some(hash) = hash else {
+                continue;
+            };
+            if reachable.contains(&hash) {
+                continue;
+            }
+
+            if self.dry_run || self.verbose {
+                let object_type = read_original_object(repo, &hash)?.0;
+                writeln!(writer, "{hash} {object_type}")?;
+            }
+            if !self.dry_run {
+                std::fs::remove_file(&path).context(format!("remove {}", path.display()))?;
+                fanouts.extend(path.parent().map(|dir| dir.to_path_buf()));
+            }
+        }
+
+        // Locks and objects left behind by interrupted commands
+        remove_leftovers(repo, SystemTime::now() - STALE_AFTER, self.dry_run, writer)?;
+
+        // Fan-out directories left empty are removed as well
+        for dir in fanouts {
+            if dir
+                .read_dir()
+                .is_ok_and(|mut entries| entries.next().is_none())
+            {
+                std::fs::remove_dir(&dir).context(format!("remove {}", dir.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collect every object reachable from the refs, the `HEAD` and index of each
+/// worktree, and the entries of the reflogs.
+fn reachable_objects(repo: &Repository) -> anyhow::Result<HashSet<String>> {
+    let mut roots: Vec<String> = Vec::new();
+    let refs = list_refs(repo, "refs/")?;
+    for (name, hash) in &refs {
+        roots.push(hash.clone());
+        for entry in read_reflog(repo, name)? {
+            roots.extend([entry.old, entry.new]);
+        }
+    }
+    for entry in read_reflog(repo, "HEAD")? {
+        roots.extend([entry.old, entry.new]);
+    }
+    for worktree in worktree::list(repo)? {
+        roots.extend(worktree.head.clone());
+        if worktree.bare {
+            continue;
+        }
+        let index = Index::load(&worktree.repository(repo)?)?;
+        for entry in index.entries() {
+            if entry.mode != MODE_GITLINK {
+                roots.push(entry.hash.clone());
+            }
+        }
+    }
+
+    // Objects only reachable through packed objects must be kept as well
+    let mut packed = HashMap::new();
+    if let Ok(entries) = std::fs::read_dir(repo.object_dir(true)?.join("pack")) {
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "pack") {
+                let pack = std::fs::read(&path).context(format!("read {}", path.display()))?;
+                for (object_type, content) in read_pack(repo, &pack)? {
+                    packed.insert(hash_object(&object_type, &content), (object_type, content));
+                }
+            }
+        }
+    }
+
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<String> = roots.into_iter().filter(|hash| hash != NULL_HASH).collect();
+    while let Some(hash) = pending.pop() {
+        // The empty tree is known without being written
+        if !reachable.insert(hash.clone()) || hash == EMPTY_TREE {
+            continue;
+        }
+        let (object_type, content) = match packed.get(&hash) {
+            Some((object_type, content)) => (*object_type, content.clone()),
+            None => read_original_object(repo, &hash)
+                .context(format!("unable to read reachable object {hash}"))?,
+        };
+        match object_type {
+            ObjectType::Blob => {},
+            ObjectType::Tree => pending.extend(
+                parse_tree(&content)?
+                    .into_iter()
+                    .filter(|entry| entry.mode != MODE_GITLINK)
+                    .map(|entry| entry.hash),
+            ),
+            ObjectType::Commit => {
+                let commit = Commit::parse(&content)?;
+                pending.push(commit.tree);
+                pending.extend(commit.parents);
+            },
+            ObjectType::Tag => pending.push(Tag::parse(&content)?.object),
+        }
+    }
+    Ok(reachable)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct PruneArgs {
+    /// only show the objects that would be removed
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// show the objects removed
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_AUTHOR_DATE, "0 +0000")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_DATE, "0 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    fn prune(repo: &Repository, dry_run: bool) -> anyhow::Result<String> {
+        let args = PruneArgs {
+            dry_run,
+            verbose: false,
+        };
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn object_exists(temp: &TempRepo, hash: &str) -> bool {
+        temp.join(format!(".git/objects/{}/{}", &hash[..2], &hash[2..]))
+            .exists()
+    }
+
+    #[test]
+    fn removes_unreachable_objects() {
+        let (temp, repo) = setup();
+        let commit = create_commit(&repo, EMPTY_TREE, Vec::new(), "root\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        let blob = write_object(&repo, ObjectType::Blob, b"unreachable\n").unwrap();
+
+        assert_eq!(prune(&repo, true).unwrap(), format!("{blob} blob\n"));
+        assert!(object_exists(&temp, &blob));
+
+        assert_eq!(prune(&repo, false).unwrap(), "");
+        assert!(!object_exists(&temp, &blob));
+        assert!(!temp.join(format!(".git/objects/{}", &blob[..2])).exists());
+        assert!(object_exists(&temp, &commit));
+    }
+
+    #[test]
+    fn refuses_to_prune_precious_objects() {
+        let (temp, repo) = setup();
+        let blob = write_object(&repo, ObjectType::Blob, b"unreachable\n").unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tpreciousObjects = true\n",
+        )
+        .unwrap();
+
+        let err = prune(&repo, false).unwrap_err();
+        assert_eq!(err.to_string(), "cannot prune in a precious-objects repo");
+        assert!(object_exists(&temp, &blob));
+
+        // Version 0 repositories predate extensions
+        fs::write(
+            temp.join(".git/config"),
+            "[extensions]\n\tpreciousObjects = true\n",
+        )
+        .unwrap();
+        prune(&repo, false).unwrap();
+        assert!(!object_exists(&temp, &blob));
+    }
+}
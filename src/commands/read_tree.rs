@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::checkout::{reset_index, tree_paths};
+use crate::utils::index::{is_valid_path, Index, IndexEntry};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_tree;
+use crate::utils::tree::{flatten_tree, TreeEntry};
+
+impl CommandArgs for ReadTreeArgs {
+    fn run<W>(self, repo: &Repository, _writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let trees = self
+            .trees
+            .iter()
+            .map(|tree| resolve_tree(repo, tree))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if self.merge {
+            let mut index = Index::load(repo)?;
+            match trees.as_slice() {
+                [tree] => reset_index(repo, &mut index, tree)?,
+                [old, new] => two_way_merge(repo, &mut index, old, new)?,
+                [base, ours, theirs] => three_way_merge(repo, &mut index, base, ours, theirs)?,
+                _ => unreachable!("clap allows one to three trees"),
+            }
+            return index.write(repo);
+        }
+        let [tree] = trees.as_slice() else {
+            anyhow::bail!("reading more than one tree requires -m");
+        };
+
+        // Without a prefix, the tree replaces the content of the index
+        let (mut index, prefix) = match &self.prefix {
+            Some(prefix) => {
+                if prefix.starts_with('/') {
+                    anyhow::bail!("Invalid prefix, prefix cannot start with '/'");
+                }
+                (Index::load(repo)?, prefix.trim_end_matches('/'))
+            },
+            None => (Index::default(), ""),
+        };
+
+        read_tree(repo, &mut index, tree, prefix)?;
+        index.write(repo)
+    }
+}
+
+/// Move the index from one tree to another, keeping its changes: each path takes
+/// the version of the new tree, unless the index changed it from the old tree
+/// to something else.
+fn two_way_merge(repo: &Repository, index: &mut Index, old: &str, new: &str) -> anyhow::Result<()> {
+    check_resolved(index)?;
+    let old = tree_paths(repo, old)?;
+    let new = tree_paths(repo, new)?;
+    let staged = staged_paths(index);
+
+    let paths: BTreeSet<&Vec<u8>> = old.keys().chain(new.keys()).collect();
+    let mut changed = BTreeSet::new();
+    for path in paths {
+        let (old, new, staged) = (old.get(path), new.get(path), staged.get(path));
+        if staged == new || old == new {
+            continue;
+        }
+        if staged != old {
+            return Err(would_overwrite(path));
+        }
+
+        index.remove_entry(path);
+        if let Some(new) = new {
+            index.add_entry(IndexEntry::new(path.clone(), new.mode, new.hash.clone()));
+        }
+        changed.insert(path);
+    }
+
+    // A file added in place of a directory of the index (or the reverse) must not
+    // replace the entries it kept
+    match staged
+        .keys()
+        .find(|path| !changed.contains(path) && !index.contains(path))
+    {
+        Some(path) => Err(would_overwrite(path)),
+        None => Ok(()),
+    }
+}
+
+/// Merge two trees with their common ancestor into the index, which must match
+/// our tree. The paths changed by only one side are taken from it, and the others
+/// are left as conflict stages, as well as the paths deleted by a side.
+///
+/// A path that is a file in a tree and a directory in another one is never
+/// merged: the file and the files of the directory are all left as stages.
+fn three_way_merge(
+    repo: &Repository,
+    index: &mut Index,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+) -> anyhow::Result<()> {
+    check_resolved(index)?;
+    let trees = [
+        tree_paths(repo, base)?,
+        tree_paths(repo, ours)?,
+        tree_paths(repo, theirs)?,
+    ];
+    let staged = staged_paths(index);
+
+    let paths: BTreeSet<&Vec<u8>> = trees
+        .iter()
+        .flat_map(BTreeMap::keys)
+        .chain(staged.keys())
+        .collect();
+    let mut changes = Vec::new();
+    for path in paths {
+        let [base, ours, theirs] = [0, 1, 2].map(|side| trees[side].get(path));
+        let result = if ours.is_some() && ours == theirs {
+            Some(ours)
+        } else if theirs.is_some() && base == ours && !in_directory_conflict(&trees[1], path) {
+            Some(theirs)
+        } else if ours.is_some() && base == theirs && !in_directory_conflict(&trees[2], path) {
+            Some(ours)
+        } else if base.is_none() && ours.is_none() && theirs.is_none() {
+            Some(None)
+        } else {
+            None
+        };
+
+        // The index may only differ from our side where it has the merged version
+        let staged = staged.get(path);
+        if result != Some(staged) {
+            if staged != ours {
+                return Err(would_overwrite(path));
+            }
+            changes.push((path.clone(), result, [base, ours, theirs]));
+        }
+    }
+
+    for (path, result, stages) in changes {
+        index.remove_entry(&path);
+        let stages = match result {
+            Some(merged) => vec![(0, merged)],
+            None => (1..).zip(stages).collect(),
+        };
+        for (stage, entry) in stages {
+            if let Some(entry) = entry {
+                let mut entry = IndexEntry::new(path.clone(), entry.mode, entry.hash.clone());
+                entry.stage = stage;
+                index.add_entry(entry);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a tree has a directory at a path, or a file at one of its directories.
+fn in_directory_conflict(tree: &BTreeMap<Vec<u8>, TreeEntry>, path: &[u8]) -> bool {
+    let directory = [path, b"/"].concat();
+    let has_directory = tree
+        .range(directory.clone()..)
+        .next()
+        .is_some_and(|(next, _)| next.starts_with(&directory));
+
+    has_directory
+        || (0..path.len())
+            .filter(|&end| path[end] == b'/')
+            .any(|end| tree.contains_key(&path[..end]))
+}
+
+/// Refuse to merge into an index with conflicts.
+fn check_resolved(index: &Index) -> anyhow::Result<()> {
+    if index.entries().iter().any(|entry| entry.stage != 0) {
+        anyhow::bail!("you need to resolve your current index first");
+    }
+    Ok(())
+}
+
+/// The entries of the index by path, as the entries of a flattened tree.
+fn staged_paths(index: &Index) -> BTreeMap<Vec<u8>, TreeEntry> {
+    index
+        .entries()
+        .iter()
+        .map(|entry| {
+            let staged = TreeEntry {
+                mode: entry.mode,
+                name: entry.path.clone(),
+                hash: entry.hash.clone(),
+            };
+            (entry.path.clone(), staged)
+        })
+        .collect()
+}
+
+fn would_overwrite(path: &[u8]) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Entry '{}' would be overwritten by merge. Cannot merge.",
+        String::from_utf8_lossy(path)
+    )
+}
+
+/// Add the entries of a tree to the index under a directory.
+///
+/// # Arguments
+///
+/// * `repo` - The repository containing the tree
+/// * `index` - The index to add the entries to
+/// * `tree` - The hash of the tree
+/// * `prefix` - The directory to add the entries under, without trailing slash
+///   (empty for the root of the working tree)
+pub(crate) fn read_tree(
+    repo: &Repository,
+    index: &mut Index,
+    tree: &str,
+    prefix: &str,
+) -> anyhow::Result<()> {
+    let mut entries = Vec::new();
+    for entry in flatten_tree(repo, tree)? {
+        let path = match prefix {
+            "" => entry.name,
+            _ => [prefix.as_bytes(), b"/", &entry.name].concat(),
+        };
+
+        if !is_valid_path(&path) {
+            anyhow::bail!("invalid path '{}'", String::from_utf8_lossy(&path));
+        }
+        // Existing entries are never overwritten
+        if index.contains(&path) {
+            let path = String::from_utf8_lossy(&path);
+            anyhow::bail!("Entry '{path}' overlaps with '{path}'.  Cannot bind.");
+        }
+        entries.push(IndexEntry::new(path, entry.mode, entry.hash));
+    }
+
+    for entry in entries {
+        index.add_entry(entry);
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ReadTreeArgs {
+    /// merge the trees into the index: one tree to reset it, two trees to move it
+    /// from the first to the second, or a base and two sides to merge
+    #[arg(short = 'm', conflicts_with = "prefix")]
+    merge: bool,
+    /// keep the current index and read the tree under this directory
+    #[arg(long, value_name = "prefix")]
+    prefix: Option<String>,
+    /// the trees (or commits) to read
+    #[arg(value_name = "tree-ish", required = true, num_args = 1..=3)]
+    trees: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::{serialize_tree, TreeEntry, MODE_TREE};
+
+    const HASH: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    /// Create a repository with the tree of `a.txt` and `dir/b.txt`.
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let entry = |mode, name: &str, hash: &str| TreeEntry {
+            mode,
+            name: name.as_bytes().to_vec(),
+            hash: hash.to_string(),
+        };
+        let write = |entries| {
+            let content = serialize_tree(entries).unwrap();
+            write_object(&repo, ObjectType::Tree, &content).unwrap()
+        };
+        let dir = write(vec![entry(0o100644, "b.txt", HASH)]);
+        let tree = write(vec![
+            entry(0o100644, "a.txt", HASH),
+            entry(MODE_TREE, "dir", &dir),
+        ]);
+
+        (temp, repo, tree)
+    }
+
+    fn read_tree(repo: &Repository, tree: &str, prefix: Option<&str>) -> anyhow::Result<()> {
+        let args = ReadTreeArgs {
+            merge: false,
+            prefix: prefix.map(str::to_string),
+            trees: vec![tree.to_string()],
+        };
+        args.run(repo, &mut Vec::new())
+    }
+
+    fn paths(repo: &Repository) -> Vec<String> {
+        let index = Index::load(repo).unwrap();
+        index
+            .entries()
+            .iter()
+            .map(|e| String::from_utf8(e.path.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reads_tree_into_index() {
+        let (_temp, repo, tree) = setup();
+
+        read_tree(&repo, &tree, None).unwrap();
+        assert_eq!(paths(&repo), vec!["a.txt", "dir/b.txt"]);
+
+        // Without a prefix the index is replaced
+        read_tree(&repo, &tree, None).unwrap();
+        assert_eq!(paths(&repo), vec!["a.txt", "dir/b.txt"]);
+    }
+
+    #[test]
+    fn reads_tree_under_prefix() {
+        let (_temp, repo, tree) = setup();
+        read_tree(&repo, &tree, None).unwrap();
+
+        read_tree(&repo, &tree, Some("vendor/lib/")).unwrap();
+        read_tree(&repo, &tree, Some("dir/sub")).unwrap();
+        assert_eq!(paths(&repo), vec![
+            "a.txt",
+            "dir/b.txt",
+            "dir/sub/a.txt",
+            "dir/sub/dir/b.txt",
+            "vendor/lib/a.txt",
+            "vendor/lib/dir/b.txt",
+        ]);
+
+        let index = Index::load(&repo).unwrap();
+        assert_eq!(index.entry(b"vendor/lib/dir/b.txt", 0).unwrap().hash, HASH);
+    }
+
+    #[test]
+    fn refuses_to_overwrite_entries() {
+        let (_temp, repo, tree) = setup();
+        read_tree(&repo, &tree, Some("vendor")).unwrap();
+
+        let err = read_tree(&repo, &tree, Some("vendor")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entry 'vendor/a.txt' overlaps with 'vendor/a.txt'.  Cannot bind."
+        );
+        assert!(read_tree(&repo, &tree, Some("/abs")).is_err());
+        assert!(read_tree(&repo, &tree, Some("a/../b")).is_err());
+        assert!(read_tree(&repo, HASH, Some("blob")).is_err());
+        assert_eq!(paths(&repo), vec!["vendor/a.txt", "vendor/dir/b.txt"]);
+    }
+
+    #[test]
+    fn merges_trees_into_the_index() {
+        let (_temp, repo, _) = setup();
+        let write = |files: &[(&str, &str)]| {
+            let mut index = Index::default();
+            for (path, content) in files {
+                let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+                index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+            }
+            index.write_tree(&repo, false).unwrap()
+        };
+        let merge = |trees: &[&str]| {
+            let args = ReadTreeArgs {
+                merge: true,
+                prefix: None,
+                trees: trees.iter().map(|tree| tree.to_string()).collect(),
+            };
+            args.run(&repo, &mut Vec::new())
+        };
+        let stages = || -> Vec<(String, u8)> {
+            let index = Index::load(&repo).unwrap();
+            index
+                .entries()
+                .iter()
+                .map(|e| (String::from_utf8(e.path.clone()).unwrap(), e.stage))
+                .collect()
+        };
+
+        let base = write(&[("a/b", "b"), ("c", "c"), ("gone", "gone")]);
+        let ours = write(&[("a/b", "b"), ("c", "ours"), ("f", "f"), ("x", "x")]);
+        let theirs = write(&[("a/b/c/d", "d"), ("c", "c"), ("f/g", "g"), ("gone", "gone")]);
+
+        // The paths of a file in the way of a directory are all left as stages
+        read_tree(&repo, &ours, None).unwrap();
+        merge(&[&base, &ours, &theirs]).unwrap();
+        assert_eq!(stages(), [
+            ("a/b".to_string(), 1),
+            ("a/b".to_string(), 2),
+            ("a/b/c/d".to_string(), 3),
+            ("c".to_string(), 0),
+            ("f".to_string(), 2),
+            ("f/g".to_string(), 3),
+            ("gone".to_string(), 1),
+            ("gone".to_string(), 3),
+            ("x".to_string(), 0),
+        ]);
+        let err = merge(&[&base, &ours, &theirs]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "you need to resolve your current index first"
+        );
+
+        // The index must match our side where the merge changes it
+        read_tree(&repo, &base, None).unwrap();
+        let err = merge(&[&base, &ours, &theirs]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entry 'c' would be overwritten by merge. Cannot merge."
+        );
+
+        // Two trees move the index from one to the other, files replacing directories
+        read_tree(&repo, &theirs, None).unwrap();
+        merge(&[&theirs, &ours]).unwrap();
+        assert_eq!(paths(&repo), ["a/b", "c", "f", "x"]);
+
+        let staged = write(&[("a/b", "b"), ("c", "ours"), ("gone/new", "new"), ("x", "x")]);
+        read_tree(&repo, &staged, None).unwrap();
+        let err = merge(&[&ours, &theirs]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entry 'gone/new' would be overwritten by merge. Cannot merge."
+        );
+    }
+}
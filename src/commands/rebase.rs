@@ -0,0 +1,913 @@
+use std::io::Write;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::cherry_pick::{
+    conflict_message, create_resolved_commit, finish_commit, merge_step, MergedStep, SequencerArgs,
+    SequencerOperation,
+};
+use crate::commands::commit::strip_comments;
+use crate::commands::switch::{switch, LocalChanges, Target};
+use crate::commands::{rerere, CommandArgs};
+use crate::utils::advice::{with_advice, Advice};
+use crate::utils::checkout::{check_overwritten, reset_tree, switch_tree, Operation};
+use crate::utils::commit::{create_commit_as, read_commit};
+use crate::utils::config::Config;
+use crate::utils::editor::launch_editor;
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::index::Index;
+use crate::utils::rebase::{self, Squash, State};
+use crate::utils::reflog::log_ref_update;
+use crate::utils::refs::{
+    delete_ref, head_branch, resolve_ref, shorten_ref, update_head, update_ref, write_ref,
+    write_symref,
+};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::sequencer::{parse_todo, Action, Step};
+use crate::utils::walk::{is_ancestor, merge_bases, RevWalk};
+
+impl CommandArgs for RebaseArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.sequencer.operation() {
+            Some(operation) => run_operation(repo, operation, writer),
+            None => start(
+                repo,
+                self.upstream.as_deref(),
+                self.branch.as_deref(),
+                self.onto.as_deref(),
+                self.interactive,
+                writer,
+            ),
+        }
+    }
+}
+
+/// Start rebasing the current branch (or `branch`, switched to first): the commits
+/// of the branch that are not in the upstream are replayed onto `onto` (the
+/// upstream by default), and the branch is moved to the last one.
+///
+/// When `interactive` is set, the user edits the list of commits to replay first.
+fn start<W>(
+    repo: &Repository,
+    upstream: Option<&str>,
+    branch: Option<&str>,
+    onto: Option<&str>,
+    interactive: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if rebase::in_progress(repo)? {
+        anyhow::bail!(
+            "It seems that there is already a rebase-merge directory, and\n\
+             I wonder if you are in the middle of another rebase.  If that is the\n\
+             case, please try\n\
+             \tgit rebase (--continue | --abort | --skip)\n\
+             If that is not the case, please\n\
+             \trm -fr \"{}\"\n\
+             and run me again.  I am stopping in case you still have something\n\
+             valuable there.",
+            repo.git_dir()?.join("rebase-merge").display()
+        );
+    }
+
+    if let Some(branch) = branch {
+        if resolve_ref(repo, &format!("refs/heads/{branch}"))?.is_none() {
+            anyhow::bail!("no such branch/commit '{branch}'");
+        }
+        if head_branch(repo)?.as_deref() != Some(&format!("refs/heads/{branch}")) {
+            let target = Target::Branch(branch.to_string());
+            switch(repo, target, LocalChanges::Carry, &mut std::io::sink())?;
+        }
+    }
+    let head_name = head_branch(repo)?;
+    let Some(orig_head) = resolve_ref(repo, "HEAD")? else {
+        anyhow::bail!("invalid upstream 'HEAD'");
+    };
+
+    let upstream_name = match upstream {
+        Some(upstream) => upstream.to_string(),
+        None => tracked_upstream(repo, head_name.as_deref())?,
+    };
+    let upstream = resolve_commit(repo, &upstream_name)
+        .map_err(|_| anyhow::anyhow!("invalid upstream '{upstream_name}'"))?;
+    let onto_name = onto.map_or(upstream_name.clone(), String::from);
+    let onto = resolve_commit(repo, &onto_name)
+        .map_err(|_| anyhow::anyhow!("Does not point to a valid commit '{onto_name}'"))?;
+
+    check_clean(repo)?;
+
+    // Nothing is replayed if the branch already starts at `onto`
+    if !interactive
+        && is_ancestor(repo, &onto, &orig_head)?
+        && merge_bases(repo, &upstream, &orig_head)?.first() == Some(&onto)
+    {
+        let name = head_name.as_deref().map_or("HEAD", shorten_ref);
+        writeln!(writer, "Current branch {name} is up to date.")?;
+        return Ok(());
+    }
+
+    // The commits of the branch, oldest first, without merges
+    let mut walk = RevWalk::new(repo);
+    walk.hide(&upstream)?;
+    walk.push(&orig_head)?;
+    let mut todo = Vec::new();
+    for entry in walk {
+        let (hash, commit) = entry?;
+        if commit.parents.len() <= 1 {
+            todo.push(Step {
+                action: Action::Pick,
+                hash,
+            });
+        }
+    }
+    todo.reverse();
+
+    write_ref(repo, "ORIG_HEAD", &orig_head)?;
+    let mut state = State {
+        head_name,
+        onto,
+        orig_head,
+        todo,
+        done: Vec::new(),
+    };
+    if interactive {
+        rebase::save(repo, &state)?;
+        match edit_todo(repo, &state, &upstream) {
+            Ok(todo) => state.todo = todo,
+            Err(err) => {
+                rebase::remove(repo)?;
+                return Err(err);
+            },
+        }
+    }
+
+    // The first commits are kept as they are if they are already on top of `onto`
+    let mut start_at = state.onto.clone();
+    while let Some(step) = state.todo.first() {
+        if step.action != Action::Pick
+            || read_commit(repo, &step.hash)?.parents != [start_at.as_str()]
+        {
+            break;
+        }
+        start_at = step.hash.clone();
+        state.done.push(state.todo.remove(0));
+    }
+
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::load(repo)?;
+    let (old_tree, new_tree) = (
+        read_commit(repo, &state.orig_head)?.tree,
+        read_commit(repo, &start_at)?.tree,
+    );
+    check_overwritten(
+        repo,
+        &index,
+        &work_tree,
+        &old_tree,
+        &new_tree,
+        Operation::Checkout,
+    )?;
+    switch_tree(repo, &mut index, &work_tree, &old_tree, &new_tree)?;
+    index.write(repo)?;
+    write_ref(repo, "HEAD", &start_at)?;
+    let message = format!("rebase (start): checkout {onto_name}");
+    log_ref_update(repo, "HEAD", Some(&state.orig_head), &start_at, &message)?;
+
+    rebase::save(repo, &state)?;
+    run_todo(repo, state, writer)
+}
+
+/// Let the user edit the list of commits to replay, listed with abbreviated hashes
+/// and followed by comments describing the actions.
+///
+/// # Returns
+///
+/// The edited list, with the full hashes of the commits
+fn edit_todo(repo: &Repository, state: &State, upstream: &str) -> anyhow::Result<Vec<Step>> {
+    let mut content = String::new();
+    for step in &state.todo {
+        let subject = read_commit(repo, &step.hash)?.subject();
+        let name = step.action.name();
+        content.push_str(&format!("{name} {} {subject}\n", &step.hash[..7]));
+    }
+    let count = match state.todo.len() {
+        1 => "1 command".to_string(),
+        count => format!("{count} commands"),
+    };
+    content.push_str(&format!(
+        "\n# Rebase {}..{} onto {} ({count})\n\
+         #\n\
+         # Commands:\n\
+         # p, pick <commit> = use commit\n\
+         # r, reword <commit> = use commit, but edit the commit message\n\
+         # s, squash <commit> = use commit, but meld into previous commit\n\
+         # f, fixup <commit> = like \"squash\" but keep only the previous\n\
+         #                    commit's log message\n\
+         # d, drop <commit> = remove commit\n\
+         #\n\
+         # These lines can be re-ordered; they are executed from top to bottom.\n\
+         #\n\
+         # If you remove a line here THAT COMMIT WILL BE LOST.\n\
+         #\n\
+         # However, if you remove everything, the rebase will be aborted.\n\
+         #\n",
+        &upstream[..7],
+        &state.orig_head[..7],
+        &state.onto[..7]
+    ));
+
+    let path = rebase::todo_path(repo)?;
+    std::fs::write(&path, content).context("write rebase-merge/git-rebase-todo")?;
+    launch_editor(repo, &path)?;
+    let content = std::fs::read_to_string(&path).context("read rebase-merge/git-rebase-todo")?;
+
+    let mut todo = parse_todo(&content)?;
+    if todo.is_empty() {
+        anyhow::bail!("nothing to do");
+    }
+    for step in &mut todo {
+        step.hash = resolve_commit(repo, &step.hash)
+            .map_err(|_| anyhow::anyhow!("invalid commit in the todo list: {step}"))?;
+    }
+    if let Some(step) = todo.iter().find(|step| step.action != Action::Drop) {
+        if matches!(step.action, Action::Squash | Action::Fixup) {
+            let name = step.action.name();
+            anyhow::bail!("cannot '{name}' without a previous commit");
+        }
+    }
+    Ok(todo)
+}
+
+/// The upstream of a branch, from its `branch.<name>.remote` and
+/// `branch.<name>.merge` configuration.
+fn tracked_upstream(repo: &Repository, branch: Option<&str>) -> anyhow::Result<String> {
+    let config = Config::load(repo)?;
+    let name = branch.map(shorten_ref);
+    let tracked = name.and_then(|name| {
+        let remote = config.get(&format!("branch.{name}.remote"))?;
+        let merge = config.get(&format!("branch.{name}.merge"))?;
+        Some(match remote {
+            "." => merge.to_string(),
+            remote => {
+                let merge = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+                format!("refs/remotes/{remote}/{merge}")
+            },
+        })
+    });
+
+    tracked.ok_or_else(|| {
+        anyhow::anyhow!(
+            "There is no tracking information for the current branch.\n\
+             Please specify which branch you want to rebase against.\n\
+             See git-rebase(1) for details.\n\n    \
+             git rebase '<branch>'\n\n\
+             If you wish to set tracking information for this branch you can do so with:\n\n    \
+             git branch --set-upstream-to=<remote>/<branch> {}\n",
+            name.unwrap_or("<branch>")
+        )
+    })
+}
+
+/// Refuse to rebase with local changes, staged or not.
+fn check_clean(repo: &Repository) -> anyhow::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::load(repo)?;
+    for entry in index.entries() {
+        if entry.stage != 0 || index.is_modified(entry, &work_tree)? {
+            anyhow::bail!(
+                "cannot rebase: You have unstaged changes.\nPlease commit or stash them."
+            );
+        }
+    }
+
+    let head_tree = match resolve_ref(repo, "HEAD")? {
+        Some(head) => read_commit(repo, &head)?.tree,
+        None => return Ok(()),
+    };
+    if index.write_tree(repo, false)? != head_tree {
+        anyhow::bail!(
+            "cannot rebase: Your index contains uncommitted changes.\nPlease commit or stash them."
+        );
+    }
+    Ok(())
+}
+
+/// Run an operation on the rebase in progress.
+fn run_operation<W>(
+    repo: &Repository,
+    operation: SequencerOperation,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let Some(state) = rebase::load(repo)? else {
+        anyhow::bail!("No rebase in progress?");
+    };
+
+    match operation {
+        SequencerOperation::Continue => {
+            let index = Index::load(repo)?;
+            let mut unmerged: Vec<_> = index
+                .entries()
+                .iter()
+                .filter(|entry| entry.stage != 0)
+                .map(|entry| String::from_utf8_lossy(&entry.path))
+                .collect();
+            unmerged.dedup();
+            if !unmerged.is_empty() {
+                let mut message = String::new();
+                for path in unmerged {
+                    message.push_str(&format!("{path}: needs merge\n"));
+                }
+                anyhow::bail!(
+                    "{message}You must edit all merge conflicts and then\n\
+                     mark them as resolved using git add"
+                );
+            }
+
+            // The resolved changes of the commit it stopped at are committed,
+            // unless they are empty, or melded into HEAD
+            if let Some(stopped) = rebase::stopped(repo)? {
+                let commit = read_commit(repo, &stopped)?;
+                match state.done.last() {
+                    Some(step) if matches!(step.action, Action::Squash | Action::Fixup) => {
+                        let tree = Index::load(repo)?.write_tree(repo, false)?;
+                        let message = String::from_utf8_lossy(&commit.message);
+                        meld_into_head(repo, &state, step, &message, &tree, writer)?;
+                    },
+                    step => {
+                        if step.is_some_and(|step| step.action == Action::Reword) {
+                            launch_editor(repo, &repo.git_dir()?.join("MERGE_MSG"))?;
+                        }
+                        if let Some(hash) = create_resolved_commit(repo, Some(&commit.author))? {
+                            finish_commit(repo, &hash, "rebase (continue): ", writer)?;
+                        }
+                    },
+                }
+            }
+        },
+        SequencerOperation::Skip => {
+            let head = resolve_commit(repo, "HEAD")?;
+            let mut index = Index::load(repo)?;
+            reset_tree(
+                repo,
+                &mut index,
+                &repo.work_tree()?,
+                &read_commit(repo, &head)?.tree,
+            )?;
+            index.write(repo)?;
+            rerere::clear(repo)?;
+        },
+        SequencerOperation::Abort => {
+            let mut index = Index::load(repo)?;
+            let tree = read_commit(repo, &state.orig_head)?.tree;
+            reset_tree(repo, &mut index, &repo.work_tree()?, &tree)?;
+            index.write(repo)?;
+            rerere::clear(repo)?;
+
+            let head = resolve_ref(repo, "HEAD")?;
+            let returning_to = match &state.head_name {
+                Some(branch) => {
+                    write_symref(repo, "HEAD", branch)?;
+                    branch.clone()
+                },
+                None => {
+                    write_ref(repo, "HEAD", &state.orig_head)?;
+                    state.orig_head.clone()
+                },
+            };
+            let message = format!("rebase (abort): returning to {returning_to}");
+            log_ref_update(repo, "HEAD", head.as_deref(), &state.orig_head, &message)?;
+            clear_stopped(repo)?;
+            return rebase::remove(repo);
+        },
+        SequencerOperation::Quit => {
+            clear_stopped(repo)?;
+            return rebase::remove(repo);
+        },
+    }
+
+    clear_stopped(repo)?;
+    run_todo(repo, state, writer)
+}
+
+/// Replay the commits left to replay, then move the branch to the result.
+fn run_todo<W>(repo: &Repository, mut state: State, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    while !state.todo.is_empty() {
+        let step = state.todo.remove(0);
+        state.done.push(step.clone());
+        rebase::save(repo, &state)?;
+        if step.action == Action::Drop {
+            continue;
+        }
+
+        let merged = match merge_step(repo, &step, false, writer) {
+            Ok(merged) => merged,
+            Err(err) => {
+                // The commit is replayed again by `--continue`
+                state.todo.insert(0, step);
+                state.done.pop();
+                rebase::save(repo, &state)?;
+                return Err(err);
+            },
+        };
+        let subject = merged.commit.subject();
+
+        if !merged.conflicts.is_empty() {
+            stop(
+                repo,
+                &merged,
+                &conflict_message(&merged.message, &merged.conflicts),
+            )?;
+            anyhow::bail!(with_advice(
+                repo,
+                Advice::MergeConflict,
+                format!("could not apply {}... {subject}", &merged.hash[..7]),
+                "Resolve all conflicts manually, mark them as resolved with\n\
+                 \"git add/rm <conflicted_files>\", then run \"git rebase --continue\".\n\
+                 You can instead skip this commit: run \"git rebase --skip\".\n\
+                 To abort and get back to the state before \"git rebase\", run \"git rebase --abort\".",
+            )?);
+        }
+
+        if matches!(step.action, Action::Squash | Action::Fixup) {
+            let melded = meld_into_head(repo, &state, &step, &merged.message, &merged.tree, writer);
+            if let Err(err) = melded {
+                stop(repo, &merged, &merged.message)?;
+                return Err(err);
+            }
+            continue;
+        }
+
+        // Commits whose changes are already in the new base are dropped,
+        // but commits that were empty from the start are kept
+        let parent_tree = match merged.commit.parents.first() {
+            Some(parent) => Some(read_commit(repo, parent)?.tree),
+            None => None,
+        };
+        let was_empty = parent_tree.as_deref() == Some(merged.commit.tree.as_str());
+        if merged.tree == merged.head_tree && !was_empty {
+            continue;
+        }
+
+        let message = match step.action {
+            Action::Reword => match edit_message(repo, &merged.message) {
+                Ok(message) => message,
+                Err(err) => {
+                    stop(repo, &merged, &merged.message)?;
+                    return Err(err);
+                },
+            },
+            _ => merged.message.clone(),
+        };
+        let parents: Vec<String> = merged.head.into_iter().collect();
+        let author = &merged.commit.author;
+        let hash = create_commit_as(repo, &merged.tree, parents, &message, author)?;
+        match step.action {
+            Action::Reword => finish_commit(repo, &hash, "rebase (reword): ", writer)?,
+            _ => {
+                update_head(repo, &hash, &format!("rebase (pick): {subject}"))?;
+                run_hook(repo, "post-commit", Hook::default())?;
+            },
+        }
+    }
+
+    finish(repo, &state, writer)
+}
+
+/// Stop at a commit, recording it for `--continue` with the content of `MERGE_MSG`.
+fn stop(repo: &Repository, merged: &MergedStep, content: &str) -> anyhow::Result<()> {
+    std::fs::write(repo.git_dir()?.join("MERGE_MSG"), content).context("write MERGE_MSG")?;
+    write_ref(repo, "REBASE_HEAD", &merged.hash)?;
+    rebase::save_stopped(repo, &merged.hash, &merged.message, &merged.commit.author)
+}
+
+/// Amend HEAD with the changes of a commit squashed or fixed up into it (the tree
+/// of the result), adding its message to the combined message.
+///
+/// The commit keeps the message of HEAD while only commits are fixed up. Otherwise
+/// its message is the combined one, which the user edits once the last commit of
+/// the chain is melded.
+fn meld_into_head<W>(
+    repo: &Repository,
+    state: &State,
+    step: &Step,
+    message: &str,
+    tree: &str,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let head = resolve_commit(repo, "HEAD")?;
+    let head_commit = read_commit(repo, &head)?;
+    let head_message = String::from_utf8_lossy(&head_commit.message).into_owned();
+    let squash = add_to_squash(repo, step, message, &head_message)?;
+
+    let is_last = !state
+        .todo
+        .iter()
+        .find(|step| step.action != Action::Drop)
+        .is_some_and(|step| matches!(step.action, Action::Squash | Action::Fixup));
+    let squashed = squash
+        .fixups
+        .iter()
+        .any(|step| step.action == Action::Squash);
+    let message = match (squashed, is_last) {
+        (false, _) => head_message,
+        (true, false) => strip_comments(&squash.message, '#'),
+        (true, true) => edit_message(repo, &squash.message)?,
+    };
+
+    let parents = head_commit.parents.clone();
+    let hash = create_commit_as(repo, tree, parents, &message, &head_commit.author)?;
+    let prefix = format!("rebase ({}): ", step.action.name());
+    match squashed && is_last {
+        true => finish_commit(repo, &hash, &prefix, writer)?,
+        false => {
+            let subject = read_commit(repo, &hash)?.subject();
+            update_head(repo, &hash, &format!("{prefix}{subject}"))?;
+            run_hook(repo, "post-commit", Hook::default())?;
+        },
+    }
+    if is_last {
+        rebase::clear_squash(repo)?;
+    }
+    Ok(())
+}
+
+/// Add the message of a commit to the combined message of the commits melded into
+/// HEAD, starting with the message of HEAD. The messages of fixed up commits are
+/// commented out.
+fn add_to_squash(
+    repo: &Repository,
+    step: &Step,
+    message: &str,
+    head_message: &str,
+) -> anyhow::Result<Squash> {
+    let (mut fixups, mut body) = match rebase::load_squash(repo)? {
+        // The commit was added already if melding it failed
+        Some(squash) if squash.fixups.last() == Some(step) => return Ok(squash),
+        Some(squash) => {
+            let body = squash.message.split_once('\n').map_or("", |(_, body)| body);
+            (squash.fixups, body.to_string())
+        },
+        None => (
+            Vec::new(),
+            format!("# This is the 1st commit message:\n\n{head_message}"),
+        ),
+    };
+    fixups.push(step.clone());
+    let count = fixups.len() + 1;
+
+    match step.action {
+        Action::Squash => body.push_str(&format!(
+            "\n# This is the commit message #{count}:\n\n{message}"
+        )),
+        _ => {
+            body.push_str(&format!(
+                "\n# The commit message #{count} will be skipped:\n\n"
+            ));
+            for line in message.lines() {
+                match line.is_empty() {
+                    true => body.push_str("#\n"),
+                    false => body.push_str(&format!("# {line}\n")),
+                }
+            }
+        },
+    }
+    let squash = Squash {
+        fixups,
+        message: format!("# This is a combination of {count} commits.\n{body}"),
+    };
+    rebase::save_squash(repo, &squash)?;
+    Ok(squash)
+}
+
+/// Let the user edit a commit message in `COMMIT_EDITMSG`.
+///
+/// # Returns
+///
+/// The edited message, without comments
+fn edit_message(repo: &Repository, message: &str) -> anyhow::Result<String> {
+    let path = repo.git_dir()?.join("COMMIT_EDITMSG");
+    let content = format!(
+        "{message}\n\
+         # Please enter the commit message for your changes. Lines starting\n\
+         # with '#' will be ignored, and an empty message aborts the commit.\n"
+    );
+    std::fs::write(&path, content).context("write COMMIT_EDITMSG")?;
+    launch_editor(repo, &path)?;
+
+    let message = strip_comments(
+        &std::fs::read_to_string(&path).context("read COMMIT_EDITMSG")?,
+        '#',
+    );
+    if message.is_empty() {
+        anyhow::bail!("Aborting commit due to empty commit message.");
+    }
+    Ok(message)
+}
+
+/// Move the rebased branch to the commit HEAD is at, and check it out again.
+fn finish<W>(repo: &Repository, state: &State, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let head = resolve_commit(repo, "HEAD")?;
+    let updated = match &state.head_name {
+        Some(branch) => {
+            let message = format!("rebase (finish): {branch} onto {}", state.onto);
+            update_ref(repo, branch, &head, &message)?;
+            write_symref(repo, "HEAD", branch)?;
+            let message = format!("rebase (finish): returning to {branch}");
+            log_ref_update(repo, "HEAD", Some(&head), &head, &message)?;
+            branch.as_str()
+        },
+        None => "detached HEAD",
+    };
+    rebase::remove(repo)?;
+    writeln!(writer, "Successfully rebased and updated {updated}.")?;
+    Ok(())
+}
+
+/// Forget the commit the rebase stopped at, and its message.
+fn clear_stopped(repo: &Repository) -> anyhow::Result<()> {
+    rebase::clear_stopped(repo)?;
+    delete_ref(repo, "REBASE_HEAD")?;
+    let message_path = repo.git_dir()?.join("MERGE_MSG");
+    if message_path.is_file() {
+        std::fs::remove_file(&message_path).context("remove MERGE_MSG")?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RebaseArgs {
+    /// replay the commits onto this commit instead of the upstream
+    #[arg(long, value_name = "newbase", conflicts_with = "operation")]
+    onto: Option<String>,
+    /// edit the list of commits to replay first, to reword, squash, fix up or drop them
+    #[arg(short, long, conflicts_with = "operation")]
+    interactive: bool,
+    #[command(flatten)]
+    sequencer: SequencerArgs,
+    /// the commits not in this branch are replayed (the tracked branch by default)
+    #[arg(conflicts_with = "operation")]
+    upstream: Option<String>,
+    /// the branch to switch to and rebase (the current branch by default)
+    #[arg(requires = "upstream")]
+    branch: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::reflog::read_reflog;
+    use crate::utils::test::TempRepo;
+
+    /// Write a commit with some files (a root commit without a parent), returning its hash.
+    fn commit(repo: &Repository, parent: &str, files: &[(&str, &str)], message: &str) -> String {
+        let mut index = Index::default();
+        for (path, content) in files {
+            let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+        }
+        let tree = index.write_tree(repo, false).unwrap();
+        let parents = [parent].into_iter().filter(|parent| !parent.is_empty());
+        create_commit(repo, &tree, parents.map(String::from).collect(), message).unwrap()
+    }
+
+    /// Set up a repository whose `main` and `topic` branches changed the same line
+    /// of `f`, `topic` then adding `g`, with `topic` checked out.
+    fn setup() -> (TempRepo, Repository, [String; 4]) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/topic").unwrap();
+
+        let base = commit(&repo, "", &[("f", "a\nb\nc\n")], "base\n");
+        let change = commit(&repo, &base, &[("f", "a\nB\nc\n")], "topic change\n");
+        let files = [("f", "a\nB\nc\n"), ("g", "g\n")];
+        let add = commit(&repo, &change, &files, "add g\n");
+        let main = commit(&repo, &base, &[("f", "a\nX\nc\n")], "main change\n");
+        write_ref(&repo, "refs/heads/main", &main).unwrap();
+        write_ref(&repo, "refs/heads/topic", &add).unwrap();
+
+        let mut index = Index::default();
+        for (path, content) in files {
+            fs::write(temp.join(path), content).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.into(), 0o100644, hash));
+        }
+        index.refresh(temp.path()).unwrap();
+        index.write(&repo).unwrap();
+        (temp, repo, [base, change, add, main])
+    }
+
+    fn operation(repo: &Repository, operation: SequencerOperation) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        run_operation(repo, operation, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn rebases_and_continues_after_conflicts() {
+        let (temp, repo, [_, change, add, main]) = setup();
+
+        let mut output = Vec::new();
+        let err = start(&repo, Some("main"), None, None, false, &mut output).unwrap_err();
+        assert!(err.to_string().starts_with(&format!(
+            "could not apply {}... topic change\n",
+            &change[..7]
+        )));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Auto-merging f\nCONFLICT (content): Merge conflict in f\n"
+        );
+        assert_eq!(resolve_ref(&repo, "HEAD").unwrap(), Some(main.clone()));
+        assert_eq!(resolve_ref(&repo, "REBASE_HEAD").unwrap(), Some(change));
+        assert_eq!(resolve_ref(&repo, "ORIG_HEAD").unwrap(), Some(add.clone()));
+        assert!(!fs::exists(temp.join("g")).unwrap());
+
+        let err = operation(&repo, SequencerOperation::Continue).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "f: needs merge\nYou must edit all merge conflicts and then\n\
+             mark them as resolved using git add"
+        );
+        fs::write(temp.join("f"), "a\nY\nc\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"a\nY\nc\n").unwrap();
+        index.add_entry(IndexEntry::new("f".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+
+        let output = operation(&repo, SequencerOperation::Continue).unwrap();
+        let head = resolve_ref(&repo, "refs/heads/topic").unwrap().unwrap();
+        let head_commit = read_commit(&repo, &head).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "[detached HEAD {}] topic change\n\
+                 Successfully rebased and updated refs/heads/topic.\n",
+                &head_commit.parents[0][..7]
+            )
+        );
+        let resolved = read_commit(&repo, &head_commit.parents[0]).unwrap();
+        assert_eq!(resolved.parents, [main.as_str()]);
+        assert_eq!(resolved.message, b"topic change\n");
+        assert_eq!(head_branch(&repo).unwrap().unwrap(), "refs/heads/topic");
+        assert_eq!(fs::read_to_string(temp.join("g")).unwrap(), "g\n");
+        assert!(!rebase::in_progress(&repo).unwrap());
+        assert_eq!(resolve_ref(&repo, "REBASE_HEAD").unwrap(), None);
+
+        let messages: Vec<_> = read_reflog(&repo, "HEAD")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(messages, [
+            "rebase (start): checkout main",
+            "rebase (continue): topic change",
+            "rebase (pick): add g",
+            "rebase (finish): returning to refs/heads/topic",
+        ]);
+        let messages: Vec<_> = read_reflog(&repo, "refs/heads/topic")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(messages, [format!(
+            "rebase (finish): refs/heads/topic onto {main}"
+        )]);
+    }
+
+    #[test]
+    fn rebases_interactively() {
+        let (mut temp, _, [base, _, _, main]) = setup();
+
+        temp.set_env(env::GIT_EDITOR, "sed -i /^pick/d");
+        let repo = temp.repo();
+        let err = start(&repo, Some(&base), None, None, true, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "nothing to do");
+        assert!(!rebase::in_progress(&repo).unwrap());
+
+        // The first commit is kept as it is, and the second one is squashed into it
+        temp.set_env(
+            env::GIT_EDITOR,
+            "sed -i 's/^pick \\(.*\\) add g/squash \\1/'",
+        );
+        let repo = temp.repo();
+        let mut output = Vec::new();
+        start(&repo, Some(&base), None, None, true, &mut output).unwrap();
+        let head = resolve_ref(&repo, "refs/heads/topic").unwrap().unwrap();
+        let head_commit = read_commit(&repo, &head).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "[detached HEAD {}] topic change\n\
+                 Successfully rebased and updated refs/heads/topic.\n",
+                &head[..7]
+            )
+        );
+        assert_eq!(head_commit.parents, [base.as_str()]);
+        assert_eq!(head_commit.message, b"topic change\n\nadd g\n");
+        assert_eq!(fs::read_to_string(temp.join("g")).unwrap(), "g\n");
+        let messages: Vec<_> = read_reflog(&repo, "HEAD")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(messages, [
+            format!("rebase (start): checkout {base}"),
+            "rebase (squash): topic change".to_string(),
+            "rebase (finish): returning to refs/heads/topic".to_string(),
+        ]);
+
+        // The message of a reworded commit is edited once its conflicts are resolved
+        temp.set_env(
+            env::GIT_EDITOR,
+            "sed -i -e s/^pick/reword/ -e s/^topic/new/",
+        );
+        let repo = temp.repo();
+        let err = start(&repo, Some("main"), None, None, true, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().starts_with("could not apply"));
+        fs::write(temp.join("f"), "a\nY\nc\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"a\nY\nc\n").unwrap();
+        index.add_entry(IndexEntry::new("f".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+        operation(&repo, SequencerOperation::Continue).unwrap();
+        let head = resolve_ref(&repo, "refs/heads/topic").unwrap().unwrap();
+        let head_commit = read_commit(&repo, &head).unwrap();
+        assert_eq!(head_commit.parents, [main]);
+        assert_eq!(head_commit.message, b"new change\n\nadd g\n");
+    }
+
+    #[test]
+    fn aborts_and_rebases_onto_other_commits() {
+        let (temp, repo, [base, change, add, main]) = setup();
+
+        let mut output = Vec::new();
+        start(&repo, Some(&base), None, None, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Current branch topic is up to date.\n"
+        );
+
+        start(&repo, Some("main"), None, None, false, &mut Vec::new()).unwrap_err();
+        let err = start(&repo, Some("main"), None, None, false, &mut Vec::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("It seems that there is already a rebase-merge directory"));
+        assert_eq!(operation(&repo, SequencerOperation::Abort).unwrap(), "");
+        assert_eq!(head_branch(&repo).unwrap().unwrap(), "refs/heads/topic");
+        assert_eq!(resolve_ref(&repo, "HEAD").unwrap(), Some(add.clone()));
+        assert_eq!(fs::read_to_string(temp.join("f")).unwrap(), "a\nB\nc\n");
+        assert!(!rebase::in_progress(&repo).unwrap());
+        let err = operation(&repo, SequencerOperation::Abort).unwrap_err();
+        assert_eq!(err.to_string(), "No rebase in progress?");
+
+        // Only the commits after the upstream are replayed onto the new base
+        let mut output = Vec::new();
+        start(&repo, Some(&change), None, Some("main"), false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Successfully rebased and updated refs/heads/topic.\n"
+        );
+        let head = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(read_commit(&repo, &head).unwrap().parents, [main]);
+        assert_eq!(fs::read_to_string(temp.join("f")).unwrap(), "a\nX\nc\n");
+        assert_eq!(fs::read_to_string(temp.join("g")).unwrap(), "g\n");
+
+        fs::write(temp.join("g"), "changed\n").unwrap();
+        let err = start(&repo, Some(&base), None, None, false, &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot rebase: You have unstaged changes.\nPlease commit or stash them."
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::protocol::receive_pack::serve;
+use crate::utils::protocol::upload_pack::ServeOptions;
+use crate::utils::repository::Repository;
+use crate::utils::transfer::open_repository;
+
+impl CommandArgs for ReceivePackArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let target = open_repository(repo, &self.directory.to_string_lossy())?.repo;
+        // Hooks run from the repository pushed to, whatever the client's directory
+        let target = target.with_cwd(target.git_dir()?);
+        let options = ServeOptions {
+            advertise_refs: self.advertise_refs,
+            stateless_rpc: self.stateless_rpc,
+        };
+        serve(&target, options, &mut std::io::stdin().lock(), writer)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ReceivePackArgs {
+    /// the repository to push to
+    directory: PathBuf,
+    /// serve a single request, without advertising the refs (as over HTTP)
+    #[arg(long)]
+    stateless_rpc: bool,
+    /// only advertise the refs
+    #[arg(long, alias = "http-backend-info-refs")]
+    advertise_refs: bool,
+}
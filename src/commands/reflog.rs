@@ -0,0 +1,194 @@
+use std::io::Write;
+
+use clap::{Args, Subcommand};
+
+use crate::commands::CommandArgs;
+use crate::utils::reflog::read_reflog;
+use crate::utils::refs::resolve_ref;
+use crate::utils::repository::Repository;
+use crate::utils::revision::ref_candidates;
+
+impl CommandArgs for ReflogArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        // Without a subcommand, the log is shown
+        match self.command.unwrap_or(ReflogCommand::Show(self.show)) {
+            ReflogCommand::Show(args) => show(repo, args, writer),
+        }
+    }
+}
+
+/// Show the updates of a ref, latest first.
+fn show<W>(repo: &Repository, args: ShowArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let name = args.reference.as_deref().unwrap_or("HEAD");
+    let candidates = ref_candidates(name);
+
+    let mut entries = Vec::new();
+    for candidate in &candidates {
+        entries = read_reflog(repo, candidate)?;
+        if !entries.is_empty() {
+            break;
+        }
+    }
+
+    // Refs that exist but are not logged (e.g. tags) have an empty log
+    if entries.is_empty() {
+        let mut exists = false;
+        for candidate in &candidates {
+            exists |= resolve_ref(repo, candidate)?.is_some();
+        }
+        if !exists {
+            anyhow::bail!("ambiguous argument '{name}': unknown revision");
+        }
+    }
+
+    let limit = args.max_count.unwrap_or(usize::MAX);
+    for (position, entry) in entries.iter().rev().enumerate().take(limit) {
+        writeln!(
+            writer,
+            "{} {name}@{{{position}}}: {}",
+            &entry.new[..7],
+            entry.message
+        )?;
+    }
+
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+pub(crate) struct ReflogArgs {
+    #[command(subcommand)]
+    command: Option<ReflogCommand>,
+    #[command(flatten)]
+    show: ShowArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum ReflogCommand {
+    /// show the updates of a ref (the default)
+    Show(ShowArgs),
+}
+
+#[derive(Args, Debug)]
+struct ShowArgs {
+    /// limit the number of entries to show
+    #[arg(short = 'n', long, value_name = "number")]
+    max_count: Option<usize>,
+    /// the ref to show the log of (HEAD by default)
+    #[arg(value_name = "ref")]
+    reference: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::Index;
+    use crate::utils::refs::{update_head, update_ref, write_ref, write_symref};
+    use crate::utils::revision::resolve_revision;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository, Vec<String>) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "Author")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_COMMITTER_NAME, "Committer")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let tree = Index::default().write_tree(&repo, false).unwrap();
+        let one = create_commit(&repo, &tree, vec![], "one\n").unwrap();
+        update_head(&repo, &one, "commit (initial): one").unwrap();
+        let two = create_commit(&repo, &tree, vec![one.clone()], "two\n").unwrap();
+        update_head(&repo, &two, "commit: two").unwrap();
+        update_ref(
+            &repo,
+            "refs/heads/side",
+            &one,
+            "branch: Created from HEAD~1",
+        )
+        .unwrap();
+
+        (temp, repo, vec![one, two])
+    }
+
+    fn show(repo: &Repository, reference: Option<&str>) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        ReflogArgs {
+            command: Some(ReflogCommand::Show(ShowArgs {
+                max_count: None,
+                reference: reference.map(str::to_string),
+            })),
+            show: ShowArgs {
+                max_count: None,
+                reference: None,
+            },
+        }
+        .run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn shows_ref_updates() {
+        let (temp, repo, commits) = setup();
+        let (one, two) = (&commits[0][..7], &commits[1][..7]);
+
+        assert_eq!(
+            show(&repo, None).unwrap(),
+            format!("{two} HEAD@{{0}}: commit: two\n{one} HEAD@{{1}}: commit (initial): one\n")
+        );
+        assert_eq!(
+            show(&repo, Some("main")).unwrap(),
+            format!("{two} main@{{0}}: commit: two\n{one} main@{{1}}: commit (initial): one\n")
+        );
+        assert_eq!(
+            show(&repo, Some("side")).unwrap(),
+            format!("{one} side@{{0}}: branch: Created from HEAD~1\n")
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join(".git/logs/refs/heads/side")).unwrap(),
+            format!(
+                "{} {} Committer <committer@example.com> 1700000000 +0000\tbranch: Created from HEAD~1\n",
+                "0".repeat(40),
+                commits[0]
+            )
+        );
+
+        // Tags are not logged by default
+        write_ref(&repo, "refs/tags/v1", &commits[0]).unwrap();
+        assert_eq!(show(&repo, Some("v1")).unwrap(), "");
+        let err = show(&repo, Some("missing")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ambiguous argument 'missing': unknown revision"
+        );
+    }
+
+    #[test]
+    fn resolves_reflog_revisions() {
+        let (_temp, repo, commits) = setup();
+
+        assert_eq!(resolve_revision(&repo, "HEAD@{0}").unwrap(), commits[1]);
+        assert_eq!(resolve_revision(&repo, "HEAD@{1}").unwrap(), commits[0]);
+        assert_eq!(resolve_revision(&repo, "main@{1}").unwrap(), commits[0]);
+        assert_eq!(resolve_revision(&repo, "@{1}").unwrap(), commits[0]);
+        assert_eq!(resolve_revision(&repo, "HEAD@{0}~1").unwrap(), commits[0]);
+
+        let err = resolve_revision(&repo, "HEAD@{2}").unwrap_err();
+        assert_eq!(err.to_string(), "log for 'HEAD' only has 2 entries");
+        assert!(resolve_revision(&repo, "HEAD@{x}").is_err());
+        assert!(resolve_revision(&repo, "missing@{0}").is_err());
+    }
+}
@@ -0,0 +1,387 @@
+use std::io::Write;
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::advice::warning;
+use crate::utils::commit::Commit;
+use crate::utils::objects::{read_original_object, write_object, ObjectType};
+use crate::utils::refs::{delete_ref, is_valid_ref_name, list_refs, resolve_ref, write_ref};
+use crate::utils::replace::REPLACE_REF_PREFIX;
+use crate::utils::repository::Repository;
+use crate::utils::revision::{resolve_commit, resolve_revision};
+use crate::utils::wildmatch::wildmatch;
+
+impl CommandArgs for ReplaceArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.delete {
+            return delete(repo, &self.objects, writer);
+        }
+        if self.convert_graft_file {
+            return convert_graft_file(repo, self.force);
+        }
+        if let Some(commit) = &self.graft {
+            return create_graft(repo, commit, &self.objects, self.force, false);
+        }
+
+        match self.objects.as_slice() {
+            [] => self.list(repo, None, writer),
+            [pattern] if self.list => self.list(repo, Some(pattern), writer),
+            [object, replacement] if !self.list => {
+                let object = resolve_revision(repo, object)
+                    .context(format!("failed to resolve '{object}' as a valid ref"))?;
+                let replacement = resolve_revision(repo, replacement)
+                    .context(format!("failed to resolve '{replacement}' as a valid ref"))?;
+                replace(repo, &self.objects[0], &object, &replacement, self.force)
+            },
+            _ => anyhow::bail!("bad number of arguments"),
+        }
+    }
+}
+
+impl ReplaceArgs {
+    /// List the replaced objects whose hash matches a pattern (all of them by default).
+    fn list<W>(
+        &self,
+        repo: &Repository,
+        pattern: Option<&str>,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        for (name, replacement) in list_refs(repo, REPLACE_REF_PREFIX)? {
+            let object = &name[REPLACE_REF_PREFIX.len()..];
+            if pattern.is_some_and(|pattern| !wildmatch(pattern, object, false)) {
+                continue;
+            }
+
+            match self.format {
+                Format::Short => writeln!(writer, "{object}")?,
+                Format::Medium => writeln!(writer, "{object} -> {replacement}")?,
+                Format::Long => {
+                    let object_type = read_original_object(repo, object)?.0;
+                    let replacement_type = read_original_object(repo, &replacement)?.0;
+                    writeln!(
+                        writer,
+                        "{object} ({object_type}) -> {replacement} ({replacement_type})"
+                    )?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Delete the replace refs of objects, reporting the ones that do not exist.
+fn delete<W>(repo: &Repository, objects: &[String], writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if objects.is_empty() {
+        anyhow::bail!("-d needs at least one argument");
+    }
+
+    let mut failed = false;
+    for object in objects {
+        let name = format!("{REPLACE_REF_PREFIX}{object}");
+        if resolve_ref(repo, &name)?.is_none() {
+            eprintln!("error: replace ref '{object}' not found");
+            failed = true;
+            continue;
+        }
+        delete_ref(repo, &name)?;
+        writeln!(writer, "Deleted replace ref '{object}'")?;
+    }
+
+    if failed {
+        return Err(SilentExit(1).into());
+    }
+    Ok(())
+}
+
+/// Point the replace ref of an object at its replacement, which must be of the
+/// same type unless forced.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the objects
+/// * `name` - The name the object was given as (e.g. `HEAD`), for messages
+/// * `object` - The hash of the replaced object
+/// * `replacement` - The hash of the replacement
+/// * `force` - Whether an existing replacement or a different type is allowed
+fn replace(
+    repo: &Repository,
+    name: &str,
+    object: &str,
+    replacement: &str,
+    force: bool,
+) -> anyhow::Result<()> {
+    let object_type = read_original_object(repo, object)?.0;
+    let replacement_type = read_original_object(repo, replacement)?.0;
+    if !force && object_type != replacement_type {
+        anyhow::bail!(
+            "Objects must be of the same type.\n\
+             '{name}' points to a replaced object of type '{object_type}'\n\
+             while '{replacement}' points to a replacement object of type '{replacement_type}'."
+        );
+    }
+
+    let replace_ref = format!("{REPLACE_REF_PREFIX}{object}");
+    if !is_valid_ref_name(&replace_ref) {
+        anyhow::bail!("'{replace_ref}' is not a valid ref name");
+    }
+    if !force && resolve_ref(repo, &replace_ref)?.is_some() {
+        anyhow::bail!("replace ref '{replace_ref}' already exists");
+    }
+    if object == replacement {
+        anyhow::bail!("new object is the same as the old one: '{object}'");
+    }
+
+    write_ref(repo, &replace_ref, replacement)
+}
+
+/// Replace a commit with a copy of it that has other parents (none for a root commit).
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the commit
+/// * `name` - The commit to graft
+/// * `parents` - The parents of the copy
+/// * `force` - Whether an existing replacement is overwritten
+/// * `gentle` - Whether a copy identical to the commit is only warned about
+fn create_graft(
+    repo: &Repository,
+    name: &str,
+    parents: &[String],
+    force: bool,
+    gentle: bool,
+) -> anyhow::Result<()> {
+    let hash = resolve_commit(repo, name).context(format!("not a valid object name: '{name}'"))?;
+    let mut commit = match read_original_object(repo, &hash)? {
+        (ObjectType::Commit, content) => Commit::parse(&content)?,
+        _ => anyhow::bail!("could not parse {name}"),
+    };
+
+    commit.parents = parents
+        .iter()
+        .map(|parent| {
+            resolve_commit(repo, parent).context(format!("not a valid object name: '{parent}'"))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    // The signature would not match the new parents
+    let signed = commit.extra_headers.len();
+    commit
+        .extra_headers
+        .retain(|(header, _)| header != "gpgsig");
+    if commit.extra_headers.len() != signed {
+        warning(&format!("the original commit '{name}' has a gpg signature"));
+        warning("the signature will be removed in the replacement commit!");
+    }
+
+    let replacement = write_object(repo, ObjectType::Commit, &commit.serialize())?;
+    if replacement == hash {
+        if gentle {
+            warning(&format!("graft for '{hash}' unnecessary"));
+            return Ok(());
+        }
+        anyhow::bail!("new commit is the same as the old one: '{hash}'");
+    }
+
+    replace(repo, name, &hash, &replacement, force)
+}
+
+/// Replace each commit of `info/grafts` with a copy of it that has the grafted
+/// parents, then remove the file if every graft was converted.
+fn convert_graft_file(repo: &Repository, force: bool) -> anyhow::Result<()> {
+    let path = repo.common_dir()?.join("info").join("grafts");
+    let content = std::fs::read_to_string(&path).context("could not read graft file")?;
+
+    let mut failed = Vec::new();
+    for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut hashes = line.split_whitespace().map(str::to_string);
+        let Some(commit) = hashes.next() else {
+            continue;
+        };
+        let parents: Vec<String> = hashes.collect();
+        if let Err(err) = create_graft(repo, &commit, &parents, force, true) {
+            eprintln!("error: {err:#}");
+            failed.push(line);
+        }
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "could not convert the following graft(s):\n{}",
+            failed.join("\n")
+        );
+    }
+    std::fs::remove_file(&path).context("remove info/grafts")
+}
+
+/// The format replacements are listed in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// The hash of the replaced object
+    Short,
+    /// The hashes of the replaced object and of its replacement
+    Medium,
+    /// The hashes and types of the replaced object and of its replacement
+    Long,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ReplaceArgs {
+    /// replace the ref of an object that is already replaced, or with an object of another type
+    #[arg(short, long)]
+    force: bool,
+    /// delete the replace refs of the objects
+    #[arg(short, long, conflicts_with_all = ["list", "graft", "convert_graft_file"])]
+    delete: bool,
+    /// list the replaced objects whose hash matches the pattern (all by default)
+    #[arg(short, long, conflicts_with_all = ["graft", "convert_graft_file"])]
+    list: bool,
+    /// the format of the list
+    #[arg(long, value_enum, default_value_t = Format::Short)]
+    format: Format,
+    /// replace a commit with a copy of it that has the given parents
+    #[arg(long, value_name = "commit", conflicts_with = "convert_graft_file")]
+    graft: Option<String>,
+    /// replace the commits of info/grafts with grafted copies, then remove the file
+    #[arg(long)]
+    convert_graft_file: bool,
+    /// the object and its replacement, the objects to delete the replace refs of,
+    /// the parents of the graft or the pattern to list
+    #[arg(value_name = "object")]
+    objects: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, read_commit};
+    use crate::utils::env;
+    use crate::utils::objects::read_object;
+    use crate::utils::refs::write_symref;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_AUTHOR_DATE, "0 +0000")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_DATE, "0 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    fn args(objects: &[&str]) -> ReplaceArgs {
+        ReplaceArgs {
+            force: false,
+            delete: false,
+            list: false,
+            format: Format::Short,
+            graft: None,
+            convert_graft_file: false,
+            objects: objects.iter().map(|object| object.to_string()).collect(),
+        }
+    }
+
+    fn replace(repo: &Repository, args: ReplaceArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn reads_replacements_in_place_of_objects() {
+        let (mut temp, repo) = setup();
+        let one = write_object(&repo, ObjectType::Blob, b"one\n").unwrap();
+        let two = write_object(&repo, ObjectType::Blob, b"two\n").unwrap();
+        let tree = write_object(&repo, ObjectType::Tree, b"").unwrap();
+
+        replace(&repo, args(&[&one, &two])).unwrap();
+        assert!(replace(&repo, args(&[&one, &two])).is_err());
+        assert!(replace(&repo, args(&[&two, &tree])).is_err());
+
+        let output = replace(&repo, ReplaceArgs {
+            format: Format::Long,
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, format!("{one} (blob) -> {two} (blob)\n"));
+
+        // Replacements are read once per repository context
+        let replaced = temp.repo();
+        assert_eq!(read_object(&replaced, &one).unwrap().1, b"two\n");
+        assert_eq!(read_original_object(&replaced, &one).unwrap().1, b"one\n");
+
+        temp.set_env(env::GIT_NO_REPLACE_OBJECTS, "1");
+        let original = temp.repo();
+        assert_eq!(read_object(&original, &one).unwrap().1, b"one\n");
+
+        let output = replace(&repo, ReplaceArgs {
+            delete: true,
+            ..args(&[&one])
+        })
+        .unwrap();
+        assert_eq!(output, format!("Deleted replace ref '{one}'\n"));
+        assert!(replace(&repo, ReplaceArgs {
+            delete: true,
+            ..args(&[&one])
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn grafts_commits() {
+        let (temp, repo) = setup();
+        let root = create_commit(&repo, EMPTY_TREE, Vec::new(), "root\n").unwrap();
+        let other = create_commit(&repo, EMPTY_TREE, Vec::new(), "other\n").unwrap();
+        let child = create_commit(&repo, EMPTY_TREE, vec![root.clone()], "child\n").unwrap();
+
+        replace(&repo, ReplaceArgs {
+            graft: Some(child.clone()),
+            ..args(&[&other])
+        })
+        .unwrap();
+        let grafted = temp.repo();
+        assert_eq!(read_commit(&grafted, &child).unwrap().parents, [other]);
+
+        // A commit becoming the same as the original is refused
+        assert!(replace(&repo, ReplaceArgs {
+            graft: Some(root.clone()),
+            force: true,
+            ..args(&[])
+        })
+        .is_err());
+
+        fs::create_dir_all(temp.join(".git/info")).unwrap();
+        fs::write(temp.join(".git/info/grafts"), format!("{root} {child}\n")).unwrap();
+        replace(&repo, ReplaceArgs {
+            convert_graft_file: true,
+            ..args(&[])
+        })
+        .unwrap();
+        assert!(!temp.join(".git/info/grafts").exists());
+        let output = replace(&repo, args(&[])).unwrap();
+        let mut expected = [root, child];
+        expected.sort();
+        assert_eq!(output, format!("{}\n", expected.join("\n")));
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+use crate::commands::add::index_entry;
+use crate::commands::CommandArgs;
+use crate::utils::config::Config;
+use crate::utils::convert::Conversion;
+use crate::utils::diff::write_hunks;
+use crate::utils::index::{Index, IndexEntry};
+use crate::utils::merge::merge_content;
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::pathspec::Pathspec;
+use crate::utils::repository::Repository;
+use crate::utils::rerere::{
+    conflict_dir, is_enabled, parse_conflicts, read_merge_rr, remove_merge_rr, rr_cache_dir,
+    write_merge_rr,
+};
+use crate::utils::work_tree::WorkTreeFile;
+
+/// How long the conflicts are kept by `gc`, in days, when they were resolved and
+/// when they were not, unless `rerere.resolved` and `rerere.unresolved` are set
+const RESOLVED_DAYS: u64 = 60;
+const UNRESOLVED_DAYS: u64 = 15;
+
+impl CommandArgs for RerereArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let Some(command) = self.command else {
+            return rerere(repo);
+        };
+        if !is_enabled(repo)? {
+            return Ok(());
+        }
+
+        match command {
+            RerereCommand::Clear => clear(repo),
+            RerereCommand::Forget(args) => {
+                let pathspec = Pathspec::new(&repo.prefix()?, &args.paths)?;
+                forget(repo, &pathspec)
+            },
+            RerereCommand::Status => {
+                for path in read_merge_rr(repo)?.keys() {
+                    writeln!(writer, "{path}")?;
+                }
+                Ok(())
+            },
+            RerereCommand::Remaining => {
+                for path in remaining(repo)? {
+                    writeln!(writer, "{path}")?;
+                }
+                Ok(())
+            },
+            RerereCommand::Diff => {
+                let work_tree = repo.work_tree()?;
+                for (path, id) in read_merge_rr(repo)? {
+                    let Ok(preimage) = std::fs::read(conflict_dir(repo, &id)?.join("preimage"))
+                    else {
+                        continue;
+                    };
+                    let current = std::fs::read(work_tree.join(&path)).unwrap_or_default();
+                    writeln!(writer, "--- a/{path}\n+++ b/{path}")?;
+                    write_hunks(&preimage, &current, 3, writer)?;
+                }
+                Ok(())
+            },
+            RerereCommand::Gc => gc(repo),
+        }
+    }
+}
+
+/// Record the conflicts left in the working tree by a merge, and resolve those
+/// with a recorded resolution that applies. The resolutions of the conflicts
+/// recorded before and resolved since then are recorded. Nothing is done unless
+/// rerere is enabled.
+///
+/// Resolved files are staged if `rerere.autoUpdate` is set.
+pub(crate) fn rerere(repo: &Repository) -> anyhow::Result<()> {
+    if !is_enabled(repo)? {
+        return Ok(());
+    }
+    std::fs::create_dir_all(rr_cache_dir(repo)?).context("create rr-cache")?;
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::load(repo)?;
+    let mut paths = read_merge_rr(repo)?;
+
+    // The conflicts found again are recorded anew
+    for (path, stages) in unmerged(&index) {
+        if !is_three_staged(&stages) {
+            continue;
+        }
+        let Ok(content) = std::fs::read(work_tree.join(&path)) else {
+            continue;
+        };
+        let Some(conflicts) = parse_conflicts(&content) else {
+            continue;
+        };
+        if let Some(id) = paths.insert(path, conflicts.id) {
+            forget_conflict(repo, &id)?;
+        }
+    }
+
+    let mut resolved = Vec::new();
+    for (path, id) in paths.clone() {
+        let full_path = work_tree.join(&path);
+        let Ok(content) = std::fs::read(&full_path) else {
+            continue;
+        };
+        let dir = conflict_dir(repo, &id)?;
+        std::fs::create_dir_all(&dir).context(format!("create {}", dir.display()))?;
+
+        // The conflicts were resolved by hand
+        let Some(conflicts) = parse_conflicts(&content) else {
+            std::fs::write(dir.join("postimage"), &content).context("write postimage")?;
+            eprintln!("Recorded resolution for '{path}'.");
+            paths.remove(&path);
+            continue;
+        };
+
+        // The changes made by the recorded resolution are merged into the file
+        let preimage = std::fs::read(dir.join("preimage"));
+        let postimage = std::fs::read(dir.join("postimage"));
+        if let (Ok(preimage), Ok(postimage)) = (preimage, postimage) {
+            let merged = merge_content(&preimage, &conflicts.content, &postimage, ("", ""));
+            if merged.conflicts == 0 {
+                std::fs::write(&full_path, &merged.content)
+                    .context(format!("write {}", full_path.display()))?;
+                // Resolutions used recently are kept longer by `gc`
+                std::fs::File::options()
+                    .write(true)
+                    .open(dir.join("postimage"))
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+                    .context("update postimage")?;
+                resolved.push(path.clone());
+                paths.remove(&path);
+                continue;
+            }
+        }
+
+        std::fs::write(dir.join("preimage"), &conflicts.content).context("write preimage")?;
+        remove_file(&dir.join("postimage"))?;
+        eprintln!("Recorded preimage for '{path}'");
+    }
+
+    if Config::load(repo)?
+        .get_bool("rerere.autoUpdate")?
+        .unwrap_or(false)
+    {
+        let mut conversion = Conversion::load(repo)?;
+        for path in &resolved {
+            let file = WorkTreeFile {
+                path: path.as_bytes().to_vec(),
+                metadata: work_tree.join(path).symlink_metadata()?,
+            };
+            index.add_entry(index_entry(repo, &work_tree, file, &mut conversion)?);
+            eprintln!("Staged '{path}' using previous resolution.");
+        }
+        index.write(repo)?;
+    } else {
+        for path in &resolved {
+            eprintln!("Resolved '{path}' using previous resolution.");
+        }
+    }
+    write_merge_rr(repo, &paths)
+}
+
+/// Forget the conflicts waiting for a resolution, removing those that were never
+/// resolved, as when the merge that left them is aborted.
+pub(crate) fn clear(repo: &Repository) -> anyhow::Result<()> {
+    if !is_enabled(repo)? {
+        return Ok(());
+    }
+    for id in read_merge_rr(repo)?.values() {
+        if !conflict_dir(repo, id)?.join("postimage").exists() {
+            forget_conflict(repo, id)?;
+        }
+    }
+    remove_merge_rr(repo)
+}
+
+/// Forget the recorded resolutions of the conflicts of the unmerged paths matching
+/// a pathspec, recreating the conflicts from the stages of the index so that their
+/// resolutions get recorded again.
+fn forget(repo: &Repository, pathspec: &Pathspec) -> anyhow::Result<()> {
+    let index = Index::load(repo)?;
+    let mut paths = read_merge_rr(repo)?;
+
+    for (path, stages) in unmerged(&index) {
+        if !is_three_staged(&stages) || !pathspec.matches(path.as_bytes()) {
+            continue;
+        }
+        let mut contents = Vec::new();
+        for entry in stages {
+            contents.push(match entry {
+                Some(entry) => read_object_of_type(repo, &entry.hash, ObjectType::Blob)?,
+                None => Vec::new(),
+            });
+        }
+        let merged = merge_content(&contents[0], &contents[1], &contents[2], ("", ""));
+        let Some(conflicts) = parse_conflicts(&merged.content) else {
+            eprintln!("error: could not parse conflict hunks in '{path}'");
+            continue;
+        };
+
+        let dir = conflict_dir(repo, &conflicts.id)?;
+        match std::fs::remove_file(dir.join("postimage")) {
+            Ok(()) => {},
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("error: no remembered resolution for '{path}'");
+                continue;
+            },
+            Err(err) => return Err(err).context("remove postimage"),
+        }
+        std::fs::write(dir.join("preimage"), &conflicts.content).context("write preimage")?;
+        eprintln!("Updated preimage for '{path}'");
+        paths.insert(path.clone(), conflicts.id);
+        eprintln!("Forgot resolution for '{path}'");
+    }
+    write_merge_rr(repo, &paths)
+}
+
+/// The paths waiting for a resolution that are not resolved in the index, and the
+/// unmerged paths whose conflicts cannot be recorded (such as a file deleted by
+/// one side).
+fn remaining(repo: &Repository) -> anyhow::Result<Vec<String>> {
+    let index = Index::load(repo)?;
+    let mut remaining: Vec<String> = read_merge_rr(repo)?
+        .into_keys()
+        .filter(|path| index.entry(path.as_bytes(), 0).is_none())
+        .collect();
+    for (path, stages) in unmerged(&index) {
+        if !is_three_staged(&stages) {
+            remaining.push(path);
+        }
+    }
+    remaining.sort_unstable();
+    remaining.dedup();
+    Ok(remaining)
+}
+
+/// Remove the conflicts not resolved for `rerere.unresolved` days (15 by
+/// default), and the resolutions not used for `rerere.resolved` days (60).
+pub(crate) fn gc(repo: &Repository) -> anyhow::Result<()> {
+    if !is_enabled(repo)? {
+        return Ok(());
+    }
+    let config = Config::load(repo)?;
+    let days = |key: &str, default: u64| -> anyhow::Result<Duration> {
+        let days = match config.get(key) {
+            Some(value) => value
+                .parse::<u64>()
+                .ok()
+                .context(format!("{key} must be a number of days"))?,
+            None => default,
+        };
+        Ok(Duration::from_secs(days * 24 * 60 * 60))
+    };
+    let resolved = days("rerere.resolved", RESOLVED_DAYS)?;
+    let unresolved = days("rerere.unresolved", UNRESOLVED_DAYS)?;
+
+    let cache = rr_cache_dir(repo)?;
+    let Ok(entries) = std::fs::read_dir(&cache) else {
+        return Ok(());
+    };
+    let now = SystemTime::now();
+    for entry in entries {
+        let dir = entry.context("read rr-cache")?.path();
+        let postimage = dir.join("postimage");
+        let (file, kept) = match postimage.exists() {
+            true => (postimage, resolved),
+            false => (dir.join("preimage"), unresolved),
+        };
+        let modified = file
+            .metadata()
+            .or_else(|_| dir.metadata())
+            .and_then(|metadata| metadata.modified())
+            .context(format!("read {}", dir.display()))?;
+        if now.duration_since(modified).unwrap_or_default() > kept {
+            std::fs::remove_dir_all(&dir).context(format!("remove {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// The unmerged paths of an index, with their stages (base, ours and theirs).
+fn unmerged(index: &Index) -> BTreeMap<String, [Option<&IndexEntry>; 3]> {
+    let mut unmerged: BTreeMap<String, [Option<&IndexEntry>; 3]> = BTreeMap::new();
+    for entry in index.entries().iter().filter(|entry| entry.stage != 0) {
+        let path = String::from_utf8_lossy(&entry.path).into_owned();
+        unmerged.entry(path).or_default()[entry.stage as usize - 1] = Some(entry);
+    }
+    unmerged
+}
+
+/// Whether both sides of an unmerged path are regular files, whose conflicts can
+/// be recorded.
+fn is_three_staged(stages: &[Option<&IndexEntry>; 3]) -> bool {
+    stages[1..]
+        .iter()
+        .all(|entry| entry.is_some_and(|entry| entry.mode & 0o170000 == 0o100000))
+}
+
+/// Remove the recorded conflict (and resolution) with an ID.
+fn forget_conflict(repo: &Repository, id: &str) -> anyhow::Result<()> {
+    let dir = conflict_dir(repo, id)?;
+    remove_file(&dir.join("preimage"))?;
+    remove_file(&dir.join("postimage"))?;
+    // Other files are left in place
+    let _ = std::fs::remove_dir(&dir);
+    Ok(())
+}
+
+fn remove_file(path: &Path) -> anyhow::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).context(format!("remove {}", path.display()))
+        },
+        _ => Ok(()),
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RerereArgs {
+    #[command(subcommand)]
+    command: Option<RerereCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum RerereCommand {
+    /// forget the conflicts waiting for a resolution, as when a merge is aborted
+    Clear,
+    /// forget the recorded resolutions of the conflicts of paths
+    Forget(ForgetArgs),
+    /// show the paths whose conflicts wait for a resolution to be recorded
+    Status,
+    /// show the paths with conflicts not resolved by a recorded resolution
+    Remaining,
+    /// show the changes made to the conflicts waiting for a resolution
+    Diff,
+    /// remove the old conflicts and resolutions
+    Gc,
+}
+
+#[derive(Args, Debug)]
+struct ForgetArgs {
+    /// the paths whose resolutions to forget
+    #[arg(value_name = "pathspec", required = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::write_object;
+    use crate::utils::test::TempRepo;
+
+    const CONFLICT: &str = "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> side\nc\n";
+
+    /// Leave `f` with conflicts, in the index and the working tree.
+    fn conflict(repo: &Repository) {
+        let mut index = Index::default();
+        for (stage, content) in [(1, "a\nb\nc\n"), (2, "a\nours\nc\n"), (3, "a\ntheirs\nc\n")] {
+            let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            let mut entry = IndexEntry::new(b"f".to_vec(), 0o100644, hash);
+            entry.stage = stage;
+            index.add_entry(entry);
+        }
+        index.write(repo).unwrap();
+        fs::write(repo.work_tree().unwrap().join("f"), CONFLICT).unwrap();
+    }
+
+    fn run(repo: &Repository, command: Option<RerereCommand>) -> String {
+        let mut output = Vec::new();
+        RerereArgs { command }.run(repo, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn records_and_reuses_resolutions() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+
+        // Nothing is recorded unless enabled
+        conflict(&repo);
+        run(&repo, None);
+        assert!(!temp.join(".git/rr-cache").exists());
+
+        fs::write(temp.join(".git/config"), "[rerere]\n\tenabled = true\n").unwrap();
+        run(&repo, None);
+        let paths = read_merge_rr(&repo).unwrap();
+        let dir = conflict_dir(&repo, &paths["f"]).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("preimage")).unwrap(),
+            "a\n<<<<<<<\nours\n=======\ntheirs\n>>>>>>>\nc\n"
+        );
+        assert_eq!(run(&repo, Some(RerereCommand::Status)), "f\n");
+        assert_eq!(run(&repo, Some(RerereCommand::Remaining)), "f\n");
+
+        fs::write(temp.join("f"), "a\nboth\nc\n").unwrap();
+        assert_eq!(
+            run(&repo, Some(RerereCommand::Diff)),
+            "--- a/f\n+++ b/f\n@@ -1,7 +1,3 @@\n a\n-<<<<<<<\n-ours\n-=======\n-theirs\n\
+             ->>>>>>>\n+both\n c\n"
+        );
+        run(&repo, None);
+        assert_eq!(
+            fs::read_to_string(dir.join("postimage")).unwrap(),
+            "a\nboth\nc\n"
+        );
+        assert_eq!(run(&repo, Some(RerereCommand::Status)), "");
+
+        // The same conflicts get the recorded resolution
+        conflict(&repo);
+        run(&repo, None);
+        assert_eq!(fs::read_to_string(temp.join("f")).unwrap(), "a\nboth\nc\n");
+        assert_eq!(run(&repo, Some(RerereCommand::Remaining)), "");
+
+        // Once forgotten, the conflicts are recorded again
+        run(
+            &repo,
+            Some(RerereCommand::Forget(ForgetArgs {
+                paths: vec![PathBuf::from("f")],
+            })),
+        );
+        assert!(!dir.join("postimage").exists());
+        assert_eq!(run(&repo, Some(RerereCommand::Status)), "f\n");
+
+        // Clearing forgets the conflicts not resolved
+        run(&repo, Some(RerereCommand::Clear));
+        assert!(!dir.exists());
+        assert_eq!(run(&repo, Some(RerereCommand::Status)), "");
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::checkout::{checkout_entry, remove_empty_parents, remove_path, tree_paths};
+use crate::utils::commit::read_commit;
+use crate::utils::convert::Conversion;
+use crate::utils::index::{Index, IndexEntry, Stat};
+use crate::utils::objects::{write_object, ObjectType};
+use crate::utils::path_from_bytes;
+use crate::utils::pathspec::{is_within, normalize};
+use crate::utils::refs::resolve_ref;
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_tree;
+use crate::utils::tree::{TreeEntry, MODE_GITLINK};
+
+impl CommandArgs for RestoreArgs {
+    fn run<W>(self, repo: &Repository, _writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.paths.is_empty() {
+            anyhow::bail!("you must specify path(s) to restore");
+        }
+
+        // The index is restored from HEAD by default, the working tree from the index
+        let source = match (&self.source, self.staged) {
+            (Some(source), _) => Some(resolve_tree(repo, source)?),
+            (None, true) => Some(match resolve_ref(repo, "HEAD")? {
+                Some(head) => read_commit(repo, &head)?.tree,
+                None => write_object(repo, ObjectType::Tree, b"")?,
+            }),
+            (None, false) => None,
+        };
+
+        restore_paths(repo, &self.paths, &Restore {
+            source: source.as_deref(),
+            staged: self.staged,
+            worktree: self.worktree || !self.staged,
+            overlay: false,
+        })?;
+        Ok(())
+    }
+}
+
+/// How paths are restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Restore<'a> {
+    /// The tree to restore from, or `None` for the index
+    pub(crate) source: Option<&'a str>,
+    /// Whether to restore the index
+    pub(crate) staged: bool,
+    /// Whether to restore the working tree
+    pub(crate) worktree: bool,
+    /// Whether to keep the matched files that are not in the source,
+    /// instead of removing them
+    pub(crate) overlay: bool,
+}
+
+/// Restore the files matching some paths in the index and/or the working tree.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to restore the files of
+/// * `paths` - The paths given on the command line, each of which must match a file
+///   of the source or of the index
+/// * `restore` - Where the files are restored from and to
+///
+/// # Returns
+///
+/// The number of files written to the working tree
+pub(crate) fn restore_paths(
+    repo: &Repository,
+    paths: &[PathBuf],
+    restore: &Restore,
+) -> anyhow::Result<usize> {
+    let work_tree = repo.work_tree()?;
+    let prefix = repo.prefix()?;
+    let mut index = Index::load(repo)?;
+
+    let specs = paths
+        .iter()
+        .map(|path| normalize(&prefix, path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let matches = |path: &[u8]| specs.iter().any(|spec| is_within(path, spec));
+
+    let source: BTreeMap<Vec<u8>, TreeEntry> = match restore.source {
+        Some(tree) => tree_paths(repo, tree)?
+            .into_iter()
+            .filter(|(path, _)| matches(path))
+            .collect(),
+        None => {
+            let unmerged = index
+                .entries()
+                .iter()
+                .find(|entry| entry.stage != 0 && matches(&entry.path));
+            if let Some(entry) = unmerged {
+                anyhow::bail!(
+                    "path '{}' is unmerged",
+                    String::from_utf8_lossy(&entry.path)
+                );
+            }
+            index
+                .entries()
+                .iter()
+                .filter(|entry| matches(&entry.path))
+                .map(|entry| {
+                    let tree_entry = TreeEntry {
+                        mode: entry.mode,
+                        name: entry.path.clone(),
+                        hash: entry.hash.clone(),
+                    };
+                    (entry.path.clone(), tree_entry)
+                })
+                .collect()
+        },
+    };
+
+    for (path, spec) in paths.iter().zip(&specs) {
+        let known = source.keys().any(|path| is_within(path, spec))
+            || index
+                .entries()
+                .iter()
+                .any(|entry| is_within(&entry.path, spec));
+        if !known {
+            anyhow::bail!(
+                "pathspec '{}' did not match any file(s) known to git",
+                path.display()
+            );
+        }
+    }
+
+    // Outside of overlay mode, the tracked files missing from the source are removed
+    let mut removed: Vec<Vec<u8>> = Vec::new();
+    if !restore.overlay {
+        for entry in index.entries() {
+            if matches(&entry.path)
+                && !source.contains_key(&entry.path)
+                && removed.last() != Some(&entry.path)
+            {
+                removed.push(entry.path.clone());
+            }
+        }
+    }
+
+    let mut written = 0;
+    if restore.worktree {
+        let mut conversion = match restore.source {
+            Some(tree) => Conversion::load_tree(repo, tree)?,
+            None => Conversion::load(repo)?,
+        };
+
+        for path in &removed {
+            let full_path = work_tree.join(path_from_bytes(path));
+            if full_path.symlink_metadata().is_ok() {
+                remove_path(&full_path)?;
+                remove_empty_parents(&full_path, &work_tree);
+            }
+        }
+
+        for (path, entry) in &source {
+            // Files that already match the index are left alone
+            let current = index
+                .entry(path, 0)
+                .filter(|current| current.mode == entry.mode && current.hash == entry.hash);
+            if let Some(current) = current {
+                if !index.is_modified(current, &work_tree)? {
+                    continue;
+                }
+            }
+
+            checkout_entry(repo, entry, &work_tree, &mut conversion)?;
+            written += 1;
+        }
+    }
+
+    if restore.staged {
+        for path in &removed {
+            index.remove_entry(path);
+        }
+    }
+    for (path, entry) in &source {
+        let current = index.entry(path, 0).filter(|current| {
+            current.mode == entry.mode && current.hash == entry.hash && !current.intent_to_add
+        });
+        let mut index_entry = match current {
+            Some(current) if restore.worktree => current.clone(),
+            None if restore.staged => IndexEntry::new(path.clone(), entry.mode, entry.hash.clone()),
+            _ => continue,
+        };
+
+        // The metadata of the restored files is cached, so they are not hashed again
+        if restore.worktree && entry.mode != MODE_GITLINK {
+            let full_path = work_tree.join(path_from_bytes(path));
+            index_entry.stat = Stat::from_metadata(&full_path.symlink_metadata()?);
+        }
+        index.add_entry(index_entry);
+    }
+    index.write(repo)?;
+
+    Ok(written)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RestoreArgs {
+    /// restore from this tree instead of the index (or HEAD with --staged)
+    #[arg(short, long, value_name = "tree")]
+    source: Option<String>,
+    /// restore the index
+    #[arg(short = 'S', long)]
+    staged: bool,
+    /// restore the working tree (the default without --staged)
+    #[arg(short = 'W', long)]
+    worktree: bool,
+    /// the paths to restore
+    #[arg(value_name = "pathspec")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        fs::create_dir_all(temp.join("dir")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let mut index = Index::default();
+        for (path, content) in [("a", "a\n"), ("dir/b", "b\n")] {
+            fs::write(temp.join(path), content).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+        }
+        let tree = index.write_tree(&repo, false).unwrap();
+        index.write(&repo).unwrap();
+        let commit = create_commit(&repo, &tree, Vec::new(), "first\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        (temp, repo, tree)
+    }
+
+    fn restore(
+        repo: &Repository,
+        source: Option<&str>,
+        staged: bool,
+        worktree: bool,
+        paths: &[&str],
+    ) -> anyhow::Result<()> {
+        let args = RestoreArgs {
+            source: source.map(String::from),
+            staged,
+            worktree,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        args.run(repo, &mut Vec::new())
+    }
+
+    fn staged_hash(repo: &Repository, path: &str) -> Option<String> {
+        let index = Index::load(repo).unwrap();
+        index
+            .entry(path.as_bytes(), 0)
+            .map(|entry| entry.hash.clone())
+    }
+
+    #[test]
+    fn restores_the_working_tree_and_the_index() {
+        let (temp, repo, tree) = setup();
+        let original = staged_hash(&repo, "a");
+
+        // The working tree is restored from the index
+        fs::write(temp.join("a"), "changed\n").unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"staged\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        index.add_entry(IndexEntry::new("dir/b".into(), 0o100644, hash.clone()));
+        index.write(&repo).unwrap();
+        fs::remove_file(temp.join("dir/b")).unwrap();
+        restore(&repo, None, false, false, &["a", "dir"]).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(temp.join("dir/b")).unwrap(), "staged\n");
+
+        // The index from HEAD, then both from a tree
+        restore(&repo, None, true, false, &["dir/b"]).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("dir/b")).unwrap(), "staged\n");
+        assert_ne!(staged_hash(&repo, "dir/b"), Some(hash));
+        restore(&repo, Some(&tree), true, true, &["."]).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("dir/b")).unwrap(), "b\n");
+        assert_eq!(staged_hash(&repo, "a"), original);
+    }
+
+    #[test]
+    fn removes_files_missing_from_the_source() {
+        let (temp, repo, _) = setup();
+        fs::write(temp.join("new"), "new\n").unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"new\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        index.add_entry(IndexEntry::new("new".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+
+        restore(&repo, Some("HEAD"), false, false, &["new"]).unwrap();
+        assert!(!fs::exists(temp.join("new")).unwrap());
+        assert!(staged_hash(&repo, "new").is_some());
+        restore(&repo, None, true, false, &["new"]).unwrap();
+        assert!(staged_hash(&repo, "new").is_none());
+
+        let err = restore(&repo, None, false, false, &["a", "missing"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'missing' did not match any file(s) known to git"
+        );
+        let err = restore(&repo, None, false, false, &[]).unwrap_err();
+        assert_eq!(err.to_string(), "you must specify path(s) to restore");
+    }
+}
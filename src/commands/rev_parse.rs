@@ -0,0 +1,214 @@
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::objects::object_exists;
+use crate::utils::repository::Repository;
+use crate::utils::revision::{resolve_full_ref_name, resolve_revision};
+
+impl CommandArgs for RevParseArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.verify {
+            return self.verify(repo, writer);
+        }
+
+        for revision in &self.revisions {
+            // Ranges print their end, then their excluded start
+            if let Some((start, end)) = revision.split_once("..") {
+                let start = if start.is_empty() { "HEAD" } else { start };
+                let end = if end.is_empty() { "HEAD" } else { end };
+                let end_hash = resolve_revision(repo, end)?;
+                let start_hash = resolve_revision(repo, start)?;
+                self.show(repo, "", end, &end_hash, writer)?;
+                self.show(repo, "^", start, &start_hash, writer)?;
+            } else if let Some(excluded) = revision.strip_prefix('^') {
+                let hash = resolve_revision(repo, excluded)?;
+                self.show(repo, "^", excluded, &hash, writer)?;
+            } else {
+                let hash = resolve_revision(repo, revision)?;
+                self.show(repo, "", revision, &hash, writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RevParseArgs {
+    /// Print a resolved revision, or with `--symbolic-full-name` the full name of the
+    /// ref it names (nothing if it is not a ref name).
+    fn show<W>(
+        &self,
+        repo: &Repository,
+        prefix: &str,
+        revision: &str,
+        hash: &str,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if !self.symbolic_full_name {
+            writeln!(writer, "{prefix}{hash}")?;
+        } else if let Some(name) = resolve_full_ref_name(repo, revision)? {
+            writeln!(writer, "{prefix}{name}")?;
+        }
+        Ok(())
+    }
+
+    /// Print the object a single revision names, failing if there is not exactly one
+    /// revision or the object does not exist (silently with `--quiet`, for scripts).
+    fn verify<W>(&self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let hash = match self.revisions.as_slice() {
+            [revision] => resolve_revision(repo, revision)
+                .ok()
+                .filter(|hash| object_exists(repo, hash).is_ok_and(|exists| exists)),
+            _ => None,
+        };
+
+        match hash {
+            Some(hash) => writeln!(writer, "{hash}").map_err(Into::into),
+            None if self.quiet => Err(SilentExit(1).into()),
+            None => anyhow::bail!("Needed a single revision"),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RevParseArgs {
+    /// verify that exactly one revision is given and names an existing object
+    #[arg(long)]
+    verify: bool,
+    /// with --verify, exit with status 1 instead of showing an error
+    #[arg(short, long, requires = "verify")]
+    quiet: bool,
+    /// show the full names of the refs the revisions name instead of their objects
+    #[arg(long)]
+    symbolic_full_name: bool,
+    /// the revisions to resolve (`^<rev>` and `<rev>..<rev>` included)
+    #[arg(value_name = "rev")]
+    revisions: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, create_tag};
+    use crate::utils::env;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    fn rev_parse(
+        repo: &Repository,
+        verify: bool,
+        quiet: bool,
+        revisions: &[&str],
+    ) -> anyhow::Result<String> {
+        let args = RevParseArgs {
+            verify,
+            quiet,
+            symbolic_full_name: false,
+            revisions: revisions.iter().map(|rev| rev.to_string()).collect(),
+        };
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn resolves_revisions_and_ranges() {
+        let (_temp, repo) = setup();
+        let first = create_commit(&repo, EMPTY_TREE, Vec::new(), "first\n").unwrap();
+        let second = create_commit(&repo, EMPTY_TREE, vec![first.clone()], "second\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &second).unwrap();
+
+        assert_eq!(
+            rev_parse(&repo, false, false, &["HEAD", "^HEAD~1", "HEAD~1..main"]).unwrap(),
+            format!("{second}\n^{first}\n{second}\n^{first}\n")
+        );
+        let err = rev_parse(&repo, false, false, &["missing"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ambiguous argument 'missing': unknown revision"
+        );
+    }
+
+    #[test]
+    fn verifies_single_revisions() {
+        let (_temp, repo) = setup();
+        write_object(&repo, ObjectType::Tree, b"").unwrap();
+        let commit = create_commit(&repo, EMPTY_TREE, Vec::new(), "first\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        let tag = create_tag(&repo, &commit, "v1", "release\n").unwrap();
+        write_ref(&repo, "refs/tags/v1", &tag).unwrap();
+
+        assert_eq!(
+            rev_parse(&repo, true, false, &["v1^{commit}"]).unwrap(),
+            format!("{commit}\n")
+        );
+        assert_eq!(
+            rev_parse(&repo, true, true, &["v1^{tree}"]).unwrap(),
+            format!("{EMPTY_TREE}\n")
+        );
+
+        // Missing objects, wrong types and several revisions are all rejected
+        write_ref(&repo, "refs/heads/dangling", &"1".repeat(40)).unwrap();
+        let cases: [&[&str]; 4] = [&["missing"], &["v1^{blob}"], &["dangling"], &["HEAD", "v1"]];
+        for revisions in cases {
+            let err = rev_parse(&repo, true, false, revisions).unwrap_err();
+            assert_eq!(err.to_string(), "Needed a single revision");
+
+            let err = rev_parse(&repo, true, true, revisions).unwrap_err();
+            assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+        }
+    }
+
+    #[test]
+    fn shows_symbolic_full_names() {
+        let (_temp, repo) = setup();
+        let commit = create_commit(&repo, EMPTY_TREE, Vec::new(), "first\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        write_ref(&repo, "refs/tags/v1", &commit).unwrap();
+        write_symref(&repo, "refs/heads/alias", "refs/heads/main").unwrap();
+        write_symref(&repo, "refs/heads/alias2", "refs/heads/alias").unwrap();
+
+        let args = RevParseArgs {
+            verify: false,
+            quiet: false,
+            symbolic_full_name: true,
+            revisions: ["HEAD", "alias2", "^v1", "v1..@", "main~0", commit.as_str()]
+                .map(String::from)
+                .to_vec(),
+        };
+        let mut output = Vec::new();
+        args.run(&repo, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "refs/heads/main\nrefs/heads/main\n^refs/tags/v1\nrefs/heads/main\n^refs/tags/v1\n"
+        );
+    }
+}
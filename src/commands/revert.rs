@@ -0,0 +1,115 @@
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::cherry_pick::{run_operation, start, SequencerArgs};
+use crate::commands::CommandArgs;
+use crate::utils::repository::Repository;
+use crate::utils::sequencer::{Action, Options};
+
+impl CommandArgs for RevertArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.sequencer.operation() {
+            Some(operation) => run_operation(repo, Action::Revert, operation, writer),
+            None => {
+                let options = Options {
+                    no_commit: self.no_commit,
+                };
+                start(repo, Action::Revert, &self.commits, options, writer)
+            },
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RevertArgs {
+    /// apply the inverse changes to the index and the working tree without committing them
+    #[arg(short = 'n', long, conflicts_with = "operation")]
+    no_commit: bool,
+    #[command(flatten)]
+    sequencer: SequencerArgs,
+    /// the commits to revert (<from>..<to> for the commits of a range)
+    #[arg(
+        value_name = "commit",
+        required_unless_present = "operation",
+        conflicts_with = "operation"
+    )]
+    commits: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, read_commit};
+    use crate::utils::env;
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::reflog::read_reflog;
+    use crate::utils::refs::{resolve_ref, write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn reverts_commits() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        // The second commit changes the first line, the third one the last line
+        let mut index = Index::default();
+        let mut parents = Vec::new();
+        for (content, message) in [
+            ("a\nb\nc\n", "add f"),
+            ("A\nb\nc\n", "change a"),
+            ("A\nb\nC\n", "change c"),
+        ] {
+            fs::write(temp.join("f"), content).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new("f".into(), 0o100644, hash));
+            let tree = index.write_tree(&repo, false).unwrap();
+            parents = vec![create_commit(&repo, &tree, parents, &format!("{message}\n")).unwrap()];
+        }
+        index.refresh(temp.path()).unwrap();
+        index.write(&repo).unwrap();
+        let head = parents.remove(0);
+        write_ref(&repo, "refs/heads/main", &head).unwrap();
+        let reverted = read_commit(&repo, &head).unwrap().parents.remove(0);
+
+        let args = RevertArgs {
+            no_commit: false,
+            sequencer: SequencerArgs::default(),
+            commits: vec!["HEAD~1".to_string()],
+        };
+        let mut output = Vec::new();
+        args.run(&repo, &mut output).unwrap();
+
+        let new = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        let commit = read_commit(&repo, &new).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("Auto-merging f\n[main {}] Revert \"change a\"\n", &new[..7])
+        );
+        assert_eq!(commit.parents, [head]);
+        assert_eq!(
+            String::from_utf8(commit.message).unwrap(),
+            format!("Revert \"change a\"\n\nThis reverts commit {reverted}.\n")
+        );
+        assert_eq!(fs::read_to_string(temp.join("f")).unwrap(), "a\nb\nC\n");
+        let reflog = read_reflog(&repo, "HEAD").unwrap();
+        assert_eq!(
+            reflog.last().unwrap().message,
+            "revert: Revert \"change a\""
+        );
+    }
+}
@@ -0,0 +1,756 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::process::Stdio;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::fast_export::{export_history, export_path};
+use crate::commands::fast_import::{import_history, parse_path, Stream};
+use crate::commands::CommandArgs;
+use crate::utils::checkout::{list_local_changes, reset_tree};
+use crate::utils::commit::{read_commit, Ident};
+use crate::utils::index::Index;
+use crate::utils::pathspec::is_within;
+use crate::utils::refs::{head_branch, list_refs, resolve_ref, update_head};
+use crate::utils::repository::Repository;
+use crate::utils::tree::EMPTY_TREE;
+
+/// What a detached HEAD is logged with when it is rewritten
+const REFLOG_MESSAGE: &str = "rewrite-history: rewritten";
+
+/// The hash given to fast-import to remove a parent or delete a ref, and
+/// recorded in the commit map for commits that were pruned
+const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+impl CommandArgs for RewriteHistoryArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.invert_paths && self.paths.is_empty() {
+            anyhow::bail!("--invert-paths needs at least one --path");
+        }
+        let trim = |path: &str| path.trim_end_matches('/').as_bytes().to_vec();
+        let mut renames = Vec::new();
+        for rename in &self.path_renames {
+            let Some((old, new)) = rename.split_once(':') else {
+                anyhow::bail!("invalid --path-rename '{rename}', expected <old>:<new>");
+            };
+            renames.push((trim(old), trim(new)));
+        }
+        let max_blob_size = self
+            .strip_blobs_bigger_than
+            .as_deref()
+            .map(parse_size)
+            .transpose()?;
+
+        let mut index = Index::load(repo)?;
+        let work_tree = repo.work_tree()?;
+        let head = resolve_ref(repo, "HEAD")?;
+        if let Some(head) = head.as_ref().filter(|_| !self.force) {
+            let tree = read_commit(repo, head)?.tree;
+            if !list_local_changes(repo, &index, &work_tree, &tree)?.is_empty() {
+                anyhow::bail!(
+                    "refusing to rewrite history with local changes (use --force to rewrite anyway)"
+                );
+            }
+        }
+        let refs = rewritten_refs(repo)?;
+        let detached_head = match head_branch(repo)? {
+            Some(_) => None,
+            None => head.clone(),
+        };
+
+        // The history is exported, filtered, then imported back
+        let mut revisions = vec!["--branches".to_string(), "--tags".to_string()];
+        if detached_head.is_some() {
+            revisions.push("HEAD".to_string());
+        }
+        let mut exported = Vec::new();
+        let marks = export_history(repo, revisions, &mut exported)?;
+        let mut filter = Filter {
+            repo,
+            args: &self,
+            paths: self.paths.iter().map(|path| trim(path)).collect(),
+            subdirectory: self.subdirectory_filter.as_deref().map(trim),
+            renames,
+            max_blob_size,
+            stripped_blobs: HashSet::new(),
+            written_paths: HashSet::new(),
+            pruned: HashMap::new(),
+            commits: Vec::new(),
+            callbacks: HashMap::new(),
+        };
+        let mut filtered = Vec::new();
+        filter.run(&mut Stream::new(exported.as_slice()), &mut filtered)?;
+        let imported = import_history(repo, filtered.as_slice())?;
+
+        // The rewritten commit of each commit, following the pruned ones to
+        // the commit they were replaced with
+        let rewritten = |hash: &str| -> Option<String> {
+            let mut mark = *marks.get(hash)?;
+            loop {
+                if let Some(new) = imported.get(&mark) {
+                    return Some(new.clone());
+                }
+                match filter.pruned.get(&mark)? {
+                    Some(from) => match from.strip_prefix(':').and_then(|m| m.parse().ok()) {
+                        Some(from) => mark = from,
+                        None => return Some(from.clone()),
+                    },
+                    None => return None,
+                }
+            }
+        };
+
+        let new_refs = rewritten_refs(repo)?;
+        let mut updated = refs
+            .iter()
+            .filter(|(name, hash)| new_refs.get(*name) != Some(*hash))
+            .count();
+        if let Some(hash) = &detached_head {
+            if let Some(new) = rewritten(hash).filter(|new| new != hash) {
+                // fast-import already moved HEAD if it was exported on it
+                if resolve_ref(repo, "HEAD")?.as_ref() != Some(&new) {
+                    update_head(repo, &new, REFLOG_MESSAGE)?;
+                }
+                updated += 1;
+            }
+        }
+
+        // The rewritten commit of each commit read, parents first
+        let hashes: HashMap<u64, &String> =
+            marks.iter().map(|(hash, mark)| (*mark, hash)).collect();
+        let mut map = String::from("old new\n");
+        let mut changed = 0;
+        for mark in &filter.commits {
+            let old = hashes[mark];
+            let new = match filter.pruned.contains_key(mark) {
+                true => NULL_HASH,
+                false => imported.get(mark).map_or(NULL_HASH, String::as_str),
+            };
+            if new != NULL_HASH && new != old {
+                changed += 1;
+            }
+            map.push_str(&format!("{old} {new}\n"));
+        }
+        let dir = repo.git_dir()?.join("rewrite-history");
+        std::fs::create_dir_all(&dir).context("create rewrite-history directory")?;
+        std::fs::write(dir.join("commit-map"), map).context("write rewrite-history/commit-map")?;
+
+        // The index and the working tree follow the rewritten HEAD
+        if head.is_some() {
+            let tree = match resolve_ref(repo, "HEAD")? {
+                Some(new) => read_commit(repo, &new)?.tree,
+                None => EMPTY_TREE.to_string(),
+            };
+            reset_tree(repo, &mut index, &work_tree, &tree)?;
+            index.write(repo)?;
+        }
+
+        writeln!(
+            writer,
+            "Parsed {} commits, rewrote {changed} and pruned {}",
+            filter.commits.len(),
+            filter.pruned.len()
+        )?;
+        writeln!(writer, "Updated {updated} refs")?;
+        Ok(())
+    }
+}
+
+/// The branches and tags, with the object each one points to.
+fn rewritten_refs(repo: &Repository) -> anyhow::Result<HashMap<String, String>> {
+    let mut refs = list_refs(repo, "refs/heads/")?;
+    refs.extend(list_refs(repo, "refs/tags/")?);
+    Ok(refs.into_iter().collect())
+}
+
+/// A filter of a fast-import stream, removing and moving files and rewriting
+/// messages and identities
+struct Filter<'a> {
+    repo: &'a Repository,
+    args: &'a RewriteHistoryArgs,
+    /// The paths kept (or removed with `--invert-paths`), all of them if empty
+    paths: Vec<Vec<u8>>,
+    /// The directory made the root, the files outside of it being removed
+    subdirectory: Option<Vec<u8>>,
+    /// The directories or files moved, and their new path
+    renames: Vec<(Vec<u8>, Vec<u8>)>,
+    max_blob_size: Option<u64>,
+    /// The marks of the blobs removed for their size
+    stripped_blobs: HashSet<u64>,
+    /// The paths written with a blob that was kept
+    written_paths: HashSet<Vec<u8>>,
+    /// The marks of the commits pruned as they were left without changes,
+    /// and the commit they are replaced with (`None` if they had no parent)
+    pruned: HashMap<u64, Option<String>>,
+    /// The marks of the commits read, parents first
+    commits: Vec<u64>,
+    /// The output of each callback for each input
+    callbacks: HashMap<(String, Vec<u8>), Vec<u8>>,
+}
+
+impl Filter<'_> {
+    fn run<R, W>(&mut self, stream: &mut Stream<R>, writer: &mut W) -> anyhow::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let args = self.args;
+        while let Some(line) = stream.next_line()? {
+            if line == b"blob" {
+                let mark = stream.next_if(b"mark :")?;
+                let data = stream.read_data()?;
+                if self
+                    .max_blob_size
+                    .is_some_and(|max| data.len() as u64 > max)
+                {
+                    if let Some(mark) = mark {
+                        self.stripped_blobs.insert(parse_mark(&mark)?);
+                    }
+                    continue;
+                }
+                writer.write_all(b"blob\n")?;
+                if let Some(mark) = mark {
+                    write_line(writer, &[b"mark :", mark.as_slice()])?;
+                }
+                write_data(writer, &data)?;
+            } else if let Some(name) = line.strip_prefix(b"commit ") {
+                self.filter_commit(stream, name, writer)?;
+            } else if let Some(name) = line.strip_prefix(b"tag ") {
+                // Tags of commits pruned without a replacement are deleted
+                let from = stream.next_if(b"from ")?;
+                let from = match &from {
+                    Some(from) => self.replacement(from)?,
+                    None => None,
+                };
+                let tagger = stream.next_if(b"tagger ")?;
+                let data = stream.read_data()?;
+                let Some(from) = from else {
+                    write_reset(writer, &[b"refs/tags/", name].concat(), None)?;
+                    continue;
+                };
+                write_line(writer, &[&line])?;
+                write_line(writer, &[b"from ", from.as_bytes()])?;
+                if let Some(tagger) = tagger {
+                    let tagger = self.rewrite_ident(&tagger)?;
+                    write_line(writer, &[b"tagger ", tagger.as_slice()])?;
+                }
+                write_data(writer, &self.callback(&args.message_callback, &data)?)?;
+            } else if let Some(name) = line.strip_prefix(b"reset ") {
+                match stream.next_if(b"from ")? {
+                    Some(from) => {
+                        let from = self.replacement(&from)?;
+                        write_reset(writer, name, from.as_deref())?;
+                    },
+                    None => write_line(writer, &[&line])?,
+                }
+            } else {
+                write_line(writer, &[&line])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Filter the files changed by a commit, pruning it if none is left
+    /// (unless it is a merge, or changed no file in the first place).
+    fn filter_commit<R, W>(
+        &mut self,
+        stream: &mut Stream<R>,
+        name: &[u8],
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let args = self.args;
+        let mut headers = Vec::new();
+        let mut mark = None;
+        while let Some(line) = stream.next_line()? {
+            if line.starts_with(b"data ") {
+                stream.unread(line);
+                break;
+            }
+            if let Some(value) = line.strip_prefix(b"mark :") {
+                mark = Some(parse_mark(value)?);
+            }
+            let line = if let Some(author) = line.strip_prefix(b"author ") {
+                [b"author ", self.rewrite_ident(author)?.as_slice()].concat()
+            } else if let Some(committer) = line.strip_prefix(b"committer ") {
+                [b"committer ", self.rewrite_ident(committer)?.as_slice()].concat()
+            } else {
+                line
+            };
+            headers.push(line);
+        }
+        self.commits.extend(mark);
+        let message = stream.read_data()?;
+        let from = match stream.next_if(b"from ")? {
+            Some(from) => Some(self.replacement(&from)?),
+            None => None,
+        };
+        let mut merges = Vec::new();
+        while let Some(merge) = stream.next_if(b"merge ")? {
+            if let Some(merge) = self.replacement(&merge)? {
+                if from.as_ref() != Some(&Some(merge.clone())) && !merges.contains(&merge) {
+                    merges.push(merge);
+                }
+            }
+        }
+
+        let mut changes = Vec::new();
+        let mut original = 0;
+        while let Some(line) = stream.next_line()? {
+            if line.is_empty() {
+                break;
+            }
+            original += 1;
+            if let Some(change) = self.filter_change(&line)? {
+                changes.push(change);
+            }
+        }
+
+        if changes.is_empty() && original > 0 && merges.is_empty() {
+            let replacement = from.flatten();
+            if let Some(mark) = mark {
+                self.pruned.insert(mark, replacement.clone());
+            }
+            // The ref of the commit is moved to its replacement, in case it was its tip
+            return write_reset(writer, name, replacement.as_deref());
+        }
+
+        write_line(writer, &[b"commit ", name])?;
+        for header in headers {
+            write_line(writer, &[&header])?;
+        }
+        write_data(writer, &self.callback(&args.message_callback, &message)?)?;
+        if let Some(from) = from {
+            let from = from.as_deref().unwrap_or(NULL_HASH);
+            write_line(writer, &[b"from ", from.as_bytes()])?;
+        }
+        for merge in merges {
+            write_line(writer, &[b"merge ", merge.as_bytes()])?;
+        }
+        for change in changes {
+            write_line(writer, &[&change])?;
+        }
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Filter a file command, removing the files that are not kept (or
+    /// replacing them with a deletion if their blob is stripped) and moving them.
+    fn filter_change(&mut self, line: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(rest) = line.strip_prefix(b"M ") {
+            let mut fields = rest.splitn(3, |&b| b == b' ');
+            let (Some(mode), Some(data), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                anyhow::bail!("invalid file command: {}", String::from_utf8_lossy(line));
+            };
+            let Some(path) = self.filter_path(&parse_path(path)?) else {
+                return Ok(None);
+            };
+            let stripped = match data.strip_prefix(b":") {
+                Some(mark) => self.stripped_blobs.contains(&parse_mark(mark)?),
+                None => false,
+            };
+            if stripped {
+                // The file is deleted in case an earlier version of it was kept
+                if !self.written_paths.contains(&path) {
+                    return Ok(None);
+                }
+                return Ok(Some([b"D ", export_path(&path).as_slice()].concat()));
+            }
+            self.written_paths.insert(path.clone());
+            Ok(Some(
+                [b"M ", mode, b" ", data, b" ", &export_path(&path)].concat(),
+            ))
+        } else if let Some(path) = line.strip_prefix(b"D ") {
+            let path = self.filter_path(&parse_path(path)?);
+            Ok(path.map(|path| [b"D ", export_path(&path).as_slice()].concat()))
+        } else {
+            Ok(Some(line.to_vec()))
+        }
+    }
+
+    /// The path a file is moved to, or `None` if it is removed.
+    fn filter_path(&self, path: &[u8]) -> Option<Vec<u8>> {
+        if !self.paths.is_empty() {
+            let listed = self.paths.iter().any(|kept| is_within(path, kept));
+            if listed == self.args.invert_paths {
+                return None;
+            }
+        }
+        let path = match &self.subdirectory {
+            Some(dir) => match path.strip_prefix(dir.as_slice()) {
+                Some([b'/', rest @ ..]) => rest,
+                _ => return None,
+            },
+            None => path,
+        };
+
+        for (old, new) in &self.renames {
+            if !is_within(path, old) {
+                continue;
+            }
+            let rest = &path[old.len()..];
+            return Some(match (old.is_empty(), new.is_empty()) {
+                (true, _) => [new.as_slice(), b"/", path].concat(),
+                (false, true) => rest.strip_prefix(b"/").unwrap_or(rest).to_vec(),
+                (false, false) => [new.as_slice(), rest].concat(),
+            });
+        }
+        Some(path.to_vec())
+    }
+
+    /// The commit a `from` or `merge` value refers to once the pruned
+    /// commits are replaced, `None` if it was pruned without a replacement.
+    fn replacement(&self, value: &[u8]) -> anyhow::Result<Option<String>> {
+        let mut value = String::from_utf8(value.to_vec())?;
+        while let Some(mark) = value.strip_prefix(':') {
+            match self.pruned.get(&mark.parse()?) {
+                Some(Some(replacement)) => value = replacement.clone(),
+                Some(None) => return Ok(None),
+                None => break,
+            }
+        }
+        Ok(Some(value))
+    }
+
+    /// Rewrite the name and email of an author, committer or tagger.
+    fn rewrite_ident(&mut self, value: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let args = self.args;
+        if args.name_callback.is_none() && args.email_callback.is_none() {
+            return Ok(value.to_vec());
+        }
+        let mut ident = Ident::parse(value)?;
+        ident.name = self.callback(&args.name_callback, &ident.name)?;
+        ident.email = self.callback(&args.email_callback, &ident.email)?;
+        for value in [&mut ident.name, &mut ident.email] {
+            while value.last().is_some_and(|&b| b == b'\n') {
+                value.pop();
+            }
+        }
+        Ok(ident.format())
+    }
+
+    /// Run a callback, a shell command given the value on its standard input and
+    /// printing its replacement.
+    fn callback(&mut self, command: &Option<String>, value: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let Some(command) = command else {
+            return Ok(value.to_vec());
+        };
+        let key = (command.clone(), value.to_vec());
+        if let Some(output) = self.callbacks.get(&key) {
+            return Ok(output.clone());
+        }
+
+        let mut child = self
+            .repo
+            .command("sh")
+            .args(["-c", command])
+            .current_dir(self.repo.work_tree()?)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context(format!("run callback '{command}'"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(value)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!("callback '{command}' failed");
+        }
+
+        self.callbacks.insert(key, output.stdout.clone());
+        Ok(output.stdout)
+    }
+}
+
+/// Point a ref at a commit, or delete it if there is none. A detached HEAD
+/// is never deleted, and is left to point to its original commit instead.
+fn write_reset<W>(writer: &mut W, name: &[u8], from: Option<&str>) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if from.is_none() && name == b"HEAD" {
+        return Ok(());
+    }
+    write_line(writer, &[b"reset ", name])?;
+    write_line(writer, &[b"from ", from.unwrap_or(NULL_HASH).as_bytes()])?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+fn parse_mark(mark: &[u8]) -> anyhow::Result<u64> {
+    let mark = std::str::from_utf8(mark)?;
+    mark.parse()
+        .map_err(|_| anyhow::anyhow!("invalid mark :{mark}"))
+}
+
+fn write_line<W>(writer: &mut W, parts: &[&[u8]]) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    for part in parts {
+        writer.write_all(part)?;
+    }
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+fn write_data<W>(writer: &mut W, data: &[u8]) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "data {}", data.len())?;
+    writer.write_all(data)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Parse a size in bytes, with an optional `k`, `m` or `g` suffix.
+fn parse_size(value: &str) -> anyhow::Result<u64> {
+    let lower = value.to_ascii_lowercase();
+    let (number, unit) = match lower.char_indices().last() {
+        Some((i, 'k')) => (&lower[..i], 1 << 10),
+        Some((i, 'm')) => (&lower[..i], 1 << 20),
+        Some((i, 'g')) => (&lower[..i], 1 << 30),
+        _ => (lower.as_str(), 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid size '{value}'"))?;
+    Ok(number * unit)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RewriteHistoryArgs {
+    /// keep only the files at this path (a file or directory), can be repeated
+    #[arg(long = "path", value_name = "path")]
+    paths: Vec<String>,
+    /// remove the files at the paths instead of keeping only them
+    #[arg(long)]
+    invert_paths: bool,
+    /// make this subdirectory the root of the history, removing the other files
+    #[arg(long, value_name = "directory")]
+    subdirectory_filter: Option<String>,
+    /// move the files at a path (a file or directory) to another path, an
+    /// empty path being the root, can be repeated
+    #[arg(long = "path-rename", value_name = "old:new")]
+    path_renames: Vec<String>,
+    /// remove the files bigger than this size (with an optional k, m or g suffix)
+    #[arg(long, value_name = "size")]
+    strip_blobs_bigger_than: Option<String>,
+    /// rewrite the messages of commits and tags with this command, given each
+    /// message on its standard input
+    #[arg(long, value_name = "command")]
+    message_callback: Option<String>,
+    /// rewrite the names of authors, committers and taggers with this command
+    #[arg(long, value_name = "command")]
+    name_callback: Option<String>,
+    /// rewrite the emails of authors, committers and taggers with this command
+    #[arg(long, value_name = "command")]
+    email_callback: Option<String>,
+    /// rewrite the history even if there are local changes
+    #[arg(short, long)]
+    force: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{update_ref, write_symref};
+    use crate::utils::revision::resolve_commit;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::flatten_tree;
+    use crate::utils::work_tree::hash_file;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    /// Write files to the working tree and commit them (and the tracked files) on HEAD.
+    fn commit_work_tree(repo: &Repository, files: &[(&str, &str)], message: &str) -> String {
+        let root = repo.work_tree().unwrap();
+        let mut index = Index::load(repo).unwrap();
+        for (path, content) in files {
+            let file = root.join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, content).unwrap();
+            let metadata = fs::metadata(&file).unwrap();
+            let hash = hash_file(&file, &metadata).unwrap();
+            write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::from_metadata(
+                path.as_bytes().to_vec(),
+                hash,
+                &metadata,
+            ));
+        }
+
+        let tree = index.write_tree(repo, false).unwrap();
+        index.write(repo).unwrap();
+        let parents = resolve_commit(repo, "HEAD").into_iter().collect();
+        let commit = create_commit(repo, &tree, parents, &format!("{message}\n")).unwrap();
+        update_head(repo, &commit, "commit").unwrap();
+        commit
+    }
+
+    fn args() -> RewriteHistoryArgs {
+        RewriteHistoryArgs {
+            paths: Vec::new(),
+            invert_paths: false,
+            subdirectory_filter: None,
+            path_renames: Vec::new(),
+            strip_blobs_bigger_than: None,
+            message_callback: None,
+            name_callback: None,
+            email_callback: None,
+            force: false,
+        }
+    }
+
+    fn rewrite(repo: &Repository, args: RewriteHistoryArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn history(repo: &Repository) -> Vec<(String, Vec<String>)> {
+        let mut history = Vec::new();
+        let mut next = resolve_ref(repo, "HEAD").unwrap();
+        while let Some(hash) = next {
+            let commit = read_commit(repo, &hash).unwrap();
+            let paths = flatten_tree(repo, &commit.tree).unwrap();
+            let paths = paths
+                .iter()
+                .map(|entry| String::from_utf8_lossy(&entry.name));
+            history.push((commit.subject(), paths.map(String::from).collect()));
+            next = commit.parents.first().cloned();
+        }
+        history
+    }
+
+    #[test]
+    fn filters_paths_and_prunes_emptied_commits() {
+        let (temp, repo) = setup();
+        let one = commit_work_tree(&repo, &[("lib/a", "a"), ("docs/d", "d")], "one");
+        let two = commit_work_tree(&repo, &[("docs/d", "dd")], "docs");
+        commit_work_tree(&repo, &[("lib/b", "b"), ("big", &"x".repeat(2048))], "lib");
+
+        fs::write(temp.join("lib/a"), "changed").unwrap();
+        let error = rewrite(&repo, args()).unwrap_err();
+        assert!(error.to_string().contains("local changes"));
+        fs::write(temp.join("lib/a"), "a").unwrap();
+
+        let filter = RewriteHistoryArgs {
+            paths: vec!["docs/".to_string()],
+            invert_paths: true,
+            strip_blobs_bigger_than: Some("1k".to_string()),
+            ..args()
+        };
+        let output = rewrite(&repo, filter).unwrap();
+        assert_eq!(
+            output,
+            "Parsed 3 commits, rewrote 2 and pruned 1\nUpdated 1 refs\n"
+        );
+        assert_eq!(history(&repo), [
+            ("lib".to_string(), vec![
+                "lib/a".to_string(),
+                "lib/b".to_string()
+            ]),
+            ("one".to_string(), vec!["lib/a".to_string()]),
+        ]);
+        assert!(
+            fs::metadata(temp.join("big")).is_err() && fs::metadata(temp.join("docs")).is_err()
+        );
+
+        let map = fs::read_to_string(temp.join(".git/rewrite-history/commit-map")).unwrap();
+        let map: Vec<_> = map.lines().collect();
+        assert_eq!(map[0], "old new");
+        assert!(map[1].starts_with(&format!("{one} ")) && !map[1].ends_with(&one));
+        assert_eq!(map[2], format!("{two} {NULL_HASH}"));
+
+        // The subdirectory becomes the root
+        let filter = RewriteHistoryArgs {
+            subdirectory_filter: Some("lib/".to_string()),
+            ..args()
+        };
+        rewrite(&repo, filter).unwrap();
+        assert_eq!(history(&repo), [
+            ("lib".to_string(), vec!["a".to_string(), "b".to_string()]),
+            ("one".to_string(), vec!["a".to_string()]),
+        ]);
+        assert_eq!(fs::read_to_string(temp.join("b")).unwrap(), "b");
+
+        // The files are moved by the first matching rename
+        let filter = RewriteHistoryArgs {
+            path_renames: vec!["b:src/b".to_string(), ":src".to_string()],
+            ..args()
+        };
+        rewrite(&repo, filter).unwrap();
+        assert_eq!(history(&repo), [
+            ("lib".to_string(), vec![
+                "src/a".to_string(),
+                "src/b".to_string()
+            ]),
+            ("one".to_string(), vec!["src/a".to_string()]),
+        ]);
+        assert_eq!(fs::read_to_string(temp.join("src/b")).unwrap(), "b");
+    }
+
+    #[test]
+    fn rewrites_messages_and_identities_with_callbacks() {
+        let (_temp, repo) = setup();
+        commit_work_tree(&repo, &[("a", "a")], "one");
+        let head = commit_work_tree(&repo, &[("b", "b")], "two");
+        update_ref(&repo, "refs/tags/v1", &head, "tag").unwrap();
+
+        let args = RewriteHistoryArgs {
+            message_callback: Some("sed 's/^/fix: /'".to_string()),
+            name_callback: Some("tr a-z A-Z".to_string()),
+            email_callback: Some("sed s/example.com/example.org/".to_string()),
+            ..args()
+        };
+        let output = rewrite(&repo, args).unwrap();
+        assert_eq!(
+            output,
+            "Parsed 2 commits, rewrote 2 and pruned 0\nUpdated 2 refs\n"
+        );
+
+        let new_head = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        assert_eq!(
+            resolve_ref(&repo, "refs/tags/v1").unwrap(),
+            Some(new_head.clone())
+        );
+        let commit = read_commit(&repo, &new_head).unwrap();
+        assert_eq!(commit.message, b"fix: two\n");
+        assert_eq!(
+            Ident::parse(&commit.author).unwrap().format(),
+            b"A U THOR <author@example.org> 1700000000 +0000"
+        );
+        assert_eq!(Ident::parse(&commit.committer).unwrap().name, b"C O MITTER");
+        let parent = read_commit(&repo, &commit.parents[0]).unwrap();
+        assert_eq!(parent.message, b"fix: one\n");
+    }
+}
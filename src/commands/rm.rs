@@ -0,0 +1,364 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::checkout::{remove_empty_parents, remove_path};
+use crate::utils::commit::read_commit;
+use crate::utils::index::Index;
+use crate::utils::path_from_bytes;
+use crate::utils::pathspec::{is_within, normalize};
+use crate::utils::refs::resolve_ref;
+use crate::utils::repository::Repository;
+use crate::utils::tree::{flatten_tree, MODE_GITLINK};
+
+impl CommandArgs for RmArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut index = Index::load(repo)?;
+        let work_tree = repo.work_tree()?;
+        let prefix = repo.prefix()?;
+
+        let mut paths = BTreeSet::new();
+        for path in &self.paths {
+            let spec = normalize(&prefix, path)?;
+            let matched: BTreeSet<_> = index
+                .entries()
+                .iter()
+                .filter(|entry| is_within(&entry.path, &spec))
+                .map(|entry| entry.path.clone())
+                .collect();
+
+            if matched.is_empty() {
+                if self.ignore_unmatch {
+                    continue;
+                }
+                anyhow::bail!("pathspec '{}' did not match any files", path.display());
+            }
+            if !self.recursive && matched.iter().any(|matched| matched != spec.as_bytes()) {
+                anyhow::bail!("not removing '{}' recursively without -r", path.display());
+            }
+            paths.extend(matched);
+        }
+
+        if !self.force {
+            self.check_local_modifications(repo, &index, &work_tree, &paths)?;
+        }
+
+        for path in &paths {
+            if !self.quiet {
+                writer.write_all(b"rm '")?;
+                writer.write_all(path)?;
+                writer.write_all(b"'\n")?;
+            }
+            if !self.dry_run {
+                index.remove_entry(path);
+            }
+        }
+
+        if self.dry_run || paths.is_empty() {
+            return Ok(());
+        }
+        index.write(repo)?;
+
+        if !self.cached {
+            for path in &paths {
+                let full_path = work_tree.join(path_from_bytes(path));
+                // Files already removed from the working tree are fine
+                if full_path.symlink_metadata().is_ok() {
+                    remove_path(&full_path)?;
+                }
+                remove_empty_parents(&full_path, &work_tree);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RmArgs {
+    /// Refuse to remove files whose changes would be lost,
+    /// i.e. that differ from HEAD or from the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository to read HEAD from
+    /// * `index` - The index the files are removed from
+    /// * `work_tree` - The root of the working tree
+    /// * `paths` - The paths about to be removed
+    fn check_local_modifications(
+        &self,
+        repo: &Repository,
+        index: &Index,
+        work_tree: &Path,
+        paths: &BTreeSet<Vec<u8>>,
+    ) -> anyhow::Result<()> {
+        let mut head_entries = BTreeMap::new();
+        if let Some(head) = resolve_ref(repo, "HEAD")? {
+            for entry in flatten_tree(repo, &read_commit(repo, &head)?.tree)? {
+                head_entries.insert(entry.name, (entry.mode, entry.hash));
+            }
+        }
+
+        let mut staged_and_local = Vec::new();
+        let mut staged = Vec::new();
+        let mut local = Vec::new();
+
+        for path in paths {
+            // Conflicted paths are removed as a way to resolve them
+            let Some(entry) = index.entry(path, 0) else {
+                continue;
+            };
+            // Neither are files that are already gone from the working tree
+            let Ok(metadata) = work_tree.join(path_from_bytes(path)).symlink_metadata() else {
+                continue;
+            };
+            if metadata.is_dir() && entry.mode != MODE_GITLINK {
+                continue;
+            }
+
+            let local_changes = index.is_modified(entry, work_tree)?;
+            let staged_changes = head_entries.get(path) != Some(&(entry.mode, entry.hash.clone()));
+
+            if local_changes && staged_changes {
+                if !self.cached || !entry.intent_to_add {
+                    staged_and_local.push(String::from_utf8_lossy(path));
+                }
+            } else if !self.cached {
+                if staged_changes {
+                    staged.push(String::from_utf8_lossy(path));
+                }
+                if local_changes {
+                    local.push(String::from_utf8_lossy(path));
+                }
+            }
+        }
+
+        let mut errors = Vec::new();
+        for (paths, description, hint) in [
+            (
+                staged_and_local,
+                "staged content different from both the file and the HEAD",
+                "(use -f to force removal)",
+            ),
+            (
+                staged,
+                "changes staged in the index",
+                "(use --cached to keep the file, or -f to force removal)",
+            ),
+            (
+                local,
+                "local modifications",
+                "(use --cached to keep the file, or -f to force removal)",
+            ),
+        ] {
+            if paths.is_empty() {
+                continue;
+            }
+
+            let subject = if paths.len() == 1 {
+                "file has"
+            } else {
+                "files have"
+            };
+            let mut error = format!("the following {subject} {description}:\n");
+            for path in paths {
+                error.push_str(&format!("    {path}\n"));
+            }
+            error.push_str(hint);
+            errors.push(error);
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("{}", errors.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct RmArgs {
+    /// only remove from the index
+    #[arg(long)]
+    cached: bool,
+    /// allow recursive removal
+    #[arg(short)]
+    recursive: bool,
+    /// override the up-to-date check
+    #[arg(short, long)]
+    force: bool,
+    /// dry run
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// do not list removed files
+    #[arg(short, long)]
+    quiet: bool,
+    /// exit with a zero status even if nothing matched
+    #[arg(long)]
+    ignore_unmatch: bool,
+    /// the files or directories to remove
+    #[arg(value_name = "pathspec", required = true)]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::update_head;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "Author")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_COMMITTER_NAME, "Committer")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::write(temp.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::create_dir_all(temp.join("dir/sub")).unwrap();
+        fs::write(temp.join("a.txt"), "a").unwrap();
+        fs::write(temp.join("dir/b.txt"), "b").unwrap();
+        fs::write(temp.join("dir/sub/c.txt"), "c").unwrap();
+        let repo = temp.repo();
+
+        let mut index = Index::default();
+        for path in ["a.txt", "dir/b.txt", "dir/sub/c.txt"] {
+            let file = temp.join(path);
+            let metadata = fs::symlink_metadata(&file).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, &fs::read(&file).unwrap()).unwrap();
+            index.add_entry(IndexEntry::from_metadata(path.into(), hash, &metadata));
+        }
+        let tree = index.write_tree(&repo, false).unwrap();
+        index.write(&repo).unwrap();
+        let commit = create_commit(&repo, &tree, vec![], "initial\n").unwrap();
+        update_head(&repo, &commit, "commit").unwrap();
+
+        (temp, repo)
+    }
+
+    fn args(paths: &[&str]) -> RmArgs {
+        RmArgs {
+            cached: false,
+            recursive: false,
+            force: false,
+            dry_run: false,
+            quiet: false,
+            ignore_unmatch: false,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn rm(repo: &Repository, args: RmArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn paths(repo: &Repository) -> Vec<String> {
+        let index = Index::load(repo).unwrap();
+        index
+            .entries()
+            .iter()
+            .map(|e| String::from_utf8(e.path.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn removes_files_and_directories() {
+        let (temp, repo) = setup();
+
+        assert_eq!(rm(&repo, args(&["a.txt"])).unwrap(), "rm 'a.txt'\n");
+        assert!(!temp.join("a.txt").exists());
+        assert_eq!(paths(&repo), vec!["dir/b.txt", "dir/sub/c.txt"]);
+
+        let err = rm(&repo, args(&["dir"])).unwrap_err();
+        assert_eq!(err.to_string(), "not removing 'dir' recursively without -r");
+
+        let output = rm(&repo, RmArgs {
+            recursive: true,
+            ..args(&["dir"])
+        })
+        .unwrap();
+        assert_eq!(output, "rm 'dir/b.txt'\nrm 'dir/sub/c.txt'\n");
+        assert!(!temp.join("dir").exists());
+        assert!(paths(&repo).is_empty());
+
+        let err = rm(&repo, args(&["missing"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pathspec 'missing' did not match any files"
+        );
+    }
+
+    #[test]
+    fn keeps_files_with_cached() {
+        let (temp, repo) = setup();
+
+        let output = rm(&repo, RmArgs {
+            cached: true,
+            dry_run: true,
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+        assert_eq!(output, "rm 'a.txt'\n");
+        assert_eq!(paths(&repo).len(), 3);
+
+        rm(&repo, RmArgs {
+            cached: true,
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+        assert!(temp.join("a.txt").exists());
+        assert_eq!(paths(&repo), vec!["dir/b.txt", "dir/sub/c.txt"]);
+    }
+
+    #[test]
+    fn refuses_to_lose_changes() {
+        let (temp, repo) = setup();
+
+        fs::write(temp.join("a.txt"), "changed").unwrap();
+        let err = rm(&repo, args(&["a.txt"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the following file has local modifications:\n    a.txt\n(use --cached to keep the file, or -f to force removal)"
+        );
+        // The index still matches HEAD, so the changes are kept in the file
+        rm(&repo, RmArgs {
+            cached: true,
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+
+        // Staged content that differs from both the file and HEAD is only removed by force
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"staged").unwrap();
+        index.add_entry(IndexEntry::new("dir/b.txt".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+        let err = rm(&repo, RmArgs {
+            cached: true,
+            ..args(&["dir/b.txt"])
+        })
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("the following file has staged content different from both"));
+
+        rm(&repo, RmArgs {
+            force: true,
+            ..args(&["dir/b.txt"])
+        })
+        .unwrap();
+        assert!(!temp.join("dir/b.txt").exists());
+        assert_eq!(paths(&repo), vec!["dir/sub/c.txt"]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::io::{BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::protocol::http_backend::{serve_connection, ServedRepository};
+use crate::utils::repository::Repository;
+use crate::utils::transfer::open_repository;
+
+impl CommandArgs for ServeHttpArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut repositories: Vec<ServedRepository> = Vec::new();
+        for directory in &self.directories {
+            let served = open_repository(repo, &directory.to_string_lossy())?;
+            let git_dir = served.repo.git_dir()?;
+            let name = match served.bare {
+                true => &git_dir,
+                false => directory,
+            };
+            let name = std::fs::canonicalize(name)?
+                .file_name()
+                .context(format!("cannot name '{}'", directory.display()))?
+                .to_string_lossy()
+                .into_owned();
+            if repositories.iter().any(|other| other.name == name) {
+                anyhow::bail!("more than one repository is named '{name}'");
+            }
+            let work_tree = match served.bare {
+                true => None,
+                false => Some(served.repo.work_tree()?),
+            };
+            repositories.push(ServedRepository {
+                name,
+                git_dir,
+                work_tree,
+            });
+        }
+
+        let listener = TcpListener::bind((self.listen.as_str(), self.port))
+            .context(format!("unable to listen on {}:{}", self.listen, self.port))?;
+        let address = listener.local_addr()?;
+        for served in &repositories {
+            writeln!(
+                writer,
+                "Serving {} at http://{address}/{}",
+                served.git_dir.display(),
+                served.name
+            )?;
+        }
+        writer.flush()?;
+
+        // Each connection is served by its own thread, so that a slow client
+        // does not hold up the others
+        let repositories = Arc::new(repositories);
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("error: accept connection: {err}");
+                    continue;
+                },
+            };
+            let repositories = Arc::clone(&repositories);
+            let repo = repo.clone();
+            std::thread::spawn(move || {
+                let result = stream
+                    .try_clone()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|reader| {
+                        let mut reader = BufReader::new(reader);
+                        let mut writer = &stream;
+                        serve_connection(&repo, &repositories, &mut reader, &mut writer)
+                    });
+                if let Err(err) = result {
+                    eprintln!("error: {err}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ServeHttpArgs {
+    /// the repositories to serve, each at http://<address>/<name of its directory>
+    #[arg(required = true)]
+    directories: Vec<PathBuf>,
+    /// the address to listen on
+    #[arg(long, value_name = "host", default_value = "0.0.0.0")]
+    listen: String,
+    /// the port to listen on (0 for any free port)
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
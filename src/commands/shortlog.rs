@@ -0,0 +1,345 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::commit::{Commit, Ident};
+use crate::utils::mailmap::Mailmap;
+use crate::utils::refs::{head_branch, resolve_ref, shorten_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::trailer::parse_trailers;
+use crate::utils::walk::RevWalk;
+
+impl CommandArgs for ShortlogArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut groups = Vec::new();
+        for group in &self.groups {
+            groups.push(Group::parse(group)?);
+        }
+        if self.committer {
+            groups.push(Group::Committer);
+        }
+        if groups.is_empty() {
+            groups.push(Group::Author);
+        }
+
+        let mailmap = Mailmap::load(repo)?;
+
+        let mut walk = RevWalk::new(repo);
+        let revisions = if self.revisions.is_empty() {
+            if resolve_ref(repo, "HEAD")?.is_none() {
+                let branch = head_branch(repo)?.unwrap_or_else(|| "HEAD".to_string());
+                anyhow::bail!(
+                    "your current branch '{}' does not have any commits yet",
+                    shorten_ref(&branch)
+                );
+            }
+            vec!["HEAD".to_string()]
+        } else {
+            self.revisions.clone()
+        };
+
+        // Exclusions have to be known before the walk starts
+        let mut include = Vec::new();
+        for revision in &revisions {
+            if let Some(excluded) = revision.strip_prefix('^') {
+                walk.hide(&resolve_commit(repo, excluded)?)?;
+            } else if let Some((from, to)) = revision.split_once("..") {
+                walk.hide(&resolve_commit(repo, or_head(from))?)?;
+                include.push(resolve_commit(repo, or_head(to))?);
+            } else {
+                include.push(resolve_commit(repo, revision)?);
+            }
+        }
+        for hash in include {
+            walk.push(&hash)?;
+        }
+
+        // The subjects of each name, newest first
+        let mut subjects: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for entry in walk {
+            let (_, commit) = entry?;
+            let subject = commit.subject();
+
+            // A commit is counted once for each name, whatever the groups it is in
+            let mut names = HashSet::new();
+            for group in &groups {
+                for name in group.names(&commit, &mailmap, self.email)? {
+                    if names.insert(name.clone()) {
+                        subjects.entry(name).or_default().push(subject.clone());
+                    }
+                }
+            }
+        }
+
+        let mut subjects: Vec<_> = subjects.into_iter().collect();
+        if self.numbered {
+            // The sort is stable, so names with as many commits stay sorted
+            subjects.sort_by_key(|(_, subjects)| Reverse(subjects.len()));
+        }
+
+        for (name, subjects) in subjects {
+            if self.summary {
+                writeln!(writer, "{:6}\t{name}", subjects.len())?;
+                continue;
+            }
+
+            writeln!(writer, "{name} ({}):", subjects.len())?;
+            for subject in subjects.iter().rev() {
+                writeln!(writer, "      {subject}")?;
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What commits are grouped by
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Group {
+    Author,
+    Committer,
+    /// The values of the trailers with this key (compared case-insensitively)
+    Trailer(String),
+}
+
+impl Group {
+    /// Parse a `--group` value (`author`, `committer` or `trailer:<key>`).
+    fn parse(group: &str) -> anyhow::Result<Self> {
+        match group {
+            "author" => Ok(Group::Author),
+            "committer" => Ok(Group::Committer),
+            _ => match group.strip_prefix("trailer:") {
+                Some(key) if !key.is_empty() => Ok(Group::Trailer(key.to_string())),
+                _ => anyhow::bail!("unknown group type: {group}"),
+            },
+        }
+    }
+
+    /// The names a commit is counted under (canonicalized by the mailmap),
+    /// with their emails if `email` is set.
+    fn names(
+        &self,
+        commit: &Commit,
+        mailmap: &Mailmap,
+        email: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let ident = match self {
+            Group::Author => Ident::parse(&commit.author)?,
+            Group::Committer => Ident::parse(&commit.committer)?,
+            Group::Trailer(key) => {
+                let names = parse_trailers(&commit.message)
+                    .into_iter()
+                    .filter(|(name, _)| name.eq_ignore_ascii_case(key))
+                    .map(|(_, value)| match split_ident(&value) {
+                        Some((name, address)) => {
+                            format_name(mailmap, name.as_bytes(), address.as_bytes(), email)
+                        },
+                        None => value,
+                    })
+                    .collect();
+                return Ok(names);
+            },
+        };
+
+        Ok(vec![format_name(mailmap, &ident.name, &ident.email, email)])
+    }
+}
+
+/// Split a `Name <email>` trailer value.
+fn split_ident(value: &str) -> Option<(&str, &str)> {
+    let (name, rest) = value.split_once('<')?;
+    let (email, _) = rest.split_once('>')?;
+    Some((name.trim(), email))
+}
+
+/// Format the canonical name of an identity, with its email if `show_email` is set.
+fn format_name(mailmap: &Mailmap, name: &[u8], email: &[u8], show_email: bool) -> String {
+    let (name, email) = mailmap.map(name, email);
+    let name = String::from_utf8_lossy(&name);
+    if show_email {
+        format!("{name} <{}>", String::from_utf8_lossy(&email))
+    } else {
+        name.into_owned()
+    }
+}
+
+/// An empty side of a `<from>..<to>` range means `HEAD`.
+fn or_head(revision: &str) -> &str {
+    if revision.is_empty() {
+        "HEAD"
+    } else {
+        revision
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ShortlogArgs {
+    /// group the commits by committer instead of author (as `--group=committer`)
+    #[arg(short, long)]
+    committer: bool,
+    /// group the commits by `author`, `committer` or the values of the
+    /// `trailer:<key>` trailers, can be repeated
+    #[arg(long = "group", value_name = "type")]
+    groups: Vec<String>,
+    /// show the email of each name
+    #[arg(short, long)]
+    email: bool,
+    /// only show the number of commits of each name
+    #[arg(short, long)]
+    summary: bool,
+    /// sort the names by their number of commits (the most first)
+    #[arg(short, long)]
+    numbered: bool,
+    /// the commits to summarize (`^<rev>` and `<rev1>..<rev2>` exclude commits)
+    revisions: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    /// Write the commits (author, committer, message) as a line of history
+    /// and point `main` at the last one.
+    fn history(repo: &Repository, commits: &[(&str, &str, &str)]) {
+        let mut parent: Option<String> = None;
+        for (timestamp, (author, committer, message)) in (1700000000..).zip(commits) {
+            let mut content = format!("tree {TREE}\n");
+            if let Some(parent) = &parent {
+                content.push_str(&format!("parent {parent}\n"));
+            }
+            content.push_str(&format!(
+                "author {author} {timestamp} +0000\n\
+                 committer {committer} {timestamp} +0000\n\n{message}"
+            ));
+            parent = Some(write_object(repo, ObjectType::Commit, content.as_bytes()).unwrap());
+        }
+        write_ref(repo, "refs/heads/main", parent.as_deref().unwrap()).unwrap();
+    }
+
+    fn shortlog(repo: &Repository, args: ShortlogArgs) -> String {
+        let mut output = Vec::new();
+        args.run(repo, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn args() -> ShortlogArgs {
+        ShortlogArgs {
+            committer: false,
+            groups: Vec::new(),
+            email: false,
+            summary: false,
+            numbered: false,
+            revisions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn groups_commits_by_author() {
+        let (_temp, repo) = setup();
+        let committer = "C O Mitter <c@example.com>";
+        history(&repo, &[
+            ("Bob <bob@example.com>", committer, "first\n"),
+            ("alice <alice@example.com>", committer, "second\nline\n"),
+            ("Bob <bob@example.com>", committer, "third\n"),
+        ]);
+
+        assert_eq!(
+            shortlog(&repo, args()),
+            "Bob (2):\n      first\n      third\n\nalice (1):\n      second line\n\n"
+        );
+        assert_eq!(
+            shortlog(&repo, ShortlogArgs {
+                committer: true,
+                email: true,
+                revisions: vec!["HEAD~2..".to_string()],
+                ..args()
+            }),
+            "C O Mitter <c@example.com> (2):\n      second line\n      third\n\n"
+        );
+    }
+
+    #[test]
+    fn counts_commits_of_canonical_names() {
+        let (temp, repo) = setup();
+        let committer = "C O Mitter <c@example.com>";
+        history(&repo, &[
+            ("alice <alice@example.com>", committer, "first\n"),
+            ("Bob <bob@example.com>", committer, "second\n"),
+            ("bob <bob@old.example.com>", committer, "third\n"),
+            ("Carol <carol@example.com>", committer, "fourth\n"),
+        ]);
+        fs::write(
+            temp.join(".mailmap"),
+            "Bob <bob@example.com> <bob@old.example.com>\n",
+        )
+        .unwrap();
+
+        let summary = ShortlogArgs {
+            summary: true,
+            numbered: true,
+            ..args()
+        };
+        assert_eq!(
+            shortlog(&repo, summary),
+            "     2\tBob\n     1\tCarol\n     1\talice\n"
+        );
+    }
+
+    #[test]
+    fn groups_commits_by_trailers() {
+        let (_temp, repo) = setup();
+        let author = "A U Thor <a@example.com>";
+        let first =
+            format!("first\n\nReviewed-by: Carol <carol@example.com>\nAcked-by: {author}\n");
+        history(&repo, &[
+            (author, author, &first),
+            (
+                author,
+                author,
+                "second\n\nreviewed-by: dave\nAcked-by: Carol\n",
+            ),
+            (author, author, "Reviewed-by: only a subject\n"),
+        ]);
+
+        let groups = vec!["trailer:Reviewed-by".to_string()];
+        assert_eq!(
+            shortlog(&repo, ShortlogArgs {
+                groups: groups.clone(),
+                ..args()
+            }),
+            "Carol (1):\n      first\n\ndave (1):\n      second\n\n"
+        );
+
+        // The first commit is counted once for its author, who acked it as well
+        let groups = vec!["author".to_string(), "trailer:acked-by".to_string()];
+        assert_eq!(
+            shortlog(&repo, ShortlogArgs { groups, ..args() }),
+            "A U Thor (3):\n      first\n      second\n      Reviewed-by: only a subject\n\n\
+             Carol (1):\n      second\n\n"
+        );
+    }
+}
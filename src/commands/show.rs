@@ -0,0 +1,314 @@
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::log::{write_commit, write_notes};
+use crate::commands::CommandArgs;
+use crate::utils::commit::{read_commit, Commit, Ident, Tag};
+use crate::utils::config::Config;
+use crate::utils::date::format_default;
+use crate::utils::diff::{diff_trees, write_patch, DEFAULT_CONTEXT};
+use crate::utils::mailmap::Mailmap;
+use crate::utils::notes::{default_notes_ref, Notes};
+use crate::utils::objects::{read_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
+use crate::utils::tree::parse_tree;
+
+impl CommandArgs for ShowArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let objects = if self.objects.is_empty() {
+            vec!["HEAD".to_string()]
+        } else {
+            self.objects.clone()
+        };
+
+        // Authors are shown with their canonical names unless `log.mailmap` is false
+        let config = Config::load(repo)?;
+        let mailmap = if config.get_bool("log.mailmap")?.unwrap_or(true) {
+            Mailmap::load(repo)?
+        } else {
+            Mailmap::default()
+        };
+        let notes = if self.no_notes {
+            None
+        } else {
+            Some(Notes::load(repo, &default_notes_ref(&config))?)
+        };
+        let format = CommitFormat { mailmap, notes };
+
+        let mut separate = false;
+        for name in &objects {
+            let hash = resolve_revision(repo, name)?;
+            self.show(repo, &format, name, &hash, &mut separate, writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How commits are shown
+struct CommitFormat {
+    /// The canonical names and emails of the authors
+    mailmap: Mailmap,
+    /// The notes shown after the messages, if any
+    notes: Option<Notes>,
+}
+
+impl ShowArgs {
+    /// Show an object according to its type.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository containing the object
+    /// * `format` - How commits are shown
+    /// * `name` - The name the object was given as, shown for trees
+    /// * `hash` - The hash of the object
+    /// * `separate` - Whether a commit, tree or tag was shown before, which
+    ///   have to be separated from the next one by an empty line
+    /// * `writer` - Where to write the output
+    fn show<W>(
+        &self,
+        repo: &Repository,
+        format: &CommitFormat,
+        name: &str,
+        hash: &str,
+        separate: &mut bool,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let (object_type, content) = read_object(repo, hash)?;
+
+        match object_type {
+            ObjectType::Commit => {
+                if *separate {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "commit {hash}")?;
+                let commit = Commit::parse(&content)?;
+                write_commit(&commit, &format.mailmap, writer)?;
+                if let Some(notes) = &format.notes {
+                    write_notes(repo, notes, hash, writer)?;
+                }
+                if !self.no_patch {
+                    self.write_commit_patch(repo, &commit, writer)?;
+                }
+                *separate = true;
+            },
+            ObjectType::Tag => {
+                if *separate {
+                    writeln!(writer)?;
+                }
+                let tag = Tag::parse(&content)?;
+                writeln!(writer, "tag {}", String::from_utf8_lossy(&tag.name))?;
+                if let Some(tagger) = &tag.tagger {
+                    let tagger = Ident::parse(tagger)?;
+                    writeln!(
+                        writer,
+                        "Tagger: {} <{}>",
+                        String::from_utf8_lossy(&tagger.name),
+                        String::from_utf8_lossy(&tagger.email)
+                    )?;
+                    writeln!(
+                        writer,
+                        "Date:   {}",
+                        format_default(tagger.timestamp, &tagger.offset)
+                    )?;
+                }
+                writeln!(writer)?;
+                writer.write_all(&tag.message)?;
+                writeln!(writer)?;
+
+                // The tagged object follows
+                *separate = false;
+                self.show(repo, format, &tag.object, &tag.object, separate, writer)?;
+            },
+            ObjectType::Tree => {
+                if *separate {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "tree {name}\n")?;
+                for entry in parse_tree(&content)? {
+                    writer.write_all(&entry.name)?;
+                    writeln!(writer, "{}", if entry.is_tree() { "/" } else { "" })?;
+                }
+                *separate = true;
+            },
+            // Blobs are shown as they are, without separators
+            ObjectType::Blob => writer.write_all(&content)?,
+        }
+
+        Ok(())
+    }
+
+    /// Write the changes a commit made to its first parent, preceded by an empty line.
+    /// Merges are not shown with a patch.
+    fn write_commit_patch<W>(
+        &self,
+        repo: &Repository,
+        commit: &Commit,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let parent_tree = match commit.parents.as_slice() {
+            [] => None,
+            [parent] => Some(read_commit(repo, parent)?.tree),
+            _ => return Ok(()),
+        };
+
+        let changes = diff_trees(repo, parent_tree.as_deref(), Some(&commit.tree))?;
+        if !changes.is_empty() {
+            writeln!(writer)?;
+            write_patch(
+                repo,
+                &changes,
+                self.unified.unwrap_or(DEFAULT_CONTEXT),
+                writer,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ShowArgs {
+    /// do not show the changes made by commits
+    #[arg(short = 's', long)]
+    no_patch: bool,
+    /// the number of context lines to show around changes
+    #[arg(short = 'U', long, value_name = "n")]
+    unified: Option<usize>,
+    /// do not show the notes of commits
+    #[arg(long)]
+    no_notes: bool,
+    /// the objects to show (HEAD by default)
+    #[arg(value_name = "object")]
+    objects: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, create_tag};
+    use crate::utils::env;
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        (temp, repo)
+    }
+
+    /// Commit the given files on top of HEAD.
+    fn commit(repo: &Repository, files: &[(&str, &str)], message: &str) -> String {
+        let mut index = Index::load(repo).unwrap();
+        for (path, content) in files {
+            let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+        }
+        let tree = index.write_tree(repo, false).unwrap();
+        index.write(repo).unwrap();
+
+        let parents = resolve_revision(repo, "HEAD").into_iter().collect();
+        let hash = create_commit(repo, &tree, parents, message).unwrap();
+        write_ref(repo, "refs/heads/main", &hash).unwrap();
+        hash
+    }
+
+    fn args(objects: &[&str]) -> ShowArgs {
+        ShowArgs {
+            no_patch: false,
+            unified: None,
+            no_notes: false,
+            objects: objects.iter().map(|object| object.to_string()).collect(),
+        }
+    }
+
+    fn show(repo: &Repository, args: ShowArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn shows_commits_with_their_patch() {
+        let (_temp, repo) = setup();
+        let first = commit(&repo, &[("a.txt", "one\ntwo\n")], "first\n");
+        let second = commit(
+            &repo,
+            &[("a.txt", "one\n2\n"), ("dir/b", "b\n")],
+            "second\n",
+        );
+
+        let header = |hash: &str, subject: &str| {
+            format!(
+                "commit {hash}\nAuthor: A U Thor <author@example.com>\n\
+                 Date:   Tue Nov 14 22:13:20 2023 +0000\n\n    {subject}\n"
+            )
+        };
+        assert_eq!(
+            show(&repo, args(&[])).unwrap(),
+            format!(
+                "{}\ndiff --git a/a.txt b/a.txt\nindex 814f4a4..99b356d 100644\n\
+                 --- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+2\n\
+                 diff --git a/dir/b b/dir/b\nnew file mode 100644\nindex 0000000..6178079\n\
+                 --- /dev/null\n+++ b/dir/b\n@@ -0,0 +1 @@\n+b\n",
+                header(&second, "second")
+            )
+        );
+
+        let output = show(&repo, ShowArgs {
+            no_patch: true,
+            ..args(&["HEAD~1", "HEAD"])
+        })
+        .unwrap();
+        assert_eq!(
+            output,
+            format!("{}\n{}", header(&first, "first"), header(&second, "second"))
+        );
+    }
+
+    #[test]
+    fn shows_tags_trees_and_blobs() {
+        let (_temp, repo) = setup();
+        let hash = commit(&repo, &[("a.txt", "a\n"), ("dir/b", "b\n")], "first\n");
+        let tag = create_tag(&repo, &hash, "v1", "release\n").unwrap();
+        write_ref(&repo, "refs/tags/v1", &tag).unwrap();
+
+        let output = show(&repo, ShowArgs {
+            no_patch: true,
+            ..args(&["v1", "HEAD:a.txt", "HEAD^{tree}"])
+        })
+        .unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "tag v1\nTagger: C O Mitter <committer@example.com>\n\
+                 Date:   Tue Nov 14 22:13:20 2023 +0000\n\nrelease\n\n\
+                 commit {hash}\nAuthor: A U Thor <author@example.com>\n\
+                 Date:   Tue Nov 14 22:13:20 2023 +0000\n\n    first\n\
+                 a\n\ntree HEAD^{{tree}}\n\na.txt\ndir/\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,635 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::advice::warning;
+use crate::utils::commit::{read_commit, Ident};
+use crate::utils::refs::{head_branch, list_refs, resolve_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+
+/// The flag of the commits reachable from all branches, and of their ancestors
+const UNINTERESTING: u32 = 1 << 1;
+/// The bit of the first branch: each branch marks the commits it reaches with its own bit
+const REV_SHIFT: usize = 2;
+/// The maximum number of branches shown
+const MAX_REVS: usize = 26;
+
+impl CommandArgs for ShowBranchArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut revs = Vec::new();
+        let (heads, remotes) = match (self.all, self.remotes) {
+            (true, _) => (true, true),
+            (false, remotes) => (!remotes && self.revs.is_empty(), remotes),
+        };
+        if heads {
+            add_refs(repo, &mut revs, "refs/heads/")?;
+        }
+        if remotes {
+            add_refs(repo, &mut revs, "refs/remotes/")?;
+        }
+        for rev in &self.revs {
+            let hash = resolve_commit(repo, rev)
+                .map_err(|_| anyhow::anyhow!("bad sha1 reference {rev}"))?;
+            add_rev(&mut revs, rev, hash);
+        }
+        if revs.is_empty() {
+            eprintln!("No revs to be shown.");
+            return Ok(());
+        }
+
+        let mut walk = Walk::new(repo);
+        let mut list = Vec::new();
+        let mut seen = Vec::new();
+        for (i, (_, hash)) in revs.iter().enumerate() {
+            walk.mark_seen(hash, &mut seen)?;
+            let flags = walk.flags(hash)?;
+            *flags |= 1 << (i + REV_SHIFT);
+            if *flags == 1 << (i + REV_SHIFT) {
+                walk.insert_by_date(&mut list, hash);
+            }
+        }
+        let rev_masks = revs
+            .iter()
+            .map(|(_, hash)| walk.flags(hash).map(|flags| *flags))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut extra = match self.list {
+            true => -1,
+            false => self.more,
+        };
+        walk.join(list, &mut seen, revs.len(), extra)?;
+        // The commits are sorted by date, the last ones seen first among equal dates
+        seen.reverse();
+        seen.sort_by_key(|hash| std::cmp::Reverse(walk.commits[hash].date));
+
+        if self.merge_base {
+            return walk.show_merge_bases(&seen, revs.len(), writer);
+        }
+        if self.independent {
+            for ((_, hash), mask) in revs.iter().zip(rev_masks) {
+                if walk.commits[hash].flags == mask {
+                    writeln!(writer, "{hash}")?;
+                }
+            }
+            return Ok(());
+        }
+
+        // The header shows the tip of each branch, marking the one checked out
+        let head = resolve_ref(repo, "HEAD")?;
+        let head_branch = head_branch(repo)?;
+        let head_name = head_branch
+            .as_deref()
+            .map(|branch| branch.strip_prefix("refs/heads/").unwrap_or(branch));
+        let mut head_at = None;
+        if revs.len() > 1 || extra < 0 {
+            for (i, (name, hash)) in revs.iter().enumerate() {
+                let name_is_head = head_name.is_some_and(|head_name| {
+                    let name = name
+                        .strip_prefix("refs/heads/")
+                        .or_else(|| name.strip_prefix("heads/"))
+                        .unwrap_or(name);
+                    name == head_name
+                });
+                let is_head = name_is_head && head.as_deref() == Some(hash.as_str());
+                if is_head {
+                    head_at = Some(i);
+                }
+                let subject = &walk.commits[hash].subject;
+                match extra < 0 {
+                    true => {
+                        let mark = if is_head { '*' } else { ' ' };
+                        writeln!(writer, "{mark} [{name}] {subject}")?
+                    },
+                    false => {
+                        let mark = if is_head { '*' } else { '!' };
+                        writeln!(writer, "{:i$}{mark} [{name}] {subject}", "")?
+                    },
+                }
+            }
+            if extra >= 0 {
+                writeln!(writer, "{}", "-".repeat(revs.len()))?;
+            }
+        }
+        if extra < 0 {
+            return Ok(());
+        }
+
+        let sorted = walk.sort_topologically(&seen);
+        let names = match self.sha1_name || self.no_name {
+            true => HashMap::new(),
+            false => walk.name_commits(&sorted, &revs),
+        };
+
+        // Each commit is shown with a column per branch marking whether it reaches
+        // the commit, until the commits all branches reach (and `--more` others)
+        let all_revs = ((1 << revs.len()) - 1) << REV_SHIFT;
+        let mut shown_merge_point = false;
+        for hash in &sorted {
+            let commit = &walk.commits[hash];
+            shown_merge_point |= commit.flags & all_revs == all_revs;
+
+            if revs.len() > 1 {
+                let is_merge = commit.parents.len() > 1;
+                if !self.sparse && is_merge && omit_in_dense(hash, commit.flags, &revs) {
+                    continue;
+                }
+                let mut marks = String::new();
+                for i in 0..revs.len() {
+                    marks.push(match commit.flags & (1 << (i + REV_SHIFT)) {
+                        0 => ' ',
+                        _ if is_merge => '-',
+                        _ if head_at == Some(i) => '*',
+                        _ => '+',
+                    });
+                }
+                write!(writer, "{marks} ")?;
+            }
+
+            if !self.no_name {
+                match names.get(hash) {
+                    Some(name) => write!(writer, "[{name}] ")?,
+                    None => write!(writer, "[{}] ", &hash[..7])?,
+                }
+            }
+            writeln!(writer, "{}", commit.subject)?;
+
+            if shown_merge_point {
+                extra -= 1;
+                if extra < 0 {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add the refs below a prefix as branches to show, by their short names sorted
+/// as versions.
+fn add_refs(
+    repo: &Repository,
+    revs: &mut Vec<(String, String)>,
+    prefix: &str,
+) -> anyhow::Result<()> {
+    let start = revs.len();
+    for (name, hash) in list_refs(repo, prefix)? {
+        let Ok(hash) = resolve_commit(repo, &hash) else {
+            continue;
+        };
+        add_rev(revs, &name[prefix.len()..], hash);
+    }
+    revs[start..].sort_by(|(a, _), (b, _)| version_cmp(a, b));
+    Ok(())
+}
+
+/// Add a branch to show, unless it was added already or there are too many.
+fn add_rev(revs: &mut Vec<(String, String)>, name: &str, hash: String) {
+    if revs.iter().any(|(added, _)| added == name) {
+        return;
+    }
+    if revs.len() >= MAX_REVS {
+        warning(&format!(
+            "ignoring {name}; cannot handle more than {MAX_REVS} refs"
+        ));
+        return;
+    }
+    revs.push((name.to_string(), hash));
+}
+
+/// Compare names, the numbers in them by their values.
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (len_a, len_b) = (digits(a), digits(b));
+                let trim = |s: &[u8]| s.iter().skip_while(|&&c| c == b'0').count();
+                let ordering = trim(&a[..len_a]).cmp(&trim(&b[..len_b])).then_with(|| {
+                    let skip = |s: &'_ [u8], len| s[len - trim(&s[..len])..len].to_vec();
+                    skip(a, len_a).cmp(&skip(b, len_b))
+                });
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                (a, b) = (&a[len_a..], &b[len_b..]);
+            },
+            (Some(x), Some(y)) if x == y => (a, b) = (&a[1..], &b[1..]),
+            (x, y) => return x.cmp(&y),
+        }
+    }
+}
+
+/// Whether a merge commit is left out of the list: merges reachable from only
+/// one branch (that are not its tip) are not interesting.
+fn omit_in_dense(hash: &str, flags: u32, revs: &[(String, String)]) -> bool {
+    if revs.iter().any(|(_, rev)| rev == hash) {
+        return false;
+    }
+    (0..revs.len())
+        .filter(|i| flags & (1 << (i + REV_SHIFT)) != 0)
+        .count()
+        == 1
+}
+
+/// A commit met by the walk
+struct WalkedCommit {
+    parents: Vec<String>,
+    /// The committer date
+    date: i64,
+    subject: String,
+    flags: u32,
+}
+
+/// The walk from the tips of the branches back to their common ancestors
+struct Walk<'a> {
+    repo: &'a Repository,
+    commits: HashMap<String, WalkedCommit>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(repo: &'a Repository) -> Self {
+        Walk {
+            repo,
+            commits: HashMap::new(),
+        }
+    }
+
+    /// The flags of a commit, reading the commit the first time.
+    fn flags(&mut self, hash: &str) -> anyhow::Result<&mut u32> {
+        if !self.commits.contains_key(hash) {
+            let commit = read_commit(self.repo, hash)?;
+            let date = Ident::parse(&commit.committer).map_or(0, |ident| ident.timestamp);
+            self.commits.insert(hash.to_string(), WalkedCommit {
+                subject: commit.subject(),
+                parents: commit.parents,
+                date,
+                flags: 0,
+            });
+        }
+        Ok(&mut self.commits.get_mut(hash).unwrap().flags)
+    }
+
+    /// Insert a (read) commit in a list sorted by date, newest first, after the
+    /// commits of the same date.
+    fn insert_by_date(&self, list: &mut Vec<String>, hash: &str) {
+        let date = self.commits[hash].date;
+        let position = list
+            .iter()
+            .position(|other| self.commits[other].date < date)
+            .unwrap_or(list.len());
+        list.insert(position, hash.to_string());
+    }
+
+    /// Mark a commit as seen, if it has no flags yet.
+    ///
+    /// # Returns
+    ///
+    /// `true` if it was not seen before
+    fn mark_seen(&mut self, hash: &str, seen: &mut Vec<String>) -> anyhow::Result<bool> {
+        if *self.flags(hash)? != 0 {
+            return Ok(false);
+        }
+        seen.push(hash.to_string());
+        Ok(true)
+    }
+
+    /// Walk back from the tips, propagating the bits of the branches to the parents,
+    /// until the commits left are reachable from all branches (and `extra` commits
+    /// more were seen), adding the commits seen to `seen` in the order they are seen.
+    fn join(
+        &mut self,
+        mut list: Vec<String>,
+        seen: &mut Vec<String>,
+        num_revs: usize,
+        mut extra: i32,
+    ) -> anyhow::Result<()> {
+        let all_revs = ((1 << num_revs) - 1) << REV_SHIFT;
+        let all_mask = all_revs | UNINTERESTING;
+
+        while !list.is_empty() {
+            let still_interesting = list
+                .iter()
+                .any(|hash| self.commits[hash].flags & UNINTERESTING == 0);
+            let hash = list.remove(0);
+            let mut flags = self.commits[&hash].flags & all_mask;
+            if !still_interesting && extra <= 0 {
+                break;
+            }
+
+            self.mark_seen(&hash, seen)?;
+            if flags & all_revs == all_revs {
+                flags |= UNINTERESTING;
+            }
+            for parent in self.commits[&hash].parents.clone() {
+                if *self.flags(&parent)? & flags == flags {
+                    continue;
+                }
+                if self.mark_seen(&parent, seen)? && !still_interesting {
+                    extra -= 1;
+                }
+                *self.flags(&parent)? |= flags;
+                self.insert_by_date(&mut list, &parent);
+            }
+        }
+
+        // The parents of the commits reachable from all branches are not interesting
+        loop {
+            let mut changed = false;
+            for hash in seen.iter() {
+                let commit = &self.commits[hash];
+                if commit.flags & all_revs != all_revs && commit.flags & UNINTERESTING == 0 {
+                    continue;
+                }
+                for parent in commit.parents.clone() {
+                    let flags = self.flags(&parent)?;
+                    if *flags & UNINTERESTING == 0 {
+                        *flags |= UNINTERESTING;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Show the commits reachable from all branches that are not ancestors of
+    /// another one, failing if there is none.
+    fn show_merge_bases<W>(
+        &mut self,
+        seen: &[String],
+        num_revs: usize,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let all_revs = ((1 << num_revs) - 1) << REV_SHIFT;
+        let mut found = false;
+        for hash in seen {
+            let flags = self.flags(hash)?;
+            if *flags & UNINTERESTING == 0 && *flags & all_revs == all_revs {
+                writeln!(writer, "{hash}")?;
+                found = true;
+                *flags |= UNINTERESTING;
+            }
+        }
+        match found {
+            true => Ok(()),
+            false => Err(SilentExit(1).into()),
+        }
+    }
+
+    /// Sort commits so that children come before their parents, following the
+    /// branches one after another.
+    fn sort_topologically(&self, commits: &[String]) -> Vec<String> {
+        let mut indegree: HashMap<&str, usize> =
+            commits.iter().map(|hash| (hash.as_str(), 1)).collect();
+        for hash in commits {
+            for parent in &self.commits[hash].parents {
+                if let Some(count) = indegree.get_mut(parent.as_str()) {
+                    *count += 1;
+                }
+            }
+        }
+
+        // The tips are taken in order, then the parents whose children were all taken
+        // are taken first
+        let mut stack: Vec<&str> = commits
+            .iter()
+            .map(String::as_str)
+            .filter(|hash| indegree[hash] == 1)
+            .collect();
+        stack.reverse();
+        let mut sorted = Vec::new();
+        while let Some(hash) = stack.pop() {
+            for parent in &self.commits[hash].parents {
+                if let Some(count) = indegree.get_mut(parent.as_str()) {
+                    *count -= 1;
+                    if *count == 1 {
+                        stack.push(parent);
+                    }
+                }
+            }
+            sorted.push(hash.to_string());
+        }
+        sorted
+    }
+
+    /// Name the commits relative to the tips of the branches, as `<branch>`,
+    /// `<branch>^`, `<branch>~<n>` or `<name>^<n>` for the other parents of merges.
+    fn name_commits(
+        &self,
+        commits: &[String],
+        revs: &[(String, String)],
+    ) -> HashMap<String, String> {
+        // The name of a commit is a name and how many first parents it is away from it
+        let mut names: HashMap<String, (String, usize)> = HashMap::new();
+        for hash in commits {
+            if let Some((name, _)) = revs.iter().find(|(_, rev)| rev == hash) {
+                names.entry(hash.clone()).or_insert((name.clone(), 0));
+            }
+        }
+
+        let name_first_parents = |names: &mut HashMap<String, (String, usize)>, hash: &str| {
+            let mut named = 0;
+            let mut current = hash.to_string();
+            while let Some((name, generation)) = names.get(&current).cloned() {
+                let Some(parent) = self.commits.get(&current).and_then(|c| c.parents.first())
+                else {
+                    break;
+                };
+                if names.contains_key(parent) || !self.commits.contains_key(parent) {
+                    break;
+                }
+                names.insert(parent.clone(), (name, generation + 1));
+                named += 1;
+                current = parent.clone();
+            }
+            named
+        };
+        while commits
+            .iter()
+            .map(|hash| name_first_parents(&mut names, hash))
+            .sum::<usize>()
+            > 0
+        {}
+
+        loop {
+            let mut named = 0;
+            for hash in commits {
+                let Some(name) = names
+                    .get(hash)
+                    .map(|(name, generation)| format_name(name, *generation))
+                else {
+                    continue;
+                };
+                for (nth, parent) in self.commits[hash].parents.iter().enumerate() {
+                    if names.contains_key(parent) || !self.commits.contains_key(parent) {
+                        continue;
+                    }
+                    let parent_name = match nth {
+                        0 => format!("{name}^"),
+                        _ => format!("{name}^{}", nth + 1),
+                    };
+                    names.insert(parent.clone(), (parent_name, 0));
+                    named += 1;
+                    name_first_parents(&mut names, parent);
+                }
+            }
+            if named == 0 {
+                break;
+            }
+        }
+
+        names
+            .into_iter()
+            .map(|(hash, (name, generation))| (hash, format_name(&name, generation)))
+            .collect()
+    }
+}
+
+/// The name of a commit some first parents away from a named one.
+fn format_name(name: &str, generation: usize) -> String {
+    match generation {
+        0 => name.to_string(),
+        1 => format!("{name}^"),
+        _ => format!("{name}~{generation}"),
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ShowBranchArgs {
+    /// show the remote-tracking branches
+    #[arg(short, long)]
+    remotes: bool,
+    /// show both the local and the remote-tracking branches
+    #[arg(short, long)]
+    all: bool,
+    /// only show the tips of the branches
+    #[arg(long, conflicts_with = "more")]
+    list: bool,
+    /// show this many more commits beyond the common ancestor
+    #[arg(
+        long,
+        value_name = "n",
+        default_value_t = 0,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1",
+        allow_negative_numbers = true
+    )]
+    more: i32,
+    /// print the best common ancestors of the branches instead
+    #[arg(long, conflicts_with_all = ["independent", "list"])]
+    merge_base: bool,
+    /// print the tips of the branches no other branch reaches instead
+    #[arg(long, conflicts_with = "list")]
+    independent: bool,
+    /// name the commits by their abbreviated hashes
+    #[arg(long)]
+    sha1_name: bool,
+    /// do not name the commits
+    #[arg(long)]
+    no_name: bool,
+    /// also show the merges reachable from only one branch
+    #[arg(long)]
+    sparse: bool,
+    /// the branches or commits to show (all local branches by default)
+    #[arg(value_name = "rev")]
+    revs: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    #[test]
+    fn shows_branches() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let one = create_commit(&repo, EMPTY_TREE, Vec::new(), "one\n").unwrap();
+        let two = create_commit(&repo, EMPTY_TREE, vec![one.clone()], "two\n").unwrap();
+        let three = create_commit(&repo, EMPTY_TREE, vec![two.clone()], "three\n").unwrap();
+        let topic = create_commit(&repo, EMPTY_TREE, vec![two.clone()], "topic\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &three).unwrap();
+        write_ref(&repo, "refs/heads/topic", &topic).unwrap();
+
+        let args = |revs: &[&str]| ShowBranchArgs {
+            remotes: false,
+            all: false,
+            list: false,
+            more: 0,
+            merge_base: false,
+            independent: false,
+            sha1_name: false,
+            no_name: false,
+            sparse: false,
+            revs: revs.iter().map(|rev| rev.to_string()).collect(),
+        };
+        let show = |args: ShowBranchArgs| {
+            let mut output = Vec::new();
+            args.run(&repo, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            show(args(&[])),
+            "* [main] three\n ! [topic] topic\n--\n + [topic] topic\n*  [main] three\n*+ [topic^] two\n"
+        );
+        let output = show(ShowBranchArgs {
+            more: 1,
+            no_name: true,
+            ..args(&[])
+        });
+        assert_eq!(output.lines().last(), Some("*+ one"));
+        let output = show(ShowBranchArgs {
+            list: true,
+            ..args(&[])
+        });
+        assert_eq!(output, "* [main] three\n  [topic] topic\n");
+
+        let output = show(ShowBranchArgs {
+            merge_base: true,
+            ..args(&["main", "topic"])
+        });
+        assert_eq!(output, format!("{two}\n"));
+        let output = show(ShowBranchArgs {
+            independent: true,
+            ..args(&["main", "topic", "main~1"])
+        });
+        assert_eq!(output, format!("{three}\n{topic}\n"));
+    }
+
+    #[test]
+    fn sorts_names_as_versions() {
+        let mut names = ["b10", "b2", "a", "b1", "b2a", "b9"];
+        names.sort_by(|a, b| version_cmp(a, b));
+        assert_eq!(names, ["a", "b1", "b2", "b2a", "b9", "b10"]);
+    }
+}
@@ -1,164 +1,70 @@
 use std::collections::BTreeMap;
-use std::fs::{read_dir, File};
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::{Path, PathBuf};
+use std::io::Write;
 
 use anyhow::Context;
 use clap::Args;
 
 use crate::commands::CommandArgs;
-use crate::utils::git_dir;
+use crate::utils::refs::RefCache;
+use crate::utils::repository::Repository;
 
 impl CommandArgs for ShowRefArgs {
-    fn run<W>(self, writer: &mut W) -> anyhow::Result<()>
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
     where
         W: Write,
     {
-        let git_dir = git_dir()?;
-        // Map of ref paths to their hashes, a BTreeMap is used
-        // to ensure the output is sorted by the ref paths
-        let mut refs = BTreeMap::<PathBuf, [u8; 40]>::new();
+        // All the refs are read through one cache, so HEAD reuses the branch read
+        // for refs/heads and each packed ref is found without re-reading packed-refs
+        let mut cache = RefCache::new(repo);
+        // Map of ref names to their hashes, a BTreeMap is used
+        // to ensure the output is sorted by the ref names
+        let mut refs = BTreeMap::<String, String>::new();
 
         // Clamp the abbrev and hash values to be between 4 and 40
         let abbrev = self.abbrev.clamp(4, 40);
         let hash_limit = self.hash.map(|n| n.clamp(4, 40));
 
         // Read the refs based on the flags
+        let mut prefixes = Vec::new();
         if self.heads {
-            read_refs(&git_dir, "refs/heads", &mut refs)?;
+            prefixes.push("refs/heads/");
         }
         if self.tags {
-            read_refs(&git_dir, "refs/tags", &mut refs)?;
+            prefixes.push("refs/tags/");
         }
         if !self.heads && !self.tags {
-            read_refs(&git_dir, "refs/heads", &mut refs)?;
-            read_refs(&git_dir, "refs/tags", &mut refs)?;
-            read_refs(&git_dir, "refs/remotes", &mut refs)?;
-            add_ref_if_exists(&git_dir, "refs/stash", &mut refs)?;
+            prefixes.extend(["refs/heads/", "refs/tags/", "refs/remotes/"]);
+            if let Some(hash) = cache.resolve("refs/stash")? {
+                refs.insert("refs/stash".to_string(), hash);
+            }
+        }
+        for prefix in prefixes {
+            refs.extend(cache.list(prefix)?);
         }
         if self.head {
-            read_head(&git_dir, &mut refs)?;
+            let hash = cache
+                .resolve("HEAD")?
+                .context("HEAD does not point to a commit")?;
+            refs.insert("HEAD".to_string(), hash);
         }
 
         let refs = refs
             .into_iter()
-            .map(|(path, hash)| {
+            .map(|(name, hash)| {
                 // If hash_limit is set, only show the first n characters of the hash
                 // and nothing else
                 if let Some(hash_limit) = hash_limit {
-                    return hash[0..hash_limit].to_vec();
+                    return hash[0..hash_limit].to_string();
                 }
                 // If abbrev is set, show the first n characters of the hash
-                // followed by a space and the path (from refs)
-                let mut entry = hash[0..abbrev].to_vec();
-                entry.push(b' ');
-                entry.extend_from_slice(path.to_string_lossy().as_bytes());
-                entry
+                // followed by a space and the name of the ref
+                format!("{} {name}", &hash[0..abbrev])
             })
-            .collect::<Vec<Vec<u8>>>()
-            .join(&b'\n');
-
-        writer.write_all(refs.as_slice()).context("write to stdout")
-    }
-}
-
-/// Recursively read all refs in a directory
-/// and add them to the refs map.
-///
-/// # Arguments
-///
-/// * `git_dir` - The path to the .git directory
-/// * `subdir` - The subdirectory to read refs from, relative to `git_dir`
-/// * `refs` - The map to add the refs to
-fn read_refs(
-    git_dir: &Path,
-    subdir: &str,
-    refs: &mut BTreeMap<PathBuf, [u8; 40]>,
-) -> anyhow::Result<()> {
-    let subdir_path = git_dir.join(subdir);
-
-    if !subdir_path.exists() {
-        return Ok(());
-    }
-
-    for entry in read_dir(subdir_path)? {
-        let ref_path = entry?.path();
-        if ref_path.is_dir() {
-            read_refs(git_dir, &ref_path.to_string_lossy(), refs)?;
-        } else {
-            add_ref(git_dir, &ref_path, refs)?;
-        }
-    }
-    Ok(())
-}
-
-/// Add a ref to the refs map if the file exists.
-///
-/// # Arguments
-///
-/// * `git_dir` - The path to the .git directory
-/// * `sub_path` - The path to the ref file, relative to `git_dir`
-/// * `refs` - The map to add the ref to
-fn add_ref_if_exists(
-    git_dir: &Path,
-    sub_path: &str,
-    refs: &mut BTreeMap<PathBuf, [u8; 40]>,
-) -> anyhow::Result<()> {
-    let ref_path = git_dir.join(sub_path);
-    if ref_path.exists() {
-        add_ref(git_dir, &ref_path, refs)?;
-    }
-    Ok(())
-}
-
-/// Add a ref to the refs map.
-///
-/// # Arguments
-///
-/// * `git_dir` - The path to the .git directory
-/// * `path` - The path to the ref file
-/// * `refs` - The map to add the ref to
-fn add_ref(
-    git_dir: &Path,
-    path: &Path,
-    refs: &mut BTreeMap<PathBuf, [u8; 40]>,
-) -> anyhow::Result<()> {
-    let mut file = File::open(path)?;
-    let mut hash = [0; 40];
-    file.read_exact(&mut hash)?;
-
-    let stripped_path = path.strip_prefix(git_dir)?;
-    refs.insert(stripped_path.to_path_buf(), hash);
-    Ok(())
-}
+            .collect::<Vec<_>>()
+            .join("\n");
 
-/// Read the HEAD file and add it to the refs map.
-///
-/// # Arguments
-///
-/// * `git_dir` - The path to the .git directory
-/// * `refs` - The map to add the HEAD ref to
-fn read_head(git_dir: &Path, refs: &mut BTreeMap<PathBuf, [u8; 40]>) -> anyhow::Result<()> {
-    let head_path = git_dir.join("HEAD");
-    let mut head = File::open(head_path)?;
-    let mut head_path = Vec::new();
-
-    head.seek(SeekFrom::Start(5))?; // Skip the "ref: " prefix
-    head.read_to_end(&mut head_path)?;
-    head_path.pop(); // Remove the trailing newline
-
-    let head_path = PathBuf::from(std::str::from_utf8(&head_path)?);
-    // If refs/heads was read, we don't need to re-read the HEAD file
-    if let Some(&hash) = refs.get(&head_path) {
-        refs.insert(PathBuf::from("HEAD"), hash);
-        return Ok(());
+        writer.write_all(refs.as_bytes()).context("write to stdout")
     }
-
-    let mut head = File::open(git_dir.join(head_path))?;
-    let mut hash = [0; 40];
-    head.read_exact(&mut hash)?;
-    refs.insert(PathBuf::from("HEAD"), hash);
-    Ok(())
 }
 
 #[derive(Args, Debug)]
@@ -184,8 +90,7 @@ pub(crate) struct ShowRefArgs {
 mod tests {
     use super::*;
     use crate::commands::init::get_head_ref_content;
-    use crate::utils::env;
-    use crate::utils::test::{TempEnv, TempPwd};
+    use crate::utils::test::TempRepo;
 
     const HEAD_HASH: &str = "aabbccddeeff00112233445566778899aabbccdd";
     const HEAD_NAME: &str = "main";
@@ -205,10 +110,9 @@ mod tests {
     /// Create a temporary `.git/refs` directory with refs of the specified types.
     ///
     /// The `stash` and `HEAD` refs are always created.
-    fn create_temp_refs<const N: usize>(refs: [Ref; N]) -> TempPwd {
-        let _env = TempEnv::unset(env::GIT_DIR);
-        let pwd = TempPwd::new();
-        let git_dir = pwd.path().join(".git");
+    fn create_temp_refs<const N: usize>(refs: [Ref; N]) -> TempRepo {
+        let temp = TempRepo::new();
+        let git_dir = temp.join(".git");
         let refs_dir = git_dir.join("refs");
 
         std::fs::create_dir_all(&refs_dir).unwrap();
@@ -234,12 +138,12 @@ mod tests {
         let stash_file = refs_dir.join("stash");
         std::fs::write(&stash_file, STASH_HASH).unwrap();
 
-        pwd
+        temp
     }
 
     #[test]
     fn show_refs() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -261,7 +165,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{HEAD_HASH} refs/heads/{HEAD_NAME}\n\
              {REMOTE_HASH} refs/remotes/{REMOTE_NAME}\n\
@@ -276,7 +180,7 @@ mod tests {
 
     #[test]
     fn show_refs_with_head() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -298,7 +202,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{HEAD_HASH} HEAD\n\
              {HEAD_HASH} refs/heads/{HEAD_NAME}\n\
@@ -314,7 +218,7 @@ mod tests {
 
     #[test]
     fn show_head_refs() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -336,7 +240,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!("{HEAD_HASH} refs/heads/{HEAD_NAME}");
 
         assert!(result.is_ok());
@@ -345,7 +249,7 @@ mod tests {
 
     #[test]
     fn show_tag_refs() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -367,7 +271,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!("{TAG_HASH} refs/tags/{TAG_NAME}");
 
         assert!(result.is_ok());
@@ -376,7 +280,7 @@ mod tests {
 
     #[test]
     fn show_tag_and_head_refs() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -398,7 +302,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{HEAD_HASH} refs/heads/{HEAD_NAME}\n\
              {TAG_HASH} refs/tags/{TAG_NAME}",
@@ -410,7 +314,7 @@ mod tests {
 
     #[test]
     fn show_tag_and_head_refs_with_head() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -432,7 +336,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{HEAD_HASH} HEAD\n\
              {HEAD_HASH} refs/heads/{HEAD_NAME}\n\
@@ -445,7 +349,7 @@ mod tests {
 
     #[test]
     fn show_tag_refs_with_head() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -467,7 +371,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{HEAD_HASH} HEAD\n\
              {TAG_HASH} refs/tags/{TAG_NAME}",
@@ -479,7 +383,7 @@ mod tests {
 
     #[test]
     fn show_no_tag_refs() {
-        let _pwd = create_temp_refs([]);
+        let temp = create_temp_refs([]);
         let args = ShowRefArgs {
             head: false,
             heads: false,
@@ -489,7 +393,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
 
         assert!(result.is_ok());
         assert_eq!(output, Vec::new());
@@ -497,7 +401,7 @@ mod tests {
 
     #[test]
     fn abbreviate_ref_hashes() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -519,7 +423,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{} refs/heads/{HEAD_NAME}\n\
              {} refs/remotes/{REMOTE_NAME}\n\
@@ -538,7 +442,7 @@ mod tests {
 
     #[test]
     fn abbreviate_ref_hashes_below_min() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -560,7 +464,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{} refs/heads/{HEAD_NAME}\n\
              {} refs/remotes/{REMOTE_NAME}\n\
@@ -579,7 +483,7 @@ mod tests {
 
     #[test]
     fn abbreviate_ref_hashes_above_max() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -601,7 +505,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{} refs/heads/{HEAD_NAME}\n\
              {} refs/remotes/{REMOTE_NAME}\n\
@@ -617,7 +521,7 @@ mod tests {
 
     #[test]
     fn show_hashes_with_limit() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -639,7 +543,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{}\n{}\n{}\n{}",
             &HEAD_HASH[0..8],
@@ -655,7 +559,7 @@ mod tests {
 
     #[test]
     fn show_hashes_with_limit_below_min() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -677,7 +581,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{}\n{}\n{}\n{}",
             &HEAD_HASH[0..4],
@@ -693,7 +597,7 @@ mod tests {
 
     #[test]
     fn show_hashes_with_limit_above_max() {
-        let _pwd = create_temp_refs([
+        let temp = create_temp_refs([
             Ref {
                 dir: "tags",
                 name: TAG_NAME,
@@ -715,7 +619,7 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         let expected = format!(
             "{}\n{}\n{}\n{}",
             &HEAD_HASH, &REMOTE_HASH, &STASH_HASH, &TAG_HASH,
@@ -728,8 +632,8 @@ mod tests {
 
     #[test]
     fn allow_invalid_head_path_without_head_arg() {
-        let pwd = create_temp_refs([]);
-        let head_file = pwd.path().join(".git/HEAD");
+        let temp = create_temp_refs([]);
+        let head_file = temp.join(".git/HEAD");
         // Overwrite the HEAD file with an invalid path
         std::fs::write(&head_file, get_head_ref_content("invalid")).unwrap();
 
@@ -742,14 +646,14 @@ mod tests {
         };
 
         let mut output = Vec::new();
-        let result = args.run(&mut output);
+        let result = args.run(&temp.repo(), &mut output);
         assert!(result.is_ok());
     }
 
     #[test]
     fn fail_on_invalid_head_path() {
-        let pwd = create_temp_refs([]);
-        let head_file = pwd.path().join(".git/HEAD");
+        let temp = create_temp_refs([]);
+        let head_file = temp.join(".git/HEAD");
         // Overwrite the HEAD file with an invalid path
         std::fs::write(&head_file, get_head_ref_content("invalid")).unwrap();
 
@@ -761,7 +665,7 @@ mod tests {
             abbrev: 40,
         };
 
-        let result = args.run(&mut Vec::new());
+        let result = args.run(&temp.repo(), &mut Vec::new());
         assert!(result.is_err());
     }
 }
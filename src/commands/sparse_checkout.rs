@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+use crate::commands::CommandArgs;
+use crate::utils::advice::{advise, warning, Advice};
+use crate::utils::config::Config;
+use crate::utils::index::Index;
+use crate::utils::repository::Repository;
+use crate::utils::sparse::{
+    escape_cone_directory, set_sparse_config, sparse_checkout_path, update_sparse_work_tree, Sparse,
+};
+
+/// The patterns of a cone with only the files at the root
+const ROOT_PATTERNS: &str = "/*\n!/*/\n";
+
+impl CommandArgs for SparseCheckoutArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.command {
+            SparseCheckoutCommand::Init(args) => {
+                // Existing patterns are kept
+                let path = sparse_checkout_path(repo)?;
+                if !path.exists() {
+                    write_patterns(repo, ROOT_PATTERNS)?;
+                }
+                set_sparse_config(repo, true, !args.no_cone)?;
+                reapply(repo)
+            },
+            SparseCheckoutCommand::Set(args) => set(repo, args),
+            SparseCheckoutCommand::List => {
+                if Sparse::load(repo)?.is_none() {
+                    anyhow::bail!("this worktree is not sparse");
+                }
+                let cone = Config::load(repo)?
+                    .get_bool("core.sparseCheckoutCone")?
+                    .unwrap_or(false);
+                match Sparse::read(repo, cone)? {
+                    Sparse::Cone(directories) => {
+                        for directory in directories {
+                            writeln!(writer, "{directory}")?;
+                        }
+                    },
+                    Sparse::Patterns(patterns) => {
+                        for pattern in patterns {
+                            writeln!(writer, "{}", pattern.text)?;
+                        }
+                    },
+                }
+                Ok(())
+            },
+            SparseCheckoutCommand::Reapply => {
+                if Sparse::load(repo)?.is_none() {
+                    anyhow::bail!("must be in a sparse-checkout to reapply sparsity patterns");
+                }
+                reapply(repo)
+            },
+            SparseCheckoutCommand::Disable => {
+                set_sparse_config(repo, false, false)?;
+                reapply(repo)
+            },
+        }
+    }
+}
+
+/// Replace the patterns of the sparse checkout, enabling it if needed.
+fn set(repo: &Repository, args: SetArgs) -> anyhow::Result<()> {
+    // The mode of the sparse checkout is kept unless another one is given
+    let config = Config::load(repo)?;
+    let cone = match (args.cone, args.no_cone) {
+        (true, _) => true,
+        (_, true) => false,
+        _ if config.get_bool("core.sparseCheckout")?.unwrap_or(false) => {
+            config.get_bool("core.sparseCheckoutCone")?.unwrap_or(false)
+        },
+        _ => true,
+    };
+
+    let content = match cone {
+        true => cone_patterns(&args.patterns)?,
+        false => args
+            .patterns
+            .iter()
+            .map(|pattern| format!("{pattern}\n"))
+            .collect(),
+    };
+    write_patterns(repo, &content)?;
+    set_sparse_config(repo, true, cone)?;
+    reapply(repo)
+}
+
+/// Write the patterns of a cone including the given directories: the files at
+/// the root, each directory with all its content, and the files directly in the
+/// directories leading to them.
+fn cone_patterns(directories: &[String]) -> anyhow::Result<String> {
+    let mut listed: Vec<&str> = Vec::new();
+    for directory in directories {
+        if directory.starts_with('/') {
+            anyhow::bail!("specify directories rather than patterns (no leading slash)");
+        }
+        if directory.contains(['*', '?', '[', ']', '\\']) {
+            anyhow::bail!(
+                "specify directories rather than patterns.  \
+                 If your directory really has any of '*?[]\\' in it, pass --skip-checks"
+            );
+        }
+        listed.push(directory.trim_end_matches('/'));
+    }
+    listed.retain(|directory| !directory.is_empty());
+
+    // A directory inside a listed one is already included, and the directories
+    // leading to a listed one only include their own files
+    let mut cone = BTreeMap::new();
+    listed.sort_unstable();
+    for directory in listed {
+        let covered = directory
+            .match_indices('/')
+            .map(|(slash, _)| &directory[..slash])
+            .chain([directory])
+            .any(|parent| cone.get(parent) == Some(&true));
+        if covered {
+            continue;
+        }
+        for (slash, _) in directory.match_indices('/') {
+            cone.entry(&directory[..slash]).or_insert(false);
+        }
+        cone.insert(directory, true);
+    }
+
+    let mut content = ROOT_PATTERNS.to_string();
+    for (directory, recursive) in cone {
+        let escaped = escape_cone_directory(directory);
+        content.push_str(&format!("/{escaped}/\n"));
+        if !recursive {
+            content.push_str(&format!("!/{escaped}/*/\n"));
+        }
+    }
+    Ok(content)
+}
+
+fn write_patterns(repo: &Repository, content: &str) -> anyhow::Result<()> {
+    let path = sparse_checkout_path(repo)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context(format!("create {}", parent.display()))?;
+    }
+    std::fs::write(&path, content).context(format!("write {}", path.display()))
+}
+
+/// Update the working tree and the skip-worktree bits of the index to match the
+/// patterns of the sparse checkout (or to include every file if it is disabled).
+fn reapply(repo: &Repository) -> anyhow::Result<()> {
+    let sparse = Sparse::load(repo)?;
+    let mut index = Index::load(repo)?;
+    let left = update_sparse_work_tree(repo, &mut index, &repo.work_tree()?, sparse.as_ref())?;
+    index.write(repo)?;
+
+    if !left.is_empty() {
+        let paths: Vec<_> = left
+            .iter()
+            .map(|path| String::from_utf8_lossy(path))
+            .collect();
+        warning(&format!(
+            "The following paths are not up to date and were left despite sparse patterns:\n\t{}",
+            paths.join("\n\t")
+        ));
+        advise(
+            repo,
+            Advice::UpdateSparsePath,
+            "After fixing the above paths, you may want to run `git sparse-checkout reapply`.",
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct SparseCheckoutArgs {
+    #[command(subcommand)]
+    command: SparseCheckoutCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum SparseCheckoutCommand {
+    /// enable the sparse checkout, with only the files at the root unless patterns were set
+    Init(InitArgs),
+    /// set the directories (or patterns with --no-cone) to check out, enabling the sparse checkout
+    Set(SetArgs),
+    /// list the directories (or patterns) of the sparse checkout
+    List,
+    /// update the working tree to match the patterns again
+    Reapply,
+    /// check out every file again, disabling the sparse checkout
+    Disable,
+}
+
+#[derive(Args, Debug)]
+struct InitArgs {
+    /// select directories (the default)
+    #[arg(long, overrides_with = "no_cone")]
+    cone: bool,
+    /// select files with patterns in the syntax of .gitignore files
+    #[arg(long)]
+    no_cone: bool,
+}
+
+#[derive(Args, Debug)]
+struct SetArgs {
+    /// select directories (the default for a new sparse checkout)
+    #[arg(long, overrides_with = "no_cone")]
+    cone: bool,
+    /// select files with patterns in the syntax of .gitignore files
+    #[arg(long)]
+    no_cone: bool,
+    /// the directories (or patterns) to check out
+    #[arg(value_name = "pattern")]
+    patterns: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_patterns_of_a_cone() {
+        let directories = |list: &[&str]| list.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            cone_patterns(&directories(&["a/b", "a", "d/"])).unwrap(),
+            "/*\n!/*/\n/a/\n/d/\n"
+        );
+        assert_eq!(
+            cone_patterns(&directories(&["a/b/c", "e"])).unwrap(),
+            "/*\n!/*/\n/a/\n!/a/*/\n/a/b/\n!/a/b/*/\n/a/b/c/\n/e/\n"
+        );
+        assert_eq!(cone_patterns(&[]).unwrap(), ROOT_PATTERNS);
+
+        let err = cone_patterns(&directories(&["/e"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "specify directories rather than patterns (no leading slash)"
+        );
+        assert!(cone_patterns(&directories(&["*.txt"])).is_err());
+    }
+}
@@ -0,0 +1,683 @@
+use std::io::Write;
+
+use clap::{Args, Subcommand};
+
+use crate::commands::add::{index_entry, write_work_tree};
+use crate::commands::status::StatusArgs;
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::checkout::{
+    check_overwritten, checkout_entry, remove_empty_parents, remove_path, reset_index, switch_tree,
+    tree_paths, Operation,
+};
+use crate::utils::commit::{create_commit, read_commit};
+use crate::utils::convert::Conversion;
+use crate::utils::ignore::Ignore;
+use crate::utils::index::Index;
+use crate::utils::merge::merge_trees;
+use crate::utils::path_from_bytes;
+use crate::utils::reflog::{append_reflog, log_ref_update, read_reflog, write_reflog, ReflogEntry};
+use crate::utils::refs::{delete_ref, head_branch, resolve_ref, shorten_ref, write_ref};
+use crate::utils::repository::Repository;
+use crate::utils::tree::flatten_tree;
+use crate::utils::work_tree::list_files;
+
+/// The ref pointing to the latest stash entry, whose reflog holds the others
+const STASH_REF: &str = "refs/stash";
+
+impl CommandArgs for StashArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        // Without a subcommand, the changes are pushed
+        match self.command.unwrap_or(StashCommand::Push(self.push)) {
+            StashCommand::Push(args) => push(repo, args, writer),
+            StashCommand::List => {
+                for (position, entry) in stash_entries(repo)?.iter().enumerate() {
+                    writeln!(writer, "stash@{{{position}}}: {}", entry.message)?;
+                }
+                Ok(())
+            },
+            StashCommand::Apply(args) => {
+                let stash = find_stash(repo, args.stash.as_deref())?;
+                apply(repo, &stash.hash, args.index, args.quiet, writer)
+            },
+            StashCommand::Pop(args) => {
+                let stash = find_stash(repo, args.stash.as_deref())?;
+                apply(repo, &stash.hash, args.index, args.quiet, writer).map_err(|err| {
+                    anyhow::anyhow!("{err}\nThe stash entry is kept in case you need it again.")
+                })?;
+                drop_stash(repo, stash, args.quiet, writer)
+            },
+            StashCommand::Drop(args) => {
+                let stash = find_stash(repo, args.stash.as_deref())?;
+                drop_stash(repo, stash, args.quiet, writer)
+            },
+            StashCommand::Clear => delete_ref(repo, STASH_REF).map(|_| ()),
+        }
+    }
+}
+
+/// Save the local changes in a new stash entry and reset the working tree to HEAD.
+fn push<W>(repo: &Repository, args: PushArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let Some(head) = resolve_ref(repo, "HEAD")? else {
+        anyhow::bail!("You do not have the initial commit yet");
+    };
+    let head_commit = read_commit(repo, &head)?;
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::load(repo)?;
+    if let Some(entry) = index.entries().iter().find(|entry| entry.stage != 0) {
+        anyhow::bail!(
+            "{}: needs merge\nCannot save the current index state",
+            String::from_utf8_lossy(&entry.path)
+        );
+    }
+    let index_tree = index.write_tree(repo, false)?;
+
+    let work_tree_hash = write_work_tree(repo, &index, &work_tree)?;
+
+    let mut conversion = Conversion::load(repo)?;
+    let untracked: Vec<_> = if args.include_untracked {
+        let mut ignore = Ignore::load(repo)?;
+        list_files(&work_tree, Some(&mut ignore))?
+            .into_iter()
+            .filter(|file| !index.contains(&file.path))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    if index_tree == head_commit.tree && work_tree_hash == index_tree && untracked.is_empty() {
+        if !args.quiet {
+            writeln!(writer, "No local changes to save")?;
+        }
+        return Ok(());
+    }
+    if args.staged && index_tree == head_commit.tree {
+        if !args.quiet {
+            eprintln!("No staged changes");
+        }
+        return Err(SilentExit(1).into());
+    }
+    // With --staged, the staged changes are reverted in the working tree while
+    // the others are kept
+    let remaining = match args.staged {
+        true => Some(
+            merge_trees(repo, &index_tree, &work_tree_hash, &head_commit.tree)
+                .map_err(|_| anyhow::anyhow!("Cannot remove worktree changes"))?,
+        ),
+        false => None,
+    };
+
+    let branch = match head_branch(repo)? {
+        Some(branch) => shorten_ref(&branch).to_string(),
+        None => "(no branch)".to_string(),
+    };
+    let base = format!("{branch}: {} {}", &head[..7], head_commit.subject());
+
+    // The stash commit has HEAD, the index and the untracked files as its parents
+    let index_commit = create_commit(
+        repo,
+        &index_tree,
+        vec![head.clone()],
+        &format!("index on {base}\n"),
+    )?;
+    let mut parents = vec![head.clone(), index_commit];
+    if !untracked.is_empty() {
+        let mut untracked_index = Index::default();
+        for file in &untracked {
+            untracked_index.add_entry(index_entry(
+                repo,
+                &work_tree,
+                file.clone(),
+                &mut conversion,
+            )?);
+        }
+        let tree = untracked_index.write_tree(repo, false)?;
+        let message = format!("untracked files on {base}\n");
+        parents.push(create_commit(repo, &tree, Vec::new(), &message)?);
+    }
+
+    // Unlike its parents, the stash commit has no newline after its message
+    let message = match &args.message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {base}"),
+    };
+    // Only the staged changes are stashed with --staged
+    let stashed_tree = match args.staged {
+        true => &index_tree,
+        false => &work_tree_hash,
+    };
+    let stash = create_commit(repo, stashed_tree, parents, &message)?;
+    let previous = resolve_ref(repo, STASH_REF)?;
+    write_ref(repo, STASH_REF, &stash)?;
+    append_reflog(repo, STASH_REF, previous.as_deref(), &stash, &message)?;
+    if !args.quiet {
+        writeln!(writer, "Saved working directory and index state {message}")?;
+    }
+
+    // Then the changes are removed, keeping the staged ones with --keep-index
+    switch_tree(
+        repo,
+        &mut index,
+        &work_tree,
+        &work_tree_hash,
+        remaining.as_ref().unwrap_or(&head_commit.tree),
+    )?;
+    reset_index(repo, &mut index, &head_commit.tree)?;
+    for file in &untracked {
+        let path = work_tree.join(path_from_bytes(&file.path));
+        remove_path(&path)?;
+        remove_empty_parents(&path, &work_tree);
+    }
+    // The staged changes are all stashed with --staged, leaving none to keep
+    if args.keep_index && !args.no_keep_index && !args.staged {
+        switch_tree(repo, &mut index, &work_tree, &head_commit.tree, &index_tree)?;
+    }
+    index.write(repo)?;
+    log_ref_update(repo, "HEAD", Some(&head), &head, "reset: moving to HEAD")
+}
+
+/// Apply the changes of a stash entry to the working tree.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to apply the changes in
+/// * `stash` - The hash of the stash commit
+/// * `restore_index` - Whether to also restore the changes that were staged
+/// * `quiet` - Whether to skip showing the status afterwards
+fn apply<W>(
+    repo: &Repository,
+    stash: &str,
+    restore_index: bool,
+    quiet: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let commit = read_commit(repo, stash)?;
+    if commit.parents.len() < 2 {
+        anyhow::bail!("'{stash}' is not a stash-like commit");
+    }
+    let base_tree = read_commit(repo, &commit.parents[0])?.tree;
+    let stashed_index = read_commit(repo, &commit.parents[1])?.tree;
+    let untracked = match commit.parents.get(2) {
+        Some(parent) => flatten_tree(repo, &read_commit(repo, parent)?.tree)?,
+        None => Vec::new(),
+    };
+
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::load(repo)?;
+    if index.entries().iter().any(|entry| entry.stage != 0) {
+        anyhow::bail!("cannot apply a stash in the middle of a merge");
+    }
+    let current = index.write_tree(repo, false)?;
+
+    for entry in &untracked {
+        if work_tree
+            .join(path_from_bytes(&entry.name))
+            .symlink_metadata()
+            .is_ok()
+        {
+            anyhow::bail!(
+                "{} already exists, no checkout\ncould not restore untracked files from stash",
+                String::from_utf8_lossy(&entry.name)
+            );
+        }
+    }
+
+    // The staged changes are only restored if they differ from what was stashed on
+    let staged = if restore_index && stashed_index != base_tree && stashed_index != current {
+        let tree = merge_trees(repo, &base_tree, &current, &stashed_index)
+            .map_err(|_| anyhow::anyhow!("Conflicts in index. Try without --index."))?;
+        Some(tree)
+    } else {
+        None
+    };
+    let merged = merge_trees(repo, &base_tree, &current, &commit.tree)?;
+    check_overwritten(
+        repo,
+        &index,
+        &work_tree,
+        &current,
+        &merged,
+        Operation::Merge,
+    )?;
+
+    switch_tree(repo, &mut index, &work_tree, &current, &merged)?;
+    match staged {
+        Some(tree) => reset_index(repo, &mut index, &tree)?,
+        None => {
+            // The changes are left unstaged, except for the files the stash added
+            let current_paths = tree_paths(repo, &current)?;
+            let added: Vec<_> = index
+                .entries()
+                .iter()
+                .filter(|entry| !current_paths.contains_key(&entry.path))
+                .cloned()
+                .collect();
+            reset_index(repo, &mut index, &current)?;
+            for entry in added {
+                index.add_entry(entry);
+            }
+        },
+    }
+    index.write(repo)?;
+
+    let mut conversion = Conversion::load(repo)?;
+    for entry in &untracked {
+        checkout_entry(repo, entry, &work_tree, &mut conversion)?;
+    }
+
+    if !quiet {
+        StatusArgs::default().run(repo, writer)?;
+    }
+    Ok(())
+}
+
+/// The stash entries, newest first.
+fn stash_entries(repo: &Repository) -> anyhow::Result<Vec<ReflogEntry>> {
+    let mut entries = read_reflog(repo, STASH_REF)?;
+    entries.reverse();
+    Ok(entries)
+}
+
+/// A stash entry selected on the command line
+struct Stash {
+    /// The name the entry is reported by (e.g. `stash@{1}`)
+    name: String,
+    /// The position of the entry, 0 being the latest
+    position: usize,
+    /// The hash of the stash commit
+    hash: String,
+}
+
+/// Find a stash entry given as `stash@{<n>}`, `refs/stash@{<n>}` or `<n>`
+/// (the latest entry by default).
+fn find_stash(repo: &Repository, stash: Option<&str>) -> anyhow::Result<Stash> {
+    let entries = stash_entries(repo)?;
+    if entries.is_empty() {
+        anyhow::bail!("No stash entries found.");
+    }
+
+    let name = match stash {
+        None => format!("{STASH_REF}@{{0}}"),
+        Some(stash) if stash.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{STASH_REF}@{{{stash}}}")
+        },
+        Some(stash) => stash.to_string(),
+    };
+    let position = name
+        .strip_prefix("refs/")
+        .unwrap_or(&name)
+        .strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .and_then(|position| position.parse::<usize>().ok());
+
+    let Some(position) = position else {
+        anyhow::bail!("{name} is not a valid reference");
+    };
+    let Some(entry) = entries.get(position) else {
+        let (log, _) = name.split_once('@').unwrap_or((&name, ""));
+        anyhow::bail!("log for '{log}' only has {} entries", entries.len());
+    };
+
+    Ok(Stash {
+        hash: entry.new.clone(),
+        name,
+        position,
+    })
+}
+
+/// Remove a stash entry from the reflog, moving `refs/stash` to the next one if needed.
+fn drop_stash<W>(repo: &Repository, stash: Stash, quiet: bool, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let mut entries = read_reflog(repo, STASH_REF)?;
+    let position = entries.len() - 1 - stash.position;
+    let removed = entries.remove(position);
+
+    // The entry after the removed one now follows the one before it
+    if let Some(next) = entries.get_mut(position) {
+        next.old = removed.old;
+    }
+    match entries.last() {
+        Some(latest) => write_ref(repo, STASH_REF, &latest.new)?,
+        None => {
+            delete_ref(repo, STASH_REF)?;
+        },
+    }
+    write_reflog(repo, STASH_REF, &entries)?;
+
+    if !quiet {
+        writeln!(writer, "Dropped {} ({})", stash.name, stash.hash)?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+pub(crate) struct StashArgs {
+    #[command(subcommand)]
+    command: Option<StashCommand>,
+    #[command(flatten)]
+    push: PushArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum StashCommand {
+    /// save the local changes in a new stash entry and revert them (the default)
+    Push(PushArgs),
+    /// list the stash entries
+    List,
+    /// apply the changes of a stash entry (the latest by default)
+    Apply(ApplyArgs),
+    /// apply the changes of a stash entry and remove it
+    Pop(ApplyArgs),
+    /// remove a stash entry
+    Drop(DropArgs),
+    /// remove all stash entries
+    Clear,
+}
+
+#[derive(Args, Debug)]
+struct PushArgs {
+    /// the description of the stash entry
+    #[arg(short, long)]
+    message: Option<String>,
+    /// also stash (and remove) the untracked files
+    #[arg(short = 'u', long)]
+    include_untracked: bool,
+    /// keep the changes that are staged in the index
+    #[arg(short, long, overrides_with = "no_keep_index")]
+    keep_index: bool,
+    /// remove the changes that are staged in the index as well (the default)
+    #[arg(long, overrides_with = "keep_index")]
+    no_keep_index: bool,
+    /// only stash (and remove) the changes that are staged in the index
+    #[arg(short = 'S', long, conflicts_with = "include_untracked")]
+    staged: bool,
+    /// suppress feedback messages
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+#[derive(Args, Debug)]
+struct ApplyArgs {
+    /// also restore the changes that were staged
+    #[arg(long)]
+    index: bool,
+    /// suppress feedback messages
+    #[arg(short, long)]
+    quiet: bool,
+    /// the stash entry (`stash@{<n>}` or `<n>`)
+    stash: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct DropArgs {
+    /// suppress feedback messages
+    #[arg(short, long)]
+    quiet: bool,
+    /// the stash entry (`stash@{<n>}` or `<n>`)
+    stash: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::reflog::NULL_HASH;
+    use crate::utils::refs::{update_head, write_symref};
+    use crate::utils::test::TempRepo;
+
+    /// Create a repository with `a.txt` and `b.txt` committed on `main`.
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let mut index = Index::default();
+        for (path, content) in [("a.txt", "a"), ("b.txt", "b")] {
+            fs::write(temp.join(path), content).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            let metadata = fs::symlink_metadata(temp.join(path)).unwrap();
+            index.add_entry(IndexEntry::from_metadata(path.into(), hash, &metadata));
+        }
+        let tree = index.write_tree(&repo, false).unwrap();
+        index.write(&repo).unwrap();
+        let commit = create_commit(&repo, &tree, Vec::new(), "initial\n").unwrap();
+        update_head(&repo, &commit, "commit").unwrap();
+
+        (temp, repo)
+    }
+
+    fn push_args() -> PushArgs {
+        PushArgs {
+            message: None,
+            include_untracked: false,
+            keep_index: false,
+            no_keep_index: false,
+            staged: false,
+            quiet: false,
+        }
+    }
+
+    fn stash(repo: &Repository, command: StashCommand) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        StashArgs {
+            command: Some(command),
+            push: push_args(),
+        }
+        .run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn apply_args(stash: Option<&str>) -> ApplyArgs {
+        ApplyArgs {
+            index: false,
+            quiet: true,
+            stash: stash.map(str::to_string),
+        }
+    }
+
+    fn staged(repo: &Repository) -> Vec<String> {
+        let index = Index::load(repo).unwrap();
+        index
+            .entries()
+            .iter()
+            .map(|entry| String::from_utf8(entry.path.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pushes_and_pops_changes() {
+        let (temp, repo) = setup();
+        assert_eq!(
+            stash(&repo, StashCommand::Push(push_args())).unwrap(),
+            "No local changes to save\n"
+        );
+
+        fs::write(temp.join("a.txt"), "changed").unwrap();
+        fs::remove_file(temp.join("b.txt")).unwrap();
+        fs::write(temp.join("untracked.txt"), "untracked").unwrap();
+        let output = stash(
+            &repo,
+            StashCommand::Push(PushArgs {
+                include_untracked: true,
+                ..push_args()
+            }),
+        )
+        .unwrap();
+        assert!(output.starts_with("Saved working directory and index state WIP on main: "));
+        assert_eq!(fs::read_to_string(temp.join("a.txt")).unwrap(), "a");
+        assert!(temp.join("b.txt").exists());
+        assert!(!temp.join("untracked.txt").exists());
+
+        let stash_commit = read_commit(&repo, &resolve_ref(&repo, STASH_REF).unwrap().unwrap());
+        assert_eq!(stash_commit.unwrap().parents.len(), 3);
+
+        fs::write(temp.join("a.txt"), "other").unwrap();
+        stash(
+            &repo,
+            StashCommand::Push(PushArgs {
+                message: Some("second".to_string()),
+                ..push_args()
+            }),
+        )
+        .unwrap();
+        let list = stash(&repo, StashCommand::List).unwrap();
+        assert!(list.starts_with("stash@{0}: On main: second\nstash@{1}: WIP on main: "));
+
+        let output = stash(
+            &repo,
+            StashCommand::Pop(ApplyArgs {
+                quiet: false,
+                ..apply_args(Some("1"))
+            }),
+        )
+        .unwrap();
+        // The status is shown after applying the changes
+        assert!(output.starts_with("On branch main\n"));
+        assert!(output.contains("\nDropped refs/stash@{1} ("));
+        assert_eq!(fs::read_to_string(temp.join("a.txt")).unwrap(), "changed");
+        assert!(!temp.join("b.txt").exists());
+        assert_eq!(
+            fs::read_to_string(temp.join("untracked.txt")).unwrap(),
+            "untracked"
+        );
+        // The changes are not staged
+        assert_eq!(staged(&repo), vec!["a.txt", "b.txt"]);
+
+        let list = stash(&repo, StashCommand::List).unwrap();
+        assert_eq!(list, "stash@{0}: On main: second\n");
+        assert_eq!(read_reflog(&repo, STASH_REF).unwrap()[0].old, NULL_HASH);
+    }
+
+    #[test]
+    fn restores_staged_changes_with_index() {
+        let (temp, repo) = setup();
+        fs::write(temp.join("c.txt"), "c").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        let metadata = fs::symlink_metadata(temp.join("c.txt")).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"c").unwrap();
+        index.add_entry(IndexEntry::from_metadata("c.txt".into(), hash, &metadata));
+        index.write(&repo).unwrap();
+
+        stash(&repo, StashCommand::Push(push_args())).unwrap();
+        assert!(!temp.join("c.txt").exists());
+        assert_eq!(staged(&repo), vec!["a.txt", "b.txt"]);
+
+        stash(
+            &repo,
+            StashCommand::Apply(ApplyArgs {
+                index: true,
+                ..apply_args(None)
+            }),
+        )
+        .unwrap();
+        assert_eq!(staged(&repo), vec!["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(stash(&repo, StashCommand::List).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn stashes_or_keeps_staged_changes() {
+        let (temp, repo) = setup();
+        let stage = |path: &str, content: &str| {
+            fs::write(temp.join(path), content).unwrap();
+            let mut index = Index::load(&repo).unwrap();
+            let metadata = fs::symlink_metadata(temp.join(path)).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::from_metadata(path.into(), hash, &metadata));
+            index.write(&repo).unwrap();
+        };
+        let staged_args = || PushArgs {
+            staged: true,
+            ..push_args()
+        };
+
+        // Unstaged changes alone are not stashed
+        fs::write(temp.join("b.txt"), "unstaged").unwrap();
+        let err = stash(&repo, StashCommand::Push(staged_args())).unwrap_err();
+        assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+
+        stage("a.txt", "staged");
+        stage("c.txt", "c");
+        let index_tree = Index::load(&repo)
+            .unwrap()
+            .write_tree(&repo, false)
+            .unwrap();
+        stash(&repo, StashCommand::Push(staged_args())).unwrap();
+        let stash_commit = read_commit(&repo, &resolve_ref(&repo, STASH_REF).unwrap().unwrap());
+        assert_eq!(stash_commit.unwrap().tree, index_tree);
+        // Only the staged changes are removed
+        assert_eq!(fs::read_to_string(temp.join("a.txt")).unwrap(), "a");
+        assert!(!temp.join("c.txt").exists());
+        assert_eq!(fs::read_to_string(temp.join("b.txt")).unwrap(), "unstaged");
+        assert_eq!(staged(&repo), vec!["a.txt", "b.txt"]);
+
+        // The staged changes are kept in the index and the working tree with --keep-index
+        stage("a.txt", "kept");
+        stash(
+            &repo,
+            StashCommand::Push(PushArgs {
+                keep_index: true,
+                ..push_args()
+            }),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(temp.join("a.txt")).unwrap(), "kept");
+        assert_eq!(fs::read_to_string(temp.join("b.txt")).unwrap(), "b");
+        let index = Index::load(&repo).unwrap();
+        let kept = write_object(&repo, ObjectType::Blob, b"kept").unwrap();
+        assert_eq!(index.entry(b"a.txt", 0).unwrap().hash, kept);
+        assert_eq!(stash(&repo, StashCommand::List).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn refuses_to_overwrite_local_changes() {
+        let (temp, repo) = setup();
+        fs::write(temp.join("a.txt"), "stashed").unwrap();
+        stash(&repo, StashCommand::Push(push_args())).unwrap();
+
+        fs::write(temp.join("a.txt"), "local").unwrap();
+        let err = stash(&repo, StashCommand::Pop(apply_args(None))).unwrap_err();
+        assert!(err.to_string().starts_with(
+            "Your local changes to the following files would be overwritten by merge:\n\ta.txt\n"
+        ));
+        assert!(err
+            .to_string()
+            .ends_with("The stash entry is kept in case you need it again."));
+        assert_eq!(fs::read_to_string(temp.join("a.txt")).unwrap(), "local");
+
+        let err = stash(
+            &repo,
+            StashCommand::Drop(DropArgs {
+                quiet: false,
+                stash: Some("stash@{1}".to_string()),
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "log for 'stash' only has 1 entries");
+
+        stash(&repo, StashCommand::Clear).unwrap();
+        let err = stash(&repo, StashCommand::Pop(apply_args(None))).unwrap_err();
+        assert_eq!(err.to_string(), "No stash entries found.");
+    }
+}
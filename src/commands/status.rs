@@ -0,0 +1,842 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+
+use crate::commands::CommandArgs;
+use crate::utils::commit::read_commit;
+use crate::utils::ignore::Ignore;
+use crate::utils::index::{Index, IndexEntry, WorkTreeChange};
+use crate::utils::pathspec::Pathspec;
+use crate::utils::quote::{quote_path, quotes_non_ascii};
+use crate::utils::refs::{head_branch, resolve_ref, shorten_ref};
+use crate::utils::repository::Repository;
+use crate::utils::stats::Stats;
+use crate::utils::submodule::{submodule_status, SubmoduleStatus};
+use crate::utils::tree::{flatten_tree, MODE_GITLINK, MODE_TYPE_MASK};
+use crate::utils::work_tree::{list_files, WorkTreeFile};
+
+impl CommandArgs for StatusArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut stats = Stats::start();
+        let status = self.collect(repo, &mut stats)?;
+
+        if self.short || self.porcelain || self.null_terminated {
+            // -z alone implies the porcelain format
+            let porcelain = self.porcelain || !self.short;
+            status.write_short(self.branch, porcelain, self.null_terminated, writer)?;
+        } else {
+            status.write_long(writer)?;
+        }
+
+        // The statistics go to stderr, to keep the output parsable
+        if self.verbose {
+            stats.write(&mut std::io::stderr())?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the status shown in the message of a commit being edited: the long
+/// format, without hints.
+pub(crate) fn write_commit_status<W>(repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let status = StatusArgs::default().collect(repo, &mut Stats::start())?;
+    Status {
+        hints: false,
+        ..status
+    }
+    .write_long(writer)
+}
+
+impl StatusArgs {
+    /// Compare HEAD, the index and the working tree.
+    fn collect(&self, repo: &Repository, stats: &mut Stats) -> anyhow::Result<Status> {
+        let index = Index::load(repo)?;
+        stats.phase("read index");
+        let work_tree = repo.work_tree()?;
+        let prefix = repo.prefix()?;
+        // Unlike most commands, the whole working tree is shown by default
+        let pathspec = if self.paths.is_empty() {
+            Pathspec::all(&prefix)
+        } else {
+            Pathspec::new(&prefix, &self.paths)?
+        };
+
+        let head = resolve_ref(repo, "HEAD")?;
+        let mut head_entries = BTreeMap::new();
+        if let Some(head) = &head {
+            for entry in flatten_tree(repo, &read_commit(repo, head)?.tree)? {
+                head_entries.insert(entry.name, (entry.mode, entry.hash));
+            }
+        }
+        stats.phase("read HEAD");
+
+        let mut files: BTreeMap<Vec<u8>, FileStatus> = BTreeMap::new();
+
+        for entry in index.entries() {
+            if !pathspec.matches(&entry.path) {
+                continue;
+            }
+            if entry.mode == MODE_GITLINK && self.ignore_submodules == IgnoreSubmodules::All {
+                continue;
+            }
+            let file = files.entry(entry.path.clone()).or_default();
+
+            if entry.stage != 0 {
+                file.stage_mask |= 1 << (entry.stage - 1);
+                continue;
+            }
+
+            if entry.intent_to_add {
+                file.unstaged = Some(Change::Added);
+                continue;
+            }
+
+            file.staged = match head_entries.get(&entry.path) {
+                None => Some(Change::Added),
+                Some((mode, _)) if (mode ^ entry.mode) & MODE_TYPE_MASK != 0 => {
+                    Some(Change::TypeChanged)
+                },
+                Some((mode, hash)) if *mode != entry.mode || *hash != entry.hash => {
+                    Some(Change::Modified)
+                },
+                Some(_) => None,
+            };
+            file.unstaged = index.compare(entry, &work_tree)?.map(Change::from);
+
+            // Checked out submodules are compared with the commit recorded for them
+            if entry.mode == MODE_GITLINK && file.unstaged.is_none() {
+                file.submodule = self.submodule_status(repo, entry)?;
+                if file.submodule.is_some() {
+                    file.unstaged = Some(Change::Modified);
+                }
+            }
+        }
+
+        for path in head_entries.keys() {
+            if pathspec.matches(path) && !index.contains(path) {
+                files.entry(path.clone()).or_default().staged = Some(Change::Deleted);
+            }
+        }
+
+        files.retain(|_, file| {
+            file.staged.is_some() || file.unstaged.is_some() || file.stage_mask != 0
+        });
+        stats.phase("compare index");
+
+        let mut ignore = Ignore::load(repo)?;
+        let work_tree_files = list_files(&work_tree, Some(&mut ignore))?;
+        let untracked = untracked_paths(&index, &work_tree_files, &pathspec);
+        stats.phase("scan working tree");
+
+        let branch = head_branch(repo)?;
+        Ok(Status {
+            branch: branch
+                .as_deref()
+                .map(|branch| shorten_ref(branch).to_string()),
+            head,
+            merging: repo.git_dir()?.join("MERGE_HEAD").exists(),
+            files,
+            untracked,
+            pathspec,
+            quote_non_ascii: quotes_non_ascii(repo)?,
+            hints: true,
+        })
+    }
+
+    /// Compare a submodule with the commit recorded for it,
+    /// leaving out the changes that are ignored.
+    ///
+    /// # Returns
+    ///
+    /// How the submodule differs, or `None` if it does not (or is not checked out)
+    fn submodule_status(
+        &self,
+        repo: &Repository,
+        entry: &IndexEntry,
+    ) -> anyhow::Result<Option<SubmoduleStatus>> {
+        let untracked = self.ignore_submodules == IgnoreSubmodules::None;
+        let Some(mut status) = submodule_status(repo, entry, untracked)? else {
+            return Ok(None);
+        };
+        if self.ignore_submodules == IgnoreSubmodules::Dirty {
+            status.modified_content = false;
+        }
+
+        Ok(Some(status).filter(SubmoduleStatus::is_changed))
+    }
+}
+
+/// A change between two versions of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Added,
+    Modified,
+    Deleted,
+    TypeChanged,
+}
+
+impl Change {
+    /// The letter used in the short format
+    fn code(self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+        }
+    }
+
+    /// The label used in the long format
+    fn label(self) -> &'static str {
+        match self {
+            Change::Added => "new file:",
+            Change::Modified => "modified:",
+            Change::Deleted => "deleted:",
+            Change::TypeChanged => "typechange:",
+        }
+    }
+}
+
+impl From<WorkTreeChange> for Change {
+    fn from(change: WorkTreeChange) -> Self {
+        match change {
+            WorkTreeChange::Modified => Change::Modified,
+            WorkTreeChange::Deleted => Change::Deleted,
+            WorkTreeChange::TypeChanged => Change::TypeChanged,
+        }
+    }
+}
+
+/// The state of a tracked path
+#[derive(Debug, Default)]
+struct FileStatus {
+    /// The change between HEAD and the index
+    staged: Option<Change>,
+    /// The change between the index and the working tree
+    unstaged: Option<Change>,
+    /// The conflict stages present in the index (bit 0 for stage 1, and so on)
+    stage_mask: u8,
+    /// How a checked out submodule differs from the commit in the index
+    submodule: Option<SubmoduleStatus>,
+}
+
+impl FileStatus {
+    /// The letter of the change between the index and the working tree in the
+    /// short format, which tells apart the ways submodules can differ
+    fn unstaged_code(&self, porcelain: bool) -> char {
+        match self.submodule {
+            Some(submodule) if !porcelain => {
+                if submodule.new_commits {
+                    'M'
+                } else if submodule.modified_content {
+                    'm'
+                } else {
+                    '?'
+                }
+            },
+            _ => self.unstaged.map_or(' ', Change::code),
+        }
+    }
+}
+
+/// Describe how a submodule differs, e.g. `(new commits, modified content)`.
+fn submodule_label(status: &SubmoduleStatus) -> String {
+    let mut changes = Vec::new();
+    if status.new_commits {
+        changes.push("new commits");
+    }
+    if status.modified_content {
+        changes.push("modified content");
+    }
+    if status.untracked_content {
+        changes.push("untracked content");
+    }
+    format!(" ({})", changes.join(", "))
+}
+
+/// Get the short code and long label of an unmerged path.
+fn unmerged_status(stage_mask: u8) -> (&'static str, &'static str) {
+    match stage_mask {
+        1 => ("DD", "both deleted:"),
+        2 => ("AU", "added by us:"),
+        3 => ("UD", "deleted by them:"),
+        4 => ("UA", "added by them:"),
+        5 => ("DU", "deleted by us:"),
+        6 => ("AA", "both added:"),
+        _ => ("UU", "both modified:"),
+    }
+}
+
+/// Collect the untracked paths of the working tree.
+/// Directories without any tracked file are collapsed into a single `dir/` entry.
+fn untracked_paths(index: &Index, files: &[WorkTreeFile], pathspec: &Pathspec) -> Vec<Vec<u8>> {
+    let mut tracked_dirs = HashSet::new();
+    for entry in index.entries() {
+        let mut path = entry.path.as_slice();
+        while let Some(slash) = path.iter().rposition(|&byte| byte == b'/') {
+            let parent = &path[..slash];
+            if !tracked_dirs.insert(parent) {
+                break;
+            }
+            path = parent;
+        }
+    }
+
+    let mut untracked: Vec<Vec<u8>> = Vec::new();
+    for file in files {
+        if index.contains(&file.path) || !pathspec.matches(&file.path) {
+            continue;
+        }
+
+        // Find the outermost directory that has no tracked files
+        let mut path = file.path.clone();
+        for (position, _) in file
+            .path
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == b'/')
+        {
+            let dir = &file.path[..position];
+            if !tracked_dirs.contains(dir) {
+                path = dir.to_vec();
+                break;
+            }
+        }
+        if path != file.path || file.is_repository() {
+            path.push(b'/');
+        }
+
+        if untracked.last() != Some(&path) {
+            untracked.push(path);
+        }
+    }
+
+    untracked
+}
+
+/// The collected status of a working tree
+struct Status {
+    /// The short name of the current branch, or `None` if HEAD is detached
+    branch: Option<String>,
+    /// The commit HEAD points to, or `None` if there are no commits yet
+    head: Option<String>,
+    /// Whether a merge is in progress
+    merging: bool,
+    files: BTreeMap<Vec<u8>, FileStatus>,
+    untracked: Vec<Vec<u8>>,
+    pathspec: Pathspec,
+    /// Whether non-ASCII bytes of paths are quoted (`core.quotePath`)
+    quote_non_ascii: bool,
+    /// Whether the long format gives hints on what to do next (not in the
+    /// message of a commit)
+    hints: bool,
+}
+
+impl Status {
+    /// Write the short format, one `XY path` line per changed file (or
+    /// NUL-terminated entry, with unquoted paths relative to the root).
+    fn write_short<W>(
+        &self,
+        show_branch: bool,
+        porcelain: bool,
+        null_terminated: bool,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let terminator = if null_terminated { b'\0' } else { b'\n' };
+        if show_branch {
+            match (self.branch.as_deref(), &self.head) {
+                (Some(branch), Some(_)) => write!(writer, "## {branch}")?,
+                (Some(branch), None) => write!(writer, "## No commits yet on {branch}")?,
+                (None, _) => write!(writer, "## HEAD (no branch)")?,
+            }
+            writer.write_all(&[terminator])?;
+        }
+
+        // The porcelain format always uses paths relative to the root
+        let display = |path: &[u8]| {
+            if null_terminated {
+                path.to_vec()
+            } else if porcelain {
+                quote_path(path, self.quote_non_ascii)
+            } else {
+                self.display(path)
+            }
+        };
+
+        for (path, file) in &self.files {
+            let code = if file.stage_mask != 0 {
+                unmerged_status(file.stage_mask).0.to_string()
+            } else {
+                let staged = file.staged.map_or(' ', Change::code);
+                format!("{staged}{}", file.unstaged_code(porcelain))
+            };
+            write!(writer, "{code} ")?;
+            writer.write_all(&display(path))?;
+            writer.write_all(&[terminator])?;
+        }
+
+        for path in &self.untracked {
+            write!(writer, "?? ")?;
+            writer.write_all(&display(path))?;
+            writer.write_all(&[terminator])?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the long, human-readable format.
+    fn write_long<W>(&self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match (self.branch.as_deref(), &self.head) {
+            (Some(branch), _) => writeln!(writer, "On branch {branch}")?,
+            (None, Some(head)) => writeln!(writer, "HEAD detached at {}", &head[..7])?,
+            (None, None) => writeln!(writer, "Not currently on any branch.")?,
+        }
+        match self.head {
+            None if self.hints => writeln!(writer, "\nNo commits yet\n")?,
+            None => writeln!(writer, "\nInitial commit\n")?,
+            Some(_) => {},
+        }
+
+        let staged: Vec<_> = self
+            .files
+            .iter()
+            .filter_map(|(path, file)| Some((path, file.staged?)))
+            .collect();
+        let unstaged: Vec<_> = self
+            .files
+            .iter()
+            .filter(|(_, file)| file.stage_mask == 0)
+            .filter_map(|(path, file)| Some((path, file.unstaged?, file.submodule)))
+            .collect();
+        let unmerged: Vec<_> = self
+            .files
+            .iter()
+            .filter(|(_, file)| file.stage_mask != 0)
+            .map(|(path, file)| (path, unmerged_status(file.stage_mask).1))
+            .collect();
+
+        if self.merging && !unmerged.is_empty() {
+            writeln!(writer, "You have unmerged paths.")?;
+            self.write_hint("fix conflicts and run \"git commit\"", writer)?;
+            self.write_hint("use \"git merge --abort\" to abort the merge", writer)?;
+            writeln!(writer)?;
+        } else if self.merging {
+            writeln!(writer, "All conflicts fixed but you are still merging.")?;
+            self.write_hint("use \"git commit\" to conclude merge", writer)?;
+            writeln!(writer)?;
+        }
+
+        if !staged.is_empty() {
+            writeln!(writer, "Changes to be committed:")?;
+            self.write_unstage_hint(writer)?;
+            for (path, change) in &staged {
+                self.write_change(change.label(), 12, path, writer)?;
+            }
+            writeln!(writer)?;
+        }
+
+        if !unmerged.is_empty() {
+            writeln!(writer, "Unmerged paths:")?;
+            self.write_unstage_hint(writer)?;
+
+            let both_deleted = unmerged.iter().any(|(_, label)| *label == "both deleted:");
+            let deleted_modified = unmerged
+                .iter()
+                .any(|(_, label)| label.starts_with("deleted by"));
+            let hint = match (both_deleted, deleted_modified) {
+                (false, false) => "use \"git add <file>...\" to mark resolution",
+                (true, false) => "use \"git rm <file>...\" to mark resolution",
+                (_, true) => "use \"git add/rm <file>...\" as appropriate to mark resolution",
+            };
+            self.write_hint(hint, writer)?;
+
+            for (path, label) in &unmerged {
+                self.write_change(label, 17, path, writer)?;
+            }
+            writeln!(writer)?;
+        }
+
+        if !unstaged.is_empty() {
+            writeln!(writer, "Changes not staged for commit:")?;
+            if unstaged
+                .iter()
+                .any(|(_, change, _)| *change == Change::Deleted)
+            {
+                self.write_hint(
+                    "use \"git add/rm <file>...\" to update what will be committed",
+                    writer,
+                )?;
+            } else {
+                self.write_hint(
+                    "use \"git add <file>...\" to update what will be committed",
+                    writer,
+                )?;
+            }
+            self.write_hint(
+                "use \"git restore <file>...\" to discard changes in working directory",
+                writer,
+            )?;
+            if unstaged.iter().any(|(_, _, submodule)| {
+                submodule.is_some_and(|submodule| {
+                    submodule.modified_content || submodule.untracked_content
+                })
+            }) {
+                self.write_hint(
+                    "commit or discard the untracked or modified content in submodules",
+                    writer,
+                )?;
+            }
+            for (path, change, submodule) in &unstaged {
+                let Some(submodule) = submodule else {
+                    self.write_change(change.label(), 12, path, writer)?;
+                    continue;
+                };
+                write!(writer, "\t{:<12}", change.label())?;
+                writer.write_all(&self.display(path))?;
+                writeln!(writer, "{}", submodule_label(submodule))?;
+            }
+            writeln!(writer)?;
+        }
+
+        if !self.untracked.is_empty() {
+            writeln!(writer, "Untracked files:")?;
+            self.write_hint(
+                "use \"git add <file>...\" to include in what will be committed",
+                writer,
+            )?;
+            for path in &self.untracked {
+                write!(writer, "\t")?;
+                writer.write_all(&self.display(path))?;
+                writeln!(writer)?;
+            }
+            writeln!(writer)?;
+        }
+
+        if !staged.is_empty() {
+            // There is something to commit
+        } else if !unstaged.is_empty() || !unmerged.is_empty() {
+            writeln!(
+                writer,
+                "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+            )?;
+        } else if !self.untracked.is_empty() {
+            writeln!(
+                writer,
+                "nothing added to commit but untracked files present (use \"git add\" to track)"
+            )?;
+        } else if self.head.is_none() {
+            writeln!(
+                writer,
+                "nothing to commit (create/copy files and use \"git add\" to track)"
+            )?;
+        } else {
+            writeln!(writer, "nothing to commit, working tree clean")?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the hint on how to unstage changes, which is not given during a merge.
+    fn write_unstage_hint<W>(&self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.merging {
+            return Ok(());
+        }
+
+        if self.head.is_some() {
+            self.write_hint("use \"git restore --staged <file>...\" to unstage", writer)
+        } else {
+            self.write_hint("use \"git rm --cached <file>...\" to unstage", writer)
+        }
+    }
+
+    /// Write a hint on what to do next, in parentheses, unless hints are off.
+    fn write_hint<W>(&self, hint: &str, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.hints {
+            writeln!(writer, "  ({hint})")?;
+        }
+        Ok(())
+    }
+
+    /// Format a path relative to the current directory, quoted if needed,
+    /// keeping the trailing slash of untracked directories.
+    fn display(&self, path: &[u8]) -> Vec<u8> {
+        let relative = match path.strip_suffix(b"/") {
+            None => self.pathspec.relative(path),
+            Some(dir) => match self.pathspec.relative(dir) {
+                dir if dir.is_empty() => b"./".to_vec(),
+                mut dir => {
+                    dir.push(b'/');
+                    dir
+                },
+            },
+        };
+        quote_path(&relative, self.quote_non_ascii)
+    }
+
+    /// Write a labelled path, with the labels padded to a common width.
+    fn write_change<W>(
+        &self,
+        label: &str,
+        width: usize,
+        path: &[u8],
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        write!(writer, "\t{label:<width$}")?;
+        writer.write_all(&self.display(path))?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct StatusArgs {
+    /// give the output in the short format
+    #[arg(short, long)]
+    short: bool,
+    /// show the branch in the short format
+    #[arg(short, long)]
+    branch: bool,
+    /// give the output in a stable, easy-to-parse format for scripts
+    #[arg(long)]
+    porcelain: bool,
+    /// terminate entries with NUL and leave paths unquoted (implies --porcelain
+    /// unless --short is given)
+    #[arg(short = 'z')]
+    null_terminated: bool,
+    /// show how many files were scanned and hashed, and the time taken, on stderr
+    #[arg(short, long)]
+    verbose: bool,
+    /// ignore changes to submodules: none, untracked, dirty or all (the default)
+    #[arg(
+        long,
+        value_name = "when",
+        num_args = 0..=1,
+        require_equals = true,
+        default_value = "none",
+        default_missing_value = "all"
+    )]
+    ignore_submodules: IgnoreSubmodules,
+    /// the files or directories to show the status of
+    #[arg(value_name = "pathspec")]
+    paths: Vec<PathBuf>,
+}
+
+/// Which changes to submodules are ignored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum IgnoreSubmodules {
+    /// Show all changes
+    #[default]
+    None,
+    /// Ignore untracked files in submodules
+    Untracked,
+    /// Only show submodules with another commit checked out
+    Dirty,
+    /// Ignore submodules entirely
+    All,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::hex;
+    use crate::utils::objects::{hash_object, write_object, ObjectType};
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::{index_file, TempRepo};
+
+    /// Create a repository with a commit of `a.txt` and `dir/b.txt`,
+    /// and an index with `a.txt` modified and `dir/b.txt` removed.
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::create_dir_all(temp.join("dir")).unwrap();
+        let repo = temp.repo();
+
+        let a = write_object(&repo, ObjectType::Blob, b"a").unwrap();
+        let b = write_object(&repo, ObjectType::Blob, b"b").unwrap();
+        let mut content = b"100644 a.txt\0".to_vec();
+        content.extend(hex::decode(a.as_bytes()).unwrap());
+        let dir = write_object(&repo, ObjectType::Tree, &{
+            let mut content = b"100644 b.txt\0".to_vec();
+            content.extend(hex::decode(b.as_bytes()).unwrap());
+            content
+        })
+        .unwrap();
+        content.extend(b"40000 dir\0");
+        content.extend(hex::decode(dir.as_bytes()).unwrap());
+        let tree = write_object(&repo, ObjectType::Tree, &content).unwrap();
+
+        let commit =
+            format!("tree {tree}\nauthor a <a@b> 0 +0000\ncommitter a <a@b> 0 +0000\n\nc\n");
+        let commit = write_object(&repo, ObjectType::Commit, commit.as_bytes()).unwrap();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+
+        fs::write(temp.join("a.txt"), "changed").unwrap();
+        fs::write(temp.join("dir/c.txt"), "c").unwrap();
+        fs::write(temp.join(".git/index"), index_file(2, &[("a.txt", &b, 0)])).unwrap();
+
+        (temp, repo)
+    }
+
+    fn args() -> StatusArgs {
+        StatusArgs {
+            short: false,
+            branch: false,
+            porcelain: false,
+            null_terminated: false,
+            verbose: false,
+            ignore_submodules: IgnoreSubmodules::None,
+            paths: Vec::new(),
+        }
+    }
+
+    fn status(repo: &Repository, args: StatusArgs) -> String {
+        let mut output = Vec::new();
+        args.run(repo, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn shows_short_status() {
+        let (_temp, repo) = setup();
+        let output = status(&repo, StatusArgs {
+            short: true,
+            branch: true,
+            ..args()
+        });
+        assert_eq!(output, "## main\nMM a.txt\nD  dir/b.txt\n?? dir/\n");
+
+        let output = status(&repo, StatusArgs {
+            null_terminated: true,
+            ..args()
+        });
+        assert_eq!(output, "MM a.txt\0D  dir/b.txt\0?? dir/\0");
+    }
+
+    #[test]
+    fn shows_long_status() {
+        let (_temp, repo) = setup();
+        let output = status(&repo, args());
+        assert_eq!(
+            output,
+            "On branch main\n\
+             Changes to be committed:\n  \
+             (use \"git restore --staged <file>...\" to unstage)\n\
+             \tmodified:   a.txt\n\
+             \tdeleted:    dir/b.txt\n\
+             \n\
+             Changes not staged for commit:\n  \
+             (use \"git add <file>...\" to update what will be committed)\n  \
+             (use \"git restore <file>...\" to discard changes in working directory)\n\
+             \tmodified:   a.txt\n\
+             \n\
+             Untracked files:\n  \
+             (use \"git add <file>...\" to include in what will be committed)\n\
+             \tdir/\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn shows_paths_relative_to_current_dir() {
+        let (temp, _) = setup();
+        let repo = temp.repo_at("dir");
+
+        let output = status(&repo, StatusArgs {
+            short: true,
+            ..args()
+        });
+        assert_eq!(output, "MM ../a.txt\nD  b.txt\n?? ./\n");
+
+        let output = status(&repo, StatusArgs {
+            porcelain: true,
+            paths: vec![PathBuf::from(".")],
+            ..args()
+        });
+        assert_eq!(output, "D  dir/b.txt\n?? dir/\n");
+    }
+
+    #[test]
+    fn shows_unmerged_paths() {
+        let (temp, repo) = setup();
+        let a = hash_object(&ObjectType::Blob, b"a");
+        fs::write(
+            temp.join(".git/index"),
+            index_file(2, &[
+                ("a.txt", &a, 1),
+                ("a.txt", &a, 2),
+                ("a.txt", &a, 3),
+                ("dir/b.txt", &a, 1),
+                ("dir/b.txt", &a, 2),
+            ]),
+        )
+        .unwrap();
+
+        let output = status(&repo, StatusArgs {
+            short: true,
+            ..args()
+        });
+        assert_eq!(output, "UU a.txt\nUD dir/b.txt\n?? dir/c.txt\n");
+    }
+
+    #[test]
+    fn shows_submodule_changes() {
+        let (temp, repo) = setup();
+        let a = hash_object(&ObjectType::Blob, b"a");
+        let b = hash_object(&ObjectType::Blob, b"b");
+        fs::write(temp.join(".git/index"), index_file(2, &[("a.txt", &b, 0)])).unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        index.add_entry(IndexEntry::new("sub".into(), MODE_GITLINK, a));
+        index.write(&repo).unwrap();
+
+        // The submodule has no commit checked out, and an untracked file
+        fs::create_dir_all(temp.join("sub/.git/objects")).unwrap();
+        fs::write(temp.join("sub/.git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(temp.join("sub/new.txt"), "new").unwrap();
+
+        let short = |ignore_submodules| {
+            status(&repo, StatusArgs {
+                short: true,
+                ignore_submodules,
+                paths: vec![PathBuf::from("sub")],
+                ..args()
+            })
+        };
+        assert_eq!(short(IgnoreSubmodules::None), "AM sub\n");
+        assert_eq!(short(IgnoreSubmodules::All), "");
+
+        let output = status(&repo, StatusArgs {
+            paths: vec![PathBuf::from("sub")],
+            ..args()
+        });
+        assert!(output.contains(
+            "  (commit or discard the untracked or modified content in submodules)\n\
+             \tmodified:   sub (new commits, untracked content)\n"
+        ));
+    }
+}
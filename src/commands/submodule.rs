@@ -0,0 +1,512 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+use crate::commands::add::index_entry;
+use crate::commands::clone::{clone_submodule, guess_directory};
+use crate::commands::describe::name_commit;
+use crate::commands::switch::{switch, LocalChanges, Target};
+use crate::commands::CommandArgs;
+use crate::utils::config::{set_config_value, Config};
+use crate::utils::convert::Conversion;
+use crate::utils::index::{Index, IndexEntry};
+use crate::utils::objects::object_exists;
+use crate::utils::pathspec::{normalize, Pathspec};
+use crate::utils::refs::resolve_ref;
+use crate::utils::repository::Repository;
+use crate::utils::submodule::{
+    connect_submodule, open_submodule, read_gitmodules, resolve_submodule_url, submodule_git_dir,
+    Submodule,
+};
+use crate::utils::transfer::{copy_objects, open_repository};
+use crate::utils::tree::MODE_GITLINK;
+use crate::utils::work_tree::WorkTreeFile;
+
+impl CommandArgs for SubmoduleArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let prefix = repo.prefix()?;
+        match self.command {
+            None => status(repo, &Pathspec::all(&prefix), false, "", writer),
+            Some(SubmoduleCommand::Add(args)) => add(repo, args, writer),
+            Some(SubmoduleCommand::Init(args)) => {
+                let pathspec = pathspec(&prefix, &args.paths)?;
+                for (entry, submodule) in submodules(repo, &pathspec)? {
+                    let display = pathspec.relative(&entry.path);
+                    let display = String::from_utf8_lossy(&display);
+                    init(repo, submodule.as_ref(), &display, writer)?;
+                }
+                Ok(())
+            },
+            Some(SubmoduleCommand::Status(args)) => {
+                let pathspec = pathspec(&prefix, &args.paths)?;
+                status(repo, &pathspec, args.recursive, "", writer)
+            },
+            Some(SubmoduleCommand::Update(args)) => {
+                let pathspec = pathspec(&prefix, &args.paths)?;
+                update_submodules(repo, &pathspec, args.init, args.recursive, "", writer)
+            },
+        }
+    }
+}
+
+/// Unlike most commands, every submodule is selected without paths.
+fn pathspec(prefix: &str, paths: &[PathBuf]) -> anyhow::Result<Pathspec> {
+    match paths.is_empty() {
+        true => Ok(Pathspec::all(prefix)),
+        false => Pathspec::new(prefix, paths),
+    }
+}
+
+/// The gitlinks of the index matching a pathspec (the first stage of unmerged
+/// ones), with the submodules declared for them in `.gitmodules`.
+fn submodules(
+    repo: &Repository,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<(IndexEntry, Option<Submodule>)>> {
+    let declared = read_gitmodules(repo)?;
+    let mut submodules: Vec<(IndexEntry, Option<Submodule>)> = Vec::new();
+    for entry in Index::load(repo)?.entries() {
+        if entry.mode != MODE_GITLINK || !pathspec.matches(&entry.path) {
+            continue;
+        }
+        if submodules
+            .last()
+            .is_some_and(|(last, _)| last.path == entry.path)
+        {
+            continue;
+        }
+        let submodule = declared
+            .iter()
+            .find(|submodule| submodule.path.as_bytes() == entry.path)
+            .cloned();
+        submodules.push((entry.clone(), submodule));
+    }
+    Ok(submodules)
+}
+
+/// Add a repository as a submodule: clone it into `.git/modules/<name>`, declare
+/// it in `.gitmodules`, and stage both.
+fn add<W>(repo: &Repository, args: AddArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let work_tree = repo.work_tree()?;
+    let path = match &args.path {
+        Some(path) => normalize(&repo.prefix()?, path)?,
+        None => guess_directory(&args.repository, false)
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let name = args.name.unwrap_or_else(|| path.clone());
+    let index = Index::load(repo)?;
+    if index.entries().iter().any(|entry| {
+        entry.path == path.as_bytes() || entry.path.starts_with(format!("{path}/").as_bytes())
+    }) {
+        anyhow::bail!("'{path}' already exists in the index");
+    }
+
+    let url = resolve_submodule_url(repo, &args.repository)?;
+    let full_path = work_tree.join(&path);
+    if full_path.join(".git").exists() {
+        writeln!(writer, "Adding existing repo at '{path}' to the index")?;
+    } else {
+        if full_path.exists() {
+            anyhow::bail!("'{path}' already exists and is not a valid git repo");
+        }
+        let git_dir = submodule_git_dir(repo, &name)?;
+        if git_dir.exists() {
+            anyhow::bail!("A git directory for '{name}' is found locally");
+        }
+        clone_submodule(repo, &url, &full_path, &git_dir, writer)?;
+        connect_submodule(repo, &path, &git_dir)?;
+    }
+
+    // `.gitmodules` keeps the URL as given, the configuration the resolved one
+    let gitmodules = work_tree.join(".gitmodules");
+    set_config_value(&gitmodules, &format!("submodule.{name}.path"), &path)?;
+    set_config_value(
+        &gitmodules,
+        &format!("submodule.{name}.url"),
+        &args.repository,
+    )?;
+    let config = repo.common_dir()?.join("config");
+    set_config_value(&config, &format!("submodule.{name}.url"), &url)?;
+    set_config_value(&config, &format!("submodule.{name}.active"), "true")?;
+
+    let mut index = index;
+    let mut conversion = Conversion::load(repo)?;
+    for path in [".gitmodules", path.as_str()] {
+        let file = WorkTreeFile {
+            path: path.as_bytes().to_vec(),
+            metadata: work_tree.join(path).symlink_metadata()?,
+        };
+        index.add_entry(index_entry(repo, &work_tree, file, &mut conversion)?);
+    }
+    index.write(repo)
+}
+
+/// Initialize a submodule, copying its URL (resolved) from `.gitmodules` to the
+/// configuration, unless it is already there.
+fn init<W>(
+    repo: &Repository,
+    submodule: Option<&Submodule>,
+    display: &str,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let Some(Submodule {
+        name,
+        url: Some(url),
+        ..
+    }) = submodule
+    else {
+        anyhow::bail!("No url found for submodule path '{display}' in .gitmodules");
+    };
+    let key = format!("submodule.{name}.url");
+    if Config::load(repo)?.get(&key).is_some() {
+        return Ok(());
+    }
+
+    let url = resolve_submodule_url(repo, url)?;
+    let config = repo.common_dir()?.join("config");
+    set_config_value(&config, &format!("submodule.{name}.active"), "true")?;
+    set_config_value(&config, &key, &url)?;
+    writeln!(
+        writer,
+        "Submodule '{name}' ({url}) registered for path '{display}'"
+    )?;
+    Ok(())
+}
+
+/// Show the commit of each submodule: prefixed with `-` if it is not checked
+/// out, `+` if another commit is checked out (which is shown instead), and `U`
+/// if it has conflicts.
+///
+/// # Arguments
+///
+/// * `repo` - The superproject
+/// * `pathspec` - The submodules to show
+/// * `recursive` - Whether to show the submodules of the submodules as well
+/// * `prefix` - The path of the superproject in the outermost one, if nested
+/// * `writer` - Where to write the status
+fn status<W>(
+    repo: &Repository,
+    pathspec: &Pathspec,
+    recursive: bool,
+    prefix: &str,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    for (entry, _) in submodules(repo, pathspec)? {
+        let display = format!(
+            "{prefix}{}",
+            String::from_utf8_lossy(&pathspec.relative(&entry.path))
+        );
+        if entry.stage != 0 {
+            writeln!(writer, "U{} {display}", "0".repeat(40))?;
+            continue;
+        }
+        let Some(submodule) = open_submodule(repo, &entry.path)? else {
+            writeln!(writer, "-{} {display}", entry.hash)?;
+            continue;
+        };
+
+        match resolve_ref(&submodule, "HEAD")? {
+            Some(head) if head == entry.hash => {
+                let name = name_commit(&submodule, &head)?;
+                writeln!(writer, " {head} {display} ({name})")?;
+            },
+            Some(head) => {
+                let name = name_commit(&submodule, &head)?;
+                writeln!(writer, "+{head} {display} ({name})")?;
+            },
+            None => writeln!(writer, "+{} {display}", "0".repeat(40))?,
+        }
+        if recursive {
+            let prefix = format!("{display}/");
+            status(&submodule, &Pathspec::all(""), true, &prefix, writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check out the commits recorded for the initialized submodules, cloning them
+/// first if needed. Commits missing from a submodule are copied from its origin.
+///
+/// # Arguments
+///
+/// * `repo` - The superproject
+/// * `pathspec` - The submodules to update
+/// * `init_submodules` - Whether to initialize the submodules that are not yet
+/// * `recursive` - Whether to update the submodules of the submodules as well
+/// * `prefix` - The path of the superproject in the outermost one, if nested
+/// * `writer` - Where to write the progress
+pub(crate) fn update_submodules<W>(
+    repo: &Repository,
+    pathspec: &Pathspec,
+    init_submodules: bool,
+    recursive: bool,
+    prefix: &str,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let work_tree = repo.work_tree()?;
+    for (entry, submodule) in submodules(repo, pathspec)? {
+        let display = format!(
+            "{prefix}{}",
+            String::from_utf8_lossy(&pathspec.relative(&entry.path))
+        );
+        if entry.stage != 0 {
+            eprintln!("Skipping unmerged submodule {display}");
+            continue;
+        }
+        if init_submodules {
+            init(repo, submodule.as_ref(), &display, writer)?;
+        }
+        // Submodules that are not initialized are left alone
+        let Some(submodule) = submodule else {
+            continue;
+        };
+        let config = Config::load(repo)?;
+        let Some(url) = config.get(&format!("submodule.{}.url", submodule.name)) else {
+            continue;
+        };
+
+        let full_path = work_tree.join(&submodule.path);
+        let cloned = !full_path.join(".git").exists();
+        if cloned {
+            let git_dir = submodule_git_dir(repo, &submodule.name)?;
+            if !git_dir.exists() {
+                clone_submodule(repo, url, &full_path, &git_dir, writer)?;
+            }
+            connect_submodule(repo, &submodule.path, &git_dir)?;
+        }
+        let Some(sub) = open_submodule(repo, &entry.path)? else {
+            anyhow::bail!("Unable to open submodule path '{display}'");
+        };
+
+        // A fresh clone is on the default branch, and detached at the commit
+        if cloned || resolve_ref(&sub, "HEAD")?.as_deref() != Some(entry.hash.as_str()) {
+            if !object_exists(&sub, &entry.hash)? {
+                let origin = open_repository(&sub, "origin")?;
+                copy_objects(&origin.repo, &sub, &entry.hash).context(format!(
+                    "Fetched in submodule path '{display}', but it did not contain {}",
+                    entry.hash
+                ))?;
+            }
+            // The working tree of a submodule just connected to its git
+            // directory is checked out again from scratch
+            let changes = match cloned {
+                true => LocalChanges::Discard,
+                false => LocalChanges::Carry,
+            };
+            let target = Target::Detached(entry.hash.clone());
+            switch(&sub, target, changes, &mut std::io::sink()).context(format!(
+                "Unable to checkout '{}' in submodule path '{display}'",
+                entry.hash
+            ))?;
+            writeln!(
+                writer,
+                "Submodule path '{display}': checked out '{}'",
+                entry.hash
+            )?;
+        }
+
+        if recursive {
+            let prefix = format!("{display}/");
+            update_submodules(
+                &sub,
+                &Pathspec::all(""),
+                init_submodules,
+                true,
+                &prefix,
+                writer,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct SubmoduleArgs {
+    #[command(subcommand)]
+    command: Option<SubmoduleCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum SubmoduleCommand {
+    /// clone a repository as a submodule, and stage it with .gitmodules
+    Add(AddArgs),
+    /// copy the URLs of submodules from .gitmodules to the configuration
+    Init(PathsArgs),
+    /// show the commit checked out in each submodule (the default)
+    Status(StatusArgs),
+    /// clone the initialized submodules and check out the commits recorded for them
+    Update(UpdateArgs),
+}
+
+#[derive(Args, Debug)]
+struct AddArgs {
+    /// the name of the submodule (its path by default)
+    #[arg(long)]
+    name: Option<String>,
+    /// the URL (or path) of the repository, relative to the superproject if it
+    /// starts with ./ or ../
+    repository: String,
+    /// where to check out the submodule
+    path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct PathsArgs {
+    /// the submodules to select (all by default)
+    #[arg(value_name = "path")]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct StatusArgs {
+    /// show the submodules of the submodules as well
+    #[arg(long)]
+    recursive: bool,
+    /// the submodules to show (all by default)
+    #[arg(value_name = "path")]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct UpdateArgs {
+    /// initialize the submodules that are not yet
+    #[arg(long)]
+    init: bool,
+    /// update the submodules of the submodules as well
+    #[arg(long)]
+    recursive: bool,
+    /// the submodules to update (all by default)
+    #[arg(value_name = "path")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    fn run(repo: &Repository, command: Option<SubmoduleCommand>) -> String {
+        let mut output = Vec::new();
+        SubmoduleArgs { command }.run(repo, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn update(init: bool) -> Option<SubmoduleCommand> {
+        Some(SubmoduleCommand::Update(UpdateArgs {
+            init,
+            recursive: false,
+            paths: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn adds_and_updates_submodules() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_AUTHOR_DATE, "0 +0000")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_DATE, "0 +0000");
+        fs::create_dir_all(temp.join("lib/.git/refs/heads")).unwrap();
+        let lib = temp
+            .repo()
+            .open(temp.join("lib/.git"), Some(temp.join("lib")));
+        write_symref(&lib, "HEAD", "refs/heads/main").unwrap();
+        let mut index = Index::default();
+        let blob = write_object(&lib, ObjectType::Blob, b"lib\n").unwrap();
+        index.add_entry(IndexEntry::new("file".into(), 0o100644, blob));
+        let tree = index.write_tree(&lib, false).unwrap();
+        let commit = create_commit(&lib, &tree, Vec::new(), "lib\n").unwrap();
+        write_ref(&lib, "refs/heads/main", &commit).unwrap();
+        write_ref(&lib, "refs/tags/v1", &commit).unwrap();
+
+        fs::create_dir_all(temp.join("super/.git/objects")).unwrap();
+        fs::create_dir_all(temp.join("super/.git/refs/heads")).unwrap();
+        fs::write(temp.join("super/.git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        let repo = temp.repo_at("super");
+
+        let add = AddArgs {
+            name: None,
+            repository: "../lib".to_string(),
+            path: Some("libs/lib".into()),
+        };
+        run(&repo, Some(SubmoduleCommand::Add(add)));
+        assert_eq!(
+            fs::read_to_string(temp.join("super/.gitmodules")).unwrap(),
+            "[submodule \"libs/lib\"]\n\tpath = libs/lib\n\turl = ../lib\n"
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join("super/libs/lib/.git")).unwrap(),
+            "gitdir: ../../.git/modules/libs/lib\n"
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join("super/libs/lib/file")).unwrap(),
+            "lib\n"
+        );
+        let index = Index::load(&repo).unwrap();
+        let gitlink = index.entry(b"libs/lib", 0).unwrap();
+        assert_eq!(
+            (gitlink.mode, gitlink.hash.as_str()),
+            (MODE_GITLINK, commit.as_str())
+        );
+        assert_eq!(run(&repo, None), format!(" {commit} libs/lib (v1)\n"));
+
+        // A submodule removed from the working tree is checked out again from
+        // its git directory once initialized
+        fs::remove_dir_all(temp.join("super/libs/lib")).unwrap();
+        fs::create_dir(temp.join("super/libs/lib")).unwrap();
+        fs::write(temp.join("super/.git/config"), "").unwrap();
+        let repo = temp.repo_at("super");
+        assert_eq!(run(&repo, None), format!("-{commit} libs/lib\n"));
+        assert_eq!(run(&repo, update(false)), "");
+
+        let url = temp.join("lib");
+        assert_eq!(
+            run(&repo, update(true)),
+            format!(
+                "Submodule 'libs/lib' ({}) registered for path 'libs/lib'\n\
+                 Submodule path 'libs/lib': checked out '{commit}'\n",
+                url.display()
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join("super/libs/lib/file")).unwrap(),
+            "lib\n"
+        );
+        assert_eq!(
+            run(
+                &repo,
+                Some(SubmoduleCommand::Status(StatusArgs {
+                    recursive: false,
+                    paths: vec!["libs".into()],
+                }))
+            ),
+            format!(" {commit} libs/lib (v1)\n")
+        );
+    }
+}
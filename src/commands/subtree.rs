@@ -0,0 +1,931 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use clap::{Args, Subcommand};
+
+use crate::commands::read_tree::read_tree;
+use crate::commands::CommandArgs;
+use crate::utils::advice::{with_advice, Advice};
+use crate::utils::checkout::switch_tree;
+use crate::utils::commit::{create_commit, read_commit, Commit};
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::index::Index;
+use crate::utils::merge::{merge_trees, shift_tree};
+use crate::utils::objects::{write_object, ObjectType};
+use crate::utils::reflog::NULL_HASH;
+use crate::utils::refs::{head_branch, is_valid_ref_name, resolve_ref, update_head, update_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::transfer::{copy_objects, open_repository, LocalRepository};
+use crate::utils::tree::{find_entry, flatten_tree, TreeEntry};
+use crate::utils::walk::{is_ancestor, merge_bases, reachable_commits, RevWalk};
+
+impl CommandArgs for SubtreeArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if !repo.prefix()?.is_empty() {
+            anyhow::bail!("You need to run this command from the toplevel of the working tree.");
+        }
+
+        // Trailing slashes are dropped, as `git subtree` does
+        let prefix = self
+            .prefix
+            .as_deref()
+            .map(|prefix| prefix.trim_end_matches('/'))
+            .filter(|prefix| !prefix.is_empty());
+        let required =
+            || prefix.ok_or_else(|| anyhow::anyhow!("you must provide the --prefix option."));
+
+        match self.command {
+            SubtreeCommand::Add(args) => add(repo, required()?, args, writer),
+            SubtreeCommand::Merge(args) => {
+                let commit = resolve_commit(repo, &args.commit)
+                    .map_err(|_| anyhow::anyhow!("'{}' does not refer to a commit", args.commit))?;
+                let message = args
+                    .message
+                    .unwrap_or_else(|| format!("Merge commit '{commit}'"));
+                merge(repo, prefix, &commit, &message, writer)
+            },
+            SubtreeCommand::Split(args) => split(repo, required()?, args, writer),
+            SubtreeCommand::Pull(args) => pull(repo, prefix, args, writer),
+            SubtreeCommand::Push(args) => push(repo, required()?, args, writer),
+        }
+    }
+}
+
+fn add<W>(repo: &Repository, prefix: &str, args: AddArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let work_tree = repo.work_tree()?;
+    if work_tree.join(prefix).symlink_metadata().is_ok() {
+        anyhow::bail!("prefix '{prefix}' already exists.");
+    }
+
+    let head = resolve_commit(repo, "HEAD")?;
+    let head_tree = read_commit(repo, &head)?.tree;
+    let mut index = Index::load(repo)?;
+    ensure_clean(repo, &index, &head_tree)?;
+
+    let commit = match &args.reference {
+        Some(reference) => fetch(repo, &args.commit, reference)?,
+        None => resolve_commit(repo, &args.commit)
+            .map_err(|_| anyhow::anyhow!("'{}' does not refer to a commit", args.commit))?,
+    };
+
+    // Read the tree of the commit under the prefix and check it out
+    read_tree(repo, &mut index, &read_commit(repo, &commit)?.tree, prefix)?;
+    let tree = index.write_tree(repo, false)?;
+    switch_tree(repo, &mut index, &work_tree, &head_tree, &tree)?;
+
+    let subject = args
+        .message
+        .unwrap_or_else(|| format!("Add '{prefix}/' from commit '{commit}'"));
+    let message = format!(
+        "{subject}\n\n\
+         git-subtree-dir: {prefix}\n\
+         git-subtree-mainline: {head}\n\
+         git-subtree-split: {commit}\n"
+    );
+    let parents = match head == commit {
+        true => vec![commit.clone()],
+        false => vec![head, commit.clone()],
+    };
+
+    let merge = create_commit(repo, &tree, parents, &message)?;
+    index.write(repo)?;
+    update_head(repo, &merge, &format!("reset: moving to {merge}"))?;
+
+    writeln!(writer, "Added dir '{prefix}'")?;
+    Ok(())
+}
+
+/// Merge a commit into HEAD, shifting its trees to line up with the subdirectory.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to merge in
+/// * `prefix` - The subdirectory the commit is merged into, or `None` to detect it
+/// * `commit` - The hash of the commit to merge
+/// * `message` - The subject of the merge commit
+fn merge<W>(
+    repo: &Repository,
+    prefix: Option<&str>,
+    commit: &str,
+    message: &str,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let head = resolve_commit(repo, "HEAD")?;
+    let head_tree = read_commit(repo, &head)?.tree;
+    let mut index = Index::load(repo)?;
+    ensure_clean(repo, &index, &head_tree)?;
+
+    if is_ancestor(repo, commit, &head)? {
+        writeln!(writer, "Already up to date.")?;
+        return Ok(());
+    }
+
+    let Some(base) = merge_bases(repo, &head, commit)?.into_iter().next() else {
+        anyhow::bail!("refusing to merge unrelated histories");
+    };
+
+    // Both their tree and the base tree are shifted to line up with ours
+    let theirs = shift_tree(repo, &head_tree, &read_commit(repo, commit)?.tree, prefix)?;
+    let base = shift_tree(repo, &head_tree, &read_commit(repo, &base)?.tree, prefix)?;
+    let tree = merge_trees(repo, &base, &head_tree, &theirs)?;
+
+    let merge = create_commit(
+        repo,
+        &tree,
+        vec![head, commit.to_string()],
+        &format!("{message}\n"),
+    )?;
+    switch_tree(repo, &mut index, &repo.work_tree()?, &head_tree, &tree)?;
+    index.write(repo)?;
+    let summary = "Merge made by the 'subtree' strategy.";
+    update_head(repo, &merge, &format!("merge {commit}: {summary}"))?;
+
+    writeln!(writer, "{summary}")?;
+    let hook = Hook {
+        args: vec!["0".to_string()],
+        ..Hook::default()
+    };
+    run_hook(repo, "post-merge", hook)?;
+    Ok(())
+}
+
+fn split<W>(repo: &Repository, prefix: &str, args: SplitArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let revision = args.commit.as_deref().unwrap_or("HEAD");
+    let commit = resolve_commit(repo, revision)
+        .map_err(|_| anyhow::anyhow!("'{revision}' does not refer to a commit"))?;
+
+    let split = Split::new(repo, prefix, &commit)?.run(&commit)?;
+
+    if let Some(branch) = args.branch {
+        let name = format!("refs/heads/{branch}");
+        if !is_valid_ref_name(&name) {
+            anyhow::bail!("'{branch}' does not look like a ref");
+        }
+
+        let action = match resolve_ref(repo, &name)? {
+            Some(existing) if !is_ancestor(repo, &existing, &split)? => {
+                anyhow::bail!("branch '{branch}' is not an ancestor of commit '{split}'.")
+            },
+            Some(_) => "Updated",
+            None => "Created",
+        };
+        update_ref(repo, &name, &split, "subtree split")?;
+        writeln!(writer, "{action} branch '{branch}'")?;
+    }
+
+    writeln!(writer, "{split}")?;
+    Ok(())
+}
+
+fn pull<W>(
+    repo: &Repository,
+    prefix: Option<&str>,
+    args: RemoteArgs,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let head_tree = read_commit(repo, &resolve_commit(repo, "HEAD")?)?.tree;
+    ensure_clean(repo, &Index::load(repo)?, &head_tree)?;
+
+    let commit = fetch(repo, &args.repository, &args.reference)?;
+    let message = args
+        .message
+        .unwrap_or_else(|| format!("Merge commit '{commit}'"));
+    merge(repo, prefix, &commit, &message, writer)
+}
+
+fn push<W>(repo: &Repository, prefix: &str, args: RemoteArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if !repo.work_tree()?.join(prefix).exists() {
+        anyhow::bail!("'{prefix}' must already exist. Try 'git subtree add'.");
+    }
+
+    // The refspec is `[+][<local-commit>:]<remote-branch>`
+    let refspec = args.reference.strip_prefix('+');
+    let force = refspec.is_some();
+    let refspec = refspec.unwrap_or(&args.reference);
+    let (local, remote_branch) = refspec.split_once(':').unwrap_or(("HEAD", refspec));
+    let remote_ref = format!("refs/heads/{remote_branch}");
+    if !is_valid_ref_name(&remote_ref) {
+        anyhow::bail!("'{remote_branch}' does not look like a ref");
+    }
+    let local = resolve_commit(repo, local)
+        .map_err(|_| anyhow::anyhow!("'{local}' does not refer to a commit"))?;
+
+    let split = Split::new(repo, prefix, &local)?.run(&local)?;
+
+    let LocalRepository {
+        repo: remote,
+        url,
+        bare,
+    } = open_repository(repo, &args.repository)?;
+    copy_objects(repo, &remote, &split)?;
+
+    let old = resolve_ref(&remote, &remote_ref)?;
+    if old.as_deref() == Some(split.as_str()) {
+        writeln!(writer, "Everything up-to-date")?;
+        return Ok(());
+    }
+    if !bare && head_branch(&remote)?.as_deref() == Some(remote_ref.as_str()) {
+        anyhow::bail!("refusing to update checked out branch: {remote_ref}");
+    }
+    if let Some(old) = &old {
+        if !force && !is_ancestor(&remote, old, &split)? {
+            anyhow::bail!(with_advice(
+                repo,
+                Advice::PushNonFastForward,
+                format!("failed to push some refs to '{url}'"),
+                &format!(
+                    "Updates were rejected because the pushed commit is not a descendant of '{remote_branch}'"
+                ),
+            )?);
+        }
+    }
+
+    // The remote is named by its URL if it is not a configured one
+    let input = format!(
+        "{split} {split} {remote_ref} {}\n",
+        old.as_deref().unwrap_or(NULL_HASH)
+    );
+    let hook = Hook {
+        args: vec![args.repository.clone(), url.clone()],
+        input: Some(input.as_bytes()),
+        ..Hook::default()
+    };
+    if run_hook(repo, "pre-push", hook)? != 0 {
+        anyhow::bail!("failed to push some refs to '{url}'");
+    }
+    update_ref(&remote, &remote_ref, &split, "push")?;
+
+    writeln!(writer, "To {url}")?;
+    match old {
+        Some(old) => writeln!(
+            writer,
+            "   {}..{}  {} -> {remote_branch}",
+            &old[..7],
+            &split[..7],
+            &split[..7]
+        )?,
+        None => writeln!(
+            writer,
+            " * [new branch]      {} -> {remote_branch}",
+            &split[..7]
+        )?,
+    }
+    Ok(())
+}
+
+/// Copy a commit and its history from another local repository.
+///
+/// # Returns
+///
+/// The hash of the commit
+fn fetch(repo: &Repository, repository: &str, reference: &str) -> anyhow::Result<String> {
+    if !is_valid_ref_name(&format!("refs/heads/{reference}")) {
+        anyhow::bail!("'{reference}' does not look like a ref");
+    }
+
+    let remote = open_repository(repo, repository)?.repo;
+    let commit = resolve_commit(&remote, reference)
+        .map_err(|_| anyhow::anyhow!("couldn't find remote ref {reference}"))?;
+    copy_objects(&remote, repo, &commit)?;
+    Ok(commit)
+}
+
+/// Fail if the index or the working tree differ from the tree of HEAD.
+fn ensure_clean(repo: &Repository, index: &Index, head_tree: &str) -> anyhow::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let head: Vec<_> = flatten_tree(repo, head_tree)?
+        .into_iter()
+        .map(|entry| (entry.name, entry.mode, entry.hash))
+        .collect();
+    let staged: Vec<_> = index
+        .entries()
+        .iter()
+        .map(|entry| (entry.path.clone(), entry.mode, entry.hash.clone()))
+        .collect();
+
+    let mut modified = head != staged || index.entries().iter().any(|entry| entry.stage != 0);
+    for entry in index.entries() {
+        modified = modified || index.is_modified(entry, &work_tree)?;
+    }
+    if modified {
+        anyhow::bail!("working tree has modifications.  Cannot add.");
+    }
+    Ok(())
+}
+
+/// Extracts the history of a subdirectory into commits that have it as their root
+/// (`subtree split`), producing the same commits as `git subtree split` does.
+struct Split<'a> {
+    repo: &'a Repository,
+    prefix: &'a str,
+    /// The split commit of each processed commit that has (or descends from) the subdirectory
+    split: HashMap<String, String>,
+    /// The commits without the subdirectory
+    without_subdirectory: HashSet<String>,
+    /// The split commit of the last processed commit with the subdirectory
+    latest: Option<String>,
+}
+
+impl<'a> Split<'a> {
+    /// Prepare a split, reusing the splits recorded by earlier `subtree add`
+    /// and `subtree merge` commits in the history of a commit.
+    fn new(repo: &'a Repository, prefix: &'a str, commit: &str) -> anyhow::Result<Self> {
+        let mut split = HashMap::new();
+        let mut walk = RevWalk::new(repo);
+        walk.push(commit)?;
+
+        for item in walk {
+            let (hash, commit) = item?;
+            let message = String::from_utf8_lossy(&commit.message);
+            let is_join = message.lines().any(|line| {
+                line.strip_prefix("git-subtree-dir: ")
+                    .map(|dir| dir.trim_end_matches('/'))
+                    == Some(prefix)
+            });
+            if !is_join {
+                continue;
+            }
+
+            let mut mainline = None;
+            let mut subtree = None;
+            for line in message.lines() {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("git-subtree-mainline:"), Some(value)) => {
+                        mainline = Some(value.to_string())
+                    },
+                    (Some("git-subtree-split:"), Some(value)) => {
+                        let resolved = resolve_commit(repo, value).map_err(|_| {
+                            anyhow::anyhow!(
+                                "could not rev-parse split hash {value} from commit {hash}"
+                            )
+                        })?;
+                        subtree = Some(resolved);
+                    },
+                    _ => {},
+                }
+            }
+
+            match (mainline, subtree) {
+                (Some(mainline), Some(subtree)) => {
+                    split.insert(mainline, subtree.clone());
+                    split.insert(subtree.clone(), subtree);
+                },
+                // Squashed commits stand for the subtree commit
+                (None, Some(subtree)) => {
+                    split.insert(hash, subtree);
+                },
+                _ => {},
+            }
+        }
+
+        Ok(Split {
+            repo,
+            prefix,
+            split,
+            without_subdirectory: HashSet::new(),
+            latest: None,
+        })
+    }
+
+    /// Split the history of a commit, parents first.
+    ///
+    /// # Returns
+    ///
+    /// The split commit of the last commit that has the subdirectory
+    fn run(mut self, commit: &str) -> anyhow::Result<String> {
+        let mut pending = vec![(commit.to_string(), false)];
+
+        while let Some((hash, parents_done)) = pending.pop() {
+            if self.is_processed(&hash) {
+                continue;
+            }
+
+            let commit = read_commit(self.repo, &hash)?;
+            if parents_done {
+                self.process(hash, commit)?;
+                continue;
+            }
+
+            pending.push((hash, true));
+            for parent in commit.parents.iter().rev() {
+                if !self.is_processed(parent) {
+                    pending.push((parent.clone(), false));
+                }
+            }
+        }
+
+        self.latest
+            .ok_or_else(|| anyhow::anyhow!("no new revisions were found"))
+    }
+
+    fn is_processed(&self, hash: &str) -> bool {
+        self.split.contains_key(hash) || self.without_subdirectory.contains(hash)
+    }
+
+    /// Split a commit whose parents were all processed.
+    fn process(&mut self, hash: String, commit: Commit) -> anyhow::Result<()> {
+        let parents: Vec<String> = commit
+            .parents
+            .iter()
+            .filter_map(|parent| self.split.get(parent).cloned())
+            .collect();
+
+        // Submodules at the prefix are not split
+        let tree = find_entry(self.repo, &commit.tree, self.prefix)?
+            .filter(TreeEntry::is_tree)
+            .map(|entry| entry.hash);
+        let Some(tree) = tree else {
+            self.without_subdirectory.insert(hash.clone());
+            // Commits descending from the subdirectory stand for themselves
+            if !parents.is_empty() {
+                self.split.insert(hash.clone(), hash);
+            }
+            return Ok(());
+        };
+
+        let split = self.copy_or_skip(commit, tree, parents)?;
+        self.split.insert(hash, split.clone());
+        self.latest = Some(split);
+        Ok(())
+    }
+
+    /// Reuse a split parent with the same tree if no history would be lost,
+    /// otherwise create a copy of the commit with the tree of the subdirectory.
+    fn copy_or_skip(
+        &self,
+        commit: Commit,
+        tree: String,
+        split_parents: Vec<String>,
+    ) -> anyhow::Result<String> {
+        let mut identical: Option<String> = None;
+        let mut nonidentical = None;
+        let mut parents = Vec::new();
+        let mut copy = false;
+
+        for parent in split_parents {
+            if read_commit(self.repo, &parent)?.tree == tree {
+                match &identical {
+                    Some(previous) => {
+                        let base = merge_bases(self.repo, previous, &parent)?
+                            .into_iter()
+                            .next();
+                        if base.as_ref() == Some(previous) {
+                            // The previous identical parent is an ancestor of this one
+                            identical = Some(parent.clone());
+                        } else if base.as_ref() != Some(&parent) {
+                            // Without common history, the commit must be kept
+                            copy = true;
+                        }
+                    },
+                    None => identical = Some(parent.clone()),
+                }
+            } else {
+                nonidentical = Some(parent.clone());
+            }
+
+            // Several parents may have the same split commit
+            if !parents.contains(&parent) {
+                parents.push(parent);
+            }
+        }
+
+        if let (Some(identical), Some(nonidentical)) = (&identical, &nonidentical) {
+            // The history along the other parent must be kept
+            let known = reachable_commits(self.repo, identical)?;
+            if reachable_commits(self.repo, nonidentical)?
+                .iter()
+                .any(|hash| !known.contains(hash))
+            {
+                copy = true;
+            }
+        }
+
+        match identical {
+            Some(identical) if !copy => Ok(identical),
+            _ => {
+                // The copy keeps the authorship and message, but not signatures
+                let copied = Commit {
+                    tree,
+                    parents,
+                    extra_headers: Vec::new(),
+                    ..commit
+                };
+                write_object(self.repo, ObjectType::Commit, &copied.serialize())
+            },
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct SubtreeArgs {
+    /// the name of the subdirectory holding the subtree
+    #[arg(short = 'P', long, global = true, value_name = "prefix")]
+    prefix: Option<String>,
+    #[command(subcommand)]
+    command: SubtreeCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum SubtreeCommand {
+    /// add a commit (or a ref of a local repository) as a new subdirectory
+    Add(AddArgs),
+    /// merge a commit into the subdirectory (detected if --prefix is not given)
+    Merge(MergeArgs),
+    /// extract the history of the subdirectory into a new history
+    Split(SplitArgs),
+    /// fetch a ref of a local repository and merge it into the subdirectory
+    Pull(RemoteArgs),
+    /// split the subdirectory and push it to a branch of a local repository
+    Push(RemoteArgs),
+}
+
+#[derive(Args, Debug)]
+struct AddArgs {
+    /// the message of the merge commit
+    #[arg(short, long)]
+    message: Option<String>,
+    /// the commit to add, or the repository to fetch it from
+    #[arg(value_name = "commit|repository")]
+    commit: String,
+    /// the ref to fetch from the repository
+    #[arg(value_name = "ref")]
+    reference: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct MergeArgs {
+    /// the message of the merge commit
+    #[arg(short, long)]
+    message: Option<String>,
+    /// the commit to merge
+    commit: String,
+}
+
+#[derive(Args, Debug)]
+struct SplitArgs {
+    /// create (or update) a branch pointing to the split history
+    #[arg(short, long)]
+    branch: Option<String>,
+    /// the commit to split the history of (HEAD by default)
+    commit: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct RemoteArgs {
+    /// the message of the merge commit (pull only)
+    #[arg(short, long)]
+    message: Option<String>,
+    /// the path to a local repository, or the name of a remote
+    repository: String,
+    /// the ref to pull, or `[+][<commit>:]<branch>` to push
+    #[arg(value_name = "ref")]
+    reference: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+    use crate::utils::work_tree::hash_file;
+
+    /// Create a repository with `main.c` committed on `main`.
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0100")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0100");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        commit_work_tree(&repo, &[("main.c", "main")], "main");
+        (temp, repo)
+    }
+
+    /// Write files to the working tree and commit them (and the tracked files) on HEAD.
+    fn commit_work_tree(repo: &Repository, files: &[(&str, &str)], message: &str) -> String {
+        let root = repo.work_tree().unwrap();
+        let mut index = Index::load(repo).unwrap();
+        for (path, content) in files {
+            let file = root.join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, content).unwrap();
+            let metadata = fs::metadata(&file).unwrap();
+            let hash = hash_file(&file, &metadata).unwrap();
+            write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::from_metadata(
+                path.as_bytes().to_vec(),
+                hash,
+                &metadata,
+            ));
+        }
+
+        let tree = index.write_tree(repo, false).unwrap();
+        index.write(repo).unwrap();
+        let parents = resolve_commit(repo, "HEAD").into_iter().collect();
+        let commit = create_commit(repo, &tree, parents, &format!("{message}\n")).unwrap();
+        update_head(repo, &commit, "commit").unwrap();
+        commit
+    }
+
+    /// Create a commit of an unrelated project, without touching the working tree.
+    fn commit_project(repo: &Repository, files: &[(&str, &str)], parent: Option<&str>) -> String {
+        let mut index = Index::default();
+        for (path, content) in files {
+            let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+        }
+        let tree = index.write_tree(repo, false).unwrap();
+        let parents = parent.map(str::to_string).into_iter().collect();
+        create_commit(repo, &tree, parents, "lib\n").unwrap()
+    }
+
+    fn subtree(
+        repo: &Repository,
+        prefix: Option<&str>,
+        command: SubtreeCommand,
+    ) -> anyhow::Result<String> {
+        let args = SubtreeArgs {
+            prefix: prefix.map(str::to_string),
+            command,
+        };
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn add_args(commit: &str) -> SubtreeCommand {
+        SubtreeCommand::Add(AddArgs {
+            message: None,
+            commit: commit.to_string(),
+            reference: None,
+        })
+    }
+
+    fn merge_args(commit: &str) -> SubtreeCommand {
+        SubtreeCommand::Merge(MergeArgs {
+            message: None,
+            commit: commit.to_string(),
+        })
+    }
+
+    fn split_args(branch: Option<&str>) -> SubtreeCommand {
+        SubtreeCommand::Split(SplitArgs {
+            branch: branch.map(str::to_string),
+            commit: None,
+        })
+    }
+
+    #[test]
+    fn adds_project_as_subdirectory() {
+        let (temp, repo) = setup();
+        let head = resolve_commit(&repo, "HEAD").unwrap();
+        let lib = commit_project(&repo, &[("a.c", "a"), ("d/b.c", "b")], None);
+
+        let output = subtree(&repo, Some("vendor/lib/"), add_args(&lib)).unwrap();
+        assert_eq!(output, "Added dir 'vendor/lib'\n");
+
+        let merge = read_commit(&repo, &resolve_commit(&repo, "HEAD").unwrap()).unwrap();
+        assert_eq!(merge.parents, vec![head.clone(), lib.clone()]);
+        assert_eq!(
+            String::from_utf8(merge.message).unwrap(),
+            format!(
+                "Add 'vendor/lib/' from commit '{lib}'\n\n\
+                 git-subtree-dir: vendor/lib\n\
+                 git-subtree-mainline: {head}\n\
+                 git-subtree-split: {lib}\n"
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join("vendor/lib/d/b.c")).unwrap(),
+            "b"
+        );
+        let index = Index::load(&repo).unwrap();
+        assert!(index.entry(b"vendor/lib/a.c", 0).is_some());
+        assert!(!index
+            .is_modified(
+                index.entry(b"vendor/lib/a.c", 0).unwrap(),
+                &repo.work_tree().unwrap()
+            )
+            .unwrap());
+
+        let err = subtree(&repo, Some("vendor/lib"), add_args(&lib)).unwrap_err();
+        assert_eq!(err.to_string(), "prefix 'vendor/lib' already exists.");
+        let err = subtree(&repo, None, add_args(&lib)).unwrap_err();
+        assert_eq!(err.to_string(), "you must provide the --prefix option.");
+
+        fs::write(temp.join("main.c"), "changed").unwrap();
+        let err = subtree(&repo, Some("other"), add_args(&lib)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "working tree has modifications.  Cannot add."
+        );
+    }
+
+    #[test]
+    fn merges_into_subdirectory() {
+        let (temp, repo) = setup();
+        let lib = commit_project(&repo, &[("a.c", "a"), ("b.c", "b")], None);
+        subtree(&repo, Some("vendor"), add_args(&lib)).unwrap();
+        commit_work_tree(&repo, &[("vendor/b.c", "ours")], "change vendor");
+
+        let lib2 = commit_project(&repo, &[("a.c", "theirs"), ("b.c", "b")], Some(&lib));
+        let output = subtree(&repo, Some("vendor"), merge_args(&lib2)).unwrap();
+        assert_eq!(output, "Merge made by the 'subtree' strategy.\n");
+        assert_eq!(
+            fs::read_to_string(temp.join("vendor/a.c")).unwrap(),
+            "theirs"
+        );
+        assert_eq!(fs::read_to_string(temp.join("vendor/b.c")).unwrap(), "ours");
+
+        let head = read_commit(&repo, &resolve_commit(&repo, "HEAD").unwrap()).unwrap();
+        assert_eq!(head.parents[1], lib2);
+        assert_eq!(head.message, format!("Merge commit '{lib2}'\n").as_bytes());
+
+        // The subdirectory is detected without a prefix
+        let lib3 = commit_project(
+            &repo,
+            &[("a.c", "theirs"), ("b.c", "b"), ("c.c", "c")],
+            Some(&lib2),
+        );
+        subtree(&repo, None, merge_args(&lib3)).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("vendor/c.c")).unwrap(), "c");
+        assert!(!temp.join("c.c").exists());
+
+        let output = subtree(&repo, None, merge_args(&lib2)).unwrap();
+        assert_eq!(output, "Already up to date.\n");
+    }
+
+    #[test]
+    fn splits_subdirectory_history() {
+        let (_temp, repo) = setup();
+        let lib = commit_project(&repo, &[("a.c", "a")], None);
+        subtree(&repo, Some("vendor"), add_args(&lib)).unwrap();
+        commit_work_tree(&repo, &[("main.c", "changed")], "main only");
+        commit_work_tree(&repo, &[("vendor/a.c", "changed")], "vendor only");
+
+        let output = subtree(&repo, Some("vendor"), split_args(Some("split"))).unwrap();
+        let (action, split) = output.split_once('\n').unwrap();
+        assert_eq!(action, "Created branch 'split'");
+        let split = split.trim_end();
+        assert_eq!(
+            resolve_ref(&repo, "refs/heads/split").unwrap().unwrap(),
+            split
+        );
+
+        // The commit that only changed the main project is skipped
+        let commit = read_commit(&repo, split).unwrap();
+        assert_eq!(commit.parents, vec![lib]);
+        assert_eq!(commit.message, b"vendor only\n");
+        let head_tree = read_commit(&repo, &resolve_commit(&repo, "HEAD").unwrap())
+            .unwrap()
+            .tree;
+        assert_eq!(
+            find_entry(&repo, &head_tree, "vendor")
+                .unwrap()
+                .unwrap()
+                .hash,
+            commit.tree
+        );
+
+        // Splitting again gives the same commits
+        let output = subtree(&repo, Some("vendor"), split_args(Some("split"))).unwrap();
+        assert_eq!(output, format!("Updated branch 'split'\n{split}\n"));
+
+        let err = subtree(&repo, Some("missing"), split_args(None)).unwrap_err();
+        assert_eq!(err.to_string(), "no new revisions were found");
+    }
+
+    #[test]
+    fn pulls_from_and_pushes_to_local_repositories() {
+        let (temp, repo) = setup();
+        fs::create_dir_all(temp.join("lib.git/objects")).unwrap();
+        let lib_repo = repo.open(temp.join("lib.git"), None);
+        write_symref(&lib_repo, "HEAD", "refs/heads/main").unwrap();
+        let lib = commit_project(&lib_repo, &[("a.c", "a")], None);
+        write_ref(&lib_repo, "refs/heads/main", &lib).unwrap();
+
+        let add = SubtreeCommand::Add(AddArgs {
+            message: None,
+            commit: "lib.git".to_string(),
+            reference: Some("main".to_string()),
+        });
+        subtree(&repo, Some("vendor"), add).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("vendor/a.c")).unwrap(), "a");
+
+        // Pulling runs `post-merge`, and pushing runs `pre-push` with the refs pushed
+        fs::create_dir_all(temp.join(".git/hooks")).unwrap();
+        for (name, script) in [
+            (
+                "post-merge",
+                "echo \"post-merge $*\" >> \"$GIT_DIR/hooks.log\"\n",
+            ),
+            (
+                "pre-push",
+                "echo \"pre-push $1\" >> \"$GIT_DIR/hooks.log\"\ncat >> \"$GIT_DIR/hooks.log\"\n",
+            ),
+        ] {
+            let path = temp.join(format!(".git/hooks/{name}"));
+            fs::write(&path, format!("#!/bin/sh\n{script}")).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let lib2 = commit_project(&lib_repo, &[("a.c", "a2")], Some(&lib));
+        write_ref(&lib_repo, "refs/heads/main", &lib2).unwrap();
+        let pull = SubtreeCommand::Pull(RemoteArgs {
+            message: None,
+            repository: "file://lib.git".to_string(),
+            reference: "main".to_string(),
+        });
+        subtree(&repo, Some("vendor"), pull).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("vendor/a.c")).unwrap(), "a2");
+        assert_eq!(
+            fs::read_to_string(temp.join(".git/hooks.log")).unwrap(),
+            "post-merge 0\n"
+        );
+        fs::remove_file(temp.join(".git/hooks.log")).unwrap();
+
+        commit_work_tree(&repo, &[("vendor/a.c", "a3")], "vendor change");
+        let push = |reference: &str| {
+            SubtreeCommand::Push(RemoteArgs {
+                message: None,
+                repository: "lib.git".to_string(),
+                reference: reference.to_string(),
+            })
+        };
+        let output = subtree(&repo, Some("vendor"), push("main")).unwrap();
+        let pushed = resolve_ref(&lib_repo, "refs/heads/main").unwrap().unwrap();
+        assert_eq!(read_commit(&lib_repo, &pushed).unwrap().parents, vec![
+            lib2.clone()
+        ]);
+        assert_eq!(
+            output,
+            format!(
+                "To lib.git\n   {}..{}  {} -> main\n",
+                &lib2[..7],
+                &pushed[..7],
+                &pushed[..7]
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join(".git/hooks.log")).unwrap(),
+            format!("pre-push lib.git\n{pushed} {pushed} refs/heads/main {lib2}\n")
+        );
+
+        // A failing `pre-push` hook stops the push
+        fs::write(temp.join(".git/hooks/pre-push"), "#!/bin/sh\nexit 1\n").unwrap();
+        assert!(subtree(&repo, Some("vendor"), push("blocked")).is_err());
+        assert_eq!(resolve_ref(&lib_repo, "refs/heads/blocked").unwrap(), None);
+        fs::remove_file(temp.join(".git/hooks/pre-push")).unwrap();
+
+        // Pushing history that does not contain the branch is rejected
+        write_ref(
+            &lib_repo,
+            "refs/heads/other",
+            &commit_project(&lib_repo, &[], None),
+        )
+        .unwrap();
+        assert!(subtree(&repo, Some("vendor"), push("other")).is_err());
+        subtree(&repo, Some("vendor"), push("+HEAD:other")).unwrap();
+
+        let pull = SubtreeCommand::Pull(RemoteArgs {
+            message: None,
+            repository: "https://example.com/lib.git".to_string(),
+            reference: "main".to_string(),
+        });
+        let err = subtree(&repo, Some("vendor"), pull).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'https://example.com/lib.git' is not a local repository, only local repositories are supported"
+        );
+    }
+}
@@ -0,0 +1,568 @@
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::add::write_work_tree;
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::advice::{with_advice, Advice};
+use crate::utils::checkout::{
+    check_overwritten, list_local_changes, reset_index, reset_tree, switch_tree, Operation,
+};
+use crate::utils::commit::read_commit;
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::index::Index;
+use crate::utils::merge::merge_files;
+use crate::utils::objects::{write_object, ObjectType};
+use crate::utils::quote::{quote_path, quotes_non_ascii};
+use crate::utils::reflog::{log_ref_update, NULL_HASH};
+use crate::utils::refs::{
+    head_branch, is_valid_ref_name, resolve_ref, shorten_ref, update_ref, write_ref, write_symref,
+};
+use crate::utils::repository::Repository;
+use crate::utils::revision::{ref_candidates, resolve_commit};
+
+impl CommandArgs for SwitchArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let target = match (self.create, self.detach, self.target) {
+            (Some(name), _, start) => Target::NewBranch {
+                name,
+                start: start.unwrap_or_else(|| "HEAD".to_string()),
+            },
+            (None, true, commit) => Target::Detached(commit.unwrap_or_else(|| "HEAD".to_string())),
+            (None, false, Some(name)) => {
+                if resolve_ref(repo, &format!("refs/heads/{name}"))?.is_none() {
+                    return Err(not_a_branch(repo, &name));
+                }
+                Target::Branch(name)
+            },
+            (None, false, None) => anyhow::bail!("missing branch or commit argument"),
+        };
+
+        let local_changes = match (self.force, self.merge) {
+            (true, _) => LocalChanges::Discard,
+            (false, true) => LocalChanges::Merge,
+            (false, false) => LocalChanges::Carry,
+        };
+        switch(repo, target, local_changes, writer)
+    }
+}
+
+/// What HEAD is switched to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// An existing branch, by its short name
+    Branch(String),
+    /// A branch to create at a start point
+    NewBranch { name: String, start: String },
+    /// A commit to detach HEAD at
+    Detached(String),
+}
+
+/// What happens to the local changes when switching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LocalChanges {
+    /// Keep the local changes, refusing to switch if they would be overwritten
+    Carry,
+    /// Throw the local changes away (`--force`)
+    Discard,
+    /// Keep the local changes, merging them with the changes of the new commit
+    /// if they would be overwritten (`--merge`)
+    Merge,
+}
+
+/// Switch HEAD to a branch or commit, updating the index and the working tree.
+///
+/// Only the files that differ between the current and the new commit are touched,
+/// so local changes to other files are carried over (and listed), and the switch
+/// is refused if it would overwrite local changes, unless they are discarded or
+/// merged.
+pub(crate) fn switch<W>(
+    repo: &Repository,
+    target: Target,
+    local_changes: LocalChanges,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let (name, revision) = match &target {
+        Target::Branch(name) => (name, format!("refs/heads/{name}")),
+        Target::NewBranch { name, start } => {
+            let branch_ref = format!("refs/heads/{name}");
+            if !is_valid_ref_name(&branch_ref) {
+                anyhow::bail!("'{name}' is not a valid branch name");
+            }
+            if resolve_ref(repo, &branch_ref)?.is_some() {
+                anyhow::bail!("a branch named '{name}' already exists");
+            }
+            (name, start.clone())
+        },
+        Target::Detached(commit) => (commit, commit.clone()),
+    };
+    let commit = resolve_commit(repo, &revision)?;
+
+    let old_head = resolve_ref(repo, "HEAD")?;
+    let old_branch = head_branch(repo)?;
+    let old_tree = match &old_head {
+        Some(head) => read_commit(repo, head)?.tree,
+        None => write_object(repo, ObjectType::Tree, b"")?,
+    };
+    let new_commit = read_commit(repo, &commit)?;
+
+    let work_tree = repo.work_tree()?;
+    let mut index = Index::load(repo)?;
+    match local_changes {
+        LocalChanges::Carry | LocalChanges::Merge => {
+            if index.entries().iter().any(|entry| entry.stage != 0) {
+                anyhow::bail!("you need to resolve your current index first");
+            }
+            if old_tree != new_commit.tree {
+                let checked = check_overwritten(
+                    repo,
+                    &index,
+                    &work_tree,
+                    &old_tree,
+                    &new_commit.tree,
+                    Operation::Checkout,
+                );
+                match checked {
+                    Ok(()) => {
+                        switch_tree(repo, &mut index, &work_tree, &old_tree, &new_commit.tree)?
+                    },
+                    Err(_) if local_changes == LocalChanges::Merge => {
+                        merge_local_changes(repo, &mut index, &old_tree, &new_commit.tree, name)?
+                    },
+                    Err(err) => return Err(err),
+                }
+                index.write(repo)?;
+            }
+        },
+        LocalChanges::Discard => {
+            reset_tree(repo, &mut index, &work_tree, &new_commit.tree)?;
+            index.write(repo)?;
+        },
+    }
+
+    if let Target::NewBranch { start, .. } = &target {
+        let message = format!("branch: Created from {start}");
+        update_ref(repo, &format!("refs/heads/{name}"), &commit, &message)?;
+    }
+    match &target {
+        Target::Detached(_) => write_ref(repo, "HEAD", &commit)?,
+        _ => write_symref(repo, "HEAD", &format!("refs/heads/{name}"))?,
+    }
+
+    let quote_non_ascii = quotes_non_ascii(repo)?;
+    for (status, path) in list_local_changes(repo, &index, &work_tree, &new_commit.tree)? {
+        write!(writer, "{status}\t")?;
+        writer.write_all(&quote_path(&path, quote_non_ascii))?;
+        writeln!(writer)?;
+    }
+
+    let from = match &old_branch {
+        Some(branch) => shorten_ref(branch).to_string(),
+        None => old_head.clone().unwrap_or_default(),
+    };
+    let message = format!("checkout: moving from {from} to {name}");
+    log_ref_update(repo, "HEAD", old_head.as_deref(), &commit, &message)?;
+
+    if let (None, Some(old_head)) = (&old_branch, &old_head) {
+        if *old_head != commit {
+            let subject = read_commit(repo, old_head)?.subject();
+            writeln!(
+                writer,
+                "Previous HEAD position was {} {subject}",
+                &old_head[..7]
+            )?;
+        }
+    }
+    match &target {
+        Target::Branch(_) if old_branch.as_deref() == Some(revision.as_str()) => {
+            writeln!(writer, "Already on '{name}'")?
+        },
+        Target::Branch(_) => writeln!(writer, "Switched to branch '{name}'")?,
+        Target::NewBranch { .. } => writeln!(writer, "Switched to a new branch '{name}'")?,
+        Target::Detached(_) => writeln!(
+            writer,
+            "HEAD is now at {} {}",
+            &commit[..7],
+            new_commit.subject()
+        )?,
+    }
+
+    // The exit code of the `post-checkout` hook becomes the one of the command
+    let hook = Hook {
+        args: vec![
+            old_head.unwrap_or_else(|| NULL_HASH.to_string()),
+            commit,
+            "1".to_string(),
+        ],
+        ..Hook::default()
+    };
+    match run_hook(repo, "post-checkout", hook)? {
+        0 => Ok(()),
+        code => Err(SilentExit(code).into()),
+    }
+}
+
+/// Merge the local changes (staged or not) with the changes between the current and
+/// the new tree, as the side labeled `local` against the new tree labeled with
+/// the name of what is switched to.
+///
+/// The index is left matching the new tree, with the stages of the conflicting
+/// paths, and the working tree holds the merged files (with conflict markers).
+fn merge_local_changes(
+    repo: &Repository,
+    index: &mut Index,
+    old_tree: &str,
+    new_tree: &str,
+    name: &str,
+) -> anyhow::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let local = write_work_tree(repo, index, &work_tree)?;
+    let merge = merge_files(repo, old_tree, new_tree, &local, (name, "local"))?;
+    let merged = merge.write_work_tree(repo)?;
+
+    // Untracked files are still not overwritten
+    let mut local_index = Index::default();
+    reset_index(repo, &mut local_index, &local)?;
+    check_overwritten(
+        repo,
+        &local_index,
+        &work_tree,
+        &local,
+        &merged,
+        Operation::Checkout,
+    )?;
+
+    switch_tree(repo, index, &work_tree, &local, &merged)?;
+    reset_index(repo, index, new_tree)?;
+    merge.add_conflicts(index);
+    Ok(())
+}
+
+/// The error for switching to something that is not a branch without `--detach`.
+fn not_a_branch(repo: &Repository, name: &str) -> anyhow::Error {
+    if resolve_commit(repo, name).is_err() {
+        return anyhow::anyhow!("invalid reference: {name}");
+    }
+
+    let full_name = ref_candidates(name)
+        .into_iter()
+        .find(|candidate| resolve_ref(repo, candidate).is_ok_and(|hash| hash.is_some()));
+    let kind = match full_name.as_deref() {
+        Some(full_name) if full_name.starts_with("refs/tags/") => "tag",
+        Some(full_name) if full_name.starts_with("refs/remotes/") => "remote branch",
+        Some(full_name) if full_name.starts_with("refs/") => "reference",
+        _ => "commit",
+    };
+    let message = format!("a branch is expected, got {kind} '{name}'");
+    let advice = "If you want to detach HEAD at the commit, try again with the --detach option.";
+    let message =
+        with_advice(repo, Advice::SuggestDetachingHead, message.clone(), advice).unwrap_or(message);
+    anyhow::anyhow!(message)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct SwitchArgs {
+    /// create a new branch at the start point (HEAD by default) and switch to it
+    #[arg(short, long, value_name = "new-branch", conflicts_with = "detach")]
+    create: Option<String>,
+    /// detach HEAD at the commit (HEAD by default)
+    #[arg(long)]
+    detach: bool,
+    /// throw away local changes and untracked files in the way
+    #[arg(short, long, alias = "discard-changes")]
+    force: bool,
+    /// merge local changes that would be overwritten with the branch switched to
+    #[arg(short, long, conflicts_with = "force")]
+    merge: bool,
+    /// the branch to switch to (the start point with -c, the commit with --detach)
+    #[arg(value_name = "branch")]
+    target: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::reflog::read_reflog;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository, [String; 2]) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        // The first commit has `a`, the second one also has `b`
+        let mut index = Index::default();
+        let mut commits = Vec::new();
+        for (path, content) in [("a", "a\n"), ("b", "b\n")] {
+            fs::write(temp.join(path), content).unwrap();
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.into(), 0o100644, hash));
+            let tree = index.write_tree(&repo, false).unwrap();
+            let commit = create_commit(&repo, &tree, commits.clone(), &format!("add {path}\n"));
+            commits = vec![commit.unwrap()];
+        }
+        index.refresh(temp.path()).unwrap();
+        index.write(&repo).unwrap();
+        let second = commits.remove(0);
+        let first = read_commit(&repo, &second).unwrap().parents.remove(0);
+
+        write_ref(&repo, "refs/heads/main", &second).unwrap();
+        write_ref(&repo, "refs/heads/old", &first).unwrap();
+        write_ref(&repo, "refs/tags/v1", &first).unwrap();
+        (temp, repo, [first, second])
+    }
+
+    fn args(create: Option<&str>, detach: bool, target: Option<&str>) -> SwitchArgs {
+        SwitchArgs {
+            create: create.map(String::from),
+            detach,
+            force: false,
+            merge: false,
+            target: target.map(String::from),
+        }
+    }
+
+    fn force(target: &str) -> SwitchArgs {
+        SwitchArgs {
+            force: true,
+            ..args(None, false, Some(target))
+        }
+    }
+
+    fn run(repo: &Repository, args: SwitchArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn switches_branches() {
+        let (temp, repo, [first, second]) = setup();
+
+        assert_eq!(
+            run(&repo, args(None, false, Some("main"))).unwrap(),
+            "Already on 'main'\n"
+        );
+        assert_eq!(
+            run(&repo, args(None, false, Some("old"))).unwrap(),
+            "Switched to branch 'old'\n"
+        );
+        assert!(!fs::exists(temp.join("b")).unwrap());
+        assert_eq!(head_branch(&repo).unwrap().unwrap(), "refs/heads/old");
+
+        assert_eq!(
+            run(&repo, args(Some("new"), false, Some("main"))).unwrap(),
+            "Switched to a new branch 'new'\n"
+        );
+        assert_eq!(fs::read_to_string(temp.join("b")).unwrap(), "b\n");
+        assert_eq!(
+            resolve_ref(&repo, "refs/heads/new").unwrap().unwrap(),
+            second
+        );
+
+        assert_eq!(
+            run(&repo, args(None, true, Some("v1"))).unwrap(),
+            format!("HEAD is now at {} add a\n", &first[..7])
+        );
+        assert_eq!(head_branch(&repo).unwrap(), None);
+        assert_eq!(
+            run(&repo, args(None, false, Some("main"))).unwrap(),
+            format!(
+                "Previous HEAD position was {} add a\nSwitched to branch 'main'\n",
+                &first[..7]
+            )
+        );
+
+        let messages: Vec<_> = read_reflog(&repo, "HEAD")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(messages, [
+            "checkout: moving from main to main",
+            "checkout: moving from main to old",
+            "checkout: moving from old to new",
+            "checkout: moving from new to v1",
+            format!("checkout: moving from {first} to main").as_str(),
+        ]);
+    }
+
+    #[test]
+    fn refuses_to_lose_changes() {
+        let (temp, repo, _) = setup();
+
+        let err = run(&repo, args(None, false, Some("v1"))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a branch is expected, got tag 'v1'\n\
+             hint: If you want to detach HEAD at the commit, try again with the --detach option."
+        );
+        let err = run(&repo, args(None, false, Some("missing"))).unwrap_err();
+        assert_eq!(err.to_string(), "invalid reference: missing");
+
+        // Changes to files that differ between the commits are kept
+        fs::write(temp.join("b"), "changed\n").unwrap();
+        let err = run(&repo, args(None, false, Some("old"))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Your local changes to the following files would be overwritten by checkout:\n\tb\n\
+             Please commit your changes or stash them before you switch branches.\nAborting"
+        );
+        assert_eq!(head_branch(&repo).unwrap().unwrap(), "refs/heads/main");
+
+        // Others are carried over, and listed
+        fs::write(temp.join("b"), "b\n").unwrap();
+        fs::write(temp.join("a"), "changed\n").unwrap();
+        assert_eq!(
+            run(&repo, args(None, false, Some("old"))).unwrap(),
+            "M\ta\nSwitched to branch 'old'\n"
+        );
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), "changed\n");
+        assert!(!fs::exists(temp.join("b")).unwrap());
+
+        fs::write(temp.join("b"), "untracked\n").unwrap();
+        let err = run(&repo, args(None, false, Some("main"))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The following untracked working tree files would be overwritten by checkout:\n\tb\n\
+             Please move or remove them before you switch branches.\nAborting"
+        );
+    }
+
+    #[test]
+    fn replaces_files_and_directories() {
+        let (temp, repo, [first, _]) = setup();
+
+        // The branch `dir` has a directory where `main` has the file `b`
+        let mut index = Index::default();
+        for (path, content) in [("a", "a\n"), ("b/c/d", "d\n")] {
+            let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.into(), 0o100644, hash));
+        }
+        let tree = index.write_tree(&repo, false).unwrap();
+        let commit = create_commit(&repo, &tree, vec![first], "add b/c/d\n").unwrap();
+        write_ref(&repo, "refs/heads/dir", &commit).unwrap();
+
+        run(&repo, args(None, false, Some("dir"))).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("b/c/d")).unwrap(), "d\n");
+        run(&repo, args(None, false, Some("main"))).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("b")).unwrap(), "b\n");
+
+        // Untracked files are not lost in a directory replaced by a file
+        run(&repo, args(None, false, Some("dir"))).unwrap();
+        fs::write(temp.join("b/c/untracked"), "untracked\n").unwrap();
+        let err = run(&repo, args(None, false, Some("main"))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Updating the following directories would lose untracked files in them:\n\tb\n\n\
+             Aborting"
+        );
+        assert_eq!(fs::read_to_string(temp.join("b/c/d")).unwrap(), "d\n");
+
+        // Nor is an untracked file in the way of a directory
+        fs::remove_dir_all(temp.join("b")).unwrap();
+        run(&repo, force("old")).unwrap();
+        fs::write(temp.join("b"), "untracked\n").unwrap();
+        let err = run(&repo, args(None, false, Some("dir"))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The following untracked working tree files would be overwritten by checkout:\n\tb\n\
+             Please move or remove them before you switch branches.\nAborting"
+        );
+        assert_eq!(fs::read_to_string(temp.join("b")).unwrap(), "untracked\n");
+    }
+
+    #[test]
+    fn discards_changes_with_force() {
+        let (temp, repo, _) = setup();
+
+        fs::write(temp.join("a"), "changed\n").unwrap();
+        fs::write(temp.join("b"), "changed\n").unwrap();
+        fs::write(temp.join("new"), "new\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"new\n").unwrap();
+        index.add_entry(IndexEntry::new("new".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+        fs::write(temp.join("untracked"), "untracked\n").unwrap();
+
+        assert_eq!(
+            run(&repo, force("old")).unwrap(),
+            "Switched to branch 'old'\n"
+        );
+        assert_eq!(fs::read_to_string(temp.join("a")).unwrap(), "a\n");
+        assert!(!fs::exists(temp.join("b")).unwrap());
+        assert!(!fs::exists(temp.join("new")).unwrap());
+        assert!(fs::exists(temp.join("untracked")).unwrap());
+        let index = Index::load(&repo).unwrap();
+        let paths: Vec<_> = index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.as_slice())
+            .collect();
+        assert_eq!(paths, [b"a"]);
+
+        // Untracked files in the way are overwritten
+        fs::write(temp.join("b"), "untracked\n").unwrap();
+        run(&repo, force("main")).unwrap();
+        assert_eq!(fs::read_to_string(temp.join("b")).unwrap(), "b\n");
+    }
+
+    #[test]
+    fn merges_local_changes() {
+        let (temp, repo, [_, second]) = setup();
+        let mut index = Index::load(&repo).unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"a\nchanged\n").unwrap();
+        index.add_entry(IndexEntry::new("a".into(), 0o100644, hash));
+        let tree = index.write_tree(&repo, false).unwrap();
+        let commit = create_commit(&repo, &tree, vec![second], "change a\n").unwrap();
+        write_ref(&repo, "refs/heads/changed", &commit).unwrap();
+        let merge = |target: &str| SwitchArgs {
+            merge: true,
+            ..args(None, false, Some(target))
+        };
+
+        // Changes that do not conflict are merged into the files of the branch
+        fs::write(temp.join("a"), "local\na\n").unwrap();
+        assert_eq!(
+            run(&repo, merge("changed")).unwrap(),
+            "M\ta\nSwitched to branch 'changed'\n"
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join("a")).unwrap(),
+            "local\na\nchanged\n"
+        );
+
+        // Others are left conflicted
+        run(&repo, force("main")).unwrap();
+        fs::write(temp.join("a"), "mine\n").unwrap();
+        assert_eq!(
+            run(&repo, merge("changed")).unwrap(),
+            "M\ta\nSwitched to branch 'changed'\n"
+        );
+        assert_eq!(
+            fs::read_to_string(temp.join("a")).unwrap(),
+            "<<<<<<< changed\na\nchanged\n=======\nmine\n>>>>>>> local\n"
+        );
+        let index = Index::load(&repo).unwrap();
+        let stages: Vec<_> = index.entries().iter().map(|entry| entry.stage).collect();
+        assert_eq!(stages, [1, 2, 3, 0]);
+    }
+}
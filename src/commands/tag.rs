@@ -0,0 +1,295 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::commit::{cleanup_message, read_message_file};
+use crate::commands::CommandArgs;
+use crate::utils::commit::{create_signed_tag, create_tag};
+use crate::utils::config::Config;
+use crate::utils::refs::{delete_ref, is_valid_ref_name, list_refs, resolve_ref, write_ref};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
+use crate::utils::walk::MergeFilter;
+
+impl CommandArgs for TagArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        if self.delete {
+            for name in &self.names {
+                let tag_ref = format!("refs/tags/{name}");
+                let Some(hash) = resolve_ref(repo, &tag_ref)? else {
+                    anyhow::bail!("tag '{name}' not found.");
+                };
+                delete_ref(repo, &tag_ref)?;
+                writeln!(writer, "Deleted tag '{name}' (was {})", &hash[..7])?;
+            }
+            return Ok(());
+        }
+
+        match self.names.as_slice() {
+            [] => {
+                let filter =
+                    MergeFilter::new(repo, self.merged.as_deref(), self.no_merged.as_deref())?;
+                for (name, hash) in list_refs(repo, "refs/tags/")? {
+                    if filter.matches(repo, &hash)? {
+                        writeln!(writer, "{}", &name["refs/tags/".len()..])?;
+                    }
+                }
+                Ok(())
+            },
+            [name] => self.create(repo, name, "HEAD"),
+            [name, object] => self.create(repo, name, object),
+            _ => anyhow::bail!("too many arguments"),
+        }
+    }
+}
+
+impl TagArgs {
+    /// Create a tag pointing at an object: an annotated tag object if a message is
+    /// given or the tag is signed, or a lightweight tag otherwise.
+    fn create(&self, repo: &Repository, name: &str, object: &str) -> anyhow::Result<()> {
+        let tag_ref = format!("refs/tags/{name}");
+        if !is_valid_ref_name(&tag_ref) {
+            anyhow::bail!("'{name}' is not a valid tag name.");
+        }
+        if resolve_ref(repo, &tag_ref)?.is_some() {
+            anyhow::bail!("tag '{name}' already exists");
+        }
+
+        let config = Config::load(repo)?;
+        // A key implies signing, and `tag.gpgSign` signs annotated tags
+        let annotate = self.annotate || !self.message.is_empty() || self.file.is_some();
+        let sign = !self.no_sign
+            && (self.sign
+                || self.local_user.is_some()
+                || (annotate && config.get_bool("tag.gpgSign")?.unwrap_or(false)));
+
+        let hash = resolve_revision(repo, object)?;
+        let hash = if annotate || sign {
+            let message = match &self.file {
+                Some(path) => read_message_file(path)?,
+                None if self.message.is_empty() => {
+                    anyhow::bail!("no tag message given (use -m or -F)")
+                },
+                None => self.message.join("\n\n"),
+            };
+            let message = cleanup_message(&message);
+
+            if sign {
+                create_signed_tag(repo, &hash, name, &message, self.local_user.as_deref())
+                    .context("unable to sign the tag")?
+            } else {
+                create_tag(repo, &hash, name, &message)?
+            }
+        } else {
+            hash
+        };
+
+        write_ref(repo, &tag_ref, &hash)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct TagArgs {
+    /// delete tags
+    #[arg(short, long)]
+    delete: bool,
+    /// create an annotated tag object
+    #[arg(short, long)]
+    annotate: bool,
+    /// use the given message for an annotated tag (multiple messages are separate paragraphs)
+    #[arg(short, long = "message", value_name = "message")]
+    message: Vec<String>,
+    /// read the message of an annotated tag from a file ("-" for standard input)
+    #[arg(short = 'F', long, value_name = "file", conflicts_with = "message")]
+    file: Option<PathBuf>,
+    /// create a signed annotated tag (with user.signingKey)
+    #[arg(short, long)]
+    sign: bool,
+    /// create a signed annotated tag with the given key
+    #[arg(short = 'u', long, value_name = "key-id")]
+    local_user: Option<String>,
+    /// do not sign the tag, even if tag.gpgSign is set
+    #[arg(long)]
+    no_sign: bool,
+    /// only list tags whose commits are reachable from the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    merged: Option<String>,
+    /// only list tags whose commits are not reachable from the commit (HEAD by default)
+    #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    no_merged: Option<String>,
+    /// the tag to create (optionally followed by the object to tag) or the tags to delete
+    #[arg(value_name = "tagname")]
+    names: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::objects::{read_object, write_object, ObjectType};
+    use crate::utils::refs::write_symref;
+    use crate::utils::signature::{split_tag_signature, verify_signature, SignatureStatus};
+    use crate::utils::test::{ssh_keygen, TempRepo};
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    /// Create a repository with `main` at a commit and three tags:
+    /// `v1.0` (annotated) at the commit, `side` on another commit and `tree` on a tree.
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let commit = |message: &str| {
+            let content = format!(
+                "tree {TREE}\nauthor a <a@b> 0 +0000\ncommitter a <a@b> 0 +0000\n\n{message}\n"
+            );
+            write_object(&repo, ObjectType::Commit, content.as_bytes()).unwrap()
+        };
+        let main = commit("main");
+        let side = commit("side");
+        let tag = format!("object {main}\ntype commit\ntag v1.0\ntagger a <a@b> 0 +0000\n\nv1.0\n");
+        let tag = write_object(&repo, ObjectType::Tag, tag.as_bytes()).unwrap();
+        write_object(&repo, ObjectType::Tree, b"").unwrap();
+
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        write_ref(&repo, "refs/heads/main", &main).unwrap();
+        write_ref(&repo, "refs/tags/v1.0", &tag).unwrap();
+        write_ref(&repo, "refs/tags/side", &side).unwrap();
+        write_ref(&repo, "refs/tags/tree", TREE).unwrap();
+
+        (temp, repo, main)
+    }
+
+    fn args(names: &[&str]) -> TagArgs {
+        TagArgs {
+            delete: false,
+            annotate: false,
+            message: Vec::new(),
+            file: None,
+            sign: false,
+            local_user: None,
+            no_sign: false,
+            merged: None,
+            no_merged: None,
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn tag(repo: &Repository, args: TagArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        args.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn lists_tags() {
+        let (_temp, repo, _) = setup();
+        assert_eq!(tag(&repo, args(&[])).unwrap(), "side\ntree\nv1.0\n");
+    }
+
+    #[test]
+    fn filters_merged_tags() {
+        let (_temp, repo, _) = setup();
+
+        let output = tag(&repo, TagArgs {
+            merged: Some("HEAD".to_string()),
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "v1.0\n");
+
+        // Tags of objects other than commits are never shown
+        let output = tag(&repo, TagArgs {
+            no_merged: Some("main".to_string()),
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(output, "side\n");
+    }
+
+    #[test]
+    fn creates_and_deletes_tags() {
+        let (_temp, repo, main) = setup();
+
+        tag(&repo, args(&["v2.0"])).unwrap();
+        assert_eq!(resolve_ref(&repo, "refs/tags/v2.0").unwrap(), Some(main));
+        assert!(tag(&repo, args(&["v2.0"])).is_err());
+
+        let output = tag(&repo, TagArgs {
+            delete: true,
+            ..args(&["side"])
+        })
+        .unwrap();
+        assert!(output.starts_with("Deleted tag 'side' (was "));
+        assert_eq!(tag(&repo, args(&[])).unwrap(), "tree\nv1.0\nv2.0\n");
+    }
+
+    #[test]
+    fn creates_annotated_and_signed_tags() {
+        let (mut temp, _, main) = setup();
+        temp.set_env(env::GIT_COMMITTER_NAME, "Tagger");
+        temp.set_env(env::GIT_COMMITTER_EMAIL, "tagger@example.com");
+        temp.set_env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        let repo = temp.repo();
+
+        tag(&repo, TagArgs {
+            message: vec!["Release  ".to_string(), "Notes".to_string()],
+            ..args(&["v2.0"])
+        })
+        .unwrap();
+        let hash = resolve_ref(&repo, "refs/tags/v2.0").unwrap().unwrap();
+        let (_, content) = read_object(&repo, &hash).unwrap();
+        assert_eq!(
+            String::from_utf8(content).unwrap(),
+            format!(
+                "object {main}\ntype commit\ntag v2.0\n\
+                 tagger Tagger <tagger@example.com> 1700000000 +0000\n\nRelease\n\nNotes\n"
+            )
+        );
+
+        let err = tag(&repo, TagArgs {
+            annotate: true,
+            ..args(&["v3.0"])
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "no tag message given (use -m or -F)");
+
+        let (key, public_key) = ssh_keygen(temp.path());
+        fs::write(
+            temp.join("allowed_signers"),
+            format!("tagger@example.com {public_key}\n"),
+        )
+        .unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            format!(
+                "[gpg]\n\tformat = ssh\n[gpg \"ssh\"]\n\tallowedSignersFile = {}\n",
+                temp.join("allowed_signers").display()
+            ),
+        )
+        .unwrap();
+
+        // A key implies a signed, annotated tag
+        tag(&repo, TagArgs {
+            local_user: Some(key.display().to_string()),
+            message: vec!["Signed".to_string()],
+            ..args(&["v3.0"])
+        })
+        .unwrap();
+        let hash = resolve_ref(&repo, "refs/tags/v3.0").unwrap().unwrap();
+        let (_, content) = read_object(&repo, &hash).unwrap();
+        let (payload, signature) = split_tag_signature(&content).unwrap();
+        assert!(payload.ends_with(b"\n\nSigned\n"));
+        let config = Config::load(&repo).unwrap();
+        let check = verify_signature(&config, &payload, &signature, None).unwrap();
+        assert_eq!(check.status, SignatureStatus::Good);
+    }
+}
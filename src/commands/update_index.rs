@@ -0,0 +1,533 @@
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+
+use crate::commands::add::index_entry;
+use crate::commands::CommandArgs;
+use crate::utils::convert::Conversion;
+use crate::utils::index::{is_valid_path, Index, IndexEntry};
+use crate::utils::pathspec::normalize;
+use crate::utils::quote::unquote_c_style;
+use crate::utils::refs::is_hash;
+use crate::utils::repository::Repository;
+use crate::utils::tree::{MODE_EXECUTABLE, MODE_FILE, MODE_GITLINK, MODE_SYMLINK, MODE_TREE};
+use crate::utils::work_tree::WorkTreeFile;
+
+impl CommandArgs for UpdateIndexArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut index = Index::load(repo)?;
+
+        let mut up_to_date = true;
+        if self.refresh {
+            let mut previous_path = None;
+            for entry in index.refresh(&repo.work_tree()?)? {
+                // Conflicting stages of a path are reported once
+                if previous_path.as_ref() == Some(&entry.path) {
+                    continue;
+                }
+
+                let state = if entry.stage == 0 { "update" } else { "merge" };
+                writer.write_all(&entry.path)?;
+                writeln!(writer, ": needs {state}")?;
+                previous_path = Some(entry.path);
+                up_to_date = false;
+            }
+        }
+
+        for cacheinfo in &self.cacheinfo {
+            add_cacheinfo(&mut index, cacheinfo, self.add)?;
+        }
+
+        if !self.paths.is_empty() {
+            let work_tree = repo.work_tree()?;
+            let prefix = repo.prefix()?;
+            let mut conversion = Conversion::load(repo)?;
+            for path in &self.paths {
+                let path = normalize(&prefix, path)?;
+                self.update_path(repo, &mut conversion, &mut index, &work_tree, &path)
+                    .context(format!("Unable to process path {path}"))?;
+            }
+        }
+
+        if self.index_info {
+            read_index_info(&mut index, std::io::stdin().lock(), self.null_terminated)?;
+        }
+
+        index.write(repo)?;
+
+        if !up_to_date {
+            anyhow::bail!("the index is not up to date");
+        }
+        Ok(())
+    }
+}
+
+impl UpdateIndexArgs {
+    /// Update the entry of a path from the working tree, or only change its flags
+    /// if `--assume-unchanged` or `--no-assume-unchanged` is given.
+    fn update_path(
+        &self,
+        repo: &Repository,
+        conversion: &mut Conversion,
+        index: &mut Index,
+        work_tree: &Path,
+        path: &str,
+    ) -> anyhow::Result<()> {
+        if self.assume_unchanged || self.no_assume_unchanged {
+            let Some(entry) = index.entry(path.as_bytes(), 0) else {
+                anyhow::bail!("Unable to mark file {path}");
+            };
+            index.add_entry(IndexEntry {
+                assume_valid: self.assume_unchanged,
+                ..entry.clone()
+            });
+            return Ok(());
+        }
+
+        let Ok(metadata) = work_tree.join(path).symlink_metadata() else {
+            if !self.remove {
+                anyhow::bail!("{path}: does not exist and --remove not passed");
+            }
+            index.remove_entry(path.as_bytes());
+            return Ok(());
+        };
+
+        let file = WorkTreeFile {
+            path: path.as_bytes().to_vec(),
+            metadata,
+        };
+        if file.metadata.is_dir() && !work_tree.join(path).join(".git").exists() {
+            anyhow::bail!("{path}: is a directory - add individual files instead");
+        }
+        if !self.add && !index.contains(path.as_bytes()) {
+            anyhow::bail!("{path}: cannot add to the index - missing --add option?");
+        }
+        index.add_entry(index_entry(repo, work_tree, file, conversion)?);
+
+        if let Some(chmod) = self.chmod {
+            chmod_entry(index, path, chmod)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add an entry given as `<mode>,<hash>,<path>`, without checking that the object exists.
+fn add_cacheinfo(index: &mut Index, cacheinfo: &str, add: bool) -> anyhow::Result<()> {
+    let malformed = || anyhow::anyhow!("option 'cacheinfo' expects <mode>,<sha1>,<path>");
+
+    let mut fields = cacheinfo.splitn(3, ',');
+    let (Some(mode), Some(hash), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(malformed());
+    };
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| malformed())?;
+    if !is_hash(hash) {
+        return Err(malformed());
+    }
+
+    if !is_valid_path(path.as_bytes()) {
+        anyhow::bail!("Invalid path '{path}'");
+    }
+    if !add && !index.contains(path.as_bytes()) {
+        anyhow::bail!("{path}: cannot add to the index - missing --add option?");
+    }
+
+    index.add_entry(IndexEntry::new(
+        path.as_bytes().to_vec(),
+        canonical_mode(mode),
+        hash.to_ascii_lowercase(),
+    ));
+    Ok(())
+}
+
+/// Set or clear the executable bit of the entry of a regular file.
+fn chmod_entry(index: &mut Index, path: &str, chmod: Chmod) -> anyhow::Result<()> {
+    let (mode, flag) = match chmod {
+        Chmod::Executable => (MODE_EXECUTABLE, "+x"),
+        Chmod::NonExecutable => (MODE_FILE, "-x"),
+    };
+
+    match index.entry(path.as_bytes(), 0) {
+        Some(entry) if matches!(entry.mode, MODE_FILE | MODE_EXECUTABLE) => {
+            index.add_entry(IndexEntry {
+                mode,
+                ..entry.clone()
+            });
+            Ok(())
+        },
+        _ => anyhow::bail!("cannot chmod {flag} '{path}'"),
+    }
+}
+
+/// Apply the records of `--index-info` to the index, one per line (or NUL-terminated).
+///
+/// Each record has one of the formats
+/// - `<mode> SP <hash> TAB <path>`
+/// - `<mode> SP <type> SP <hash> TAB <path>` (as printed by `ls-tree`)
+/// - `<mode> SP <hash> SP <stage> TAB <path>` (as printed by `ls-files --stage`)
+///
+/// A mode of `0` removes the path from the index.
+fn read_index_info<R>(index: &mut Index, reader: R, null_terminated: bool) -> anyhow::Result<()>
+where
+    R: BufRead,
+{
+    let terminator = if null_terminated { b'\0' } else { b'\n' };
+
+    for record in reader.split(terminator) {
+        let record = record?;
+        if record.is_empty() {
+            continue;
+        }
+        apply_index_info(index, &record, null_terminated)?;
+    }
+
+    Ok(())
+}
+
+/// Apply a single `--index-info` record to the index.
+fn apply_index_info(index: &mut Index, record: &[u8], null_terminated: bool) -> anyhow::Result<()> {
+    let malformed = || anyhow::anyhow!("malformed index info {}", String::from_utf8_lossy(record));
+
+    let tab = record
+        .iter()
+        .position(|&byte| byte == b'\t')
+        .ok_or_else(malformed)?;
+    let info = std::str::from_utf8(&record[..tab]).map_err(|_| malformed())?;
+    let path = &record[tab + 1..];
+    let fields: Vec<_> = info.split(' ').collect();
+    let (mode, hash, stage) = match fields.as_slice() {
+        [mode, hash] => (*mode, *hash, "0"),
+        [mode, hash, stage] if is_hash(hash) => (*mode, *hash, *stage),
+        [mode, _object_type, hash] => (*mode, *hash, "0"),
+        _ => return Err(malformed()),
+    };
+
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| malformed())?;
+    let stage: u8 = stage.parse().map_err(|_| malformed())?;
+    if !is_hash(hash) || stage > 3 {
+        return Err(malformed());
+    }
+
+    // Paths are quoted when they contain special characters, unless records are NUL-terminated
+    let path = if null_terminated || path.first() != Some(&b'"') {
+        path.to_vec()
+    } else {
+        unquote_c_style(path)?
+    };
+
+    if mode == 0 {
+        index.remove_entry(&path);
+        return Ok(());
+    }
+
+    if !is_valid_path(&path) {
+        anyhow::bail!("Invalid path '{}'", String::from_utf8_lossy(&path));
+    }
+
+    index.add_entry(IndexEntry {
+        stage,
+        ..IndexEntry::new(path, canonical_mode(mode), hash.to_ascii_lowercase())
+    });
+    Ok(())
+}
+
+/// Convert a mode to one of the modes stored in the index.
+fn canonical_mode(mode: u32) -> u32 {
+    match mode & 0o170000 {
+        MODE_SYMLINK => MODE_SYMLINK,
+        MODE_TREE | MODE_GITLINK => MODE_GITLINK,
+        _ if mode & 0o111 != 0 => MODE_EXECUTABLE,
+        _ => MODE_FILE,
+    }
+}
+
+/// The executable bit to set with `--chmod`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Chmod {
+    #[value(name = "+x")]
+    Executable,
+    #[value(name = "-x")]
+    NonExecutable,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct UpdateIndexArgs {
+    /// add files that are not in the index yet
+    #[arg(long)]
+    add: bool,
+    /// remove files that are in the index but missing from the working tree
+    #[arg(long)]
+    remove: bool,
+    /// refresh the cached metadata of unchanged files and report the changed ones
+    #[arg(long)]
+    refresh: bool,
+    /// add an entry for an object that need not exist in the working tree
+    #[arg(long, value_name = "mode>,<sha1>,<path")]
+    cacheinfo: Vec<String>,
+    /// mark the files as unchanged, so their changes are ignored
+    #[arg(long, conflicts_with = "no_assume_unchanged")]
+    assume_unchanged: bool,
+    /// clear the mark set by --assume-unchanged
+    #[arg(long)]
+    no_assume_unchanged: bool,
+    /// set or clear the executable bit of the files
+    #[arg(long, value_name = "(+|-)x", allow_hyphen_values = true)]
+    chmod: Option<Chmod>,
+    /// read index information (`<mode> <hash> <path>` records) from standard input
+    #[arg(long)]
+    index_info: bool,
+    /// records read from standard input are separated by NUL instead of newline
+    #[arg(short = 'z')]
+    null_terminated: bool,
+    /// the files to update
+    #[arg(value_name = "file")]
+    paths: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{hash_object, ObjectType};
+    use crate::utils::test::TempRepo;
+
+    const HASH: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+    const OTHER_HASH: &str = "78981922613b2afb6025042ff6bd878ac1994e85";
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::create_dir_all(temp.join("dir")).unwrap();
+        fs::write(temp.join("a.txt"), "a").unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn args(paths: &[&str]) -> UpdateIndexArgs {
+        UpdateIndexArgs {
+            add: false,
+            remove: false,
+            refresh: false,
+            cacheinfo: Vec::new(),
+            assume_unchanged: false,
+            no_assume_unchanged: false,
+            chmod: None,
+            index_info: false,
+            null_terminated: false,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn update_index(repo: &Repository, args: UpdateIndexArgs) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        let result = args.run(repo, &mut output);
+        let output = String::from_utf8(output).unwrap();
+        result.map(|_| output)
+    }
+
+    fn entries(index: &Index) -> Vec<String> {
+        index
+            .entries()
+            .iter()
+            .map(|e| {
+                let path = String::from_utf8_lossy(&e.path);
+                format!("{:06o} {} {}\t{path}", e.mode, e.hash, e.stage)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_all_record_formats() {
+        let mut index = Index::default();
+        let input = format!(
+            "100644 {HASH}\ta.txt\n\
+             100755 blob {HASH}\tdir/run.sh\n\
+             100644 {HASH} 2\tconflict\n\
+             100644 {OTHER_HASH} 3\tconflict\n\
+             644 {HASH}\t\"tab\\there\"\n"
+        );
+        read_index_info(&mut index, input.as_bytes(), false).unwrap();
+
+        assert_eq!(entries(&index), vec![
+            format!("100644 {HASH} 0\ta.txt"),
+            format!("100644 {HASH} 2\tconflict"),
+            format!("100644 {OTHER_HASH} 3\tconflict"),
+            format!("100755 {HASH} 0\tdir/run.sh"),
+            format!("100644 {HASH} 0\ttab\there"),
+        ]);
+    }
+
+    #[test]
+    fn removes_paths_with_zero_mode() {
+        let mut index = Index::default();
+        let input = format!(
+            "100644 {HASH} 1\tconflict\0\
+             100644 {HASH} 2\tconflict\0\
+             0 {}\tconflict\0\
+             100644 {OTHER_HASH}\tconflict\0\
+             160000 commit {HASH}\t\"quoted\"\0",
+            "0".repeat(40)
+        );
+        read_index_info(&mut index, input.as_bytes(), true).unwrap();
+
+        assert_eq!(entries(&index), vec![
+            format!("160000 {HASH} 0\t\"quoted\""),
+            format!("100644 {OTHER_HASH} 0\tconflict"),
+        ]);
+    }
+
+    #[test]
+    fn fails_on_malformed_records() {
+        for input in [
+            "100644 abc\ta.txt\n".to_string(),
+            format!("100644 {HASH} a.txt\n"),
+            format!("100644 {HASH} 4\ta.txt\n"),
+            format!("10064x {HASH}\ta.txt\n"),
+            format!("100644 {HASH}\t../a.txt\n"),
+            format!("100644 {HASH}\t.git/config\n"),
+        ] {
+            let mut index = Index::default();
+            assert!(
+                read_index_info(&mut index, input.as_bytes(), false).is_err(),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn adds_and_removes_paths() {
+        let (temp, repo) = setup();
+
+        let err = update_index(&repo, args(&["a.txt"])).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "a.txt: cannot add to the index - missing --add option?"
+        );
+        assert!(update_index(&repo, UpdateIndexArgs {
+            add: true,
+            ..args(&["dir"])
+        })
+        .is_err());
+
+        update_index(&repo, UpdateIndexArgs {
+            add: true,
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+        let index = Index::load(&repo).unwrap();
+        let entry = index.entry(b"a.txt", 0).unwrap();
+        assert_eq!(entry.hash, hash_object(&ObjectType::Blob, b"a"));
+
+        fs::remove_file(temp.join("a.txt")).unwrap();
+        assert!(update_index(&repo, args(&["a.txt"])).is_err());
+        update_index(&repo, UpdateIndexArgs {
+            remove: true,
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+        assert!(entries(&Index::load(&repo).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn adds_cacheinfo_entries() {
+        let (_temp, repo) = setup();
+        let cacheinfo = |cacheinfo: &str, add: bool| UpdateIndexArgs {
+            add,
+            cacheinfo: vec![cacheinfo.to_string()],
+            ..args(&[])
+        };
+
+        let info = format!("755,{HASH},bin/run,x");
+        assert!(update_index(&repo, cacheinfo(&info, false)).is_err());
+        update_index(&repo, cacheinfo(&info, true)).unwrap();
+        // Existing entries can be replaced without --add
+        update_index(&repo, cacheinfo(&format!("100644,{OTHER_HASH},a"), true)).unwrap();
+        update_index(&repo, cacheinfo(&format!("100644,{HASH},a"), false)).unwrap();
+
+        assert_eq!(entries(&Index::load(&repo).unwrap()), vec![
+            format!("100644 {HASH} 0\ta"),
+            format!("100755 {HASH} 0\tbin/run,x"),
+        ]);
+
+        for info in ["100644,abc,a", "100644,a", &format!("100644,{HASH},../a")] {
+            assert!(
+                update_index(&repo, cacheinfo(info, true)).is_err(),
+                "{info}"
+            );
+        }
+    }
+
+    #[test]
+    fn changes_flags_and_modes() {
+        let (_temp, repo) = setup();
+        update_index(&repo, UpdateIndexArgs {
+            add: true,
+            chmod: Some(Chmod::Executable),
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+        assert_eq!(
+            Index::load(&repo).unwrap().entry(b"a.txt", 0).unwrap().mode,
+            MODE_EXECUTABLE
+        );
+
+        update_index(&repo, UpdateIndexArgs {
+            assume_unchanged: true,
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+        let index = Index::load(&repo).unwrap();
+        let entry = index.entry(b"a.txt", 0).unwrap();
+        assert!(entry.assume_valid);
+        assert_eq!(entry.mode, MODE_EXECUTABLE);
+
+        update_index(&repo, UpdateIndexArgs {
+            no_assume_unchanged: true,
+            ..args(&["a.txt"])
+        })
+        .unwrap();
+        assert!(
+            !Index::load(&repo)
+                .unwrap()
+                .entry(b"a.txt", 0)
+                .unwrap()
+                .assume_valid
+        );
+
+        assert!(update_index(&repo, UpdateIndexArgs {
+            assume_unchanged: true,
+            ..args(&["missing"])
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn refreshes_unchanged_files() {
+        let (temp, repo) = setup();
+        let hash = hash_object(&ObjectType::Blob, b"a");
+        let refresh = || UpdateIndexArgs {
+            refresh: true,
+            ..args(&[])
+        };
+
+        update_index(&repo, UpdateIndexArgs {
+            add: true,
+            cacheinfo: vec![format!("100644,{hash},a.txt")],
+            ..args(&[])
+        })
+        .unwrap();
+        assert_eq!(update_index(&repo, refresh()).unwrap(), "");
+        let index = Index::load(&repo).unwrap();
+        assert_ne!(index.entry(b"a.txt", 0).unwrap().stat.mtime.seconds, 0);
+
+        fs::write(temp.join("a.txt"), "changed").unwrap();
+        let mut output = Vec::new();
+        assert!(refresh().run(&repo, &mut output).is_err());
+        assert_eq!(output, b"a.txt: needs update\n");
+    }
+}
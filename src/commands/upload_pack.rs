@@ -0,0 +1,48 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::protocol::upload_pack::{serve, ServeOptions};
+use crate::utils::repository::Repository;
+use crate::utils::transfer::open_repository;
+
+impl CommandArgs for UploadPackArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        // A working tree is not served in place of its repository
+        if self.strict && self.directory.join(".git").exists() {
+            anyhow::bail!(
+                "'{}' does not appear to be a git repository",
+                self.directory.display()
+            );
+        }
+        let source = open_repository(repo, &self.directory.to_string_lossy())?;
+        let options = ServeOptions {
+            advertise_refs: self.advertise_refs,
+            stateless_rpc: self.stateless_rpc,
+        };
+        serve(&source.repo, options, &mut std::io::stdin().lock(), writer)
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct UploadPackArgs {
+    /// the repository to serve
+    directory: PathBuf,
+    /// do not serve <directory>/.git if <directory> is not a git directory
+    #[arg(long, overrides_with = "no_strict")]
+    strict: bool,
+    /// serve <directory>/.git if <directory> is not a git directory (the default)
+    #[arg(long, overrides_with = "strict")]
+    no_strict: bool,
+    /// serve a single request, without advertising the refs (as over HTTP)
+    #[arg(long)]
+    stateless_rpc: bool,
+    /// only advertise the refs
+    #[arg(long, alias = "http-backend-info-refs")]
+    advertise_refs: bool,
+}
@@ -0,0 +1,217 @@
+use std::io::Write;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::commit::{Commit, Ident};
+use crate::utils::config::Config;
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::signature::{split_commit_signature, verify_signature, SignatureStatus};
+
+impl CommandArgs for VerifyCommitArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let config = Config::load(repo)?;
+
+        for name in &self.commits {
+            let hash = resolve_commit(repo, name)?;
+            let content = read_object_of_type(repo, &hash, ObjectType::Commit)?;
+            let (payload, signature) =
+                split_commit_signature(&content).context(format!("{name}: no signature found"))?;
+
+            if self.verbose {
+                writer.write_all(&payload)?;
+            }
+
+            // The commit is checked against the keys valid at the time it was committed
+            let commit = Commit::parse(&content)?;
+            let timestamp = Ident::parse(&commit.committer)
+                .ok()
+                .map(|ident| ident.timestamp);
+
+            let check = verify_signature(&config, &payload, &signature, timestamp)?;
+            writeln!(writer, "{}", check.output)?;
+
+            if check.status != SignatureStatus::Good {
+                anyhow::bail!("{name}: could not verify the signature");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct VerifyCommitArgs {
+    /// print the contents of the commit object before validating it
+    #[arg(short, long)]
+    verbose: bool,
+    /// the commits to verify
+    #[arg(value_name = "commit", required = true)]
+    commits: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::write_ref;
+    use crate::utils::test::{ssh_keygen, ssh_sign, TempRepo};
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    const PRINCIPAL: &str = "signer@example.com";
+
+    /// Create a repository trusting a freshly generated key for [`PRINCIPAL`].
+    fn setup() -> (TempRepo, Repository, std::path::PathBuf) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+
+        let (key, public_key) = ssh_keygen(temp.path());
+        fs::write(
+            temp.join("allowed_signers"),
+            format!("{PRINCIPAL} {public_key}\n"),
+        )
+        .unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            format!(
+                "[gpg \"ssh\"]\n\tallowedSignersFile = {}\n",
+                temp.join("allowed_signers").display()
+            ),
+        )
+        .unwrap();
+
+        let repo = temp.repo();
+        (temp, repo, key)
+    }
+
+    /// Write a commit signed with `key` (or unsigned) and point `main` at it.
+    fn write_commit(repo: &Repository, key: Option<&std::path::Path>, message: &str) -> String {
+        let payload = format!(
+            "tree {TREE}\nauthor A <a@example.com> 1700000000 +0000\n\
+             committer A <a@example.com> 1700000000 +0000\n\n{message}\n"
+        );
+
+        let content = match key {
+            Some(key) => {
+                let signature = ssh_sign(key, payload.as_bytes());
+                let header = format!("gpgsig {}", signature.trim_end().replace('\n', "\n "));
+                payload.replacen("\n\n", &format!("\n{header}\n\n"), 1)
+            },
+            None => payload,
+        };
+
+        let hash = write_object(repo, ObjectType::Commit, content.as_bytes()).unwrap();
+        write_ref(repo, "refs/heads/main", &hash).unwrap();
+        hash
+    }
+
+    fn args(commit: &str) -> VerifyCommitArgs {
+        VerifyCommitArgs {
+            verbose: false,
+            commits: vec![commit.to_string()],
+        }
+    }
+
+    #[test]
+    fn verifies_signed_commit() {
+        let (_temp, repo, key) = setup();
+        write_commit(&repo, Some(&key), "signed");
+
+        let mut output = Vec::new();
+        let result = args("main").run(&repo, &mut output);
+        assert!(result.is_ok(), "{result:?}");
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(&format!(
+            "Good \"git\" signature for {PRINCIPAL} with ED25519 key SHA256:"
+        )));
+    }
+
+    #[test]
+    fn prints_payload_when_verbose() {
+        let (_temp, repo, key) = setup();
+        let hash = write_commit(&repo, Some(&key), "signed");
+
+        let mut output = Vec::new();
+        let result = VerifyCommitArgs {
+            verbose: true,
+            commits: vec![hash],
+        }
+        .run(&repo, &mut output);
+        assert!(result.is_ok());
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(&format!("tree {TREE}\n")));
+        assert!(output.contains("\n\nsigned\nGood \"git\" signature"));
+        assert!(!output.contains("gpgsig"));
+    }
+
+    #[test]
+    fn fails_on_untrusted_key() {
+        let (temp, repo, _key) = setup();
+        let other = temp.join("other");
+        fs::create_dir(&other).unwrap();
+        let (other_key, _) = ssh_keygen(&other);
+        write_commit(&repo, Some(&other_key), "signed by someone else");
+
+        let mut output = Vec::new();
+        let result = args("main").run(&repo, &mut output);
+        assert!(result.is_err());
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Good \"git\" signature with ED25519 key SHA256:"));
+        assert!(output.ends_with("No principal matched.\n"));
+    }
+
+    #[test]
+    fn fails_on_tampered_commit() {
+        let (_temp, repo, key) = setup();
+        let hash = write_commit(&repo, Some(&key), "signed");
+
+        // Re-write the commit with the same signature but a different message
+        let content = read_object_of_type(&repo, &hash, ObjectType::Commit).unwrap();
+        let tampered = String::from_utf8(content)
+            .unwrap()
+            .replace("signed\n", "tampered\n");
+        let tampered = write_object(&repo, ObjectType::Commit, tampered.as_bytes()).unwrap();
+
+        let mut output = Vec::new();
+        assert!(args(&tampered).run(&repo, &mut output).is_err());
+        assert!(!String::from_utf8(output).unwrap().starts_with("Good"));
+    }
+
+    #[test]
+    fn fails_on_unsigned_commit() {
+        let (_temp, repo, _key) = setup();
+        write_commit(&repo, None, "unsigned");
+
+        let result = args("main").run(&repo, &mut Vec::new());
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("no signature found"));
+    }
+
+    #[test]
+    fn fails_without_allowed_signers_file() {
+        let (temp, repo, key) = setup();
+        write_commit(&repo, Some(&key), "signed");
+        fs::write(temp.join(".git/config"), "").unwrap();
+
+        let result = args("main").run(&repo, &mut Vec::new());
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("gpg.ssh.allowedSignersFile"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::pack::{PackEntry, PackFile};
+use crate::utils::repository::Repository;
+use crate::utils::stats::Throughput;
+
+impl CommandArgs for VerifyPackArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut failed = false;
+        for path in &self.packs {
+            let index = path.with_extension("idx");
+            let pack = path.with_extension("pack");
+
+            let mut throughput = Throughput::start("Verifying objects", self.progress);
+            let entries = PackFile::open(&repo.cwd().join(index))
+                .and_then(|file| file.verify(repo, &mut throughput));
+            throughput.finish();
+
+            match entries {
+                Ok(entries) => {
+                    if self.verbose && !self.stat_only {
+                        list_entries(&entries, writer)?;
+                    }
+                    if self.verbose || self.stat_only {
+                        write_histogram(&entries, writer)?;
+                        writeln!(writer, "{}: ok", pack.display())?;
+                    }
+                },
+                Err(err) => {
+                    eprintln!("error: {}: {err:#}", pack.display());
+                    if self.verbose || self.stat_only {
+                        writeln!(writer, "{}: bad", pack.display())?;
+                    }
+                    failed = true;
+                },
+            }
+        }
+
+        if failed {
+            return Err(SilentExit(1).into());
+        }
+        Ok(())
+    }
+}
+
+/// List the entries of a pack: their hash, type, size, size in the pack and
+/// offset, then the length of their chain and their base for deltas.
+fn list_entries<W>(entries: &[PackEntry], writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    for entry in entries {
+        write!(
+            writer,
+            "{} {:<6} {} {} {}",
+            entry.hash,
+            entry.object_type.to_string(),
+            entry.size,
+            entry.packed_size,
+            entry.offset
+        )?;
+        if let Some((depth, base)) = &entry.delta {
+            write!(writer, " {depth} {base}")?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Count the entries of a pack by the length of their chain of deltas.
+fn write_histogram<W>(entries: &[PackEntry], writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let mut chains: BTreeMap<usize, usize> = BTreeMap::new();
+    for entry in entries {
+        let depth = entry.delta.as_ref().map_or(0, |(depth, _)| *depth);
+        *chains.entry(depth).or_default() += 1;
+    }
+
+    let plural = |count: usize| if count > 1 { "s" } else { "" };
+    let non_delta = chains.remove(&0).unwrap_or(0);
+    writeln!(writer, "non delta: {non_delta} object{}", plural(non_delta))?;
+    for (depth, count) in chains {
+        writeln!(
+            writer,
+            "chain length = {depth}: {count} object{}",
+            plural(count)
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct VerifyPackArgs {
+    /// list the objects of each pack, then how many are stored as chains of
+    /// deltas of each length
+    #[arg(short, long)]
+    verbose: bool,
+    /// only show how many objects are stored as chains of deltas of each length
+    #[arg(short, long)]
+    stat_only: bool,
+    /// show how many objects are verified per second, and a summary of the throughput
+    #[arg(long)]
+    progress: bool,
+    /// the packs to verify, given by the path of their index or pack file
+    #[arg(value_name = "pack", required = true)]
+    packs: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::ObjectType;
+    use crate::utils::pack::PackBuilder;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn verifies_packs() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let mut builder = PackBuilder::new();
+        let blob = builder.add(ObjectType::Blob, b"hello\n").unwrap();
+        let tree = [
+            b"100644 hello\0".as_slice(),
+            &crate::utils::hex::decode(blob.as_bytes()).unwrap(),
+        ]
+        .concat();
+        let tree = builder.add(ObjectType::Tree, &tree).unwrap();
+        let (mut pack, index) = builder.finish();
+        fs::write(temp.join("pack-test.pack"), &pack).unwrap();
+        fs::write(temp.join("pack-test.idx"), index).unwrap();
+
+        let verify = |verbose, stat_only| {
+            let mut output = Vec::new();
+            let result = VerifyPackArgs {
+                verbose,
+                stat_only,
+                progress: false,
+                packs: vec![PathBuf::from("pack-test.idx")],
+            }
+            .run(&repo, &mut output);
+            (result, String::from_utf8(output).unwrap())
+        };
+
+        let (result, output) = verify(true, false);
+        result.unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "{blob} blob   6 15 12\n\
+                 {tree} tree   33 46 27\n\
+                 non delta: 2 objects\n\
+                 pack-test.pack: ok\n"
+            )
+        );
+        let (result, output) = verify(false, false);
+        result.unwrap();
+        assert_eq!(output, "");
+
+        // A corrupt pack fails its checksum
+        pack[30] ^= 1;
+        fs::write(temp.join("pack-test.pack"), &pack).unwrap();
+        let (result, output) = verify(false, true);
+        let err = result.unwrap_err();
+        assert_eq!(err.downcast_ref::<SilentExit>(), Some(&SilentExit(1)));
+        assert_eq!(output, "pack-test.pack: bad\n");
+    }
+}
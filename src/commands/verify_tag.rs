@@ -0,0 +1,141 @@
+use std::io::Write;
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::commit::{Ident, Tag};
+use crate::utils::config::Config;
+use crate::utils::objects::{read_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
+use crate::utils::signature::{split_tag_signature, verify_signature, SignatureStatus};
+
+impl CommandArgs for VerifyTagArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let config = Config::load(repo)?;
+
+        for name in &self.tags {
+            let hash = resolve_revision(repo, name)?;
+            let (object_type, content) = read_object(repo, &hash)?;
+            if object_type != ObjectType::Tag {
+                anyhow::bail!("{name}: cannot verify a non-tag object of type {object_type}");
+            }
+
+            let (payload, signature) =
+                split_tag_signature(&content).context(format!("{name}: no signature found"))?;
+
+            if self.verbose {
+                writer.write_all(&payload)?;
+            }
+
+            // The tag is checked against the keys valid at the time it was created
+            let tag = Tag::parse(&content)?;
+            let timestamp = tag
+                .tagger
+                .and_then(|tagger| Ident::parse(&tagger).ok())
+                .map(|ident| ident.timestamp);
+
+            let check = verify_signature(&config, &payload, &signature, timestamp)?;
+            writeln!(writer, "{}", check.output)?;
+
+            if check.status != SignatureStatus::Good {
+                anyhow::bail!("{name}: could not verify the signature");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct VerifyTagArgs {
+    /// print the contents of the tag object before validating it
+    #[arg(short, long)]
+    verbose: bool,
+    /// the tags to verify
+    #[arg(value_name = "tag", required = true)]
+    tags: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::write_ref;
+    use crate::utils::test::{ssh_keygen, ssh_sign, TempRepo};
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    const PRINCIPAL: &str = "signer@example.com";
+
+    /// Create a repository with a tag `v1.0` signed by a trusted key.
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/tags")).unwrap();
+
+        let (key, public_key) = ssh_keygen(temp.path());
+        fs::write(
+            temp.join("allowed_signers"),
+            format!("{PRINCIPAL} {public_key}\n"),
+        )
+        .unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            format!(
+                "[gpg \"ssh\"]\n\tallowedSignersFile = {}\n",
+                temp.join("allowed_signers").display()
+            ),
+        )
+        .unwrap();
+
+        let repo = temp.repo();
+        let payload = format!(
+            "object {TREE}\ntype tree\ntag v1.0\n\
+             tagger T <t@example.com> 1700000000 +0000\n\nRelease\n"
+        );
+        let content = payload.clone() + &ssh_sign(&key, payload.as_bytes());
+        let tag = write_object(&repo, ObjectType::Tag, content.as_bytes()).unwrap();
+        write_ref(&repo, "refs/tags/v1.0", &tag).unwrap();
+        write_ref(&repo, "refs/tags/lightweight", TREE).unwrap();
+
+        (temp, repo)
+    }
+
+    fn args(tag: &str) -> VerifyTagArgs {
+        VerifyTagArgs {
+            verbose: false,
+            tags: vec![tag.to_string()],
+        }
+    }
+
+    #[test]
+    fn verifies_signed_tag() {
+        let (_temp, repo) = setup();
+
+        let mut output = Vec::new();
+        let result = args("v1.0").run(&repo, &mut output);
+        assert!(result.is_ok(), "{result:?}");
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with(&format!("Good \"git\" signature for {PRINCIPAL}")));
+    }
+
+    #[test]
+    fn fails_on_non_tag_object() {
+        let (_temp, repo) = setup();
+        // Lightweight tags point directly to the object
+        write_object(&repo, ObjectType::Tree, b"").unwrap();
+
+        let result = args("lightweight").run(&repo, &mut Vec::new());
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("cannot verify a non-tag object of type tree"));
+    }
+}
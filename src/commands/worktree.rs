@@ -0,0 +1,592 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+
+use crate::commands::{CommandArgs, SilentExit};
+use crate::utils::checkout::checkout_tree;
+use crate::utils::commit::read_commit;
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::index::{Index, IndexEntry, Stat};
+use crate::utils::path_from_bytes;
+use crate::utils::reflog::{log_ref_update, NULL_HASH};
+use crate::utils::refs::{
+    resolve_ref, resolve_symref, shorten_ref, update_ref, write_ref, write_symref,
+};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+use crate::utils::tree::{flatten_tree, MODE_GITLINK};
+use crate::utils::work_tree::{hash_file, list_files};
+
+impl CommandArgs for WorktreeArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match self.command {
+            WorktreeCommand::Add(args) => add_worktree(repo, args, writer),
+            WorktreeCommand::List(args) => list_worktrees(repo, args.porcelain, writer),
+            WorktreeCommand::Remove(args) => remove_worktree(repo, &args.worktree, args.force),
+        }
+    }
+}
+
+/// A working tree attached to the repository
+pub(crate) struct Worktree {
+    /// The path to the root of the working tree
+    pub(crate) path: PathBuf,
+    /// The administrative directory of a linked worktree
+    /// (`$GIT_COMMON_DIR/worktrees/<id>`), `None` for the main worktree
+    admin_dir: Option<PathBuf>,
+    /// The commit HEAD points to, `None` if the branch is unborn
+    pub(crate) head: Option<String>,
+    /// The branch HEAD points to, `None` if HEAD is detached
+    pub(crate) branch: Option<String>,
+    /// Whether the main worktree is a bare repository
+    pub(crate) bare: bool,
+}
+
+impl Worktree {
+    /// Open the repository of the worktree, as if the command was run from it.
+    pub(crate) fn repository(&self, repo: &Repository) -> anyhow::Result<Repository> {
+        let git_dir = match &self.admin_dir {
+            Some(admin_dir) => admin_dir.clone(),
+            None => repo.common_dir()?,
+        };
+        Ok(repo.open(git_dir, Some(self.path.clone())))
+    }
+}
+
+fn add_worktree<W>(repo: &Repository, args: AddArgs, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let common_dir = repo.common_dir()?;
+    let path = repo.cwd().join(&args.path);
+
+    if path.exists() && path.read_dir()?.next().is_some() {
+        anyhow::bail!("'{}' already exists", args.path.display());
+    }
+
+    // Work out which branch (if any) the new worktree is associated with
+    let start = args.commit_ish.as_deref().unwrap_or("HEAD");
+    let (branch, new_branch) = if let Some(new_branch) = args.new_branch {
+        (Some(new_branch), true)
+    } else if args.detach {
+        (None, false)
+    } else if let Some(commit_ish) = &args.commit_ish {
+        let is_branch = resolve_ref(repo, &format!("refs/heads/{commit_ish}"))?.is_some();
+        (is_branch.then(|| commit_ish.clone()), false)
+    } else {
+        let name = worktree_basename(&args.path)?;
+        let exists = resolve_ref(repo, &format!("refs/heads/{name}"))?.is_some();
+        (Some(name), !exists)
+    };
+
+    let commit = match (&branch, new_branch) {
+        (Some(branch), false) => resolve_commit(repo, &format!("refs/heads/{branch}"))?,
+        _ => resolve_commit(repo, start)?,
+    };
+
+    if let Some(branch) = &branch {
+        let branch_ref = format!("refs/heads/{branch}");
+
+        if new_branch {
+            if resolve_ref(repo, &branch_ref)?.is_some() {
+                anyhow::bail!("a branch named '{branch}' already exists");
+            }
+            let message = format!("branch: Created from {start}");
+            update_ref(repo, &branch_ref, &commit, &message)?;
+        } else if !args.force {
+            let in_use = list(repo)?
+                .into_iter()
+                .find(|worktree| worktree.branch.as_deref() == Some(branch_ref.as_str()));
+            if let Some(worktree) = in_use {
+                anyhow::bail!(
+                    "'{branch}' is already used by worktree at '{}'",
+                    worktree.path.display()
+                );
+            }
+        }
+    }
+
+    let message = match (&branch, new_branch) {
+        (Some(branch), true) => format!("Preparing worktree (new branch '{branch}')\n"),
+        (Some(branch), false) => format!("Preparing worktree (checking out '{branch}')\n"),
+        (None, _) => format!("Preparing worktree (detached HEAD {})\n", &commit[..7]),
+    };
+    writer.write_all(message.as_bytes())?;
+
+    // Create the working tree and its administrative directory
+    std::fs::create_dir_all(&path).context(format!("create {}", args.path.display()))?;
+    let path = path.canonicalize()?;
+    let admin_dir = unique_admin_dir(&common_dir, &worktree_basename(&path)?)?;
+    std::fs::create_dir_all(&admin_dir).context("create worktree administrative directory")?;
+
+    std::fs::write(admin_dir.join("commondir"), "../..\n")?;
+    std::fs::write(
+        admin_dir.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+    std::fs::write(
+        path.join(".git"),
+        format!("gitdir: {}\n", admin_dir.display()),
+    )?;
+
+    let worktree_repo = repo.open(admin_dir, Some(path.clone()));
+    match &branch {
+        Some(branch) => write_symref(&worktree_repo, "HEAD", &format!("refs/heads/{branch}"))?,
+        None => write_ref(&worktree_repo, "HEAD", &commit)?,
+    }
+    log_ref_update(&worktree_repo, "HEAD", None, &commit, "")?;
+
+    let commit_object = read_commit(repo, &commit)?;
+    let mut index = Index::default();
+    for entry in checkout_tree(repo, &commit_object.tree, &path)? {
+        let metadata = path.join(path_from_bytes(&entry.name)).symlink_metadata()?;
+        index.add_entry(IndexEntry {
+            stat: Stat::from_metadata(&metadata),
+            ..IndexEntry::new(entry.name, entry.mode, entry.hash)
+        });
+    }
+    index.write(&worktree_repo)?;
+
+    let subject = commit_object.subject();
+    writer.write_all(format!("HEAD is now at {} {}\n", &commit[..7], subject).as_bytes())?;
+
+    let hook = Hook {
+        args: vec![NULL_HASH.to_string(), commit, "1".to_string()],
+        ..Hook::default()
+    };
+    match run_hook(&worktree_repo, "post-checkout", hook)? {
+        0 => Ok(()),
+        code => Err(SilentExit(code).into()),
+    }
+}
+
+fn list_worktrees<W>(repo: &Repository, porcelain: bool, writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let worktrees = list(repo)?;
+    // Paths are padded to line up, with at least two spaces after the longest one
+    let path_width = worktrees
+        .iter()
+        .map(|worktree| worktree.path.display().to_string().len() + 1)
+        .max()
+        .unwrap_or_default();
+
+    for worktree in worktrees {
+        let path = worktree.path.display().to_string();
+        let head = worktree.head.unwrap_or_else(|| "0".repeat(40));
+
+        let line = if porcelain {
+            let mut line = format!("worktree {path}\n");
+            if worktree.bare {
+                line.push_str("bare\n");
+            } else {
+                line.push_str(&format!("HEAD {head}\n"));
+                match &worktree.branch {
+                    Some(branch) => line.push_str(&format!("branch {branch}\n")),
+                    None => line.push_str("detached\n"),
+                }
+            }
+            line.push('\n');
+            line
+        } else if worktree.bare {
+            format!("{path:<path_width$} (bare)\n")
+        } else {
+            let state = match &worktree.branch {
+                Some(branch) => format!("[{}]", shorten_ref(branch)),
+                None => "(detached HEAD)".to_string(),
+            };
+            format!("{path:<path_width$} {} {state}\n", &head[..7])
+        };
+
+        writer.write_all(line.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn remove_worktree(repo: &Repository, target: &Path, force: bool) -> anyhow::Result<()> {
+    let target_path = repo.cwd().join(target);
+    let target_path = target_path.canonicalize().unwrap_or(target_path);
+    let worktree = list(repo)?
+        .into_iter()
+        .find(|worktree| {
+            worktree.path == target_path
+                || worktree
+                    .admin_dir
+                    .as_ref()
+                    .is_some_and(|admin_dir| admin_dir.file_name() == Some(target.as_os_str()))
+        })
+        .context(format!("'{}' is not a working tree", target.display()))?;
+
+    let Some(admin_dir) = &worktree.admin_dir else {
+        anyhow::bail!("'{}' is a main working tree", target.display());
+    };
+
+    if !force && worktree.path.exists() && !is_clean(&worktree.repository(repo)?)? {
+        anyhow::bail!(
+            "'{}' contains modified or untracked files, use --force to delete it",
+            target.display()
+        );
+    }
+
+    if worktree.path.exists() {
+        std::fs::remove_dir_all(&worktree.path)
+            .context(format!("remove {}", worktree.path.display()))?;
+    }
+    std::fs::remove_dir_all(admin_dir).context(format!("remove {}", admin_dir.display()))
+}
+
+/// List the main worktree followed by all linked worktrees.
+pub(crate) fn list(repo: &Repository) -> anyhow::Result<Vec<Worktree>> {
+    let common_dir = repo.common_dir()?;
+
+    // A repository is bare if its git directory is not named `.git`
+    let bare = common_dir.file_name() != Some(".git".as_ref());
+    let main_path = match bare {
+        true => common_dir.clone(),
+        false => common_dir
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| common_dir.clone()),
+    };
+
+    let (head, branch) = worktree_head(repo, "main-worktree/HEAD")?;
+    let mut worktrees = vec![Worktree {
+        path: main_path,
+        admin_dir: None,
+        head,
+        branch,
+        bare,
+    }];
+
+    let admin_root = common_dir.join("worktrees");
+    if !admin_root.is_dir() {
+        return Ok(worktrees);
+    }
+
+    // Sort the linked worktrees by their ID for a stable output
+    let mut linked = BTreeMap::new();
+    for entry in std::fs::read_dir(&admin_root)? {
+        let admin_dir = entry?.path();
+        let Ok(gitdir) = std::fs::read_to_string(admin_dir.join("gitdir")) else {
+            continue;
+        };
+        let gitfile = PathBuf::from(gitdir.trim_end());
+        let path = gitfile.parent().map(Path::to_path_buf).unwrap_or(gitfile);
+        linked.insert(
+            admin_dir
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            (admin_dir, path),
+        );
+    }
+
+    for (id, (admin_dir, path)) in linked {
+        let (head, branch) = worktree_head(repo, &format!("worktrees/{id}/HEAD"))?;
+        worktrees.push(Worktree {
+            head,
+            branch,
+            path,
+            admin_dir: Some(admin_dir),
+            bare: false,
+        });
+    }
+
+    Ok(worktrees)
+}
+
+/// Read the HEAD of a worktree, given as `main-worktree/HEAD` or `worktrees/<id>/HEAD`.
+///
+/// # Returns
+///
+/// The commit HEAD points to and the branch checked out (`None` if detached)
+fn worktree_head(
+    repo: &Repository,
+    name: &str,
+) -> anyhow::Result<(Option<String>, Option<String>)> {
+    let resolved = resolve_symref(repo, name)?;
+    let branch = (resolved.name != name).then_some(resolved.name);
+    Ok((resolved.hash, branch))
+}
+
+/// Check whether the files of a working tree match its HEAD commit exactly.
+fn is_clean(repo: &Repository) -> anyhow::Result<bool> {
+    let work_tree = repo.work_tree()?;
+    let mut expected = BTreeMap::new();
+
+    if let Some(head) = resolve_ref(repo, "HEAD")? {
+        for entry in flatten_tree(repo, &read_commit(repo, &head)?.tree)? {
+            if entry.mode != MODE_GITLINK {
+                expected.insert(entry.name, entry.hash);
+            }
+        }
+    }
+
+    let mut actual = BTreeMap::new();
+    // Submodules are not compared, and ignored files count as changes too
+    for file in list_files(&work_tree, None)?
+        .into_iter()
+        .filter(|file| !file.is_repository())
+    {
+        let path = work_tree.join(path_from_bytes(&file.path));
+        actual.insert(file.path, hash_file(&path, &file.metadata)?);
+    }
+
+    Ok(actual == expected)
+}
+
+/// Get the last component of a worktree path.
+fn worktree_basename(path: &Path) -> anyhow::Result<String> {
+    let name = path
+        .file_name()
+        .context(format!("invalid worktree path: {}", path.display()))?;
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// Find an unused administrative directory for a new worktree.
+fn unique_admin_dir(common_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let admin_root = common_dir.join("worktrees");
+    let mut admin_dir = admin_root.join(name);
+    let mut suffix = 1;
+
+    while admin_dir.exists() {
+        admin_dir = admin_root.join(format!("{name}{suffix}"));
+        suffix += 1;
+    }
+
+    Ok(admin_dir)
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct WorktreeArgs {
+    #[command(subcommand)]
+    command: WorktreeCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum WorktreeCommand {
+    /// create a new working tree
+    Add(AddArgs),
+    /// list the working trees
+    List(ListArgs),
+    /// remove a working tree
+    Remove(RemoveArgs),
+}
+
+#[derive(Args, Debug)]
+struct AddArgs {
+    /// checkout <branch> even if already checked out in another worktree
+    #[arg(short, long)]
+    force: bool,
+    /// create a new branch
+    #[arg(short = 'b', value_name = "new-branch")]
+    new_branch: Option<String>,
+    /// detach HEAD at the named commit
+    #[arg(long, conflicts_with = "new_branch")]
+    detach: bool,
+    /// the path of the new working tree
+    path: PathBuf,
+    /// the branch or commit to check out
+    #[arg(value_name = "commit-ish")]
+    commit_ish: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// machine-readable output
+    #[arg(long)]
+    porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+struct RemoveArgs {
+    /// force removal even if the worktree is dirty
+    #[arg(short, long)]
+    force: bool,
+    /// the path or name of the working tree
+    worktree: PathBuf,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::refs::head_branch;
+    use crate::utils::test::TempRepo;
+
+    const FILE_NAME: &str = "file.txt";
+    const FILE_CONTENT: &str = "Hello, World!";
+
+    /// Create a repository in `main/` with a single commit on `main`.
+    fn setup() -> (TempRepo, Repository, String) {
+        let temp = TempRepo::new();
+        let main = temp.join("main");
+        fs::create_dir_all(main.join(".git/refs/heads")).unwrap();
+        fs::write(main.join(FILE_NAME), FILE_CONTENT).unwrap();
+
+        let repo = temp.repo_at("main");
+        let blob = write_object(&repo, ObjectType::Blob, FILE_CONTENT.as_bytes()).unwrap();
+        let mut tree = format!("100644 {FILE_NAME}\0").into_bytes();
+        tree.extend(crate::utils::hex::decode(blob.as_bytes()).unwrap());
+        let tree = write_object(&repo, ObjectType::Tree, &tree).unwrap();
+        let commit = format!(
+            "tree {tree}\nauthor A <a@example.com> 0 +0000\n\
+             committer A <a@example.com> 0 +0000\n\ninitial\n"
+        );
+        let commit = write_object(&repo, ObjectType::Commit, commit.as_bytes()).unwrap();
+
+        write_ref(&repo, "refs/heads/main", &commit).unwrap();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        (temp, repo, commit)
+    }
+
+    fn add_args(path: &str, new_branch: Option<&str>, commit_ish: Option<&str>) -> WorktreeArgs {
+        WorktreeArgs {
+            command: WorktreeCommand::Add(AddArgs {
+                force: false,
+                new_branch: new_branch.map(str::to_string),
+                detach: false,
+                path: PathBuf::from(path),
+                commit_ish: commit_ish.map(str::to_string),
+            }),
+        }
+    }
+
+    fn list_args(porcelain: bool) -> WorktreeArgs {
+        WorktreeArgs {
+            command: WorktreeCommand::List(ListArgs { porcelain }),
+        }
+    }
+
+    #[test]
+    fn adds_worktree_with_new_branch() {
+        let (temp, repo, commit) = setup();
+
+        let result = add_args("../linked", None, None).run(&repo, &mut Vec::new());
+        assert!(result.is_ok());
+
+        let linked = temp.join("linked");
+        assert_eq!(
+            fs::read_to_string(linked.join(FILE_NAME)).unwrap(),
+            FILE_CONTENT
+        );
+        assert!(linked.join(".git").is_file());
+
+        // The linked worktree is discovered through its gitfile
+        let linked_repo = temp.repo_at("linked");
+        assert_eq!(
+            head_branch(&linked_repo).unwrap(),
+            Some("refs/heads/linked".to_string())
+        );
+        assert_eq!(resolve_ref(&linked_repo, "HEAD").unwrap(), Some(commit));
+        assert!(Index::load(&linked_repo)
+            .unwrap()
+            .contains(FILE_NAME.as_bytes()));
+        assert_eq!(linked_repo.common_dir().unwrap(), temp.join("main/.git"));
+    }
+
+    #[test]
+    fn adds_detached_worktree() {
+        let (_temp, repo, commit) = setup();
+
+        let result = add_args("../detached", None, Some(&commit)).run(&repo, &mut Vec::new());
+        assert!(result.is_ok());
+
+        let mut output = Vec::new();
+        list_args(true).run(&repo, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("HEAD {commit}\ndetached\n")));
+    }
+
+    #[test]
+    fn lists_worktrees() {
+        let (temp, repo, commit) = setup();
+        add_args("../linked", Some("feature"), None)
+            .run(&repo, &mut Vec::new())
+            .unwrap();
+
+        let mut output = Vec::new();
+        list_args(false).run(&repo, &mut output).unwrap();
+
+        let main = temp.join("main");
+        let linked = temp.join("linked");
+        let width = linked.display().to_string().len() + 1;
+        let expected = format!(
+            "{:<width$} {} [main]\n{:<width$} {} [feature]\n",
+            main.display(),
+            &commit[..7],
+            linked.display(),
+            &commit[..7],
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn refuses_branch_checked_out_elsewhere() {
+        let (_temp, repo, _) = setup();
+
+        let result = add_args("../linked", None, Some("main")).run(&repo, &mut Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn removes_clean_worktree() {
+        let (temp, repo, _) = setup();
+        add_args("../linked", None, None)
+            .run(&repo, &mut Vec::new())
+            .unwrap();
+
+        let args = WorktreeArgs {
+            command: WorktreeCommand::Remove(RemoveArgs {
+                force: false,
+                worktree: PathBuf::from("../linked"),
+            }),
+        };
+        assert!(args.run(&repo, &mut Vec::new()).is_ok());
+        assert!(!temp.join("linked").exists());
+        assert!(!temp.join("main/.git/worktrees/linked").exists());
+    }
+
+    #[test]
+    fn refuses_to_remove_dirty_worktree() {
+        let (temp, repo, _) = setup();
+        add_args("../linked", None, None)
+            .run(&repo, &mut Vec::new())
+            .unwrap();
+        fs::write(temp.join("linked/untracked.txt"), "new").unwrap();
+
+        let remove = |force| WorktreeArgs {
+            command: WorktreeCommand::Remove(RemoveArgs {
+                force,
+                worktree: PathBuf::from("../linked"),
+            }),
+        };
+        assert!(remove(false).run(&repo, &mut Vec::new()).is_err());
+        assert!(remove(true).run(&repo, &mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn refuses_to_remove_main_worktree() {
+        let (_temp, repo, _) = setup();
+
+        let args = WorktreeArgs {
+            command: WorktreeCommand::Remove(RemoveArgs {
+                force: true,
+                worktree: PathBuf::from("."),
+            }),
+        };
+        assert!(args.run(&repo, &mut Vec::new()).is_err());
+    }
+}
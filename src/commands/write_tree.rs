@@ -0,0 +1,127 @@
+use std::io::Write;
+
+use clap::Args;
+
+use crate::commands::CommandArgs;
+use crate::utils::index::Index;
+use crate::utils::repository::Repository;
+
+impl CommandArgs for WriteTreeArgs {
+    fn run<W>(self, repo: &Repository, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let mut index = Index::load(repo)?;
+
+        let hash = match index.cached_tree() {
+            Some(hash) => hash.to_string(),
+            None => {
+                let hash = index.write_tree(repo, self.missing_ok)?;
+                // The cached trees only speed up the next call, so failing
+                // to store them (e.g. while the index is locked) is not an error
+                let _ = index.write(repo);
+                hash
+            },
+        };
+
+        writeln!(writer, "{hash}")?;
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct WriteTreeArgs {
+    /// allow objects referenced by the index to be missing from the object database
+    #[arg(long)]
+    missing_ok: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::test::{index_file, TempRepo};
+    use crate::utils::tree::flatten_tree;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn write_tree(repo: &Repository, missing_ok: bool) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        WriteTreeArgs { missing_ok }.run(repo, &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn writes_empty_tree() {
+        let (_temp, repo) = setup();
+        assert_eq!(
+            write_tree(&repo, false).unwrap(),
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904\n"
+        );
+    }
+
+    #[test]
+    fn writes_tree_of_index() {
+        let (temp, repo) = setup();
+        let blob = write_object(&repo, ObjectType::Blob, b"content").unwrap();
+        fs::write(
+            temp.join(".git/index"),
+            index_file(2, &[("a.txt", &blob, 0), ("dir/b.txt", &blob, 0)]),
+        )
+        .unwrap();
+
+        let hash = write_tree(&repo, false).unwrap();
+        let paths: Vec<_> = flatten_tree(&repo, hash.trim())
+            .unwrap()
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).unwrap())
+            .collect();
+        assert_eq!(paths, vec!["a.txt", "dir/b.txt"]);
+
+        // The trees are cached in the index until an entry changes
+        let mut index = Index::load(&repo).unwrap();
+        assert_eq!(index.cached_tree(), Some(hash.trim()));
+        index.add_entry(IndexEntry::new("dir/c.txt".into(), 0o100644, blob));
+        assert_eq!(index.cached_tree(), None);
+        index.write(&repo).unwrap();
+        assert_ne!(write_tree(&repo, false).unwrap(), hash);
+    }
+
+    #[test]
+    fn fails_on_missing_objects_and_conflicts() {
+        let (temp, repo) = setup();
+        let missing = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        fs::write(
+            temp.join(".git/index"),
+            index_file(2, &[("a.txt", missing, 0)]),
+        )
+        .unwrap();
+
+        assert!(write_tree(&repo, false).is_err());
+        assert!(write_tree(&repo, true).is_ok());
+
+        // A missing object is only reported when its tree is written
+        assert!(write_tree(&repo, false).is_ok());
+        fs::write(
+            temp.join(".git/index"),
+            index_file(2, &[("a.txt", missing, 0)]),
+        )
+        .unwrap();
+        assert!(write_tree(&repo, false).is_err());
+
+        fs::write(
+            temp.join(".git/index"),
+            index_file(2, &[("a.txt", missing, 2)]),
+        )
+        .unwrap();
+        assert!(write_tree(&repo, true).is_err());
+    }
+}
@@ -1,17 +1,77 @@
 mod commands;
 mod utils;
 
-use clap::Parser;
-use commands::Command;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{ArgAction, Parser};
+use commands::{Command, SilentExit};
+use utils::env::Environment;
+use utils::repository::Repository;
+use utils::{config, get_current_dir};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None, arg_required_else_help = true)]
 struct Args {
+    /// run as if git was started in <path> instead of the current working directory
+    #[arg(short = 'C', value_name = "path", action = ArgAction::Append)]
+    change_dir: Vec<PathBuf>,
+    /// set the path to the repository (".git" directory)
+    #[arg(long, value_name = "path")]
+    git_dir: Option<PathBuf>,
+    /// set the path to the working tree
+    #[arg(long, value_name = "path")]
+    work_tree: Option<PathBuf>,
+    /// pass a configuration parameter to the command, overriding the configuration files
+    #[arg(short = 'c', value_name = "name>=<value", action = ArgAction::Append)]
+    config: Vec<String>,
+    /// do not read replacement objects in place of the objects they replace
+    #[arg(long)]
+    no_replace_objects: bool,
     #[command(subcommand)]
     command: Command,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    args.command.run()
+
+    // Each -C is interpreted relative to the preceding one
+    for path in &args.change_dir {
+        std::env::set_current_dir(path)
+            .context(format!("cannot change to '{}'", path.display()))?;
+    }
+
+    // The options are passed in the environment of the command, so that the
+    // commands run by this one follow them
+    let mut env = Environment::capture();
+    for parameter in &args.config {
+        config::add_config_parameter(&mut env, parameter)?;
+    }
+    if args.no_replace_objects {
+        env.set(utils::env::GIT_NO_REPLACE_OBJECTS, "1");
+    }
+
+    let repo = Repository::with_environment(get_current_dir()?, env, args.git_dir, args.work_tree);
+    match args.command.run(&repo) {
+        Err(err) => match err.downcast_ref::<SilentExit>() {
+            Some(SilentExit(code)) => std::process::exit(*code),
+            // The reader of the output went away (e.g. `git log | head`), which
+            // ends the command as the SIGPIPE it ignores would have
+            None if is_broken_pipe(&err) => std::process::exit(128 + SIGPIPE),
+            None => Err(err),
+        },
+        Ok(()) => Ok(()),
+    }
+}
+
+/// The number of the signal sent when writing to a pipe nobody reads anymore
+const SIGPIPE: i32 = 13;
+
+/// Whether an error was caused by writing to a closed pipe.
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+    })
 }
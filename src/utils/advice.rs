@@ -0,0 +1,140 @@
+//! Advice and warnings for the user, written to stderr
+//!
+//! Both are kept apart from the output of commands, so that scripts reading it
+//! are not confused by them. Advice (the `hint:` lines) tells what to do next,
+//! and each kind can be turned off with its `advice.*` config key; warnings
+//! cannot be.
+
+use crate::utils::config::Config;
+use crate::utils::repository::Repository;
+
+/// A kind of advice, turned off by setting its `advice.*` key to false
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Advice {
+    /// `add` was given nothing to add
+    AddEmptyPathspec,
+    /// `add` was given ignored paths
+    AddIgnoredFile,
+    /// Local changes prevent a cherry-pick or revert
+    CommitBeforeMerge,
+    /// HEAD was detached by checking out a commit
+    DetachedHead,
+    /// `info/grafts` is used
+    GraftFileDeprecated,
+    /// A hook is not executable
+    IgnoredHook,
+    /// A commit could not be applied because of conflicts
+    MergeConflict,
+    /// A pushed commit is not a descendant of the ref it updates
+    PushNonFastForward,
+    /// A cherry-pick or revert is stopped by unresolved conflicts
+    ResolveConflict,
+    /// A sequencer operation is already in progress
+    SequencerInUse,
+    /// `switch` was given something else than a branch
+    SuggestDetachingHead,
+    /// Paths outside of the sparse checkout could not be updated
+    UpdateSparsePath,
+}
+
+impl Advice {
+    /// The name of the config key, without `advice.`
+    fn key(self) -> &'static str {
+        match self {
+            Advice::AddEmptyPathspec => "addEmptyPathspec",
+            Advice::AddIgnoredFile => "addIgnoredFile",
+            Advice::CommitBeforeMerge => "commitBeforeMerge",
+            Advice::DetachedHead => "detachedHead",
+            Advice::GraftFileDeprecated => "graftFileDeprecated",
+            Advice::IgnoredHook => "ignoredHook",
+            Advice::MergeConflict => "mergeConflict",
+            Advice::PushNonFastForward => "pushNonFFMatching",
+            Advice::ResolveConflict => "resolveConflict",
+            Advice::SequencerInUse => "sequencerInUse",
+            Advice::SuggestDetachingHead => "suggestDetachingHead",
+            Advice::UpdateSparsePath => "updateSparsePath",
+        }
+    }
+
+    /// Whether the advice is shown. It is unless its key (or, for the advice
+    /// of push, `advice.pushUpdateRejected`) is false.
+    pub(crate) fn enabled(self, repo: &Repository) -> anyhow::Result<bool> {
+        let config = Config::load(repo)?;
+        if self == Advice::PushNonFastForward
+            && config.get_bool("advice.pushUpdateRejected")? == Some(false)
+        {
+            return Ok(false);
+        }
+        Ok(config
+            .get_bool(&format!("advice.{}", self.key()))?
+            .unwrap_or(true))
+    }
+}
+
+/// Prefix each line of a message with `hint: `.
+fn hint(message: &str) -> String {
+    message
+        .lines()
+        .map(|line| format!("hint: {line}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Append advice to a message (typically an error) if it is enabled.
+pub(crate) fn with_advice(
+    repo: &Repository,
+    advice: Advice,
+    message: String,
+    advice_message: &str,
+) -> anyhow::Result<String> {
+    match advice.enabled(repo)? {
+        true => Ok(format!("{message}\n{}", hint(advice_message))),
+        false => Ok(message),
+    }
+}
+
+/// Show advice on stderr if it is enabled.
+pub(crate) fn advise(repo: &Repository, advice: Advice, message: &str) -> anyhow::Result<()> {
+    if advice.enabled(repo)? {
+        eprintln!("{}", hint(message));
+    }
+    Ok(())
+}
+
+/// Show a warning on stderr.
+pub(crate) fn warning(message: &str) {
+    eprintln!("warning: {message}");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn turned_off_by_config() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            "[advice]\n\tdetachedHead = false\n\tpushUpdateRejected = false\n",
+        )
+        .unwrap();
+        let repo = temp.repo();
+
+        assert!(!Advice::DetachedHead.enabled(&repo).unwrap());
+        assert!(!Advice::PushNonFastForward.enabled(&repo).unwrap());
+        assert!(Advice::AddIgnoredFile.enabled(&repo).unwrap());
+
+        assert_eq!(
+            with_advice(&repo, Advice::AddIgnoredFile, "error".to_string(), "a\n\nb").unwrap(),
+            "error\nhint: a\nhint: \nhint: b"
+        );
+        assert_eq!(
+            with_advice(&repo, Advice::DetachedHead, "error".to_string(), "a").unwrap(),
+            "error"
+        );
+    }
+}
@@ -0,0 +1,727 @@
+//! Parsing patches and applying their changes to the content of files
+//!
+//! Patches are unified diffs, each file starting with `--- <old path>` and
+//! `+++ <new path>` lines followed by hunks, or git diffs, each file starting with
+//! `diff --git a/<old path> b/<new path>` and extended header lines:
+//!
+//! - `old mode <mode>` and `new mode <mode>` change the mode of the file
+//! - `new file mode <mode>` and `deleted file mode <mode>` create and delete it
+//! - `rename from <path>` and `rename to <path>` (or `copy from` and `copy to`)
+//!   rename (or copy) it, the lines of the paths having no prefix to strip
+//! - `index <old hash>..<new hash> [<mode>]` identifies its old and new content
+//!
+//! The changes of binary files follow a `GIT binary patch` line, as a `literal <size>`
+//! or `delta <size>` chunk building the new content followed by one building the
+//! old content back, their zlib-compressed data being encoded in base85 lines
+//! starting with the length of their decoded data (`A`-`Z` for 1-26 bytes, `a`-`z`
+//! for 27-52).
+
+use std::io::Read;
+
+use anyhow::Context;
+use flate2::read::ZlibDecoder;
+
+use crate::utils::base85;
+use crate::utils::diff::split_lines;
+use crate::utils::pack::apply_delta;
+use crate::utils::quote::{quote_path, unquote_c_style};
+
+/// The changes of a patch to a single file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FilePatch {
+    /// The path of the file before the changes, `None` if the patch creates it
+    pub(crate) old_path: Option<Vec<u8>>,
+    /// The path of the file after the changes, `None` if the patch deletes it
+    pub(crate) new_path: Option<Vec<u8>>,
+    /// The mode of the file before the changes, if the patch tells
+    pub(crate) old_mode: Option<u32>,
+    /// The mode of the file after the changes, if the patch tells
+    pub(crate) new_mode: Option<u32>,
+    /// Whether the old file is kept when its path differs from the new one
+    pub(crate) is_copy: bool,
+    /// The (possibly abbreviated) hash of the content before the changes, if the
+    /// patch tells
+    pub(crate) old_hash: Option<String>,
+    /// The (possibly abbreviated) hash of the content after the changes, if the
+    /// patch tells
+    pub(crate) new_hash: Option<String>,
+    pub(crate) body: PatchBody,
+}
+
+/// How a patch changes the content of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PatchBody {
+    /// The hunks changing the lines of a text file (none if only the mode or the
+    /// path of the file change)
+    Text(Vec<Hunk>),
+    /// The chunks building the new content of a binary file from the old one, and
+    /// the old content back from the new one
+    Binary {
+        forward: Option<BinaryChunk>,
+        reverse: Option<BinaryChunk>,
+    },
+    /// The content of a binary file changes, without the data to change it
+    BinaryDiffers,
+}
+
+/// A chunk of a binary patch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BinaryChunk {
+    /// The whole content of the file
+    Literal(Vec<u8>),
+    /// A delta against the content of the file, as stored in packs
+    Delta(Vec<u8>),
+}
+
+/// A hunk of a text patch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hunk {
+    /// The first line of the hunk in the old content (1-based, 0 if it is empty)
+    pub(crate) old_start: usize,
+    /// The first line of the hunk in the new content (1-based, 0 if it is empty)
+    pub(crate) new_start: usize,
+    /// The lines the hunk replaces, context included
+    pub(crate) old_lines: Vec<Vec<u8>>,
+    /// The lines the hunk replaces them with, context included
+    pub(crate) new_lines: Vec<Vec<u8>>,
+    /// The number of context lines before the first changed line
+    pub(crate) leading: usize,
+    /// The number of context lines after the last changed line
+    pub(crate) trailing: usize,
+}
+
+impl FilePatch {
+    /// The path the patch is reported with, `<old> => <new>` for renames and
+    /// copies, quoted if needed (with its non-ASCII bytes if `quote_non_ascii`).
+    pub(crate) fn name(&self, quote_non_ascii: bool) -> String {
+        let quote =
+            |path: &[u8]| String::from_utf8_lossy(&quote_path(path, quote_non_ascii)).into_owned();
+        match (&self.old_path, &self.new_path) {
+            (Some(old), Some(new)) if old != new => {
+                format!("{} => {}", quote(old), quote(new))
+            },
+            (Some(path), _) | (None, Some(path)) => quote(path),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Whether the patch moves the file to another path.
+    pub(crate) fn is_rename(&self) -> bool {
+        !self.is_copy
+            && self.old_path.is_some()
+            && self.new_path.is_some()
+            && self.old_path != self.new_path
+    }
+
+    /// Turn the patch into the one undoing its changes.
+    pub(crate) fn reverse(&mut self) {
+        std::mem::swap(&mut self.old_path, &mut self.new_path);
+        std::mem::swap(&mut self.old_mode, &mut self.new_mode);
+        std::mem::swap(&mut self.old_hash, &mut self.new_hash);
+        match &mut self.body {
+            PatchBody::Text(hunks) => {
+                for hunk in hunks {
+                    std::mem::swap(&mut hunk.old_start, &mut hunk.new_start);
+                    std::mem::swap(&mut hunk.old_lines, &mut hunk.new_lines);
+                }
+            },
+            PatchBody::Binary { forward, reverse } => std::mem::swap(forward, reverse),
+            PatchBody::BinaryDiffers => {},
+        }
+    }
+}
+
+/// Parse the patches of each file from a patch, skipping the lines that are not
+/// part of them (e.g. the message of a commit before its diff).
+///
+/// # Arguments
+///
+/// * `input` - The content of the patch
+/// * `strip` - The number of leading components removed from the paths (1 for the
+///   `a/` and `b/` prefixes of git diffs)
+pub(crate) fn parse_patch(input: &[u8], strip: usize) -> anyhow::Result<Vec<FilePatch>> {
+    let mut parser = Parser {
+        lines: split_lines(input),
+        position: 0,
+        strip,
+    };
+    let mut patches = Vec::new();
+
+    while let Some(line) = parser.peek() {
+        if line.starts_with(b"diff --git ") {
+            patches.push(parser.parse_git_patch()?);
+        } else if line.starts_with(b"--- ")
+            && parser
+                .peek_at(1)
+                .is_some_and(|line| line.starts_with(b"+++ "))
+            && parser
+                .peek_at(2)
+                .is_some_and(|line| line.starts_with(b"@@ -"))
+        {
+            patches.push(parser.parse_unified_patch()?);
+        } else if line.starts_with(b"@@ -") {
+            anyhow::bail!(
+                "patch fragment without header at line {}: {}",
+                parser.position + 1,
+                text(line)
+            );
+        } else {
+            parser.position += 1;
+        }
+    }
+
+    Ok(patches)
+}
+
+/// The state of the parsing of a patch
+struct Parser<'a> {
+    lines: Vec<&'a [u8]>,
+    /// The index of the next line to parse
+    position: usize,
+    /// The number of leading components removed from the paths
+    strip: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a [u8]> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&'a [u8]> {
+        self.lines.get(self.position + offset).copied()
+    }
+
+    fn next_line(&mut self) -> Option<&'a [u8]> {
+        let line = self.peek()?;
+        self.position += 1;
+        Some(line)
+    }
+
+    /// Parse the patch of a file starting with a `diff --git` line.
+    fn parse_git_patch(&mut self) -> anyhow::Result<FilePatch> {
+        let line_number = self.position + 1;
+        let header = trim_line(self.next_line().unwrap_or_default());
+        let name = self.git_header_name(&header[b"diff --git ".len()..]);
+        let mut patch = FilePatch {
+            old_path: name.clone(),
+            new_path: name,
+            old_mode: None,
+            new_mode: None,
+            is_copy: false,
+            old_hash: None,
+            new_hash: None,
+            body: PatchBody::Text(Vec::new()),
+        };
+        let (mut is_new, mut is_deleted) = (false, false);
+
+        while let Some(line) = self.peek() {
+            let raw = trim_line(line);
+            let line = text(line);
+            if let Some(mode) = line.strip_prefix("old mode ") {
+                patch.old_mode = Some(parse_mode(mode)?);
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                patch.new_mode = Some(parse_mode(mode)?);
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                patch.old_mode = Some(parse_mode(mode)?);
+                is_deleted = true;
+            } else if let Some(mode) = line.strip_prefix("new file mode ") {
+                patch.new_mode = Some(parse_mode(mode)?);
+                is_new = true;
+            } else if let Some(path) = raw
+                .strip_prefix(b"rename from ")
+                .or_else(|| raw.strip_prefix(b"copy from "))
+            {
+                patch.old_path = Some(unquote_c_style(path)?);
+            } else if let Some(path) = raw
+                .strip_prefix(b"rename to ")
+                .or_else(|| raw.strip_prefix(b"copy to "))
+            {
+                patch.new_path = Some(unquote_c_style(path)?);
+                patch.is_copy = raw.starts_with(b"copy");
+            } else if let Some(hashes) = line.strip_prefix("index ") {
+                let (hashes, mode) = hashes.split_once(' ').unwrap_or((hashes, ""));
+                let (old, new) = hashes
+                    .split_once("..")
+                    .context(format!("invalid index line at line {}", self.position + 1))?;
+                patch.old_hash = Some(old.to_string());
+                patch.new_hash = Some(new.to_string());
+                if !mode.is_empty() {
+                    let mode = parse_mode(mode)?;
+                    patch.old_mode.get_or_insert(mode);
+                    patch.new_mode.get_or_insert(mode);
+                }
+            } else if let Some(path) = raw.strip_prefix(b"--- ").map(line_path) {
+                if path != b"/dev/null" {
+                    patch.old_path = self.strip_path(path)?.or(patch.old_path);
+                }
+            } else if let Some(path) = raw.strip_prefix(b"+++ ").map(line_path) {
+                if path != b"/dev/null" {
+                    patch.new_path = self.strip_path(path)?.or(patch.new_path);
+                }
+            } else if !line.starts_with("similarity index ")
+                && !line.starts_with("dissimilarity index ")
+            {
+                break;
+            }
+            self.position += 1;
+        }
+
+        if patch.old_path.is_none() || patch.new_path.is_none() {
+            anyhow::bail!(
+                "git diff header lacks filename information when removing {} leading \
+                 pathname component{} (line {line_number})",
+                self.strip,
+                if self.strip == 1 { "" } else { "s" }
+            );
+        }
+        if is_new {
+            patch.old_path = None;
+        }
+        if is_deleted {
+            patch.new_path = None;
+        }
+
+        match self.peek() {
+            Some(line) if line.starts_with(b"@@ -") => {
+                patch.body = PatchBody::Text(self.parse_hunks()?);
+            },
+            Some(line) if line.starts_with(b"GIT binary patch") => {
+                self.position += 1;
+                let forward = self.parse_binary_chunk()?;
+                let reverse = self.parse_binary_chunk()?;
+                patch.body = PatchBody::Binary { forward, reverse };
+            },
+            Some(line) if line.starts_with(b"Binary files ") => {
+                self.position += 1;
+                patch.body = PatchBody::BinaryDiffers;
+            },
+            _ => {},
+        }
+        Ok(patch)
+    }
+
+    /// Parse the patch of a file starting with `---` and `+++` lines.
+    fn parse_unified_patch(&mut self) -> anyhow::Result<FilePatch> {
+        let mut path = |prefix: &[u8]| -> anyhow::Result<Option<Vec<u8>>> {
+            let line = trim_line(self.next_line().unwrap_or_default());
+            match line_path(&line[prefix.len()..]) {
+                b"/dev/null" => Ok(None),
+                path => self.strip_path(path),
+            }
+        };
+        let old_path = path(b"--- ")?;
+        let new_path = path(b"+++ ")?;
+        if old_path.is_none() && new_path.is_none() {
+            anyhow::bail!("unable to find filename in patch at line {}", self.position);
+        }
+
+        Ok(FilePatch {
+            old_path,
+            new_path,
+            old_mode: None,
+            new_mode: None,
+            is_copy: false,
+            old_hash: None,
+            new_hash: None,
+            body: PatchBody::Text(self.parse_hunks()?),
+        })
+    }
+
+    /// Parse the consecutive hunks of a patch.
+    fn parse_hunks(&mut self) -> anyhow::Result<Vec<Hunk>> {
+        let mut hunks = Vec::new();
+        while self.peek().is_some_and(|line| line.starts_with(b"@@ -")) {
+            hunks.push(self.parse_hunk()?);
+        }
+        Ok(hunks)
+    }
+
+    /// Parse a hunk, from its `@@ -<old start>,<old count> +<new start>,<new count> @@`
+    /// line (a missing count being 1).
+    fn parse_hunk(&mut self) -> anyhow::Result<Hunk> {
+        let line_number = self.position + 1;
+        let header = text(self.next_line().unwrap_or_default());
+        let corrupt = || format!("corrupt patch at line {line_number}");
+        let mut ranges = header["@@ ".len()..].split(' ');
+        let (old_start, mut old_count) = ranges
+            .next()
+            .and_then(|range| parse_range(range.strip_prefix('-')?))
+            .with_context(corrupt)?;
+        let (new_start, mut new_count) = ranges
+            .next()
+            .and_then(|range| parse_range(range.strip_prefix('+')?))
+            .with_context(corrupt)?;
+
+        let mut hunk = Hunk {
+            old_start,
+            new_start,
+            old_lines: Vec::new(),
+            new_lines: Vec::new(),
+            leading: 0,
+            trailing: 0,
+        };
+        // Which sides the last line belongs to, for `\ No newline at end of file`
+        let mut last = (false, false);
+        let mut changed = false;
+
+        loop {
+            let line = match self.peek() {
+                Some(line) if line.starts_with(b"\\") => {
+                    for (is_last, lines) in
+                        [(last.0, &mut hunk.old_lines), (last.1, &mut hunk.new_lines)]
+                    {
+                        if let Some(line) = lines.last_mut().filter(|_| is_last) {
+                            if line.ends_with(b"\n") {
+                                line.pop();
+                            }
+                        }
+                    }
+                    self.position += 1;
+                    continue;
+                },
+                _ if old_count == 0 && new_count == 0 => break,
+                Some(line) => line,
+                None => anyhow::bail!("corrupt patch at line {}", self.position + 1),
+            };
+            let content = match line {
+                b"\n" => b"\n".as_slice(),
+                _ => &line[1..],
+            };
+            last = match line[0] {
+                b' ' | b'\n' => (true, true),
+                b'-' => (true, false),
+                b'+' => (false, true),
+                _ => anyhow::bail!("corrupt patch at line {}", self.position + 1),
+            };
+            if last.0 {
+                old_count = old_count
+                    .checked_sub(1)
+                    .with_context(|| format!("corrupt patch at line {}", self.position + 1))?;
+                hunk.old_lines.push(content.to_vec());
+            }
+            if last.1 {
+                new_count = new_count
+                    .checked_sub(1)
+                    .with_context(|| format!("corrupt patch at line {}", self.position + 1))?;
+                hunk.new_lines.push(content.to_vec());
+            }
+
+            if last == (true, true) {
+                match changed {
+                    true => hunk.trailing += 1,
+                    false => hunk.leading += 1,
+                }
+            } else {
+                changed = true;
+                hunk.trailing = 0;
+            }
+            self.position += 1;
+        }
+
+        Ok(hunk)
+    }
+
+    /// Parse a `literal <size>` or `delta <size>` chunk of a binary patch.
+    ///
+    /// # Returns
+    ///
+    /// The decompressed data of the chunk, or `None` if there is no chunk
+    fn parse_binary_chunk(&mut self) -> anyhow::Result<Option<BinaryChunk>> {
+        let Some(header) = self.peek().map(text) else {
+            return Ok(None);
+        };
+        let (kind, size) = match header.split_once(' ') {
+            Some((kind @ ("literal" | "delta"), size)) => (kind, size),
+            _ => return Ok(None),
+        };
+        let corrupt = |position: usize| format!("corrupt binary patch at line {position}");
+        let size: usize = size.parse().with_context(|| corrupt(self.position + 1))?;
+        self.position += 1;
+
+        let mut compressed = Vec::new();
+        while let Some(line) = self.next_line() {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let Some((&length, data)) = line.split_first() else {
+                break;
+            };
+            let length = match length {
+                b'A'..=b'Z' => length - b'A' + 1,
+                b'a'..=b'z' => length - b'a' + 27,
+                _ => anyhow::bail!(corrupt(self.position)),
+            };
+            let decoded =
+                base85::decode(data, length as usize).with_context(|| corrupt(self.position))?;
+            compressed.extend(decoded);
+        }
+
+        let mut data = Vec::with_capacity(size);
+        ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut data)
+            .with_context(|| corrupt(self.position))?;
+        if data.len() != size {
+            anyhow::bail!(corrupt(self.position));
+        }
+
+        Ok(Some(match kind {
+            "literal" => BinaryChunk::Literal(data),
+            _ => BinaryChunk::Delta(data),
+        }))
+    }
+
+    /// Find the path in the rest of a `diff --git` line, the old and new paths
+    /// being the same once their prefixes are removed (the paths of renames and
+    /// copies are found on their own lines).
+    fn git_header_name(&self, names: &[u8]) -> Option<Vec<u8>> {
+        if let Some(quoted) = names.strip_prefix(b"\"") {
+            let end = quoted
+                .iter()
+                .enumerate()
+                .scan(false, |escaped, (i, &byte)| {
+                    let is_end = byte == b'"' && !*escaped;
+                    *escaped = byte == b'\\' && !*escaped;
+                    Some((i, is_end))
+                })
+                .find(|&(_, is_end)| is_end)?
+                .0
+                + 2;
+            let old = self.strip_path(&names[..end]).ok()??;
+            let new = self.strip_path(names[end..].trim_ascii_start()).ok()??;
+            return (old == new).then_some(old);
+        }
+
+        names
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == b' ')
+            .map(|(i, _)| (&names[..i], &names[i + 1..]))
+            .find_map(|(old, new)| {
+                let old = self.strip_path(old).ok()??;
+                (self.strip_path(new).ok()?? == old).then_some(old)
+            })
+    }
+
+    /// Unquote a path and remove its leading components.
+    ///
+    /// # Returns
+    ///
+    /// The path, or `None` if it does not have more components than those removed
+    fn strip_path(&self, path: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let path = unquote_c_style(path)?;
+        let mut components = path
+            .split(|&byte| byte == b'/')
+            .filter(|component| !component.is_empty());
+        for _ in 0..self.strip {
+            components.next();
+        }
+        let path = components.collect::<Vec<_>>().join(&b'/');
+        Ok((!path.is_empty()).then_some(path))
+    }
+}
+
+/// A line without its line feed.
+fn trim_line(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+/// The text of a line, without its line feed.
+fn text(line: &[u8]) -> String {
+    String::from_utf8_lossy(trim_line(line)).into_owned()
+}
+
+/// The path of a `---` or `+++` line, without the timestamp that may follow it
+/// after a tab.
+fn line_path(path: &[u8]) -> &[u8] {
+    match path.starts_with(b"\"") {
+        true => path.trim_ascii_end(),
+        false => path
+            .split(|&byte| byte == b'\t')
+            .next()
+            .unwrap_or_default()
+            .trim_ascii_end(),
+    }
+}
+
+fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+    u32::from_str_radix(mode.trim(), 8).context(format!("invalid mode '{mode}'"))
+}
+
+/// Parse the `<start>[,<count>]` range of a hunk header.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+/// Apply the hunks of a text patch to the content of a file.
+///
+/// Each hunk is looked for around the line it starts at (shifted by the lines the
+/// previous hunks added or removed), its context having to match exactly, and at
+/// the start (or end) of the content if it has no context before (or after) its
+/// changes.
+///
+/// # Returns
+///
+/// The changed content, or the index of the first hunk that does not apply
+pub(crate) fn apply_hunks(content: &[u8], hunks: &[Hunk]) -> Result<Vec<u8>, usize> {
+    let mut image: Vec<&[u8]> = split_lines(content);
+
+    for (i, hunk) in hunks.iter().enumerate() {
+        let match_beginning = hunk.old_start <= 1;
+        let match_end = hunk.trailing == 0;
+        let preimage: Vec<&[u8]> = hunk.old_lines.iter().map(Vec::as_slice).collect();
+        if preimage.len() > image.len() {
+            return Err(i);
+        }
+
+        let matches_at = |position: usize| {
+            (!match_beginning || position == 0)
+                && (!match_end || position + preimage.len() == image.len())
+                && image[position..position + preimage.len()] == preimage[..]
+        };
+        let last = image.len() - preimage.len();
+        let start = hunk.new_start.saturating_sub(1).min(last);
+        // Look backward and forward alternately, closest positions first
+        let position = (0..=last)
+            .flat_map(|distance| [start.checked_sub(distance), Some(start + distance)])
+            .flatten()
+            .filter(|&position| position <= last)
+            .find(|&position| matches_at(position))
+            .ok_or(i)?;
+
+        image.splice(
+            position..position + preimage.len(),
+            hunk.new_lines.iter().map(Vec::as_slice),
+        );
+    }
+
+    Ok(image.concat())
+}
+
+/// Apply a chunk of a binary patch to the content of a file.
+pub(crate) fn apply_binary(content: &[u8], chunk: &BinaryChunk) -> anyhow::Result<Vec<u8>> {
+    match chunk {
+        BinaryChunk::Literal(data) => Ok(data.clone()),
+        BinaryChunk::Delta(delta) => apply_delta(content, delta),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_git_and_unified_patches() {
+        let patch = b"commit message\n\
+            \n\
+            diff --git a/old name b/new name\n\
+            similarity index 90%\n\
+            rename from old name\n\
+            rename to new name\n\
+            index 1234567..89abcde 100755\n\
+            --- a/old name\n\
+            +++ b/new name\n\
+            @@ -1,3 +1,3 @@\n \
+            one\n\
+            -two\n\
+            +2\n \
+            three\n\
+            \\ No newline at end of file\n\
+            diff --git a/gone b/gone\n\
+            deleted file mode 100644\n\
+            index 1234567..0000000\n\
+            Binary files a/gone and /dev/null differ\n\
+            --- x/dir/file\t2020-01-01\n\
+            +++ y/dir/file\n\
+            @@ -0,0 +1 @@\n\
+            +added\n";
+        let patches = parse_patch(patch, 1).unwrap();
+        assert_eq!(patches.len(), 3);
+
+        assert_eq!(patches[0].name(true), "old name => new name");
+        assert!(patches[0].is_rename());
+        assert_eq!(
+            (patches[0].old_mode, patches[0].new_mode),
+            (Some(0o100755), Some(0o100755))
+        );
+        assert_eq!(patches[0].old_hash.as_deref(), Some("1234567"));
+        assert_eq!(
+            patches[0].body,
+            PatchBody::Text(vec![Hunk {
+                old_start: 1,
+                new_start: 1,
+                old_lines: vec![b"one\n".to_vec(), b"two\n".to_vec(), b"three".to_vec()],
+                new_lines: vec![b"one\n".to_vec(), b"2\n".to_vec(), b"three".to_vec()],
+                leading: 1,
+                trailing: 1,
+            }])
+        );
+
+        assert_eq!(patches[1].old_path.as_deref(), Some(&b"gone"[..]));
+        assert_eq!(patches[1].new_path, None);
+        assert_eq!(patches[1].body, PatchBody::BinaryDiffers);
+
+        assert_eq!(patches[2].name(true), "dir/file");
+        assert_eq!(patches[2].old_path.as_deref(), Some(&b"dir/file"[..]));
+
+        assert!(parse_patch(b"@@ -1 +1 @@\n-a\n+b\n", 1)
+            .unwrap_err()
+            .to_string()
+            .contains("patch fragment without header at line 1"));
+    }
+
+    #[test]
+    fn keeps_paths_as_bytes() {
+        let patch = b"diff --git \"a/caf\\351\" \"b/caf\\351\"\n\
+            new file mode 100644\n\
+            --- /dev/null\n\
+            +++ \"b/caf\\351\"\n\
+            @@ -0,0 +1 @@\n\
+            +a\n\
+            --- a/../up\xff\t2020-01-01\n\
+            +++ b/../up\xff\n\
+            @@ -1 +1 @@\n\
+            -a\n\
+            +b\n";
+        let patches = parse_patch(patch, 1).unwrap();
+        assert_eq!(patches[0].old_path, None);
+        assert_eq!(patches[0].new_path.as_deref(), Some(&b"caf\xe9"[..]));
+        assert_eq!(patches[0].name(true), "\"caf\\351\"");
+
+        // Leading `..` components are kept, for the paths to be refused
+        assert_eq!(patches[1].old_path.as_deref(), Some(&b"../up\xff"[..]));
+        assert_eq!(patches[1].new_path.as_deref(), Some(&b"../up\xff"[..]));
+    }
+
+    #[test]
+    fn applies_hunks_around_their_position() {
+        let hunk = |old_start, old: &[&str], new: &[&str], leading, trailing| Hunk {
+            old_start,
+            new_start: old_start,
+            old_lines: old.iter().map(|line| line.as_bytes().to_vec()).collect(),
+            new_lines: new.iter().map(|line| line.as_bytes().to_vec()).collect(),
+            leading,
+            trailing,
+        };
+        let content = b"a\nb\nc\nd\ne\n";
+
+        // Shifted hunks are found, and hunks without trailing context must end the file
+        let hunks = [
+            hunk(4, &["a\n", "b\n"], &["a\n", "B\n"], 1, 0),
+            hunk(4, &["d\n", "e\n"], &["d\n", "E\n"], 1, 0),
+        ];
+        assert_eq!(
+            apply_hunks(content, &hunks[1..]).unwrap(),
+            b"a\nb\nc\nd\nE\n"
+        );
+        assert_eq!(apply_hunks(content, &hunks[..1]), Err(0));
+
+        let mut reversed = hunks[1].clone();
+        std::mem::swap(&mut reversed.old_lines, &mut reversed.new_lines);
+        assert_eq!(apply_hunks(content, &[reversed]), Err(0));
+        assert_eq!(
+            apply_hunks(b"", &[hunk(0, &[], &["new\n"], 0, 0)]).unwrap(),
+            b"new\n"
+        );
+    }
+}
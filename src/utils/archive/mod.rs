@@ -0,0 +1,123 @@
+//! Writing the files of a tree as a tar or zip archive, as `git archive` does
+
+mod tar;
+mod zip;
+
+pub(crate) use tar::write_tar;
+pub(crate) use zip::write_zip;
+
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::pathspec::Pathspec;
+use crate::utils::repository::Repository;
+use crate::utils::tree::{read_tree, MODE_GITLINK};
+
+/// The formats an archive can be written in
+pub(crate) const FORMATS: [&str; 2] = ["tar", "zip"];
+
+/// A file, symlink or directory to store in an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ArchiveEntry {
+    /// The path within the archive, including the prefix (directories end with `/`)
+    pub(crate) path: Vec<u8>,
+    /// The mode of the entry in its tree
+    pub(crate) mode: u32,
+    /// The hash of the object of the entry
+    pub(crate) hash: String,
+}
+
+impl ArchiveEntry {
+    /// Whether the entry is stored as a directory (submodules are stored as empty ones).
+    pub(crate) fn is_dir(&self) -> bool {
+        self.path.ends_with(b"/")
+    }
+
+    /// Read the content of a file, or the target of a symlink.
+    pub(crate) fn read(&self, repo: &Repository) -> anyhow::Result<Vec<u8>> {
+        if self.is_dir() {
+            return Ok(Vec::new());
+        }
+        read_object_of_type(repo, &self.hash, ObjectType::Blob)
+    }
+}
+
+/// The contents of an archive and how to describe it
+#[derive(Debug)]
+pub(crate) struct Archive {
+    /// The entries in tree order, each directory before its contents
+    pub(crate) entries: Vec<ArchiveEntry>,
+    /// The commit the tree was taken from, recorded in the archive
+    pub(crate) commit: Option<String>,
+    /// The modification time of every entry (seconds since the Unix epoch)
+    pub(crate) mtime: i64,
+}
+
+/// List the entries of a tree to archive.
+///
+/// # Arguments
+///
+/// * `repo` - The repository containing the tree
+/// * `tree` - The hash of the tree
+/// * `prefix` - Prepended to every path; a prefix ending with `/` is a directory of its own
+/// * `pathspec` - The paths of the tree to include
+pub(crate) fn collect_entries(
+    repo: &Repository,
+    tree: &str,
+    prefix: &str,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    if prefix.ends_with('/') {
+        entries.push(ArchiveEntry {
+            path: prefix.as_bytes().to_vec(),
+            mode: 0o040000,
+            hash: tree.to_string(),
+        });
+    }
+    collect_entries_into(repo, tree, prefix.as_bytes(), b"", pathspec, &mut entries)?;
+    Ok(entries)
+}
+
+fn collect_entries_into(
+    repo: &Repository,
+    tree: &str,
+    prefix: &[u8],
+    base: &[u8],
+    pathspec: &Pathspec,
+    entries: &mut Vec<ArchiveEntry>,
+) -> anyhow::Result<()> {
+    for entry in read_tree(repo, tree)? {
+        let path = [base, &entry.name].concat();
+        let mut archive_path = prefix.to_vec();
+        archive_path.extend(&path);
+
+        if entry.is_tree() {
+            if !pathspec.matches_within(&path) {
+                continue;
+            }
+            archive_path.push(b'/');
+            let base = [&path[..], b"/"].concat();
+            let start = entries.len();
+            entries.push(ArchiveEntry {
+                path: archive_path,
+                mode: entry.mode,
+                hash: entry.hash.clone(),
+            });
+            collect_entries_into(repo, &entry.hash, prefix, &base, pathspec, entries)?;
+
+            // Directories leading to matched paths are only stored if something below matched
+            if entries.len() == start + 1 && !pathspec.matches(&path) {
+                entries.pop();
+            }
+        } else if pathspec.matches(&path) {
+            if entry.mode == MODE_GITLINK {
+                archive_path.push(b'/');
+            }
+            entries.push(ArchiveEntry {
+                path: archive_path,
+                mode: entry.mode,
+                hash: entry.hash,
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,260 @@
+//! Writing archives in the POSIX ustar format, with pax headers for long names
+
+use std::io::Write;
+
+use crate::utils::archive::{Archive, ArchiveEntry};
+use crate::utils::repository::Repository;
+use crate::utils::tree::MODE_SYMLINK;
+
+/// The size of a header, and the unit data is padded to
+const BLOCK_SIZE: usize = 512;
+
+/// The unit the whole archive is padded to
+const RECORD_SIZE: usize = 10240;
+
+const TYPEFLAG_REG: u8 = b'0';
+const TYPEFLAG_LNK: u8 = b'2';
+const TYPEFLAG_DIR: u8 = b'5';
+const TYPEFLAG_GLOBAL_HEADER: u8 = b'g';
+const TYPEFLAG_EXT_HEADER: u8 = b'x';
+
+/// The mode given to pax headers
+const MODE_PAX_HEADER: u32 = 0o100666;
+
+/// The lengths of the `name`, `linkname` and `prefix` header fields
+const NAME_LENGTH: usize = 100;
+const PREFIX_LENGTH: usize = 155;
+
+/// Write an archive as a tar file, with the same layout `git archive` produces.
+///
+/// # Arguments
+///
+/// * `repo` - The repository containing the objects
+/// * `archive` - The entries to write
+/// * `umask` - The permission bits to clear from files and directories (`tar.umask`)
+/// * `writer` - Where to write the archive
+pub(crate) fn write_tar<W>(
+    repo: &Repository,
+    archive: &Archive,
+    umask: u32,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let mut tar = TarWriter {
+        writer,
+        written: 0,
+        mtime: archive.mtime,
+    };
+
+    // The commit is recorded in a global header, where `git get-tar-commit-id` finds it
+    if let Some(commit) = &archive.commit {
+        let mut records = Vec::new();
+        append_pax_record(&mut records, "comment", commit.as_bytes());
+        let mut header = [0; BLOCK_SIZE];
+        set_field(&mut header, 0, NAME_LENGTH, b"pax_global_header");
+        header[156] = TYPEFLAG_GLOBAL_HEADER;
+        tar.write_header(&mut header, MODE_PAX_HEADER, records.len())?;
+        tar.write_blocked(&records)?;
+    }
+
+    for entry in &archive.entries {
+        tar.write_entry(repo, entry, umask)?;
+    }
+
+    tar.write_trailer()
+}
+
+struct TarWriter<'w, W> {
+    writer: &'w mut W,
+    /// The number of bytes written so far
+    written: usize,
+    mtime: i64,
+}
+
+impl<W> TarWriter<'_, W>
+where
+    W: Write,
+{
+    fn write_entry(
+        &mut self,
+        repo: &Repository,
+        entry: &ArchiveEntry,
+        umask: u32,
+    ) -> anyhow::Result<()> {
+        let mut header = [0; BLOCK_SIZE];
+        let content = entry.read(repo)?;
+        let is_symlink = entry.mode == MODE_SYMLINK;
+
+        let mode = if entry.is_dir() {
+            header[156] = TYPEFLAG_DIR;
+            (entry.mode | 0o777) & !umask
+        } else if is_symlink {
+            header[156] = TYPEFLAG_LNK;
+            entry.mode | 0o777
+        } else {
+            header[156] = TYPEFLAG_REG;
+            let permissions = if entry.mode & 0o100 != 0 {
+                0o777
+            } else {
+                0o666
+            };
+            (entry.mode | permissions) & !umask
+        };
+
+        // Long paths are split into the prefix and name fields if possible,
+        // and stored in a pax header otherwise
+        let mut records = Vec::new();
+        let path = &entry.path;
+        if path.len() > NAME_LENGTH {
+            let split = path_prefix_length(path);
+            let rest = path.len().saturating_sub(split + 1);
+            if split > 0 && rest <= NAME_LENGTH {
+                set_field(&mut header, 345, PREFIX_LENGTH, &path[..split]);
+                set_field(&mut header, 0, NAME_LENGTH, &path[split + 1..]);
+            } else {
+                let name = format!("{}.data", entry.hash);
+                set_field(&mut header, 0, NAME_LENGTH, name.as_bytes());
+                append_pax_record(&mut records, "path", path);
+            }
+        } else {
+            set_field(&mut header, 0, NAME_LENGTH, path);
+        }
+
+        if is_symlink {
+            if content.len() > NAME_LENGTH {
+                let linkname = format!("see {}.paxheader", entry.hash);
+                set_field(&mut header, 157, NAME_LENGTH, linkname.as_bytes());
+                append_pax_record(&mut records, "linkpath", &content);
+            } else {
+                set_field(&mut header, 157, NAME_LENGTH, &content);
+            }
+        }
+
+        if !records.is_empty() {
+            let mut pax_header = [0; BLOCK_SIZE];
+            let name = format!("{}.paxheader", entry.hash);
+            set_field(&mut pax_header, 0, NAME_LENGTH, name.as_bytes());
+            pax_header[156] = TYPEFLAG_EXT_HEADER;
+            self.write_header(&mut pax_header, MODE_PAX_HEADER, records.len())?;
+            self.write_blocked(&records)?;
+        }
+
+        let is_file = header[156] == TYPEFLAG_REG;
+        self.write_header(&mut header, mode, if is_file { content.len() } else { 0 })?;
+        if is_file {
+            self.write_blocked(&content)?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the numeric fields and checksum of a header, and write it.
+    fn write_header(
+        &mut self,
+        header: &mut [u8; BLOCK_SIZE],
+        mode: u32,
+        size: usize,
+    ) -> anyhow::Result<()> {
+        set_field(header, 100, 8, format!("{:07o}", mode & 0o7777).as_bytes());
+        set_field(header, 108, 8, b"0000000");
+        set_field(header, 116, 8, b"0000000");
+        set_field(header, 124, 12, format!("{size:011o}").as_bytes());
+        set_field(header, 136, 12, format!("{:011o}", self.mtime).as_bytes());
+        set_field(header, 257, 6, b"ustar\0");
+        set_field(header, 263, 2, b"00");
+        set_field(header, 265, 32, b"root");
+        set_field(header, 297, 32, b"root");
+        set_field(header, 329, 8, b"0000000");
+        set_field(header, 337, 8, b"0000000");
+
+        // The checksum is computed as if its own field were filled with spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        set_field(header, 148, 8, format!("{checksum:07o}\0").as_bytes());
+
+        self.write_blocked(header.as_slice())
+    }
+
+    /// Write data padded with zeros to a whole number of blocks.
+    fn write_blocked(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.writer.write_all(data)?;
+        let padding = data.len().next_multiple_of(BLOCK_SIZE) - data.len();
+        self.writer.write_all(&[0; BLOCK_SIZE][..padding])?;
+        self.written += data.len() + padding;
+        Ok(())
+    }
+
+    /// End the archive with zeros, filling the last record. At least two zero blocks
+    /// are needed to mark the end, so another record is added if they don't fit.
+    fn write_trailer(&mut self) -> anyhow::Result<()> {
+        let tail = RECORD_SIZE - self.written % RECORD_SIZE;
+        self.writer.write_all(&vec![0; tail])?;
+        if tail < 2 * BLOCK_SIZE {
+            self.writer.write_all(&[0; RECORD_SIZE])?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy a value into a header field, truncating it to the length of the field.
+fn set_field(header: &mut [u8; BLOCK_SIZE], offset: usize, length: usize, value: &[u8]) {
+    let length = value.len().min(length);
+    header[offset..offset + length].copy_from_slice(&value[..length]);
+}
+
+/// Find where to split a long path between the prefix and name fields:
+/// the last slash that still fits in the prefix (ignoring a trailing one).
+///
+/// # Returns
+///
+/// The length of the prefix, or 0 if there is no such slash
+fn path_prefix_length(path: &[u8]) -> usize {
+    let mut i = path.len();
+    if i > 1 && path[i - 1] == b'/' {
+        i -= 1;
+    }
+    i = i.min(PREFIX_LENGTH);
+    loop {
+        i -= 1;
+        if i == 0 || path[i] == b'/' {
+            return i;
+        }
+    }
+}
+
+/// Append a `<length> <key>=<value>\n` record to a pax header,
+/// where the length counts the whole record including its own digits.
+fn append_pax_record(records: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let mut length = key.len() + value.len() + 4;
+    let mut digits = 1;
+    while length / 10 >= digits {
+        length += 1;
+        digits *= 10;
+    }
+
+    records.extend(format!("{length} {key}=").as_bytes());
+    records.extend(value);
+    records.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_pax_records() {
+        let mut records = Vec::new();
+        append_pax_record(&mut records, "comment", &[b'a'; 40]);
+        append_pax_record(&mut records, "path", &[b'b'; 92]);
+        let expected = format!(
+            "52 comment={}\n102 path={}\n",
+            "a".repeat(40),
+            "b".repeat(92)
+        );
+        assert_eq!(records, expected.as_bytes());
+
+        assert_eq!(path_prefix_length(b"abc/def/ghi/"), 7);
+        assert_eq!(path_prefix_length(b"abcdef"), 0);
+    }
+}
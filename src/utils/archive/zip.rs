@@ -0,0 +1,159 @@
+//! Writing archives in the zip format
+
+use std::io::Write;
+
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+
+use crate::utils::archive::Archive;
+use crate::utils::date::DateTime;
+use crate::utils::diff::is_binary;
+use crate::utils::repository::Repository;
+use crate::utils::tree::MODE_SYMLINK;
+
+const LOCAL_HEADER_MAGIC: u32 = 0x04034b50;
+const CENTRAL_HEADER_MAGIC: u32 = 0x02014b50;
+const END_OF_DIRECTORY_MAGIC: u32 = 0x06054b50;
+
+/// The zip version needed to extract the entries (1.0)
+const VERSION_NEEDED: u16 = 10;
+
+/// The "made by" version of entries with unix permissions (unix, zip 2.3)
+const VERSION_MADE_BY_UNIX: u16 = 0x0317;
+
+const METHOD_STORE: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// The flag marking paths as utf-8
+const FLAG_UTF8: u16 = 0x800;
+
+/// The external attribute of directories (the MS-DOS directory bit)
+const ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+/// Write an archive as a zip file, with the same layout `git archive` produces.
+/// Files are deflated unless that doesn't make them smaller.
+///
+/// # Arguments
+///
+/// * `repo` - The repository containing the objects
+/// * `archive` - The entries to write
+/// * `writer` - Where to write the archive
+pub(crate) fn write_zip<W>(
+    repo: &Repository,
+    archive: &Archive,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let (dos_time, dos_date) = dos_date_time(archive.mtime);
+    // The extended timestamp field, holding the modification time
+    let mut extra = b"UT\x05\x00\x01".to_vec();
+    extra.extend((archive.mtime as u32).to_le_bytes());
+
+    let mut offset = 0;
+    let mut directory = Vec::new();
+
+    for entry in &archive.entries {
+        let content = entry.read(repo)?;
+        let is_symlink = entry.mode == MODE_SYMLINK;
+        let is_executable = !entry.is_dir() && entry.mode & 0o111 != 0;
+
+        let (version_made_by, internal_attributes, external_attributes) = if entry.is_dir() {
+            (0, 0, ATTRIBUTE_DIRECTORY)
+        } else if is_symlink {
+            (VERSION_MADE_BY_UNIX, 1, (entry.mode | 0o777) << 16)
+        } else if is_executable {
+            let text = u16::from(!is_binary(&content));
+            (VERSION_MADE_BY_UNIX, text, entry.mode << 16)
+        } else {
+            (0, u16::from(!is_binary(&content)), 0)
+        };
+
+        let mut crc = Crc::new();
+        crc.update(&content);
+
+        let mut method = METHOD_STORE;
+        let mut data = content.clone();
+        if !entry.is_dir() && !is_symlink && !content.is_empty() {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&content)?;
+            let deflated = encoder.finish()?;
+            if deflated.len() < content.len() {
+                method = METHOD_DEFLATE;
+                data = deflated;
+            }
+        }
+
+        let flags = if entry.path.is_ascii() { 0 } else { FLAG_UTF8 };
+
+        // The fields shared by the local and central headers
+        let mut fields = Vec::new();
+        put_u16(&mut fields, VERSION_NEEDED);
+        put_u16(&mut fields, flags);
+        put_u16(&mut fields, method);
+        put_u16(&mut fields, dos_time);
+        put_u16(&mut fields, dos_date);
+        put_u32(&mut fields, crc.sum());
+        put_u32(&mut fields, data.len() as u32);
+        put_u32(&mut fields, content.len() as u32);
+        put_u16(&mut fields, entry.path.len() as u16);
+        put_u16(&mut fields, extra.len() as u16);
+
+        let mut local = Vec::new();
+        put_u32(&mut local, LOCAL_HEADER_MAGIC);
+        local.extend(&fields);
+        local.extend(&entry.path);
+        local.extend(&extra);
+
+        put_u32(&mut directory, CENTRAL_HEADER_MAGIC);
+        put_u16(&mut directory, version_made_by);
+        directory.extend(&fields);
+        // The comment length and starting disk
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, internal_attributes);
+        put_u32(&mut directory, external_attributes);
+        put_u32(&mut directory, offset as u32);
+        directory.extend(&entry.path);
+        directory.extend(&extra);
+
+        writer.write_all(&local)?;
+        writer.write_all(&data)?;
+        offset += local.len() + data.len();
+    }
+
+    // The archive comment holds the commit the tree was taken from
+    let comment = archive.commit.as_deref().unwrap_or_default();
+    let mut end = Vec::new();
+    put_u32(&mut end, END_OF_DIRECTORY_MAGIC);
+    // The number of this disk, and of the disk the directory starts on
+    put_u16(&mut end, 0);
+    put_u16(&mut end, 0);
+    put_u16(&mut end, archive.entries.len() as u16);
+    put_u16(&mut end, archive.entries.len() as u16);
+    put_u32(&mut end, directory.len() as u32);
+    put_u32(&mut end, offset as u32);
+    put_u16(&mut end, comment.len() as u16);
+    end.extend(comment.as_bytes());
+
+    writer.write_all(&directory)?;
+    writer.write_all(&end)?;
+    Ok(())
+}
+
+/// Convert a timestamp to the MS-DOS time and date fields, in UTC.
+fn dos_date_time(timestamp: i64) -> (u16, u16) {
+    let date = DateTime::from_timestamp(timestamp, 0);
+    let time = date.second / 2 + date.minute * 32 + date.hour * 2048;
+    let day = date.day + date.month * 32 + (date.year - 1980).max(0) as u32 * 512;
+    (time as u16, day as u16)
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend(value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend(value.to_le_bytes());
+}
@@ -0,0 +1,565 @@
+//! Assigning attributes to paths, from the patterns of (by precedence):
+//!
+//! 1. `$GIT_COMMON_DIR/info/attributes`
+//! 2. The `.gitattributes` files of the working tree (or of the index or a tree), the deepest one first
+//! 3. The file set with `core.attributesFile` (`$XDG_CONFIG_HOME/git/attributes` or
+//!    `~/.config/git/attributes` by default)
+//!
+//! Each line of these files is a pattern followed by attributes, which are set
+//! (`attr`), unset (`-attr`), given a value (`attr=value`) or reset to unspecified
+//! (`!attr`). For each attribute, the first file by precedence deciding it wins,
+//! and within a file the last matching line does.
+//!
+//! Lines of the form `[attr]<name> <attributes>...` define macros, which stand for
+//! their attributes wherever they are set (e.g. the built-in `binary` macro is
+//! `-diff -merge -text`). Macros can only be defined outside the `.gitattributes`
+//! files of subdirectories.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::utils::advice::warning;
+use crate::utils::config::{xdg_config_path, Config};
+use crate::utils::index::Index;
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::quote::unquote_c_style;
+use crate::utils::repository::Repository;
+use crate::utils::tree::flatten_tree;
+use crate::utils::wildmatch::wildmatch;
+
+/// The macros defined before any file is read
+const BUILTIN_MACROS: &str = "[attr]binary -diff -merge -text";
+
+/// The state of an attribute for a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AttrValue {
+    Set,
+    Unset,
+    Unspecified,
+    Value(String),
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttrValue::Set => write!(f, "set"),
+            AttrValue::Unset => write!(f, "unset"),
+            AttrValue::Unspecified => write!(f, "unspecified"),
+            AttrValue::Value(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+/// An attribute assignment of a line (the attribute is an index into the known names)
+type Assignment = (usize, AttrValue);
+
+/// A pattern of an attributes file and the attributes it assigns
+#[derive(Debug)]
+struct AttrLine {
+    /// The glob to match, without a leading `/`
+    glob: String,
+    /// Whether the pattern ends with `/`: it only matches directories, so never matches a file
+    dir_only: bool,
+    /// Whether the pattern is matched against the path relative to `base`
+    /// rather than against the file name (it contains a `/`)
+    anchored: bool,
+    /// The directory containing the attributes file (`dir/`), empty for the root
+    /// and for the files outside the working tree
+    base: String,
+    assignments: Vec<Assignment>,
+}
+
+impl AttrLine {
+    /// Whether the pattern matches a file (relative to the root of the working tree).
+    fn matches(&self, path: &str, ignore_case: bool) -> bool {
+        if self.dir_only {
+            return false;
+        }
+        let Some(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+
+        if self.anchored {
+            wildmatch(&self.glob, relative, ignore_case)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.glob, name, ignore_case)
+        }
+    }
+}
+
+/// Where the `.gitattributes` files are read from
+#[derive(Debug)]
+enum Source {
+    /// The working tree at this path
+    WorkTree(PathBuf),
+    /// The index or a tree, with the content of each `.gitattributes` file by directory (`dir/`)
+    Blobs(HashMap<String, Vec<u8>>),
+}
+
+/// The attribute rules of a repository
+#[derive(Debug)]
+pub(crate) struct Attributes {
+    source: Source,
+    /// Whether patterns are matched case-insensitively (`core.ignoreCase`)
+    ignore_case: bool,
+    /// The names of the attributes, in the order they were first seen
+    names: Vec<String>,
+    /// The attributes each macro stands for, by the index of its name
+    macros: HashMap<usize, Vec<Assignment>>,
+    /// The lines of `info/attributes`
+    info: Vec<AttrLine>,
+    /// The lines of `core.attributesFile`
+    global: Vec<AttrLine>,
+    /// The lines of the `.gitattributes` file of each directory read so far (`dir/`)
+    directories: HashMap<String, Vec<AttrLine>>,
+}
+
+impl Attributes {
+    /// Load the attribute rules of a repository, reading the `.gitattributes`
+    /// files of the working tree as the directories containing them are reached
+    /// (none for a bare repository).
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Self> {
+        match repo.is_bare()? {
+            true => Self::load_from(repo, Source::Blobs(HashMap::new())),
+            false => Self::load_from(repo, Source::WorkTree(repo.work_tree()?)),
+        }
+    }
+
+    /// Load the attribute rules of a repository, reading the `.gitattributes`
+    /// files staged in the index instead of those of the working tree.
+    pub(crate) fn load_cached(repo: &Repository, index: &Index) -> anyhow::Result<Self> {
+        let entries = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage == 0)
+            .map(|entry| {
+                (
+                    String::from_utf8_lossy(&entry.path).into_owned(),
+                    entry.hash.clone(),
+                )
+            });
+        Self::load_blobs(repo, entries)
+    }
+
+    /// Load the attribute rules of a repository, reading the `.gitattributes`
+    /// files of a tree (as when checking it out) instead of those of the working tree.
+    pub(crate) fn load_tree(repo: &Repository, tree: &str) -> anyhow::Result<Self> {
+        let entries = flatten_tree(repo, tree)?.into_iter().map(|entry| {
+            (
+                String::from_utf8_lossy(&entry.name).into_owned(),
+                entry.hash,
+            )
+        });
+        Self::load_blobs(repo, entries)
+    }
+
+    /// Load the attribute rules with the `.gitattributes` files among some blobs.
+    fn load_blobs<I>(repo: &Repository, entries: I) -> anyhow::Result<Self>
+    where
+        I: Iterator<Item = (String, String)>,
+    {
+        let mut files = HashMap::new();
+        for (path, hash) in entries {
+            let Some(dir) = path.strip_suffix(".gitattributes") else {
+                continue;
+            };
+            if dir.is_empty() || dir.ends_with('/') {
+                let content = read_object_of_type(repo, &hash, ObjectType::Blob)
+                    .context(format!("read {path}"))?;
+                files.insert(dir.to_string(), content);
+            }
+        }
+        Self::load_from(repo, Source::Blobs(files))
+    }
+
+    fn load_from(repo: &Repository, source: Source) -> anyhow::Result<Self> {
+        let config = Config::load(repo)?;
+        let mut attributes = Attributes {
+            source,
+            ignore_case: config.get_bool("core.ignoreCase")?.unwrap_or(false),
+            names: Vec::new(),
+            macros: HashMap::new(),
+            info: Vec::new(),
+            global: Vec::new(),
+            directories: HashMap::new(),
+        };
+
+        // The files allowing macros are read first, from the lowest precedence
+        attributes.parse(BUILTIN_MACROS.as_bytes(), "[builtin]", "", true);
+        if let Some(path) = config
+            .get_path("core.attributesFile")
+            .or_else(|| xdg_config_path(config.env(), "attributes"))
+        {
+            let content = read_file(&path)?;
+            attributes.global = attributes.parse(&content, &path.to_string_lossy(), "", true);
+        }
+        attributes.load_directory("")?;
+        let info = repo.common_dir()?.join("info/attributes");
+        let content = read_file(&info)?;
+        attributes.info = attributes.parse(&content, &info.to_string_lossy(), "", true);
+
+        Ok(attributes)
+    }
+
+    /// Get the value of some attributes for a path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the root of the working tree
+    /// * `names` - The names of the attributes
+    pub(crate) fn check(&mut self, path: &str, names: &[&str]) -> anyhow::Result<Vec<AttrValue>> {
+        let values = self.values(path)?;
+        Ok(names
+            .iter()
+            .map(|name| {
+                self.names
+                    .iter()
+                    .position(|known| known == name)
+                    .and_then(|index| values[index].clone())
+                    .unwrap_or(AttrValue::Unspecified)
+            })
+            .collect())
+    }
+
+    /// Get every attribute that is not unspecified for a path, in the order
+    /// the attributes were first seen.
+    pub(crate) fn all(&mut self, path: &str) -> anyhow::Result<Vec<(String, AttrValue)>> {
+        let values = self.values(path)?;
+        Ok(self
+            .names
+            .iter()
+            .zip(values)
+            .filter_map(|(name, value)| match value {
+                Some(AttrValue::Unspecified) | None => None,
+                Some(value) => Some((name.clone(), value)),
+            })
+            .collect())
+    }
+
+    /// Decide the value of every known attribute for a path (`None` if no line decides it).
+    fn values(&mut self, path: &str) -> anyhow::Result<Vec<Option<AttrValue>>> {
+        let mut dirs = vec![String::new()];
+        dirs.extend(
+            path.match_indices('/')
+                .map(|(index, _)| path[..=index].to_string()),
+        );
+        for dir in &dirs {
+            self.load_directory(dir)?;
+        }
+
+        let mut values = vec![None; self.names.len()];
+        let lists = std::iter::once(&self.info)
+            .chain(
+                dirs.iter()
+                    .rev()
+                    .filter_map(|dir| self.directories.get(dir)),
+            )
+            .chain(std::iter::once(&self.global));
+        for lines in lists {
+            for line in lines.iter().rev() {
+                if line.matches(path, self.ignore_case) {
+                    self.fill(&line.assignments, &mut values);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Assign the attributes not decided yet, expanding the macros that are set.
+    fn fill(&self, assignments: &[Assignment], values: &mut [Option<AttrValue>]) {
+        for (attr, value) in assignments.iter().rev() {
+            if values[*attr].is_some() {
+                continue;
+            }
+            values[*attr] = Some(value.clone());
+            if *value == AttrValue::Set {
+                if let Some(expansion) = self.macros.get(attr) {
+                    self.fill(expansion, values);
+                }
+            }
+        }
+    }
+
+    /// Read the `.gitattributes` file of a directory (`dir/`), unless already read.
+    fn load_directory(&mut self, dir: &str) -> anyhow::Result<()> {
+        if self.directories.contains_key(dir) {
+            return Ok(());
+        }
+
+        let content = match &self.source {
+            Source::WorkTree(work_tree) => read_file(&work_tree.join(dir).join(".gitattributes"))?,
+            Source::Blobs(files) => files.get(dir).cloned().unwrap_or_default(),
+        };
+        // Only the top-level file can define macros
+        let lines = self.parse(
+            &content,
+            &format!("{dir}.gitattributes"),
+            dir,
+            dir.is_empty(),
+        );
+        self.directories.insert(dir.to_string(), lines);
+        Ok(())
+    }
+
+    /// Parse the lines of an attributes file, recording the macros it defines.
+    /// Invalid lines are skipped with a warning, as git does.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the file
+    /// * `source` - The name of the file shown in warnings
+    /// * `base` - The directory containing the file (`dir/`), relative to the root of the working tree
+    /// * `allow_macros` - Whether the file can define macros
+    fn parse(
+        &mut self,
+        content: &[u8],
+        source: &str,
+        base: &str,
+        allow_macros: bool,
+    ) -> Vec<AttrLine> {
+        let mut lines = Vec::new();
+        for (index, line) in String::from_utf8_lossy(content).lines().enumerate() {
+            match self.parse_line(line, base, allow_macros) {
+                Ok(Some(line)) => lines.push(line),
+                Ok(None) => {},
+                Err(err) => eprintln!("{err}: {source}:{}", index + 1),
+            }
+        }
+        lines
+    }
+
+    /// Parse a line of an attributes file.
+    ///
+    /// # Returns
+    ///
+    /// The pattern and its attributes, or `None` for empty lines, comments and macros
+    fn parse_line(
+        &mut self,
+        line: &str,
+        base: &str,
+        allow_macros: bool,
+    ) -> anyhow::Result<Option<AttrLine>> {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        // A pattern starting with a quote is unquoted, and can contain spaces
+        let (pattern, rest) = if line.starts_with('"') {
+            let end = quoted_end(line).context("unterminated quoted pattern")?;
+            let pattern =
+                String::from_utf8_lossy(&unquote_c_style(&line.as_bytes()[..=end])?).into_owned();
+            (pattern, &line[end + 1..])
+        } else {
+            let end = line.find([' ', '\t']).unwrap_or(line.len());
+            (line[..end].to_string(), &line[end..])
+        };
+
+        if let Some(name) = pattern.strip_prefix("[attr]") {
+            if !allow_macros {
+                anyhow::bail!("{} not allowed", line.trim_end());
+            }
+            if !is_valid_name(name) {
+                anyhow::bail!("{name} is not a valid attribute name");
+            }
+            let name = self.intern(name);
+            let assignments = self.parse_assignments(rest)?;
+            self.macros.insert(name, assignments);
+            return Ok(None);
+        }
+
+        if pattern.starts_with('!') {
+            warning(
+                "Negative patterns are ignored in git attributes\n\
+                 Use '\\!' for literal leading exclamation.",
+            );
+            return Ok(None);
+        }
+
+        let (dir_only, glob) = match pattern.strip_suffix('/') {
+            Some(glob) => (true, glob),
+            None => (false, pattern.as_str()),
+        };
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+
+        Ok(Some(AttrLine {
+            glob: glob.to_string(),
+            dir_only,
+            anchored,
+            base: base.to_string(),
+            assignments: self.parse_assignments(rest)?,
+        }))
+    }
+
+    /// Parse the whitespace-separated attributes of a line.
+    fn parse_assignments(&mut self, text: &str) -> anyhow::Result<Vec<Assignment>> {
+        let mut assignments = Vec::new();
+        for word in text.split_whitespace() {
+            let (name, value) = if let Some(name) = word.strip_prefix('-') {
+                (name, AttrValue::Unset)
+            } else if let Some(name) = word.strip_prefix('!') {
+                (name, AttrValue::Unspecified)
+            } else if let Some((name, value)) = word.split_once('=') {
+                (name, AttrValue::Value(value.to_string()))
+            } else {
+                (word, AttrValue::Set)
+            };
+
+            if !is_valid_name(name) {
+                anyhow::bail!("{name} is not a valid attribute name");
+            }
+            assignments.push((self.intern(name), value));
+        }
+        Ok(assignments)
+    }
+
+    /// Get the index of an attribute name, adding it to the known names if needed.
+    fn intern(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|known| known == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            },
+        }
+    }
+}
+
+/// Check whether an attribute name is valid: made of letters, digits, `-`, `.`
+/// and `_`, and not starting with `-`.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))
+}
+
+/// Find the closing quote of a line starting with a quoted pattern.
+fn quoted_end(line: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in line.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(index),
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Read an attributes file, which may not exist.
+fn read_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotADirectory => Ok(Vec::new()),
+        Err(err) => Err(err).context(format!("read {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::write_object;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/info")).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn all(attributes: &mut Attributes, path: &str) -> String {
+        attributes
+            .all(path)
+            .unwrap()
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn follows_precedence_of_attribute_files() {
+        let (temp, repo) = setup();
+        fs::create_dir_all(temp.join("sub/deep")).unwrap();
+        fs::write(
+            temp.join(".gitattributes"),
+            "[attr]mine foo bar=baz\n*.txt text eol=lf\n*.bin binary\nsub/ dir\n*.c mine\n\
+             \"with space\" quoted\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.join("sub/.gitattributes"),
+            "*.txt -text\ndeep/*.txt !eol\n[attr]bad x\n",
+        )
+        .unwrap();
+        fs::write(temp.join(".git/info/attributes"), "*.c -foo\n").unwrap();
+        fs::write(temp.join("global"), "*.txt global\n").unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            format!(
+                "[core]\n\tattributesFile = {}\n",
+                temp.join("global").display()
+            ),
+        )
+        .unwrap();
+
+        let mut attributes = Attributes::load(&repo).unwrap();
+        assert_eq!(all(&mut attributes, "a.txt"), "text=set global=set eol=lf");
+        assert_eq!(
+            all(&mut attributes, "sub/a.txt"),
+            "text=unset global=set eol=lf"
+        );
+        assert_eq!(
+            all(&mut attributes, "sub/deep/a.txt"),
+            "text=unset global=set"
+        );
+        assert_eq!(
+            all(&mut attributes, "x.bin"),
+            "binary=set diff=unset merge=unset text=unset"
+        );
+        assert_eq!(
+            all(&mut attributes, "sub/y.c"),
+            "mine=set foo=unset bar=baz"
+        );
+        assert_eq!(all(&mut attributes, "with space"), "quoted=set");
+        assert_eq!(all(&mut attributes, "sub"), "");
+
+        assert_eq!(
+            attributes
+                .check("sub/deep/a.txt", &["eol", "text", "x"])
+                .unwrap(),
+            [
+                AttrValue::Unspecified,
+                AttrValue::Unset,
+                AttrValue::Unspecified
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_attributes_from_the_index() {
+        let (temp, repo) = setup();
+        fs::write(temp.join(".gitattributes"), "*.txt text\n").unwrap();
+        let hash = write_object(&repo, ObjectType::Blob, b"*.txt -text\n").unwrap();
+        let mut index = Index::default();
+        index.add_entry(IndexEntry::new(".gitattributes".into(), 0o100644, hash));
+
+        let mut attributes = Attributes::load_cached(&repo, &index).unwrap();
+        assert_eq!(attributes.check("a.txt", &["text"]).unwrap(), [
+            AttrValue::Unset
+        ]);
+    }
+}
@@ -0,0 +1,68 @@
+//! Standard (RFC 4648) base64 decoding
+
+use anyhow::Context;
+
+/// Decode a padded or unpadded base64 string, ignoring whitespace.
+pub(crate) fn decode(input: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+
+    for &c in input.iter().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            padding += 1;
+            continue;
+        }
+        if padding > 0 {
+            anyhow::bail!("invalid base64 padding");
+        }
+
+        buffer = (buffer << 6) | decode_char(c).context("invalid base64 character")?;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    if padding > 2 || bits >= 6 {
+        anyhow::bail!("invalid base64 length");
+    }
+
+    Ok(bytes)
+}
+
+fn decode_char(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+        b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::base64;
+
+    #[test]
+    fn base64_decode() {
+        assert_eq!(base64::decode(b"").unwrap(), b"");
+        assert_eq!(base64::decode(b"Zg==").unwrap(), b"f");
+        assert_eq!(base64::decode(b"Zm8=").unwrap(), b"fo");
+        assert_eq!(base64::decode(b"Zm9v").unwrap(), b"foo");
+        assert_eq!(base64::decode(b"Zm9v\nYmFy").unwrap(), b"foobar");
+    }
+
+    #[test]
+    fn fails_on_invalid_base64() {
+        assert!(base64::decode(b"Zm9v!").is_err());
+        assert!(base64::decode(b"Z").is_err());
+        assert!(base64::decode(b"Zg==Zg").is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! The base85 encoding of git's binary patches
+
+use anyhow::Context;
+
+/// The characters of the encoding, in the order of their values
+const ALPHABET: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Decode groups of five characters into `len` bytes (four per group, the last
+/// group being padded).
+pub(crate) fn decode(input: &[u8], len: usize) -> anyhow::Result<Vec<u8>> {
+    if input.len() != len.div_ceil(4) * 5 {
+        anyhow::bail!("invalid base85 length");
+    }
+
+    let mut bytes = Vec::with_capacity(len);
+    for group in input.chunks(5) {
+        let mut value = 0u64;
+        for &c in group {
+            let digit = ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .context("invalid base85 character")?;
+            value = value * 85 + digit as u64;
+        }
+        let value = u32::try_from(value).context("invalid base85 group")?;
+        bytes.extend_from_slice(&value.to_be_bytes());
+    }
+    bytes.truncate(len);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::base85;
+
+    #[test]
+    fn base85_decode() {
+        assert_eq!(base85::decode(b"", 0).unwrap(), b"");
+        assert_eq!(base85::decode(b"00000", 4).unwrap(), [0; 4]);
+        assert_eq!(base85::decode(b"W^Zo*", 3).unwrap(), b"foo");
+        assert_eq!(base85::decode(b"|NsC0", 4).unwrap(), [0xff; 4]);
+        assert!(base85::decode(b"|NsC1", 4).is_err());
+        assert!(base85::decode(b"0000", 4).is_err());
+        assert!(base85::decode(b"0000\"", 4).is_err());
+    }
+}
@@ -0,0 +1,572 @@
+//! Reachability bitmaps of packs (`pack-<checksum>.bitmap`)
+//!
+//! A bitmap has a bit for each object of a pack, in the order of the pack.
+//! The bitmap file stores the objects of each type, and for some commits
+//! every object reachable from them, so that the objects to send for a clone
+//! or a fetch are found by combining bitmaps instead of walking the history
+//! and the trees. Bitmaps are compressed with EWAH: runs of empty or full
+//! 64-bit words, each followed by literal words.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::utils::objects::ObjectType;
+use crate::utils::pack::PackFile;
+
+const SIGNATURE: &[u8; 4] = b"BITM";
+const VERSION: u16 = 1;
+/// Set when every object reachable from the commits with a bitmap is in the pack
+const OPTION_FULL_DAG: u16 = 0x1;
+const HASH_LEN: usize = 20;
+
+/// The longest run a marker word of EWAH can describe
+const MAX_RUN: u64 = (1 << 32) - 1;
+
+/// A set of objects of a pack, by position in the pack
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    /// Add the objects of another set.
+    pub(crate) fn or(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// Remove the objects of another set.
+    pub(crate) fn and_not(&mut self, other: &Bitmap) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    fn xor(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word ^= other;
+        }
+    }
+
+    /// The positions of the objects in the set, in increasing order.
+    pub(crate) fn positions(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index * 64 + bit)
+        })
+    }
+
+    /// Read a bitmap compressed with EWAH: the number of bits, the number of
+    /// words and the words, and the position of the last marker word.
+    fn read(data: &[u8], offset: &mut usize) -> anyhow::Result<Self> {
+        let read_u32 = |offset: &mut usize| -> anyhow::Result<u32> {
+            let bytes = data.get(*offset..*offset + 4).context("truncated bitmap")?;
+            *offset += 4;
+            Ok(u32::from_be_bytes(bytes.try_into()?))
+        };
+        read_u32(offset)?;
+        let count = read_u32(offset)? as usize;
+        let compressed = data
+            .get(*offset..*offset + count * 8)
+            .context("truncated bitmap")?
+            .chunks_exact(8)
+            .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+            .collect::<Vec<u64>>();
+        *offset += count * 8;
+        read_u32(offset)?;
+
+        let mut words = Vec::new();
+        let mut index = 0;
+        while index < compressed.len() {
+            let marker = compressed[index];
+            let fill = match marker & 1 {
+                1 => u64::MAX,
+                _ => 0,
+            };
+            let run = ((marker >> 1) & MAX_RUN) as usize;
+            let literals = (marker >> 33) as usize;
+            words.extend(std::iter::repeat_n(fill, run));
+            let literals = compressed
+                .get(index + 1..index + 1 + literals)
+                .context("truncated bitmap")?;
+            words.extend(literals);
+            index += 1 + literals.len();
+        }
+        Ok(Bitmap { words })
+    }
+}
+
+/// The bitmaps of a pack
+#[derive(Debug, Clone)]
+pub(crate) struct PackBitmap {
+    /// The hashes of the objects of the pack, in the order of the pack
+    objects: Vec<String>,
+    /// The objects of each type (commits, trees, blobs and tags)
+    types: [Bitmap; 4],
+    /// The objects reachable from each commit with a bitmap
+    commits: HashMap<String, Bitmap>,
+}
+
+impl PackBitmap {
+    /// Load the bitmaps of a pack (next to its index), if it has any.
+    pub(crate) fn load(pack: &PackFile) -> anyhow::Result<Option<Self>> {
+        let bitmap_path = pack.path().with_extension("bitmap");
+        if !bitmap_path.is_file() {
+            return Ok(None);
+        }
+        let content =
+            std::fs::read(&bitmap_path).context(format!("read {}", bitmap_path.display()))?;
+        let bitmap = PackBitmap::parse(&content, pack.hashes()?, pack.checksum())
+            .context(format!("read {}", bitmap_path.display()))?;
+        Ok(Some(bitmap))
+    }
+
+    /// Parse a bitmap file.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the file, including its checksum
+    /// * `objects` - The hashes of the objects of the pack, in the order of the pack
+    /// * `pack_checksum` - The checksum ending the pack
+    pub(crate) fn parse(
+        content: &[u8],
+        objects: Vec<String>,
+        pack_checksum: &[u8],
+    ) -> anyhow::Result<Self> {
+        if content.len() < 12 + 2 * HASH_LEN || !content.starts_with(SIGNATURE) {
+            anyhow::bail!("not a bitmap file");
+        }
+        let (data, checksum) = content.split_at(content.len() - HASH_LEN);
+        if Sha1::digest(data).as_slice() != checksum {
+            anyhow::bail!("bitmap checksum mismatch");
+        }
+        let version = u16::from_be_bytes(data[4..6].try_into()?);
+        if version != VERSION {
+            anyhow::bail!("unsupported bitmap version {version}");
+        }
+        let options = u16::from_be_bytes(data[6..8].try_into()?);
+        if options & OPTION_FULL_DAG == 0 {
+            anyhow::bail!("bitmaps of packs missing reachable objects are not supported");
+        }
+        let count = u32::from_be_bytes(data[8..12].try_into()?) as usize;
+        if &data[12..12 + HASH_LEN] != pack_checksum {
+            anyhow::bail!("bitmap does not match its pack");
+        }
+
+        let mut offset = 12 + HASH_LEN;
+        let types = [
+            Bitmap::read(data, &mut offset)?,
+            Bitmap::read(data, &mut offset)?,
+            Bitmap::read(data, &mut offset)?,
+            Bitmap::read(data, &mut offset)?,
+        ];
+
+        // Commits are given by their position in the index of the pack, sorted by hash
+        let mut sorted: Vec<&String> = objects.iter().collect();
+        sorted.sort();
+        let mut entries: Vec<(String, Bitmap)> = Vec::with_capacity(count);
+        for index in 0..count {
+            let header = data.get(offset..offset + 6).context("truncated bitmap")?;
+            offset += 6;
+            let position = u32::from_be_bytes(header[..4].try_into()?) as usize;
+            let commit = sorted
+                .get(position)
+                .context(format!("invalid object position {position}"))?;
+
+            // The bitmap may be stored XORed with one of the previous entries
+            let mut bitmap = Bitmap::read(data, &mut offset)?;
+            let xor_offset = header[4] as usize;
+            if xor_offset > 0 {
+                let base = index
+                    .checked_sub(xor_offset)
+                    .context(format!("invalid XOR offset {xor_offset}"))?;
+                bitmap.xor(&entries[base].1);
+            }
+            entries.push((commit.to_string(), bitmap));
+        }
+
+        Ok(PackBitmap {
+            objects,
+            types,
+            commits: entries.into_iter().collect(),
+        })
+    }
+
+    /// List the objects reachable from some commits but not from others,
+    /// as `rev-list --objects <include> ^<exclude>` does.
+    ///
+    /// # Arguments
+    ///
+    /// * `include` - The commits whose reachable objects are listed
+    /// * `exclude` - The commits whose reachable objects are left out
+    /// * `skip` - The types of the objects left out
+    ///
+    /// # Returns
+    ///
+    /// The hashes of the objects in the order of the pack, or `None` if one
+    /// of the commits has no bitmap (the objects having to be walked instead)
+    pub(crate) fn reachable(
+        &self,
+        include: &[String],
+        exclude: &[String],
+        skip: &[ObjectType],
+    ) -> Option<Vec<String>> {
+        let mut bitmap = Bitmap::default();
+        for hash in include {
+            bitmap.or(self.commits.get(hash)?);
+        }
+        for hash in exclude {
+            bitmap.and_not(self.commits.get(hash)?);
+        }
+        for &object_type in skip {
+            bitmap.and_not(&self.types[type_index(object_type)]);
+        }
+        Some(self.hashes(&bitmap))
+    }
+
+    fn hashes(&self, bitmap: &Bitmap) -> Vec<String> {
+        bitmap
+            .positions()
+            .map_while(|position| self.objects.get(position).cloned())
+            .collect()
+    }
+}
+
+/// The index of the bitmap of an object type, in the order they are stored.
+fn type_index(object_type: ObjectType) -> usize {
+    match object_type {
+        ObjectType::Commit => 0,
+        ObjectType::Tree => 1,
+        ObjectType::Blob => 2,
+        ObjectType::Tag => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::{create_commit, Commit, Tag};
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::{read_original_object, write_object};
+    use crate::utils::pack::{write_pack, PackBuilder};
+    use crate::utils::protocol::filter::Filter;
+    use crate::utils::protocol::upload_pack::objects_to_send;
+    use crate::utils::repository::Repository;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::{parse_tree, MODE_GITLINK};
+    use crate::utils::{env, hex};
+
+    /// The most literal words a marker word of EWAH can describe
+    const MAX_LITERALS: u64 = (1 << 31) - 1;
+
+    // Packs are not repacked here, so their bitmaps are only written by tests
+    impl Bitmap {
+        /// Add the object at a position.
+        fn set(&mut self, position: usize) {
+            let word = position / 64;
+            if word >= self.words.len() {
+                self.words.resize(word + 1, 0);
+            }
+            self.words[word] |= 1 << (position % 64);
+        }
+
+        /// Whether the object at a position is in the set.
+        fn get(&self, position: usize) -> bool {
+            self.words
+                .get(position / 64)
+                .is_some_and(|word| word & (1 << (position % 64)) != 0)
+        }
+
+        /// Write the bitmap compressed with EWAH.
+        fn write(&self, out: &mut Vec<u8>) {
+            let end = self.words.iter().rposition(|&word| word != 0);
+            let words = &self.words[..end.map_or(0, |end| end + 1)];
+            let bits = words
+                .last()
+                .map_or(0, |last| words.len() * 64 - last.leading_zeros() as usize);
+
+            let mut compressed = Vec::new();
+            let mut marker;
+            let mut index = 0;
+            loop {
+                marker = compressed.len();
+                compressed.push(0);
+                let mut run = 0;
+                let fill = words.get(index).copied().unwrap_or(0);
+                let full = fill == u64::MAX;
+                if fill == 0 || full {
+                    while run < MAX_RUN && words.get(index) == Some(&fill) {
+                        run += 1;
+                        index += 1;
+                    }
+                }
+                let start = index;
+                while (index - start) < MAX_LITERALS as usize
+                    && words
+                        .get(index)
+                        .is_some_and(|&word| word != 0 && word != u64::MAX)
+                {
+                    index += 1;
+                }
+                let literals = (index - start) as u64;
+                compressed[marker] = full as u64 | (run << 1) | (literals << 33);
+                compressed.extend(&words[start..index]);
+                if index >= words.len() {
+                    break;
+                }
+            }
+
+            out.extend((bits as u32).to_be_bytes());
+            out.extend((compressed.len() as u32).to_be_bytes());
+            for word in compressed {
+                out.extend(word.to_be_bytes());
+            }
+            out.extend((marker as u32).to_be_bytes());
+        }
+    }
+
+    /// Write the bitmaps of a pack, as `git repack --write-bitmap-index` does.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository containing the objects of the pack
+    /// * `objects` - The hashes of the objects of the pack, in the order of the pack
+    /// * `pack_checksum` - The checksum ending the pack
+    /// * `commits` - The commits to store the reachable objects of, which must all be in the pack
+    ///
+    /// # Returns
+    ///
+    /// The content of the bitmap file
+    fn write_bitmap(
+        repo: &Repository,
+        objects: &[String],
+        pack_checksum: &[u8],
+        commits: &[String],
+    ) -> anyhow::Result<Vec<u8>> {
+        let positions: HashMap<&str, usize> = objects
+            .iter()
+            .enumerate()
+            .map(|(position, hash)| (hash.as_str(), position))
+            .collect();
+
+        // The objects each object refers to, by position
+        let mut types: [Bitmap; 4] = Default::default();
+        let mut links = Vec::with_capacity(objects.len());
+        for (position, hash) in objects.iter().enumerate() {
+            let (object_type, content) = read_original_object(repo, hash)?;
+            types[type_index(object_type)].set(position);
+            links.push(match object_type {
+                ObjectType::Blob => Vec::new(),
+                ObjectType::Tree => parse_tree(&content)?
+                    .into_iter()
+                    .filter(|entry| entry.mode != MODE_GITLINK)
+                    .map(|entry| entry.hash)
+                    .collect(),
+                ObjectType::Commit => {
+                    let commit = Commit::parse(&content)?;
+                    [vec![commit.tree], commit.parents].concat()
+                },
+                ObjectType::Tag => vec![Tag::parse(&content)?.object],
+            });
+        }
+
+        let mut bitmaps: HashMap<usize, Bitmap> = HashMap::new();
+        for commit in commits {
+            let start = *positions
+                .get(commit.as_str())
+                .context(format!("commit {commit} is not in the pack"))?;
+            let mut bitmap = Bitmap::default();
+            let mut pending = vec![start];
+            while let Some(position) = pending.pop() {
+                if bitmap.get(position) {
+                    continue;
+                }
+                // The objects reachable from commits already done are not walked again
+                if let Some(reachable) = bitmaps.get(&position) {
+                    bitmap.or(reachable);
+                    continue;
+                }
+                bitmap.set(position);
+                for hash in &links[position] {
+                    match positions.get(hash.as_str()) {
+                        Some(&position) => pending.push(position),
+                        None => anyhow::bail!(
+                            "pack does not have every object reachable from {commit} (missing {hash})"
+                        ),
+                    }
+                }
+            }
+            bitmaps.insert(start, bitmap);
+        }
+
+        let mut content = SIGNATURE.to_vec();
+        content.extend(VERSION.to_be_bytes());
+        content.extend(OPTION_FULL_DAG.to_be_bytes());
+        content.extend((commits.len() as u32).to_be_bytes());
+        content.extend(pack_checksum);
+        for bitmap in &types {
+            bitmap.write(&mut content);
+        }
+
+        let mut sorted: Vec<&String> = objects.iter().collect();
+        sorted.sort();
+        for commit in commits {
+            let index = sorted.binary_search(&commit).unwrap_or_default();
+            content.extend((index as u32).to_be_bytes());
+            // Not XORed with another entry, without flags
+            content.extend([0, 0]);
+            bitmaps[&positions[commit.as_str()]].write(&mut content);
+        }
+
+        let checksum = Sha1::digest(&content);
+        content.extend(checksum);
+        Ok(content)
+    }
+
+    #[test]
+    fn compresses_bitmaps() {
+        let mut bitmap = Bitmap::default();
+        bitmap.set(0);
+        bitmap.set(1);
+        let mut out = Vec::new();
+        bitmap.write(&mut out);
+        // 2 bits, a marker followed by one literal word, the last marker at 0
+        assert_eq!(
+            hex::decode(b"00000002000000020000000200000000000000000000000300000000").unwrap(),
+            out
+        );
+
+        // Runs of empty and full words between literal words
+        let mut bitmap = Bitmap::default();
+        for position in (70..80).chain(256..448).chain([1000]) {
+            bitmap.set(position);
+        }
+        let mut out = Vec::new();
+        bitmap.write(&mut out);
+        let read = Bitmap::read(&out, &mut 0).unwrap();
+        assert_eq!(
+            read.positions().collect::<Vec<_>>(),
+            bitmap.positions().collect::<Vec<_>>()
+        );
+        assert!(read.get(300) && !read.get(200));
+
+        let mut out = Vec::new();
+        Bitmap::default().write(&mut out);
+        assert_eq!(Bitmap::read(&out, &mut 0).unwrap().positions().count(), 0);
+    }
+
+    #[test]
+    fn finds_reachable_objects() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let mut index = Index::default();
+        let first_blob = write_object(&repo, ObjectType::Blob, b"first").unwrap();
+        index.add_entry(IndexEntry::new("a".into(), 0o100644, first_blob.clone()));
+        let first_tree = index.write_tree(&repo, false).unwrap();
+        let first = create_commit(&repo, &first_tree, Vec::new(), "first\n").unwrap();
+        let second_blob = write_object(&repo, ObjectType::Blob, b"second").unwrap();
+        index.add_entry(IndexEntry::new("b".into(), 0o100644, second_blob.clone()));
+        let second_tree = index.write_tree(&repo, false).unwrap();
+        let second = create_commit(&repo, &second_tree, vec![first.clone()], "second\n").unwrap();
+
+        let objects = vec![
+            second.clone(),
+            first.clone(),
+            second_tree.clone(),
+            first_tree.clone(),
+            first_blob.clone(),
+            second_blob.clone(),
+        ];
+        let mut pack = Vec::new();
+        write_pack(&repo, &objects, &mut pack).unwrap();
+        let checksum = &pack[pack.len() - HASH_LEN..];
+        let content =
+            write_bitmap(&repo, &objects, checksum, &[first.clone(), second.clone()]).unwrap();
+
+        let bitmap = PackBitmap::parse(&content, objects.clone(), checksum).unwrap();
+        let commits = bitmap.reachable(std::slice::from_ref(&second), &[], &[
+            ObjectType::Tree,
+            ObjectType::Blob,
+        ]);
+        assert_eq!(commits.unwrap(), [second.clone(), first.clone()]);
+        assert_eq!(
+            bitmap
+                .reachable(std::slice::from_ref(&second), &[], &[])
+                .unwrap(),
+            objects
+        );
+        assert_eq!(
+            bitmap
+                .reachable(
+                    std::slice::from_ref(&second),
+                    std::slice::from_ref(&first),
+                    &[]
+                )
+                .unwrap(),
+            [second.clone(), second_tree.clone(), second_blob.clone()]
+        );
+        assert_eq!(bitmap.reachable(&[first_tree], &[], &[]), None);
+
+        // upload-pack lists the objects to send with the bitmaps of a pack, in its order
+        let mut builder = PackBuilder::new();
+        for hash in &objects {
+            let (object_type, content) = read_original_object(&repo, hash).unwrap();
+            builder.add(object_type, &content).unwrap();
+        }
+        let (pack, index) = builder.finish();
+        let checksum = &pack[pack.len() - HASH_LEN..];
+        let mut name = checksum.to_vec();
+        hex::encode_in_place(&mut name);
+        let path = temp.join(format!(
+            ".git/objects/pack/pack-{}",
+            String::from_utf8(name).unwrap()
+        ));
+        fs::create_dir_all(temp.join(".git/objects/pack")).unwrap();
+        fs::write(path.with_extension("pack"), &pack).unwrap();
+        fs::write(path.with_extension("idx"), index).unwrap();
+        let wants = std::slice::from_ref(&second);
+        let send = |haves: &[String], filter: Option<&Filter>| {
+            let none = BTreeSet::new();
+            objects_to_send(&repo, wants, haves, filter, &none, &none).unwrap()
+        };
+        // Without bitmaps, the objects are walked from the commit
+        assert_eq!(send(&[], None)[..2], [second.clone(), second_tree.clone()]);
+        let bitmap = write_bitmap(&repo, &objects, checksum, wants);
+        fs::write(path.with_extension("bitmap"), bitmap.unwrap()).unwrap();
+        assert_eq!(send(&[], None), objects);
+        assert_eq!(send(&[], Some(&Filter::BlobNone)), objects[..4]);
+        // Commits without a bitmap are walked
+        assert_eq!(send(std::slice::from_ref(&first), None), [
+            second.clone(),
+            second_tree,
+            second_blob
+        ]);
+
+        // The objects reachable from a commit must all be in the pack
+        let err = write_bitmap(&repo, &objects[..5], checksum, &[second]).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("pack does not have every object"));
+        let err = PackBitmap::parse(&content, objects, &[0; HASH_LEN]).unwrap_err();
+        assert_eq!(err.to_string(), "bitmap does not match its pack");
+    }
+}
@@ -0,0 +1,189 @@
+//! Reading and writing bundles: a list of refs and the commits they require,
+//! followed by a pack of the objects, to move history without a transport
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::utils::objects::{object_exists, read_object, ObjectType};
+use crate::utils::refs::is_hash;
+use crate::utils::repository::Repository;
+
+/// The first line of the bundles written, which only support SHA-1
+const BUNDLE_V2_SIGNATURE: &str = "# v2 git bundle";
+
+/// The first line of bundles with capabilities (read if they only use SHA-1)
+const BUNDLE_V3_SIGNATURE: &str = "# v3 git bundle";
+
+/// Whether a file starts with the signature of a bundle, as bundles may be
+/// given where a repository is expected (e.g. to clone from).
+pub(crate) fn is_bundle(path: &Path) -> bool {
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let mut line = Vec::new();
+    let read = BufReader::new(file)
+        .take(BUNDLE_V2_SIGNATURE.len() as u64 + 1)
+        .read_until(b'\n', &mut line);
+    read.is_ok()
+        && [BUNDLE_V2_SIGNATURE, BUNDLE_V3_SIGNATURE]
+            .iter()
+            .any(|signature| line.strip_suffix(b"\n") == Some(signature.as_bytes()))
+}
+
+/// The refs and objects of a bundle file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Bundle {
+    /// The commits the receiving repository must have, with their subject line
+    pub(crate) prerequisites: Vec<(String, String)>,
+    /// The hash and full name of each ref
+    pub(crate) refs: Vec<(String, String)>,
+    /// The pack holding the objects, including its checksum
+    pub(crate) pack: Vec<u8>,
+}
+
+impl Bundle {
+    /// Read and parse a bundle file.
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read(path).context(format!("read {}", path.display()))?;
+        Bundle::parse(&content).context(format!(
+            "'{}' does not look like a v2 or v3 bundle file",
+            path.display()
+        ))
+    }
+
+    /// Parse the content of a bundle: a header of lines ended by an empty one, then the pack.
+    pub(crate) fn parse(content: &[u8]) -> anyhow::Result<Self> {
+        let mut lines = Vec::new();
+        let mut position = 0;
+        loop {
+            let end = content[position..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .context("truncated bundle header")?;
+            let line = std::str::from_utf8(&content[position..position + end])
+                .context("bundle header is not utf-8")?;
+            position += end + 1;
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line);
+        }
+
+        let mut lines = lines.into_iter();
+        match lines.next() {
+            Some(BUNDLE_V2_SIGNATURE | BUNDLE_V3_SIGNATURE) => {},
+            _ => anyhow::bail!("missing bundle signature"),
+        }
+
+        let mut prerequisites = Vec::new();
+        let mut refs = Vec::new();
+        for line in lines {
+            if let Some(capability) = line.strip_prefix('@') {
+                if capability != "object-format=sha1" {
+                    anyhow::bail!("unsupported bundle capability '{capability}'");
+                }
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                let (hash, comment) = prerequisite.split_once(' ').unwrap_or((prerequisite, ""));
+                if !is_hash(hash) {
+                    anyhow::bail!("invalid prerequisite '{line}'");
+                }
+                prerequisites.push((hash.to_string(), comment.to_string()));
+            } else {
+                match line.split_once(' ') {
+                    Some((hash, name)) if is_hash(hash) => {
+                        refs.push((hash.to_string(), name.to_string()));
+                    },
+                    _ => anyhow::bail!("invalid ref line '{line}'"),
+                }
+            }
+        }
+
+        Ok(Bundle {
+            prerequisites,
+            refs,
+            pack: content[position..].to_vec(),
+        })
+    }
+
+    /// Write the bundle in the v2 format.
+    pub(crate) fn write<W>(&self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        writeln!(writer, "{BUNDLE_V2_SIGNATURE}")?;
+        for (hash, comment) in &self.prerequisites {
+            writeln!(writer, "-{hash} {comment}")?;
+        }
+        for (hash, name) in &self.refs {
+            writeln!(writer, "{hash} {name}")?;
+        }
+        writeln!(writer)?;
+        writer.write_all(&self.pack)?;
+        Ok(())
+    }
+
+    /// List the prerequisites that are not commits of a repository.
+    pub(crate) fn missing_prerequisites(
+        &self,
+        repo: &Repository,
+    ) -> anyhow::Result<Vec<&(String, String)>> {
+        let mut missing = Vec::new();
+        for prerequisite in &self.prerequisites {
+            let hash = &prerequisite.0;
+            let exists =
+                object_exists(repo, hash)? && read_object(repo, hash)?.0 == ObjectType::Commit;
+            if !exists {
+                missing.push(prerequisite);
+            }
+        }
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn parses_bundles() {
+        let content = format!(
+            "# v3 git bundle\n@object-format=sha1\n-{} base commit\n{} refs/heads/main\n\nPACK",
+            "a".repeat(40),
+            "b".repeat(40)
+        );
+        let bundle = Bundle::parse(content.as_bytes()).unwrap();
+        assert_eq!(bundle, Bundle {
+            prerequisites: vec![("a".repeat(40), "base commit".to_string())],
+            refs: vec![("b".repeat(40), "refs/heads/main".to_string())],
+            pack: b"PACK".to_vec(),
+        });
+
+        let mut written = Vec::new();
+        bundle.write(&mut written).unwrap();
+        assert!(written.starts_with(b"# v2 git bundle\n-aaaa"));
+        assert_eq!(Bundle::parse(&written).unwrap(), bundle);
+
+        let content = content.replace("sha1", "sha256");
+        let err = Bundle::parse(content.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported bundle capability 'object-format=sha256'"
+        );
+        assert!(Bundle::parse(b"not a bundle\n\n").is_err());
+    }
+
+    #[test]
+    fn recognizes_bundle_files() {
+        let temp = TempRepo::new();
+        let path = temp.join("repo.bundle");
+        std::fs::write(&path, "# v2 git bundle\n\nPACK").unwrap();
+        assert!(is_bundle(&path));
+        std::fs::write(&path, "# v2 git bundles\n\nPACK").unwrap();
+        assert!(!is_bundle(&path));
+        assert!(!is_bundle(&temp.join("")));
+        assert!(!is_bundle(&temp.join("missing")));
+    }
+}
@@ -0,0 +1,596 @@
+//! Materializing trees in a working tree
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::utils::convert::Conversion;
+use crate::utils::index::{is_valid_path, Index, IndexEntry, Stat};
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::path_from_bytes;
+use crate::utils::repository::Repository;
+use crate::utils::sparse::Sparse;
+use crate::utils::tree::{
+    flatten_tree_checked, TreeEntry, MODE_EXECUTABLE, MODE_GITLINK, MODE_SYMLINK,
+};
+use crate::utils::work_tree::list_files;
+
+/// Write every file of a tree into a directory. Nothing is written if the tree
+/// has an invalid path (see [`flatten_tree_checked`]).
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read the objects from
+/// * `tree` - The hash of the tree to check out
+/// * `work_tree` - The directory to write the files to
+///
+/// # Returns
+///
+/// The checked out entries, with their full paths as names
+pub(crate) fn checkout_tree(
+    repo: &Repository,
+    tree: &str,
+    work_tree: &Path,
+) -> anyhow::Result<Vec<TreeEntry>> {
+    let entries = flatten_tree_checked(repo, tree)?;
+    let mut conversion = Conversion::load_tree(repo, tree)?;
+
+    for entry in &entries {
+        checkout_entry(repo, entry, work_tree, &mut conversion)?;
+    }
+
+    Ok(entries)
+}
+
+/// Move the working tree and the index from one tree to another,
+/// only touching the paths that differ between them. The files outside of
+/// a sparse checkout are only updated in the index.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read the objects from
+/// * `index` - The index to update, expected to match the old tree
+/// * `work_tree` - The root of the working tree
+/// * `old` - The hash of the tree currently checked out
+/// * `new` - The hash of the tree to check out
+pub(crate) fn switch_tree(
+    repo: &Repository,
+    index: &mut Index,
+    work_tree: &Path,
+    old: &str,
+    new: &str,
+) -> anyhow::Result<()> {
+    let by_path = |tree| -> anyhow::Result<BTreeMap<Vec<u8>, TreeEntry>> {
+        Ok(flatten_tree_checked(repo, tree)?
+            .into_iter()
+            .map(|entry| (entry.name.clone(), entry))
+            .collect())
+    };
+    let mut conversion = Conversion::load_tree(repo, new)?;
+    let old = by_path(old)?;
+    let new = by_path(new)?;
+
+    // Removed files go first, so that directories can replace them
+    for name in old.keys().filter(|name| !new.contains_key(*name)) {
+        let full_path = work_tree.join(path_from_bytes(name));
+        if full_path.symlink_metadata().is_ok() {
+            remove_path(&full_path)?;
+        }
+        remove_empty_parents(&full_path, work_tree);
+        index.remove_entry(name);
+    }
+
+    let sparse = Sparse::load(repo)?;
+    for (name, entry) in &new {
+        if old.get(name) == Some(entry) {
+            continue;
+        }
+        update_entry(
+            repo,
+            index,
+            work_tree,
+            entry,
+            &mut conversion,
+            sparse.as_ref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Make the index and the working tree match a tree, discarding local changes:
+/// the files that differ from the tree are checked out again, and the tracked
+/// files missing from it are removed. Untracked files are left alone, unless
+/// they are in the way of a file of the tree.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read the objects from
+/// * `index` - The index to update, which may have conflicts
+/// * `work_tree` - The root of the working tree
+/// * `tree` - The hash of the tree to check out
+pub(crate) fn reset_tree(
+    repo: &Repository,
+    index: &mut Index,
+    work_tree: &Path,
+    tree: &str,
+) -> anyhow::Result<()> {
+    let mut conversion = Conversion::load_tree(repo, tree)?;
+    let entries = tree_paths(repo, tree)?;
+    let sparse = Sparse::load(repo)?;
+
+    let mut removed: Vec<Vec<u8>> = index
+        .entries()
+        .iter()
+        .filter(|entry| !entries.contains_key(&entry.path))
+        .map(|entry| entry.path.clone())
+        .collect();
+    removed.dedup();
+    for path in removed {
+        let full_path = work_tree.join(path_from_bytes(&path));
+        if full_path.symlink_metadata().is_ok() {
+            remove_path(&full_path)?;
+        }
+        remove_empty_parents(&full_path, work_tree);
+        index.remove_entry(&path);
+    }
+
+    for (path, entry) in &entries {
+        let current = index
+            .entry(path, 0)
+            .filter(|current| current.mode == entry.mode && current.hash == entry.hash);
+        if let Some(current) = current {
+            if !current.intent_to_add && !index.is_modified(current, work_tree)? {
+                continue;
+            }
+        }
+
+        update_entry(
+            repo,
+            index,
+            work_tree,
+            entry,
+            &mut conversion,
+            sparse.as_ref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Check out a file of a tree and record it in the index, or only record it
+/// (with the skip-worktree bit) if it is outside of the sparse checkout.
+fn update_entry(
+    repo: &Repository,
+    index: &mut Index,
+    work_tree: &Path,
+    entry: &TreeEntry,
+    conversion: &mut Conversion,
+    sparse: Option<&Sparse>,
+) -> anyhow::Result<()> {
+    let full_path = work_tree.join(path_from_bytes(&entry.name));
+    let mut index_entry = IndexEntry::new(entry.name.clone(), entry.mode, entry.hash.clone());
+
+    if sparse.is_some_and(|sparse| !sparse.includes(&entry.name)) {
+        if full_path.symlink_metadata().is_ok() {
+            remove_path(&full_path)?;
+            remove_empty_parents(&full_path, work_tree);
+        }
+        index_entry.skip_worktree = true;
+    } else {
+        checkout_entry(repo, entry, work_tree, conversion)?;
+        if entry.mode != MODE_GITLINK {
+            index_entry.stat = Stat::from_metadata(&full_path.symlink_metadata()?);
+        }
+    }
+
+    index.add_entry(index_entry);
+    Ok(())
+}
+
+/// List the local changes against a tree, staged or not, as `git checkout` shows
+/// them after switching: `A` for the files only in the index, `D` for the files
+/// deleted from the index or the working tree, and `M` for the modified files.
+///
+/// # Returns
+///
+/// The status letter and path of each changed file, sorted by path
+pub(crate) fn list_local_changes(
+    repo: &Repository,
+    index: &Index,
+    work_tree: &Path,
+    tree: &str,
+) -> anyhow::Result<Vec<(char, Vec<u8>)>> {
+    let entries = tree_paths(repo, tree)?;
+    let mut changes = BTreeMap::new();
+
+    for entry in index.entries().iter().filter(|entry| entry.stage == 0) {
+        let status = match entries.get(&entry.path) {
+            None => 'A',
+            Some(_)
+                if !entry.skip_worktree
+                    && work_tree
+                        .join(path_from_bytes(&entry.path))
+                        .symlink_metadata()
+                        .is_err() =>
+            {
+                'D'
+            },
+            Some(staged) if staged.mode != entry.mode || staged.hash != entry.hash => 'M',
+            Some(_) if index.is_modified(entry, work_tree)? => 'M',
+            Some(_) => continue,
+        };
+        changes.insert(entry.path.clone(), status);
+    }
+    for path in entries.keys().filter(|path| !index.contains(path)) {
+        changes.insert(path.clone(), 'D');
+    }
+    // Paths left with conflicts (by `switch --merge`) are modified
+    for entry in index.entries().iter().filter(|entry| entry.stage != 0) {
+        changes.insert(entry.path.clone(), 'M');
+    }
+
+    Ok(changes
+        .into_iter()
+        .map(|(path, status)| (status, path))
+        .collect())
+}
+
+/// The operation updating the working tree, as named in its error messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Checkout,
+    Merge,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Checkout => "checkout",
+            Operation::Merge => "merge",
+        }
+    }
+
+    /// What the user is told to do after dealing with their changes
+    fn action(self) -> &'static str {
+        match self {
+            Operation::Checkout => "switch branches",
+            Operation::Merge => "merge",
+        }
+    }
+}
+
+/// Refuse to move the working tree from one tree to another if it would lose
+/// local changes: staged or unstaged changes to the files that differ between the
+/// trees, untracked files in the way of the files added (or of their directories),
+/// or untracked files in directories replaced by files.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read the trees from
+/// * `index` - The current index
+/// * `work_tree` - The root of the working tree
+/// * `old` - The hash of the tree currently checked out
+/// * `new` - The hash of the tree to check out
+/// * `operation` - The operation to name in the error messages
+pub(crate) fn check_overwritten(
+    repo: &Repository,
+    index: &Index,
+    work_tree: &Path,
+    old: &str,
+    new: &str,
+    operation: Operation,
+) -> anyhow::Result<()> {
+    let old = tree_paths(repo, old)?;
+    let new = tree_paths(repo, new)?;
+    let changed = new
+        .iter()
+        .filter(|(path, entry)| old.get(*path) != Some(*entry))
+        .map(|(path, _)| path)
+        .chain(old.keys().filter(|path| !new.contains_key(*path)));
+
+    let mut modified = BTreeSet::new();
+    let mut untracked = BTreeSet::new();
+    let mut directories = BTreeSet::new();
+    for path in changed {
+        // Local changes are staged if the index no longer matches the old tree
+        let staged = index.entry(path, 0);
+        let is_staged = staged.map(|entry| (entry.mode, &entry.hash))
+            != old.get(path).map(|entry| (entry.mode, &entry.hash));
+        match staged {
+            _ if is_staged => {
+                modified.insert(String::from_utf8_lossy(path));
+            },
+            Some(staged) if index.is_modified(staged, work_tree)? => {
+                modified.insert(String::from_utf8_lossy(path));
+            },
+            // An added file, which untracked files may be in the way of
+            None => {
+                if let Some(file) = untracked_in_the_way(index, work_tree, path) {
+                    untracked.insert(String::from_utf8_lossy(file));
+                } else if has_untracked_files(index, work_tree, path)? {
+                    directories.insert(String::from_utf8_lossy(path));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if !modified.is_empty() {
+        anyhow::bail!(
+            "Your local changes to the following files would be overwritten by {}:\n\t{}\n\
+             Please commit your changes or stash them before you {}.\nAborting",
+            operation.name(),
+            modified.into_iter().collect::<Vec<_>>().join("\n\t"),
+            operation.action()
+        );
+    }
+    if !untracked.is_empty() {
+        anyhow::bail!(
+            "The following untracked working tree files would be overwritten by {}:\n\t{}\n\
+             Please move or remove them before you {}.\nAborting",
+            operation.name(),
+            untracked.into_iter().collect::<Vec<_>>().join("\n\t"),
+            operation.action()
+        );
+    }
+    if !directories.is_empty() {
+        anyhow::bail!(
+            "Updating the following directories would lose untracked files in them:\n\t{}\n\n\
+             Aborting",
+            directories.into_iter().collect::<Vec<_>>().join("\n\t")
+        );
+    }
+    Ok(())
+}
+
+/// Find the untracked file in the way of writing a file: either at its path, or
+/// at the path of one of its directories.
+fn untracked_in_the_way<'a>(index: &Index, work_tree: &Path, path: &'a [u8]) -> Option<&'a [u8]> {
+    let directories = path
+        .iter()
+        .enumerate()
+        .filter(|(_, &byte)| byte == b'/')
+        .map(|(slash, _)| &path[..slash]);
+
+    for directory in directories {
+        let metadata = work_tree
+            .join(path_from_bytes(directory))
+            .symlink_metadata()
+            .ok()?;
+        if !metadata.is_dir() {
+            // A tracked file is removed before its path becomes a directory
+            return (!index.contains(directory)).then_some(directory);
+        }
+    }
+
+    let metadata = work_tree.join(path_from_bytes(path)).symlink_metadata();
+    metadata
+        .is_ok_and(|metadata| !metadata.is_dir())
+        .then_some(path)
+}
+
+/// Whether there is a directory at the path of a file, containing untracked files
+/// that writing the file would remove.
+fn has_untracked_files(index: &Index, work_tree: &Path, path: &[u8]) -> anyhow::Result<bool> {
+    let directory = work_tree.join(path_from_bytes(path));
+    if !directory
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Ok(false);
+    }
+
+    Ok(list_files(&directory, None)?
+        .iter()
+        .any(|file| !index.contains(&[path, b"/", &file.path].concat())))
+}
+
+/// The files of a tree by their path, failing if one of them is invalid (see
+/// [`flatten_tree_checked`]).
+pub(crate) fn tree_paths(
+    repo: &Repository,
+    tree: &str,
+) -> anyhow::Result<BTreeMap<Vec<u8>, TreeEntry>> {
+    Ok(flatten_tree_checked(repo, tree)?
+        .into_iter()
+        .map(|entry| (entry.name.clone(), entry))
+        .collect())
+}
+
+/// Make the index match a tree, without touching the working tree.
+/// Entries that do not change keep their cached metadata.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read the tree from
+/// * `index` - The index to update
+/// * `tree` - The hash of the tree to match
+pub(crate) fn reset_index(repo: &Repository, index: &mut Index, tree: &str) -> anyhow::Result<()> {
+    let entries = tree_paths(repo, tree)?;
+
+    let removed: Vec<_> = index
+        .entries()
+        .iter()
+        .filter(|entry| !entries.contains_key(&entry.path))
+        .map(|entry| entry.path.clone())
+        .collect();
+    for path in removed {
+        index.remove_entry(&path);
+    }
+
+    // The files outside of a sparse checkout stay out of the working tree
+    let sparse = Sparse::load(repo)?;
+    for (path, entry) in entries {
+        let unchanged = index.entry(&path, 0).is_some_and(|existing| {
+            existing.mode == entry.mode && existing.hash == entry.hash && !existing.intent_to_add
+        });
+        if !unchanged {
+            let mut index_entry = IndexEntry::new(path, entry.mode, entry.hash);
+            index_entry.skip_worktree = sparse
+                .as_ref()
+                .is_some_and(|sparse| !sparse.includes(&index_entry.path));
+            index.add_entry(index_entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the directories containing a removed path that became empty,
+/// up to the root of the working tree.
+pub(crate) fn remove_empty_parents(path: &Path, work_tree: &Path) {
+    let mut directory = path.parent();
+    while let Some(path) = directory.filter(|path| *path != work_tree) {
+        // Removing a directory fails if it is not empty
+        if std::fs::remove_dir(path).is_err() {
+            break;
+        }
+        directory = path.parent();
+    }
+}
+
+/// Write a single (flattened) tree entry into a directory, converting the
+/// line endings of files as configured. Invalid paths (e.g. with a `..` or
+/// `.git` component) are refused.
+pub(crate) fn checkout_entry(
+    repo: &Repository,
+    entry: &TreeEntry,
+    work_tree: &Path,
+    conversion: &mut Conversion,
+) -> anyhow::Result<()> {
+    if !is_valid_path(&entry.name) {
+        anyhow::bail!("invalid path '{}'", String::from_utf8_lossy(&entry.name));
+    }
+    let path = work_tree.join(path_from_bytes(&entry.name));
+
+    // Submodules are represented by an empty directory
+    if entry.mode == MODE_GITLINK {
+        return std::fs::create_dir_all(&path).context(format!("create {}", path.display()));
+    }
+
+    let mut content = read_object_of_type(repo, &entry.hash, ObjectType::Blob)?;
+    if entry.mode != MODE_SYMLINK {
+        content =
+            conversion.convert_to_work_tree(&String::from_utf8_lossy(&entry.name), content)?;
+    }
+    write_file(&path, entry.mode, &content)
+}
+
+/// Write a file (or the target of a symlink) of the given mode into the working
+/// tree, replacing whatever is currently at the path.
+pub(crate) fn write_file(path: &Path, mode: u32, content: &[u8]) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context(format!("create {}", parent.display()))?;
+    }
+
+    if path.symlink_metadata().is_ok() {
+        remove_path(path)?;
+    }
+
+    if mode == MODE_SYMLINK {
+        return write_symlink(content, path);
+    }
+
+    std::fs::write(path, content).context(format!("write {}", path.display()))?;
+    set_executable(path, mode == MODE_EXECUTABLE)
+}
+
+/// Remove a file, symlink or directory.
+pub(crate) fn remove_path(path: &Path) -> anyhow::Result<()> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+    .context(format!("remove {}", path.display()))
+}
+
+#[cfg(unix)]
+fn write_symlink(target: &[u8], path: &Path) -> anyhow::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let target = std::ffi::OsStr::from_bytes(target);
+    std::os::unix::fs::symlink(target, path).context(format!("symlink {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn write_symlink(target: &[u8], path: &Path) -> anyhow::Result<()> {
+    // Without symlink support, the link target is written as a plain file
+    std::fs::write(path, target).context(format!("write {}", path.display()))
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = if executable { 0o755 } else { 0o644 };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .context(format!("set permissions of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path, _executable: bool) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::checkout_tree;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::repository::Repository;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::{serialize_tree, TreeEntry, MODE_EXECUTABLE, MODE_FILE, MODE_TREE};
+
+    /// Write a tree as is, as a crafted repository would have it
+    fn write_tree(repo: &Repository, entries: &[(u32, &[u8], &str)]) -> String {
+        let entries = entries
+            .iter()
+            .map(|(mode, name, hash)| TreeEntry {
+                mode: *mode,
+                name: name.to_vec(),
+                hash: hash.to_string(),
+            })
+            .collect();
+        let content = serialize_tree(entries).unwrap();
+        write_object(repo, ObjectType::Tree, &content).unwrap()
+    }
+
+    #[test]
+    fn refuses_paths_outside_of_the_work_tree() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join("repo/.git/objects")).unwrap();
+        let repo = temp.repo_at("repo");
+        let work_tree = temp.join("repo");
+
+        let blob = write_object(&repo, ObjectType::Blob, b"#!/bin/sh\n").unwrap();
+        let hooks = write_tree(&repo, &[(MODE_EXECUTABLE, b"pre-commit", &blob)]);
+        let git_dir = write_tree(&repo, &[(MODE_TREE, b"hooks", &hooks)]);
+        let escaped = write_tree(&repo, &[(MODE_FILE, b"escaped.txt", &blob)]);
+        let nested = write_tree(&repo, &[(MODE_TREE, b".Git", &git_dir)]);
+        for (name, tree, invalid) in [
+            (&b".."[..], &escaped, ".."),
+            (b".", &escaped, "."),
+            (b"", &escaped, ""),
+            (b"../..", &escaped, "../.."),
+            (b".git", &git_dir, ".git"),
+            (b"dir", &nested, "dir/.Git"),
+        ] {
+            let root = write_tree(&repo, &[
+                (MODE_FILE, b"a.txt", &blob),
+                (MODE_TREE, name, tree),
+            ]);
+            let err = checkout_tree(&repo, &root, &work_tree).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid path '{invalid}'"));
+        }
+
+        // Nothing is written, even the valid files
+        assert!(!temp.join("escaped.txt").exists());
+        assert!(!temp.join("repo/.git/hooks").exists());
+        assert!(!temp.join("repo/a.txt").exists());
+    }
+}
@@ -0,0 +1,190 @@
+//! Colors of the output, as ANSI escape sequences
+//!
+//! A color is configured (e.g. in `color.decorate.<slot>`) as up to two colors,
+//! the foreground then the background, and any number of attributes, separated
+//! by spaces. Colors are names (`normal`, `default`, `black`, `red`, `green`,
+//! `yellow`, `blue`, `magenta`, `cyan` and `white`, the last eight optionally
+//! prefixed with `bright`), numbers from 0 to 255, or `#rrggbb`. Attributes are
+//! `bold`, `dim`, `italic`, `ul`, `blink`, `reverse` and `strike`, turned off
+//! instead when prefixed with `no` or `no-`.
+
+use std::io::IsTerminal;
+
+use crate::utils::config::{parse_bool, Config};
+use crate::utils::env;
+
+/// Reset the colors and attributes
+pub(crate) const RESET: &str = "\x1b[m";
+
+const COLORS: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+const ATTRIBUTES: [(&str, u8); 7] = [
+    ("bold", 1),
+    ("dim", 2),
+    ("italic", 3),
+    ("ul", 4),
+    ("blink", 5),
+    ("reverse", 7),
+    ("strike", 9),
+];
+
+/// Whether to color the output, from the `--color` option of the command if
+/// given (`always`, `never` or `auto`), or else from the configuration key of
+/// the command or `color.ui`. In `auto` mode (the default), the output is
+/// colored if it goes to a terminal.
+pub(crate) fn use_color(config: &Config, key: &str, when: Option<&str>) -> anyhow::Result<bool> {
+    let auto = || std::io::stdout().is_terminal() && config.env().var(env::TERM) != Some("dumb");
+    if let Some(when) = when {
+        return match when {
+            "always" => Ok(true),
+            "never" => Ok(false),
+            "auto" => Ok(auto()),
+            _ => anyhow::bail!("invalid --color option: {when}"),
+        };
+    }
+
+    let Some(value) = config.get(key).or_else(|| config.get("color.ui")) else {
+        return Ok(auto());
+    };
+    match value.to_ascii_lowercase().as_str() {
+        "always" => Ok(true),
+        "never" => Ok(false),
+        "auto" => Ok(auto()),
+        // `true` only colors the output of terminals
+        value => Ok(parse_bool(value)? && auto()),
+    }
+}
+
+/// The color set with a configuration key, or else a default one, as an escape
+/// sequence.
+pub(crate) fn config_color(config: &Config, key: &str, default: &str) -> anyhow::Result<String> {
+    let value = config.get(key).unwrap_or(default);
+    parse_color(value).map_err(|err| err.context(format!("unable to parse '{key}'")))
+}
+
+/// Parse a color into the escape sequence setting it (empty if it sets nothing).
+pub(crate) fn parse_color(value: &str) -> anyhow::Result<String> {
+    let invalid = || anyhow::anyhow!("invalid color value: {value}");
+    let mut attributes = Vec::new();
+    let mut colors: Vec<Option<Color>> = Vec::new();
+
+    for word in value.split_whitespace() {
+        let word = word.to_ascii_lowercase();
+        if let Some(color) = Color::parse(&word) {
+            if colors.len() == 2 {
+                return Err(invalid());
+            }
+            colors.push(color);
+            continue;
+        }
+
+        let (negated, name) = match word.strip_prefix("no") {
+            Some(name) => (true, name.strip_prefix('-').unwrap_or(name)),
+            None => (false, word.as_str()),
+        };
+        let Some(&(_, code)) = ATTRIBUTES.iter().find(|(attribute, _)| *attribute == name) else {
+            return Err(invalid());
+        };
+        // Bold and dim are both turned off by 22
+        let code = match (negated, code) {
+            (false, code) => code,
+            (true, 1) => 22,
+            (true, code) => code + 20,
+        };
+        if !attributes.contains(&code) {
+            attributes.push(code);
+        }
+    }
+
+    attributes.sort_unstable();
+    let mut codes: Vec<String> = attributes.iter().map(u8::to_string).collect();
+    for (color, background) in colors.into_iter().zip([false, true]) {
+        if let Some(color) = color {
+            codes.push(color.code(background));
+        }
+    }
+    match codes.is_empty() {
+        true => Ok(String::new()),
+        false => Ok(format!("\x1b[{}m", codes.join(";"))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Default,
+    /// One of the eight colors, or of their bright variants
+    Ansi(u8, bool),
+    /// One of the 256 colors
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Parse a color, `Some(None)` being `normal` (which leaves it unchanged).
+    fn parse(word: &str) -> Option<Option<Color>> {
+        match word {
+            "normal" | "-1" => return Some(None),
+            "default" => return Some(Some(Color::Default)),
+            _ => {},
+        }
+        let (bright, name) = match word.strip_prefix("bright") {
+            Some(name) => (true, name),
+            None => (false, word),
+        };
+        if let Some(index) = COLORS.iter().position(|color| *color == name) {
+            return Some(Some(Color::Ansi(index as u8, bright)));
+        }
+
+        if let Some(hex) = word.strip_prefix('#') {
+            if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?)));
+        }
+        match word.parse::<u8>().ok()? {
+            index @ 0..=7 => Some(Some(Color::Ansi(index, false))),
+            index => Some(Some(Color::Indexed(index))),
+        }
+    }
+
+    fn code(self, background: bool) -> String {
+        let base = if background { 40 } else { 30 };
+        match self {
+            Color::Default => (base + 9).to_string(),
+            Color::Ansi(index, false) => (base + index).to_string(),
+            Color::Ansi(index, true) => (base + 60 + index).to_string(),
+            Color::Indexed(index) => format!("{};5;{index}", base + 8),
+            Color::Rgb(r, g, b) => format!("{};2;{r};{g};{b}", base + 8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("bold green").unwrap(), "\x1b[1;32m");
+        assert_eq!(parse_color("red reverse").unwrap(), "\x1b[7;31m");
+        assert_eq!(
+            parse_color("#ff0000 bold ul 200").unwrap(),
+            "\x1b[1;4;38;2;255;0;0;48;5;200m"
+        );
+        assert_eq!(parse_color("normal brightblue").unwrap(), "\x1b[104m");
+        assert_eq!(parse_color("no-bold noitalic 3").unwrap(), "\x1b[22;23;33m");
+        assert_eq!(parse_color("normal").unwrap(), "");
+        assert_eq!(parse_color("").unwrap(), "");
+
+        assert_eq!(
+            parse_color("bogus").unwrap_err().to_string(),
+            "invalid color value: bogus"
+        );
+        assert!(parse_color("red green blue").is_err());
+        assert!(parse_color("#ff00").is_err());
+        assert!(parse_color("256").is_err());
+    }
+}
@@ -0,0 +1,646 @@
+//! Utilities for working with commit and tag objects
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use crate::utils::config::Config;
+use crate::utils::date::parse_date;
+use crate::utils::env;
+use crate::utils::objects::{read_object, read_object_of_type, write_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::signature::sign_payload;
+
+/// A parsed commit object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Commit {
+    /// The hash of the root tree
+    pub(crate) tree: String,
+    /// The hashes of the parent commits
+    pub(crate) parents: Vec<String>,
+    /// The author line (`Name <email> timestamp offset`)
+    pub(crate) author: Vec<u8>,
+    /// The committer line (`Name <email> timestamp offset`)
+    pub(crate) committer: Vec<u8>,
+    /// Any other headers (e.g. `encoding`, `gpgsig`), in order of appearance
+    pub(crate) extra_headers: Vec<(String, Vec<u8>)>,
+    /// The commit message
+    pub(crate) message: Vec<u8>,
+}
+
+impl Commit {
+    /// Parse the content of a commit object.
+    pub(crate) fn parse(content: &[u8]) -> anyhow::Result<Self> {
+        let (headers, message) = parse_headers(content)?;
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+
+        for (name, value) in headers {
+            match name.as_str() {
+                "tree" if tree.is_none() => tree = Some(parse_hash(&value)?),
+                "parent" => parents.push(parse_hash(&value)?),
+                "author" if author.is_none() => author = Some(value),
+                "committer" if committer.is_none() => committer = Some(value),
+                _ => extra_headers.push((name, value)),
+            }
+        }
+
+        Ok(Commit {
+            tree: tree.context("commit is missing a tree")?,
+            parents,
+            author: author.context("commit is missing an author")?,
+            committer: committer.context("commit is missing a committer")?,
+            extra_headers,
+            message,
+        })
+    }
+
+    /// Serialize the content of the commit object, the inverse of [`Commit::parse`].
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut content = format!("tree {}\n", self.tree).into_bytes();
+        for parent in &self.parents {
+            content.extend(format!("parent {parent}\n").as_bytes());
+        }
+        write_header(&mut content, "author", &self.author);
+        write_header(&mut content, "committer", &self.committer);
+        for (name, value) in &self.extra_headers {
+            write_header(&mut content, name, value);
+        }
+
+        content.push(b'\n');
+        content.extend(&self.message);
+        content
+    }
+
+    /// The subject of the commit: the first paragraph of the message,
+    /// with its lines joined by spaces.
+    pub(crate) fn subject(&self) -> String {
+        message_subject(&self.message)
+    }
+}
+
+/// The subject of a commit or tag message: its first paragraph, joined into one line.
+pub(crate) fn message_subject(message: &[u8]) -> String {
+    let message = String::from_utf8_lossy(message);
+    message
+        .trim_start_matches('\n')
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A parsed (annotated) tag object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Tag {
+    /// The hash of the tagged object
+    pub(crate) object: String,
+    /// The type of the tagged object
+    pub(crate) object_type: ObjectType,
+    /// The name of the tag
+    pub(crate) name: Vec<u8>,
+    /// The tagger line (`Name <email> timestamp offset`), if any
+    pub(crate) tagger: Option<Vec<u8>>,
+    /// The tag message
+    pub(crate) message: Vec<u8>,
+}
+
+impl Tag {
+    /// Parse the content of a tag object.
+    pub(crate) fn parse(content: &[u8]) -> anyhow::Result<Self> {
+        let (headers, message) = parse_headers(content)?;
+
+        let mut object = None;
+        let mut object_type = None;
+        let mut name = None;
+        let mut tagger = None;
+
+        for (header, value) in headers {
+            match header.as_str() {
+                "object" => object = Some(parse_hash(&value)?),
+                "type" => object_type = Some(ObjectType::try_from(value.as_slice())?),
+                "tag" => name = Some(value),
+                "tagger" => tagger = Some(value),
+                _ => {},
+            }
+        }
+
+        Ok(Tag {
+            object: object.context("tag is missing an object")?,
+            object_type: object_type.context("tag is missing a type")?,
+            name: name.context("tag is missing a name")?,
+            tagger,
+            message,
+        })
+    }
+
+    /// Serialize the content of the tag object, the inverse of [`Tag::parse`].
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut content =
+            format!("object {}\ntype {}\n", self.object, self.object_type).into_bytes();
+        write_header(&mut content, "tag", &self.name);
+        if let Some(tagger) = &self.tagger {
+            write_header(&mut content, "tagger", tagger);
+        }
+
+        content.push(b'\n');
+        content.extend(&self.message);
+        content
+    }
+}
+
+/// Whose identity is used for a new object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Author,
+    Committer,
+}
+
+/// A parsed author, committer or tagger line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Ident {
+    /// The name of the person
+    pub(crate) name: Vec<u8>,
+    /// The email address (without angle brackets)
+    pub(crate) email: Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub(crate) timestamp: i64,
+    /// The timezone offset as written (e.g. `+0100`)
+    pub(crate) offset: String,
+}
+
+impl Ident {
+    /// Parse an identity line (`Name <email> timestamp offset`).
+    pub(crate) fn parse(line: &[u8]) -> anyhow::Result<Self> {
+        let open = line
+            .iter()
+            .position(|&b| b == b'<')
+            .context("identity is missing an email")?;
+        let close = line[open..]
+            .iter()
+            .position(|&b| b == b'>')
+            .map(|end| open + end)
+            .context("identity email is not terminated")?;
+
+        let date = std::str::from_utf8(&line[close + 1..]).context("identity date is not utf-8")?;
+        let mut date = date.split_whitespace();
+        let timestamp = date
+            .next()
+            .context("identity is missing a timestamp")?
+            .parse()
+            .context("invalid identity timestamp")?;
+        let offset = date.next().unwrap_or("+0000").to_string();
+
+        Ok(Ident {
+            name: line[..open].trim_ascii_end().to_vec(),
+            email: line[open + 1..close].to_vec(),
+            timestamp,
+            offset,
+        })
+    }
+
+    /// The identity of the user creating a new object, from `$GIT_AUTHOR_NAME`,
+    /// `$GIT_AUTHOR_EMAIL` and `$GIT_AUTHOR_DATE` (or their `COMMITTER` counterparts),
+    /// falling back to `user.name`, `user.email` and the current time in UTC.
+    pub(crate) fn current(config: &Config, role: Role) -> anyhow::Result<Self> {
+        Self::resolve(config, role, true)
+    }
+
+    /// Like [`Ident::current`], but a missing name or email is not an error: they default
+    /// to the login name and `<login>@<hostname>`, as for logging ref updates.
+    pub(crate) fn current_or_default(config: &Config, role: Role) -> anyhow::Result<Self> {
+        Self::resolve(config, role, false)
+    }
+
+    fn resolve(config: &Config, role: Role, strict: bool) -> anyhow::Result<Self> {
+        let (name_var, email_var, date_var, label) = match role {
+            Role::Author => (
+                env::GIT_AUTHOR_NAME,
+                env::GIT_AUTHOR_EMAIL,
+                env::GIT_AUTHOR_DATE,
+                "Author",
+            ),
+            Role::Committer => (
+                env::GIT_COMMITTER_NAME,
+                env::GIT_COMMITTER_EMAIL,
+                env::GIT_COMMITTER_DATE,
+                "Committer",
+            ),
+        };
+
+        let env = config.env();
+        let lookup = |var: &str, key: &str| {
+            env.var(var)
+                .or_else(|| config.get(key))
+                .map(str::to_string)
+                .filter(|value| !value.trim().is_empty())
+        };
+        let (mut name, mut email) = (
+            lookup(name_var, "user.name"),
+            lookup(email_var, "user.email"),
+        );
+        if !strict {
+            let login = env
+                .var("USER")
+                .or_else(|| env.var("LOGNAME"))
+                .unwrap_or("unknown")
+                .to_string();
+            let host = std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|host| host.trim().to_string())
+                .filter(|host| !host.is_empty())
+                .unwrap_or_else(|| "localhost".to_string());
+            email = email.or_else(|| Some(format!("{login}@{host}")));
+            name = name.or(Some(login));
+        }
+        let (Some(name), Some(email)) = (name, email) else {
+            anyhow::bail!(
+                "{label} identity unknown\n\n\
+                 *** Please tell me who you are.\n\n\
+                 Run\n\n  \
+                 git config user.email \"you@example.com\"\n  \
+                 git config user.name \"Your Name\""
+            );
+        };
+
+        let (timestamp, offset) = match env.var(date_var) {
+            Some(date) => parse_date(date).context(format!("invalid date format: {date}"))?,
+            None => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                (now.as_secs() as i64, "+0000".to_string())
+            },
+        };
+
+        Ok(Ident {
+            name: name.trim().as_bytes().to_vec(),
+            email: email.trim().trim_matches(['<', '>']).as_bytes().to_vec(),
+            timestamp,
+            offset,
+        })
+    }
+
+    /// Format the identity as an object header value (`Name <email> timestamp offset`).
+    pub(crate) fn format(&self) -> Vec<u8> {
+        let mut line = self.name.clone();
+        line.extend(b" <");
+        line.extend(&self.email);
+        line.extend(format!("> {} {}", self.timestamp, self.offset).as_bytes());
+        line
+    }
+}
+
+/// Create a commit object authored and committed by the current user.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to write the commit to
+/// * `tree` - The hash of the root tree
+/// * `parents` - The hashes of the parent commits
+/// * `message` - The commit message, which should end with a newline
+///
+/// # Returns
+///
+/// The hash of the commit
+pub(crate) fn create_commit(
+    repo: &Repository,
+    tree: &str,
+    parents: Vec<String>,
+    message: &str,
+) -> anyhow::Result<String> {
+    let config = Config::load(repo)?;
+    let commit = new_commit(&config, tree, parents, message)?;
+    write_object(repo, ObjectType::Commit, &commit.serialize())
+}
+
+/// Create a commit object like [`create_commit`], keeping the author of
+/// another commit (e.g. one being cherry-picked).
+///
+/// # Arguments
+///
+/// * `repo` - The repository to write the commit to
+/// * `tree` - The hash of the root tree
+/// * `parents` - The hashes of the parent commits
+/// * `message` - The commit message, which should end with a newline
+/// * `author` - The author line to keep (`Name <email> timestamp offset`)
+///
+/// # Returns
+///
+/// The hash of the commit
+pub(crate) fn create_commit_as(
+    repo: &Repository,
+    tree: &str,
+    parents: Vec<String>,
+    message: &str,
+    author: &[u8],
+) -> anyhow::Result<String> {
+    let config = Config::load(repo)?;
+    let mut commit = new_commit(&config, tree, parents, message)?;
+    commit.author = author.to_vec();
+    write_object(repo, ObjectType::Commit, &commit.serialize())
+}
+
+/// Create a commit object like [`create_commit`], signed in a `gpgsig` header.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to write the commit to
+/// * `tree` - The hash of the root tree
+/// * `parents` - The hashes of the parent commits
+/// * `message` - The commit message, which should end with a newline
+/// * `key` - The key to sign with, or `None` for the configured key
+///
+/// # Returns
+///
+/// The hash of the commit
+pub(crate) fn create_signed_commit(
+    repo: &Repository,
+    tree: &str,
+    parents: Vec<String>,
+    message: &str,
+    key: Option<&str>,
+) -> anyhow::Result<String> {
+    let config = Config::load(repo)?;
+    let mut commit = new_commit(&config, tree, parents, message)?;
+
+    // The signature covers the commit without the signature header
+    let signature = sign_payload(&config, &commit.serialize(), key)?;
+    let signature = signature.strip_suffix(b"\n").unwrap_or(&signature);
+    commit
+        .extra_headers
+        .push(("gpgsig".to_string(), signature.to_vec()));
+    write_object(repo, ObjectType::Commit, &commit.serialize())
+}
+
+fn new_commit(
+    config: &Config,
+    tree: &str,
+    parents: Vec<String>,
+    message: &str,
+) -> anyhow::Result<Commit> {
+    Ok(Commit {
+        tree: tree.to_string(),
+        parents,
+        author: Ident::current(config, Role::Author)?.format(),
+        committer: Ident::current(config, Role::Committer)?.format(),
+        extra_headers: Vec::new(),
+        message: message.as_bytes().to_vec(),
+    })
+}
+
+/// Create an annotated tag object, tagged by the current user.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to write the tag to
+/// * `object` - The hash of the tagged object
+/// * `name` - The name of the tag
+/// * `message` - The tag message, which should end with a newline
+///
+/// # Returns
+///
+/// The hash of the tag
+pub(crate) fn create_tag(
+    repo: &Repository,
+    object: &str,
+    name: &str,
+    message: &str,
+) -> anyhow::Result<String> {
+    let config = Config::load(repo)?;
+    let tag = new_tag(repo, &config, object, name, message)?;
+    write_object(repo, ObjectType::Tag, &tag.serialize())
+}
+
+/// Create an annotated tag object like [`create_tag`], with a signature appended to its message.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to write the tag to
+/// * `object` - The hash of the tagged object
+/// * `name` - The name of the tag
+/// * `message` - The tag message, which should end with a newline
+/// * `key` - The key to sign with, or `None` for the configured key
+///
+/// # Returns
+///
+/// The hash of the tag
+pub(crate) fn create_signed_tag(
+    repo: &Repository,
+    object: &str,
+    name: &str,
+    message: &str,
+    key: Option<&str>,
+) -> anyhow::Result<String> {
+    let config = Config::load(repo)?;
+    let mut content = new_tag(repo, &config, object, name, message)?.serialize();
+    let signature = sign_payload(&config, &content, key)?;
+    content.extend(signature);
+    write_object(repo, ObjectType::Tag, &content)
+}
+
+fn new_tag(
+    repo: &Repository,
+    config: &Config,
+    object: &str,
+    name: &str,
+    message: &str,
+) -> anyhow::Result<Tag> {
+    let (object_type, _) = read_object(repo, object)?;
+    Ok(Tag {
+        object: object.to_string(),
+        object_type,
+        name: name.as_bytes().to_vec(),
+        tagger: Some(Ident::current(config, Role::Committer)?.format()),
+        message: message.as_bytes().to_vec(),
+    })
+}
+
+/// Read and parse a commit object from the object database.
+pub(crate) fn read_commit(repo: &Repository, hash: &str) -> anyhow::Result<Commit> {
+    let content = read_object_of_type(repo, hash, ObjectType::Commit)?;
+    Commit::parse(&content).context(format!("parse commit {hash}"))
+}
+
+/// Follow tags until an object that is not a tag is reached.
+///
+/// # Returns
+///
+/// The hash and type of the peeled object
+pub(crate) fn peel(repo: &Repository, hash: &str) -> anyhow::Result<(String, ObjectType)> {
+    let mut hash = hash.to_string();
+
+    loop {
+        let (object_type, content) = read_object(repo, &hash)?;
+        if object_type != ObjectType::Tag {
+            return Ok((hash, object_type));
+        }
+        hash = Tag::parse(&content)?.object;
+    }
+}
+
+/// Peel an object to a commit, failing if it does not point to one.
+pub(crate) fn peel_to_commit(repo: &Repository, hash: &str) -> anyhow::Result<String> {
+    match peel(repo, hash)? {
+        (hash, ObjectType::Commit) => Ok(hash),
+        (hash, object_type) => anyhow::bail!("object {hash} is a {object_type}, not a commit"),
+    }
+}
+
+/// Peel an object to a tree. Commits are peeled to their root tree.
+pub(crate) fn peel_to_tree(repo: &Repository, hash: &str) -> anyhow::Result<String> {
+    match peel(repo, hash)? {
+        (hash, ObjectType::Tree) => Ok(hash),
+        (hash, ObjectType::Commit) => Ok(read_commit(repo, &hash)?.tree),
+        (hash, object_type) => anyhow::bail!("object {hash} is a {object_type}, not a tree"),
+    }
+}
+
+/// A header of a commit or tag object (name and value)
+type Header = (String, Vec<u8>);
+
+/// Split the headers of a commit or tag from its message.
+///
+/// Continuation lines (starting with a space) are appended to the value
+/// of the previous header, separated by a newline.
+fn parse_headers(content: &[u8]) -> anyhow::Result<(Vec<Header>, Vec<u8>)> {
+    let mut headers: Vec<Header> = Vec::new();
+    let mut rest = content;
+
+    loop {
+        let (line, remaining) = match rest.iter().position(|&b| b == b'\n') {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, &rest[rest.len()..]),
+        };
+        rest = remaining;
+
+        // An empty line separates the headers from the message
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers.last_mut().context("invalid header continuation")?;
+            value.push(b'\n');
+            value.extend(continuation);
+            continue;
+        }
+
+        let space = line
+            .iter()
+            .position(|&b| b == b' ')
+            .context("invalid object header")?;
+        let name = String::from_utf8(line[..space].to_vec()).context("header is not utf-8")?;
+        headers.push((name, line[space + 1..].to_vec()));
+
+        if rest.is_empty() {
+            break;
+        }
+    }
+
+    Ok((headers, rest.to_vec()))
+}
+
+/// Write a header of a commit or tag object, indenting the continuation lines of its value.
+fn write_header(content: &mut Vec<u8>, name: &str, value: &[u8]) {
+    content.extend(name.as_bytes());
+    content.push(b' ');
+    for &byte in value {
+        content.push(byte);
+        if byte == b'\n' {
+            content.push(b' ');
+        }
+    }
+    content.push(b'\n');
+}
+
+/// Validate a hex object hash from a header value.
+fn parse_hash(value: &[u8]) -> anyhow::Result<String> {
+    if value.len() != 40 || !value.iter().all(u8::is_ascii_hexdigit) {
+        anyhow::bail!("invalid object hash in header");
+    }
+    Ok(String::from_utf8(value.to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Commit, Ident, Tag};
+    use crate::utils::objects::ObjectType;
+
+    const TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    const PARENT: &str = "aabbccddeeff00112233445566778899aabbccdd";
+
+    #[test]
+    fn parses_commit() {
+        let content = format!(
+            "tree {TREE}\n\
+             parent {PARENT}\n\
+             author A U Thor <author@example.com> 1700000000 +0100\n\
+             committer C O Mitter <committer@example.com> 1700000001 -0500\n\
+             gpgsig -----BEGIN PGP SIGNATURE-----\n \n line\n -----END PGP SIGNATURE-----\n\
+             \n\
+             Subject\n\nBody\n"
+        );
+
+        let commit = Commit::parse(content.as_bytes()).unwrap();
+        assert_eq!(commit.tree, TREE);
+        assert_eq!(commit.parents, vec![PARENT.to_string()]);
+        assert_eq!(
+            commit.author,
+            b"A U Thor <author@example.com> 1700000000 +0100"
+        );
+        assert_eq!(commit.extra_headers.len(), 1);
+        assert_eq!(commit.extra_headers[0].0, "gpgsig");
+        assert_eq!(
+            commit.extra_headers[0].1,
+            b"-----BEGIN PGP SIGNATURE-----\n\nline\n-----END PGP SIGNATURE-----"
+        );
+        assert_eq!(commit.message, b"Subject\n\nBody\n");
+        assert_eq!(commit.subject(), "Subject");
+        assert_eq!(commit.serialize(), content.as_bytes());
+    }
+
+    #[test]
+    fn fails_on_commit_without_tree() {
+        let content = "author A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nmsg\n";
+        assert!(Commit::parse(content.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parses_tag() {
+        let content = format!(
+            "object {PARENT}\n\
+             type commit\n\
+             tag v1.0\n\
+             tagger T <t@example.com> 1700000000 +0000\n\
+             \n\
+             Release\n"
+        );
+
+        let tag = Tag::parse(content.as_bytes()).unwrap();
+        assert_eq!(tag.object, PARENT);
+        assert_eq!(tag.object_type, ObjectType::Commit);
+        assert_eq!(tag.name, b"v1.0");
+        assert_eq!(tag.message, b"Release\n");
+    }
+
+    #[test]
+    fn parses_ident() {
+        let ident = Ident::parse(b"A U Thor <author@example.com> 1700000000 +0100").unwrap();
+        assert_eq!(ident.name, b"A U Thor");
+        assert_eq!(ident.email, b"author@example.com");
+        assert_eq!(ident.timestamp, 1700000000);
+        assert_eq!(ident.offset, "+0100");
+
+        assert_eq!(
+            ident.format(),
+            b"A U Thor <author@example.com> 1700000000 +0100"
+        );
+
+        assert!(Ident::parse(b"A U Thor author@example.com 1700000000 +0100").is_err());
+        assert!(Ident::parse(b"A U Thor <author@example.com>").is_err());
+    }
+}
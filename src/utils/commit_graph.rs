@@ -0,0 +1,630 @@
+//! The commit-graph file (`objects/info/commit-graph`)
+//!
+//! It stores the tree, parents, committer date and generation number of
+//! commits, so that walking the history does not need to read and parse each
+//! commit object. The generation number of a commit is one more than the
+//! highest of its parents (1 for root commits), so a commit can only be an
+//! ancestor of the commits with a higher one.
+//!
+//! The file is ignored while grafts or replacements change the parents of
+//! commits, as it only records the parents stored in the objects.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::utils::commit::{read_commit, Commit, Ident};
+use crate::utils::config::Config;
+use crate::utils::grafts::read_shallow;
+use crate::utils::hex;
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::TempPath;
+
+const SIGNATURE: &[u8; 4] = b"CGPH";
+const VERSION: u8 = 1;
+/// The version of the hash function (SHA-1)
+const HASH_VERSION: u8 = 1;
+const HASH_LEN: usize = 20;
+
+const CHUNK_OID_FANOUT: &[u8; 4] = b"OIDF";
+const CHUNK_OID_LOOKUP: &[u8; 4] = b"OIDL";
+const CHUNK_COMMIT_DATA: &[u8; 4] = b"CDAT";
+const CHUNK_EXTRA_EDGES: &[u8; 4] = b"EDGE";
+
+/// The size of each commit in the commit data chunk: the tree, two parents,
+/// and the generation number and date packed in 64 bits
+const COMMIT_DATA_LEN: usize = HASH_LEN + 16;
+/// The parent position of a commit without that parent
+const PARENT_NONE: u32 = 0x7000_0000;
+/// Set on the second parent position when it is a position in the extra edges,
+/// and on the last parent of a commit in the extra edges
+const EDGE_FLAG: u32 = 0x8000_0000;
+
+/// The generation number of commits not in the commit-graph
+pub(crate) const GENERATION_INFINITY: u32 = u32::MAX;
+/// The highest generation number that can be stored (30 bits)
+const GENERATION_MAX: u32 = (1 << 30) - 1;
+
+/// A commit as recorded in the commit-graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GraphCommit {
+    /// The hash of the root tree
+    pub(crate) tree: String,
+    /// The hashes of the parents
+    pub(crate) parents: Vec<String>,
+    /// The committer date
+    pub(crate) timestamp: i64,
+    /// The generation number
+    pub(crate) generation: u32,
+}
+
+/// A parsed commit-graph file
+#[derive(Debug, Clone)]
+pub(crate) struct CommitGraph {
+    data: Vec<u8>,
+    /// The offset of each chunk
+    fanout: usize,
+    lookup: usize,
+    commit_data: usize,
+    extra_edges: Option<usize>,
+    /// The number of commits
+    len: usize,
+}
+
+impl CommitGraph {
+    /// The path of the commit-graph file of a repository.
+    pub(crate) fn path(repo: &Repository) -> anyhow::Result<PathBuf> {
+        Ok(repo.object_dir(false)?.join("info").join("commit-graph"))
+    }
+
+    /// Read the commit-graph of a repository, unless it has none or it cannot
+    /// be used (`core.commitGraph=false`, grafts, replacements or a shallow history).
+    pub(crate) fn read(repo: &Repository) -> anyhow::Result<Option<Self>> {
+        let path = CommitGraph::path(repo)?;
+        if !path.is_file() || !is_compatible(repo)? {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).context(format!("read {}", path.display()))?;
+        CommitGraph::parse(data).map(Some)
+    }
+
+    /// Parse the content of a commit-graph file, checking its header and chunks.
+    pub(crate) fn parse(data: Vec<u8>) -> anyhow::Result<Self> {
+        if data.len() < 8 + 12 + HASH_LEN {
+            anyhow::bail!("commit-graph file is too small");
+        }
+        if &data[..4] != SIGNATURE {
+            anyhow::bail!(
+                "commit-graph signature {:X} does not match signature {:X}",
+                u32::from_be_bytes(data[..4].try_into()?),
+                u32::from_be_bytes(*SIGNATURE)
+            );
+        }
+        if data[4] != VERSION {
+            anyhow::bail!(
+                "commit-graph version {:X} does not match version {VERSION:X}",
+                data[4]
+            );
+        }
+        if data[5] != HASH_VERSION {
+            anyhow::bail!(
+                "commit-graph hash version {:X} does not match version {HASH_VERSION:X}",
+                data[5]
+            );
+        }
+
+        // The table of contents ends with an entry pointing past the last chunk
+        let chunk_count = data[6] as usize;
+        let mut chunks: BTreeMap<[u8; 4], (usize, usize)> = BTreeMap::new();
+        let end = data.len() - HASH_LEN;
+        for index in 0..chunk_count {
+            let entry = 8 + index * 12;
+            let next = entry + 12;
+            if next + 12 > end {
+                anyhow::bail!(
+                    "commit-graph chunk lookup table entry missing; file may be incomplete"
+                );
+            }
+            let id: [u8; 4] = data[entry..entry + 4].try_into()?;
+            let offset = u64::from_be_bytes(data[entry + 4..next].try_into()?) as usize;
+            let next_offset = u64::from_be_bytes(data[next + 4..next + 12].try_into()?) as usize;
+            if offset > next_offset || next_offset > end {
+                anyhow::bail!("improper chunk offset(s) {offset:x} and {next_offset:x}");
+            }
+            chunks.insert(id, (offset, next_offset - offset));
+        }
+
+        let (fanout, fanout_len) = chunks
+            .get(CHUNK_OID_FANOUT)
+            .copied()
+            .filter(|&(_, len)| len == 256 * 4)
+            .context("commit-graph required OID fanout chunk missing or corrupted")?;
+        let len = u32::from_be_bytes(data[fanout + fanout_len - 4..fanout + fanout_len].try_into()?)
+            as usize;
+        let (lookup, _) = chunks
+            .get(CHUNK_OID_LOOKUP)
+            .copied()
+            .filter(|&(_, size)| size == len * HASH_LEN)
+            .context("commit-graph required OID lookup chunk missing or corrupted")?;
+        let (commit_data, _) = chunks
+            .get(CHUNK_COMMIT_DATA)
+            .copied()
+            .filter(|&(_, size)| size == len * COMMIT_DATA_LEN)
+            .context("commit-graph required commit data chunk missing or corrupted")?;
+        let extra_edges = chunks.get(CHUNK_EXTRA_EDGES).map(|&(offset, _)| offset);
+
+        Ok(CommitGraph {
+            data,
+            fanout,
+            lookup,
+            commit_data,
+            extra_edges,
+            len,
+        })
+    }
+
+    /// The hash of the commit at a position.
+    fn hash(&self, position: usize) -> String {
+        let start = self.lookup + position * HASH_LEN;
+        let mut hash = self.data[start..start + HASH_LEN].to_vec();
+        hex::encode_in_place(&mut hash);
+        String::from_utf8(hash).unwrap()
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Find the position of a commit, searching the commits sharing its first byte.
+    fn position(&self, hash: &str) -> Option<usize> {
+        let bytes = hex::decode(hash.as_bytes()).ok()?;
+        let first = *bytes.first()? as usize;
+        let start = match first {
+            0 => 0,
+            _ => self.read_u32(self.fanout + (first - 1) * 4) as usize,
+        };
+        let end = (self.read_u32(self.fanout + first * 4) as usize).min(self.len);
+        let (mut low, mut high) = (start.min(end), end);
+        while low < high {
+            let middle = (low + high) / 2;
+            let offset = self.lookup + middle * HASH_LEN;
+            match self.data[offset..offset + HASH_LEN].cmp(&bytes) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    /// Look a commit up in the commit-graph.
+    ///
+    /// # Returns
+    ///
+    /// The commit, or `None` if it is not in the commit-graph
+    pub(crate) fn commit(&self, hash: &str) -> anyhow::Result<Option<GraphCommit>> {
+        match self.position(hash) {
+            Some(position) => self.commit_at(position).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the commit at a position.
+    fn commit_at(&self, position: usize) -> anyhow::Result<GraphCommit> {
+        let offset = self.commit_data + position * COMMIT_DATA_LEN;
+        let mut tree = self.data[offset..offset + HASH_LEN].to_vec();
+        hex::encode_in_place(&mut tree);
+
+        let parent_hash = |parent: u32| -> anyhow::Result<String> {
+            let parent = parent as usize;
+            if parent >= self.len {
+                anyhow::bail!("invalid parent position {parent}");
+            }
+            Ok(self.hash(parent))
+        };
+
+        let mut parents = Vec::new();
+        let first = self.read_u32(offset + HASH_LEN);
+        let second = self.read_u32(offset + HASH_LEN + 4);
+        if first != PARENT_NONE {
+            parents.push(parent_hash(first)?);
+        }
+        if second & EDGE_FLAG != 0 {
+            // The other parents of octopus merges are listed in the extra edges
+            let edges = self
+                .extra_edges
+                .context("commit-graph has no extra edges chunk")?;
+            let mut edge = edges + (second & !EDGE_FLAG) as usize * 4;
+            loop {
+                if edge + 4 > self.data.len() - HASH_LEN {
+                    anyhow::bail!("commit-graph extra-edges pointer out of bounds");
+                }
+                let parent = self.read_u32(edge);
+                parents.push(parent_hash(parent & !EDGE_FLAG)?);
+                if parent & EDGE_FLAG != 0 {
+                    break;
+                }
+                edge += 4;
+            }
+        } else if second != PARENT_NONE {
+            parents.push(parent_hash(second)?);
+        }
+
+        let high = self.read_u32(offset + HASH_LEN + 8);
+        let low = self.read_u32(offset + HASH_LEN + 12);
+        Ok(GraphCommit {
+            tree: String::from_utf8(tree)?,
+            parents,
+            timestamp: (((high & 0b11) as i64) << 32) | low as i64,
+            generation: high >> 2,
+        })
+    }
+
+    /// Check the commit-graph against the commit objects.
+    ///
+    /// # Returns
+    ///
+    /// The problems found (none if the commit-graph is valid)
+    pub(crate) fn verify(&self, repo: &Repository) -> anyhow::Result<Vec<String>> {
+        let mut errors = Vec::new();
+        let (content, checksum) = self.data.split_at(self.data.len() - HASH_LEN);
+        if Sha1::digest(content).as_slice() != checksum {
+            errors.push("commit-graph has incorrect checksum and is likely corrupt".to_string());
+        }
+
+        // The commits are sorted, and the fan-out counts the commits up to each first
+        // byte; the commits cannot be looked up otherwise, so they are not checked
+        let corrupt = errors.len();
+        let mut counts = [0u32; 256];
+        for position in 0..self.len {
+            let offset = self.lookup + position * HASH_LEN;
+            counts[self.data[offset] as usize] += 1;
+            if position > 0
+                && self.data[offset - HASH_LEN..offset] >= self.data[offset..offset + HASH_LEN]
+            {
+                errors.push(format!(
+                    "commit-graph has incorrect OID order: {} then {}",
+                    self.hash(position - 1),
+                    self.hash(position)
+                ));
+            }
+        }
+        let mut expected = 0;
+        for (byte, count) in counts.iter().enumerate() {
+            expected += count;
+            let actual = self.read_u32(self.fanout + byte * 4);
+            if actual != expected {
+                errors.push(format!(
+                    "commit-graph has incorrect fanout value: fanout[{byte}] = {actual} != {expected}"
+                ));
+            }
+        }
+        if errors.len() > corrupt {
+            return Ok(errors);
+        }
+
+        for position in 0..self.len {
+            let hash = self.hash(position);
+            let graph_commit = match self.commit_at(position) {
+                Ok(commit) => commit,
+                Err(_) => {
+                    errors.push(format!("failed to parse commit {hash} from commit-graph"));
+                    continue;
+                },
+            };
+            let Ok(commit) = read_commit(repo, &hash) else {
+                errors.push(format!(
+                    "failed to parse commit {hash} from object database for commit-graph"
+                ));
+                continue;
+            };
+
+            if graph_commit.tree != commit.tree {
+                errors.push(format!(
+                    "root tree OID for commit {hash} in commit-graph is {} != {}",
+                    graph_commit.tree, commit.tree
+                ));
+            }
+            if graph_commit.parents != commit.parents {
+                errors.push(format!(
+                    "commit-graph parent list for commit {hash} is {} != {}",
+                    graph_commit.parents.join(" "),
+                    commit.parents.join(" ")
+                ));
+            }
+
+            let mut generation = 0;
+            for parent in &graph_commit.parents {
+                if let Some(parent) = self.commit(parent)? {
+                    generation = generation.max(parent.generation);
+                }
+            }
+            let generation = (generation + 1).min(GENERATION_MAX);
+            if graph_commit.generation != generation {
+                errors.push(format!(
+                    "commit-graph generation for commit {hash} is {} != {generation}",
+                    graph_commit.generation
+                ));
+            }
+
+            let timestamp = commit_timestamp(&commit.committer);
+            if graph_commit.timestamp != timestamp {
+                errors.push(format!(
+                    "commit date for commit {hash} in commit-graph is {} != {timestamp}",
+                    graph_commit.timestamp
+                ));
+            }
+        }
+        Ok(errors)
+    }
+}
+
+/// Whether the parents of commits are the ones stored in their objects, so
+/// that the commit-graph can be used.
+fn is_compatible(repo: &Repository) -> anyhow::Result<bool> {
+    if !Config::load(repo)?
+        .get_bool("core.commitGraph")?
+        .unwrap_or(true)
+    {
+        return Ok(false);
+    }
+    let grafts = repo.common_dir()?.join("info").join("grafts");
+    Ok(!grafts.is_file() && read_shallow(repo)?.is_empty() && repo.replacements()?.is_empty())
+}
+
+/// The committer date stored for a commit (dates before the epoch are stored as 0).
+fn commit_timestamp(committer: &[u8]) -> i64 {
+    Ident::parse(committer).map_or(0, |ident| ident.timestamp.clamp(0, (1 << 34) - 1))
+}
+
+/// Write the commit-graph of a set of commits and all of their ancestors,
+/// replacing the existing one. Nothing is written if the commit-graph cannot
+/// be used (see [`CommitGraph::read`]).
+///
+/// # Returns
+///
+/// The number of commits written, if the commit-graph was written
+pub(crate) fn write_commit_graph(
+    repo: &Repository,
+    commits: &[String],
+) -> anyhow::Result<Option<usize>> {
+    if !is_compatible(repo)? {
+        return Ok(None);
+    }
+
+    // Every ancestor must be in the commit-graph, as parents are stored by position
+    let mut parsed = BTreeMap::new();
+    let mut seen = HashSet::new();
+    let mut pending = commits.to_vec();
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let commit = read_commit(repo, &hash)?;
+        pending.extend(commit.parents.iter().cloned());
+        let bytes = hex::decode(hash.as_bytes())?;
+        parsed.insert(bytes, (hash, commit));
+    }
+    let commits: HashMap<&str, &Commit> = parsed
+        .values()
+        .map(|(hash, commit)| (hash.as_str(), commit))
+        .collect();
+    let positions: HashMap<&str, u32> = parsed
+        .values()
+        .enumerate()
+        .map(|(position, (hash, _))| (hash.as_str(), position as u32))
+        .collect();
+
+    // Generation numbers, computed parents first
+    let mut generations: HashMap<&str, u32> = HashMap::new();
+    for &hash in commits.keys() {
+        let mut stack = vec![hash];
+        while let Some(&hash) = stack.last() {
+            if generations.contains_key(hash) {
+                stack.pop();
+                continue;
+            }
+            let parents = &commits[hash].parents;
+            let missing: Vec<&str> = parents
+                .iter()
+                .map(String::as_str)
+                .filter(|parent| !generations.contains_key(parent))
+                .collect();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+            let generation = parents
+                .iter()
+                .map(|parent| generations[parent.as_str()])
+                .max()
+                .unwrap_or(0);
+            generations.insert(hash, (generation + 1).min(GENERATION_MAX));
+            stack.pop();
+        }
+    }
+
+    let mut fanout = Vec::with_capacity(256 * 4);
+    let mut lookup = Vec::with_capacity(parsed.len() * HASH_LEN);
+    let mut commit_data = Vec::with_capacity(parsed.len() * COMMIT_DATA_LEN);
+    let mut extra_edges: Vec<u8> = Vec::new();
+    let mut counts = [0u32; 256];
+    for (bytes, (hash, commit)) in &parsed {
+        counts[bytes[0] as usize] += 1;
+        lookup.extend(bytes);
+        commit_data.extend(hex::decode(commit.tree.as_bytes())?);
+
+        let parents: Vec<u32> = commit
+            .parents
+            .iter()
+            .map(|parent| positions[parent.as_str()])
+            .collect();
+        let (first, second) = match parents.as_slice() {
+            [] => (PARENT_NONE, PARENT_NONE),
+            [first] => (*first, PARENT_NONE),
+            [first, second] => (*first, *second),
+            [first, others @ ..] => {
+                let edge = (extra_edges.len() / 4) as u32 | EDGE_FLAG;
+                for (index, parent) in others.iter().enumerate() {
+                    let last = index == others.len() - 1;
+                    let flag = if last { EDGE_FLAG } else { 0 };
+                    extra_edges.extend((parent | flag).to_be_bytes());
+                }
+                (*first, edge)
+            },
+        };
+        commit_data.extend(first.to_be_bytes());
+        commit_data.extend(second.to_be_bytes());
+
+        let timestamp = commit_timestamp(&commit.committer);
+        let generation = generations[hash.as_str()];
+        commit_data.extend(((generation << 2) | (timestamp >> 32) as u32).to_be_bytes());
+        commit_data.extend((timestamp as u32).to_be_bytes());
+    }
+    let mut total = 0;
+    for count in counts {
+        total += count;
+        fanout.extend(total.to_be_bytes());
+    }
+
+    let mut chunks: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (CHUNK_OID_FANOUT, fanout),
+        (CHUNK_OID_LOOKUP, lookup),
+        (CHUNK_COMMIT_DATA, commit_data),
+    ];
+    if !extra_edges.is_empty() {
+        chunks.push((CHUNK_EXTRA_EDGES, extra_edges));
+    }
+
+    let mut data = SIGNATURE.to_vec();
+    data.extend([VERSION, HASH_VERSION, chunks.len() as u8, 0]);
+    let mut offset = (data.len() + (chunks.len() + 1) * 12) as u64;
+    for (id, chunk) in &chunks {
+        data.extend(*id);
+        data.extend(offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    data.extend([0; 4]);
+    data.extend(offset.to_be_bytes());
+    for (_, chunk) in chunks {
+        data.extend(chunk);
+    }
+    let checksum = Sha1::digest(&data);
+    data.extend(checksum);
+
+    // The new file replaces the old one at once, as other commands may be reading it
+    let path = CommitGraph::path(repo)?;
+    let parent = path.parent().unwrap();
+    std::fs::create_dir_all(parent).context(format!("create {}", parent.display()))?;
+    let lock = TempPath::new(path.with_file_name("commit-graph.lock"));
+    std::fs::write(lock.path(), &data).context(format!("write {}", lock.path().display()))?;
+    std::fs::rename(lock.path(), &path).context(format!("write {}", path.display()))?;
+    lock.keep();
+    Ok(Some(parsed.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+    use crate::utils::walk::{is_ancestor, merge_bases};
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0000")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0000");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    #[test]
+    fn writes_and_reads_commit_graphs() {
+        let (temp, repo) = setup();
+        let root = create_commit(&repo, EMPTY_TREE, Vec::new(), "root\n").unwrap();
+        let sides: Vec<String> = ["one\n", "two\n", "three\n"]
+            .iter()
+            .map(|message| create_commit(&repo, EMPTY_TREE, vec![root.clone()], message).unwrap())
+            .collect();
+        let octopus = create_commit(&repo, EMPTY_TREE, sides.clone(), "merge\n").unwrap();
+        let other = create_commit(&repo, EMPTY_TREE, vec![sides[0].clone()], "other\n").unwrap();
+
+        // The ancestors of the commits written are written as well
+        assert_eq!(
+            write_commit_graph(&repo, std::slice::from_ref(&octopus)).unwrap(),
+            Some(5)
+        );
+        let graph = CommitGraph::read(&repo).unwrap().unwrap();
+        assert_eq!(graph.verify(&repo).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            graph.commit(&octopus).unwrap(),
+            Some(GraphCommit {
+                tree: EMPTY_TREE.to_string(),
+                parents: sides.clone(),
+                timestamp: 1700000000,
+                generation: 3,
+            })
+        );
+        assert_eq!(graph.commit(&root).unwrap().unwrap().generation, 1);
+        assert_eq!(graph.commit(&other).unwrap(), None);
+
+        // Commits missing from the commit-graph are read from their objects
+        let repo = temp.repo();
+        assert!(repo.commit_graph().is_some());
+        assert!(is_ancestor(&repo, &root, &other).unwrap());
+        assert!(!is_ancestor(&repo, &sides[1], &other).unwrap());
+        assert!(!is_ancestor(&repo, &octopus, &root).unwrap());
+        assert_eq!(merge_bases(&repo, &other, &octopus).unwrap(), [
+            sides[0].clone()
+        ]);
+    }
+
+    #[test]
+    fn ignores_commit_graphs_of_grafted_histories() {
+        let (temp, repo) = setup();
+        let root = create_commit(&repo, EMPTY_TREE, Vec::new(), "root\n").unwrap();
+        let child = create_commit(&repo, EMPTY_TREE, vec![root.clone()], "child\n").unwrap();
+        write_commit_graph(&repo, std::slice::from_ref(&child)).unwrap();
+
+        fs::write(temp.join(".git/shallow"), format!("{child}\n")).unwrap();
+        assert!(CommitGraph::read(&repo).unwrap().is_none());
+        assert_eq!(
+            write_commit_graph(&repo, std::slice::from_ref(&child)).unwrap(),
+            None
+        );
+        assert!(!is_ancestor(&temp.repo(), &root, &child).unwrap());
+        fs::remove_file(temp.join(".git/shallow")).unwrap();
+
+        let data = fs::read(temp.join(".git/objects/info/commit-graph")).unwrap();
+        let mut corrupt = data.clone();
+        corrupt[0] = b'X';
+        let err = CommitGraph::parse(corrupt).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "commit-graph signature 58475048 does not match signature 43475048"
+        );
+
+        // A wrong date is only found by checking the commits
+        let mut corrupt = data;
+        let date = corrupt.len() - HASH_LEN - 1;
+        corrupt[date] ^= 1;
+        let errors = CommitGraph::parse(corrupt).unwrap().verify(&repo).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0],
+            "commit-graph has incorrect checksum and is likely corrupt"
+        );
+        assert!(errors[1].starts_with("commit date for commit "));
+    }
+}
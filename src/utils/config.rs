@@ -0,0 +1,1013 @@
+//! Reading git configuration files
+//!
+//! Configuration is read from the following scopes, in order (later values
+//! take precedence over earlier ones):
+//!
+//! 1. System: `$GIT_CONFIG_SYSTEM` or `/etc/gitconfig` (skipped if `$GIT_CONFIG_NOSYSTEM` is set)
+//! 2. Global: `$GIT_CONFIG_GLOBAL`, or `$XDG_CONFIG_HOME/git/config` and `~/.gitconfig`
+//! 3. Local: `$GIT_COMMON_DIR/config`
+//! 4. Worktree: `$GIT_DIR/config.worktree`, if `extensions.worktreeConfig` is set
+//! 5. Command: the `$GIT_CONFIG_KEY_<n>` and `$GIT_CONFIG_VALUE_<n>` pairs (for `n` below
+//!    `$GIT_CONFIG_COUNT`), then `$GIT_CONFIG_PARAMETERS` (set by `-c <name>=<value>`)
+//!
+//! Each file may include other files with `include.path`, or with
+//! `includeIf.<condition>.path` if the condition holds for the repository:
+//!
+//! - `gitdir:<pattern>` (or `gitdir/i:<pattern>`, ignoring case) matches the git directory
+//! - `onbranch:<pattern>` matches the branch HEAD points to
+//!
+//! Single keys are written to a file with [`set_config_value`].
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::utils::env::{self, Environment};
+use crate::utils::refs::head_branch;
+use crate::utils::repository::Repository;
+use crate::utils::wildmatch::wildmatch;
+
+/// How deeply includes may be nested, to stop circular includes
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Where a configuration value was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+    Command,
+}
+
+/// A single `key = value` entry of a configuration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ConfigEntry {
+    /// The normalized key (`section.subsection.name`, with the section
+    /// and name lowercased)
+    pub(crate) key: String,
+    /// The value, `None` for keys without `=` (implicitly `true`)
+    pub(crate) value: Option<String>,
+    /// The file the entry was read from (`None` for the command scope)
+    pub(crate) origin: Option<PathBuf>,
+    /// The scope of the file the entry was read from
+    pub(crate) scope: ConfigScope,
+}
+
+/// The merged configuration of all scopes
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Config {
+    entries: Vec<ConfigEntry>,
+    /// The environment variables the configuration was loaded with
+    env: Environment,
+}
+
+/// The repository conditional includes are evaluated against
+#[derive(Debug, Default)]
+struct IncludeContext {
+    /// The environment variables the command is run with
+    env: Environment,
+    /// The git directory, if the command is run in a repository
+    git_dir: Option<PathBuf>,
+    /// The branch HEAD points to (without `refs/heads/`), if any
+    branch: Option<String>,
+}
+
+impl Config {
+    /// Load the configuration for a repository.
+    /// The local scope is skipped if the repository does not exist.
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let context = IncludeContext::new(repo);
+        let mut config = Config {
+            env: repo.env().clone(),
+            ..Config::default()
+        };
+        config.read_user_files(&context)?;
+
+        if let Ok(common_dir) = repo.common_dir() {
+            let local = common_dir.join("config");
+            config.read_file(&local, ConfigScope::Local, &context, 0)?;
+
+            // Each worktree has its own settings on top of the shared ones
+            if config
+                .get_bool("extensions.worktreeConfig")?
+                .unwrap_or(false)
+            {
+                let worktree = repo.git_dir()?.join("config.worktree");
+                config.read_file(&worktree, ConfigScope::Worktree, &context, 0)?;
+            }
+        }
+
+        config.read_command_scope()?;
+        Ok(config)
+    }
+
+    /// Load the protected configuration: the scopes the user controls, leaving out
+    /// the repository's own configuration, which could have been written by anyone
+    /// who had access to the repository. Security-sensitive keys (e.g. `safe.directory`)
+    /// are only read from here. Conditional includes never apply, as there is no
+    /// trusted repository to evaluate them against.
+    pub(crate) fn load_protected(env: &Environment) -> anyhow::Result<Self> {
+        let mut config = Config {
+            env: env.clone(),
+            ..Config::default()
+        };
+        let context = IncludeContext {
+            env: env.clone(),
+            ..IncludeContext::default()
+        };
+        config.read_user_files(&context)?;
+        config.read_command_scope()?;
+        Ok(config)
+    }
+
+    /// Add the entries of the system and global files.
+    fn read_user_files(&mut self, context: &IncludeContext) -> anyhow::Result<()> {
+        if self.env.var_os(env::GIT_CONFIG_NOSYSTEM).is_none() {
+            let system = self
+                .env
+                .var_os(env::GIT_CONFIG_SYSTEM)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("/etc/gitconfig"));
+            self.read_file(&system, ConfigScope::System, context, 0)?;
+        }
+
+        for global in global_config_paths(&self.env) {
+            self.read_file(&global, ConfigScope::Global, context, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add the entries passed through the environment and the command line.
+    fn read_command_scope(&mut self) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+
+        if let Some(count) = self.env.var(env::GIT_CONFIG_COUNT) {
+            let count: usize = count
+                .parse()
+                .context(format!("bogus count in {}", env::GIT_CONFIG_COUNT))?;
+
+            for i in 0..count {
+                let key_var = format!("{}_{i}", env::GIT_CONFIG_KEY);
+                let value_var = format!("{}_{i}", env::GIT_CONFIG_VALUE);
+                let key = self
+                    .env
+                    .var(&key_var)
+                    .context(format!("missing config key {key_var}"))?;
+                let value = self
+                    .env
+                    .var(&value_var)
+                    .context(format!("missing config value {value_var}"))?;
+                entries.push((parse_key(key)?, Some(value.to_string())));
+            }
+        }
+
+        if let Some(parameters) = self.env.var(env::GIT_CONFIG_PARAMETERS) {
+            entries.extend(
+                parse_config_parameters(parameters)
+                    .context(format!("bogus format in {}", env::GIT_CONFIG_PARAMETERS))?,
+            );
+        }
+
+        self.entries
+            .extend(entries.into_iter().map(|(key, value)| ConfigEntry {
+                key,
+                value,
+                origin: None,
+                scope: ConfigScope::Command,
+            }));
+        Ok(())
+    }
+
+    /// Parse configuration entries from a string.
+    pub(crate) fn parse(content: &str, origin: &Path, scope: ConfigScope) -> anyhow::Result<Self> {
+        let entries = parse_config(content)
+            .with_context(|| format!("bad config file {}", origin.display()))?
+            .into_iter()
+            .map(|(key, value)| ConfigEntry {
+                key,
+                value,
+                origin: Some(origin.to_path_buf()),
+                scope,
+            })
+            .collect();
+
+        Ok(Config {
+            entries,
+            ..Config::default()
+        })
+    }
+
+    /// Add the entries of a file to the configuration, if it exists,
+    /// along with the entries of the files it includes.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    /// * `scope` - The scope of the file (included files share it)
+    /// * `context` - The repository conditional includes are evaluated against
+    /// * `depth` - How many includes led to this file
+    fn read_file(
+        &mut self,
+        path: &Path,
+        scope: ConfigScope,
+        context: &IncludeContext,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        let content =
+            std::fs::read_to_string(path).context(format!("read config {}", path.display()))?;
+        let parsed = Config::parse(&content, path, scope)?;
+
+        for entry in parsed.entries {
+            let include = match &entry.value {
+                Some(value) if context.includes(&entry.key, path) => {
+                    // Relative paths are relative to the including file
+                    let dir = path.parent().unwrap_or(Path::new(""));
+                    Some(dir.join(expand_path(&self.env, value)))
+                },
+                _ => None,
+            };
+            self.entries.push(entry);
+
+            if let Some(include) = include {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    anyhow::bail!(
+                        "exceeded maximum include depth ({MAX_INCLUDE_DEPTH}) while including {} from {}",
+                        include.display(),
+                        path.display()
+                    );
+                }
+                self.read_file(&include, scope, context, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the entries of all scopes, in the order they were read.
+    pub(crate) fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// Get the last value of a key (`None` if it is not set).
+    /// Keys without a value are returned as `"true"`.
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.value.as_deref().unwrap_or("true"))
+    }
+
+    /// Get all values of a multi-valued key, in the order they were read.
+    pub(crate) fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|entry| entry.key == key)
+            .map(|entry| entry.value.as_deref().unwrap_or("true"))
+            .collect()
+    }
+
+    /// Get the value of a key as a boolean.
+    pub(crate) fn get_bool(&self, key: &str) -> anyhow::Result<Option<bool>> {
+        self.get(key)
+            .map(|value| parse_bool(value).context(format!("bad boolean config value for {key}")))
+            .transpose()
+    }
+
+    /// The environment variables the configuration was loaded with.
+    pub(crate) fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    /// Get the value of a key as a path, expanding a leading `~/`.
+    pub(crate) fn get_path(&self, key: &str) -> Option<PathBuf> {
+        self.get(key).map(|path| expand_path(&self.env, path))
+    }
+}
+
+impl IncludeContext {
+    fn new(repo: &Repository) -> Self {
+        let env = repo.env().clone();
+        let Ok(git_dir) = repo.git_dir() else {
+            return IncludeContext {
+                env,
+                ..IncludeContext::default()
+            };
+        };
+        let branch = head_branch(repo)
+            .ok()
+            .flatten()
+            .and_then(|branch| branch.strip_prefix("refs/heads/").map(str::to_string));
+
+        IncludeContext {
+            env,
+            git_dir: Some(git_dir),
+            branch,
+        }
+    }
+
+    /// Check whether an entry includes another file: `include.path`, or an
+    /// `includeIf.<condition>.path` whose condition holds (unknown conditions never do).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The normalized key of the entry
+    /// * `origin` - The file the entry was read from
+    fn includes(&self, key: &str, origin: &Path) -> bool {
+        if key == "include.path" {
+            return true;
+        }
+        let Some(condition) = key
+            .strip_prefix("includeif.")
+            .and_then(|rest| rest.strip_suffix(".path"))
+        else {
+            return false;
+        };
+
+        if let Some(pattern) = condition.strip_prefix("gitdir:") {
+            self.matches_git_dir(pattern, origin, false)
+        } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+            self.matches_git_dir(pattern, origin, true)
+        } else if let Some(pattern) = condition.strip_prefix("onbranch:") {
+            let pattern = with_trailing_stars(pattern.to_string());
+            self.branch
+                .as_deref()
+                .is_some_and(|branch| wildmatch(&pattern, branch, false))
+        } else {
+            false
+        }
+    }
+
+    /// Match the git directory against the pattern of a `gitdir:` condition.
+    /// A leading `~/` is the home directory and a leading `./` the directory of the
+    /// including file, other relative patterns match anywhere (`**/` is prepended),
+    /// and a trailing `/` matches everything inside the directory.
+    fn matches_git_dir(&self, pattern: &str, origin: &Path, ignore_case: bool) -> bool {
+        let Some(git_dir) = &self.git_dir else {
+            return false;
+        };
+
+        let pattern = with_trailing_stars(pattern.to_string());
+        // The directory of the including file is matched literally, not as a pattern
+        let (prefix, pattern) = match pattern.strip_prefix("./") {
+            Some(rest) => {
+                let origin = origin.canonicalize().unwrap_or(origin.to_path_buf());
+                let dir = origin.parent().unwrap_or(Path::new("/"));
+                let dir = dir.to_string_lossy();
+                (format!("{}/", dir.trim_end_matches('/')), rest.to_string())
+            },
+            None => {
+                let pattern = expand_path(&self.env, &pattern)
+                    .to_string_lossy()
+                    .into_owned();
+                if Path::new(&pattern).is_absolute() {
+                    (String::new(), pattern)
+                } else {
+                    (String::new(), format!("**/{pattern}"))
+                }
+            },
+        };
+
+        // Both the path as given and the path with symbolic links resolved may match
+        let candidates = [Some(git_dir.clone()), git_dir.canonicalize().ok()];
+        candidates.into_iter().flatten().any(|git_dir| {
+            let git_dir = git_dir.to_string_lossy();
+            let Some(head) = git_dir.get(..prefix.len()) else {
+                return false;
+            };
+            let prefix_matches = if ignore_case {
+                head.eq_ignore_ascii_case(&prefix)
+            } else {
+                head == prefix
+            };
+            prefix_matches && wildmatch(&pattern, &git_dir[prefix.len()..], ignore_case)
+        })
+    }
+}
+
+/// Make a pattern ending with `/` match everything inside the directory.
+fn with_trailing_stars(mut pattern: String) -> String {
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    pattern
+}
+
+/// Pass a `<name>=<value>` entry (or `<name>` alone, for `true`) to the command scope of
+/// an environment and the git processes run with it, through `$GIT_CONFIG_PARAMETERS`.
+pub(crate) fn add_config_parameter(env: &mut Environment, parameter: &str) -> anyhow::Result<()> {
+    let (key, value) = match parameter.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (parameter, None),
+    };
+    parse_key(key)?;
+
+    // A key without a value is written as `'<name>'=`
+    let mut entry = format!("{}=", quote_parameter(key));
+    if let Some(value) = value {
+        entry.push_str(&quote_parameter(value));
+    }
+
+    let parameters = match env.var(env::GIT_CONFIG_PARAMETERS) {
+        Some(existing) if !existing.is_empty() => format!("{existing} {entry}"),
+        _ => entry,
+    };
+    env.set(env::GIT_CONFIG_PARAMETERS, parameters);
+    Ok(())
+}
+
+/// Check that a key passed outside of a file is well-formed (`section[.subsection].name`).
+///
+/// # Returns
+///
+/// The normalized key
+fn parse_key(key: &str) -> anyhow::Result<String> {
+    let Some((section, _)) = key.split_once('.') else {
+        anyhow::bail!("key does not contain a section: {key}");
+    };
+    let Some((_, name)) = key.rsplit_once('.').filter(|(_, name)| !name.is_empty()) else {
+        anyhow::bail!("key does not contain variable name: {key}");
+    };
+
+    let valid_section = !section.is_empty()
+        && section
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_section || !valid_name || key.contains('\n') {
+        anyhow::bail!("invalid key: {key}");
+    }
+
+    Ok(normalize_key(key))
+}
+
+/// Quote a string for `$GIT_CONFIG_PARAMETERS`, in single quotes
+/// (with `'` and `!` escaped as `'\''` and `'\!'`).
+fn quote_parameter(value: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in value.chars() {
+        match c {
+            '\'' | '!' => quoted.push_str(&format!("'\\{c}'")),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Parse the `'<name>'='<value>'` (or `'<name>'=` without a value) entries of
+/// `$GIT_CONFIG_PARAMETERS`, separated by whitespace. The older `'<name>=<value>'`
+/// form is accepted as well.
+fn parse_config_parameters(parameters: &str) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let mut entries = Vec::new();
+    let mut chars = parameters.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let key = read_quoted(&mut chars)?;
+        let (key, value) = if chars.next_if_eq(&'=').is_some() {
+            match chars.peek() {
+                Some('\'') => (key, Some(read_quoted(&mut chars)?)),
+                _ => (key, None),
+            }
+        } else {
+            match key.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (key, None),
+            }
+        };
+        entries.push((parse_key(&key)?, value));
+    }
+
+    Ok(entries)
+}
+
+/// Read a single-quoted word, where `'\''` stands for a quote.
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> anyhow::Result<String> {
+    if chars.next() != Some('\'') {
+        anyhow::bail!("expected a quoted string");
+    }
+
+    let mut word = String::new();
+    loop {
+        match chars.next() {
+            Some('\'') => {
+                // A closing quote, unless an escaped quote follows
+                if chars.next_if_eq(&'\\').is_none() {
+                    return Ok(word);
+                }
+                word.push(chars.next().context("unterminated escape")?);
+                if chars.next() != Some('\'') {
+                    anyhow::bail!("expected a quote after an escape");
+                }
+            },
+            Some(c) => word.push(c),
+            None => anyhow::bail!("unterminated quoted string"),
+        }
+    }
+}
+
+/// Set a key in a configuration file, replacing its last value in the file or
+/// adding it at the end of its section. The file and the section are created
+/// if needed, and the rest of the file is kept as it is.
+///
+/// # Arguments
+///
+/// * `path` - The path of the configuration file
+/// * `key` - The key to set (`section.name` or `section.subsection.name`)
+/// * `value` - The value, written as is
+pub(crate) fn set_config_value(path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    let normalized = parse_key(key)?;
+    let (section, _) = normalized.rsplit_once('.').expect("keys have a section");
+    let (prefix, name) = key.rsplit_once('.').expect("keys have a section");
+
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context(format!("read config {}", path.display())),
+    };
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+
+    // Find the last line of the section, and the last line setting the key in it
+    let mut in_section = false;
+    let mut section_end = None;
+    let mut existing = None;
+    for (number, line) in lines.iter().enumerate() {
+        let mut rest = line.trim_start();
+        if rest.starts_with('[') {
+            let (header, after) = parse_section_header(rest)
+                .with_context(|| format!("invalid section header on line {}", number + 1))?;
+            in_section = header == section;
+            rest = after.trim_start();
+        }
+        if !in_section || rest.is_empty() || rest.starts_with(['#', ';']) {
+            if in_section {
+                section_end = Some(number + 1);
+            }
+            continue;
+        }
+
+        section_end = Some(number + 1);
+        let line_name = rest
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .next()
+            .unwrap_or_default();
+        if line_name.eq_ignore_ascii_case(name) {
+            existing = Some(number);
+        }
+    }
+
+    let entry = format!("\t{name} = {value}");
+    match (existing, section_end) {
+        (Some(number), _) => lines[number] = entry,
+        (None, Some(end)) => lines.insert(end, entry),
+        (None, None) => {
+            let header = match prefix.split_once('.') {
+                Some((section, subsection)) => format!(
+                    "[{section} \"{}\"]",
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                ),
+                None => format!("[{prefix}]"),
+            };
+            lines.push(header);
+            lines.push(entry);
+        },
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    std::fs::write(path, content).context(format!("write config {}", path.display()))
+}
+
+/// Parse a boolean configuration value.
+pub(crate) fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => anyhow::bail!("invalid boolean: {}", value),
+    }
+}
+
+/// Expand a leading `~/` in a path to the home directory (`$HOME`).
+pub(crate) fn expand_path(env: &Environment, path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env.var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Normalize a key for comparison: the section and the variable name are
+/// case-insensitive, the subsection is not.
+pub(crate) fn normalize_key(key: &str) -> String {
+    let Some((section, rest)) = key.split_once('.') else {
+        return key.to_ascii_lowercase();
+    };
+
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => format!(
+            "{}.{}.{}",
+            section.to_ascii_lowercase(),
+            subsection,
+            name.to_ascii_lowercase()
+        ),
+        None => format!(
+            "{}.{}",
+            section.to_ascii_lowercase(),
+            rest.to_ascii_lowercase()
+        ),
+    }
+}
+
+/// The global configuration files, in the order they are read.
+fn global_config_paths(env: &Environment) -> Vec<PathBuf> {
+    if let Some(global) = env.var_os(env::GIT_CONFIG_GLOBAL) {
+        return vec![PathBuf::from(global)];
+    }
+
+    let mut paths = Vec::new();
+    paths.extend(xdg_config_path(env, "config"));
+    paths.extend(
+        env.var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".gitconfig")),
+    );
+
+    paths
+}
+
+/// Get the path of a file in git's XDG configuration directory:
+/// `$XDG_CONFIG_HOME/git/<name>`, or `~/.config/git/<name>` if the variable is unset or empty.
+pub(crate) fn xdg_config_path(env: &Environment, name: &str) -> Option<PathBuf> {
+    match env.var_os("XDG_CONFIG_HOME") {
+        Some(xdg) if !xdg.is_empty() => Some(PathBuf::from(xdg).join("git").join(name)),
+        _ => env
+            .var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config/git").join(name)),
+    }
+}
+
+/// Parse the content of a configuration file into normalized keys and values.
+fn parse_config(content: &str) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let mut entries = Vec::new();
+    let mut section: Option<String> = None;
+    let mut lines = content.lines().enumerate();
+
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim_start();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let mut rest = line;
+        if rest.starts_with('[') {
+            let (header, after) = parse_section_header(rest)
+                .with_context(|| format!("invalid section header on line {}", number + 1))?;
+            section = Some(header);
+            rest = after.trim_start();
+
+            // A key may follow the section header on the same line
+            if rest.is_empty() || rest.starts_with('#') || rest.starts_with(';') {
+                continue;
+            }
+        }
+
+        let section = section
+            .as_ref()
+            .with_context(|| format!("key outside of a section on line {}", number + 1))?;
+
+        let name_end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            anyhow::bail!("invalid key on line {}", number + 1);
+        }
+
+        let key = format!("{section}.{}", name.to_ascii_lowercase());
+        let after_name = rest[name_end..].trim_start();
+
+        let value = if let Some(raw) = after_name.strip_prefix('=') {
+            let mut raw = raw.to_string();
+            // A trailing backslash continues the value on the next line
+            while ends_with_continuation(&raw) {
+                raw.pop();
+                match lines.next() {
+                    Some((_, next)) => raw.push_str(next),
+                    None => break,
+                }
+            }
+            Some(parse_value(&raw).with_context(|| format!("bad value on line {}", number + 1))?)
+        } else if after_name.is_empty() || after_name.starts_with(['#', ';']) {
+            None
+        } else {
+            anyhow::bail!("invalid key on line {}", number + 1);
+        };
+
+        entries.push((key, value));
+    }
+
+    Ok(entries)
+}
+
+/// Parse a `[section]`, `[section "subsection"]` or `[section.subsection]` header.
+///
+/// # Returns
+///
+/// The normalized section prefix and the rest of the line
+fn parse_section_header(line: &str) -> anyhow::Result<(String, &str)> {
+    let inner = &line[1..];
+
+    let name_end = inner
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+        .context("unterminated section header")?;
+    let name = &inner[..name_end];
+    if name.is_empty() {
+        anyhow::bail!("empty section name");
+    }
+    let rest = &inner[name_end..];
+
+    if let Some(rest) = rest.strip_prefix(']') {
+        // Legacy `[section.subsection]` headers lowercase the subsection
+        return Ok((name.to_ascii_lowercase(), rest));
+    }
+
+    let rest = rest.trim_start();
+    let Some(quoted) = rest.strip_prefix('"') else {
+        anyhow::bail!("invalid section header");
+    };
+
+    let mut subsection = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let (_, escaped) = chars.next().context("unterminated subsection")?;
+                subsection.push(escaped);
+            },
+            '"' => {
+                let rest = quoted[index + 1..]
+                    .strip_prefix(']')
+                    .context("unterminated section header")?;
+                return Ok((format!("{}.{subsection}", name.to_ascii_lowercase()), rest));
+            },
+            _ => subsection.push(c),
+        }
+    }
+
+    anyhow::bail!("unterminated subsection")
+}
+
+/// Check whether a raw value ends with an unescaped backslash outside of a comment.
+fn ends_with_continuation(raw: &str) -> bool {
+    let trailing = raw.bytes().rev().take_while(|&b| b == b'\\').count();
+    trailing % 2 == 1
+}
+
+/// Unquote and unescape a raw value, stripping comments and surrounding whitespace.
+fn parse_value(raw: &str) -> anyhow::Result<String> {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    // Whitespace is only kept if it is followed by non-whitespace
+    let mut pending_space = String::new();
+    let mut chars = raw.trim_start().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                in_quotes = !in_quotes;
+            },
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some(other) => anyhow::bail!("invalid escape sequence: \\{}", other),
+                    None => break,
+                };
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(escaped);
+            },
+            '#' | ';' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            },
+        }
+    }
+
+    if in_quotes {
+        anyhow::bail!("unterminated quote");
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{add_config_parameter, normalize_key, set_config_value, Config, ConfigScope};
+    use crate::utils::env::{self};
+    use crate::utils::test::TempRepo;
+
+    fn parse(content: &str) -> Config {
+        Config::parse(content, Path::new("config"), ConfigScope::Local).unwrap()
+    }
+
+    #[test]
+    fn parses_sections_and_subsections() {
+        let config = parse(
+            "[core]\n\
+             \tbare = false\n\
+             [remote \"origin\"]\n\
+             \turl = https://example.com/repo.git\n\
+             \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+             [Branch.Main]\n\
+             \tremote = origin\n",
+        );
+
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(
+            config.get("remote.origin.url"),
+            Some("https://example.com/repo.git")
+        );
+        assert_eq!(config.get("branch.main.remote"), Some("origin"));
+        assert_eq!(config.get("remote.Origin.url"), None);
+    }
+
+    #[test]
+    fn keys_are_case_insensitive() {
+        let config = parse("[GPG \"ssh\"]\n\tallowedSignersFile = ~/.ssh/allowed\n");
+        assert_eq!(
+            config.get("gpg.ssh.allowedsignersfile"),
+            Some("~/.ssh/allowed")
+        );
+        assert_eq!(normalize_key("Core.FileMode"), "core.filemode");
+        assert_eq!(normalize_key("Remote.Origin.URL"), "remote.Origin.url");
+    }
+
+    #[test]
+    fn parses_values() {
+        let config = parse(
+            "[test]\n\
+             quoted = \"  spaced  \" # comment\n\
+             escaped = a\\tb\\\"c\n\
+             inner = one   two ; comment\n\
+             continued = first \\\n\
+             second\n\
+             implicit\n\
+             empty =\n",
+        );
+
+        assert_eq!(config.get("test.quoted"), Some("  spaced  "));
+        assert_eq!(config.get("test.escaped"), Some("a\tb\"c"));
+        assert_eq!(config.get("test.inner"), Some("one   two"));
+        assert_eq!(config.get("test.continued"), Some("first second"));
+        assert_eq!(config.get_bool("test.implicit").unwrap(), Some(true));
+        assert_eq!(config.get_bool("test.empty").unwrap(), Some(false));
+    }
+
+    #[test]
+    fn later_values_take_precedence() {
+        let config = parse("[a]\n\tb = 1\n\tb = 2\n");
+        assert_eq!(config.get("a.b"), Some("2"));
+        assert_eq!(config.get_all("a.b"), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn fails_on_invalid_config() {
+        let origin = Path::new("config");
+        assert!(Config::parse("key = value\n", origin, ConfigScope::Local).is_err());
+        assert!(Config::parse("[section\n", origin, ConfigScope::Local).is_err());
+        assert!(Config::parse("[a]\nb = \"open\n", origin, ConfigScope::Local).is_err());
+    }
+
+    #[test]
+    fn follows_includes() {
+        let mut temp = TempRepo::new();
+        let root = temp.path().display().to_string();
+        temp.set_env(env::GIT_CONFIG_GLOBAL, format!("{root}/gitconfig"));
+
+        fs::create_dir_all(temp.join("repo/.git")).unwrap();
+        fs::write(temp.join("repo/.git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        fs::write(
+            temp.join("gitconfig"),
+            format!(
+                "[user]\n\tname = Global\n\
+                 [include]\n\tpath = plain\n\
+                 [includeIf \"gitdir:{root}/repo/\"]\n\tpath = work\n\
+                 [includeIf \"gitdir:./repo/\"]\n\tpath = repo/local\n\
+                 [includeIf \"gitdir:other/\"]\n\tpath = other\n\
+                 [includeIf \"gitdir/i:REPO/.GIT\"]\n\tpath = icase\n\
+                 [includeIf \"onbranch:feature/\"]\n\tpath = branch\n\
+                 [includeIf \"onbranch:main\"]\n\tpath = other\n"
+            ),
+        )
+        .unwrap();
+        fs::write(temp.join("plain"), "[a]\n\tplain = true\n").unwrap();
+        fs::write(temp.join("work"), "[user]\n\tname = Work\n").unwrap();
+        fs::write(temp.join("repo/local"), "[a]\n\tlocal = true\n").unwrap();
+        fs::write(temp.join("icase"), "[a]\n\ticase = true\n").unwrap();
+        fs::write(temp.join("branch"), "[a]\n\tbranch = true\n").unwrap();
+        fs::write(temp.join("other"), "[a]\n\tother = true\n").unwrap();
+
+        let repo = temp.repo_at("repo");
+        let config = Config::load(&repo).unwrap();
+        assert_eq!(config.get("user.name"), Some("Work"));
+        for key in ["a.plain", "a.local", "a.icase", "a.branch"] {
+            assert_eq!(config.get(key), Some("true"), "{key}");
+        }
+        assert_eq!(config.get("a.other"), None);
+
+        // Circular includes are stopped
+        fs::write(temp.join("gitconfig"), "[include]\n\tpath = gitconfig\n").unwrap();
+        let err = Config::load(&repo).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("exceeded maximum include depth"));
+    }
+
+    #[test]
+    fn reads_command_scope() {
+        let mut temp = TempRepo::new()
+            .env(env::GIT_CONFIG_COUNT, "2")
+            .env("GIT_CONFIG_KEY_0", "User.Name")
+            .env("GIT_CONFIG_VALUE_0", "From Env")
+            .env("GIT_CONFIG_KEY_1", "url.https://example.com/.insteadOf")
+            .env("GIT_CONFIG_VALUE_1", "ex:");
+
+        let mut env = temp.repo().env().clone();
+        let config = Config::load_protected(&env).unwrap();
+        assert_eq!(config.get("user.name"), Some("From Env"));
+        assert_eq!(
+            config.get("url.https://example.com/.insteadof"),
+            Some("ex:")
+        );
+
+        // Parameters from the command line come last
+        add_config_parameter(&mut env, "user.name=It's me").unwrap();
+        add_config_parameter(&mut env, "core.bare").unwrap();
+        add_config_parameter(&mut env, "user.email=").unwrap();
+        assert_eq!(
+            env.var(env::GIT_CONFIG_PARAMETERS).unwrap(),
+            "'user.name'='It'\\''s me' 'core.bare'= 'user.email'=''"
+        );
+        add_config_parameter(&mut env, "alias.e=!env").unwrap();
+        let config = Config::load_protected(&env).unwrap();
+        assert_eq!(config.get("user.name"), Some("It's me"));
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get("user.email"), Some(""));
+        assert_eq!(config.get("alias.e"), Some("!env"));
+
+        let err = add_config_parameter(&mut env, "nosection=1").unwrap_err();
+        assert_eq!(err.to_string(), "key does not contain a section: nosection");
+        assert!(add_config_parameter(&mut env, "a.1b=1").is_err());
+
+        temp.set_env(env::GIT_CONFIG_COUNT, "3");
+        let err = Config::load_protected(temp.repo().env()).unwrap_err();
+        assert_eq!(err.to_string(), "missing config key GIT_CONFIG_KEY_2");
+    }
+
+    #[test]
+    fn sets_values_in_a_file() {
+        let temp = TempRepo::new();
+        let path = &temp.join("config");
+        fs::write(path, "[core]\n\tbare = false\n[user]\n\tname = Me\n").unwrap();
+
+        set_config_value(path, "core.bare", "true").unwrap();
+        set_config_value(path, "core.sparseCheckout", "true").unwrap();
+        set_config_value(path, "remote.origin.url", "here").unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "[core]\n\tbare = true\n\tsparseCheckout = true\n[user]\n\tname = Me\n\
+             [remote \"origin\"]\n\turl = here\n"
+        );
+
+        set_config_value(&temp.join("new"), "Core.Bare", "false").unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("new")).unwrap(),
+            "[Core]\n\tBare = false\n"
+        );
+    }
+}
@@ -0,0 +1,478 @@
+//! Converting line endings between the working tree and the object database
+//!
+//! Which files are converted is decided by the `text` and `eol` attributes, or by
+//! `core.autocrlf` for the files without a `text` attribute:
+//!
+//! - Text files (`text`, or `eol` set) are stored with LF line endings, and get
+//!   the line endings of `eol` (or `core.autocrlf`, then `core.eol`) when checked out
+//! - Files with `text=auto` (or any file with `core.autocrlf` set to `true` or `input`)
+//!   are only converted if they look like text and the staged version has no CR
+//! - Other files (`-text`, including `binary`) are never converted
+//!
+//! When adding a file would not round-trip through a checkout (e.g. a CRLF file
+//! stored with LF but checked out with LF), `core.safecrlf` decides whether to
+//! warn (the default), to refuse or to do nothing.
+//!
+//! Files with `filter=lfs` are also replaced by Git LFS pointer files when added,
+//! and pointer files by their content when checked out, if the content is local.
+
+use anyhow::Context;
+
+use crate::utils::advice::warning;
+use crate::utils::attributes::{AttrValue, Attributes};
+use crate::utils::config::{parse_bool, Config};
+use crate::utils::index::Index;
+use crate::utils::lfs;
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::repository::Repository;
+
+/// How the line endings of a file are converted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CrlfAction {
+    /// Never converted
+    Binary,
+    /// Stored with LF, checked out with the default line endings
+    Text,
+    /// Stored with LF, checked out as is
+    TextInput,
+    /// Stored with LF, checked out with CRLF
+    TextCrlf,
+    /// Like `Text`, if the file looks like text
+    Auto,
+    /// Like `TextInput`, if the file looks like text
+    AutoInput,
+    /// Like `TextCrlf`, if the file looks like text
+    AutoCrlf,
+}
+
+impl CrlfAction {
+    fn is_auto(self) -> bool {
+        matches!(
+            self,
+            CrlfAction::Auto | CrlfAction::AutoInput | CrlfAction::AutoCrlf
+        )
+    }
+}
+
+/// What to do when adding a file would not round-trip (`core.safecrlf`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SafeCrlf {
+    Ignore,
+    Warn,
+    Fail,
+}
+
+/// The line endings of a content
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct TextStats {
+    /// LFs not preceded by a CR
+    lone_lf: usize,
+    /// CRs not followed by a LF
+    lone_cr: usize,
+    crlf: usize,
+    nul: usize,
+    printable: usize,
+    non_printable: usize,
+}
+
+impl TextStats {
+    fn gather(content: &[u8]) -> Self {
+        let mut stats = TextStats::default();
+        let mut i = 0;
+        while i < content.len() {
+            match content[i] {
+                b'\r' if content.get(i + 1) == Some(&b'\n') => {
+                    stats.crlf += 1;
+                    i += 1;
+                },
+                b'\r' => stats.lone_cr += 1,
+                b'\n' => stats.lone_lf += 1,
+                0x7f => stats.non_printable += 1,
+                // Backspace, tab, escape and form feed are common in text
+                0x08 | b'\t' | 0x1b | 0x0c => stats.printable += 1,
+                0 => {
+                    stats.nul += 1;
+                    stats.non_printable += 1;
+                },
+                c if c < 0x20 => stats.non_printable += 1,
+                _ => stats.printable += 1,
+            }
+            i += 1;
+        }
+        // A DOS end-of-file marker is not counted
+        if content.last() == Some(&0x1a) {
+            stats.non_printable -= 1;
+        }
+        stats
+    }
+
+    /// Whether the content looks binary, as guessed by `text=auto`.
+    fn is_binary(&self) -> bool {
+        self.lone_cr > 0 || self.nul > 0 || (self.printable >> 7) < self.non_printable
+    }
+}
+
+/// The line ending conversions of a repository, for the files being added or
+/// checked out by a command
+#[derive(Debug)]
+pub(crate) struct Conversion<'a> {
+    repo: &'a Repository,
+    attributes: Attributes,
+    /// `core.autocrlf`: `None` if false, `Some(true)` if true and `Some(false)` for `input`
+    auto_crlf: Option<bool>,
+    /// Whether `core.eol` is `crlf`
+    eol_crlf: bool,
+    safe_crlf: SafeCrlf,
+    /// The index, read when a staged file is first needed
+    index: Option<Index>,
+}
+
+impl<'a> Conversion<'a> {
+    /// Load the conversions of the files of the working tree.
+    pub(crate) fn load(repo: &'a Repository) -> anyhow::Result<Self> {
+        Self::new(repo, Attributes::load(repo)?)
+    }
+
+    /// Load the conversions of the files of a tree being checked out,
+    /// using the `.gitattributes` files of the tree.
+    pub(crate) fn load_tree(repo: &'a Repository, tree: &str) -> anyhow::Result<Self> {
+        Self::new(repo, Attributes::load_tree(repo, tree)?)
+    }
+
+    fn new(repo: &'a Repository, attributes: Attributes) -> anyhow::Result<Self> {
+        let config = Config::load(repo)?;
+        let auto_crlf = match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => Some(false),
+            Some(value) => parse_bool(value)
+                .context("bad boolean config value for core.autocrlf")?
+                .then_some(true),
+            None => None,
+        };
+        let safe_crlf = match config.get("core.safecrlf") {
+            Some(value) if value.eq_ignore_ascii_case("warn") => SafeCrlf::Warn,
+            Some(value) => match parse_bool(value).context("bad config value for core.safecrlf")? {
+                true => SafeCrlf::Fail,
+                false => SafeCrlf::Ignore,
+            },
+            None => SafeCrlf::Warn,
+        };
+        let eol_crlf = match config.get("core.eol") {
+            Some("crlf") => true,
+            Some("lf" | "native") | None => false,
+            Some(value) => anyhow::bail!("bad config value for core.eol: {value}"),
+        };
+
+        Ok(Conversion {
+            repo,
+            attributes,
+            auto_crlf,
+            eol_crlf,
+            safe_crlf,
+            index: None,
+        })
+    }
+
+    /// Do not check whether added files round-trip, as when only hashing them.
+    pub(crate) fn without_safe_crlf(self) -> Self {
+        Conversion {
+            safe_crlf: SafeCrlf::Ignore,
+            ..self
+        }
+    }
+
+    /// Decide how a file is converted from its attributes and the configuration.
+    fn action(&mut self, path: &str) -> anyhow::Result<CrlfAction> {
+        let values = self.attributes.check(path, &["text", "eol"])?;
+        let mut action = match &values[0] {
+            AttrValue::Set => Some(CrlfAction::Text),
+            AttrValue::Unset => return Ok(CrlfAction::Binary),
+            AttrValue::Value(value) if value == "auto" => Some(CrlfAction::Auto),
+            AttrValue::Value(value) if value == "input" => Some(CrlfAction::TextInput),
+            _ => None,
+        };
+
+        // `eol` makes a file text, unless `text=auto` leaves the guess to the content
+        action = match (&values[1], action) {
+            (AttrValue::Value(eol), Some(CrlfAction::Auto)) if eol == "lf" => {
+                Some(CrlfAction::AutoInput)
+            },
+            (AttrValue::Value(eol), Some(CrlfAction::Auto)) if eol == "crlf" => {
+                Some(CrlfAction::AutoCrlf)
+            },
+            (AttrValue::Value(eol), _) if eol == "lf" => Some(CrlfAction::TextInput),
+            (AttrValue::Value(eol), _) if eol == "crlf" => Some(CrlfAction::TextCrlf),
+            (_, action) => action,
+        };
+
+        Ok(match (action, self.auto_crlf) {
+            (Some(CrlfAction::Text), _) if self.text_eol_is_crlf() => CrlfAction::TextCrlf,
+            (Some(CrlfAction::Text), _) => CrlfAction::TextInput,
+            (Some(action), _) => action,
+            (None, None) => CrlfAction::Binary,
+            (None, Some(true)) => CrlfAction::AutoCrlf,
+            (None, Some(false)) => CrlfAction::AutoInput,
+        })
+    }
+
+    /// Whether a file is stored with Git LFS (`filter=lfs`).
+    fn is_lfs(&mut self, path: &str) -> anyhow::Result<bool> {
+        let values = self.attributes.check(path, &["filter"])?;
+        Ok(matches!(&values[0], AttrValue::Value(filter) if filter == "lfs"))
+    }
+
+    /// Whether text files are checked out with CRLF when nothing else says so.
+    fn text_eol_is_crlf(&self) -> bool {
+        match self.auto_crlf {
+            Some(crlf) => crlf,
+            None => self.eol_crlf,
+        }
+    }
+
+    /// Whether the line endings of a file are converted to CRLF when checked out.
+    fn checks_out_crlf(&self, action: CrlfAction, stats: &TextStats) -> bool {
+        let crlf = match action {
+            CrlfAction::Binary | CrlfAction::TextInput | CrlfAction::AutoInput => false,
+            CrlfAction::TextCrlf | CrlfAction::AutoCrlf => true,
+            CrlfAction::Text | CrlfAction::Auto => self.text_eol_is_crlf(),
+        };
+        if !crlf || stats.lone_lf == 0 {
+            return false;
+        }
+        // Guessed text files are left alone if they already have CRs
+        !(action.is_auto() && (stats.lone_cr > 0 || stats.crlf > 0 || stats.is_binary()))
+    }
+
+    /// Whether the version of a file staged in the index contains a CR.
+    fn has_cr_in_index(&mut self, path: &str) -> anyhow::Result<bool> {
+        let index = match self.index.take() {
+            Some(index) => index,
+            None => Index::load(self.repo)?,
+        };
+        let index = self.index.insert(index);
+        match index.entry(path.as_bytes(), 0) {
+            Some(entry) => {
+                Ok(read_object_of_type(self.repo, &entry.hash, ObjectType::Blob)?.contains(&b'\r'))
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Convert the content of a file of the working tree to the content stored
+    /// in the object database (CRLF to LF for text files, and the content of
+    /// LFS files to their pointer).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file, relative to the root of the working tree
+    /// * `content` - The content of the file
+    pub(crate) fn convert_to_git(
+        &mut self,
+        path: &str,
+        mut content: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        if self.is_lfs(path)? {
+            content = lfs::clean(self.repo, content)?;
+        }
+
+        let action = self.action(path)?;
+        if action == CrlfAction::Binary || content.is_empty() {
+            return Ok(content);
+        }
+
+        let stats = TextStats::gather(&content);
+        let mut convert = stats.crlf > 0;
+        if action.is_auto() {
+            if stats.is_binary() {
+                return Ok(content);
+            }
+            // Files staged with CRs keep them, so they do not suddenly change
+            if convert && self.has_cr_in_index(path)? {
+                convert = false;
+            }
+        }
+
+        if self.safe_crlf != SafeCrlf::Ignore {
+            self.check_round_trip(path, action, &stats, convert)?;
+        }
+
+        if !convert {
+            return Ok(content);
+        }
+        let mut converted = Vec::with_capacity(content.len());
+        for (i, &byte) in content.iter().enumerate() {
+            if byte != b'\r' || content.get(i + 1) != Some(&b'\n') {
+                converted.push(byte);
+            }
+        }
+        Ok(converted)
+    }
+
+    /// Warn about (or refuse) a file whose line endings would change
+    /// after being added then checked out.
+    fn check_round_trip(
+        &self,
+        path: &str,
+        action: CrlfAction,
+        stats: &TextStats,
+        convert: bool,
+    ) -> anyhow::Result<()> {
+        // Simulate adding then checking out the file
+        let mut new_stats = *stats;
+        if convert {
+            new_stats.lone_lf += new_stats.crlf;
+            new_stats.crlf = 0;
+        }
+        if self.checks_out_crlf(action, &new_stats) {
+            new_stats.crlf += new_stats.lone_lf;
+            new_stats.lone_lf = 0;
+        }
+
+        let (from, to) = if stats.crlf > 0 && new_stats.crlf == 0 {
+            ("CRLF", "LF")
+        } else if stats.lone_lf > 0 && new_stats.lone_lf == 0 {
+            ("LF", "CRLF")
+        } else {
+            return Ok(());
+        };
+        match self.safe_crlf {
+            SafeCrlf::Fail => anyhow::bail!("{from} would be replaced by {to} in {path}"),
+            SafeCrlf::Warn => warning(&format!(
+                "in the working copy of '{path}', {from} will be replaced by {to} \
+                 the next time Git touches it"
+            )),
+            SafeCrlf::Ignore => {},
+        }
+        Ok(())
+    }
+
+    /// Convert the content of a blob to the content of its file in the working
+    /// tree (LF to CRLF for text files checked out with CRLF, and LFS pointers
+    /// to their content).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file, relative to the root of the working tree
+    /// * `content` - The content of the blob
+    pub(crate) fn convert_to_work_tree(
+        &mut self,
+        path: &str,
+        content: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let content = self.convert_line_endings(path, content)?;
+        match self.is_lfs(path)? {
+            true => lfs::smudge(self.repo, content),
+            false => Ok(content),
+        }
+    }
+
+    /// Convert LF to CRLF in a blob checked out with CRLF.
+    fn convert_line_endings(&mut self, path: &str, content: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let action = self.action(path)?;
+        if action == CrlfAction::Binary || content.is_empty() {
+            return Ok(content);
+        }
+
+        let stats = TextStats::gather(&content);
+        if !self.checks_out_crlf(action, &stats) {
+            return Ok(content);
+        }
+
+        let mut converted = Vec::with_capacity(content.len() + stats.lone_lf);
+        for (i, &byte) in content.iter().enumerate() {
+            if byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+                converted.push(b'\r');
+            }
+            converted.push(byte);
+        }
+        Ok(converted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::objects::write_object;
+    use crate::utils::test::TempRepo;
+
+    fn setup(config: &str, attributes: &str) -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        fs::write(temp.join(".git/config"), config).unwrap();
+        fs::write(temp.join(".gitattributes"), attributes).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    #[test]
+    fn converts_text_files() {
+        let (_temp, repo) = setup(
+            "",
+            "*.txt text\n*.crlf eol=crlf\n*.bin binary\n*.auto text=auto eol=crlf\n",
+        );
+        let mut conversion = Conversion::load(&repo).unwrap();
+        let mut to_git =
+            |path: &str, content: &[u8]| conversion.convert_to_git(path, content.to_vec());
+
+        assert_eq!(to_git("a.txt", b"a\r\nb\n").unwrap(), b"a\nb\n");
+        assert_eq!(to_git("a.bin", b"a\r\nb\n").unwrap(), b"a\r\nb\n");
+        assert_eq!(to_git("other", b"a\r\n").unwrap(), b"a\r\n");
+        assert_eq!(to_git("a.auto", b"a\r\nb\r\n").unwrap(), b"a\nb\n");
+        // Guessed text files with lone CRs or NULs are binary
+        assert_eq!(to_git("b.auto", b"a\rb\r\n").unwrap(), b"a\rb\r\n");
+        assert_eq!(to_git("c.auto", b"a\0\r\n").unwrap(), b"a\0\r\n");
+
+        let mut conversion = Conversion::load(&repo).unwrap();
+        let mut to_work_tree = |path: &str, content: &[u8]| {
+            conversion
+                .convert_to_work_tree(path, content.to_vec())
+                .unwrap()
+        };
+        assert_eq!(to_work_tree("a.txt", b"a\nb\n"), b"a\nb\n");
+        assert_eq!(to_work_tree("a.crlf", b"a\r\nb\n"), b"a\r\nb\r\n");
+        assert_eq!(to_work_tree("a.auto", b"a\nb\n"), b"a\r\nb\r\n");
+        assert_eq!(to_work_tree("b.auto", b"a\r\nb\n"), b"a\r\nb\n");
+    }
+
+    #[test]
+    fn follows_autocrlf_and_safecrlf() {
+        let (_temp, repo) = setup(
+            "[core]\n\tautocrlf = true\n\tsafecrlf = true\n",
+            "*.lf eol=lf\n",
+        );
+        let mut conversion = Conversion::load(&repo).unwrap();
+        assert_eq!(
+            conversion.convert_to_git("a", b"a\r\n".to_vec()).unwrap(),
+            b"a\n"
+        );
+        assert_eq!(
+            conversion
+                .convert_to_work_tree("a", b"a\n".to_vec())
+                .unwrap(),
+            b"a\r\n"
+        );
+
+        // Files that would not round-trip are refused
+        let err = conversion
+            .convert_to_git("b", b"a\nb\r\n".to_vec())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "LF would be replaced by CRLF in b");
+        let err = conversion
+            .convert_to_git("b.lf", b"a\r\n".to_vec())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "CRLF would be replaced by LF in b.lf");
+
+        // Files staged with CRs are not converted
+        let hash = write_object(&repo, ObjectType::Blob, b"a\r\n").unwrap();
+        let mut index = Index::default();
+        index.add_entry(IndexEntry::new("c".into(), 0o100644, hash));
+        index.write(&repo).unwrap();
+        let mut conversion = Conversion::load(&repo).unwrap();
+        assert_eq!(
+            conversion.convert_to_git("c", b"b\r\n".to_vec()).unwrap(),
+            b"b\r\n"
+        );
+    }
+}
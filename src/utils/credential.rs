@@ -0,0 +1,473 @@
+//! Getting the credentials of HTTP servers from credential helpers, or by
+//! asking the user, as `git credential`
+//!
+//! Credentials are described by `key=value` lines ended by an empty line (or
+//! the end of the input): `protocol`, `host` (with its port), `path`,
+//! `username` and `password` (and `url`, which sets the other fields).
+//!
+//! - `credential.helper` (or `credential.<url>.helper`) is run with `get` to
+//!   fill a credential, `store` once it was accepted by the server, and
+//!   `erase` once it was rejected. It may be set more than once, each helper
+//!   being run in turn, and an empty value drops the helpers set before it.
+//!   A helper is run as `git credential-<helper>`, unless it is an absolute
+//!   path or a shell command starting with `!`
+//! - `credential.username` is the username used when the URL has none
+//! - `credential.useHttpPath` tells helpers the path of HTTP URLs, so that
+//!   each repository of a server can have its own credentials
+//! - What the helpers do not fill is asked with `$GIT_ASKPASS`,
+//!   `core.askPass` or `$SSH_ASKPASS`, or on the terminal unless
+//!   `$GIT_TERMINAL_PROMPT` is `0`
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+use anyhow::Context;
+
+use crate::utils::config::{parse_bool, Config};
+use crate::utils::env;
+use crate::utils::http::url_matches;
+use crate::utils::protocol::http_backend::percent_decode;
+use crate::utils::repository::Repository;
+
+/// The credential of a server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Credential {
+    pub(crate) protocol: String,
+    /// The host, with its port if it is not the default one
+    pub(crate) host: String,
+    /// The path of the URL, only given to helpers with `credential.useHttpPath`
+    pub(crate) path: Option<String>,
+    pub(crate) username: Option<String>,
+    pub(crate) password: Option<String>,
+}
+
+impl Credential {
+    /// Describe the credential of a URL, with the username and password it
+    /// contains (if any).
+    pub(crate) fn from_url(url: &str) -> anyhow::Result<Self> {
+        let (protocol, rest) = url
+            .split_once("://")
+            .context(format!("invalid credential url '{url}'"))?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user_info, host) = match authority.rsplit_once('@') {
+            Some((user_info, host)) => (Some(user_info), host),
+            None => (None, authority),
+        };
+        let (username, password) = match user_info {
+            Some(user_info) => match user_info.split_once(':') {
+                Some((username, password)) => (username, Some(password)),
+                None => (user_info, None),
+            },
+            None => ("", None),
+        };
+        // The query and fragment are not part of the path
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+
+        Ok(Credential {
+            protocol: protocol.to_string(),
+            host: host.to_string(),
+            path: (!path.is_empty()).then(|| path.to_string()),
+            username: match username {
+                "" => None,
+                username => Some(percent_decode(username)?),
+            },
+            password: password.map(percent_decode).transpose()?,
+        })
+    }
+
+    /// Read a credential in the format of the credential protocol.
+    pub(crate) fn read<R>(reader: &mut R) -> anyhow::Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut credential = Credential::default();
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .context(format!("invalid credential line: {line}"))?;
+            match key {
+                "protocol" => credential.protocol = value.to_string(),
+                "host" => credential.host = value.to_string(),
+                "path" => credential.path = Some(value.to_string()),
+                "username" => credential.username = Some(value.to_string()),
+                "password" => credential.password = Some(value.to_string()),
+                "url" => {
+                    let url = Credential::from_url(value)?;
+                    credential = Credential {
+                        username: url.username.or(credential.username),
+                        password: url.password.or(credential.password),
+                        ..url
+                    };
+                },
+                // Other attributes (e.g. `quit` or `password_expiry_utc`) are ignored
+                _ => {},
+            }
+        }
+        Ok(credential)
+    }
+
+    /// Write the credential in the format of the credential protocol.
+    pub(crate) fn write<W>(&self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let fields = [
+            ("protocol", Some(&self.protocol)),
+            ("host", Some(&self.host)),
+            ("path", self.path.as_ref()),
+            ("username", self.username.as_ref()),
+            ("password", self.password.as_ref()),
+        ];
+        for (key, value) in fields {
+            let Some(value) = value.filter(|value| !value.is_empty()) else {
+                continue;
+            };
+            if value.contains(['\n', '\0']) {
+                anyhow::bail!("credential value for {key} contains invalid characters");
+            }
+            writeln!(writer, "{key}={value}")?;
+        }
+        Ok(())
+    }
+
+    /// The URL the credential is for, as matched by `credential.<url>.*`.
+    fn url(&self) -> String {
+        match &self.path {
+            Some(path) => format!("{}://{}/{path}", self.protocol, self.host),
+            None => format!("{}://{}", self.protocol, self.host),
+        }
+    }
+
+    /// Whether the credential is for the same server (and path, if any).
+    pub(crate) fn matches(&self, other: &Credential) -> bool {
+        self.protocol == other.protocol
+            && self.host == other.host
+            && (self.path.is_none() || self.path == other.path)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.username.is_some() && self.password.is_some()
+    }
+}
+
+/// What credentials are filled with, from the settings of a repository
+#[derive(Debug, Clone)]
+pub(crate) struct Credentials {
+    /// The repository the helpers are run from, with its environment
+    repo: Repository,
+    /// The `credential.*` settings (without the prefix), with the URL they
+    /// are restricted to (if any), in the order they were configured
+    settings: Vec<(Option<String>, String, String)>,
+    askpass: Option<String>,
+    /// Whether the user may be asked on the terminal (`$GIT_TERMINAL_PROMPT`)
+    terminal_prompt: bool,
+}
+
+impl Credentials {
+    /// Load the credential settings of a repository.
+    pub(crate) fn new(repo: &Repository) -> anyhow::Result<Self> {
+        let config = Config::load(repo)?;
+        let settings = config
+            .entries()
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.key.strip_prefix("credential.")?;
+                let (url, name) = match name.rsplit_once('.') {
+                    Some((url, name)) => (Some(url.to_string()), name),
+                    None => (None, name),
+                };
+                let value = entry.value.clone().unwrap_or_else(|| "true".to_string());
+                Some((url, name.to_string(), value))
+            })
+            .collect();
+
+        let env = repo.env();
+        let askpass = env
+            .var(env::GIT_ASKPASS)
+            .or_else(|| config.get("core.askPass"))
+            .or_else(|| env.var(env::SSH_ASKPASS))
+            .filter(|askpass| !askpass.is_empty())
+            .map(str::to_string);
+        let terminal_prompt = env
+            .var(env::GIT_TERMINAL_PROMPT)
+            .is_none_or(|prompt| prompt != "0");
+
+        Ok(Credentials {
+            repo: repo.clone(),
+            settings,
+            askpass,
+            terminal_prompt,
+        })
+    }
+
+    /// The values of a setting that apply to a credential, in the order they
+    /// were configured.
+    fn values<'s>(&'s self, credential: &Credential, name: &str) -> Vec<&'s str> {
+        let url = credential.url();
+        self.settings
+            .iter()
+            .filter(|(prefix, key, _)| {
+                key == name
+                    && prefix
+                        .as_deref()
+                        .is_none_or(|prefix| url_matches(prefix, &url))
+            })
+            .map(|(_, _, value)| value.as_str())
+            .collect()
+    }
+
+    fn helpers(&self, credential: &Credential) -> Vec<&str> {
+        let mut helpers = Vec::new();
+        for helper in self.values(credential, "helper") {
+            match helper.is_empty() {
+                true => helpers.clear(),
+                false => helpers.push(helper),
+            }
+        }
+        helpers
+    }
+
+    /// Drop the path of HTTP credentials unless `credential.useHttpPath` is
+    /// set, and set the username of `credential.username` if there is none.
+    fn prepare(&self, credential: &Credential) -> anyhow::Result<Credential> {
+        let mut credential = credential.clone();
+        if credential.protocol.starts_with("http") {
+            let use_http_path = self
+                .values(&credential, "usehttppath")
+                .last()
+                .map(|value| parse_bool(value))
+                .transpose()?
+                .unwrap_or(false);
+            if !use_http_path {
+                credential.path = None;
+            }
+        }
+        if credential.username.is_none() {
+            let username = self.values(&credential, "username").last().copied();
+            credential.username = username.map(str::to_string);
+        }
+        Ok(credential)
+    }
+
+    /// Fill the username and password of a credential, from the helpers then
+    /// by asking the user.
+    pub(crate) fn fill(&self, credential: &Credential) -> anyhow::Result<Credential> {
+        let mut credential = self.prepare(credential)?;
+        for helper in self.helpers(&credential) {
+            if credential.is_complete() {
+                break;
+            }
+            let answer = run_helper(&self.repo, helper, "get", &credential)?;
+            if answer
+                .lines()
+                .any(|line| line == "quit=1" || line == "quit=true")
+            {
+                anyhow::bail!("credential helper '{helper}' told us to quit");
+            }
+            let answer = Credential::read(&mut answer.as_bytes())?;
+            credential.username = answer.username.or(credential.username);
+            credential.password = answer.password.or(credential.password);
+        }
+
+        if credential.username.is_none() {
+            credential.username = Some(self.prompt(&credential, "Username", true)?);
+        }
+        if credential.password.is_none() {
+            credential.password = Some(self.prompt(&credential, "Password", false)?);
+        }
+        Ok(credential)
+    }
+
+    /// Tell the helpers that a credential was accepted, for them to store it.
+    pub(crate) fn approve(&self, credential: &Credential) -> anyhow::Result<()> {
+        let credential = self.prepare(credential)?;
+        if !credential.is_complete() {
+            return Ok(());
+        }
+        for helper in self.helpers(&credential) {
+            run_helper(&self.repo, helper, "store", &credential)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the helpers that a credential was rejected, for them to erase it.
+    pub(crate) fn reject(&self, credential: &Credential) -> anyhow::Result<()> {
+        let credential = self.prepare(credential)?;
+        for helper in self.helpers(&credential) {
+            run_helper(&self.repo, helper, "erase", &credential)?;
+        }
+        Ok(())
+    }
+
+    /// Ask the user for the username or password of a credential.
+    fn prompt(&self, credential: &Credential, what: &str, echo: bool) -> anyhow::Result<String> {
+        let url = match (what, &credential.username) {
+            ("Password", Some(username)) => {
+                format!("{}://{username}@{}", credential.protocol, credential.host)
+            },
+            _ => format!("{}://{}", credential.protocol, credential.host),
+        };
+        let prompt = format!("{what} for '{url}': ");
+
+        if let Some(askpass) = &self.askpass {
+            let output = self
+                .repo
+                .command("sh")
+                .arg("-c")
+                .arg(format!("{askpass} \"$@\""))
+                .arg(askpass)
+                .arg(&prompt)
+                .stdin(Stdio::null())
+                .stderr(Stdio::inherit())
+                .output()
+                .context(format!("unable to run askpass helper '{askpass}'"))?;
+            if !output.status.success() {
+                anyhow::bail!("could not read {what} for '{url}': askpass helper failed");
+            }
+            let answer = String::from_utf8_lossy(&output.stdout);
+            return Ok(answer.trim_end_matches(['\r', '\n']).to_string());
+        }
+
+        if !self.terminal_prompt {
+            anyhow::bail!("could not read {what} for '{url}': terminal prompts disabled");
+        }
+        let mut tty = File::options()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .context(format!(
+                "could not read {what} for '{url}': No such device or address"
+            ))?;
+        tty.write_all(prompt.as_bytes())?;
+        tty.flush()?;
+        // The password is not shown while it is typed
+        let set_echo = |echo: &str| {
+            Command::new("stty")
+                .arg(echo)
+                .stdin(File::open("/dev/tty")?)
+                .status()
+        };
+        if !echo {
+            set_echo("-echo")?;
+        }
+        let mut answer = String::new();
+        let read = BufReader::new(&tty).read_line(&mut answer);
+        if !echo {
+            set_echo("echo")?;
+            tty.write_all(b"\n")?;
+        }
+        read.context(format!("could not read {what} for '{url}'"))?;
+        Ok(answer.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Run a credential helper, giving it a credential.
+///
+/// # Arguments
+///
+/// * `repo` - The repository the helper is run from
+/// * `helper` - The helper, as configured by `credential.helper`
+/// * `action` - `get`, `store` or `erase`
+/// * `credential` - The credential given to the helper
+///
+/// # Returns
+///
+/// What the helper answered (only read for `get`)
+fn run_helper(
+    repo: &Repository,
+    helper: &str,
+    action: &str,
+    credential: &Credential,
+) -> anyhow::Result<String> {
+    let command = match helper.strip_prefix('!') {
+        Some(command) => command.to_string(),
+        None if helper.starts_with('/') => helper.to_string(),
+        None => format!("git credential-{helper}"),
+    };
+    let mut child = repo
+        .command("sh")
+        .arg("-c")
+        .arg(format!("{command} {action}"))
+        .stdin(Stdio::piped())
+        .stdout(match action {
+            "get" => Stdio::piped(),
+            _ => Stdio::null(),
+        })
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context(format!("unable to run credential helper '{helper}'"))?;
+
+    let mut input = Vec::new();
+    credential.write(&mut input)?;
+    input.push(b'\n');
+    // A helper may exit without reading what it does not need
+    let _ = child.stdin.take().unwrap().write_all(&input);
+    let output = child.wait_with_output()?;
+    // A failing helper is skipped, as git does
+    if !output.status.success() {
+        return Ok(String::new());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn fills_credentials_from_helpers() {
+        let mut temp = TempRepo::new().env(env::GIT_TERMINAL_PROMPT, "0");
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        // The first helper only knows the username, the second logs what it is told
+        fs::write(
+            temp.join(".git/config"),
+            "[credential]\n\thelper = dropped\n\thelper =\n\
+             \thelper = \"!f() { test $1 = get && echo username=alice; }; f\"\n\
+             \thelper = \"!f() { echo $1 >>log; cat >>log; echo password=secret; }; f\"\n\
+             [credential \"https://other.example.com\"]\n\thelper = \"!exit 1\"\n",
+        )
+        .unwrap();
+        let repo = temp.repo();
+        let credentials = Credentials::new(&repo).unwrap();
+
+        let url = Credential::from_url("https://example.com/repo.git").unwrap();
+        let credential = credentials.fill(&url).unwrap();
+        assert_eq!(credential, Credential {
+            protocol: "https".to_string(),
+            host: "example.com".to_string(),
+            path: None,
+            username: Some("alice".to_string()),
+            password: Some("secret".to_string()),
+        });
+        credentials.approve(&credential).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("log")).unwrap(),
+            "get\nprotocol=https\nhost=example.com\nusername=alice\n\n\
+             store\nprotocol=https\nhost=example.com\nusername=alice\npassword=secret\n\n"
+        );
+
+        // What no helper fills is asked, unless prompts are disabled
+        fs::write(temp.join(".git/config"), "").unwrap();
+        let credentials = Credentials::new(&repo).unwrap();
+        let url = Credential::from_url("https://bob@example.com:8443/repo.git").unwrap();
+        assert_eq!(
+            credentials.fill(&url).unwrap_err().to_string(),
+            "could not read Password for 'https://bob@example.com:8443': terminal prompts disabled"
+        );
+        temp.set_env(env::GIT_ASKPASS, "echo");
+        let repo = temp.repo();
+        let credentials = Credentials::new(&repo).unwrap();
+        assert_eq!(
+            credentials.fill(&url).unwrap().password.as_deref(),
+            Some("Password for 'https://bob@example.com:8443': ")
+        );
+    }
+}
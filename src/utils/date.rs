@@ -0,0 +1,204 @@
+//! Parsing and formatting commit timestamps
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A broken-down date and time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub(crate) year: i64,
+    /// The month (1-12)
+    pub(crate) month: u32,
+    /// The day of the month (1-31)
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    /// The day of the week (0 is Sunday)
+    pub(crate) weekday: u32,
+}
+
+impl DateTime {
+    /// Break down a Unix timestamp, shifted by a timezone offset in seconds.
+    pub(crate) fn from_timestamp(timestamp: i64, offset_seconds: i64) -> Self {
+        let local = timestamp + offset_seconds;
+        let days = local.div_euclid(86400);
+        let seconds = local.rem_euclid(86400);
+
+        // Convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+
+        DateTime {
+            year: year_of_era + era * 400 + i64::from(month <= 2),
+            month: month as u32,
+            day: day as u32,
+            hour: (seconds / 3600) as u32,
+            minute: (seconds % 3600 / 60) as u32,
+            second: (seconds % 60) as u32,
+            // The epoch was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+}
+
+/// Parse a timezone offset (`+0100`, `-0530`) into seconds.
+pub(crate) fn parse_offset(offset: &str) -> Option<i64> {
+    let (sign, digits) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parse a date as accepted by `$GIT_AUTHOR_DATE` and `$GIT_COMMITTER_DATE`:
+/// git's internal format (`[@]<timestamp> <offset>`) or ISO 8601
+/// (`YYYY-MM-DD[T ]HH:MM:SS[ <offset>]`, in UTC without an offset).
+///
+/// # Returns
+///
+/// The Unix timestamp and the timezone offset as written (e.g. `+0100`),
+/// or `None` if the date is not in a supported format
+pub(crate) fn parse_date(date: &str) -> Option<(i64, String)> {
+    let date = date.trim();
+
+    let raw = date.strip_prefix('@').unwrap_or(date);
+    if let Some((timestamp, offset)) = raw.split_once(' ') {
+        if let Ok(timestamp) = timestamp.parse() {
+            parse_offset(offset)?;
+            return Some((timestamp, offset.to_string()));
+        }
+    }
+
+    let (date, offset) = match date.rsplit_once(' ') {
+        Some((date, offset)) if parse_offset(offset).is_some() => (date, offset),
+        _ => (date, "+0000"),
+    };
+    let (day, time) = date.split_once(['T', ' '])?;
+
+    let mut day = day.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (day.next()?.ok()?, day.next()?.ok()?, day.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    // Convert the civil date to days since the epoch (Howard Hinnant's algorithm)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let local = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some((local - parse_offset(offset)?, offset.to_string()))
+}
+
+/// Format a timestamp in git's default format (`Thu Nov 14 22:13:20 2023 +0000`),
+/// in the timezone it was recorded in.
+pub(crate) fn format_default(timestamp: i64, offset: &str) -> String {
+    let date = DateTime::from_timestamp(timestamp, parse_offset(offset).unwrap_or(0));
+
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {offset}",
+        WEEKDAYS[date.weekday as usize],
+        MONTHS[date.month as usize - 1],
+        date.day,
+        date.hour,
+        date.minute,
+        date.second,
+        date.year
+    )
+}
+
+/// Format a timestamp in git's ISO-like format (`2023-11-14 22:13:20 +0000`),
+/// in the timezone it was recorded in.
+pub(crate) fn format_iso(timestamp: i64, offset: &str) -> String {
+    let date = DateTime::from_timestamp(timestamp, parse_offset(offset).unwrap_or(0));
+
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02} {offset}",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_default, format_iso, parse_date, parse_offset, DateTime};
+
+    #[test]
+    fn breaks_down_timestamps() {
+        let date = DateTime::from_timestamp(951782400, 0);
+        assert_eq!((date.year, date.month, date.day), (2000, 2, 29));
+        assert_eq!(date.weekday, 2);
+
+        let date = DateTime::from_timestamp(0, -3600);
+        assert_eq!(
+            (date.year, date.month, date.day, date.hour),
+            (1969, 12, 31, 23)
+        );
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("+0000"), Some(0));
+        assert_eq!(parse_offset("+0130"), Some(5400));
+        assert_eq!(parse_offset("-0500"), Some(-18000));
+        assert_eq!(parse_offset("0500"), None);
+        assert_eq!(parse_offset("+05"), None);
+    }
+
+    #[test]
+    fn parses_dates() {
+        let expected = Some((1700000000, "+0000".to_string()));
+        assert_eq!(parse_date("1700000000 +0000"), expected);
+        assert_eq!(parse_date("@1700000000 +0000"), expected);
+        assert_eq!(parse_date("2023-11-14T22:13:20"), expected);
+        assert_eq!(
+            parse_date("2023-11-15 00:13:20 +0200"),
+            Some((1700000000, "+0200".to_string()))
+        );
+        assert_eq!(parse_date("1700000000 0000"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn formats_default_dates() {
+        assert_eq!(
+            format_default(1700000000, "+0000"),
+            "Tue Nov 14 22:13:20 2023 +0000"
+        );
+        assert_eq!(
+            format_default(1700000000, "+0200"),
+            "Wed Nov 15 00:13:20 2023 +0200"
+        );
+    }
+
+    #[test]
+    fn formats_iso_dates() {
+        assert_eq!(format_iso(1700000000, "-0500"), "2023-11-14 17:13:20 -0500");
+    }
+}
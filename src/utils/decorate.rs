@@ -0,0 +1,208 @@
+//! Decorations of commits, the names of the refs pointing to them
+//!
+//! `HEAD`, branches, remote-tracking branches, tags (on the commits they point
+//! to, as `tag: <name>`) and `refs/stash` are shown, as well as `grafted` for the
+//! commits whose parents are overridden or cut off. When `HEAD` points to a
+//! branch decorating the same commit, both are shown as `HEAD -> <branch>`.
+//!
+//! Each kind of decoration is colored with its slot of `color.decorate.<slot>`:
+//! `branch`, `remoteBranch`, `tag`, `stash`, `HEAD` and `grafted`.
+
+use std::collections::HashMap;
+
+use crate::utils::color::{config_color, RESET};
+use crate::utils::commit::peel;
+use crate::utils::config::Config;
+use crate::utils::grafts::Grafts;
+use crate::utils::refs::{shorten_ref, RefCache};
+use crate::utils::repository::Repository;
+
+/// The kind of a decoration, which selects its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecorationKind {
+    Branch,
+    RemoteBranch,
+    Tag,
+    Stash,
+    Head,
+    Grafted,
+}
+
+impl DecorationKind {
+    const ALL: [DecorationKind; 6] = [
+        DecorationKind::Branch,
+        DecorationKind::RemoteBranch,
+        DecorationKind::Tag,
+        DecorationKind::Stash,
+        DecorationKind::Head,
+        DecorationKind::Grafted,
+    ];
+
+    /// The slot of its color in `color.decorate.<slot>`, with the default color
+    fn color_slot(self) -> (&'static str, &'static str) {
+        match self {
+            DecorationKind::Branch => ("branch", "bold green"),
+            DecorationKind::RemoteBranch => ("remoteBranch", "bold red"),
+            DecorationKind::Tag => ("tag", "bold yellow"),
+            DecorationKind::Stash => ("stash", "bold magenta"),
+            DecorationKind::Head => ("HEAD", "bold cyan"),
+            DecorationKind::Grafted => ("grafted", "bold blue"),
+        }
+    }
+}
+
+/// A name decorating a commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Decoration {
+    kind: DecorationKind,
+    /// The full name of the ref (or `HEAD` and `grafted`)
+    refname: String,
+    /// The name shown
+    name: String,
+}
+
+/// The decorations of the commits of a repository
+#[derive(Debug, Default)]
+pub(crate) struct Decorations {
+    /// The decorations of each commit, in the order they are shown
+    decorations: HashMap<String, Vec<Decoration>>,
+    /// The branch `HEAD` points to, if any
+    head_branch: Option<String>,
+    /// The color of the commit line around the decorations, and the color of
+    /// each kind of decoration (`None` without colors)
+    colors: Option<(String, HashMap<&'static str, String>)>,
+}
+
+impl Decorations {
+    /// Load the decorations of the commits of a repository.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository to read the refs from
+    /// * `config` - The configuration to read the colors from
+    /// * `full` - Whether to show the full names of the refs (e.g. `refs/heads/main`)
+    /// * `color` - Whether to color the decorations
+    pub(crate) fn load(
+        repo: &Repository,
+        config: &Config,
+        full: bool,
+        color: bool,
+    ) -> anyhow::Result<Self> {
+        let mut decorations: HashMap<String, Vec<Decoration>> = HashMap::new();
+        let mut refs = RefCache::new(repo);
+
+        for (refname, hash) in refs.list("refs/")? {
+            let kind = match refname.as_str() {
+                "refs/stash" => DecorationKind::Stash,
+                name if name.starts_with("refs/heads/") => DecorationKind::Branch,
+                name if name.starts_with("refs/remotes/") => DecorationKind::RemoteBranch,
+                name if name.starts_with("refs/tags/") => DecorationKind::Tag,
+                _ => continue,
+            };
+            let name = match (kind, full) {
+                (DecorationKind::Stash, _) | (_, true) => refname.clone(),
+                _ => shorten_ref(&refname).to_string(),
+            };
+            let (name, hash) = match kind {
+                DecorationKind::Tag => (format!("tag: {name}"), peel(repo, &hash)?.0),
+                _ => (name, hash),
+            };
+            decorations.entry(hash).or_default().push(Decoration {
+                kind,
+                refname,
+                name,
+            });
+        }
+
+        let head = refs.resolve_symref("HEAD")?;
+        if let Some(hash) = head.hash {
+            decorations.entry(hash).or_default().push(Decoration {
+                kind: DecorationKind::Head,
+                refname: "HEAD".to_string(),
+                name: "HEAD".to_string(),
+            });
+        }
+        for hash in Grafts::load(repo)?.commits() {
+            decorations
+                .entry(hash.to_string())
+                .or_default()
+                .push(Decoration {
+                    kind: DecorationKind::Grafted,
+                    refname: "grafted".to_string(),
+                    name: "grafted".to_string(),
+                });
+        }
+        // The refs listed last are shown first
+        for list in decorations.values_mut() {
+            list.reverse();
+        }
+
+        let colors = match color {
+            true => {
+                let mut slots = HashMap::new();
+                for kind in DecorationKind::ALL {
+                    let (slot, default) = kind.color_slot();
+                    let key = format!("color.decorate.{slot}");
+                    slots.insert(slot, config_color(config, &key, default)?);
+                }
+                Some((config_color(config, "color.diff.commit", "yellow")?, slots))
+            },
+            false => None,
+        };
+
+        Ok(Decorations {
+            decorations,
+            head_branch: (head.name != "HEAD").then_some(head.name),
+            colors,
+        })
+    }
+
+    /// The decorations of a commit, in the order they are shown.
+    fn get(&self, hash: &str) -> &[Decoration] {
+        self.decorations.get(hash).map_or(&[], Vec::as_slice)
+    }
+
+    /// Format the decorations of a commit as shown after its hash, e.g.
+    /// ` (HEAD -> main, origin/main, tag: v1.0)`, or an empty string if it has none.
+    pub(crate) fn format(&self, hash: &str) -> String {
+        let decorations = self.get(hash);
+        if decorations.is_empty() {
+            return String::new();
+        }
+
+        let (commit, reset) = match &self.colors {
+            Some((commit, _)) => (commit.as_str(), RESET),
+            None => ("", ""),
+        };
+        let color = |kind: DecorationKind| match &self.colors {
+            Some((_, slots)) => slots[kind.color_slot().0].as_str(),
+            None => "",
+        };
+        // The branch HEAD points to is shown along with it
+        let current = decorations.iter().position(|decoration| {
+            decoration.kind == DecorationKind::Branch
+                && self.head_branch.as_ref() == Some(&decoration.refname)
+        });
+
+        let mut shown = Vec::new();
+        for (index, decoration) in decorations.iter().enumerate() {
+            match (decoration.kind, current) {
+                (_, Some(current)) if current == index => continue,
+                (DecorationKind::Head, Some(current)) => {
+                    let branch = &decorations[current];
+                    shown.push(format!(
+                        "{}HEAD -> {reset}{}{}{reset}",
+                        color(DecorationKind::Head),
+                        color(branch.kind),
+                        branch.name
+                    ));
+                },
+                (kind, _) => shown.push(format!("{}{}{reset}", color(kind), decoration.name)),
+            }
+        }
+        format!(
+            "{commit} ({reset}{}{commit}){reset}",
+            shown.join(&format!("{commit}, {reset}"))
+        )
+    }
+}
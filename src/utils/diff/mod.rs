@@ -0,0 +1,377 @@
+//! Comparing file contents line by line, and formatting the differences as unified diffs
+//!
+//! Lines are compared with Myers' O(ND) algorithm. As with git, groups of changed lines
+//! that could be placed at several positions (e.g. an inserted line equal to the one
+//! after it) are slid down as far as possible, so diffs look the same as git's.
+
+use std::io::Write;
+
+mod patch;
+mod tree;
+
+pub(crate) use patch::write_patch;
+pub(crate) use tree::{diff_trees, FileVersion, TreeChange};
+
+/// The default number of context lines around changes
+pub(crate) const DEFAULT_CONTEXT: usize = 3;
+
+/// The maximum length of the function name shown in hunk headers
+const MAX_FUNCNAME: usize = 80;
+
+/// A range of lines changed between two files: `old_len` lines starting at
+/// `old_start` were replaced by `new_len` lines starting at `new_start`
+/// (0-based; a length of 0 means lines were only inserted or deleted)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Change {
+    pub(crate) old_start: usize,
+    pub(crate) old_len: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_len: usize,
+}
+
+/// Split content into lines, keeping the line terminators.
+pub(crate) fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Compare two sequences of lines.
+///
+/// # Returns
+///
+/// The changed ranges, in order
+pub(crate) fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<Change> {
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+
+    // Common leading and trailing lines are never part of a change
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    myers(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+        &mut old_changed[prefix..old.len() - suffix],
+        &mut new_changed[prefix..new.len() - suffix],
+    );
+
+    compact(old, &mut old_changed);
+    compact(new, &mut new_changed);
+
+    collect_changes(&old_changed, &new_changed)
+}
+
+/// Mark the lines deleted from `old` and inserted in `new` along a shortest edit script.
+fn myers(old: &[&[u8]], new: &[&[u8]], old_changed: &mut [bool], new_changed: &mut [bool]) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    if n == 0 || m == 0 {
+        old_changed.fill(true);
+        new_changed.fill(true);
+        return;
+    }
+
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // The furthest x reached on each diagonal k = x - y
+    let mut v = vec![0isize; 2 * max + 3];
+    // The furthest x on diagonals -d..=d before each number of edits d, to walk back
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk the edits back from the end
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev().take_while(|&(d, _)| d > 0) {
+        let d = d as isize;
+        let furthest = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && furthest(k - 1) < furthest(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = furthest(previous_k);
+        let previous_y = previous_x - previous_k;
+
+        // Matching lines are followed back to the edit
+        x -= (x - previous_x).min(y - previous_y);
+        if x == previous_x {
+            new_changed[previous_y as usize] = true;
+        } else {
+            old_changed[previous_x as usize] = true;
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+}
+
+/// Slide each group of changed lines down as far as the content allows,
+/// merging it with the groups it reaches.
+fn compact(lines: &[&[u8]], changed: &mut [bool]) {
+    let mut start = 0;
+
+    while start < lines.len() {
+        if !changed[start] {
+            start += 1;
+            continue;
+        }
+
+        let mut end = start;
+        while end < changed.len() && changed[end] {
+            end += 1;
+        }
+
+        // The line after the group can become part of it if it equals the first line
+        while end < lines.len() && lines[start] == lines[end] {
+            changed[start] = false;
+            changed[end] = true;
+            start += 1;
+            end += 1;
+            while end < changed.len() && changed[end] {
+                end += 1;
+            }
+        }
+        start = end;
+    }
+}
+
+/// Collect the changed ranges from the per-line change marks of both sides.
+fn collect_changes(old_changed: &[bool], new_changed: &[bool]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_changed.len() || j < new_changed.len() {
+        let old_change = old_changed.get(i).copied().unwrap_or(false);
+        let new_change = new_changed.get(j).copied().unwrap_or(false);
+        if !old_change && !new_change {
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let (old_start, new_start) = (i, j);
+        while old_changed.get(i).copied().unwrap_or(false) {
+            i += 1;
+        }
+        while new_changed.get(j).copied().unwrap_or(false) {
+            j += 1;
+        }
+        changes.push(Change {
+            old_start,
+            old_len: i - old_start,
+            new_start,
+            new_len: j - new_start,
+        });
+    }
+
+    changes
+}
+
+/// Whether content is binary: git considers files containing a NUL byte
+/// within their first 8000 bytes to be binary.
+pub(crate) fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(8000)].contains(&0)
+}
+
+/// Write the hunks of a unified diff between two contents
+/// (without the `---` and `+++` header lines).
+///
+/// # Arguments
+///
+/// * `old` - The old content
+/// * `new` - The new content
+/// * `context` - The number of unchanged lines to show around changes
+/// * `writer` - Where to write the hunks
+pub(crate) fn write_hunks<W>(
+    old: &[u8],
+    new: &[u8],
+    context: usize,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let changes = diff_lines(&old_lines, &new_lines);
+
+    let mut index = 0;
+    while index < changes.len() {
+        // Changes separated by at most twice the context are shown in the same hunk
+        let mut last = index;
+        while last + 1 < changes.len()
+            && changes[last + 1].old_start - (changes[last].old_start + changes[last].old_len)
+                <= 2 * context
+        {
+            last += 1;
+        }
+
+        let (first_change, last_change) = (changes[index], changes[last]);
+        let old_start = first_change.old_start.saturating_sub(context);
+        let new_start = first_change.new_start - (first_change.old_start - old_start);
+        let old_end = (last_change.old_start + last_change.old_len + context).min(old_lines.len());
+        let new_end = new_lines
+            .len()
+            .min(last_change.new_start + last_change.new_len + context);
+
+        write!(
+            writer,
+            "@@ -{} +{} @@",
+            format_range(old_start, old_end - old_start),
+            format_range(new_start, new_end - new_start)
+        )?;
+        if let Some(funcname) = find_funcname(&old_lines[..old_start]) {
+            writer.write_all(b" ")?;
+            writer.write_all(funcname)?;
+        }
+        writeln!(writer)?;
+
+        let (mut i, mut j) = (old_start, new_start);
+        for change in &changes[index..=last] {
+            for line in &old_lines[i..change.old_start] {
+                write_line(writer, b' ', line)?;
+            }
+            for line in &old_lines[change.old_start..change.old_start + change.old_len] {
+                write_line(writer, b'-', line)?;
+            }
+            for line in &new_lines[change.new_start..change.new_start + change.new_len] {
+                write_line(writer, b'+', line)?;
+            }
+            i = change.old_start + change.old_len;
+            j = change.new_start + change.new_len;
+        }
+        for line in &old_lines[i..old_end] {
+            write_line(writer, b' ', line)?;
+        }
+        debug_assert_eq!(new_end - j, old_end - i);
+
+        index = last + 1;
+    }
+
+    Ok(())
+}
+
+/// Format the range of a hunk header: `start,length` with a 1-based start,
+/// where a length of 1 is omitted and an empty range starts at the line before it.
+fn format_range(start: usize, length: usize) -> String {
+    match length {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{length}", start + 1),
+    }
+}
+
+/// Find the line shown after a hunk header: the last line before the hunk
+/// that starts with a letter, `_` or `$`, as git does without a `diff` driver.
+fn find_funcname<'a>(lines: &[&'a [u8]]) -> Option<&'a [u8]> {
+    lines
+        .iter()
+        .rev()
+        .find(|line| {
+            line.first()
+                .is_some_and(|&c| c.is_ascii_alphabetic() || c == b'_' || c == b'$')
+        })
+        .map(|line| line[..line.len().min(MAX_FUNCNAME)].trim_ascii_end())
+}
+
+/// Write a line of a hunk, noting when the line has no terminating newline.
+fn write_line<W>(writer: &mut W, prefix: u8, line: &[u8]) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    writer.write_all(&[prefix])?;
+    writer.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        writer.write_all(b"\n\\ No newline at end of file\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> String {
+        let mut output = Vec::new();
+        write_hunks(old.as_bytes(), new.as_bytes(), DEFAULT_CONTEXT, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn finds_changed_ranges() {
+        let old = split_lines(b"a\nb\nc\nd\n");
+        let new = split_lines(b"a\nx\nc\nd\ne\n");
+        assert_eq!(diff_lines(&old, &new), [
+            Change {
+                old_start: 1,
+                old_len: 1,
+                new_start: 1,
+                new_len: 1
+            },
+            Change {
+                old_start: 4,
+                old_len: 0,
+                new_start: 4,
+                new_len: 1
+            },
+        ]);
+        assert!(diff_lines(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn slides_changes_down() {
+        // The inserted `b` could be either of the two; git shows the second one
+        let old = split_lines(b"a\nb\nc\n");
+        let new = split_lines(b"a\nb\nb\nc\n");
+        assert_eq!(diff_lines(&old, &new), [Change {
+            old_start: 2,
+            old_len: 0,
+            new_start: 2,
+            new_len: 1
+        }]);
+    }
+
+    #[test]
+    fn writes_hunks() {
+        assert_eq!(
+            diff("one\ntwo\nthree\n", "one\n2\nthree\nfour"),
+            "@@ -1,3 +1,4 @@\n one\n-two\n+2\n three\n+four\n\\ No newline at end of file\n"
+        );
+        assert_eq!(diff("", "a\nb\n"), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+        assert_eq!(diff("x\n", ""), "@@ -1 +0,0 @@\n-x\n");
+
+        // Distant changes are shown in separate hunks, with the function line
+        let old: String = (1..=20).map(|n| format!("line {n}\n")).collect();
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 18\n", "");
+        assert_eq!(
+            diff(&old, &new),
+            "@@ -1,5 +1,5 @@\n line 1\n-line 2\n+line two\n line 3\n line 4\n line 5\n\
+             @@ -15,6 +15,5 @@ line 14\n line 15\n line 16\n line 17\n-line 18\n line 19\n line 20\n"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Formatting the changes between two trees as a git patch
+
+use std::io::Write;
+
+use crate::utils::diff::{is_binary, write_hunks, FileVersion, TreeChange};
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::quote::{quote_path, quotes_non_ascii};
+use crate::utils::repository::Repository;
+use crate::utils::tree::{MODE_GITLINK, MODE_TYPE_MASK};
+
+/// The length of the abbreviated hashes on `index` lines
+const ABBREV: usize = 7;
+
+/// Write the changes between two trees as a patch, as `git diff` shows them.
+/// Paths with special characters are quoted, as configured by `core.quotePath`.
+///
+/// # Arguments
+///
+/// * `repo` - The repository containing the objects
+/// * `changes` - The changed files, from [`crate::utils::diff::diff_trees`]
+/// * `context` - The number of unchanged lines to show around changes
+/// * `writer` - Where to write the patch
+pub(crate) fn write_patch<W>(
+    repo: &Repository,
+    changes: &[TreeChange],
+    context: usize,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let quote_non_ascii = quotes_non_ascii(repo)?;
+    let write_file = |path, old, new, writer: &mut W| {
+        write_file_patch(repo, path, old, new, context, quote_non_ascii, writer)
+    };
+    for change in changes {
+        match (&change.old, &change.new) {
+            // A file replaced by a symlink (or submodule) is shown as a deletion and an addition
+            (Some(old), Some(new)) if (old.0 ^ new.0) & MODE_TYPE_MASK != 0 => {
+                write_file(&change.path, Some(old), None, writer)?;
+                write_file(&change.path, None, Some(new), writer)?;
+            },
+            (old, new) => write_file(&change.path, old.as_ref(), new.as_ref(), writer)?,
+        }
+    }
+    Ok(())
+}
+
+/// Write the patch of a single file.
+fn write_file_patch<W>(
+    repo: &Repository,
+    path: &[u8],
+    old: Option<&FileVersion>,
+    new: Option<&FileVersion>,
+    context: usize,
+    quote_non_ascii: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    // The prefixes are quoted along with the path
+    let old_name = quote_path(&[b"a/", path].concat(), quote_non_ascii);
+    let new_name = quote_path(&[b"b/", path].concat(), quote_non_ascii);
+    writer.write_all(b"diff --git ")?;
+    writer.write_all(&old_name)?;
+    writer.write_all(b" ")?;
+    writer.write_all(&new_name)?;
+    writeln!(writer)?;
+
+    let null_hash = "0".repeat(ABBREV);
+    let abbrev = |side: Option<&FileVersion>| {
+        side.map_or(null_hash.as_str(), |(_, hash)| &hash[..ABBREV])
+            .to_string()
+    };
+
+    match (old, new) {
+        (None, Some((mode, _))) => writeln!(writer, "new file mode {mode:06o}")?,
+        (Some((mode, _)), None) => writeln!(writer, "deleted file mode {mode:06o}")?,
+        (Some((old_mode, _)), Some((new_mode, _))) if old_mode != new_mode => {
+            writeln!(writer, "old mode {old_mode:06o}\nnew mode {new_mode:06o}")?;
+        },
+        _ => {},
+    }
+
+    let old_hash = old.map(|(_, hash)| hash);
+    let new_hash = new.map(|(_, hash)| hash);
+    if old_hash == new_hash {
+        // Only the mode changed
+        return Ok(());
+    }
+
+    write!(writer, "index {}..{}", abbrev(old), abbrev(new))?;
+    match (old, new) {
+        (Some((old_mode, _)), Some((new_mode, _))) if old_mode == new_mode => {
+            writeln!(writer, " {old_mode:06o}")?
+        },
+        _ => writeln!(writer)?,
+    }
+
+    let old_content = read_content(repo, old)?;
+    let new_content = read_content(repo, new)?;
+    let old_name = old.map_or(&b"/dev/null"[..], |_| &old_name);
+    let new_name = new.map_or(&b"/dev/null"[..], |_| &new_name);
+
+    if is_binary(&old_content) || is_binary(&new_content) {
+        writer.write_all(b"Binary files ")?;
+        writer.write_all(old_name)?;
+        writer.write_all(b" and ")?;
+        writer.write_all(new_name)?;
+        writeln!(writer, " differ")?;
+        return Ok(());
+    }
+
+    let mut hunks = Vec::new();
+    write_hunks(&old_content, &new_content, context, &mut hunks)?;
+    // Empty files have no hunks, and no file names are shown for them
+    if !hunks.is_empty() {
+        writer.write_all(b"--- ")?;
+        writer.write_all(old_name)?;
+        writer.write_all(b"\n+++ ")?;
+        writer.write_all(new_name)?;
+        writeln!(writer)?;
+        writer.write_all(&hunks)?;
+    }
+
+    Ok(())
+}
+
+/// Read the content of a side of a file change: the blob, or the
+/// `Subproject commit <hash>` line of a submodule.
+fn read_content(repo: &Repository, side: Option<&FileVersion>) -> anyhow::Result<Vec<u8>> {
+    match side {
+        None => Ok(Vec::new()),
+        Some((MODE_GITLINK, hash)) => Ok(format!("Subproject commit {hash}\n").into_bytes()),
+        Some((_, hash)) => read_object_of_type(repo, hash, ObjectType::Blob),
+    }
+}
@@ -0,0 +1,57 @@
+//! Comparing the files of two trees
+
+use std::collections::BTreeMap;
+
+use crate::utils::repository::Repository;
+use crate::utils::tree::flatten_tree;
+
+/// The mode and hash of a file in a tree
+pub(crate) type FileVersion = (u32, String);
+
+/// A file that differs between two trees
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TreeChange {
+    /// The path of the file relative to the root of the trees
+    pub(crate) path: Vec<u8>,
+    /// The mode and hash of the file in the old tree, if it exists there
+    pub(crate) old: Option<FileVersion>,
+    /// The mode and hash of the file in the new tree, if it exists there
+    pub(crate) new: Option<FileVersion>,
+}
+
+/// Compare the files of two trees.
+///
+/// # Arguments
+///
+/// * `repo` - The repository containing the trees
+/// * `old` - The hash of the old tree, or `None` to compare with an empty tree
+/// * `new` - The hash of the new tree, or `None` to compare with an empty tree
+///
+/// # Returns
+///
+/// The changed files, in tree order
+pub(crate) fn diff_trees(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> anyhow::Result<Vec<TreeChange>> {
+    // Full paths sort in the same order as the entries of nested trees
+    let mut files: BTreeMap<Vec<u8>, (Option<FileVersion>, Option<FileVersion>)> = BTreeMap::new();
+
+    if let Some(old) = old {
+        for entry in flatten_tree(repo, old)? {
+            files.entry(entry.name).or_default().0 = Some((entry.mode, entry.hash));
+        }
+    }
+    if let Some(new) = new {
+        for entry in flatten_tree(repo, new)? {
+            files.entry(entry.name).or_default().1 = Some((entry.mode, entry.hash));
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(path, (old, new))| TreeChange { path, old, new })
+        .collect())
+}
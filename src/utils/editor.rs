@@ -0,0 +1,98 @@
+//! Launching the editor users write commit messages and todo lists with
+//!
+//! The editor is the command of `$GIT_EDITOR`, `core.editor`, `$VISUAL` (unless the
+//! terminal is dumb), `$EDITOR` or `vi`, run by the shell with the path of the file
+//! to edit. The editor `:` leaves files as they are.
+
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::utils::config::Config;
+use crate::utils::env;
+use crate::utils::repository::Repository;
+
+/// The editor command to run.
+fn editor(repo: &Repository) -> anyhow::Result<String> {
+    let env = repo.env();
+    if let Some(editor) = env.var(env::GIT_EDITOR) {
+        return Ok(editor.to_string());
+    }
+    if let Some(editor) = Config::load(repo)?.get("core.editor") {
+        return Ok(editor.to_string());
+    }
+
+    let dumb = env.var("TERM").is_none_or(|term| term == "dumb");
+    if !dumb {
+        if let Some(editor) = env.var("VISUAL") {
+            return Ok(editor.to_string());
+        }
+    }
+    match env.var("EDITOR") {
+        Some(editor) => Ok(editor.to_string()),
+        None if dumb => anyhow::bail!("Terminal is dumb, but EDITOR unset"),
+        None => Ok("vi".to_string()),
+    }
+}
+
+/// Let the user edit a file, waiting for the editor to exit.
+pub(crate) fn launch_editor(repo: &Repository, path: &Path) -> anyhow::Result<()> {
+    let editor = editor(repo)?;
+    if editor == ":" {
+        return Ok(());
+    }
+
+    let status = repo
+        .command("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .context(format!("cannot run {editor}"))?;
+    if !status.success() {
+        anyhow::bail!("There was a problem with the editor '{editor}'.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn launches_configured_editor() {
+        let mut temp = TempRepo::new().env("TERM", "dumb").env("VISUAL", "false");
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        let repo = temp.repo();
+        let path = temp.join("file");
+        fs::write(&path, "one\n").unwrap();
+
+        let err = launch_editor(&repo, &path).unwrap_err();
+        assert_eq!(err.to_string(), "Terminal is dumb, but EDITOR unset");
+
+        fs::write(
+            temp.join(".git/config"),
+            "[core]\n\teditor = sed -i s/one/two/\n",
+        )
+        .unwrap();
+        launch_editor(&repo, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two\n");
+
+        // The editor of the environment comes first, and `:` does not edit
+        temp.set_env(env::GIT_EDITOR, "exit 3;");
+        let repo = temp.repo();
+        let err = launch_editor(&repo, &path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "There was a problem with the editor 'exit 3;'."
+        );
+        temp.set_env(env::GIT_EDITOR, ":");
+        let repo = temp.repo();
+        launch_editor(&repo, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two\n");
+    }
+}
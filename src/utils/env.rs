@@ -1,4 +1,93 @@
 //! Environment variables used by the Git CLI
+//!
+//! Commands read them from the [`Environment`] of their repository, captured
+//! once when the repository context is created, rather than from the process,
+//! so that a host can use repositories with different environments at once.
 
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::Arc;
+
+pub(crate) const GIT_ALTERNATE_OBJECT_DIRECTORIES: &str = "GIT_ALTERNATE_OBJECT_DIRECTORIES";
+pub(crate) const GIT_ASKPASS: &str = "GIT_ASKPASS";
+pub(crate) const GIT_AUTHOR_DATE: &str = "GIT_AUTHOR_DATE";
+pub(crate) const GIT_AUTHOR_EMAIL: &str = "GIT_AUTHOR_EMAIL";
+pub(crate) const GIT_AUTHOR_NAME: &str = "GIT_AUTHOR_NAME";
+pub(crate) const GIT_COMMITTER_DATE: &str = "GIT_COMMITTER_DATE";
+pub(crate) const GIT_COMMITTER_EMAIL: &str = "GIT_COMMITTER_EMAIL";
+pub(crate) const GIT_COMMITTER_NAME: &str = "GIT_COMMITTER_NAME";
+pub(crate) const GIT_CONFIG_COUNT: &str = "GIT_CONFIG_COUNT";
+pub(crate) const GIT_CONFIG_GLOBAL: &str = "GIT_CONFIG_GLOBAL";
+pub(crate) const GIT_CONFIG_KEY: &str = "GIT_CONFIG_KEY";
+pub(crate) const GIT_CONFIG_NOSYSTEM: &str = "GIT_CONFIG_NOSYSTEM";
+pub(crate) const GIT_CONFIG_PARAMETERS: &str = "GIT_CONFIG_PARAMETERS";
+pub(crate) const GIT_CONFIG_SYSTEM: &str = "GIT_CONFIG_SYSTEM";
+pub(crate) const GIT_CONFIG_VALUE: &str = "GIT_CONFIG_VALUE";
 pub(crate) const GIT_DIR: &str = "GIT_DIR";
+pub(crate) const GIT_EDITOR: &str = "GIT_EDITOR";
+pub(crate) const GIT_HTTP_USER_AGENT: &str = "GIT_HTTP_USER_AGENT";
+pub(crate) const GIT_INDEX_FILE: &str = "GIT_INDEX_FILE";
+pub(crate) const GIT_NOTES_REF: &str = "GIT_NOTES_REF";
+pub(crate) const GIT_NO_REPLACE_OBJECTS: &str = "GIT_NO_REPLACE_OBJECTS";
 pub(crate) const GIT_OBJECT_DIRECTORY: &str = "GIT_OBJECT_DIRECTORY";
+pub(crate) const GIT_QUARANTINE_PATH: &str = "GIT_QUARANTINE_PATH";
+pub(crate) const GIT_TERMINAL_PROMPT: &str = "GIT_TERMINAL_PROMPT";
+pub(crate) const GIT_TEST_ASSUME_DIFFERENT_OWNER: &str = "GIT_TEST_ASSUME_DIFFERENT_OWNER";
+pub(crate) const GIT_USER_AGENT: &str = "GIT_USER_AGENT";
+pub(crate) const GIT_WORK_TREE: &str = "GIT_WORK_TREE";
+pub(crate) const SSH_ASKPASS: &str = "SSH_ASKPASS";
+pub(crate) const TERM: &str = "TERM";
+
+/// A snapshot of environment variables
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Environment(Arc<HashMap<OsString, OsString>>);
+
+impl Environment {
+    /// Capture the environment of the process.
+    pub(crate) fn capture() -> Self {
+        Environment(Arc::new(std::env::vars_os().collect()))
+    }
+
+    /// The value of a variable, if it is set and valid unicode.
+    pub(crate) fn var(&self, name: &str) -> Option<&str> {
+        self.var_os(name)?.to_str()
+    }
+
+    /// The value of a variable, if it is set.
+    pub(crate) fn var_os(&self, name: &str) -> Option<&OsStr> {
+        self.0.get(OsStr::new(name)).map(OsString::as_os_str)
+    }
+
+    /// Set a variable, in this environment only (and the ones cloned from it
+    /// afterwards).
+    pub(crate) fn set<V>(&mut self, name: &str, value: V)
+    where
+        V: Into<OsString>,
+    {
+        Arc::make_mut(&mut self.0).insert(name.into(), value.into());
+    }
+
+    /// The variables, in no particular order.
+    pub(crate) fn vars(&self) -> impl Iterator<Item = (&OsStr, &OsStr)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_os_str(), value.as_os_str()))
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for Environment
+where
+    K: Into<OsString>,
+    V: Into<OsString>,
+{
+    fn from_iter<I>(vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let vars = vars
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+        Environment(Arc::new(vars))
+    }
+}
@@ -0,0 +1,170 @@
+//! Grafts, which override the parents of commits while walking the history
+//!
+//! - `info/grafts` lists commits with the parents to use instead of theirs
+//!   (`<commit> [<parent>...]`), as older tools rewrote history; it is deprecated
+//! - `shallow` lists the commits whose parents are cut off, as shallow clones leave
+//!   them without their history
+
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Context;
+
+use crate::utils::advice::{advise, Advice};
+use crate::utils::commit::{read_commit, Commit};
+use crate::utils::refs::is_hash;
+use crate::utils::repository::Repository;
+
+thread_local! {
+    /// The deprecation of `info/grafts` is only reported once per command
+    static WARNED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The commits whose parents are overridden
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Grafts(HashMap<String, Vec<String>>);
+
+impl Grafts {
+    /// Load the grafts of a repository, warning that `info/grafts` is deprecated.
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let dir = repo.common_dir()?;
+        let mut grafts = HashMap::new();
+
+        let path = dir.join("info").join("grafts");
+        if path.is_file() {
+            let content = std::fs::read_to_string(&path).context("read info/grafts")?;
+            for line in content.lines() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let hashes: Vec<&str> = line.split_whitespace().collect();
+                if !hashes.iter().all(|hash| is_hash(hash)) {
+                    eprintln!("error: bad graft data: {line}");
+                    continue;
+                }
+                let parents = hashes[1..].iter().map(|hash| hash.to_string()).collect();
+                grafts.insert(hashes[0].to_string(), parents);
+            }
+
+            if !WARNED.replace(true) {
+                advise(
+                    repo,
+                    Advice::GraftFileDeprecated,
+                    "Support for <GIT_DIR>/info/grafts is deprecated\n\
+                     and will be removed in a future Git version.\n\
+                     \n\
+                     Please use \"git replace --convert-graft-file\"\n\
+                     to convert the grafts into replace refs.\n\
+                     \n\
+                     Turn this message off by running\n\
+                     \"git config advice.graftFileDeprecated false\"",
+                )?;
+            }
+        }
+
+        // The history of shallow commits is cut off, whatever their grafts
+        for hash in read_shallow(repo)? {
+            grafts.insert(hash, Vec::new());
+        }
+        Ok(Grafts(grafts))
+    }
+
+    /// The commits whose parents are overridden (or cut off).
+    pub(crate) fn commits(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    /// Read a commit, with its parents overridden by its graft if it has one.
+    pub(crate) fn read_commit(&self, repo: &Repository, hash: &str) -> anyhow::Result<Commit> {
+        let mut commit = read_commit(repo, hash)?;
+        if let Some(parents) = self.0.get(hash) {
+            commit.parents = parents.clone();
+        }
+        Ok(commit)
+    }
+}
+
+/// Read the commits whose parents are cut off by a shallow clone (none if the
+/// repository is complete).
+pub(crate) fn read_shallow(repo: &Repository) -> anyhow::Result<BTreeSet<String>> {
+    let path = repo.common_dir()?.join("shallow");
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(err) => Err(err).context("read shallow"),
+    }
+}
+
+/// Write the commits whose parents are cut off, removing `shallow` when there
+/// are none left (the repository being complete again).
+pub(crate) fn write_shallow(repo: &Repository, shallow: &BTreeSet<String>) -> anyhow::Result<()> {
+    let path = repo.common_dir()?.join("shallow");
+    if shallow.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).context("remove shallow")
+            },
+            _ => Ok(()),
+        };
+    }
+
+    let content: String = shallow.iter().map(|hash| format!("{hash}\n")).collect();
+    std::fs::write(&path, content).context("write shallow")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+    use crate::utils::walk::{reachable_commits, RevWalk};
+
+    #[test]
+    fn overrides_parents_while_walking() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com");
+        fs::create_dir_all(temp.join(".git/info")).unwrap();
+        let repo = temp.repo();
+        let one = create_commit(&repo, EMPTY_TREE, Vec::new(), "one\n").unwrap();
+        let two = create_commit(&repo, EMPTY_TREE, vec![one.clone()], "two\n").unwrap();
+        let three = create_commit(&repo, EMPTY_TREE, vec![two.clone()], "three\n").unwrap();
+        assert_eq!(Grafts::load(&repo).unwrap(), Grafts::default());
+
+        let walk = |start: &str| -> Vec<String> {
+            let mut walk = RevWalk::new(&repo);
+            walk.push(start).unwrap();
+            walk.map(|entry| entry.unwrap().0).collect()
+        };
+
+        // `three` is grafted onto `one`, skipping `two`, and bad lines are ignored
+        fs::write(
+            temp.join(".git/info/grafts"),
+            format!("# comment\n{three} {one}\nbad line\n"),
+        )
+        .unwrap();
+        let grafts = Grafts::load(&repo).unwrap();
+        assert_eq!(grafts.read_commit(&repo, &three).unwrap().parents, [
+            one.as_str()
+        ]);
+        assert_eq!(walk(&three), [three.clone(), one.clone()]);
+        assert!(!reachable_commits(&repo, &three).unwrap().contains(&two));
+
+        // The history of shallow commits is cut off
+        fs::write(temp.join(".git/shallow"), format!("{three}\n")).unwrap();
+        assert_eq!(walk(&three), [three.as_str()]);
+        fs::remove_file(temp.join(".git/info/grafts")).unwrap();
+        fs::write(temp.join(".git/shallow"), format!("{two}\n")).unwrap();
+        assert_eq!(walk(&three), [three, two]);
+    }
+}
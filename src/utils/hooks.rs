@@ -0,0 +1,228 @@
+//! Running hooks, the programs commands run at certain points
+//!
+//! Hooks are the executable files of `core.hooksPath` (relative to the root of the
+//! working tree), or of `$GIT_COMMON_DIR/hooks` by default, named after the point
+//! they run at:
+//!
+//! - `pre-commit` before a commit is created, which it stops by failing
+//! - `commit-msg` with the path of the file holding the commit message, which it may
+//!   edit, or stop the commit by failing
+//! - `post-commit` after a commit is created
+//! - `pre-push` with the name and URL of the remote, and a line per ref to update on
+//!   its input (`<local ref> <local hash> <remote ref> <remote hash>`), which it stops
+//!   by failing
+//! - `post-checkout` after HEAD or files are checked out, with the old and new HEAD
+//!   and `1` for a branch checkout (`0` for files)
+//! - `post-merge` after a merge, with `1` for a squash merge (`0` otherwise)
+//! - `pre-receive` before the refs of a push are updated, with a line per ref on its
+//!   input (`<old hash> <new hash> <ref>`), which it stops by failing
+//! - `update` before each ref of a push is updated, with the ref and its old and new
+//!   hash, which it stops for that ref by failing
+//! - `post-receive` after the refs of a push are updated, with the lines of
+//!   `pre-receive` for those updated
+//!
+//! Hooks run from the root of the working tree, with `$GIT_DIR` set to the git
+//! directory, no input unless they read a list of refs, and their output sent to the
+//! standard error of the command.
+//!
+//! Hooks run while new objects are quarantined see them through
+//! `$GIT_OBJECT_DIRECTORY`, with the object directory of the repository in
+//! `$GIT_ALTERNATE_OBJECT_DIRECTORIES`, and `$GIT_QUARANTINE_PATH` set so that they
+//! cannot update refs.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::Context;
+
+use crate::utils::advice::{advise, Advice};
+use crate::utils::config::Config;
+use crate::utils::env;
+use crate::utils::repository::Repository;
+
+/// A hook to run
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Hook<'a> {
+    /// The arguments of the hook
+    pub(crate) args: Vec<String>,
+    /// What the hook reads on its standard input
+    pub(crate) input: Option<&'a [u8]>,
+    /// The index the hook works on (`$GIT_INDEX_FILE`), for the commit hooks
+    pub(crate) index_file: Option<PathBuf>,
+}
+
+/// Find the executable file of a hook.
+///
+/// # Returns
+///
+/// The path to the hook, or `None` if there is no such hook
+fn find_hook(repo: &Repository, config: &Config, name: &str) -> anyhow::Result<Option<PathBuf>> {
+    let path = match config.get_path("core.hooksPath") {
+        Some(dir) if repo.is_bare()? => repo.git_dir()?.join(dir).join(name),
+        Some(dir) => repo.work_tree()?.join(dir).join(name),
+        None => repo.common_dir()?.join("hooks").join(name),
+    };
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    if !is_executable(&path) {
+        advise(
+            repo,
+            Advice::IgnoredHook,
+            &format!(
+                "The '{}' hook was ignored because it's not set as executable.\n\
+                 You can disable this warning with `git config advice.ignoredHook false`.",
+                path.display()
+            ),
+        )?;
+        return Ok(None);
+    }
+    Ok(Some(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &std::path::Path) -> bool {
+    true
+}
+
+/// Run a hook, if the repository has it.
+///
+/// # Arguments
+///
+/// * `repo` - The repository the hook belongs to
+/// * `name` - The name of the hook (e.g. `pre-commit`)
+/// * `hook` - The arguments and input of the hook
+///
+/// # Returns
+///
+/// The exit code of the hook, `0` if there is no such hook
+pub(crate) fn run_hook(repo: &Repository, name: &str, hook: Hook) -> anyhow::Result<i32> {
+    let config = Config::load(repo)?;
+    let Some(path) = find_hook(repo, &config, name)? else {
+        return Ok(0);
+    };
+
+    // Hooks of a bare repository run from its git directory
+    let dir = match repo.is_bare()? {
+        true => repo.git_dir()?,
+        false => repo.work_tree()?,
+    };
+    let mut command = repo.command(&path);
+    command
+        .args(&hook.args)
+        .current_dir(dir)
+        .env(env::GIT_DIR, repo.git_dir()?)
+        .stdin(match hook.input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(std::io::stderr());
+    if let Some(index_file) = &hook.index_file {
+        command.env(env::GIT_INDEX_FILE, index_file);
+    }
+    if let Some(quarantine) = repo.quarantine() {
+        let alternates = std::env::join_paths(repo.alternate_object_dirs()?)?;
+        command
+            .env(env::GIT_OBJECT_DIRECTORY, quarantine)
+            .env(env::GIT_ALTERNATE_OBJECT_DIRECTORIES, alternates)
+            .env(env::GIT_QUARANTINE_PATH, quarantine);
+    }
+    let mut child = command
+        .spawn()
+        .context(format!("cannot run {}", path.display()))?;
+
+    // The hook may exit before reading all of its input
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), hook.input) {
+        let _ = stdin.write_all(input);
+    }
+    let status = child
+        .wait()
+        .context(format!("wait for {}", path.display()))?;
+
+    // A hook killed by a signal fails like a shell reports it
+    Ok(status.code().unwrap_or(128))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/hooks")).unwrap();
+        fs::create_dir_all(temp.join("sub")).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn write_hook(temp: &TempRepo, path: &str, script: &str) {
+        let path = temp.join(path);
+        fs::write(&path, format!("#!/bin/sh\n{script}")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn runs_hooks_with_arguments_and_input() {
+        let (temp, repo) = setup();
+        assert_eq!(run_hook(&repo, "pre-push", Hook::default()).unwrap(), 0);
+
+        write_hook(
+            &temp,
+            ".git/hooks/pre-push",
+            "echo \"$1 $2 $GIT_DIR\" > out\ncat >> out\nexit 3\n",
+        );
+        let code = run_hook(&repo, "pre-push", Hook {
+            args: vec!["origin".to_string(), "url".to_string()],
+            input: Some(b"HEAD 1 refs/heads/main 2\n"),
+            ..Hook::default()
+        })
+        .unwrap();
+        assert_eq!(code, 3);
+        let git_dir = repo.git_dir().unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("out")).unwrap(),
+            format!(
+                "origin url {}\nHEAD 1 refs/heads/main 2\n",
+                git_dir.display()
+            )
+        );
+
+        // Hooks that are not executable are ignored
+        fs::set_permissions(
+            temp.join(".git/hooks/pre-push"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        assert_eq!(run_hook(&repo, "pre-push", Hook::default()).unwrap(), 0);
+    }
+
+    #[test]
+    fn honors_hooks_path() {
+        let (temp, _) = setup();
+        fs::write(temp.join(".git/config"), "[core]\n\thooksPath = sub\n").unwrap();
+        write_hook(&temp, ".git/hooks/post-commit", "exit 1\n");
+        write_hook(&temp, "sub/post-commit", "pwd > out\n");
+
+        let repo = temp.repo_at("sub");
+        assert_eq!(run_hook(&repo, "post-commit", Hook::default()).unwrap(), 0);
+        let root = repo.work_tree().unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("out")).unwrap().trim_end(),
+            root.display().to_string()
+        );
+    }
+}
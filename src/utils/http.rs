@@ -0,0 +1,422 @@
+//! Making HTTP requests with `curl`, as configured by the `http.*` settings
+//!
+//! - `http.extraHeader` adds a header to every request (e.g. the token of an
+//!   authenticating proxy); it may be set more than once, and an empty value
+//!   drops the headers set before it
+//! - `http.<url>.extraHeader` only applies to the requests below `<url>`
+//! - `http.userAgent` (or `$GIT_HTTP_USER_AGENT`) replaces the user agent,
+//!   which is the agent sent over the protocol by default
+//! - `http.maxRetries` is how many times a request failing with a transient
+//!   error (a timeout, a refused connection, or a 408, 429 or 5xx response) is
+//!   retried, waiting one second before the first retry and twice as long
+//!   before each next one (or as long as the server asks with `Retry-After`)
+//! - A request refused with a 401 response is sent again with the credential
+//!   filled by the credential helpers (see [`crate::utils::credential`]),
+//!   which is then stored (or erased if it is refused too) and used for the
+//!   next requests to the same server
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use anyhow::Context;
+
+use crate::utils::config::Config;
+use crate::utils::credential::{Credential, Credentials};
+use crate::utils::env;
+use crate::utils::protocol::capabilities::Capabilities;
+use crate::utils::repository::Repository;
+
+/// Sends HTTP requests with the settings of a repository
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    /// The repository `curl` is run from, with its environment
+    repo: Repository,
+    /// The extra headers, with the URL they are restricted to (if any), in
+    /// the order they were configured
+    extra_headers: Vec<(Option<String>, String)>,
+    user_agent: String,
+    max_retries: u32,
+    credentials: Credentials,
+    /// The credential accepted by the server last requested
+    credential: RefCell<Option<Credential>>,
+}
+
+impl HttpClient {
+    /// Load the HTTP settings of a repository.
+    pub(crate) fn new(repo: &Repository) -> anyhow::Result<Self> {
+        let config = Config::load(repo)?;
+        let extra_headers = config
+            .entries()
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.key.strip_prefix("http.")?;
+                let url = match name.strip_suffix(".extraheader") {
+                    Some(url) => Some(url.to_string()),
+                    None if name == "extraheader" => None,
+                    None => return None,
+                };
+                Some((url, entry.value.clone().unwrap_or_default()))
+            })
+            .collect();
+
+        let user_agent = match repo.env().var(env::GIT_HTTP_USER_AGENT) {
+            Some(agent) => agent.to_string(),
+            None => config
+                .get("http.userAgent")
+                .map(str::to_string)
+                .unwrap_or_else(|| Capabilities::default_agent(repo.env())),
+        };
+
+        let max_retries = match config.get("http.maxRetries") {
+            Some(value) => value.parse().context(format!(
+                "bad numeric config value '{value}' for 'http.maxretries'"
+            ))?,
+            None => 0,
+        };
+
+        Ok(HttpClient {
+            repo: repo.clone(),
+            extra_headers,
+            user_agent,
+            max_retries,
+            credentials: Credentials::new(repo)?,
+            credential: RefCell::new(None),
+        })
+    }
+
+    /// The extra headers of the requests to a URL.
+    fn extra_headers(&self, url: &str) -> Vec<&str> {
+        let mut headers = Vec::new();
+        for (prefix, header) in &self.extra_headers {
+            if prefix
+                .as_deref()
+                .is_some_and(|prefix| !url_matches(prefix, url))
+            {
+                continue;
+            }
+            match header.is_empty() {
+                true => headers.clear(),
+                false => headers.push(header.as_str()),
+            }
+        }
+        headers
+    }
+
+    /// The `curl` command making a request, which `curl` also authenticates
+    /// (with the credential given, the credentials of the URL or `~/.netrc`)
+    /// and retries.
+    fn command(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(String, String)],
+        credential: Option<&Credential>,
+    ) -> Command {
+        let mut command = self.repo.command("curl");
+        command.args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--location",
+            "--netrc-optional",
+            "--retry",
+            &self.max_retries.to_string(),
+            "--retry-connrefused",
+            "--user-agent",
+            &self.user_agent,
+            "--request",
+            method,
+        ]);
+        for (name, value) in headers {
+            command.args(["--header", &format!("{name}: {value}")]);
+        }
+        for header in self.extra_headers(url) {
+            command.args(["--header", header]);
+        }
+        // curl hides the password from its command line once started
+        if let Some(credential) = credential {
+            let username = credential.username.as_deref().unwrap_or_default();
+            let password = credential.password.as_deref().unwrap_or_default();
+            command.args(["--user", &format!("{username}:{password}")]);
+        }
+        command
+    }
+
+    /// Run a request, filling a credential and running it again if the
+    /// server asks for one.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL requested
+    /// * `run` - Run the request with a credential (if any)
+    fn authenticated<F>(&self, url: &str, run: F) -> anyhow::Result<Output>
+    where
+        F: Fn(Option<&Credential>) -> anyhow::Result<Output>,
+    {
+        let wanted = Credential::from_url(url)?;
+        let known = self
+            .credential
+            .borrow()
+            .clone()
+            .filter(|credential| credential.matches(&wanted));
+        let output = run(known.as_ref())?;
+        if !is_unauthorized(&output) {
+            return Ok(output);
+        }
+        if let Some(known) = known {
+            self.credentials.reject(&known)?;
+            self.credential.replace(None);
+        }
+
+        let credential = self.credentials.fill(&wanted)?;
+        let output = run(Some(&credential))?;
+        if is_unauthorized(&output) {
+            self.credentials.reject(&credential)?;
+            anyhow::bail!("Authentication failed for '{url}'");
+        }
+        if output.status.success() {
+            self.credentials.approve(&credential)?;
+            self.credential.replace(Some(credential));
+        }
+        Ok(output)
+    }
+
+    /// Make a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The HTTP method (e.g. `GET`)
+    /// * `url` - The URL to request
+    /// * `headers` - The headers of the request, sent before the extra headers
+    /// * `body` - The body of the request, if any
+    ///
+    /// # Returns
+    ///
+    /// The body of the response
+    pub(crate) fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(String, String)],
+        body: Option<&[u8]>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let output = self.authenticated(url, |credential| {
+            let mut command = self.command(method, url, headers, credential);
+            if body.is_some() {
+                command.args(["--data-binary", "@-"]);
+            }
+            let mut child = command
+                .arg("--")
+                .arg(url)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .context("run curl")?;
+
+            if let (Some(mut stdin), Some(body)) = (child.stdin.take(), body) {
+                stdin.write_all(body).context("write request body")?;
+            }
+            child.wait_with_output().context("wait for curl")
+        })?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{method} {url} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+        Ok(output.stdout)
+    }
+
+    /// Download a file, resuming the download if part of it was already
+    /// downloaded (the part being kept if the download fails again).
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to download
+    /// * `headers` - The headers of the request, sent before the extra headers
+    /// * `path` - The file to download into
+    pub(crate) fn download(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        let resumed = path.metadata().is_ok_and(|metadata| metadata.len() > 0);
+        let output = self.authenticated(url, |credential| {
+            self.command("GET", url, headers, credential)
+                .args(["--continue-at", "-", "--output"])
+                .arg(path)
+                .arg("--")
+                .arg(url)
+                .stdin(Stdio::null())
+                .output()
+                .context("run curl")
+        })?;
+        if output.status.success() {
+            return Ok(());
+        }
+
+        // The download starts over when the server refuses to send the rest
+        // of the file (curl failing with an HTTP error or a range error),
+        // but not when the connection fails
+        if resumed && matches!(output.status.code(), Some(22 | 33)) {
+            std::fs::remove_file(path).context(format!("remove {}", path.display()))?;
+            return self.download(url, headers, path);
+        }
+        anyhow::bail!(
+            "GET {url} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )
+    }
+}
+
+/// Whether curl failed because the server asked for a credential.
+fn is_unauthorized(output: &Output) -> bool {
+    output.status.code() == Some(22)
+        && String::from_utf8_lossy(&output.stderr).contains("returned error: 401")
+}
+
+/// Whether a URL is below the URL of `http.<url>.*` (or `credential.<url>.*`)
+/// settings.
+pub(crate) fn url_matches(prefix: &str, url: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    url.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::utils::base64;
+    use crate::utils::test::TempRepo;
+
+    fn setup(config: &str) -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        fs::write(temp.join(".git/config"), config).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    #[test]
+    fn adds_extra_headers() {
+        let (_temp, repo) = setup(
+            "[http]\n\textraHeader = X-Dropped: 1\n\textraHeader =\n\
+             \textraHeader = Authorization: Bearer proxy\n\
+             [http \"https://example.com/org/\"]\n\textraHeader = X-Org: 1\n",
+        );
+        let client = HttpClient::new(&repo).unwrap();
+
+        assert_eq!(
+            client.extra_headers("https://example.com/org/repo.git/info/lfs"),
+            ["Authorization: Bearer proxy", "X-Org: 1"]
+        );
+        assert_eq!(client.extra_headers("https://example.com/organization"), [
+            "Authorization: Bearer proxy"
+        ]);
+    }
+
+    #[test]
+    fn configures_user_agent() {
+        let (mut temp, repo) = setup("");
+        let client = HttpClient::new(&repo).unwrap();
+        assert_eq!(client.user_agent, Capabilities::default_agent(repo.env()));
+
+        temp.set_env(env::GIT_USER_AGENT, "custom/1.0");
+        let repo = temp.repo();
+        assert_eq!(HttpClient::new(&repo).unwrap().user_agent, "custom/1.0");
+
+        fs::write(
+            temp.join(".git/config"),
+            "[http]\n\tuserAgent = proxy-friendly/2.0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            HttpClient::new(&repo).unwrap().user_agent,
+            "proxy-friendly/2.0"
+        );
+
+        temp.set_env(env::GIT_HTTP_USER_AGENT, "env/3.0");
+        let repo = temp.repo();
+        assert_eq!(HttpClient::new(&repo).unwrap().user_agent, "env/3.0");
+    }
+
+    #[test]
+    fn retries_and_resumes_downloads() {
+        let (temp, repo) = setup("[http]\n\tmaxRetries = 3\n");
+        let client = HttpClient::new(&repo).unwrap();
+        let command = client.command("GET", "https://example.com", &[], None);
+        let args: Vec<_> = command.get_args().collect();
+        assert!(args.windows(2).any(|args| args == ["--retry", "3"]));
+
+        fs::write(temp.join("source"), "hello world").unwrap();
+        fs::write(temp.join("partial"), "hello").unwrap();
+        let url = format!("file://{}", temp.join("source").display());
+        client.download(&url, &[], &temp.join("partial")).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join("partial")).unwrap(),
+            "hello world"
+        );
+
+        // The part downloaded is kept when the download fails
+        fs::write(temp.join("partial"), "hello").unwrap();
+        let missing = format!("file://{}", temp.join("missing").display());
+        client
+            .download(&missing, &[], &temp.join("partial"))
+            .unwrap_err();
+        assert_eq!(fs::read_to_string(temp.join("partial")).unwrap(), "hello");
+
+        fs::write(temp.join(".git/config"), "[http]\n\tmaxRetries = many\n").unwrap();
+        assert_eq!(
+            HttpClient::new(&repo).unwrap_err().to_string(),
+            "bad numeric config value 'many' for 'http.maxretries'"
+        );
+    }
+
+    #[test]
+    fn authenticates_with_credential_helpers() {
+        let (temp, repo) = setup(
+            "[credential]\n\thelper = \"!f() { test $1 = get || echo $1 >>log; \
+             echo username=alice; echo password=secret; }; f\"\n",
+        );
+        // Requests are refused unless they are sent with the credential
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/repo", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut authorized = Vec::new();
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut credential = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.trim_end().strip_prefix("Authorization: Basic ") {
+                        credential = Some(base64::decode(value.as_bytes()).unwrap());
+                    }
+                    line.clear();
+                }
+                let response: &[u8] = match credential.as_deref() {
+                    Some(b"alice:secret") => b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+                    _ => b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n",
+                };
+                (&stream).write_all(response).unwrap();
+                authorized.push(credential.is_some());
+            }
+            authorized
+        });
+
+        let client = HttpClient::new(&repo).unwrap();
+        assert_eq!(client.request("GET", &url, &[], None).unwrap(), b"ok");
+        // The credential accepted is stored, and sent with the next requests
+        assert_eq!(fs::read_to_string(temp.join("log")).unwrap(), "store\n");
+        assert_eq!(client.request("GET", &url, &[], None).unwrap(), b"ok");
+        assert_eq!(server.join().unwrap(), [false, true, true]);
+    }
+}
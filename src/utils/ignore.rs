@@ -0,0 +1,362 @@
+//! Deciding which untracked files are ignored, from the patterns of (by precedence):
+//!
+//! 1. The `.gitignore` files of the working tree, the deepest one first
+//! 2. `$GIT_COMMON_DIR/info/exclude`
+//! 3. The file set with `core.excludesFile` (`$XDG_CONFIG_HOME/git/ignore` or
+//!    `~/.config/git/ignore` by default)
+//!
+//! Within a file the last matching pattern wins, and a pattern starting with `!`
+//! re-includes the paths matched by earlier patterns. Each pattern keeps the file
+//! and line it comes from, so commands can report why a path is ignored.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::utils::config::{xdg_config_path, Config};
+use crate::utils::repository::Repository;
+use crate::utils::wildmatch::wildmatch;
+
+/// A pattern of an ignore file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IgnorePattern {
+    /// The file the pattern comes from, as shown to the user
+    pub(crate) source: String,
+    /// The 1-based line number of the pattern in its file
+    pub(crate) line: usize,
+    /// The pattern as written in the file (without trailing whitespace)
+    pub(crate) text: String,
+    /// Whether the pattern re-includes the paths it matches (`!` prefix)
+    pub(crate) negated: bool,
+    /// The glob to match, without the `!` prefix, leading `/` and trailing `/`
+    glob: String,
+    /// Whether the pattern only matches directories (`/` suffix)
+    dir_only: bool,
+    /// Whether the pattern is matched against the path relative to `base`
+    /// rather than against the file name (it contains a `/`)
+    anchored: bool,
+    /// The directory containing the ignore file (`dir/`), empty for the root
+    /// and for the files outside the working tree
+    base: String,
+}
+
+impl IgnorePattern {
+    /// Whether the pattern matches a path (relative to the root of the working tree).
+    pub(crate) fn matches(&self, path: &str, is_dir: bool, ignore_case: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+
+        if self.anchored {
+            wildmatch(&self.glob, relative, ignore_case)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.glob, name, ignore_case)
+        }
+    }
+}
+
+/// The ignore rules of a working tree
+#[derive(Debug)]
+pub(crate) struct Ignore {
+    /// The root of the working tree
+    work_tree: PathBuf,
+    /// Whether patterns are matched case-insensitively (`core.ignoreCase`)
+    ignore_case: bool,
+    /// The patterns of `info/exclude` and `core.excludesFile`, by precedence
+    excludes: Vec<Vec<IgnorePattern>>,
+    /// The patterns of the `.gitignore` file of each directory read so far (`dir/`)
+    directories: HashMap<String, Vec<IgnorePattern>>,
+}
+
+impl Ignore {
+    /// Load the ignore rules of a repository. The `.gitignore` files are read
+    /// as the directories containing them are reached.
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let work_tree = repo.work_tree()?;
+        let config = Config::load(repo)?;
+
+        let exclude = repo.common_dir()?.join("info/exclude");
+        let source = match exclude.strip_prefix(&work_tree) {
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            Err(_) => exclude.to_string_lossy().into_owned(),
+        };
+        let mut excludes = vec![read_patterns(&exclude, &source, "")?];
+
+        // The global file is shown as configured, after `~/` expansion
+        if let Some(path) = config
+            .get_path("core.excludesFile")
+            .or_else(|| xdg_config_path(config.env(), "ignore"))
+        {
+            excludes.push(read_patterns(&path, &path.to_string_lossy(), "")?);
+        }
+
+        Ok(Ignore {
+            work_tree,
+            ignore_case: config.get_bool("core.ignoreCase")?.unwrap_or(false),
+            excludes,
+            directories: HashMap::new(),
+        })
+    }
+
+    /// Check whether a path is ignored, assuming its parent directories are not
+    /// (as when walking the working tree, where ignored directories are skipped).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the root of the working tree
+    /// * `is_dir` - Whether the path is a directory
+    pub(crate) fn is_ignored(&mut self, path: &str, is_dir: bool) -> anyhow::Result<bool> {
+        Ok(self
+            .last_match(path, is_dir)?
+            .is_some_and(|pattern| !pattern.negated))
+    }
+
+    /// Find the pattern deciding whether a path is ignored. A path inside an
+    /// ignored directory is ignored by the pattern matching the directory,
+    /// whatever the patterns matching the path itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the root of the working tree
+    /// * `is_dir` - Whether the path is a directory
+    ///
+    /// # Returns
+    ///
+    /// The matching pattern (which re-includes the path if it is negated),
+    /// or `None` if no pattern matches
+    pub(crate) fn matching_pattern(
+        &mut self,
+        path: &str,
+        is_dir: bool,
+    ) -> anyhow::Result<Option<&IgnorePattern>> {
+        let mut ignored_dir = None;
+        for (index, _) in path.match_indices('/') {
+            if self.is_ignored(&path[..index], true)? {
+                ignored_dir = Some(&path[..index]);
+                break;
+            }
+        }
+
+        match ignored_dir {
+            Some(dir) => self.last_match(dir, true),
+            None => self.last_match(path, is_dir),
+        }
+    }
+
+    /// Find the last pattern matching a path, in the files by precedence.
+    fn last_match(&mut self, path: &str, is_dir: bool) -> anyhow::Result<Option<&IgnorePattern>> {
+        // The `.gitignore` files of the directories containing the path, deepest first
+        let mut dirs = vec![String::new()];
+        dirs.extend(
+            path.match_indices('/')
+                .map(|(index, _)| path[..=index].to_string()),
+        );
+        for dir in &dirs {
+            self.load_directory(dir)?;
+        }
+
+        let ignore_case = self.ignore_case;
+        let lists = dirs
+            .iter()
+            .rev()
+            .filter_map(|dir| self.directories.get(dir))
+            .chain(&self.excludes);
+        for patterns in lists {
+            let found = patterns
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(path, is_dir, ignore_case));
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Read the `.gitignore` file of a directory (`dir/`), unless already read.
+    fn load_directory(&mut self, dir: &str) -> anyhow::Result<()> {
+        if !self.directories.contains_key(dir) {
+            let path = self.work_tree.join(dir).join(".gitignore");
+            let patterns = read_patterns(&path, &format!("{dir}.gitignore"), dir)?;
+            self.directories.insert(dir.to_string(), patterns);
+        }
+        Ok(())
+    }
+}
+
+/// Read the patterns of an ignore file, which may not exist.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file
+/// * `source` - The name of the file shown to the user
+/// * `base` - The directory containing the file (`dir/`), relative to the root of the working tree
+pub(crate) fn read_patterns(
+    path: &Path,
+    source: &str,
+    base: &str,
+) -> anyhow::Result<Vec<IgnorePattern>> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotADirectory => return Ok(Vec::new()),
+        Err(err) => return Err(err).context(format!("read {}", path.display())),
+    };
+
+    Ok(String::from_utf8_lossy(&content)
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| parse_pattern(line, source, index + 1, base))
+        .collect())
+}
+
+/// Parse a line of an ignore file.
+///
+/// # Returns
+///
+/// The pattern, or `None` for empty lines and comments
+fn parse_pattern(line: &str, source: &str, number: usize, base: &str) -> Option<IgnorePattern> {
+    let text = trim_trailing_spaces(line.strip_suffix('\r').unwrap_or(line));
+    if text.is_empty() || text.starts_with('#') {
+        return None;
+    }
+
+    let (negated, glob) = match text.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    // `\#` and `\!` escape the special meaning of a leading character
+    let glob = match glob.strip_prefix('\\') {
+        Some(rest) if rest.starts_with(['#', '!']) => rest,
+        _ => glob,
+    };
+    let (dir_only, glob) = match glob.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, glob),
+    };
+    let anchored = glob.contains('/');
+    let glob = glob.strip_prefix('/').unwrap_or(glob);
+    if glob.is_empty() {
+        return None;
+    }
+
+    Some(IgnorePattern {
+        source: source.to_string(),
+        line: number,
+        text: text.to_string(),
+        negated,
+        glob: glob.to_string(),
+        dir_only,
+        anchored,
+        base: base.to_string(),
+    })
+}
+
+/// Remove the trailing spaces of a line, except those escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while line[..end].ends_with(' ') {
+        let backslashes = line[..end - 1]
+            .chars()
+            .rev()
+            .take_while(|&c| c == '\\')
+            .count();
+        if backslashes % 2 == 1 {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/info")).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    #[test]
+    fn parses_patterns() {
+        let pattern = |line| parse_pattern(line, ".gitignore", 1, "");
+        assert_eq!(pattern("# comment"), None);
+        assert_eq!(pattern("   "), None);
+
+        let parsed = pattern("!/build/  ").unwrap();
+        assert_eq!(parsed.text, "!/build/");
+        assert!(parsed.negated && parsed.dir_only && parsed.anchored);
+        assert_eq!(parsed.glob, "build");
+
+        let parsed = pattern("\\#file\\ ").unwrap();
+        assert_eq!(parsed.text, "\\#file\\ ");
+        assert_eq!(parsed.glob, "#file\\ ");
+        assert!(!parsed.negated && !parsed.anchored);
+    }
+
+    #[test]
+    fn follows_precedence_of_ignore_files() {
+        let (mut temp, _) = setup();
+        let xdg = temp.join("xdg");
+        fs::create_dir_all(xdg.join("git")).unwrap();
+        fs::write(xdg.join("git/ignore"), "*.log\n!keep.log\n").unwrap();
+        temp.set_env("XDG_CONFIG_HOME", &xdg);
+        let repo = temp.repo();
+
+        fs::create_dir_all(temp.join("sub/deep")).unwrap();
+        fs::write(temp.join(".gitignore"), "build/\n/top\n").unwrap();
+        fs::write(temp.join("sub/.gitignore"), "!*.log\n*.tmp\n").unwrap();
+        fs::write(temp.join(".git/info/exclude"), "secret\n").unwrap();
+
+        let mut ignore = Ignore::load(&repo).unwrap();
+        let mut matched = |path: &str, is_dir| {
+            ignore
+                .matching_pattern(path, is_dir)
+                .unwrap()
+                .map(|pattern| format!("{}:{}:{}", pattern.source, pattern.line, pattern.text))
+        };
+
+        let global = xdg.join("git/ignore").display().to_string();
+        assert_eq!(matched("a.log", false), Some(format!("{global}:1:*.log")));
+        assert_eq!(
+            matched("keep.log", false),
+            Some(format!("{global}:2:!keep.log"))
+        );
+        assert_eq!(
+            matched("sub/deep/a.log", false).unwrap(),
+            "sub/.gitignore:1:!*.log"
+        );
+        assert_eq!(
+            matched("sub/a.tmp", false).unwrap(),
+            "sub/.gitignore:2:*.tmp"
+        );
+        assert_eq!(matched("top", false).unwrap(), ".gitignore:2:/top");
+        assert_eq!(matched("sub/top", false), None);
+        assert_eq!(matched("build", false), None);
+        assert_eq!(
+            matched("build/a.txt", false).unwrap(),
+            ".gitignore:1:build/"
+        );
+        assert_eq!(
+            matched("sub/secret", false).unwrap(),
+            ".git/info/exclude:1:secret"
+        );
+
+        // core.excludesFile replaces the default global file
+        fs::write(temp.join(".git/config"), "[core]\n\texcludesFile = other\n").unwrap();
+        let mut ignore = Ignore::load(&repo).unwrap();
+        assert!(!ignore.is_ignored("a.log", false).unwrap());
+    }
+}
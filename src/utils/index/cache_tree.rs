@@ -0,0 +1,311 @@
+//! The cached trees of the index (the `TREE` extension)
+//!
+//! Writing the trees of a large index means hashing every directory. Instead,
+//! the hashes of the trees written from the index are recorded along with the
+//! number of entries they cover, and a directory is only written again once
+//! one of its entries changed.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::utils::hex;
+use crate::utils::index::{IndexEntry, Reader};
+use crate::utils::objects::{object_exists, write_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::tree::{serialize_tree, TreeEntry, EMPTY_TREE, MODE_GITLINK, MODE_TREE};
+
+/// The cached tree of a directory of the index and those of its subdirectories
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct CacheTree {
+    /// The number of index entries in the directory and the hash of its tree,
+    /// or `None` if an entry changed since the tree was written
+    valid: Option<(usize, String)>,
+    /// The cached trees of the subdirectories, by name
+    subtrees: BTreeMap<Vec<u8>, CacheTree>,
+}
+
+impl CacheTree {
+    /// Parse the content of the extension.
+    ///
+    /// Each directory, starting at the root, is stored as
+    /// `{name}\0{entry count} {subtree count}\n` followed by the 20-byte hash of
+    /// its tree (unless the entry count is `-1`) and then its subdirectories.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader(data);
+        let (_, tree) = parse_directory(&mut reader)?;
+        if !reader.0.is_empty() {
+            anyhow::bail!("trailing data after cached trees");
+        }
+        Ok(tree)
+    }
+
+    /// Serialize the content of the extension.
+    pub(crate) fn serialize(&self, data: &mut Vec<u8>) {
+        self.serialize_directory(b"", data);
+    }
+
+    fn serialize_directory(&self, name: &[u8], data: &mut Vec<u8>) {
+        data.extend(name);
+        data.push(0);
+
+        match &self.valid {
+            Some((count, hash)) => {
+                data.extend(format!("{count} {}\n", self.subtrees.len()).as_bytes());
+                data.extend(hex::decode(hash.as_bytes()).unwrap_or_else(|_| vec![0; 20]));
+            },
+            None => data.extend(format!("-1 {}\n", self.subtrees.len()).as_bytes()),
+        }
+
+        for (name, subtree) in &self.subtrees {
+            subtree.serialize_directory(name, data);
+        }
+    }
+
+    /// The hash of the tree, if no entry changed since it was written
+    pub(crate) fn hash(&self) -> Option<&str> {
+        self.valid.as_ref().map(|(_, hash)| hash.as_str())
+    }
+
+    /// Invalidate the trees of the directories containing a path, and forget
+    /// the cached tree of the path itself, which may no longer be a directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to this directory
+    pub(crate) fn invalidate(&mut self, path: &[u8]) {
+        self.valid = None;
+        match path.iter().position(|&byte| byte == b'/') {
+            Some(slash) => {
+                if let Some(subtree) = self.subtrees.get_mut(&path[..slash]) {
+                    subtree.invalidate(&path[slash + 1..]);
+                }
+            },
+            None => {
+                self.subtrees.remove(path);
+            },
+        }
+    }
+
+    /// Write the tree of a directory, reusing the cached trees that are still valid
+    /// and caching the ones that were written.
+    /// Entries added with `--intent-to-add` are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository to write the objects to
+    /// * `entries` - The resolved index entries within the directory, sorted by path
+    /// * `prefix_len` - The length of the directory path, including its trailing slash
+    /// * `missing_ok` - Whether entries may refer to objects missing from the repository
+    ///
+    /// # Returns
+    ///
+    /// The hash of the tree, and whether entries were left out of it
+    pub(crate) fn update(
+        &mut self,
+        repo: &Repository,
+        entries: &[IndexEntry],
+        prefix_len: usize,
+        missing_ok: bool,
+    ) -> anyhow::Result<(String, bool)> {
+        if let Some((count, hash)) = &self.valid {
+            if *count == entries.len() {
+                return Ok((hash.clone(), false));
+            }
+        }
+
+        let mut tree = Vec::new();
+        let mut subtree_names = Vec::new();
+        let mut intent_to_add = false;
+        let mut rest = entries;
+
+        while let Some(entry) = rest.first() {
+            let name = &entry.path[prefix_len..];
+            let Some(slash) = name.iter().position(|&byte| byte == b'/') else {
+                rest = &rest[1..];
+                if entry.intent_to_add {
+                    intent_to_add = true;
+                    continue;
+                }
+
+                // Submodule commits live in another repository
+                if !missing_ok && entry.mode != MODE_GITLINK && !object_exists(repo, &entry.hash)? {
+                    anyhow::bail!(
+                        "invalid object {} for '{}'",
+                        entry.hash,
+                        String::from_utf8_lossy(&entry.path)
+                    );
+                }
+
+                tree.push(TreeEntry {
+                    mode: entry.mode,
+                    name: name.to_vec(),
+                    hash: entry.hash.clone(),
+                });
+                continue;
+            };
+
+            // All entries of a subdirectory are adjacent in path order
+            let directory = &entry.path[..prefix_len + slash + 1];
+            let count = rest
+                .iter()
+                .take_while(|entry| entry.path.starts_with(directory))
+                .count();
+            let name = &name[..slash];
+
+            let subtree = self.subtrees.entry(name.to_vec()).or_default();
+            let (hash, subtree_intent_to_add) =
+                subtree.update(repo, &rest[..count], directory.len(), missing_ok)?;
+            intent_to_add |= subtree_intent_to_add;
+            subtree_names.push(name);
+
+            // Directories that only contain entries added with `--intent-to-add` are left out
+            if !(subtree_intent_to_add && hash == EMPTY_TREE) {
+                tree.push(TreeEntry {
+                    mode: MODE_TREE,
+                    name: name.to_vec(),
+                    hash,
+                });
+            }
+            rest = &rest[count..];
+        }
+
+        self.subtrees
+            .retain(|name, _| subtree_names.contains(&name.as_slice()));
+
+        let hash = write_object(repo, ObjectType::Tree, &serialize_tree(tree)?)?;
+        // Trees missing entries are not cached, so they are written again once the entries are added
+        self.valid = (!intent_to_add).then(|| (entries.len(), hash.clone()));
+        Ok((hash, intent_to_add))
+    }
+}
+
+/// Parse the cached tree of a directory and its subdirectories.
+///
+/// # Returns
+///
+/// The name of the directory and its cached tree
+fn parse_directory(reader: &mut Reader) -> anyhow::Result<(Vec<u8>, CacheTree)> {
+    let name = reader.read_until(b'\0')?.to_vec();
+
+    let mut read_number = |delimiter| -> anyhow::Result<i64> {
+        let number = std::str::from_utf8(reader.read_until(delimiter)?)?;
+        number
+            .parse()
+            .context(format!("invalid count {number} in cached tree"))
+    };
+    let entry_count = read_number(b' ')?;
+    let subtree_count = read_number(b'\n')?;
+
+    let valid = if entry_count >= 0 {
+        let mut hash = reader.read_bytes(20)?.to_vec();
+        hex::encode_in_place(&mut hash);
+        Some((entry_count as usize, String::from_utf8(hash)?))
+    } else {
+        None
+    };
+
+    let mut subtrees = BTreeMap::new();
+    for _ in 0..subtree_count {
+        let (name, subtree) = parse_directory(reader)?;
+        subtrees.insert(name, subtree);
+    }
+
+    Ok((name, CacheTree { valid, subtrees }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacheTree;
+    use crate::utils::hex;
+    use crate::utils::index::IndexEntry;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::flatten_tree;
+
+    const HASH: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    #[test]
+    fn parses_cached_trees() {
+        let binary = hex::decode(HASH.as_bytes()).unwrap();
+        let mut data = b"\x003 2\n".to_vec();
+        data.extend(&binary);
+        data.extend(b"dir\0-1 0\n");
+        data.extend(b"other\x001 0\n");
+        data.extend(&binary);
+
+        let tree = CacheTree::parse(&data).unwrap();
+        assert_eq!(tree.hash(), Some(HASH));
+        assert_eq!(tree.subtrees[&b"dir"[..]].hash(), None);
+        assert_eq!(tree.subtrees[&b"other"[..]].hash(), Some(HASH));
+
+        let mut serialized = Vec::new();
+        tree.serialize(&mut serialized);
+        assert_eq!(serialized, data);
+
+        assert!(CacheTree::parse(b"\x001 0\n").is_err());
+        assert!(CacheTree::parse(b"\0-1 1\n").is_err());
+    }
+
+    #[test]
+    fn reuses_valid_trees() {
+        let temp = TempRepo::new();
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let entry =
+            |path: &str| IndexEntry::new(path.as_bytes().to_vec(), 0o100644, HASH.to_string());
+        let entries = vec![entry("a.txt"), entry("dir/b.txt"), entry("dir/sub/c.txt")];
+
+        let mut tree = CacheTree::default();
+        let (hash, _) = tree.update(&repo, &entries, 0, true).unwrap();
+        assert_eq!(tree.hash(), Some(hash.as_str()));
+        let paths: Vec<_> = flatten_tree(&repo, &hash)
+            .unwrap()
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).unwrap())
+            .collect();
+        assert_eq!(paths, vec!["a.txt", "dir/b.txt", "dir/sub/c.txt"]);
+
+        // A valid subtree is used as is, even if it differs from the entries
+        let dir = tree.subtrees.get_mut(&b"dir"[..]).unwrap();
+        dir.valid = Some((2, HASH.to_string()));
+        tree.invalidate(b"a.txt");
+        let (changed, _) = tree.update(&repo, &entries, 0, true).unwrap();
+        assert_ne!(changed, hash);
+        assert_eq!(tree.subtrees[&b"dir"[..]].hash(), Some(HASH));
+
+        // Invalidating a path invalidates all directories containing it
+        tree.invalidate(b"dir/b.txt");
+        assert_eq!(tree.hash(), None);
+        assert_eq!(tree.subtrees[&b"dir"[..]].hash(), None);
+        assert!(tree.subtrees[&b"dir"[..]].subtrees[&b"sub"[..]]
+            .hash()
+            .is_some());
+        let (hash_again, _) = tree.update(&repo, &entries, 0, true).unwrap();
+        assert_eq!(hash_again, hash);
+    }
+
+    #[test]
+    fn does_not_cache_trees_with_intent_to_add_entries() {
+        let temp = TempRepo::new();
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let entries = vec![
+            IndexEntry::new(b"a.txt".to_vec(), 0o100644, HASH.to_string()),
+            IndexEntry {
+                intent_to_add: true,
+                ..IndexEntry::new(b"new/b.txt".to_vec(), 0o100644, HASH.to_string())
+            },
+        ];
+
+        let mut tree = CacheTree::default();
+        let (hash, intent_to_add) = tree.update(&repo, &entries, 0, true).unwrap();
+        assert!(intent_to_add);
+        assert_eq!(tree.hash(), None);
+        assert_eq!(flatten_tree(&repo, &hash).unwrap().len(), 1);
+
+        // Objects must exist unless they may be missing
+        assert!(tree.update(&repo, &entries, 0, false).is_err());
+    }
+}
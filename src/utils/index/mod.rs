@@ -0,0 +1,999 @@
+//! Reading and writing the index (`.git/index`)
+//!
+//! See [`index_format.md`](../../../index_format.md) for the layout of the file.
+//! Versions 2, 3 (extended flags) and 4 (prefix-compressed paths) are supported.
+//! The cached tree (`TREE`) and resolve-undo (`REUC`) extensions are kept,
+//! other optional extensions are skipped when reading and not written back.
+
+use std::fs::Metadata;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::utils::repository::Repository;
+use crate::utils::stats::Counters;
+use crate::utils::tempfile::TempPath;
+use crate::utils::tree::{MODE_GITLINK, MODE_TYPE_MASK};
+use crate::utils::work_tree::{file_mode, hash_file};
+use crate::utils::{hex, path_from_bytes};
+
+mod cache_tree;
+mod resolve_undo;
+
+pub(crate) use cache_tree::CacheTree;
+pub(crate) use resolve_undo::ResolveUndo;
+
+/// The signature at the start of every index file ("directory cache")
+const SIGNATURE: &[u8; 4] = b"DIRC";
+/// The length of the header (signature, version and entry count)
+const HEADER_LEN: usize = 12;
+/// The length of the trailing checksum
+const CHECKSUM_LEN: usize = 20;
+/// The signature of the cached tree extension
+const EXTENSION_TREE: &[u8] = b"TREE";
+/// The signature of the resolve-undo extension
+const EXTENSION_REUC: &[u8] = b"REUC";
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+const EXTENDED_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+/// A timestamp as stored in the index
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Timestamp {
+    pub(crate) seconds: u32,
+    pub(crate) nanoseconds: u32,
+}
+
+/// The file system metadata cached for an index entry,
+/// used to detect changes without hashing the file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stat {
+    pub(crate) ctime: Timestamp,
+    pub(crate) mtime: Timestamp,
+    pub(crate) dev: u32,
+    pub(crate) ino: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    /// The file size, truncated to 32 bits
+    pub(crate) size: u32,
+}
+
+impl Stat {
+    /// Collect the cached metadata of a file.
+    pub(crate) fn from_metadata(metadata: &Metadata) -> Self {
+        // The index stores all values truncated to 32 bits
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            Stat {
+                ctime: Timestamp {
+                    seconds: metadata.ctime() as u32,
+                    nanoseconds: metadata.ctime_nsec() as u32,
+                },
+                mtime: Timestamp {
+                    seconds: metadata.mtime() as u32,
+                    nanoseconds: metadata.mtime_nsec() as u32,
+                },
+                dev: metadata.dev() as u32,
+                ino: metadata.ino() as u32,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                size: metadata.size() as u32,
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .unwrap_or_default();
+
+            Stat {
+                mtime: Timestamp {
+                    seconds: mtime.as_secs() as u32,
+                    nanoseconds: mtime.subsec_nanos(),
+                },
+                size: metadata.len() as u32,
+                ..Stat::default()
+            }
+        }
+    }
+}
+
+/// An entry of the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    /// The cached file system metadata
+    pub(crate) stat: Stat,
+    /// The file mode (e.g. `0o100644`)
+    pub(crate) mode: u32,
+    /// The hex hash of the blob (or commit, for gitlinks)
+    pub(crate) hash: String,
+    /// The merge stage (0 for regular entries, 1-3 for conflicts)
+    pub(crate) stage: u8,
+    /// Whether the file is assumed to be unchanged (`update-index --assume-unchanged`)
+    pub(crate) assume_valid: bool,
+    /// Whether the file is excluded from the working tree (sparse checkout)
+    pub(crate) skip_worktree: bool,
+    /// Whether the file was added with `add --intent-to-add`
+    pub(crate) intent_to_add: bool,
+    /// The `/`-separated path relative to the root of the working tree, as
+    /// raw bytes since it need not be valid UTF-8
+    pub(crate) path: Vec<u8>,
+}
+
+impl IndexEntry {
+    /// Create a stage 0 entry without cached metadata,
+    /// e.g. for content that was read from a tree.
+    pub(crate) fn new(path: Vec<u8>, mode: u32, hash: String) -> Self {
+        IndexEntry {
+            stat: Stat::default(),
+            mode,
+            hash,
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path,
+        }
+    }
+
+    /// Create a stage 0 entry for a file of the working tree.
+    pub(crate) fn from_metadata(path: Vec<u8>, hash: String, metadata: &Metadata) -> Self {
+        IndexEntry {
+            stat: Stat::from_metadata(metadata),
+            ..IndexEntry::new(path, file_mode(metadata), hash)
+        }
+    }
+
+    /// Whether the entry needs the extended flags of index version 3
+    fn has_extended_flags(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+/// How a file of the working tree differs from its index entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WorkTreeChange {
+    Modified,
+    Deleted,
+    /// The file changed between a regular file, a symlink and a submodule
+    TypeChanged,
+}
+
+/// The parsed index of a working tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Index {
+    version: u32,
+    entries: Vec<IndexEntry>,
+    /// The modification time of the index file when it was loaded.
+    /// Entries modified at or after it cannot be trusted by their metadata alone.
+    timestamp: Option<Timestamp>,
+    /// The trees last written from the index, if any
+    cache_tree: Option<CacheTree>,
+    /// The conflicting stages of the paths that were resolved
+    resolve_undo: ResolveUndo,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index {
+            version: 2,
+            entries: Vec::new(),
+            timestamp: None,
+            cache_tree: None,
+            resolve_undo: ResolveUndo::default(),
+        }
+    }
+}
+
+impl Index {
+    /// Load the index of a repository.
+    /// A missing index file is treated as an empty index.
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let path = repo.index_path()?;
+        let Ok(metadata) = path.metadata() else {
+            return Ok(Index::default());
+        };
+
+        let data = std::fs::read(&path).context(format!("read index {}", path.display()))?;
+        let mut index = Index::parse(&data).context(format!("parse index {}", path.display()))?;
+        index.timestamp = Some(Stat::from_metadata(&metadata).mtime);
+        Ok(index)
+    }
+
+    /// Parse the content of an index file, verifying its checksum.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < HEADER_LEN + CHECKSUM_LEN {
+            anyhow::bail!("index file is too short");
+        }
+
+        let (content, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+        // An all-zero checksum means it was skipped when writing (`index.skipHash`)
+        if checksum.iter().any(|&b| b != 0) && Sha1::digest(content).as_slice() != checksum {
+            anyhow::bail!("index file checksum mismatch");
+        }
+
+        let mut reader = Reader(content);
+        if reader.read_bytes(4)? != SIGNATURE {
+            anyhow::bail!("bad index file signature");
+        }
+
+        let version = reader.read_u32()?;
+        if !(2..=4).contains(&version) {
+            anyhow::bail!("bad index file version {}", version);
+        }
+
+        let count = reader.read_u32()? as usize;
+        let mut entries = Vec::with_capacity(count);
+        let mut previous_path = Vec::new();
+
+        for _ in 0..count {
+            let entry = parse_entry(&mut reader, version, &previous_path)?;
+            previous_path.clone_from(&entry.path);
+            entries.push(entry);
+        }
+
+        let mut cache_tree = None;
+        let mut resolve_undo = ResolveUndo::default();
+
+        // Extensions whose signature starts with an uppercase letter are optional
+        while !reader.0.is_empty() {
+            let signature = reader.read_bytes(4)?;
+            let size = reader.read_u32()? as usize;
+            let data = reader.read_bytes(size)?;
+
+            match signature {
+                // An empty extension means that no tree was cached
+                EXTENSION_TREE if !data.is_empty() => {
+                    cache_tree = Some(CacheTree::parse(data).context("parse cached trees")?);
+                },
+                EXTENSION_REUC => {
+                    resolve_undo = ResolveUndo::parse(data).context("parse resolve-undo")?;
+                },
+                _ if signature[0].is_ascii_uppercase() => {},
+                _ => anyhow::bail!(
+                    "unsupported index extension {}",
+                    String::from_utf8_lossy(signature)
+                ),
+            }
+        }
+
+        Ok(Index {
+            version,
+            entries,
+            timestamp: None,
+            cache_tree,
+            resolve_undo,
+        })
+    }
+
+    /// Write the index of a repository.
+    ///
+    /// The content is written to `index.lock` first and then renamed into place,
+    /// so a concurrent writer fails instead of overwriting the changes.
+    pub(crate) fn write(&self, repo: &Repository) -> anyhow::Result<()> {
+        let path = repo.index_path()?;
+        let lock_path = path.with_file_name(format!(
+            "{}.lock",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        let mut file = match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                anyhow::bail!("Unable to create '{}': File exists.", lock_path.display())
+            },
+            Err(err) => {
+                return Err(err).context(format!("Unable to create '{}'", lock_path.display()))
+            },
+        };
+
+        let lock = TempPath::new(lock_path);
+
+        file.write_all(&self.serialize())
+            .context(format!("write {}", lock.path().display()))?;
+        std::fs::rename(lock.path(), &path).context(format!("rename {}", lock.path().display()))?;
+        lock.keep();
+        Ok(())
+    }
+
+    /// Serialize the index, followed by the SHA-1 checksum of the content.
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        // Version 2 cannot store extended flags, so those indexes are upgraded to 3
+        let version: u32 = match self.version {
+            4 => 4,
+            _ if self.entries.iter().any(IndexEntry::has_extended_flags) => 3,
+            _ => 2,
+        };
+
+        let mut data = SIGNATURE.to_vec();
+        data.extend(version.to_be_bytes());
+        data.extend((self.entries.len() as u32).to_be_bytes());
+
+        let mut previous_path: &[u8] = b"";
+        for entry in &self.entries {
+            serialize_entry(&mut data, entry, version, previous_path);
+            previous_path = &entry.path;
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut extension = Vec::new();
+            cache_tree.serialize(&mut extension);
+            serialize_extension(&mut data, EXTENSION_TREE, &extension);
+        }
+        if !self.resolve_undo.is_empty() {
+            let mut extension = Vec::new();
+            self.resolve_undo.serialize(&mut extension);
+            serialize_extension(&mut data, EXTENSION_REUC, &extension);
+        }
+
+        let checksum = Sha1::digest(&data);
+        data.extend(checksum);
+        data
+    }
+
+    /// All entries, sorted by path and stage.
+    pub(crate) fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Get the entry for a path at a stage.
+    pub(crate) fn entry(&self, path: &[u8], stage: u8) -> Option<&IndexEntry> {
+        self.position(path, stage)
+            .ok()
+            .map(|position| &self.entries[position])
+    }
+
+    /// Whether any stage of a path is tracked.
+    pub(crate) fn contains(&self, path: &[u8]) -> bool {
+        (0..=3).any(|stage| self.entry(path, stage).is_some())
+    }
+
+    /// The conflicting stages of the paths that were resolved.
+    pub(crate) fn resolve_undo(&self) -> &ResolveUndo {
+        &self.resolve_undo
+    }
+
+    /// Add an entry, replacing any entry for the same path and stage.
+    ///
+    /// Adding a resolved (stage 0) entry removes the conflict stages of the path
+    /// (recording them for resolve-undo), and adding a conflict stage removes the
+    /// resolved entry. Entries of the same stage that would conflict with the path
+    /// as a directory (or a file in one) are removed as well, while the stages of
+    /// a conflict between a file and a directory are kept side by side.
+    pub(crate) fn add_entry(&mut self, entry: IndexEntry) {
+        self.invalidate_tree(&entry.path);
+        if entry.stage == 0 {
+            self.remove_entry(&entry.path);
+        } else {
+            self.remove_stage(&entry.path, 0);
+            self.remove_stage(&entry.path, entry.stage);
+        }
+
+        // A file replaces a directory of the same name, and vice versa
+        self.entries.retain(|existing| {
+            existing.stage != entry.stage
+                || !is_inside(&existing.path, &entry.path)
+                    && !is_inside(&entry.path, &existing.path)
+        });
+
+        let position = self
+            .position(&entry.path, entry.stage)
+            .unwrap_or_else(|position| position);
+        self.entries.insert(position, entry);
+    }
+
+    /// Remove all stages of a path, recording conflicting stages for resolve-undo.
+    ///
+    /// # Returns
+    ///
+    /// `true` if any entry was removed
+    pub(crate) fn remove_entry(&mut self, path: &[u8]) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| {
+            if entry.path != path {
+                return true;
+            }
+            self.resolve_undo.record(entry);
+            false
+        });
+
+        let removed = self.entries.len() != len;
+        if removed {
+            self.invalidate_tree(path);
+        }
+        removed
+    }
+
+    /// Recreate the conflicting stages of a resolved path from its resolve-undo
+    /// record, in place of its resolved entry.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the path had a record
+    pub(crate) fn unresolve(&mut self, path: &[u8]) -> bool {
+        let Some(stages) = self.resolve_undo.remove(path) else {
+            return false;
+        };
+        for (stage, state) in (1..).zip(stages) {
+            if let Some((mode, hash)) = state {
+                let mut entry = IndexEntry::new(path.to_vec(), mode, hash);
+                entry.stage = stage;
+                self.add_entry(entry);
+            }
+        }
+        true
+    }
+
+    /// Remove a single stage of a path.
+    fn remove_stage(&mut self, path: &[u8], stage: u8) {
+        if let Ok(position) = self.position(path, stage) {
+            self.entries.remove(position);
+        }
+    }
+
+    /// Invalidate the cached trees of the directories containing a path.
+    fn invalidate_tree(&mut self, path: &[u8]) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(path);
+        }
+    }
+
+    fn position(&self, path: &[u8], stage: u8) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| (entry.path.as_slice(), entry.stage).cmp(&(path, stage)))
+    }
+
+    /// The hash of the root tree, if it was written from the index
+    /// and no entry changed since.
+    pub(crate) fn cached_tree(&self) -> Option<&str> {
+        self.cache_tree.as_ref().and_then(CacheTree::hash)
+    }
+
+    /// Write the tree objects for the resolved entries of the index,
+    /// only writing the directories whose cached trees are no longer valid.
+    /// Entries added with `--intent-to-add` are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository to write the objects to
+    /// * `missing_ok` - Whether entries may refer to objects missing from the repository
+    ///
+    /// # Returns
+    ///
+    /// The hash of the root tree
+    pub(crate) fn write_tree(
+        &mut self,
+        repo: &Repository,
+        missing_ok: bool,
+    ) -> anyhow::Result<String> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.stage != 0) {
+            anyhow::bail!(
+                "{}: unmerged ({})",
+                String::from_utf8_lossy(&entry.path),
+                entry.hash
+            );
+        }
+
+        let cache_tree = self.cache_tree.get_or_insert_with(CacheTree::default);
+        let (hash, _) = cache_tree.update(repo, &self.entries, 0, missing_ok)?;
+        Ok(hash)
+    }
+
+    /// Compare the file in the working tree with an entry.
+    /// The cached metadata is compared first, so unchanged files are not hashed.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - An entry of this index
+    /// * `work_tree` - The root of the working tree
+    ///
+    /// # Returns
+    ///
+    /// How the file changed, or `None` if it matches the entry
+    pub(crate) fn compare(
+        &self,
+        entry: &IndexEntry,
+        work_tree: &Path,
+    ) -> anyhow::Result<Option<WorkTreeChange>> {
+        Counters::update(|counters| counters.entries_compared += 1);
+        // Files outside of a sparse checkout are missing on purpose
+        if entry.skip_worktree {
+            return Ok(None);
+        }
+        let path = work_tree.join(path_from_bytes(&entry.path));
+        let Ok(metadata) = path.symlink_metadata() else {
+            return Ok(Some(WorkTreeChange::Deleted));
+        };
+
+        if entry.assume_valid {
+            return Ok(None);
+        }
+
+        let mode = file_mode(&metadata);
+        if (mode & MODE_TYPE_MASK) != (entry.mode & MODE_TYPE_MASK) {
+            return Ok(Some(WorkTreeChange::TypeChanged));
+        }
+
+        if entry.mode == MODE_GITLINK {
+            // Submodules are only compared by their presence
+            return Ok(None);
+        }
+
+        if mode != entry.mode {
+            return Ok(Some(WorkTreeChange::Modified));
+        }
+
+        if Stat::from_metadata(&metadata) == entry.stat && !self.is_racy(entry) {
+            return Ok(None);
+        }
+
+        if hash_file(&path, &metadata)? != entry.hash {
+            return Ok(Some(WorkTreeChange::Modified));
+        }
+        Ok(None)
+    }
+
+    /// Check whether the file in the working tree differs from an entry.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the file was modified or deleted
+    pub(crate) fn is_modified(&self, entry: &IndexEntry, work_tree: &Path) -> anyhow::Result<bool> {
+        Ok(self.compare(entry, work_tree)?.is_some())
+    }
+
+    /// Update the cached metadata of the entries whose files did not change,
+    /// so that they are no longer hashed when compared.
+    /// Entries assumed to be unchanged are skipped.
+    ///
+    /// # Returns
+    ///
+    /// The entries whose files changed, and all conflicting stages
+    pub(crate) fn refresh(&mut self, work_tree: &Path) -> anyhow::Result<Vec<IndexEntry>> {
+        let mut changed = Vec::new();
+
+        for position in 0..self.entries.len() {
+            let entry = &self.entries[position];
+            if entry.stage != 0 {
+                changed.push(entry.clone());
+                continue;
+            }
+            if entry.assume_valid || entry.skip_worktree {
+                continue;
+            }
+            if self.compare(entry, work_tree)?.is_some() {
+                changed.push(entry.clone());
+                continue;
+            }
+
+            let metadata = work_tree
+                .join(path_from_bytes(&entry.path))
+                .symlink_metadata()?;
+            self.entries[position].stat = Stat::from_metadata(&metadata);
+        }
+
+        Ok(changed)
+    }
+
+    /// Whether an entry was modified so close to the index being written
+    /// that a later change within the same timestamp would go unnoticed
+    fn is_racy(&self, entry: &IndexEntry) -> bool {
+        self.timestamp.is_some_and(|timestamp| {
+            (entry.stat.mtime.seconds, entry.stat.mtime.nanoseconds)
+                >= (timestamp.seconds, timestamp.nanoseconds)
+        })
+    }
+}
+
+/// Check whether a path can be stored in the index: relative, `/`-separated,
+/// without empty, `.` or `..` components, and not inside a `.git` directory.
+pub(crate) fn is_valid_path(path: &[u8]) -> bool {
+    !path.is_empty()
+        && !path.contains(&0)
+        && path.split(|&byte| byte == b'/').all(|component| {
+            !matches!(component, b"" | b"." | b"..") && !component.eq_ignore_ascii_case(b".git")
+        })
+}
+
+/// Whether a path is inside a directory (both `/`-separated).
+pub(crate) fn is_inside(path: &[u8], directory: &[u8]) -> bool {
+    path.strip_prefix(directory)
+        .is_some_and(|rest| rest.first() == Some(&b'/'))
+}
+
+/// Serialize a single entry of the index.
+fn serialize_entry(data: &mut Vec<u8>, entry: &IndexEntry, version: u32, previous_path: &[u8]) {
+    let start_len = data.len();
+
+    let stat = &entry.stat;
+    for value in [
+        stat.ctime.seconds,
+        stat.ctime.nanoseconds,
+        stat.mtime.seconds,
+        stat.mtime.nanoseconds,
+        stat.dev,
+        stat.ino,
+        entry.mode,
+        stat.uid,
+        stat.gid,
+        stat.size,
+    ] {
+        data.extend(value.to_be_bytes());
+    }
+    data.extend(hex::decode(entry.hash.as_bytes()).unwrap_or_else(|_| vec![0; 20]));
+
+    // Longer names are stored with the maximum length and found by the null byte
+    let mut flags = entry.path.len().min(FLAG_NAME_MASK as usize) as u16;
+    flags |= (entry.stage as u16 & 0b11) << FLAG_STAGE_SHIFT;
+    if entry.assume_valid {
+        flags |= FLAG_ASSUME_VALID;
+    }
+    if entry.has_extended_flags() {
+        flags |= FLAG_EXTENDED;
+    }
+    data.extend(flags.to_be_bytes());
+
+    if entry.has_extended_flags() {
+        let mut extended_flags = 0;
+        if entry.skip_worktree {
+            extended_flags |= EXTENDED_FLAG_SKIP_WORKTREE;
+        }
+        if entry.intent_to_add {
+            extended_flags |= EXTENDED_FLAG_INTENT_TO_ADD;
+        }
+        data.extend(extended_flags.to_be_bytes());
+    }
+
+    if version >= 4 {
+        let common = previous_path
+            .iter()
+            .zip(&entry.path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_varint(data, previous_path.len() - common);
+        data.extend(&entry.path[common..]);
+        data.push(0);
+    } else {
+        data.extend(&entry.path);
+
+        // Pad with 1-8 null bytes to a multiple of 8 bytes
+        let entry_len = data.len() - start_len;
+        let padded_len = (entry_len + 8) & !7;
+        data.resize(start_len + padded_len, 0);
+    }
+}
+
+/// Serialize an extension: its signature, the size of its content and the content.
+fn serialize_extension(data: &mut Vec<u8>, signature: &[u8], extension: &[u8]) {
+    data.extend(signature);
+    data.extend((extension.len() as u32).to_be_bytes());
+    data.extend(extension);
+}
+
+/// Write a variable-length integer in the offset encoding used by index version 4.
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+
+    bytes.reverse();
+    data.extend(bytes);
+}
+
+/// Parse a single entry of the index.
+fn parse_entry(
+    reader: &mut Reader,
+    version: u32,
+    previous_path: &[u8],
+) -> anyhow::Result<IndexEntry> {
+    let start_len = reader.0.len();
+
+    let read_timestamp = |reader: &mut Reader| -> anyhow::Result<Timestamp> {
+        Ok(Timestamp {
+            seconds: reader.read_u32()?,
+            nanoseconds: reader.read_u32()?,
+        })
+    };
+    let ctime = read_timestamp(reader)?;
+    let mtime = read_timestamp(reader)?;
+    let dev = reader.read_u32()?;
+    let ino = reader.read_u32()?;
+    let mode = reader.read_u32()?;
+    let uid = reader.read_u32()?;
+    let gid = reader.read_u32()?;
+    let size = reader.read_u32()?;
+
+    let mut hash = reader.read_bytes(20)?.to_vec();
+    hex::encode_in_place(&mut hash);
+
+    let flags = reader.read_u16()?;
+    let extended_flags = if flags & FLAG_EXTENDED != 0 {
+        if version < 3 {
+            anyhow::bail!("extended flags in index version {}", version);
+        }
+        reader.read_u16()?
+    } else {
+        0
+    };
+
+    let path = if version >= 4 {
+        // The path replaces a number of bytes from the end of the previous path
+        let strip = reader.read_varint()?;
+        let keep = previous_path
+            .len()
+            .checked_sub(strip)
+            .context("invalid index path compression")?;
+        let suffix = reader.read_until(b'\0')?;
+
+        let mut path = previous_path[..keep].to_vec();
+        path.extend(suffix);
+        path
+    } else {
+        let path = reader.read_until(b'\0')?.to_vec();
+
+        // Entries are padded with 1-8 null bytes to a multiple of 8 bytes
+        let entry_len = start_len - reader.0.len();
+        let padded_len = (entry_len + 7) & !7;
+        reader.read_bytes(padded_len - entry_len)?;
+        path
+    };
+
+    Ok(IndexEntry {
+        stat: Stat {
+            ctime,
+            mtime,
+            dev,
+            ino,
+            uid,
+            gid,
+            size,
+        },
+        mode,
+        hash: String::from_utf8(hash)?,
+        stage: ((flags >> FLAG_STAGE_SHIFT) & 0b11) as u8,
+        assume_valid: flags & FLAG_ASSUME_VALID != 0,
+        skip_worktree: extended_flags & EXTENDED_FLAG_SKIP_WORKTREE != 0,
+        intent_to_add: extended_flags & EXTENDED_FLAG_INTENT_TO_ADD != 0,
+        path,
+    })
+}
+
+/// A big-endian reader over the content of the index
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < length {
+            anyhow::bail!("truncated index file");
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn read_u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a variable-length integer in the offset encoding used by index version 4.
+    fn read_varint(&mut self) -> anyhow::Result<usize> {
+        let mut byte = self.read_bytes(1)?[0];
+        let mut value = (byte & 0x7f) as usize;
+
+        while byte & 0x80 != 0 {
+            byte = self.read_bytes(1)?[0];
+            value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+        }
+
+        Ok(value)
+    }
+
+    /// Read up to a delimiter, consuming but not returning it.
+    fn read_until(&mut self, delimiter: u8) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .0
+            .iter()
+            .position(|&b| b == delimiter)
+            .context("truncated index file")?;
+        let bytes = &self.0[..end];
+        self.0 = &self.0[end + 1..];
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::{is_valid_path, Index, IndexEntry, WorkTreeChange};
+    use crate::utils::test::{index_file, TempRepo};
+
+    const HASH: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    #[test]
+    fn parses_index_v2() {
+        let data = index_file(2, &[
+            ("a.txt", HASH, 0),
+            ("dir/b.txt", HASH, 0),
+            ("dir/b.txt", HASH, 2),
+        ]);
+        let index = Index::parse(&data).unwrap();
+
+        assert_eq!(index.version, 2);
+        assert_eq!(index.entries().len(), 3);
+        assert_eq!(index.entries()[0].path, b"a.txt");
+        assert_eq!(index.entries()[0].hash, HASH);
+        assert_eq!(index.entries()[0].mode, 0o100644);
+        assert_eq!(index.entries()[2].stage, 2);
+        assert!(index.entry(b"dir/b.txt", 2).is_some());
+        assert!(index.entry(b"dir/b.txt", 1).is_none());
+        assert!(index.contains(b"a.txt"));
+        assert!(!index.contains(b"dir"));
+    }
+
+    #[test]
+    fn parses_index_v4() {
+        let data = index_file(4, &[
+            ("dir/a.txt", HASH, 0),
+            ("dir/b.txt", HASH, 0),
+            ("file", HASH, 0),
+        ]);
+        let index = Index::parse(&data).unwrap();
+
+        let paths: Vec<_> = index.entries().iter().map(|e| e.path.as_slice()).collect();
+        assert_eq!(paths, [&b"dir/a.txt"[..], b"dir/b.txt", b"file"]);
+    }
+
+    #[test]
+    fn fails_on_corrupt_index() {
+        let mut data = index_file(2, &[("a.txt", HASH, 0)]);
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert!(Index::parse(&data).is_err());
+
+        // Unsupported version with a valid checksum
+        let mut data = index_file(2, &[("a.txt", HASH, 0)]);
+        data.truncate(data.len() - 20);
+        data[7] = 5;
+        let checksum = Sha1::digest(&data);
+        data.extend(checksum);
+        assert!(Index::parse(&data).is_err());
+
+        assert!(Index::parse(b"DIRC").is_err());
+    }
+
+    #[test]
+    fn round_trips_serialized_index() {
+        for version in [2, 4] {
+            let data = index_file(version, &[
+                ("a.txt", HASH, 0),
+                ("dir/b.txt", HASH, 0),
+                ("dir/c.txt", HASH, 1),
+            ]);
+            let mut index = Index::parse(&data).unwrap();
+            index.entries[1].intent_to_add = true;
+            // Paths are bytes, whatever their encoding
+            index.add_entry(IndexEntry::new(
+                b"caf\xe9".to_vec(),
+                0o100644,
+                HASH.to_string(),
+            ));
+
+            let serialized = index.serialize();
+            let parsed = Index::parse(&serialized).unwrap();
+            assert_eq!(parsed.entries, index.entries);
+            // Extended flags require version 3
+            assert_eq!(parsed.version, if version == 4 { 4 } else { 3 });
+        }
+
+        // The entries are written exactly as git does, without the (empty) TREE extension
+        let data = index_file(2, &[("a.txt", HASH, 0), ("dir/b.txt", HASH, 2)]);
+        let serialized = Index::parse(&data).unwrap().serialize();
+        assert_eq!(serialized[..serialized.len() - 20], data[..data.len() - 28]);
+    }
+
+    #[test]
+    fn validates_paths() {
+        assert!(is_valid_path(b"dir/file.txt"));
+        assert!(is_valid_path(b".gitignore"));
+        assert!(is_valid_path(b"caf\xe9"));
+        for path in [
+            "",
+            "/abs",
+            "dir/",
+            "a//b",
+            "./a",
+            "a/../b",
+            ".git/config",
+            "sub/.GIT",
+        ] {
+            assert!(!is_valid_path(path.as_bytes()), "{path}");
+        }
+    }
+
+    #[test]
+    fn replaces_conflicting_entries() {
+        let entry = |path: &str, stage| IndexEntry {
+            stage,
+            ..IndexEntry::new(path.into(), 0o100644, HASH.to_string())
+        };
+        let paths = |index: &Index| -> Vec<_> {
+            index
+                .entries()
+                .iter()
+                .map(|e| format!("{}:{}", String::from_utf8_lossy(&e.path), e.stage))
+                .collect()
+        };
+
+        let mut index = Index::default();
+        index.add_entry(entry("b", 0));
+        index.add_entry(entry("a", 2));
+        index.add_entry(entry("a", 3));
+        assert_eq!(paths(&index), vec!["a:2", "a:3", "b:0"]);
+
+        // Resolving a conflict removes the other stages
+        index.add_entry(entry("a", 0));
+        assert_eq!(paths(&index), vec!["a:0", "b:0"]);
+
+        // A directory replaces a file of the same name, and vice versa
+        index.add_entry(entry("a/file", 0));
+        assert_eq!(paths(&index), vec!["a/file:0", "b:0"]);
+        index.add_entry(entry("a", 0));
+        assert_eq!(paths(&index), vec!["a:0", "b:0"]);
+
+        assert!(index.remove_entry(b"a"));
+        assert!(!index.remove_entry(b"a"));
+        assert_eq!(paths(&index), vec!["b:0"]);
+    }
+
+    #[test]
+    fn detects_changes_in_work_tree() {
+        let temp = TempRepo::new();
+        std::fs::write(temp.join("a.txt"), "a").unwrap();
+        let metadata = std::fs::symlink_metadata(temp.join("a.txt")).unwrap();
+
+        // The cached metadata matches, but the recorded content does not
+        let entry = IndexEntry::from_metadata("a.txt".into(), HASH.to_string(), &metadata);
+        let mut index = Index::default();
+        index.add_entry(entry.clone());
+        assert_eq!(index.compare(&entry, temp.path()).unwrap(), None);
+
+        // The metadata cannot be trusted if the index was written in the same instant
+        index.timestamp = Some(entry.stat.mtime);
+        assert_eq!(
+            index.compare(&entry, temp.path()).unwrap(),
+            Some(WorkTreeChange::Modified)
+        );
+
+        std::fs::remove_file(temp.join("a.txt")).unwrap();
+        assert_eq!(
+            index.compare(&entry, temp.path()).unwrap(),
+            Some(WorkTreeChange::Deleted)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! The conflicts resolved in the index (the `REUC` extension)
+//!
+//! When the conflicting stages of a path are replaced (e.g. by `add`), their
+//! modes and hashes are recorded so that the conflict can be recreated later.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::utils::hex;
+use crate::utils::index::{IndexEntry, Reader};
+
+/// The mode and hash of the conflicting stages 1 to 3 of a path
+pub(crate) type ResolvedStages = [Option<(u32, String)>; 3];
+
+/// The conflicting stages of resolved paths
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ResolveUndo(BTreeMap<Vec<u8>, ResolvedStages>);
+
+impl ResolveUndo {
+    /// Parse the content of the extension.
+    ///
+    /// Each path is stored as `{path}\0`, the octal modes of stages 1 to 3 (`0` for
+    /// missing stages) each followed by `\0`, and the 20-byte hashes of the present stages.
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader(data);
+        let mut paths = BTreeMap::new();
+
+        while !reader.0.is_empty() {
+            let path = reader.read_until(b'\0')?.to_vec();
+
+            let mut modes = [0; 3];
+            for mode in &mut modes {
+                let octal = std::str::from_utf8(reader.read_until(b'\0')?)?;
+                *mode = u32::from_str_radix(octal, 8).with_context(|| {
+                    format!(
+                        "invalid mode {octal} in resolve-undo of {}",
+                        String::from_utf8_lossy(&path)
+                    )
+                })?;
+            }
+
+            let mut stages = ResolvedStages::default();
+            for (stage, mode) in stages.iter_mut().zip(modes) {
+                if mode != 0 {
+                    let mut hash = reader.read_bytes(20)?.to_vec();
+                    hex::encode_in_place(&mut hash);
+                    *stage = Some((mode, String::from_utf8(hash)?));
+                }
+            }
+            paths.insert(path, stages);
+        }
+
+        Ok(ResolveUndo(paths))
+    }
+
+    /// Serialize the content of the extension.
+    pub(crate) fn serialize(&self, data: &mut Vec<u8>) {
+        for (path, stages) in &self.0 {
+            data.extend(path);
+            data.push(0);
+
+            for stage in stages {
+                let mode = stage.as_ref().map_or(0, |(mode, _)| *mode);
+                data.extend(format!("{mode:o}").as_bytes());
+                data.push(0);
+            }
+            for (_, hash) in stages.iter().flatten() {
+                data.extend(hex::decode(hash.as_bytes()).unwrap_or_else(|_| vec![0; 20]));
+            }
+        }
+    }
+
+    /// Record a conflicting stage that is removed from the index.
+    /// Resolved (stage 0) entries are ignored.
+    pub(crate) fn record(&mut self, entry: &IndexEntry) {
+        if entry.stage == 0 {
+            return;
+        }
+
+        let stages = self.0.entry(entry.path.clone()).or_default();
+        stages[entry.stage as usize - 1] = Some((entry.mode, entry.hash.clone()));
+    }
+
+    /// Remove the record of a path.
+    ///
+    /// # Returns
+    ///
+    /// The recorded stages of the path, if any
+    pub(crate) fn remove(&mut self, path: &[u8]) -> Option<ResolvedStages> {
+        self.0.remove(path)
+    }
+
+    /// Whether no path was recorded
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// All recorded paths with their stages, sorted by path.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &ResolvedStages)> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResolveUndo;
+    use crate::utils::hex;
+    use crate::utils::index::IndexEntry;
+
+    const HASH: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    #[test]
+    fn records_resolved_stages() {
+        let entry = |path: &str, stage| IndexEntry {
+            stage,
+            ..IndexEntry::new(path.into(), 0o100644, HASH.to_string())
+        };
+
+        let mut resolve_undo = ResolveUndo::default();
+        resolve_undo.record(&entry("a", 0));
+        assert!(resolve_undo.is_empty());
+
+        resolve_undo.record(&entry("b", 3));
+        resolve_undo.record(&entry("a", 2));
+        resolve_undo.record(&entry("a", 3));
+
+        let mut data = Vec::new();
+        resolve_undo.serialize(&mut data);
+        let mut expected = b"a\x000\x00100644\x00100644\x00".to_vec();
+        let binary = hex::decode(HASH.as_bytes()).unwrap();
+        expected.extend(binary.repeat(2));
+        expected.extend(b"b\x000\x000\x00100644\x00");
+        expected.extend(binary);
+        assert_eq!(data, expected);
+
+        let parsed = ResolveUndo::parse(&data).unwrap();
+        assert_eq!(parsed, resolve_undo);
+        let (path, stages) = parsed.iter().next().unwrap();
+        assert_eq!(path, b"a");
+        assert_eq!(stages[0], None);
+        assert_eq!(stages[1], Some((0o100644, HASH.to_string())));
+
+        assert!(ResolveUndo::parse(b"a\x00100644\x000\x00").is_err());
+    }
+}
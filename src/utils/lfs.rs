@@ -0,0 +1,420 @@
+//! Git LFS pointer files, the local LFS object store, and fetching objects from
+//! an LFS server
+//!
+//! Files with the `filter=lfs` attribute are stored in the object database as small
+//! pointer files naming the SHA-256 of their content, the content itself being kept
+//! in `$GIT_COMMON_DIR/lfs/objects`. Objects missing from the store are fetched with
+//! the batch API of the LFS server of a remote (`lfs.url`, `remote.<name>.lfsurl`,
+//! or derived from `remote.<name>.url`), or copied from the store of a local remote.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::utils::config::Config;
+use crate::utils::http::HttpClient;
+use crate::utils::json::Json;
+use crate::utils::repository::Repository;
+use crate::utils::sha256;
+use crate::utils::transfer::{is_remote_url, open_repository};
+
+/// The version line identifying a pointer file
+const POINTER_VERSION: &str = "https://git-lfs.github.com/spec/v1";
+
+/// Pointer files are always smaller than this
+const MAX_POINTER_SIZE: usize = 1024;
+
+/// The media type of the requests and responses of the batch API
+const MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// A pointer file, standing in the object database for the content of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pointer {
+    /// The SHA-256 of the content, in hex
+    pub(crate) oid: String,
+    pub(crate) size: u64,
+}
+
+impl Pointer {
+    /// Parse the content of a blob, if it is a pointer file.
+    pub(crate) fn parse(content: &[u8]) -> Option<Pointer> {
+        if content.len() >= MAX_POINTER_SIZE || !content.ends_with(b"\n") {
+            return None;
+        }
+        let content = std::str::from_utf8(content).ok()?;
+        let mut lines = content.lines().map(|line| line.split_once(' '));
+
+        if lines.next()? != Some(("version", POINTER_VERSION)) {
+            return None;
+        }
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            match line? {
+                ("oid", value) => {
+                    let hash = value.strip_prefix("sha256:")?;
+                    let is_hex = hash.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'));
+                    if hash.len() != 64 || !is_hex {
+                        return None;
+                    }
+                    oid = Some(hash.to_string());
+                },
+                ("size", value) if value.bytes().all(|c| c.is_ascii_digit()) => {
+                    size = Some(value.parse().ok()?)
+                },
+                // Extensions and future keys are ignored
+                (key, _) if key != "size" => {},
+                _ => return None,
+            }
+        }
+
+        Some(Pointer {
+            oid: oid?,
+            size: size?,
+        })
+    }
+}
+
+impl fmt::Display for Pointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version {POINTER_VERSION}\noid sha256:{}\nsize {}\n",
+            self.oid, self.size
+        )
+    }
+}
+
+/// Get the path of an object in the local LFS store.
+fn object_path(repo: &Repository, oid: &str) -> anyhow::Result<PathBuf> {
+    Ok(repo
+        .common_dir()?
+        .join("lfs/objects")
+        .join(&oid[..2])
+        .join(&oid[2..4])
+        .join(oid))
+}
+
+/// Whether the content of a pointer is in the local LFS store.
+pub(crate) fn has_object(repo: &Repository, pointer: &Pointer) -> anyhow::Result<bool> {
+    Ok(object_path(repo, &pointer.oid)?.is_file())
+}
+
+/// Read the content of a pointer from the local LFS store.
+///
+/// # Returns
+///
+/// The content, or `None` if it is not in the store
+pub(crate) fn read_object(repo: &Repository, pointer: &Pointer) -> anyhow::Result<Option<Vec<u8>>> {
+    let path = object_path(repo, &pointer.oid)?;
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read(&path).context(format!("read {}", path.display()))?;
+    Ok(Some(content))
+}
+
+/// Write some content into the local LFS store.
+///
+/// # Returns
+///
+/// The pointer to the content
+pub(crate) fn write_object(repo: &Repository, content: &[u8]) -> anyhow::Result<Pointer> {
+    let pointer = Pointer {
+        oid: sha256::hex_digest(content),
+        size: content.len() as u64,
+    };
+    let path = object_path(repo, &pointer.oid)?;
+    if path.is_file() {
+        return Ok(pointer);
+    }
+
+    let dir = path.parent().context("invalid LFS object path")?;
+    std::fs::create_dir_all(dir).context(format!("create {}", dir.display()))?;
+    std::fs::write(&path, content).context(format!("write {}", path.display()))?;
+    Ok(pointer)
+}
+
+/// Convert the content of a file to the pointer file stored in its place,
+/// keeping the content in the local LFS store. Pointer files are kept as is.
+pub(crate) fn clean(repo: &Repository, content: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if Pointer::parse(&content).is_some() {
+        return Ok(content);
+    }
+    Ok(write_object(repo, &content)?.to_string().into_bytes())
+}
+
+/// Convert a pointer file to the content it points to, if the content is in
+/// the local LFS store. Other content (and pointers to missing objects) is kept as is.
+pub(crate) fn smudge(repo: &Repository, content: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match Pointer::parse(&content) {
+        Some(pointer) => Ok(read_object(repo, &pointer)?.unwrap_or(content)),
+        None => Ok(content),
+    }
+}
+
+/// Where the LFS objects of a remote are fetched from
+#[derive(Debug)]
+pub(crate) enum Endpoint {
+    /// The URL of an LFS server, which the batch API is below of
+    Http(String),
+    /// The LFS store of a repository on the local file system
+    Local(Box<Repository>),
+}
+
+impl Endpoint {
+    /// Find the LFS endpoint of a remote.
+    pub(crate) fn for_remote(repo: &Repository, remote: &str) -> anyhow::Result<Endpoint> {
+        let config = Config::load(repo)?;
+        if let Some(url) = config
+            .get("lfs.url")
+            .or_else(|| config.get(&format!("remote.{remote}.lfsurl")))
+        {
+            return Ok(Endpoint::Http(url.trim_end_matches('/').to_string()));
+        }
+
+        let url = config
+            .get(&format!("remote.{remote}.url"))
+            .context(format!("remote '{remote}' has no url"))?;
+        if !is_remote_url(url) || url.starts_with("file://") {
+            return Ok(Endpoint::Local(Box::new(open_repository(repo, url)?.repo)));
+        }
+        Ok(Endpoint::Http(server_url(url)?))
+    }
+
+    /// Fetch objects into the local LFS store, checking their content.
+    pub(crate) fn download(&self, repo: &Repository, pointers: &[Pointer]) -> anyhow::Result<()> {
+        match self {
+            Endpoint::Local(other) => {
+                for pointer in pointers {
+                    let content = read_object(other, pointer)?.context(format!(
+                        "object {} not found in {}",
+                        pointer.oid,
+                        other.common_dir()?.display()
+                    ))?;
+                    store_download(repo, pointer, &content)?;
+                }
+                Ok(())
+            },
+            Endpoint::Http(url) => {
+                let client = HttpClient::new(repo)?;
+                let incomplete = repo.common_dir()?.join("lfs/incomplete");
+                std::fs::create_dir_all(&incomplete)
+                    .context(format!("create {}", incomplete.display()))?;
+                for download in batch_download(&client, url, pointers)? {
+                    // A failed download is kept there, to be resumed by the next fetch
+                    let path = incomplete.join(&download.pointer.oid);
+                    client.download(&download.href, &download.headers, &path)?;
+                    let content =
+                        std::fs::read(&path).context(format!("read {}", path.display()))?;
+                    std::fs::remove_file(&path).context(format!("remove {}", path.display()))?;
+                    store_download(repo, download.pointer, &content)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Derive the URL of the LFS server of a remote from the URL of the remote:
+/// `<url>.git/info/lfs`, over HTTPS for SSH remotes.
+fn server_url(url: &str) -> anyhow::Result<String> {
+    let url = url.trim_end_matches('/');
+    let url = if url.starts_with("https://") || url.starts_with("http://") {
+        url.to_string()
+    } else if let Some(rest) = url.strip_prefix("ssh://") {
+        // The user and port are those of SSH
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let host = authority.rsplit('@').next().unwrap_or(authority);
+        let host = host.split(':').next().unwrap_or(host);
+        format!("https://{host}/{path}")
+    } else if let Some((authority, path)) = url.split_once(':').filter(|_| !url.contains("://")) {
+        let host = authority.rsplit('@').next().unwrap_or(authority);
+        format!("https://{host}/{}", path.trim_start_matches('/'))
+    } else {
+        anyhow::bail!("unsupported LFS remote url '{url}'");
+    };
+
+    match url.ends_with(".git") {
+        true => Ok(format!("{url}/info/lfs")),
+        false => Ok(format!("{url}.git/info/lfs")),
+    }
+}
+
+/// Where to download an object from, as told by an LFS server
+struct Download<'p> {
+    pointer: &'p Pointer,
+    href: String,
+    headers: Vec<(String, String)>,
+}
+
+/// Ask an LFS server where to download objects from.
+fn batch_download<'p>(
+    client: &HttpClient,
+    url: &str,
+    pointers: &'p [Pointer],
+) -> anyhow::Result<Vec<Download<'p>>> {
+    let objects = pointers
+        .iter()
+        .map(|pointer| {
+            Json::Object(vec![
+                ("oid".to_string(), Json::String(pointer.oid.clone())),
+                ("size".to_string(), Json::Number(pointer.size as f64)),
+            ])
+        })
+        .collect();
+    let request = Json::Object(vec![
+        (
+            "operation".to_string(),
+            Json::String("download".to_string()),
+        ),
+        (
+            "transfers".to_string(),
+            Json::Array(vec![Json::String("basic".to_string())]),
+        ),
+        ("objects".to_string(), Json::Array(objects)),
+        ("hash_algo".to_string(), Json::String("sha256".to_string())),
+    ]);
+
+    let headers = [
+        ("Accept".to_string(), MEDIA_TYPE.to_string()),
+        ("Content-Type".to_string(), MEDIA_TYPE.to_string()),
+    ];
+    let batch_url = format!("{url}/objects/batch");
+    let response = client.request(
+        "POST",
+        &batch_url,
+        &headers,
+        Some(request.to_string().as_bytes()),
+    )?;
+    let response = Json::parse(&String::from_utf8_lossy(&response))
+        .context(format!("invalid response from {batch_url}"))?;
+
+    let mut downloads = Vec::new();
+    for object in response
+        .get("objects")
+        .and_then(Json::as_array)
+        .unwrap_or_default()
+    {
+        let oid = object.get("oid").and_then(Json::as_str).unwrap_or_default();
+        let Some(pointer) = pointers.iter().find(|pointer| pointer.oid == oid) else {
+            continue;
+        };
+        if let Some(error) = object.get("error") {
+            let message = error.get("message").and_then(Json::as_str);
+            anyhow::bail!(
+                "failed to fetch LFS object {oid}: {}",
+                message.unwrap_or("unknown error")
+            );
+        }
+
+        let download = object
+            .get("actions")
+            .and_then(|actions| actions.get("download"))
+            .context(format!("no download action for LFS object {oid}"))?;
+        let href = download
+            .get("href")
+            .and_then(Json::as_str)
+            .context(format!("no download url for LFS object {oid}"))?;
+        let headers = download
+            .get("header")
+            .and_then(Json::as_object)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+            .collect();
+        downloads.push(Download {
+            pointer,
+            href: href.to_string(),
+            headers,
+        });
+    }
+
+    if let Some(missing) = pointers.iter().find(|pointer| {
+        !downloads
+            .iter()
+            .any(|download| download.pointer == *pointer)
+    }) {
+        anyhow::bail!("LFS server did not return object {}", missing.oid);
+    }
+    Ok(downloads)
+}
+
+/// Check that downloaded content matches its pointer, then store it.
+fn store_download(repo: &Repository, pointer: &Pointer, content: &[u8]) -> anyhow::Result<()> {
+    if content.len() as u64 != pointer.size || sha256::hex_digest(content) != pointer.oid {
+        anyhow::bail!("corrupt LFS object {}", pointer.oid);
+    }
+    write_object(repo, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn parses_pointer_files() {
+        let oid = sha256::hex_digest(b"content\n");
+        let text = format!("version {POINTER_VERSION}\noid sha256:{oid}\nsize 8\n");
+        let pointer = Pointer::parse(text.as_bytes()).unwrap();
+        assert_eq!(pointer, Pointer {
+            oid: oid.clone(),
+            size: 8
+        });
+        assert_eq!(pointer.to_string(), text);
+
+        let with_extension = text.replace("oid", "ext-0-foo sha256:00\noid");
+        assert!(Pointer::parse(with_extension.as_bytes()).is_some());
+        assert!(Pointer::parse(text.trim_end().as_bytes()).is_none());
+        assert!(Pointer::parse(text.replace("size 8", "size x").as_bytes()).is_none());
+        assert!(Pointer::parse(text.replace(&oid[..2], "zz").as_bytes()).is_none());
+        assert!(Pointer::parse(b"content\n").is_none());
+
+        assert_eq!(
+            server_url("https://example.com/org/repo").unwrap(),
+            "https://example.com/org/repo.git/info/lfs"
+        );
+        assert_eq!(
+            server_url("git@example.com:org/repo.git").unwrap(),
+            "https://example.com/org/repo.git/info/lfs"
+        );
+        assert_eq!(
+            server_url("ssh://git@example.com:22/org/repo.git").unwrap(),
+            "https://example.com/org/repo.git/info/lfs"
+        );
+    }
+
+    #[test]
+    fn cleans_smudges_and_fetches_from_local_remotes() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join("remote/.git/objects")).unwrap();
+        fs::create_dir_all(temp.join("local/.git")).unwrap();
+        let url = fs::canonicalize(temp.join("remote")).unwrap();
+        let config = format!("[remote \"origin\"]\n\turl = {}\n", url.display());
+        fs::write(temp.join("local/.git/config"), config).unwrap();
+        let remote = temp.repo_at("remote");
+        let local = temp.repo_at("local");
+
+        let pointer = clean(&remote, b"large\n".to_vec()).unwrap();
+        assert_eq!(clean(&remote, pointer.clone()).unwrap(), pointer);
+        assert_eq!(smudge(&remote, pointer.clone()).unwrap(), b"large\n");
+        assert_eq!(smudge(&local, pointer.clone()).unwrap(), pointer);
+
+        let pointer = Pointer::parse(&pointer).unwrap();
+        let endpoint = Endpoint::for_remote(&local, "origin").unwrap();
+        endpoint
+            .download(&local, std::slice::from_ref(&pointer))
+            .unwrap();
+        assert_eq!(read_object(&local, &pointer).unwrap().unwrap(), b"large\n");
+
+        let missing = Pointer {
+            oid: sha256::hex_digest(b"missing\n"),
+            size: 8,
+        };
+        assert!(endpoint.download(&local, &[missing]).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Canonical names and emails of people, from (later files taking precedence):
+//!
+//! 1. The `.mailmap` file at the root of the working tree
+//! 2. The blob set with `mailmap.blob` (`HEAD:.mailmap` by default in bare repositories)
+//! 3. The file set with `mailmap.file`
+//!
+//! Each line maps the identities using an email (or a name and an email) to a
+//! proper name, a proper email or both:
+//!
+//! ```text
+//! Proper Name <commit@example.com>
+//! <proper@example.com> <commit@example.com>
+//! Proper Name <proper@example.com> <commit@example.com>
+//! Proper Name <proper@example.com> Commit Name <commit@example.com>
+//! ```
+//!
+//! Names and emails are matched case-insensitively, and `#` starts a comment.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::utils::commit::Ident;
+use crate::utils::config::Config;
+use crate::utils::objects::{read_object_of_type, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_revision;
+
+/// The replacements of a name and an email (`None` keeps the original)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Replacement {
+    name: Option<Vec<u8>>,
+    email: Option<Vec<u8>>,
+}
+
+impl Replacement {
+    /// Replace the parts set by a later line.
+    fn update(&mut self, name: Option<&[u8]>, email: Option<&[u8]>) {
+        if let Some(name) = name {
+            self.name = Some(name.to_vec());
+        }
+        if let Some(email) = email {
+            self.email = Some(email.to_vec());
+        }
+    }
+}
+
+/// The replacements for the identities using an email
+#[derive(Debug, Clone, Default)]
+struct Entry {
+    /// The replacement for any name
+    any: Replacement,
+    /// The replacements for specific names (lowercased)
+    names: HashMap<Vec<u8>, Replacement>,
+}
+
+/// A name (if any) and an email of a mailmap line
+type Identity<'a> = (Option<&'a [u8]>, &'a [u8]);
+
+/// A parsed mailmap
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Mailmap {
+    /// The entries by (lowercased) email
+    entries: HashMap<Vec<u8>, Entry>,
+}
+
+impl Mailmap {
+    /// Load the mailmap of a repository, from the files that exist.
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let config = Config::load(repo)?;
+        let bare = repo.is_bare()? || config.get_bool("core.bare")?.unwrap_or(false);
+        let mut mailmap = Mailmap::default();
+
+        if !bare {
+            mailmap.read_file(&repo.work_tree()?.join(".mailmap"))?;
+        }
+
+        // A blob that cannot be found is ignored, as a missing file is
+        let blob = config
+            .get("mailmap.blob")
+            .or(bare.then_some("HEAD:.mailmap"));
+        if let Some(hash) = blob.and_then(|blob| resolve_revision(repo, blob).ok()) {
+            mailmap.parse(&read_object_of_type(repo, &hash, ObjectType::Blob)?);
+        }
+
+        if let Some(path) = config.get_path("mailmap.file") {
+            mailmap.read_file(&path)?;
+        }
+        Ok(mailmap)
+    }
+
+    /// Add the lines of a mailmap file, ignoring it if it does not exist.
+    fn read_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        match std::fs::read(path) {
+            Ok(content) => {
+                self.parse(&content);
+                Ok(())
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).context(format!("read {}", path.display())),
+        }
+    }
+
+    /// Add the lines of a mailmap, skipping the ones without an email.
+    pub(crate) fn parse(&mut self, content: &[u8]) {
+        for line in content.split(|&b| b == b'\n') {
+            let line = match line.iter().position(|&b| b == b'#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let Some(((proper_name, proper_email), rest)) = split_name_and_email(line) else {
+                continue;
+            };
+
+            match split_name_and_email(rest) {
+                Some(((commit_name, commit_email), _)) => {
+                    let entry = self
+                        .entries
+                        .entry(commit_email.to_ascii_lowercase())
+                        .or_default();
+                    let replacement = match commit_name {
+                        Some(name) => entry.names.entry(name.to_ascii_lowercase()).or_default(),
+                        None => &mut entry.any,
+                    };
+                    replacement.update(proper_name, Some(proper_email));
+                },
+                // A single identity only gives the proper name of its email
+                None => {
+                    self.entries
+                        .entry(proper_email.to_ascii_lowercase())
+                        .or_default()
+                        .any
+                        .update(proper_name, None);
+                },
+            }
+        }
+    }
+
+    /// Map a name and an email to their canonical versions.
+    ///
+    /// # Returns
+    ///
+    /// The canonical name and email (the given ones if they are not mapped)
+    pub(crate) fn map(&self, name: &[u8], email: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let Some(entry) = self.entries.get(&email.to_ascii_lowercase()) else {
+            return (name.to_vec(), email.to_vec());
+        };
+        let replacement = entry
+            .names
+            .get(&name.to_ascii_lowercase())
+            .unwrap_or(&entry.any);
+
+        (
+            replacement.name.as_deref().unwrap_or(name).to_vec(),
+            replacement.email.as_deref().unwrap_or(email).to_vec(),
+        )
+    }
+
+    /// Replace the name and email of an identity with their canonical versions.
+    pub(crate) fn map_ident(&self, ident: &mut Ident) {
+        (ident.name, ident.email) = self.map(&ident.name, &ident.email);
+    }
+}
+
+/// Split the first `Name <email>` (the name being optional) off a line.
+///
+/// # Returns
+///
+/// The name (`None` if empty) and the email, and the rest of the line
+fn split_name_and_email(line: &[u8]) -> Option<(Identity<'_>, &[u8])> {
+    let open = line.iter().position(|&b| b == b'<')?;
+    let close = open + line[open..].iter().position(|&b| b == b'>')?;
+
+    let name = line[..open].trim_ascii();
+    let name = (!name.is_empty()).then_some(name);
+    Some(((name, &line[open + 1..close]), &line[close + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mailmap;
+
+    fn map(mailmap: &Mailmap, name: &str, email: &str) -> (String, String) {
+        let (name, email) = mailmap.map(name.as_bytes(), email.as_bytes());
+        (
+            String::from_utf8(name).unwrap(),
+            String::from_utf8(email).unwrap(),
+        )
+    }
+
+    fn pair(name: &str, email: &str) -> (String, String) {
+        (name.to_string(), email.to_string())
+    }
+
+    #[test]
+    fn maps_names_and_emails() {
+        let mut mailmap = Mailmap::default();
+        mailmap.parse(
+            b"# comment\n\
+              Proper Name <A@example.com>\n\
+              <proper@example.com> <b@example.com> # trailing comment\n\
+              Other <other@example.com> <c@example.com>\n\
+              not an identity\n",
+        );
+
+        assert_eq!(
+            map(&mailmap, "a", "a@EXAMPLE.com"),
+            pair("Proper Name", "a@EXAMPLE.com")
+        );
+        assert_eq!(
+            map(&mailmap, "b", "b@example.com"),
+            pair("b", "proper@example.com")
+        );
+        assert_eq!(
+            map(&mailmap, "c", "c@example.com"),
+            pair("Other", "other@example.com")
+        );
+        assert_eq!(
+            map(&mailmap, "d", "d@example.com"),
+            pair("d", "d@example.com")
+        );
+    }
+
+    #[test]
+    fn maps_specific_names() {
+        let mut mailmap = Mailmap::default();
+        mailmap.parse(
+            b"Joe <joe@example.com> joe <shared@example.com>\n\
+              Jane <jane@example.com> Jane Doe <shared@example.com>\n",
+        );
+
+        assert_eq!(
+            map(&mailmap, "JOE", "shared@example.com"),
+            pair("Joe", "joe@example.com")
+        );
+        assert_eq!(
+            map(&mailmap, "jane doe", "shared@example.com"),
+            pair("Jane", "jane@example.com")
+        );
+        assert_eq!(
+            map(&mailmap, "someone", "shared@example.com"),
+            pair("someone", "shared@example.com")
+        );
+    }
+}
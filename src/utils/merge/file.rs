@@ -0,0 +1,191 @@
+//! Merging the content of files line by line
+//!
+//! Both sides are compared with the base, and the lines changed by only one side
+//! are taken from it. Where both sides changed the same lines (or adjacent ones)
+//! differently, both versions are kept between conflict markers, as git does:
+//!
+//! ```text
+//! <<<<<<< ours
+//! our lines
+//! =======
+//! their lines
+//! >>>>>>> theirs
+//! ```
+
+use crate::utils::diff::{diff_lines, split_lines, Change};
+
+/// The length of the conflict markers
+const MARKER_SIZE: usize = 7;
+
+/// The result of merging the content of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MergedContent {
+    /// The merged content, with conflict markers around the conflicting lines
+    pub(crate) content: Vec<u8>,
+    /// The number of conflicting regions
+    pub(crate) conflicts: usize,
+}
+
+/// Merge the changes made to the content of a file by two sides.
+///
+/// # Arguments
+///
+/// * `base` - The content both sides started from
+/// * `ours` - The content of our side
+/// * `theirs` - The content of their side
+/// * `labels` - The names of our side and their side, shown after the conflict markers
+pub(crate) fn merge_content(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: (&str, &str),
+) -> MergedContent {
+    let base_lines = split_lines(base);
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+    let our_changes = diff_lines(&base_lines, &our_lines);
+    let their_changes = diff_lines(&base_lines, &their_lines);
+
+    let mut merged = MergedContent {
+        content: Vec::new(),
+        conflicts: 0,
+    };
+    // The next base line to copy, and how far each side is shifted from the base
+    let mut position = 0;
+    let (mut our_offset, mut their_offset) = (0isize, 0isize);
+    let (mut i, mut j) = (0, 0);
+
+    while i < our_changes.len() || j < their_changes.len() {
+        // A region starts with the first change, and grows while a change of either
+        // side overlaps or touches it
+        let first = match (our_changes.get(i), their_changes.get(j)) {
+            (Some(a), Some(b)) => a.old_start.min(b.old_start),
+            (Some(a), None) => a.old_start,
+            (None, Some(b)) => b.old_start,
+            (None, None) => unreachable!(),
+        };
+        let (mut end, i_start, j_start) = (first, i, j);
+        loop {
+            if let Some(change) = our_changes.get(i).filter(|change| change.old_start <= end) {
+                end = end.max(change.old_start + change.old_len);
+                i += 1;
+            } else if let Some(change) = their_changes
+                .get(j)
+                .filter(|change| change.old_start <= end)
+            {
+                end = end.max(change.old_start + change.old_len);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        for line in &base_lines[position..first] {
+            merged.content.extend_from_slice(line);
+        }
+        position = end;
+
+        let ours = side_lines(
+            &our_lines,
+            &our_changes[i_start..i],
+            first,
+            end,
+            &mut our_offset,
+        );
+        let theirs = side_lines(
+            &their_lines,
+            &their_changes[j_start..j],
+            first,
+            end,
+            &mut their_offset,
+        );
+        if i == i_start {
+            extend_lines(&mut merged.content, theirs, false);
+        } else if j == j_start || ours == theirs {
+            extend_lines(&mut merged.content, ours, false);
+        } else {
+            merged.conflicts += 1;
+            let content = &mut merged.content;
+            content.extend(format!("{} {}\n", "<".repeat(MARKER_SIZE), labels.0).as_bytes());
+            extend_lines(content, ours, true);
+            content.extend(format!("{}\n", "=".repeat(MARKER_SIZE)).as_bytes());
+            extend_lines(content, theirs, true);
+            content.extend(format!("{} {}\n", ">".repeat(MARKER_SIZE), labels.1).as_bytes());
+        }
+    }
+
+    for line in &base_lines[position..] {
+        merged.content.extend_from_slice(line);
+    }
+    merged
+}
+
+/// The lines of a side replacing the base lines `start..end`, given the changes
+/// of the side within them and how far the side is shifted before them, which is
+/// updated past them.
+fn side_lines<'a>(
+    lines: &'a [&'a [u8]],
+    changes: &[Change],
+    start: usize,
+    end: usize,
+    offset: &mut isize,
+) -> &'a [&'a [u8]] {
+    let side_start = (start as isize + *offset) as usize;
+    for change in changes {
+        *offset += change.new_len as isize - change.old_len as isize;
+    }
+    let side_end = (end as isize + *offset) as usize;
+    &lines[side_start..side_end]
+}
+
+/// Append lines, terminating the last one if it is followed by a conflict marker.
+fn extend_lines(content: &mut Vec<u8>, lines: &[&[u8]], terminate: bool) {
+    for line in lines {
+        content.extend_from_slice(line);
+    }
+    if terminate && lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        content.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge_content;
+
+    const LABELS: (&str, &str) = ("HEAD", "theirs");
+
+    #[test]
+    fn merges_changes_to_different_lines() {
+        let base = b"a\nb\nc\nd\ne\n";
+        let merged = merge_content(base, b"A\nb\nc\nd\ne\n", b"a\nb\nc\nd\nE\nf\n", LABELS);
+        assert_eq!(merged.content, b"A\nb\nc\nd\nE\nf\n");
+        assert_eq!(merged.conflicts, 0);
+
+        // The same change made by both sides is taken once
+        let merged = merge_content(base, b"a\nx\nc\nd\ne\n", b"a\nx\nc\nd\ne\n", LABELS);
+        assert_eq!(merged.content, b"a\nx\nc\nd\ne\n");
+        assert_eq!(merged.conflicts, 0);
+
+        let merged = merge_content(base, base, b"", LABELS);
+        assert_eq!(merged.content, b"");
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        let base = b"a\nb\nc\nd\ne\n";
+        let merged = merge_content(base, b"a\nours\nc\nd\nE\n", b"a\ntheirs\nc\nd\ne\n", LABELS);
+        assert_eq!(
+            String::from_utf8(merged.content).unwrap(),
+            "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> theirs\nc\nd\nE\n"
+        );
+        assert_eq!(merged.conflicts, 1);
+
+        // Changes to adjacent lines conflict, and unterminated lines are terminated
+        let merged = merge_content(b"a\nb", b"A\nb", b"a\nB", LABELS);
+        assert_eq!(
+            String::from_utf8(merged.content).unwrap(),
+            "<<<<<<< HEAD\nA\nb\n=======\na\nB\n>>>>>>> theirs\n"
+        );
+        assert_eq!(merged.conflicts, 1);
+    }
+}
@@ -0,0 +1,543 @@
+//! Merging trees
+//!
+//! Merges are resolved per path: a path is taken from the side that changed it,
+//! and the files changed by both sides have their content merged line by line.
+//! The paths whose changes cannot be merged are reported as conflicts, with the
+//! three versions of the file to be recorded as the stages of the index.
+//! A file of one side in the way of a directory of the other side conflicts
+//! too, and is moved aside to `<path>~<side>`.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::utils::diff::is_binary;
+use crate::utils::index::{Index, IndexEntry};
+use crate::utils::objects::{read_object_of_type, write_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::tree::{flatten_tree, MODE_GITLINK, MODE_SYMLINK};
+
+mod file;
+mod subtree;
+
+pub(crate) use file::merge_content;
+pub(crate) use subtree::shift_tree;
+
+/// The mode and hash of a file in a tree
+pub(crate) type FileState = (u32, String);
+
+/// The result of merging a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MergedFile {
+    /// The changes were merged into a single version of the file
+    Clean(FileState),
+    /// The changes conflict
+    Conflict {
+        /// The base, our and their versions of the file (index stages 1 to 3),
+        /// `None` for the sides without the file
+        stages: [Option<FileState>; 3],
+        /// The version left in the working tree, with conflict markers if the
+        /// content was merged
+        work_tree: FileState,
+        /// Whether the content could not be merged as a version is binary
+        /// (callers warn about it, our version being left)
+        binary: bool,
+    },
+}
+
+/// The result of merging two trees
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TreeMerge {
+    /// The merged files by path
+    pub(crate) files: BTreeMap<Vec<u8>, MergedFile>,
+    /// What happened to the files merged by both sides, in order of path:
+    /// `Auto-merging <path>` and `CONFLICT (<kind>): ...` lines
+    pub(crate) messages: Vec<String>,
+}
+
+impl TreeMerge {
+    /// Whether any path conflicts.
+    pub(crate) fn has_conflicts(&self) -> bool {
+        self.files
+            .values()
+            .any(|file| matches!(file, MergedFile::Conflict { .. }))
+    }
+
+    /// The conflicting paths, in order.
+    pub(crate) fn conflicts(&self) -> Vec<Cow<'_, str>> {
+        self.files
+            .iter()
+            .filter(|(_, file)| matches!(file, MergedFile::Conflict { .. }))
+            .map(|(path, _)| String::from_utf8_lossy(path))
+            .collect()
+    }
+
+    /// The paths that conflict as their content is binary, in order.
+    pub(crate) fn binary_conflicts(&self) -> Vec<Cow<'_, str>> {
+        self.files
+            .iter()
+            .filter(|(_, file)| matches!(file, MergedFile::Conflict { binary: true, .. }))
+            .map(|(path, _)| String::from_utf8_lossy(path))
+            .collect()
+    }
+
+    /// Write the tree of the files to put in the working tree: the merged files,
+    /// and the working tree version of the conflicting ones.
+    pub(crate) fn write_work_tree(&self, repo: &Repository) -> anyhow::Result<String> {
+        let mut index = Index::default();
+        for (path, file) in &self.files {
+            let (mode, hash) = match file {
+                MergedFile::Clean(state) => state,
+                MergedFile::Conflict { work_tree, .. } => work_tree,
+            };
+            index.add_entry(IndexEntry::new(path.clone(), *mode, hash.clone()));
+        }
+        index.write_tree(repo, true)
+    }
+
+    /// Record the stages of the conflicting paths in an index, in place of
+    /// their resolved entries.
+    pub(crate) fn add_conflicts(&self, index: &mut Index) {
+        for (path, file) in &self.files {
+            let MergedFile::Conflict { stages, .. } = file else {
+                continue;
+            };
+            index.remove_entry(path);
+            for (stage, state) in (1..).zip(stages) {
+                if let Some((mode, hash)) = state {
+                    let mut entry = IndexEntry::new(path.clone(), *mode, hash.clone());
+                    entry.stage = stage;
+                    index.add_entry(entry);
+                }
+            }
+        }
+    }
+}
+
+/// Merge the changes made to a tree by two sides.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read and write the objects in
+/// * `base` - The hash of the tree both sides started from
+/// * `ours` - The hash of the tree of our side
+/// * `theirs` - The hash of the tree of their side
+///
+/// # Returns
+///
+/// The hash of the merged tree, or an error listing the conflicting paths
+pub(crate) fn merge_trees(
+    repo: &Repository,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+) -> anyhow::Result<String> {
+    if ours == theirs || base == theirs {
+        return Ok(ours.to_string());
+    }
+    if base == ours {
+        return Ok(theirs.to_string());
+    }
+
+    let merge = merge_files(repo, base, ours, theirs, ("ours", "theirs"))?;
+    if merge.has_conflicts() {
+        let conflicts: Vec<_> = merge
+            .messages
+            .iter()
+            .filter(|message| message.starts_with("CONFLICT"))
+            .map(String::as_str)
+            .collect();
+        anyhow::bail!("{}\nAutomatic merge failed", conflicts.join("\n"));
+    }
+    merge.write_work_tree(repo)
+}
+
+/// Merge the changes made to a tree by two sides, keeping the conflicts.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read and write the objects in
+/// * `base` - The hash of the tree both sides started from
+/// * `ours` - The hash of the tree of our side
+/// * `theirs` - The hash of the tree of their side
+/// * `labels` - The names of our side and their side, used in conflict markers
+///   and messages
+///
+/// # Returns
+///
+/// The merged and conflicting files. A file of one side in the way of a
+/// directory of the other side is moved aside as a conflict.
+pub(crate) fn merge_files(
+    repo: &Repository,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    labels: (&str, &str),
+) -> anyhow::Result<TreeMerge> {
+    let files = |tree| -> anyhow::Result<BTreeMap<Vec<u8>, FileState>> {
+        Ok(flatten_tree(repo, tree)?
+            .into_iter()
+            .map(|entry| (entry.name, (entry.mode, entry.hash)))
+            .collect())
+    };
+    let base = files(base)?;
+    let ours = files(ours)?;
+    let theirs = files(theirs)?;
+
+    let paths: BTreeSet<&Vec<u8>> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    let mut merge = TreeMerge::default();
+    let mut messages = BTreeMap::new();
+
+    for path in &paths {
+        let (base, ours, theirs) = (base.get(*path), ours.get(*path), theirs.get(*path));
+        let mut path_messages = Vec::new();
+        let file = if ours == theirs || base == theirs {
+            ours.cloned().map(MergedFile::Clean)
+        } else if base == ours {
+            theirs.cloned().map(MergedFile::Clean)
+        } else {
+            Some(merge_file(
+                repo,
+                &String::from_utf8_lossy(path),
+                [base, ours, theirs],
+                labels,
+                &mut path_messages,
+            )?)
+        };
+
+        messages.insert((*path).clone(), path_messages);
+        if let Some(file) = file {
+            merge.files.insert((*path).clone(), file);
+        }
+    }
+
+    // A file cannot be kept where the other side added a directory: it is moved
+    // aside to `<path>~<side>` and left as a conflict
+    let in_the_way: Vec<Vec<u8>> = merge
+        .files
+        .keys()
+        .filter(|path| {
+            let directory = [path.as_slice(), b"/"].concat();
+            merge
+                .files
+                .range(directory.clone()..)
+                .next()
+                .is_some_and(|(next, _)| next.starts_with(&directory))
+        })
+        .cloned()
+        .collect();
+    let mut taken: BTreeSet<Vec<u8>> = paths.into_iter().cloned().collect();
+    for path in in_the_way {
+        let side = match ours.contains_key(&path) {
+            true => labels.0,
+            false => labels.1,
+        };
+        let moved = unique_path(&path, side, &taken);
+        taken.insert(moved.clone());
+
+        let states = [base.get(&path), ours.get(&path), theirs.get(&path)];
+        let moved_name = String::from_utf8_lossy(&moved).into_owned();
+        let mut path_messages = vec![format!(
+            "CONFLICT (file/directory): directory in the way of {} from {side}; \
+             moving it to {moved_name} instead.",
+            String::from_utf8_lossy(&path)
+        )];
+        let file = match merge.files.remove(&path) {
+            Some(MergedFile::Clean(state)) => MergedFile::Conflict {
+                stages: states.map(|state| state.cloned()),
+                work_tree: state,
+                binary: false,
+            },
+            // Only a file modified on one side and deleted on the other conflicts
+            // where a directory was added, and is reported under its new path
+            _ => merge_file(repo, &moved_name, states, labels, &mut path_messages)?,
+        };
+        messages.insert(path, path_messages);
+        merge.files.insert(moved, file);
+    }
+
+    merge.messages = messages.into_values().flatten().collect();
+    Ok(merge)
+}
+
+/// Find a path to move a file in the way of a directory to, not used by any side:
+/// `<path>~<side>`, followed by `_<n>` if needed.
+fn unique_path(path: &[u8], side: &str, taken: &BTreeSet<Vec<u8>>) -> Vec<u8> {
+    let moved = [path, b"~", side.replace('/', "_").as_bytes()].concat();
+    let mut unique = moved.clone();
+    let mut suffix = 0;
+    while taken.contains(&unique) {
+        unique = [moved.as_slice(), format!("_{suffix}").as_bytes()].concat();
+        suffix += 1;
+    }
+    unique
+}
+
+/// Merge a file changed differently by both sides.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read and write the blobs in
+/// * `path` - The path of the file
+/// * `states` - The base, our and their versions of the file
+/// * `labels` - The names of our side and their side
+/// * `messages` - Where the messages about the merge are added
+fn merge_file(
+    repo: &Repository,
+    path: &str,
+    states: [Option<&FileState>; 3],
+    labels: (&str, &str),
+    messages: &mut Vec<String>,
+) -> anyhow::Result<MergedFile> {
+    let stages = states.map(|state| state.cloned());
+    let [base, ours, theirs] = states;
+
+    let (Some(our_state), Some(their_state)) = (ours, theirs) else {
+        // One side deleted the file the other side modified, which stays
+        let (deleted, modified, work_tree) = match ours {
+            Some(ours) => (labels.1, labels.0, ours),
+            None => (labels.0, labels.1, theirs.expect("one side has the file")),
+        };
+        messages.push(format!(
+            "CONFLICT (modify/delete): {path} deleted in {deleted} and modified in {modified}.  \
+             Version {modified} of {path} left in tree."
+        ));
+        return Ok(MergedFile::Conflict {
+            stages,
+            work_tree: work_tree.clone(),
+            binary: false,
+        });
+    };
+
+    // Only the modes of regular files can be merged, then their content
+    let is_file = |state: Option<&FileState>| {
+        state.is_none_or(|(mode, _)| *mode != MODE_SYMLINK && *mode != MODE_GITLINK)
+    };
+    let mode = match base {
+        Some((mode, _)) if *mode == our_state.0 => their_state.0,
+        _ => our_state.0,
+    };
+    let kind = if base.is_none() { "add/add" } else { "content" };
+    messages.push(format!("Auto-merging {path}"));
+
+    let mut binary = false;
+    if [base, ours, theirs].into_iter().all(is_file) {
+        let read = |state: Option<&FileState>| match state {
+            Some((_, hash)) => read_object_of_type(repo, hash, ObjectType::Blob),
+            None => Ok(Vec::new()),
+        };
+        let (base_content, our_content, their_content) = (read(base)?, read(ours)?, read(theirs)?);
+
+        binary = [&base_content, &our_content, &their_content]
+            .iter()
+            .any(|content| is_binary(content));
+        if !binary {
+            let merged = merge_content(&base_content, &our_content, &their_content, labels);
+            let hash = write_object(repo, ObjectType::Blob, &merged.content)?;
+            if merged.conflicts == 0 {
+                return Ok(MergedFile::Clean((mode, hash)));
+            }
+            messages.push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+            return Ok(MergedFile::Conflict {
+                stages,
+                work_tree: (mode, hash),
+                binary: false,
+            });
+        }
+    }
+
+    // Our version is left in the working tree of the files that cannot be merged
+    messages.push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+    Ok(MergedFile::Conflict {
+        stages,
+        work_tree: our_state.clone(),
+        binary,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_files, merge_trees, MergedFile};
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::{hash_object, write_object, ObjectType};
+    use crate::utils::repository::Repository;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::flatten_tree;
+
+    fn write_tree(repo: &Repository, files: &[(&str, &str)]) -> String {
+        let mut index = Index::default();
+        for (path, content) in files {
+            let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+        }
+        index.write_tree(repo, false).unwrap()
+    }
+
+    #[test]
+    fn merges_changes_of_both_sides() {
+        let temp = TempRepo::new();
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let base = write_tree(&repo, &[("a", "a"), ("b", "b"), ("c", "c")]);
+        let ours = write_tree(&repo, &[("a", "ours"), ("b", "b")]);
+        let theirs = write_tree(&repo, &[("a", "a"), ("b", "b"), ("c", "c"), ("d/e", "e")]);
+
+        let merged = merge_trees(&repo, &base, &ours, &theirs).unwrap();
+        let expected = write_tree(&repo, &[("a", "ours"), ("b", "b"), ("d/e", "e")]);
+        assert_eq!(merged, expected);
+        assert_eq!(flatten_tree(&repo, &merged).unwrap().len(), 3);
+
+        let conflicting = write_tree(&repo, &[("a", "theirs"), ("b", "b"), ("c", "c")]);
+        let err = merge_trees(&repo, &base, &ours, &conflicting).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("CONFLICT (content): Merge conflict in a\n"));
+
+        let file = write_tree(&repo, &[("a", "a"), ("b", "b"), ("c", "c"), ("d", "d")]);
+        let err = merge_trees(&repo, &base, &file, &theirs).unwrap_err();
+        assert!(err.to_string().starts_with(
+            "CONFLICT (file/directory): directory in the way of d from ours; \
+                 moving it to d~ours instead.\n"
+        ));
+    }
+
+    #[test]
+    fn moves_files_in_the_way_of_directories() {
+        let temp = TempRepo::new();
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let base = write_tree(&repo, &[("a/b", "base\n")]);
+        let ours = write_tree(&repo, &[("a/b", "ours\n"), ("a/b~HEAD", ""), ("d", "d\n")]);
+        let theirs = write_tree(&repo, &[("a/b/c/d", "d\n"), ("d/e", "e\n")]);
+
+        let merge = merge_files(&repo, &base, &ours, &theirs, ("HEAD", "them")).unwrap();
+        assert_eq!(merge.conflicts(), ["a/b~HEAD_0", "d~HEAD"]);
+        assert_eq!(merge.messages, [
+            "CONFLICT (file/directory): directory in the way of a/b from HEAD; \
+                 moving it to a/b~HEAD_0 instead.",
+            "CONFLICT (modify/delete): a/b~HEAD_0 deleted in them and modified in HEAD.  \
+                 Version HEAD of a/b~HEAD_0 left in tree.",
+            "CONFLICT (file/directory): directory in the way of d from HEAD; \
+                 moving it to d~HEAD instead.",
+        ]);
+
+        // The directories are merged, and the files are left next to them
+        let work_tree = merge.write_work_tree(&repo).unwrap();
+        let expected = write_tree(&repo, &[
+            ("a/b/c/d", "d\n"),
+            ("a/b~HEAD", ""),
+            ("a/b~HEAD_0", "ours\n"),
+            ("d/e", "e\n"),
+            ("d~HEAD", "d\n"),
+        ]);
+        assert_eq!(work_tree, expected);
+
+        let mut index = Index::default();
+        merge.add_conflicts(&mut index);
+        let stages: Vec<_> = index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path.as_slice(), entry.stage))
+            .collect();
+        assert_eq!(stages, [
+            (&b"a/b~HEAD_0"[..], 1),
+            (b"a/b~HEAD_0", 2),
+            (b"d~HEAD", 2)
+        ]);
+
+        // Their files are moved aside the same way
+        let merge = merge_files(&repo, &base, &theirs, &ours, ("HEAD", "feature/x")).unwrap();
+        assert_eq!(merge.conflicts(), ["a/b~feature_x", "d~feature_x"]);
+    }
+
+    #[test]
+    fn keeps_conflicts_with_their_stages() {
+        let temp = TempRepo::new();
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let base = write_tree(&repo, &[("a", "1\n2\n3\n"), ("b", "b\n"), ("c", "c\n")]);
+        let ours = write_tree(&repo, &[("a", "one\n2\n3\n"), ("b", "ours\n")]);
+        let theirs = write_tree(&repo, &[
+            ("a", "1\n2\nthree\n"),
+            ("b", "theirs\n"),
+            ("c", "C\n"),
+        ]);
+
+        let merge = merge_files(&repo, &base, &ours, &theirs, ("HEAD", "them")).unwrap();
+        assert_eq!(merge.conflicts(), ["b", "c"]);
+        assert_eq!(merge.messages, [
+            "Auto-merging a",
+            "Auto-merging b",
+            "CONFLICT (content): Merge conflict in b",
+            "CONFLICT (modify/delete): c deleted in HEAD and modified in them.  \
+                 Version them of c left in tree."
+        ]);
+        let Some(MergedFile::Conflict {
+            stages, work_tree, ..
+        }) = merge.files.get(&b"c"[..])
+        else {
+            panic!("c does not conflict");
+        };
+        assert!(stages[0].is_some() && stages[1].is_none() && stages[2].is_some());
+        assert_eq!(Some(work_tree), stages[2].as_ref());
+
+        // The working tree has the merged files, and the conflict markers
+        let work_tree = merge.write_work_tree(&repo).unwrap();
+        let expected = write_tree(&repo, &[
+            ("a", "one\n2\nthree\n"),
+            ("b", "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> them\n"),
+            ("c", "C\n"),
+        ]);
+        assert_eq!(work_tree, expected);
+
+        let mut index = Index::default();
+        merge.add_conflicts(&mut index);
+        let stages: Vec<_> = index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path.as_slice(), entry.stage))
+            .collect();
+        assert_eq!(stages, [
+            (&b"b"[..], 1),
+            (b"b", 2),
+            (b"b", 3),
+            (b"c", 1),
+            (b"c", 3)
+        ]);
+    }
+
+    #[test]
+    fn flags_binary_conflicts() {
+        let temp = TempRepo::new();
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let base = write_tree(&repo, &[("bin", "a\0"), ("text", "a\n")]);
+        let ours = write_tree(&repo, &[("bin", "b\0"), ("text", "b\n")]);
+        let theirs = write_tree(&repo, &[("bin", "c\0"), ("text", "c\n")]);
+
+        let merge = merge_files(&repo, &base, &ours, &theirs, ("HEAD", "them")).unwrap();
+        assert_eq!(merge.conflicts(), ["bin", "text"]);
+        assert_eq!(merge.binary_conflicts(), ["bin"]);
+        // Only the conflict is reported, the warning being left to callers
+        assert_eq!(merge.messages, [
+            "Auto-merging bin",
+            "CONFLICT (content): Merge conflict in bin",
+            "Auto-merging text",
+            "CONFLICT (content): Merge conflict in text",
+        ]);
+        let Some(MergedFile::Conflict { work_tree, .. }) = merge.files.get(&b"bin"[..]) else {
+            panic!("bin does not conflict");
+        };
+        assert_eq!(
+            work_tree,
+            &(0o100644, hash_object(&ObjectType::Blob, b"b\0"))
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Shifting trees for subtree merges
+//!
+//! A project merged as a subdirectory of another one has its files at the root
+//! of its own trees. Before merging, its trees are shifted to line up with ours,
+//! either under a given prefix or under the subdirectory whose content resembles
+//! them the most (scored like git's `match-trees`).
+
+use crate::utils::objects::{write_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::tree::{
+    find_entry, read_tree, serialize_tree, TreeEntry, MODE_SYMLINK, MODE_TREE,
+};
+
+/// How deep subdirectories are searched for a matching tree
+const DEPTH_LIMIT: usize = 2;
+
+/// Shift a tree so that it lines up with our tree.
+///
+/// Their tree is either placed under a subdirectory of ours (replacing its content),
+/// or replaced by one of its own subdirectories, whichever matches our tree better.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read and write the trees in
+/// * `ours` - The hash of our tree
+/// * `theirs` - The hash of the tree to shift
+/// * `prefix` - The subdirectory to shift by, or `None` to detect it
+///
+/// # Returns
+///
+/// The hash of the shifted tree, or of their tree if it is not shifted
+pub(crate) fn shift_tree(
+    repo: &Repository,
+    ours: &str,
+    theirs: &str,
+    prefix: Option<&str>,
+) -> anyhow::Result<String> {
+    let Some(prefix) = prefix.map(|prefix| prefix.trim_matches('/')) else {
+        return detect_shift(repo, ours, theirs);
+    };
+
+    let subtree = |tree| -> anyhow::Result<Option<String>> {
+        Ok(find_entry(repo, tree, prefix)?
+            .filter(TreeEntry::is_tree)
+            .map(|entry| entry.hash))
+    };
+
+    // Both trees may have the subdirectory, in which case the better match wins
+    let (shift_theirs, unshift_theirs) = match (subtree(ours)?, subtree(theirs)?) {
+        (Some(_), None) => (true, false),
+        (None, Some(_)) => (false, true),
+        (Some(ours_sub), Some(theirs_sub)) => {
+            let mut best = (score_trees(repo, ours, theirs)?, (false, false));
+            let shifted = score_trees(repo, &ours_sub, theirs)?;
+            if shifted > best.0 {
+                best = (shifted, (true, false));
+            }
+            if score_trees(repo, &theirs_sub, ours)? > best.0 {
+                best.1 = (false, true);
+            }
+            best.1
+        },
+        (None, None) => (false, false),
+    };
+
+    if shift_theirs {
+        splice_tree(repo, ours, prefix, theirs)
+    } else if unshift_theirs {
+        Ok(subtree(theirs)?.unwrap_or_else(|| theirs.to_string()))
+    } else {
+        Ok(theirs.to_string())
+    }
+}
+
+/// Shift their tree by the subdirectory that matches best, if any.
+fn detect_shift(repo: &Repository, ours: &str, theirs: &str) -> anyhow::Result<String> {
+    let unshifted = score_trees(repo, ours, theirs)?;
+
+    // Does a subdirectory of ours resemble their tree?
+    let mut add = (unshifted, String::new());
+    match_trees(repo, ours, theirs, "", DEPTH_LIMIT, &mut add)?;
+    // Does a subdirectory of theirs resemble our tree?
+    let mut remove = (unshifted, String::new());
+    match_trees(repo, theirs, ours, "", DEPTH_LIMIT, &mut remove)?;
+
+    if add.0 < remove.0 {
+        if remove.1.is_empty() {
+            return Ok(theirs.to_string());
+        }
+        let entry = find_entry(repo, theirs, &remove.1)?;
+        return Ok(entry.map_or_else(|| theirs.to_string(), |entry| entry.hash));
+    }
+
+    if add.1.is_empty() {
+        return Ok(theirs.to_string());
+    }
+    splice_tree(repo, ours, &add.1, theirs)
+}
+
+/// Find the subdirectory of a tree that best matches another tree.
+///
+/// # Arguments
+///
+/// * `tree` - The hash of the tree to search the subdirectories of
+/// * `other` - The hash of the tree to match
+/// * `base` - The path of `tree`, including a trailing slash (empty for the root)
+/// * `depth` - How many more levels of subdirectories to search
+/// * `best` - The best score so far and its path, updated as better matches are found
+fn match_trees(
+    repo: &Repository,
+    tree: &str,
+    other: &str,
+    base: &str,
+    depth: usize,
+    best: &mut (i64, String),
+) -> anyhow::Result<()> {
+    for entry in read_tree(repo, tree)? {
+        if !entry.is_tree() {
+            continue;
+        }
+
+        let path = format!("{base}{}", String::from_utf8_lossy(&entry.name));
+        let score = score_trees(repo, &entry.hash, other)?;
+        if score > best.0 {
+            *best = (score, path.clone());
+        }
+        if depth > 0 {
+            match_trees(
+                repo,
+                &entry.hash,
+                other,
+                &format!("{path}/"),
+                depth - 1,
+                best,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Score how much two trees resemble each other, by comparing their entries:
+/// matching entries raise the score and missing or differing entries lower it,
+/// directories weighing more than symlinks, which weigh more than files.
+fn score_trees(repo: &Repository, one: &str, two: &str) -> anyhow::Result<i64> {
+    let mut one = sorted_entries(repo, one)?.into_iter().peekable();
+    let mut two = sorted_entries(repo, two)?.into_iter().peekable();
+    let mut score = 0;
+
+    loop {
+        let ordering = match (one.peek(), two.peek()) {
+            (Some((one, _)), Some((two, _))) => one.cmp(two),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match ordering {
+            std::cmp::Ordering::Less => score += score_missing(one.next().unwrap().1.mode),
+            std::cmp::Ordering::Greater => score += score_missing(two.next().unwrap().1.mode),
+            std::cmp::Ordering::Equal => {
+                let (_, one) = one.next().unwrap();
+                let (_, two) = two.next().unwrap();
+                score += if one.hash == two.hash {
+                    score_matches(one.mode, two.mode)
+                } else {
+                    score_differs(one.mode, two.mode)
+                };
+            },
+        }
+    }
+
+    Ok(score)
+}
+
+/// The entries of a tree by their name, in tree order
+/// (directories sort as if their name ended with a slash).
+fn sorted_entries(repo: &Repository, tree: &str) -> anyhow::Result<Vec<(Vec<u8>, TreeEntry)>> {
+    let mut entries: Vec<_> = read_tree(repo, tree)?
+        .into_iter()
+        .map(|entry| {
+            let mut key = entry.name.clone();
+            if entry.is_tree() {
+                key.push(b'/');
+            }
+            (key, entry)
+        })
+        .collect();
+    entries.sort_by(|(one, _), (two, _)| one.cmp(two));
+    Ok(entries)
+}
+
+fn is_tree(mode: u32) -> bool {
+    mode == MODE_TREE
+}
+
+fn is_symlink(mode: u32) -> bool {
+    mode == MODE_SYMLINK
+}
+
+fn score_missing(mode: u32) -> i64 {
+    if is_tree(mode) {
+        -1000
+    } else if is_symlink(mode) {
+        -500
+    } else {
+        -50
+    }
+}
+
+fn score_differs(one: u32, two: u32) -> i64 {
+    if is_tree(one) != is_tree(two) {
+        -100
+    } else if is_symlink(one) != is_symlink(two) {
+        -50
+    } else {
+        -5
+    }
+}
+
+fn score_matches(one: u32, two: u32) -> i64 {
+    if is_tree(one) != is_tree(two) {
+        -100
+    } else if is_symlink(one) != is_symlink(two) {
+        -50
+    } else if is_tree(one) {
+        1000
+    } else if is_symlink(one) {
+        500
+    } else {
+        250
+    }
+}
+
+/// Replace the content of a subdirectory of a tree with another tree.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read and write the trees in
+/// * `tree` - The hash of the tree containing the subdirectory
+/// * `prefix` - The `/`-separated path of the subdirectory, which must exist
+/// * `subtree` - The hash of the tree to put in its place
+///
+/// # Returns
+///
+/// The hash of the new tree
+fn splice_tree(
+    repo: &Repository,
+    tree: &str,
+    prefix: &str,
+    subtree: &str,
+) -> anyhow::Result<String> {
+    let (name, rest) = match prefix.split_once('/') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (prefix, None),
+    };
+
+    let mut entries = read_tree(repo, tree)?;
+    let Some(entry) = entries
+        .iter_mut()
+        .find(|entry| entry.is_tree() && entry.name == name.as_bytes())
+    else {
+        anyhow::bail!("cannot find subtree '{name}' in tree {tree}");
+    };
+
+    entry.hash = match rest {
+        Some(rest) => splice_tree(repo, &entry.hash, rest, subtree)?,
+        None => subtree.to_string(),
+    };
+    write_object(repo, ObjectType::Tree, &serialize_tree(entries)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shift_tree;
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::repository::Repository;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::find_entry;
+
+    fn write_tree(repo: &Repository, files: &[(&str, &str)]) -> String {
+        let mut index = Index::default();
+        for (path, content) in files {
+            let hash = write_object(repo, ObjectType::Blob, content.as_bytes()).unwrap();
+            index.add_entry(IndexEntry::new(path.as_bytes().to_vec(), 0o100644, hash));
+        }
+        index.write_tree(repo, false).unwrap()
+    }
+
+    #[test]
+    fn shifts_trees_into_matching_subdirectory() {
+        let temp = TempRepo::new();
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let ours = write_tree(&repo, &[
+            ("main.c", "main"),
+            ("vendor/lib/a.c", "a"),
+            ("vendor/lib/b.c", "b"),
+            ("vendor/lib/c.c", "c"),
+        ]);
+        let theirs = write_tree(&repo, &[("a.c", "a"), ("b.c", "changed"), ("c.c", "c")]);
+        let expected = write_tree(&repo, &[
+            ("main.c", "main"),
+            ("vendor/lib/a.c", "a"),
+            ("vendor/lib/b.c", "changed"),
+            ("vendor/lib/c.c", "c"),
+        ]);
+
+        assert_eq!(shift_tree(&repo, &ours, &theirs, None).unwrap(), expected);
+        assert_eq!(
+            shift_tree(&repo, &ours, &theirs, Some("vendor/lib/")).unwrap(),
+            expected
+        );
+
+        // Their tree may contain ours instead
+        let lib = find_entry(&repo, &ours, "vendor/lib").unwrap().unwrap();
+        assert_eq!(shift_tree(&repo, &theirs, &expected, None).unwrap(), {
+            let shifted = find_entry(&repo, &expected, "vendor/lib").unwrap().unwrap();
+            assert_ne!(shifted.hash, lib.hash);
+            shifted.hash
+        });
+
+        // Matching trees are left alone
+        assert_eq!(shift_tree(&repo, &ours, &expected, None).unwrap(), expected);
+    }
+}
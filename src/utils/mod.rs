@@ -1,114 +1,103 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 
+use crate::utils::repository::Repository;
+
+pub(crate) mod advice;
+pub(crate) mod apply;
+pub(crate) mod archive;
+pub(crate) mod attributes;
+pub(crate) mod base64;
+pub(crate) mod base85;
+pub(crate) mod bitmap;
+pub(crate) mod bundle;
+pub(crate) mod checkout;
+pub(crate) mod color;
+pub(crate) mod commit;
+pub(crate) mod commit_graph;
+pub(crate) mod config;
+pub(crate) mod convert;
+pub(crate) mod credential;
+pub(crate) mod date;
+pub(crate) mod decorate;
+pub(crate) mod diff;
+pub(crate) mod editor;
 pub(crate) mod env;
+pub(crate) mod grafts;
 pub(crate) mod hex;
+pub(crate) mod hooks;
+pub(crate) mod http;
+pub(crate) mod ignore;
+pub(crate) mod index;
+pub(crate) mod json;
+pub(crate) mod lfs;
+pub(crate) mod mailmap;
+pub(crate) mod merge;
+pub(crate) mod notes;
 pub(crate) mod objects;
+pub(crate) mod pack;
+pub(crate) mod pathspec;
+pub(crate) mod protocol;
+pub(crate) mod quote;
+pub(crate) mod rebase;
+pub(crate) mod reflog;
+pub(crate) mod refs;
+pub(crate) mod regex;
+pub(crate) mod replace;
+pub(crate) mod repository;
+pub(crate) mod rerere;
+pub(crate) mod revision;
+pub(crate) mod sequencer;
+pub(crate) mod sha256;
+pub(crate) mod signature;
+pub(crate) mod sparse;
+pub(crate) mod stats;
+pub(crate) mod submodule;
+pub(crate) mod tempfile;
 pub(crate) mod test;
+pub(crate) mod trailer;
+pub(crate) mod transfer;
+pub(crate) mod tree;
+pub(crate) mod walk;
+pub(crate) mod wildmatch;
+pub(crate) mod work_tree;
 
 /// Get the path of the current directory.
 pub(crate) fn get_current_dir() -> anyhow::Result<PathBuf> {
     std::env::current_dir().context("get path of current directory")
 }
 
-/// Get the path to the git directory.
-/// This could be either of the following (in order of precedence):
-///
-/// 1. `$GIT_DIR`
-/// 2. `.git`
-///
-/// # Returns
-///
-/// The path to the git directory
-pub(crate) fn git_dir() -> anyhow::Result<PathBuf> {
-    let git_dir_path = std::env::var(env::GIT_DIR).unwrap_or_else(|_| ".git".to_string());
-    let mut current_dir = get_current_dir()?;
-
-    // Search for the git directory in the current directory and its parents
-    while current_dir.exists() {
-        let git_dir = current_dir.join(&git_dir_path);
-
-        // Return the git directory if it exists
-        if git_dir.exists() {
-            return Ok(git_dir);
-        }
-
-        let Some(parent_dir) = current_dir.parent() else {
-            break;
-        };
-
-        current_dir = parent_dir.to_path_buf();
-    }
-
-    anyhow::bail!(
-        "not a git repository (or any of the parent directories): {}",
-        git_dir_path
-    )
+/// Get a path as shown to the user: relative to the directory the command is
+/// run from if it is inside it.
+pub(crate) fn display_path(repo: &Repository, path: &Path) -> String {
+    let relative = path.strip_prefix(repo.cwd()).unwrap_or(path);
+    relative.display().to_string()
 }
 
-/// Get the path to the git object directory.
-/// This could be either of the following (in order of precedence):
-///
-/// 1. `<git_directory>/$GIT_OBJECT_DIRECTORY`
-/// 2. `<git_directory>/objects`
-///
-/// # Arguments
-///
-/// * `check_exists` - Whether to check if the object directory exists,
-///   exiting with an error if it does not
-///
-/// # Returns
-///
-/// The path to the git object directory
-pub(crate) fn git_object_dir(check_exists: bool) -> anyhow::Result<PathBuf> {
-    let git_dir = git_dir()?;
-    let git_object_dir =
-        std::env::var(env::GIT_OBJECT_DIRECTORY).unwrap_or_else(|_| "objects".to_string());
-    let git_object_dir = git_dir.join(&git_object_dir);
+/// Get the file system path of a path of the repository (the raw bytes stored
+/// in trees and the index), without losing bytes that are not valid UTF-8.
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
 
-    // Check if the object directory exists
-    if check_exists && !git_object_dir.exists() {
-        anyhow::bail!(
-            "{}/{} directory does not exist",
-            git_dir.display(),
-            git_object_dir.display()
-        );
-    }
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
 
-    Ok(git_object_dir)
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
 }
 
-/// Get the path to a git object.
-/// The path is constructed as follows:
-///
-/// `<git_object_directory>/<hash[..2]>/<hash[2..]>`
-///
-/// # Example
-///
-/// If the default git and object directories are used,
-/// the path for object `e7a11a969c037e00a796aafeff6258501ec15e9a` would be:
-///
-/// `.git/objects/e7/a11a969c037e00a796aafeff6258501ec15e9a`
-///
-/// # Arguments
-///
-/// * `hash` - The object hash
-/// * `check_exists` - Whether to check if the object exists,
-///   exiting with an error if it does not
-///
-/// # Returns
-///
-/// The path to the object file
-pub(crate) fn get_object_path(hash: &str, check_exists: bool) -> anyhow::Result<PathBuf> {
-    let object_dir = git_object_dir(check_exists)?;
-    let object_dir = object_dir.join(&hash[..2]);
-    let object_path = object_dir.join(&hash[2..]);
+/// Get the raw bytes of a file system path, as stored in trees and the index.
+#[cfg(unix)]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
 
-    // Check if the object exists
-    if check_exists && !object_path.exists() {
-        anyhow::bail!("{} is not a valid object", hash);
-    }
+    path.as_os_str().as_bytes().to_vec()
+}
 
-    Ok(object_path)
+#[cfg(not(unix))]
+pub(crate) fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
 }
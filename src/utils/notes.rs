@@ -0,0 +1,165 @@
+//! Notes attached to objects, kept in the tree of the commits of a notes ref
+//! (`refs/notes/commits` by default)
+//!
+//! Each note is a blob named after the hash of the object it annotates. Large
+//! notes trees split the hashes into directories (e.g. `ab/cdef...`), which
+//! are read but never written.
+
+use std::collections::BTreeMap;
+
+use crate::utils::commit::{create_commit, read_commit};
+use crate::utils::config::Config;
+use crate::utils::env;
+use crate::utils::objects::{read_object_of_type, write_object, ObjectType};
+use crate::utils::refs::{is_hash, resolve_ref, update_ref};
+use crate::utils::repository::Repository;
+use crate::utils::tree::{flatten_tree, serialize_tree, TreeEntry};
+
+/// The notes ref used when none is configured
+pub(crate) const DEFAULT_NOTES_REF: &str = "refs/notes/commits";
+
+/// The notes ref to use: `$GIT_NOTES_REF`, `core.notesRef` or `refs/notes/commits`.
+pub(crate) fn default_notes_ref(config: &Config) -> String {
+    match config.env().var(env::GIT_NOTES_REF) {
+        Some(name) => expand_notes_ref(name),
+        None => config
+            .get("core.notesRef")
+            .map_or(DEFAULT_NOTES_REF.to_string(), expand_notes_ref),
+    }
+}
+
+/// Expand a notes ref name (e.g. `review` or `notes/review`) to its full name.
+pub(crate) fn expand_notes_ref(name: &str) -> String {
+    if name.starts_with("refs/notes/") {
+        name.to_string()
+    } else if name.starts_with("notes/") {
+        format!("refs/{name}")
+    } else {
+        format!("refs/notes/{name}")
+    }
+}
+
+/// The notes of a notes ref
+#[derive(Debug, Clone)]
+pub(crate) struct Notes {
+    /// The full name of the notes ref
+    pub(crate) name: String,
+    /// The commit the ref points to, if it exists
+    pub(crate) commit: Option<String>,
+    /// The hashes of the note blobs, by the hash of the object they annotate
+    pub(crate) notes: BTreeMap<String, String>,
+}
+
+impl Notes {
+    /// Load the notes of a ref, which has none if it does not exist.
+    pub(crate) fn load(repo: &Repository, name: &str) -> anyhow::Result<Self> {
+        let commit = resolve_ref(repo, name)?;
+        let notes = match &commit {
+            Some(commit) => read_notes(repo, commit)?,
+            None => BTreeMap::new(),
+        };
+        Ok(Notes {
+            name: name.to_string(),
+            commit,
+            notes,
+        })
+    }
+
+    /// Read the note of an object.
+    pub(crate) fn read(&self, repo: &Repository, object: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.notes.get(object) {
+            Some(blob) => Ok(Some(read_object_of_type(repo, blob, ObjectType::Blob)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Write a commit of the current notes on top of the commit of the ref,
+    /// without updating the ref.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository of the notes
+    /// * `parents` - The other parents of the commit (e.g. merged notes)
+    /// * `message` - The commit message, without its final newline
+    ///
+    /// # Returns
+    ///
+    /// The hash of the commit
+    pub(crate) fn commit_tree(
+        &self,
+        repo: &Repository,
+        parents: Vec<String>,
+        message: &str,
+    ) -> anyhow::Result<String> {
+        let tree = write_notes_tree(repo, &self.notes)?;
+        let parents = self.commit.iter().cloned().chain(parents).collect();
+        create_commit(repo, &tree, parents, &format!("{message}\n"))
+    }
+
+    /// Commit the current notes like [`Notes::commit_tree`], and point the ref at the commit.
+    pub(crate) fn commit(
+        &mut self,
+        repo: &Repository,
+        parents: Vec<String>,
+        message: &str,
+    ) -> anyhow::Result<String> {
+        let commit = self.commit_tree(repo, parents, message)?;
+        update_ref(repo, &self.name, &commit, &format!("notes: {message}"))?;
+        self.commit = Some(commit.clone());
+        Ok(commit)
+    }
+}
+
+/// Read the notes of a notes commit.
+///
+/// # Returns
+///
+/// The hashes of the note blobs by the hash of the object they annotate
+/// (files that are not named after a hash are skipped)
+pub(crate) fn read_notes(
+    repo: &Repository,
+    commit: &str,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let tree = read_commit(repo, commit)?.tree;
+    let mut notes = BTreeMap::new();
+    for entry in flatten_tree(repo, &tree)? {
+        let object = String::from_utf8_lossy(&entry.name).replace('/', "");
+        if is_hash(&object) {
+            notes.insert(object.to_ascii_lowercase(), entry.hash);
+        }
+    }
+    Ok(notes)
+}
+
+/// Write the tree of notes, with a file named after each annotated object.
+///
+/// # Returns
+///
+/// The hash of the tree
+pub(crate) fn write_notes_tree(
+    repo: &Repository,
+    notes: &BTreeMap<String, String>,
+) -> anyhow::Result<String> {
+    let entries = notes
+        .iter()
+        .map(|(object, blob)| TreeEntry {
+            mode: 0o100644,
+            name: object.as_bytes().to_vec(),
+            hash: blob.clone(),
+        })
+        .collect();
+    write_object(repo, ObjectType::Tree, &serialize_tree(entries)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand_notes_ref;
+
+    #[test]
+    fn expands_notes_refs() {
+        assert_eq!(expand_notes_ref("review"), "refs/notes/review");
+        assert_eq!(expand_notes_ref("notes/review"), "refs/notes/review");
+        assert_eq!(expand_notes_ref("refs/notes/review"), "refs/notes/review");
+        assert_eq!(expand_notes_ref("refs/heads/x"), "refs/notes/refs/heads/x");
+    }
+}
@@ -1,9 +1,25 @@
 //! Utilities for working with Git objects
 
 use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
 use clap::ValueEnum;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+
+use crate::utils::pack::{read_pack, PackFile};
+use crate::utils::protocol::promisor::fetch_missing;
+use crate::utils::refs::is_hash;
+use crate::utils::replace::replace_object;
+use crate::utils::repository::Repository;
+use crate::utils::stats::Counters;
+use crate::utils::tempfile::TempPath;
 
 /// Format the header of a `.git/objects` file
 pub(crate) fn format_header<O, S>(object_type: O, size: S) -> String
@@ -15,7 +31,7 @@ where
 }
 
 /// Parse the header of a `.git/objects` file into the [`ObjectHeader`] struct.
-pub(crate) fn parse_header(header: &[u8]) -> anyhow::Result<ObjectHeader> {
+pub(crate) fn parse_header(header: &[u8]) -> anyhow::Result<ObjectHeader<'_>> {
     // Split the header into type and size
     let mut header = header.splitn(2, |&b| b == b' ');
 
@@ -26,8 +42,272 @@ pub(crate) fn parse_header(header: &[u8]) -> anyhow::Result<ObjectHeader> {
     Ok(ObjectHeader { object_type, size })
 }
 
+/// Compute the hash of an object, as it would be stored in the object database.
+pub(crate) fn hash_object(object_type: &ObjectType, content: &[u8]) -> String {
+    Counters::update(|counters| counters.objects_hashed += 1);
+    let mut hasher = Sha1::new();
+    hasher.update(format_header(object_type, content.len()));
+    hasher.update(content);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Read an object from the object database, or its replacement if it is replaced.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read the object from
+/// * `hash` - The full hash of the object
+///
+/// # Returns
+///
+/// The type and content of the object
+pub(crate) fn read_object(repo: &Repository, hash: &str) -> anyhow::Result<(ObjectType, Vec<u8>)> {
+    read_original_object(repo, &replace_object(repo, hash)?)
+}
+
+/// Read an object as it is stored, even if it is replaced, as the objects
+/// copied or checked must match their hash.
+/// Loose objects are looked for first, then packed ones, then the ones a
+/// partial clone lacks are fetched from its promisor remote.
+pub(crate) fn read_original_object(
+    repo: &Repository,
+    hash: &str,
+) -> anyhow::Result<(ObjectType, Vec<u8>)> {
+    let Ok(object_path) = repo.object_path(hash, true) else {
+        let pack = match find_pack(repo, hash)? {
+            None if is_hash(hash) && fetch_missing(repo, &[hash.to_string()])? => {
+                find_pack(repo, hash)?
+            },
+            pack => pack,
+        };
+        let pack = pack.context(format!("{hash} is not a valid object"))?;
+        return pack
+            .read(repo, hash)?
+            .context(format!("{hash} is not a valid object"));
+    };
+    let file = std::fs::File::open(object_path).context(format!("open object {hash}"))?;
+
+    let mut object = Vec::new();
+    ZlibDecoder::new(file)
+        .read_to_end(&mut object)
+        .context(format!("decompress object {hash}"))?;
+
+    let header_end = object
+        .iter()
+        .position(|&b| b == 0)
+        .context("invalid object header")?;
+    let header = parse_header(&object[..=header_end])?;
+    let object_type = header.parse_type()?;
+    let content = object[header_end + 1..].to_vec();
+
+    // Ensure the object size matches the header
+    if header.parse_size()? != content.len() {
+        anyhow::bail!("object size does not match header");
+    }
+
+    Ok((object_type, content))
+}
+
+/// Open an object for reading as it is stored: its header, then its content.
+/// Loose objects are decompressed as they are read.
+pub(crate) fn open_object(repo: &Repository, hash: &str) -> anyhow::Result<Box<dyn BufRead>> {
+    let Ok(object_path) = repo.object_path(hash, true) else {
+        let (object_type, content) = read_original_object(repo, hash)?;
+        let mut object = format_header(object_type, content.len()).into_bytes();
+        object.extend(content);
+        return Ok(Box::new(std::io::Cursor::new(object)));
+    };
+    let file = std::fs::File::open(object_path).context(format!("open object {hash}"))?;
+    Ok(Box::new(BufReader::new(ZlibDecoder::new(file))))
+}
+
+/// Check whether an object is in the object database, loose or packed,
+/// or in one of its alternates.
+pub(crate) fn object_exists(repo: &Repository, hash: &str) -> anyhow::Result<bool> {
+    Ok(repo.object_path(hash, true).is_ok() || find_pack(repo, hash)?.is_some())
+}
+
+/// Read the type of an object from its header, without reading its content.
+pub(crate) fn read_object_header_type(repo: &Repository, hash: &str) -> anyhow::Result<ObjectType> {
+    let mut object = open_object(repo, hash)?;
+    let mut header = Vec::new();
+    object.read_until(0, &mut header)?;
+    parse_header(&header)?.parse_type()
+}
+
+/// Find the pack holding an object, looking for packs added since they were
+/// last listed if none has it.
+fn find_pack(repo: &Repository, hash: &str) -> anyhow::Result<Option<Arc<PackFile>>> {
+    if !is_hash(hash) {
+        return Ok(None);
+    }
+    for reload in [false, true] {
+        let pack = repo
+            .packs(reload)?
+            .into_iter()
+            .find(|pack| pack.find(hash).is_some());
+        if pack.is_some() {
+            return Ok(pack);
+        }
+    }
+    Ok(None)
+}
+
+/// Read an object from the object database, ensuring it has the expected type.
+pub(crate) fn read_object_of_type(
+    repo: &Repository,
+    hash: &str,
+    expected: ObjectType,
+) -> anyhow::Result<Vec<u8>> {
+    let (object_type, content) = read_object(repo, hash)?;
+    if object_type != expected {
+        anyhow::bail!("object {hash} is a {object_type}, not a {expected}");
+    }
+    Ok(content)
+}
+
+/// Write an object to the object database.
+/// Objects that already exist (or exist in an alternate) are not rewritten.
+///
+/// # Returns
+///
+/// The hash of the object
+pub(crate) fn write_object(
+    repo: &Repository,
+    object_type: ObjectType,
+    content: &[u8],
+) -> anyhow::Result<String> {
+    let hash = hash_object(&object_type, content);
+    let object_path = repo.object_path(&hash, false)?;
+
+    // Objects borrowed from alternates are not copied either
+    if object_path.exists() || repo.object_path(&hash, true).is_ok() {
+        return Ok(hash);
+    }
+
+    let object_dir = object_path.parent().context("invalid object path")?;
+    std::fs::create_dir_all(object_dir).context("create subdir in .git/objects")?;
+
+    // Compress the header and content with zlib
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(format_header(object_type, content.len()).as_bytes())?;
+    zlib.write_all(content)?;
+    let compressed = zlib.finish().context("finish zlib")?;
+
+    // Written under a temporary name first, so that an interrupted write
+    // leaves no truncated object behind
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let temp = TempPath::new(object_dir.join(format!("tmp_obj_{:x}{nanos:x}", std::process::id())));
+    std::fs::write(temp.path(), compressed).context("write object")?;
+    std::fs::rename(temp.path(), &object_path).context("write object")?;
+    temp.keep();
+    Ok(hash)
+}
+
+/// List every object of the object database, in the order they are stored:
+/// the loose objects by fan-out directory, then the objects of each pack,
+/// those of the repository coming before those of its alternates.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to list the objects of
+///
+/// # Returns
+///
+/// The hash, type and size of each object, an object both loose
+/// and packed (or in several packs) being listed once per copy
+pub(crate) fn list_objects(repo: &Repository) -> anyhow::Result<Vec<(String, ObjectType, usize)>> {
+    let object_dir = repo.object_dir(true)?;
+    let mut object_dirs = vec![object_dir];
+    object_dirs.extend(repo.alternate_object_dirs()?.iter().cloned());
+    let mut objects = Vec::new();
+
+    for object_dir in &object_dirs {
+        for (path, hash) in loose_files(object_dir)? {
+            let Some(hash) = hash else {
+                continue;
+            };
+
+            // Only the header is needed, not the whole content
+            let file = std::fs::File::open(path).context(format!("open object {hash}"))?;
+            let mut zlib = BufReader::new(ZlibDecoder::new(file));
+            let mut header = Vec::new();
+            zlib.read_until(0, &mut header)
+                .context(format!("decompress object {hash}"))?;
+            let header = parse_header(&header)?;
+            objects.push((hash, header.parse_type()?, header.parse_size()?));
+        }
+    }
+
+    for object_dir in &object_dirs {
+        let mut packs = Vec::new();
+        if let Ok(entries) = std::fs::read_dir(object_dir.join("pack")) {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "pack") {
+                    packs.push(path);
+                }
+            }
+        }
+        packs.sort();
+
+        for path in packs {
+            let pack = std::fs::read(&path).context(format!("read {}", path.display()))?;
+            let packed = read_pack(repo, &pack).context(format!("read {}", path.display()))?;
+            for (object_type, content) in packed {
+                let hash = hash_object(&object_type, &content);
+                objects.push((hash, object_type, content.len()));
+            }
+        }
+    }
+
+    Ok(objects)
+}
+
+/// List the files of the fan-out directories of the object database
+/// (`objects/<xx>/`), sorted by path.
+///
+/// # Returns
+///
+/// The path of each file, with the hash of the object it holds if its name is
+/// one (other files are garbage)
+pub(crate) fn list_loose_files(
+    repo: &Repository,
+) -> anyhow::Result<Vec<(PathBuf, Option<String>)>> {
+    loose_files(&repo.object_dir(true)?)
+}
+
+/// List the files of the fan-out directories of an object directory.
+fn loose_files(object_dir: &Path) -> anyhow::Result<Vec<(PathBuf, Option<String>)>> {
+    let mut fanouts = Vec::new();
+    for entry in std::fs::read_dir(object_dir).context("read object directory")? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            fanouts.push(name);
+        }
+    }
+    fanouts.sort();
+
+    let mut files = Vec::new();
+    for fanout in fanouts {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(object_dir.join(&fanout))? {
+            let entry = entry?;
+            let hash = format!("{fanout}{}", entry.file_name().to_string_lossy());
+            entries.push((entry.path(), is_hash(&hash).then_some(hash)));
+        }
+        entries.sort();
+        files.extend(entries);
+    }
+
+    Ok(files)
+}
+
 /// The type of object in the Git object database
-#[derive(Default, Debug, ValueEnum, Clone)]
+#[derive(Default, Debug, ValueEnum, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum ObjectType {
     #[default]
     Blob,
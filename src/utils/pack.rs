@@ -0,0 +1,954 @@
+//! Reading and writing pack files, the format objects are exchanged in
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::Context;
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use sha1::{Digest, Sha1};
+
+use crate::utils::advice::warning;
+use crate::utils::hex;
+use crate::utils::objects::{hash_object, read_original_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::stats::Throughput;
+
+/// The signature at the start of every pack
+const PACK_SIGNATURE: &[u8] = b"PACK";
+
+/// The only pack version written (version 3 is read as well)
+const PACK_VERSION: u32 = 2;
+
+/// The length of the SHA-1 checksum ending the pack
+const CHECKSUM_LENGTH: usize = 20;
+
+/// The signature at the start of pack indexes (from version 2 on)
+const INDEX_SIGNATURE: &[u8] = b"\xfftOc";
+
+/// The only pack index version read and written
+const INDEX_VERSION: u32 = 2;
+
+/// The bit of an offset of a pack index that makes it a position in the table of 64-bit offsets
+const LARGE_OFFSET: u32 = 0x8000_0000;
+
+/// The entry types of deltas, stored against an earlier entry (by offset) or any object (by hash)
+const TYPE_OFS_DELTA: u8 = 6;
+const TYPE_REF_DELTA: u8 = 7;
+
+/// Write a pack holding the given objects, without deltas.
+///
+/// # Arguments
+///
+/// * `repo` - The repository containing the objects
+/// * `hashes` - The hashes of the objects to store
+/// * `writer` - Where to write the pack
+pub(crate) fn write_pack<W>(
+    repo: &Repository,
+    hashes: &[String],
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let mut pack = PackBuilder::new();
+    for hash in hashes {
+        let (object_type, content) = read_original_object(repo, hash)?;
+        pack.add(object_type, &content)?;
+    }
+    writer.write_all(&pack.finish().0)?;
+    Ok(())
+}
+
+/// A pack being built in memory (without deltas), keeping track of where
+/// each object is stored so that it can be indexed
+pub(crate) struct PackBuilder {
+    pack: Vec<u8>,
+    /// The hash, offset and CRC-32 of each entry
+    entries: Vec<(String, usize, u32)>,
+}
+
+impl PackBuilder {
+    pub(crate) fn new() -> Self {
+        let mut pack = PACK_SIGNATURE.to_vec();
+        pack.extend(PACK_VERSION.to_be_bytes());
+        // The number of objects, known once they are all added
+        pack.extend(0u32.to_be_bytes());
+        PackBuilder {
+            pack,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The number of objects added so far.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Add an object to the pack.
+    ///
+    /// # Returns
+    ///
+    /// The hash of the object
+    pub(crate) fn add(
+        &mut self,
+        object_type: ObjectType,
+        content: &[u8],
+    ) -> anyhow::Result<String> {
+        let offset = self.pack.len();
+
+        // The type and size, the size continuing in 7-bit groups after the first 4 bits
+        let mut size = content.len();
+        let mut byte = (type_number(object_type) << 4) | (size & 0x0f) as u8;
+        size >>= 4;
+        while size > 0 {
+            self.pack.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        self.pack.push(byte);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content)?;
+        self.pack.extend(encoder.finish()?);
+
+        let mut crc = Crc::new();
+        crc.update(&self.pack[offset..]);
+        let hash = hash_object(&object_type, content);
+        self.entries.push((hash.clone(), offset, crc.sum()));
+        Ok(hash)
+    }
+
+    /// Finish the pack with its checksum.
+    ///
+    /// # Returns
+    ///
+    /// The content of the pack, and of its index (version 2): the number of
+    /// objects by first byte of their hashes, their sorted hashes, the CRC-32
+    /// and the offset of their entries (those beyond 31 bits in a table of
+    /// 64-bit offsets), then the checksums of the pack and of the index
+    pub(crate) fn finish(mut self) -> (Vec<u8>, Vec<u8>) {
+        let count = self.entries.len() as u32;
+        self.pack[8..12].copy_from_slice(&count.to_be_bytes());
+        let checksum = Sha1::digest(&self.pack);
+        self.pack.extend(checksum);
+
+        let mut entries = self.entries;
+        entries.sort();
+        let hashes: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|(hash, ..)| hex::decode(hash.as_bytes()).expect("hashes are hexadecimal"))
+            .collect();
+
+        let mut index = INDEX_SIGNATURE.to_vec();
+        index.extend(INDEX_VERSION.to_be_bytes());
+        for byte in 0..=255u8 {
+            let below = hashes.iter().filter(|hash| hash[0] <= byte).count() as u32;
+            index.extend(below.to_be_bytes());
+        }
+        for hash in &hashes {
+            index.extend(hash);
+        }
+        for (_, _, crc) in &entries {
+            index.extend(crc.to_be_bytes());
+        }
+        let mut large_offsets = Vec::new();
+        for (_, offset, _) in &entries {
+            match u32::try_from(*offset) {
+                Ok(offset) if offset & LARGE_OFFSET == 0 => index.extend(offset.to_be_bytes()),
+                _ => {
+                    let position = (large_offsets.len() / 8) as u32;
+                    index.extend((position | LARGE_OFFSET).to_be_bytes());
+                    large_offsets.extend((*offset as u64).to_be_bytes());
+                },
+            }
+        }
+        index.extend(large_offsets);
+        index.extend(checksum);
+        let checksum = Sha1::digest(&index);
+        index.extend(checksum);
+
+        (self.pack, index)
+    }
+
+    /// Finish the pack and store it with its index in the object directory of a
+    /// repository, as `pack-<checksum>.pack` and `.idx`.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository to store the pack in
+    /// * `promisor` - Whether to mark the pack as fetched from a promisor remote
+    ///   (with a `.promisor` file), the objects it lacks being fetched on demand
+    pub(crate) fn store(self, repo: &Repository, promisor: bool) -> anyhow::Result<()> {
+        let (pack, index) = self.finish();
+        let mut checksum = pack[pack.len() - CHECKSUM_LENGTH..].to_vec();
+        hex::encode_in_place(&mut checksum);
+        let checksum = String::from_utf8(checksum)?;
+        let dir = repo.object_dir(true)?.join("pack");
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("pack-{checksum}"));
+        // The index is written last, as packs are found by their index
+        std::fs::write(path.with_extension("pack"), pack)?;
+        if promisor {
+            std::fs::write(path.with_extension("promisor"), "")?;
+        }
+        std::fs::write(path.with_extension("idx"), index)?;
+        Ok(())
+    }
+}
+
+/// The packs of the object directories of a repository, read when first needed
+/// and looked for again when an object is not found in them, as packs may
+/// have been added since (e.g. by `fast-import`)
+#[derive(Debug, Default)]
+pub(crate) struct Packs(Mutex<Option<Vec<Arc<PackFile>>>>);
+
+// Each copy of a repository context reads the packs again
+impl Clone for Packs {
+    fn clone(&self) -> Self {
+        Packs::default()
+    }
+}
+
+impl Packs {
+    /// List the packs of object directories, opening the ones not opened yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `object_dirs` - The object directories, in the order they are searched
+    /// * `reload` - Whether to look for packs added since they were last listed
+    pub(crate) fn list(
+        &self,
+        object_dirs: &[PathBuf],
+        reload: bool,
+    ) -> anyhow::Result<Vec<Arc<PackFile>>> {
+        let mut packs = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let (Some(packs), false) = (&*packs, reload) {
+            return Ok(packs.clone());
+        }
+
+        let mut opened = packs.take().unwrap_or_default();
+        let mut listed = Vec::new();
+        for object_dir in object_dirs {
+            let mut paths = Vec::new();
+            if let Ok(entries) = std::fs::read_dir(object_dir.join("pack")) {
+                for entry in entries {
+                    let path = entry?.path();
+                    if path.extension().is_some_and(|ext| ext == "idx") {
+                        paths.push(path);
+                    }
+                }
+            }
+            paths.sort();
+
+            for path in paths {
+                if let Some(position) = opened.iter().position(|pack| pack.path == path) {
+                    listed.push(opened.swap_remove(position));
+                    continue;
+                }
+                match PackFile::open(&path) {
+                    Ok(pack) => listed.push(Arc::new(pack)),
+                    Err(err) => warning(&format!("ignoring pack {}: {err:#}", path.display())),
+                }
+            }
+        }
+        *packs = Some(listed.clone());
+        Ok(listed)
+    }
+}
+
+/// A pack of the object database, read with its index to find objects in it
+pub(crate) struct PackFile {
+    /// The path of the index
+    path: PathBuf,
+    pack: Vec<u8>,
+    index: Vec<u8>,
+    count: usize,
+}
+
+impl fmt::Debug for PackFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PackFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl PackFile {
+    /// Read a pack and its index, given the path of the index (`pack-<hash>.idx`).
+    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+        let index = std::fs::read(path).context(format!("read {}", path.display()))?;
+        let pack_path = path.with_extension("pack");
+        let pack = std::fs::read(&pack_path).context(format!("read {}", pack_path.display()))?;
+
+        if index.len() < 8 + 256 * 4 + 2 * CHECKSUM_LENGTH || !index.starts_with(INDEX_SIGNATURE) {
+            anyhow::bail!("{} is not a pack index", path.display());
+        }
+        let version = u32::from_be_bytes(index[4..8].try_into()?);
+        if version != INDEX_VERSION {
+            anyhow::bail!("unsupported pack index version {version}");
+        }
+        let count = u32::from_be_bytes(index[8 + 255 * 4..8 + 256 * 4].try_into()?) as usize;
+        if index.len() < 8 + 256 * 4 + count * 28 + 2 * CHECKSUM_LENGTH {
+            anyhow::bail!("{} is truncated", path.display());
+        }
+        if pack.len() < 12 + CHECKSUM_LENGTH || !pack.starts_with(PACK_SIGNATURE) {
+            anyhow::bail!("{} is not a pack file", pack_path.display());
+        }
+        let checksum = &index[index.len() - 2 * CHECKSUM_LENGTH..index.len() - CHECKSUM_LENGTH];
+        if pack[pack.len() - CHECKSUM_LENGTH..] != *checksum {
+            anyhow::bail!("{} does not match its index", pack_path.display());
+        }
+
+        Ok(PackFile {
+            path: path.to_path_buf(),
+            pack,
+            index,
+            count,
+        })
+    }
+
+    /// The path of the index of the pack.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The checksum ending the pack.
+    pub(crate) fn checksum(&self) -> &[u8] {
+        &self.pack[self.pack.len() - CHECKSUM_LENGTH..]
+    }
+
+    /// The hashes of the objects of the pack, in the order of the pack.
+    pub(crate) fn hashes(&self) -> anyhow::Result<Vec<String>> {
+        let hashes = 8 + 256 * 4;
+        let mut positions = Vec::with_capacity(self.count);
+        for position in 0..self.count {
+            let offset = self.offset(position).context("invalid pack index offset")?;
+            let mut hash =
+                self.index[hashes + position * 20..hashes + (position + 1) * 20].to_vec();
+            hex::encode_in_place(&mut hash);
+            positions.push((offset, String::from_utf8(hash)?));
+        }
+        positions.sort();
+        Ok(positions.into_iter().map(|(_, hash)| hash).collect())
+    }
+
+    /// Look an object up in the index.
+    ///
+    /// # Returns
+    ///
+    /// The offset of its entry in the pack, if the pack has it
+    pub(crate) fn find(&self, hash: &str) -> Option<usize> {
+        let hash = hex::decode(hash.as_bytes())
+            .ok()
+            .filter(|hash| hash.len() == 20)?;
+        let fanout = |byte: usize| -> usize {
+            let start = 8 + byte * 4;
+            u32::from_be_bytes(self.index[start..start + 4].try_into().unwrap()) as usize
+        };
+        // The objects whose hash starts with the same byte
+        let mut low = match hash[0] {
+            0 => 0,
+            byte => fanout(byte as usize - 1),
+        };
+        let mut high = fanout(hash[0] as usize);
+
+        let hashes = 8 + 256 * 4;
+        let position = loop {
+            if low >= high {
+                return None;
+            }
+            let middle = (low + high) / 2;
+            let start = hashes + middle * 20;
+            match self.index[start..start + 20].cmp(&hash) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => break middle,
+            }
+        };
+
+        self.offset(position)
+    }
+
+    /// The offset of the entry at a position of the index.
+    fn offset(&self, position: usize) -> Option<usize> {
+        let offsets = 8 + 256 * 4 + self.count * 24;
+        let start = offsets + position * 4;
+        let offset = u32::from_be_bytes(self.index[start..start + 4].try_into().unwrap());
+        if offset & LARGE_OFFSET == 0 {
+            return Some(offset as usize);
+        }
+        let start = offsets + self.count * 4 + (offset & !LARGE_OFFSET) as usize * 8;
+        let offset = self.index.get(start..start + 8)?;
+        Some(u64::from_be_bytes(offset.try_into().unwrap()) as usize)
+    }
+
+    /// Read an object of the pack.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository holding the bases of deltas missing from the pack
+    /// * `hash` - The hash of the object
+    ///
+    /// # Returns
+    ///
+    /// The type and content of the object, if the pack has it
+    pub(crate) fn read(
+        &self,
+        repo: &Repository,
+        hash: &str,
+    ) -> anyhow::Result<Option<(ObjectType, Vec<u8>)>> {
+        let Some(offset) = self.find(hash) else {
+            return Ok(None);
+        };
+        let (object_type, content, _) = self
+            .read_entry(repo, offset)
+            .context(format!("read {hash} from {}", self.path.display()))?;
+        Ok(Some((object_type, content)))
+    }
+
+    /// Read the entry at an offset, resolving its chain of deltas.
+    ///
+    /// # Returns
+    ///
+    /// The type and content of the object, and the length of the chain of
+    /// deltas it is stored as (`0` if it is not a delta)
+    fn read_entry(
+        &self,
+        repo: &Repository,
+        offset: usize,
+    ) -> anyhow::Result<(ObjectType, Vec<u8>, usize)> {
+        let content = &self.pack[..self.pack.len() - CHECKSUM_LENGTH];
+        let header = parse_entry_header(content, offset)?;
+        let (data, _) = inflate_entry(content, &header)?;
+        let (object_type, base, depth) = match &header.base {
+            None => return Ok((object_type(header.entry_type)?, data, 0)),
+            Some(DeltaBase::Offset(base)) => self.read_entry(repo, *base)?,
+            Some(DeltaBase::Hash(hash)) => match self.find(hash) {
+                Some(base) => self.read_entry(repo, base)?,
+                None => {
+                    let (object_type, base) = read_original_object(repo, hash)
+                        .context(format!("missing delta base {hash}"))?;
+                    (object_type, base, 0)
+                },
+            },
+        };
+        Ok((object_type, apply_delta(&base, &data)?, depth + 1))
+    }
+
+    /// Check the checksums of the pack and of its index, then read every
+    /// entry of the pack, checking its CRC-32 and the hash of its object.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository holding the bases of deltas missing from the pack
+    /// * `throughput` - Where each object read is counted
+    ///
+    /// # Returns
+    ///
+    /// The entries, in the order of the pack
+    pub(crate) fn verify(
+        &self,
+        repo: &Repository,
+        throughput: &mut Throughput,
+    ) -> anyhow::Result<Vec<PackEntry>> {
+        let (content, checksum) = self.pack.split_at(self.pack.len() - CHECKSUM_LENGTH);
+        if Sha1::digest(content).as_slice() != checksum {
+            anyhow::bail!("pack checksum mismatch");
+        }
+        let (index, checksum) = self.index.split_at(self.index.len() - CHECKSUM_LENGTH);
+        if Sha1::digest(index).as_slice() != checksum {
+            anyhow::bail!("index checksum mismatch");
+        }
+        let count = u32::from_be_bytes(self.pack[8..12].try_into()?) as usize;
+        if count != self.count {
+            anyhow::bail!("pack has {count} objects, its index {}", self.count);
+        }
+
+        let hashes = 8 + 256 * 4;
+        let crcs = hashes + self.count * 20;
+        let mut positions = Vec::with_capacity(self.count);
+        for position in 0..self.count {
+            let offset = self.offset(position).context("invalid pack index offset")?;
+            let mut hash =
+                self.index[hashes + position * 20..hashes + (position + 1) * 20].to_vec();
+            hex::encode_in_place(&mut hash);
+            let crc = &self.index[crcs + position * 4..crcs + (position + 1) * 4];
+            positions.push((
+                offset,
+                String::from_utf8(hash)?,
+                u32::from_be_bytes(crc.try_into()?),
+            ));
+        }
+        positions.sort();
+        let by_offset: HashMap<usize, &str> = positions
+            .iter()
+            .map(|(offset, hash, _)| (*offset, hash.as_str()))
+            .collect();
+
+        let mut entries = Vec::with_capacity(self.count);
+        for (number, (offset, hash, crc)) in positions.iter().enumerate() {
+            let end = positions
+                .get(number + 1)
+                .map_or(content.len(), |(next, ..)| *next);
+            let entry = content
+                .get(*offset..end)
+                .context("invalid pack index offset")?;
+            let mut real = Crc::new();
+            real.update(entry);
+            if real.sum() != *crc {
+                anyhow::bail!("CRC mismatch for object {hash} at offset {offset}");
+            }
+
+            let header = parse_entry_header(content, *offset)?;
+            let (object_type, object, depth) = self
+                .read_entry(repo, *offset)
+                .context(format!("read {hash} at offset {offset}"))?;
+            if hash_object(&object_type, &object) != *hash {
+                anyhow::bail!("object {hash} at offset {offset} does not match its hash");
+            }
+            let base = match header.base {
+                None => None,
+                Some(DeltaBase::Offset(base)) => Some(
+                    by_offset
+                        .get(&base)
+                        .context(format!("invalid delta offset for {hash}"))?
+                        .to_string(),
+                ),
+                Some(DeltaBase::Hash(base)) => Some(base),
+            };
+            throughput.add(object.len());
+
+            entries.push(PackEntry {
+                hash: hash.clone(),
+                object_type,
+                size: header.size,
+                packed_size: entry.len(),
+                offset: *offset,
+                delta: base.map(|base| (depth, base)),
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// An entry of a pack, as listed by [`PackFile::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PackEntry {
+    pub(crate) hash: String,
+    /// The type of the object, even if it is stored as a delta
+    pub(crate) object_type: ObjectType,
+    /// The size of the object, or of the delta
+    pub(crate) size: usize,
+    /// The size of the entry in the pack, compressed
+    pub(crate) packed_size: usize,
+    pub(crate) offset: usize,
+    /// The length of the chain of deltas and the hash of the base, for deltas
+    pub(crate) delta: Option<(usize, String)>,
+}
+
+/// Read a pack from a stream, stopping at its end rather than at the end of
+/// the stream, which stays open when the pack is sent over a connection
+/// (e.g. a push, whose client then waits for the outcome).
+///
+/// # Returns
+///
+/// The content of the pack, including its checksum
+pub(crate) fn read_pack_from<R>(input: &mut R) -> anyhow::Result<Vec<u8>>
+where
+    R: BufRead,
+{
+    let mut reader = Recorder {
+        inner: input,
+        bytes: Vec::new(),
+    };
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).context("truncated pack")?;
+    if !header.starts_with(PACK_SIGNATURE) {
+        anyhow::bail!("not a pack file");
+    }
+    let count = u32::from_be_bytes(header[8..12].try_into()?);
+
+    let mut byte = [0; 1];
+    for _ in 0..count {
+        // The type and size, then the base of deltas
+        reader.read_exact(&mut byte).context("truncated pack")?;
+        let entry_type = (byte[0] >> 4) & 0x07;
+        while byte[0] & 0x80 != 0 {
+            reader.read_exact(&mut byte).context("truncated pack")?;
+        }
+        match entry_type {
+            TYPE_OFS_DELTA => loop {
+                reader.read_exact(&mut byte).context("truncated pack")?;
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+            },
+            TYPE_REF_DELTA => reader.read_exact(&mut [0; 20]).context("truncated pack")?,
+            _ => {},
+        }
+        // The decoder only consumes the compressed data
+        std::io::copy(&mut ZlibDecoder::new(&mut reader), &mut std::io::sink())
+            .context("decompress pack entry")?;
+    }
+    reader
+        .read_exact(&mut [0; CHECKSUM_LENGTH])
+        .context("truncated pack")?;
+    Ok(reader.bytes)
+}
+
+/// A reader keeping a copy of the bytes consumed from another one
+struct Recorder<'a, R> {
+    inner: &'a mut R,
+    bytes: Vec<u8>,
+}
+
+impl<R: BufRead> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+        Ok(length)
+    }
+}
+
+impl<R: BufRead> BufRead for Recorder<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Ok(available) = self.inner.fill_buf() {
+            self.bytes.extend_from_slice(&available[..amount]);
+        }
+        self.inner.consume(amount);
+    }
+}
+
+/// Read the objects of a pack, resolving the deltas.
+///
+/// # Arguments
+///
+/// * `repo` - The repository holding the bases of deltas missing
+///   from the pack (as in the thin packs of bundles)
+/// * `pack` - The content of the pack, including its checksum
+///
+/// # Returns
+///
+/// The type and content of each object, in the order of the pack
+pub(crate) fn read_pack(
+    repo: &Repository,
+    pack: &[u8],
+) -> anyhow::Result<Vec<(ObjectType, Vec<u8>)>> {
+    if pack.len() < 12 + CHECKSUM_LENGTH || !pack.starts_with(PACK_SIGNATURE) {
+        anyhow::bail!("not a pack file");
+    }
+    let version = u32::from_be_bytes(pack[4..8].try_into()?);
+    if version != 2 && version != 3 {
+        anyhow::bail!("unsupported pack version {version}");
+    }
+    let (content, checksum) = pack.split_at(pack.len() - CHECKSUM_LENGTH);
+    if Sha1::digest(content).as_slice() != checksum {
+        anyhow::bail!("pack checksum mismatch");
+    }
+
+    let count = u32::from_be_bytes(pack[8..12].try_into()?) as usize;
+    let mut objects: Vec<(ObjectType, Vec<u8>)> = Vec::with_capacity(count);
+    // The position of each entry in `objects`, by offset and by hash
+    let mut by_offset: HashMap<usize, usize> = HashMap::new();
+    let mut by_hash: HashMap<String, usize> = HashMap::new();
+    let mut position = 12;
+
+    for _ in 0..count {
+        let start = position;
+        let header = parse_entry_header(content, start)?;
+        let base = match &header.base {
+            Some(DeltaBase::Offset(offset)) => {
+                let index = by_offset.get(offset).context("invalid delta offset")?;
+                Some(objects[*index].clone())
+            },
+            Some(DeltaBase::Hash(hash)) => match by_hash.get(hash) {
+                Some(&index) => Some(objects[index].clone()),
+                None => Some(
+                    read_original_object(repo, hash)
+                        .context(format!("missing delta base {hash}"))?,
+                ),
+            },
+            None => None,
+        };
+        let (data, end) = inflate_entry(content, &header)?;
+        position = end;
+
+        let object = match base {
+            Some((object_type, base)) => (object_type, apply_delta(&base, &data)?),
+            None => (object_type(header.entry_type)?, data),
+        };
+
+        by_offset.insert(start, objects.len());
+        by_hash.insert(hash_object(&object.0, &object.1), objects.len());
+        objects.push(object);
+    }
+
+    if position != content.len() {
+        anyhow::bail!("garbage at the end of the pack");
+    }
+    Ok(objects)
+}
+
+/// The header of a pack entry
+struct EntryHeader {
+    entry_type: u8,
+    /// The size of the object, or of the delta
+    size: usize,
+    /// The base of a delta
+    base: Option<DeltaBase>,
+    /// The position of the compressed data
+    data: usize,
+}
+
+/// Where the base of a delta is found
+enum DeltaBase {
+    /// An earlier entry of the pack, by offset
+    Offset(usize),
+    /// Any object, by hash
+    Hash(String),
+}
+
+/// Parse the header of the pack entry at an offset: its type and size, the
+/// size continuing in 7-bit groups after the first 4 bits, then the base of deltas.
+fn parse_entry_header(content: &[u8], start: usize) -> anyhow::Result<EntryHeader> {
+    let mut position = start;
+    let mut byte = *content.get(position).context("truncated pack")?;
+    position += 1;
+    let entry_type = (byte >> 4) & 0x07;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = *content.get(position).context("truncated pack")?;
+        position += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let base = match entry_type {
+        TYPE_OFS_DELTA => {
+            // The distance back to the base, each continuation byte adding one
+            let mut byte = *content.get(position).context("truncated pack")?;
+            position += 1;
+            let mut distance = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = *content.get(position).context("truncated pack")?;
+                position += 1;
+                distance = ((distance + 1) << 7) | (byte & 0x7f) as usize;
+            }
+            let offset = start
+                .checked_sub(distance)
+                .context("invalid delta offset")?;
+            Some(DeltaBase::Offset(offset))
+        },
+        TYPE_REF_DELTA => {
+            let hash = content
+                .get(position..position + 20)
+                .context("truncated pack")?;
+            position += 20;
+            let mut hash = hash.to_vec();
+            hex::encode_in_place(&mut hash);
+            Some(DeltaBase::Hash(String::from_utf8(hash)?))
+        },
+        _ => None,
+    };
+
+    Ok(EntryHeader {
+        entry_type,
+        size,
+        base,
+        data: position,
+    })
+}
+
+/// Decompress the data of a pack entry.
+///
+/// # Returns
+///
+/// The data, and the position of the entry following it
+fn inflate_entry(content: &[u8], header: &EntryHeader) -> anyhow::Result<(Vec<u8>, usize)> {
+    let compressed = content.get(header.data..).context("truncated pack")?;
+    let mut decoder = ZlibDecoder::new(compressed);
+    let mut data = Vec::with_capacity(header.size);
+    decoder
+        .read_to_end(&mut data)
+        .context("decompress pack entry")?;
+    if data.len() != header.size {
+        anyhow::bail!("pack entry size does not match its header");
+    }
+    Ok((data, header.data + decoder.total_in() as usize))
+}
+
+/// Rebuild an object from its base and a delta: the sizes of the base and the
+/// result, followed by instructions copying ranges of the base or inserting new data.
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut position = 0;
+    let mut read_size = || -> anyhow::Result<usize> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(position).context("truncated delta")?;
+            position += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+
+    if read_size()? != base.len() {
+        anyhow::bail!("delta base size mismatch");
+    }
+    let size = read_size()?;
+    let mut result = Vec::with_capacity(size);
+
+    while let Some(&instruction) = delta.get(position) {
+        position += 1;
+        if instruction & 0x80 != 0 {
+            // The bits select which bytes of the offset (4) and size (3) follow
+            let mut values = [0usize; 2];
+            for bit in 0..7 {
+                if instruction & (1 << bit) != 0 {
+                    let byte = *delta.get(position).context("truncated delta")?;
+                    position += 1;
+                    let (value, shift) = if bit < 4 { (0, bit) } else { (1, bit - 4) };
+                    values[value] |= (byte as usize) << (8 * shift);
+                }
+            }
+            let [offset, length] = values;
+            let length = if length == 0 { 0x10000 } else { length };
+            let copied = base
+                .get(offset..offset + length)
+                .context("delta copies outside of its base")?;
+            result.extend(copied);
+        } else if instruction != 0 {
+            let length = instruction as usize;
+            let inserted = delta
+                .get(position..position + length)
+                .context("truncated delta")?;
+            result.extend(inserted);
+            position += length;
+        } else {
+            anyhow::bail!("invalid delta instruction");
+        }
+    }
+
+    if result.len() != size {
+        anyhow::bail!("delta result size mismatch");
+    }
+    Ok(result)
+}
+
+/// The number of an object type in pack entry headers.
+fn type_number(object_type: ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Commit => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+        ObjectType::Tag => 4,
+    }
+}
+
+fn object_type(number: u8) -> anyhow::Result<ObjectType> {
+    match number {
+        1 => Ok(ObjectType::Commit),
+        2 => Ok(ObjectType::Tree),
+        3 => Ok(ObjectType::Blob),
+        4 => Ok(ObjectType::Tag),
+        _ => anyhow::bail!("invalid pack entry type {number}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::BufReader;
+
+    use super::*;
+    use crate::utils::objects::{object_exists, read_object, write_object};
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn reads_the_packs_it_writes() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+
+        let large = "line\n".repeat(100);
+        let hashes = vec![
+            write_object(&repo, ObjectType::Blob, b"small").unwrap(),
+            write_object(&repo, ObjectType::Blob, large.as_bytes()).unwrap(),
+        ];
+        let mut pack = Vec::new();
+        write_pack(&repo, &hashes, &mut pack).unwrap();
+
+        assert_eq!(read_pack(&repo, &pack).unwrap(), [
+            (ObjectType::Blob, b"small".to_vec()),
+            (ObjectType::Blob, large.into_bytes()),
+        ]);
+
+        // A pack is read from a stream up to its end only
+        let mut stream = [&pack[..], b"more"].concat();
+        let mut reader = BufReader::with_capacity(7, stream.as_slice());
+        assert_eq!(read_pack_from(&mut reader).unwrap(), pack);
+        assert_eq!(reader.fill_buf().unwrap(), b"more");
+        stream.truncate(pack.len() - 1);
+        assert!(read_pack_from(&mut stream.as_slice()).is_err());
+
+        pack[20] ^= 1;
+        let err = read_pack(&repo, &pack).unwrap_err();
+        assert_eq!(err.to_string(), "pack checksum mismatch");
+    }
+
+    #[test]
+    fn finds_objects_of_indexed_packs() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects/pack")).unwrap();
+        let repo = temp.repo();
+        let write = |name: &str, objects: &[&[u8]]| {
+            let mut pack = PackBuilder::new();
+            let hashes: Vec<_> = objects
+                .iter()
+                .map(|content| pack.add(ObjectType::Blob, content).unwrap())
+                .collect();
+            let (pack, index) = pack.finish();
+            fs::write(temp.join(format!(".git/objects/pack/{name}.pack")), pack).unwrap();
+            fs::write(temp.join(format!(".git/objects/pack/{name}.idx")), index).unwrap();
+            hashes
+        };
+
+        let first = write("pack-a", &[b"one\n", b"two\n"]);
+        assert_eq!(
+            read_object(&repo, &first[1]).unwrap(),
+            (ObjectType::Blob, b"two\n".to_vec())
+        );
+        assert!(!object_exists(&repo, &hash_object(&ObjectType::Blob, b"three\n")).unwrap());
+
+        // Packs added since the packs were listed are found as well
+        let second = write("pack-b", &[b"three\n"]);
+        assert!(object_exists(&repo, &second[0]).unwrap());
+        assert_eq!(read_object(&repo, &second[0]).unwrap().1, b"three\n");
+        assert!(read_object(&repo, &"0".repeat(40)).is_err());
+    }
+
+    #[test]
+    fn applies_deltas() {
+        let base = b"hello world, hello git";
+        // Sizes 22 and 17, copy 6 bytes at offset 0, insert "there", copy 6 at 13
+        let delta = b"\x16\x11\x90\x06\x05there\x91\x0d\x06";
+        assert_eq!(apply_delta(base, delta).unwrap(), b"hello therehello ");
+        assert!(apply_delta(b"short", delta).is_err());
+    }
+}
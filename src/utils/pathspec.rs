@@ -0,0 +1,186 @@
+//! Matching paths against the pathspecs given on the command line
+
+use std::path::{Component, Path};
+
+/// A set of paths (files or directories) to limit a command to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pathspec {
+    /// The path of the current directory relative to the working tree
+    prefix: String,
+    /// The normalized paths relative to the root of the working tree
+    paths: Vec<String>,
+}
+
+impl Pathspec {
+    /// Create a pathspec from paths given relative to the current directory.
+    /// Without any paths, everything below the current directory is matched.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The path of the current directory relative to the working tree
+    /// * `paths` - The paths given on the command line
+    pub(crate) fn new<P>(prefix: &str, paths: &[P]) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let paths = if paths.is_empty() {
+            vec![prefix.to_string()]
+        } else {
+            paths
+                .iter()
+                .map(|path| normalize(prefix, path.as_ref()))
+                .collect::<anyhow::Result<_>>()?
+        };
+
+        Ok(Pathspec {
+            prefix: prefix.to_string(),
+            paths,
+        })
+    }
+
+    /// Create a pathspec matching the whole working tree,
+    /// with paths still displayed relative to the current directory.
+    pub(crate) fn all(prefix: &str) -> Self {
+        Pathspec {
+            prefix: prefix.to_string(),
+            paths: vec![String::new()],
+        }
+    }
+
+    /// Whether a path (relative to the root of the working tree) is matched.
+    pub(crate) fn matches<P>(&self, path: P) -> bool
+    where
+        P: AsRef<[u8]>,
+    {
+        self.paths.iter().any(|spec| is_within(&path, spec))
+    }
+
+    /// Whether a directory (relative to the root of the working tree)
+    /// is matched or contains paths that may be matched.
+    pub(crate) fn matches_within<P>(&self, dir: P) -> bool
+    where
+        P: AsRef<[u8]>,
+    {
+        self.paths
+            .iter()
+            .any(|spec| is_within(&dir, spec) || is_within(spec, &dir))
+    }
+
+    /// Format a path (relative to the root of the working tree)
+    /// relative to the current directory, for messages.
+    pub(crate) fn display<P>(&self, path: P) -> String
+    where
+        P: AsRef<[u8]>,
+    {
+        String::from_utf8_lossy(&self.relative(path)).into_owned()
+    }
+
+    /// Express a path (relative to the root of the working tree) relative to
+    /// the current directory, keeping its bytes as they are.
+    pub(crate) fn relative<P>(&self, path: P) -> Vec<u8>
+    where
+        P: AsRef<[u8]>,
+    {
+        relative_to(path.as_ref(), self.prefix.as_bytes())
+    }
+}
+
+/// Normalize a path given relative to the current directory into a
+/// `/`-separated path relative to the root of the working tree.
+pub(crate) fn normalize(prefix: &str, path: &Path) -> anyhow::Result<String> {
+    let mut components: Vec<String> = prefix
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                if components.pop().is_none() {
+                    anyhow::bail!("{}: is outside repository", path.display());
+                }
+            },
+            Component::Normal(name) => components.push(name.to_string_lossy().into_owned()),
+            Component::RootDir | Component::Prefix(_) => {
+                anyhow::bail!("{}: is outside repository", path.display())
+            },
+        }
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Whether a path is the given directory (or file), or inside of it.
+/// The empty path is the root of the working tree.
+pub(crate) fn is_within<P, D>(path: P, dir: D) -> bool
+where
+    P: AsRef<[u8]>,
+    D: AsRef<[u8]>,
+{
+    let (path, dir) = (path.as_ref(), dir.as_ref());
+    dir.is_empty() || path == dir || (path.starts_with(dir) && path.get(dir.len()) == Some(&b'/'))
+}
+
+/// Express a path relative to a directory (both relative to the root of the working tree).
+pub(crate) fn relative_to(path: &[u8], dir: &[u8]) -> Vec<u8> {
+    let path_components: Vec<_> = path.split(|&byte| byte == b'/').collect();
+    let dir_components: Vec<_> = dir
+        .split(|&byte| byte == b'/')
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    let common = path_components
+        .iter()
+        .zip(&dir_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative: Vec<&[u8]> = vec![b".."; dir_components.len() - common];
+    relative.extend(&path_components[common..]);
+    relative.join(&b'/')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{normalize, relative_to, Pathspec};
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize("", Path::new("a/./b")).unwrap(), "a/b");
+        assert_eq!(normalize("dir", Path::new("../a")).unwrap(), "a");
+        assert_eq!(normalize("dir", Path::new(".")).unwrap(), "dir");
+        assert!(normalize("", Path::new("../a")).is_err());
+        assert!(normalize("", Path::new("/etc")).is_err());
+    }
+
+    #[test]
+    fn matches_paths() {
+        let spec = Pathspec::new("", &["src", "README.md"]).unwrap();
+        assert!(spec.matches("src/main.rs"));
+        assert!(spec.matches("README.md"));
+        assert!(!spec.matches("src.txt"));
+        assert!(spec.matches_within("src/utils"));
+        assert!(!spec.matches_within("tests"));
+
+        let spec = Pathspec::new::<&str>("src", &[]).unwrap();
+        assert!(spec.matches("src/main.rs"));
+        assert!(!spec.matches("README.md"));
+        assert!(spec.matches_within(""));
+
+        let spec = Pathspec::all("src");
+        assert!(spec.matches("README.md"));
+        assert_eq!(spec.display("README.md"), "../README.md");
+    }
+
+    #[test]
+    fn displays_relative_paths() {
+        assert_eq!(relative_to(b"src/main.rs", b""), b"src/main.rs");
+        assert_eq!(relative_to(b"src/main.rs", b"src"), b"main.rs");
+        assert_eq!(relative_to(b"README.md", b"src/utils"), b"../../README.md");
+        assert_eq!(relative_to(b"caf\xe9", b"src"), b"../caf\xe9");
+    }
+}
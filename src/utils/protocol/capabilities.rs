@@ -0,0 +1,513 @@
+//! Capability negotiation for the pack protocol
+//!
+//! Capabilities are advertised by the server after the first ref
+//! (separated by a null byte) and requested by the client on its first
+//! `want` line. Both sides parse and format them through [`Capabilities`].
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::utils::config::Config;
+use crate::utils::env::{self, Environment};
+use crate::utils::repository::Repository;
+
+/// A capability that is either present or absent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Flag {
+    MultiAck,
+    MultiAckDetailed,
+    NoDone,
+    ThinPack,
+    SideBand,
+    SideBand64k,
+    OfsDelta,
+    Shallow,
+    DeepenSince,
+    DeepenNot,
+    DeepenRelative,
+    NoProgress,
+    IncludeTag,
+    ReportStatus,
+    DeleteRefs,
+    Quiet,
+    Atomic,
+    PushOptions,
+    AllowTipSha1InWant,
+    AllowReachableSha1InWant,
+    Filter,
+}
+
+impl Flag {
+    /// Every flag, in the order they are advertised
+    pub(crate) const ALL: &'static [Flag] = &[
+        Flag::MultiAck,
+        Flag::MultiAckDetailed,
+        Flag::NoDone,
+        Flag::ThinPack,
+        Flag::SideBand,
+        Flag::SideBand64k,
+        Flag::OfsDelta,
+        Flag::Shallow,
+        Flag::DeepenSince,
+        Flag::DeepenNot,
+        Flag::DeepenRelative,
+        Flag::NoProgress,
+        Flag::IncludeTag,
+        Flag::ReportStatus,
+        Flag::DeleteRefs,
+        Flag::Quiet,
+        Flag::Atomic,
+        Flag::PushOptions,
+        Flag::AllowTipSha1InWant,
+        Flag::AllowReachableSha1InWant,
+        Flag::Filter,
+    ];
+
+    /// The name of the flag on the wire
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Flag::MultiAck => "multi_ack",
+            Flag::MultiAckDetailed => "multi_ack_detailed",
+            Flag::NoDone => "no-done",
+            Flag::ThinPack => "thin-pack",
+            Flag::SideBand => "side-band",
+            Flag::SideBand64k => "side-band-64k",
+            Flag::OfsDelta => "ofs-delta",
+            Flag::Shallow => "shallow",
+            Flag::DeepenSince => "deepen-since",
+            Flag::DeepenNot => "deepen-not",
+            Flag::DeepenRelative => "deepen-relative",
+            Flag::NoProgress => "no-progress",
+            Flag::IncludeTag => "include-tag",
+            Flag::ReportStatus => "report-status",
+            Flag::DeleteRefs => "delete-refs",
+            Flag::Quiet => "quiet",
+            Flag::Atomic => "atomic",
+            Flag::PushOptions => "push-options",
+            Flag::AllowTipSha1InWant => "allow-tip-sha1-in-want",
+            Flag::AllowReachableSha1InWant => "allow-reachable-sha1-in-want",
+            Flag::Filter => "filter",
+        }
+    }
+
+    /// Look up a flag by its name on the wire
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Flag::ALL.iter().copied().find(|flag| flag.name() == name)
+    }
+}
+
+/// The hash algorithm used by a repository (`object-format=<algo>`)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectFormat::Sha1 => write!(f, "sha1"),
+            ObjectFormat::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl TryFrom<&str> for ObjectFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => anyhow::bail!("unknown object format: {}", value),
+        }
+    }
+}
+
+/// A set of protocol capabilities, as advertised by a server
+/// or requested by a client
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Capabilities {
+    /// Capabilities without a value
+    flags: BTreeSet<Flag>,
+    /// The implementation name and version (`agent=<agent>`)
+    pub(crate) agent: Option<String>,
+    /// The hash algorithm of the repository (`object-format=<algo>`)
+    pub(crate) object_format: Option<ObjectFormat>,
+    /// An identifier used to correlate client and server traces (`session-id=<id>`)
+    pub(crate) session_id: Option<String>,
+    /// Symbolic refs and their targets (`symref=<ref>:<target>`)
+    pub(crate) symrefs: Vec<(String, String)>,
+    /// Capabilities this implementation does not understand, kept verbatim
+    unknown: Vec<String>,
+}
+
+impl Capabilities {
+    /// Parse a space separated capability list.
+    ///
+    /// Unknown capabilities are kept so they can be inspected, but are
+    /// never advertised or requested back.
+    pub(crate) fn parse(list: &str) -> anyhow::Result<Self> {
+        let mut capabilities = Capabilities::default();
+
+        for capability in list.split_ascii_whitespace() {
+            let (name, value) = match capability.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (capability, None),
+            };
+
+            match (name, value) {
+                ("agent", Some(agent)) => capabilities.agent = Some(agent.to_string()),
+                ("object-format", Some(format)) => {
+                    capabilities.object_format = Some(ObjectFormat::try_from(format)?)
+                },
+                ("session-id", Some(id)) => capabilities.session_id = Some(id.to_string()),
+                ("symref", Some(symref)) => {
+                    let Some((name, target)) = symref.split_once(':') else {
+                        anyhow::bail!("invalid symref capability: {}", symref);
+                    };
+                    capabilities
+                        .symrefs
+                        .push((name.to_string(), target.to_string()));
+                },
+                ("agent" | "object-format" | "session-id" | "symref", None) => {
+                    anyhow::bail!("capability {} requires a value", name)
+                },
+                _ => match Flag::from_name(name) {
+                    Some(flag) if value.is_none() => {
+                        capabilities.flags.insert(flag);
+                    },
+                    _ => capabilities.unknown.push(capability.to_string()),
+                },
+            }
+        }
+
+        Ok(capabilities)
+    }
+
+    /// The agent string sent by this implementation (`$GIT_USER_AGENT` if set)
+    pub(crate) fn default_agent(env: &Environment) -> String {
+        match env.var(env::GIT_USER_AGENT) {
+            Some(agent) => agent.to_string(),
+            None => format!("git/{}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    /// The session id of this process, shared by every connection it makes
+    /// (`<seconds>.<microseconds>-P<pid>`)
+    pub(crate) fn session_id() -> &'static str {
+        static SESSION_ID: OnceLock<String> = OnceLock::new();
+        SESSION_ID.get_or_init(|| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            format!(
+                "{}.{:06}-P{:08x}",
+                now.as_secs(),
+                now.subsec_micros(),
+                std::process::id()
+            )
+        })
+    }
+
+    /// Add the session id of this process if `transfer.advertiseSID` is set:
+    /// servers advertise it, and clients send it back to the servers that
+    /// advertised theirs.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository the settings are read from
+    /// * `advertised` - The advertisement of the server, for clients
+    pub(crate) fn with_session_id(
+        mut self,
+        repo: &Repository,
+        advertised: Option<&Capabilities>,
+    ) -> anyhow::Result<Self> {
+        let enabled = Config::load(repo)?
+            .get_bool("transfer.advertiseSID")?
+            .unwrap_or(false);
+        if enabled && advertised.is_none_or(|advertised| advertised.session_id.is_some()) {
+            self.session_id = Some(Capabilities::session_id().to_string());
+        }
+        Ok(self)
+    }
+
+    /// Check whether a flag is present
+    pub(crate) fn has(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Add a flag to the set
+    pub(crate) fn insert(&mut self, flag: Flag) {
+        self.flags.insert(flag);
+    }
+
+    /// Remove a flag from the set
+    pub(crate) fn remove(&mut self, flag: Flag) {
+        self.flags.remove(&flag);
+    }
+
+    /// Add a flag to the set, returning the updated set
+    #[cfg(test)]
+    pub(crate) fn with(mut self, flag: Flag) -> Self {
+        self.insert(flag);
+        self
+    }
+
+    /// The capabilities that were not recognized while parsing
+    #[cfg(test)]
+    pub(crate) fn unknown(&self) -> &[String] {
+        &self.unknown
+    }
+
+    /// Choose the capabilities a client should request from this advertisement.
+    ///
+    /// Only the `preferred` flags the server advertised are kept. Mutually
+    /// exclusive flags are resolved in favor of the more capable variant
+    /// (`side-band-64k` over `side-band`, `multi_ack_detailed` over `multi_ack`).
+    ///
+    /// # Arguments
+    ///
+    /// * `preferred` - The flags the client supports
+    /// * `agent` - The agent string of the client
+    pub(crate) fn select(&self, preferred: &[Flag], agent: &str) -> Capabilities {
+        let mut selected = Capabilities {
+            agent: Some(agent.to_string()),
+            object_format: self.object_format,
+            session_id: None,
+            ..Default::default()
+        };
+
+        for &flag in preferred {
+            if self.has(flag) {
+                selected.insert(flag);
+            }
+        }
+
+        if selected.has(Flag::SideBand64k) {
+            selected.remove(Flag::SideBand);
+        }
+        if selected.has(Flag::MultiAckDetailed) {
+            selected.remove(Flag::MultiAck);
+        }
+
+        selected
+    }
+
+    /// Validate the capabilities requested by a client against this advertisement.
+    ///
+    /// Clients may only request flags that were advertised and must agree
+    /// on the object format.
+    pub(crate) fn validate_request(&self, requested: &Capabilities) -> anyhow::Result<()> {
+        if let Some(flag) = requested.flags.iter().find(|&&flag| !self.has(flag)) {
+            anyhow::bail!("client requested unadvertised capability: {}", flag.name());
+        }
+
+        if requested.has(Flag::SideBand) && requested.has(Flag::SideBand64k) {
+            anyhow::bail!("side-band and side-band-64k are mutually exclusive");
+        }
+
+        let ours = self.object_format.unwrap_or_default();
+        let theirs = requested.object_format.unwrap_or_default();
+        if ours != theirs {
+            anyhow::bail!("mismatched object format: expected {ours}, got {theirs}");
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Capabilities {
+    /// Format the capabilities as a space separated list, ready to be
+    /// appended to the first ref advertisement or `want` line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = Vec::new();
+
+        for flag in Flag::ALL {
+            if self.has(*flag) {
+                list.push(flag.name().to_string());
+            }
+        }
+        for (name, target) in &self.symrefs {
+            list.push(format!("symref={name}:{target}"));
+        }
+        if let Some(format) = &self.object_format {
+            list.push(format!("object-format={format}"));
+        }
+        if let Some(agent) = &self.agent {
+            list.push(format!("agent={}", sanitize(agent)));
+        }
+        if let Some(id) = &self.session_id {
+            list.push(format!("session-id={id}"));
+        }
+
+        write!(f, "{}", list.join(" "))
+    }
+}
+
+/// Replace the characters that cannot be sent in a capability value (spaces
+/// and control characters) with dots.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '.' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Capabilities, Flag, ObjectFormat};
+    use crate::utils::env;
+    use crate::utils::test::TempRepo;
+
+    const ADVERTISEMENT: &str = "multi_ack thin-pack side-band side-band-64k ofs-delta shallow \
+                                 no-progress include-tag multi_ack_detailed \
+                                 symref=HEAD:refs/heads/main object-format=sha1 \
+                                 agent=git/2.43.0 session-id=abc123 bogus-cap";
+
+    #[test]
+    fn parses_advertisement() {
+        let capabilities = Capabilities::parse(ADVERTISEMENT).unwrap();
+
+        assert!(capabilities.has(Flag::OfsDelta));
+        assert!(capabilities.has(Flag::SideBand64k));
+        assert!(!capabilities.has(Flag::ReportStatus));
+        assert_eq!(capabilities.agent.as_deref(), Some("git/2.43.0"));
+        assert_eq!(capabilities.object_format, Some(ObjectFormat::Sha1));
+        assert_eq!(capabilities.session_id.as_deref(), Some("abc123"));
+        assert_eq!(capabilities.symrefs, vec![(
+            "HEAD".to_string(),
+            "refs/heads/main".to_string()
+        )]);
+        assert_eq!(capabilities.unknown(), ["bogus-cap"]);
+    }
+
+    #[test]
+    fn formats_capabilities() {
+        let capabilities = Capabilities::default()
+            .with(Flag::SideBand64k)
+            .with(Flag::OfsDelta);
+        let capabilities = Capabilities {
+            agent: Some("git/test".to_string()),
+            ..capabilities
+        };
+
+        assert_eq!(
+            capabilities.to_string(),
+            "side-band-64k ofs-delta agent=git/test"
+        );
+    }
+
+    #[test]
+    fn round_trips_known_capabilities() {
+        let capabilities = Capabilities::parse(ADVERTISEMENT).unwrap();
+        let reparsed = Capabilities::parse(&capabilities.to_string()).unwrap();
+
+        assert_eq!(reparsed.flags, capabilities.flags);
+        assert_eq!(reparsed.symrefs, capabilities.symrefs);
+        // Unknown capabilities are never re-advertised
+        assert!(reparsed.unknown().is_empty());
+    }
+
+    #[test]
+    fn selects_supported_capabilities() {
+        let advertised = Capabilities::parse(ADVERTISEMENT).unwrap();
+        let selected = advertised.select(
+            &[
+                Flag::SideBand,
+                Flag::SideBand64k,
+                Flag::OfsDelta,
+                Flag::ReportStatus,
+                Flag::MultiAck,
+                Flag::MultiAckDetailed,
+            ],
+            "git/test",
+        );
+
+        assert_eq!(
+            selected.to_string(),
+            "multi_ack_detailed side-band-64k ofs-delta object-format=sha1 agent=git/test"
+        );
+    }
+
+    #[test]
+    fn validates_requested_capabilities() {
+        let advertised = Capabilities::parse("ofs-delta side-band-64k").unwrap();
+
+        let valid = Capabilities::parse("ofs-delta agent=client").unwrap();
+        assert!(advertised.validate_request(&valid).is_ok());
+
+        let unadvertised = Capabilities::parse("thin-pack").unwrap();
+        assert!(advertised.validate_request(&unadvertised).is_err());
+
+        let sha256 = Capabilities::parse("object-format=sha256").unwrap();
+        assert!(advertised.validate_request(&sha256).is_err());
+    }
+
+    #[test]
+    fn fails_on_missing_value() {
+        assert!(Capabilities::parse("agent").is_err());
+        assert!(Capabilities::parse("symref=HEAD").is_err());
+        assert!(Capabilities::parse("object-format=md5").is_err());
+    }
+
+    #[test]
+    fn sends_agent_and_session_id() {
+        let temp = TempRepo::new().env(env::GIT_USER_AGENT, "my agent/1.0");
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        let repo = temp.repo();
+
+        let advertised = Capabilities::parse(ADVERTISEMENT).unwrap();
+        let selected = advertised.select(&[], &Capabilities::default_agent(repo.env()));
+        assert_eq!(
+            selected
+                .clone()
+                .with_session_id(&repo, Some(&advertised))
+                .unwrap(),
+            selected
+        );
+        assert_eq!(
+            selected.to_string(),
+            "object-format=sha1 agent=my.agent/1.0"
+        );
+
+        fs::write(
+            temp.join(".git/config"),
+            "[transfer]\n\tadvertiseSID = true\n",
+        )
+        .unwrap();
+        let session_id = Capabilities::session_id();
+        let requested = selected
+            .clone()
+            .with_session_id(&repo, Some(&advertised))
+            .unwrap();
+        assert_eq!(requested.session_id.as_deref(), Some(session_id));
+        assert_eq!(
+            Capabilities::parse(&requested.to_string())
+                .unwrap()
+                .session_id
+                .as_deref(),
+            Some(session_id)
+        );
+
+        // Clients only send it to the servers that advertise theirs
+        let silent = Capabilities::parse("ofs-delta").unwrap();
+        let requested = silent
+            .select(&[Flag::OfsDelta], "git/test")
+            .with_session_id(&repo, Some(&silent))
+            .unwrap();
+        assert_eq!(requested.session_id, None);
+        let advertisement = Capabilities::default()
+            .with_session_id(&repo, None)
+            .unwrap();
+        assert_eq!(
+            advertisement.to_string(),
+            format!("session-id={session_id}")
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! The git protocol (`git://`), served by `daemon` on port 9418
+//!
+//! A client opens a TCP connection and sends a single line naming the
+//! service and the repository (`git-upload-pack /path\0host=<host>\0`), then
+//! talks the pack protocol with the service as over any other transport.
+//! Clients are anonymous, so a repository is only served if it is exported
+//! (it has a `git-daemon-export-ok` file, or `--export-all` is given) and
+//! pushing must be enabled explicitly.
+
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::utils::protocol::pkt_line::{read_line, write_line};
+use crate::utils::protocol::upload_pack::ServeOptions;
+use crate::utils::protocol::Service;
+use crate::utils::repository::Repository;
+
+/// The port the git protocol is served on by default
+pub(crate) const DEFAULT_PORT: u16 = 9418;
+
+/// The file marking a repository as exported
+const EXPORT_OK: &str = "git-daemon-export-ok";
+
+/// Which repositories are served, and how
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DaemonOptions {
+    /// The directory the paths requested are relative to (`--base-path`)
+    pub(crate) base_path: Option<PathBuf>,
+    /// Whether repositories are served without `git-daemon-export-ok`
+    pub(crate) export_all: bool,
+    /// The directories repositories must be in to be served (any if empty)
+    pub(crate) whitelist: Vec<PathBuf>,
+    /// The services enabled
+    pub(crate) services: Vec<Service>,
+}
+
+impl DaemonOptions {
+    /// Find the repository served at a requested path, trying the path
+    /// itself, then with `.git` appended (e.g. `/project` for
+    /// `/project.git`), as a working tree or a bare repository.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository context of the daemon, which relative base
+    ///   paths are resolved from
+    /// * `path` - The path requested
+    ///
+    /// # Returns
+    ///
+    /// The git directory and the working tree (`None` if bare)
+    fn resolve(&self, repo: &Repository, path: &str) -> anyhow::Result<(PathBuf, Option<PathBuf>)> {
+        let relative = path
+            .strip_prefix('/')
+            .context("user paths are not supported")?;
+        if Path::new(relative)
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            anyhow::bail!("'{path}': path outside of the served directories");
+        }
+        let path = match &self.base_path {
+            Some(base_path) => repo.cwd().join(base_path).join(relative),
+            None => PathBuf::from(path),
+        };
+
+        let mut candidates = Vec::new();
+        for dir in [
+            path.clone(),
+            PathBuf::from(format!("{}.git", path.display())),
+        ] {
+            candidates.push((dir.join(".git"), Some(dir.clone())));
+            candidates.push((dir, None));
+        }
+        let (git_dir, work_tree) = candidates
+            .into_iter()
+            .find(|(git_dir, _)| git_dir.join("HEAD").is_file() && git_dir.join("objects").is_dir())
+            .context(format!(
+                "'{}' does not appear to be a git repository",
+                path.display()
+            ))?;
+
+        let dir = work_tree.as_ref().unwrap_or(&git_dir).canonicalize()?;
+        if !self.whitelist.is_empty()
+            && !self
+                .whitelist
+                .iter()
+                .filter_map(|allowed| repo.cwd().join(allowed).canonicalize().ok())
+                .any(|allowed| dir.starts_with(allowed))
+        {
+            anyhow::bail!("'{}': not in whitelist", dir.display());
+        }
+        if !self.export_all && !git_dir.join(EXPORT_OK).exists() {
+            anyhow::bail!("'{}': repository not exported", dir.display());
+        }
+        Ok((git_dir, work_tree))
+    }
+}
+
+/// Serve the request of a connection: read the service and repository
+/// requested, then serve the repository with the service.
+///
+/// Refused requests are answered with an `ERR` line, which clients show.
+///
+/// # Arguments
+///
+/// * `repo` - The repository context of the daemon, whose directory and
+///   environment the repositories served are opened with
+/// * `options` - Which repositories are served, and how
+/// * `input` - Where the requests of the client are read from
+/// * `output` - Where the answers are written to
+pub(crate) fn serve_connection<R, W>(
+    repo: &Repository,
+    options: &DaemonOptions,
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let line = read_line(input)?.context("protocol error: expected a request")?;
+    // Extra parameters (`host=`, or a protocol version after two NULs) are ignored
+    let line = String::from_utf8_lossy(&line);
+    let request = line.split('\0').next().unwrap_or_default().trim_end();
+    let (service, path) = request
+        .split_once(' ')
+        .and_then(|(name, path)| Some((Service::parse(name)?, path)))
+        .context(format!("protocol error: unknown request '{request}'"))?;
+
+    let refuse = |output: &mut W, message: &str| -> anyhow::Result<()> {
+        write_line(output, format!("ERR {message}: {path}").as_bytes())?;
+        output.flush()?;
+        Ok(())
+    };
+    if !options.services.contains(&service) {
+        refuse(output, "service not enabled")?;
+        anyhow::bail!("'{path}': {} not enabled", service.name());
+    }
+    let (git_dir, work_tree) = match options.resolve(repo, path) {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            refuse(output, "access denied or repository not exported")?;
+            return Err(err);
+        },
+    };
+
+    let repo = repo.open(git_dir, work_tree);
+    service.serve(&repo, ServeOptions::default(), input, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::objects::{read_object, write_object, ObjectType};
+    use crate::utils::protocol::transport::Connection;
+    use crate::utils::refs::write_ref;
+    use crate::utils::test::TempRepo;
+
+    /// Serve a single connection from a thread, returning the URL of the
+    /// repository at `path`.
+    fn serve_once(repo: &Repository, options: DaemonOptions, path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("git://{}{path}", listener.local_addr().unwrap());
+        let repo = repo.clone();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = serve_connection(&repo, &options, &mut &stream, &mut &stream);
+        });
+        url
+    }
+
+    #[test]
+    fn serves_exported_repositories() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b");
+        fs::create_dir_all(temp.join("repos/project/.git/refs/heads")).unwrap();
+        fs::create_dir_all(temp.join("clone/objects")).unwrap();
+        fs::write(
+            temp.join("repos/project/.git/HEAD"),
+            "ref: refs/heads/main\n",
+        )
+        .unwrap();
+        let source = temp.repo_at("repos/project");
+        let tree = write_object(&source, ObjectType::Tree, b"").unwrap();
+        let commit = create_commit(&source, &tree, Vec::new(), "root\n").unwrap();
+        write_ref(&source, "refs/heads/main", &commit).unwrap();
+        let clone = temp.repo().open(temp.join("clone"), None);
+
+        let options = DaemonOptions {
+            base_path: Some(temp.join("repos")),
+            services: vec![Service::UploadPack],
+            ..DaemonOptions::default()
+        };
+        let url = serve_once(&clone, options.clone(), "/project");
+        assert_eq!(
+            Connection::connect(&url).err().unwrap().to_string(),
+            "remote error: access denied or repository not exported: /project"
+        );
+
+        fs::write(temp.join("repos/project/.git/git-daemon-export-ok"), "").unwrap();
+        let connection =
+            Connection::connect(&serve_once(&clone, options.clone(), "/project")).unwrap();
+        assert_eq!(connection.head_symref(), Some("refs/heads/main"));
+        assert_eq!(
+            connection
+                .fetch(&clone, std::slice::from_ref(&commit), None, None)
+                .unwrap(),
+            2
+        );
+        assert!(read_object(&clone, &commit).is_ok());
+
+        // Paths may not leave the base path, and pushing must be enabled
+        let url = serve_once(&clone, options.clone(), "/../repos/project");
+        assert!(Connection::connect(&url).is_err());
+        let mut stream = std::net::TcpStream::connect(
+            serve_once(&clone, options, "").trim_start_matches("git://"),
+        )
+        .unwrap();
+        write_line(&mut stream, b"git-receive-pack /project\0host=x\0").unwrap();
+        let mut answer = Vec::new();
+        stream.read_to_end(&mut answer).unwrap();
+        assert_eq!(answer, b"0025ERR service not enabled: /project");
+    }
+}
@@ -0,0 +1,248 @@
+//! Object filters of partial clones (`--filter=<spec>`)
+//!
+//! A client asks the server to leave some objects out of the pack with a
+//! `filter <spec>` line (once the server advertised the `filter` capability),
+//! and fetches the objects it lacks later, from the remote it records as a
+//! promisor. The server decides which objects to send with [`Filter::includes`].
+
+use std::fmt;
+
+use crate::utils::objects::ObjectType;
+use crate::utils::protocol::capabilities::{Capabilities, Flag};
+
+/// A filter leaving objects out of a pack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Filter {
+    /// Leave every blob out (`blob:none`)
+    BlobNone,
+    /// Leave the blobs of at least this many bytes out (`blob:limit=<n>[kmg]`)
+    BlobLimit(u64),
+    /// Leave the trees and blobs at this depth or deeper out, the root trees
+    /// being at depth 0 (`tree:<depth>`)
+    TreeDepth(u64),
+    /// Only send the objects of a type (`object:type=<type>`)
+    ObjectType(ObjectType),
+    /// Leave out the objects any of the filters leaves out (`combine:<spec>+<spec>...`)
+    Combine(Vec<Filter>),
+}
+
+impl Filter {
+    /// Parse a filter spec, as given to `--filter` or sent on a `filter` line.
+    pub(crate) fn parse(spec: &str) -> anyhow::Result<Self> {
+        if spec == "blob:none" {
+            return Ok(Filter::BlobNone);
+        }
+        if let Some(limit) = spec.strip_prefix("blob:limit=") {
+            return match parse_size(limit) {
+                Some(limit) => Ok(Filter::BlobLimit(limit)),
+                None => anyhow::bail!("invalid filter-spec '{spec}'"),
+            };
+        }
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            return match depth.parse() {
+                Ok(depth) => Ok(Filter::TreeDepth(depth)),
+                Err(_) => anyhow::bail!("expected 'tree:<depth>'"),
+            };
+        }
+        if let Some(name) = spec.strip_prefix("object:type=") {
+            return match ObjectType::try_from(name.as_bytes()) {
+                Ok(object_type) => Ok(Filter::ObjectType(object_type)),
+                Err(_) => {
+                    anyhow::bail!("'{name}' for 'object:type=<type>' is not a valid object type")
+                },
+            };
+        }
+        if let Some(specs) = spec.strip_prefix("combine:") {
+            let filters = specs
+                .split('+')
+                .map(|spec| Filter::parse(&decode_spec(spec)?))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            if filters.len() < 2 {
+                anyhow::bail!("expected something after combine:");
+            }
+            return Ok(Filter::Combine(filters));
+        }
+        anyhow::bail!("invalid filter-spec '{spec}'")
+    }
+
+    /// Whether the server can filter the objects it sends, a filter being
+    /// ignored (with a warning) by clients otherwise.
+    pub(crate) fn supported_by(advertised: &Capabilities) -> bool {
+        advertised.has(Flag::Filter)
+    }
+
+    /// Whether an object is sent by the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `object_type` - The type of the object
+    /// * `size` - The size of the object, in bytes
+    /// * `depth` - The depth of the object below the root tree of a commit
+    ///   (0 for root trees, as well as for commits and tags)
+    pub(crate) fn includes(&self, object_type: ObjectType, size: u64, depth: u64) -> bool {
+        match self {
+            Filter::BlobNone => object_type != ObjectType::Blob,
+            Filter::BlobLimit(limit) => object_type != ObjectType::Blob || size < *limit,
+            Filter::TreeDepth(max) => {
+                matches!(object_type, ObjectType::Commit | ObjectType::Tag) || depth < *max
+            },
+            Filter::ObjectType(only) => object_type == *only,
+            Filter::Combine(filters) => filters
+                .iter()
+                .all(|filter| filter.includes(object_type, size, depth)),
+        }
+    }
+
+    /// The depth from which the filter leaves out every tree and blob, the
+    /// trees above it being the only ones worth walking.
+    pub(crate) fn max_depth(&self) -> Option<u64> {
+        match self {
+            Filter::TreeDepth(max) => Some(*max),
+            Filter::Combine(filters) => filters.iter().filter_map(Filter::max_depth).min(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    /// Format the spec in its canonical form (e.g. `blob:limit=1k` as `blob:limit=1024`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::BlobNone => write!(f, "blob:none"),
+            Filter::BlobLimit(limit) => write!(f, "blob:limit={limit}"),
+            Filter::TreeDepth(depth) => write!(f, "tree:{depth}"),
+            Filter::ObjectType(object_type) => write!(f, "object:type={object_type}"),
+            Filter::Combine(filters) => {
+                let specs: Vec<String> = filters
+                    .iter()
+                    .map(|filter| encode_spec(&filter.to_string()))
+                    .collect();
+                write!(f, "combine:{}", specs.join("+"))
+            },
+        }
+    }
+}
+
+/// Parse a size with an optional `k`, `m` or `g` unit (powers of 1024).
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, factor) = match value.char_indices().last()? {
+        (index, 'k' | 'K') => (&value[..index], 1 << 10),
+        (index, 'm' | 'M') => (&value[..index], 1 << 20),
+        (index, 'g' | 'G') => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+/// The characters that must be percent-encoded in the specs of a combined filter
+const RESERVED: &[u8] = b"~`!@#$^&*()[]{}\\;'\",<>?+%";
+
+/// Percent-encode the reserved characters of a spec inside `combine:`.
+fn encode_spec(spec: &str) -> String {
+    let mut encoded = String::new();
+    for byte in spec.bytes() {
+        if RESERVED.contains(&byte) || !byte.is_ascii_graphic() {
+            encoded.push_str(&format!("%{byte:02x}"));
+        } else {
+            encoded.push(byte as char);
+        }
+    }
+    encoded
+}
+
+/// Decode a percent-encoded spec inside `combine:`.
+fn decode_spec(spec: &str) -> anyhow::Result<String> {
+    let bytes = spec.as_bytes();
+    let mut decoded = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = spec
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let Some(byte) = hex else {
+                anyhow::bail!("invalid filter-spec '{spec}'");
+            };
+            decoded.push(byte);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_filter_specs() {
+        assert_eq!(Filter::parse("blob:none").unwrap(), Filter::BlobNone);
+        assert_eq!(
+            Filter::parse("blob:limit=1k").unwrap(),
+            Filter::BlobLimit(1024)
+        );
+        assert_eq!(Filter::parse("tree:0").unwrap(), Filter::TreeDepth(0));
+        assert_eq!(
+            Filter::parse("object:type=tree").unwrap(),
+            Filter::ObjectType(ObjectType::Tree)
+        );
+        assert_eq!(
+            Filter::parse("combine:blob:none+tree:2").unwrap(),
+            Filter::Combine(vec![Filter::BlobNone, Filter::TreeDepth(2)])
+        );
+        assert_eq!(
+            Filter::parse("blob:limit=1m").unwrap().to_string(),
+            "blob:limit=1048576"
+        );
+        assert_eq!(
+            Filter::Combine(vec![Filter::BlobLimit(5), Filter::TreeDepth(1)]).to_string(),
+            "combine:blob:limit=5+tree:1"
+        );
+        assert_eq!(
+            Filter::parse("combine:blob%3Alimit=5+tree:1").unwrap(),
+            Filter::Combine(vec![Filter::BlobLimit(5), Filter::TreeDepth(1)])
+        );
+
+        assert!(Filter::parse("blob:limit=x").is_err());
+        assert!(Filter::parse("combine:blob:none").is_err());
+        assert_eq!(
+            Filter::parse("object:type=foo").unwrap_err().to_string(),
+            "'foo' for 'object:type=<type>' is not a valid object type"
+        );
+        assert_eq!(
+            Filter::parse("sparse:oid=abc").unwrap_err().to_string(),
+            "invalid filter-spec 'sparse:oid=abc'"
+        );
+    }
+
+    #[test]
+    fn filters_objects() {
+        let blob_none = Filter::BlobNone;
+        assert!(!blob_none.includes(ObjectType::Blob, 0, 1));
+        assert!(blob_none.includes(ObjectType::Tree, 0, 0));
+
+        let limit = Filter::BlobLimit(100);
+        assert!(limit.includes(ObjectType::Blob, 99, 1));
+        assert!(!limit.includes(ObjectType::Blob, 100, 1));
+
+        let depth = Filter::TreeDepth(1);
+        assert!(depth.includes(ObjectType::Commit, 0, 0));
+        assert!(depth.includes(ObjectType::Tree, 0, 0));
+        assert!(!depth.includes(ObjectType::Blob, 0, 1));
+
+        let combined = Filter::Combine(vec![Filter::TreeDepth(2), Filter::BlobLimit(10)]);
+        assert!(combined.includes(ObjectType::Blob, 5, 1));
+        assert!(!combined.includes(ObjectType::Blob, 50, 1));
+        assert!(!combined.includes(ObjectType::Tree, 0, 2));
+
+        assert!(Filter::supported_by(
+            &Capabilities::parse("filter shallow").unwrap()
+        ));
+        assert!(!Filter::supported_by(
+            &Capabilities::parse("shallow").unwrap()
+        ));
+    }
+}
@@ -0,0 +1,600 @@
+//! Serving repositories over the smart HTTP protocol, as `git http-backend`
+//!
+//! Each repository is served below `/<name>/`:
+//! - `GET /<name>/info/refs?service=git-upload-pack` (or `git-receive-pack`)
+//!   advertises the refs, after a `# service=<service>` line
+//! - `POST /<name>/git-upload-pack` serves a fetch, and
+//!   `POST /<name>/git-receive-pack` a push, each request being a single
+//!   stateless round of the conversation
+//!
+//! Fetching is allowed unless `http.uploadpack` is false, and pushing only if
+//! `http.receivepack` is true, as clients are not authenticated. The old
+//! "dumb" protocol (fetching the files of the repository) is not served.
+//!
+//! Request bodies are read whole, so those larger than [`MAX_BODY_SIZE`] (sent
+//! or once uncompressed) are refused, and the errors of the server are only
+//! shown on its stderr, not to the client.
+
+use std::io::{BufRead, Read, Write};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use flate2::read::GzDecoder;
+
+use crate::utils::config::Config;
+use crate::utils::protocol::pkt_line::{write_flush, write_line};
+use crate::utils::protocol::upload_pack::ServeOptions;
+use crate::utils::protocol::Service;
+use crate::utils::repository::Repository;
+
+/// The largest request body read, sent or once uncompressed
+pub(crate) const MAX_BODY_SIZE: u64 = 100 << 20;
+
+/// A repository served over HTTP
+#[derive(Debug, Clone)]
+pub(crate) struct ServedRepository {
+    /// The first component of the URL path of the repository
+    pub(crate) name: String,
+    pub(crate) git_dir: PathBuf,
+    /// The working tree, `None` for a bare repository
+    pub(crate) work_tree: Option<PathBuf>,
+}
+
+/// Whether a service is enabled for a repository (`http.uploadpack` or
+/// `http.receivepack`).
+fn is_enabled(service: Service, repo: &Repository) -> anyhow::Result<bool> {
+    let config = Config::load(repo)?;
+    Ok(match service {
+        Service::UploadPack => config.get_bool("http.uploadpack")?.unwrap_or(true),
+        Service::ReceivePack => config.get_bool("http.receivepack")?.unwrap_or(false),
+    })
+}
+
+/// An HTTP request
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Request {
+    method: String,
+    /// The percent-decoded path of the URL
+    path: String,
+    query: String,
+    /// Whether the request is HTTP/1.0, whose connections are not kept alive
+    /// by default
+    http_1_0: bool,
+    /// The headers, with lowercase names
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Read the request line and the headers of a request.
+    ///
+    /// # Returns
+    ///
+    /// The request, without its body, or `None` if the connection is closed
+    pub(crate) fn read_head<R>(reader: &mut R) -> anyhow::Result<Option<Self>>
+    where
+        R: BufRead,
+    {
+        let Some(line) = read_crlf_line(reader)? else {
+            return Ok(None);
+        };
+        let mut parts = line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("malformed request line '{line}'");
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut request = Request {
+            method: method.to_string(),
+            path: percent_decode(path)?,
+            query: query.to_string(),
+            http_1_0: version == "HTTP/1.0",
+            ..Request::default()
+        };
+
+        loop {
+            let line = read_crlf_line(reader)?.context("connection closed in the headers")?;
+            if line.is_empty() {
+                return Ok(Some(request));
+            }
+            let (name, value) = line
+                .split_once(':')
+                .context(format!("malformed header '{line}'"))?;
+            request
+                .headers
+                .push((name.to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    /// The value of a header, by its lowercase name.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Read the body of the request (sent whole or in chunks), uncompressing
+    /// it if it is gzipped.
+    ///
+    /// # Returns
+    ///
+    /// Whether the body was read, `false` if it is larger than `limit` (sent or
+    /// once uncompressed), in which case the rest of it is left unread
+    pub(crate) fn read_body<R>(&mut self, reader: &mut R, limit: u64) -> anyhow::Result<bool>
+    where
+        R: BufRead,
+    {
+        let mut body = Vec::new();
+        if self
+            .header("transfer-encoding")
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+        {
+            loop {
+                let line = read_crlf_line(reader)?.context("connection closed in a chunk")?;
+                let size = line.split(';').next().unwrap_or_default().trim();
+                let size = u64::from_str_radix(size, 16)
+                    .context(format!("malformed chunk size '{size}'"))?;
+                if size == 0 {
+                    // Trailers are ignored
+                    while read_crlf_line(reader)?.is_some_and(|line| !line.is_empty()) {}
+                    break;
+                }
+                if size > limit - body.len() as u64 {
+                    return Ok(false);
+                }
+                let read = reader.by_ref().take(size).read_to_end(&mut body)?;
+                if read as u64 != size {
+                    anyhow::bail!("connection closed in a chunk");
+                }
+                read_crlf_line(reader)?;
+            }
+        } else if let Some(length) = self.header("content-length") {
+            let length: u64 = length
+                .parse()
+                .context(format!("malformed content length '{length}'"))?;
+            if length > limit {
+                return Ok(false);
+            }
+            let read = reader.by_ref().take(length).read_to_end(&mut body)?;
+            if read as u64 != length {
+                anyhow::bail!("connection closed in the body");
+            }
+        }
+
+        self.body = match self.header("content-encoding") {
+            Some("gzip" | "x-gzip") => {
+                let mut uncompressed = Vec::new();
+                GzDecoder::new(body.as_slice())
+                    .take(limit + 1)
+                    .read_to_end(&mut uncompressed)
+                    .context("uncompress request body")?;
+                if uncompressed.len() as u64 > limit {
+                    return Ok(false);
+                }
+                uncompressed
+            },
+            _ => body,
+        };
+        Ok(true)
+    }
+
+    /// Whether the connection is closed after the request is answered.
+    fn closes_connection(&self) -> bool {
+        match self.header("connection") {
+            Some(connection) if connection.eq_ignore_ascii_case("close") => true,
+            Some(connection) if connection.eq_ignore_ascii_case("keep-alive") => false,
+            _ => self.http_1_0,
+        }
+    }
+}
+
+/// An HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    status: u16,
+    content_type: String,
+    body: Vec<u8>,
+    /// Whether the response must not be cached (the answers of the services)
+    no_cache: bool,
+}
+
+impl Response {
+    fn ok(service: Service, kind: &str, body: Vec<u8>) -> Self {
+        Response {
+            status: 200,
+            content_type: format!("application/x-{}-{kind}", service.name()),
+            body,
+            no_cache: true,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8".to_string(),
+            body: format!("{message}\n").into_bytes(),
+            no_cache: false,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            100 => "Continue",
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            415 => "Unsupported Media Type",
+            _ => "Internal Server Error",
+        }
+    }
+
+    /// Write the response, telling the client whether the connection is closed.
+    pub(crate) fn write<W>(&self, writer: &mut W, close: bool) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        )?;
+        if self.no_cache {
+            writer.write_all(
+                b"Expires: Fri, 01 Jan 1980 00:00:00 GMT\r\nPragma: no-cache\r\n\
+                  Cache-Control: no-cache, max-age=0, must-revalidate\r\n",
+            )?;
+        }
+        if close {
+            writer.write_all(b"Connection: close\r\n")?;
+        }
+        writer.write_all(b"\r\n")?;
+        writer.write_all(&self.body)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Serve the requests of a connection until it is closed.
+///
+/// # Arguments
+///
+/// * `repo` - The repository context of the server, whose directory and
+///   environment the repositories served are opened with
+/// * `repositories` - The repositories served
+/// * `reader` - Where the requests are read from
+/// * `writer` - Where the responses are written to
+pub(crate) fn serve_connection<R, W>(
+    repo: &Repository,
+    repositories: &[ServedRepository],
+    reader: &mut R,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    while let Some(mut request) = Request::read_head(reader)? {
+        if request
+            .header("expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+        {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            writer.flush()?;
+        }
+        // The rest of a body too large is not read, so the connection cannot be reused
+        if !request.read_body(reader, MAX_BODY_SIZE)? {
+            Response::error(413, "Payload Too Large").write(writer, true)?;
+            break;
+        }
+
+        let response = handle(repo, repositories, &request);
+        let close = request.closes_connection();
+        response.write(writer, close)?;
+        if close {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Answer a request.
+pub(crate) fn handle(
+    repo: &Repository,
+    repositories: &[ServedRepository],
+    request: &Request,
+) -> Response {
+    let path = request.path.trim_start_matches('/');
+    let Some((served, rest)) = repositories.iter().find_map(|served| {
+        let rest = path.strip_prefix(&served.name)?.strip_prefix('/')?;
+        Some((served, rest))
+    }) else {
+        return Response::error(404, "Not Found");
+    };
+
+    let (service, advertise_refs) = match (request.method.as_str(), rest) {
+        ("GET" | "HEAD", "info/refs") => {
+            let service = request
+                .query
+                .split('&')
+                .find_map(|param| param.strip_prefix("service="));
+            match service.and_then(Service::parse) {
+                Some(service) => (service, true),
+                None => return Response::error(403, "dumb HTTP is not supported"),
+            }
+        },
+        ("POST", rest) if Service::parse(rest).is_some() => (Service::parse(rest).unwrap(), false),
+        (_, "info/refs" | "git-upload-pack" | "git-receive-pack") => {
+            return Response::error(405, "Method Not Allowed");
+        },
+        _ => return Response::error(404, "Not Found"),
+    };
+    if !advertise_refs
+        && request.header("content-type")
+            != Some(&format!("application/x-{}-request", service.name()))
+    {
+        return Response::error(415, "Unsupported Media Type");
+    }
+
+    // Opened for each request, so that nothing read is cached between pushes
+    let repo = repo.open(served.git_dir.clone(), served.work_tree.clone());
+    match is_enabled(service, &repo) {
+        Ok(true) => {},
+        Ok(false) => return Response::error(403, &format!("{} is disabled", service.name())),
+        Err(err) => return internal_error(&err),
+    }
+
+    let options = ServeOptions {
+        advertise_refs,
+        stateless_rpc: true,
+    };
+    let mut body = Vec::new();
+    if advertise_refs {
+        let header = format!("# service={}\n", service.name());
+        if let Err(err) =
+            write_line(&mut body, header.as_bytes()).and_then(|()| write_flush(&mut body))
+        {
+            return internal_error(&err);
+        }
+    }
+    let result = service.serve(&repo, options, &mut request.body.as_slice(), &mut body);
+    match result {
+        Ok(()) => {},
+        // Errors told to the client (e.g. an `ERR` line) are in the body
+        Err(err) if !body.is_empty() => eprintln!("error: {err}"),
+        Err(err) => return internal_error(&err),
+    }
+    let kind = match advertise_refs {
+        true => "advertisement",
+        false => "result",
+    };
+    Response::ok(service, kind, body)
+}
+
+/// Show an error of the server on its stderr, answering the client without
+/// its details (e.g. the paths of the repository).
+fn internal_error(err: &anyhow::Error) -> Response {
+    eprintln!("error: {err}");
+    Response::error(500, "Internal Server Error")
+}
+
+/// Read a line ending with CRLF (or LF), without its end.
+///
+/// # Returns
+///
+/// The line, or `None` at the end of the input
+fn read_crlf_line<R>(reader: &mut R) -> anyhow::Result<Option<String>>
+where
+    R: BufRead,
+{
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with(b"\n") {
+        line.pop();
+    }
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Decode the `%XX` escapes of a URL path.
+pub(crate) fn percent_decode(path: &str) -> anyhow::Result<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3).unwrap_or_default();
+            let byte = u8::from_str_radix(hex, 16).context(format!("malformed path '{path}'"))?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).context(format!("malformed path '{path}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::protocol::pkt_line::read_line;
+    use crate::utils::refs::write_ref;
+    use crate::utils::test::TempRepo;
+
+    /// Serve the requests of a connection, returning the responses.
+    fn serve(repo: &Repository, repositories: &[ServedRepository], requests: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        serve_connection(repo, repositories, &mut &requests[..], &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn serves_upload_pack() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join("repo/.git/refs/heads")).unwrap();
+        fs::create_dir_all(temp.join("repo/.git/objects")).unwrap();
+        let repositories = [ServedRepository {
+            name: "repo".to_string(),
+            git_dir: temp.join("repo/.git"),
+            work_tree: Some(temp.join("repo")),
+        }];
+        let repo = temp.repo().open(
+            repositories[0].git_dir.clone(),
+            repositories[0].work_tree.clone(),
+        );
+        let blob = write_object(&repo, ObjectType::Blob, b"content\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &blob).unwrap();
+
+        let output = serve(
+            &repo,
+            &repositories,
+            b"GET /repo/info/refs?service=git-upload-pack HTTP/1.1\r\nHost: x\r\n\r\n\
+              GET /other/info/refs?service=git-upload-pack HTTP/1.0\r\n\r\n",
+        );
+        let output = String::from_utf8_lossy(&output);
+        let (first, second) = output.split_once("HTTP/1.1 404").unwrap();
+        assert!(first.starts_with(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-git-upload-pack-advertisement\r\n"
+        ));
+        let (_, body) = first.split_once("\r\n\r\n").unwrap();
+        assert!(body.starts_with("001e# service=git-upload-pack\n0000"));
+        assert!(body.contains(&format!("{blob} refs/heads/main\0")));
+        // HTTP/1.0 connections are closed after each request
+        assert!(second.contains("Connection: close\r\n"));
+
+        // Requests may be gzipped and sent in chunks
+        let mut request = Vec::new();
+        write_line(&mut request, format!("want {blob}\n").as_bytes()).unwrap();
+        write_flush(&mut request).unwrap();
+        write_line(&mut request, b"done\n").unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&request).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let mut requests = b"POST /repo/git-upload-pack HTTP/1.1\r\n\
+              Content-Type: application/x-git-upload-pack-request\r\n\
+              Content-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n"
+            .to_vec();
+        for chunk in gzipped.chunks(10) {
+            requests.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            requests.extend(chunk);
+            requests.extend(b"\r\n");
+        }
+        requests.extend(b"0\r\n\r\n");
+        let output = serve(&repo, &repositories, &requests);
+        let (head, body) =
+            output.split_at(output.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4);
+        let head = String::from_utf8_lossy(head);
+        assert!(head.starts_with(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-git-upload-pack-result\r\n"
+        ));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        let mut body = body;
+        assert_eq!(read_line(&mut body).unwrap(), Some(b"NAK".to_vec()));
+        assert!(body.starts_with(b"PACK"));
+
+        // Pushing must be enabled, as clients are not authenticated
+        let output = serve(
+            &repo,
+            &repositories,
+            b"GET /repo/info/refs?service=git-receive-pack HTTP/1.1\r\n\r\n",
+        );
+        assert!(output.starts_with(b"HTTP/1.1 403 Forbidden\r\n"));
+        assert!(output.ends_with(b"\r\n\r\ngit-receive-pack is disabled\n"));
+        fs::write(
+            temp.join("repo/.git/config"),
+            "[http]\n\treceivepack = true\n",
+        )
+        .unwrap();
+        let output = serve(
+            &repo,
+            &repositories,
+            b"GET /repo/info/refs?service=git-receive-pack HTTP/1.1\r\n\r\n",
+        );
+        assert!(output.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        // The errors of the server are not shown to the client
+        fs::write(
+            temp.join("repo/.git/config"),
+            "[http]\n\tuploadpack = maybe\n",
+        )
+        .unwrap();
+        let output = serve(
+            &repo,
+            &repositories,
+            b"GET /repo/info/refs?service=git-upload-pack HTTP/1.1\r\n\r\n",
+        );
+        assert!(output.starts_with(b"HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(output.ends_with(b"\r\n\r\nInternal Server Error\n"));
+    }
+
+    #[test]
+    fn refuses_bodies_too_large() {
+        let temp = TempRepo::new();
+        let repo = temp.repo();
+
+        // The connection is closed without reading the body
+        let output = serve(
+            &repo,
+            &[],
+            b"POST /repo/git-upload-pack HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\n\
+              GET /repo/info/refs?service=git-upload-pack HTTP/1.1\r\n\r\n",
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(output.contains("Connection: close\r\n"));
+        assert_eq!(output.matches("HTTP/1.1").count(), 1);
+
+        let read = |headers: &[(&str, &str)], body: &[u8]| {
+            let mut request = Request {
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                ..Request::default()
+            };
+            let read = request.read_body(&mut &body[..], 100).unwrap();
+            read.then_some(request.body)
+        };
+        assert_eq!(
+            read(&[("content-length", "3")], b"abcdef"),
+            Some(b"abc".to_vec())
+        );
+        assert_eq!(read(&[("content-length", "101")], &[0; 101]), None);
+        let chunked = [("transfer-encoding", "chunked")];
+        let chunk = [b"3c\r\n".as_slice(), &[b'a'; 60], b"\r\n"].concat();
+        assert_eq!(
+            read(&chunked, &[chunk.as_slice(), b"0\r\n\r\n"].concat()),
+            Some(vec![b'a'; 60])
+        );
+        assert_eq!(read(&chunked, &chunk.repeat(2)), None);
+
+        // The size of a gzipped body is checked once uncompressed
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 1000]).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let length = gzipped.len().to_string();
+        let headers = [
+            ("content-length", length.as_str()),
+            ("content-encoding", "gzip"),
+        ];
+        assert_eq!(read(&headers, &gzipped), None);
+    }
+}
@@ -0,0 +1,61 @@
+//! Building blocks for the git transfer protocols
+
+pub(crate) mod capabilities;
+pub(crate) mod daemon;
+pub(crate) mod filter;
+pub(crate) mod http_backend;
+pub(crate) mod pkt_line;
+pub(crate) mod promisor;
+pub(crate) mod quarantine;
+pub(crate) mod receive_pack;
+pub(crate) mod shallow;
+pub(crate) mod transport;
+pub(crate) mod upload_pack;
+
+use std::io::{Read, Write};
+
+use crate::utils::protocol::upload_pack::ServeOptions;
+use crate::utils::repository::Repository;
+
+/// A service a repository is served with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Service {
+    UploadPack,
+    ReceivePack,
+}
+
+impl Service {
+    /// Parse the name of the program serving the service (e.g. `git-upload-pack`).
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "git-upload-pack" => Some(Service::UploadPack),
+            "git-receive-pack" => Some(Service::ReceivePack),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Service::UploadPack => "git-upload-pack",
+            Service::ReceivePack => "git-receive-pack",
+        }
+    }
+
+    /// Serve a request of the service (see `upload_pack::serve`).
+    pub(crate) fn serve<R, W>(
+        self,
+        repo: &Repository,
+        options: ServeOptions,
+        input: &mut R,
+        output: &mut W,
+    ) -> anyhow::Result<()>
+    where
+        R: Read,
+        W: Write,
+    {
+        match self {
+            Service::UploadPack => upload_pack::serve(repo, options, input, output),
+            Service::ReceivePack => receive_pack::serve(repo, options, input, output),
+        }
+    }
+}
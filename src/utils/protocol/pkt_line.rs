@@ -0,0 +1,123 @@
+//! The pkt-line framing of the pack protocol
+//!
+//! Each line is prefixed with its length in 4 hex digits (the prefix
+//! included), and `0000` (a flush packet) ends a section of the conversation.
+
+use std::io::{Read, Write};
+
+use anyhow::Context;
+
+/// The largest payload a line can hold
+const MAX_PAYLOAD: usize = 65516;
+
+/// Write a line, whose payload should end with a newline unless it is binary.
+pub(crate) fn write_line<W>(writer: &mut W, payload: &[u8]) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    if payload.len() > MAX_PAYLOAD {
+        anyhow::bail!("protocol error: impossibly long line");
+    }
+    write!(writer, "{:04x}", payload.len() + 4)?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+/// Write a flush packet, ending a section.
+pub(crate) fn write_flush<W>(writer: &mut W) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    writer.write_all(b"0000")?;
+    Ok(())
+}
+
+/// Write data on a band of the side-band channel, split into as many lines
+/// as needed: band 1 carries the pack, band 2 progress messages and band 3
+/// a fatal error.
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the lines
+/// * `band` - The band of the data
+/// * `data` - The data
+/// * `max` - The most data a line may carry (995 with `side-band`, 65515
+///   with `side-band-64k`)
+pub(crate) fn write_band<W>(writer: &mut W, band: u8, data: &[u8], max: usize) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    for chunk in data.chunks(max) {
+        let mut payload = Vec::with_capacity(chunk.len() + 1);
+        payload.push(band);
+        payload.extend_from_slice(chunk);
+        write_line(writer, &payload)?;
+    }
+    Ok(())
+}
+
+/// Read a line.
+///
+/// # Returns
+///
+/// The payload of the line without its trailing newline, or `None` for a
+/// flush packet
+pub(crate) fn read_line<R>(reader: &mut R) -> anyhow::Result<Option<Vec<u8>>>
+where
+    R: Read,
+{
+    let mut prefix = [0; 4];
+    reader
+        .read_exact(&mut prefix)
+        .context("the remote end hung up unexpectedly")?;
+    let length = std::str::from_utf8(&prefix)
+        .ok()
+        .and_then(|prefix| usize::from_str_radix(prefix, 16).ok())
+        .with_context(|| {
+            format!(
+                "protocol error: bad line length character: {}",
+                String::from_utf8_lossy(&prefix)
+            )
+        })?;
+    match length {
+        0 => return Ok(None),
+        1..=3 => anyhow::bail!("protocol error: bad line length {length}"),
+        _ => {},
+    }
+
+    let mut payload = vec![0; length - 4];
+    reader
+        .read_exact(&mut payload)
+        .context("the remote end hung up unexpectedly")?;
+    if payload.last() == Some(&b'\n') {
+        payload.pop();
+    }
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_lines() {
+        let mut out = Vec::new();
+        write_line(&mut out, b"want 1234\n").unwrap();
+        write_flush(&mut out).unwrap();
+        write_line(&mut out, b"done").unwrap();
+        assert_eq!(out, b"000ewant 1234\n00000008done");
+
+        let mut reader = out.as_slice();
+        assert_eq!(read_line(&mut reader).unwrap(), Some(b"want 1234".to_vec()));
+        assert_eq!(read_line(&mut reader).unwrap(), None);
+        assert_eq!(read_line(&mut reader).unwrap(), Some(b"done".to_vec()));
+        assert_eq!(
+            read_line(&mut reader).unwrap_err().to_string(),
+            "the remote end hung up unexpectedly"
+        );
+        assert_eq!(
+            read_line(&mut b"zzzz".as_slice()).unwrap_err().to_string(),
+            "protocol error: bad line length character: zzzz"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Promisor remotes of partial clones
+//!
+//! A partial clone (`clone --filter=<spec>`) records the remote it was cloned
+//! from as its promisor (`extensions.partialClone`, along with
+//! `remote.<name>.promisor` and the `remote.<name>.partialCloneFilter` it was
+//! cloned with), and marks the packs fetched from it with a `.promisor` file.
+//! The objects the filter left out are fetched from the promisor when they are
+//! read, which the remote only allows with `uploadpack.allowAnySHA1InWant`.
+
+use anyhow::Context;
+
+use crate::utils::config::Config;
+use crate::utils::protocol::transport::Connection;
+use crate::utils::repository::Repository;
+use crate::utils::transfer::open_repository;
+
+/// The name of the remote the objects missing from a repository are fetched
+/// from, if it is a partial clone.
+pub(crate) fn promisor_remote(repo: &Repository) -> anyhow::Result<Option<String>> {
+    let config = Config::load(repo)?;
+    Ok(config.get("extensions.partialClone").map(str::to_string))
+}
+
+/// Fetch objects missing from a partial clone from its promisor remote.
+///
+/// # Arguments
+///
+/// * `repo` - The repository lacking the objects
+/// * `hashes` - The objects to fetch
+///
+/// # Returns
+///
+/// Whether the objects were fetched, as only partial clones have a remote to
+/// fetch them from
+pub(crate) fn fetch_missing(repo: &Repository, hashes: &[String]) -> anyhow::Result<bool> {
+    let Some(remote) = promisor_remote(repo)? else {
+        return Ok(false);
+    };
+    let config = Config::load(repo)?;
+    let url = config
+        .get(&format!("remote.{remote}.url"))
+        .context(format!("promisor remote '{remote}' has no url"))?;
+
+    let connection = match url.starts_with("git://") {
+        true => Connection::connect(url)?,
+        false => {
+            let source = open_repository(repo, url)?;
+            let upload_pack = config.get(&format!("remote.{remote}.uploadpack"));
+            Connection::open(repo, &source.repo.git_dir()?, upload_pack)?
+        },
+    };
+    connection.fetch(repo, hashes, None, None).context(format!(
+        "could not fetch {} from promisor remote",
+        hashes.join(", ")
+    ))?;
+    Ok(true)
+}
@@ -0,0 +1,193 @@
+//! Quarantine of the objects received by a push
+//!
+//! receive-pack writes the objects of a push to a temporary directory of the
+//! object directory (`tmp_objdir-incoming-*`) rather than to the object directory
+//! itself, and runs the `pre-receive` hook with the repository quarantined (see
+//! [`crate::utils::hooks`]). The objects are only moved into the object directory
+//! once the hook accepts the push, so a rejected push leaves nothing behind.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::TempPath;
+
+/// A temporary object directory holding the objects received by a push
+#[derive(Debug)]
+pub(crate) struct Quarantine {
+    /// The repository, writing new objects to the quarantine
+    repo: Repository,
+    /// The quarantine directory, discarded unless migrated
+    dir: TempPath,
+    /// The object directory the objects are moved to
+    object_dir: PathBuf,
+}
+
+impl Quarantine {
+    /// Create an empty quarantine in the object directory of a repository.
+    pub(crate) fn new(repo: &Repository) -> anyhow::Result<Self> {
+        let object_dir = repo.object_dir(true)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let path = object_dir.join(format!(
+            "tmp_objdir-incoming-{:x}{nanos:x}",
+            std::process::id()
+        ));
+        std::fs::create_dir(&path).context(format!("create {}", path.display()))?;
+
+        Ok(Quarantine {
+            repo: repo.with_quarantine(path.clone()),
+            dir: TempPath::new(path),
+            object_dir,
+        })
+    }
+
+    /// The repository with its new objects written to the quarantine, to
+    /// receive the objects and run the hooks with.
+    pub(crate) fn repo(&self) -> &Repository {
+        &self.repo
+    }
+
+    /// Move the quarantined objects into the object directory, keeping the
+    /// objects it already has, then remove the quarantine.
+    pub(crate) fn migrate(self) -> anyhow::Result<()> {
+        migrate_dir(self.dir.path(), &self.object_dir)?;
+        self.dir.keep();
+        Ok(())
+    }
+}
+
+/// Move the files of a directory into another one, recursively.
+fn migrate_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let mut entries = std::fs::read_dir(from)
+        .context(format!("read {}", from.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    // The files of a pack are moved before its index, which makes it usable
+    entries.sort_by_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        (copy_priority(&name), path.clone())
+    });
+
+    for path in entries {
+        let target = to.join(path.file_name().context("invalid quarantine path")?);
+        if path.is_dir() {
+            std::fs::create_dir_all(&target).context(format!("create {}", target.display()))?;
+            migrate_dir(&path, &target)?;
+        } else if !target.exists() {
+            std::fs::rename(&path, &target).context(format!("move {}", path.display()))?;
+        }
+    }
+    std::fs::remove_dir_all(from).context(format!("remove {}", from.display()))
+}
+
+/// The order the files of a directory are moved in: loose objects and
+/// directories first, then the files of packs with their index last.
+fn copy_priority(name: &str) -> u8 {
+    if !name.starts_with("pack") {
+        return 0;
+    }
+    match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+        Some("keep") => 1,
+        Some("pack") => 2,
+        Some("rev") => 3,
+        Some("idx") => 4,
+        _ => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::hooks::{run_hook, Hook};
+    use crate::utils::objects::{read_object, write_object, ObjectType};
+    use crate::utils::refs::write_ref;
+    use crate::utils::test::TempRepo;
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    #[test]
+    fn migrates_accepted_objects() {
+        let (temp, repo) = setup();
+        let existing = write_object(&repo, ObjectType::Blob, b"existing").unwrap();
+
+        let quarantine = Quarantine::new(&repo).unwrap();
+        let received = write_object(quarantine.repo(), ObjectType::Blob, b"received").unwrap();
+        // The objects of the repository are still read, but not written again
+        assert_eq!(
+            write_object(quarantine.repo(), ObjectType::Blob, b"existing").unwrap(),
+            existing
+        );
+        assert!(read_object(quarantine.repo(), &existing).is_ok());
+        assert!(read_object(&repo, &received).is_err());
+
+        // Only the fan-out directories of the two objects are left
+        quarantine.migrate().unwrap();
+        assert!(read_object(&repo, &received).is_ok());
+        assert_eq!(fs::read_dir(temp.join(".git/objects")).unwrap().count(), 2);
+
+        // Objects of a rejected push are discarded
+        let quarantine = Quarantine::new(&repo).unwrap();
+        let rejected = write_object(quarantine.repo(), ObjectType::Blob, b"rejected").unwrap();
+        drop(quarantine);
+        assert!(read_object(&repo, &rejected).is_err());
+        assert_eq!(fs::read_dir(temp.join(".git/objects")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn exposes_quarantine_to_hooks() {
+        let (mut temp, repo) = setup();
+        let quarantine = Quarantine::new(&repo).unwrap();
+        let blob = write_object(quarantine.repo(), ObjectType::Blob, b"received").unwrap();
+
+        // The hook finds the received objects, and the repository as an alternate
+        fs::create_dir_all(temp.join(".git/hooks")).unwrap();
+        fs::write(
+            temp.join(".git/hooks/pre-receive"),
+            format!(
+                "#!/bin/sh\ntest -f \"$GIT_OBJECT_DIRECTORY/{}/{}\" || exit 1\n\
+                 test \"$GIT_QUARANTINE_PATH\" = \"$GIT_OBJECT_DIRECTORY\" || exit 2\n\
+                 test \"$GIT_ALTERNATE_OBJECT_DIRECTORIES\" = \"{}\" || exit 3\n",
+                &blob[..2],
+                &blob[2..],
+                temp.path()
+                    .canonicalize()
+                    .unwrap()
+                    .join(".git/objects")
+                    .display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(
+            temp.join(".git/hooks/pre-receive"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        assert_eq!(
+            run_hook(quarantine.repo(), "pre-receive", Hook::default()).unwrap(),
+            0
+        );
+
+        temp.set_env(env::GIT_QUARANTINE_PATH, "quarantine");
+        let repo = temp.repo();
+        assert_eq!(
+            write_ref(&repo, "refs/heads/main", &blob)
+                .unwrap_err()
+                .to_string(),
+            "ref updates forbidden inside quarantine environment"
+        );
+    }
+}
@@ -0,0 +1,549 @@
+//! The server side of pushes (receive-pack)
+//!
+//! The client sends the ref updates it wants (`<old> <new> <ref>`), then the
+//! pack of the objects they need. The objects are kept in a [`Quarantine`]
+//! while the `pre-receive` hook decides whether to accept the whole push, then
+//! each update is checked (by the `receive.deny*` settings and the `update`
+//! hook) before the refs are updated, and the `post-receive` hook is told
+//! which ones were. The client is told the outcome of each update if it asked
+//! for it (`report-status`).
+//!
+//! With `atomic`, the refs are updated in a single transaction, and a single
+//! update refused makes the whole push fail.
+
+use std::io::{BufReader, Read, Write};
+
+use crate::utils::advice::warning;
+use crate::utils::config::Config;
+use crate::utils::hooks::{run_hook, Hook};
+use crate::utils::objects::{object_exists, read_original_object, write_object, ObjectType};
+use crate::utils::pack::{read_pack, read_pack_from};
+use crate::utils::protocol::capabilities::{Capabilities, Flag, ObjectFormat};
+use crate::utils::protocol::pkt_line::{read_line, write_band, write_flush, write_line};
+use crate::utils::protocol::quarantine::Quarantine;
+use crate::utils::protocol::upload_pack::ServeOptions;
+use crate::utils::reflog::NULL_HASH;
+use crate::utils::refs::{head_branch, is_valid_ref_name, list_refs, resolve_ref, RefTransaction};
+use crate::utils::repository::Repository;
+use crate::utils::walk::is_ancestor;
+
+/// The flags advertised
+const FLAGS: &[Flag] = &[
+    Flag::ReportStatus,
+    Flag::DeleteRefs,
+    Flag::SideBand64k,
+    Flag::Quiet,
+    Flag::Atomic,
+    Flag::OfsDelta,
+];
+
+/// What is done when a push updates (or deletes) the branch checked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Deny {
+    /// The update is applied
+    Ignore,
+    /// The update is applied, with a warning
+    Warn,
+    /// The update is refused
+    Refuse,
+}
+
+impl Deny {
+    fn parse(config: &Config, key: &str) -> anyhow::Result<Self> {
+        match config.get(key) {
+            None => Ok(Deny::Refuse),
+            Some("ignore") => Ok(Deny::Ignore),
+            Some("warn") => Ok(Deny::Warn),
+            Some("refuse") => Ok(Deny::Refuse),
+            Some(_) => match config.get_bool(key)? {
+                Some(true) => Ok(Deny::Refuse),
+                _ => Ok(Deny::Ignore),
+            },
+        }
+    }
+}
+
+/// What clients may push to a repository
+#[derive(Debug, Clone)]
+struct ReceivePack {
+    /// Whether the repository has no working tree (`core.bare`)
+    bare: bool,
+    /// Whether branches may not be deleted (`receive.denyDeletes`)
+    deny_deletes: bool,
+    /// Whether branches may only be fast-forwarded
+    /// (`receive.denyNonFastForwards`)
+    deny_non_fast_forwards: bool,
+    /// Updating the branch checked out (`receive.denyCurrentBranch`)
+    deny_current_branch: Deny,
+    /// Deleting the branch HEAD points to (`receive.denyDeleteCurrent`)
+    deny_delete_current: Deny,
+}
+
+impl ReceivePack {
+    /// Load the settings of a repository.
+    fn new(repo: &Repository) -> anyhow::Result<Self> {
+        let config = Config::load(repo)?;
+        if config.get("receive.denyCurrentBranch") == Some("updateInstead") {
+            anyhow::bail!("receive.denyCurrentBranch=updateInstead is not supported");
+        }
+        Ok(ReceivePack {
+            bare: config.get_bool("core.bare")?.unwrap_or(false),
+            deny_deletes: config.get_bool("receive.denyDeletes")?.unwrap_or(false),
+            deny_non_fast_forwards: config
+                .get_bool("receive.denyNonFastForwards")?
+                .unwrap_or(false),
+            deny_current_branch: Deny::parse(&config, "receive.denyCurrentBranch")?,
+            deny_delete_current: Deny::parse(&config, "receive.denyDeleteCurrent")?,
+        })
+    }
+
+    /// Check that an update may be applied.
+    ///
+    /// # Returns
+    ///
+    /// Why the update is refused, as reported to the client, if it is
+    fn check(
+        &self,
+        repo: &Repository,
+        command: &Command,
+        head: Option<&str>,
+    ) -> anyhow::Result<Option<&'static str>> {
+        let name = command.name.as_str();
+        if !name.starts_with("refs/") || !is_valid_ref_name(name) {
+            eprintln!("error: refusing to create funny ref '{name}' remotely");
+            return Ok(Some("funny refname"));
+        }
+        let is_branch = name.starts_with("refs/heads/");
+        let is_head = head == Some(name);
+
+        let Some(new) = &command.new else {
+            if self.deny_deletes && is_branch {
+                eprintln!("error: denying ref deletion for {name}");
+                return Ok(Some("deletion prohibited"));
+            }
+            if is_head {
+                match self.deny_delete_current {
+                    Deny::Ignore => {},
+                    Deny::Warn => warning("deleting the current branch"),
+                    Deny::Refuse => {
+                        eprintln!("error: refusing to delete the current branch: {name}");
+                        return Ok(Some("deletion of the current branch prohibited"));
+                    },
+                }
+            }
+            return Ok(None);
+        };
+
+        if !self.bare && is_head {
+            match self.deny_current_branch {
+                Deny::Ignore => {},
+                Deny::Warn => warning("updating the current branch"),
+                Deny::Refuse => {
+                    eprintln!("error: refusing to update checked out branch: {name}");
+                    return Ok(Some("branch is currently checked out"));
+                },
+            }
+        }
+        if !object_exists(repo, new)? {
+            eprintln!("error: unpack should have generated {new}, but I can't find it!");
+            return Ok(Some("bad pack"));
+        }
+        if let Some(old) = command.old.as_deref().filter(|_| is_branch) {
+            if self.deny_non_fast_forwards && !is_fast_forward(repo, old, new)? {
+                eprintln!("error: denying non-fast-forward {name} (you should pull first)");
+                return Ok(Some("non-fast-forward"));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A ref update requested by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Command {
+    /// The hash the ref is expected at, `None` if it should not exist
+    old: Option<String>,
+    /// The new hash of the ref, `None` to delete it
+    new: Option<String>,
+    /// The full name of the ref
+    name: String,
+}
+
+impl Command {
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let mut parts = line.splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+            anyhow::bail!("protocol error: expected old/new/ref, got '{line}'");
+        };
+        let hash = |hash: &str| (hash != NULL_HASH).then(|| hash.to_string());
+        Ok(Command {
+            old: hash(old),
+            new: hash(new),
+            name: name.to_string(),
+        })
+    }
+
+    /// The line describing the update to the hooks (`<old> <new> <ref>`)
+    fn hook_line(&self) -> String {
+        format!(
+            "{} {} {}\n",
+            self.old.as_deref().unwrap_or(NULL_HASH),
+            self.new.as_deref().unwrap_or(NULL_HASH),
+            self.name
+        )
+    }
+}
+
+/// Serve a push to a repository (protocol v0): advertise the refs, read the
+/// updates and the pack, then apply the updates that are accepted.
+///
+/// # Arguments
+///
+/// * `repo` - The repository pushed to
+/// * `options` - How the push is served
+/// * `input` - Where the requests of the client are read from
+/// * `output` - Where the answers are written to
+pub(crate) fn serve<R, W>(
+    repo: &Repository,
+    options: ServeOptions,
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let settings = ReceivePack::new(repo)?;
+    let mut capabilities = Capabilities::default();
+    for &flag in FLAGS {
+        capabilities.insert(flag);
+    }
+    capabilities.agent = Some(Capabilities::default_agent(repo.env()));
+    capabilities.object_format = Some(ObjectFormat::Sha1);
+    let capabilities = capabilities.with_session_id(repo, None)?;
+
+    if !options.stateless_rpc || options.advertise_refs {
+        let refs = list_refs(repo, "refs/")?;
+        let zero = NULL_HASH.to_string();
+        let first = refs
+            .first()
+            .map_or((zero.as_str(), "capabilities^{}"), |(name, hash)| {
+                (hash.as_str(), name.as_str())
+            });
+        write_line(
+            output,
+            format!("{} {}\0{capabilities}\n", first.0, first.1).as_bytes(),
+        )?;
+        for (name, hash) in refs.iter().skip(1) {
+            write_line(output, format!("{hash} {name}\n").as_bytes())?;
+        }
+        write_flush(output)?;
+        output.flush()?;
+    }
+    if options.advertise_refs {
+        return Ok(());
+    }
+
+    // Clients with nothing to push end the conversation right away
+    let mut commands = Vec::new();
+    let mut requested = Capabilities::default();
+    while let Some(line) = read_line(input)? {
+        let line = String::from_utf8_lossy(&line).into_owned();
+        let (line, list) = line.split_once('\0').unwrap_or((&line, ""));
+        if commands.is_empty() {
+            requested = Capabilities::parse(list)?;
+            capabilities.validate_request(&requested)?;
+        }
+        commands.push(Command::parse(line)?);
+    }
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    let quarantine = Quarantine::new(repo)?;
+    let unpacked = match commands.iter().any(|command| command.new.is_some()) {
+        true => unpack(quarantine.repo(), input),
+        false => Ok(()),
+    };
+    let mut errors: Vec<Option<&str>> = vec![None; commands.len()];
+    match &unpacked {
+        Ok(()) => {
+            let input = commands.iter().map(Command::hook_line).collect::<String>();
+            let hook = Hook {
+                input: Some(input.as_bytes()),
+                ..Hook::default()
+            };
+            if run_hook(quarantine.repo(), "pre-receive", hook)? != 0 {
+                errors.fill(Some("pre-receive hook declined"));
+            } else {
+                quarantine.migrate()?;
+                update_refs(repo, &settings, &requested, &commands, &mut errors)?;
+            }
+        },
+        Err(err) => {
+            eprintln!("error: unpack failed: {err}");
+            errors.fill(Some("unpacker error"));
+        },
+    }
+
+    if requested.has(Flag::ReportStatus) {
+        let mut report = Vec::new();
+        match &unpacked {
+            Ok(()) => write_line(&mut report, b"unpack ok\n")?,
+            Err(err) => write_line(&mut report, format!("unpack {err}\n").as_bytes())?,
+        }
+        for (command, error) in commands.iter().zip(&errors) {
+            let line = match error {
+                Some(error) => format!("ng {} {error}\n", command.name),
+                None => format!("ok {}\n", command.name),
+            };
+            write_line(&mut report, line.as_bytes())?;
+        }
+        write_flush(&mut report)?;
+        match requested.has(Flag::SideBand64k) {
+            true => {
+                write_band(output, 1, &report, 65515)?;
+                write_flush(output)?;
+            },
+            false => output.write_all(&report)?,
+        }
+        output.flush()?;
+    }
+
+    let updated: String = commands
+        .iter()
+        .zip(&errors)
+        .filter(|(_, error)| error.is_none())
+        .map(|(command, _)| command.hook_line())
+        .collect();
+    if !updated.is_empty() {
+        let hook = Hook {
+            input: Some(updated.as_bytes()),
+            ..Hook::default()
+        };
+        run_hook(repo, "post-receive", hook)?;
+    }
+    Ok(())
+}
+
+/// Read the pack sent by the client (up to the end of the input) and write
+/// its objects.
+fn unpack<R>(repo: &Repository, input: &mut R) -> anyhow::Result<()>
+where
+    R: Read,
+{
+    // The client keeps the connection open to read the outcome
+    let pack = read_pack_from(&mut BufReader::new(input))?;
+    for (object_type, content) in read_pack(repo, &pack)? {
+        write_object(repo, object_type, &content)?;
+    }
+    Ok(())
+}
+
+/// Check the updates, run the `update` hook for each one, then apply those
+/// that are accepted, recording why the others are not in `errors`.
+fn update_refs(
+    repo: &Repository,
+    settings: &ReceivePack,
+    requested: &Capabilities,
+    commands: &[Command],
+    errors: &mut [Option<&str>],
+) -> anyhow::Result<()> {
+    let head = head_branch(repo)?;
+    for (command, error) in commands.iter().zip(errors.iter_mut()) {
+        *error = settings.check(repo, command, head.as_deref())?;
+        if error.is_some() {
+            continue;
+        }
+        let hook = Hook {
+            args: vec![
+                command.name.clone(),
+                command.old.clone().unwrap_or_else(|| NULL_HASH.to_string()),
+                command.new.clone().unwrap_or_else(|| NULL_HASH.to_string()),
+            ],
+            ..Hook::default()
+        };
+        if run_hook(repo, "update", hook)? != 0 {
+            eprintln!("error: hook declined to update {}", command.name);
+            *error = Some("hook declined");
+        }
+    }
+
+    if requested.has(Flag::Atomic) {
+        if errors.iter().any(Option::is_some) {
+            for error in errors.iter_mut().filter(|error| error.is_none()) {
+                *error = Some("atomic push failure");
+            }
+            return Ok(());
+        }
+        let mut transaction = RefTransaction::new(repo)?;
+        for command in commands {
+            add_update(repo, &mut transaction, command)?;
+        }
+        if let Err(err) = transaction.commit() {
+            eprintln!("error: {err}");
+            errors.fill(Some("atomic transaction failed"));
+        }
+        return Ok(());
+    }
+
+    for (command, error) in commands.iter().zip(errors.iter_mut()) {
+        if error.is_some() {
+            continue;
+        }
+        let mut transaction = RefTransaction::new(repo)?;
+        add_update(repo, &mut transaction, command)?;
+        if let Err(err) = transaction.commit() {
+            eprintln!("error: {err}");
+            *error = Some(match command.new {
+                Some(_) => "failed to update ref",
+                None => "failed to delete",
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Add an update to a transaction, expecting the ref at the hash the client saw.
+fn add_update(
+    repo: &Repository,
+    transaction: &mut RefTransaction,
+    command: &Command,
+) -> anyhow::Result<()> {
+    match &command.new {
+        Some(new) => transaction.update(&command.name, new, Some(command.old.as_deref()), "push"),
+        // Deleting a ref that does not exist is not an error
+        None if resolve_ref(repo, &command.name)?.is_none() => {
+            warning("deleting a non-existent ref");
+            Ok(())
+        },
+        None => transaction.delete(&command.name, command.old.as_deref()),
+    }
+}
+
+/// Whether a ref may move from a commit to another without losing history.
+/// Objects other than commits are never fast-forwarded.
+fn is_fast_forward(repo: &Repository, old: &str, new: &str) -> anyhow::Result<bool> {
+    let is_commit = |hash: &str| {
+        read_original_object(repo, hash)
+            .is_ok_and(|(object_type, _)| object_type == ObjectType::Commit)
+    };
+    Ok(is_commit(old) && is_commit(new) && is_ancestor(repo, old, new)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::pack::write_pack;
+    use crate::utils::test::TempRepo;
+
+    /// Push to a repository, returning the lines reported (`None` for flush packets).
+    fn push(repo: &Repository, commands: &[String], pack: &[u8]) -> Vec<Option<String>> {
+        let mut input = Vec::new();
+        for command in commands {
+            write_line(&mut input, format!("{command}\n").as_bytes()).unwrap();
+        }
+        write_flush(&mut input).unwrap();
+        input.extend_from_slice(pack);
+        let mut output = Vec::new();
+        serve(
+            repo,
+            ServeOptions::default(),
+            &mut input.as_slice(),
+            &mut output,
+        )
+        .unwrap();
+
+        let mut reader = output.as_slice();
+        while read_line(&mut reader).unwrap().is_some() {}
+        let mut lines = Vec::new();
+        while !reader.is_empty() {
+            let line = read_line(&mut reader).unwrap();
+            lines.push(line.map(|line| String::from_utf8(line).unwrap()));
+        }
+        lines
+    }
+
+    #[test]
+    fn applies_accepted_updates() {
+        let temp = TempRepo::new()
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b")
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b");
+        fs::create_dir_all(temp.join("client/objects")).unwrap();
+        fs::create_dir_all(temp.join("server/refs/heads")).unwrap();
+        fs::create_dir_all(temp.join("server/objects")).unwrap();
+        fs::create_dir_all(temp.join("server/hooks")).unwrap();
+        fs::write(temp.join("server/config"), "[core]\n\tbare = true\n").unwrap();
+        let client = temp.repo().open(temp.join("client"), None);
+        let server = temp.repo().open(temp.join("server"), None);
+
+        let tree = write_object(&client, ObjectType::Tree, b"").unwrap();
+        let commit = create_commit(&client, &tree, Vec::new(), "root\n").unwrap();
+        let mut pack = Vec::new();
+        write_pack(&client, &[commit.clone(), tree], &mut pack).unwrap();
+
+        // The update hook declines the tags, and post-receive sees the rest
+        for (name, script) in [
+            ("update", "case \"$1\" in refs/tags/*) exit 1;; esac\n"),
+            ("post-receive", "cat > post-receive.out\n"),
+        ] {
+            let path = temp.join(format!("server/hooks/{name}"));
+            fs::write(&path, format!("#!/bin/sh\n{script}")).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let commands = [
+            format!("{NULL_HASH} {commit} refs/heads/main\0report-status side-band-64k"),
+            format!("{NULL_HASH} {commit} refs/tags/v1"),
+            format!("{NULL_HASH} {commit} HEAD"),
+        ];
+        let lines = push(&server, &commands, &pack);
+        let report = lines[0].as_ref().unwrap();
+        let mut reader = report.strip_prefix('\x01').unwrap().as_bytes();
+        let mut report = Vec::new();
+        while let Some(line) = read_line(&mut reader).unwrap() {
+            report.push(String::from_utf8(line).unwrap());
+        }
+        assert_eq!(report, [
+            "unpack ok",
+            "ok refs/heads/main",
+            "ng refs/tags/v1 hook declined",
+            "ng HEAD funny refname",
+        ]);
+        assert_eq!(lines[1], None);
+        assert_eq!(
+            resolve_ref(&server, "refs/heads/main").unwrap(),
+            Some(commit.clone())
+        );
+        assert_eq!(resolve_ref(&server, "refs/tags/v1").unwrap(), None);
+        assert_eq!(
+            fs::read_to_string(temp.join("post-receive.out")).unwrap(),
+            format!("{NULL_HASH} {commit} refs/heads/main\n")
+        );
+
+        // A single update refused fails an atomic push
+        let commands = [
+            format!("{commit} {NULL_HASH} refs/heads/main\0report-status atomic"),
+            format!("{NULL_HASH} {commit} refs/tags/v1"),
+        ];
+        // Clients send a pack even if the server has every object
+        let mut empty = Vec::new();
+        write_pack(&client, &[], &mut empty).unwrap();
+        assert_eq!(push(&server, &commands, &empty), [
+            Some("unpack ok".to_string()),
+            Some("ng refs/heads/main atomic push failure".to_string()),
+            Some("ng refs/tags/v1 hook declined".to_string()),
+            None,
+        ]);
+        assert_eq!(
+            resolve_ref(&server, "refs/heads/main").unwrap(),
+            Some(commit)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Shallow negotiation for the pack protocol
+//!
+//! A client asking for a shallow history sends, after its `want` lines, the
+//! commits whose parents it already lacks (`shallow <hash>`) and how far to
+//! deepen the history ([`Deepen`]). The server answers with the commits whose
+//! parents it will not send (`shallow <hash>`) and the previously shallow commits
+//! whose parents it will (`unshallow <hash>`), which the client records in
+//! `.git/shallow` with [`ShallowUpdate::apply`].
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::utils::commit::{peel, read_commit};
+use crate::utils::grafts::{read_shallow, write_shallow};
+use crate::utils::objects::ObjectType;
+use crate::utils::protocol::capabilities::{Capabilities, Flag};
+use crate::utils::repository::Repository;
+
+/// How far a shallow history is deepened: the number of commits kept of
+/// each history (`--depth`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Deepen(pub(crate) u32);
+
+impl Deepen {
+    /// Parse a `deepen` request line.
+    pub(crate) fn parse(line: &str) -> anyhow::Result<Self> {
+        let Some(value) = line.strip_prefix("deepen ") else {
+            anyhow::bail!("invalid deepen line: {line}");
+        };
+        match value.parse() {
+            Ok(depth) if depth > 0 => Ok(Deepen(depth)),
+            _ => anyhow::bail!("invalid depth: {value}"),
+        }
+    }
+
+    /// Check that the server advertised the capability this request needs.
+    pub(crate) fn check_supported(&self, advertised: &Capabilities) -> anyhow::Result<()> {
+        if !advertised.has(Flag::Shallow) {
+            anyhow::bail!("Server does not support shallow clients");
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Deepen {
+    /// Format the request line, without its pkt-line framing
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deepen {}", self.0)
+    }
+}
+
+/// The changes to the shallow commits sent by the server
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ShallowUpdate {
+    /// The commits whose parents are not sent
+    pub(crate) shallow: BTreeSet<String>,
+    /// The shallow commits of the client whose parents are sent
+    pub(crate) unshallow: BTreeSet<String>,
+}
+
+impl ShallowUpdate {
+    /// Add a `shallow <hash>` or `unshallow <hash>` line of the server's answer.
+    pub(crate) fn parse_line(&mut self, line: &str) -> anyhow::Result<()> {
+        match line.split_once(' ') {
+            Some(("shallow", hash)) => self.shallow.insert(hash.to_string()),
+            Some(("unshallow", hash)) => self.unshallow.insert(hash.to_string()),
+            _ => anyhow::bail!("expected shallow/unshallow, got {line}"),
+        };
+        Ok(())
+    }
+
+    /// Compute the answer of the server to a request deepened by a depth: the
+    /// commits at that depth from the wanted ones are shallow, and the client's
+    /// shallow commits above it are not anymore. Wanted tags are peeled, and
+    /// wanted objects other than commits have no history to cut off.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository of the server
+    /// * `wants` - The commits wanted by the client
+    /// * `depth` - The number of commits of each history to send
+    /// * `client_shallow` - The commits whose parents the client lacks
+    pub(crate) fn for_depth(
+        repo: &Repository,
+        wants: &[String],
+        depth: u32,
+        client_shallow: &BTreeSet<String>,
+    ) -> anyhow::Result<Self> {
+        let mut update = ShallowUpdate::default();
+
+        // The shortest distance of each commit from a wanted one, the wanted ones being 1
+        let mut distances: HashMap<String, u32> = HashMap::new();
+        let mut pending: Vec<(String, u32)> = Vec::new();
+        for want in wants {
+            if let (hash, ObjectType::Commit) = peel(repo, want)? {
+                pending.push((hash, 1));
+            }
+        }
+        while let Some((hash, distance)) = pending.pop() {
+            if distances.get(&hash).is_some_and(|&known| known <= distance) {
+                continue;
+            }
+            distances.insert(hash.clone(), distance);
+
+            let parents = read_commit(repo, &hash)?.parents;
+            if distance >= depth {
+                if !parents.is_empty() {
+                    update.shallow.insert(hash);
+                }
+                continue;
+            }
+            // A commit reached again closer to a wanted one is not shallow after all
+            update.shallow.remove(&hash);
+            if client_shallow.contains(&hash) {
+                update.unshallow.insert(hash);
+            }
+            pending.extend(parents.into_iter().map(|parent| (parent, distance + 1)));
+        }
+
+        // The client already lacks the parents of its shallow commits
+        update.shallow.retain(|hash| !client_shallow.contains(hash));
+        Ok(update)
+    }
+
+    /// Record the update in `.git/shallow`.
+    pub(crate) fn apply(&self, repo: &Repository) -> anyhow::Result<()> {
+        let mut shallow = read_shallow(repo)?;
+        shallow.retain(|hash| !self.unshallow.contains(hash));
+        shallow.extend(self.shallow.iter().cloned());
+        write_shallow(repo, &shallow)
+    }
+}
+
+impl fmt::Display for ShallowUpdate {
+    /// Format the answer as `shallow` then `unshallow` lines, without their pkt-line framing
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for hash in &self.shallow {
+            writeln!(f, "shallow {hash}")?;
+        }
+        for hash in &self.unshallow {
+            writeln!(f, "unshallow {hash}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+    use crate::utils::walk::RevWalk;
+
+    #[test]
+    fn parses_and_checks_deepen_requests() {
+        assert_eq!(Deepen::parse("deepen 3").unwrap(), Deepen(3));
+        assert!(Deepen::parse("deepen 0").is_err());
+        assert!(Deepen::parse("deepen").is_err());
+        assert!(Deepen::parse("deepen-since 1700000000").is_err());
+        assert_eq!(Deepen(3).to_string(), "deepen 3");
+
+        assert!(Deepen(1)
+            .check_supported(&Capabilities::parse("shallow").unwrap())
+            .is_ok());
+        let err = Deepen(1)
+            .check_supported(&Capabilities::parse("deepen-since").unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Server does not support shallow clients");
+    }
+
+    #[test]
+    fn deepens_shallow_histories() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com");
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        let one = create_commit(&repo, EMPTY_TREE, Vec::new(), "one\n").unwrap();
+        let two = create_commit(&repo, EMPTY_TREE, vec![one.clone()], "two\n").unwrap();
+        let three = create_commit(&repo, EMPTY_TREE, vec![two.clone()], "three\n").unwrap();
+        let wants = [three.clone()];
+
+        // A clone of depth 2 cuts the history off below `two`
+        let update = ShallowUpdate::for_depth(&repo, &wants, 2, &BTreeSet::new()).unwrap();
+        assert_eq!(update.to_string(), format!("shallow {two}\n"));
+        update.apply(&repo).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.join(".git/shallow")).unwrap(),
+            format!("{two}\n")
+        );
+        let mut walk = RevWalk::new(&repo);
+        walk.push(&three).unwrap();
+        assert_eq!(walk.count(), 2);
+
+        // Deepening past the root makes the repository complete again
+        let shallow = read_shallow(&repo).unwrap();
+        let update = ShallowUpdate::for_depth(&repo, &wants, 10, &shallow).unwrap();
+        let mut parsed = ShallowUpdate::default();
+        for line in update.to_string().lines() {
+            parsed.parse_line(line).unwrap();
+        }
+        assert_eq!(parsed.unshallow, BTreeSet::from([two]));
+        assert!(parsed.shallow.is_empty());
+        parsed.apply(&repo).unwrap();
+        assert!(!fs::exists(temp.join(".git/shallow")).unwrap());
+    }
+}
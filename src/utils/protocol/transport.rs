@@ -0,0 +1,401 @@
+//! The `file://` and `git://` transports: fetching from `upload-pack`
+//!
+//! A local repository is served by a child process talking the pack
+//! protocol over its standard input and output, as it would be over the
+//! network, so only the objects reachable from the refs wanted are sent.
+//! Cloning a plain path instead copies the object directory as is (see
+//! `clone --local`). A `git://` URL is served by a daemon over TCP.
+
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, Stdio};
+
+use anyhow::Context;
+
+use crate::utils::advice::warning;
+use crate::utils::grafts::read_shallow;
+use crate::utils::objects::write_object;
+use crate::utils::pack::{read_pack, PackBuilder};
+use crate::utils::protocol::capabilities::{Capabilities, Flag};
+use crate::utils::protocol::daemon::DEFAULT_PORT;
+use crate::utils::protocol::filter::Filter;
+use crate::utils::protocol::pkt_line::{read_line, write_flush, write_line};
+use crate::utils::protocol::promisor::promisor_remote;
+use crate::utils::protocol::shallow::{Deepen, ShallowUpdate};
+use crate::utils::repository::Repository;
+
+/// A conversation with `upload-pack`, once it advertised its refs
+pub(crate) struct Connection {
+    /// The process serving the repository
+    server: Server,
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+    /// The advertised refs and their hashes, in the order advertised (the
+    /// peeled `<ref>^{}` entries of annotated tags included)
+    pub(crate) refs: Vec<(String, String)>,
+    /// The advertised capabilities
+    pub(crate) capabilities: Capabilities,
+}
+
+/// What runs `upload-pack`
+enum Server {
+    Process(Child),
+    /// A daemon, at the other end of a TCP connection
+    Daemon,
+    /// The test binary cannot serve, so tests serve from a thread instead
+    #[cfg(test)]
+    Thread(std::thread::JoinHandle<anyhow::Result<()>>),
+}
+
+impl Connection {
+    /// Run `upload-pack` on a repository and read its advertisement.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository fetching, whose directory and environment
+    ///   `upload-pack` runs with
+    /// * `path` - The path of the repository
+    /// * `upload_pack` - The shell command to run instead of `git upload-pack`
+    ///   (given the path as its argument), as with `clone --upload-pack`
+    pub(crate) fn open(
+        repo: &Repository,
+        path: &Path,
+        upload_pack: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let mut command = match upload_pack {
+            Some(program) => {
+                let mut command = repo.command("sh");
+                command.args(["-c", &format!("{program} \"$@\""), program]);
+                command
+            },
+            #[cfg(test)]
+            None => return Connection::serve_from_thread(repo, path),
+            #[cfg(not(test))]
+            None => {
+                let mut command = repo.command(std::env::current_exe()?);
+                command.arg("upload-pack");
+                command
+            },
+        };
+        let mut child = command
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("unable to fork")?;
+
+        let reader = Box::new(child.stdout.take().context("open upload-pack output")?);
+        let writer = Box::new(child.stdin.take().context("open upload-pack input")?);
+        Connection::start(Server::Process(child), reader, writer)
+    }
+
+    /// Connect to a repository served by a daemon
+    /// (`git://<host>[:<port>]/<path>`) and read its advertisement.
+    pub(crate) fn connect(url: &str) -> anyhow::Result<Self> {
+        let rest = url
+            .strip_prefix("git://")
+            .context(format!("'{url}' is not a git:// URL"))?;
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if host.is_empty() || path.is_empty() {
+            anyhow::bail!("no path specified in '{url}'");
+        }
+        // The port may be left out, also after a bracketed IPv6 address
+        let has_port = host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+        let address = match has_port {
+            true => host.to_string(),
+            false => format!("{host}:{DEFAULT_PORT}"),
+        };
+
+        let stream =
+            TcpStream::connect(&address).context(format!("unable to connect to {host}"))?;
+        let mut writer = stream.try_clone()?;
+        write_line(
+            &mut writer,
+            format!("git-upload-pack {path}\0host={host}\0").as_bytes(),
+        )?;
+        Connection::start(Server::Daemon, Box::new(stream), Box::new(writer))
+    }
+
+    #[cfg(test)]
+    fn serve_from_thread(repo: &Repository, path: &Path) -> anyhow::Result<Self> {
+        use crate::utils::protocol::upload_pack::{serve, ServeOptions};
+        use crate::utils::transfer::open_repository;
+
+        let (client_reader, mut server_writer) = std::io::pipe()?;
+        let (mut server_reader, client_writer) = std::io::pipe()?;
+        let path = path.to_string_lossy().into_owned();
+        let repo = repo.clone();
+        let thread = std::thread::spawn(move || {
+            let source = open_repository(&repo, &path)?;
+            let options = ServeOptions::default();
+            serve(
+                &source.repo,
+                options,
+                &mut server_reader,
+                &mut server_writer,
+            )
+        });
+        Connection::start(
+            Server::Thread(thread),
+            Box::new(client_reader),
+            Box::new(client_writer),
+        )
+    }
+
+    fn start(
+        server: Server,
+        reader: Box<dyn Read + Send>,
+        writer: Box<dyn Write + Send>,
+    ) -> anyhow::Result<Self> {
+        let mut connection = Connection {
+            server,
+            reader: BufReader::new(reader),
+            writer,
+            refs: Vec::new(),
+            capabilities: Capabilities::default(),
+        };
+
+        while let Some(line) = read_line(&mut connection.reader)? {
+            let line = String::from_utf8_lossy(&line).into_owned();
+            if let Some(message) = line.strip_prefix("ERR ") {
+                anyhow::bail!("remote error: {message}");
+            }
+            let line = match line.split_once('\0') {
+                Some((line, capabilities)) => {
+                    connection.capabilities = Capabilities::parse(capabilities)?;
+                    line.to_string()
+                },
+                None => line,
+            };
+            let (hash, name) = line
+                .split_once(' ')
+                .context(format!("protocol error: unexpected '{line}'"))?;
+            if name != "capabilities^{}" {
+                connection.refs.push((name.to_string(), hash.to_string()));
+            }
+        }
+        Ok(connection)
+    }
+
+    /// The ref HEAD points to, as advertised (`symref=HEAD:<ref>`).
+    pub(crate) fn head_symref(&self) -> Option<&str> {
+        self.capabilities
+            .symrefs
+            .iter()
+            .find(|(name, _)| name == "HEAD")
+            .map(|(_, target)| target.as_str())
+    }
+
+    /// Fetch the objects reachable from some objects into a repository, then
+    /// end the conversation.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository fetching
+    /// * `wants` - The objects wanted
+    /// * `deepen` - How far the history is fetched, the commits it is cut off
+    ///   at being recorded in `.git/shallow` (the whole history by default)
+    /// * `filter` - The objects left out, fetched on demand from the promisor
+    ///   remote of the repository (ignored with a warning if the server cannot
+    ///   filter)
+    ///
+    /// # Returns
+    ///
+    /// The number of objects received
+    pub(crate) fn fetch(
+        mut self,
+        repo: &Repository,
+        wants: &[String],
+        deepen: Option<Deepen>,
+        filter: Option<&Filter>,
+    ) -> anyhow::Result<usize> {
+        let mut preferred = Vec::new();
+        if let Some(deepen) = deepen {
+            deepen.check_supported(&self.capabilities)?;
+            preferred.push(Flag::Shallow);
+        }
+        let filter = match filter {
+            Some(_) if !Filter::supported_by(&self.capabilities) => {
+                warning("filtering not recognized by server, ignoring");
+                None
+            },
+            filter => filter,
+        };
+        if filter.is_some() {
+            preferred.push(Flag::Filter);
+        }
+        let requested = self
+            .capabilities
+            .select(&preferred, &Capabilities::default_agent(repo.env()))
+            .with_session_id(repo, Some(&self.capabilities))?;
+        for (index, want) in wants.iter().enumerate() {
+            let line = match index {
+                0 => format!("want {want} {requested}\n"),
+                _ => format!("want {want}\n"),
+            };
+            write_line(&mut self.writer, line.as_bytes())?;
+        }
+        // The commits whose parents are lacking are told, so that the server
+        // sends their history if deepened past them
+        if let Some(deepen) = deepen {
+            for hash in read_shallow(repo)? {
+                write_line(&mut self.writer, format!("shallow {hash}\n").as_bytes())?;
+            }
+            write_line(&mut self.writer, format!("{deepen}\n").as_bytes())?;
+        }
+        if let Some(filter) = filter {
+            write_line(&mut self.writer, format!("filter {filter}\n").as_bytes())?;
+        }
+        write_flush(&mut self.writer)?;
+        if wants.is_empty() {
+            self.finish()?;
+            return Ok(0);
+        }
+        // upload-pack may refuse the wants (with an `ERR` line) and exit before
+        // reading `done`, so its answer is read even if `done` cannot be sent
+        let sent = write_line(&mut self.writer, b"done\n")
+            .and_then(|()| self.writer.flush().map_err(anyhow::Error::from));
+
+        // The shallow commits come first when deepening, ending with a flush
+        let mut update = None;
+        if deepen.is_some() {
+            let update = update.insert(ShallowUpdate::default());
+            while let Some(line) = read_line(&mut self.reader)? {
+                let line = String::from_utf8_lossy(&line);
+                if let Some(message) = line.strip_prefix("ERR ") {
+                    anyhow::bail!("remote error: {message}");
+                }
+                update.parse_line(&line)?;
+            }
+        }
+        match read_line(&mut self.reader)? {
+            Some(line) if line == b"NAK" || line.starts_with(b"ACK ") => {},
+            Some(line) if line.starts_with(b"ERR ") => {
+                anyhow::bail!("remote error: {}", String::from_utf8_lossy(&line[4..]))
+            },
+            Some(line) => anyhow::bail!(
+                "git fetch-pack: expected ACK/NAK, got '{}'",
+                String::from_utf8_lossy(&line)
+            ),
+            None => anyhow::bail!("git fetch-pack: expected ACK/NAK, got a flush packet"),
+        }
+        sent?;
+        let mut pack = Vec::new();
+        self.reader
+            .read_to_end(&mut pack)
+            .context("read pack from upload-pack")?;
+        self.finish()?;
+
+        // The objects of promisor remotes are kept apart in a pack marked as
+        // such, so that the objects they lack are known to be fetched on demand
+        let objects = read_pack(repo, &pack)?;
+        if filter.is_some() || promisor_remote(repo)?.is_some() {
+            let mut pack = PackBuilder::new();
+            for (object_type, content) in &objects {
+                pack.add(*object_type, content)?;
+            }
+            pack.store(repo, true)?;
+        } else {
+            for (object_type, content) in &objects {
+                write_object(repo, *object_type, content)?;
+            }
+        }
+        if let Some(update) = update {
+            update.apply(repo)?;
+        }
+        Ok(objects.len())
+    }
+
+    /// Close the input of `upload-pack` and wait for it to exit.
+    fn finish(self) -> anyhow::Result<()> {
+        drop(self.writer);
+        match self.server {
+            Server::Process(mut child) => {
+                let status = child.wait().context("wait for upload-pack")?;
+                if !status.success() {
+                    anyhow::bail!("upload-pack failed");
+                }
+                Ok(())
+            },
+            Server::Daemon => Ok(()),
+            #[cfg(test)]
+            Server::Thread(thread) => thread.join().expect("upload-pack thread panicked"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::objects::{read_object, ObjectType};
+    use crate::utils::refs::{write_ref, write_symref};
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn fetches_from_upload_pack() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b");
+        fs::create_dir_all(temp.join("src/refs/heads")).unwrap();
+        fs::create_dir_all(temp.join("dst/objects")).unwrap();
+        let source = temp.repo().open(temp.join("src"), None);
+        let dest = temp.repo().open(temp.join("dst"), None);
+        write_symref(&source, "HEAD", "refs/heads/main").unwrap();
+        let tree = write_object(&source, ObjectType::Tree, b"").unwrap();
+        let commit = create_commit(&source, &tree, Vec::new(), "root\n").unwrap();
+        write_ref(&source, "refs/heads/main", &commit).unwrap();
+        let tag = write_object(
+            &source,
+            ObjectType::Tag,
+            format!("object {commit}\ntype commit\ntag v1\ntagger a <a@b> 0 +0000\n\nv1\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        write_ref(&source, "refs/tags/v1", &tag).unwrap();
+
+        let path = temp.join("src");
+        let connection = Connection::open(&dest, &path, None).unwrap();
+        assert_eq!(connection.head_symref(), Some("refs/heads/main"));
+        assert_eq!(connection.refs, [
+            ("HEAD".to_string(), commit.clone()),
+            ("refs/heads/main".to_string(), commit.clone()),
+            ("refs/tags/v1".to_string(), tag.clone()),
+            ("refs/tags/v1^{}".to_string(), commit.clone()),
+        ]);
+        // The tag, the commit and its tree
+        assert_eq!(
+            connection
+                .fetch(&dest, std::slice::from_ref(&tag), None, None)
+                .unwrap(),
+            3
+        );
+        assert!(read_object(&dest, &commit).is_ok());
+
+        // Only the objects of advertised refs may be wanted
+        let blob = write_object(&source, ObjectType::Blob, b"hidden\n").unwrap();
+        let connection = Connection::open(&dest, &path, None).unwrap();
+        assert_eq!(
+            connection
+                .fetch(&dest, std::slice::from_ref(&blob), None, None)
+                .unwrap_err()
+                .to_string(),
+            format!("remote error: git upload-pack: not our ref {blob}")
+        );
+        assert_eq!(
+            Connection::open(&dest, &path, None)
+                .unwrap()
+                .fetch(&dest, &[], None, None)
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,1044 @@
+//! The server side of fetches (upload-pack)
+//!
+//! Clients may only want the objects the server advertised, unless the
+//! `uploadpack.*` settings allow more: the tips of every ref
+//! (`allowTipSHA1InWant`), any commit reachable from them
+//! (`allowReachableSHA1InWant`), or any object (`allowAnySHA1InWant`). Object
+//! filters are only accepted with `uploadpack.allowFilter`, and each kind of
+//! filter can be refused with `uploadpackfilter.allow` and
+//! `uploadpackfilter.<kind>.allow` (`tree` filters being limited to
+//! `uploadpackfilter.tree.maxDepth`). Shallow clients get the history down to
+//! the depth they ask for (see [`crate::utils::protocol::shallow`]).
+//!
+//! The objects to send are found with the reachability bitmaps of a pack when
+//! it has bitmaps of every commit wanted and had, rather than by walking the
+//! history and the trees.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::{Read, Write};
+
+use crate::utils::advice::warning;
+use crate::utils::bitmap::PackBitmap;
+use crate::utils::commit::{peel, Commit, Tag};
+use crate::utils::config::Config;
+use crate::utils::objects::{object_exists, read_original_object, ObjectType};
+use crate::utils::pack::write_pack;
+use crate::utils::protocol::capabilities::{Capabilities, Flag, ObjectFormat};
+use crate::utils::protocol::filter::Filter;
+use crate::utils::protocol::pkt_line::{read_line, write_band, write_flush, write_line};
+use crate::utils::protocol::shallow::{Deepen, ShallowUpdate};
+use crate::utils::refs::{head_branch, list_refs, resolve_ref};
+use crate::utils::repository::Repository;
+use crate::utils::tree::{parse_tree, MODE_GITLINK};
+use crate::utils::walk::is_ancestor;
+
+/// What clients of a repository may ask for
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UploadPack {
+    /// Whether the tips of refs that are not advertised may be wanted
+    allow_tip: bool,
+    /// Whether the commits reachable from a ref may be wanted
+    allow_reachable: bool,
+    /// Whether any object may be wanted
+    allow_any: bool,
+    /// Whether clients may filter the objects sent
+    allow_filter: bool,
+    /// Whether the kinds of filters not configured are accepted
+    allow_filters: bool,
+    /// The kinds of filters configured, and whether they are accepted
+    filters: Vec<(String, bool)>,
+    /// The highest depth of `tree` filters
+    max_tree_depth: Option<u64>,
+}
+
+impl UploadPack {
+    /// Load the settings of a repository.
+    pub(crate) fn new(repo: &Repository) -> anyhow::Result<Self> {
+        let config = Config::load(repo)?;
+        let flag = |key: &str| -> anyhow::Result<bool> {
+            Ok(config
+                .get_bool(&format!("uploadpack.{key}"))?
+                .unwrap_or(false))
+        };
+
+        let mut filters = Vec::new();
+        for kind in ["blob:none", "blob:limit", "tree", "object:type", "combine"] {
+            let key = format!("uploadpackfilter.{kind}.allow");
+            if let Some(allowed) = config.get_bool(&key)? {
+                filters.push((kind.to_string(), allowed));
+            }
+        }
+        let max_tree_depth = match config.get("uploadpackfilter.tree.maxDepth") {
+            Some(value) => Some(value.parse().map_err(|_| {
+                anyhow::anyhow!(
+                    "bad numeric config value '{value}' for 'uploadpackfilter.tree.maxdepth'"
+                )
+            })?),
+            None => None,
+        };
+
+        Ok(UploadPack {
+            allow_tip: flag("allowTipSHA1InWant")?,
+            allow_reachable: flag("allowReachableSHA1InWant")?,
+            allow_any: flag("allowAnySHA1InWant")?,
+            allow_filter: flag("allowFilter")?,
+            allow_filters: config.get_bool("uploadpackfilter.allow")?.unwrap_or(true),
+            filters,
+            max_tree_depth,
+        })
+    }
+
+    /// Add the capabilities telling clients what they may ask for.
+    pub(crate) fn advertise(&self, mut capabilities: Capabilities) -> Capabilities {
+        if self.allow_tip || self.allow_any {
+            capabilities.insert(Flag::AllowTipSha1InWant);
+        }
+        if self.allow_reachable || self.allow_any {
+            capabilities.insert(Flag::AllowReachableSha1InWant);
+        }
+        if self.allow_filter {
+            capabilities.insert(Flag::Filter);
+        }
+        capabilities
+    }
+
+    /// Check that a client may have the objects it wants.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository of the server
+    /// * `advertised` - The hashes of the refs that were advertised
+    /// * `wants` - The objects the client wants
+    pub(crate) fn check_wants(
+        &self,
+        repo: &Repository,
+        advertised: &[String],
+        wants: &[String],
+    ) -> anyhow::Result<()> {
+        let mut tips: Option<Vec<String>> = None;
+        for want in wants {
+            if advertised.contains(want) {
+                continue;
+            }
+            let exists = object_exists(repo, want)?;
+            if self.allow_any && exists {
+                continue;
+            }
+
+            let tips = match &mut tips {
+                Some(tips) => tips,
+                None => {
+                    let mut list: Vec<String> = list_refs(repo, "refs/")?
+                        .into_iter()
+                        .map(|(_, hash)| hash)
+                        .collect();
+                    list.extend(resolve_ref(repo, "HEAD")?);
+                    tips.insert(list)
+                },
+            };
+            if self.allow_tip && tips.contains(want) {
+                continue;
+            }
+            if self.allow_reachable && exists && is_reachable(repo, want, tips)? {
+                continue;
+            }
+            anyhow::bail!("git upload-pack: not our ref {want}");
+        }
+        Ok(())
+    }
+
+    /// Check that a client may filter the objects sent with a filter.
+    pub(crate) fn check_filter(&self, filter: &Filter) -> anyhow::Result<()> {
+        if !self.allow_filter {
+            anyhow::bail!("git upload-pack: filtering capability not negotiated");
+        }
+
+        let kind = filter_kind(filter);
+        let allowed = self
+            .filters
+            .iter()
+            .rev()
+            .find(|(name, _)| name == kind)
+            .map_or(self.allow_filters, |(_, allowed)| *allowed);
+        if !allowed {
+            anyhow::bail!("filter '{kind}' not supported");
+        }
+        match filter {
+            Filter::TreeDepth(depth) => {
+                if let Some(max) = self.max_tree_depth.filter(|max| depth > max) {
+                    anyhow::bail!("tree filter allows max depth {max}, but got {depth}");
+                }
+            },
+            Filter::Combine(filters) => {
+                for filter in filters {
+                    self.check_filter(filter)?;
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+/// How a fetch is served
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ServeOptions {
+    /// Only advertise the refs, then end (`--advertise-refs`)
+    pub(crate) advertise_refs: bool,
+    /// Serve a single request of a stateless conversation, as over HTTP: the
+    /// refs are not advertised, and the conversation ends after one round of
+    /// negotiation unless the client is done (`--stateless-rpc`)
+    pub(crate) stateless_rpc: bool,
+}
+
+/// The flags advertised whatever the settings. Packs are sent without deltas,
+/// so clients accepting thin packs or offset deltas get full objects anyway.
+const FLAGS: &[Flag] = &[
+    Flag::MultiAck,
+    Flag::MultiAckDetailed,
+    Flag::ThinPack,
+    Flag::SideBand,
+    Flag::SideBand64k,
+    Flag::OfsDelta,
+    Flag::Shallow,
+    Flag::NoProgress,
+    Flag::IncludeTag,
+];
+
+/// How the objects a client has are acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AckMode {
+    /// The first common object only
+    Single,
+    /// Every common object (`multi_ack`)
+    Multi,
+    /// Every common object, and when the server is ready to send the pack
+    /// (`multi_ack_detailed`)
+    Detailed,
+}
+
+/// Serve a fetch of a repository (protocol v0): advertise the refs, read
+/// the objects the client wants and has, then send the pack.
+///
+/// # Arguments
+///
+/// * `repo` - The repository served
+/// * `options` - How the fetch is served
+/// * `input` - Where the requests of the client are read from
+/// * `output` - Where the answers are written to
+pub(crate) fn serve<R, W>(
+    repo: &Repository,
+    options: ServeOptions,
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let settings = UploadPack::new(repo)?;
+    let advertised = advertise_refs(repo)?;
+    let mut capabilities = Capabilities::default();
+    for &flag in FLAGS {
+        capabilities.insert(flag);
+    }
+    // Stateless clients cannot wait for the pack after a round without `done`
+    if options.stateless_rpc {
+        capabilities.insert(Flag::NoDone);
+    }
+    capabilities.agent = Some(Capabilities::default_agent(repo.env()));
+    capabilities.object_format = Some(ObjectFormat::Sha1);
+    let mut capabilities = settings
+        .advertise(capabilities)
+        .with_session_id(repo, None)?;
+    if let Some(branch) = head_branch(repo)? {
+        capabilities.symrefs.push(("HEAD".to_string(), branch));
+    }
+
+    if !options.stateless_rpc || options.advertise_refs {
+        let zero = "0".repeat(40);
+        let first = advertised
+            .first()
+            .map_or((zero.as_str(), "capabilities^{}"), |(name, hash)| {
+                (hash.as_str(), name.as_str())
+            });
+        write_line(
+            output,
+            format!("{} {}\0{capabilities}\n", first.0, first.1).as_bytes(),
+        )?;
+        for (name, hash) in advertised.iter().skip(1) {
+            write_line(output, format!("{hash} {name}\n").as_bytes())?;
+        }
+        write_flush(output)?;
+        output.flush()?;
+    }
+    if options.advertise_refs {
+        return Ok(());
+    }
+
+    // Clients only listing the refs end the conversation right away
+    let mut wants = Vec::new();
+    let mut requested = Capabilities::default();
+    let mut filter = None;
+    let mut client_shallow = BTreeSet::new();
+    let mut deepen = None;
+    while let Some(line) = read_line(input)? {
+        let line = String::from_utf8_lossy(&line).into_owned();
+        if let Some(want) = line.strip_prefix("want ") {
+            let (hash, list) = want.split_once(' ').unwrap_or((want, ""));
+            if wants.is_empty() {
+                requested = Capabilities::parse(list)?;
+                capabilities.validate_request(&requested)?;
+            }
+            wants.push(hash.to_string());
+        } else if let Some(spec) = line.strip_prefix("filter ") {
+            let parsed = Filter::parse(spec)?;
+            settings.check_filter(&parsed)?;
+            filter = Some(parsed);
+        } else if let Some(hash) = line.strip_prefix("shallow ") {
+            client_shallow.insert(hash.to_string());
+        } else if line.starts_with("deepen") {
+            deepen = Some(Deepen::parse(&line)?);
+        } else {
+            anyhow::bail!(
+                "git upload-pack: protocol error, expected to get object ID, not '{line}'"
+            );
+        }
+    }
+    if wants.is_empty() {
+        return Ok(());
+    }
+    let tips: Vec<String> = advertised.into_iter().map(|(_, hash)| hash).collect();
+    if let Err(err) = settings.check_wants(repo, &tips, &wants) {
+        // The client is told why, as the error of the server is not its own
+        write_line(output, format!("ERR {err}\n").as_bytes())?;
+        output.flush()?;
+        return Err(err);
+    }
+
+    // The history is cut off at the depth asked for, which is told before negotiating
+    let mut shallow = client_shallow.clone();
+    if let Some(Deepen(depth)) = deepen {
+        let update = ShallowUpdate::for_depth(repo, &wants, depth, &client_shallow)?;
+        for line in update.to_string().lines() {
+            write_line(output, format!("{line}\n").as_bytes())?;
+        }
+        write_flush(output)?;
+        output.flush()?;
+        shallow.retain(|hash| !update.unshallow.contains(hash));
+        shallow.extend(update.shallow);
+    }
+
+    let Some(common) = negotiate(repo, &requested, options, &wants, input, output)? else {
+        return Ok(());
+    };
+    let mut objects = objects_to_send(
+        repo,
+        &wants,
+        &common,
+        filter.as_ref(),
+        &client_shallow,
+        &shallow,
+    )?;
+    if requested.has(Flag::IncludeTag) {
+        include_tags(repo, &mut objects)?;
+    }
+    send_pack(repo, &requested, &objects, output)
+}
+
+/// Read the objects the client has, acknowledging those the server has too,
+/// until the client is done or the server is ready to send the pack.
+///
+/// Without `multi_ack`, only the first common object is acknowledged, and
+/// `NAK` answers each round until one is found. With it, every common object
+/// is acknowledged (`ACK <hash> continue`, or `ACK <hash> common` with
+/// `multi_ack_detailed`), and the server tells when the common objects cover
+/// every commit wanted (`ACK <hash> ready`), `NAK` ending each round.
+///
+/// # Returns
+///
+/// The common objects, or `None` if the round of a stateless request ended
+/// without the pack being sent
+fn negotiate<R, W>(
+    repo: &Repository,
+    requested: &Capabilities,
+    options: ServeOptions,
+    wants: &[String],
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<Option<Vec<String>>>
+where
+    R: Read,
+    W: Write,
+{
+    let mode = match (
+        requested.has(Flag::MultiAckDetailed),
+        requested.has(Flag::MultiAck),
+    ) {
+        (true, _) => AckMode::Detailed,
+        (false, true) => AckMode::Multi,
+        (false, false) => AckMode::Single,
+    };
+    let mut common: Vec<String> = Vec::new();
+    let (mut got_common, mut got_other, mut sent_ready) = (false, false, false);
+    // Once covered, the wanted commits stay so as common objects are added
+    let mut covered = false;
+
+    loop {
+        let Some(line) = read_line(input)? else {
+            let last = common.last().cloned().unwrap_or_default();
+            if mode == AckMode::Detailed && got_common && !got_other {
+                covered = covered || can_give_up(repo, wants, &common)?;
+                if covered {
+                    sent_ready = true;
+                    write_line(output, format!("ACK {last} ready\n").as_bytes())?;
+                }
+            }
+            if common.is_empty() || mode != AckMode::Single {
+                write_line(output, b"NAK\n")?;
+            }
+            if sent_ready && requested.has(Flag::NoDone) {
+                write_line(output, format!("ACK {last}\n").as_bytes())?;
+                return Ok(Some(common));
+            }
+            output.flush()?;
+            if options.stateless_rpc {
+                return Ok(None);
+            }
+            (got_common, got_other) = (false, false);
+            continue;
+        };
+        let line = String::from_utf8_lossy(&line).into_owned();
+        if line == "done" {
+            match common.last() {
+                Some(hash) if mode != AckMode::Single => {
+                    write_line(output, format!("ACK {hash}\n").as_bytes())?
+                },
+                Some(_) => {},
+                None => write_line(output, b"NAK\n")?,
+            }
+            return Ok(Some(common));
+        }
+        let Some(hash) = line.strip_prefix("have ") else {
+            anyhow::bail!("git upload-pack: expected SHA1 list, got '{line}'");
+        };
+
+        if object_exists(repo, hash)? {
+            got_common = true;
+            if !common.iter().any(|known| known == hash) {
+                common.push(hash.to_string());
+            }
+            let ack = match mode {
+                AckMode::Detailed => format!("ACK {hash} common\n"),
+                AckMode::Multi => format!("ACK {hash} continue\n"),
+                AckMode::Single if common.len() == 1 => format!("ACK {hash}\n"),
+                AckMode::Single => continue,
+            };
+            write_line(output, ack.as_bytes())?;
+        } else {
+            got_other = true;
+            if mode != AckMode::Single {
+                covered = covered || can_give_up(repo, wants, &common)?;
+                if covered {
+                    let ack = match mode {
+                        AckMode::Detailed => {
+                            sent_ready = true;
+                            format!("ACK {hash} ready\n")
+                        },
+                        _ => format!("ACK {hash} continue\n"),
+                    };
+                    write_line(output, ack.as_bytes())?;
+                }
+            }
+        }
+    }
+}
+
+/// Whether the common objects cover every commit wanted (each one has a
+/// common commit in its history), so that more objects the client has would
+/// not make the pack smaller by much. Wanted objects other than commits are
+/// covered by nature.
+fn can_give_up(repo: &Repository, wants: &[String], common: &[String]) -> anyhow::Result<bool> {
+    if common.is_empty() {
+        return Ok(false);
+    }
+    let mut commits = Vec::new();
+    for hash in common {
+        if let (hash, ObjectType::Commit) = peel(repo, hash)? {
+            commits.push(hash);
+        }
+    }
+    for want in wants {
+        let (want, object_type) = peel(repo, want)?;
+        if object_type != ObjectType::Commit {
+            continue;
+        }
+        let mut covered = false;
+        for commit in &commits {
+            if is_ancestor(repo, commit, &want)? {
+                covered = true;
+                break;
+            }
+        }
+        if !covered {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Add the annotated tags of `refs/tags/` that point to the objects sent
+/// (`include-tag`), so that the client gets the tags of what it fetches.
+fn include_tags(repo: &Repository, objects: &mut Vec<String>) -> anyhow::Result<()> {
+    let mut sent: HashSet<String> = objects.iter().cloned().collect();
+    for (_, hash) in list_refs(repo, "refs/tags/")? {
+        if sent.contains(&hash) || read_original_object(repo, &hash)?.0 != ObjectType::Tag {
+            continue;
+        }
+        if sent.contains(&peel(repo, &hash)?.0) {
+            sent.insert(hash.clone());
+            objects.push(hash);
+        }
+    }
+    Ok(())
+}
+
+/// Send the pack of the objects, on band 1 of the side-band channel along
+/// with progress messages on band 2 if the client asked for it (the pack is
+/// sent as is otherwise).
+fn send_pack<W>(
+    repo: &Repository,
+    requested: &Capabilities,
+    objects: &[String],
+    output: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    let max = match (
+        requested.has(Flag::SideBand64k),
+        requested.has(Flag::SideBand),
+    ) {
+        (true, _) => 65515,
+        (false, true) => 995,
+        (false, false) => {
+            write_pack(repo, objects, output)?;
+            return Ok(output.flush()?);
+        },
+    };
+    let progress = !requested.has(Flag::NoProgress);
+
+    let count = objects.len();
+    if progress {
+        let message = format!("Enumerating objects: {count}, done.\n");
+        write_band(output, 2, message.as_bytes(), max)?;
+    }
+    let mut pack = Vec::new();
+    if let Err(err) = write_pack(repo, objects, &mut pack) {
+        // The client shows the error of the server, then gives up
+        write_band(output, 3, format!("{err}\n").as_bytes(), max)?;
+        output.flush()?;
+        return Err(err);
+    }
+    write_band(output, 1, &pack, max)?;
+    if progress {
+        let message = format!("Total {count} (delta 0), reused 0 (delta 0), pack-reused 0\n");
+        write_band(output, 2, message.as_bytes(), max)?;
+    }
+    write_flush(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// The refs advertised to clients: HEAD, then every ref followed by the
+/// object it peels to if it is an annotated tag (`<ref>^{}`).
+fn advertise_refs(repo: &Repository) -> anyhow::Result<Vec<(String, String)>> {
+    let mut advertised = Vec::new();
+    if let Some(hash) = resolve_ref(repo, "HEAD")? {
+        advertised.push(("HEAD".to_string(), hash));
+    }
+    for (name, hash) in list_refs(repo, "refs/")? {
+        let peeled = match read_original_object(repo, &hash) {
+            Ok((ObjectType::Tag, _)) => Some(peel(repo, &hash)?.0),
+            _ => None,
+        };
+        advertised.push((name.clone(), hash));
+        if let Some(peeled) = peeled {
+            advertised.push((format!("{name}^{{}}"), peeled));
+        }
+    }
+    Ok(advertised)
+}
+
+/// Whether a commit is reachable from one of the tips of the refs.
+fn is_reachable(repo: &Repository, hash: &str, tips: &[String]) -> anyhow::Result<bool> {
+    if read_original_object(repo, hash)?.0 != ObjectType::Commit {
+        return Ok(false);
+    }
+    for tip in tips {
+        let (tip, object_type) = peel(repo, tip)?;
+        if object_type == ObjectType::Commit && is_ancestor(repo, hash, &tip)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The name of the kind of a filter in the `uploadpackfilter.<kind>.allow` settings.
+fn filter_kind(filter: &Filter) -> &'static str {
+    match filter {
+        Filter::BlobNone => "blob:none",
+        Filter::BlobLimit(_) => "blob:limit",
+        Filter::TreeDepth(_) => "tree",
+        Filter::ObjectType(_) => "object:type",
+        Filter::Combine(_) => "combine",
+    }
+}
+
+/// List the objects to send to a client: those reachable from the objects it
+/// wants but not from the ones it has, that the filter includes. The wanted
+/// objects themselves are always sent.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the server
+/// * `wants` - The objects the client wants
+/// * `haves` - The objects the client has (those the server lacks are ignored)
+/// * `filter` - The filter of the client, if any
+/// * `client_shallow` - The commits whose parents the client lacks
+/// * `shallow` - The commits whose parents are not sent
+pub(crate) fn objects_to_send(
+    repo: &Repository,
+    wants: &[String],
+    haves: &[String],
+    filter: Option<&Filter>,
+    client_shallow: &BTreeSet<String>,
+    shallow: &BTreeSet<String>,
+) -> anyhow::Result<Vec<String>> {
+    let mut excluded = HashSet::new();
+    let mut known = Vec::new();
+    for hash in haves {
+        if object_exists(repo, hash)? {
+            known.push(hash.clone());
+        }
+    }
+    let haves = known;
+    if client_shallow.is_empty() && shallow.is_empty() {
+        if let Some(objects) = objects_from_bitmaps(repo, wants, &haves, filter)? {
+            return Ok(objects);
+        }
+    }
+    walk(
+        repo,
+        &haves,
+        None,
+        client_shallow,
+        &mut excluded,
+        &mut |_| {},
+    )?;
+
+    let mut objects = Vec::new();
+    walk(repo, wants, filter, shallow, &mut excluded, &mut |hash| {
+        objects.push(hash)
+    })?;
+    Ok(objects)
+}
+
+/// List the objects to send with the bitmaps of a pack, if one has bitmaps of
+/// every commit wanted and had (wanted tags being sent along with the objects
+/// reachable from what they point to). Only `blob:none` filters are applied
+/// to bitmaps, the others depending on the trees.
+///
+/// # Returns
+///
+/// The objects to send, or `None` if they have to be walked instead
+fn objects_from_bitmaps(
+    repo: &Repository,
+    wants: &[String],
+    haves: &[String],
+    filter: Option<&Filter>,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let skip = match filter {
+        None => &[][..],
+        Some(Filter::BlobNone) => &[ObjectType::Blob][..],
+        Some(_) => return Ok(None),
+    };
+    let mut tags = Vec::new();
+    let mut include = Vec::new();
+    for want in wants {
+        let mut hash = want.clone();
+        loop {
+            match read_original_object(repo, &hash)? {
+                (ObjectType::Commit, _) => break,
+                (ObjectType::Tag, content) => {
+                    let object = Tag::parse(&content)?.object;
+                    tags.push(std::mem::replace(&mut hash, object));
+                },
+                _ => return Ok(None),
+            }
+        }
+        include.push(hash);
+    }
+    let mut exclude = Vec::new();
+    for have in haves {
+        match peel(repo, have)? {
+            (hash, ObjectType::Commit) => exclude.push(hash),
+            _ => return Ok(None),
+        }
+    }
+
+    for pack in repo.packs(false)? {
+        let bitmap = match PackBitmap::load(&pack) {
+            Ok(Some(bitmap)) => bitmap,
+            Ok(None) => continue,
+            Err(err) => {
+                warning(&format!("ignoring bitmap: {err:#}"));
+                continue;
+            },
+        };
+        if let Some(mut objects) = bitmap.reachable(&include, &exclude, skip) {
+            objects.extend(tags);
+            return Ok(Some(objects));
+        }
+    }
+    Ok(None)
+}
+
+/// Walk the objects reachable from some objects, skipping the ones already seen.
+///
+/// # Arguments
+///
+/// * `repo` - The repository holding the objects
+/// * `starts` - The objects to start from, which are always included
+/// * `filter` - The filter deciding which of the other objects are included
+/// * `shallow` - The commits whose parents are not walked
+/// * `seen` - The objects already walked
+/// * `include` - Called with each object included
+fn walk(
+    repo: &Repository,
+    starts: &[String],
+    filter: Option<&Filter>,
+    shallow: &BTreeSet<String>,
+    seen: &mut HashSet<String>,
+    include: &mut dyn FnMut(String),
+) -> anyhow::Result<()> {
+    // Trees as deep as the filter allows are not walked, as nothing below them is sent
+    let max_depth = filter.and_then(Filter::max_depth);
+    let mut pending: Vec<(String, u64, bool)> = starts
+        .iter()
+        .rev()
+        .map(|hash| (hash.clone(), 0, true))
+        .collect();
+    while let Some((hash, depth, wanted)) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let (object_type, content) = read_original_object(repo, &hash)?;
+        let included = wanted
+            || filter
+                .is_none_or(|filter| filter.includes(object_type, content.len() as u64, depth));
+
+        match object_type {
+            ObjectType::Commit => {
+                let commit = Commit::parse(&content)?;
+                if !shallow.contains(&hash) {
+                    pending.extend(commit.parents.into_iter().map(|hash| (hash, 0, false)));
+                }
+                pending.push((commit.tree, 0, false));
+            },
+            ObjectType::Tree if max_depth.is_none_or(|max| depth + 1 < max) => {
+                for entry in parse_tree(&content)?.into_iter().rev() {
+                    if entry.mode != MODE_GITLINK {
+                        pending.push((entry.hash, depth + 1, false));
+                    }
+                }
+            },
+            ObjectType::Tag => pending.push((Tag::parse(&content)?.object, 0, false)),
+            _ => {},
+        }
+        if included {
+            include(hash);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::index::{Index, IndexEntry};
+    use crate::utils::objects::write_object;
+    use crate::utils::refs::write_ref;
+    use crate::utils::test::TempRepo;
+
+    /// Create two commits on `main`, the second adding `dir/file`.
+    fn setup() -> (TempRepo, Repository, [String; 2]) {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "a")
+            .env(env::GIT_AUTHOR_EMAIL, "a@b")
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+
+        let mut index = Index::default();
+        let blob = write_object(&repo, ObjectType::Blob, b"first\n").unwrap();
+        index.add_entry(IndexEntry::new("top".into(), 0o100644, blob));
+        let tree = index.write_tree(&repo, false).unwrap();
+        let first = create_commit(&repo, &tree, Vec::new(), "first\n").unwrap();
+        let blob = write_object(&repo, ObjectType::Blob, b"second\n").unwrap();
+        index.add_entry(IndexEntry::new("dir/file".into(), 0o100644, blob));
+        let tree = index.write_tree(&repo, false).unwrap();
+        let second = create_commit(&repo, &tree, vec![first.clone()], "second\n").unwrap();
+        write_ref(&repo, "refs/heads/main", &second).unwrap();
+        (temp, repo, [first, second])
+    }
+
+    #[test]
+    fn checks_wanted_objects() {
+        let (temp, repo, [first, second]) = setup();
+        let advertised = [second.clone()];
+        let check = |wants: &[&String]| {
+            let wants: Vec<String> = wants.iter().map(|hash| hash.to_string()).collect();
+            UploadPack::new(&repo)
+                .unwrap()
+                .check_wants(&repo, &advertised, &wants)
+        };
+
+        assert!(check(&[&second]).is_ok());
+        assert_eq!(
+            check(&[&first]).unwrap_err().to_string(),
+            format!("git upload-pack: not our ref {first}")
+        );
+
+        // A hidden ref may be wanted with allowTipSHA1InWant
+        write_ref(&repo, "refs/hidden/first", &first).unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            "[uploadpack]\n\tallowTipSHA1InWant\n",
+        )
+        .unwrap();
+        assert!(check(&[&first]).is_ok());
+
+        fs::remove_file(temp.join(".git/refs/hidden/first")).unwrap();
+        assert!(check(&[&first]).is_err());
+        fs::write(
+            temp.join(".git/config"),
+            "[uploadpack]\n\tallowReachableSHA1InWant\n",
+        )
+        .unwrap();
+        assert!(check(&[&first]).is_ok());
+
+        // Only existing objects are allowed with allowAnySHA1InWant
+        let tree = read_original_object(&repo, &first).unwrap().1;
+        let tree = Commit::parse(&tree).unwrap().tree;
+        assert!(check(&[&tree]).is_err());
+        fs::write(
+            temp.join(".git/config"),
+            "[uploadpack]\n\tallowAnySHA1InWant\n",
+        )
+        .unwrap();
+        assert!(check(&[&tree]).is_ok());
+        assert!(check(&[&"0".repeat(40)]).is_err());
+        let advertised = UploadPack::new(&repo)
+            .unwrap()
+            .advertise(Capabilities::default());
+        assert!(advertised.has(Flag::AllowTipSha1InWant));
+        assert!(!advertised.has(Flag::Filter));
+    }
+
+    /// Serve a request, returning the lines answered after the advertisement
+    /// (`None` for flush packets).
+    fn request(repo: &Repository, options: ServeOptions, lines: &[&str]) -> Vec<Option<Vec<u8>>> {
+        let mut input = Vec::new();
+        for line in lines {
+            match *line {
+                "" => write_flush(&mut input).unwrap(),
+                line => write_line(&mut input, format!("{line}\n").as_bytes()).unwrap(),
+            }
+        }
+        let mut output = Vec::new();
+        serve(repo, options, &mut input.as_slice(), &mut output).unwrap();
+
+        let mut reader = output.as_slice();
+        let mut lines = Vec::new();
+        while !reader.is_empty() {
+            // A pack sent without side-band is not framed
+            if reader.starts_with(b"PACK") {
+                lines.push(Some(reader.to_vec()));
+                break;
+            }
+            lines.push(read_line(&mut reader).unwrap());
+        }
+        match options.stateless_rpc {
+            true => lines,
+            false => lines.split_off(lines.iter().position(Option::is_none).unwrap() + 1),
+        }
+    }
+
+    #[test]
+    fn negotiates_common_objects() {
+        let (_temp, repo, [first, second]) = setup();
+        let tag = write_object(
+            &repo,
+            ObjectType::Tag,
+            format!("object {second}\ntype commit\ntag v1\ntagger a <a@b> 0 +0000\n\nv1\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        write_ref(&repo, "refs/tags/v1", &tag).unwrap();
+        let unknown = "1".repeat(40);
+        let ack = |line: String| Some(line.into_bytes());
+
+        // Once the commits wanted are covered, the server is ready to send the pack
+        let want = format!("want {second} multi_ack_detailed side-band-64k include-tag");
+        let have = format!("have {first}");
+        let other = format!("have {unknown}");
+        let lines = request(&repo, ServeOptions::default(), &[
+            &want, "", &have, "", &other, "", "done",
+        ]);
+        assert_eq!(lines[..5], [
+            ack(format!("ACK {first} common")),
+            ack(format!("ACK {first} ready")),
+            ack("NAK".to_string()),
+            ack(format!("ACK {unknown} ready")),
+            ack("NAK".to_string()),
+        ]);
+        assert_eq!(lines[5], ack(format!("ACK {first}")));
+        assert_eq!(
+            lines[6],
+            ack("\x02Enumerating objects: 5, done.".to_string())
+        );
+        let pack = lines[7].as_ref().unwrap();
+        assert!(pack.starts_with(b"\x01PACK"));
+        // The commit, its 2 trees and new blob, and the tag pointing to it
+        assert_eq!(pack[9..13], 5u32.to_be_bytes());
+        assert_eq!(lines.last(), Some(&None));
+
+        // Without multi_ack, only the first common object is acknowledged
+        let want = format!("want {second}");
+        let lines = request(&repo, ServeOptions::default(), &[
+            &want, "", &other, "", &have, "done",
+        ]);
+        assert_eq!(lines[..2], [
+            ack("NAK".to_string()),
+            ack(format!("ACK {first}"))
+        ]);
+        assert!(lines[2].as_ref().unwrap().starts_with(b"PACK"));
+
+        // A stateless request ends with its round, unless ready with no-done
+        let stateless = ServeOptions {
+            stateless_rpc: true,
+            ..ServeOptions::default()
+        };
+        let want = format!("want {second} multi_ack_detailed");
+        assert_eq!(request(&repo, stateless, &[&want, "", &other, ""]), [ack(
+            "NAK".to_string()
+        )]);
+        let want = format!("want {second} multi_ack_detailed no-done");
+        let lines = request(&repo, stateless, &[&want, "", &have, ""]);
+        assert_eq!(lines[..4], [
+            ack(format!("ACK {first} common")),
+            ack(format!("ACK {first} ready")),
+            ack("NAK".to_string()),
+            ack(format!("ACK {first}")),
+        ]);
+        assert!(lines[4].as_ref().unwrap().starts_with(b"PACK"));
+    }
+
+    #[test]
+    fn serves_shallow_histories() {
+        let (_temp, repo, [_, second]) = setup();
+        let ack = |line: String| Some(line.into_bytes());
+
+        // The commits at the depth asked for are shallow, told before the pack
+        let want = format!("want {second} shallow");
+        let lines = request(&repo, ServeOptions::default(), &[
+            &want, "deepen 1", "", "done",
+        ]);
+        assert_eq!(lines[..3], [
+            ack(format!("shallow {second}")),
+            None,
+            ack("NAK".to_string()),
+        ]);
+        let pack = lines[3].as_ref().unwrap();
+        // The commit, its 2 trees and 2 blobs
+        assert_eq!(pack[8..12], 5u32.to_be_bytes());
+
+        // Deepening a shallow client sends the history it lacks
+        let shallow = format!("shallow {second}");
+        let lines = request(&repo, ServeOptions::default(), &[
+            &want, &shallow, "deepen 2", "", "done",
+        ]);
+        assert_eq!(lines[..3], [
+            ack(format!("unshallow {second}")),
+            None,
+            ack("NAK".to_string()),
+        ]);
+        let pack = lines[3].as_ref().unwrap();
+        assert_eq!(pack[8..12], 7u32.to_be_bytes());
+    }
+
+    #[test]
+    fn filters_objects_sent() {
+        let (temp, repo, [first, second]) = setup();
+        let wants = [second.clone()];
+        let none = BTreeSet::new();
+        let count = |haves: &[String], filter: Option<Filter>| {
+            objects_to_send(&repo, &wants, haves, filter.as_ref(), &none, &none)
+                .unwrap()
+                .len()
+        };
+
+        // 2 commits, 3 trees and 2 blobs
+        assert_eq!(count(&[], None), 7);
+        assert_eq!(count(std::slice::from_ref(&first), None), 4);
+        assert_eq!(count(&["0".repeat(40)], None), 7);
+        assert_eq!(count(&[], Some(Filter::BlobNone)), 5);
+        assert_eq!(count(&[], Some(Filter::TreeDepth(1))), 4);
+        assert_eq!(count(&[], Some(Filter::TreeDepth(0))), 2);
+        // Wanted objects are sent even if they are filtered out
+        let blob = write_object(&repo, ObjectType::Blob, b"second\n").unwrap();
+        assert_eq!(
+            objects_to_send(&repo, &[blob], &[], Some(&Filter::BlobNone), &none, &none)
+                .unwrap()
+                .len(),
+            1
+        );
+        // Nothing is sent below the shallow commits
+        let shallow = BTreeSet::from([second.clone()]);
+        assert_eq!(
+            objects_to_send(&repo, &wants, &[], None, &none, &shallow)
+                .unwrap()
+                .len(),
+            5
+        );
+
+        let check = |filter: &Filter| UploadPack::new(&repo).unwrap().check_filter(filter);
+        assert!(check(&Filter::BlobNone).is_err());
+        fs::write(
+            temp.join(".git/config"),
+            "[uploadpack]\n\tallowFilter\n[uploadpackfilter \"blob:none\"]\n\tallow = false\n\
+             [uploadpackfilter \"tree\"]\n\tmaxDepth = 1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            check(&Filter::BlobNone).unwrap_err().to_string(),
+            "filter 'blob:none' not supported"
+        );
+        assert!(check(&Filter::BlobLimit(10)).is_ok());
+        assert_eq!(
+            check(&Filter::Combine(vec![
+                Filter::BlobLimit(10),
+                Filter::TreeDepth(2)
+            ]))
+            .unwrap_err()
+            .to_string(),
+            "tree filter allows max depth 1, but got 2"
+        );
+    }
+}
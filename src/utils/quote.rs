@@ -0,0 +1,144 @@
+//! Quoting of paths in the C style used by git
+//! (e.g. `"dir/tab\there"` or `"caf\303\251"`)
+
+use crate::utils::config::Config;
+use crate::utils::repository::Repository;
+
+/// Unquote a path if it is enclosed in double quotes, otherwise return it unchanged.
+///
+/// # Returns
+///
+/// The unquoted bytes of the path
+pub(crate) fn unquote_c_style(quoted: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let Some(inner) = quoted
+        .strip_prefix(b"\"")
+        .and_then(|rest| rest.strip_suffix(b"\""))
+    else {
+        return Ok(quoted.to_vec());
+    };
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut rest = inner;
+    let quoted = String::from_utf8_lossy(quoted);
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte == b'"' {
+            anyhow::bail!("unescaped quote in {quoted}");
+        }
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let Some((&escaped, tail)) = rest.split_first() else {
+            anyhow::bail!("unterminated escape in {quoted}");
+        };
+        rest = tail;
+
+        bytes.push(match escaped {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            b'"' | b'\\' => escaped,
+            // Three octal digits encode a raw byte
+            b'0'..=b'3' => {
+                let digits = [
+                    escaped,
+                    *rest.first().unwrap_or(&0),
+                    *rest.get(1).unwrap_or(&0),
+                ];
+                if !digits.iter().all(|digit| (b'0'..=b'7').contains(digit)) {
+                    anyhow::bail!("invalid octal escape in {quoted}");
+                }
+                rest = &rest[2..];
+                digits
+                    .iter()
+                    .fold(0, |value, digit| (value << 3) | (digit - b'0'))
+            },
+            _ => anyhow::bail!("invalid escape in {quoted}"),
+        });
+    }
+
+    Ok(bytes)
+}
+
+/// Whether paths are shown with their non-ASCII bytes quoted (`core.quotePath`)
+pub(crate) fn quotes_non_ascii(repo: &Repository) -> anyhow::Result<bool> {
+    Ok(Config::load(repo)?
+        .get_bool("core.quotePath")?
+        .unwrap_or(true))
+}
+
+/// Quote a path for output if it contains special characters. Bytes of
+/// non-ASCII characters are only escaped if `quote_non_ascii` is set, and
+/// are otherwise written as they are, whatever their encoding.
+pub(crate) fn quote_path(path: &[u8], quote_non_ascii: bool) -> Vec<u8> {
+    let needs_quoting = |byte: &u8| {
+        *byte < 0x20
+            || *byte == 0x7f
+            || (quote_non_ascii && *byte > 0x7f)
+            || matches!(byte, b'"' | b'\\')
+    };
+    if !path.iter().any(needs_quoting) {
+        return path.to_vec();
+    }
+
+    let mut quoted = vec![b'"'];
+    for &byte in path {
+        match byte {
+            0x07 => quoted.extend(b"\\a"),
+            0x08 => quoted.extend(b"\\b"),
+            0x0c => quoted.extend(b"\\f"),
+            b'\n' => quoted.extend(b"\\n"),
+            b'\r' => quoted.extend(b"\\r"),
+            b'\t' => quoted.extend(b"\\t"),
+            0x0b => quoted.extend(b"\\v"),
+            b'"' | b'\\' => quoted.extend([b'\\', byte]),
+            _ if needs_quoting(&byte) => quoted.extend(format!("\\{byte:03o}").as_bytes()),
+            _ => quoted.push(byte),
+        }
+    }
+    quoted.push(b'"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote_path, unquote_c_style};
+
+    #[test]
+    fn quotes_paths() {
+        assert_eq!(quote_path(b"plain path", true), b"plain path");
+        assert_eq!(quote_path(b"a\tb\\c\"", true), b"\"a\\tb\\\\c\\\"\"");
+        assert_eq!(quote_path("café".as_bytes(), true), b"\"caf\\303\\251\"");
+        let quoted = quote_path(b"\x01\x7f", true);
+        assert_eq!(unquote_c_style(&quoted).unwrap(), b"\x01\x7f");
+
+        // Without core.quotePath, non-ASCII bytes are kept as they are
+        assert_eq!(quote_path("café".as_bytes(), false), "café".as_bytes());
+        assert_eq!(quote_path(b"caf\xe9\t", false), b"\"caf\xe9\\t\"");
+    }
+
+    #[test]
+    fn unquotes_paths() {
+        assert_eq!(unquote_c_style(b"plain path").unwrap(), b"plain path");
+        assert_eq!(
+            unquote_c_style(b"\"a\\tb\\\\c\\\"\"").unwrap(),
+            b"a\tb\\c\""
+        );
+        assert_eq!(
+            unquote_c_style(b"\"caf\\303\\251\"").unwrap(),
+            "café".as_bytes()
+        );
+        // Bytes that are not UTF-8 are kept as they are
+        assert_eq!(unquote_c_style(b"\"caf\xe9\\t\"").unwrap(), b"caf\xe9\t");
+        assert!(unquote_c_style(b"\"a\\q\"").is_err());
+        assert!(unquote_c_style(b"\"a\\47\"").is_err());
+        assert!(unquote_c_style(b"\"a\"b\"").is_err());
+    }
+}
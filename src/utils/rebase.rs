@@ -0,0 +1,293 @@
+//! The state of a rebase, stored under `.git/rebase-merge` while it runs, as git
+//! stores it:
+//!
+//! - `head-name` holds the branch being rebased (`detached HEAD` if there is none)
+//! - `onto` holds the commit the commits are replayed onto
+//! - `orig-head` holds the commit HEAD was at before it started, restored by `--abort`
+//! - `git-rebase-todo` lists the commits still to replay, and `done` the ones
+//!   replayed (`pick <hash> <subject>`), the last one being the current one
+//! - `msgnum` and `end` hold the number of the current commit and of all commits
+//! - `current-fixups` lists the commits squashed or fixed up into the current one so
+//!   far (`squash <hash>` or `fixup <hash>`), and `message-squash` their combined
+//!   message, while an interactive rebase melds commits
+//!
+//! When it stops at a commit whose changes conflict, the commit is recorded in
+//! `stopped-sha` and `REBASE_HEAD`, its message in `message` (and `MERGE_MSG`)
+//! and its author in `author-script`.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::utils::commit::Ident;
+use crate::utils::repository::Repository;
+use crate::utils::sequencer::{format_todo, parse_todo, Step};
+
+/// What `head-name` holds when HEAD was detached
+const DETACHED_HEAD: &str = "detached HEAD";
+
+/// The state of a rebase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct State {
+    /// The full name of the branch being rebased, or `None` if HEAD was detached
+    pub(crate) head_name: Option<String>,
+    /// The commit the commits are replayed onto
+    pub(crate) onto: String,
+    /// The commit HEAD was at before the rebase started
+    pub(crate) orig_head: String,
+    /// The commits still to replay
+    pub(crate) todo: Vec<Step>,
+    /// The commits replayed, the last one being the current one
+    pub(crate) done: Vec<Step>,
+}
+
+/// The directory of the state.
+fn rebase_dir(repo: &Repository) -> anyhow::Result<PathBuf> {
+    Ok(repo.git_dir()?.join("rebase-merge"))
+}
+
+/// The path of the todo list, which interactive rebases let the user edit.
+pub(crate) fn todo_path(repo: &Repository) -> anyhow::Result<PathBuf> {
+    Ok(rebase_dir(repo)?.join("git-rebase-todo"))
+}
+
+/// Whether a rebase is in progress.
+pub(crate) fn in_progress(repo: &Repository) -> anyhow::Result<bool> {
+    Ok(rebase_dir(repo)?.is_dir())
+}
+
+/// Save the state of a rebase.
+pub(crate) fn save(repo: &Repository, state: &State) -> anyhow::Result<()> {
+    let dir = rebase_dir(repo)?;
+    std::fs::create_dir_all(&dir).context("create rebase-merge directory")?;
+    let write = |name: &str, content: String| {
+        std::fs::write(dir.join(name), content).context(format!("write rebase-merge/{name}"))
+    };
+
+    let head_name = state.head_name.as_deref().unwrap_or(DETACHED_HEAD);
+    write("head-name", format!("{head_name}\n"))?;
+    write("onto", format!("{}\n", state.onto))?;
+    write("orig-head", format!("{}\n", state.orig_head))?;
+    // git only replays commits with this backend of rebase when the file exists
+    write("interactive", String::new())?;
+    write("git-rebase-todo", format_todo(repo, &state.todo)?)?;
+    write("done", format_todo(repo, &state.done)?)?;
+    write("msgnum", format!("{}\n", state.done.len()))?;
+    write("end", format!("{}\n", state.done.len() + state.todo.len()))
+}
+
+/// Load the state of a rebase.
+///
+/// # Returns
+///
+/// The state, or `None` if no rebase is in progress
+pub(crate) fn load(repo: &Repository) -> anyhow::Result<Option<State>> {
+    let dir = rebase_dir(repo)?;
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let read = |name: &str| {
+        std::fs::read_to_string(dir.join(name)).context(format!("read rebase-merge/{name}"))
+    };
+    let done = match dir.join("done").is_file() {
+        true => parse_todo(&read("done")?)?,
+        false => Vec::new(),
+    };
+
+    let head_name = read("head-name")?.trim().to_string();
+    Ok(Some(State {
+        head_name: (head_name != DETACHED_HEAD).then_some(head_name),
+        onto: read("onto")?.trim().to_string(),
+        orig_head: read("orig-head")?.trim().to_string(),
+        todo: parse_todo(&read("git-rebase-todo")?)?,
+        done,
+    }))
+}
+
+/// Record the commit the rebase stopped at, with its message and author.
+pub(crate) fn save_stopped(
+    repo: &Repository,
+    hash: &str,
+    message: &str,
+    author: &[u8],
+) -> anyhow::Result<()> {
+    let dir = rebase_dir(repo)?;
+    std::fs::write(dir.join("stopped-sha"), format!("{hash}\n"))
+        .context("write rebase-merge/stopped-sha")?;
+    std::fs::write(dir.join("message"), message).context("write rebase-merge/message")?;
+
+    let author = Ident::parse(author)?;
+    let quote = |value: &[u8]| {
+        format!(
+            "'{}'",
+            String::from_utf8_lossy(value).replace('\'', "'\\''")
+        )
+    };
+    let script = format!(
+        "GIT_AUTHOR_NAME={}\nGIT_AUTHOR_EMAIL={}\nGIT_AUTHOR_DATE='@{} {}'\n",
+        quote(&author.name),
+        quote(&author.email),
+        author.timestamp,
+        author.offset
+    );
+    std::fs::write(dir.join("author-script"), script).context("write rebase-merge/author-script")
+}
+
+/// The commit the rebase stopped at, if any.
+pub(crate) fn stopped(repo: &Repository) -> anyhow::Result<Option<String>> {
+    let path = rebase_dir(repo)?.join("stopped-sha");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let hash = std::fs::read_to_string(path).context("read rebase-merge/stopped-sha")?;
+    Ok(Some(hash.trim().to_string()))
+}
+
+/// Forget the commit the rebase stopped at.
+pub(crate) fn clear_stopped(repo: &Repository) -> anyhow::Result<()> {
+    let dir = rebase_dir(repo)?;
+    for name in ["stopped-sha", "message", "author-script"] {
+        let path = dir.join(name);
+        if path.is_file() {
+            std::fs::remove_file(&path).context(format!("remove rebase-merge/{name}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// The commits melded into the current one so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Squash {
+    /// The commits squashed or fixed up, in order
+    pub(crate) fixups: Vec<Step>,
+    /// The combined message of the commits, with comments
+    pub(crate) message: String,
+}
+
+/// Save the commits melded into the current one so far.
+pub(crate) fn save_squash(repo: &Repository, squash: &Squash) -> anyhow::Result<()> {
+    let dir = rebase_dir(repo)?;
+    let fixups: String = squash
+        .fixups
+        .iter()
+        .map(|step| format!("{step}\n"))
+        .collect();
+    std::fs::write(dir.join("current-fixups"), fixups)
+        .context("write rebase-merge/current-fixups")?;
+    std::fs::write(dir.join("message-squash"), &squash.message)
+        .context("write rebase-merge/message-squash")
+}
+
+/// Load the commits melded into the current one so far.
+///
+/// # Returns
+///
+/// The commits and their message, or `None` if no commits are being melded
+pub(crate) fn load_squash(repo: &Repository) -> anyhow::Result<Option<Squash>> {
+    let dir = rebase_dir(repo)?;
+    if !dir.join("current-fixups").is_file() {
+        return Ok(None);
+    }
+    let read = |name: &str| {
+        std::fs::read_to_string(dir.join(name)).context(format!("read rebase-merge/{name}"))
+    };
+    Ok(Some(Squash {
+        fixups: parse_todo(&read("current-fixups")?)?,
+        message: read("message-squash")?,
+    }))
+}
+
+/// Forget the commits melded into the current one.
+pub(crate) fn clear_squash(repo: &Repository) -> anyhow::Result<()> {
+    let dir = rebase_dir(repo)?;
+    for name in ["current-fixups", "message-squash"] {
+        let path = dir.join(name);
+        if path.is_file() {
+            std::fs::remove_file(&path).context(format!("remove rebase-merge/{name}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the state of a rebase, if any.
+pub(crate) fn remove(repo: &Repository) -> anyhow::Result<()> {
+    let dir = rebase_dir(repo)?;
+    if dir.is_dir() {
+        std::fs::remove_dir_all(&dir).context("remove rebase-merge directory")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::commit::{create_commit, read_commit};
+    use crate::utils::env;
+    use crate::utils::sequencer::Action;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    #[test]
+    fn saves_and_loads_state() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A 'U' Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_AUTHOR_DATE, "1700000000 +0100")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com");
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        let one = create_commit(&repo, EMPTY_TREE, Vec::new(), "one\n").unwrap();
+        let two = create_commit(&repo, EMPTY_TREE, vec![one.clone()], "two\n").unwrap();
+        assert_eq!(load(&repo).unwrap(), None);
+
+        let pick = |hash: &String| Step {
+            action: Action::Pick,
+            hash: hash.clone(),
+        };
+        let state = State {
+            head_name: None,
+            onto: one.clone(),
+            orig_head: two.clone(),
+            todo: vec![pick(&two)],
+            done: vec![pick(&one)],
+        };
+        save(&repo, &state).unwrap();
+        assert_eq!(load(&repo).unwrap().unwrap(), state);
+
+        let dir = repo.git_dir().unwrap().join("rebase-merge");
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("head-name"), "detached HEAD\n");
+        assert_eq!(read("git-rebase-todo"), format!("pick {two} two\n"));
+        assert_eq!((read("msgnum"), read("end")), ("1\n".into(), "2\n".into()));
+
+        let author = read_commit(&repo, &two).unwrap().author;
+        save_stopped(&repo, &two, "two\n", &author).unwrap();
+        assert_eq!(stopped(&repo).unwrap(), Some(two.clone()));
+        assert_eq!(
+            read("author-script"),
+            "GIT_AUTHOR_NAME='A '\\''U'\\'' Thor'\n\
+             GIT_AUTHOR_EMAIL='author@example.com'\n\
+             GIT_AUTHOR_DATE='@1700000000 +0100'\n"
+        );
+        clear_stopped(&repo).unwrap();
+        assert_eq!(stopped(&repo).unwrap(), None);
+
+        assert_eq!(load_squash(&repo).unwrap(), None);
+        let squash = Squash {
+            fixups: vec![Step {
+                action: Action::Fixup,
+                hash: two.clone(),
+            }],
+            message: "# This is a combination of 2 commits.\n".to_string(),
+        };
+        save_squash(&repo, &squash).unwrap();
+        assert_eq!(read("current-fixups"), format!("fixup {two}\n"));
+        assert_eq!(load_squash(&repo).unwrap(), Some(squash));
+        clear_squash(&repo).unwrap();
+        assert_eq!(load_squash(&repo).unwrap(), None);
+
+        remove(&repo).unwrap();
+        assert!(!in_progress(&repo).unwrap());
+    }
+}
@@ -0,0 +1,233 @@
+//! Reading and writing the logs of ref updates (`logs/<ref>`)
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::utils::commit::{Ident, Role};
+use crate::utils::config::{parse_bool, Config};
+use crate::utils::refs::ref_location;
+use crate::utils::repository::Repository;
+
+/// The hash logged as the old value of a ref that did not exist
+pub(crate) const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// A single update of a ref
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ReflogEntry {
+    /// The hash the ref pointed to before the update
+    pub(crate) old: String,
+    /// The hash the ref pointed to after the update
+    pub(crate) new: String,
+    /// Who updated the ref, and when (`Name <email> timestamp offset`)
+    pub(crate) ident: Vec<u8>,
+    /// Why the ref was updated
+    pub(crate) message: String,
+}
+
+impl ReflogEntry {
+    /// Parse a line of a reflog (`<old> <new> <ident>\t<message>`).
+    fn parse(line: &[u8]) -> anyhow::Result<Self> {
+        let (header, message) = match line.iter().position(|&b| b == b'\t') {
+            Some(tab) => (&line[..tab], &line[tab + 1..]),
+            None => (line, &b""[..]),
+        };
+
+        let mut parts = header.splitn(3, |&b| b == b' ');
+        let (Some(old), Some(new), Some(ident)) = (parts.next(), parts.next(), parts.next()) else {
+            anyhow::bail!("invalid reflog entry: {}", String::from_utf8_lossy(line));
+        };
+
+        Ok(ReflogEntry {
+            old: String::from_utf8_lossy(old).into_owned(),
+            new: String::from_utf8_lossy(new).into_owned(),
+            ident: ident.to_vec(),
+            message: String::from_utf8_lossy(message).into_owned(),
+        })
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut line = format!("{} {} ", self.old, self.new).into_bytes();
+        line.extend(&self.ident);
+        if !self.message.is_empty() {
+            line.push(b'\t');
+            line.extend(self.message.as_bytes());
+        }
+        line.push(b'\n');
+        line
+    }
+}
+
+/// Get the path of the log of a ref, stored next to the ref itself.
+fn reflog_path(repo: &Repository, name: &str) -> anyhow::Result<PathBuf> {
+    let (dir, name) = ref_location(repo, name)?;
+    Ok(dir.join("logs").join(name))
+}
+
+/// Read the log of a ref.
+///
+/// # Returns
+///
+/// The updates of the ref, oldest first (empty if the ref has no log)
+pub(crate) fn read_reflog(repo: &Repository, name: &str) -> anyhow::Result<Vec<ReflogEntry>> {
+    let path = reflog_path(repo, name)?;
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read(&path).context(format!("read reflog {}", path.display()))?;
+    content
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+/// Log an update of a ref if `core.logAllRefUpdates` asks for it, or if the ref already
+/// has a log. By default (and when set to `true`), only `HEAD`, branches, remote-tracking
+/// branches and notes are logged, and only in non-bare repositories. When set to `always`,
+/// every ref is logged.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the ref
+/// * `name` - The full name of the ref (e.g. `refs/heads/main`)
+/// * `old` - The hash the ref pointed to, or `None` if it did not exist
+/// * `new` - The hash the ref points to now
+/// * `message` - Why the ref was updated
+pub(crate) fn log_ref_update(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let config = Config::load(repo)?;
+    let log_all = match config.get("core.logAllRefUpdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => {
+            return append_entry(repo, &config, name, old, new, message);
+        },
+        Some(value) => parse_bool(value).context("bad core.logAllRefUpdates")?,
+        None => !config.get_bool("core.bare")?.unwrap_or(false),
+    };
+
+    let loggable = name == "HEAD"
+        || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+
+    if (log_all && loggable) || reflog_path(repo, name)?.is_file() {
+        append_entry(repo, &config, name, old, new, message)?;
+    }
+    Ok(())
+}
+
+/// Log an update of a ref, by the current committer.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the ref
+/// * `name` - The full name of the ref (e.g. `refs/heads/main`)
+/// * `old` - The hash the ref pointed to, or `None` if it did not exist
+/// * `new` - The hash the ref points to now
+/// * `message` - Why the ref was updated (only its first line is kept)
+pub(crate) fn append_reflog(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    append_entry(repo, &Config::load(repo)?, name, old, new, message)
+}
+
+fn append_entry(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let entry = ReflogEntry {
+        old: old.unwrap_or(NULL_HASH).to_string(),
+        new: new.to_string(),
+        ident: Ident::current_or_default(config, Role::Committer)?.format(),
+        message: message.lines().next().unwrap_or_default().to_string(),
+    };
+
+    let path = reflog_path(repo, name)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context(format!("create directory for reflog {name}"))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context(format!("open reflog {}", path.display()))?;
+    file.write_all(&entry.serialize())
+        .context(format!("write reflog {}", path.display()))
+}
+
+/// Replace the log of a ref, removing it if there are no entries left.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the ref
+/// * `name` - The full name of the ref
+/// * `entries` - The updates of the ref, oldest first
+pub(crate) fn write_reflog(
+    repo: &Repository,
+    name: &str,
+    entries: &[ReflogEntry],
+) -> anyhow::Result<()> {
+    let path = reflog_path(repo, name)?;
+    if entries.is_empty() {
+        if path.is_file() {
+            std::fs::remove_file(&path).context(format!("delete reflog {}", path.display()))?;
+        }
+        return Ok(());
+    }
+
+    let content: Vec<u8> = entries.iter().flat_map(ReflogEntry::serialize).collect();
+    std::fs::write(&path, content).context(format!("write reflog {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn appends_and_rewrites_entries() {
+        let temp = TempRepo::new()
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com")
+            .env(env::GIT_COMMITTER_DATE, "1700000000 +0100");
+        std::fs::create_dir_all(temp.join(".git")).unwrap();
+        let repo = temp.repo();
+        let (one, two) = ("1".repeat(40), "2".repeat(40));
+
+        append_reflog(&repo, "refs/stash", None, &one, "first\nbody").unwrap();
+        append_reflog(&repo, "refs/stash", Some(&one), &two, "second").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(temp.join(".git/logs/refs/stash")).unwrap(),
+            format!(
+                "{NULL_HASH} {one} C O Mitter <committer@example.com> 1700000000 +0100\tfirst\n\
+                 {one} {two} C O Mitter <committer@example.com> 1700000000 +0100\tsecond\n"
+            )
+        );
+
+        let entries = read_reflog(&repo, "refs/stash").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].old, one);
+        assert_eq!(entries[1].message, "second");
+
+        write_reflog(&repo, "refs/stash", &entries[1..]).unwrap();
+        assert_eq!(read_reflog(&repo, "refs/stash").unwrap(), entries[1..]);
+        write_reflog(&repo, "refs/stash", &[]).unwrap();
+        assert!(read_reflog(&repo, "refs/stash").unwrap().is_empty());
+    }
+}
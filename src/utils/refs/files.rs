@@ -0,0 +1,384 @@
+//! The files backend of the ref store: a file per ref below `refs/` (loose
+//! refs), and the `packed-refs` file holding the refs packed by `pack-refs`
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::utils::advice::warning;
+use crate::utils::commit::peel;
+use crate::utils::objects::{object_exists, read_original_object, ObjectType};
+use crate::utils::refs::{
+    acquire_lock, is_hash, is_per_worktree_ref, ref_path, split_worktree_qualifier,
+    worktree_git_dir, RefStore, RefUpdate, RefValue,
+};
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::TempPath;
+
+/// The refs of a repository stored as files. Loose refs take precedence
+/// over the `packed-refs` file, which is read at most once.
+pub(crate) struct FilesStore<'a> {
+    repo: &'a Repository,
+    /// The entries of `packed-refs`, once read
+    packed: Option<HashMap<String, String>>,
+    /// The locks held on loose refs, by the name of the ref
+    locks: HashMap<String, TempPath>,
+    /// The lock held on `packed-refs`
+    packed_lock: Option<TempPath>,
+}
+
+impl<'a> FilesStore<'a> {
+    pub(crate) fn new(repo: &'a Repository) -> Self {
+        FilesStore {
+            repo,
+            packed: None,
+            locks: HashMap::new(),
+            packed_lock: None,
+        }
+    }
+
+    fn packed(&mut self) -> anyhow::Result<&HashMap<String, String>> {
+        let packed = match self.packed.take() {
+            Some(packed) => packed,
+            None => read_packed_refs(self.repo)?.into_iter().collect(),
+        };
+        Ok(self.packed.insert(packed))
+    }
+}
+
+impl RefStore for FilesStore<'_> {
+    fn read(&mut self, name: &str) -> anyhow::Result<Option<RefValue>> {
+        match read_loose_ref(self.repo, name)? {
+            Some(value) => Ok(Some(value)),
+            None => Ok(self.packed()?.get(name).cloned().map(RefValue::Direct)),
+        }
+    }
+
+    fn names(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        // The refs of a specific worktree are listed with their qualifier
+        if let Some((qualifier, id, rest)) = split_worktree_qualifier(prefix) {
+            let git_dir = worktree_git_dir(self.repo, id)?;
+            let mut names = BTreeSet::new();
+            list_loose_refs(&git_dir, &git_dir.join(rest), &mut names)?;
+            return Ok(names
+                .into_iter()
+                .filter(|name| name.starts_with("refs/") && is_per_worktree_ref(name))
+                .map(|name| format!("{qualifier}{name}"))
+                .collect());
+        }
+
+        let common_dir = self.repo.common_dir()?;
+        let mut names = BTreeSet::new();
+        list_loose_refs(&common_dir, &common_dir.join(prefix), &mut names)?;
+
+        names.extend(
+            self.packed()?
+                .keys()
+                .filter(|name| name.starts_with(prefix))
+                .cloned(),
+        );
+
+        // The per-worktree refs of the common directory are those of the main worktree
+        let git_dir = self.repo.git_dir()?;
+        if git_dir != common_dir {
+            names.retain(|name| !is_per_worktree_ref(name));
+
+            let mut own = BTreeSet::new();
+            list_loose_refs(&git_dir, &git_dir.join(prefix), &mut own)?;
+            names.extend(own.into_iter().filter(|name| is_per_worktree_ref(name)));
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    /// Lock the loose file of each ref (`<ref>.lock`, holding its new value),
+    /// and `packed-refs` if refs are deleted.
+    fn lock(&mut self, updates: &[RefUpdate]) -> anyhow::Result<()> {
+        for update in updates {
+            let name = &update.name;
+            let path = ref_path(self.repo, name)?;
+            let parent = path.parent().unwrap_or(Path::new(""));
+            // A ref whose directory does not exist has no loose file to delete
+            if update.new.is_none() && !parent.is_dir() {
+                continue;
+            }
+            std::fs::create_dir_all(parent).context(format!("create directory for ref {name}"))?;
+            let lock = acquire_lock(&path)
+                .map_err(|err| anyhow::anyhow!("cannot lock ref '{name}': {err}"))?;
+            let content = match &update.new {
+                Some(RefValue::Direct(hash)) => format!("{hash}\n"),
+                Some(RefValue::Symbolic(target)) => format!("ref: {target}\n"),
+                None => String::new(),
+            };
+            std::fs::write(lock.path(), content).context(format!("write ref {name}"))?;
+            self.locks.insert(name.clone(), lock);
+        }
+
+        let packed_refs = self.repo.common_dir()?.join("packed-refs");
+        if updates.iter().any(|update| update.new.is_none()) && packed_refs.is_file() {
+            self.packed_lock = Some(acquire_lock(&packed_refs)?);
+        }
+        Ok(())
+    }
+
+    /// Remove the deleted refs from `packed-refs`, then move the locks of the
+    /// updated refs over their files and remove the files of the deleted ones.
+    fn commit(&mut self, updates: &[RefUpdate]) -> anyhow::Result<()> {
+        self.packed = None;
+        if let Some(lock) = self.packed_lock.take() {
+            let deleted: Vec<_> = updates
+                .iter()
+                .filter(|update| update.new.is_none())
+                .map(|update| update.name.as_str())
+                .collect();
+            write_packed_refs(self.repo, lock, |content| {
+                Ok(without_packed_refs(content, &deleted))
+            })?;
+        }
+
+        for update in updates {
+            let Some(lock) = self.locks.remove(&update.name) else {
+                continue;
+            };
+            let path = ref_path(self.repo, &update.name)?;
+            match update.new {
+                Some(_) => {
+                    std::fs::rename(lock.path(), &path)
+                        .context(format!("write ref {}", update.name))?;
+                    lock.keep();
+                },
+                None if path.is_file() => {
+                    std::fs::remove_file(&path).context(format!("delete ref {}", update.name))?
+                },
+                None => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn unlock(&mut self) {
+        self.locks.clear();
+        self.packed_lock = None;
+    }
+
+    /// Move the loose refs into the `packed-refs` file (see [`super::pack_refs`]).
+    fn pack(&mut self, all: bool, prune: bool) -> anyhow::Result<()> {
+        let repo = self.repo;
+        let common_dir = repo.common_dir()?;
+        let mut names = BTreeSet::new();
+        list_loose_refs(&common_dir, &common_dir.join("refs"), &mut names)?;
+
+        let mut loose = BTreeMap::new();
+        for name in names {
+            if is_per_worktree_ref(&name) || !(all || name.starts_with("refs/tags/")) {
+                continue;
+            }
+            let Ok(Some(RefValue::Direct(hash))) = read_loose_ref(repo, &name) else {
+                continue;
+            };
+            if object_exists(repo, &hash)? {
+                loose.insert(name, hash);
+            }
+        }
+
+        self.packed = None;
+        update_packed_refs(repo, |content| {
+            let mut refs: BTreeMap<String, String> = BTreeMap::new();
+            for line in content.lines() {
+                if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+                    continue;
+                }
+                let (hash, name) = line.split_once(' ').context("invalid packed-refs line")?;
+                refs.insert(name.to_string(), hash.to_string());
+            }
+            refs.extend(loose.clone());
+
+            let mut packed = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+            for (name, hash) in refs {
+                packed.push_str(&format!("{hash} {name}\n"));
+                if let Ok((ObjectType::Tag, _)) = read_original_object(repo, &hash) {
+                    packed.push_str(&format!("^{}\n", peel(repo, &hash)?.0));
+                }
+            }
+            Ok(Some(packed))
+        })?;
+
+        if prune {
+            for (name, hash) in loose {
+                prune_loose_ref(repo, &name, &hash)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read the value of a ref from its loose file, if there is one.
+fn read_loose_ref(repo: &Repository, name: &str) -> anyhow::Result<Option<RefValue>> {
+    let path = ref_path(repo, name)?;
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(&path).context(format!("read ref {}", path.display()))?;
+    let content = content.trim_end();
+
+    if let Some(target) = content.strip_prefix("ref: ") {
+        return Ok(Some(RefValue::Symbolic(target.to_string())));
+    }
+    if !is_hash(content) {
+        anyhow::bail!("invalid ref {}: {}", name, content);
+    }
+    Ok(Some(RefValue::Direct(content.to_string())))
+}
+
+/// Read all entries of the `packed-refs` file.
+///
+/// # Returns
+///
+/// The name and hash of every packed ref (peeled `^` lines are skipped)
+fn read_packed_refs(repo: &Repository) -> anyhow::Result<Vec<(String, String)>> {
+    let path = repo.common_dir()?.join("packed-refs");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read(&path).context("read packed-refs")?;
+    let mut refs = Vec::new();
+
+    for line in content.split(|&byte| byte == b'\n') {
+        if line.starts_with(b"#") || line.starts_with(b"^") || line.is_empty() {
+            continue;
+        }
+        // Names that are not UTF-8 cannot be handled without corrupting them
+        let Ok(line) = std::str::from_utf8(line) else {
+            let name = line.splitn(2, |&byte| byte == b' ').last().unwrap_or(line);
+            warning(&format!(
+                "ignoring ref with broken name {}",
+                String::from_utf8_lossy(name)
+            ));
+            continue;
+        };
+
+        let (hash, name) = line.split_once(' ').context("invalid packed-refs line")?;
+        refs.push((name.to_string(), hash.to_string()));
+    }
+
+    Ok(refs)
+}
+
+fn list_loose_refs(
+    common_dir: &Path,
+    dir: &Path,
+    names: &mut BTreeSet<String>,
+) -> anyhow::Result<()> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_loose_refs(common_dir, &path, names)?;
+        } else if path.extension().is_some_and(|ext| ext == "lock") {
+            // The lock of a ref being updated by another command
+            continue;
+        } else if let Ok(name) = path.strip_prefix(common_dir) {
+            let components: Option<Vec<_>> = name
+                .components()
+                .map(|component| component.as_os_str().to_str())
+                .collect();
+            match components {
+                Some(components) => {
+                    names.insert(components.join("/"));
+                },
+                // Names that are not UTF-8 cannot be handled without corrupting them
+                None => warning(&format!("ignoring ref with broken name {}", name.display())),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the `packed-refs` file while holding its lock (`packed-refs.lock`),
+/// so that concurrent writers fail instead of losing each other's changes.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the refs
+/// * `update` - Given the current content of the file (empty if it does not
+///   exist), returns the new content, or `None` to leave the file as is
+fn update_packed_refs<F>(repo: &Repository, update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&str) -> anyhow::Result<Option<String>>,
+{
+    let lock = acquire_lock(&repo.common_dir()?.join("packed-refs"))?;
+    write_packed_refs(repo, lock, update)
+}
+
+/// Rewrite the `packed-refs` file whose lock is held (see [`update_packed_refs`]).
+fn write_packed_refs<F>(repo: &Repository, lock: TempPath, update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&str) -> anyhow::Result<Option<String>>,
+{
+    let path = repo.common_dir()?.join("packed-refs");
+    let content = match path.is_file() {
+        true => std::fs::read_to_string(&path).context("read packed-refs")?,
+        false => String::new(),
+    };
+    if let Some(content) = update(&content)? {
+        std::fs::write(lock.path(), content).context("write packed-refs")?;
+        std::fs::rename(lock.path(), &path).context("write packed-refs")?;
+        lock.keep();
+    }
+    Ok(())
+}
+
+/// Remove refs from the content of `packed-refs`, along with their peeled values.
+///
+/// # Returns
+///
+/// The new content, or `None` if none of the refs were packed
+fn without_packed_refs(content: &str, names: &[&str]) -> Option<String> {
+    let mut kept = String::new();
+    let mut removed = false;
+
+    for line in content.lines() {
+        // The peeled value (`^<hash>`) belongs to the preceding ref
+        if line.starts_with('^') && removed {
+            continue;
+        }
+        removed = line
+            .split_once(' ')
+            .is_some_and(|(_, packed)| names.contains(&packed));
+        if !removed {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    (kept.len() != content.len()).then_some(kept)
+}
+
+/// Remove the loose file of a packed ref under its lock, unless it no longer
+/// points to the packed object, then the directories it leaves empty (up to
+/// `refs/<kind>/`). A ref locked by another command is kept.
+fn prune_loose_ref(repo: &Repository, name: &str, hash: &str) -> anyhow::Result<()> {
+    let path = ref_path(repo, name)?;
+    let Ok(lock) = acquire_lock(&path) else {
+        return Ok(());
+    };
+    if let Ok(Some(RefValue::Direct(current))) = read_loose_ref(repo, name) {
+        if current == hash {
+            std::fs::remove_file(&path).context(format!("delete ref {name}"))?;
+        }
+    }
+    drop(lock);
+
+    let mut parent = Path::new(name).parent();
+    while let Some(dir) = parent.filter(|dir| dir.components().count() > 2) {
+        if std::fs::remove_dir(ref_path(repo, &dir.to_string_lossy())?).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
+}
@@ -0,0 +1,688 @@
+//! Utilities for reading and writing references
+//!
+//! Refs are kept by a [`RefStore`]: loose files and `packed-refs` by default
+//! (see [`files`]), or reftables when `extensions.refStorage` is `reftable`
+//! (see [`reftable`]). Reflogs are kept in `logs/` either way. Refs are
+//! updated through a [`RefTransaction`], so that several refs can be updated
+//! all together or not at all.
+
+mod files;
+mod reftable;
+mod transaction;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+pub(crate) use transaction::{RefTransaction, RefUpdate};
+
+use crate::utils::advice::warning;
+use crate::utils::env;
+use crate::utils::reflog::log_ref_update;
+use crate::utils::refs::files::FilesStore;
+use crate::utils::refs::reftable::ReftableStore;
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::TempPath;
+
+/// The value stored in a reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RefValue {
+    /// The ref points directly to an object
+    Direct(String),
+    /// The ref points to another ref (`ref: <target>`)
+    Symbolic(String),
+}
+
+/// A ref resolved by following its chain of symbolic refs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResolvedRef {
+    /// The name of the last ref of the chain (the ref itself if it is not symbolic)
+    pub(crate) name: String,
+    /// The hash the chain ends at, or `None` if its last ref does not exist
+    pub(crate) hash: Option<String>,
+}
+
+/// Where the refs of a repository are kept
+pub(crate) trait RefStore {
+    /// Read the value of a ref without following symbolic refs.
+    ///
+    /// # Returns
+    ///
+    /// The value of the ref, or `None` if the ref does not exist
+    fn read(&mut self, name: &str) -> anyhow::Result<Option<RefValue>>;
+
+    /// List the names of the refs below a prefix (e.g. `refs/heads/`), sorted,
+    /// without reading the refs themselves when they are stored separately.
+    /// Per-worktree refs are those of the current worktree, unless the prefix
+    /// is qualified by a worktree (e.g. `main-worktree/refs/`).
+    fn names(&mut self, prefix: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Take the locks needed to apply updates, which are held by the store
+    /// until the updates are committed or the locks released. Fails if
+    /// another command holds one of them.
+    fn lock(&mut self, updates: &[RefUpdate]) -> anyhow::Result<()>;
+
+    /// Apply updates whose locks are held, then release the locks.
+    fn commit(&mut self, updates: &[RefUpdate]) -> anyhow::Result<()>;
+
+    /// Release the locks held without applying any update.
+    fn unlock(&mut self);
+
+    /// Store the refs more compactly (see `git pack-refs`).
+    ///
+    /// # Arguments
+    ///
+    /// * `all` - Whether to pack every ref rather than only the tags
+    /// * `prune` - Whether to remove the loose files of the refs packed
+    fn pack(&mut self, all: bool, prune: bool) -> anyhow::Result<()>;
+}
+
+/// Open the ref store of a repository, as selected by `extensions.refStorage`.
+pub(crate) fn ref_store(repo: &Repository) -> anyhow::Result<Box<dyn RefStore + '_>> {
+    Ok(match repo.uses_reftable()? {
+        true => Box::new(ReftableStore::new(repo)),
+        false => Box::new(FilesStore::new(repo)),
+    })
+}
+
+/// Check whether a ref is private to the current worktree.
+///
+/// `HEAD`, pseudo-refs such as `MERGE_HEAD` and `refs/bisect/*` are stored
+/// in the worktree's own git directory, everything else is shared.
+pub(crate) fn is_per_worktree_ref(name: &str) -> bool {
+    !name.contains('/') || name.starts_with("refs/bisect/") || name.starts_with("refs/worktree/")
+}
+
+/// Split a name qualified by a worktree, `main-worktree/<rest>` or `worktrees/<id>/<rest>`.
+///
+/// # Returns
+///
+/// The qualifier (e.g. `worktrees/<id>/`), the ID of the worktree (`None` for the
+/// main worktree) and the rest of the name, or `None` if the name is not qualified
+fn split_worktree_qualifier(name: &str) -> Option<(&str, Option<&str>, &str)> {
+    if let Some(rest) = name.strip_prefix("main-worktree/") {
+        return Some(("main-worktree/", None, rest));
+    }
+
+    let (id, rest) = name.strip_prefix("worktrees/")?.split_once('/')?;
+    (!id.is_empty()).then(|| (&name[..name.len() - rest.len()], Some(id), rest))
+}
+
+/// Check whether a ref names a per-worktree ref of a specific worktree
+/// (e.g. `main-worktree/HEAD` or `worktrees/<id>/refs/bisect/bad`).
+pub(crate) fn is_worktree_qualified_ref(name: &str) -> bool {
+    split_worktree_qualifier(name)
+        .is_some_and(|(_, _, rest)| !rest.is_empty() && is_per_worktree_ref(rest))
+}
+
+/// Get the git directory of a worktree from its ID (`None` for the main worktree).
+fn worktree_git_dir(repo: &Repository, id: Option<&str>) -> anyhow::Result<PathBuf> {
+    let common_dir = repo.common_dir()?;
+    Ok(match id {
+        Some(id) => common_dir.join("worktrees").join(id),
+        None => common_dir,
+    })
+}
+
+/// Get the git directory storing a ref and the name of the ref inside it:
+/// the worktree's own directory for per-worktree refs (of the current worktree,
+/// or of the one the name is qualified by), the common directory otherwise.
+pub(crate) fn ref_location<'n>(
+    repo: &Repository,
+    name: &'n str,
+) -> anyhow::Result<(PathBuf, &'n str)> {
+    match split_worktree_qualifier(name) {
+        Some((_, id, rest)) if !rest.is_empty() && is_per_worktree_ref(rest) => {
+            Ok((worktree_git_dir(repo, id)?, rest))
+        },
+        _ if is_per_worktree_ref(name) => Ok((repo.git_dir()?, name)),
+        _ => Ok((repo.common_dir()?, name)),
+    }
+}
+
+/// Get the path of the file storing a loose ref.
+pub(crate) fn ref_path(repo: &Repository, name: &str) -> anyhow::Result<PathBuf> {
+    let (dir, name) = ref_location(repo, name)?;
+    Ok(dir.join(name))
+}
+
+/// Read the value of a ref without following symbolic refs.
+/// Loose refs take precedence over the `packed-refs` file.
+///
+/// # Returns
+///
+/// The value of the ref, or `None` if the ref does not exist
+pub(crate) fn read_ref(repo: &Repository, name: &str) -> anyhow::Result<Option<RefValue>> {
+    ref_store(repo)?.read(name)
+}
+
+/// Resolve a ref to the hash it ultimately points to.
+///
+/// # Returns
+///
+/// The hash, or `None` if the ref (or the ref it points to) does not exist
+pub(crate) fn resolve_ref(repo: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+    Ok(resolve_symref(repo, name)?.hash)
+}
+
+/// Follow the chain of symbolic refs starting at a ref, however deep it is.
+///
+/// # Returns
+///
+/// The last ref of the chain and the hash it points to
+pub(crate) fn resolve_symref(repo: &Repository, name: &str) -> anyhow::Result<ResolvedRef> {
+    let mut store = ref_store(repo)?;
+    follow_symrefs(name, |name| store.read(name))
+}
+
+/// Follow a chain of symbolic refs, reading each ref with the given function.
+/// Fails if the chain loops back to a ref it already went through.
+fn follow_symrefs<F>(name: &str, mut read: F) -> anyhow::Result<ResolvedRef>
+where
+    F: FnMut(&str) -> anyhow::Result<Option<RefValue>>,
+{
+    let mut chain = vec![name.to_string()];
+
+    loop {
+        let name = chain.last().unwrap();
+        let target = match read(name)? {
+            Some(RefValue::Symbolic(target)) => target,
+            Some(RefValue::Direct(hash)) => {
+                let name = name.clone();
+                return Ok(ResolvedRef {
+                    name,
+                    hash: Some(hash),
+                });
+            },
+            None => {
+                let name = name.clone();
+                return Ok(ResolvedRef { name, hash: None });
+            },
+        };
+
+        let looped = chain.contains(&target);
+        chain.push(target);
+        if looped {
+            anyhow::bail!("symbolic ref loop: {}", chain.join(" -> "));
+        }
+    }
+}
+
+/// Get the branch HEAD points to, following any symbolic refs it goes through.
+///
+/// # Returns
+///
+/// The full name of the branch (e.g. `refs/heads/main`),
+/// or `None` if HEAD is detached
+pub(crate) fn head_branch(repo: &Repository) -> anyhow::Result<Option<String>> {
+    let resolved = resolve_symref(repo, "HEAD")?;
+    Ok((resolved.name != "HEAD").then_some(resolved.name))
+}
+
+/// Point a ref directly at an object, creating it if needed.
+pub(crate) fn write_ref(repo: &Repository, name: &str, hash: &str) -> anyhow::Result<()> {
+    write_ref_value(repo, name, &RefValue::Direct(hash.to_string()))
+}
+
+/// Point a ref directly at an object, logging the update in its reflog.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the ref
+/// * `name` - The full name of the ref
+/// * `hash` - The hash of the object
+/// * `message` - Why the ref was updated
+pub(crate) fn update_ref(
+    repo: &Repository,
+    name: &str,
+    hash: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let mut transaction = RefTransaction::new(repo)?;
+    transaction.update(name, hash, None, message)?;
+    transaction.commit()
+}
+
+/// Point HEAD at a commit: the branch it points to is updated,
+/// or HEAD itself if it is detached. The update is logged for both.
+pub(crate) fn update_head(repo: &Repository, hash: &str, message: &str) -> anyhow::Result<()> {
+    let old = resolve_ref(repo, "HEAD")?;
+    match head_branch(repo)? {
+        Some(branch) => update_ref(repo, &branch, hash, message)?,
+        None => write_ref(repo, "HEAD", hash)?,
+    }
+    log_ref_update(repo, "HEAD", old.as_deref(), hash, message)
+}
+
+/// Point a ref at another ref, creating it if needed.
+pub(crate) fn write_symref(repo: &Repository, name: &str, target: &str) -> anyhow::Result<()> {
+    write_ref_value(repo, name, &RefValue::Symbolic(target.to_string()))
+}
+
+fn write_ref_value(repo: &Repository, name: &str, value: &RefValue) -> anyhow::Result<()> {
+    let mut transaction = RefTransaction::new(repo)?;
+    transaction.add(RefUpdate {
+        name: name.to_string(),
+        new: Some(value.clone()),
+        old: None,
+        message: None,
+    })?;
+    transaction.commit()
+}
+
+/// Refuse to update refs from the hooks that run while the objects of a push
+/// are quarantined, as the refs could point to objects that are then discarded.
+fn check_not_quarantined(repo: &Repository) -> anyhow::Result<()> {
+    if repo.env().var_os(env::GIT_QUARANTINE_PATH).is_some() {
+        anyhow::bail!("ref updates forbidden inside quarantine environment");
+    }
+    Ok(())
+}
+
+/// List the refs below a prefix (e.g. `refs/heads/`), loose and packed.
+///
+/// # Returns
+///
+/// The full names and resolved hashes of the refs, sorted by name.
+/// Symbolic refs pointing to missing refs are skipped.
+pub(crate) fn list_refs(repo: &Repository, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+    RefCache::new(repo).list(prefix)
+}
+
+/// The refs of a repository as seen by a single command, so that listing and
+/// resolving many refs reads `packed-refs` and each loose ref file at most once.
+///
+/// Names are listed first, without reading any ref file, and values are only
+/// read (then remembered) when asked for.
+pub(crate) struct RefCache<'a> {
+    repo: &'a Repository,
+    /// The store of the refs, once opened
+    store: Option<Box<dyn RefStore + 'a>>,
+    /// The value of each ref read so far (`None` if it does not exist)
+    values: HashMap<String, Option<RefValue>>,
+}
+
+impl<'a> RefCache<'a> {
+    pub(crate) fn new(repo: &'a Repository) -> Self {
+        RefCache {
+            repo,
+            store: None,
+            values: HashMap::new(),
+        }
+    }
+
+    fn store(&mut self) -> anyhow::Result<&mut (dyn RefStore + 'a)> {
+        let store = match self.store.take() {
+            Some(store) => store,
+            None => ref_store(self.repo)?,
+        };
+        Ok(self.store.insert(store).as_mut())
+    }
+
+    /// List the names of the refs below a prefix (e.g. `refs/heads/`), loose
+    /// and packed, sorted. The refs themselves are not read.
+    pub(crate) fn names(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.store()?.names(prefix)
+    }
+
+    /// Read the value of a ref without following symbolic refs, as [`read_ref`] does.
+    pub(crate) fn read(&mut self, name: &str) -> anyhow::Result<Option<RefValue>> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(value.clone());
+        }
+
+        let value = self.store()?.read(name)?;
+        self.values.insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    /// Resolve a ref to the hash it ultimately points to, as [`resolve_ref`] does.
+    pub(crate) fn resolve(&mut self, name: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.resolve_symref(name)?.hash)
+    }
+
+    /// Follow the chain of symbolic refs starting at a ref, as [`resolve_symref`] does.
+    pub(crate) fn resolve_symref(&mut self, name: &str) -> anyhow::Result<ResolvedRef> {
+        follow_symrefs(name, |name| self.read(name))
+    }
+
+    /// Get the branch HEAD points to, as [`head_branch`] does.
+    pub(crate) fn head_branch(&mut self) -> anyhow::Result<Option<String>> {
+        let resolved = self.resolve_symref("HEAD")?;
+        Ok((resolved.name != "HEAD").then_some(resolved.name))
+    }
+
+    /// List the refs below a prefix with their resolved hashes, as [`list_refs`] does.
+    pub(crate) fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let mut refs = Vec::new();
+        for name in self.names(prefix)? {
+            // A ref that cannot be resolved (garbage in its file, a symbolic ref
+            // loop) is left out rather than hiding all the others, as git does
+            match self.resolve(&name) {
+                Ok(Some(hash)) => refs.push((name, hash)),
+                Ok(None) => {},
+                Err(_) => warning(&format!("ignoring broken ref {name}")),
+            }
+        }
+        Ok(refs)
+    }
+}
+
+/// Delete a ref, its loose file, its `packed-refs` entry and its log.
+///
+/// # Returns
+///
+/// `true` if the ref existed
+pub(crate) fn delete_ref(repo: &Repository, name: &str) -> anyhow::Result<bool> {
+    let existed = read_ref(repo, name)?.is_some();
+    let mut transaction = RefTransaction::new(repo)?;
+    transaction.delete(name, None)?;
+    transaction.commit()?;
+    Ok(existed)
+}
+
+/// Move the loose refs into the `packed-refs` file, along with the object
+/// annotated tags point to (`^<hash>` lines), or compact the stack of
+/// reftables into a single table.
+///
+/// Symbolic refs, the refs of a single worktree and refs pointing to missing
+/// objects are left alone.
+///
+/// # Arguments
+///
+/// * `repo` - The repository of the refs
+/// * `all` - Whether to pack every ref rather than only the tags
+/// * `prune` - Whether to remove the loose files of the refs packed, each one
+///   under its own lock and only if it was not updated meanwhile
+pub(crate) fn pack_refs(repo: &Repository, all: bool, prune: bool) -> anyhow::Result<()> {
+    check_not_quarantined(repo)?;
+    ref_store(repo)?.pack(all, prune)
+}
+
+/// Take the lock of a file by creating `<file>.lock`, which fails if another
+/// command holds it.
+///
+/// # Returns
+///
+/// The lock, removed when dropped unless kept once renamed over the file
+fn acquire_lock(path: &Path) -> anyhow::Result<TempPath> {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    let lock = PathBuf::from(lock);
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+    {
+        Ok(_) => Ok(TempPath::new(lock)),
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            anyhow::bail!("Unable to create '{}': File exists.", lock.display())
+        },
+        Err(err) => Err(err).context(format!("Unable to create '{}'", lock.display())),
+    }
+}
+
+/// Check whether a ref name is well-formed (see `git check-ref-format`).
+pub(crate) fn is_valid_ref_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && name != "@"
+        && !name.contains(|c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+        && name.split('/').all(|component| {
+            !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+        })
+}
+
+/// Check whether a string is a full hex object hash.
+pub(crate) fn is_hash(value: &str) -> bool {
+    value.len() == 40 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Shorten a full ref name for display (e.g. `refs/heads/main` to `main`).
+pub(crate) fn shorten_ref(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    const HASH: &str = "aabbccddeeff00112233445566778899aabbccdd";
+    const PACKED_HASH: &str = "112233445566778899aabbccddeeff0011223344";
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    #[test]
+    fn resolves_symbolic_refs() {
+        let (_temp, repo) = setup();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        write_ref(&repo, "refs/heads/main", HASH).unwrap();
+
+        assert_eq!(
+            read_ref(&repo, "HEAD").unwrap(),
+            Some(RefValue::Symbolic("refs/heads/main".to_string()))
+        );
+        assert_eq!(resolve_ref(&repo, "HEAD").unwrap(), Some(HASH.to_string()));
+        assert_eq!(
+            head_branch(&repo).unwrap(),
+            Some("refs/heads/main".to_string())
+        );
+
+        // Chains of any depth are followed to their last ref
+        for i in 0..8 {
+            write_symref(
+                &repo,
+                &format!("refs/heads/l{i}"),
+                &format!("refs/heads/l{}", i + 1),
+            )
+            .unwrap();
+        }
+        write_symref(&repo, "refs/heads/l8", "refs/heads/main").unwrap();
+        write_symref(&repo, "HEAD", "refs/heads/l0").unwrap();
+        assert_eq!(resolve_symref(&repo, "HEAD").unwrap(), ResolvedRef {
+            name: "refs/heads/main".to_string(),
+            hash: Some(HASH.to_string()),
+        });
+        assert_eq!(
+            head_branch(&repo).unwrap(),
+            Some("refs/heads/main".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_packed_refs() {
+        let (temp, repo) = setup();
+        fs::write(
+            temp.join(".git/packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n\
+                 {PACKED_HASH} refs/tags/v1.0\n\
+                 ^{HASH}\n"
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolve_ref(&repo, "refs/tags/v1.0").unwrap(),
+            Some(PACKED_HASH.to_string())
+        );
+
+        // Loose refs take precedence
+        write_ref(&repo, "refs/tags/v1.0", HASH).unwrap();
+        assert_eq!(
+            resolve_ref(&repo, "refs/tags/v1.0").unwrap(),
+            Some(HASH.to_string())
+        );
+    }
+
+    #[test]
+    fn missing_ref_resolves_to_none() {
+        let (_temp, repo) = setup();
+        write_symref(&repo, "HEAD", "refs/heads/unborn").unwrap();
+
+        assert_eq!(resolve_ref(&repo, "HEAD").unwrap(), None);
+        assert_eq!(resolve_ref(&repo, "refs/heads/missing").unwrap(), None);
+    }
+
+    #[test]
+    fn fails_on_symref_loop() {
+        let (_temp, repo) = setup();
+        write_symref(&repo, "refs/heads/a", "refs/heads/b").unwrap();
+        write_symref(&repo, "refs/heads/b", "refs/heads/a").unwrap();
+
+        let err = resolve_ref(&repo, "refs/heads/a").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "symbolic ref loop: refs/heads/a -> refs/heads/b -> refs/heads/a"
+        );
+
+        // Broken refs are left out of listings, with the others still listed
+        write_ref(&repo, "refs/heads/main", HASH).unwrap();
+        fs::write(repo.git_dir().unwrap().join("refs/heads/garbage"), "xyz\n").unwrap();
+        assert_eq!(list_refs(&repo, "refs/heads/").unwrap(), vec![(
+            "refs/heads/main".to_string(),
+            HASH.to_string()
+        )]);
+    }
+
+    #[test]
+    fn shortens_ref_names() {
+        assert_eq!(shorten_ref("refs/heads/main"), "main");
+        assert_eq!(shorten_ref("refs/remotes/origin/main"), "origin/main");
+        assert_eq!(shorten_ref("HEAD"), "HEAD");
+    }
+
+    #[test]
+    fn lists_and_deletes_refs() {
+        let (temp, repo) = setup();
+        fs::write(
+            temp.join(".git/packed-refs"),
+            format!(
+                "{PACKED_HASH} refs/heads/packed\n\
+                 {PACKED_HASH} refs/tags/v1.0\n\
+                 ^{HASH}\n"
+            ),
+        )
+        .unwrap();
+        write_ref(&repo, "refs/heads/feature/a", HASH).unwrap();
+        write_ref(&repo, "refs/heads/packed", HASH).unwrap();
+
+        assert_eq!(list_refs(&repo, "refs/heads/").unwrap(), vec![
+            ("refs/heads/feature/a".to_string(), HASH.to_string()),
+            ("refs/heads/packed".to_string(), HASH.to_string()),
+        ]);
+
+        assert!(delete_ref(&repo, "refs/heads/packed").unwrap());
+        assert!(delete_ref(&repo, "refs/tags/v1.0").unwrap());
+        assert!(!delete_ref(&repo, "refs/tags/v1.0").unwrap());
+        assert_eq!(resolve_ref(&repo, "refs/heads/packed").unwrap(), None);
+        assert_eq!(
+            fs::read_to_string(temp.join(".git/packed-refs")).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn caches_refs_within_a_command() {
+        let (temp, repo) = setup();
+        fs::write(
+            temp.join(".git/packed-refs"),
+            format!("{PACKED_HASH} refs/heads/packed\n{PACKED_HASH} refs/tags/v1.0\n"),
+        )
+        .unwrap();
+        write_ref(&repo, "refs/heads/main", HASH).unwrap();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+
+        let mut cache = RefCache::new(&repo);
+        assert_eq!(cache.names("refs/heads/").unwrap(), [
+            "refs/heads/main",
+            "refs/heads/packed"
+        ]);
+        assert_eq!(cache.head_branch().unwrap().unwrap(), "refs/heads/main");
+        assert_eq!(cache.resolve("HEAD").unwrap().unwrap(), HASH);
+        assert_eq!(cache.list("refs/tags/").unwrap(), vec![(
+            "refs/tags/v1.0".to_string(),
+            PACKED_HASH.to_string()
+        )]);
+
+        // Refs read once are not read again
+        write_ref(&repo, "refs/heads/main", PACKED_HASH).unwrap();
+        assert_eq!(cache.resolve("refs/heads/main").unwrap().unwrap(), HASH);
+        assert_eq!(
+            RefCache::new(&repo).resolve("HEAD").unwrap().unwrap(),
+            PACKED_HASH
+        );
+    }
+
+    #[test]
+    fn scopes_per_worktree_refs() {
+        let (temp, repo) = setup();
+        let admin_dir = temp.join(".git/worktrees/wt");
+        fs::create_dir_all(&admin_dir).unwrap();
+        fs::write(admin_dir.join("commondir"), "../..\n").unwrap();
+        let linked = repo.open(admin_dir.clone(), Some(temp.join("wt")));
+
+        write_ref(&repo, "refs/heads/main", HASH).unwrap();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        write_ref(&repo, "refs/bisect/bad", HASH).unwrap();
+        write_ref(&linked, "HEAD", PACKED_HASH).unwrap();
+        write_ref(&linked, "refs/bisect/good", PACKED_HASH).unwrap();
+        assert!(admin_dir.join("refs/bisect/good").is_file());
+
+        // Each worktree only lists its own per-worktree refs
+        assert_eq!(RefCache::new(&repo).names("refs/").unwrap(), [
+            "refs/bisect/bad",
+            "refs/heads/main"
+        ]);
+        assert_eq!(RefCache::new(&linked).names("refs/").unwrap(), [
+            "refs/bisect/good",
+            "refs/heads/main"
+        ]);
+
+        // Those of other worktrees are named with a qualifier
+        assert_eq!(
+            resolve_ref(&linked, "main-worktree/HEAD").unwrap(),
+            Some(HASH.to_string())
+        );
+        assert_eq!(
+            resolve_ref(&repo, "worktrees/wt/refs/bisect/good").unwrap(),
+            Some(PACKED_HASH.to_string())
+        );
+        assert_eq!(
+            resolve_ref(&linked, "main-worktree/refs/heads/main").unwrap(),
+            None
+        );
+        assert_eq!(RefCache::new(&repo).names("worktrees/wt/").unwrap(), [
+            "worktrees/wt/refs/bisect/good"
+        ]);
+        assert_eq!(
+            RefCache::new(&linked).names("main-worktree/refs/").unwrap(),
+            ["main-worktree/refs/bisect/bad"]
+        );
+    }
+
+    #[test]
+    fn validates_ref_names() {
+        assert!(is_valid_ref_name("feature/a-b_c.d"));
+        for name in [
+            "", "-a", "a/", "a..b", "a b", "a~1", ".a", "a/.b", "a.lock", "a//b",
+        ] {
+            assert!(!is_valid_ref_name(name), "{name}");
+        }
+    }
+}
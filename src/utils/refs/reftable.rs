@@ -0,0 +1,762 @@
+//! The reftable backend of the ref store (`extensions.refStorage = reftable`)
+//!
+//! Refs are kept in a stack of binary tables in `reftable/`, listed oldest
+//! first by `reftable/tables.list`. Each update adds a table holding the refs
+//! it changes (a deleted ref being recorded as such), and reading merges the
+//! tables, the newest winning. Tables are merged as the stack grows so that
+//! each one holds at least twice as many refs as the one above it, and
+//! `pack-refs` merges the whole stack into a single table.
+//!
+//! Only the ref blocks of the format are written and read: reflogs are kept
+//! in `logs/` as with loose refs, and tables are written without the indexes
+//! that speed up lookups in large tables (readers do not need them).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use flate2::Crc;
+
+use crate::utils::hex;
+use crate::utils::refs::{
+    acquire_lock, is_per_worktree_ref, ref_location, split_worktree_qualifier, worktree_git_dir,
+    RefStore, RefUpdate, RefValue,
+};
+use crate::utils::repository::Repository;
+use crate::utils::tempfile::TempPath;
+
+const MAGIC: &[u8] = b"REFT";
+
+/// The version of the format using SHA-1 hashes
+const VERSION: u8 = 1;
+
+const HEADER_SIZE: usize = 24;
+
+const FOOTER_SIZE: usize = 68;
+
+/// The size blocks are padded to
+const BLOCK_SIZE: usize = 4096;
+
+/// The number of records between restart points, whose names are stored in
+/// full rather than sharing a prefix with the previous record
+const RESTART_INTERVAL: usize = 16;
+
+const HASH_SIZE: usize = 20;
+
+/// The records of a table, sorted by name (`None` for a deleted ref)
+type Records = Vec<(String, Option<RefValue>)>;
+
+/// The refs of a repository stored in stacks of reftables: one in the common
+/// directory, and one per linked worktree for its own per-worktree refs.
+pub(crate) struct ReftableStore<'a> {
+    repo: &'a Repository,
+    /// The refs of each stack read so far, by the directory of the stack
+    stacks: HashMap<PathBuf, BTreeMap<String, RefValue>>,
+    /// The locks held on stacks, by the directory of the stack
+    locks: BTreeMap<PathBuf, TempPath>,
+}
+
+impl<'a> ReftableStore<'a> {
+    pub(crate) fn new(repo: &'a Repository) -> Self {
+        ReftableStore {
+            repo,
+            stacks: HashMap::new(),
+            locks: BTreeMap::new(),
+        }
+    }
+
+    /// The refs of the stack in a git directory, merged.
+    fn refs(&mut self, git_dir: &Path) -> anyhow::Result<&BTreeMap<String, RefValue>> {
+        let dir = git_dir.join("reftable");
+        let refs = match self.stacks.remove(&dir) {
+            Some(refs) => refs,
+            None => merge(&read_stack(&dir)?, false)
+                .into_iter()
+                .filter_map(|(name, value)| Some((name, value?)))
+                .collect(),
+        };
+        Ok(self.stacks.entry(dir).or_insert(refs))
+    }
+}
+
+impl RefStore for ReftableStore<'_> {
+    fn read(&mut self, name: &str) -> anyhow::Result<Option<RefValue>> {
+        let (git_dir, name) = ref_location(self.repo, name)?;
+        Ok(self.refs(&git_dir)?.get(name).cloned())
+    }
+
+    fn names(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        // The refs of a specific worktree are listed with their qualifier
+        if let Some((qualifier, id, rest)) = split_worktree_qualifier(prefix) {
+            let git_dir = worktree_git_dir(self.repo, id)?;
+            return Ok(self
+                .refs(&git_dir)?
+                .keys()
+                .filter(|name| name.starts_with(rest) && name.starts_with("refs/"))
+                .filter(|name| is_per_worktree_ref(name))
+                .map(|name| format!("{qualifier}{name}"))
+                .collect());
+        }
+
+        let common_dir = self.repo.common_dir()?;
+        let git_dir = self.repo.git_dir()?;
+        let mut names: Vec<_> = self
+            .refs(&common_dir)?
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .filter(|name| git_dir == common_dir || !is_per_worktree_ref(name))
+            .cloned()
+            .collect();
+
+        // The per-worktree refs of the common directory are those of the main worktree
+        if git_dir != common_dir {
+            names.extend(
+                self.refs(&git_dir)?
+                    .keys()
+                    .filter(|name| name.starts_with(prefix) && is_per_worktree_ref(name))
+                    .cloned(),
+            );
+            names.sort();
+        }
+        Ok(names)
+    }
+
+    /// Lock each stack storing one of the refs (`tables.list.lock`).
+    fn lock(&mut self, updates: &[RefUpdate]) -> anyhow::Result<()> {
+        for update in updates {
+            let dir = ref_location(self.repo, &update.name)?.0.join("reftable");
+            if !self.locks.contains_key(&dir) {
+                let lock = lock_stack(&dir)
+                    .map_err(|err| anyhow::anyhow!("cannot lock ref '{}': {err}", update.name))?;
+                // The stack may have changed since it was read
+                self.stacks.remove(&dir);
+                self.locks.insert(dir, lock);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a table holding all the updates of a stack to it, then merge the
+    /// tables at its top until each one is at least twice as large as the
+    /// one above it.
+    fn commit(&mut self, updates: &[RefUpdate]) -> anyhow::Result<()> {
+        let mut records: BTreeMap<PathBuf, BTreeMap<String, Option<RefValue>>> = BTreeMap::new();
+        for update in updates {
+            let (git_dir, name) = ref_location(self.repo, &update.name)?;
+            // Deleting a missing ref changes nothing
+            if update.new.is_none() && !self.refs(&git_dir)?.contains_key(name) {
+                continue;
+            }
+            records
+                .entry(git_dir.join("reftable"))
+                .or_default()
+                .insert(name.to_string(), update.new.clone());
+        }
+
+        for (dir, lock) in std::mem::take(&mut self.locks) {
+            self.stacks.remove(&dir);
+            let Some(refs) = records.remove(&dir) else {
+                continue;
+            };
+            write_stack(&dir, lock, |tables| {
+                let index = tables.last().map_or(1, |table| table.max_update_index + 1);
+                tables.push(Table {
+                    name: None,
+                    min_update_index: index,
+                    max_update_index: index,
+                    refs: refs.into_iter().collect(),
+                });
+
+                while let [.., below, top] = tables.as_slice() {
+                    if below.refs.len() >= 2 * top.refs.len() {
+                        break;
+                    }
+                    let merged = tables.split_off(tables.len() - 2);
+                    tables.push(merge_tables(&merged, tables.is_empty()));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn unlock(&mut self) {
+        self.locks.clear();
+    }
+
+    /// Merge each stack of the current worktree into a single table. All the
+    /// refs are in tables already, so there is nothing to prune.
+    fn pack(&mut self, _all: bool, _prune: bool) -> anyhow::Result<()> {
+        let mut dirs = vec![self.repo.common_dir()?];
+        dirs.push(self.repo.git_dir()?);
+        dirs.dedup();
+        for git_dir in dirs {
+            let dir = git_dir.join("reftable");
+            self.stacks.remove(&dir);
+            update_stack(&dir, |tables| {
+                if tables.len() > 1 {
+                    let merged = std::mem::take(tables);
+                    tables.push(merge_tables(&merged, true));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// A table of a stack
+#[derive(Debug)]
+struct Table {
+    /// The name of its file in the directory of the stack (`None` until written)
+    name: Option<String>,
+    /// The update index of its oldest record
+    min_update_index: u64,
+    /// The update index of its newest record
+    max_update_index: u64,
+    refs: Records,
+}
+
+/// Read the tables of a stack, oldest first.
+fn read_stack(dir: &Path) -> anyhow::Result<Vec<Table>> {
+    let Ok(list) = std::fs::read_to_string(dir.join("tables.list")) else {
+        return Ok(Vec::new());
+    };
+    list.lines()
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let path = dir.join(name);
+            let data = std::fs::read(&path).context(format!("read {}", path.display()))?;
+            read_table(name, &data)
+        })
+        .collect()
+}
+
+/// Update a stack while holding its lock (see [`write_stack`]).
+fn update_stack<F>(dir: &Path, update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Vec<Table>) -> anyhow::Result<()>,
+{
+    write_stack(dir, lock_stack(dir)?, update)
+}
+
+/// Take the lock of a stack (`tables.list.lock`), creating its directory if needed.
+fn lock_stack(dir: &Path) -> anyhow::Result<TempPath> {
+    if !dir.is_dir() {
+        std::fs::create_dir_all(dir).context(format!("create {}", dir.display()))?;
+        // Older versions of git look for HEAD to recognize a git directory
+        if let Some(head) = dir.parent().map(|git_dir| git_dir.join("HEAD")) {
+            if !head.exists() {
+                std::fs::write(&head, "ref: refs/heads/.invalid\n").context("write HEAD")?;
+            }
+        }
+    }
+
+    acquire_lock(&dir.join("tables.list"))
+}
+
+/// Update a stack whose lock is held, then remove the tables that were
+/// dropped from it.
+///
+/// # Arguments
+///
+/// * `dir` - The directory of the stack
+/// * `lock` - The lock of the stack, released once done
+/// * `update` - Given the tables of the stack, oldest first, changes them.
+///   The tables added are written once it returns.
+fn write_stack<F>(dir: &Path, lock: TempPath, update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Vec<Table>) -> anyhow::Result<()>,
+{
+    let path = dir.join("tables.list");
+    let mut tables = read_stack(dir)?;
+    let old: Vec<_> = tables
+        .iter()
+        .filter_map(|table| table.name.clone())
+        .collect();
+    update(&mut tables)?;
+
+    // The tables written are removed if the stack cannot be updated
+    let mut written = Vec::new();
+    let mut list = String::new();
+    for table in &mut tables {
+        if table.name.is_none() {
+            let file = write_table(dir, table)?;
+            table.name = file
+                .path()
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            written.push(file);
+        }
+        list.push_str(&format!("{}\n", table.name.as_deref().unwrap_or_default()));
+    }
+    std::fs::write(lock.path(), list).context("write tables.list")?;
+    std::fs::rename(lock.path(), &path).context("write tables.list")?;
+    lock.keep();
+    written.into_iter().for_each(TempPath::keep);
+
+    for name in old {
+        if !tables
+            .iter()
+            .any(|table| table.name.as_ref() == Some(&name))
+        {
+            let _ = std::fs::remove_file(dir.join(name));
+        }
+    }
+    Ok(())
+}
+
+/// Merge the records of consecutive tables, the newest winning.
+///
+/// # Arguments
+///
+/// * `tables` - The tables, oldest first
+/// * `bottom` - Whether the tables start at the bottom of the stack, in which
+///   case deleted refs are dropped as no older table can hold them
+fn merge(tables: &[Table], bottom: bool) -> Records {
+    let mut refs = BTreeMap::new();
+    for table in tables {
+        for (name, value) in &table.refs {
+            refs.insert(name.clone(), value.clone());
+        }
+    }
+    refs.into_iter()
+        .filter(|(_, value)| !bottom || value.is_some())
+        .collect()
+}
+
+/// Merge consecutive tables of a stack into a new table (see [`merge`]).
+fn merge_tables(tables: &[Table], bottom: bool) -> Table {
+    Table {
+        name: None,
+        min_update_index: tables.first().map_or(1, |table| table.min_update_index),
+        max_update_index: tables.last().map_or(1, |table| table.max_update_index),
+        refs: merge(tables, bottom),
+    }
+}
+
+/// Write the file of a new table to the directory of a stack, named after
+/// the update indexes of the table and a random suffix.
+fn write_table(dir: &Path, table: &Table) -> anyhow::Result<TempPath> {
+    let (min, max) = (table.min_update_index, table.max_update_index);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let suffix = nanos ^ std::process::id().rotate_left(16);
+    let name = format!("0x{min:012x}-0x{max:012x}-{suffix:08x}.ref");
+    let file = TempPath::new(dir.join(name));
+    std::fs::write(file.path(), encode_table(min, max, &table.refs)?)
+        .context(format!("write {}", file.path().display()))?;
+    Ok(file)
+}
+
+fn encode_header(min: u64, max: u64) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(VERSION);
+    header.extend_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+    header.extend_from_slice(&min.to_be_bytes());
+    header.extend_from_slice(&max.to_be_bytes());
+    header
+}
+
+/// Encode a table: the header, the ref blocks (the first one including the
+/// header), then the footer repeating the header and locating the indexes
+/// and logs (none here).
+fn encode_table(
+    min: u64,
+    max: u64,
+    refs: &[(String, Option<RefValue>)],
+) -> anyhow::Result<Vec<u8>> {
+    let header = encode_header(min, max);
+    let mut table = Vec::new();
+    let mut block = BlockWriter::new(header.clone());
+
+    for (name, value) in refs {
+        let mut record = Vec::new();
+        let kind = match value {
+            None => 0,
+            Some(RefValue::Direct(hash)) => {
+                record.extend(hex::decode(hash.as_bytes())?);
+                1
+            },
+            Some(RefValue::Symbolic(target)) => {
+                put_varint(&mut record, target.len() as u64);
+                record.extend_from_slice(target.as_bytes());
+                3
+            },
+        };
+        // Every record of a table has the update index of the table
+        let entry = (name.as_bytes(), kind, 0, record.as_slice());
+        if !block.add(entry)? {
+            table.extend(std::mem::replace(&mut block, BlockWriter::new(Vec::new())).finish());
+            block.add(entry)?;
+        }
+    }
+    if block.count > 0 || !table.is_empty() {
+        table.extend(block.finish());
+    } else {
+        table = header.clone();
+    }
+
+    let mut footer = header;
+    // The positions of the ref index, object blocks and index, log blocks and index
+    for _ in 0..5 {
+        footer.extend_from_slice(&0u64.to_be_bytes());
+    }
+    let mut crc = Crc::new();
+    crc.update(&footer);
+    footer.extend_from_slice(&crc.sum().to_be_bytes());
+    table.extend(footer);
+    Ok(table)
+}
+
+/// A ref block being written, padded to [`BLOCK_SIZE`] once full
+struct BlockWriter {
+    /// The block so far, starting with the header of the table for the first block
+    data: Vec<u8>,
+    /// The offset of the block type within `data`
+    start: usize,
+    /// The offsets of the restart points within `data`
+    restarts: Vec<usize>,
+    /// The number of records in the block
+    count: usize,
+    /// The name of the last record
+    last: Vec<u8>,
+}
+
+impl BlockWriter {
+    fn new(mut data: Vec<u8>) -> Self {
+        let start = data.len();
+        data.push(b'r');
+        data.extend_from_slice(&[0; 3]);
+        BlockWriter {
+            data,
+            start,
+            restarts: Vec::new(),
+            count: 0,
+            last: Vec::new(),
+        }
+    }
+
+    /// Add a record, given its name, value type, update index delta and value.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the block is full
+    fn add(
+        &mut self,
+        (name, kind, delta, value): (&[u8], u64, u64, &[u8]),
+    ) -> anyhow::Result<bool> {
+        let restart = self.count.is_multiple_of(RESTART_INTERVAL);
+        let prefix = match restart {
+            true => 0,
+            false => name
+                .iter()
+                .zip(&self.last)
+                .take_while(|(a, b)| a == b)
+                .count(),
+        };
+        let mut record = Vec::new();
+        put_varint(&mut record, prefix as u64);
+        put_varint(&mut record, ((name.len() - prefix) as u64) << 3 | kind);
+        record.extend_from_slice(&name[prefix..]);
+        put_varint(&mut record, delta);
+        record.extend_from_slice(value);
+
+        let restarts = self.restarts.len() + usize::from(restart);
+        if self.data.len() + record.len() + 3 * restarts + 2 > BLOCK_SIZE {
+            if self.count == 0 {
+                anyhow::bail!(
+                    "reftable: ref {} too long for a block",
+                    String::from_utf8_lossy(name)
+                );
+            }
+            return Ok(false);
+        }
+        if restart {
+            self.restarts.push(self.data.len());
+        }
+        self.data.extend(record);
+        self.count += 1;
+        self.last = name.to_vec();
+        Ok(true)
+    }
+
+    /// Write the restart points and the length of the block, then pad it.
+    fn finish(mut self) -> Vec<u8> {
+        for restart in &self.restarts {
+            self.data
+                .extend_from_slice(&(*restart as u32).to_be_bytes()[1..]);
+        }
+        self.data
+            .extend_from_slice(&(self.restarts.len() as u16).to_be_bytes());
+        let length = (self.data.len() as u32).to_be_bytes();
+        self.data[self.start + 1..self.start + 4].copy_from_slice(&length[1..]);
+        self.data.resize(BLOCK_SIZE, 0);
+        self.data
+    }
+}
+
+/// Read the ref records of a table, checking its header and footer.
+fn read_table(name: &str, data: &[u8]) -> anyhow::Result<Table> {
+    let invalid = || format!("reftable: corrupt table {name}");
+    if data.len() < HEADER_SIZE + FOOTER_SIZE || !data.starts_with(MAGIC) {
+        anyhow::bail!(invalid());
+    }
+    if data[4] != VERSION {
+        anyhow::bail!("reftable: unsupported version {} of {name}", data[4]);
+    }
+    let block_size = read_u24(&data[5..8]);
+    let min = u64::from_be_bytes(data[8..16].try_into()?);
+    let max = u64::from_be_bytes(data[16..24].try_into()?);
+
+    let footer = &data[data.len() - FOOTER_SIZE..];
+    let mut crc = Crc::new();
+    crc.update(&footer[..FOOTER_SIZE - 4]);
+    if footer[..HEADER_SIZE] != data[..HEADER_SIZE]
+        || crc.sum().to_be_bytes() != footer[FOOTER_SIZE - 4..]
+    {
+        anyhow::bail!(invalid());
+    }
+
+    // Ref blocks come first, followed by the other kinds of blocks if any
+    let end = data.len() - FOOTER_SIZE;
+    let mut refs = Vec::new();
+    let mut offset = 0;
+    let mut header = HEADER_SIZE;
+    while offset + header < end && data[offset + header] == b'r' {
+        let length = read_u24(
+            data.get(offset + header + 1..offset + header + 4)
+                .with_context(invalid)?,
+        );
+        let block = data
+            .get(offset..offset + length)
+            .filter(|_| length >= header + 6)
+            .with_context(invalid)?;
+        read_block(block, header, &mut refs).with_context(invalid)?;
+
+        // Blocks are padded with zeros to the block size, unless they are not
+        offset += match data.get(offset + length) {
+            Some(0) if block_size > 0 => block_size,
+            _ => length,
+        };
+        header = 0;
+    }
+
+    Ok(Table {
+        name: Some(name.to_string()),
+        min_update_index: min,
+        max_update_index: max,
+        refs,
+    })
+}
+
+/// Read the records of a ref block, whose records start after its block type
+/// and length (and the header of the table for the first block).
+fn read_block(block: &[u8], header: usize, refs: &mut Records) -> anyhow::Result<()> {
+    let restarts = u16::from_be_bytes(block[block.len() - 2..].try_into()?) as usize;
+    let end = (block.len() - 2)
+        .checked_sub(3 * restarts)
+        .context("too many restart points")?;
+    let mut reader = &block[header + 4..end];
+    let mut last = Vec::new();
+
+    while !reader.is_empty() {
+        let prefix = get_varint(&mut reader)? as usize;
+        let suffix_type = get_varint(&mut reader)?;
+        let mut name = last.get(..prefix).context("invalid prefix")?.to_vec();
+        name.extend_from_slice(take(&mut reader, (suffix_type >> 3) as usize)?);
+        let _update_index_delta = get_varint(&mut reader)?;
+
+        let value = match suffix_type & 7 {
+            0 => None,
+            // The peeled object of an annotated tag follows its hash
+            kind @ (1 | 2) => {
+                let mut hash = take(&mut reader, HASH_SIZE * kind as usize)?[..HASH_SIZE].to_vec();
+                hex::encode_in_place(&mut hash);
+                Some(RefValue::Direct(String::from_utf8(hash)?))
+            },
+            3 => {
+                let length = get_varint(&mut reader)? as usize;
+                let target = take(&mut reader, length)?;
+                Some(RefValue::Symbolic(String::from_utf8(target.to_vec())?))
+            },
+            kind => anyhow::bail!("invalid value type {kind}"),
+        };
+        refs.push((String::from_utf8(name.clone())?, value));
+        last = name;
+    }
+    Ok(())
+}
+
+fn read_u24(bytes: &[u8]) -> usize {
+    (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize
+}
+
+fn take<'a>(reader: &mut &'a [u8], length: usize) -> anyhow::Result<&'a [u8]> {
+    if reader.len() < length {
+        anyhow::bail!("truncated record");
+    }
+    let (bytes, rest) = reader.split_at(length);
+    *reader = rest;
+    Ok(bytes)
+}
+
+/// Write a variable-length integer, most significant group first, each
+/// continuation byte offset by one so that every value has a single encoding
+/// (as for the offsets of pack deltas).
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn get_varint(reader: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut byte = take(reader, 1)?[0];
+    let mut value = u64::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = take(reader, 1)?[0];
+        value = ((value + 1) << 7) | u64::from(byte & 0x7f);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::refs::{
+        delete_ref, list_refs, pack_refs, resolve_ref, write_ref, write_symref, RefCache,
+        RefTransaction,
+    };
+    use crate::utils::test::TempRepo;
+
+    const HASH: &str = "aabbccddeeff00112233445566778899aabbccdd";
+    const OTHER_HASH: &str = "112233445566778899aabbccddeeff0011223344";
+
+    fn setup() -> (TempRepo, Repository) {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        fs::write(
+            temp.join(".git/config"),
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\trefStorage = reftable\n",
+        )
+        .unwrap();
+        let repo = temp.repo();
+        (temp, repo)
+    }
+
+    fn stack(temp: &TempRepo) -> Vec<Table> {
+        read_stack(&temp.join(".git/reftable")).unwrap()
+    }
+
+    #[test]
+    fn stores_refs_in_tables() {
+        let (temp, repo) = setup();
+        write_symref(&repo, "HEAD", "refs/heads/main").unwrap();
+        write_ref(&repo, "refs/heads/main", HASH).unwrap();
+        write_ref(&repo, "refs/tags/v1", OTHER_HASH).unwrap();
+        assert!(!temp.join(".git/refs/heads/main").exists());
+        assert_eq!(resolve_ref(&repo, "HEAD").unwrap(), Some(HASH.to_string()));
+
+        // A deletion hides the ref in older tables
+        write_ref(&repo, "refs/heads/topic", HASH).unwrap();
+        assert!(delete_ref(&repo, "refs/heads/topic").unwrap());
+        assert!(!delete_ref(&repo, "refs/heads/topic").unwrap());
+        assert_eq!(list_refs(&repo, "refs/").unwrap(), vec![
+            ("refs/heads/main".to_string(), HASH.to_string()),
+            ("refs/tags/v1".to_string(), OTHER_HASH.to_string()),
+        ]);
+        assert_eq!(
+            RefCache::new(&repo).head_branch().unwrap().unwrap(),
+            "refs/heads/main"
+        );
+
+        // The stack stays geometric as tables are added, and is merged by pack-refs
+        let tables = stack(&temp);
+        assert!(tables.len() > 1 && tables.len() < 5);
+        assert!(tables
+            .windows(2)
+            .all(|pair| pair[0].max_update_index < pair[1].min_update_index));
+        pack_refs(&repo, true, true).unwrap();
+        let tables = stack(&temp);
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].refs.len(), 3);
+        assert_eq!(fs::read_dir(temp.join(".git/reftable")).unwrap().count(), 2);
+        assert_eq!(
+            resolve_ref(&repo, "refs/tags/v1").unwrap(),
+            Some(OTHER_HASH.to_string())
+        );
+
+        // A transaction adds a single table for all its updates
+        let index = tables[0].max_update_index;
+        let mut transaction = RefTransaction::new(&repo).unwrap();
+        transaction
+            .update("refs/heads/a", HASH, Some(None), "create")
+            .unwrap();
+        transaction
+            .update("refs/heads/b", HASH, Some(None), "create")
+            .unwrap();
+        transaction
+            .delete("refs/tags/v1", Some(OTHER_HASH))
+            .unwrap();
+        transaction.commit().unwrap();
+        let tables = stack(&temp);
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].max_update_index, index + 1);
+        assert_eq!(list_refs(&repo, "refs/").unwrap(), vec![
+            ("refs/heads/a".to_string(), HASH.to_string()),
+            ("refs/heads/b".to_string(), HASH.to_string()),
+            ("refs/heads/main".to_string(), HASH.to_string()),
+        ]);
+    }
+
+    #[test]
+    fn encodes_tables() {
+        let mut out = Vec::new();
+        for value in [0, 127, 128, 16511, 16512] {
+            put_varint(&mut out, value);
+        }
+        assert_eq!(out, [0, 127, 0x80, 0, 0xff, 0x7f, 0x80, 0x80, 0]);
+        let mut reader = out.as_slice();
+        for value in [0, 127, 128, 16511, 16512] {
+            assert_eq!(get_varint(&mut reader).unwrap(), value);
+        }
+
+        // Enough refs to span several blocks, sharing prefixes between restarts
+        let refs: Records = (0..500)
+            .map(|i| {
+                (
+                    format!("refs/tags/v{i:04}"),
+                    Some(RefValue::Direct(HASH.to_string())),
+                )
+            })
+            .chain([("refs/zz".to_string(), None)])
+            .collect();
+        let data = encode_table(3, 7, &refs).unwrap();
+        assert_eq!(data.len() % BLOCK_SIZE, FOOTER_SIZE);
+        assert!(data.len() > 2 * BLOCK_SIZE);
+        let table = read_table("table", &data).unwrap();
+        assert_eq!((table.min_update_index, table.max_update_index), (3, 7));
+        assert_eq!(table.refs, refs);
+
+        // An empty table is only a header and a footer
+        let data = encode_table(1, 1, &[]).unwrap();
+        assert_eq!(data.len(), HEADER_SIZE + FOOTER_SIZE);
+        assert!(read_table("empty", &data).unwrap().refs.is_empty());
+        let mut corrupt = data.clone();
+        corrupt[HEADER_SIZE + 1] = 1;
+        assert_eq!(
+            read_table("empty", &corrupt).unwrap_err().to_string(),
+            "reftable: corrupt table empty"
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Updates of several refs applied all together or not at all
+//!
+//! A transaction collects updates, then is prepared: every ref it changes is
+//! locked, and the refs that must have a given value are checked. If any of
+//! this fails, nothing is changed and the locks are released. Once prepared,
+//! the transaction is committed (the updates applied and logged, then the
+//! locks released) or aborted. Dropping a transaction aborts it.
+
+use crate::utils::reflog::{log_ref_update, write_reflog};
+use crate::utils::refs::{check_not_quarantined, follow_symrefs, ref_store, RefStore, RefValue};
+use crate::utils::repository::Repository;
+
+/// A change to a ref, part of a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RefUpdate {
+    /// The full name of the ref
+    pub(crate) name: String,
+    /// The new value of the ref, or `None` to delete it
+    pub(crate) new: Option<RefValue>,
+    /// The hash the ref must resolve to for the transaction to apply
+    /// (`Some(None)` if it must not exist), or `None` to not check it
+    pub(crate) old: Option<Option<String>>,
+    /// Why the ref is updated, logged in its reflog (`None` to not log it)
+    pub(crate) message: Option<String>,
+}
+
+/// Where a transaction is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionState {
+    /// Updates can still be added
+    Open,
+    /// The refs are locked and checked, ready to be committed
+    Prepared,
+    /// The updates were applied
+    Committed,
+    /// The updates were given up, or failed to apply
+    Aborted,
+}
+
+/// Updates of several refs, applied atomically
+pub(crate) struct RefTransaction<'a> {
+    repo: &'a Repository,
+    store: Box<dyn RefStore + 'a>,
+    updates: Vec<RefUpdate>,
+    /// The hash each updated ref resolved to when it was locked
+    old_hashes: Vec<Option<String>>,
+    state: TransactionState,
+}
+
+impl<'a> RefTransaction<'a> {
+    pub(crate) fn new(repo: &'a Repository) -> anyhow::Result<Self> {
+        Ok(RefTransaction {
+            repo,
+            store: ref_store(repo)?,
+            updates: Vec::new(),
+            old_hashes: Vec::new(),
+            state: TransactionState::Open,
+        })
+    }
+
+    /// Add an update to the transaction. A ref can only be updated once.
+    pub(crate) fn add(&mut self, update: RefUpdate) -> anyhow::Result<()> {
+        if self.state != TransactionState::Open {
+            anyhow::bail!("ref transaction is no longer open");
+        }
+        if self.updates.iter().any(|other| other.name == update.name) {
+            anyhow::bail!("multiple updates for ref '{}' not allowed", update.name);
+        }
+        self.updates.push(update);
+        Ok(())
+    }
+
+    /// Point a ref at an object, creating it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The full name of the ref
+    /// * `hash` - The hash of the object
+    /// * `old` - The hash the ref must point to (`Some(None)` if it must not
+    ///   exist), or `None` to update it whatever it points to
+    /// * `message` - Why the ref is updated, logged in its reflog
+    pub(crate) fn update(
+        &mut self,
+        name: &str,
+        hash: &str,
+        old: Option<Option<&str>>,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        self.add(RefUpdate {
+            name: name.to_string(),
+            new: Some(RefValue::Direct(hash.to_string())),
+            old: old.map(|old| old.map(str::to_string)),
+            message: Some(message.to_string()),
+        })
+    }
+
+    /// Delete a ref and its log.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The full name of the ref
+    /// * `old` - The hash the ref must point to, or `None` to delete it
+    ///   whatever it points to
+    pub(crate) fn delete(&mut self, name: &str, old: Option<&str>) -> anyhow::Result<()> {
+        self.add(RefUpdate {
+            name: name.to_string(),
+            new: None,
+            old: old.map(|old| Some(old.to_string())),
+            message: None,
+        })
+    }
+
+    /// Lock the refs of the transaction and check their values, so that
+    /// committing it cannot fail because of another command. The transaction
+    /// is aborted if this fails.
+    pub(crate) fn prepare(&mut self) -> anyhow::Result<()> {
+        if self.state != TransactionState::Open {
+            anyhow::bail!("ref transaction is no longer open");
+        }
+        let result = self.lock_and_check();
+        self.state = match result {
+            Ok(()) => TransactionState::Prepared,
+            Err(_) => {
+                self.store.unlock();
+                TransactionState::Aborted
+            },
+        };
+        result
+    }
+
+    fn lock_and_check(&mut self) -> anyhow::Result<()> {
+        check_not_quarantined(self.repo)?;
+        self.store.lock(&self.updates)?;
+
+        self.old_hashes.clear();
+        for update in &self.updates {
+            let store = &mut self.store;
+            let current = follow_symrefs(&update.name, |name| store.read(name))?.hash;
+            match (&update.old, &current) {
+                (Some(Some(old)), Some(current)) if old != current => anyhow::bail!(
+                    "cannot lock ref '{}': is at {current} but expected {old}",
+                    update.name
+                ),
+                (Some(Some(_)), None) => anyhow::bail!(
+                    "cannot lock ref '{}': unable to resolve reference '{}'",
+                    update.name,
+                    update.name
+                ),
+                (Some(None), Some(_)) => anyhow::bail!(
+                    "cannot lock ref '{}': reference already exists",
+                    update.name
+                ),
+                _ => {},
+            }
+            self.old_hashes.push(current);
+        }
+        Ok(())
+    }
+
+    /// Apply the updates of the transaction (preparing it first if needed),
+    /// then log them.
+    pub(crate) fn commit(&mut self) -> anyhow::Result<()> {
+        if self.state == TransactionState::Open {
+            self.prepare()?;
+        }
+        if self.state != TransactionState::Prepared {
+            anyhow::bail!("ref transaction is not prepared");
+        }
+        if let Err(err) = self.store.commit(&self.updates) {
+            self.abort();
+            return Err(err);
+        }
+        self.state = TransactionState::Committed;
+
+        for (update, old) in self.updates.iter().zip(&self.old_hashes) {
+            match (&update.new, &update.message) {
+                (None, _) => write_reflog(self.repo, &update.name, &[])?,
+                (Some(RefValue::Direct(hash)), Some(message)) => {
+                    log_ref_update(self.repo, &update.name, old.as_deref(), hash, message)?
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    /// Give up the updates of the transaction, releasing the locks taken.
+    pub(crate) fn abort(&mut self) {
+        if self.state != TransactionState::Committed {
+            self.store.unlock();
+            self.state = TransactionState::Aborted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::env;
+    use crate::utils::refs::{read_ref, resolve_ref, write_ref};
+    use crate::utils::test::TempRepo;
+
+    const HASH: &str = "aabbccddeeff00112233445566778899aabbccdd";
+    const OTHER_HASH: &str = "112233445566778899aabbccddeeff0011223344";
+
+    #[test]
+    fn applies_all_updates_or_none() {
+        let temp = TempRepo::new()
+            .env(env::GIT_COMMITTER_NAME, "a")
+            .env(env::GIT_COMMITTER_EMAIL, "a@b");
+        fs::create_dir_all(temp.join(".git/refs/heads")).unwrap();
+        let repo = temp.repo();
+        write_ref(&repo, "refs/heads/main", HASH).unwrap();
+        fs::write(
+            temp.join(".git/packed-refs"),
+            format!("{HASH} refs/tags/v1\n"),
+        )
+        .unwrap();
+
+        // A ref that moved fails the whole transaction, and releases the locks
+        let mut transaction = RefTransaction::new(&repo).unwrap();
+        transaction
+            .update("refs/heads/topic", HASH, Some(None), "create")
+            .unwrap();
+        transaction
+            .update(
+                "refs/heads/main",
+                OTHER_HASH,
+                Some(Some(OTHER_HASH)),
+                "move",
+            )
+            .unwrap();
+        assert_eq!(
+            transaction.commit().unwrap_err().to_string(),
+            format!("cannot lock ref 'refs/heads/main': is at {HASH} but expected {OTHER_HASH}")
+        );
+        assert_eq!(
+            transaction.prepare().unwrap_err().to_string(),
+            "ref transaction is no longer open"
+        );
+        assert_eq!(read_ref(&repo, "refs/heads/topic").unwrap(), None);
+        assert_eq!(
+            fs::read_dir(temp.join(".git/refs/heads")).unwrap().count(),
+            1
+        );
+
+        let mut transaction = RefTransaction::new(&repo).unwrap();
+        transaction
+            .update("refs/heads/topic", HASH, Some(None), "create")
+            .unwrap();
+        transaction
+            .update("refs/heads/main", OTHER_HASH, Some(Some(HASH)), "move")
+            .unwrap();
+        transaction.delete("refs/tags/v1", Some(HASH)).unwrap();
+        assert_eq!(
+            transaction
+                .delete("refs/tags/v1", None)
+                .unwrap_err()
+                .to_string(),
+            "multiple updates for ref 'refs/tags/v1' not allowed"
+        );
+
+        // Prepared refs are locked against other commands
+        transaction.prepare().unwrap();
+        assert_eq!(
+            write_ref(&repo, "refs/heads/main", HASH)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "cannot lock ref 'refs/heads/main': Unable to create '{}': File exists.",
+                repo.git_dir()
+                    .unwrap()
+                    .join("refs/heads/main.lock")
+                    .display()
+            )
+        );
+        transaction.commit().unwrap();
+        assert_eq!(
+            resolve_ref(&repo, "refs/heads/main").unwrap(),
+            Some(OTHER_HASH.to_string())
+        );
+        assert_eq!(
+            resolve_ref(&repo, "refs/heads/topic").unwrap(),
+            Some(HASH.to_string())
+        );
+        assert_eq!(resolve_ref(&repo, "refs/tags/v1").unwrap(), None);
+        assert!(fs::read_to_string(temp.join(".git/logs/refs/heads/main"))
+            .unwrap()
+            .ends_with("\tmove\n"));
+        assert!(!fs::exists(temp.join(".git/refs/heads/main.lock")).unwrap());
+        assert!(!fs::exists(temp.join(".git/packed-refs.lock")).unwrap());
+    }
+}
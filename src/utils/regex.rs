@@ -0,0 +1,292 @@
+//! Matching lines against POSIX basic regular expressions, as used by `grep`
+//!
+//! The supported syntax is a subset of basic regular expressions:
+//!
+//! - `.` matches any character
+//! - `[...]` matches a character class (`^` negates it, `a-z` is a range,
+//!   and `[:alpha:]` and friends are named classes)
+//! - `*` repeats the previous item zero or more times, and the GNU extensions
+//!   `\+` and `\?` one or more times and at most once
+//! - `^` at the start and `$` at the end anchor the pattern to the line
+//! - `\` escapes the next character
+//!
+//! Patterns match anywhere in the line unless anchored.
+
+/// A compiled regular expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Regex {
+    items: Vec<Item>,
+    /// Whether the pattern must match at the start of the line (`^`)
+    anchored_start: bool,
+    /// Whether the pattern must match at the end of the line (`$`)
+    anchored_end: bool,
+    ignore_case: bool,
+}
+
+/// A single-character matcher and how many times it may repeat
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Item {
+    atom: Atom,
+    min: usize,
+    max: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Byte(u8),
+    Any,
+    Class {
+        /// The inclusive ranges of bytes in the class (single bytes are ranges of one)
+        ranges: Vec<(u8, u8)>,
+        /// The named classes (e.g. `alpha`) in the class
+        named: Vec<String>,
+        negated: bool,
+    },
+}
+
+impl Regex {
+    /// Compile a basic regular expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The regular expression
+    /// * `ignore_case` - Whether to compare letters case-insensitively
+    pub(crate) fn new(pattern: &str, ignore_case: bool) -> anyhow::Result<Self> {
+        let mut pattern = pattern.as_bytes();
+        let anchored_start = pattern.first() == Some(&b'^');
+        if anchored_start {
+            pattern = &pattern[1..];
+        }
+        // A trailing `$` is an anchor unless it is escaped
+        let anchored_end = pattern.ends_with(b"$") && !pattern.ends_with(b"\\$");
+        if anchored_end {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+
+        let mut items: Vec<Item> = Vec::new();
+        let mut p = 0;
+        while p < pattern.len() {
+            let repeat = match (pattern[p], pattern.get(p + 1)) {
+                (b'*', _) => Some((0, None, 1)),
+                (b'\\', Some(b'+')) => Some((1, None, 2)),
+                (b'\\', Some(b'?')) => Some((0, Some(1), 2)),
+                _ => None,
+            };
+            // A repetition at the start of the pattern is a literal character
+            if let (Some((min, max, length)), Some(last)) = (repeat, items.last_mut()) {
+                last.min *= min;
+                last.max = match (last.max, max) {
+                    (Some(a), Some(b)) => Some(a * b),
+                    _ => None,
+                };
+                p += length;
+                continue;
+            }
+
+            let atom = match pattern[p] {
+                b'.' => {
+                    p += 1;
+                    Atom::Any
+                },
+                b'[' => {
+                    let (atom, next) = parse_class(pattern, p + 1)?;
+                    p = next;
+                    atom
+                },
+                b'\\' => {
+                    let c = *pattern
+                        .get(p + 1)
+                        .ok_or_else(|| anyhow::anyhow!("trailing backslash in pattern"))?;
+                    p += 2;
+                    Atom::Byte(c)
+                },
+                c => {
+                    p += 1;
+                    Atom::Byte(c)
+                },
+            };
+            items.push(Item {
+                atom,
+                min: 1,
+                max: Some(1),
+            });
+        }
+
+        Ok(Regex {
+            items,
+            anchored_start,
+            anchored_end,
+            ignore_case,
+        })
+    }
+
+    /// Whether the pattern matches anywhere in a line (without its terminator).
+    pub(crate) fn is_match(&self, line: &[u8]) -> bool {
+        if self.anchored_start {
+            return self.matches_at(0, line, 0);
+        }
+        (0..=line.len()).any(|start| self.matches_at(0, line, start))
+    }
+
+    /// Match the items from `item` on against the line from `position`.
+    fn matches_at(&self, item: usize, line: &[u8], position: usize) -> bool {
+        let Some(current) = self.items.get(item) else {
+            return !self.anchored_end || position == line.len();
+        };
+
+        // Take as many repetitions as possible, then give them back one by one
+        let max = current.max.unwrap_or(usize::MAX);
+        let mut end = position;
+        while end - position < max
+            && end < line.len()
+            && self.atom_matches(&current.atom, line[end])
+        {
+            end += 1;
+        }
+        loop {
+            if end - position < current.min {
+                return false;
+            }
+            if self.matches_at(item + 1, line, end) {
+                return true;
+            }
+            if end == position {
+                return false;
+            }
+            end -= 1;
+        }
+    }
+
+    fn atom_matches(&self, atom: &Atom, c: u8) -> bool {
+        let cases = if self.ignore_case {
+            [c, c.to_ascii_lowercase(), c.to_ascii_uppercase()]
+        } else {
+            [c; 3]
+        };
+
+        match atom {
+            Atom::Byte(byte) => cases.contains(byte),
+            Atom::Any => c != b'\n',
+            Atom::Class {
+                ranges,
+                named,
+                negated,
+            } => {
+                let matched = cases.iter().any(|&c| {
+                    ranges
+                        .iter()
+                        .any(|&(start, end)| (start..=end).contains(&c))
+                        || named.iter().any(|name| named_class_matches(name, c))
+                });
+                matched != *negated
+            },
+        }
+    }
+}
+
+/// Parse the character class starting after a `[`.
+///
+/// # Returns
+///
+/// The class and the position after its closing `]`
+fn parse_class(pattern: &[u8], start: usize) -> anyhow::Result<(Atom, usize)> {
+    let unterminated = || anyhow::anyhow!("unmatched [ in pattern");
+    let mut p = start;
+    let negated = pattern.get(p) == Some(&b'^');
+    if negated {
+        p += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut named = Vec::new();
+    let mut first = true;
+    loop {
+        let current = *pattern.get(p).ok_or_else(unterminated)?;
+        // A `]` first in the class is a literal
+        if current == b']' && !first {
+            break;
+        }
+        first = false;
+
+        if current == b'[' && pattern.get(p + 1) == Some(&b':') {
+            let name_end = pattern[p + 2..]
+                .windows(2)
+                .position(|window| window == b":]")
+                .map(|end| p + 2 + end)
+                .ok_or_else(unterminated)?;
+            named.push(String::from_utf8_lossy(&pattern[p + 2..name_end]).into_owned());
+            p = name_end + 2;
+            continue;
+        }
+
+        if pattern.get(p + 1) == Some(&b'-') && pattern.get(p + 2).is_some_and(|&c| c != b']') {
+            ranges.push((current, pattern[p + 2]));
+            p += 3;
+        } else {
+            ranges.push((current, current));
+            p += 1;
+        }
+    }
+
+    Ok((
+        Atom::Class {
+            ranges,
+            named,
+            negated,
+        },
+        p + 1,
+    ))
+}
+
+fn named_class_matches(name: &str, c: u8) -> bool {
+    match name {
+        "alnum" => c.is_ascii_alphanumeric(),
+        "alpha" => c.is_ascii_alphabetic(),
+        "blank" => c == b' ' || c == b'\t',
+        "cntrl" => c.is_ascii_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => c.is_ascii_graphic(),
+        "lower" => c.is_ascii_lowercase(),
+        "print" => c.is_ascii_graphic() || c == b' ',
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_ascii_whitespace(),
+        "upper" => c.is_ascii_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    fn is_match(pattern: &str, line: &str) -> bool {
+        Regex::new(pattern, false)
+            .unwrap()
+            .is_match(line.as_bytes())
+    }
+
+    #[test]
+    fn matches_basic_regular_expressions() {
+        assert!(is_match("foo", "a foo b"));
+        assert!(!is_match("foo", "fo o"));
+        assert!(is_match("f.o", "fxo"));
+        assert!(is_match("^fo*$", "f"));
+        assert!(is_match("^fo*$", "fooo"));
+        assert!(!is_match("^fo*$", "foob"));
+        assert!(is_match("fo\\+b", "foob"));
+        assert!(!is_match("fo\\+b", "fb"));
+        assert!(is_match("colou\\?r", "color"));
+        assert!(is_match("[0-9][[:alpha:]]", "x1y"));
+        assert!(!is_match("[^a-z]", "abc"));
+        assert!(is_match("a\\.b", "a.b"));
+        assert!(!is_match("a\\.b", "axb"));
+        assert!(is_match("*x", "a*x"));
+        assert!(is_match("end$", "the end"));
+        assert!(is_match("[]]", "]"));
+
+        let regex = Regex::new("HELLO [a-c]", true).unwrap();
+        assert!(regex.is_match(b"say hello B"));
+        assert!(Regex::new("[abc", false).is_err());
+    }
+}
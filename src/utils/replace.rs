@@ -0,0 +1,67 @@
+//! Replacement objects, which are read in place of the objects they replace
+//!
+//! Each `refs/replace/<hash>` ref points to the object to read instead of the
+//! object `<hash>`, without rewriting the objects that refer to it (e.g. to graft
+//! a history onto another one). The replaced object can still be read with
+//! [`read_original_object`](crate::utils::objects::read_original_object).
+//!
+//! Replacements are ignored with `--no-replace-objects`, `$GIT_NO_REPLACE_OBJECTS`
+//! or `core.useReplaceRefs=false`.
+
+use std::collections::HashMap;
+
+use crate::utils::config::Config;
+use crate::utils::env;
+use crate::utils::refs::{is_hash, list_refs};
+use crate::utils::repository::Repository;
+
+/// The prefix of the refs of replacement objects
+pub(crate) const REPLACE_REF_PREFIX: &str = "refs/replace/";
+
+/// The longest chain of replacements followed (a replacement being replaced in turn)
+const MAX_REPLACE_DEPTH: usize = 5;
+
+/// Read the replacements of a repository, unless they are disabled.
+///
+/// # Returns
+///
+/// The hash of each replacement by the hash of the object it replaces
+pub(crate) fn read_replacements(repo: &Repository) -> anyhow::Result<HashMap<String, String>> {
+    if repo.env().var_os(env::GIT_NO_REPLACE_OBJECTS).is_some() {
+        return Ok(HashMap::new());
+    }
+
+    let replacements: HashMap<_, _> = list_refs(repo, REPLACE_REF_PREFIX)?
+        .into_iter()
+        .filter_map(|(name, hash)| {
+            let object = name.strip_prefix(REPLACE_REF_PREFIX)?;
+            is_hash(object).then(|| (object.to_ascii_lowercase(), hash))
+        })
+        .collect();
+
+    if replacements.is_empty()
+        || !Config::load(repo)?
+            .get_bool("core.useReplaceRefs")?
+            .unwrap_or(true)
+    {
+        return Ok(HashMap::new());
+    }
+    Ok(replacements)
+}
+
+/// Find the object to read in place of an object, following replacements of replacements.
+///
+/// # Returns
+///
+/// The hash of the replacement (the given hash if the object is not replaced)
+pub(crate) fn replace_object(repo: &Repository, hash: &str) -> anyhow::Result<String> {
+    let replacements = repo.replacements()?;
+    let mut current = hash;
+    for _ in 0..=MAX_REPLACE_DEPTH {
+        match replacements.get(current) {
+            Some(replacement) => current = replacement,
+            None => return Ok(current.to_string()),
+        }
+    }
+    anyhow::bail!("replace depth too high for object {hash}")
+}
@@ -0,0 +1,1048 @@
+//! The repository context shared by all commands
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Context;
+
+use crate::utils::commit_graph::CommitGraph;
+use crate::utils::config::{expand_path, parse_bool, Config, ConfigScope};
+use crate::utils::env::{self, Environment};
+use crate::utils::pack::{PackFile, Packs};
+use crate::utils::replace::read_replacements;
+
+/// The highest `core.repositoryformatversion` understood
+const MAX_FORMAT_VERSION: u32 = 1;
+
+/// The longest chain of alternates followed (an alternate having alternates in turn)
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// The repository context resolved from the global command line options
+/// and the environment. Commands receive this instead of reading
+/// `$GIT_DIR` and friends (or the current directory) themselves.
+#[derive(Debug, Clone)]
+pub(crate) struct Repository {
+    /// The directory the command is run from (after `-C` is applied)
+    cwd: PathBuf,
+    /// The environment variables the command is run with
+    env: Environment,
+    /// The explicit git directory (`--git-dir` or `$GIT_DIR`)
+    git_dir: Option<PathBuf>,
+    /// The explicit working tree (`--work-tree` or `$GIT_WORK_TREE`)
+    work_tree: Option<PathBuf>,
+    /// Whether refs are stored in reftables (`extensions.refStorage`), set once
+    /// the format of the repository is known to be understood
+    reftable: OnceLock<bool>,
+    /// The replacement objects, once read
+    replacements: OnceLock<HashMap<String, String>>,
+    /// The object directories objects are borrowed from, once read
+    alternates: OnceLock<Vec<PathBuf>>,
+    /// The commit-graph, once read (if it can be used), boxed as most commands
+    /// do not read it
+    commit_graph: OnceLock<Option<Box<CommitGraph>>>,
+    /// The packs of the object directories, once read
+    packs: Packs,
+    /// The directory new objects are written to instead of the object
+    /// directory, until they are accepted
+    quarantine: Option<PathBuf>,
+}
+
+impl Repository {
+    /// Create a new repository context in a given directory and environment,
+    /// leaving those of the process alone (e.g. for a host serving several
+    /// repositories from different threads).
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - The directory the command is run from
+    /// * `env` - The environment variables the command is run with
+    /// * `git_dir` - The path passed to `--git-dir`
+    /// * `work_tree` - The path passed to `--work-tree`
+    pub(crate) fn with_environment(
+        cwd: PathBuf,
+        env: Environment,
+        git_dir: Option<PathBuf>,
+        work_tree: Option<PathBuf>,
+    ) -> Self {
+        let git_dir = git_dir.or_else(|| env.var_os(env::GIT_DIR).map(PathBuf::from));
+        let work_tree = work_tree.or_else(|| env.var_os(env::GIT_WORK_TREE).map(PathBuf::from));
+
+        Self {
+            git_dir: git_dir.map(|path| cwd.join(path)),
+            work_tree: work_tree.map(|path| cwd.join(path)),
+            cwd,
+            env,
+            reftable: OnceLock::new(),
+            replacements: OnceLock::new(),
+            alternates: OnceLock::new(),
+            commit_graph: OnceLock::new(),
+            packs: Packs::default(),
+            quarantine: None,
+        }
+    }
+
+    /// Another repository (e.g. a submodule or a clone), used from the same
+    /// directory and environment.
+    ///
+    /// # Arguments
+    ///
+    /// * `git_dir` - The git directory of the repository
+    /// * `work_tree` - Its working tree, if any
+    pub(crate) fn open(&self, git_dir: PathBuf, work_tree: Option<PathBuf>) -> Self {
+        Self::with_environment(self.cwd.clone(), self.env.clone(), Some(git_dir), work_tree)
+    }
+
+    /// The same repository, used from another directory (e.g. the one its hooks
+    /// run from).
+    pub(crate) fn with_cwd(&self, cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            ..self.clone()
+        }
+    }
+
+    /// The environment variables the command is run with.
+    pub(crate) fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    /// The directory the command is run from.
+    pub(crate) fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Prepare a program (e.g. a hook or an editor) to run from the directory
+    /// and with the environment of the command, rather than those of the process.
+    pub(crate) fn command<S>(&self, program: S) -> Command
+    where
+        S: AsRef<OsStr>,
+    {
+        let mut command = Command::new(program);
+        command
+            .current_dir(&self.cwd)
+            .env_clear()
+            .envs(self.env.vars());
+        command
+    }
+
+    /// The same repository, writing new objects to a quarantine directory
+    /// while still reading the objects of its object directory.
+    pub(crate) fn with_quarantine(&self, dir: PathBuf) -> Self {
+        Self {
+            alternates: OnceLock::new(),
+            quarantine: Some(dir),
+            ..self.clone()
+        }
+    }
+
+    /// The quarantine directory new objects are written to, if any.
+    pub(crate) fn quarantine(&self) -> Option<&Path> {
+        self.quarantine.as_deref()
+    }
+
+    /// The git directory explicitly requested through `--git-dir` or `$GIT_DIR`, if any.
+    pub(crate) fn explicit_git_dir(&self) -> Option<&Path> {
+        self.git_dir.as_deref()
+    }
+
+    /// Get the path to the git directory.
+    /// This could be either of the following (in order of precedence):
+    ///
+    /// 1. `--git-dir` or `$GIT_DIR` (used as is, without discovery)
+    /// 2. `.git` in the current directory or any of its parents, or the
+    ///    directory itself if it is a bare repository
+    ///
+    /// If the path is a gitfile (a file containing `gitdir: <path>`, as used
+    /// by linked worktrees and submodules), the directory it points to is returned.
+    ///
+    /// # Returns
+    ///
+    /// The path to the git directory
+    pub(crate) fn git_dir(&self) -> anyhow::Result<PathBuf> {
+        self.discover().map(|(git_dir, _)| git_dir)
+    }
+
+    /// Get the path to the common git directory.
+    /// Linked worktrees share the refs and objects of the main repository,
+    /// which is pointed to by the `commondir` file of their git directory.
+    ///
+    /// # Returns
+    ///
+    /// The path to the common git directory (the git directory itself if
+    /// there is no `commondir` file)
+    pub(crate) fn common_dir(&self) -> anyhow::Result<PathBuf> {
+        let git_dir = self.git_dir()?;
+        read_common_dir(&git_dir)
+    }
+
+    /// Check whether refs are stored in reftables (`extensions.refStorage =
+    /// reftable`) rather than in loose files and `packed-refs`.
+    pub(crate) fn uses_reftable(&self) -> anyhow::Result<bool> {
+        self.discover()?;
+        Ok(self.reftable.get() == Some(&true))
+    }
+
+    /// Get the path to the working tree.
+    /// This could be either of the following (in order of precedence):
+    ///
+    /// 1. `--work-tree` or `$GIT_WORK_TREE`
+    /// 2. The current directory, if the git directory was set explicitly
+    /// 3. The directory containing the discovered `.git` directory or gitfile
+    ///
+    /// A bare repository found by discovery has no working tree.
+    ///
+    /// # Returns
+    ///
+    /// The path to the working tree
+    pub(crate) fn work_tree(&self) -> anyhow::Result<PathBuf> {
+        if let Some(work_tree) = &self.work_tree {
+            return Ok(work_tree.clone());
+        }
+
+        match self.discover()? {
+            (_, Some(work_tree)) => Ok(work_tree),
+            _ if self.is_bare()? => anyhow::bail!("this operation must be run in a work tree"),
+            (_, None) => Ok(self.cwd.clone()),
+        }
+    }
+
+    /// Check whether the repository was found as a bare repository (the
+    /// current directory or one of its parents being the git directory
+    /// itself), so that it has no working tree.
+    pub(crate) fn is_bare(&self) -> anyhow::Result<bool> {
+        if self.git_dir.is_some() || self.work_tree.is_some() {
+            return Ok(false);
+        }
+        Ok(self.discover()?.1.is_none())
+    }
+
+    /// Get the path to the index file of the working tree.
+    /// This is `$GIT_INDEX_FILE` if set, otherwise `<git_directory>/index`.
+    pub(crate) fn index_path(&self) -> anyhow::Result<PathBuf> {
+        match self.env.var_os(env::GIT_INDEX_FILE) {
+            Some(index_file) => Ok(self.cwd.join(index_file)),
+            None => Ok(self.git_dir()?.join("index")),
+        }
+    }
+
+    /// Get the path of the current directory relative to the working tree.
+    ///
+    /// # Returns
+    ///
+    /// The `/`-separated path (empty at the root, or if the current
+    /// directory is outside the working tree)
+    pub(crate) fn prefix(&self) -> anyhow::Result<String> {
+        if self.is_bare()? {
+            return Ok(String::new());
+        }
+        let work_tree = self.work_tree()?;
+        let work_tree = work_tree.canonicalize().unwrap_or(work_tree);
+        let cwd = self.cwd.canonicalize().unwrap_or_else(|_| self.cwd.clone());
+
+        let Ok(relative) = cwd.strip_prefix(&work_tree) else {
+            return Ok(String::new());
+        };
+
+        let components: Vec<_> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        Ok(components.join("/"))
+    }
+
+    /// Locate the git directory, returning it along with the working tree
+    /// it was discovered in (if it was discovered rather than set explicitly).
+    /// The first time, the format of the repository is checked as well.
+    fn discover(&self) -> anyhow::Result<(PathBuf, Option<PathBuf>)> {
+        let found = self.locate()?;
+        if self.reftable.get().is_none() {
+            let reftable = check_repository_format(&read_common_dir(&found.0)?)?;
+            let _ = self.reftable.set(reftable);
+        }
+        Ok(found)
+    }
+
+    fn locate(&self) -> anyhow::Result<(PathBuf, Option<PathBuf>)> {
+        if let Some(git_dir) = &self.git_dir {
+            if !git_dir.exists() {
+                anyhow::bail!("not a git repository: '{}'", git_dir.display());
+            }
+            return Ok((resolve_gitfile(git_dir)?, None));
+        }
+
+        let mut current_dir = self.cwd.as_path();
+
+        // Search for the git directory in the current directory and its parents
+        loop {
+            let git_dir = current_dir.join(".git");
+
+            // Return the git directory if it exists
+            if git_dir.exists() {
+                let gitfile = git_dir.is_file().then_some(git_dir.as_path());
+                let resolved = resolve_gitfile(&git_dir)?;
+                ensure_valid_ownership(&self.env, current_dir, gitfile, &resolved)?;
+                return Ok((resolved, Some(current_dir.to_path_buf())));
+            }
+
+            // Or the directory itself, if it is a bare repository (or the
+            // `.git` directory of a working tree)
+            if is_git_directory(current_dir) {
+                ensure_valid_ownership(&self.env, current_dir, None, current_dir)?;
+                return Ok((current_dir.to_path_buf(), None));
+            }
+
+            let Some(parent_dir) = current_dir.parent() else {
+                break;
+            };
+
+            current_dir = parent_dir;
+        }
+
+        anyhow::bail!("not a git repository (or any of the parent directories): .git")
+    }
+
+    /// Get the path to the git object directory.
+    /// This could be either of the following (in order of precedence):
+    ///
+    /// 1. The quarantine directory, if new objects are written there
+    /// 2. `<common_git_directory>/$GIT_OBJECT_DIRECTORY`
+    /// 3. `<common_git_directory>/objects`
+    ///
+    /// # Arguments
+    ///
+    /// * `check_exists` - Whether to check if the object directory exists,
+    ///   exiting with an error if it does not
+    ///
+    /// # Returns
+    ///
+    /// The path to the git object directory
+    pub(crate) fn object_dir(&self, check_exists: bool) -> anyhow::Result<PathBuf> {
+        let object_dir = match &self.quarantine {
+            Some(quarantine) => quarantine.clone(),
+            None => self.shared_object_dir()?,
+        };
+
+        // Check if the object directory exists
+        if check_exists && !object_dir.exists() {
+            anyhow::bail!("{} directory does not exist", object_dir.display());
+        }
+
+        Ok(object_dir)
+    }
+
+    /// The object directory of the repository, ignoring its quarantine.
+    fn shared_object_dir(&self) -> anyhow::Result<PathBuf> {
+        let object_dir = self
+            .env
+            .var_os(env::GIT_OBJECT_DIRECTORY)
+            .unwrap_or("objects".as_ref());
+        Ok(self.common_dir()?.join(object_dir))
+    }
+
+    /// Get the path to a git object.
+    /// The path is constructed as follows:
+    ///
+    /// `<git_object_directory>/<hash[..2]>/<hash[2..]>`
+    ///
+    /// # Example
+    ///
+    /// If the default git and object directories are used,
+    /// the path for object `e7a11a969c037e00a796aafeff6258501ec15e9a` would be:
+    ///
+    /// `.git/objects/e7/a11a969c037e00a796aafeff6258501ec15e9a`
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The object hash
+    /// * `check_exists` - Whether to check if the object exists,
+    ///   exiting with an error if it does not
+    ///
+    /// # Returns
+    ///
+    /// The path to the object file
+    pub(crate) fn object_path(&self, hash: &str, check_exists: bool) -> anyhow::Result<PathBuf> {
+        if hash.len() < 3 {
+            anyhow::bail!("{} is not a valid object", hash);
+        }
+
+        let object_dir = self.object_dir(check_exists)?;
+        let object_path = object_dir.join(&hash[..2]).join(&hash[2..]);
+        if !check_exists || object_path.exists() {
+            return Ok(object_path);
+        }
+
+        // Objects missing from the repository may be borrowed from its alternates
+        for alternate in self.alternate_object_dirs()? {
+            let object_path = alternate.join(&hash[..2]).join(&hash[2..]);
+            if object_path.exists() {
+                return Ok(object_path);
+            }
+        }
+        anyhow::bail!("{} is not a valid object", hash)
+    }
+
+    /// The object directories the repository borrows objects from: its own
+    /// object directory if it is quarantined, the ones listed in
+    /// `objects/info/alternates` (relative to the object directory),
+    /// those of their own alternates, and the ones of `$GIT_ALTERNATE_OBJECT_DIRECTORIES`.
+    /// Directories that do not exist are reported and skipped.
+    pub(crate) fn alternate_object_dirs(&self) -> anyhow::Result<&[PathBuf]> {
+        if let Some(alternates) = self.alternates.get() {
+            return Ok(alternates);
+        }
+
+        let object_dir = self.object_dir(false)?;
+        let mut alternates = Vec::new();
+        let mut pending = vec![(object_dir.clone(), 0)];
+        // A quarantine borrows the objects of the object directory
+        if self.quarantine.is_some() {
+            let shared = self.shared_object_dir()?;
+            pending
+                .extend(add_alternate(&mut alternates, &object_dir, &shared).map(|dir| (dir, 0)));
+        }
+        while let Some((dir, depth)) = pending.pop() {
+            let Ok(content) = std::fs::read_to_string(dir.join("info").join("alternates")) else {
+                continue;
+            };
+            if depth > MAX_ALTERNATE_DEPTH {
+                eprintln!(
+                    "error: {}: ignoring alternate object stores, nesting too deep",
+                    dir.display()
+                );
+                continue;
+            }
+            for line in content.lines() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some(alternate) =
+                    add_alternate(&mut alternates, &object_dir, &dir.join(line))
+                {
+                    pending.push((alternate, depth + 1));
+                }
+            }
+        }
+
+        if let Some(dirs) = self.env.var_os(env::GIT_ALTERNATE_OBJECT_DIRECTORIES) {
+            for dir in std::env::split_paths(dirs) {
+                if !dir.as_os_str().is_empty() {
+                    add_alternate(&mut alternates, &object_dir, &self.cwd.join(dir));
+                }
+            }
+        }
+
+        Ok(self.alternates.get_or_init(|| alternates))
+    }
+
+    /// The packs of the object directory and of its alternates, read once per
+    /// command unless they are looked for again.
+    ///
+    /// # Arguments
+    ///
+    /// * `reload` - Whether to look for packs added since they were last listed
+    pub(crate) fn packs(&self, reload: bool) -> anyhow::Result<Vec<Arc<PackFile>>> {
+        let mut object_dirs = vec![self.object_dir(true)?];
+        object_dirs.extend(self.alternate_object_dirs()?.iter().cloned());
+        self.packs.list(&object_dirs, reload)
+    }
+
+    /// Whether the objects of the repository must never be deleted
+    /// (`extensions.preciousObjects`), as other repositories borrow them.
+    pub(crate) fn has_precious_objects(&self) -> anyhow::Result<bool> {
+        let path = self.common_dir()?.join("config");
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Ok(false);
+        };
+        let config = Config::parse(&content, &path, ConfigScope::Local)?;
+
+        // Version 0 repositories predate extensions
+        let version = config
+            .get("core.repositoryformatversion")
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(0);
+        Ok(version >= 1
+            && config
+                .get_bool("extensions.preciousObjects")?
+                .unwrap_or(false))
+    }
+
+    /// The replacement objects of the repository, read once per command.
+    ///
+    /// # Returns
+    ///
+    /// The hash of each replacement by the hash of the object it replaces
+    /// (none if replacements are disabled)
+    pub(crate) fn replacements(&self) -> anyhow::Result<&HashMap<String, String>> {
+        if let Some(replacements) = self.replacements.get() {
+            return Ok(replacements);
+        }
+        let replacements = read_replacements(self)?;
+        Ok(self.replacements.get_or_init(|| replacements))
+    }
+
+    /// The commit-graph of the repository, read once per command. A corrupt
+    /// commit-graph is reported and ignored, the commits being read instead.
+    pub(crate) fn commit_graph(&self) -> Option<&CommitGraph> {
+        if let Some(graph) = self.commit_graph.get() {
+            return graph.as_deref();
+        }
+        let graph = CommitGraph::read(self).unwrap_or_else(|err| {
+            eprintln!("error: {err:#}");
+            None
+        });
+        self.commit_graph
+            .get_or_init(|| graph.map(Box::new))
+            .as_deref()
+    }
+}
+
+/// Add an alternate object directory, unless it is the repository's own or
+/// already added, reporting it if it does not exist.
+///
+/// # Returns
+///
+/// The normalized directory, if it was added
+fn add_alternate(alternates: &mut Vec<PathBuf>, object_dir: &Path, dir: &Path) -> Option<PathBuf> {
+    let Ok(dir) = dir.canonicalize() else {
+        eprintln!(
+            "error: object directory {} does not exist; check .git/objects/info/alternates",
+            dir.display()
+        );
+        return None;
+    };
+    if object_dir.canonicalize().is_ok_and(|own| own == dir) || alternates.contains(&dir) {
+        return None;
+    }
+    alternates.push(dir.clone());
+    Some(dir)
+}
+
+/// Refuse to use a repository whose format is newer than the one understood
+/// (`core.repositoryformatversion` above 1), or that needs extensions which are
+/// unknown or whose values are not supported, rather than misreading it.
+/// Version 0 repositories predate extensions, so theirs are ignored.
+///
+/// # Returns
+///
+/// Whether refs are stored in reftables rather than files
+fn check_repository_format(common_dir: &Path) -> anyhow::Result<bool> {
+    let path = common_dir.join("config");
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        return Ok(false);
+    };
+    let config = Config::parse(&content, &path, ConfigScope::Local)?;
+
+    let version = match config.get("core.repositoryformatversion") {
+        Some(value) => value.parse().ok().context(format!(
+            "bad numeric config value '{value}' for 'core.repositoryformatversion'"
+        ))?,
+        None => 0,
+    };
+    if version > MAX_FORMAT_VERSION {
+        anyhow::bail!("Expected git repo version <= {MAX_FORMAT_VERSION}, found {version}");
+    }
+    if version == 0 {
+        return Ok(false);
+    }
+
+    let mut reftable = false;
+    let mut unknown = Vec::new();
+    for entry in config.entries() {
+        let Some(extension) = entry.key.strip_prefix("extensions.") else {
+            continue;
+        };
+        let value = entry.value.as_deref().unwrap_or("true");
+        match extension {
+            "noop" | "preciousobjects" | "partialclone" | "worktreeconfig" => {},
+            "objectformat" if value.eq_ignore_ascii_case("sha1") => {},
+            "objectformat" => anyhow::bail!("unsupported object format '{value}'"),
+            "refstorage" if value.eq_ignore_ascii_case("files") => reftable = false,
+            "refstorage" if value.eq_ignore_ascii_case("reftable") => reftable = true,
+            "refstorage" => anyhow::bail!("unsupported ref storage format '{value}'"),
+            _ => unknown.push(format!("\t{extension}")),
+        }
+    }
+
+    match unknown.len() {
+        0 => Ok(reftable),
+        1 => anyhow::bail!("unknown repository extension found:\n{}", unknown[0]),
+        _ => anyhow::bail!(
+            "unknown repository extensions found:\n{}",
+            unknown.join("\n")
+        ),
+    }
+}
+
+/// Refuse to use a discovered repository owned by another user, whose configuration
+/// could make git run arbitrary commands, unless it is listed in `safe.directory`.
+///
+/// # Arguments
+///
+/// * `env` - The environment variables the command is run with
+/// * `work_tree` - The directory the repository was discovered in
+/// * `gitfile` - The gitfile pointing to the git directory, if any
+/// * `git_dir` - The git directory
+fn ensure_valid_ownership(
+    env: &Environment,
+    work_tree: &Path,
+    gitfile: Option<&Path>,
+    git_dir: &Path,
+) -> anyhow::Result<()> {
+    let assume_different_owner = env
+        .var(env::GIT_TEST_ASSUME_DIFFERENT_OWNER)
+        .is_some_and(|value| parse_bool(value).unwrap_or(false));
+    let owned = [Some(work_tree), gitfile, Some(git_dir)]
+        .into_iter()
+        .flatten()
+        .all(|path| is_owned_by_current_user(env, path));
+
+    if (owned && !assume_different_owner) || is_safe_directory(env, work_tree)? {
+        return Ok(());
+    }
+
+    let path = work_tree.display();
+    anyhow::bail!(
+        "detected dubious ownership in repository at '{path}'\n\
+         To add an exception for this directory, call:\n\n\
+         \tgit config --global --add safe.directory {path}"
+    )
+}
+
+/// Check whether a directory is listed in `safe.directory`, which is only read from
+/// the protected configuration. `*` allows every directory, a trailing `/*` every
+/// directory below a path, and an empty value clears the previous entries.
+fn is_safe_directory(env: &Environment, dir: &Path) -> anyhow::Result<bool> {
+    let config = Config::load_protected(env)?;
+    let real_dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let same_path =
+        |path: &Path| path == dir || path.canonicalize().is_ok_and(|path| path == real_dir);
+
+    let mut safe = false;
+    for value in config.get_all("safe.directory") {
+        safe = match value {
+            "" => false,
+            "*" => true,
+            value => match value.strip_suffix("/*") {
+                Some(parent) => {
+                    let parent = expand_path(env, parent);
+                    let real_parent = parent.canonicalize().unwrap_or_else(|_| parent.clone());
+                    safe || dir.starts_with(&parent) || real_dir.starts_with(&real_parent)
+                },
+                None => safe || same_path(&expand_path(env, value)),
+            },
+        };
+    }
+
+    Ok(safe)
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn geteuid() -> u32;
+}
+
+/// Check whether a path is owned by the user running git
+/// (or the user who called `sudo`, when running as root through it).
+#[cfg(unix)]
+fn is_owned_by_current_user(env: &Environment, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    // SAFETY: geteuid has no preconditions and always succeeds
+    let mut uid = unsafe { geteuid() };
+    if uid == 0 {
+        if let Some(sudo_uid) = env.var("SUDO_UID").and_then(|id| id.parse().ok()) {
+            uid = sudo_uid;
+        }
+    }
+
+    path.symlink_metadata()
+        .is_ok_and(|metadata| metadata.uid() == uid)
+}
+
+#[cfg(not(unix))]
+fn is_owned_by_current_user(_env: &Environment, _path: &Path) -> bool {
+    true
+}
+
+/// Check whether a directory looks like a git directory: it has a `HEAD`
+/// file, an object directory and a refs directory, as git checks.
+fn is_git_directory(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
+
+/// Follow a gitfile to the git directory it points to.
+///
+/// # Arguments
+///
+/// * `path` - The path to a git directory or a gitfile
+///
+/// # Returns
+///
+/// The path itself if it is a directory, otherwise the (absolute) path
+/// stored in the `gitdir: <path>` line of the file
+pub(crate) fn resolve_gitfile(path: &Path) -> anyhow::Result<PathBuf> {
+    if path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("read gitfile {}", path.display()))?;
+    let Some(target) = content.strip_prefix("gitdir: ") else {
+        anyhow::bail!("invalid gitfile format: {}", path.display());
+    };
+
+    // Relative paths are relative to the directory containing the gitfile
+    let base = path.parent().unwrap_or(Path::new("."));
+    let git_dir = base.join(target.trim_end());
+
+    if !git_dir.is_dir() {
+        anyhow::bail!("not a git repository: {}", git_dir.display());
+    }
+
+    Ok(git_dir)
+}
+
+/// Read the `commondir` file of a git directory.
+///
+/// # Returns
+///
+/// The path to the common git directory, or the git directory itself
+/// if it has no `commondir` file
+pub(crate) fn read_common_dir(git_dir: &Path) -> anyhow::Result<PathBuf> {
+    let commondir_path = git_dir.join("commondir");
+    if !commondir_path.exists() {
+        return Ok(git_dir.to_path_buf());
+    }
+
+    let content = std::fs::read_to_string(&commondir_path).context("read commondir")?;
+    let common_dir = git_dir.join(content.trim_end());
+    Ok(common_dir.canonicalize().unwrap_or(common_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::Repository;
+    use crate::utils::config::Config;
+    use crate::utils::env::{self, Environment};
+    use crate::utils::objects::{list_objects, read_object, write_object, ObjectType};
+    use crate::utils::revision::resolve_abbrev;
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn computes_prefix_of_current_dir() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git")).unwrap();
+        fs::create_dir_all(temp.join("a/b")).unwrap();
+
+        let repo = temp.repo();
+        assert_eq!(repo.prefix().unwrap(), "");
+
+        let repo = temp.repo_at("a/b");
+        assert_eq!(repo.prefix().unwrap(), "a/b");
+    }
+
+    #[test]
+    fn discovers_git_dir_in_parent() {
+        let temp = TempRepo::new();
+        let nested = temp.join("a/b");
+
+        fs::create_dir(temp.join(".git")).unwrap();
+        fs::create_dir_all(&nested).unwrap();
+
+        let repo = temp.repo_at(&nested);
+        assert_eq!(repo.git_dir().unwrap(), temp.join(".git"));
+        assert_eq!(repo.work_tree().unwrap(), temp.path());
+    }
+
+    #[test]
+    fn discovers_bare_repositories() {
+        let temp = TempRepo::new();
+        let git_dir = temp.join("x.git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        // From the git directory or any directory in it
+        for dir in [&git_dir, &git_dir.join("refs/heads")] {
+            let repo = temp.repo_at(dir);
+            assert_eq!(repo.git_dir().unwrap(), git_dir);
+            assert!(repo.is_bare().unwrap());
+            assert_eq!(repo.prefix().unwrap(), "");
+            assert_eq!(
+                repo.work_tree().unwrap_err().to_string(),
+                "this operation must be run in a work tree"
+            );
+        }
+
+        // A directory missing refs is not a repository
+        fs::remove_dir_all(git_dir.join("refs")).unwrap();
+        let repo = temp.repo_at(&git_dir);
+        assert!(repo.git_dir().is_err());
+    }
+
+    #[test]
+    fn explicit_git_dir_skips_discovery() {
+        let temp = TempRepo::new();
+
+        fs::create_dir(temp.join(".git")).unwrap();
+        fs::create_dir(temp.join("other")).unwrap();
+
+        let repo = temp.repo().open(PathBuf::from("other"), None);
+        assert_eq!(repo.git_dir().unwrap(), temp.join("other"));
+        // The working tree defaults to the current directory
+        assert_eq!(repo.work_tree().unwrap(), temp.path());
+    }
+
+    #[test]
+    fn explicit_options_override_environment() {
+        let temp = TempRepo::new()
+            .env(env::GIT_DIR, "env_git_dir")
+            .env(env::GIT_WORK_TREE, "env_work_tree");
+
+        let repo = temp
+            .repo()
+            .open("cli_git_dir".into(), Some("cli_work_tree".into()));
+        assert_eq!(
+            repo.explicit_git_dir(),
+            Some(temp.join("cli_git_dir").as_path())
+        );
+        assert_eq!(repo.work_tree().unwrap(), temp.join("cli_work_tree"));
+    }
+
+    #[test]
+    fn environment_used_without_options() {
+        let temp = TempRepo::new()
+            .env(env::GIT_DIR, "env_git_dir")
+            .env(env::GIT_WORK_TREE, "env_work_tree");
+
+        let repo = temp.repo();
+        assert_eq!(
+            repo.explicit_git_dir(),
+            Some(temp.join("env_git_dir").as_path())
+        );
+        assert_eq!(repo.work_tree().unwrap(), temp.join("env_work_tree"));
+    }
+
+    #[test]
+    fn follows_gitfile() {
+        let temp = TempRepo::new();
+        let admin_dir = temp.join("main/.git/worktrees/linked");
+        let linked = temp.join("linked");
+
+        fs::create_dir_all(&admin_dir).unwrap();
+        fs::create_dir(&linked).unwrap();
+        fs::write(admin_dir.join("commondir"), "../..\n").unwrap();
+        fs::write(
+            linked.join(".git"),
+            "gitdir: ../main/.git/worktrees/linked\n",
+        )
+        .unwrap();
+
+        let repo = temp.repo_at(&linked);
+        let git_dir = repo.git_dir().unwrap();
+        assert_eq!(
+            git_dir.canonicalize().unwrap(),
+            admin_dir.canonicalize().unwrap()
+        );
+        assert_eq!(repo.common_dir().unwrap(), temp.join("main/.git"));
+        assert_eq!(repo.work_tree().unwrap(), linked);
+    }
+
+    #[test]
+    fn fails_on_invalid_gitfile() {
+        let temp = TempRepo::new();
+
+        fs::write(temp.join(".git"), "not a gitfile").unwrap();
+
+        let repo = temp.repo();
+        assert!(repo.git_dir().is_err());
+    }
+
+    #[test]
+    fn fails_outside_repository() {
+        let temp = TempRepo::new();
+
+        let repo = temp.repo();
+        assert!(repo.git_dir().is_err());
+    }
+
+    #[test]
+    fn refuses_repositories_owned_by_others() {
+        let mut temp = TempRepo::new().env(env::GIT_TEST_ASSUME_DIFFERENT_OWNER, "1");
+        let global = temp.join("gitconfig");
+        temp.set_env(env::GIT_CONFIG_GLOBAL, &global);
+        let work_tree = temp.join("repo");
+        fs::create_dir_all(work_tree.join(".git")).unwrap();
+
+        // The repository cannot mark itself as safe
+        fs::write(work_tree.join(".git/config"), "[safe]\n\tdirectory = *\n").unwrap();
+        let repo = temp.repo_at(&work_tree);
+        let err = repo.git_dir().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "detected dubious ownership in repository at '{0}'\n\
+                 To add an exception for this directory, call:\n\n\
+                 \tgit config --global --add safe.directory {0}",
+                work_tree.display()
+            )
+        );
+
+        fs::write(
+            &global,
+            format!("[safe]\n\tdirectory = {}\n", work_tree.display()),
+        )
+        .unwrap();
+        assert!(repo.git_dir().is_ok());
+
+        // An empty value clears the list
+        fs::write(&global, "[safe]\n\tdirectory = *\n\tdirectory =\n").unwrap();
+        assert!(repo.git_dir().is_err());
+
+        fs::write(
+            &global,
+            format!("[safe]\n\tdirectory = {}/*\n", temp.path().display()),
+        )
+        .unwrap();
+        assert!(repo.git_dir().is_ok());
+
+        // Explicit git directories are not checked
+        let repo = repo.open(work_tree.join(".git"), None);
+        fs::write(&global, "").unwrap();
+        assert!(repo.git_dir().is_ok());
+    }
+
+    #[test]
+    fn refuses_unknown_repository_formats() {
+        let temp = TempRepo::new();
+        fs::create_dir(temp.join(".git")).unwrap();
+        let check = |config: &str| {
+            fs::write(temp.join(".git/config"), config).unwrap();
+            // Each repository context checks the format once
+            temp.repo().git_dir().map_err(|err| err.to_string())
+        };
+
+        assert!(check("[core]\n\trepositoryformatversion = 0\n[extensions]\n\tfoo = 1\n").is_ok());
+        assert_eq!(
+            check("[core]\n\trepositoryformatversion = 2\n").unwrap_err(),
+            "Expected git repo version <= 1, found 2"
+        );
+        assert_eq!(
+            check("[core]\n\trepositoryformatversion = 1\n[extensions]\n\tfoo = 1\n\tnoop\n")
+                .unwrap_err(),
+            "unknown repository extension found:\n\tfoo"
+        );
+        assert_eq!(
+            check("[core]\n\trepositoryformatversion = 1\n[extensions]\n\tobjectFormat = sha256\n")
+                .unwrap_err(),
+            "unsupported object format 'sha256'"
+        );
+        assert!(check(
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\trefStorage = reftable\n"
+        )
+        .is_ok());
+
+        // Worktree settings override the shared ones once the extension is set
+        let config = "[core]\n\trepositoryformatversion = 1\n\
+                      [extensions]\n\tobjectFormat = sha1\n\tworktreeConfig = true\n\
+                      [user]\n\tname = Shared\n";
+        assert!(check(config).is_ok());
+        fs::write(
+            temp.join(".git/config.worktree"),
+            "[user]\n\tname = Worktree\n",
+        )
+        .unwrap();
+        let repo = temp.repo();
+        assert_eq!(
+            Config::load(&repo).unwrap().get("user.name"),
+            Some("Worktree")
+        );
+    }
+
+    #[test]
+    fn borrows_objects_from_alternates() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join("shared/objects")).unwrap();
+        fs::create_dir_all(temp.join("repo/.git/objects/info")).unwrap();
+        fs::write(
+            temp.join("repo/.git/objects/info/alternates"),
+            "# borrowed\n../../../shared/objects\n../../../missing\n",
+        )
+        .unwrap();
+
+        let repo = temp.repo_at("repo");
+        let shared = repo.open(temp.join("shared"), None);
+        let hash = write_object(&shared, ObjectType::Blob, b"shared\n").unwrap();
+
+        assert_eq!(repo.alternate_object_dirs().unwrap(), [
+            temp.join("shared/objects")
+        ]);
+        assert_eq!(read_object(&repo, &hash).unwrap().1, b"shared\n");
+
+        // Borrowed objects are not copied into the repository
+        write_object(&repo, ObjectType::Blob, b"shared\n").unwrap();
+        assert!(!repo.object_path(&hash, false).unwrap().exists());
+
+        // They are listed and found by abbreviated hash as well
+        assert_eq!(list_objects(&repo).unwrap(), [(
+            hash.clone(),
+            ObjectType::Blob,
+            7
+        )]);
+        assert_eq!(resolve_abbrev(&repo, &hash[..7]).unwrap(), hash);
+    }
+
+    #[test]
+    fn uses_repositories_from_threads() {
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let threads: Vec<_> = dirs
+            .iter()
+            .enumerate()
+            .map(|(i, dir)| {
+                let root = dir.path().canonicalize().unwrap();
+                fs::create_dir_all(root.join(".git/objects")).unwrap();
+                fs::create_dir_all(root.join("sub")).unwrap();
+                std::thread::spawn(move || {
+                    let env = Environment::from_iter([
+                        (env::GIT_CONFIG_NOSYSTEM, "1"),
+                        (env::GIT_CONFIG_GLOBAL, "/dev/null"),
+                        (env::GIT_INDEX_FILE, &format!("index-{i}")),
+                        ("MARK", &format!("repo {i}")),
+                    ]);
+                    let repo = Repository::with_environment(root.join("sub"), env, None, None);
+                    assert_eq!(repo.work_tree().unwrap(), root);
+                    assert_eq!(repo.prefix().unwrap(), "sub");
+                    assert_eq!(
+                        repo.index_path().unwrap(),
+                        root.join(format!("sub/index-{i}"))
+                    );
+
+                    let content = format!("object {i}\n");
+                    let hash = write_object(&repo, ObjectType::Blob, content.as_bytes()).unwrap();
+                    assert_eq!(read_object(&repo, &hash).unwrap().1, content.as_bytes());
+                    assert!(root.join(".git/objects").join(&hash[..2]).exists());
+
+                    let output = repo
+                        .command("sh")
+                        .args(["-c", "echo $MARK; pwd"])
+                        .output()
+                        .unwrap();
+                    assert_eq!(
+                        String::from_utf8(output.stdout).unwrap(),
+                        format!("repo {i}\n{}\n", root.join("sub").display())
+                    );
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Recorded resolutions of conflicts, reused when the same conflicts happen
+//! again ("reuse recorded resolution"), stored as git stores them:
+//!
+//! - `rr-cache/<id>/preimage` holds a file with conflicts, normalized: the
+//!   conflict markers are written without labels, the common ancestor of each
+//!   conflict (in diff3 style) is dropped, and the two sides are sorted so that
+//!   the same conflict is recognized whichever side is ours. The ID is the hash
+//!   of the sides of every conflict of the file.
+//! - `rr-cache/<id>/postimage` holds the file once the conflicts were resolved.
+//! - `MERGE_RR` lists the paths whose conflicts wait for a resolution to be
+//!   recorded (`<id>\t<path>`, each terminated by NUL).
+//!
+//! Resolutions are recorded and reused when `rerere.enabled` is set, or if it is
+//! not set but `rr-cache` exists. A single resolution is kept per conflict.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::utils::config::Config;
+use crate::utils::hex;
+use crate::utils::repository::Repository;
+
+/// The length of the conflict markers
+const MARKER_SIZE: usize = 7;
+
+/// Whether resolutions are recorded and reused.
+pub(crate) fn is_enabled(repo: &Repository) -> anyhow::Result<bool> {
+    match Config::load(repo)?.get_bool("rerere.enabled")? {
+        Some(enabled) => Ok(enabled),
+        None => Ok(rr_cache_dir(repo)?.is_dir()),
+    }
+}
+
+/// The directory of the recorded conflicts and resolutions.
+pub(crate) fn rr_cache_dir(repo: &Repository) -> anyhow::Result<PathBuf> {
+    Ok(repo.common_dir()?.join("rr-cache"))
+}
+
+/// The directory of a conflict, holding its `preimage` and `postimage`.
+pub(crate) fn conflict_dir(repo: &Repository, id: &str) -> anyhow::Result<PathBuf> {
+    Ok(rr_cache_dir(repo)?.join(id))
+}
+
+fn merge_rr_path(repo: &Repository) -> anyhow::Result<PathBuf> {
+    Ok(repo.git_dir()?.join("MERGE_RR"))
+}
+
+/// Read the paths waiting for a resolution, with the ID of their conflicts.
+pub(crate) fn read_merge_rr(repo: &Repository) -> anyhow::Result<BTreeMap<String, String>> {
+    let path = merge_rr_path(repo)?;
+    let content = match std::fs::read(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err).context("read MERGE_RR"),
+    };
+
+    let mut paths = BTreeMap::new();
+    for record in content
+        .split(|&b| b == 0)
+        .filter(|record| !record.is_empty())
+    {
+        let record = String::from_utf8_lossy(record);
+        let Some((id, path)) = record.split_once('\t') else {
+            anyhow::bail!("corrupt MERGE_RR");
+        };
+        paths.insert(path.to_string(), id.to_string());
+    }
+    Ok(paths)
+}
+
+/// Write the paths waiting for a resolution, with the ID of their conflicts.
+pub(crate) fn write_merge_rr(
+    repo: &Repository,
+    paths: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let content: String = paths
+        .iter()
+        .map(|(path, id)| format!("{id}\t{path}\0"))
+        .collect();
+    std::fs::write(merge_rr_path(repo)?, content).context("write MERGE_RR")
+}
+
+/// Remove the list of paths waiting for a resolution.
+pub(crate) fn remove_merge_rr(repo: &Repository) -> anyhow::Result<()> {
+    match std::fs::remove_file(merge_rr_path(repo)?) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).context("remove MERGE_RR")
+        },
+        _ => Ok(()),
+    }
+}
+
+/// The conflicts of a file, normalized as recorded in a preimage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Conflicts {
+    /// The ID of the conflicts, the hash of their sides
+    pub(crate) id: String,
+    /// The content of the file with its conflicts normalized
+    pub(crate) content: Vec<u8>,
+}
+
+/// Find the conflicts of a file and normalize them.
+///
+/// # Returns
+///
+/// The normalized conflicts, or `None` if the file has no conflict markers (or
+/// markers that do not delimit conflicts, such as nested ones)
+pub(crate) fn parse_conflicts(content: &[u8]) -> Option<Conflicts> {
+    enum Section {
+        Outside,
+        Ours,
+        Base,
+        Theirs,
+    }
+
+    let marker = |line: &[u8], c: u8| {
+        let Some(rest) = line.strip_prefix([c; MARKER_SIZE].as_slice()) else {
+            return false;
+        };
+        // The markers of the sides are followed by their label
+        match c {
+            b'<' | b'>' => rest.first() == Some(&b' '),
+            _ => rest.first().is_some_and(u8::is_ascii_whitespace),
+        }
+    };
+
+    let mut hasher = Sha1::new();
+    let mut normalized = Vec::with_capacity(content.len());
+    let mut section = Section::Outside;
+    let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+    let mut found = false;
+
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        match section {
+            Section::Outside if marker(line, b'<') => section = Section::Ours,
+            Section::Outside => normalized.extend_from_slice(line),
+            _ if marker(line, b'<') => return None,
+            Section::Ours if marker(line, b'|') => section = Section::Base,
+            Section::Ours | Section::Base if marker(line, b'=') => section = Section::Theirs,
+            Section::Theirs if marker(line, b'>') => {
+                if ours > theirs {
+                    std::mem::swap(&mut ours, &mut theirs);
+                }
+                for (side, separator) in [(&ours, b'<'), (&theirs, b'=')] {
+                    normalized.extend_from_slice(&[separator; MARKER_SIZE]);
+                    normalized.push(b'\n');
+                    normalized.extend_from_slice(side);
+                    hasher.update(side);
+                    hasher.update([0]);
+                }
+                normalized.extend_from_slice(&[b'>'; MARKER_SIZE]);
+                normalized.push(b'\n');
+                ours.clear();
+                theirs.clear();
+                section = Section::Outside;
+                found = true;
+            },
+            Section::Ours => ours.extend_from_slice(line),
+            Section::Base => {},
+            Section::Theirs => theirs.extend_from_slice(line),
+        }
+    }
+
+    match (section, found) {
+        (Section::Outside, true) => {
+            let mut id = hasher.finalize().to_vec();
+            hex::encode_in_place(&mut id);
+            Some(Conflicts {
+                id: String::from_utf8_lossy(&id).into_owned(),
+                content: normalized,
+            })
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_conflicts() {
+        let content = b"1\n<<<<<<< HEAD\ntwo-main\n=======\ntwo-b\n>>>>>>> b\n3\n\
+                        <<<<<<< HEAD\nfour-a\n||||||| base\n4\n=======\nfour-b\n>>>>>>> b\n";
+        let conflicts = parse_conflicts(content).unwrap();
+        assert_eq!(
+            conflicts.content,
+            b"1\n<<<<<<<\ntwo-b\n=======\ntwo-main\n>>>>>>>\n3\n\
+              <<<<<<<\nfour-a\n=======\nfour-b\n>>>>>>>\n"
+        );
+        // The hash of `two-b\n\0two-main\n\0four-a\n\0four-b\n\0`
+        assert_eq!(conflicts.id, "e0f9fb89177296bf5fa9cfcdf10e1cd81d5d4437");
+
+        // The same conflict from the other side has the same ID
+        let swapped = b"<<<<<<< ours\nfive-b\n=======\nfive-main\n>>>>>>> theirs\n";
+        let other = b"<<<<<<< ours\nfive-main\n=======\nfive-b\n>>>>>>> theirs\n";
+        assert_eq!(parse_conflicts(swapped), parse_conflicts(other));
+        assert_eq!(
+            parse_conflicts(swapped).unwrap().id,
+            "7e33802ab226f47a5077314adb1b35ec2ca32748"
+        );
+
+        assert!(parse_conflicts(b"no conflicts\n").is_none());
+        assert!(parse_conflicts(b"<<<<<<< ours\nunterminated\n=======\n").is_none());
+        assert!(parse_conflicts(b"<<<<<<<< too long\n=======\n>>>>>>> x\n").is_none());
+    }
+}
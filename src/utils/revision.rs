@@ -0,0 +1,283 @@
+//! Resolving revision expressions (`HEAD~2`, `main^{tree}`, `v1.0^0`, ...) to objects
+
+use anyhow::Context;
+
+use crate::utils::commit::{peel, peel_to_commit, peel_to_tree, read_commit};
+use crate::utils::index::Index;
+use crate::utils::objects::{object_exists, read_object, ObjectType};
+use crate::utils::reflog::read_reflog;
+use crate::utils::refs::{
+    head_branch, is_hash, is_worktree_qualified_ref, resolve_ref, resolve_symref,
+};
+use crate::utils::repository::Repository;
+use crate::utils::tree::find_entry;
+
+/// The minimum length of an abbreviated object hash
+const MIN_ABBREV: usize = 4;
+
+/// Resolve a revision expression to an object hash.
+///
+/// Supported syntax:
+///
+/// - Full or abbreviated (at least 4 characters) object hashes
+/// - Ref names (`HEAD`, `main`, `v1.0`, `origin/main`, `refs/heads/main`)
+/// - `<ref>@{<n>}` for the value of a ref n updates ago, from its reflog
+///   (`@{<n>}` alone is the current branch)
+/// - `<rev>^<n>` for the n-th parent (`^` alone is the first parent)
+/// - `<rev>~<n>` for the n-th generation ancestor following first parents
+/// - `<rev>^{<type>}` to peel to an object type, `<rev>^{}` to peel tags
+/// - `<rev>:<path>` for the object at a path in the tree of a revision,
+///   and `:<path>` for the object staged at a path in the index
+pub(crate) fn resolve_revision(repo: &Repository, revision: &str) -> anyhow::Result<String> {
+    if let Some((base, path)) = revision.split_once(':') {
+        return resolve_path(repo, base, path);
+    }
+
+    let base_end = revision.find(['^', '~']).unwrap_or(revision.len());
+    let (base, mut suffix) = revision.split_at(base_end);
+
+    let mut hash = resolve_base(repo, base)?;
+
+    while !suffix.is_empty() {
+        if let Some(rest) = suffix.strip_prefix("^{") {
+            let end = rest
+                .find('}')
+                .context(format!("invalid revision: {revision}"))?;
+            hash = peel_to_type(repo, &hash, &rest[..end])?;
+            suffix = &rest[end + 1..];
+            continue;
+        }
+
+        let operator = suffix.as_bytes()[0];
+        let digits = suffix[1..].bytes().take_while(u8::is_ascii_digit).count();
+        let count = match digits {
+            0 => 1,
+            _ => suffix[1..=digits].parse::<usize>()?,
+        };
+        suffix = &suffix[1 + digits..];
+
+        hash = match operator {
+            b'^' => nth_parent(repo, &hash, count)?,
+            b'~' => nth_ancestor(repo, &hash, count)?,
+            _ => anyhow::bail!("invalid revision: {revision}"),
+        };
+    }
+
+    Ok(hash)
+}
+
+/// Resolve a revision expression and peel it to a commit.
+pub(crate) fn resolve_commit(repo: &Repository, revision: &str) -> anyhow::Result<String> {
+    let hash = resolve_revision(repo, revision)?;
+    peel_to_commit(repo, &hash).context(format!("{revision} is not a commit"))
+}
+
+/// Resolve a revision expression and peel it to a tree.
+pub(crate) fn resolve_tree(repo: &Repository, revision: &str) -> anyhow::Result<String> {
+    let hash = resolve_revision(repo, revision)?;
+    peel_to_tree(repo, &hash).context(format!("{revision} is not a tree"))
+}
+
+/// Get the full name of the ref a revision names, following symbolic refs
+/// (e.g. `main` or `HEAD` to `refs/heads/main`).
+///
+/// # Returns
+///
+/// The full ref name, or `None` if the revision is not a ref name
+pub(crate) fn resolve_full_ref_name(
+    repo: &Repository,
+    revision: &str,
+) -> anyhow::Result<Option<String>> {
+    let revision = if revision == "@" { "HEAD" } else { revision };
+
+    for candidate in ref_candidates(revision) {
+        let resolved = resolve_symref(repo, &candidate)?;
+        if resolved.hash.is_some() {
+            return Ok(Some(resolved.name));
+        }
+    }
+    Ok(None)
+}
+
+/// Get the candidate ref names for a short name, in order of precedence.
+///
+/// The name itself is only a candidate if it is a full ref name, a
+/// pseudo-ref (all uppercase, e.g. `HEAD` or `ORIG_HEAD`) or a ref of
+/// another worktree (e.g. `main-worktree/HEAD`), so that files such as
+/// `.git/config` are never mistaken for refs.
+pub(crate) fn ref_candidates(name: &str) -> Vec<String> {
+    let is_pseudo_ref = name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_');
+    let own_name = (name.starts_with("refs/") || is_pseudo_ref || is_worktree_qualified_ref(name))
+        .then(|| name.to_string());
+
+    own_name
+        .into_iter()
+        .chain([
+            format!("refs/{name}"),
+            format!("refs/tags/{name}"),
+            format!("refs/heads/{name}"),
+            format!("refs/remotes/{name}"),
+            format!("refs/remotes/{name}/HEAD"),
+        ])
+        .collect()
+}
+
+/// Resolve a revision without any suffix operators.
+fn resolve_base(repo: &Repository, base: &str) -> anyhow::Result<String> {
+    // `@` is a shortcut for HEAD
+    let base = if base == "@" { "HEAD" } else { base };
+
+    if base.is_empty() {
+        anyhow::bail!("empty revision");
+    }
+
+    if let Some((name, rest)) = base.split_once("@{") {
+        let Some(n) = rest.strip_suffix('}').and_then(|n| n.parse().ok()) else {
+            anyhow::bail!("ambiguous argument '{base}': unknown revision");
+        };
+        return resolve_reflog_entry(repo, name, n)?
+            .context(format!("ambiguous argument '{base}': unknown revision"));
+    }
+
+    if is_hash(base) && object_exists(repo, base)? {
+        return Ok(base.to_lowercase());
+    }
+
+    for candidate in ref_candidates(base) {
+        if let Some(hash) = resolve_ref(repo, &candidate)? {
+            return Ok(hash);
+        }
+    }
+
+    if base.len() >= MIN_ABBREV && base.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return resolve_abbrev(repo, &base.to_lowercase());
+    }
+
+    anyhow::bail!("ambiguous argument '{base}': unknown revision")
+}
+
+/// Resolve `<rev>:<path>` (or `:<path>` for the index) to the object at the path.
+fn resolve_path(repo: &Repository, base: &str, path: &str) -> anyhow::Result<String> {
+    let path = path.trim_matches('/');
+
+    if base.is_empty() {
+        let index = Index::load(repo)?;
+        return index
+            .entry(path.as_bytes(), 0)
+            .map(|entry| entry.hash.clone())
+            .context(format!("path '{path}' does not exist in the index"));
+    }
+
+    let tree = resolve_tree(repo, base)?;
+    if path.is_empty() {
+        return Ok(tree);
+    }
+    find_entry(repo, &tree, path)?
+        .map(|entry| entry.hash)
+        .context(format!("path '{path}' does not exist in '{base}'"))
+}
+
+/// Resolve `<name>@{<n>}` to the value of a ref n updates ago.
+///
+/// # Returns
+///
+/// The hash, or `None` if no ref matching the name has a reflog
+fn resolve_reflog_entry(repo: &Repository, name: &str, n: usize) -> anyhow::Result<Option<String>> {
+    let candidates = match name {
+        "" => vec![head_branch(repo)?.unwrap_or_else(|| "HEAD".to_string())],
+        _ => ref_candidates(name),
+    };
+
+    for candidate in candidates {
+        let entries = read_reflog(repo, &candidate)?;
+        if entries.is_empty() {
+            continue;
+        }
+
+        let Some(entry) = entries.iter().rev().nth(n) else {
+            let name = if name.is_empty() { &candidate } else { name };
+            anyhow::bail!("log for '{name}' only has {} entries", entries.len());
+        };
+        return Ok(Some(entry.new.clone()));
+    }
+
+    Ok(None)
+}
+
+/// Find the unique object whose hash starts with the given prefix, in the
+/// repository or its alternates.
+pub(crate) fn resolve_abbrev(repo: &Repository, prefix: &str) -> anyhow::Result<String> {
+    let mut object_dirs = vec![repo.object_dir(false)?];
+    object_dirs.extend(repo.alternate_object_dirs()?.iter().cloned());
+    let mut matches = Vec::new();
+
+    for object_dir in object_dirs {
+        let subdir = object_dir.join(&prefix[..2]);
+        if !subdir.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&subdir)? {
+            let file_name = entry?.file_name();
+            let file_name = file_name.to_string_lossy();
+            let hash = format!("{}{}", &prefix[..2], file_name);
+
+            // An object may be both in the repository and in an alternate
+            if hash.starts_with(prefix) && is_hash(&hash) && !matches.contains(&hash) {
+                matches.push(hash);
+            }
+        }
+    }
+
+    match matches.len() {
+        0 => anyhow::bail!("ambiguous argument '{prefix}': unknown revision"),
+        1 => Ok(matches.remove(0)),
+        _ => anyhow::bail!("short object ID {prefix} is ambiguous"),
+    }
+}
+
+/// Get the n-th parent of a commit (`^0` is the commit itself).
+fn nth_parent(repo: &Repository, hash: &str, n: usize) -> anyhow::Result<String> {
+    let hash = peel_to_commit(repo, hash)?;
+    if n == 0 {
+        return Ok(hash);
+    }
+
+    let commit = read_commit(repo, &hash)?;
+    commit
+        .parents
+        .get(n - 1)
+        .cloned()
+        .context(format!("commit {hash} does not have parent {n}"))
+}
+
+/// Get the n-th generation ancestor of a commit, following first parents.
+fn nth_ancestor(repo: &Repository, hash: &str, n: usize) -> anyhow::Result<String> {
+    let mut hash = peel_to_commit(repo, hash)?;
+    for _ in 0..n {
+        hash = nth_parent(repo, &hash, 1)?;
+    }
+    Ok(hash)
+}
+
+/// Peel an object for the `^{<type>}` suffix.
+fn peel_to_type(repo: &Repository, hash: &str, object_type: &str) -> anyhow::Result<String> {
+    match object_type {
+        "" => Ok(peel(repo, hash)?.0),
+        "commit" => peel_to_commit(repo, hash),
+        "tree" => peel_to_tree(repo, hash),
+        "object" => Ok(hash.to_string()),
+        other => {
+            let expected = ObjectType::try_from(other.as_bytes())?;
+            if read_object(repo, hash)?.0 == expected {
+                return Ok(hash.to_string());
+            }
+
+            match peel(repo, hash)? {
+                (hash, object_type) if object_type == expected => Ok(hash),
+                (hash, object_type) => {
+                    anyhow::bail!("object {hash} is a {object_type}, not a {expected}")
+                },
+            }
+        },
+    }
+}
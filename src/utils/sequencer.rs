@@ -0,0 +1,281 @@
+//! The state of a cherry-pick or revert of several commits, stored under
+//! `.git/sequencer` while it is stopped (by a conflict or an empty commit):
+//!
+//! - `todo` lists the commits still to apply, the one it stopped at first
+//!   (`pick <hash> <subject>` or `revert <hash> <subject>`)
+//! - `head` holds the commit HEAD was at before it started, restored by `--abort`
+//! - `abort-safety` holds the commit HEAD was at when it stopped: if HEAD moved
+//!   since, `--abort` does not rewind it
+//! - `opts` holds its options if any, in the format of configuration files
+//!
+//! The commit it stopped at is also recorded in `CHERRY_PICK_HEAD` (or `REVERT_HEAD`),
+//! unless its changes are not to be committed, and its message in `MERGE_MSG`.
+//!
+//! The todo lists of interactive rebases also reword, squash, fix up and drop commits.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::utils::commit::read_commit;
+use crate::utils::config::{Config, ConfigScope};
+use crate::utils::repository::Repository;
+
+/// What is done with a commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// Apply the changes of the commit
+    Pick,
+    /// Apply the inverse of the changes of the commit
+    Revert,
+    /// Apply the changes of the commit, editing its message
+    Reword,
+    /// Meld the changes of the commit into the previous one, combining their messages
+    Squash,
+    /// Meld the changes of the commit into the previous one, keeping its message
+    Fixup,
+    /// Leave the commit out
+    Drop,
+}
+
+impl Action {
+    /// The name of the command doing the action (the actions of rebases pick commits)
+    pub(crate) fn command(self) -> &'static str {
+        match self {
+            Action::Revert => "revert",
+            _ => "cherry-pick",
+        }
+    }
+
+    /// The pseudo-ref recording the commit the action stopped at
+    pub(crate) fn head_ref(self) -> &'static str {
+        match self {
+            Action::Revert => "REVERT_HEAD",
+            _ => "CHERRY_PICK_HEAD",
+        }
+    }
+
+    /// The name of the action in todo lists
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+            Action::Reword => "reword",
+            Action::Squash => "squash",
+            Action::Fixup => "fixup",
+            Action::Drop => "drop",
+        }
+    }
+}
+
+/// A commit to apply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Step {
+    pub(crate) action: Action,
+    /// The hash of the commit
+    pub(crate) hash: String,
+}
+
+impl Step {
+    /// Parse a line of the todo list.
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let mut words = line.split_whitespace();
+        let action = match words.next() {
+            Some("pick" | "p") => Action::Pick,
+            Some("revert") => Action::Revert,
+            Some("reword" | "r") => Action::Reword,
+            Some("squash" | "s") => Action::Squash,
+            Some("fixup" | "f") => Action::Fixup,
+            Some("drop" | "d") => Action::Drop,
+            _ => anyhow::bail!("invalid line in the todo list: {line}"),
+        };
+        let hash = words
+            .next()
+            .context(format!("missing commit in the todo list: {line}"))?;
+        Ok(Step {
+            action,
+            hash: hash.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.action.name(), self.hash)
+    }
+}
+
+/// Format a todo list, a line per commit with its subject.
+pub(crate) fn format_todo(repo: &Repository, todo: &[Step]) -> anyhow::Result<String> {
+    let mut content = String::new();
+    for step in todo {
+        let subject = read_commit(repo, &step.hash)?.subject();
+        content.push_str(&format!("{step} {subject}\n"));
+    }
+    Ok(content)
+}
+
+/// Parse a todo list, skipping empty lines and comments.
+pub(crate) fn parse_todo(content: &str) -> anyhow::Result<Vec<Step>> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(Step::parse)
+        .collect()
+}
+
+/// The options of a sequence of commits to apply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Options {
+    /// Whether the changes are only applied to the index and the working tree
+    pub(crate) no_commit: bool,
+}
+
+/// The directory of the state.
+fn sequencer_dir(repo: &Repository) -> anyhow::Result<PathBuf> {
+    Ok(repo.git_dir()?.join("sequencer"))
+}
+
+/// Whether a sequence of commits is stopped.
+pub(crate) fn in_progress(repo: &Repository) -> anyhow::Result<bool> {
+    Ok(sequencer_dir(repo)?.is_dir())
+}
+
+/// Save the state of a stopped sequence.
+///
+/// # Arguments
+///
+/// * `repo` - The repository the sequence is applied in
+/// * `todo` - The commits still to apply, the one it stopped at first
+/// * `head` - The commit HEAD was at before the sequence started, kept from the
+///   saved state if there is one
+/// * `current` - The commit HEAD is at now
+/// * `options` - The options of the sequence
+pub(crate) fn save(
+    repo: &Repository,
+    todo: &[Step],
+    head: &str,
+    current: &str,
+    options: Options,
+) -> anyhow::Result<()> {
+    let dir = sequencer_dir(repo)?;
+    std::fs::create_dir_all(&dir).context("create sequencer directory")?;
+
+    std::fs::write(dir.join("todo"), format_todo(repo, todo)?).context("write sequencer todo")?;
+    if !dir.join("head").is_file() {
+        std::fs::write(dir.join("head"), format!("{head}\n")).context("write sequencer head")?;
+    }
+    std::fs::write(dir.join("abort-safety"), format!("{current}\n"))
+        .context("write sequencer abort-safety")?;
+
+    if options.no_commit {
+        std::fs::write(dir.join("opts"), "[options]\n\tno-commit = true\n")
+            .context("write sequencer opts")?;
+    }
+    Ok(())
+}
+
+/// The saved state of a stopped sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct State {
+    /// The commits still to apply, the one it stopped at first
+    pub(crate) todo: Vec<Step>,
+    /// The commit HEAD was at before the sequence started
+    pub(crate) head: String,
+    /// The commit HEAD was at when the sequence stopped
+    pub(crate) abort_safety: String,
+    pub(crate) options: Options,
+}
+
+/// Load the state of a stopped sequence.
+///
+/// # Returns
+///
+/// The state, or `None` if no sequence is stopped
+pub(crate) fn load(repo: &Repository) -> anyhow::Result<Option<State>> {
+    let dir = sequencer_dir(repo)?;
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let read = |name: &str| {
+        std::fs::read_to_string(dir.join(name)).context(format!("read sequencer {name}"))
+    };
+
+    let todo = parse_todo(&read("todo")?)?;
+    let opts_path = dir.join("opts");
+    let opts = match opts_path.is_file() {
+        true => Config::parse(&read("opts")?, &opts_path, ConfigScope::Local)?,
+        false => Config::default(),
+    };
+
+    Ok(Some(State {
+        todo,
+        head: read("head")?.trim().to_string(),
+        abort_safety: read("abort-safety")?.trim().to_string(),
+        options: Options {
+            no_commit: opts.get_bool("options.no-commit")?.unwrap_or(false),
+        },
+    }))
+}
+
+/// Remove the state of a stopped sequence, if any.
+pub(crate) fn remove(repo: &Repository) -> anyhow::Result<()> {
+    let dir = sequencer_dir(repo)?;
+    if dir.is_dir() {
+        std::fs::remove_dir_all(&dir).context("remove sequencer directory")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::commit::create_commit;
+    use crate::utils::env;
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::EMPTY_TREE;
+
+    #[test]
+    fn saves_and_loads_state() {
+        let temp = TempRepo::new()
+            .env(env::GIT_AUTHOR_NAME, "A U Thor")
+            .env(env::GIT_AUTHOR_EMAIL, "author@example.com")
+            .env(env::GIT_COMMITTER_NAME, "C O Mitter")
+            .env(env::GIT_COMMITTER_EMAIL, "committer@example.com");
+        std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        let repo = temp.repo();
+        let one = create_commit(&repo, EMPTY_TREE, Vec::new(), "one\n").unwrap();
+        let two = create_commit(&repo, EMPTY_TREE, vec![one.clone()], "two\n").unwrap();
+        assert_eq!(load(&repo).unwrap(), None);
+
+        let todo = vec![
+            Step {
+                action: Action::Pick,
+                hash: one.clone(),
+            },
+            Step {
+                action: Action::Revert,
+                hash: two.clone(),
+            },
+        ];
+        let options = Options { no_commit: true };
+        save(&repo, &todo, &one, &two, options).unwrap();
+        // The original HEAD is kept when the state is saved again
+        save(&repo, &todo[1..], &two, &two, options).unwrap();
+
+        let dir = repo.git_dir().unwrap().join("sequencer");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("todo")).unwrap(),
+            format!("revert {two} two\n")
+        );
+        let state = load(&repo).unwrap().unwrap();
+        assert_eq!(state.todo, &todo[1..]);
+        assert_eq!(state.head, one);
+        assert_eq!(state.abort_safety, two);
+        assert_eq!(state.options, options);
+
+        remove(&repo).unwrap();
+        assert!(!in_progress(&repo).unwrap());
+    }
+}
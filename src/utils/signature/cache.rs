@@ -0,0 +1,220 @@
+//! Caching signature verification results across runs
+//!
+//! Verifying a signature spawns an external program, which is slow for long
+//! histories. Results are stored in `$GIT_COMMON_DIR/signature-cache`, keyed by
+//! the object hash. The file records a fingerprint of the verification settings
+//! (e.g. the allowed signers), and is discarded whenever they change.
+//!
+//! Only the results of SSH signatures are stored, as everything they depend on
+//! is in the settings. Those of OpenPGP and X.509 signatures also depend on the
+//! keyring and trust database of `gpg` (keys imported, expired or revoked since),
+//! so they are only remembered for the command that verified them.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::utils::commit::{Commit, Ident};
+use crate::utils::config::Config;
+use crate::utils::repository::Repository;
+use crate::utils::signature::{
+    split_commit_signature, ssh, verify_signature, SignatureCheck, SignatureFormat, SignatureStatus,
+};
+use crate::utils::tempfile::TempPath;
+
+/// The first word of the cache file, followed by the settings fingerprint
+const CACHE_HEADER: &str = "signature-cache v2";
+
+/// Verification results of signed objects
+pub(crate) struct SignatureCache<'a> {
+    config: &'a Config,
+    path: PathBuf,
+    /// A hash of the settings the cached results were computed with
+    fingerprint: String,
+    /// The results stored in the cache file
+    entries: HashMap<String, SignatureCheck>,
+    /// The results only remembered by this command
+    unsaved: HashMap<String, SignatureCheck>,
+    /// Whether entries were added since the cache was loaded
+    dirty: bool,
+}
+
+impl<'a> SignatureCache<'a> {
+    /// Load the cache of a repository.
+    /// Results computed with different verification settings are discarded.
+    pub(crate) fn load(repo: &Repository, config: &'a Config) -> anyhow::Result<Self> {
+        let path = repo.common_dir()?.join("signature-cache");
+        let fingerprint = settings_fingerprint(config);
+
+        let mut entries = HashMap::new();
+        if let Ok(content) = std::fs::read_to_string(&path) {
+            let mut lines = content.lines();
+            if lines.next() == Some(&format!("{CACHE_HEADER} {fingerprint}")) {
+                // Malformed lines are skipped, they will be verified again
+                entries.extend(lines.filter_map(parse_entry));
+            }
+        }
+
+        Ok(SignatureCache {
+            config,
+            path,
+            fingerprint,
+            entries,
+            unsaved: HashMap::new(),
+            dirty: false,
+        })
+    }
+
+    /// Verify the signature of a commit, using the cached result if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The hash of the commit
+    /// * `content` - The raw content of the commit object
+    ///
+    /// # Returns
+    ///
+    /// The result of the verification, or `None` if the commit is not signed
+    pub(crate) fn verify_commit(
+        &mut self,
+        hash: &str,
+        content: &[u8],
+    ) -> anyhow::Result<Option<SignatureCheck>> {
+        if let Some(check) = self.entries.get(hash).or(self.unsaved.get(hash)) {
+            return Ok(Some(check.clone()));
+        }
+
+        let Some((payload, signature)) = split_commit_signature(content) else {
+            return Ok(None);
+        };
+
+        let commit = Commit::parse(content)?;
+        let timestamp = Ident::parse(&commit.committer)
+            .ok()
+            .map(|ident| ident.timestamp);
+
+        let check = verify_signature(self.config, &payload, &signature, timestamp)?;
+        match SignatureFormat::detect(&signature) {
+            Some(SignatureFormat::Ssh) => {
+                self.entries.insert(hash.to_string(), check.clone());
+                self.dirty = true;
+            },
+            _ => {
+                self.unsaved.insert(hash.to_string(), check.clone());
+            },
+        }
+        Ok(Some(check))
+    }
+
+    /// Write the cache back to disk if entries were added.
+    pub(crate) fn save(&self) -> anyhow::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut content = format!("{CACHE_HEADER} {}\n", self.fingerprint);
+        for (hash, check) in &self.entries {
+            content.push_str(&format_entry(hash, check));
+            content.push('\n');
+        }
+
+        // Write to a temporary file first so readers never see a partial cache
+        let temp_path = TempPath::new(self.path.with_extension("lock"));
+        std::fs::write(temp_path.path(), content)
+            .context(format!("write {}", temp_path.path().display()))?;
+        std::fs::rename(temp_path.path(), &self.path)
+            .context(format!("rename {}", temp_path.path().display()))?;
+        temp_path.keep();
+        Ok(())
+    }
+}
+
+/// Hash the settings that affect the outcome of the verification of an SSH
+/// signature.
+fn settings_fingerprint(config: &Config) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(ssh::settings(config));
+    format!("{:x}", hasher.finalize())
+}
+
+/// Format a cache entry as `<hash> <status> <signer or -> <escaped output>`.
+fn format_entry(hash: &str, check: &SignatureCheck) -> String {
+    let status = match check.status {
+        SignatureStatus::Good => "good",
+        SignatureStatus::Untrusted => "untrusted",
+        SignatureStatus::Bad => "bad",
+    };
+    let output = check.output.replace('\\', "\\\\").replace('\n', "\\n");
+
+    format!(
+        "{hash} {status} {} {output}",
+        check.signer.as_deref().unwrap_or("-")
+    )
+}
+
+/// Parse a cache entry written by [`format_entry`].
+fn parse_entry(line: &str) -> Option<(String, SignatureCheck)> {
+    let mut parts = line.splitn(4, ' ');
+    let hash = parts.next()?.to_string();
+    let status = match parts.next()? {
+        "good" => SignatureStatus::Good,
+        "untrusted" => SignatureStatus::Untrusted,
+        "bad" => SignatureStatus::Bad,
+        _ => return None,
+    };
+    let signer = match parts.next()? {
+        "-" => None,
+        signer => Some(signer.to_string()),
+    };
+
+    let mut output = String::new();
+    let mut chars = parts.next().unwrap_or_default().chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => output.push('\n'),
+            escaped => output.push(escaped),
+        }
+    }
+
+    Some((hash, SignatureCheck {
+        status,
+        signer,
+        output,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_entry, parse_entry};
+    use crate::utils::signature::{SignatureCheck, SignatureStatus};
+
+    #[test]
+    fn round_trips_entries() {
+        let check = SignatureCheck {
+            status: SignatureStatus::Untrusted,
+            signer: None,
+            output: "Good \"git\" signature\nNo principal matched. \\o/".to_string(),
+        };
+
+        let line = format_entry("abc", &check);
+        assert!(!line.contains('\n'));
+        assert_eq!(parse_entry(&line), Some(("abc".to_string(), check)));
+
+        let check = SignatureCheck {
+            status: SignatureStatus::Good,
+            signer: Some("a@example.com".to_string()),
+            output: String::new(),
+        };
+        assert_eq!(
+            parse_entry(&format_entry("def", &check)),
+            Some(("def".to_string(), check))
+        );
+        assert_eq!(parse_entry("abc unknown - output"), None);
+    }
+}
@@ -0,0 +1,137 @@
+//! Creating and verifying OpenPGP and X.509 signatures with `gpg` and `gpgsm`
+//!
+//! Both programs share the same interface: signatures are made with
+//! `--status-fd=2 -bsau <key>` and verified with `--status-fd=1 --verify`,
+//! and the machine-readable status lines (`[GNUPG:] ...`) tell the outcome.
+
+use anyhow::Context;
+
+use crate::utils::commit::{Ident, Role};
+use crate::utils::config::Config;
+use crate::utils::signature::{
+    run_program, SignatureCheck, SignatureFormat, SignatureStatus, TempFile,
+};
+
+/// The prefix of the status lines written to the status file descriptor
+const STATUS_PREFIX: &str = "[GNUPG:] ";
+
+/// Get the program used for a signature format from `gpg.<format>.program`,
+/// falling back to `gpg.program` for OpenPGP.
+fn program(config: &Config, format: SignatureFormat) -> &str {
+    match format {
+        SignatureFormat::X509 => config.get("gpg.x509.program").unwrap_or("gpgsm"),
+        _ => config
+            .get("gpg.openpgp.program")
+            .or_else(|| config.get("gpg.program"))
+            .unwrap_or("gpg"),
+    }
+}
+
+/// Sign a payload with `gpg` (or `gpgsm`).
+///
+/// # Arguments
+///
+/// * `config` - The configuration to read the program from
+/// * `format` - The format of the signature (OpenPGP or X.509)
+/// * `payload` - The data to sign
+/// * `key` - The key to sign with, or `None` for the committer identity
+///
+/// # Returns
+///
+/// The armored signature
+pub(super) fn sign(
+    config: &Config,
+    format: SignatureFormat,
+    payload: &[u8],
+    key: Option<&str>,
+) -> anyhow::Result<Vec<u8>> {
+    let program = program(config, format);
+    let key = match key {
+        Some(key) => key.to_string(),
+        None => {
+            let ident = Ident::current(config, Role::Committer)?;
+            format!(
+                "{} <{}>",
+                String::from_utf8_lossy(&ident.name),
+                String::from_utf8_lossy(&ident.email)
+            )
+        },
+    };
+
+    let output = run_program(config, program, &["--status-fd=2", "-bsau", &key], payload)?;
+    let created = output
+        .stderr
+        .lines()
+        .any(|line| line.starts_with(&format!("{STATUS_PREFIX}SIG_CREATED ")));
+    if !output.success || !created {
+        anyhow::bail!("{}\n{program} failed to sign the data", output.stderr);
+    }
+
+    let mut signature = output.stdout.replace('\r', "").into_bytes();
+    signature.push(b'\n');
+    Ok(signature)
+}
+
+/// Verify an OpenPGP or X.509 signature over a payload.
+pub(super) fn verify(
+    config: &Config,
+    format: SignatureFormat,
+    payload: &[u8],
+    armored: &[u8],
+) -> anyhow::Result<SignatureCheck> {
+    let program = program(config, format);
+    let signature_file = TempFile::new(armored)?;
+    let signature_path = signature_file.path().to_string_lossy();
+
+    let output = run_program(
+        config,
+        program,
+        &[
+            "--keyid-format=long",
+            "--status-fd=1",
+            "--verify",
+            &signature_path,
+            "-",
+        ],
+        payload,
+    )
+    .context(format!("verify signature with {program}"))?;
+
+    let mut status = SignatureStatus::Bad;
+    let mut signer = None;
+    for line in output.stdout.lines() {
+        let Some(line) = line.strip_prefix(STATUS_PREFIX) else {
+            continue;
+        };
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match keyword {
+            "GOODSIG" => {
+                status = SignatureStatus::Good;
+                // The key ID is followed by the user ID of the signer
+                signer = rest.split_once(' ').map(|(_, uid)| uid.to_string());
+            },
+            // Valid signatures made with an expired or revoked key
+            "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG" => {
+                status = SignatureStatus::Untrusted;
+                signer = rest.split_once(' ').map(|(_, uid)| uid.to_string());
+            },
+            "BADSIG" | "ERRSIG" => {
+                status = SignatureStatus::Bad;
+                signer = None;
+                break;
+            },
+            _ => {},
+        }
+    }
+
+    // A good signature does not count if the program failed anyway
+    if !output.success && status == SignatureStatus::Good {
+        status = SignatureStatus::Bad;
+    }
+
+    Ok(SignatureCheck {
+        status,
+        signer,
+        output: output.stderr,
+    })
+}
@@ -0,0 +1,339 @@
+//! Creating, extracting and verifying signatures of commits and tags
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anyhow::Context;
+
+use crate::utils::config::Config;
+
+mod cache;
+mod gpg;
+mod ssh;
+
+pub(crate) use cache::SignatureCache;
+
+/// The commit header containing the signature of a SHA-1 commit
+const SIGNATURE_HEADER: &[u8] = b"gpgsig";
+/// The commit header containing the signature of a SHA-256 commit
+const SIGNATURE_HEADER_SHA256: &[u8] = b"gpgsig-sha256";
+
+/// The first line of each supported signature format
+const SIGNATURE_STARTS: [(&[u8], SignatureFormat); 4] = [
+    (b"-----BEGIN PGP SIGNATURE-----", SignatureFormat::OpenPgp),
+    (b"-----BEGIN PGP MESSAGE-----", SignatureFormat::OpenPgp),
+    (b"-----BEGIN SIGNED MESSAGE-----", SignatureFormat::X509),
+    (b"-----BEGIN SSH SIGNATURE-----", SignatureFormat::Ssh),
+];
+
+/// The format of a signature, detected from its armor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignatureFormat {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+impl SignatureFormat {
+    /// Detect the format of an armored signature.
+    pub(crate) fn detect(signature: &[u8]) -> Option<Self> {
+        SIGNATURE_STARTS
+            .iter()
+            .find(|(start, _)| signature.starts_with(start))
+            .map(|&(_, format)| format)
+    }
+}
+
+/// The outcome of a signature verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignatureStatus {
+    /// The signature is valid and made by a trusted signer
+    Good,
+    /// The signature is valid, but the signer is not trusted
+    Untrusted,
+    /// The signature is invalid
+    Bad,
+}
+
+/// The result of verifying a signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SignatureCheck {
+    /// The outcome of the verification
+    pub(crate) status: SignatureStatus,
+    /// The identity of the signer, if it is known
+    pub(crate) signer: Option<String>,
+    /// The human-readable output of the verification
+    pub(crate) output: String,
+}
+
+/// Split a commit into the signed payload and its signature.
+///
+/// # Returns
+///
+/// The commit without any signature headers and the signature,
+/// or `None` if the commit is not signed
+pub(crate) fn split_commit_signature(content: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut payload = Vec::with_capacity(content.len());
+    let mut signature = Vec::new();
+    // Whether continuation lines belong to the signature (`Some(true)`),
+    // to a signature for the other hash algorithm (`Some(false)`) or to a regular header
+    let mut in_signature = None;
+    let mut in_headers = true;
+
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        if !in_headers {
+            payload.extend(line);
+            continue;
+        }
+
+        if line == b"\n" {
+            in_headers = false;
+            payload.extend(line);
+            continue;
+        }
+
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            match in_signature {
+                Some(true) => signature.extend(continuation),
+                Some(false) => {},
+                None => payload.extend(line),
+            }
+            continue;
+        }
+
+        if let Some(value) = strip_header(line, SIGNATURE_HEADER) {
+            in_signature = Some(true);
+            signature.extend(value);
+        } else if strip_header(line, SIGNATURE_HEADER_SHA256).is_some() {
+            // Signatures for the other hash algorithm are not part of the payload
+            in_signature = Some(false);
+        } else {
+            in_signature = None;
+            payload.extend(line);
+        }
+    }
+
+    if signature.is_empty() {
+        None
+    } else {
+        Some((payload, signature))
+    }
+}
+
+/// Split a tag into the signed payload and its signature,
+/// which is appended to the tag message.
+///
+/// # Returns
+///
+/// The tag up to the signature and the signature,
+/// or `None` if the tag is not signed
+pub(crate) fn split_tag_signature(content: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut offset = 0;
+
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        if SignatureFormat::detect(line).is_some() {
+            return Some((content[..offset].to_vec(), content[offset..].to_vec()));
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+/// Verify a signature over a payload.
+///
+/// # Arguments
+///
+/// * `config` - The configuration to read the verification settings from
+/// * `payload` - The signed data
+/// * `signature` - The armored signature
+/// * `timestamp` - The time the payload claims to be signed at (seconds since the Unix epoch)
+pub(crate) fn verify_signature(
+    config: &Config,
+    payload: &[u8],
+    signature: &[u8],
+    timestamp: Option<i64>,
+) -> anyhow::Result<SignatureCheck> {
+    match SignatureFormat::detect(signature) {
+        Some(SignatureFormat::Ssh) => ssh::verify(config, payload, signature, timestamp),
+        Some(format @ (SignatureFormat::OpenPgp | SignatureFormat::X509)) => {
+            gpg::verify(config, format, payload, signature)
+        },
+        None => anyhow::bail!("unknown signature format"),
+    }
+}
+
+/// Sign a payload in the format configured with `gpg.format`
+/// (`openpgp` by default, `x509` or `ssh`).
+///
+/// # Arguments
+///
+/// * `config` - The configuration to read the signing settings from
+/// * `payload` - The data to sign
+/// * `key` - The key to sign with, overriding `user.signingKey`
+///
+/// # Returns
+///
+/// The armored signature, ending with a newline
+pub(crate) fn sign_payload(
+    config: &Config,
+    payload: &[u8],
+    key: Option<&str>,
+) -> anyhow::Result<Vec<u8>> {
+    let key = key.or_else(|| config.get("user.signingKey"));
+    match config.get("gpg.format").unwrap_or("openpgp") {
+        "openpgp" => gpg::sign(config, SignatureFormat::OpenPgp, payload, key),
+        "x509" => gpg::sign(config, SignatureFormat::X509, payload, key),
+        "ssh" => ssh::sign(config, payload, key),
+        format => anyhow::bail!("invalid value for 'gpg.format': '{format}'"),
+    }
+}
+
+/// Strip a header name (followed by a space) from a header line.
+fn strip_header<'a>(line: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    line.strip_prefix(name)?.strip_prefix(b" ")
+}
+
+/// The number of temporary files created by the process
+static TEMP_FILES: AtomicU32 = AtomicU32::new(0);
+
+/// A temporary file that is removed on drop
+struct TempFile(PathBuf);
+
+impl TempFile {
+    /// Write content to a new, uniquely named file in the temporary directory.
+    fn new(content: &[u8]) -> anyhow::Result<Self> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or_default();
+        // Threads of the same process may create files in the same nanosecond
+        let count = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            ".git_signature_{}_{nanos}_{count}",
+            std::process::id()
+        ));
+
+        let mut file = std::fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .context(format!("create {}", path.display()))?;
+        file.write_all(content)
+            .context(format!("write {}", path.display()))?;
+
+        Ok(TempFile(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// The result of running a verification program
+struct ProgramOutput {
+    success: bool,
+    stdout: String,
+    stderr: String,
+}
+
+/// Run a program with the payload on its standard input, in the environment
+/// the configuration was loaded with.
+fn run_program(
+    config: &Config,
+    program: &str,
+    args: &[&str],
+    payload: &[u8],
+) -> anyhow::Result<ProgramOutput> {
+    let mut child = Command::new(program)
+        .env_clear()
+        .envs(config.env().vars())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(format!("run {program}"))?;
+
+    // The program may exit before reading all of its input
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(payload);
+    }
+
+    let output = child
+        .wait_with_output()
+        .context(format!("wait for {program}"))?;
+
+    Ok(ProgramOutput {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_commit_signature, split_tag_signature, SignatureFormat};
+
+    const COMMIT: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+        author A <a@example.com> 0 +0000\n\
+        committer A <a@example.com> 0 +0000\n\
+        gpgsig -----BEGIN SSH SIGNATURE-----\n \
+        U1NIU0lH\n \
+        -----END SSH SIGNATURE-----\n\
+        \n\
+        message\n";
+
+    #[test]
+    fn splits_commit_signature() {
+        let (payload, signature) = split_commit_signature(COMMIT).unwrap();
+        assert_eq!(
+            payload,
+            b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+              author A <a@example.com> 0 +0000\n\
+              committer A <a@example.com> 0 +0000\n\
+              \n\
+              message\n"
+        );
+        assert_eq!(
+            signature,
+            b"-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----END SSH SIGNATURE-----\n"
+        );
+        assert_eq!(
+            SignatureFormat::detect(&signature),
+            Some(SignatureFormat::Ssh)
+        );
+    }
+
+    #[test]
+    fn ignores_unsigned_commit() {
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\ngpgsig not a header\n";
+        assert!(split_commit_signature(commit).is_none());
+    }
+
+    #[test]
+    fn splits_tag_signature() {
+        let tag = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype tree\ntag v1\n\n\
+            Release\n-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----\n";
+
+        let (payload, signature) = split_tag_signature(tag).unwrap();
+        assert!(payload.ends_with(b"\n\nRelease\n"));
+        assert_eq!(
+            SignatureFormat::detect(&signature),
+            Some(SignatureFormat::OpenPgp)
+        );
+        assert!(split_tag_signature(&payload).is_none());
+    }
+}
@@ -0,0 +1,569 @@
+//! Creating SSH signatures (`SSHSIG`) and verifying them against an allowed signers file
+//!
+//! Signatures are made with `ssh-keygen -Y sign` in the `git` namespace.
+//! The signers trusted for a signature are looked up in the file configured with
+//! `gpg.ssh.allowedSignersFile` (see `ssh-keygen(1)`, "ALLOWED SIGNERS"), and the
+//! signature is then verified cryptographically with `ssh-keygen -Y verify`.
+
+use anyhow::Context;
+
+use crate::utils::base64;
+use crate::utils::config::{expand_path, Config};
+use crate::utils::date::DateTime;
+use crate::utils::signature::{run_program, SignatureCheck, SignatureStatus, TempFile};
+
+/// The magic preamble of an SSH signature blob
+const MAGIC: &[u8] = b"SSHSIG";
+/// The namespace git signatures are made in
+const NAMESPACE: &str = "git";
+/// The first and last lines of an armored SSH signature
+const ARMOR_BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const ARMOR_END: &str = "-----END SSH SIGNATURE-----";
+
+/// A parsed SSH signature blob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SshSignature {
+    /// The public key blob of the signer
+    pub(crate) public_key: Vec<u8>,
+    /// The namespace the signature was made in (`git` for commits and tags)
+    pub(crate) namespace: String,
+    /// The hash algorithm used for the signed data (e.g. `sha512`)
+    pub(crate) hash_algorithm: String,
+}
+
+impl SshSignature {
+    /// Parse an armored SSH signature.
+    pub(crate) fn parse(armored: &[u8]) -> anyhow::Result<Self> {
+        let armored = std::str::from_utf8(armored).context("ssh signature is not utf-8")?;
+        let body = armored
+            .trim()
+            .strip_prefix(ARMOR_BEGIN)
+            .and_then(|body| body.strip_suffix(ARMOR_END))
+            .context("invalid ssh signature armor")?;
+        let blob = base64::decode(body.as_bytes()).context("invalid ssh signature encoding")?;
+
+        let mut reader = BlobReader(&blob);
+        if reader.read_bytes(MAGIC.len())? != MAGIC {
+            anyhow::bail!("invalid ssh signature preamble");
+        }
+
+        let version = reader.read_u32()?;
+        if version != 1 {
+            anyhow::bail!("unsupported ssh signature version {}", version);
+        }
+
+        let public_key = reader.read_string()?.to_vec();
+        let namespace = String::from_utf8(reader.read_string()?.to_vec())?;
+        let _reserved = reader.read_string()?;
+        let hash_algorithm = String::from_utf8(reader.read_string()?.to_vec())?;
+        let _signature = reader.read_string()?;
+
+        Ok(SshSignature {
+            public_key,
+            namespace,
+            hash_algorithm,
+        })
+    }
+}
+
+/// An entry of an allowed signers file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AllowedSigner {
+    /// The comma-separated principal patterns
+    pub(crate) principals: String,
+    /// Whether the key is a certificate authority rather than a signing key
+    pub(crate) cert_authority: bool,
+    /// The comma-separated namespace patterns the key may sign in, if restricted
+    pub(crate) namespaces: Option<String>,
+    /// The type of the key (e.g. `ssh-ed25519`)
+    pub(crate) key_type: String,
+    /// The public key blob
+    pub(crate) key: Vec<u8>,
+}
+
+impl AllowedSigner {
+    /// Whether the entry allows a key to sign in a namespace.
+    pub(crate) fn allows(&self, key: &[u8], namespace: &str) -> bool {
+        !self.cert_authority
+            && self.key == key
+            && self
+                .namespaces
+                .as_deref()
+                .is_none_or(|namespaces| match_pattern_list(namespace, namespaces))
+    }
+}
+
+/// Parse the content of an allowed signers file.
+///
+/// Each line has the format `principals [options] keytype base64-key [comment]`.
+/// Empty lines and lines starting with `#` are ignored.
+pub(crate) fn parse_allowed_signers(content: &str) -> anyhow::Result<Vec<AllowedSigner>> {
+    let mut signers = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let signer = parse_allowed_signer(line)
+            .with_context(|| format!("invalid allowed signer on line {}", number + 1))?;
+        signers.push(signer);
+    }
+
+    Ok(signers)
+}
+
+/// Verify an SSH signature over a payload.
+pub(super) fn verify(
+    config: &Config,
+    payload: &[u8],
+    armored: &[u8],
+    timestamp: Option<i64>,
+) -> anyhow::Result<SignatureCheck> {
+    let allowed_signers = config
+        .get_path("gpg.ssh.allowedSignersFile")
+        .filter(|path| path.is_file())
+        .context(
+            "gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature \
+             verification",
+        )?;
+    let program = config.get("gpg.ssh.program").unwrap_or("ssh-keygen");
+
+    let signature = SshSignature::parse(armored)?;
+    let content = std::fs::read_to_string(&allowed_signers)
+        .context(format!("read {}", allowed_signers.display()))?;
+    let principals: Vec<_> = parse_allowed_signers(&content)?
+        .into_iter()
+        .filter(|signer| signer.allows(&signature.public_key, &signature.namespace))
+        .flat_map(|signer| {
+            signer
+                .principals
+                .split(',')
+                .filter(|principal| !principal.starts_with('!'))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let signature_file = TempFile::new(armored)?;
+    let signature_path = signature_file.path().to_string_lossy();
+    let allowed_signers = allowed_signers.to_string_lossy();
+    let verify_time =
+        timestamp.map(|timestamp| format!("-Overify-time={}", format_time(timestamp)));
+
+    let mut common_args = vec!["-n", NAMESPACE, "-s", &signature_path];
+    common_args.extend(verify_time.as_deref());
+
+    // Without a trusted principal, the signature can only be checked for validity
+    if principals.is_empty() {
+        let mut args = vec!["-Y", "check-novalidate"];
+        args.extend(&common_args);
+        let output = run_program(config, program, &args, payload)?;
+
+        return Ok(if output.success {
+            SignatureCheck {
+                status: SignatureStatus::Untrusted,
+                signer: None,
+                output: format!("{}\nNo principal matched.", output.stdout),
+            }
+        } else {
+            bad_signature(output.stdout, output.stderr)
+        });
+    }
+
+    let revocation_file = config
+        .get_path("gpg.ssh.revocationFile")
+        .map(|path| path.to_string_lossy().into_owned());
+
+    let mut last_output = None;
+    for principal in &principals {
+        let mut args = vec!["-Y", "verify", "-f", &allowed_signers, "-I", principal];
+        if let Some(revocation_file) = &revocation_file {
+            args.extend(["-r", revocation_file.as_str()]);
+        }
+        args.extend(&common_args);
+
+        let output = run_program(config, program, &args, payload)?;
+        if output.success {
+            return Ok(SignatureCheck {
+                status: SignatureStatus::Good,
+                signer: Some(principal.clone()),
+                output: output.stdout,
+            });
+        }
+        last_output = Some(output);
+    }
+
+    let output = last_output.context("no principal to verify")?;
+    Ok(bad_signature(output.stdout, output.stderr))
+}
+
+/// Sign a payload in the `git` namespace with `ssh-keygen -Y sign`.
+///
+/// # Arguments
+///
+/// * `config` - The configuration to read the program and default key from
+/// * `payload` - The data to sign
+/// * `key` - The path to a key, or a public key prefixed with `key::` whose private
+///   key is held by `ssh-agent`. Without a key, the first key printed by
+///   `gpg.ssh.defaultKeyCommand` is used.
+///
+/// # Returns
+///
+/// The armored signature
+pub(super) fn sign(config: &Config, payload: &[u8], key: Option<&str>) -> anyhow::Result<Vec<u8>> {
+    let program = config.get("gpg.ssh.program").unwrap_or("ssh-keygen");
+    let key = match key {
+        Some(key) => key.to_string(),
+        None => default_key(config)?,
+    };
+
+    // Literal public keys are written to a file, and signed with the agent
+    let literal_key = key
+        .strip_prefix("key::")
+        .or_else(|| key.starts_with("ssh-").then_some(key.as_str()));
+    let key_file = literal_key
+        .map(|key| TempFile::new(format!("{key}\n").as_bytes()))
+        .transpose()?;
+    let key_path = match &key_file {
+        Some(file) => file.path().to_string_lossy().into_owned(),
+        None => expand_path(config.env(), &key)
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    let mut args = vec!["-Y", "sign", "-n", NAMESPACE, "-f", &key_path];
+    if key_file.is_some() {
+        args.push("-U");
+    }
+
+    let output = run_program(config, program, &args, payload)?;
+    if !output.success || !output.stdout.starts_with(ARMOR_BEGIN) {
+        if output.stderr.contains("usage:") {
+            anyhow::bail!(
+                "ssh-keygen -Y sign is needed for ssh signing (available in openssh version \
+                 8.2p1+)"
+            );
+        }
+        anyhow::bail!("{}\n{program} failed to sign the data", output.stderr);
+    }
+
+    let mut signature = output.stdout.replace('\r', "").into_bytes();
+    signature.push(b'\n');
+    Ok(signature)
+}
+
+/// Get the signing key from the output of `gpg.ssh.defaultKeyCommand`:
+/// the first line that is a public key.
+fn default_key(config: &Config) -> anyhow::Result<String> {
+    let command = config
+        .get("gpg.ssh.defaultKeyCommand")
+        .context("either user.signingkey or gpg.ssh.defaultKeyCommand needs to be configured")?;
+
+    let output = run_program(config, "sh", &["-c", command], b"")?;
+    if !output.success {
+        anyhow::bail!("{}\ngpg.ssh.defaultKeyCommand failed", output.stderr);
+    }
+
+    output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("key::") || line.starts_with("ssh-"))
+        .map(|line| format!("key::{}", line.strip_prefix("key::").unwrap_or(line)))
+        .context(format!(
+            "gpg.ssh.defaultKeyCommand succeeded but returned no keys: {}",
+            output.stdout
+        ))
+}
+
+/// Collect the settings that affect the outcome of a verification:
+/// the program and the content of the allowed signers and revocation files.
+pub(super) fn settings(config: &Config) -> Vec<u8> {
+    let mut settings = config
+        .get("gpg.ssh.program")
+        .unwrap_or("ssh-keygen")
+        .as_bytes()
+        .to_vec();
+
+    for key in ["gpg.ssh.allowedSignersFile", "gpg.ssh.revocationFile"] {
+        settings.push(0);
+        if let Some(path) = config.get_path(key) {
+            settings.extend(path.to_string_lossy().as_bytes());
+            settings.push(0);
+            settings.extend(std::fs::read(path).unwrap_or_default());
+        }
+    }
+
+    settings
+}
+
+/// Build the result of a failed verification from the program output.
+fn bad_signature(stdout: String, stderr: String) -> SignatureCheck {
+    let output = [stdout, stderr]
+        .into_iter()
+        .filter(|output| !output.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    SignatureCheck {
+        status: SignatureStatus::Bad,
+        signer: None,
+        output,
+    }
+}
+
+/// Parse a single (non-empty, non-comment) line of an allowed signers file.
+fn parse_allowed_signer(line: &str) -> anyhow::Result<AllowedSigner> {
+    let (principals, rest) = next_token(line).context("missing principals")?;
+    let (token, mut rest) = next_token(rest).context("missing key")?;
+
+    let mut cert_authority = false;
+    let mut namespaces = None;
+
+    let key_type = if is_key_type(&token) {
+        token
+    } else {
+        for option in split_options(&token) {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim_matches('"').to_string())),
+                None => (option.as_str(), None),
+            };
+
+            match name.to_ascii_lowercase().as_str() {
+                "cert-authority" => cert_authority = true,
+                "namespaces" => namespaces = value,
+                // Validity periods are enforced by `ssh-keygen -Y verify`
+                "valid-after" | "valid-before" => {},
+                _ => anyhow::bail!("unknown option: {}", name),
+            }
+        }
+
+        let (key_type, after) = next_token(rest).context("missing key type")?;
+        rest = after;
+        key_type
+    };
+
+    let (key, _comment) = next_token(rest).context("missing key")?;
+    let key = base64::decode(key.as_bytes()).context("invalid key encoding")?;
+
+    // The key blob starts with its type
+    if BlobReader(&key).read_string()? != key_type.as_bytes() {
+        anyhow::bail!("key type does not match key");
+    }
+
+    Ok(AllowedSigner {
+        principals,
+        cert_authority,
+        namespaces,
+        key_type,
+        key,
+    })
+}
+
+/// Split the next whitespace-separated token from a line.
+/// Whitespace inside double quotes does not end a token, and the quotes
+/// around a token that is quoted entirely are removed.
+fn next_token(line: &str) -> Option<(String, &str)> {
+    let line = line.trim_start();
+    if line.is_empty() {
+        return None;
+    }
+
+    let mut in_quotes = false;
+    let end = line
+        .char_indices()
+        .find(|&(_, c)| {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            }
+            c.is_whitespace() && !in_quotes
+        })
+        .map_or(line.len(), |(index, _)| index);
+
+    let token = &line[..end];
+    let token = match token.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(unquoted) if !unquoted.contains('"') => unquoted,
+        _ => token,
+    };
+
+    Some((token.to_string(), &line[end..]))
+}
+
+/// Split a comma-separated option list, keeping commas inside quotes.
+fn split_options(options: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in options.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            },
+            ',' if !in_quotes => split.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    split.push(current);
+
+    split
+}
+
+/// Whether a token names an SSH key type.
+fn is_key_type(token: &str) -> bool {
+    ["ssh-", "ecdsa-", "sk-", "rsa-"]
+        .iter()
+        .any(|prefix| token.starts_with(prefix))
+}
+
+/// Match a name against a comma-separated list of patterns.
+/// Patterns may contain `*` and `?` wildcards and be negated with `!`;
+/// a matching negated pattern rejects the name.
+fn match_pattern_list(name: &str, patterns: &str) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns.split(',') {
+        match pattern.strip_prefix('!') {
+            Some(negated) if match_pattern(name.as_bytes(), negated.as_bytes()) => return false,
+            Some(_) => {},
+            None => matched |= match_pattern(name.as_bytes(), pattern.as_bytes()),
+        }
+    }
+
+    matched
+}
+
+/// Match a name against a single wildcard pattern.
+fn match_pattern(name: &[u8], pattern: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_pattern(&name[skip..], rest)),
+        Some((b'?', rest)) => !name.is_empty() && match_pattern(&name[1..], rest),
+        Some((&c, rest)) => name.first() == Some(&c) && match_pattern(&name[1..], rest),
+    }
+}
+
+/// Format a Unix timestamp as `YYYYMMDDHHMMSSZ` (UTC), as expected by `ssh-keygen`.
+fn format_time(timestamp: i64) -> String {
+    let date = DateTime::from_timestamp(timestamp, 0);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+/// A reader for the SSH wire encoding (RFC 4251)
+struct BlobReader<'a>(&'a [u8]);
+
+impl<'a> BlobReader<'a> {
+    fn read_bytes(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < length {
+            anyhow::bail!("truncated ssh blob");
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_string(&mut self) -> anyhow::Result<&'a [u8]> {
+        let length = self.read_u32()? as usize;
+        self.read_bytes(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_time, match_pattern_list, parse_allowed_signers, SshSignature};
+    use crate::utils::base64;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIBxbMUvXRAtPCDc+K7tjS63PVE3YeBVBJag1CtAb0pQy";
+
+    const SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgHFsxS9dEC08INz4ru2NLrc9UTd
+h4FUElqDUK0BvSlDIAAAADZ2l0AAAAAAAAAAZzaGE1MTIAAABTAAAAC3NzaC1lZDI1NTE5
+AAAAQEu48sxuLscydTsFI6/DyiEvintpmgI1UjOaP7hM+P5bHtm16Kx0G5zXsMTFjCAUqe
+45NxC6t2suhelCFNBZBwg=
+-----END SSH SIGNATURE-----
+";
+
+    #[test]
+    fn parses_ssh_signature() {
+        let signature = SshSignature::parse(SIGNATURE.as_bytes()).unwrap();
+        assert_eq!(
+            signature.public_key,
+            base64::decode(KEY.as_bytes()).unwrap()
+        );
+        assert_eq!(signature.namespace, "git");
+        assert_eq!(signature.hash_algorithm, "sha512");
+
+        assert!(SshSignature::parse(b"-----BEGIN SSH SIGNATURE-----\nAAAA\n").is_err());
+        let truncated = SIGNATURE.replace("45NxC6t2suhelCFNBZBwg=\n", "");
+        assert!(SshSignature::parse(truncated.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parses_allowed_signers() {
+        let content = format!(
+            "# comment\n\
+             \n\
+             alice@example.com ssh-ed25519 {KEY} alice\n\
+             \"bob@example.com,*@corp.example.com\" namespaces=\"git,file\" ssh-ed25519 {KEY}\n\
+             *@example.com cert-authority,valid-after=20240101 ssh-ed25519 {KEY}\n"
+        );
+
+        let signers = parse_allowed_signers(&content).unwrap();
+        assert_eq!(signers.len(), 3);
+
+        assert_eq!(signers[0].principals, "alice@example.com");
+        assert_eq!(signers[0].key_type, "ssh-ed25519");
+        assert!(signers[0].allows(&signers[0].key, "git"));
+
+        assert_eq!(signers[1].namespaces.as_deref(), Some("git,file"));
+        assert!(match_pattern_list(
+            "eve@corp.example.com",
+            &signers[1].principals
+        ));
+        assert!(!match_pattern_list(
+            "eve@example.com",
+            &signers[1].principals
+        ));
+        assert!(!signers[1].allows(&signers[1].key, "email"));
+
+        assert!(signers[2].cert_authority);
+        assert!(!signers[2].allows(&signers[2].key, "git"));
+    }
+
+    #[test]
+    fn fails_on_invalid_allowed_signers() {
+        assert!(parse_allowed_signers("alice@example.com\n").is_err());
+        assert!(parse_allowed_signers("alice@example.com ssh-ed25519 !!!\n").is_err());
+        assert!(parse_allowed_signers(&format!("a unknown-option ssh-ed25519 {KEY}\n")).is_err());
+        assert!(parse_allowed_signers(&format!("a ssh-rsa {KEY}\n")).is_err());
+    }
+
+    #[test]
+    fn matches_pattern_lists() {
+        assert!(match_pattern_list("alice@example.com", "*@example.com"));
+        assert!(match_pattern_list("git", "file,gi?"));
+        assert!(!match_pattern_list(
+            "bob@example.com",
+            "*@example.com,!bob@*"
+        ));
+        assert!(!match_pattern_list("alice@example.org", "*@example.com"));
+    }
+
+    #[test]
+    fn formats_verify_time() {
+        assert_eq!(format_time(0), "19700101000000Z");
+        assert_eq!(format_time(1700000000), "20231114221320Z");
+        assert_eq!(format_time(951782400), "20000229000000Z");
+    }
+}
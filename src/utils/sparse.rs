@@ -0,0 +1,331 @@
+//! Sparse checkouts
+//!
+//! When `core.sparseCheckout` is set, only the files of the index selected by the
+//! patterns of `$GIT_DIR/info/sparse-checkout` are in the working tree. The other
+//! entries have the skip-worktree bit set, and are treated as unchanged.
+//!
+//! In cone mode (`core.sparseCheckoutCone`), the patterns select directories: the
+//! files at the root, every file of the directories listed, and the files directly
+//! in the directories leading to them. Otherwise they are patterns in the syntax of
+//! `.gitignore` files, the last one matching a file (or else one of its directories)
+//! deciding whether it is included.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::utils::advice::warning;
+use crate::utils::checkout::{checkout_entry, remove_empty_parents, remove_path};
+use crate::utils::config::{set_config_value, Config};
+use crate::utils::convert::Conversion;
+use crate::utils::ignore::{read_patterns, IgnorePattern};
+use crate::utils::index::{Index, IndexEntry, Stat};
+use crate::utils::path_from_bytes;
+use crate::utils::repository::Repository;
+use crate::utils::tree::{TreeEntry, MODE_GITLINK};
+
+/// The patterns of a sparse checkout
+#[derive(Debug, Clone)]
+pub(crate) enum Sparse {
+    /// The directories whose files are all included, in cone mode
+    Cone(BTreeSet<String>),
+    /// The patterns selecting the files to include
+    Patterns(Vec<IgnorePattern>),
+}
+
+impl Sparse {
+    /// Load the patterns of the sparse checkout of the working tree.
+    ///
+    /// # Returns
+    ///
+    /// The patterns, or `None` if sparse checkout is not enabled
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Option<Self>> {
+        let config = Config::load(repo)?;
+        if !config.get_bool("core.sparseCheckout")?.unwrap_or(false) {
+            return Ok(None);
+        }
+        let cone = config.get_bool("core.sparseCheckoutCone")?.unwrap_or(false);
+        Sparse::read(repo, cone).map(Some)
+    }
+
+    /// Read the patterns of `info/sparse-checkout`, falling back to non-cone
+    /// patterns if they cannot be read as directories in cone mode.
+    pub(crate) fn read(repo: &Repository, cone: bool) -> anyhow::Result<Self> {
+        let path = sparse_checkout_path(repo)?;
+        if cone {
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(err).context(format!("read {}", path.display())),
+            };
+            if let Some(directories) = parse_cone_patterns(&content) {
+                return Ok(Sparse::Cone(directories));
+            }
+            warning("disabling cone pattern matching");
+        }
+        Ok(Sparse::Patterns(read_patterns(
+            &path,
+            "info/sparse-checkout",
+            "",
+        )?))
+    }
+
+    /// Whether a file of the index is in the working tree.
+    pub(crate) fn includes(&self, path: &[u8]) -> bool {
+        let path = String::from_utf8_lossy(path);
+        match self {
+            Sparse::Cone(directories) => {
+                let Some((directory, _)) = path.rsplit_once('/') else {
+                    return true;
+                };
+
+                // The files of a listed directory, or of a directory inside one
+                let listed = directory
+                    .match_indices('/')
+                    .map(|(slash, _)| &directory[..slash])
+                    .chain([directory])
+                    .any(|parent| directories.contains(parent));
+                // The files directly in a directory leading to a listed one
+                let prefix = format!("{directory}/");
+                listed
+                    || directories
+                        .range(prefix.clone()..)
+                        .next()
+                        .is_some_and(|next| next.starts_with(&prefix))
+            },
+            Sparse::Patterns(patterns) => {
+                let decide = |path: &str, is_dir: bool| {
+                    patterns
+                        .iter()
+                        .rev()
+                        .find(|pattern| pattern.matches(path, is_dir, false))
+                        .map(|pattern| !pattern.negated)
+                };
+
+                // A file not matched itself is decided by its closest directory matched
+                let directories = path.match_indices('/').map(|(slash, _)| &path[..slash]);
+                decide(&path, false)
+                    .or_else(|| directories.rev().find_map(|dir| decide(dir, true)))
+                    .unwrap_or(false)
+            },
+        }
+    }
+}
+
+/// The path of the patterns of the sparse checkout of the working tree.
+pub(crate) fn sparse_checkout_path(repo: &Repository) -> anyhow::Result<PathBuf> {
+    Ok(repo.git_dir()?.join("info/sparse-checkout"))
+}
+
+/// Parse the patterns of a sparse checkout in cone mode: `/*` and `!/*/` for the
+/// files at the root, `/<dir>/` for each directory, and `!/<dir>/*/` for the
+/// directories leading to the listed ones, whose subdirectories are not included.
+///
+/// # Returns
+///
+/// The directories whose files are all included, or `None` if a pattern is not
+/// one of the cone patterns
+fn parse_cone_patterns(content: &str) -> Option<BTreeSet<String>> {
+    let mut directories = BTreeSet::new();
+    let mut parents = BTreeSet::new();
+
+    for line in content.lines().map(str::trim_end) {
+        if line.is_empty() || line.starts_with('#') || line == "/*" || line == "!/*/" {
+            continue;
+        }
+        if let Some(parent) = line.strip_prefix("!/").and_then(|l| l.strip_suffix("/*/")) {
+            parents.insert(unescape(parent));
+        } else if let Some(directory) = line
+            .strip_prefix('/')
+            .and_then(|l| l.strip_suffix('/'))
+            .filter(|directory| !directory.is_empty() && !has_unescaped_wildcard(directory))
+        {
+            directories.insert(unescape(directory));
+        } else {
+            warning(&format!("unrecognized pattern: '{line}'"));
+            return None;
+        }
+    }
+
+    Some(directories.difference(&parents).cloned().collect())
+}
+
+/// Escape the wildcard characters of a directory, as written in the patterns
+/// of cone mode.
+pub(crate) fn escape_cone_directory(directory: &str) -> String {
+    let mut escaped = String::with_capacity(directory.len());
+    for c in directory.chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(pattern: &str) -> String {
+    let mut unescaped = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn has_unescaped_wildcard(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            },
+            '*' | '?' | '[' => return true,
+            _ => {},
+        }
+    }
+    false
+}
+
+/// Enable (or disable) the sparse checkout of the working tree, in its own
+/// configuration file (`config.worktree`, enabling `extensions.worktreeConfig`).
+pub(crate) fn set_sparse_config(
+    repo: &Repository,
+    enabled: bool,
+    cone: bool,
+) -> anyhow::Result<()> {
+    set_config_value(
+        &repo.common_dir()?.join("config"),
+        "extensions.worktreeConfig",
+        "true",
+    )?;
+    let path = repo.git_dir()?.join("config.worktree");
+    set_config_value(&path, "core.sparseCheckout", &enabled.to_string())?;
+    set_config_value(&path, "core.sparseCheckoutCone", &cone.to_string())
+}
+
+/// Make the skip-worktree bits of the index match a sparse checkout: the files of
+/// the entries no longer included are removed from the working tree, and those
+/// of the entries included again are checked out. Files with local changes are
+/// left in place, as are the files already where an entry is checked out.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to read the objects from
+/// * `index` - The index to update
+/// * `work_tree` - The root of the working tree
+/// * `sparse` - The patterns of the sparse checkout, or `None` to include every file
+///
+/// # Returns
+///
+/// The paths left in the working tree despite the patterns
+pub(crate) fn update_sparse_work_tree(
+    repo: &Repository,
+    index: &mut Index,
+    work_tree: &Path,
+    sparse: Option<&Sparse>,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut conversion = Conversion::load(repo)?;
+    let mut updated = Vec::new();
+    let mut left = Vec::new();
+
+    for entry in index.entries().iter().filter(|entry| entry.stage == 0) {
+        let included = sparse.is_none_or(|sparse| sparse.includes(&entry.path));
+        if included != entry.skip_worktree {
+            continue;
+        }
+        let full_path = work_tree.join(path_from_bytes(&entry.path));
+        let exists = full_path.symlink_metadata().is_ok();
+
+        if included {
+            let mut entry = IndexEntry {
+                skip_worktree: false,
+                ..entry.clone()
+            };
+            if !exists {
+                let tree_entry = TreeEntry {
+                    mode: entry.mode,
+                    name: entry.path.clone(),
+                    hash: entry.hash.clone(),
+                };
+                checkout_entry(repo, &tree_entry, work_tree, &mut conversion)?;
+                if entry.mode != MODE_GITLINK {
+                    entry.stat = Stat::from_metadata(&full_path.symlink_metadata()?);
+                }
+            }
+            updated.push(entry);
+            continue;
+        }
+
+        if exists {
+            // Checked out submodules are only removed if empty
+            let removed = match entry.mode {
+                MODE_GITLINK => std::fs::remove_dir(&full_path).is_ok(),
+                _ if index.is_modified(entry, work_tree)? => false,
+                _ => remove_path(&full_path).is_ok(),
+            };
+            if !removed {
+                left.push(entry.path.clone());
+                continue;
+            }
+            remove_empty_parents(&full_path, work_tree);
+        }
+        updated.push(IndexEntry {
+            skip_worktree: true,
+            ..entry.clone()
+        });
+    }
+
+    for entry in updated {
+        index.add_entry(entry);
+    }
+    Ok(left)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    fn cone(content: &str) -> Sparse {
+        Sparse::Cone(parse_cone_patterns(content).unwrap())
+    }
+
+    #[test]
+    fn includes_the_files_of_the_cone() {
+        let sparse = cone("/*\n!/*/\n/a/\n!/a/*/\n/a/b/\n/d\\*/\n");
+        let included = |path: &str| sparse.includes(path.as_bytes());
+
+        assert!(included("top"));
+        assert!(included("a/x"));
+        assert!(!included("a/c/x"));
+        assert!(included("a/b/y"));
+        assert!(included("a/b/c/z"));
+        assert!(included("d*/w"));
+        assert!(!included("e/v"));
+        assert!(!included("ab/x"));
+
+        assert!(parse_cone_patterns("/*\n!/*/\n*.txt\n").is_none());
+        assert!(parse_cone_patterns("/*\n!/*/\n/a*/\n").is_none());
+        assert_eq!(escape_cone_directory("d*/[x]"), "d\\*/\\[x\\]");
+    }
+
+    #[test]
+    fn includes_the_files_matching_patterns() {
+        let temp = TempRepo::new();
+        std::fs::write(temp.join("patterns"), "/*\n!/*/\n/a/b/\n*.txt\n!/a/b/c/\n").unwrap();
+        let patterns = read_patterns(&temp.join("patterns"), "patterns", "").unwrap();
+        let sparse = Sparse::Patterns(patterns);
+        let included = |path: &str| sparse.includes(path.as_bytes());
+
+        assert!(included("top"));
+        assert!(!included("a/x"));
+        assert!(included("a/b/y"));
+        assert!(!included("a/b/c/z"));
+        assert!(included("a/b/c/notes.txt"));
+        assert!(included("e/notes.txt"));
+    }
+}
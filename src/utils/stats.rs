@@ -0,0 +1,262 @@
+//! Counting and timing the work done while scanning a working tree or
+//! verifying objects, to diagnose why a command is slow in a particular repository
+
+use std::cell::Cell;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// The counters are kept per thread, so concurrent commands (and tests) don't mix them
+    static COUNTERS: Cell<Counters> = const { Cell::new(Counters::new()) };
+}
+
+/// The amount of work done so far
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Counters {
+    /// The entries of the working tree that were looked at
+    pub(crate) files_scanned: usize,
+    /// The ignored directories that were not descended into
+    pub(crate) directories_pruned: usize,
+    /// The index entries compared with their file in the working tree
+    pub(crate) entries_compared: usize,
+    /// The objects whose hash was computed
+    pub(crate) objects_hashed: usize,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            files_scanned: 0,
+            directories_pruned: 0,
+            entries_compared: 0,
+            objects_hashed: 0,
+        }
+    }
+
+    /// Get the counters of the current thread.
+    pub(crate) fn current() -> Self {
+        COUNTERS.get()
+    }
+
+    /// Update the counters of the current thread.
+    pub(crate) fn update<F>(update: F)
+    where
+        F: FnOnce(&mut Counters),
+    {
+        let mut counters = COUNTERS.get();
+        update(&mut counters);
+        COUNTERS.set(counters);
+    }
+
+    /// The work done since an earlier snapshot.
+    fn since(&self, start: &Counters) -> Counters {
+        Counters {
+            files_scanned: self.files_scanned - start.files_scanned,
+            directories_pruned: self.directories_pruned - start.directories_pruned,
+            entries_compared: self.entries_compared - start.entries_compared,
+            objects_hashed: self.objects_hashed - start.objects_hashed,
+        }
+    }
+}
+
+/// The statistics of a command: its counters and the time taken by each of its phases
+#[derive(Debug)]
+pub(crate) struct Stats {
+    start: Instant,
+    /// When the last phase ended
+    last: Instant,
+    counters: Counters,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Stats {
+    /// Start measuring, from the current state of the counters.
+    pub(crate) fn start() -> Self {
+        let now = Instant::now();
+        Stats {
+            start: now,
+            last: now,
+            counters: Counters::current(),
+            phases: Vec::new(),
+        }
+    }
+
+    /// End a phase of the command, which started when the previous one ended.
+    pub(crate) fn phase(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push((name, now - self.last));
+        self.last = now;
+    }
+
+    /// The work done since the measuring started.
+    pub(crate) fn counters(&self) -> Counters {
+        Counters::current().since(&self.counters)
+    }
+
+    /// Write the counters, then the time taken by each phase and in total.
+    pub(crate) fn write<W>(&self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let counters = self.counters();
+        let mut rows = vec![
+            ("files scanned", counters.files_scanned.to_string()),
+            (
+                "directories pruned",
+                counters.directories_pruned.to_string(),
+            ),
+            (
+                "index entries compared",
+                counters.entries_compared.to_string(),
+            ),
+            ("objects hashed", counters.objects_hashed.to_string()),
+        ];
+        for (name, duration) in &self.phases {
+            rows.push((name, format_duration(*duration)));
+        }
+        rows.push(("total", format_duration(self.start.elapsed())));
+
+        for (label, value) in rows {
+            writeln!(writer, "{:<24}{value}", format!("{label}:"))?;
+        }
+        Ok(())
+    }
+}
+
+/// How often the progress of a long operation is shown
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The progress of a long operation over objects (e.g. verifying a pack),
+/// shown on stderr with the number of objects and of bytes processed per
+/// second, so that slow inflating and hashing can be noticed
+#[derive(Debug)]
+pub(crate) struct Throughput {
+    title: &'static str,
+    /// Whether the progress and the summary are shown
+    show: bool,
+    start: Instant,
+    /// When the progress was last shown
+    shown: Instant,
+    objects: usize,
+    /// The size of the objects, once inflated
+    bytes: u64,
+}
+
+impl Throughput {
+    /// Start measuring an operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `title` - What is done to the objects (e.g. `Checking objects`)
+    /// * `show` - Whether to show the progress, or only to measure it
+    pub(crate) fn start(title: &'static str, show: bool) -> Self {
+        let now = Instant::now();
+        Throughput {
+            title,
+            show,
+            start: now,
+            shown: now,
+            objects: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Count an object processed, showing the progress if it was not shown recently.
+    pub(crate) fn add(&mut self, bytes: usize) {
+        self.objects += 1;
+        self.bytes += bytes as u64;
+        if self.show && self.shown.elapsed() >= PROGRESS_INTERVAL {
+            self.shown = Instant::now();
+            let (objects, mebibytes) = self.rates(self.start.elapsed());
+            eprint!(
+                "\r{}: {} ({objects:.1} objects/s, {mebibytes:.2} MiB/s)",
+                self.title, self.objects
+            );
+        }
+    }
+
+    /// End the operation, showing a summary of its throughput.
+    pub(crate) fn finish(&self) {
+        if self.show {
+            eprintln!("\r{}: {}, done.", self.title, self.objects);
+            eprintln!("{}", self.summary(self.start.elapsed()));
+        }
+    }
+
+    /// The objects and the mebibytes processed per second.
+    fn rates(&self, elapsed: Duration) -> (f64, f64) {
+        // Operations too quick to be measured are counted as taking a microsecond
+        let seconds = elapsed.as_secs_f64().max(1e-6);
+        (
+            self.objects as f64 / seconds,
+            self.bytes as f64 / MEBIBYTE / seconds,
+        )
+    }
+
+    /// Summarize the operation, e.g. `12 objects, 1.50 MiB in 0.25 s (48.0 objects/s, 6.00 MiB/s)`.
+    fn summary(&self, elapsed: Duration) -> String {
+        let (objects, mebibytes) = self.rates(elapsed);
+        format!(
+            "{} objects, {:.2} MiB in {:.2} s ({objects:.1} objects/s, {mebibytes:.2} MiB/s)",
+            self.objects,
+            self.bytes as f64 / MEBIBYTE,
+            elapsed.as_secs_f64(),
+        )
+    }
+}
+
+const MEBIBYTE: f64 = 1024.0 * 1024.0;
+
+/// Format a duration in milliseconds, e.g. `12.345 ms`.
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_work_since_the_start() {
+        Counters::update(|counters| counters.files_scanned += 5);
+        let mut stats = Stats::start();
+        Counters::update(|counters| {
+            counters.files_scanned += 2;
+            counters.objects_hashed += 1;
+        });
+        stats.phase("scan");
+
+        assert_eq!(stats.counters(), Counters {
+            files_scanned: 2,
+            objects_hashed: 1,
+            ..Counters::default()
+        });
+
+        let mut output = Vec::new();
+        stats.write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[..4], [
+            "files scanned:          2",
+            "directories pruned:     0",
+            "index entries compared: 0",
+            "objects hashed:         1",
+        ]);
+        assert!(lines[4].starts_with("scan:                   "));
+        assert!(lines[5].starts_with("total:                  "));
+        assert!(lines[5].ends_with(" ms"));
+    }
+
+    #[test]
+    fn summarizes_the_throughput() {
+        let mut throughput = Throughput::start("Checking objects", false);
+        throughput.add(1024 * 1024);
+        throughput.add(2 * 1024 * 1024);
+        throughput.add(0);
+
+        assert_eq!(
+            throughput.summary(Duration::from_millis(1500)),
+            "3 objects, 3.00 MiB in 1.50 s (2.0 objects/s, 2.00 MiB/s)"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+//! Inspecting the submodules checked out in a working tree
+//!
+//! Submodules are declared in `.gitmodules` (`submodule.<name>.path` and
+//! `submodule.<name>.url`), and recorded as gitlinks in trees and the index. Once
+//! initialized (their URL copied to the configuration of the superproject), they
+//! are cloned into `.git/modules/<name>`, with a `.git` gitfile at their path.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::utils::advice::warning;
+use crate::utils::commit::read_commit;
+use crate::utils::config::{Config, ConfigScope};
+use crate::utils::ignore::Ignore;
+use crate::utils::index::{is_valid_path, Index, IndexEntry};
+use crate::utils::path_from_bytes;
+use crate::utils::pathspec::relative_to;
+use crate::utils::refs::resolve_ref;
+use crate::utils::repository::Repository;
+use crate::utils::tree::{flatten_tree, MODE_GITLINK};
+use crate::utils::work_tree::list_files;
+
+/// A submodule declared in `.gitmodules`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Submodule {
+    /// The name of the submodule, which its git directory is named after
+    pub(crate) name: String,
+    /// The path of the submodule, relative to the root of the working tree
+    pub(crate) path: String,
+    /// The URL of its repository, as written (possibly relative to the superproject)
+    pub(crate) url: Option<String>,
+}
+
+/// Read the submodules declared in the `.gitmodules` file of the working tree.
+/// Submodules without a path are left out, and so are those with a name or path
+/// reaching out of their directory (with a warning), as `.gitmodules` comes
+/// from whoever made the commits.
+pub(crate) fn read_gitmodules(repo: &Repository) -> anyhow::Result<Vec<Submodule>> {
+    let path = repo.work_tree()?.join(".gitmodules");
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("read .gitmodules"),
+    };
+    let config = Config::parse(&content, &path, ConfigScope::Local)?;
+
+    let mut submodules: Vec<Submodule> = Vec::new();
+    for entry in config.entries() {
+        let Some((name, key)) = entry
+            .key
+            .strip_prefix("submodule.")
+            .and_then(|rest| rest.rsplit_once('.'))
+        else {
+            continue;
+        };
+        let position = match submodules.iter().position(|s| s.name == name) {
+            Some(position) => position,
+            None => {
+                submodules.push(Submodule {
+                    name: name.to_string(),
+                    path: String::new(),
+                    url: None,
+                });
+                submodules.len() - 1
+            },
+        };
+        let value = entry.value.clone().unwrap_or_default();
+        match key {
+            "path" => submodules[position].path = value.trim_end_matches('/').to_string(),
+            "url" => submodules[position].url = Some(value),
+            _ => {},
+        }
+    }
+
+    submodules.retain(|submodule| {
+        if submodule.path.is_empty() {
+            return false;
+        }
+        if !is_valid_submodule_name(&submodule.name) {
+            warning(&format!(
+                "ignoring suspicious submodule name: {}",
+                submodule.name
+            ));
+            return false;
+        }
+        if !is_valid_path(submodule.path.as_bytes()) {
+            warning(&format!(
+                "ignoring suspicious submodule path: {}",
+                submodule.path
+            ));
+            return false;
+        }
+        true
+    });
+    Ok(submodules)
+}
+
+/// Resolve the URL of a submodule: `./` and `../` URLs are relative to the URL
+/// of the `origin` remote of the superproject, or else to its working tree.
+pub(crate) fn resolve_submodule_url(repo: &Repository, url: &str) -> anyhow::Result<String> {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return Ok(url.to_string());
+    }
+
+    let mut base = match Config::load(repo)?.get("remote.origin.url") {
+        Some(remote) => remote.to_string(),
+        None => repo.work_tree()?.to_string_lossy().into_owned(),
+    };
+    let mut rest = url;
+    let mut separator = '/';
+    loop {
+        base.truncate(base.trim_end_matches('/').len());
+        if let Some(after) = rest.strip_prefix("./") {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("../") {
+            rest = after;
+            // Each `../` drops the last component of the base, keeping the
+            // colon of `host:path` URLs
+            match base.rfind(['/', ':']) {
+                Some(end) => {
+                    separator = base[end..].chars().next().unwrap_or('/');
+                    base.truncate(end);
+                },
+                None => anyhow::bail!("cannot strip one component off url '{base}'"),
+            }
+        } else {
+            break;
+        }
+    }
+    Ok(format!("{base}{separator}{rest}"))
+}
+
+/// Whether a submodule name can name its git directory in `.git/modules`: it
+/// must not be empty, absolute or have `..` components.
+pub(crate) fn is_valid_submodule_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['/', '\\'])
+        && !name.split(['/', '\\']).any(|component| component == "..")
+}
+
+/// The git directory a submodule is cloned into, `.git/modules/<name>`.
+pub(crate) fn submodule_git_dir(repo: &Repository, name: &str) -> anyhow::Result<PathBuf> {
+    if !is_valid_submodule_name(name) {
+        anyhow::bail!("'{name}' is not a valid submodule name");
+    }
+    Ok(repo.common_dir()?.join("modules").join(name))
+}
+
+/// Point the `.git` gitfile of a submodule to its git directory, with a path
+/// relative to the submodule.
+///
+/// # Arguments
+///
+/// * `repo` - The superproject
+/// * `path` - The path of the submodule relative to the root of the working tree
+/// * `git_dir` - The git directory of the submodule
+pub(crate) fn connect_submodule(
+    repo: &Repository,
+    path: &str,
+    git_dir: &Path,
+) -> anyhow::Result<()> {
+    let path = repo.work_tree()?.join(path).canonicalize()?;
+    let git_dir = git_dir.canonicalize()?;
+    let absolute = |path: &Path| path.to_string_lossy().trim_start_matches('/').to_string();
+    let target = relative_to(absolute(&git_dir).as_bytes(), absolute(&path).as_bytes());
+
+    let gitfile = path.join(".git");
+    std::fs::write(
+        &gitfile,
+        format!("gitdir: {}\n", String::from_utf8_lossy(&target)),
+    )
+    .context(format!("write {}", gitfile.display()))
+}
+
+/// How a checked out submodule differs from the commit recorded for it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SubmoduleStatus {
+    /// The submodule has another commit checked out
+    pub(crate) new_commits: bool,
+    /// Files tracked by the submodule were changed
+    pub(crate) modified_content: bool,
+    /// The submodule has untracked files
+    pub(crate) untracked_content: bool,
+}
+
+impl SubmoduleStatus {
+    /// Whether the submodule differs in any way
+    pub(crate) fn is_changed(&self) -> bool {
+        self.new_commits || self.modified_content || self.untracked_content
+    }
+}
+
+/// Open the repository of a submodule, if it is initialized
+/// (its directory has a `.git` directory or gitfile).
+///
+/// # Arguments
+///
+/// * `repo` - The superproject
+/// * `path` - The path of the submodule relative to the root of the working tree
+pub(crate) fn open_submodule(repo: &Repository, path: &[u8]) -> anyhow::Result<Option<Repository>> {
+    let path = repo.work_tree()?.join(path_from_bytes(path));
+    if !path.join(".git").exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(repo.open(path.join(".git"), Some(path))))
+}
+
+/// Compare a checked out submodule with the commit recorded for it in the index.
+///
+/// # Arguments
+///
+/// * `repo` - The superproject
+/// * `entry` - The gitlink entry of the submodule
+/// * `untracked` - Whether to look for untracked files in the submodule
+///
+/// # Returns
+///
+/// How the submodule differs, or `None` if it is not initialized
+pub(crate) fn submodule_status(
+    repo: &Repository,
+    entry: &IndexEntry,
+    untracked: bool,
+) -> anyhow::Result<Option<SubmoduleStatus>> {
+    let Some(submodule) = open_submodule(repo, &entry.path)? else {
+        return Ok(None);
+    };
+    let sub_work_tree = submodule.work_tree()?;
+    let index = Index::load(&submodule)?;
+    let head = resolve_ref(&submodule, "HEAD")?;
+
+    let mut status = SubmoduleStatus {
+        new_commits: head.as_deref() != Some(entry.hash.as_str()),
+        ..Default::default()
+    };
+
+    // Changes staged in the submodule count as modified content as well
+    status.modified_content = has_tracked_changes(&submodule, &index, head.as_deref())?;
+
+    if untracked {
+        let mut ignore = Ignore::load(&submodule)?;
+        status.untracked_content = list_files(&sub_work_tree, Some(&mut ignore))?
+            .iter()
+            .any(|file| !index.contains(&file.path));
+    }
+
+    Ok(Some(status))
+}
+
+/// Whether the tracked files of a repository differ from its HEAD commit,
+/// either in the index or in the working tree.
+///
+/// # Arguments
+///
+/// * `repo` - The repository
+/// * `index` - The index of the repository
+/// * `head` - The commit checked out, if any
+pub(crate) fn has_tracked_changes(
+    repo: &Repository,
+    index: &Index,
+    head: Option<&str>,
+) -> anyhow::Result<bool> {
+    let mut head_entries = BTreeMap::new();
+    if let Some(head) = head {
+        for entry in flatten_tree(repo, &read_commit(repo, head)?.tree)? {
+            head_entries.insert(entry.name, (entry.mode, entry.hash));
+        }
+    }
+    let staged = index.entries().len() != head_entries.len()
+        || index
+            .entries()
+            .iter()
+            .any(|entry| head_entries.get(&entry.path) != Some(&(entry.mode, entry.hash.clone())));
+
+    Ok(staged || has_modified_files(repo, index)?)
+}
+
+/// Whether any file tracked by a repository was changed in its working tree.
+/// Nested submodules are modified if they have new commits or modified content.
+fn has_modified_files(repo: &Repository, index: &Index) -> anyhow::Result<bool> {
+    let work_tree = repo.work_tree()?;
+    for entry in index.entries() {
+        let modified = if entry.mode == MODE_GITLINK
+            && work_tree.join(path_from_bytes(&entry.path)).is_dir()
+        {
+            submodule_status(repo, entry, false)?
+                .is_some_and(|nested| nested.new_commits || nested.modified_content)
+        } else {
+            index.is_modified(entry, &work_tree)?
+        };
+
+        if modified {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::objects::{write_object, ObjectType};
+    use crate::utils::test::TempRepo;
+
+    #[test]
+    fn compares_submodules_with_their_commit() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join("sub/.git/objects")).unwrap();
+        fs::write(temp.join("sub/.git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(temp.join("sub/a.txt"), "a").unwrap();
+
+        let repo = Repository::with_environment(
+            temp.path().to_path_buf(),
+            temp.repo().env().clone(),
+            None,
+            Some(temp.path().to_path_buf()),
+        );
+        let submodule = open_submodule(&repo, b"sub").unwrap().unwrap();
+        let mut index = Index::default();
+        let hash = write_object(&submodule, ObjectType::Blob, b"a").unwrap();
+        let metadata = fs::symlink_metadata(temp.join("sub/a.txt")).unwrap();
+        index.add_entry(IndexEntry::from_metadata("a.txt".into(), hash, &metadata));
+        let tree = index.write_tree(&submodule, false).unwrap();
+        index.write(&submodule).unwrap();
+        let commit =
+            format!("tree {tree}\nauthor a <a@b> 0 +0000\ncommitter a <a@b> 0 +0000\n\nc\n");
+        let commit = write_object(&submodule, ObjectType::Commit, commit.as_bytes()).unwrap();
+        fs::create_dir_all(temp.join("sub/.git/refs/heads")).unwrap();
+        fs::write(temp.join("sub/.git/refs/heads/main"), format!("{commit}\n")).unwrap();
+
+        let entry = IndexEntry::new("sub".into(), MODE_GITLINK, commit);
+        let status = submodule_status(&repo, &entry, true).unwrap().unwrap();
+        assert!(!status.is_changed());
+
+        fs::write(temp.join("sub/a.txt"), "changed").unwrap();
+        fs::write(temp.join("sub/b.txt"), "b").unwrap();
+        let status = submodule_status(&repo, &entry, true).unwrap().unwrap();
+        assert_eq!(status, SubmoduleStatus {
+            new_commits: false,
+            modified_content: true,
+            untracked_content: true,
+        });
+
+        let other = IndexEntry::new("sub".into(), MODE_GITLINK, "0".repeat(40));
+        assert!(
+            submodule_status(&repo, &other, false)
+                .unwrap()
+                .unwrap()
+                .new_commits
+        );
+        assert!(open_submodule(&repo, b"missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_gitmodules_and_resolves_urls() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::write(temp.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(
+            temp.join(".gitmodules"),
+            "[submodule \"lib\"]\n\tpath = libs/lib/\n\turl = ../lib\n\
+             [submodule \"gone\"]\n\turl = /gone\n",
+        )
+        .unwrap();
+
+        let repo = temp.repo();
+        assert_eq!(read_gitmodules(&repo).unwrap(), [Submodule {
+            name: "lib".to_string(),
+            path: "libs/lib".to_string(),
+            url: Some("../lib".to_string()),
+        }]);
+
+        // Names and paths reaching out of their directory are ignored
+        fs::write(
+            temp.join(".gitmodules"),
+            "[submodule \"../../x\"]\n\tpath = x\n\
+             [submodule \"/x\"]\n\tpath = x\n\
+             [submodule \"a\\\\..\"]\n\tpath = x\n\
+             [submodule \"up\"]\n\tpath = ../x\n\
+             [submodule \"hooks\"]\n\tpath = .git/hooks\n\
+             [submodule \"x..y\"]\n\tpath = x\n",
+        )
+        .unwrap();
+        let names: Vec<_> = read_gitmodules(&repo)
+            .unwrap()
+            .into_iter()
+            .map(|submodule| submodule.name)
+            .collect();
+        assert_eq!(names, ["x..y"]);
+        assert!(submodule_git_dir(&repo, "../../x").is_err());
+
+        let work_tree = repo.work_tree().unwrap();
+        let parent = work_tree.parent().unwrap().to_string_lossy();
+        assert_eq!(
+            resolve_submodule_url(&repo, "../lib").unwrap(),
+            format!("{parent}/lib")
+        );
+        assert_eq!(resolve_submodule_url(&repo, "/lib").unwrap(), "/lib");
+
+        fs::write(
+            temp.join(".git/config"),
+            "[remote \"origin\"]\n\turl = host:repos/super.git/\n",
+        )
+        .unwrap();
+        let repo = temp.repo();
+        assert_eq!(
+            resolve_submodule_url(&repo, "./../lib").unwrap(),
+            "host:repos/lib"
+        );
+        assert_eq!(
+            resolve_submodule_url(&repo, "../../lib").unwrap(),
+            "host:lib"
+        );
+        assert!(resolve_submodule_url(&repo, "../../../lib").is_err());
+    }
+}
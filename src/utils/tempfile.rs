@@ -0,0 +1,332 @@
+//! Temporary files that are cleaned up when a command fails or is interrupted
+//!
+//! Lock files, temporary objects and repositories being cloned are removed
+//! when the [`TempPath`] holding them is dropped (e.g. when the command fails),
+//! unless kept. They are also registered while they exist so that a signal
+//! ending the command (Ctrl-C, `kill`) removes them as well, instead of leaving
+//! a lock that makes the next command fail or a partial file behind.
+//!
+//! What is left when the command is killed outright (or the machine crashes)
+//! is removed later by `gc` and `prune`, once old enough not to belong to a
+//! command still running (see [`remove_leftovers`]).
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, Once, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+
+use crate::utils::advice::warning;
+use crate::utils::display_path;
+use crate::utils::repository::Repository;
+
+/// How long a lock or temporary file must have been left untouched to be
+/// considered left behind, rather than in use by a running command
+pub(crate) const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// The paths to remove if the command is interrupted
+static REGISTERED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// A file or directory that is removed when dropped, unless kept
+#[derive(Debug)]
+pub(crate) struct TempPath {
+    path: PathBuf,
+    kept: bool,
+}
+
+impl TempPath {
+    /// Take charge of a path, which the caller is about to create (or just created).
+    pub(crate) fn new(path: PathBuf) -> Self {
+        install_signal_handlers();
+        if let Ok(mut registered) = REGISTERED.lock() {
+            registered.push(path.clone());
+        }
+        TempPath { path, kept: false }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keep the path (e.g. once renamed into place, or once a clone is done).
+    pub(crate) fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if let Ok(mut registered) = REGISTERED.lock() {
+            if let Some(index) = registered.iter().rposition(|path| *path == self.path) {
+                registered.remove(index);
+            }
+        }
+        if !self.kept {
+            remove_path(&self.path);
+        }
+    }
+}
+
+fn remove_path(path: &Path) {
+    match path.is_dir() {
+        true => {
+            let _ = std::fs::remove_dir_all(path);
+        },
+        false => {
+            let _ = std::fs::remove_file(path);
+        },
+    }
+}
+
+/// Remove the registered paths, newest first.
+fn remove_registered() {
+    let registered = REGISTERED.lock().unwrap_or_else(PoisonError::into_inner);
+    remove_paths(&registered);
+}
+
+fn remove_paths(paths: &[PathBuf]) {
+    for path in paths.iter().rev() {
+        remove_path(path);
+    }
+}
+
+/// A lock or temporary file found in a repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Leftover {
+    path: PathBuf,
+    /// Whether it is a lock (`*.lock`) rather than a temporary object or pack
+    lock: bool,
+    /// Whether it was modified after the expiry date, so may be in use
+    recent: bool,
+}
+
+/// Find the locks (`*.lock`), temporary objects (`tmp_obj_*`) and temporary
+/// packs (`tmp_pack_*`) of a repository, in its common directory (including
+/// those of its worktrees) and its object directory, sorted by path. The
+/// repositories of submodules are left to their own commands.
+///
+/// # Arguments
+///
+/// * `repo` - The repository to look in
+/// * `expire` - The files modified after this date are marked as recent
+fn find_leftovers(repo: &Repository, expire: SystemTime) -> anyhow::Result<Vec<Leftover>> {
+    let common_dir = repo.common_dir()?;
+    let mut leftovers = Vec::new();
+    find_in(&common_dir, &common_dir, expire, &mut leftovers)?;
+
+    let object_dir = repo.object_dir(true)?;
+    if !object_dir.starts_with(&common_dir) {
+        find_in(&object_dir, &object_dir, expire, &mut leftovers)?;
+    }
+    leftovers.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(leftovers)
+}
+
+fn find_in(
+    root: &Path,
+    dir: &Path,
+    expire: SystemTime,
+    leftovers: &mut Vec<Leftover>,
+) -> anyhow::Result<()> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if path != root.join("modules") {
+                find_in(root, &path, expire, leftovers)?;
+            }
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let lock = name.ends_with(".lock");
+        if !lock && !name.starts_with("tmp_obj_") && !name.starts_with("tmp_pack_") {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        leftovers.push(Leftover {
+            path,
+            lock,
+            recent: modified > expire,
+        });
+    }
+    Ok(())
+}
+
+/// Remove the locks and temporary files left in a repository by commands that
+/// did not end cleanly (see [`find_leftovers`]). Those modified after the
+/// expiry date are kept, with a warning for locks as they are likely held by
+/// a running command (or one that died only recently).
+///
+/// # Arguments
+///
+/// * `repo` - The repository to clean up
+/// * `expire` - The date after which files are considered in use
+/// * `dry_run` - Whether to only show the files that would be removed
+/// * `writer` - Where to report each file removed
+pub(crate) fn remove_leftovers<W>(
+    repo: &Repository,
+    expire: SystemTime,
+    dry_run: bool,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: Write,
+{
+    for leftover in find_leftovers(repo, expire)? {
+        let path = display_path(repo, &leftover.path);
+        match (leftover.recent, leftover.lock) {
+            (true, true) => {
+                warning(&format!(
+                    "not removing {path}: it may be held by a running process"
+                ));
+                continue;
+            },
+            (true, false) => continue,
+            (false, true) => writeln!(writer, "Removing stale lock file {path}")?,
+            (false, false) => writeln!(writer, "Removing stale temporary file {path}")?,
+        }
+        if !dry_run {
+            std::fs::remove_file(&leftover.path).context(format!("remove {path}"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+    fn raise(signum: i32) -> i32;
+    fn pipe(fds: *mut i32) -> i32;
+    fn read(fd: i32, buf: *mut u8, count: usize) -> isize;
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+}
+
+/// SIGHUP, SIGINT, SIGQUIT and SIGTERM
+#[cfg(unix)]
+const SIGNALS: [i32; 4] = [1, 2, 3, 15];
+
+#[cfg(unix)]
+const SIG_DFL: usize = 0;
+
+/// The end of the pipe the signal handler writes the signals received to
+#[cfg(unix)]
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Pass the signal on to the cleanup thread. A handler may interrupt a thread
+/// anywhere (e.g. holding the lock of the registered paths, or in the
+/// allocator), so it does nothing but write to the pipe.
+#[cfg(unix)]
+extern "C" fn handle_signal(signum: i32) {
+    let byte = signum as u8;
+    // SAFETY: write is async-signal-safe, and is given a valid one-byte buffer
+    // and the pipe, which stays open as long as the process runs
+    unsafe {
+        write(SIGNAL_PIPE.load(Ordering::Relaxed), &byte, 1);
+    }
+}
+
+/// Wait for a signal from the handler, then remove the registered paths and
+/// end the process by the signal as it would have been without the handler
+/// (so that its status tells the signal).
+#[cfg(unix)]
+fn clean_up_on_signal(fd: i32) {
+    let mut byte = 0u8;
+    loop {
+        // SAFETY: the buffer is a valid byte and the pipe stays open
+        match unsafe { read(fd, &mut byte, 1) } {
+            1 => break,
+            -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {},
+            _ => return,
+        }
+    }
+
+    remove_registered();
+    let signum = i32::from(byte);
+    // SAFETY: restoring the default action and raising a signal have no preconditions
+    unsafe {
+        signal(signum, SIG_DFL);
+        raise(signum);
+    }
+}
+
+/// Install the handlers of the signals ending the command, and the thread
+/// cleaning up when one is received, once.
+fn install_signal_handlers() {
+    static INSTALLED: Once = Once::new();
+    #[cfg(unix)]
+    INSTALLED.call_once(|| {
+        let mut fds = [0; 2];
+        // SAFETY: the array has room for both ends of the pipe
+        if unsafe { pipe(fds.as_mut_ptr()) } != 0 {
+            return;
+        }
+        SIGNAL_PIPE.store(fds[1], Ordering::Relaxed);
+        let spawned = std::thread::Builder::new()
+            .name("signal cleanup".to_string())
+            .spawn(move || clean_up_on_signal(fds[0]));
+        if spawned.is_err() {
+            return;
+        }
+
+        for signum in SIGNALS {
+            // SAFETY: the handler only writes to the pipe, which is async-signal-safe
+            unsafe {
+                signal(signum, handle_signal as extern "C" fn(i32) as usize);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::utils::test::TempRepo;
+
+    /// The registered paths of the test, leaving those of the tests running
+    /// alongside it alone
+    fn registered_in(temp: &TempRepo) -> Vec<PathBuf> {
+        let registered = REGISTERED.lock().unwrap();
+        registered
+            .iter()
+            .filter(|path| path.starts_with(temp.path()))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn removes_paths_not_kept() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join("dir/sub")).unwrap();
+        let dir = TempPath::new(temp.join("dir"));
+        let file = TempPath::new(temp.join("file.lock"));
+        fs::write(file.path(), "").unwrap();
+        let kept = TempPath::new(temp.join("kept"));
+        fs::write(kept.path(), "").unwrap();
+
+        // An interrupted command removes them all
+        remove_paths(&registered_in(&temp));
+        assert!(!temp.join("dir").exists() && !temp.join("file.lock").exists());
+        assert!(!temp.join("kept").exists());
+        drop((dir, file));
+
+        // Otherwise only those dropped without being kept
+        fs::write(kept.path(), "").unwrap();
+        let lock = TempPath::new(temp.join("main.lock"));
+        fs::write(lock.path(), "").unwrap();
+        kept.keep();
+        drop(lock);
+        assert!(temp.join("kept").exists());
+        assert!(!temp.join("main.lock").exists());
+        assert!(registered_in(&temp).is_empty());
+    }
+}
@@ -2,152 +2,181 @@
 
 #![cfg(test)]
 
-use std::collections::HashMap;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 
-/// A temporary environment for testing.
-/// Changes the environment variable and restores it on drop.
-/// Tests must be run serially to avoid conflicts (`cargo test -- --test-threads=1`)
-pub(crate) struct TempEnv(HashMap<String, Option<String>>);
-
-impl TempEnv {
-    /// Set a new temporary environment variable.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use crate::utils::test::TempEnv;
-    /// let temp_env = TempEnv::set("KEY", "VALUE");
-    /// assert_eq!(std::env::var("KEY"), Ok("VALUE".to_string()));
-    ///
-    /// // The environment variable is restored when the `TempEnv` instance is dropped
-    /// drop(temp_env);
-    /// assert!(std::env::var("KEY").is_err());
-    #[allow(dead_code)]
-    pub(crate) fn set<S>(key: S, value: &str) -> Self
+use crate::utils::env::{self, Environment};
+use crate::utils::repository::Repository;
+
+/// A temporary directory to use repositories from, with the environment their
+/// commands run with. Neither the current directory nor the environment of the
+/// process are changed, so tests can run in parallel.
+///
+/// # Example
+///
+/// ```
+/// # use crate::utils::test::TempRepo;
+/// let temp = TempRepo::new().env(env::GIT_AUTHOR_NAME, "a");
+/// std::fs::create_dir_all(temp.join(".git/objects")).unwrap();
+/// let repo = temp.repo();
+/// assert_eq!(repo.git_dir().unwrap(), temp.join(".git"));
+/// ```
+pub(crate) struct TempRepo {
+    _dir: tempfile::TempDir,
+    /// The canonical path of the directory, as that of the current directory is
+    path: PathBuf,
+    env: Environment,
+}
+
+impl TempRepo {
+    /// An empty directory, with only `PATH` kept from the environment of the
+    /// process and the system config ignored.
+    pub(crate) fn new() -> Self {
+        let mut env = Environment::default();
+        if let Some(path) = std::env::var_os("PATH") {
+            env.set("PATH", path);
+        }
+        env.set(env::GIT_CONFIG_NOSYSTEM, "1");
+        let dir = tempfile::tempdir().unwrap();
+        Self {
+            path: dir.path().canonicalize().unwrap(),
+            _dir: dir,
+            env,
+        }
+    }
+
+    /// Set an environment variable.
+    pub(crate) fn env<V>(mut self, name: &str, value: V) -> Self
     where
-        S: Into<String>,
+        V: Into<OsString>,
     {
-        let key = key.into();
-        // Get the current value of the environment variable
-        let old_value = std::env::var(&key).ok();
-        std::env::set_var(&key, value);
-        // Store the previous state of the environment variable
-        TempEnv(HashMap::from([(key, old_value)]))
+        self.set_env(name, value);
+        self
     }
 
-    /// Unset a temporary environment variable.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use crate::utils::test::TempEnv;
-    /// let temp_env = TempEnv::unset("KEY");
-    /// assert!(std::env::var("KEY").is_err());
-    #[allow(dead_code)]
-    pub(crate) fn unset<S>(key: S) -> Self
+    /// Set an environment variable, for the repositories used afterwards.
+    pub(crate) fn set_env<V>(&mut self, name: &str, value: V)
     where
-        S: Into<String>,
+        V: Into<OsString>,
     {
-        let key = key.into();
-        // Get the current value of the environment variable
-        let old_value = std::env::var(&key).ok();
-        std::env::remove_var(&key);
-        // Store the previous state of the environment variable
-        TempEnv(HashMap::from([(key, old_value)]))
+        self.env.set(name, value);
     }
-}
 
-impl<S, const N: usize> From<[(S, Option<&str>); N]> for TempEnv
-where
-    S: Into<String> + Clone,
-{
-    /// Create a new temporary environment variable from an array of key-value pairs.
-    /// See [`TempEnv::new`] for more information.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// # use crate::utils::test::TempEnv;
-    /// let temp_env = TempEnv::from([("KEY1", Some("VALUE1")), ("KEY2", None)]);
-    /// assert_eq!(std::env::var("KEY1"), Ok("VALUE1".to_string()));
-    /// assert!(std::env::var("KEY2").is_err());
-    ///
-    /// // The environment variables are restored when the `TempEnv` instance is dropped
-    /// drop(temp_env);
-    fn from(slice: [(S, Option<&str>); N]) -> Self {
-        let mut map = HashMap::with_capacity(N);
-
-        for (key, value) in slice.iter() {
-            let key: String = key.clone().into();
-            // Get the current value of the environment variable
-            let old_value = std::env::var(&key).ok();
-
-            // Set or unset the environment variable
-            if let Some(value) = value {
-                std::env::set_var(&key, value);
-            } else {
-                std::env::remove_var(&key);
-            }
-
-            // Store the previous state of the environment variable
-            map.insert(key, old_value);
-        }
+    /// The path of the directory.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
 
-        TempEnv(map)
+    /// A path in the directory.
+    pub(crate) fn join<P>(&self, path: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        self.path.join(path)
     }
-}
 
-impl Drop for TempEnv {
-    fn drop(&mut self) {
-        for (key, old_value) in self.0.iter() {
-            // Restore the previous state of the environment variable
-            if let Some(old_value) = old_value {
-                std::env::set_var(key, old_value);
-            } else {
-                std::env::remove_var(key);
-            }
-        }
+    /// The repository found from the directory (or the one given by the
+    /// environment).
+    pub(crate) fn repo(&self) -> Repository {
+        self.repo_at("")
+    }
+
+    /// The repository found from a subdirectory, used from it.
+    pub(crate) fn repo_at<P>(&self, dir: P) -> Repository
+    where
+        P: AsRef<Path>,
+    {
+        Repository::with_environment(self.join(dir), self.env.clone(), None, None)
     }
 }
 
-/// A temporary directory for testing.
-/// Changes the current directory to the temporary directory and restores it on drop.
+/// Generate an unencrypted ed25519 key pair with `ssh-keygen`.
 ///
-/// # Example
-///
-/// ```
-/// # use crate::utils::test::TempPwd;
-/// let temp_pwd = TempPwd::new();
-/// assert_eq!(std::env::current_dir().unwrap(), temp_pwd.temp_pwd.path());
+/// # Returns
 ///
-/// // The current directory is restored when the `TempPwd` instance is dropped
-/// drop(temp_pwd);
-/// ```
-pub(crate) struct TempPwd {
-    old_pwd: PathBuf,
-    temp_pwd: tempfile::TempDir,
+/// The path to the private key and the public key line (`ssh-ed25519 <base64>`)
+#[allow(dead_code)]
+pub(crate) fn ssh_keygen(dir: &Path) -> (PathBuf, String) {
+    let key = dir.join("id_ed25519");
+    let status = std::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "", "-f"])
+        .arg(&key)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let public_key = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+    (key, public_key.trim().to_string())
 }
 
-impl TempPwd {
-    pub(crate) fn new() -> Self {
-        let old_pwd = std::env::current_dir().unwrap();
-        let temp_pwd = tempfile::tempdir().unwrap();
-
-        // Change the current directory to the temporary directory
-        std::env::set_current_dir(&temp_pwd).unwrap();
+/// Sign a payload in the `git` namespace with `ssh-keygen`.
+///
+/// # Returns
+///
+/// The armored signature
+#[allow(dead_code)]
+pub(crate) fn ssh_sign(key: &Path, payload: &[u8]) -> String {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), payload).unwrap();
+
+    let status = std::process::Command::new("ssh-keygen")
+        .args(["-q", "-Y", "sign", "-n", "git", "-f"])
+        .arg(key)
+        .arg(file.path())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let signature = PathBuf::from(format!("{}.sig", file.path().display()));
+    let armored = std::fs::read_to_string(&signature).unwrap();
+    std::fs::remove_file(signature).unwrap();
+    armored
+}
 
-        Self { old_pwd, temp_pwd }
+/// Serialize an index file containing regular files with the given
+/// path, hash and stage (the cached metadata is all zeros).
+/// Paths must be sorted; an empty `TREE` extension is appended.
+#[allow(dead_code)]
+pub(crate) fn index_file(version: u32, entries: &[(&str, &str, u16)]) -> Vec<u8> {
+    use sha1::{Digest, Sha1};
+
+    let mut data = b"DIRC".to_vec();
+    data.extend(version.to_be_bytes());
+    data.extend((entries.len() as u32).to_be_bytes());
+
+    let mut previous: &str = "";
+    for (path, hash, stage) in entries {
+        let start = data.len();
+        // ctime, mtime, dev, ino
+        data.extend([0; 24]);
+        data.extend(0o100644u32.to_be_bytes());
+        // uid, gid, size
+        data.extend([0; 12]);
+        data.extend(crate::utils::hex::decode(hash.as_bytes()).unwrap());
+        data.extend(((stage << 12) | path.len() as u16).to_be_bytes());
+
+        if version == 4 {
+            let common = previous
+                .bytes()
+                .zip(path.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            data.push((previous.len() - common) as u8);
+            data.extend(&path.as_bytes()[common..]);
+            data.push(0);
+        } else {
+            data.extend(path.as_bytes());
+            let len = data.len() - start;
+            data.extend(vec![0; (len + 8) / 8 * 8 - len]);
+        }
+        previous = path;
     }
 
-    pub(crate) fn path(&self) -> &Path {
-        self.temp_pwd.path()
-    }
-}
+    data.extend(b"TREE");
+    data.extend(0u32.to_be_bytes());
 
-impl Drop for TempPwd {
-    fn drop(&mut self) {
-        // Restore the current directory
-        std::env::set_current_dir(&self.old_pwd).unwrap();
-    }
+    let checksum = Sha1::digest(&data);
+    data.extend(checksum);
+    data
 }
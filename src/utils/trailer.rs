@@ -0,0 +1,147 @@
+//! Parsing of the trailers at the end of commit messages
+//! (e.g. `Signed-off-by: Name <email>`)
+
+/// Prefixes of the trailers added by git itself, which make a paragraph a
+/// trailer block even when it has other lines as well
+const GIT_GENERATED_PREFIXES: [&str; 2] = ["Signed-off-by: ", "(cherry picked from commit "];
+
+/// Parse the trailers of a commit message.
+///
+/// The trailers are the lines of the last paragraph of the message (never
+/// the subject), if the paragraph only has `Key: value` lines, or if git
+/// added one of them and at least a quarter of its lines are trailers.
+/// Lines starting with whitespace continue the value of the previous trailer.
+///
+/// # Returns
+///
+/// The keys and the (unfolded) values of the trailers, in order
+pub(crate) fn parse_trailers(message: &[u8]) -> Vec<(String, String)> {
+    let message = String::from_utf8_lossy(message);
+    let lines: Vec<&str> = message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .skip_while(|line| line.trim().is_empty())
+        .collect();
+    let end = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(0, |last| last + 1);
+
+    // The subject comes before the first blank line, so a message without
+    // one has no trailers
+    let Some(blank) = lines[..end].iter().rposition(|line| line.trim().is_empty()) else {
+        return Vec::new();
+    };
+    let block = &lines[blank + 1..end];
+    if !is_trailer_block(block) {
+        return Vec::new();
+    }
+
+    let mut trailers: Vec<(String, String)> = Vec::new();
+    let mut continues = false;
+    for line in block {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = trailers.last_mut().filter(|_| continues) {
+                if !value.is_empty() {
+                    value.push(' ');
+                }
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+
+        continues = match split_trailer(line) {
+            Some((key, value)) => {
+                trailers.push((key.to_string(), value.to_string()));
+                true
+            },
+            None => false,
+        };
+    }
+    trailers
+}
+
+/// Whether the lines of the last paragraph of a message are trailers.
+fn is_trailer_block(block: &[&str]) -> bool {
+    let mut trailer_lines = 0;
+    let mut other_lines = 0;
+    let mut generated = false;
+    let mut in_trailer = false;
+
+    for line in block {
+        if line.starts_with([' ', '\t']) {
+            if in_trailer {
+                trailer_lines += 1;
+            } else {
+                other_lines += 1;
+            }
+            continue;
+        }
+
+        if GIT_GENERATED_PREFIXES
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+        {
+            generated = true;
+            in_trailer = true;
+        } else {
+            in_trailer = split_trailer(line).is_some();
+        }
+
+        if in_trailer {
+            trailer_lines += 1;
+        } else {
+            other_lines += 1;
+        }
+    }
+
+    (trailer_lines > 0 && other_lines == 0) || (generated && trailer_lines * 3 >= other_lines)
+}
+
+/// Split a `Key: value` line, where the key only has alphanumeric characters and dashes.
+fn split_trailer(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    let key = key.trim_end();
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '-');
+    valid.then(|| (key, value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_trailers;
+
+    fn pairs(trailers: &[(&str, &str)]) -> Vec<(String, String)> {
+        trailers
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_the_last_paragraph() {
+        let message = b"subject\n\nbody: not a trailer\nat all\n\n\
+            Reviewed-by: A <a@example.com>\nAcked-by: B\n  continued\n";
+        assert_eq!(
+            parse_trailers(message),
+            pairs(&[
+                ("Reviewed-by", "A <a@example.com>"),
+                ("Acked-by", "B continued")
+            ])
+        );
+        assert!(parse_trailers(b"Reviewed-by: subject only\n").is_empty());
+        assert!(parse_trailers(b"subject\n\nReviewed-by: X\nnot a trailer\n").is_empty());
+    }
+
+    #[test]
+    fn accepts_other_lines_next_to_generated_trailers() {
+        let message = b"subject\n\nReviewed-by: X\nSigned-off-by: Y\nnot a trailer\n";
+        assert_eq!(
+            parse_trailers(message),
+            pairs(&[("Reviewed-by", "X"), ("Signed-off-by", "Y")])
+        );
+        assert!(parse_trailers(b"subject\n\nSigned-off-by: Y\na\nb\nc\nd\n").is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+//! Exchanging objects with other repositories on the local file system
+
+use std::path::{Path, PathBuf};
+
+use crate::utils::commit::{Commit, Tag};
+use crate::utils::config::Config;
+use crate::utils::objects::{object_exists, read_original_object, write_object, ObjectType};
+use crate::utils::repository::Repository;
+use crate::utils::tree::{parse_tree, MODE_GITLINK};
+
+/// Another repository on the local file system
+pub(crate) struct LocalRepository {
+    pub(crate) repo: Repository,
+    /// The URL (or path) the repository was opened from
+    pub(crate) url: String,
+    /// Whether the repository has no working tree
+    pub(crate) bare: bool,
+}
+
+/// Open another repository by the name of a remote (`remote.<name>.url`) or by its path.
+/// Only local paths (and `file://` URLs) are supported, as there are no network transports.
+pub(crate) fn open_repository(
+    repo: &Repository,
+    repository: &str,
+) -> anyhow::Result<LocalRepository> {
+    let config = Config::load(repo)?;
+    let url = config
+        .get(&format!("remote.{repository}.url"))
+        .unwrap_or(repository)
+        .to_string();
+
+    let path = match url.strip_prefix("file://") {
+        Some(path) => PathBuf::from(path),
+        None if is_remote_url(&url) => {
+            anyhow::bail!(
+                "'{url}' is not a local repository, only local repositories are supported"
+            )
+        },
+        None => PathBuf::from(&url),
+    };
+
+    // Relative paths are relative to the directory of the command
+    let path = repo.cwd().join(path);
+    // Non-bare repositories keep their git directory in `.git`
+    let (git_dir, work_tree) = if path.join(".git").exists() {
+        (path.join(".git"), Some(path))
+    } else {
+        (path, None)
+    };
+    let bare = work_tree.is_none();
+    let other = repo.open(git_dir, work_tree);
+    if !other.object_dir(false)?.is_dir() {
+        anyhow::bail!("'{url}' does not appear to be a git repository");
+    }
+
+    Ok(LocalRepository {
+        repo: other,
+        url,
+        bare,
+    })
+}
+
+/// Whether a URL points to another machine (`<scheme>://` or scp-like `host:path`).
+pub(crate) fn is_remote_url(url: &str) -> bool {
+    if url.contains("://") {
+        return true;
+    }
+    match url.split_once(':') {
+        Some((host, _)) => !host.contains('/') && !Path::new(url).exists(),
+        None => false,
+    }
+}
+
+/// Copy an object and everything it refers to into another repository.
+/// Objects the other repository already has are assumed to be complete,
+/// so what they refer to is not copied again.
+///
+/// # Arguments
+///
+/// * `from` - The repository to copy the objects from
+/// * `to` - The repository to copy the objects to
+/// * `hash` - The hash of the commit, tree, blob or tag to copy
+///
+/// # Returns
+///
+/// The number of objects that were copied
+pub(crate) fn copy_objects(
+    from: &Repository,
+    to: &Repository,
+    hash: &str,
+) -> anyhow::Result<usize> {
+    let mut pending = vec![hash.to_string()];
+    let mut copied = 0;
+
+    while let Some(hash) = pending.pop() {
+        if object_exists(to, &hash)? {
+            continue;
+        }
+
+        let (object_type, content) = read_original_object(from, &hash)?;
+        match object_type {
+            ObjectType::Commit => {
+                let commit = Commit::parse(&content)?;
+                pending.push(commit.tree);
+                pending.extend(commit.parents);
+            },
+            ObjectType::Tree => {
+                // Submodule commits live in another repository
+                let entries = parse_tree(&content)?;
+                pending.extend(
+                    entries
+                        .into_iter()
+                        .filter(|entry| entry.mode != MODE_GITLINK)
+                        .map(|entry| entry.hash),
+                );
+            },
+            ObjectType::Tag => pending.push(Tag::parse(&content)?.object),
+            _ => {},
+        }
+
+        write_object(to, object_type, &content)?;
+        copied += 1;
+    }
+
+    Ok(copied)
+}
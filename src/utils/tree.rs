@@ -0,0 +1,343 @@
+//! Utilities for working with tree objects
+
+use anyhow::Context;
+
+use crate::utils::hex;
+use crate::utils::index::is_valid_path;
+use crate::utils::objects::{
+    object_exists, read_object_header_type, read_object_of_type, write_object, ObjectType,
+};
+use crate::utils::repository::Repository;
+
+/// The mode of a regular (non-executable) file
+pub(crate) const MODE_FILE: u32 = 0o100644;
+/// The mode of an executable file
+pub(crate) const MODE_EXECUTABLE: u32 = 0o100755;
+/// The mode of a symbolic link
+pub(crate) const MODE_SYMLINK: u32 = 0o120000;
+/// The mode of a submodule commit (gitlink)
+pub(crate) const MODE_GITLINK: u32 = 0o160000;
+/// The mode of a subdirectory
+pub(crate) const MODE_TREE: u32 = 0o040000;
+/// The bits of a mode that hold the object type (file, symlink or gitlink)
+pub(crate) const MODE_TYPE_MASK: u32 = 0o170000;
+/// The hash of the tree without entries
+pub(crate) const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+/// An entry of a tree object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TreeEntry {
+    /// The file mode (e.g. `0o100644`)
+    pub(crate) mode: u32,
+    /// The name of the file or directory
+    pub(crate) name: Vec<u8>,
+    /// The hex hash of the object the entry points to
+    pub(crate) hash: String,
+}
+
+impl TreeEntry {
+    /// Whether the entry points to a subtree
+    pub(crate) fn is_tree(&self) -> bool {
+        self.mode == MODE_TREE
+    }
+
+    /// The type of the object the entry points to, given by its mode
+    pub(crate) fn object_type(&self) -> ObjectType {
+        match self.mode {
+            MODE_TREE => ObjectType::Tree,
+            MODE_GITLINK => ObjectType::Commit,
+            _ => ObjectType::Blob,
+        }
+    }
+}
+
+/// Builds a single tree object from entries added in any order, which are
+/// sorted in tree order when it is written
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TreeBuilder {
+    entries: Vec<TreeEntry>,
+}
+
+impl TreeBuilder {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry, failing if its name is not a single path component
+    /// or is already taken by another entry.
+    pub(crate) fn insert(&mut self, mode: u32, name: &[u8], hash: &str) -> anyhow::Result<()> {
+        let display = String::from_utf8_lossy(name);
+        if name.contains(&b'/') {
+            anyhow::bail!("path {display} contains slash");
+        }
+        if matches!(name, b"" | b"." | b"..") || name.contains(&0) {
+            anyhow::bail!("invalid tree entry name '{display}'");
+        }
+        if hash.len() != 40 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("invalid object hash '{hash}' for entry '{display}'");
+        }
+        if self.entries.iter().any(|entry| entry.name == name) {
+            anyhow::bail!("duplicate tree entry '{display}'");
+        }
+
+        self.entries.push(TreeEntry {
+            mode,
+            name: name.to_vec(),
+            hash: hash.to_ascii_lowercase(),
+        });
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the tree object, checking that the object of each entry has the
+    /// type its mode gives. Missing objects fail unless `missing_ok` is set,
+    /// and submodule commits (and the empty tree, which always exists) are
+    /// never checked.
+    ///
+    /// # Returns
+    ///
+    /// The hash of the tree
+    pub(crate) fn write(&self, repo: &Repository, missing_ok: bool) -> anyhow::Result<String> {
+        let checked = |entry: &&TreeEntry| entry.mode != MODE_GITLINK && entry.hash != EMPTY_TREE;
+        for entry in self.entries.iter().filter(checked) {
+            let name = String::from_utf8_lossy(&entry.name);
+            let hash = &entry.hash;
+            if !object_exists(repo, hash)? {
+                if missing_ok {
+                    continue;
+                }
+                anyhow::bail!("entry '{name}' object {hash} is unavailable");
+            }
+            let (actual, expected) = (read_object_header_type(repo, hash)?, entry.object_type());
+            if actual != expected {
+                anyhow::bail!(
+                    "entry '{name}' object {hash} is a {actual} but specified type was ({expected})"
+                );
+            }
+        }
+        write_object(
+            repo,
+            ObjectType::Tree,
+            &serialize_tree(self.entries.clone())?,
+        )
+    }
+}
+
+/// Parse the content of a tree object.
+///
+/// Each entry has the format `{mode} {name}\0{20-byte hash}`.
+pub(crate) fn parse_tree(content: &[u8]) -> anyhow::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    let mut rest = content;
+
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|&b| b == b' ')
+            .context("invalid tree entry mode")?;
+        let mode = std::str::from_utf8(&rest[..space]).context("tree entry mode is not utf-8")?;
+        let mode = u32::from_str_radix(mode, 8).context("tree entry mode is not octal")?;
+        rest = &rest[space + 1..];
+
+        let null = rest
+            .iter()
+            .position(|&b| b == 0)
+            .context("invalid tree entry name")?;
+        let name = rest[..null].to_vec();
+        rest = &rest[null + 1..];
+
+        if rest.len() < 20 {
+            anyhow::bail!("truncated tree entry hash");
+        }
+        let mut hash = rest[..20].to_vec();
+        hex::encode_in_place(&mut hash);
+        rest = &rest[20..];
+
+        entries.push(TreeEntry {
+            mode,
+            name,
+            hash: String::from_utf8(hash)?,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Read and parse a tree object from the object database.
+pub(crate) fn read_tree(repo: &Repository, hash: &str) -> anyhow::Result<Vec<TreeEntry>> {
+    let content = read_object_of_type(repo, hash, ObjectType::Tree)?;
+    parse_tree(&content)
+}
+
+/// Look up the entry at a `/`-separated path within a tree.
+///
+/// # Returns
+///
+/// The entry (named after the last component of the path),
+/// or `None` if the path does not exist
+pub(crate) fn find_entry(
+    repo: &Repository,
+    hash: &str,
+    path: &str,
+) -> anyhow::Result<Option<TreeEntry>> {
+    let mut tree = hash.to_string();
+    let mut components = path.split('/').filter(|name| !name.is_empty()).peekable();
+
+    while let Some(name) = components.next() {
+        let entry = read_tree(repo, &tree)?
+            .into_iter()
+            .find(|entry| entry.name == name.as_bytes());
+
+        match entry {
+            Some(entry) if components.peek().is_none() => return Ok(Some(entry)),
+            Some(entry) if entry.is_tree() => tree = entry.hash,
+            _ => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+/// Recursively list the non-tree entries of a tree.
+///
+/// # Returns
+///
+/// The entries in tree order, with names replaced by their
+/// full `/`-separated paths relative to the root tree
+pub(crate) fn flatten_tree(repo: &Repository, hash: &str) -> anyhow::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    flatten_tree_into(repo, hash, b"", false, &mut entries)?;
+    Ok(entries)
+}
+
+/// Recursively list the non-tree entries of a tree to write into a working
+/// tree, like [`flatten_tree`], failing on the names that are not a single
+/// valid path component (empty, `.`, `..`, `.git` in any case, or containing a
+/// slash), which would be written outside of it or into its git directory.
+pub(crate) fn flatten_tree_checked(
+    repo: &Repository,
+    hash: &str,
+) -> anyhow::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    flatten_tree_into(repo, hash, b"", true, &mut entries)?;
+    Ok(entries)
+}
+
+fn flatten_tree_into(
+    repo: &Repository,
+    hash: &str,
+    prefix: &[u8],
+    checked: bool,
+    entries: &mut Vec<TreeEntry>,
+) -> anyhow::Result<()> {
+    for entry in read_tree(repo, hash)? {
+        let mut path = prefix.to_vec();
+        path.extend(&entry.name);
+
+        if checked && (entry.name.contains(&b'/') || !is_valid_path(&entry.name)) {
+            anyhow::bail!("invalid path '{}'", String::from_utf8_lossy(&path));
+        }
+        if entry.is_tree() {
+            path.push(b'/');
+            flatten_tree_into(repo, &entry.hash, &path, checked, entries)?;
+        } else {
+            entries.push(TreeEntry {
+                name: path,
+                ..entry
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Serialize the entries of a single tree object, sorting them in tree order.
+pub(crate) fn serialize_tree(mut entries: Vec<TreeEntry>) -> anyhow::Result<Vec<u8>> {
+    // Subtrees are sorted as if their name ended with a slash
+    entries.sort_by_cached_key(|entry| {
+        let mut key = entry.name.clone();
+        if entry.is_tree() {
+            key.push(b'/');
+        }
+        key
+    });
+
+    let mut content = Vec::new();
+    for entry in entries {
+        content.extend(format!("{:o} ", entry.mode).as_bytes());
+        content.extend(&entry.name);
+        content.push(0);
+        content.extend(hex::decode(entry.hash.as_bytes())?);
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tree, serialize_tree, TreeBuilder, TreeEntry, EMPTY_TREE, MODE_TREE};
+    use crate::utils::hex;
+
+    const HASH: &str = "01c6a63b7fc32f6f49988a9a12b8d7d199febeab";
+
+    #[test]
+    fn parses_tree_entries() {
+        let binary = hex::decode(HASH.as_bytes()).unwrap();
+        let mut content = b"100644 file.txt\0".to_vec();
+        content.extend(&binary);
+        content.extend(b"40000 dir\0");
+        content.extend(&binary);
+
+        let entries = parse_tree(&content).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].mode, 0o100644);
+        assert_eq!(entries[0].name, b"file.txt");
+        assert_eq!(entries[0].hash, HASH);
+        assert_eq!(entries[1].mode, MODE_TREE);
+        assert!(entries[1].is_tree());
+    }
+
+    #[test]
+    fn fails_on_truncated_tree() {
+        assert!(parse_tree(b"100644 file.txt\0abc").is_err());
+        assert!(parse_tree(b"100644 file.txt").is_err());
+    }
+
+    #[test]
+    fn sorts_subtrees_with_trailing_slash() {
+        let entry = |mode, name: &str| TreeEntry {
+            mode,
+            name: name.as_bytes().to_vec(),
+            hash: HASH.to_string(),
+        };
+        let content = serialize_tree(vec![
+            entry(MODE_TREE, "a"),
+            entry(0o100644, "a.txt"),
+            entry(0o100644, "a-b"),
+        ])
+        .unwrap();
+
+        let names: Vec<_> = parse_tree(&content)
+            .unwrap()
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).unwrap())
+            .collect();
+        assert_eq!(names, vec!["a-b", "a.txt", "a"]);
+    }
+
+    #[test]
+    fn builds_trees_from_entries() {
+        let mut builder = TreeBuilder::new();
+        builder.insert(0o100644, b"z", HASH).unwrap();
+        builder.insert(MODE_TREE, b"a", EMPTY_TREE).unwrap();
+        builder.insert(0o100644, b"a.b", HASH).unwrap();
+
+        let err = builder.insert(0o100644, b"z", HASH).unwrap_err();
+        assert_eq!(err.to_string(), "duplicate tree entry 'z'");
+        let err = builder.insert(0o100644, b"a/b", HASH).unwrap_err();
+        assert_eq!(err.to_string(), "path a/b contains slash");
+        assert!(builder.insert(0o100644, b"..", HASH).is_err());
+        assert!(builder.insert(0o100644, b"x", "1234").is_err());
+        assert!(!builder.is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+//! Walking the commit history
+//!
+//! The parents of commits are the ones of their grafts, if they have one
+//! (see [`Grafts`]). Commits are looked up in the commit-graph first (see
+//! [`CommitGraph`](crate::utils::commit_graph::CommitGraph)), only reading the
+//! objects of the commits it does not have.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+use crate::utils::commit::{peel, read_commit, Commit, Ident};
+use crate::utils::commit_graph::{GraphCommit, GENERATION_INFINITY};
+use crate::utils::grafts::Grafts;
+use crate::utils::objects::ObjectType;
+use crate::utils::repository::Repository;
+use crate::utils::revision::resolve_commit;
+
+/// Walks the commits reachable from a set of starting points,
+/// newest (by committer date) first
+pub(crate) struct RevWalk<'a> {
+    repo: &'a Repository,
+    /// Commits waiting to be visited, ordered by committer date
+    /// and then by insertion order
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    /// Commits that were queued (or hidden) already
+    seen: HashSet<String>,
+    /// The number of commits queued so far, used to break ties
+    sequence: usize,
+    /// The grafts of the repository, loaded when the walk starts
+    grafts: Option<Grafts>,
+}
+
+impl<'a> RevWalk<'a> {
+    /// Create a walk without any starting points.
+    pub(crate) fn new(repo: &'a Repository) -> Self {
+        RevWalk {
+            repo,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            sequence: 0,
+            grafts: None,
+        }
+    }
+
+    /// Read a commit, with the parents of its graft.
+    fn read_commit(&mut self, hash: &str) -> anyhow::Result<Commit> {
+        if self.grafts.is_none() {
+            self.grafts = Some(Grafts::load(self.repo)?);
+        }
+        self.grafts.as_ref().unwrap().read_commit(self.repo, hash)
+    }
+
+    /// The committer date of a commit, used to order the walk.
+    fn commit_date(&mut self, hash: &str) -> anyhow::Result<i64> {
+        if let Some(commit) = graph_commit(self.repo, hash)? {
+            return Ok(commit.timestamp);
+        }
+        let commit = self.read_commit(hash)?;
+        Ok(Ident::parse(&commit.committer).map_or(0, |ident| ident.timestamp))
+    }
+
+    /// Add a starting point to the walk.
+    pub(crate) fn push(&mut self, hash: &str) -> anyhow::Result<()> {
+        if !self.seen.insert(hash.to_string()) {
+            return Ok(());
+        }
+
+        let date = self.commit_date(hash)?;
+        self.enqueue(hash.to_string(), date);
+        Ok(())
+    }
+
+    /// Exclude a commit and all of its ancestors from the walk.
+    /// Must be called before the walk is started.
+    pub(crate) fn hide(&mut self, hash: &str) -> anyhow::Result<()> {
+        let hidden = reachable_commits(self.repo, hash)?;
+
+        // Starting points that were pushed before are dropped as well
+        self.queue.retain(|(_, _, queued)| !hidden.contains(queued));
+        self.seen.extend(hidden);
+        Ok(())
+    }
+
+    fn enqueue(&mut self, hash: String, date: i64) {
+        self.queue.push((date, Reverse(self.sequence), hash));
+        self.sequence += 1;
+    }
+}
+
+/// Look a commit up in the commit-graph of a repository, if it has one.
+fn graph_commit(repo: &Repository, hash: &str) -> anyhow::Result<Option<GraphCommit>> {
+    match repo.commit_graph() {
+        Some(graph) => graph.commit(hash),
+        None => Ok(None),
+    }
+}
+
+/// Read the parents of a commit, with the parents of its graft.
+fn read_parents(repo: &Repository, grafts: &Grafts, hash: &str) -> anyhow::Result<Vec<String>> {
+    match graph_commit(repo, hash)? {
+        Some(commit) => Ok(commit.parents),
+        None => Ok(grafts.read_commit(repo, hash)?.parents),
+    }
+}
+
+/// Collect every commit reachable from a commit (including itself).
+pub(crate) fn reachable_commits(repo: &Repository, hash: &str) -> anyhow::Result<HashSet<String>> {
+    let grafts = Grafts::load(repo)?;
+    let mut reachable = HashSet::new();
+    let mut pending = vec![hash.to_string()];
+
+    while let Some(hash) = pending.pop() {
+        if reachable.insert(hash.clone()) {
+            pending.extend(read_parents(repo, &grafts, &hash)?);
+        }
+    }
+
+    Ok(reachable)
+}
+
+/// Find the best common ancestors of two commits: the commits reachable from
+/// both that are not ancestors of another such commit.
+///
+/// # Returns
+///
+/// The merge bases, newest (by committer date) first
+pub(crate) fn merge_bases(repo: &Repository, one: &str, two: &str) -> anyhow::Result<Vec<String>> {
+    let reachable = reachable_commits(repo, one)?;
+    let common: HashSet<String> = reachable_commits(repo, two)?
+        .into_iter()
+        .filter(|hash| reachable.contains(hash))
+        .collect();
+
+    // The ancestors of common commits are common as well, so they are all
+    // found by walking back from the parents of the common commits
+    let grafts = Grafts::load(repo)?;
+    let mut ancestors = HashSet::new();
+    let mut pending = Vec::new();
+    for hash in &common {
+        pending.extend(read_parents(repo, &grafts, hash)?);
+    }
+    while let Some(hash) = pending.pop() {
+        if ancestors.insert(hash.clone()) {
+            pending.extend(read_parents(repo, &grafts, &hash)?);
+        }
+    }
+
+    let mut bases = Vec::new();
+    for hash in common.into_iter().filter(|hash| !ancestors.contains(hash)) {
+        let date = match graph_commit(repo, &hash)? {
+            Some(commit) => commit.timestamp,
+            None => {
+                let commit = read_commit(repo, &hash)?;
+                Ident::parse(&commit.committer).map_or(0, |ident| ident.timestamp)
+            },
+        };
+        bases.push((Reverse(date), hash));
+    }
+    bases.sort();
+    Ok(bases.into_iter().map(|(_, hash)| hash).collect())
+}
+
+/// Check whether a commit is an ancestor of (or the same as) another commit.
+/// With a commit-graph, the walk stops at the commits whose generation number
+/// is too low for them to have the ancestor in their history.
+pub(crate) fn is_ancestor(repo: &Repository, ancestor: &str, hash: &str) -> anyhow::Result<bool> {
+    let min_generation = graph_commit(repo, ancestor)?.map_or(0, |commit| commit.generation);
+    let grafts = Grafts::load(repo)?;
+    let mut seen = HashSet::new();
+    let mut pending = vec![hash.to_string()];
+
+    while let Some(hash) = pending.pop() {
+        if hash == ancestor {
+            return Ok(true);
+        }
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let (parents, generation) = match graph_commit(repo, &hash)? {
+            Some(commit) => (commit.parents, commit.generation),
+            None => (
+                grafts.read_commit(repo, &hash)?.parents,
+                GENERATION_INFINITY,
+            ),
+        };
+        if generation > min_generation {
+            pending.extend(parents);
+        }
+    }
+    Ok(false)
+}
+
+/// Selects commits by whether they are reachable from a target commit,
+/// as done by `--merged` and `--no-merged`
+#[derive(Debug, Default)]
+pub(crate) struct MergeFilter {
+    /// The commits reachable from the `--merged` target
+    merged: Option<HashSet<String>>,
+    /// The commits reachable from the `--no-merged` target
+    no_merged: Option<HashSet<String>>,
+}
+
+impl MergeFilter {
+    /// Create a filter from the (optional) revisions given to `--merged` and `--no-merged`.
+    pub(crate) fn new(
+        repo: &Repository,
+        merged: Option<&str>,
+        no_merged: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let reachable = |revision: Option<&str>| -> anyhow::Result<Option<HashSet<String>>> {
+            revision
+                .map(|revision| reachable_commits(repo, &resolve_commit(repo, revision)?))
+                .transpose()
+        };
+
+        Ok(MergeFilter {
+            merged: reachable(merged)?,
+            no_merged: reachable(no_merged)?,
+        })
+    }
+
+    /// Whether an object passes the filter.
+    /// Tags are peeled, and objects that are not commits only pass an empty filter.
+    pub(crate) fn matches(&self, repo: &Repository, hash: &str) -> anyhow::Result<bool> {
+        if self.merged.is_none() && self.no_merged.is_none() {
+            return Ok(true);
+        }
+
+        let (hash, object_type) = peel(repo, hash)?;
+        if object_type != ObjectType::Commit {
+            return Ok(false);
+        }
+
+        let merged = self.merged.as_ref().is_none_or(|set| set.contains(&hash));
+        let no_merged = self
+            .no_merged
+            .as_ref()
+            .is_none_or(|set| !set.contains(&hash));
+        Ok(merged && no_merged)
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = anyhow::Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, _, hash) = self.queue.pop()?;
+
+        let commit = match self.read_commit(&hash) {
+            Ok(commit) => commit,
+            Err(err) => return Some(Err(err)),
+        };
+
+        for parent in &commit.parents {
+            if !self.seen.insert(parent.clone()) {
+                continue;
+            }
+            match self.commit_date(parent) {
+                Ok(date) => self.enqueue(parent.clone(), date),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        Some(Ok((hash, commit)))
+    }
+}
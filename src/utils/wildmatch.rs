@@ -0,0 +1,237 @@
+//! Matching paths against shell-like glob patterns, as git does for
+//! conditional includes and ignore rules
+//!
+//! Patterns are matched against whole paths:
+//!
+//! - `?` matches any character except `/`
+//! - `*` matches any sequence of characters except `/`
+//! - `**` matches any sequence of directories, when it is a whole path component
+//! - `[...]` matches a character class (`!` or `^` negates it, `a-z` is a range,
+//!   and `[:alpha:]` and friends are named classes)
+//! - `\` escapes the next character
+
+/// Check whether a path matches a glob pattern.
+///
+/// # Arguments
+///
+/// * `pattern` - The glob pattern
+/// * `text` - The path to match
+/// * `ignore_case` - Whether to compare letters case-insensitively
+pub(crate) fn wildmatch(pattern: &str, text: &str, ignore_case: bool) -> bool {
+    Matcher {
+        pattern: pattern.as_bytes(),
+        ignore_case,
+    }
+    .matches(0, text.as_bytes())
+}
+
+struct Matcher<'a> {
+    pattern: &'a [u8],
+    ignore_case: bool,
+}
+
+impl Matcher<'_> {
+    /// Match the pattern, starting at `start`, against the whole text.
+    fn matches(&self, start: usize, text: &[u8]) -> bool {
+        let pattern = self.pattern;
+        let (mut p, mut t) = (start, 0);
+
+        while p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    let stars = pattern[p..].iter().take_while(|&&c| c == b'*').count();
+                    let rest = p + stars;
+                    let whole_component = stars >= 2
+                        && (p == 0 || pattern[p - 1] == b'/')
+                        && (rest == pattern.len() || pattern[rest] == b'/');
+
+                    if whole_component {
+                        if rest == pattern.len() {
+                            return true;
+                        }
+                        // `**/` matches zero or more leading directories
+                        return (t..=text.len())
+                            .filter(|&i| i == t || text[i - 1] == b'/')
+                            .any(|i| self.matches(rest + 1, &text[i..]));
+                    }
+
+                    let end = text[t..]
+                        .iter()
+                        .position(|&c| c == b'/')
+                        .map_or(text.len(), |slash| t + slash);
+                    return (t..=end).any(|i| self.matches(rest, &text[i..]));
+                },
+                b'?' => {
+                    if t == text.len() || text[t] == b'/' {
+                        return false;
+                    }
+                    p += 1;
+                    t += 1;
+                },
+                b'[' => {
+                    let Some(&c) = text.get(t) else {
+                        return false;
+                    };
+                    t += 1;
+                    match self.match_class(p + 1, c) {
+                        Some((true, next)) => p = next,
+                        Some((false, _)) => return false,
+                        // An unterminated class matches a literal `[`
+                        None if c == b'[' => p += 1,
+                        None => return false,
+                    }
+                },
+                c => {
+                    let c = if c == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        pattern[p]
+                    } else {
+                        c
+                    };
+                    if t == text.len() || !self.eq(c, text[t]) {
+                        return false;
+                    }
+                    p += 1;
+                    t += 1;
+                },
+            }
+        }
+
+        t == text.len()
+    }
+
+    /// Match a character against the class starting after a `[`.
+    ///
+    /// # Returns
+    ///
+    /// Whether the character matched and the position after the class,
+    /// or `None` if the class is not terminated
+    fn match_class(&self, start: usize, c: u8) -> Option<(bool, usize)> {
+        let pattern = self.pattern;
+        let mut p = start;
+        let negated = matches!(pattern.get(p), Some(b'!' | b'^'));
+        if negated {
+            p += 1;
+        }
+
+        let mut matched = false;
+        let mut first = true;
+        loop {
+            let mut current = *pattern.get(p)?;
+            if current == b']' && !first {
+                break;
+            }
+            first = false;
+
+            if current == b'[' && pattern.get(p + 1) == Some(&b':') {
+                let name_end = pattern[p + 2..]
+                    .windows(2)
+                    .position(|window| window == b":]")
+                    .map(|end| p + 2 + end)?;
+                let name = std::str::from_utf8(&pattern[p + 2..name_end]).ok()?;
+                matched |= self.match_named_class(name, c);
+                p = name_end + 2;
+                continue;
+            }
+
+            if current == b'\\' {
+                p += 1;
+                current = *pattern.get(p)?;
+            }
+
+            if pattern.get(p + 1) == Some(&b'-') && pattern.get(p + 2).is_some_and(|&c| c != b']') {
+                let mut end = pattern[p + 2];
+                p += 2;
+                if end == b'\\' {
+                    p += 1;
+                    end = *pattern.get(p)?;
+                }
+                matched |= (current..=end).contains(&c)
+                    || (self.ignore_case
+                        && ((current..=end).contains(&c.to_ascii_lowercase())
+                            || (current..=end).contains(&c.to_ascii_uppercase())));
+            } else {
+                matched |= self.eq(current, c);
+            }
+            p += 1;
+        }
+
+        // Classes never match the directory separator
+        Some((matched != negated && c != b'/', p + 1))
+    }
+
+    fn match_named_class(&self, name: &str, c: u8) -> bool {
+        match name {
+            "alnum" => c.is_ascii_alphanumeric(),
+            "alpha" => c.is_ascii_alphabetic(),
+            "blank" => c == b' ' || c == b'\t',
+            "cntrl" => c.is_ascii_control(),
+            "digit" => c.is_ascii_digit(),
+            "graph" => c.is_ascii_graphic(),
+            "lower" => c.is_ascii_lowercase() || (self.ignore_case && c.is_ascii_uppercase()),
+            "print" => c.is_ascii_graphic() || c == b' ',
+            "punct" => c.is_ascii_punctuation(),
+            "space" => c.is_ascii_whitespace(),
+            "upper" => c.is_ascii_uppercase() || (self.ignore_case && c.is_ascii_lowercase()),
+            "xdigit" => c.is_ascii_hexdigit(),
+            _ => false,
+        }
+    }
+
+    fn eq(&self, a: u8, b: u8) -> bool {
+        if self.ignore_case {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wildmatch;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildmatch("foo", "foo", false));
+        assert!(!wildmatch("foo", "foobar", false));
+        assert!(wildmatch("f?o", "foo", false));
+        assert!(wildmatch("*.txt", "a.txt", false));
+        assert!(!wildmatch("*.txt", "dir/a.txt", false));
+        assert!(wildmatch("dir/*", "dir/a.txt", false));
+        assert!(!wildmatch("dir/*", "dir/sub/a.txt", false));
+        assert!(!wildmatch("a?b", "a/b", false));
+        assert!(wildmatch("\\*", "*", false));
+        assert!(!wildmatch("\\*", "a", false));
+        assert!(wildmatch("FOO", "foo", true));
+        assert!(!wildmatch("FOO", "foo", false));
+    }
+
+    #[test]
+    fn matches_double_stars() {
+        assert!(wildmatch("**/foo", "foo", false));
+        assert!(wildmatch("**/foo", "a/b/foo", false));
+        assert!(wildmatch("a/**/b", "a/b", false));
+        assert!(wildmatch("a/**/b", "a/x/y/b", false));
+        assert!(wildmatch("/home/**", "/home/user/work/.git", false));
+        assert!(!wildmatch("/home/**", "/homes/user", false));
+        // Stars that are not a whole path component do not cross directories
+        assert!(!wildmatch("a**b", "a/b", false));
+        assert!(wildmatch("a**b", "axxb", false));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(wildmatch("[abc]", "b", false));
+        assert!(!wildmatch("[abc]", "d", false));
+        assert!(wildmatch("[!abc]", "d", false));
+        assert!(wildmatch("[^abc]", "d", false));
+        assert!(wildmatch("[a-c]x", "bx", false));
+        assert!(wildmatch("[]]", "]", false));
+        assert!(wildmatch("[[:digit:]][[:alpha:]]", "1a", false));
+        assert!(!wildmatch("[[:digit:]]", "a", false));
+        assert!(wildmatch("[A-C]", "b", true));
+        assert!(!wildmatch("[!a]", "/", false));
+        assert!(wildmatch("[", "[", false));
+    }
+}
@@ -0,0 +1,176 @@
+//! Scanning and hashing the files of a working tree
+
+use std::fs::Metadata;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::utils::ignore::Ignore;
+use crate::utils::objects::{hash_object, ObjectType};
+use crate::utils::path_to_bytes;
+use crate::utils::stats::Counters;
+use crate::utils::tree::{MODE_EXECUTABLE, MODE_FILE, MODE_GITLINK, MODE_SYMLINK};
+
+/// A file (or nested repository) found in a working tree
+#[derive(Debug, Clone)]
+pub(crate) struct WorkTreeFile {
+    /// The `/`-separated path relative to the root of the working tree
+    pub(crate) path: Vec<u8>,
+    /// The metadata of the file (not following symlinks)
+    pub(crate) metadata: Metadata,
+}
+
+impl WorkTreeFile {
+    /// Whether the entry is a nested repository rather than a file
+    pub(crate) fn is_repository(&self) -> bool {
+        self.metadata.is_dir()
+    }
+}
+
+/// Recursively list the files of a working tree, skipping `.git`.
+/// Directories containing a `.git` entry are nested repositories
+/// and are listed as a single entry without descending into them.
+///
+/// # Arguments
+///
+/// * `root` - The root of the working tree
+/// * `ignore` - The ignore rules to leave out ignored files (and not descend into
+///   ignored directories), or `None` to list every file
+///
+/// # Returns
+///
+/// The files, sorted by path
+pub(crate) fn list_files(
+    root: &Path,
+    mut ignore: Option<&mut Ignore>,
+) -> anyhow::Result<Vec<WorkTreeFile>> {
+    let mut files = Vec::new();
+    list_files_into(root, root, &mut ignore, &mut files)?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+fn list_files_into(
+    root: &Path,
+    dir: &Path,
+    ignore: &mut Option<&mut Ignore>,
+    files: &mut Vec<WorkTreeFile>,
+) -> anyhow::Result<()> {
+    let entries = std::fs::read_dir(dir).context(format!("read directory {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.file_name() == Some(".git".as_ref()) {
+            continue;
+        }
+
+        let metadata = path
+            .symlink_metadata()
+            .context(format!("stat {}", path.display()))?;
+        let relative = relative_path(root, &path)?;
+        Counters::update(|counters| counters.files_scanned += 1);
+        if let Some(ignore) = ignore {
+            if ignore.is_ignored(&String::from_utf8_lossy(&relative), metadata.is_dir())? {
+                if metadata.is_dir() {
+                    Counters::update(|counters| counters.directories_pruned += 1);
+                }
+                continue;
+            }
+        }
+
+        if metadata.is_dir() && !path.join(".git").exists() {
+            list_files_into(root, &path, ignore, files)?;
+            continue;
+        }
+
+        files.push(WorkTreeFile {
+            path: relative,
+            metadata,
+        });
+    }
+
+    Ok(())
+}
+
+/// Get the `/`-separated path of a file relative to the root of the working tree.
+pub(crate) fn relative_path(root: &Path, path: &Path) -> anyhow::Result<Vec<u8>> {
+    let relative = path
+        .strip_prefix(root)
+        .context(format!("{} is outside the working tree", path.display()))?;
+
+    let components: Vec<_> = relative
+        .components()
+        .map(|component| path_to_bytes(Path::new(component.as_os_str())))
+        .collect();
+    Ok(components.join(&b'/'))
+}
+
+/// Get the mode a file would be recorded with in a tree.
+pub(crate) fn file_mode(metadata: &Metadata) -> u32 {
+    if metadata.is_symlink() {
+        MODE_SYMLINK
+    } else if metadata.is_dir() {
+        MODE_GITLINK
+    } else if is_executable(metadata) {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+/// Read the content a file would be stored with as a blob
+/// (the link target for symlinks).
+pub(crate) fn read_file(path: &Path, metadata: &Metadata) -> anyhow::Result<Vec<u8>> {
+    if metadata.is_symlink() {
+        let target = std::fs::read_link(path).context(format!("read link {}", path.display()))?;
+        return Ok(path_to_bytes(&target));
+    }
+
+    std::fs::read(path).context(format!("read {}", path.display()))
+}
+
+/// Compute the blob hash of a file without writing it to the object database.
+pub(crate) fn hash_file(path: &Path, metadata: &Metadata) -> anyhow::Result<String> {
+    let content = read_file(path, metadata)?;
+    Ok(hash_object(&ObjectType::Blob, &content))
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{file_mode, list_files};
+    use crate::utils::test::TempRepo;
+    use crate::utils::tree::MODE_GITLINK;
+
+    #[test]
+    fn lists_files_recursively() {
+        let temp = TempRepo::new();
+        fs::create_dir_all(temp.join(".git/objects")).unwrap();
+        fs::create_dir_all(temp.join("dir/sub")).unwrap();
+        fs::create_dir_all(temp.join("nested/.git")).unwrap();
+        fs::write(temp.join("dir/sub/file"), "").unwrap();
+        fs::write(temp.join("dir.txt"), "").unwrap();
+        fs::write(temp.join("nested/ignored"), "").unwrap();
+
+        let files = list_files(temp.path(), None).unwrap();
+        let paths: Vec<_> = files.iter().map(|file| file.path.as_slice()).collect();
+        assert_eq!(paths, [&b"dir.txt"[..], b"dir/sub/file", b"nested"]);
+
+        assert!(files[2].is_repository());
+        assert_eq!(file_mode(&files[2].metadata), MODE_GITLINK);
+        assert_eq!(file_mode(&files[0].metadata), 0o100644);
+    }
+}
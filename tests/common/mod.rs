@@ -0,0 +1,239 @@
+//! Utility structs and functions for the integration tests
+//!
+//! The integration tests run the built binary in fixture repositories and
+//! check what it writes to stdout and stderr, and its exit code. Each command
+//! is its own process, with its own environment and working directory, so
+//! these tests share no global state and can run in parallel.
+
+// Each test file only uses some of the helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The date of the first commit of the fixtures, each following commit being a
+/// minute later
+const FIRST_COMMIT_DATE: u64 = 1_700_000_000;
+
+/// A temporary directory holding fixture repositories, which is also the
+/// home directory of the commands run in it.
+pub struct Fixture {
+    dir: tempfile::TempDir,
+    commits: std::cell::Cell<u64>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Fixture {
+            dir: tempfile::tempdir().unwrap(),
+            commits: std::cell::Cell::new(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Prepare a command run in the fixture directory.
+    ///
+    /// Only the variables set here are in its environment: the system
+    /// configuration is not read, the global configuration is the
+    /// `.gitconfig` of the fixture directory, and the author and committer
+    /// are fixed, so that the objects created are the same on every run.
+    pub fn git<I, S>(&self, args: I) -> Git
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_git"));
+        command
+            .args(args)
+            .current_dir(self.path())
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.path())
+            .env("TZ", "UTC")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "A U Thor")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "C O Mitter")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com");
+        Git {
+            command,
+            root: self.path().to_path_buf(),
+            stdin: Vec::new(),
+        }
+    }
+
+    /// Write a file of the fixture directory, creating its parent directories.
+    pub fn write<P>(&self, path: P, contents: &[u8])
+    where
+        P: AsRef<Path>,
+    {
+        let path = self.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn read<P>(&self, path: P) -> Vec<u8>
+    where
+        P: AsRef<Path>,
+    {
+        fs::read(self.path().join(path)).unwrap()
+    }
+
+    pub fn read_to_string<P>(&self, path: P) -> String
+    where
+        P: AsRef<Path>,
+    {
+        fs::read_to_string(self.path().join(path)).unwrap()
+    }
+
+    /// Commit the changes of the working tree of a repository, dated a minute
+    /// after the previous commit of the fixture.
+    pub fn commit(&self, repo: &str, message: &str) {
+        let date = format!("{} +0000", FIRST_COMMIT_DATE + 60 * self.commits.get());
+        self.commits.set(self.commits.get() + 1);
+        self.git(["add", "."]).current_dir(repo).succeeds();
+        self.git(["commit", "-q", "-m", message])
+            .current_dir(repo)
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .succeeds();
+    }
+
+    /// Create the repository `repo` with a known history: `initial` adding
+    /// `hello.txt` and `src/main.rs` (on `topic`), then `second` appending to
+    /// `hello.txt` (on `main`).
+    pub fn repository(&self, repo: &str) {
+        self.git(["init", "-q", repo]).succeeds();
+        self.write(format!("{repo}/hello.txt"), b"hello\n");
+        self.write(format!("{repo}/src/main.rs"), b"fn main() {}\n");
+        self.commit(repo, "initial");
+        self.write(format!("{repo}/hello.txt"), b"hello\nworld\n");
+        self.commit(repo, "second");
+        self.git(["branch", "topic", "HEAD~1"])
+            .current_dir(repo)
+            .succeeds();
+    }
+}
+
+/// A command to run
+pub struct Git {
+    command: Command,
+    root: PathBuf,
+    stdin: Vec<u8>,
+}
+
+impl Git {
+    /// Run the command in a directory of the fixture.
+    pub fn current_dir<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.command.current_dir(self.root.join(dir));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.command.env(key, value);
+        self
+    }
+
+    pub fn stdin(mut self, input: &[u8]) -> Self {
+        self.stdin = input.to_vec();
+        self
+    }
+
+    /// Run the command, waiting for it to exit.
+    pub fn output(mut self) -> Output {
+        let mut child = self
+            .command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Dropping stdin once written closes it, so that the command reads to its end
+        let mut stdin = child.stdin.take().unwrap();
+        let input = std::mem::take(&mut self.stdin);
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output().unwrap();
+        // The command may exit without reading its input
+        let _ = writer.join().unwrap();
+        Output {
+            code: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        }
+    }
+
+    /// Start the command, without waiting for it to exit.
+    pub fn spawn(mut self) -> std::process::Child {
+        self.command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+
+    /// Run the command with its stdout a pipe nobody reads anymore (as when
+    /// piped into `head`, once `head` has exited).
+    pub fn output_to_closed_pipe(mut self) -> Output {
+        let (reader, writer) = std::io::pipe().unwrap();
+        drop(reader);
+        let output = self
+            .command
+            .stdin(Stdio::null())
+            .stdout(writer)
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        Output {
+            code: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        }
+    }
+
+    /// Run the command, checking that it succeeds without writing to stderr.
+    ///
+    /// # Returns
+    ///
+    /// What the command wrote to stdout, which must be UTF-8 (compared as
+    /// text so that differences are readable)
+    pub fn succeeds(self) -> String {
+        let output = self.output();
+        output.assert(0, &output.stdout, b"");
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+/// The outcome of a command
+#[derive(Debug)]
+pub struct Output {
+    /// The exit code, `None` if the command was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    /// Check the exit code and the exact output of the command, showing what
+    /// it wrote if they differ.
+    pub fn assert(&self, code: i32, stdout: &[u8], stderr: &[u8]) {
+        assert!(
+            self.code == Some(code) && self.stdout == stdout && self.stderr == stderr,
+            "expected exit code {code}, stdout:\n{}\nstderr:\n{}\n\
+             got exit code {:?}, stdout:\n{}\nstderr:\n{}",
+            String::from_utf8_lossy(stdout),
+            String::from_utf8_lossy(stderr),
+            self.code,
+            String::from_utf8_lossy(&self.stdout),
+            String::from_utf8_lossy(&self.stderr),
+        );
+    }
+}
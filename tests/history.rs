@@ -0,0 +1,219 @@
+//! Walking the history, refs and working tree of fixture repositories
+
+mod common;
+
+use common::Fixture;
+
+#[test]
+fn shows_the_history() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    let output = fixture.git(["log"]).current_dir("repo").succeeds();
+    assert_eq!(
+        output,
+        "commit 562915acbc07ba50e6ecb2f0d712f1a1ec764c9e\n\
+          Author: A U Thor <author@example.com>\n\
+          Date:   Tue Nov 14 22:14:20 2023 +0000\n\
+          \n    second\n\
+          \n\
+          commit 58822f5e7e68ec866e7585b4aa46ccb15fa95c38\n\
+          Author: A U Thor <author@example.com>\n\
+          Date:   Tue Nov 14 22:13:20 2023 +0000\n\
+          \n    initial\n"
+    );
+    let output = fixture
+        .git(["rev-parse", "HEAD~1", "main^{tree}"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(
+        output,
+        "58822f5e7e68ec866e7585b4aa46ccb15fa95c38\n\
+          8d5bab9c527c4f0f495b91ee2f497c4ac81f34bc\n"
+    );
+
+    // Packing the refs changes how they are stored, not the refs listed
+    let refs = "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e commit\trefs/heads/main\n\
+                 58822f5e7e68ec866e7585b4aa46ccb15fa95c38 commit\trefs/heads/topic\n";
+    let output = fixture.git(["for-each-ref"]).current_dir("repo").succeeds();
+    assert_eq!(output, refs);
+    fixture
+        .git(["pack-refs", "--all"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(
+        fixture.read_to_string("repo/.git/packed-refs"),
+        "# pack-refs with: peeled fully-peeled sorted \n\
+          562915acbc07ba50e6ecb2f0d712f1a1ec764c9e refs/heads/main\n\
+          58822f5e7e68ec866e7585b4aa46ccb15fa95c38 refs/heads/topic\n"
+    );
+    let output = fixture.git(["for-each-ref"]).current_dir("repo").succeeds();
+    assert_eq!(output, refs);
+}
+
+#[test]
+fn stops_quietly_when_the_output_is_closed() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    // As git, killed by SIGPIPE
+    let output = fixture
+        .git(["log"])
+        .current_dir("repo")
+        .output_to_closed_pipe();
+    output.assert(141, b"", b"");
+}
+
+#[test]
+fn shows_the_status() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+    fixture.write("repo/new.txt", b"new\n");
+    fixture.write("repo/src/main.rs", b"fn main() {}\nx");
+
+    let output = fixture
+        .git(["ls-files", "-s"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(
+        output,
+        "100644 94954abda49de8615a048f8d2e64b5de848e27a1 0\thello.txt\n\
+          100644 f328e4d9d04c31d0d70d16d21a07d1613be9d577 0\tsrc/main.rs\n"
+    );
+    let output = fixture
+        .git(["status", "--porcelain"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(output, " M src/main.rs\n?? new.txt\n");
+    let output = fixture.git(["status"]).current_dir("repo").succeeds();
+    assert_eq!(
+        output,
+        "On branch main\n\
+          Changes not staged for commit:\n\
+          \x20 (use \"git add <file>...\" to update what will be committed)\n\
+          \x20 (use \"git restore <file>...\" to discard changes in working directory)\n\
+          \tmodified:   src/main.rs\n\
+          \n\
+          Untracked files:\n\
+          \x20 (use \"git add <file>...\" to include in what will be committed)\n\
+          \tnew.txt\n\
+          \n\
+          no changes added to commit (use \"git add\" and/or \"git commit -a\")\n"
+    );
+}
+
+#[test]
+fn round_trips_history_through_fast_export_and_fast_import() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+    fixture.git(["init", "-q", "copy"]).succeeds();
+
+    let stream = fixture
+        .git(["fast-export", "--all"])
+        .current_dir("repo")
+        .succeeds();
+    assert!(stream.starts_with("blob\nmark :1\ndata 6\nhello\n\nblob\nmark :2\n"));
+    fixture
+        .git(["fast-import", "--quiet"])
+        .current_dir("copy")
+        .stdin(stream.as_bytes())
+        .succeeds();
+
+    // The imported objects are in a pack, which the commands read
+    let refs = "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e commit\trefs/heads/main\n\
+                 58822f5e7e68ec866e7585b4aa46ccb15fa95c38 commit\trefs/heads/topic\n";
+    let output = fixture.git(["for-each-ref"]).current_dir("copy").succeeds();
+    assert_eq!(output, refs);
+    let output = fixture
+        .git(["cat-file", "-p", "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e"])
+        .current_dir("copy")
+        .succeeds();
+    assert!(output.starts_with("tree 8d5bab9c527c4f0f495b91ee2f497c4ac81f34bc\n"));
+
+    // A branch that would lose commits is not updated without --force
+    let stream = "commit refs/heads/main\n\
+                  committer C O Mitter <committer@example.com> 1700000000 +0000\n\
+                  data 6\nreset\nfrom topic\n\n";
+    let output = fixture
+        .git(["fast-import", "--quiet"])
+        .current_dir("copy")
+        .stdin(stream.as_bytes())
+        .output();
+    assert_eq!(output.code, Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .starts_with("warning: Not updating refs/heads/main (new tip "));
+    fixture
+        .git(["fast-import", "--quiet", "--force"])
+        .current_dir("copy")
+        .stdin(stream.as_bytes())
+        .succeeds();
+    let output = fixture
+        .git(["rev-parse", "main~1"])
+        .current_dir("copy")
+        .succeeds();
+    assert_eq!(output, "58822f5e7e68ec866e7585b4aa46ccb15fa95c38\n");
+}
+
+#[test]
+fn rewrites_history_through_fast_export_and_fast_import() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    // The second commit only changes hello.txt, so it is pruned (`rewrite`
+    // being an alias of `rewrite-history`)
+    let output = fixture
+        .git(["rewrite", "--path", "src", "--path-rename", "src:"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(
+        output,
+        "Parsed 2 commits, rewrote 1 and pruned 1\nUpdated 2 refs\n"
+    );
+    let main = fixture
+        .git(["rev-parse", "main"])
+        .current_dir("repo")
+        .succeeds();
+    let topic = fixture
+        .git(["rev-parse", "topic"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(main, topic);
+    let output = fixture.git(["ls-files"]).current_dir("repo").succeeds();
+    assert_eq!(output, "main.rs\n");
+    assert!(!fixture.path().join("repo/hello.txt").exists());
+}
+
+#[test]
+fn bisects_with_a_command() {
+    let fixture = Fixture::new();
+    fixture.git(["init", "-q", "repo"]).succeeds();
+    for n in 1..=6 {
+        fixture.write("repo/n", format!("{n}\n").as_bytes());
+        fixture.commit("repo", &format!("commit {n}"));
+    }
+
+    fixture
+        .git(["bisect", "start", "HEAD", "HEAD~5"])
+        .current_dir("repo")
+        .succeeds();
+    let output = fixture
+        .git(["bisect", "run", "sh", "-c", "test $(cat n) -lt 4"])
+        .current_dir("repo")
+        .succeeds();
+    let first_bad = fixture
+        .git(["rev-parse", "main~2"])
+        .current_dir("repo")
+        .succeeds();
+    assert!(output.contains(&format!("{} is the first bad commit\n", first_bad.trim())));
+    assert!(output.ends_with("    commit 4\nbisect found first bad commit\n"));
+
+    // Resetting checks the branch out again and forgets the bisection
+    fixture
+        .git(["bisect", "reset"])
+        .current_dir("repo")
+        .succeeds();
+    let output = fixture.read_to_string("repo/n");
+    assert_eq!(output, "6\n");
+    let output = fixture.git(["bisect", "log"]).current_dir("repo").output();
+    assert_eq!(output.code, Some(1));
+}
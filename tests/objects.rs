@@ -0,0 +1,75 @@
+//! Reading the objects of fixture repositories, and the errors reported
+
+mod common;
+
+use common::Fixture;
+
+#[test]
+fn reads_objects() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    let output = fixture
+        .git(["cat-file", "-p", "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(
+        output,
+        "tree 8d5bab9c527c4f0f495b91ee2f497c4ac81f34bc\n\
+          parent 58822f5e7e68ec866e7585b4aa46ccb15fa95c38\n\
+          author A U Thor <author@example.com> 1700000060 +0000\n\
+          committer C O Mitter <committer@example.com> 1700000060 +0000\n\
+          \n\
+          second\n"
+    );
+    let output = fixture
+        .git(["cat-file", "-p", "94954abda49de8615a048f8d2e64b5de848e27a1"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(output, "hello\nworld\n");
+
+    // Objects are checked from the input, missing ones being reported
+    let output = fixture
+        .git(["cat-file", "--batch-check"])
+        .current_dir("repo")
+        .stdin(
+            b"8d5bab9c527c4f0f495b91ee2f497c4ac81f34bc\n\
+              0000000000000000000000000000000000000000\n\
+              f328e4d9d04c31d0d70d16d21a07d1613be9d577\n",
+        )
+        .succeeds();
+    assert_eq!(
+        output,
+        "8d5bab9c527c4f0f495b91ee2f497c4ac81f34bc tree 67\n\
+          0000000000000000000000000000000000000000 missing\n\
+          f328e4d9d04c31d0d70d16d21a07d1613be9d577 blob 13\n"
+    );
+}
+
+#[test]
+fn reports_errors() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    fixture.git(["status"]).output().assert(
+        1,
+        b"",
+        b"Error: not a git repository (or any of the parent directories): .git\n",
+    );
+    fixture
+        .git(["cat-file", "-p", "0000000000000000000000000000000000000000"])
+        .current_dir("repo")
+        .output()
+        .assert(
+            1,
+            b"",
+            b"Error: 0000000000000000000000000000000000000000 is not a valid object\n",
+        );
+    // Usage errors are reported by the argument parser
+    let output = fixture.git(["frobnicate"]).output();
+    assert_eq!(output.code, Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(output
+        .stderr
+        .starts_with(b"error: unrecognized subcommand 'frobnicate'\n"));
+}
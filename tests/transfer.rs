@@ -0,0 +1,204 @@
+//! Transferring the objects of fixture repositories as packs
+
+mod common;
+
+use common::Fixture;
+
+#[test]
+fn clones_from_upload_pack() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    // Without --no-local, the objects would be hardlinked instead
+    fixture
+        .git(["clone", "-q", "--no-local", "repo", "clone"])
+        .succeeds();
+    let output = fixture
+        .git(["for-each-ref"])
+        .current_dir("clone")
+        .succeeds();
+    assert_eq!(
+        output,
+        "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e commit\trefs/heads/main\n\
+          562915acbc07ba50e6ecb2f0d712f1a1ec764c9e commit\trefs/remotes/origin/HEAD\n\
+          562915acbc07ba50e6ecb2f0d712f1a1ec764c9e commit\trefs/remotes/origin/main\n\
+          58822f5e7e68ec866e7585b4aa46ccb15fa95c38 commit\trefs/remotes/origin/topic\n"
+    );
+    let output = fixture
+        .git(["cat-file", "--batch-check", "--batch-all-objects"])
+        .current_dir("clone")
+        .succeeds();
+    assert_eq!(
+        output,
+        "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e commit 218\n\
+          58822f5e7e68ec866e7585b4aa46ccb15fa95c38 commit 171\n\
+          5d90422423db5ef6b431e8b9e60e0baf04b8742a tree 35\n\
+          8d5bab9c527c4f0f495b91ee2f497c4ac81f34bc tree 67\n\
+          94954abda49de8615a048f8d2e64b5de848e27a1 blob 12\n\
+          aae2b3618f4a481bc1bde056dae4b7617edb7e83 tree 67\n\
+          ce013625030ba8dba906f756967f9e9ca394464a blob 6\n\
+          f328e4d9d04c31d0d70d16d21a07d1613be9d577 blob 13\n"
+    );
+    fixture.git(["fsck"]).current_dir("clone").succeeds();
+}
+
+#[test]
+fn clones_shallow_histories() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+    fixture.write("repo/hello.txt", b"hello\nagain\n");
+    fixture.commit("repo", "third");
+
+    // Only the last commit of main is fetched, topic being wanted on its own
+    fixture
+        .git(["clone", "-q", "--depth", "1", "--no-local", "repo", "clone"])
+        .succeeds();
+    let main = fixture
+        .git(["rev-parse", "main"])
+        .current_dir("clone")
+        .succeeds();
+    assert_eq!(fixture.read("clone/.git/shallow"), main.as_bytes());
+    let log = fixture
+        .git(["log", "--oneline", "--no-decorate"])
+        .current_dir("clone")
+        .succeeds();
+    assert_eq!(log, format!("{} third\n", &main[..7]));
+    // The parent of main (the fixture's second commit) is not fetched
+    let output = fixture
+        .git(["cat-file", "-e", "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e"])
+        .current_dir("clone")
+        .output();
+    output.assert(
+        1,
+        b"",
+        b"Error: 562915acbc07ba50e6ecb2f0d712f1a1ec764c9e is not a valid object\n",
+    );
+
+    // Local clones copy the objects as they are
+    let output = fixture
+        .git(["clone", "-q", "--depth", "1", "repo", "local"])
+        .output();
+    output.assert(
+        0,
+        b"",
+        b"warning: --depth is ignored in local clones; use file:// instead.\n",
+    );
+    assert!(!fixture.path().join("local/.git/shallow").exists());
+}
+
+#[test]
+fn removes_the_clone_when_interrupted() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::time::{Duration, Instant};
+
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    // The remote side is slow to answer, so the clone is interrupted while
+    // waiting for it, once it created its directory
+    let upload_pack = format!("sleep 2; {} upload-pack", env!("CARGO_BIN_EXE_git"));
+    let mut child = fixture
+        .git([
+            "clone",
+            "--no-local",
+            "--upload-pack",
+            &upload_pack,
+            "repo",
+            "clone",
+        ])
+        .spawn();
+    let start = Instant::now();
+    while !fixture.path().join("clone/.git").exists() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let killed = std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    // It ends by the signal, as without the handler removing the clone
+    assert_eq!(child.wait().unwrap().signal(), Some(15));
+    assert!(!fixture.path().join("clone").exists());
+}
+
+#[test]
+fn bundles_the_history() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+
+    fixture
+        .git(["bundle", "create", "../repo.bundle", "topic"])
+        .current_dir("repo")
+        .succeeds();
+    let output = fixture
+        .git(["bundle", "list-heads", "../repo.bundle"])
+        .current_dir("repo")
+        .succeeds();
+    assert_eq!(
+        output,
+        "58822f5e7e68ec866e7585b4aa46ccb15fa95c38 refs/heads/topic\n"
+    );
+    assert!(fixture.read("repo.bundle").starts_with(
+        b"# v2 git bundle\n\
+          58822f5e7e68ec866e7585b4aa46ccb15fa95c38 refs/heads/topic\n\
+          \n\
+          PACK"
+    ));
+}
+
+#[test]
+fn receives_a_push_from_another_directory() {
+    let fixture = Fixture::new();
+    fixture.repository("repo");
+    fixture
+        .git(["init", "-q", "--bare", "remote.git"])
+        .succeeds();
+    fixture.write(
+        "remote.git/hooks/pre-receive",
+        b"#!/bin/sh\npwd > hook-cwd\n",
+    );
+    let hook = fixture.path().join("remote.git/hooks/pre-receive");
+    std::fs::set_permissions(&hook, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    // The pack of the push is the one of a bundle of `main`
+    fixture
+        .git(["bundle", "create", "../main.bundle", "main"])
+        .current_dir("repo")
+        .succeeds();
+    let bundle = fixture.read("main.bundle");
+    let pack = &bundle[bundle.windows(4).position(|w| w == b"PACK").unwrap()..];
+    let command = "0000000000000000000000000000000000000000 \
+                   562915acbc07ba50e6ecb2f0d712f1a1ec764c9e refs/heads/pushed\0report-status\n";
+    let mut input = format!("{:04x}{command}0000", command.len() + 4).into_bytes();
+    input.extend_from_slice(pack);
+
+    // The repository is found from the directory of the client
+    let output = fixture
+        .git(["receive-pack", "../remote.git"])
+        .current_dir("repo")
+        .stdin(&input)
+        .output();
+    assert_eq!(
+        output.code,
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output
+        .stdout
+        .ends_with(b"000eunpack ok\n0019ok refs/heads/pushed\n0000"));
+    // Hooks run from the repository pushed to
+    let cwd = fixture.read_to_string("remote.git/hook-cwd");
+    let remote = fixture.path().join("remote.git").canonicalize().unwrap();
+    assert_eq!(cwd.trim_end(), remote.to_str().unwrap());
+
+    let output = fixture
+        .git(["--git-dir=remote.git", "for-each-ref", "refs/heads/pushed"])
+        .succeeds();
+    assert_eq!(
+        output,
+        "562915acbc07ba50e6ecb2f0d712f1a1ec764c9e commit\trefs/heads/pushed\n"
+    );
+}